@@ -0,0 +1,343 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Administration of a scheduler through its REST API
+
+use clap::Subcommand;
+use datafusion::error::{DataFusionError, Result};
+use hyper::body::Bytes;
+use hyper::client::HttpConnector;
+use hyper::header::{ACCEPT, AUTHORIZATION};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Interval between two polls of the tasks of a followed job
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Commands administering the scheduler
+#[derive(Debug, Subcommand, PartialEq)]
+pub enum AdminCommand {
+    /// List the jobs of the scheduler
+    Jobs {
+        #[clap(long, help = "Only list jobs with this status, such as running")]
+        status: Option<String>,
+        #[clap(long, help = "List at most this many jobs")]
+        limit: Option<usize>,
+    },
+    /// Cancel a queued or running job
+    Cancel { job_id: String },
+    /// List the executors with their task slots
+    Executors,
+    /// Stop scheduling tasks on an executor, letting the tasks it runs finish
+    Drain { executor_id: String },
+    /// Schedule tasks on a drained executor again
+    Undrain { executor_id: String },
+    /// Print the physical plans of the stages of a job
+    Plan {
+        job_id: String,
+        #[clap(
+            long,
+            help = "Export the plan and the metrics of its operators as dot or json instead"
+        )]
+        format: Option<String>,
+    },
+    /// Print the status of the tasks of a job
+    Logs {
+        job_id: String,
+        #[clap(
+            short,
+            long,
+            help = "Keep printing the status changes of the tasks until the job finishes"
+        )]
+        follow: bool,
+    },
+}
+
+/// A client of the REST API of a scheduler
+pub struct AdminClient {
+    client: Client<HttpConnector>,
+    url: String,
+    token: Option<String>,
+}
+
+impl AdminClient {
+    /// Create a client of the scheduler listening on `host` and `port`, sending `token`
+    /// as bearer token if client authentication is enabled
+    pub fn new(host: &str, port: u16, token: Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            url: format!("http://{}:{}", host, port),
+            token,
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Value> {
+        self.request(Method::GET, path).await
+    }
+
+    async fn post(&self, path: &str) -> Result<Value> {
+        self.request(Method::POST, path).await
+    }
+
+    async fn request(&self, method: Method, path: &str) -> Result<Value> {
+        let (status, body) = self.send(method, path).await?;
+        let value: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        if status.is_success() {
+            Ok(value)
+        } else {
+            Err(DataFusionError::Execution(format!(
+                "Request {} failed with status {}: {}",
+                path, status, value
+            )))
+        }
+    }
+
+    /// GET `path`, whose response is not JSON
+    async fn get_text(&self, path: &str) -> Result<String> {
+        let (status, body) = self.send(Method::GET, path).await?;
+        let text = String::from_utf8_lossy(&body).into_owned();
+        if status.is_success() {
+            Ok(text)
+        } else {
+            Err(DataFusionError::Execution(format!(
+                "Request {} failed with status {}: {}",
+                path, status, text
+            )))
+        }
+    }
+
+    async fn send(&self, method: Method, path: &str) -> Result<(StatusCode, Bytes)> {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(format!("{}{}", self.url, path))
+            .header(ACCEPT, "application/json");
+        if let Some(token) = &self.token {
+            builder = builder.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let request = builder.body(Body::empty()).map_err(|e| {
+            DataFusionError::Execution(format!("Invalid request: {:?}", e))
+        })?;
+
+        let response = self.client.request(request).await.map_err(|e| {
+            DataFusionError::Execution(format!(
+                "Failed to connect to scheduler {}: {:?}",
+                self.url, e
+            ))
+        })?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| {
+                DataFusionError::Execution(format!("Failed to read response: {:?}", e))
+            })?;
+        Ok((status, body))
+    }
+}
+
+/// Run `command` against the scheduler of `client`, printing its outcome
+pub async fn exec_admin_command(
+    client: &AdminClient,
+    command: AdminCommand,
+) -> Result<()> {
+    match command {
+        AdminCommand::Jobs { status, limit } => {
+            let mut params = vec![];
+            if let Some(status) = status {
+                params.push(format!("status={}", status));
+            }
+            if let Some(limit) = limit {
+                params.push(format!("limit={}", limit));
+            }
+            let path = if params.is_empty() {
+                "/jobs".to_owned()
+            } else {
+                format!("/jobs?{}", params.join("&"))
+            };
+            let jobs = client.get(&path).await?;
+            let rows = array(&jobs)
+                .iter()
+                .map(|job| {
+                    vec![
+                        text(&job["job_id"]),
+                        text(&job["status"]),
+                        format!("{}/{}", job["completed_stages"], job["num_stages"]),
+                        text(&job["error"]),
+                    ]
+                })
+                .collect();
+            print_table(&["JOB", "STATUS", "STAGES", "ERROR"], rows);
+        }
+        AdminCommand::Cancel { job_id } => {
+            client.post(&format!("/job/{}/cancel", job_id)).await?;
+            println!("Cancelling job {}", job_id);
+        }
+        AdminCommand::Executors => {
+            let executors = client.get("/executors").await?;
+            let rows = array(&executors)
+                .iter()
+                .map(|executor| {
+                    let state = match (
+                        executor["alive"].as_bool(),
+                        executor["draining"].as_bool(),
+                    ) {
+                        (Some(false), _) => "lost",
+                        (_, Some(true)) => "draining",
+                        _ => "alive",
+                    };
+                    vec![
+                        text(&executor["id"]),
+                        format!("{}:{}", text(&executor["host"]), executor["port"]),
+                        state.to_owned(),
+                        format!(
+                            "{}/{}",
+                            executor["available_task_slots"],
+                            executor["total_task_slots"]
+                        ),
+                    ]
+                })
+                .collect();
+            print_table(&["EXECUTOR", "ADDRESS", "STATE", "FREE SLOTS"], rows);
+        }
+        AdminCommand::Drain { executor_id } => {
+            client
+                .post(&format!("/executor/{}/drain", executor_id))
+                .await?;
+            println!("Draining executor {}", executor_id);
+        }
+        AdminCommand::Undrain { executor_id } => {
+            client
+                .post(&format!("/executor/{}/undrain", executor_id))
+                .await?;
+            println!("Undrained executor {}", executor_id);
+        }
+        AdminCommand::Plan {
+            job_id,
+            format: Some(format),
+        } => {
+            let path = format!("/job/{}/plan/export?format={}", job_id, format);
+            print!("{}", client.get_text(&path).await?);
+        }
+        AdminCommand::Plan { job_id, .. } => {
+            let plans = client.get(&format!("/job/{}/plan", job_id)).await?;
+            for plan in array(&plans) {
+                println!("Stage {}:", plan["stage_id"]);
+                println!("{}", text(&plan["plan"]));
+            }
+        }
+        AdminCommand::Logs { job_id, follow } => {
+            follow_tasks(client, &job_id, follow).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Print a line per task of job `job_id` which left the pending status, then, if
+/// `follow`, a line per status change until the job finishes
+async fn follow_tasks(client: &AdminClient, job_id: &str, follow: bool) -> Result<()> {
+    // Latest status and attempts of each task, by stage and partition
+    let mut seen: HashMap<(String, u64), (String, u64)> = HashMap::new();
+    loop {
+        let summary = client.get(&format!("/job/{}", job_id)).await?;
+        if let Some(stages) = summary["tasks"].as_object() {
+            let mut stage_ids: Vec<&String> = stages.keys().collect();
+            stage_ids.sort_by_key(|id| id.parse::<usize>().unwrap_or_default());
+            for stage_id in stage_ids {
+                for task in array(&stages[stage_id]) {
+                    let status = text(&task["status"]);
+                    let attempts = task["attempts"].as_u64().unwrap_or_default();
+                    let partition = task["partition_id"].as_u64().unwrap_or_default();
+                    let current = (status.clone(), attempts);
+                    if status == "Pending"
+                        || seen.get(&(stage_id.clone(), partition)) == Some(&current)
+                    {
+                        continue;
+                    }
+                    seen.insert((stage_id.clone(), partition), current);
+                    println!("{}", task_line(stage_id, task));
+                }
+            }
+        }
+
+        let status = text(&summary["job"]["status"]);
+        if status == "Completed" || status == "Failed" {
+            println!("Job {} {}", job_id, status.to_lowercase());
+            if let Some(error) = summary["job"]["error"].as_str() {
+                println!("{}", error);
+            }
+            return Ok(());
+        }
+        if !follow {
+            return Ok(());
+        }
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+/// A line describing the status of `task` of stage `stage_id`
+fn task_line(stage_id: &str, task: &Value) -> String {
+    let mut line = format!(
+        "stage {} task {} attempt {}: {}",
+        stage_id,
+        task["partition_id"],
+        task["attempts"],
+        text(&task["status"])
+    );
+    if let Some(executor_id) = task["executor_id"].as_str() {
+        line.push_str(&format!(" on executor {}", executor_id));
+    }
+    if let Some(run_time) = task["run_time"].as_u64() {
+        line.push_str(&format!(" after {} ms", run_time));
+    }
+    if let Some(error) = task["error"].as_str() {
+        line.push_str(&format!(": {}", error));
+    }
+    line
+}
+
+fn array(value: &Value) -> &[Value] {
+    value.as_array().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// A string value without quotes, or an empty string if there is none
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+/// Print `rows` in columns as wide as their widest value
+fn print_table(header: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.len());
+        }
+    }
+    let header: Vec<String> = header.iter().map(|h| h.to_string()).collect();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
@@ -18,9 +18,11 @@
 //! Command within CLI
 
 use crate::context::Context;
+use crate::exec::wait_for_job;
 use crate::functions::{display_all_functions, Function};
 use crate::print_format::PrintFormat;
 use crate::print_options::PrintOptions;
+use ballista::context::SubmittedQuery;
 use clap::ArgEnum;
 use datafusion::arrow::array::{ArrayRef, StringArray};
 use datafusion::arrow::datatypes::{DataType, Field, Schema};
@@ -41,6 +43,9 @@ pub enum Command {
     SearchFunctions(String),
     QuietMode(Option<bool>),
     OutputFormat(Option<String>),
+    SubmitQuery(String),
+    AttachJob(String),
+    CancelJob(String),
 }
 
 pub enum OutputFormat {
@@ -104,6 +109,29 @@ impl Command {
             Self::OutputFormat(_) => Err(DataFusionError::Execution(
                 "Unexpected change output format, this should be handled outside".into(),
             )),
+            Self::SubmitQuery(sql) => match ctx.submit_sql(sql).await? {
+                SubmittedQuery::Job(job_id) => {
+                    println!("Submitted job {}", job_id);
+                    Ok(())
+                }
+                SubmittedQuery::Output(batches) => print_options
+                    .print_batches(&batches, now)
+                    .map_err(|e| DataFusionError::Execution(e.to_string())),
+            },
+            Self::AttachJob(job_id) => {
+                let batches = wait_for_job(ctx, job_id, print_options.quiet).await?;
+                print_options
+                    .print_batches(&batches, now)
+                    .map_err(|e| DataFusionError::Execution(e.to_string()))
+            }
+            Self::CancelJob(job_id) => {
+                if ctx.cancel_job(job_id).await? {
+                    println!("Cancelled job {}", job_id);
+                } else {
+                    println!("Job {} already finished", job_id);
+                }
+                Ok(())
+            }
         }
     }
 
@@ -119,11 +147,19 @@ impl Command {
             Self::OutputFormat(_) => {
                 ("\\pset [NAME [VALUE]]", "set table output option\n(format)")
             }
+            Self::SubmitQuery(_) => (
+                "\\submit query",
+                "run query as a job without waiting for it",
+            ),
+            Self::AttachJob(_) => {
+                ("\\attach job_id", "wait for job and print its output")
+            }
+            Self::CancelJob(_) => ("\\cancel job_id", "cancel job"),
         }
     }
 }
 
-const ALL_COMMANDS: [Command; 8] = [
+const ALL_COMMANDS: [Command; 11] = [
     Command::ListTables,
     Command::DescribeTable(String::new()),
     Command::Quit,
@@ -132,6 +168,9 @@ const ALL_COMMANDS: [Command; 8] = [
     Command::SearchFunctions(String::new()),
     Command::QuietMode(None),
     Command::OutputFormat(None),
+    Command::SubmitQuery(String::new()),
+    Command::AttachJob(String::new()),
+    Command::CancelJob(String::new()),
 ];
 
 fn all_commands_info() -> RecordBatch {
@@ -180,6 +219,9 @@ impl FromStr for Command {
                 Self::OutputFormat(Some(subcommand.to_string()))
             }
             ("pset", None) => Self::OutputFormat(None),
+            ("submit", Some(sql)) => Self::SubmitQuery(sql.into()),
+            ("attach", Some(job_id)) => Self::AttachJob(job_id.into()),
+            ("cancel", Some(job_id)) => Self::CancelJob(job_id.into()),
             _ => return Err(()),
         })
     }
@@ -17,6 +17,9 @@
 
 //! Context (remote or local)
 
+use ballista::context::SubmittedQuery;
+use ballista_core::serde::protobuf::job_status;
+use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::dataframe::DataFrame;
 use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::{SessionConfig, SessionContext};
@@ -48,6 +51,56 @@ impl Context {
             Context::Remote(ballista) => ballista.sql(sql).await,
         }
     }
+
+    /// submit an SQL statement without waiting for the remote job running it, local
+    /// contexts return the output of the statement
+    pub async fn submit_sql(&mut self, sql: &str) -> Result<SubmittedQuery> {
+        match self {
+            Context::Local(datafusion) => Ok(SubmittedQuery::Output(
+                datafusion.sql(sql).await?.collect().await?,
+            )),
+            Context::Remote(ballista) => ballista.submit_sql(sql).await,
+        }
+    }
+
+    /// get the status of a remote job
+    pub async fn job_status(&self, job_id: &str) -> Result<job_status::Status> {
+        match self {
+            Context::Local(_) => Err(jobs_not_supported()),
+            Context::Remote(ballista) => ballista.job_status(job_id).await,
+        }
+    }
+
+    /// cancel a remote job, returning whether it was still queued or running
+    pub async fn cancel_job(&self, job_id: &str) -> Result<bool> {
+        match self {
+            Context::Local(_) => Err(jobs_not_supported()),
+            Context::Remote(ballista) => ballista.cancel_job(job_id).await,
+        }
+    }
+
+    /// fetch the output of a completed remote job
+    pub async fn job_output(&self, job_id: &str) -> Result<Vec<RecordBatch>> {
+        match self {
+            Context::Local(_) => Err(jobs_not_supported()),
+            Context::Remote(ballista) => ballista.job_output(job_id).await,
+        }
+    }
+
+    /// close the remote session, dropping its temporary tables
+    pub async fn close(&self) -> Result<()> {
+        match self {
+            Context::Local(_) => Ok(()),
+            Context::Remote(ballista) => ballista.close().await,
+        }
+    }
+}
+
+fn jobs_not_supported() -> DataFusionError {
+    DataFusionError::NotImplemented(
+        "Jobs are only run by a remote context, start the CLI with --host and --port"
+            .to_string(),
+    )
 }
 
 // implement wrappers around the BallistaContext to support running without ballista
@@ -72,6 +125,21 @@ impl BallistaContext {
     pub async fn sql(&mut self, sql: &str) -> Result<Arc<DataFrame>> {
         self.0.sql(sql).await
     }
+    pub async fn submit_sql(&mut self, sql: &str) -> Result<SubmittedQuery> {
+        self.0.submit_sql(sql).await
+    }
+    pub async fn job_status(&self, job_id: &str) -> Result<job_status::Status> {
+        self.0.job_status(job_id).await
+    }
+    pub async fn cancel_job(&self, job_id: &str) -> Result<bool> {
+        self.0.cancel_job(job_id).await
+    }
+    pub async fn job_output(&self, job_id: &str) -> Result<Vec<RecordBatch>> {
+        self.0.job_output(job_id).await
+    }
+    pub async fn close(&self) -> Result<()> {
+        self.0.close().await.map(|_| ())
+    }
 }
 
 #[cfg(not(feature = "ballista"))]
@@ -87,4 +155,19 @@ impl BallistaContext {
     pub async fn sql(&mut self, _sql: &str) -> Result<Arc<DataFrame>> {
         unreachable!()
     }
+    pub async fn submit_sql(&mut self, _sql: &str) -> Result<SubmittedQuery> {
+        unreachable!()
+    }
+    pub async fn job_status(&self, _job_id: &str) -> Result<job_status::Status> {
+        unreachable!()
+    }
+    pub async fn cancel_job(&self, _job_id: &str) -> Result<bool> {
+        unreachable!()
+    }
+    pub async fn job_output(&self, _job_id: &str) -> Result<Vec<RecordBatch>> {
+        unreachable!()
+    }
+    pub async fn close(&self) -> Result<()> {
+        unreachable!()
+    }
 }
@@ -23,13 +23,22 @@ use crate::{
     helper::CliHelper,
     print_options::PrintOptions,
 };
-use datafusion::error::Result;
+use ballista::context::SubmittedQuery;
+use ballista_core::serde::protobuf::{job_status, RunningJob};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+
+/// Interval between two polls of the status of a remote job
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Number of characters of the progress bar of remote jobs
+const PROGRESS_WIDTH: usize = 30;
 
 /// run and execute SQL statements and commands from a file, against a context with the given print options
 pub async fn exec_from_lines(
@@ -162,9 +171,100 @@ async fn exec_and_print(
     sql: String,
 ) -> Result<()> {
     let now = Instant::now();
-    let df = ctx.sql(&sql).await?;
-    let results = df.collect().await?;
+    let results = match ctx.submit_sql(&sql).await? {
+        SubmittedQuery::Job(job_id) => {
+            wait_for_job(ctx, &job_id, print_options.quiet).await?
+        }
+        SubmittedQuery::Output(results) => results,
+    };
     print_options.print_batches(&results, now)?;
 
     Ok(())
 }
+
+/// Wait for remote job `job_id` to complete and fetch its output, drawing the progress
+/// of the job on stderr unless `quiet`. Pressing Ctrl-C cancels the job.
+pub async fn wait_for_job(
+    ctx: &Context,
+    job_id: &str,
+    quiet: bool,
+) -> Result<Vec<RecordBatch>> {
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut progress = Progress {
+        quiet,
+        drawn: false,
+    };
+    loop {
+        match ctx.job_status(job_id).await? {
+            job_status::Status::Queued(_) => progress.draw(job_id, None),
+            job_status::Status::Running(running) => progress.draw(job_id, Some(&running)),
+            job_status::Status::Completed(_) => {
+                progress.clear();
+                return ctx.job_output(job_id).await;
+            }
+            job_status::Status::Failed(failed) => {
+                progress.clear();
+                return Err(DataFusionError::Execution(format!(
+                    "Job {} failed: {}",
+                    job_id, failed.error
+                )));
+            }
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(PROGRESS_INTERVAL) => {}
+            _ = &mut ctrl_c => {
+                progress.clear();
+                ctx.cancel_job(job_id).await?;
+                return Err(DataFusionError::Execution(format!(
+                    "Job {} cancelled",
+                    job_id
+                )));
+            }
+        }
+    }
+}
+
+/// The progress bar of a remote job, drawn over a single line of stderr
+struct Progress {
+    quiet: bool,
+    drawn: bool,
+}
+
+impl Progress {
+    fn draw(&mut self, job_id: &str, running: Option<&RunningJob>) {
+        if self.quiet {
+            return;
+        }
+        let line = match running {
+            Some(job) if job.total_tasks > 0 => {
+                let done = (PROGRESS_WIDTH * job.completed_tasks as usize
+                    / job.total_tasks as usize)
+                    .min(PROGRESS_WIDTH);
+                format!(
+                    "Job {} [{}{}] {}/{} tasks, {}/{} stages",
+                    job_id,
+                    "#".repeat(done),
+                    " ".repeat(PROGRESS_WIDTH - done),
+                    job.completed_tasks,
+                    job.total_tasks,
+                    job.completed_stages,
+                    job.total_stages
+                )
+            }
+            Some(_) => format!("Job {} running", job_id),
+            None => format!("Job {} queued", job_id),
+        };
+        eprint!("\r\x1b[2K{}", line);
+        std::io::stderr().flush().ok();
+        self.drawn = true;
+    }
+
+    fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            std::io::stderr().flush().ok();
+            self.drawn = false;
+        }
+    }
+}
@@ -18,6 +18,7 @@
 #![doc = include_str!("../README.md")]
 pub const BALLISTA_CLI_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+pub mod admin;
 pub mod command;
 pub mod context;
 pub mod exec;
@@ -16,11 +16,15 @@
 // under the License.
 
 use ballista_cli::{
-    context::Context, exec, print_format::PrintFormat, print_options::PrintOptions,
+    admin::{exec_admin_command, AdminClient, AdminCommand},
+    context::Context,
+    exec,
+    print_format::PrintFormat,
+    print_options::PrintOptions,
     BALLISTA_CLI_VERSION,
 };
-use clap::Parser;
-use datafusion::error::Result;
+use clap::{Parser, Subcommand};
+use datafusion::error::{DataFusionError, Result};
 use datafusion::execution::context::SessionConfig;
 use mimalloc::MiMalloc;
 use std::env;
@@ -76,12 +80,29 @@ struct Args {
     #[clap(long, help = "Ballista scheduler port")]
     port: Option<u16>,
 
+    #[clap(
+        long,
+        env = "BALLISTA_TOKEN",
+        help = "Bearer token sent to the scheduler by admin commands"
+    )]
+    token: Option<String>,
+
     #[clap(
         short,
         long,
         help = "Reduce printing other than the results and work quietly"
     )]
     quiet: bool,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand, PartialEq)]
+enum Command {
+    /// Administer the scheduler given by --host and --port instead of running SQL
+    #[clap(subcommand)]
+    Admin(AdminCommand),
 }
 
 #[tokio::main]
@@ -89,6 +110,18 @@ pub async fn main() -> Result<()> {
     env_logger::init();
     let args = Args::parse();
 
+    if let Some(Command::Admin(command)) = args.command {
+        let client = match (args.host, args.port) {
+            (Some(ref h), Some(p)) => AdminClient::new(h, p, args.token),
+            _ => {
+                return Err(DataFusionError::Execution(
+                    "Admin commands require --host and --port".to_owned(),
+                ))
+            }
+        };
+        return exec_admin_command(&client, command).await;
+    }
+
     if !args.quiet {
         println!("Ballista CLI v{}", BALLISTA_CLI_VERSION);
     }
@@ -137,6 +170,8 @@ pub async fn main() -> Result<()> {
         }
         exec::exec_from_repl(&mut ctx, &mut print_options).await;
     }
+    // Drop the temporary tables of the session
+    ctx.close().await?;
 
     Ok(())
 }
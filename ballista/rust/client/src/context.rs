@@ -19,27 +19,61 @@
 
 use log::info;
 use parking_lot::Mutex;
-use sqlparser::ast::Statement;
-use std::collections::HashMap;
+use sqlparser::ast::{Ident, Statement, Value};
+use sqlparser::dialect::keywords::Keyword;
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
-
-use ballista_core::config::BallistaConfig;
+use uuid::Uuid;
+
+use ballista_core::auth::authorized_request;
+use ballista_core::config::{
+    BallistaConfig, BALLISTA_ANALYZE_SAMPLE_PERCENT, BALLISTA_ANALYZE_TABLE,
+    BALLISTA_CACHE_TABLE, BALLISTA_CACHE_TEMPORARY, BALLISTA_OBJECT_STORE_CREDENTIALS,
+    BALLISTA_REWRITE_COMPACT, BALLISTA_REWRITE_PREDICATE, BALLISTA_REWRITE_TABLE,
+    BALLISTA_WRITE_PARQUET_PARTITION_BY, BALLISTA_WRITE_PARQUET_PATH,
+};
+use ballista_core::execution_plans::{
+    fetch_job_output, DistributedQueryExec, ParquetSinkExec,
+};
+use ballista_core::object_store_registry::{
+    ObjectStoreCredentials, ObjectStoreFactories,
+};
 use ballista_core::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
-use ballista_core::serde::protobuf::{ExecuteQueryParams, KeyValuePair};
+use ballista_core::serde::protobuf::{
+    job_status, CancelJobParams, CatalogTable, CloseSessionParams, ExecuteQueryParams,
+    GetJobStatusParams, GetJobStatusResult, GetTableParams, KeyValuePair,
+    RegisterTableParams, UncacheTableParams,
+};
+use ballista_core::table_format::cache::{temporary_table_location, CACHE};
+use ballista_core::table_format::time_travel::{parse_time_travel, time_travel_context};
+use ballista_core::table_format::{iceberg, manifest, ExternalTable, TableFormats};
+use ballista_core::tls::{create_grpc_channel, grpc_url};
 use ballista_core::utils::create_df_ctx_with_ballista_query_planner;
 use datafusion_proto::protobuf::LogicalPlanNode;
+use futures::TryStreamExt;
 
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::catalog::catalog::CatalogProvider;
 use datafusion::catalog::TableReference;
 use datafusion::dataframe::DataFrame;
-use datafusion::datasource::TableProvider;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::datasource::{MemTable, TableProvider};
 use datafusion::error::{DataFusionError, Result};
 use datafusion::logical_plan::{
-    source_as_provider, CreateExternalTable, FileType, LogicalPlan, TableScan,
+    source_as_provider, Column, CreateExternalTable, DFSchema, EmptyRelation, Expr,
+    FileType, LogicalPlan, TableScan,
 };
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::ExecutionPlan;
 use datafusion::prelude::{
-    AvroReadOptions, CsvReadOptions, ParquetReadOptions, SessionConfig, SessionContext,
+    AvroReadOptions, CsvReadOptions, NdJsonReadOptions, ParquetReadOptions,
+    SessionConfig, SessionContext,
 };
 use datafusion::sql::parser::{DFParser, Statement as DFStatement};
 
@@ -52,6 +86,8 @@ struct BallistaContextState {
     scheduler_port: u16,
     /// Tables that have been registered with this context
     tables: HashMap<String, Arc<dyn TableProvider>>,
+    /// Catalogs that have been registered with this context
+    catalogs: HashMap<String, Arc<dyn CatalogProvider>>,
 }
 
 impl BallistaContextState {
@@ -65,6 +101,7 @@ impl BallistaContextState {
             scheduler_host,
             scheduler_port,
             tables: HashMap::new(),
+            catalogs: HashMap::new(),
         }
     }
 
@@ -87,18 +124,19 @@ impl BallistaContext {
     ) -> ballista_core::error::Result<Self> {
         let state = BallistaContextState::new(host.to_owned(), port, config);
 
-        let scheduler_url =
-            format!("http://{}:{}", &state.scheduler_host, state.scheduler_port);
+        let scheduler_url = grpc_url(&state.scheduler_host, state.scheduler_port);
         info!(
             "Connecting to Ballista scheduler at {}",
             scheduler_url.clone()
         );
-        let mut scheduler = SchedulerGrpcClient::connect(scheduler_url.clone())
-            .await
-            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
 
         let remote_session_id = scheduler
-            .execute_query(ExecuteQueryParams {
+            .execute_query(authorized_request(ExecuteQueryParams {
                 query: None,
                 settings: config
                     .settings()
@@ -109,7 +147,9 @@ impl BallistaContext {
                     })
                     .collect::<Vec<_>>(),
                 optional_session_id: None,
-            })
+                trace_context: vec![],
+                job_id: String::new(),
+            }))
             .await
             .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
             .into_inner()
@@ -168,6 +208,8 @@ impl BallistaContext {
                     })
                     .collect::<Vec<_>>(),
                 optional_session_id: None,
+                trace_context: vec![],
+                job_id: String::new(),
             })
             .await
             .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
@@ -222,6 +264,22 @@ impl BallistaContext {
         Ok(df)
     }
 
+    /// Create a DataFrame representing a scan of newline-delimited JSON files
+    /// TODO fetch schema from scheduler instead of resolving locally
+    pub async fn read_json(
+        &self,
+        path: &str,
+        options: NdJsonReadOptions<'_>,
+    ) -> Result<Arc<DataFrame>> {
+        // convert to absolute path because the executor likely has a different working directory
+        let path = PathBuf::from(path);
+        let path = fs::canonicalize(&path)?;
+
+        let ctx = self.context.clone();
+        let df = ctx.read_json(path.to_str().unwrap(), options).await?;
+        Ok(df)
+    }
+
     /// Create a DataFrame representing a Parquet table scan
     /// TODO fetch schema from scheduler instead of resolving locally
     pub async fn read_parquet(
@@ -285,6 +343,19 @@ impl BallistaContext {
         path: &str,
         options: ParquetReadOptions<'_>,
     ) -> Result<()> {
+        // Only the committed files of the output of COPY statements are read. The URL
+        // of the location is absolute, like the paths of other Parquet tables.
+        if manifest::has_manifest(path, &self.context.runtime_env()).await? {
+            let location = ListingTableUrl::parse(path)?.to_string();
+            return self
+                .register_external_table(
+                    name,
+                    manifest::MANIFEST,
+                    &location,
+                    BTreeMap::new(),
+                )
+                .await;
+        }
         match self.read_parquet(path, options).await?.to_logical_plan()? {
             LogicalPlan::TableScan(TableScan { source, .. }) => {
                 self.register_table(name, source_as_provider(&source)?)
@@ -307,6 +378,206 @@ impl BallistaContext {
         }
     }
 
+    pub async fn register_json(
+        &self,
+        name: &str,
+        path: &str,
+        options: NdJsonReadOptions<'_>,
+    ) -> Result<()> {
+        match self.read_json(path, options).await?.to_logical_plan()? {
+            LogicalPlan::TableScan(TableScan { source, .. }) => {
+                self.register_table(name, source_as_provider(&source)?)
+            }
+            _ => Err(DataFusionError::Internal("Expected tables scan".to_owned())),
+        }
+    }
+
+    /// Register a table stored in a table format such as `delta`. Queries scanning it
+    /// are planned by the scheduler, which reads the files to scan from the table.
+    pub async fn register_external_table(
+        &self,
+        name: &str,
+        format: &str,
+        location: &str,
+        options: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let table = ExternalTable::try_new(
+            &TableFormats::builtin(),
+            format,
+            location,
+            options,
+            self.context.runtime_env(),
+        )
+        .await?;
+        self.register_table(name, Arc::new(table))
+    }
+
+    /// Upload `partitions` of rows of `schema` to the cluster as table `name`, so that
+    /// queries can join them with the tables of the cluster. Each partition is streamed
+    /// to a Parquet file under `location`, a directory of an object store the executors
+    /// can read, and the files are committed with a manifest like the output of `COPY`
+    /// statements. Uploading to the same location again replaces the table and deletes
+    /// the files of the previous upload. The stores configured with
+    /// `ballista.object_store.credentials` are used for the upload as well.
+    ///
+    /// The table is registered in this context and in the catalog of the scheduler,
+    /// where other clients find it with [BallistaContext::register_catalog_table].
+    pub async fn upload_table(
+        &self,
+        name: &str,
+        location: &str,
+        schema: SchemaRef,
+        partitions: Vec<Vec<RecordBatch>>,
+    ) -> Result<()> {
+        let credentials = self
+            .state
+            .lock()
+            .config
+            .settings()
+            .get(BALLISTA_OBJECT_STORE_CREDENTIALS)
+            .filter(|json| !json.is_empty())
+            .map(|json| ObjectStoreCredentials::from_json(json))
+            .transpose()
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .unwrap_or_default();
+        let runtime = self.context.runtime_env();
+        ObjectStoreFactories::builtin()
+            .register_stores(runtime.as_ref(), &credentials)
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
+
+        // The manifest of an output without files can not be read as a table
+        let has_rows = partitions
+            .iter()
+            .flatten()
+            .any(|batch| batch.num_rows() > 0);
+        if !has_rows {
+            return Err(DataFusionError::Plan(format!(
+                "Cannot upload table {} without rows",
+                name
+            )));
+        }
+        let location = ListingTableUrl::parse(location)?.to_string();
+        // Files are named after the write, so they never overwrite the files of earlier
+        // uploads, which are only deleted once the new files are committed
+        let write_id = format!("upload-{}", Uuid::new_v4());
+        let input = Arc::new(MemoryExec::try_new(&partitions, schema, None)?);
+        let sink = ParquetSinkExec::try_new(input, location.clone(), vec![], write_id)?;
+        let task_ctx = self.context.task_ctx();
+        for partition in 0..partitions.len() {
+            sink.execute(partition, task_ctx.clone())?
+                .try_collect::<Vec<_>>()
+                .await?;
+        }
+        let manifest = sink.commit(&runtime, &vec![0; partitions.len()]).await?;
+        info!(
+            "Uploaded {} files of table {} to {}",
+            manifest.files.len(),
+            name,
+            location
+        );
+        self.register_external_table(
+            name,
+            manifest::MANIFEST,
+            &location,
+            BTreeMap::new(),
+        )
+        .await?;
+
+        let (scheduler_url, schema) = {
+            let state = self.state.lock();
+            (
+                grpc_url(&state.scheduler_host, state.scheduler_port),
+                state.tables[name].schema(),
+            )
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        scheduler
+            .register_table(authorized_request(RegisterTableParams {
+                table: Some(CatalogTable {
+                    name: name.to_owned(),
+                    format: manifest::MANIFEST.to_owned(),
+                    location,
+                    schema: Some(schema.as_ref().into()),
+                    owner: String::new(),
+                }),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
+        Ok(())
+    }
+
+    /// Register table `name` of the catalog of the scheduler, uploaded by this or another
+    /// client with [BallistaContext::upload_table]
+    pub async fn register_catalog_table(&self, name: &str) -> Result<()> {
+        let scheduler_url = {
+            let state = self.state.lock();
+            grpc_url(&state.scheduler_host, state.scheduler_port)
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        let table = scheduler
+            .get_table(authorized_request(GetTableParams {
+                name: name.to_owned(),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .into_inner()
+            .table
+            .ok_or_else(|| {
+                DataFusionError::Plan(format!(
+                    "Table {} is not in the catalog of the scheduler",
+                    name
+                ))
+            })?;
+        let schema: Schema = table
+            .schema
+            .as_ref()
+            .ok_or_else(|| {
+                DataFusionError::Internal(format!("Table {} has no schema", name))
+            })?
+            .try_into()?;
+        // The scheduler opens the table, so the client does not need to reach its files
+        let table = ExternalTable::with_schema(
+            &table.format,
+            &table.location,
+            BTreeMap::new(),
+            Arc::new(schema),
+        );
+        self.register_table(name, Arc::new(table))
+    }
+
+    /// Register the tables of a namespace of an Iceberg Hadoop catalog, which stores
+    /// every table in a directory `<warehouse>/<namespace>/<table>`. Returns the names
+    /// of the tables.
+    pub async fn register_iceberg_namespace(
+        &self,
+        warehouse: &str,
+        namespace: &str,
+    ) -> Result<Vec<String>> {
+        let tables =
+            iceberg::list_tables(warehouse, namespace, self.context.runtime_env())
+                .await?;
+        for (name, location) in &tables {
+            self.register_external_table(name, "iceberg", location, BTreeMap::new())
+                .await?;
+        }
+        Ok(tables.into_iter().map(|(name, _)| name).collect())
+    }
+
+    /// Register a catalog, whose tables are referenced as `<name>.<schema>.<table>`.
+    /// Its tables are looked up when queries are planned by this context.
+    pub fn register_catalog(&self, name: &str, catalog: Arc<dyn CatalogProvider>) {
+        let mut state = self.state.lock();
+        state.catalogs.insert(name.to_owned(), catalog);
+    }
+
     /// is a 'show *' sql
     pub async fn is_show_statement(&self, sql: &str) -> Result<bool> {
         let mut is_show_variable: bool = false;
@@ -343,6 +614,84 @@ impl BallistaContext {
     pub async fn sql(&self, sql: &str) -> Result<Arc<DataFrame>> {
         let mut ctx = self.context.clone();
 
+        // DataFusion does not know the table formats, so their tables are created here
+        if let Some(create) = parse_create_format_table(sql, &TableFormats::builtin())? {
+            let table_exists = self.state.lock().tables.contains_key(&create.name);
+            match (create.if_not_exists, table_exists) {
+                (_, false) => {
+                    self.register_external_table(
+                        &create.name,
+                        &create.format,
+                        &create.location,
+                        create.options,
+                    )
+                    .await?
+                }
+                (true, true) => {}
+                (false, true) => {
+                    return Err(DataFusionError::Execution(format!(
+                        "Table '{:?}' already exists",
+                        create.name
+                    )))
+                }
+            }
+            let plan = LogicalPlan::EmptyRelation(EmptyRelation {
+                produce_one_row: false,
+                schema: Arc::new(DFSchema::empty()),
+            });
+            return Ok(Arc::new(DataFrame::new(ctx.state.clone(), &plan)));
+        }
+        if let Some(copy) = parse_copy(sql)? {
+            return self.copy(copy).await;
+        }
+        if let Some(change) = parse_table_change(sql)? {
+            return self.change_table(change).await;
+        }
+        if let Some(name) = parse_optimize_table(sql)? {
+            return self.optimize_table(&name).await;
+        }
+        if let Some(statement) = parse_cache_statement(sql)? {
+            match statement {
+                CacheStatement::Cache { name, query } => {
+                    self.cache_table(&name, query.as_deref()).await?
+                }
+                CacheStatement::CreateTemporary { name, query } => {
+                    self.create_temporary_table(&name, &query).await?
+                }
+                CacheStatement::Uncache { name, if_exists } => {
+                    if !self.uncache_table(&name).await? && !if_exists {
+                        return Err(DataFusionError::Plan(format!(
+                            "Table {} is not cached",
+                            name
+                        )));
+                    }
+                }
+            }
+            let plan = LogicalPlan::EmptyRelation(EmptyRelation {
+                produce_one_row: false,
+                schema: Arc::new(DFSchema::empty()),
+            });
+            return Ok(Arc::new(DataFrame::new(ctx.state.clone(), &plan)));
+        }
+        if let Some(analyze) = parse_analyze_table(sql)? {
+            self.analyze_table(&analyze.name, analyze.sample_percent)
+                .await?;
+            let plan = LogicalPlan::EmptyRelation(EmptyRelation {
+                produce_one_row: false,
+                schema: Arc::new(DFSchema::empty()),
+            });
+            return Ok(Arc::new(DataFrame::new(ctx.state.clone(), &plan)));
+        }
+
+        if let Some(query) = parse_time_travel(sql)? {
+            let ctx = self.job_context(&[])?;
+            let formats = TableFormats::builtin();
+            return time_travel_context(&ctx, &query.tables, &formats)
+                .await?
+                .sql(&query.sql)
+                .await;
+        }
+
         let is_show = self.is_show_statement(sql).await?;
         // the show tables、 show columns sql can not run at scheduler because the tables is store at client
         if is_show {
@@ -367,6 +716,9 @@ impl BallistaContext {
                     )?;
                 }
             }
+            for (name, catalog) in &state.catalogs {
+                ctx.register_catalog(name, Arc::clone(catalog));
+            }
         }
 
         let plan = ctx.create_logical_plan(sql)?;
@@ -419,6 +771,22 @@ impl BallistaContext {
                             .await?;
                             Ok(Arc::new(DataFrame::new(ctx.state.clone(), &plan)))
                         }
+                        FileType::NdJson => {
+                            let schema: Arc<Schema> =
+                                Arc::new(schema.as_ref().to_owned().into());
+                            self.register_json(
+                                name,
+                                location,
+                                NdJsonReadOptions {
+                                    schema: Some(schema)
+                                        .filter(|schema| !schema.fields().is_empty()),
+                                    table_partition_cols: table_partition_cols.to_vec(),
+                                    ..Default::default()
+                                },
+                            )
+                            .await?;
+                            Ok(Arc::new(DataFrame::new(ctx.state.clone(), &plan)))
+                        }
                         _ => Err(DataFusionError::NotImplemented(format!(
                             "Unsupported file type {:?}.",
                             file_type
@@ -436,10 +804,1007 @@ impl BallistaContext {
             _ => ctx.sql(sql).await,
         }
     }
+
+    /// Run the query of a `COPY` statement, whose output is written to Parquet files by
+    /// the executors, and return the files it wrote
+    async fn copy(&self, copy: CopyToParquet) -> Result<Arc<DataFrame>> {
+        let partition_by = copy.partition_by.join(",");
+        let ctx = self.job_context(&[
+            (BALLISTA_WRITE_PARQUET_PATH, copy.location.as_str()),
+            (BALLISTA_WRITE_PARQUET_PARTITION_BY, partition_by.as_str()),
+        ])?;
+        let files = ctx.sql(&copy.query).await?.collect().await?;
+        let table = MemTable::try_new(ParquetSinkExec::output_schema(), vec![files])?;
+        self.context.read_table(Arc::new(table))
+    }
+
+    /// Run a `DELETE` or `UPDATE` statement on a table of a table format, as a job
+    /// rewriting the files of the table holding the rows it changes. The scheduler
+    /// commits the new files in place of the rewritten ones once the job completes.
+    /// Returns the files the job wrote.
+    async fn change_table(&self, change: TableChange) -> Result<Arc<DataFrame>> {
+        let table = self.state.lock().tables.get(&change.table).cloned();
+        let schema = table
+            .as_ref()
+            .and_then(|table| table.as_any().downcast_ref::<ExternalTable>())
+            .map(|table| table.schema())
+            .ok_or_else(|| {
+                DataFusionError::Plan(format!(
+                    "DELETE and UPDATE statements can only change the tables of table \
+                     formats, which {} is not",
+                    change.table
+                ))
+            })?;
+        let table_name = Ident::with_quote('"', change.table.as_str()).to_string();
+        let quoted = |column: &str| Ident::with_quote('"', column).to_string();
+
+        let query = match &change.assignments {
+            // The rows which do not match the predicate are kept, including those for
+            // which it is null
+            None => format!(
+                "SELECT * FROM {} WHERE CASE WHEN {} THEN false ELSE true END",
+                table_name,
+                change.predicate.as_deref().unwrap_or("true")
+            ),
+            Some(assignments) => {
+                for (column, _) in assignments {
+                    schema.index_of(column).map_err(|_| {
+                        DataFusionError::Plan(format!(
+                            "Table {} has no column {}",
+                            change.table, column
+                        ))
+                    })?;
+                }
+                let values: Vec<String> = schema
+                    .fields()
+                    .iter()
+                    .map(|field| {
+                        let column = quoted(field.name());
+                        let value = assignments
+                            .iter()
+                            .find(|(name, _)| name == field.name())
+                            .map(|(_, value)| match &change.predicate {
+                                Some(predicate) => format!(
+                                    "CASE WHEN {} THEN {} ELSE {} END",
+                                    predicate, value, column
+                                ),
+                                None => value.clone(),
+                            })
+                            .unwrap_or_else(|| column.clone());
+                        format!("{} AS {}", value, column)
+                    })
+                    .collect();
+                format!("SELECT {} FROM {}", values.join(", "), table_name)
+            }
+        };
+
+        let ctx = self.job_context(&[
+            (BALLISTA_REWRITE_TABLE, change.table.as_str()),
+            (
+                BALLISTA_REWRITE_PREDICATE,
+                change.predicate.as_deref().unwrap_or(""),
+            ),
+        ])?;
+        // Updated values are written with the types of their columns
+        let casts: Vec<Expr> = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                Expr::Cast {
+                    expr: Box::new(Expr::Column(Column::from_name(field.name()))),
+                    data_type: field.data_type().clone(),
+                }
+                .alias(field.name())
+            })
+            .collect();
+        let files = ctx.sql(&query).await?.select(casts)?.collect().await?;
+        let table = MemTable::try_new(ParquetSinkExec::output_schema(), vec![files])?;
+        self.context.read_table(Arc::new(table))
+    }
+
+    /// Compact the small files of table `name` of a table format, as a job coalescing
+    /// the files of each of its partitions into files of about
+    /// `ballista.compact.target_file_bytes` bytes. The scheduler commits the new files
+    /// in place of the coalesced ones once the job completes. Returns the files the
+    /// job wrote.
+    pub async fn optimize_table(&self, name: &str) -> Result<Arc<DataFrame>> {
+        let table = self.state.lock().tables.get(name).cloned();
+        if !table
+            .as_ref()
+            .map(|table| table.as_any().is::<ExternalTable>())
+            .unwrap_or(false)
+        {
+            return Err(DataFusionError::Plan(format!(
+                "OPTIMIZE TABLE can only compact the tables of table formats, which {} \
+                 is not",
+                name
+            )));
+        }
+        let ctx = self.job_context(&[
+            (BALLISTA_REWRITE_TABLE, name),
+            (BALLISTA_REWRITE_COMPACT, "true"),
+        ])?;
+        let query = format!("SELECT * FROM {}", Ident::with_quote('"', name));
+        let files = ctx.sql(&query).await?.collect().await?;
+        let table = MemTable::try_new(ParquetSinkExec::output_schema(), vec![files])?;
+        self.context.read_table(Arc::new(table))
+    }
+
+    /// Run `query` on the executors and keep its output on them as table `name`, or the
+    /// rows of table `name` if there is no query. Queries scanning the table read the
+    /// partitions kept by the executors instead of its source.
+    pub async fn cache_table(&self, name: &str, query: Option<&str>) -> Result<()> {
+        let query = query
+            .map(str::to_owned)
+            .unwrap_or_else(|| format!("SELECT * FROM {}", name));
+        self.cache_query(name, &query, false).await
+    }
+
+    /// Run `query` on the executors and keep its output on them as temporary table
+    /// `name` of the session. The table is only visible to this session, and is dropped
+    /// along with the partitions the executors keep for it when the session is closed.
+    pub async fn create_temporary_table(&self, name: &str, query: &str) -> Result<()> {
+        self.cache_query(name, query, true).await
+    }
+
+    async fn cache_query(&self, name: &str, query: &str, temporary: bool) -> Result<()> {
+        let ctx = self.job_context(&[
+            (BALLISTA_CACHE_TABLE, name),
+            (
+                BALLISTA_CACHE_TEMPORARY,
+                if temporary { "true" } else { "false" },
+            ),
+        ])?;
+        let df = ctx.sql(query).await?;
+        let schema: Schema = df.schema().clone().into();
+        // The job returns no rows, its output is kept by the executors
+        df.collect().await?;
+
+        let location = if temporary {
+            temporary_table_location(&self.context.session_id(), name)
+        } else {
+            name.to_owned()
+        };
+        let table = ExternalTable::with_schema(
+            CACHE,
+            &location,
+            BTreeMap::new(),
+            Arc::new(schema),
+        );
+        self.register_table(name, Arc::new(table))?;
+        // Queries are planned with the cached table from now on
+        self.context.deregister_table(name)?;
+        Ok(())
+    }
+
+    /// Drop cached or temporary table `name` along with the partitions the executors
+    /// keep for it. Returns whether the table was cached.
+    pub async fn uncache_table(&self, name: &str) -> Result<bool> {
+        let (scheduler_url, location) = {
+            let state = self.state.lock();
+            let location = state
+                .tables
+                .get(name)
+                .and_then(|table| {
+                    table
+                        .as_any()
+                        .downcast_ref::<ExternalTable>()
+                        .filter(|table| table.format() == CACHE)
+                        .map(|table| table.location().to_owned())
+                })
+                .unwrap_or_else(|| name.to_owned());
+            (
+                grpc_url(&state.scheduler_host, state.scheduler_port),
+                location,
+            )
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        let found = scheduler
+            .uncache_table(authorized_request(UncacheTableParams {
+                name: location,
+                session_id: self.context.session_id(),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .into_inner()
+            .found;
+
+        let registered = {
+            let mut state = self.state.lock();
+            let cached = state.tables.get(name).map_or(false, |table| {
+                table
+                    .as_any()
+                    .downcast_ref::<ExternalTable>()
+                    .map_or(false, |table| table.format() == CACHE)
+            });
+            if cached {
+                state.tables.remove(name);
+            }
+            cached
+        };
+        if registered {
+            self.context.deregister_table(name)?;
+        }
+        Ok(found)
+    }
+
+    /// Close the session on the scheduler, dropping its temporary tables along with the
+    /// partitions the executors keep for them. Returns the names of the dropped tables.
+    pub async fn close(&self) -> Result<Vec<String>> {
+        let session_id = self.context.session_id();
+        let scheduler_url = {
+            let state = self.state.lock();
+            grpc_url(&state.scheduler_host, state.scheduler_port)
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        let dropped_tables = scheduler
+            .close_session(authorized_request(CloseSessionParams {
+                session_id: session_id.clone(),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .into_inner()
+            .dropped_tables;
+
+        let prefix = temporary_table_location(&session_id, "");
+        let temporary: Vec<String> = {
+            let mut state = self.state.lock();
+            let temporary: Vec<String> = state
+                .tables
+                .iter()
+                .filter(|(_, table)| {
+                    table.as_any().downcast_ref::<ExternalTable>().map_or(
+                        false,
+                        |table| {
+                            table.format() == CACHE
+                                && table.location().starts_with(&prefix)
+                        },
+                    )
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in &temporary {
+                state.tables.remove(name);
+            }
+            temporary
+        };
+        for name in &temporary {
+            self.context.deregister_table(name.as_str())?;
+        }
+        Ok(dropped_tables)
+    }
+
+    /// Estimate the number of rows of table `name`, and the null count, number of
+    /// distinct values and range of its columns, from a random sample of
+    /// `sample_percent` percent of its rows, or from all of them. The table is
+    /// aggregated by a job, from whose output the scheduler estimates the statistics
+    /// and saves them under the location of the table. They are used to plan the later
+    /// queries scanning the table.
+    pub async fn analyze_table(
+        &self,
+        name: &str,
+        sample_percent: Option<f64>,
+    ) -> Result<()> {
+        let sample_percent = sample_percent.unwrap_or(100.0);
+        if !(sample_percent > 0.0 && sample_percent <= 100.0) {
+            return Err(DataFusionError::Plan(format!(
+                "Cannot sample {} percent of table {}",
+                sample_percent, name
+            )));
+        }
+        let ctx = self.job_context(&[
+            (BALLISTA_ANALYZE_TABLE, "true"),
+            (BALLISTA_ANALYZE_SAMPLE_PERCENT, &sample_percent.to_string()),
+        ])?;
+        ctx.table(name)?.collect().await?;
+        Ok(())
+    }
+
+    /// Submit `sql` without waiting for its job to complete. Queries run by the
+    /// scheduler return the ID of their job, whose status can be polled with
+    /// [Self::job_status] and output fetched with [Self::job_output]. Statements run
+    /// by the client, such as `CREATE EXTERNAL TABLE`, return their output right away.
+    pub async fn submit_sql(&self, sql: &str) -> Result<SubmittedQuery> {
+        let df = self.sql(sql).await?;
+        let plan = df.create_physical_plan().await?;
+        match plan
+            .as_any()
+            .downcast_ref::<DistributedQueryExec<LogicalPlanNode>>()
+        {
+            Some(exec) => Ok(SubmittedQuery::Job(exec.submit().await?)),
+            None => Ok(SubmittedQuery::Output(df.collect().await?)),
+        }
+    }
+
+    /// The status of job `job_id`, including the progress of running jobs
+    pub async fn job_status(&self, job_id: &str) -> Result<job_status::Status> {
+        let scheduler_url = {
+            let state = self.state.lock();
+            grpc_url(&state.scheduler_host, state.scheduler_port)
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        let GetJobStatusResult { status } = scheduler
+            .get_job_status(authorized_request(GetJobStatusParams {
+                job_id: job_id.to_owned(),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .into_inner();
+        status.and_then(|s| s.status).ok_or_else(|| {
+            DataFusionError::Execution(format!("Job {} has no status", job_id))
+        })
+    }
+
+    /// Cancel job `job_id`. Returns whether the job was still queued or running.
+    pub async fn cancel_job(&self, job_id: &str) -> Result<bool> {
+        let scheduler_url = {
+            let state = self.state.lock();
+            grpc_url(&state.scheduler_host, state.scheduler_port)
+        };
+        let mut scheduler = SchedulerGrpcClient::new(
+            create_grpc_channel(&scheduler_url)
+                .await
+                .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+        );
+        let result = scheduler
+            .cancel_job(authorized_request(CancelJobParams {
+                job_id: job_id.to_owned(),
+            }))
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
+            .into_inner();
+        Ok(result.cancelled)
+    }
+
+    /// Fetch the output of completed job `job_id` from the executors keeping it
+    pub async fn job_output(&self, job_id: &str) -> Result<Vec<RecordBatch>> {
+        let compression = self.state.lock().config.results_compression();
+        match self.job_status(job_id).await? {
+            job_status::Status::Completed(completed) => {
+                Ok(fetch_job_output(completed.partition_location, compression)
+                    .try_collect()
+                    .await?)
+            }
+            job_status::Status::Failed(failed) => Err(DataFusionError::Execution(
+                format!("Job {} failed: {}", job_id, failed.error),
+            )),
+            _ => Err(DataFusionError::Execution(format!(
+                "Job {} has not completed",
+                job_id
+            ))),
+        }
+    }
+
+    /// A context planning queries with the scheduler, which runs them with the settings
+    /// of this context along with `settings`
+    fn job_context(&self, settings: &[(&str, &str)]) -> Result<SessionContext> {
+        let state = self.state.lock();
+        let mut builder = BallistaConfig::builder();
+        for (key, value) in state.config.settings() {
+            builder = builder.set(key, value);
+        }
+        for (key, value) in settings {
+            builder = builder.set(key, value);
+        }
+        let config = builder
+            .build()
+            .map_err(|e| DataFusionError::Plan(e.to_string()))?;
+        let ctx = create_df_ctx_with_ballista_query_planner::<LogicalPlanNode>(
+            grpc_url(&state.scheduler_host, state.scheduler_port),
+            self.context.session_id(),
+            &config,
+        );
+        for (name, table) in &state.tables {
+            ctx.register_table(name.as_str(), Arc::clone(table))?;
+        }
+        for (name, catalog) in &state.catalogs {
+            ctx.register_catalog(name, Arc::clone(catalog));
+        }
+        Ok(ctx)
+    }
+}
+
+/// A query submitted with [BallistaContext::submit_sql]
+#[derive(Debug)]
+pub enum SubmittedQuery {
+    /// The ID of the job running the query
+    Job(String),
+    /// The output of a statement run by the client
+    Output(Vec<RecordBatch>),
+}
+
+/// A `COPY` statement writing the output of a query to Parquet files
+#[derive(Debug, PartialEq)]
+struct CopyToParquet {
+    query: String,
+    location: String,
+    partition_by: Vec<String>,
+}
+
+/// Parse a statement writing the output of a query to Parquet files, in hive-style
+/// directories of the values of the `PARTITIONED BY` columns:
+///
+/// ```sql
+/// COPY (query) TO 'location' [STORED AS PARQUET] [PARTITIONED BY (column, ...)]
+/// ```
+///
+/// Returns `None` for any other statement.
+fn parse_copy(sql: &str) -> Result<Option<CopyToParquet>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    if !parser.parse_keyword(Keyword::COPY) || !parser.consume_token(&Token::LParen) {
+        return Ok(None);
+    }
+    let query = parser.parse_query()?.to_string();
+    parser.expect_token(&Token::RParen)?;
+    parser.expect_keyword(Keyword::TO)?;
+    let location = parser.parse_literal_string()?;
+    if parser.parse_keywords(&[Keyword::STORED, Keyword::AS]) {
+        let format = parser.parse_identifier()?.value;
+        if !format.eq_ignore_ascii_case("parquet") {
+            return Err(DataFusionError::NotImplemented(format!(
+                "COPY cannot write files stored as {}",
+                format
+            )));
+        }
+    }
+    let mut partition_by = vec![];
+    if parser.parse_keywords(&[Keyword::PARTITIONED, Keyword::BY]) {
+        parser.expect_token(&Token::LParen)?;
+        partition_by = parser
+            .parse_comma_separated(Parser::parse_identifier)?
+            .into_iter()
+            .map(|column| column.value)
+            .collect();
+        parser.expect_token(&Token::RParen)?;
+    }
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} at the end of COPY statement",
+            token
+        )));
+    }
+
+    Ok(Some(CopyToParquet {
+        query,
+        location,
+        partition_by,
+    }))
+}
+
+/// A `DELETE` or `UPDATE` statement changing the rows of a table
+#[derive(Debug, PartialEq)]
+struct TableChange {
+    table: String,
+    /// The columns an `UPDATE` sets and their new values as SQL expressions, `None` for
+    /// a `DELETE`
+    assignments: Option<Vec<(String, String)>>,
+    /// SQL predicate of the changed rows, all of them if `None`
+    predicate: Option<String>,
+}
+
+/// Parse a statement deleting or updating the rows of a table matching a predicate:
+///
+/// ```sql
+/// DELETE FROM name [WHERE predicate]
+/// UPDATE name SET column = value [, ...] [WHERE predicate]
+/// ```
+///
+/// Returns `None` for any other statement.
+fn parse_table_change(sql: &str) -> Result<Option<TableChange>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    let (table, assignments) = if parser.parse_keyword(Keyword::DELETE) {
+        parser.expect_keyword(Keyword::FROM)?;
+        (parser.parse_identifier()?.value, None)
+    } else if parser.parse_keyword(Keyword::UPDATE) {
+        let table = parser.parse_identifier()?.value;
+        parser.expect_keyword(Keyword::SET)?;
+        let assignments = parser.parse_comma_separated(|parser| {
+            let column = parser.parse_identifier()?.value;
+            parser.expect_token(&Token::Eq)?;
+            Ok((column, parser.parse_expr()?.to_string()))
+        })?;
+        (table, Some(assignments))
+    } else {
+        return Ok(None);
+    };
+    let predicate = if parser.parse_keyword(Keyword::WHERE) {
+        Some(parser.parse_expr()?.to_string())
+    } else {
+        None
+    };
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} at the end of {} statement",
+            token,
+            if assignments.is_some() {
+                "UPDATE"
+            } else {
+                "DELETE"
+            }
+        )));
+    }
+    Ok(Some(TableChange {
+        table,
+        assignments,
+        predicate,
+    }))
+}
+
+/// Parse a statement compacting the small files of a table:
+///
+/// ```sql
+/// OPTIMIZE TABLE name
+/// ```
+///
+/// Returns the name of the table, or `None` for any other statement.
+fn parse_optimize_table(sql: &str) -> Result<Option<String>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    match parser.next_token() {
+        Token::Word(w) if w.value.eq_ignore_ascii_case("optimize") => {}
+        _ => return Ok(None),
+    }
+    if !parser.parse_keyword(Keyword::TABLE) {
+        return Ok(None);
+    }
+    let name = parser.parse_identifier()?.value;
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} at the end of OPTIMIZE TABLE statement",
+            token
+        )));
+    }
+    Ok(Some(name))
+}
+
+/// A statement caching a table on the executors, or dropping a cached table
+#[derive(Debug, PartialEq)]
+enum CacheStatement {
+    /// Cache the output of `query` as table `name`, or the rows of table `name`
+    Cache {
+        name: String,
+        query: Option<String>,
+    },
+    /// Cache the output of `query` as temporary table `name` of the session
+    CreateTemporary {
+        name: String,
+        query: String,
+    },
+    Uncache {
+        name: String,
+        if_exists: bool,
+    },
+}
+
+/// Parse a statement caching the output of a query, or the rows of a table, on the
+/// executors, or dropping a cached table:
+///
+/// ```sql
+/// CACHE TABLE name [[AS] query]
+/// CREATE {TEMP | TEMPORARY} TABLE name AS query
+/// UNCACHE TABLE [IF EXISTS] name
+/// ```
+///
+/// Returns `None` for any other statement.
+fn parse_cache_statement(sql: &str) -> Result<Option<CacheStatement>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    let (uncache, temporary) = match parser.peek_token() {
+        Token::Word(w) if w.value.eq_ignore_ascii_case("cache") => (false, false),
+        Token::Word(w) if w.value.eq_ignore_ascii_case("uncache") => (true, false),
+        Token::Word(w) if w.value.eq_ignore_ascii_case("create") => (false, true),
+        _ => return Ok(None),
+    };
+    parser.next_token();
+    if temporary
+        && parser
+            .parse_one_of_keywords(&[Keyword::TEMP, Keyword::TEMPORARY])
+            .is_none()
+    {
+        return Ok(None);
+    }
+    if !parser.parse_keyword(Keyword::TABLE) {
+        return Ok(None);
+    }
+    let statement = if temporary {
+        let name = parser.parse_object_name()?.to_string();
+        parser.expect_keyword(Keyword::AS)?;
+        let query = parser.parse_query()?.to_string();
+        CacheStatement::CreateTemporary { name, query }
+    } else if uncache {
+        let if_exists = parser.parse_keywords(&[Keyword::IF, Keyword::EXISTS]);
+        let name = parser.parse_object_name()?.to_string();
+        CacheStatement::Uncache { name, if_exists }
+    } else {
+        let name = parser.parse_object_name()?.to_string();
+        let has_query = parser.parse_keyword(Keyword::AS)
+            || !matches!(parser.peek_token(), Token::EOF | Token::SemiColon);
+        let query = if has_query {
+            Some(parser.parse_query()?.to_string())
+        } else {
+            None
+        };
+        CacheStatement::Cache { name, query }
+    };
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} at the end of CACHE statement",
+            token
+        )));
+    }
+    Ok(Some(statement))
+}
+
+/// An `ANALYZE TABLE` statement
+#[derive(Debug, PartialEq)]
+struct AnalyzeTable {
+    name: String,
+    /// Percentage of the rows of the table sampled, all of them if `None`
+    sample_percent: Option<f64>,
+}
+
+/// Parse a statement estimating the statistics of a table from a sample of its rows:
+///
+/// ```sql
+/// ANALYZE TABLE name [SAMPLE percent PERCENT]
+/// ```
+///
+/// Returns `None` for any other statement.
+fn parse_analyze_table(sql: &str) -> Result<Option<AnalyzeTable>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    match parser.next_token() {
+        Token::Word(w) if w.value.eq_ignore_ascii_case("analyze") => {}
+        _ => return Ok(None),
+    }
+    if !parser.parse_keyword(Keyword::TABLE) {
+        return Ok(None);
+    }
+    let name = parser.parse_object_name()?.to_string();
+    let mut sample_percent = None;
+    if matches!(parser.peek_token(), Token::Word(w) if w.value.eq_ignore_ascii_case("sample"))
+    {
+        parser.next_token();
+        let percent = match parser.parse_number_value()? {
+            Value::Number(value, _) => value.parse::<f64>().map_err(|e| {
+                DataFusionError::Plan(format!("Invalid sample {}: {}", value, e))
+            })?,
+            value => {
+                return Err(DataFusionError::Plan(format!("Invalid sample {}", value)))
+            }
+        };
+        match parser.next_token() {
+            Token::Word(w) if w.value.eq_ignore_ascii_case("percent") => {}
+            token => {
+                return Err(DataFusionError::Plan(format!(
+                    "Expected PERCENT after the sample of table {}, found {}",
+                    name, token
+                )))
+            }
+        }
+        sample_percent = Some(percent);
+    }
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} at the end of ANALYZE TABLE statement",
+            token
+        )));
+    }
+    Ok(Some(AnalyzeTable {
+        name,
+        sample_percent,
+    }))
+}
+
+/// A `CREATE EXTERNAL TABLE` statement for a table of a table format
+#[derive(Debug, PartialEq)]
+struct CreateFormatTable {
+    name: String,
+    if_not_exists: bool,
+    format: String,
+    options: BTreeMap<String, String>,
+    location: String,
+}
+
+/// Parse a statement creating a table of one of `formats`, which DataFusion rejects:
+///
+/// ```sql
+/// CREATE EXTERNAL TABLE [IF NOT EXISTS] name STORED AS format
+///     [OPTIONS (key = 'value', ...)] LOCATION 'location'
+/// ```
+///
+/// Returns `None` for any other statement.
+fn parse_create_format_table(
+    sql: &str,
+    formats: &TableFormats,
+) -> Result<Option<CreateFormatTable>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut parser = Parser::new(tokens, &dialect);
+    if !parser.parse_keywords(&[Keyword::CREATE, Keyword::EXTERNAL, Keyword::TABLE]) {
+        return Ok(None);
+    }
+    let if_not_exists =
+        parser.parse_keywords(&[Keyword::IF, Keyword::NOT, Keyword::EXISTS]);
+    let name = parser.parse_object_name()?.to_string();
+    if !parser.parse_keywords(&[Keyword::STORED, Keyword::AS]) {
+        return Ok(None);
+    }
+    let format = parser.parse_identifier()?.value.to_lowercase();
+    if !formats.contains(&format) {
+        return Ok(None);
+    }
+
+    let mut options = BTreeMap::new();
+    if matches!(parser.peek_token(), Token::Word(w) if w.value.eq_ignore_ascii_case("options"))
+    {
+        parser.next_token();
+        parser.expect_token(&Token::LParen)?;
+        loop {
+            let key = parser.parse_identifier()?.value;
+            parser.expect_token(&Token::Eq)?;
+            let value = match parser.parse_value()? {
+                Value::SingleQuotedString(value) | Value::DoubleQuotedString(value) => {
+                    value
+                }
+                Value::Number(value, _) => value,
+                Value::Boolean(value) => value.to_string(),
+                value => {
+                    return Err(DataFusionError::Plan(format!(
+                        "Unsupported value {} of table option {}",
+                        value, key
+                    )))
+                }
+            };
+            options.insert(key, value);
+            if !parser.consume_token(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect_token(&Token::RParen)?;
+    }
+    parser.expect_keyword(Keyword::LOCATION)?;
+    let location = parser.parse_literal_string()?;
+    while parser.consume_token(&Token::SemiColon) {}
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(DataFusionError::Plan(format!(
+            "Unexpected {} after the location of table {}",
+            token, name
+        )));
+    }
+
+    Ok(Some(CreateFormatTable {
+        name,
+        if_not_exists,
+        format,
+        options,
+        location,
+    }))
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_create_format_table() -> Result<()> {
+        let formats = TableFormats::builtin();
+        let create = parse_create_format_table(
+            "CREATE EXTERNAL TABLE IF NOT EXISTS events STORED AS DELTA \
+             OPTIONS (version = 3) LOCATION 's3://bucket/events';",
+            &formats,
+        )?;
+        assert_eq!(
+            create,
+            Some(CreateFormatTable {
+                name: "events".to_owned(),
+                if_not_exists: true,
+                format: "delta".to_owned(),
+                options: vec![("version".to_owned(), "3".to_owned())]
+                    .into_iter()
+                    .collect(),
+                location: "s3://bucket/events".to_owned(),
+            })
+        );
+
+        let csv = "CREATE EXTERNAL TABLE t STORED AS CSV LOCATION 'data.csv'";
+        assert_eq!(parse_create_format_table(csv, &formats)?, None);
+        assert_eq!(parse_create_format_table("SELECT 1", &formats)?, None);
+        assert!(parse_create_format_table(
+            "CREATE EXTERNAL TABLE t STORED AS DELTA LOCATION 'data' LIMIT 1",
+            &formats
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_copy() -> Result<()> {
+        let copy = parse_copy(
+            "COPY (SELECT year, month, sum(amount) FROM sales GROUP BY year, month) \
+             TO 's3://bucket/totals' STORED AS PARQUET PARTITIONED BY (year, month);",
+        )?;
+        assert_eq!(
+            copy,
+            Some(CopyToParquet {
+                query: "SELECT year, month, sum(amount) FROM sales GROUP BY year, month"
+                    .to_owned(),
+                location: "s3://bucket/totals".to_owned(),
+                partition_by: vec!["year".to_owned(), "month".to_owned()],
+            })
+        );
+
+        let copy = parse_copy("COPY (SELECT 1 AS a) TO '/tmp/out'")?.unwrap();
+        assert!(copy.partition_by.is_empty());
+        assert_eq!(parse_copy("SELECT 1")?, None);
+        assert!(parse_copy("COPY (SELECT 1) TO '/tmp/out' STORED AS CSV").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_table_change() -> Result<()> {
+        assert_eq!(
+            parse_table_change(
+                "DELETE FROM events WHERE day = '2022-11-01' AND id > 10;"
+            )?,
+            Some(TableChange {
+                table: "events".to_owned(),
+                assignments: None,
+                predicate: Some("day = '2022-11-01' AND id > 10".to_owned()),
+            })
+        );
+        assert_eq!(
+            parse_table_change("UPDATE events SET score = score * 2, tag = 'x'")?,
+            Some(TableChange {
+                table: "events".to_owned(),
+                assignments: Some(vec![
+                    ("score".to_owned(), "score * 2".to_owned()),
+                    ("tag".to_owned(), "'x'".to_owned()),
+                ]),
+                predicate: None,
+            })
+        );
+        assert_eq!(parse_table_change("SELECT * FROM events")?, None);
+        assert!(parse_table_change("DELETE events").is_err());
+        assert!(parse_table_change("UPDATE events SET score = 1 LIMIT 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_optimize_table() -> Result<()> {
+        assert_eq!(
+            parse_optimize_table("OPTIMIZE TABLE events;")?,
+            Some("events".to_owned())
+        );
+        assert_eq!(
+            parse_optimize_table("optimize table \"Events\"")?,
+            Some("Events".to_owned())
+        );
+        assert_eq!(parse_optimize_table("SELECT * FROM optimize")?, None);
+        assert!(parse_optimize_table("OPTIMIZE TABLE events ZORDER BY id").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cache_statement() -> Result<()> {
+        assert_eq!(
+            parse_cache_statement(
+                "CACHE TABLE recent AS SELECT * FROM trips WHERE year = 2022;"
+            )?,
+            Some(CacheStatement::Cache {
+                name: "recent".to_owned(),
+                query: Some("SELECT * FROM trips WHERE year = 2022".to_owned()),
+            })
+        );
+        assert_eq!(
+            parse_cache_statement("cache table trips")?,
+            Some(CacheStatement::Cache {
+                name: "trips".to_owned(),
+                query: None,
+            })
+        );
+        assert_eq!(
+            parse_cache_statement("UNCACHE TABLE IF EXISTS trips")?,
+            Some(CacheStatement::Uncache {
+                name: "trips".to_owned(),
+                if_exists: true,
+            })
+        );
+        assert_eq!(
+            parse_cache_statement(
+                "CREATE TEMPORARY TABLE recent AS SELECT * FROM trips WHERE year = 2022"
+            )?,
+            Some(CacheStatement::CreateTemporary {
+                name: "recent".to_owned(),
+                query: "SELECT * FROM trips WHERE year = 2022".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_cache_statement("create temp table ids as select id from trips;")?,
+            Some(CacheStatement::CreateTemporary {
+                name: "ids".to_owned(),
+                query: "SELECT id FROM trips".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse_cache_statement("CREATE TABLE recent AS SELECT * FROM trips")?,
+            None
+        );
+        assert!(parse_cache_statement("CREATE TEMPORARY TABLE recent").is_err());
+        assert_eq!(parse_cache_statement("SELECT * FROM cache")?, None);
+        assert!(parse_cache_statement("UNCACHE TABLE trips recent").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_analyze_table() -> Result<()> {
+        assert_eq!(
+            parse_analyze_table("ANALYZE TABLE trips SAMPLE 2.5 PERCENT;")?,
+            Some(AnalyzeTable {
+                name: "trips".to_owned(),
+                sample_percent: Some(2.5),
+            })
+        );
+        assert_eq!(
+            parse_analyze_table("analyze table public.trips")?,
+            Some(AnalyzeTable {
+                name: "public.trips".to_owned(),
+                sample_percent: None,
+            })
+        );
+        assert_eq!(parse_analyze_table("SELECT * FROM analyze")?, None);
+        assert!(parse_analyze_table("ANALYZE TABLE trips SAMPLE 10 ROWS").is_err());
+        Ok(())
+    }
+
     #[cfg(feature = "standalone")]
     use datafusion::datasource::listing::ListingTableUrl;
 
@@ -947,4 +2312,88 @@ mod tests {
             );
         }
     }
+
+    #[tokio::test]
+    #[cfg(feature = "standalone")]
+    async fn test_upload_table() {
+        use super::*;
+        use datafusion::arrow::array::Int64Array;
+        use datafusion::arrow::datatypes::{DataType, Field};
+        use datafusion::arrow::util::pretty::pretty_format_batches;
+        use tempfile::TempDir;
+
+        let context = BallistaContext::standalone(&BallistaConfig::new().unwrap(), 1)
+            .await
+            .unwrap();
+        let schema =
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch = |ids: Vec<i64>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(ids))])
+                .unwrap()
+        };
+        let tmp_dir = TempDir::new().unwrap();
+        let location = tmp_dir.path().join("uploaded");
+        let location = location.to_str().unwrap();
+
+        context
+            .upload_table(
+                "uploaded",
+                location,
+                schema.clone(),
+                vec![vec![batch(vec![1, 2])], vec![], vec![batch(vec![3])]],
+            )
+            .await
+            .unwrap();
+        let parquet_files = || {
+            fs::read_dir(location)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_str().unwrap().to_owned())
+                .filter(|name| name.ends_with(".parquet"))
+                .count()
+        };
+        // Empty partitions are not written
+        assert_eq!(parquet_files(), 2);
+        async fn totals(context: &BallistaContext, name: &str) -> String {
+            let sql = format!("select count(*) as rows, sum(id) as total from {}", name);
+            let batches = context.sql(&sql).await.unwrap().collect().await.unwrap();
+            pretty_format_batches(&batches).unwrap().to_string()
+        }
+        let expected = vec![
+            "+------+-------+",
+            "| rows | total |",
+            "+------+-------+",
+            "| 3    | 6     |",
+            "+------+-------+",
+        ];
+        assert_eq!(totals(&context, "uploaded").await, expected.join("\n"));
+
+        // Uploading again replaces the rows and the files of the table
+        context
+            .upload_table(
+                "uploaded",
+                location,
+                schema.clone(),
+                vec![vec![batch(vec![10])]],
+            )
+            .await
+            .unwrap();
+        assert_eq!(parquet_files(), 1);
+        let expected = vec![
+            "+------+-------+",
+            "| rows | total |",
+            "+------+-------+",
+            "| 1    | 10    |",
+            "+------+-------+",
+        ];
+        assert_eq!(totals(&context, "uploaded").await, expected.join("\n"));
+
+        // The table is in the catalog of the scheduler
+        context.register_catalog_table("uploaded").await.unwrap();
+        assert_eq!(totals(&context, "uploaded").await, expected.join("\n"));
+        assert!(context.register_catalog_table("missing").await.is_err());
+        assert!(context
+            .upload_table("empty", location, schema, vec![vec![]])
+            .await
+            .is_err());
+    }
 }
@@ -23,8 +23,12 @@ fn main() -> Result<(), String> {
     let version = rustc_version::version().unwrap();
     println!("cargo:rustc-env=RUSTC_VERSION={}", version);
     println!("cargo:rerun-if-changed=proto/datafusion.proto");
+    let out_dir = std::env::var("OUT_DIR").map_err(|e| e.to_string())?;
     tonic_build::configure()
         .extern_path(".datafusion", "::datafusion_proto::protobuf")
+        .file_descriptor_set_path(
+            std::path::Path::new(&out_dir).join("ballista_descriptor.bin"),
+        )
         .compile(&["proto/ballista.proto"], &["proto"])
         .map_err(|e| format!("protobuf compilation failed: {}", e))
 }
@@ -0,0 +1,88 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Bearer token authentication of clients.
+//!
+//! Clients install their token once per process with [init_client_token]. Requests to
+//! the client-facing scheduler APIs built with [authorized_request] then carry it in the
+//! `authorization` header.
+
+use crate::error::{BallistaError, Result};
+use once_cell::sync::OnceCell;
+use tonic::metadata::{AsciiMetadataValue, MetadataMap};
+use tonic::Request;
+
+pub const AUTHORIZATION_HEADER: &str = "authorization";
+const BEARER_PREFIX: &str = "Bearer ";
+
+static CLIENT_TOKEN: OnceCell<AsciiMetadataValue> = OnceCell::new();
+
+/// Send `token` with all requests built with [authorized_request] in this process
+pub fn init_client_token(token: &str) -> Result<()> {
+    let value = format!("{}{}", BEARER_PREFIX, token)
+        .parse::<AsciiMetadataValue>()
+        .map_err(|_| BallistaError::General("Invalid bearer token".to_owned()))?;
+    CLIENT_TOKEN
+        .set(value)
+        .map_err(|_| BallistaError::General("Client token is already set".to_owned()))
+}
+
+/// A request carrying the token set with [init_client_token], if any
+pub fn authorized_request<T>(message: T) -> Request<T> {
+    let mut request = Request::new(message);
+    if let Some(value) = CLIENT_TOKEN.get() {
+        request
+            .metadata_mut()
+            .insert(AUTHORIZATION_HEADER, value.clone());
+    }
+    request
+}
+
+/// The bearer token of a request, `None` if there is none
+pub fn bearer_token(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get(AUTHORIZATION_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_bearer_token)
+}
+
+/// Parse the value of an `authorization` header
+pub fn parse_bearer_token(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(BEARER_PREFIX)
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_token() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(bearer_token(&metadata), None);
+
+        metadata.insert(AUTHORIZATION_HEADER, "Bearer abc".parse().unwrap());
+        assert_eq!(bearer_token(&metadata), Some("abc"));
+
+        metadata.insert(AUTHORIZATION_HEADER, "Basic abc".parse().unwrap());
+        assert_eq!(bearer_token(&metadata), None);
+
+        assert_eq!(parse_bearer_token("Bearer "), None);
+    }
+}
@@ -0,0 +1,829 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Catalog of the tables of a Hive Metastore.
+//!
+//! The metastore is queried with its Thrift API over the binary protocol. Only the calls
+//! needed to look up tables are implemented, reading the fields used by
+//! [TableDefinition] and skipping the others, so the client works with the metastores
+//! of Hive 2 and 3 as well as the ones compatible with them.
+
+use crate::catalog::{ColumnDefinition, TableDefinition};
+use crate::error::{BallistaError, Result};
+use datafusion::catalog::catalog::CatalogProvider;
+use datafusion::catalog::schema::SchemaProvider;
+use datafusion::datasource::TableProvider;
+use datafusion::prelude::SessionConfig;
+use log::warn;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thrift::protocol::{
+    TBinaryInputProtocol, TBinaryOutputProtocol, TFieldIdentifier, TInputProtocol,
+    TMessageIdentifier, TMessageType, TOutputProtocol, TStructIdentifier, TType,
+};
+use thrift::transport::{
+    TBufferedReadTransport, TBufferedWriteTransport, TIoChannel, TTcpChannel,
+};
+use thrift::{ProtocolError, ProtocolErrorKind};
+
+/// Timeout of connecting to the metastore and of each read and write. Tables are looked
+/// up while planning queries, which waits for the metastore.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the partitions of a table are checked for ones stored outside of it
+const PARTITION_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// The number of partitions stored outside of a table which are logged by location
+const LOGGED_PARTITIONS: usize = 10;
+
+/// A partition of a table
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartitionDefinition {
+    /// The values of the partition keys of the table
+    pub values: Vec<String>,
+    pub location: String,
+}
+
+/// Client of the Thrift API of a Hive Metastore. Connections are kept open between
+/// calls, and a call opens a new one when all are in use. Calls block, so when made from
+/// a worker of a multi-threaded Tokio runtime they run with
+/// [tokio::task::block_in_place].
+pub struct HiveMetastoreClient {
+    /// Such as `metastore:9083`
+    address: String,
+    /// The connections not used by a call
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl HiveMetastoreClient {
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    /// The names of all databases
+    pub fn databases(&self) -> Result<Vec<String>> {
+        let result = self.call("get_all_databases", &|_| Ok(()), &|input| {
+            read_list(input, |input| input.read_string())
+        })?;
+        returned("get_all_databases", result)
+    }
+
+    /// The names of the tables of `database`
+    pub fn tables(&self, database: &str) -> Result<Vec<String>> {
+        let result = self.call(
+            "get_all_tables",
+            &|output| write_string_field(output, 1, database),
+            &|input| read_list(input, |input| input.read_string()),
+        )?;
+        returned("get_all_tables", result)
+    }
+
+    /// The table `name` of `database`, if it exists
+    pub fn table(&self, database: &str, name: &str) -> Result<Option<TableDefinition>> {
+        let result = self.call(
+            "get_table",
+            &|output| {
+                write_string_field(output, 1, database)?;
+                write_string_field(output, 2, name)
+            },
+            &read_table,
+        )?;
+        match result {
+            // NoSuchObjectException
+            Err(exception) if exception.field_id == 2 => Ok(None),
+            result => returned("get_table", result).map(Some),
+        }
+    }
+
+    /// The partitions of the table `name` of `database`
+    pub fn partitions(
+        &self,
+        database: &str,
+        name: &str,
+    ) -> Result<Vec<PartitionDefinition>> {
+        let result = self.call(
+            "get_partitions",
+            &|output| {
+                write_string_field(output, 1, database)?;
+                write_string_field(output, 2, name)?;
+                // Without a limit
+                output.write_field_begin(&TFieldIdentifier::new(
+                    "max_parts",
+                    TType::I16,
+                    3,
+                ))?;
+                output.write_i16(-1)?;
+                output.write_field_end()
+            },
+            &|input| read_list(input, read_partition),
+        )?;
+        match result {
+            // NoSuchObjectException
+            Err(exception) if exception.field_id == 1 => Ok(vec![]),
+            result => returned("get_partitions", result),
+        }
+    }
+
+    /// Call `method` on an idle connection. A connection left idle may have been
+    /// closed by the metastore, so the call is retried once on a new connection.
+    fn call<T>(
+        &self,
+        method: &str,
+        write_args: &dyn Fn(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        read_success: &dyn Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    ) -> Result<CallResult<T>> {
+        let multi_threaded = tokio::runtime::Handle::try_current()
+            .map(|handle| {
+                handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread
+            })
+            .unwrap_or(false);
+        if multi_threaded {
+            tokio::task::block_in_place(|| {
+                self.call_blocking(method, write_args, read_success)
+            })
+        } else {
+            self.call_blocking(method, write_args, read_success)
+        }
+    }
+
+    fn call_blocking<T>(
+        &self,
+        method: &str,
+        write_args: &dyn Fn(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        read_success: &dyn Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    ) -> Result<CallResult<T>> {
+        // The lock is only held to take a connection, not while calling on it
+        let idle = self.idle.lock().pop();
+        let reused = idle.is_some();
+        let mut connection = idle;
+        for attempt in 0..2 {
+            let mut open = match connection.take() {
+                Some(open) => open,
+                None => Connection::open(&self.address)?,
+            };
+            match open.call(method, write_args, read_success) {
+                Ok(result) => {
+                    self.idle.lock().push(open);
+                    return Ok(result);
+                }
+                Err(e) if !reused || attempt > 0 => {
+                    return Err(BallistaError::General(format!(
+                        "Hive Metastore call {} failed: {}",
+                        method, e
+                    )))
+                }
+                Err(e) => warn!(
+                    "Hive Metastore call {} failed, reconnecting to {}: {}",
+                    method, self.address, e
+                ),
+            }
+        }
+        unreachable!("the second attempt returns")
+    }
+}
+
+/// The value returned by a call, or the exception thrown by it. The value is missing
+/// when a call returns null.
+type CallResult<T> = std::result::Result<Option<T>, Exception>;
+
+/// An exception declared by a method, by the id of its field in the result of the call
+#[derive(Debug)]
+struct Exception {
+    field_id: i16,
+    message: String,
+}
+
+/// The value of a call which must return one
+fn returned<T>(method: &str, result: CallResult<T>) -> Result<T> {
+    match result {
+        Ok(Some(value)) => Ok(value),
+        Ok(None) => Err(BallistaError::General(format!(
+            "Hive Metastore call {} returned no value",
+            method
+        ))),
+        Err(exception) => Err(BallistaError::General(format!(
+            "Hive Metastore call {} failed: {}",
+            method, exception.message
+        ))),
+    }
+}
+
+struct Connection {
+    input: Box<dyn TInputProtocol + Send>,
+    output: Box<dyn TOutputProtocol + Send>,
+    sequence_number: i32,
+}
+
+impl Connection {
+    fn open(address: &str) -> Result<Self> {
+        let socket_address = address.to_socket_addrs()?.next().ok_or_else(|| {
+            BallistaError::General(format!("Could not resolve {}", address))
+        })?;
+        let stream = TcpStream::connect_timeout(&socket_address, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        let (read, write) = TTcpChannel::with_stream(stream)
+            .split()
+            .map_err(|e| BallistaError::General(e.to_string()))?;
+        Ok(Self {
+            input: Box::new(TBinaryInputProtocol::new(
+                TBufferedReadTransport::new(read),
+                true,
+            )),
+            output: Box::new(TBinaryOutputProtocol::new(
+                TBufferedWriteTransport::new(write),
+                true,
+            )),
+            sequence_number: 0,
+        })
+    }
+
+    fn call<T>(
+        &mut self,
+        method: &str,
+        write_args: &dyn Fn(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+        read_success: &dyn Fn(&mut dyn TInputProtocol) -> thrift::Result<T>,
+    ) -> thrift::Result<CallResult<T>> {
+        self.sequence_number += 1;
+        let output: &mut dyn TOutputProtocol = self.output.as_mut();
+        output.write_message_begin(&TMessageIdentifier::new(
+            method,
+            TMessageType::Call,
+            self.sequence_number,
+        ))?;
+        output.write_struct_begin(&TStructIdentifier::new(format!("{}_args", method)))?;
+        write_args(output)?;
+        output.write_field_stop()?;
+        output.write_struct_end()?;
+        output.write_message_end()?;
+        output.flush()?;
+
+        let input: &mut dyn TInputProtocol = self.input.as_mut();
+        let message = input.read_message_begin()?;
+        if message.message_type == TMessageType::Exception {
+            let error = thrift::Error::read_application_error_from_in_protocol(input)?;
+            input.read_message_end()?;
+            return Err(thrift::Error::Application(error));
+        }
+        if message.name != method || message.sequence_number != self.sequence_number {
+            return Err(thrift::Error::Protocol(ProtocolError::new(
+                ProtocolErrorKind::InvalidData,
+                format!(
+                    "Expected the reply to {} #{}, received {} #{}",
+                    method, self.sequence_number, message.name, message.sequence_number
+                ),
+            )));
+        }
+        let mut value = None;
+        let mut exception = None;
+        read_struct(input, &mut |input, id, field_type| {
+            match (id, field_type) {
+                (0, _) => value = Some(read_success(input)?),
+                // All exceptions of the metastore have a message in their first field
+                (_, TType::Struct) => {
+                    let mut message = String::new();
+                    read_struct(input, &mut |input, id, field_type| {
+                        if (id, field_type) != (1, TType::String) {
+                            return Ok(false);
+                        }
+                        message = input.read_string()?;
+                        Ok(true)
+                    })?;
+                    exception = Some(Exception {
+                        field_id: id,
+                        message,
+                    });
+                }
+                _ => return Ok(false),
+            }
+            Ok(true)
+        })?;
+        input.read_message_end()?;
+        Ok(match exception {
+            Some(exception) => Err(exception),
+            None => Ok(value),
+        })
+    }
+}
+
+fn write_string_field(
+    output: &mut dyn TOutputProtocol,
+    id: i16,
+    value: &str,
+) -> thrift::Result<()> {
+    output.write_field_begin(&TFieldIdentifier::new(
+        None::<String>,
+        TType::String,
+        id,
+    ))?;
+    output.write_string(value)?;
+    output.write_field_end()
+}
+
+/// Read a struct, calling `read_field` with the id and type of each field. Fields for
+/// which it returns false are skipped.
+fn read_struct(
+    input: &mut dyn TInputProtocol,
+    read_field: &mut dyn FnMut(
+        &mut dyn TInputProtocol,
+        i16,
+        TType,
+    ) -> thrift::Result<bool>,
+) -> thrift::Result<()> {
+    input.read_struct_begin()?;
+    loop {
+        let field = input.read_field_begin()?;
+        if field.field_type == TType::Stop {
+            break;
+        }
+        if !read_field(input, field.id.unwrap_or_default(), field.field_type)? {
+            input.skip(field.field_type)?;
+        }
+        input.read_field_end()?;
+    }
+    input.read_struct_end()
+}
+
+fn read_list<T>(
+    input: &mut dyn TInputProtocol,
+    mut read_element: impl FnMut(&mut dyn TInputProtocol) -> thrift::Result<T>,
+) -> thrift::Result<Vec<T>> {
+    let list = input.read_list_begin()?;
+    let elements = (0..list.size)
+        .map(|_| read_element(input))
+        .collect::<thrift::Result<_>>()?;
+    input.read_list_end()?;
+    Ok(elements)
+}
+
+fn read_string_map(
+    input: &mut dyn TInputProtocol,
+) -> thrift::Result<HashMap<String, String>> {
+    let map = input.read_map_begin()?;
+    let mut entries = HashMap::new();
+    for _ in 0..map.size {
+        let key = input.read_string()?;
+        entries.insert(key, input.read_string()?);
+    }
+    input.read_map_end()?;
+    Ok(entries)
+}
+
+fn read_table(input: &mut dyn TInputProtocol) -> thrift::Result<TableDefinition> {
+    let mut table = TableDefinition::default();
+    read_struct(input, &mut |input, id, field_type| {
+        match (id, field_type) {
+            (1, TType::String) => table.name = input.read_string()?,
+            (7, TType::Struct) => {
+                let storage = read_storage_descriptor(input)?;
+                table.location = storage.location;
+                table.columns = storage.columns;
+                table.input_format = storage.input_format;
+                table.serialization_library = storage.serialization_library;
+                table.serde_parameters = storage.serde_parameters;
+            }
+            (8, TType::List) => table.partition_keys = read_list(input, read_column)?,
+            (9, TType::Map) => table.parameters = read_string_map(input)?,
+            (12, TType::String) => table.table_type = Some(input.read_string()?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(table)
+}
+
+fn read_partition(input: &mut dyn TInputProtocol) -> thrift::Result<PartitionDefinition> {
+    let mut partition = PartitionDefinition::default();
+    read_struct(input, &mut |input, id, field_type| {
+        match (id, field_type) {
+            (1, TType::List) => {
+                partition.values = read_list(input, |input| input.read_string())?
+            }
+            (6, TType::Struct) => {
+                partition.location = read_storage_descriptor(input)?.location
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(partition)
+}
+
+/// The fields of a StorageDescriptor and its SerDeInfo
+#[derive(Default)]
+struct StorageDescriptor {
+    columns: Vec<ColumnDefinition>,
+    location: String,
+    input_format: Option<String>,
+    serialization_library: Option<String>,
+    serde_parameters: HashMap<String, String>,
+}
+
+fn read_storage_descriptor(
+    input: &mut dyn TInputProtocol,
+) -> thrift::Result<StorageDescriptor> {
+    let mut storage = StorageDescriptor::default();
+    read_struct(input, &mut |input, id, field_type| {
+        match (id, field_type) {
+            (1, TType::List) => storage.columns = read_list(input, read_column)?,
+            (2, TType::String) => storage.location = input.read_string()?,
+            (3, TType::String) => storage.input_format = Some(input.read_string()?),
+            (7, TType::Struct) => {
+                read_struct(input, &mut |input, id, field_type| {
+                    match (id, field_type) {
+                        (2, TType::String) => {
+                            storage.serialization_library = Some(input.read_string()?)
+                        }
+                        (3, TType::Map) => {
+                            storage.serde_parameters = read_string_map(input)?
+                        }
+                        _ => return Ok(false),
+                    }
+                    Ok(true)
+                })?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(storage)
+}
+
+fn read_column(input: &mut dyn TInputProtocol) -> thrift::Result<ColumnDefinition> {
+    let mut column = ColumnDefinition::default();
+    read_struct(input, &mut |input, id, field_type| {
+        match (id, field_type) {
+            (1, TType::String) => column.name = input.read_string()?,
+            (2, TType::String) => column.data_type = input.read_string()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    })?;
+    Ok(column)
+}
+
+/// Catalog of the databases of a Hive Metastore. Tables are looked up when queries are
+/// planned, so they are always up to date with the metastore.
+pub struct HiveCatalog {
+    client: Arc<HiveMetastoreClient>,
+    target_partitions: usize,
+    /// When the partitions of each table were last checked, by database and table
+    partitions_checked: Arc<Mutex<HashMap<(String, String), Instant>>>,
+}
+
+impl HiveCatalog {
+    /// The catalog of the metastore at `address`, such as `metastore:9083`
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            client: Arc::new(HiveMetastoreClient::new(address)),
+            target_partitions: SessionConfig::new().target_partitions,
+            partitions_checked: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The number of partitions the tables are scanned in
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> Self {
+        self.target_partitions = target_partitions;
+        self
+    }
+}
+
+impl CatalogProvider for HiveCatalog {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.client.databases().unwrap_or_else(|e| {
+            warn!("Could not list the Hive databases: {}", e);
+            vec![]
+        })
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        Some(Arc::new(HiveSchema {
+            client: self.client.clone(),
+            database: name.to_owned(),
+            target_partitions: self.target_partitions,
+            partitions_checked: self.partitions_checked.clone(),
+        }))
+    }
+}
+
+/// The tables of a database of a Hive Metastore
+pub struct HiveSchema {
+    client: Arc<HiveMetastoreClient>,
+    database: String,
+    target_partitions: usize,
+    partitions_checked: Arc<Mutex<HashMap<(String, String), Instant>>>,
+}
+
+impl HiveSchema {
+    fn load_table(&self, name: &str) -> Result<Option<Arc<dyn TableProvider>>> {
+        let table = match self.client.table(&self.database, name)? {
+            Some(table) => table,
+            None => return Ok(None),
+        };
+        if !table.partition_keys.is_empty() && self.check_partitions_due(name) {
+            self.check_partitions(name, &table.location)?;
+        }
+        Ok(Some(Arc::new(table.listing_table(self.target_partitions)?)))
+    }
+
+    /// Whether the partitions of table `name` were not checked in the last
+    /// [PARTITION_CHECK_INTERVAL], recording that they are checked now if so. Fetching
+    /// all partitions of a large table is expensive, so it is not done for every query.
+    fn check_partitions_due(&self, name: &str) -> bool {
+        let now = Instant::now();
+        let mut checked = self.partitions_checked.lock();
+        let key = (self.database.clone(), name.to_owned());
+        match checked.get(&key) {
+            Some(last) if now.duration_since(*last) < PARTITION_CHECK_INTERVAL => false,
+            _ => {
+                checked.insert(key, now);
+                true
+            }
+        }
+    }
+
+    /// The files of partitions are found by listing the location of the table, which
+    /// misses partitions added with a location of their own, so these are logged
+    fn check_partitions(&self, name: &str, location: &str) -> Result<()> {
+        let partitions = self.client.partitions(&self.database, name)?;
+        let elsewhere = outside_partitions(&partitions, location);
+        if !elsewhere.is_empty() {
+            let locations: Vec<&str> = elsewhere
+                .iter()
+                .take(LOGGED_PARTITIONS)
+                .map(|partition| partition.location.as_str())
+                .collect();
+            warn!(
+                "{} partitions of Hive table {}.{} are stored outside of {} and will not be scanned, such as {}",
+                elsewhere.len(),
+                self.database,
+                name,
+                location,
+                locations.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The partitions which are not stored below the table `location`
+fn outside_partitions<'a>(
+    partitions: &'a [PartitionDefinition],
+    location: &str,
+) -> Vec<&'a PartitionDefinition> {
+    let location = location.trim_end_matches('/');
+    let prefix = format!("{}/", location);
+    partitions
+        .iter()
+        .filter(|partition| {
+            let path = partition.location.trim_end_matches('/');
+            path != location && !path.starts_with(&prefix)
+        })
+        .collect()
+}
+
+impl SchemaProvider for HiveSchema {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.client.tables(&self.database).unwrap_or_else(|e| {
+            warn!(
+                "Could not list the tables of Hive database {}: {}",
+                self.database, e
+            );
+            vec![]
+        })
+    }
+
+    fn table(&self, name: &str) -> Option<Arc<dyn TableProvider>> {
+        self.load_table(name).unwrap_or_else(|e| {
+            warn!(
+                "Could not load Hive table {}.{}: {}",
+                self.database, name, e
+            );
+            None
+        })
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        matches!(self.client.table(&self.database, name), Ok(Some(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use thrift::protocol::{TListIdentifier, TMapIdentifier};
+
+    fn write_struct(
+        output: &mut dyn TOutputProtocol,
+        id: i16,
+        write_fields: impl FnOnce(&mut dyn TOutputProtocol) -> thrift::Result<()>,
+    ) -> thrift::Result<()> {
+        output.write_field_begin(&TFieldIdentifier::new(
+            None::<String>,
+            TType::Struct,
+            id,
+        ))?;
+        output.write_struct_begin(&TStructIdentifier::new("struct"))?;
+        write_fields(output)?;
+        output.write_field_stop()?;
+        output.write_struct_end()?;
+        output.write_field_end()
+    }
+
+    fn write_columns(
+        output: &mut dyn TOutputProtocol,
+        id: i16,
+        columns: &[(&str, &str)],
+    ) -> thrift::Result<()> {
+        output.write_field_begin(&TFieldIdentifier::new(
+            None::<String>,
+            TType::List,
+            id,
+        ))?;
+        output.write_list_begin(&TListIdentifier::new(
+            TType::Struct,
+            columns.len() as i32,
+        ))?;
+        for (name, data_type) in columns {
+            output.write_struct_begin(&TStructIdentifier::new("FieldSchema"))?;
+            write_string_field(output, 1, name)?;
+            write_string_field(output, 2, data_type)?;
+            write_string_field(output, 3, "a comment which is skipped")?;
+            output.write_field_stop()?;
+            output.write_struct_end()?;
+        }
+        output.write_list_end()?;
+        output.write_field_end()
+    }
+
+    fn write_table(output: &mut dyn TOutputProtocol) -> thrift::Result<()> {
+        write_struct(output, 0, |output| {
+            write_string_field(output, 1, "events")?;
+            write_string_field(output, 2, "default")?;
+            write_struct(output, 7, |output| {
+                write_columns(output, 1, &[("id", "bigint"), ("name", "string")])?;
+                write_string_field(output, 2, "hdfs://namenode/warehouse/events")?;
+                write_string_field(
+                    output,
+                    3,
+                    "org.apache.hadoop.hive.ql.io.parquet.MapredParquetInputFormat",
+                )?;
+                write_struct(output, 7, |output| {
+                    write_string_field(
+                        output,
+                        2,
+                        "org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe",
+                    )?;
+                    output.write_field_begin(&TFieldIdentifier::new(
+                        None::<String>,
+                        TType::Map,
+                        3,
+                    ))?;
+                    output.write_map_begin(&TMapIdentifier::new(
+                        TType::String,
+                        TType::String,
+                        1,
+                    ))?;
+                    output.write_string("serialization.format")?;
+                    output.write_string("1")?;
+                    output.write_map_end()?;
+                    output.write_field_end()
+                })
+            })?;
+            write_columns(output, 8, &[("day", "string")])?;
+            write_string_field(output, 12, "EXTERNAL_TABLE")
+        })
+    }
+
+    /// Serve two get_table calls, returning a table and then a NoSuchObjectException
+    fn serve(listener: TcpListener) -> thrift::Result<()> {
+        let (stream, _) = listener.accept()?;
+        let (read, write) = TTcpChannel::with_stream(stream).split()?;
+        let mut input = TBinaryInputProtocol::new(read, true);
+        let mut output = TBinaryOutputProtocol::new(write, true);
+        for found in [true, false] {
+            let call = input.read_message_begin()?;
+            input.skip(TType::Struct)?;
+            input.read_message_end()?;
+
+            output.write_message_begin(&TMessageIdentifier::new(
+                call.name,
+                TMessageType::Reply,
+                call.sequence_number,
+            ))?;
+            output.write_struct_begin(&TStructIdentifier::new("get_table_result"))?;
+            if found {
+                write_table(&mut output)?;
+            } else {
+                write_struct(&mut output, 2, |output| {
+                    write_string_field(output, 1, "missing table not found")
+                })?;
+            }
+            output.write_field_stop()?;
+            output.write_struct_end()?;
+            output.write_message_end()?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_table() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let server = std::thread::spawn(move || serve(listener));
+
+        let client = HiveMetastoreClient::new(address);
+        let table = client.table("default", "events")?.unwrap();
+        assert_eq!(table.name, "events");
+        assert_eq!(table.location, "hdfs://namenode/warehouse/events");
+        assert_eq!(
+            table.columns,
+            vec![
+                ColumnDefinition {
+                    name: "id".to_owned(),
+                    data_type: "bigint".to_owned()
+                },
+                ColumnDefinition {
+                    name: "name".to_owned(),
+                    data_type: "string".to_owned()
+                }
+            ]
+        );
+        assert_eq!(table.partition_keys.len(), 1);
+        assert_eq!(
+            table.serialization_library.as_deref(),
+            Some("org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe")
+        );
+        assert_eq!(
+            table.serde_parameters.get("serialization.format"),
+            Some(&"1".to_owned())
+        );
+        assert_eq!(table.table_type.as_deref(), Some("EXTERNAL_TABLE"));
+
+        assert_eq!(client.table("default", "missing")?, None);
+        server.join().unwrap().unwrap();
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn get_table_in_runtime() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?.to_string();
+        let server = std::thread::spawn(move || serve(listener));
+
+        let client = HiveMetastoreClient::new(address);
+        assert_eq!(client.table("default", "events")?.unwrap().name, "events");
+        assert_eq!(client.table("default", "missing")?, None);
+        assert_eq!(client.idle.lock().len(), 1);
+        server.join().unwrap().unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn partitions_outside_of_table() {
+        let partition = |location: &str| PartitionDefinition {
+            values: vec![],
+            location: location.to_owned(),
+        };
+        let partitions = vec![
+            partition("hdfs://namenode/warehouse/events/day=1"),
+            partition("hdfs://namenode/warehouse/events_old/day=2"),
+            partition("s3://archive/events/day=3"),
+        ];
+        let outside =
+            outside_partitions(&partitions, "hdfs://namenode/warehouse/events/");
+        assert_eq!(outside, vec![&partitions[1], &partitions[2]]);
+    }
+}
@@ -0,0 +1,355 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Catalogs of the tables defined in external metastores.
+//!
+//! Hive-compatible metastores describe tables the same way: the location of their
+//! files, the input format and serialization library the files are read with, and the
+//! columns, with the partition keys stored as `key=value` directories below the
+//! location. Such tables are resolved into [ListingTable]s, which are sent to the
+//! scheduler and executors like the tables registered by clients.
+
+#[cfg(feature = "hive")]
+pub mod hive;
+
+use crate::error::{BallistaError, Result};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use datafusion::datasource::file_format::csv::CsvFormat;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A column of a table, with its type in Hive's notation such as `array<string>`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub data_type: String,
+}
+
+/// A table of a Hive-compatible metastore
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableDefinition {
+    pub name: String,
+    pub location: String,
+    pub columns: Vec<ColumnDefinition>,
+    pub partition_keys: Vec<ColumnDefinition>,
+    pub input_format: Option<String>,
+    pub serialization_library: Option<String>,
+    pub serde_parameters: HashMap<String, String>,
+    pub parameters: HashMap<String, String>,
+    /// Such as `EXTERNAL_TABLE` or `VIRTUAL_VIEW`
+    pub table_type: Option<String>,
+}
+
+impl TableDefinition {
+    /// A [ListingTable] scanning the files of the table in `target_partitions`
+    /// partitions. Partition keys are read as strings from the paths of the files.
+    pub fn listing_table(&self, target_partitions: usize) -> Result<ListingTable> {
+        if self.table_type.as_deref() == Some("VIRTUAL_VIEW") {
+            return Err(BallistaError::NotImplemented(format!(
+                "{} is a view, which is not supported",
+                self.name
+            )));
+        }
+        let (format, file_extension) = self.file_format()?;
+        let fields = self
+            .columns
+            .iter()
+            .map(|column| {
+                Ok(Field::new(
+                    &column.name,
+                    arrow_type(&column.data_type)?,
+                    true,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let options = ListingOptions {
+            file_extension,
+            format,
+            table_partition_cols: self
+                .partition_keys
+                .iter()
+                .map(|key| key.name.clone())
+                .collect(),
+            // Statistics would be read from every file when the scheduler plans a scan
+            collect_stat: false,
+            target_partitions,
+        };
+        let config = ListingTableConfig::new(ListingTableUrl::parse(
+            normalize_location(&self.location),
+        )?)
+        .with_schema(Arc::new(Schema::new(fields)))
+        .with_listing_options(options);
+        Ok(ListingTable::try_new(config)?)
+    }
+
+    /// The format of the files of the table and their extension, which is empty for
+    /// tables written by Hive as it does not name its files with one
+    fn file_format(&self) -> Result<(Arc<dyn FileFormat>, String)> {
+        let serde = self.serialization_library.as_deref().unwrap_or_default();
+        let input_format = self.input_format.as_deref().unwrap_or_default();
+        // Tables created by Spark name their data source
+        let provider = self
+            .parameters
+            .get("spark.sql.sources.provider")
+            .map(|provider| provider.to_lowercase());
+        let extension = |extension: &str| {
+            if provider.is_some() {
+                extension.to_owned()
+            } else {
+                String::new()
+            }
+        };
+
+        if provider.as_deref() == Some("parquet")
+            || serde.contains("Parquet")
+            || input_format.contains("Parquet")
+        {
+            Ok((Arc::new(ParquetFormat::default()), extension(".parquet")))
+        } else if provider.as_deref() == Some("csv") || serde.ends_with("OpenCSVSerde") {
+            let delimiter = self.delimiter(&["separatorChar"], b',')?;
+            Ok((Arc::new(self.csv_format(delimiter)), extension(".csv")))
+        } else if serde.ends_with("LazySimpleSerDe")
+            || input_format.ends_with("TextInputFormat")
+        {
+            let delimiter =
+                self.delimiter(&["field.delim", "serialization.format"], b'\x01')?;
+            Ok((Arc::new(self.csv_format(delimiter)), String::new()))
+        } else {
+            Err(BallistaError::NotImplemented(format!(
+                "Table {} is stored with {}, which is not supported",
+                self.name,
+                if serde.is_empty() {
+                    input_format
+                } else {
+                    serde
+                }
+            )))
+        }
+    }
+
+    fn csv_format(&self, delimiter: u8) -> CsvFormat {
+        let has_header = self
+            .parameters
+            .get("skip.header.line.count")
+            .map_or(false, |count| count.trim() == "1");
+        CsvFormat::default()
+            .with_delimiter(delimiter)
+            .with_has_header(has_header)
+    }
+
+    /// The field delimiter of text files, from the first of `keys` set in the
+    /// parameters of the serialization library
+    fn delimiter(&self, keys: &[&str], default: u8) -> Result<u8> {
+        let value = match keys.iter().find_map(|key| self.serde_parameters.get(*key)) {
+            Some(value) => value,
+            None => return Ok(default),
+        };
+        match value.as_bytes() {
+            [delimiter] => Ok(*delimiter),
+            _ => Err(BallistaError::NotImplemented(format!(
+                "Table {} has delimiter {:?}, only single byte delimiters are supported",
+                self.name, value
+            ))),
+        }
+    }
+}
+
+/// The location of a table as an object store URL. Hadoop names the schemes of its S3
+/// clients `s3a` and `s3n`.
+fn normalize_location(location: &str) -> String {
+    for scheme in ["s3a://", "s3n://"] {
+        if let Some(path) = location.strip_prefix(scheme) {
+            return format!("s3://{}", path);
+        }
+    }
+    location.to_owned()
+}
+
+/// The Arrow type of a Hive type
+pub fn arrow_type(hive_type: &str) -> Result<DataType> {
+    let hive_type = hive_type.trim();
+    let lower = hive_type.to_lowercase();
+    if let Some(element) = nested(hive_type, &lower, "array") {
+        return Ok(DataType::List(Box::new(Field::new(
+            "element",
+            arrow_type(element)?,
+            true,
+        ))));
+    }
+    if let Some(fields) = nested(hive_type, &lower, "struct") {
+        return Ok(DataType::Struct(
+            split_top_level(fields)
+                .into_iter()
+                .map(|field| {
+                    let (name, field_type) = field.split_once(':').ok_or_else(|| {
+                        BallistaError::General(format!(
+                            "Invalid Hive struct field {}",
+                            field
+                        ))
+                    })?;
+                    Ok(Field::new(name.trim(), arrow_type(field_type)?, true))
+                })
+                .collect::<Result<_>>()?,
+        ));
+    }
+    // Parameters such as the length of varchar(10) do not change the type
+    let name = lower.split('(').next().unwrap_or_default().trim();
+    Ok(match name {
+        "tinyint" => DataType::Int8,
+        "smallint" => DataType::Int16,
+        "int" | "integer" => DataType::Int32,
+        "bigint" => DataType::Int64,
+        "float" => DataType::Float32,
+        "double" | "double precision" => DataType::Float64,
+        "boolean" => DataType::Boolean,
+        "string" | "varchar" | "char" => DataType::Utf8,
+        "binary" => DataType::Binary,
+        "date" => DataType::Date32,
+        "timestamp" => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        _ => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Unsupported Hive type {}",
+                hive_type
+            )))
+        }
+    })
+}
+
+/// The parameters of a nested type such as `array<int>`, in their original case
+fn nested<'a>(hive_type: &'a str, lower: &str, name: &str) -> Option<&'a str> {
+    if lower.starts_with(name)
+        && lower[name.len()..].trim_start().starts_with('<')
+        && lower.ends_with('>')
+    {
+        let start = hive_type.find('<')? + 1;
+        Some(&hive_type[start..hive_type.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Split the parameters of a nested type at the commas which are not nested themselves
+pub(crate) fn split_top_level(parameters: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in parameters.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(parameters[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(parameters[start..].trim());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, data_type: &str) -> ColumnDefinition {
+        ColumnDefinition {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+        }
+    }
+
+    #[test]
+    fn hive_types() -> Result<()> {
+        assert_eq!(arrow_type("BIGINT")?, DataType::Int64);
+        assert_eq!(arrow_type("varchar(20)")?, DataType::Utf8);
+        assert_eq!(
+            arrow_type("array<struct<Id:int,tags:array<string>>>")?,
+            DataType::List(Box::new(Field::new(
+                "element",
+                DataType::Struct(vec![
+                    Field::new("Id", DataType::Int32, true),
+                    Field::new(
+                        "tags",
+                        DataType::List(Box::new(Field::new(
+                            "element",
+                            DataType::Utf8,
+                            true
+                        ))),
+                        true
+                    ),
+                ]),
+                true
+            )))
+        );
+        assert!(arrow_type("map<string,int>").is_err());
+        assert!(arrow_type("decimal(10,2)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn listing_tables() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut table = TableDefinition {
+            name: "events".to_owned(),
+            location: dir.path().to_str().unwrap().to_owned(),
+            columns: vec![column("id", "bigint"), column("name", "string")],
+            partition_keys: vec![column("day", "string")],
+            input_format: Some(
+                "org.apache.hadoop.hive.ql.io.parquet.MapredParquetInputFormat"
+                    .to_owned(),
+            ),
+            serialization_library: Some(
+                "org.apache.hadoop.hive.ql.io.parquet.serde.ParquetHiveSerDe".to_owned(),
+            ),
+            ..Default::default()
+        };
+        let listing_table = table.listing_table(4)?;
+        assert_eq!(listing_table.options().file_extension, "");
+        assert_eq!(listing_table.options().table_partition_cols, vec!["day"]);
+
+        table.parameters.insert(
+            "spark.sql.sources.provider".to_owned(),
+            "parquet".to_owned(),
+        );
+        assert_eq!(table.listing_table(4)?.options().file_extension, ".parquet");
+
+        table.serialization_library =
+            Some("org.apache.hadoop.hive.serde2.lazy.LazySimpleSerDe".to_owned());
+        table.input_format = Some("org.apache.hadoop.mapred.TextInputFormat".to_owned());
+        table.parameters.clear();
+        table
+            .serde_parameters
+            .insert("field.delim".to_owned(), "\t".to_owned());
+        assert_eq!(table.delimiter(&["field.delim"], b'\x01')?, b'\t');
+        assert!(table.listing_table(4).is_ok());
+
+        table.serialization_library =
+            Some("org.apache.hadoop.hive.ql.io.orc.OrcSerde".to_owned());
+        table.input_format = None;
+        assert!(table.listing_table(4).is_err());
+
+        assert_eq!(normalize_location("s3a://bucket/t"), "s3://bucket/t");
+        Ok(())
+    }
+}
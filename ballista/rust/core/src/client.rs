@@ -16,8 +16,20 @@
 // under the License.
 
 //! Client API for sending requests to executors.
+//!
+//! Partitions are fetched from executors with a `DoGet` call per partition, or through
+//! a [PartitionExchange], a `DoExchange` call kept open with an executor through which
+//! a task fetches all the partitions it reads from it. Each message the client sends on
+//! the exchange asks for a partition, which the executor sends back as it would with
+//! `DoGet`, followed by a message marking its end.
+//!
+//! Shuffle partition files are copied to another executor with a `DoPut` call, whose
+//! first message describes the partition and whose following ones carry the bytes of
+//! its file.
 
 use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use std::{
@@ -25,14 +37,21 @@ use std::{
     task::{Context, Poll},
 };
 
+use crate::encryption::EncryptionKey;
 use crate::error::{ballista_error, BallistaError, Result};
+use crate::flight_transfer::{configure_flight_endpoint, IpcCompression};
+use crate::local_socket;
 use crate::serde::protobuf::{self};
-use crate::serde::scheduler::Action;
+use crate::serde::scheduler::{Action, ExecutorMetadata, FetchPriority};
+use crate::tls::{grpc_endpoint, grpc_url};
 
+use arrow_flight::flight_descriptor::DescriptorType;
 use arrow_flight::utils::flight_data_to_arrow_batch;
-use arrow_flight::Ticket;
 use arrow_flight::{flight_service_client::FlightServiceClient, FlightData};
+use arrow_flight::{FlightDescriptor, Ticket};
 use datafusion::arrow::array::ArrayRef;
+use datafusion::arrow::buffer::Buffer;
+use datafusion::arrow::ipc::{reader::read_dictionary, root_as_message};
 use datafusion::arrow::{
     datatypes::{Schema, SchemaRef},
     error::{ArrowError, Result as ArrowResult},
@@ -40,10 +59,21 @@ use datafusion::arrow::{
 };
 
 use datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream};
-use futures::{Stream, StreamExt};
-use log::debug;
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::{ready, Stream, StreamExt};
+use log::{debug, warn};
 use prost::Message;
-use tonic::Streaming;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tonic::codec::CompressionEncoding;
+use tonic::{Code, Streaming};
+
+/// The `app_metadata` of the message marking the end of a partition sent on an exchange
+pub const END_OF_PARTITION: &[u8] = b"end_of_partition";
+
+/// Bytes of the messages a partition file is copied to another executor in
+const REPLICA_CHUNK_BYTES: u64 = 1024 * 1024;
 
 /// Client for interacting with Ballista executors.
 #[derive(Clone)]
@@ -55,53 +85,145 @@ impl BallistaClient {
     /// Create a new BallistaClient to connect to the executor listening on the specified
     /// host and port
     pub async fn try_new(host: &str, port: u16) -> Result<Self> {
-        let addr = format!("http://{}:{}", host, port);
+        let addr = grpc_url(host, port);
         debug!("BallistaClient connecting to {}", addr);
-        let flight_client =
-            FlightServiceClient::connect(addr.clone())
-                .await
-                .map_err(|e| {
-                    BallistaError::General(format!(
-                        "Error connecting to Ballista scheduler or executor at {}: {:?}",
-                        addr, e
-                    ))
-                })?;
+        let endpoint = configure_flight_endpoint(grpc_endpoint(&addr)?);
+        let channel = endpoint.connect().await.map_err(|e| {
+            BallistaError::General(format!(
+                "Error connecting to Ballista scheduler or executor at {}: {:?}",
+                addr, e
+            ))
+        })?;
+        // Partitions are only sent compressed by servers with compression enabled
+        let flight_client = FlightServiceClient::new(channel)
+            .accept_compressed(CompressionEncoding::Gzip);
         debug!("BallistaClient connected OK");
 
         Ok(Self { flight_client })
     }
 
-    /// Fetch a partition from an executor
+    /// Create a new BallistaClient to connect to `executor`, through its local socket if
+    /// it runs on the same host
+    pub async fn try_new_for_executor(executor: &ExecutorMetadata) -> Result<Self> {
+        match local_socket::connect_local(executor).await {
+            Some(Ok(channel)) => {
+                debug!("BallistaClient connected to {} locally", executor.id);
+                return Ok(Self {
+                    flight_client: FlightServiceClient::new(channel),
+                });
+            }
+            Some(Err(e)) => warn!(
+                "Could not connect to the local socket of executor {}, using TCP: {:?}",
+                executor.id, e
+            ),
+            None => {}
+        }
+        Self::try_new(executor.host.as_str(), executor.port).await
+    }
+
+    /// Fetch a partition from an executor, sent in IPC messages whose buffers are
+    /// compressed with `compression`
     pub async fn fetch_partition(
         &mut self,
         job_id: &str,
         stage_id: usize,
         partition_id: usize,
         path: &str,
+        compression: IpcCompression,
     ) -> Result<SendableRecordBatchStream> {
         let action = Action::FetchPartition {
             job_id: job_id.to_string(),
             stage_id,
             partition_id,
             path: path.to_owned(),
+            compression,
+            priority: None,
         };
         self.execute_action(&action).await
     }
 
+    /// Open a `DoExchange` call with the executor to fetch partitions through. Fails
+    /// with [Code::Unimplemented] if the executor does not support it.
+    pub async fn open_exchange(&mut self) -> Result<ExchangeConnection> {
+        let (requests, request_stream) = unbounded();
+        let responses = self
+            .flight_client
+            .do_exchange(request_stream)
+            .await
+            .map_err(BallistaError::GrpcError)?
+            .into_inner();
+        Ok(ExchangeConnection {
+            requests,
+            responses,
+        })
+    }
+
+    /// Copy the shuffle partition file at `path`, partition `partition_id` of stage
+    /// `stage_id` of job `job_id`, to the executor, returning the path of the copy. The
+    /// file is copied as written, along with the key it is encrypted with, if any.
+    pub async fn replicate_partition(
+        &mut self,
+        job_id: &str,
+        stage_id: usize,
+        partition_id: usize,
+        path: &str,
+        encryption_key: Option<&EncryptionKey>,
+    ) -> Result<String> {
+        let file_name = Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| {
+                BallistaError::General(format!("Invalid partition path {}", path))
+            })?;
+        let file = File::open(path).await?;
+        let replica = protobuf::ReplicatePartition {
+            job_id: job_id.to_owned(),
+            stage_id: stage_id as u32,
+            partition_id: partition_id as u32,
+            file_name: file_name.to_owned(),
+            num_bytes: file.metadata().await?.len(),
+            encryption_key: encryption_key
+                .map(|key| key.as_bytes().to_vec())
+                .unwrap_or_default(),
+        };
+        let first = FlightData {
+            flight_descriptor: Some(FlightDescriptor {
+                r#type: DescriptorType::Cmd.into(),
+                cmd: replica.encode_to_vec(),
+                path: vec![],
+            }),
+            ..Default::default()
+        };
+        let messages = futures::stream::once(async { first }).chain(file_chunks(file));
+
+        let mut results = self
+            .flight_client
+            .do_put(messages)
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+            .into_inner();
+        match results
+            .message()
+            .await
+            .map_err(|e| BallistaError::General(format!("{:?}", e)))?
+        {
+            Some(result) => String::from_utf8(result.app_metadata).map_err(|e| {
+                BallistaError::General(format!("Invalid path of the copy: {:?}", e))
+            }),
+            None => Err(ballista_error(
+                "Did not receive the path of the copy from flight server",
+            )),
+        }
+    }
+
     /// Execute an action and retrieve the results
     pub async fn execute_action(
         &mut self,
         action: &Action,
     ) -> Result<SendableRecordBatchStream> {
-        let serialized_action: protobuf::Action = action.to_owned().try_into()?;
-
-        let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
-
-        serialized_action
-            .encode(&mut buf)
-            .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
-
-        let request = tonic::Request::new(Ticket { ticket: buf });
+        let request = tonic::Request::new(Ticket {
+            ticket: encode_action(action)?,
+        });
 
         let mut stream = self
             .flight_client
@@ -130,6 +252,266 @@ impl BallistaClient {
     }
 }
 
+/// The messages carrying the bytes of a file. They end early if the file fails to be
+/// read, which the executor receiving them detects from its size.
+/// The contents of `file`, in messages of up to [REPLICA_CHUNK_BYTES] bytes. The
+/// messages end early if the file fails to be read, which the receiver detects.
+fn file_chunks(file: File) -> impl Stream<Item = FlightData> {
+    futures::stream::unfold(file, |mut file| async move {
+        let mut data_body = vec![];
+        match (&mut file)
+            .take(REPLICA_CHUNK_BYTES)
+            .read_to_end(&mut data_body)
+            .await
+        {
+            Ok(0) => None,
+            Ok(_) => Some((
+                FlightData {
+                    data_body,
+                    ..Default::default()
+                },
+                file,
+            )),
+            Err(e) => {
+                warn!("Failed to read the partition file being copied: {:?}", e);
+                None
+            }
+        }
+    })
+}
+
+fn encode_action(action: &Action) -> Result<Vec<u8>> {
+    let serialized_action: protobuf::Action = action.to_owned().try_into()?;
+
+    let mut buf: Vec<u8> = Vec::with_capacity(serialized_action.encoded_len());
+
+    serialized_action
+        .encode(&mut buf)
+        .map_err(|e| BallistaError::General(format!("{:?}", e)))?;
+    Ok(buf)
+}
+
+/// The message asking for the partition of `action` on an exchange
+pub fn exchange_request(action: &Action) -> Result<FlightData> {
+    Ok(FlightData {
+        app_metadata: encode_action(action)?,
+        ..Default::default()
+    })
+}
+
+/// The message marking the end of a partition sent on an exchange
+pub fn end_of_partition() -> FlightData {
+    FlightData {
+        app_metadata: END_OF_PARTITION.to_vec(),
+        ..Default::default()
+    }
+}
+
+fn is_end_of_partition(data: &FlightData) -> bool {
+    data.data_header.is_empty() && data.app_metadata == END_OF_PARTITION
+}
+
+/// An open `DoExchange` call with an executor
+pub struct ExchangeConnection {
+    requests: UnboundedSender<FlightData>,
+    responses: Streaming<FlightData>,
+}
+
+/// Fetches partitions from an executor one after another through a single `DoExchange`
+/// call, opened with the first partition. Partitions of executors which do not support
+/// `DoExchange`, as they run an earlier version, are fetched with `DoGet` calls instead.
+/// Clones share the call.
+#[derive(Clone)]
+pub struct PartitionExchange {
+    executor: ExecutorMetadata,
+    connection: Arc<Mutex<Option<ExchangeConnection>>>,
+    /// Set once the executor answered that it does not support `DoExchange`
+    unsupported: Arc<AtomicBool>,
+}
+
+impl PartitionExchange {
+    /// An exchange with `executor`, connected when the first partition is fetched
+    pub fn new(executor: ExecutorMetadata) -> Self {
+        Self {
+            executor,
+            connection: Arc::new(Mutex::new(None)),
+            unsupported: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Fetch a partition from the executor. The partitions fetched while the stream of
+    /// a previous one is read wait for it to end, and the call is closed if the stream
+    /// is dropped before. Executors serving more partitions than they send at once may
+    /// send them in the order of their `priority`.
+    pub async fn fetch_partition(
+        &self,
+        job_id: &str,
+        stage_id: usize,
+        partition_id: usize,
+        path: &str,
+        priority: FetchPriority,
+    ) -> Result<SendableRecordBatchStream> {
+        // Partitions are exchanged uncompressed, as executors usually share a fast
+        // network
+        let action = Action::FetchPartition {
+            job_id: job_id.to_owned(),
+            stage_id,
+            partition_id,
+            path: path.to_owned(),
+            compression: IpcCompression::None,
+            priority: Some(priority),
+        };
+        if !self.unsupported.load(Ordering::Relaxed) {
+            match self.exchange_partition(&action).await {
+                Err(BallistaError::GrpcError(status))
+                    if status.code() == Code::Unimplemented =>
+                {
+                    warn!(
+                        "Executor {} does not support DoExchange, fetching its \
+                         partitions with DoGet",
+                        self.executor.id
+                    );
+                    self.unsupported.store(true, Ordering::Relaxed);
+                }
+                result => return result,
+            }
+        }
+        BallistaClient::try_new_for_executor(&self.executor)
+            .await?
+            .execute_action(&action)
+            .await
+    }
+
+    /// Fetch the partition of `action` through the `DoExchange` call with the executor
+    async fn exchange_partition(
+        &self,
+        action: &Action,
+    ) -> Result<SendableRecordBatchStream> {
+        let mut connection = self.connection.clone().lock_owned().await;
+        if connection.is_none() {
+            let mut client = BallistaClient::try_new_for_executor(&self.executor).await?;
+            *connection = Some(client.open_exchange().await?);
+        }
+        let schema = match request_partition(&mut connection, action).await {
+            Ok(schema) => schema,
+            Err(e) => {
+                *connection = None;
+                return Err(e);
+            }
+        };
+        Ok(Box::pin(ExchangePartitionStream {
+            connection: Some(connection),
+            schema,
+            dictionaries_by_id: HashMap::new(),
+        }))
+    }
+}
+
+/// Ask for the partition of `action` on `connection`, returning its schema
+async fn request_partition(
+    connection: &mut OwnedMutexGuard<Option<ExchangeConnection>>,
+    action: &Action,
+) -> Result<SchemaRef> {
+    let connection = (**connection)
+        .as_mut()
+        .ok_or_else(|| ballista_error("Exchange is not open"))?;
+    connection
+        .requests
+        .unbounded_send(exchange_request(action)?)
+        .map_err(|e| BallistaError::General(format!("Exchange is closed: {:?}", e)))?;
+    // Executors which do not support the call answer its first message
+    match connection
+        .responses
+        .message()
+        .await
+        .map_err(BallistaError::GrpcError)?
+    {
+        Some(flight_data) => Ok(Arc::new(Schema::try_from(&flight_data)?)),
+        None => Err(ballista_error(
+            "Did not receive schema batch from flight server",
+        )),
+    }
+}
+
+/// The batches of a partition sent on an exchange, holding the exchange until the end of
+/// the partition
+struct ExchangePartitionStream {
+    connection: Option<OwnedMutexGuard<Option<ExchangeConnection>>>,
+    schema: SchemaRef,
+    dictionaries_by_id: HashMap<i64, ArrayRef>,
+}
+
+impl Stream for ExchangePartitionStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        loop {
+            let this = &mut *self;
+            let next = match this.connection.as_mut().and_then(|c| (**c).as_mut()) {
+                Some(connection) => ready!(connection.responses.poll_next_unpin(cx)),
+                None => return Poll::Ready(None),
+            };
+            let flight_data = match next {
+                Some(Ok(flight_data)) if is_end_of_partition(&flight_data) => {
+                    // The exchange is given back for the next partitions
+                    this.connection = None;
+                    return Poll::Ready(None);
+                }
+                Some(Ok(flight_data)) => flight_data,
+                Some(Err(e)) => {
+                    this.close();
+                    return Poll::Ready(Some(Err(ArrowError::from_external_error(
+                        Box::new(e),
+                    ))));
+                }
+                None => {
+                    this.close();
+                    return Poll::Ready(Some(Err(ArrowError::IoError(
+                        "Exchange closed before the end of the partition".to_owned(),
+                    ))));
+                }
+            };
+            match decode_flight_data(
+                &flight_data,
+                &this.schema,
+                &mut this.dictionaries_by_id,
+            ) {
+                Ok(Some(batch)) => return Poll::Ready(Some(Ok(batch))),
+                Ok(None) => continue,
+                Err(e) => {
+                    this.close();
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
+
+impl ExchangePartitionStream {
+    /// Close the exchange, whose next messages are not the start of a partition, so
+    /// that the next partition opens a new one
+    fn close(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            *connection = None;
+        }
+    }
+}
+
+impl Drop for ExchangePartitionStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl RecordBatchStream for ExchangePartitionStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
 struct FlightDataStream {
     stream: Streaming<FlightData>,
     schema: SchemaRef,
@@ -153,21 +535,55 @@ impl Stream for FlightDataStream {
         mut self: std::pin::Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        self.stream.poll_next_unpin(cx).map(|x| match x {
-            Some(flight_data_chunk_result) => {
-                let converted_chunk = flight_data_chunk_result
-                    .map_err(|e| ArrowError::from_external_error(Box::new(e)))
-                    .and_then(|flight_data_chunk| {
-                        flight_data_to_arrow_batch(
-                            &flight_data_chunk,
-                            self.schema.clone(),
-                            &self.dictionaries_by_id,
-                        )
-                    });
-                Some(converted_chunk)
+        loop {
+            let flight_data_chunk = match ready!(self.stream.poll_next_unpin(cx)) {
+                Some(Ok(flight_data_chunk)) => flight_data_chunk,
+                Some(Err(e)) => {
+                    return Poll::Ready(Some(Err(ArrowError::from_external_error(
+                        Box::new(e),
+                    ))))
+                }
+                None => return Poll::Ready(None),
+            };
+            let this = &mut *self;
+            // dictionary batches are kept until the record batches referring to them
+            match decode_flight_data(
+                &flight_data_chunk,
+                &this.schema,
+                &mut this.dictionaries_by_id,
+            ) {
+                Ok(Some(batch)) => return Poll::Ready(Some(Ok(batch))),
+                Ok(None) => continue,
+                Err(e) => return Poll::Ready(Some(Err(e))),
             }
-            None => None,
-        })
+        }
+    }
+}
+
+/// Decode the record batch held by `data`, if any. The dictionaries it holds instead
+/// are added to `dictionaries_by_id`, to decode the record batches which follow.
+pub(crate) fn decode_flight_data(
+    data: &FlightData,
+    schema: &SchemaRef,
+    dictionaries_by_id: &mut HashMap<i64, ArrayRef>,
+) -> ArrowResult<Option<RecordBatch>> {
+    let message = root_as_message(&data.data_header[..]).map_err(|e| {
+        ArrowError::ParseError(format!("Unable to get root as message: {:?}", e))
+    })?;
+    match message.header_as_dictionary_batch() {
+        Some(dictionary_batch) => {
+            read_dictionary(
+                &Buffer::from(&data.data_body),
+                dictionary_batch,
+                schema,
+                dictionaries_by_id,
+                &message.version(),
+            )?;
+            Ok(None)
+        }
+        None => {
+            flight_data_to_arrow_batch(data, schema.clone(), dictionaries_by_id).map(Some)
+        }
     }
 }
 
@@ -176,3 +592,210 @@ impl RecordBatchStream for FlightDataStream {
         self.schema.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::decode_protobuf;
+    use crate::serde::scheduler::ExecutorSpecification;
+    use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+    use arrow_flight::utils::flight_data_from_arrow_batch;
+    use arrow_flight::{
+        Action as FlightAction, ActionType, Criteria, Empty, FlightInfo,
+        HandshakeRequest, HandshakeResponse, PutResult, SchemaAsIpc, SchemaResult,
+    };
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field};
+    use datafusion::arrow::ipc::writer::IpcWriteOptions;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use futures::stream::BoxStream;
+    use tonic::{Request, Response, Status};
+
+    #[test]
+    fn exchange_messages() -> Result<()> {
+        let action = Action::FetchPartition {
+            job_id: "job".to_owned(),
+            stage_id: 2,
+            partition_id: 3,
+            path: "/data/job/2/3/data.arrow".to_owned(),
+            compression: IpcCompression::Lz4,
+            priority: Some(FetchPriority {
+                job_priority: 5,
+                stages_remaining: 1,
+            }),
+        };
+        let request = exchange_request(&action)?;
+        assert!(request.data_header.is_empty());
+        match decode_protobuf(&request.app_metadata)? {
+            Action::FetchPartition {
+                job_id,
+                stage_id,
+                partition_id,
+                path,
+                compression,
+                priority,
+            } => {
+                assert_eq!(job_id, "job");
+                assert_eq!((stage_id, partition_id), (2, 3));
+                assert_eq!(path, "/data/job/2/3/data.arrow");
+                assert_eq!(compression, IpcCompression::Lz4);
+                assert_eq!(
+                    priority,
+                    Some(FetchPriority {
+                        job_priority: 5,
+                        stages_remaining: 1
+                    })
+                );
+            }
+        }
+
+        assert!(is_end_of_partition(&end_of_partition()));
+        assert!(!is_end_of_partition(&request));
+        Ok(())
+    }
+
+    /// An executor of an earlier version, which serves partitions with `DoGet` only
+    struct DoGetOnly {
+        batch: RecordBatch,
+    }
+
+    type Unsupported<T> = std::result::Result<Response<T>, Status>;
+
+    #[tonic::async_trait]
+    impl FlightService for DoGetOnly {
+        type HandshakeStream =
+            BoxStream<'static, std::result::Result<HandshakeResponse, Status>>;
+        type ListFlightsStream =
+            BoxStream<'static, std::result::Result<FlightInfo, Status>>;
+        type DoGetStream = BoxStream<'static, std::result::Result<FlightData, Status>>;
+        type DoPutStream = BoxStream<'static, std::result::Result<PutResult, Status>>;
+        type DoActionStream =
+            BoxStream<'static, std::result::Result<arrow_flight::Result, Status>>;
+        type ListActionsStream =
+            BoxStream<'static, std::result::Result<ActionType, Status>>;
+        type DoExchangeStream =
+            BoxStream<'static, std::result::Result<FlightData, Status>>;
+
+        async fn do_get(
+            &self,
+            _request: Request<Ticket>,
+        ) -> Unsupported<Self::DoGetStream> {
+            let options = IpcWriteOptions::default();
+            let mut messages =
+                vec![Ok(
+                    SchemaAsIpc::new(self.batch.schema().as_ref(), &options).into()
+                )];
+            let (dictionaries, batch) =
+                flight_data_from_arrow_batch(&self.batch, &options);
+            messages.extend(dictionaries.into_iter().map(Ok));
+            messages.push(Ok(batch));
+            Ok(Response::new(futures::stream::iter(messages).boxed()))
+        }
+
+        async fn handshake(
+            &self,
+            _request: Request<Streaming<HandshakeRequest>>,
+        ) -> Unsupported<Self::HandshakeStream> {
+            Err(Status::unimplemented("handshake"))
+        }
+
+        async fn list_flights(
+            &self,
+            _request: Request<Criteria>,
+        ) -> Unsupported<Self::ListFlightsStream> {
+            Err(Status::unimplemented("list_flights"))
+        }
+
+        async fn get_flight_info(
+            &self,
+            _request: Request<FlightDescriptor>,
+        ) -> Unsupported<FlightInfo> {
+            Err(Status::unimplemented("get_flight_info"))
+        }
+
+        async fn get_schema(
+            &self,
+            _request: Request<FlightDescriptor>,
+        ) -> Unsupported<SchemaResult> {
+            Err(Status::unimplemented("get_schema"))
+        }
+
+        async fn do_put(
+            &self,
+            _request: Request<Streaming<FlightData>>,
+        ) -> Unsupported<Self::DoPutStream> {
+            Err(Status::unimplemented("do_put"))
+        }
+
+        async fn do_action(
+            &self,
+            _request: Request<FlightAction>,
+        ) -> Unsupported<Self::DoActionStream> {
+            Err(Status::unimplemented("do_action"))
+        }
+
+        async fn list_actions(
+            &self,
+            _request: Request<Empty>,
+        ) -> Unsupported<Self::ListActionsStream> {
+            Err(Status::unimplemented("list_actions"))
+        }
+
+        async fn do_exchange(
+            &self,
+            _request: Request<Streaming<FlightData>>,
+        ) -> Unsupported<Self::DoExchangeStream> {
+            Err(Status::unimplemented("do_exchange"))
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_partitions_without_exchange() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let port = listener.local_addr()?.port();
+        let service = FlightServiceServer::new(DoGetOnly { batch });
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(service)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(
+                    listener,
+                )),
+        );
+
+        let exchange = PartitionExchange::new(ExecutorMetadata {
+            id: "executor-1".to_owned(),
+            host: "127.0.0.1".to_owned(),
+            port,
+            grpc_port: port,
+            specification: ExecutorSpecification { task_slots: 1 },
+            labels: Default::default(),
+            protocol: Default::default(),
+        });
+        // The first fetch finds the exchange unsupported, the second does not try it
+        for partition_id in 0..2 {
+            let batches: Vec<_> = exchange
+                .fetch_partition(
+                    "job",
+                    1,
+                    partition_id,
+                    "/data.arrow",
+                    FetchPriority::default(),
+                )
+                .await?
+                .collect()
+                .await;
+            let rows: usize = batches
+                .into_iter()
+                .map(|batch| batch.map(|batch| batch.num_rows()))
+                .sum::<std::result::Result<_, _>>()?;
+            assert_eq!(rows, 3);
+            assert!(exchange.unsupported.load(Ordering::Relaxed));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,325 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Configuration file shared by the scheduler and the executors of a cluster.
+//!
+//! The cluster configuration file is a TOML file with a `[scheduler]` and an
+//! `[executor]` table, holding the options of each binary under the names of its
+//! command line options:
+//!
+//! ```toml
+//! [scheduler]
+//! bind_port = 50050
+//! scheduler_policy = "push-staged"
+//!
+//! [executor]
+//! scheduler_host = "ballista-scheduler"
+//! concurrent_tasks = 8
+//! ```
+//!
+//! The options of a table are validated against the configuration specification of its
+//! binary, so that unknown options and values of the wrong type are rejected when the
+//! process starts, and applied with a lower precedence than the configuration file, the
+//! environment and the command line of the binary. On `SIGHUP` the binaries read the file
+//! again, and apply the new values of the options which can change at runtime.
+
+use crate::error::{BallistaError, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use toml::value::Table;
+use toml::Value;
+
+/// Default path of the cluster configuration file
+pub const CLUSTER_CONFIG_FILE: &str = "/etc/ballista/ballista.toml";
+
+/// Environment variable overriding the path of the cluster configuration file
+pub const CLUSTER_CONFIG_FILE_ENV: &str = "BALLISTA_CONFIG_FILE";
+
+/// The table of the options of one binary in the cluster configuration file
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    path: PathBuf,
+    section: String,
+    /// Type of each option of the binary
+    options: HashMap<String, OptionType>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OptionType {
+    Bool,
+    Integer { min: i64, max: i64 },
+    String,
+}
+
+/// The `[[param]]` and `[[switch]]` entries of a configure_me specification
+#[derive(Deserialize)]
+struct Spec {
+    #[serde(default)]
+    param: Vec<SpecParam>,
+    #[serde(default)]
+    switch: Vec<SpecSwitch>,
+}
+
+#[derive(Deserialize)]
+struct SpecParam {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Deserialize)]
+struct SpecSwitch {
+    name: String,
+}
+
+impl ClusterConfig {
+    /// The table `section` of the cluster configuration file, whose options are the ones
+    /// of the configure_me specification `spec`
+    pub fn try_new(section: &str, spec: &str) -> Result<Self> {
+        let path = std::env::var_os(CLUSTER_CONFIG_FILE_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(CLUSTER_CONFIG_FILE));
+        Self::try_new_with_path(path, section, spec)
+    }
+
+    pub fn try_new_with_path(
+        path: impl Into<PathBuf>,
+        section: &str,
+        spec: &str,
+    ) -> Result<Self> {
+        let spec: Spec = toml::from_str(spec).map_err(|e| {
+            BallistaError::Internal(format!("Invalid configuration specification: {}", e))
+        })?;
+        let mut options = HashMap::new();
+        for param in spec.param {
+            let ty = match param.ty.as_str() {
+                "bool" => OptionType::Bool,
+                "u8" => integer(u8::MIN as i64, u8::MAX as i64),
+                "u16" => integer(u16::MIN as i64, u16::MAX as i64),
+                "u32" => integer(u32::MIN as i64, u32::MAX as i64),
+                "u64" | "usize" => integer(0, i64::MAX),
+                "i32" => integer(i32::MIN as i64, i32::MAX as i64),
+                "i64" | "isize" => integer(i64::MIN, i64::MAX),
+                // Strings and the types parsed from them
+                _ => OptionType::String,
+            };
+            options.insert(param.name, ty);
+        }
+        for switch in spec.switch {
+            options.insert(switch.name, OptionType::Bool);
+        }
+        Ok(Self {
+            path: path.into(),
+            section: section.to_owned(),
+            options,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The validated options of the table, empty if the file does not exist
+    pub fn read(&self) -> Result<Table> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Table::new()),
+            Err(e) => {
+                return Err(BallistaError::General(format!(
+                    "Could not read cluster configuration file {}: {}",
+                    self.path.display(),
+                    e
+                )))
+            }
+        };
+        self.parse(&contents)
+    }
+
+    /// The validated options of the table, deserialized as `T`
+    pub fn read_as<T: DeserializeOwned>(&self) -> Result<T> {
+        Value::Table(self.read()?).try_into().map_err(|e| {
+            BallistaError::General(format!(
+                "Invalid [{}] options in {}: {}",
+                self.section,
+                self.path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Write the validated options of the table to `path`, as a configuration file of
+    /// the binary
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string(&self.read()?).map_err(|e| {
+            BallistaError::Internal(format!("Could not serialize options: {}", e))
+        })?;
+        std::fs::write(path, contents)?;
+        Ok(())
+    }
+
+    fn parse(&self, contents: &str) -> Result<Table> {
+        let error = |message: String| {
+            BallistaError::General(format!(
+                "Invalid cluster configuration file {}: {}",
+                self.path.display(),
+                message
+            ))
+        };
+        let mut file: Table =
+            toml::from_str(contents).map_err(|e| error(e.to_string()))?;
+        if let Some(section) = file
+            .keys()
+            .find(|section| !matches!(section.as_str(), "scheduler" | "executor"))
+        {
+            return Err(error(format!("unknown table [{}]", section)));
+        }
+        let options = match file.remove(&self.section) {
+            Some(Value::Table(options)) => options,
+            Some(_) => return Err(error(format!("{} is not a table", self.section))),
+            None => return Ok(Table::new()),
+        };
+        for (name, value) in &options {
+            let valid = match self.options.get(name) {
+                Some(OptionType::Bool) => value.is_bool(),
+                Some(OptionType::Integer { min, max }) => value
+                    .as_integer()
+                    .map(|value| value >= *min && value <= *max)
+                    .unwrap_or(false),
+                Some(OptionType::String) => value.is_str(),
+                None => {
+                    return Err(error(format!(
+                        "unknown option {} in [{}]",
+                        name, self.section
+                    )))
+                }
+            };
+            if !valid {
+                return Err(error(format!(
+                    "invalid value {} of option {} in [{}]",
+                    value, name, self.section
+                )));
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn integer(min: i64, max: i64) -> OptionType {
+    OptionType::Integer { min, max }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r#"
+[general]
+name = "Test"
+
+[[switch]]
+name = "version"
+
+[[param]]
+name = "bind_port"
+type = "u16"
+default = "50050"
+
+[[param]]
+name = "concurrent_tasks"
+type = "usize"
+default = "4"
+
+[[param]]
+name = "scheduler_policy"
+type = "ballista_core::config::TaskSchedulingPolicy"
+"#;
+
+    fn config() -> ClusterConfig {
+        ClusterConfig::try_new_with_path("ballista.toml", "executor", SPEC).unwrap()
+    }
+
+    #[test]
+    fn parse_options() -> Result<()> {
+        let options = config().parse(
+            r#"
+[scheduler]
+unknown_to_executor = 1
+
+[executor]
+bind_port = 50051
+concurrent_tasks = 8
+scheduler_policy = "push-staged"
+"#,
+        )?;
+        assert_eq!(options.len(), 3);
+        assert_eq!(options["bind_port"].as_integer(), Some(50051));
+
+        assert!(config().parse("[scheduler]\nbind_port = 1")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_options() {
+        for contents in [
+            "[executors]\nbind_port = 1",
+            "executor = 1",
+            "[executor]\nport = 1",
+            "[executor]\nbind_port = 65536",
+            "[executor]\nconcurrent_tasks = -1",
+            "[executor]\nconcurrent_tasks = \"8\"",
+            "[executor]\nscheduler_policy = 1",
+            "[executor]\nversion = \"yes\"",
+        ] {
+            assert!(config().parse(contents).is_err(), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn read_missing_file() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let config = ClusterConfig::try_new_with_path(
+            dir.path().join("missing"),
+            "executor",
+            SPEC,
+        )?;
+        assert!(config.read()?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn read_options_as() -> Result<()> {
+        #[derive(Deserialize)]
+        struct Reloadable {
+            concurrent_tasks: Option<usize>,
+        }
+
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("ballista.toml");
+        std::fs::write(&path, "[executor]\nconcurrent_tasks = 8\nbind_port = 1")?;
+        let config = ClusterConfig::try_new_with_path(&path, "executor", SPEC)?;
+        let reloadable: Reloadable = config.read_as()?;
+        assert_eq!(reloadable.concurrent_tasks, Some(8));
+
+        let written = dir.path().join("executor.toml");
+        config.write_to(&written)?;
+        let options: Table = toml::from_str(&std::fs::read_to_string(&written)?).unwrap();
+        assert_eq!(options["bind_port"].as_integer(), Some(1));
+        Ok(())
+    }
+}
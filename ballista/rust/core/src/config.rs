@@ -24,6 +24,7 @@ use std::collections::HashMap;
 use std::result;
 
 use crate::error::{BallistaError, Result};
+use crate::flight_transfer::IpcCompression;
 
 use datafusion::arrow::datatypes::DataType;
 
@@ -36,6 +37,109 @@ pub const BALLISTA_PARQUET_PRUNING: &str = "ballista.parquet.pruning";
 pub const BALLISTA_WITH_INFORMATION_SCHEMA: &str = "ballista.with_information_schema";
 /// give a plugin files dir, and then the dynamic library files in this dir will be load when scheduler state init.
 pub const BALLISTA_PLUGIN_DIR: &str = "ballista.plugin_dir";
+/// HTTP URL the scheduler POSTs a notification to once the job completes or fails
+pub const BALLISTA_JOB_CALLBACK_URL: &str = "ballista.job.callback_url";
+/// Name of the user submitting queries, recorded in the audit log of the scheduler
+pub const BALLISTA_USER: &str = "ballista.user";
+/// Object stores the job reads from along with their credentials, as a JSON object. Sent
+/// to the executors running the job only, see [crate::object_store_registry].
+pub const BALLISTA_OBJECT_STORE_CREDENTIALS: &str = "ballista.object_store.credentials";
+/// Location the job writes its output to as Parquet files, instead of returning it
+pub const BALLISTA_WRITE_PARQUET_PATH: &str = "ballista.write.parquet.path";
+/// Comma-separated columns whose values partition the Parquet files written to
+/// [BALLISTA_WRITE_PARQUET_PATH] into directories
+pub const BALLISTA_WRITE_PARQUET_PARTITION_BY: &str =
+    "ballista.write.parquet.partition_by";
+/// Table whose files holding the rows changed by a DELETE or UPDATE statement the job
+/// rewrites, with its output
+pub const BALLISTA_REWRITE_TABLE: &str = "ballista.rewrite.table";
+/// SQL predicate of the rows of [BALLISTA_REWRITE_TABLE] changed by the statement
+pub const BALLISTA_REWRITE_PREDICATE: &str = "ballista.rewrite.predicate";
+/// Whether the job rewrites the small files of [BALLISTA_REWRITE_TABLE] into larger ones
+/// for an OPTIMIZE TABLE statement, instead of the files changed by a statement
+pub const BALLISTA_REWRITE_COMPACT: &str = "ballista.rewrite.compact";
+/// Size in bytes of the files OPTIMIZE TABLE coalesces the small files of a table into
+pub const BALLISTA_COMPACT_TARGET_FILE_BYTES: &str = "ballista.compact.target_file_bytes";
+/// Whether the job estimates the statistics of the table it scans for an ANALYZE TABLE
+/// statement, instead of returning its rows
+pub const BALLISTA_ANALYZE_TABLE: &str = "ballista.analyze.table";
+/// Percentage of the rows of the table analyzed by the job its statistics are estimated
+/// from
+pub const BALLISTA_ANALYZE_SAMPLE_PERCENT: &str = "ballista.analyze.sample_percent";
+/// Name of the table the output of the job is cached as on the executors, instead of
+/// returning it
+pub const BALLISTA_CACHE_TABLE: &str = "ballista.cache.table";
+/// Whether the table of [BALLISTA_CACHE_TABLE] is a temporary table of the session,
+/// dropped when the session is closed
+pub const BALLISTA_CACHE_TEMPORARY: &str = "ballista.cache.temporary";
+/// Whether the job may complete with the result of an earlier job cached by the
+/// scheduler, and cache its own result
+pub const BALLISTA_CACHE_RESULTS: &str = "ballista.cache.results";
+/// Whether the partition count of each shuffle is chosen from the estimated size of its
+/// input, instead of [BALLISTA_DEFAULT_SHUFFLE_PARTITIONS]
+pub const BALLISTA_SHUFFLE_PARTITIONS_AUTO: &str = "ballista.shuffle.partitions.auto";
+/// Minimum partition count of a shuffle, with [BALLISTA_SHUFFLE_PARTITIONS_AUTO]
+pub const BALLISTA_SHUFFLE_PARTITIONS_MIN: &str = "ballista.shuffle.partitions.min";
+/// Maximum partition count of a shuffle, with [BALLISTA_SHUFFLE_PARTITIONS_AUTO]
+pub const BALLISTA_SHUFFLE_PARTITIONS_MAX: &str = "ballista.shuffle.partitions.max";
+/// Estimated input bytes per shuffle partition, with [BALLISTA_SHUFFLE_PARTITIONS_AUTO]
+pub const BALLISTA_SHUFFLE_PARTITION_BYTES: &str = "ballista.shuffle.partition.bytes";
+/// Bytes of files read by each task of a scan
+pub const BALLISTA_SCAN_TARGET_PARTITION_BYTES: &str =
+    "ballista.scan.target_partition_bytes";
+/// Maximum number of files, or ranges of files, read by each task of a scan
+pub const BALLISTA_SCAN_MAX_FILES_PER_PARTITION: &str =
+    "ballista.scan.max_files_per_partition";
+/// Percentage of the rows of its input above which the estimated number of groups of a
+/// partial aggregation makes it run after the shuffle rather than before, 0 to disable
+pub const BALLISTA_PARTIAL_AGGREGATION_MAX_GROUPS_PERCENT: &str =
+    "ballista.aggregate.partial.max_groups_percent";
+/// Whether `COUNT(DISTINCT)`s are estimated with HyperLogLog sketches rather than
+/// counted exactly
+pub const BALLISTA_APPROXIMATE_COUNT_DISTINCT: &str =
+    "ballista.count_distinct.approximate";
+/// Whether inner joins are reordered by the statistics of the tables estimated by
+/// `ANALYZE TABLE`
+pub const BALLISTA_JOIN_REORDERING: &str = "ballista.optimizer.join_reordering";
+/// The number of task slots the job reserves for its lifetime
+pub const BALLISTA_JOB_RESERVED_SLOTS: &str = "ballista.job.reserved_slots";
+/// Whether the executors keep the output of the job in memory and serve it from there,
+/// rather than writing it to files
+pub const BALLISTA_JOB_IN_MEMORY_RESULTS: &str = "ballista.job.in_memory_results";
+/// The number of times the job is run again after it fails
+pub const BALLISTA_JOB_RETRIES: &str = "ballista.job.retries";
+/// Milliseconds the job waits before its first retry, doubled for each following one
+pub const BALLISTA_JOB_RETRY_BACKOFF_MS: &str = "ballista.job.retry_backoff_ms";
+/// Seconds after its submission the job fails if it has not completed
+pub const BALLISTA_JOB_TIMEOUT_SECONDS: &str = "ballista.job.timeout_seconds";
+/// Seconds after its first task was launched a stage of the job fails if it has not
+/// completed
+pub const BALLISTA_STAGE_TIMEOUT_SECONDS: &str = "ballista.stage.timeout_seconds";
+/// Seconds a task of the job may run before it is killed
+pub const BALLISTA_TASK_TIMEOUT_SECONDS: &str = "ballista.task.timeout_seconds";
+/// Seconds a task of the job may run without producing rows before it is killed
+pub const BALLISTA_TASK_STALL_TIMEOUT_SECONDS: &str =
+    "ballista.task.stall_timeout_seconds";
+/// Multiple of the bytes it read above which the bytes spilled to disk by a stage of the
+/// job are logged as a warning
+pub const BALLISTA_STAGE_SPILL_WARNING_RATIO: &str = "ballista.stage.spill_warning_ratio";
+/// Compression of the buffers of the output partitions of the job as the client fetches
+/// them, one of none, lz4 and zstd
+pub const BALLISTA_RESULTS_COMPRESSION: &str = "ballista.results.compression";
+/// The pool of executors running the tasks of the job, rather than the pool of the
+/// tenant submitting it or the default pool
+pub const BALLISTA_JOB_POOL: &str = "ballista.job.pool";
+/// Whether Bloom filters of the keys of the build side of joins drop the rows of the
+/// probe side which cannot match before they are shuffled
+pub const BALLISTA_JOIN_BLOOM_FILTERS: &str = "ballista.join.bloom_filters";
+/// Bytes of each Bloom filter of the keys of the build side of a join
+pub const BALLISTA_JOIN_BLOOM_FILTER_BYTES: &str = "ballista.join.bloom_filter_bytes";
+/// Priority of the job, higher first, by which executors serving shuffle partitions to
+/// several jobs may order them
+pub const BALLISTA_JOB_PRIORITY: &str = "ballista.job.priority";
+/// Whether the shuffle partitions of the job are copied to a second executor, so that
+/// the loss of the executor which wrote them does not run their stage again
+pub const BALLISTA_SHUFFLE_REPLICATE: &str = "ballista.shuffle.replicate";
 
 pub type ParseResult<T> = result::Result<T, String>;
 
@@ -126,6 +230,10 @@ impl BallistaConfig {
             }
         }
 
+        if let Some(compression) = settings.get(BALLISTA_RESULTS_COMPRESSION) {
+            compression.parse::<IpcCompression>()?;
+        }
+
         Ok(Self { settings })
     }
 
@@ -136,6 +244,11 @@ impl BallistaConfig {
                     .parse::<usize>()
                     .map_err(|e| format!("{:?}", e))?;
             }
+            DataType::Float64 => {
+                val.to_string()
+                    .parse::<f64>()
+                    .map_err(|e| format!("{:?}", e))?;
+            }
             DataType::Boolean => {
                 val.to_string()
                     .parse::<bool>()
@@ -179,6 +292,120 @@ impl BallistaConfig {
             ConfigEntry::new(BALLISTA_PLUGIN_DIR.to_string(),
                              "Sets the plugin dir".to_string(),
                              DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_CALLBACK_URL.to_string(),
+                             "Sets the URL notified when the job completes or fails".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_USER.to_string(),
+                             "Sets the name of the user submitting queries".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_OBJECT_STORE_CREDENTIALS.to_string(),
+                             "Sets the object stores of the job and their credentials".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_WRITE_PARQUET_PATH.to_string(),
+                             "Sets the location the job writes its output to as Parquet files".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_WRITE_PARQUET_PARTITION_BY.to_string(),
+                             "Sets the columns partitioning the Parquet files written by the job".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_REWRITE_TABLE.to_string(),
+                             "Sets the table whose files the job rewrites for a DELETE or UPDATE statement".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_REWRITE_PREDICATE.to_string(),
+                             "Sets the predicate of the rows changed by the DELETE or UPDATE statement of the job".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_REWRITE_COMPACT.to_string(),
+                             "Sets whether the job compacts the files of the rewritten table for an OPTIMIZE TABLE statement".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_COMPACT_TARGET_FILE_BYTES.to_string(),
+                             "Sets the size in bytes of the files OPTIMIZE TABLE coalesces small files into".to_string(),
+                             DataType::UInt16, Some("134217728".to_string())),
+            ConfigEntry::new(BALLISTA_ANALYZE_TABLE.to_string(),
+                             "Sets whether the job estimates the statistics of the table it scans for an ANALYZE TABLE statement".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_ANALYZE_SAMPLE_PERCENT.to_string(),
+                             "Sets the percentage of the rows of the analyzed table its statistics are estimated from".to_string(),
+                             DataType::Float64, Some("100".to_string())),
+            ConfigEntry::new(BALLISTA_CACHE_TABLE.to_string(),
+                             "Sets the name of the table the output of the job is cached as".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_CACHE_TEMPORARY.to_string(),
+                             "Sets whether the table the output of the job is cached as is dropped when the session is closed".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_CACHE_RESULTS.to_string(),
+                             "Sets whether the result of the job may be taken from or saved to the result cache".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_SHUFFLE_PARTITIONS_AUTO.to_string(),
+                             "Sets whether the partition count of each shuffle is chosen from the estimated size of its input".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_SHUFFLE_PARTITIONS_MIN.to_string(),
+                             "Sets the minimum partition count of a shuffle chosen from the size of its input".to_string(),
+                             DataType::UInt16, Some("1".to_string())),
+            ConfigEntry::new(BALLISTA_SHUFFLE_PARTITIONS_MAX.to_string(),
+                             "Sets the maximum partition count of a shuffle chosen from the size of its input".to_string(),
+                             DataType::UInt16, Some("200".to_string())),
+            ConfigEntry::new(BALLISTA_SHUFFLE_PARTITION_BYTES.to_string(),
+                             "Sets the estimated input bytes per shuffle partition".to_string(),
+                             DataType::UInt16, Some("67108864".to_string())),
+            ConfigEntry::new(BALLISTA_SCAN_TARGET_PARTITION_BYTES.to_string(),
+                             "Sets the bytes of files read by each task of a scan".to_string(),
+                             DataType::UInt16, Some("134217728".to_string())),
+            ConfigEntry::new(BALLISTA_SCAN_MAX_FILES_PER_PARTITION.to_string(),
+                             "Sets the maximum number of files read by each task of a scan".to_string(),
+                             DataType::UInt16, Some("100".to_string())),
+            ConfigEntry::new(BALLISTA_PARTIAL_AGGREGATION_MAX_GROUPS_PERCENT.to_string(),
+                             "Sets the percentage of the rows of its input above which the estimated groups of a partial aggregation make it run after the shuffle, 0 to always run it before".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_APPROXIMATE_COUNT_DISTINCT.to_string(),
+                             "Sets whether COUNT(DISTINCT) is estimated with HyperLogLog sketches rather than counted exactly".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_JOIN_REORDERING.to_string(),
+                             "Sets whether inner joins are reordered by the statistics of the analyzed tables they join".to_string(),
+                             DataType::Boolean, Some("true".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_RESERVED_SLOTS.to_string(),
+                             "Sets the number of task slots the job reserves for its lifetime, none if zero".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_IN_MEMORY_RESULTS.to_string(),
+                             "Sets whether the executors keep the output of the job in memory rather than in files".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_RETRIES.to_string(),
+                             "Sets the number of times the job is run again after it fails".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_RETRY_BACKOFF_MS.to_string(),
+                             "Sets the milliseconds the job waits before its first retry, doubled for each following one".to_string(),
+                             DataType::UInt16, Some("1000".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_TIMEOUT_SECONDS.to_string(),
+                             "Sets the seconds after its submission the job fails if it has not completed, none if zero".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_STAGE_TIMEOUT_SECONDS.to_string(),
+                             "Sets the seconds after its first task was launched a stage fails if it has not completed, none if zero".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_TASK_TIMEOUT_SECONDS.to_string(),
+                             "Sets the seconds a task may run before it is killed, none if zero".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_TASK_STALL_TIMEOUT_SECONDS.to_string(),
+                             "Sets the seconds a task may run without producing rows before it is killed, none if zero".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_STAGE_SPILL_WARNING_RATIO.to_string(),
+                             "Sets the multiple of the bytes it read above which the bytes a stage spills to disk are logged as a warning, never if zero".to_string(),
+                             DataType::UInt16, Some("2".to_string())),
+            ConfigEntry::new(BALLISTA_RESULTS_COMPRESSION.to_string(),
+                             "Sets the compression of the buffers of the output of the job as it is fetched: none, lz4 or zstd".to_string(),
+                             DataType::Utf8, Some("none".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_POOL.to_string(),
+                             "Sets the pool of executors running the tasks of the job, the pool of its tenant or the default pool if empty".to_string(),
+                             DataType::Utf8, Some("".to_string())),
+            ConfigEntry::new(BALLISTA_JOIN_BLOOM_FILTERS.to_string(),
+                             "Sets whether Bloom filters of the keys of the build side of joins drop the rows of the probe side before they are shuffled".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
+            ConfigEntry::new(BALLISTA_JOIN_BLOOM_FILTER_BYTES.to_string(),
+                             "Sets the bytes of each Bloom filter of the keys of the build side of a join".to_string(),
+                             DataType::UInt16, Some("262144".to_string())),
+            ConfigEntry::new(BALLISTA_JOB_PRIORITY.to_string(),
+                             "Sets the priority of the job, higher first, of its shuffle fetches among those an executor serves".to_string(),
+                             DataType::UInt16, Some("0".to_string())),
+            ConfigEntry::new(BALLISTA_SHUFFLE_REPLICATE.to_string(),
+                             "Sets whether the shuffle partitions of the job are copied to a second executor, which serves them if the first one is lost".to_string(),
+                             DataType::Boolean, Some("false".to_string())),
         ];
         entries
             .iter()
@@ -198,6 +425,207 @@ impl BallistaConfig {
         self.get_string_setting(BALLISTA_PLUGIN_DIR)
     }
 
+    /// The URL to notify once the job finishes, if any
+    pub fn job_callback_url(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_JOB_CALLBACK_URL))
+            .filter(|url| !url.is_empty())
+    }
+
+    /// The user submitting queries, if any
+    pub fn user(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_USER)).filter(|user| !user.is_empty())
+    }
+
+    /// The location to write the output of the job to as Parquet files, if any
+    pub fn write_parquet_path(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_WRITE_PARQUET_PATH))
+            .filter(|path| !path.is_empty())
+    }
+
+    /// The columns partitioning the Parquet files written by the job
+    pub fn write_parquet_partition_by(&self) -> Vec<String> {
+        self.get_string_setting(BALLISTA_WRITE_PARQUET_PARTITION_BY)
+            .split(',')
+            .map(|column| column.trim().to_owned())
+            .filter(|column| !column.is_empty())
+            .collect()
+    }
+
+    /// The table whose files the job rewrites for a DELETE or UPDATE statement, if any
+    pub fn rewrite_table(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_REWRITE_TABLE))
+            .filter(|name| !name.is_empty())
+    }
+
+    /// The predicate of the rows changed by the DELETE or UPDATE statement of the job,
+    /// if any
+    pub fn rewrite_predicate(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_REWRITE_PREDICATE))
+            .filter(|predicate| !predicate.is_empty())
+    }
+
+    /// Whether the job compacts the files of the rewritten table for an OPTIMIZE TABLE
+    /// statement
+    pub fn rewrite_compact(&self) -> bool {
+        self.get_bool_setting(BALLISTA_REWRITE_COMPACT)
+    }
+
+    /// The size in bytes of the files OPTIMIZE TABLE coalesces small files into
+    pub fn compact_target_file_bytes(&self) -> usize {
+        self.get_usize_setting(BALLISTA_COMPACT_TARGET_FILE_BYTES)
+    }
+
+    /// Whether the job estimates the statistics of the table it scans for an ANALYZE
+    /// TABLE statement
+    pub fn analyze_table(&self) -> bool {
+        self.get_bool_setting(BALLISTA_ANALYZE_TABLE)
+    }
+
+    /// The percentage of the rows of the analyzed table its statistics are estimated from
+    pub fn analyze_sample_percent(&self) -> f64 {
+        self.get_f64_setting(BALLISTA_ANALYZE_SAMPLE_PERCENT)
+    }
+
+    /// The name of the table to cache the output of the job as, if any
+    pub fn cache_table(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_CACHE_TABLE))
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Whether the table the output of the job is cached as is a temporary table of
+    /// the session
+    pub fn cache_temporary(&self) -> bool {
+        self.get_bool_setting(BALLISTA_CACHE_TEMPORARY)
+    }
+
+    /// Whether the result of the job may be taken from or saved to the result cache of
+    /// the scheduler
+    pub fn cache_results(&self) -> bool {
+        self.get_bool_setting(BALLISTA_CACHE_RESULTS)
+    }
+
+    /// Whether the partition count of each shuffle is chosen from the estimated size of
+    /// its input
+    pub fn shuffle_partitions_auto(&self) -> bool {
+        self.get_bool_setting(BALLISTA_SHUFFLE_PARTITIONS_AUTO)
+    }
+
+    pub fn shuffle_partitions_min(&self) -> usize {
+        self.get_usize_setting(BALLISTA_SHUFFLE_PARTITIONS_MIN)
+    }
+
+    pub fn shuffle_partitions_max(&self) -> usize {
+        self.get_usize_setting(BALLISTA_SHUFFLE_PARTITIONS_MAX)
+    }
+
+    pub fn shuffle_partition_bytes(&self) -> usize {
+        self.get_usize_setting(BALLISTA_SHUFFLE_PARTITION_BYTES)
+    }
+
+    pub fn scan_target_partition_bytes(&self) -> usize {
+        self.get_usize_setting(BALLISTA_SCAN_TARGET_PARTITION_BYTES)
+    }
+
+    pub fn scan_max_files_per_partition(&self) -> usize {
+        self.get_usize_setting(BALLISTA_SCAN_MAX_FILES_PER_PARTITION)
+    }
+
+    /// Percentage of the rows of its input above which the estimated number of groups of
+    /// a partial aggregation makes it run after the shuffle rather than before, 0 if
+    /// partial aggregations always run before the shuffle
+    pub fn partial_aggregation_max_groups_percent(&self) -> usize {
+        self.get_usize_setting(BALLISTA_PARTIAL_AGGREGATION_MAX_GROUPS_PERCENT)
+    }
+
+    /// Whether `COUNT(DISTINCT)`s are estimated with HyperLogLog sketches
+    pub fn approximate_count_distinct(&self) -> bool {
+        self.get_bool_setting(BALLISTA_APPROXIMATE_COUNT_DISTINCT)
+    }
+
+    pub fn join_reordering(&self) -> bool {
+        self.get_bool_setting(BALLISTA_JOIN_REORDERING)
+    }
+
+    /// The number of task slots the job reserves for its lifetime
+    pub fn job_reserved_slots(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOB_RESERVED_SLOTS)
+    }
+
+    /// Whether the executors keep the output of the job in memory
+    pub fn job_in_memory_results(&self) -> bool {
+        self.get_bool_setting(BALLISTA_JOB_IN_MEMORY_RESULTS)
+    }
+
+    /// The number of times the job is run again after it fails
+    pub fn job_retries(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOB_RETRIES)
+    }
+
+    /// Milliseconds the job waits before its first retry
+    pub fn job_retry_backoff_ms(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOB_RETRY_BACKOFF_MS)
+    }
+
+    /// Seconds after its submission the job fails if it has not completed
+    pub fn job_timeout_seconds(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOB_TIMEOUT_SECONDS)
+    }
+
+    /// Seconds after its first task was launched a stage fails if it has not completed
+    pub fn stage_timeout_seconds(&self) -> usize {
+        self.get_usize_setting(BALLISTA_STAGE_TIMEOUT_SECONDS)
+    }
+
+    /// Seconds a task may run before it is killed
+    pub fn task_timeout_seconds(&self) -> usize {
+        self.get_usize_setting(BALLISTA_TASK_TIMEOUT_SECONDS)
+    }
+
+    /// Seconds a task may run without producing rows before it is killed
+    pub fn task_stall_timeout_seconds(&self) -> usize {
+        self.get_usize_setting(BALLISTA_TASK_STALL_TIMEOUT_SECONDS)
+    }
+
+    /// Multiple of the bytes it read above which the bytes a stage spills to disk are
+    /// logged as a warning
+    pub fn stage_spill_warning_ratio(&self) -> usize {
+        self.get_usize_setting(BALLISTA_STAGE_SPILL_WARNING_RATIO)
+    }
+
+    /// Compression of the output partitions of the job as the client fetches them
+    pub fn results_compression(&self) -> IpcCompression {
+        // infallible because we validate it in the constructor
+        self.get_string_setting(BALLISTA_RESULTS_COMPRESSION)
+            .parse()
+            .unwrap()
+    }
+
+    /// The pool of executors running the tasks of the job, if it is set
+    pub fn job_pool(&self) -> Option<String> {
+        Some(self.get_string_setting(BALLISTA_JOB_POOL)).filter(|pool| !pool.is_empty())
+    }
+
+    /// Whether Bloom filters of the keys of the build side of joins filter their probe
+    /// side
+    pub fn join_bloom_filters(&self) -> bool {
+        self.get_bool_setting(BALLISTA_JOIN_BLOOM_FILTERS)
+    }
+
+    /// Bytes of each Bloom filter of the keys of the build side of a join
+    pub fn join_bloom_filter_bytes(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOIN_BLOOM_FILTER_BYTES)
+    }
+
+    /// Priority of the job, higher first
+    pub fn job_priority(&self) -> usize {
+        self.get_usize_setting(BALLISTA_JOB_PRIORITY)
+    }
+
+    /// Whether the shuffle partitions of the job are copied to a second executor
+    pub fn shuffle_replicate(&self) -> bool {
+        self.get_bool_setting(BALLISTA_SHUFFLE_REPLICATE)
+    }
+
     pub fn default_batch_size(&self) -> usize {
         self.get_usize_setting(BALLISTA_DEFAULT_BATCH_SIZE)
     }
@@ -234,6 +662,18 @@ impl BallistaConfig {
         }
     }
 
+    fn get_f64_setting(&self, key: &str) -> f64 {
+        if let Some(v) = self.settings.get(key) {
+            // infallible because we validate all configs in the constructor
+            v.parse().unwrap()
+        } else {
+            let entries = Self::valid_entries();
+            // infallible because we validate all configs in the constructor
+            let v = entries.get(key).unwrap().default_value.as_ref().unwrap();
+            v.parse().unwrap()
+        }
+    }
+
     fn get_bool_setting(&self, key: &str) -> bool {
         if let Some(v) = self.settings.get(key) {
             // infallible because we validate all configs in the constructor
@@ -280,6 +720,61 @@ impl parse_arg::ParseArgFromStr for TaskSchedulingPolicy {
     }
 }
 
+// an enum used to configure the log output format of the scheduler and executor
+// needs to be visible to code generated by configure_me
+#[derive(Clone, ArgEnum, Copy, Debug, serde::Deserialize)]
+pub enum LogFormat {
+    /// Human readable log lines
+    Text,
+    /// One JSON object per log line
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        ArgEnum::from_str(s, true)
+    }
+}
+
+impl parse_arg::ParseArgFromStr for LogFormat {
+    fn describe_type<W: fmt::Write>(mut writer: W) -> fmt::Result {
+        write!(writer, "The log output format")
+    }
+}
+
+// an enum used to configure the order in which the executor sends the shuffle partitions
+// waiting to be sent, needs to be visible to code generated by configure_me
+#[derive(Clone, ArgEnum, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+pub enum ShuffleFetchOrder {
+    /// In the order the fetches arrived
+    Arrival,
+    /// The fetches of stages with the fewest stages left to run after them in their job
+    /// first, then those of jobs of the highest priority
+    CriticalStage,
+    /// The fetches of jobs of the highest priority first, then those of stages with the
+    /// fewest stages left to run after them
+    JobPriority,
+}
+
+impl std::str::FromStr for ShuffleFetchOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        ArgEnum::from_str(s, true)
+    }
+}
+
+impl parse_arg::ParseArgFromStr for ShuffleFetchOrder {
+    fn describe_type<W: fmt::Write>(mut writer: W) -> fmt::Result {
+        write!(
+            writer,
+            "The order of the shuffle partitions waiting to be sent"
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -290,6 +785,38 @@ mod tests {
         assert_eq!(2, config.default_shuffle_partitions());
         assert!(!config.default_with_information_schema());
         assert_eq!("", config.default_plugin_dir().as_str());
+        assert_eq!(None, config.job_callback_url());
+        assert_eq!(None, config.write_parquet_path());
+        assert!(config.write_parquet_partition_by().is_empty());
+        assert_eq!(None, config.rewrite_table());
+        assert!(!config.rewrite_compact());
+        assert_eq!(128 * 1024 * 1024, config.compact_target_file_bytes());
+        assert!(!config.analyze_table());
+        assert_eq!(100.0, config.analyze_sample_percent());
+        assert_eq!(None, config.cache_table());
+        assert!(!config.cache_temporary());
+        assert!(!config.shuffle_partitions_auto());
+        assert_eq!(64 * 1024 * 1024, config.shuffle_partition_bytes());
+        assert_eq!(128 * 1024 * 1024, config.scan_target_partition_bytes());
+        assert_eq!(100, config.scan_max_files_per_partition());
+        assert_eq!(0, config.partial_aggregation_max_groups_percent());
+        assert!(!config.approximate_count_distinct());
+        assert!(config.join_reordering());
+        assert_eq!(0, config.job_reserved_slots());
+        assert!(!config.job_in_memory_results());
+        assert_eq!(0, config.job_retries());
+        assert_eq!(1000, config.job_retry_backoff_ms());
+        assert_eq!(0, config.job_timeout_seconds());
+        assert_eq!(0, config.stage_timeout_seconds());
+        assert_eq!(0, config.task_timeout_seconds());
+        assert_eq!(0, config.task_stall_timeout_seconds());
+        assert_eq!(2, config.stage_spill_warning_ratio());
+        assert_eq!(IpcCompression::None, config.results_compression());
+        assert_eq!(None, config.job_pool());
+        assert!(!config.join_bloom_filters());
+        assert_eq!(256 * 1024, config.join_bloom_filter_bytes());
+        assert_eq!(0, config.job_priority());
+        assert!(!config.shuffle_replicate());
         Ok(())
     }
 
@@ -298,9 +825,32 @@ mod tests {
         let config = BallistaConfig::builder()
             .set(BALLISTA_DEFAULT_SHUFFLE_PARTITIONS, "123")
             .set(BALLISTA_WITH_INFORMATION_SCHEMA, "true")
+            .set(BALLISTA_JOB_CALLBACK_URL, "http://localhost:8000/hook")
+            .set(BALLISTA_WRITE_PARQUET_PARTITION_BY, "year, month")
+            .set(BALLISTA_CACHE_TABLE, "trips")
+            .set(BALLISTA_CACHE_TEMPORARY, "true")
+            .set(BALLISTA_RESULTS_COMPRESSION, "zstd")
+            .set(BALLISTA_JOB_POOL, "interactive")
+            .set(BALLISTA_JOIN_BLOOM_FILTERS, "true")
+            .set(BALLISTA_JOB_PRIORITY, "10")
+            .set(BALLISTA_SHUFFLE_REPLICATE, "true")
+            .set(BALLISTA_ANALYZE_SAMPLE_PERCENT, "2.5")
             .build()?;
         assert_eq!(123, config.default_shuffle_partitions());
         assert!(config.default_with_information_schema());
+        assert_eq!(
+            Some("http://localhost:8000/hook".to_owned()),
+            config.job_callback_url()
+        );
+        assert_eq!(vec!["year", "month"], config.write_parquet_partition_by());
+        assert_eq!(IpcCompression::Zstd, config.results_compression());
+        assert_eq!(Some("trips".to_owned()), config.cache_table());
+        assert!(config.cache_temporary());
+        assert_eq!(Some("interactive".to_owned()), config.job_pool());
+        assert!(config.join_bloom_filters());
+        assert_eq!(10, config.job_priority());
+        assert!(config.shuffle_replicate());
+        assert_eq!(2.5, config.analyze_sample_percent());
         Ok(())
     }
 
@@ -318,6 +868,11 @@ mod tests {
             .build();
         assert!(config.is_err());
         assert_eq!("General(\"Failed to parse user-supplied value 'ballista.with_information_schema' for configuration setting '123': ParseBoolError\")", format!("{:?}", config.unwrap_err()));
+
+        let config = BallistaConfig::builder()
+            .set(BALLISTA_RESULTS_COMPRESSION, "gzip")
+            .build();
+        assert!(config.is_err());
         Ok(())
     }
 }
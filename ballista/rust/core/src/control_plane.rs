@@ -0,0 +1,123 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Connections of the control plane between executors and the scheduler.
+//!
+//! The scheduler considers an executor lost once it has not heard from it for a while,
+//! so executors send their heartbeats and the statuses of their tasks over a connection
+//! of their own, which is never shared with other requests. The connection disables
+//! Nagle's algorithm and sends HTTP/2 pings, so that a connection broken by a saturated
+//! or failing network path is detected and opened again rather than hanging until the
+//! TCP timeouts. Its packets can also be marked with a DSCP class, for networks which
+//! prioritize them over the shuffle traffic between executors.
+
+use crate::error::{BallistaError, Result};
+use crate::tls::grpc_endpoint;
+use std::io;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tonic::transport::{Channel, Uri};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Interval of the pings on the connection, and how long it waits for their answer
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Largest DSCP class, which has 6 bits
+const MAX_DSCP: u8 = 63;
+
+/// Open a control plane connection to the scheduler at `url`, whose packets are marked
+/// with the DSCP class `dscp` unless it is 0
+pub async fn create_control_channel(url: &str, dscp: u8) -> Result<Channel> {
+    if dscp > MAX_DSCP {
+        return Err(BallistaError::General(format!(
+            "Invalid DSCP class {}, expected 0 to {}",
+            dscp, MAX_DSCP
+        )));
+    }
+    let channel = grpc_endpoint(url)?
+        .connect_timeout(CONNECT_TIMEOUT)
+        .http2_keep_alive_interval(KEEP_ALIVE_INTERVAL)
+        .keep_alive_timeout(KEEP_ALIVE_TIMEOUT)
+        .keep_alive_while_idle(true)
+        .connect_with_connector(tower::service_fn(move |uri: Uri| connect(uri, dscp)))
+        .await?;
+    Ok(channel)
+}
+
+/// Connect to the host of `uri`. TLS is set up on top of the connection by the
+/// endpoint, if enabled.
+async fn connect(uri: Uri, dscp: u8) -> io::Result<TcpStream> {
+    let host = uri.host().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("No host in {}", uri))
+    })?;
+    // IPv6 addresses are enclosed in brackets in URIs
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        _ => 80,
+    };
+    let port = uri.port_u16().unwrap_or(default_port);
+    let stream = TcpStream::connect((host, port)).await?;
+    stream.set_nodelay(true)?;
+    if dscp > 0 {
+        mark(&stream, dscp)?;
+    }
+    Ok(stream)
+}
+
+/// Mark the packets of `stream` with DSCP class `dscp`, the upper 6 bits of their type
+/// of service. Only IPv4 connections are marked.
+#[cfg(unix)]
+fn mark(stream: &TcpStream, dscp: u8) -> io::Result<()> {
+    if stream.local_addr()?.is_ipv4() {
+        socket2::SockRef::from(stream).set_tos((dscp as u32) << 2)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn mark(_stream: &TcpStream, _dscp: u8) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn connect_without_delay() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let uri: Uri = format!("http://{}", listener.local_addr()?)
+            .parse()
+            .unwrap();
+        let stream = connect(uri, 46).await?;
+        assert!(stream.nodelay()?);
+        #[cfg(unix)]
+        assert_eq!(socket2::SockRef::from(&stream).tos()?, 46 << 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reject_invalid_dscp() {
+        assert!(create_control_channel("http://localhost:50050", 64)
+            .await
+            .is_err());
+    }
+}
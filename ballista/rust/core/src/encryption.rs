@@ -0,0 +1,275 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Encryption of shuffle files at rest.
+//!
+//! The scheduler generates an [EncryptionKey] for every job and sends it to executors
+//! along with the tasks of the job. Shuffle files are encrypted with AES-256 in CTR mode
+//! under a random IV which is stored in the first bytes of the file. CTR mode lets
+//! readers seek, which the Arrow IPC file reader relies on.
+//!
+//! Encryption protects the confidentiality of the files but not their integrity.
+
+use crate::error::{BallistaError, Result};
+use aes::Aes256;
+use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::convert::TryInto;
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+type Cipher = ctr::Ctr128BE<Aes256>;
+
+const KEY_LEN: usize = 32;
+const IV_LEN: usize = 16;
+/// Size of the buffers of shuffle files, the default of [BufWriter]
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Key encrypting the shuffle files of a single job
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; KEY_LEN]);
+
+impl EncryptionKey {
+    /// A new random key
+    pub fn generate() -> Self {
+        let mut key = [0; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key = bytes.try_into().map_err(|_| {
+            BallistaError::General(format!(
+                "Invalid encryption key length {}, expected {}",
+                bytes.len(),
+                KEY_LEN
+            ))
+        })?;
+        Ok(Self(key))
+    }
+
+    /// The key sent with a task, where an empty value means no encryption
+    pub fn from_proto(bytes: &[u8]) -> Result<Option<Self>> {
+        if bytes.is_empty() {
+            Ok(None)
+        } else {
+            Self::try_from_bytes(bytes).map(Some)
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn cipher(&self, iv: &[u8]) -> Cipher {
+        // Both lengths are fixed, so this cannot fail
+        Cipher::new_from_slices(&self.0, iv).expect("valid key and IV length")
+    }
+}
+
+impl Debug for EncryptionKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Never log the key itself
+        write!(f, "EncryptionKey(..)")
+    }
+}
+
+/// Encrypts everything written to it. Like a [BufWriter], it collects small writes in
+/// a buffer, which is encrypted in place before it is written to `inner`.
+pub struct EncryptedWriter<W: Write> {
+    inner: W,
+    cipher: Cipher,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptedWriter<W> {
+    /// Start a new encrypted file in `inner`, writing a random IV first
+    pub fn try_new(mut inner: W, key: &EncryptionKey) -> io::Result<Self> {
+        let mut iv = [0; IV_LEN];
+        OsRng.fill_bytes(&mut iv);
+        inner.write_all(&iv)?;
+        Ok(Self {
+            inner,
+            cipher: key.cipher(&iv),
+            buffer: Vec::with_capacity(BUFFER_SIZE),
+        })
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.cipher.apply_keystream(&mut self.buffer);
+        // The keystream has advanced past the buffer, so it must be written in full
+        let result = self.inner.write_all(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+}
+
+impl<W: Write> Write for EncryptedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BUFFER_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == BUFFER_SIZE {
+            self.write_buffer()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for EncryptedWriter<W> {
+    fn drop(&mut self) {
+        // Errors are ignored as in BufWriter, callers see them if they flush
+        let _ = self.flush();
+    }
+}
+
+/// Decrypts a file written by an [EncryptedWriter]
+pub struct EncryptedReader<R: Read + Seek> {
+    inner: R,
+    cipher: Cipher,
+}
+
+impl<R: Read + Seek> EncryptedReader<R> {
+    pub fn try_new(mut inner: R, key: &EncryptionKey) -> io::Result<Self> {
+        inner.seek(SeekFrom::Start(0))?;
+        let mut iv = [0; IV_LEN];
+        inner.read_exact(&mut iv)?;
+        Ok(Self {
+            inner,
+            cipher: key.cipher(&iv),
+        })
+    }
+}
+
+impl<R: Read + Seek> Read for EncryptedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.cipher.apply_keystream(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for EncryptedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        // Positions are relative to the plaintext, which starts after the IV
+        let pos = match pos {
+            SeekFrom::Start(offset) => SeekFrom::Start(offset + IV_LEN as u64),
+            pos => pos,
+        };
+        let offset = self.inner.seek(pos)?;
+        let offset = offset.checked_sub(IV_LEN as u64).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the encrypted data",
+            )
+        })?;
+        self.cipher.seek(offset);
+        Ok(offset)
+    }
+}
+
+/// Something shuffle files can be read from
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Create a shuffle file, encrypting it if a key is given
+pub fn create_file(
+    path: impl AsRef<Path>,
+    key: Option<&EncryptionKey>,
+) -> io::Result<Box<dyn Write + Send>> {
+    let file = File::create(path)?;
+    Ok(match key {
+        Some(key) => Box::new(EncryptedWriter::try_new(file, key)?),
+        None => Box::new(BufWriter::with_capacity(BUFFER_SIZE, file)),
+    })
+}
+
+/// Open a shuffle file written by [create_file] with the same key
+pub fn open_file(
+    path: impl AsRef<Path>,
+    key: Option<&EncryptionKey>,
+) -> io::Result<Box<dyn ReadSeek + Send>> {
+    let file = File::open(path)?;
+    Ok(match key {
+        Some(key) => Box::new(EncryptedReader::try_new(BufReader::new(file), key)?),
+        None => Box::new(file),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip_with_seek() -> Result<()> {
+        let key = EncryptionKey::generate();
+        // Spans several buffers
+        let plaintext: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+
+        let mut ciphertext = vec![];
+        let mut writer = EncryptedWriter::try_new(&mut ciphertext, &key)?;
+        writer.write_all(&plaintext[..300])?;
+        writer.write_all(&plaintext[300..])?;
+        writer.flush()?;
+        drop(writer);
+        assert_eq!(ciphertext.len(), IV_LEN + plaintext.len());
+        assert_ne!(&ciphertext[IV_LEN..], plaintext.as_slice());
+
+        let mut reader = EncryptedReader::try_new(Cursor::new(ciphertext.clone()), &key)?;
+        let mut decrypted = vec![];
+        reader.read_to_end(&mut decrypted)?;
+        assert_eq!(decrypted, plaintext);
+
+        let mut tail = [0; 10];
+        assert_eq!(reader.seek(SeekFrom::End(-10))?, 19_990);
+        reader.read_exact(&mut tail)?;
+        assert_eq!(&tail, &plaintext[19_990..]);
+
+        assert_eq!(reader.seek(SeekFrom::Start(17))?, 17);
+        reader.read_exact(&mut tail)?;
+        assert_eq!(&tail, &plaintext[17..27]);
+        assert!(reader.seek(SeekFrom::Current(-100)).is_err());
+
+        // A different key produces garbage
+        let mut reader = EncryptedReader::try_new(
+            Cursor::new(ciphertext),
+            &EncryptionKey::generate(),
+        )?;
+        let mut garbage = vec![];
+        reader.read_to_end(&mut garbage)?;
+        assert_ne!(garbage, plaintext);
+        Ok(())
+    }
+
+    #[test]
+    fn key_from_proto() -> Result<()> {
+        assert!(EncryptionKey::from_proto(&[])?.is_none());
+        let key = EncryptionKey::generate();
+        assert_eq!(EncryptionKey::from_proto(key.as_bytes())?, Some(key));
+        assert!(EncryptionKey::from_proto(&[1, 2, 3]).is_err());
+        Ok(())
+    }
+}
@@ -47,6 +47,14 @@ pub enum BallistaError {
     TonicError(tonic::transport::Error),
     GrpcError(tonic::Status),
     TokioError(tokio::task::JoinError),
+    /// A plan node could not be serialized or deserialized. Holds the operators on the
+    /// path from the root of the plan down to the failing node, and the underlying error.
+    PlanSerdeError(Vec<String>, Box<BallistaError>),
+    /// Fetching a shuffle partition failed. Holds the id of the executor that produced
+    /// the partition, the stage id, the partition id and a description of the failure.
+    FetchFailed(String, usize, usize, String),
+    /// An operation ran for longer than it was allowed to
+    Timeout(String),
 }
 
 #[allow(clippy::from_over_into)]
@@ -60,6 +68,23 @@ pub fn ballista_error(message: &str) -> BallistaError {
     BallistaError::General(message.to_owned())
 }
 
+impl BallistaError {
+    /// Attach the plan operator whose serde failed to this error. Called at every level
+    /// of the plan tree as the error propagates, so the outermost operator ends up first
+    /// in the path.
+    pub fn with_plan_context(self, operator: impl Into<String>) -> Self {
+        match self {
+            BallistaError::PlanSerdeError(mut path, source) => {
+                path.insert(0, operator.into());
+                BallistaError::PlanSerdeError(path, source)
+            }
+            other => {
+                BallistaError::PlanSerdeError(vec![operator.into()], Box::new(other))
+            }
+        }
+    }
+}
+
 impl From<String> for BallistaError {
     fn from(e: String) -> Self {
         BallistaError::General(e)
@@ -178,6 +203,21 @@ impl Display for BallistaError {
                 write!(f, "Internal Ballista error: {}", desc)
             }
             BallistaError::TokioError(desc) => write!(f, "Tokio join error: {}", desc),
+            BallistaError::FetchFailed(executor_id, stage_id, partition_id, desc) => {
+                write!(
+                    f,
+                    "Failed to fetch partition {}/{} from executor {}: {}",
+                    stage_id, partition_id, executor_id, desc
+                )
+            }
+            BallistaError::Timeout(desc) => write!(f, "Timeout: {}", desc),
+            BallistaError::PlanSerdeError(path, source) => write!(
+                f,
+                "Failed to serde plan node {} at plan path [{}]: {}",
+                path.last().map(|s| s.as_str()).unwrap_or("<unknown>"),
+                path.join(" -> "),
+                source
+            ),
         }
     }
 }
@@ -132,6 +132,14 @@ pub struct EventSender<E> {
     tx_event: mpsc::Sender<E>,
 }
 
+impl<E> Clone for EventSender<E> {
+    fn clone(&self) -> Self {
+        Self {
+            tx_event: self.tx_event.clone(),
+        }
+    }
+}
+
 impl<E> EventSender<E> {
     pub async fn post_event(&self, event: E) -> Result<()> {
         self.tx_event.send(event).await.map_err(|e| {
@@ -0,0 +1,837 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Bloom filters of the join keys of the build side of a join, used to drop the rows of
+//! the probe side which cannot match before they are shuffled.
+//!
+//! BloomFilterBuildExec inserts the keys of the rows of the build side in a filter as
+//! they pass through it. The filters of all the tasks of the build stage are merged by
+//! the scheduler, which hands the merged filter to the BloomFilterExec of the probe
+//! stage when it resolves its inputs. BloomFilterExec then drops the rows whose keys are
+//! certainly not in the filter.
+//!
+//! When the probe stage scans Parquet files, the readers of the scan producing the key
+//! also skip the row groups none of whose keys may be in the filter, by the statistics
+//! of the key: integer keys whose row groups span a few values, and keys of a single
+//! value in a row group.
+//!
+//! Keys are hashed with the vectorized hash of DataFusion, with fixed seeds, so that the
+//! filters built by different executors agree, as the hash partitions of their shuffles
+//! do. Rows with a null key never match and are not inserted.
+
+use ahash::RandomState;
+use bytes::Bytes;
+use datafusion::arrow::array::{Array, ArrayRef, BooleanArray, Int64Array, StringArray};
+use datafusion::arrow::compute::{cast, filter_record_batch};
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::arrow::error::ArrowError;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::datasource::file_format::parquet::fetch_parquet_metadata;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::parquet::arrow::async_reader::AsyncFileReader;
+use datafusion::parquet::errors::ParquetError;
+use datafusion::parquet::file::metadata::{ParquetMetaData, RowGroupMetaData};
+use datafusion::parquet::file::statistics::Statistics as ParquetStatistics;
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+use datafusion::physical_plan::expressions::{Column, PhysicalSortExpr};
+use datafusion::physical_plan::file_format::{
+    FileMeta, ParquetExec, ParquetFileReaderFactory,
+};
+use datafusion::physical_plan::filter::FilterExec;
+use datafusion::physical_plan::hash_utils::create_hashes;
+use datafusion::physical_plan::metrics::{Count, ExecutionPlanMetricsSet, MetricBuilder};
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt, TryFutureExt};
+use log::info;
+use object_store::{ObjectMeta, ObjectStore};
+use parking_lot::Mutex;
+use std::any::Any;
+use std::fmt::{self, Formatter};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Number of bits set for every key
+const NUM_HASHES: u64 = 4;
+
+/// Seeds of the hashes of keys
+const SEEDS: [u64; 4] = [
+    0x243f_6a88_85a3_08d3,
+    0x1319_8a2e_0370_7344,
+    0xa409_3822_299f_31d0,
+    0x082e_fa98_ec4e_6c89,
+];
+
+/// Most values of an integer key a row group may span for its values to be looked up
+/// in a filter
+const MAX_ROW_GROUP_KEYS: i128 = 1024;
+
+/// A Bloom filter of the hashes of join keys
+#[derive(Clone, PartialEq)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl fmt::Debug for BloomFilter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "BloomFilter({} bytes)", self.bits.len() * 8)
+    }
+}
+
+impl BloomFilter {
+    /// An empty filter of about `num_bytes` bytes
+    pub fn new(num_bytes: usize) -> Self {
+        Self {
+            bits: vec![0; (num_bytes / 8).max(1)],
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() || bytes.len() % 8 != 0 {
+            return Err(DataFusionError::Internal(format!(
+                "Invalid Bloom filter of {} bytes",
+                bytes.len()
+            )));
+        }
+        let bits = bytes
+            .chunks(8)
+            .map(|word| {
+                let mut buf = [0; 8];
+                buf.copy_from_slice(word);
+                u64::from_le_bytes(buf)
+            })
+            .collect();
+        Ok(Self { bits })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    /// Add the keys of `other` to this filter, which must be of the same size
+    pub fn union(&mut self, other: &BloomFilter) -> Result<()> {
+        if self.bits.len() != other.bits.len() {
+            return Err(DataFusionError::Internal(format!(
+                "Cannot merge Bloom filters of {} and {} bytes",
+                self.bits.len() * 8,
+                other.bits.len() * 8
+            )));
+        }
+        for (word, other) in self.bits.iter_mut().zip(&other.bits) {
+            *word |= other;
+        }
+        Ok(())
+    }
+
+    /// Insert the keys of the rows of `keys` which have no null key
+    pub fn insert(&mut self, keys: &[ArrayRef]) -> Result<()> {
+        for (row, hash) in key_hashes(keys)?.into_iter().enumerate() {
+            if !has_null_key(keys, row) {
+                for bit in self.bit_positions(hash) {
+                    self.bits[bit / 64] |= 1u64 << (bit % 64);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the keys of each row of `keys` may be in the filter. Rows with a null key
+    /// are not.
+    pub fn contains(&self, keys: &[ArrayRef]) -> Result<BooleanArray> {
+        Ok(key_hashes(keys)?
+            .into_iter()
+            .enumerate()
+            .map(|(row, hash)| {
+                Some(
+                    !has_null_key(keys, row)
+                        && self
+                            .bit_positions(hash)
+                            .all(|bit| self.bits[bit / 64] & (1u64 << (bit % 64)) != 0),
+                )
+            })
+            .collect())
+    }
+
+    /// The bits of a key, derived from its hash by double hashing
+    fn bit_positions(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 64;
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32);
+        (0..NUM_HASHES)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+}
+
+fn num_rows(keys: &[ArrayRef]) -> usize {
+    keys.first().map(|key| key.len()).unwrap_or(0)
+}
+
+/// The hashes of the keys of the rows of `keys`
+fn key_hashes(keys: &[ArrayRef]) -> Result<Vec<u64>> {
+    let random_state = RandomState::with_seeds(SEEDS[0], SEEDS[1], SEEDS[2], SEEDS[3]);
+    let mut hashes = vec![0; num_rows(keys)];
+    create_hashes(keys, &random_state, &mut hashes)?;
+    Ok(hashes)
+}
+
+fn has_null_key(keys: &[ArrayRef], row: usize) -> bool {
+    keys.iter().any(|key| key.is_null(row))
+}
+
+fn key_columns(batch: &RecordBatch, keys: &[Column]) -> Vec<ArrayRef> {
+    keys.iter()
+        .map(|key| batch.column(key.index()).clone())
+        .collect()
+}
+
+/// BloomFilterBuildExec passes its input through, inserting the `keys` of its rows in a
+/// Bloom filter of `num_bytes` bytes
+#[derive(Debug)]
+pub struct BloomFilterBuildExec {
+    input: Arc<dyn ExecutionPlan>,
+    keys: Vec<Column>,
+    num_bytes: usize,
+    filter: Arc<Mutex<BloomFilter>>,
+}
+
+impl BloomFilterBuildExec {
+    pub fn new(
+        input: Arc<dyn ExecutionPlan>,
+        keys: Vec<Column>,
+        num_bytes: usize,
+    ) -> Self {
+        Self {
+            input,
+            keys,
+            num_bytes,
+            filter: Arc::new(Mutex::new(BloomFilter::new(num_bytes))),
+        }
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    pub fn keys(&self) -> &[Column] {
+        &self.keys
+    }
+
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    /// The filter of the keys of the rows passed through so far
+    pub fn bloom_filter(&self) -> BloomFilter {
+        self.filter.lock().clone()
+    }
+}
+
+impl ExecutionPlan for BloomFilterBuildExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        self.input.output_ordering()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "BloomFilterBuildExec wrong number of children".to_string(),
+            ));
+        }
+        Ok(Arc::new(BloomFilterBuildExec::new(
+            children[0].clone(),
+            self.keys.clone(),
+            self.num_bytes,
+        )))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("BloomFilterBuildExec::execute({})", partition);
+
+        let input = self.input.execute(partition, context)?;
+        let keys = self.keys.clone();
+        let filter = self.filter.clone();
+        let stream = input.map(move |batch| {
+            let batch = batch?;
+            filter
+                .lock()
+                .insert(&key_columns(&batch, &keys))
+                .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+            Ok(batch)
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let keys: Vec<String> = self.keys.iter().map(|k| k.to_string()).collect();
+                write!(
+                    f,
+                    "BloomFilterBuildExec: keys=[{}], bytes={}",
+                    keys.join(", "),
+                    self.num_bytes
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.input.statistics()
+    }
+}
+
+/// BloomFilterExec drops the rows of its input whose `keys` are not in the Bloom filter
+/// built by stage `build_stage_id`. It passes all rows through until the filter is set.
+#[derive(Debug, Clone)]
+pub struct BloomFilterExec {
+    input: Arc<dyn ExecutionPlan>,
+    build_stage_id: usize,
+    keys: Vec<Column>,
+    filter: Option<Arc<BloomFilter>>,
+}
+
+impl BloomFilterExec {
+    pub fn new(
+        input: Arc<dyn ExecutionPlan>,
+        build_stage_id: usize,
+        keys: Vec<Column>,
+    ) -> Self {
+        Self {
+            input,
+            build_stage_id,
+            keys,
+            filter: None,
+        }
+    }
+
+    pub fn with_filter(mut self, filter: Option<Arc<BloomFilter>>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    /// The stage whose rows the filter is built from
+    pub fn build_stage_id(&self) -> usize {
+        self.build_stage_id
+    }
+
+    pub fn keys(&self) -> &[Column] {
+        &self.keys
+    }
+
+    pub fn filter(&self) -> Option<&Arc<BloomFilter>> {
+        self.filter.as_ref()
+    }
+
+    /// Have the Parquet scan producing the key of this operator skip the row groups
+    /// whose keys are not in the filter, reading its files from the object stores of
+    /// `runtime`. The input is left as it is when the filter is not set, when there are
+    /// several keys, or when the key is not read unchanged from a scan through filters
+    /// and projections.
+    pub fn with_row_group_filter(mut self, runtime: &RuntimeEnv) -> Result<Self> {
+        if let (Some(filter), [key]) = (&self.filter, self.keys.as_slice()) {
+            if let Some(input) =
+                with_row_group_filter(&self.input, key.index(), filter, runtime)?
+            {
+                self.input = input;
+            }
+        }
+        Ok(self)
+    }
+}
+
+/// `plan` with the Parquet scan producing its column `key` reading its files with a
+/// [RowGroupFilterFactory], if there is one
+fn with_row_group_filter(
+    plan: &Arc<dyn ExecutionPlan>,
+    key: usize,
+    filter: &Arc<BloomFilter>,
+    runtime: &RuntimeEnv,
+) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+    let any = plan.as_any();
+    if let Some(scan) = any.downcast_ref::<ParquetExec>() {
+        let config = scan.base_config();
+        let schema = scan.schema();
+        let field = schema.field(key);
+        // Partition columns are not in the files
+        if config.table_partition_cols.contains(field.name())
+            || !RowGroupFilterFactory::supports(field.data_type())
+        {
+            return Ok(None);
+        }
+        // Plans are also decoded by the scheduler, which may not know the stores of
+        // the job
+        let store = match runtime.object_store(&config.object_store_url) {
+            Ok(store) => store,
+            Err(_) => return Ok(None),
+        };
+        let factory = RowGroupFilterFactory {
+            store,
+            column: field.name().clone(),
+            data_type: field.data_type().clone(),
+            filter: filter.clone(),
+        };
+        let predicate = scan
+            .pruning_predicate()
+            .map(|predicate| predicate.logical_expr().clone());
+        let scan = ParquetExec::new(config.clone(), predicate, None)
+            .with_parquet_file_reader_factory(Arc::new(factory));
+        return Ok(Some(Arc::new(scan)));
+    }
+
+    let input_key = if let Some(projection) = any.downcast_ref::<ProjectionExec>() {
+        match projection.expr()[key].0.as_any().downcast_ref::<Column>() {
+            Some(column) => column.index(),
+            None => return Ok(None),
+        }
+    } else if any.is::<FilterExec>() || any.is::<CoalesceBatchesExec>() {
+        key
+    } else {
+        return Ok(None);
+    };
+    match with_row_group_filter(&plan.children()[0], input_key, filter, runtime)? {
+        Some(input) => Ok(Some(plan.clone().with_new_children(vec![input])?)),
+        None => Ok(None),
+    }
+}
+
+/// Creates the readers of the Parquet files of a scan, which skip the row groups whose
+/// values of `column` cannot be in `filter`
+#[derive(Debug)]
+struct RowGroupFilterFactory {
+    store: Arc<dyn ObjectStore>,
+    column: String,
+    data_type: DataType,
+    filter: Arc<BloomFilter>,
+}
+
+impl RowGroupFilterFactory {
+    /// Whether the values of a key of `data_type` can be derived from the statistics of
+    /// the row groups, and hashed as the key is
+    fn supports(data_type: &DataType) -> bool {
+        matches!(
+            data_type,
+            DataType::Int8
+                | DataType::Int16
+                | DataType::Int32
+                | DataType::Int64
+                | DataType::Utf8
+        )
+    }
+}
+
+impl ParquetFileReaderFactory for RowGroupFilterFactory {
+    fn create_reader(
+        &self,
+        partition_index: usize,
+        file_meta: FileMeta,
+        metadata_size_hint: Option<usize>,
+        metrics: &ExecutionPlanMetricsSet,
+    ) -> Result<Box<dyn AsyncFileReader + Send>> {
+        Ok(Box::new(RowGroupFilterReader {
+            store: self.store.clone(),
+            meta: file_meta.object_meta,
+            metadata_size_hint,
+            column: self.column.clone(),
+            data_type: self.data_type.clone(),
+            filter: self.filter.clone(),
+            row_groups_pruned: MetricBuilder::new(metrics)
+                .counter("row_groups_pruned_bloom_filter", partition_index),
+        }))
+    }
+}
+
+/// Reads a Parquet file, leaving the row groups whose keys are not in the filter out of
+/// its metadata, so that they are not read
+struct RowGroupFilterReader {
+    store: Arc<dyn ObjectStore>,
+    meta: ObjectMeta,
+    metadata_size_hint: Option<usize>,
+    column: String,
+    data_type: DataType,
+    filter: Arc<BloomFilter>,
+    row_groups_pruned: Count,
+}
+
+impl RowGroupFilterReader {
+    /// Whether some of the keys of `row_group` may be in the filter, by the statistics of
+    /// the key
+    fn may_contain(&self, row_group: &RowGroupMetaData) -> bool {
+        let statistics = row_group
+            .columns()
+            .iter()
+            .find(|column| column.column_path().string() == self.column)
+            .and_then(|column| column.statistics());
+        let statistics = match statistics {
+            Some(statistics) if statistics.has_min_max_set() => statistics,
+            _ => return true,
+        };
+        let values: ArrayRef = match statistics {
+            ParquetStatistics::Int32(s) => {
+                match int_range(*s.min() as i64, *s.max() as i64) {
+                    Some(values) => values,
+                    None => return true,
+                }
+            }
+            ParquetStatistics::Int64(s) => match int_range(*s.min(), *s.max()) {
+                Some(values) => values,
+                None => return true,
+            },
+            ParquetStatistics::ByteArray(s) if s.min() == s.max() => {
+                match s.min().as_utf8() {
+                    Ok(value) => Arc::new(StringArray::from(vec![value])),
+                    Err(_) => return true,
+                }
+            }
+            _ => return true,
+        };
+        // The values are hashed as the keys of the scanned type were
+        cast(&values, &self.data_type)
+            .map_err(DataFusionError::from)
+            .and_then(|values| self.filter.contains(&[values]))
+            .map(|contained| contained.iter().any(|value| value == Some(true)))
+            .unwrap_or(true)
+    }
+}
+
+/// The integers from `min` to `max`, unless there are too many of them to look up
+fn int_range(min: i64, max: i64) -> Option<ArrayRef> {
+    if max < min || max as i128 - min as i128 >= MAX_ROW_GROUP_KEYS {
+        return None;
+    }
+    Some(Arc::new(Int64Array::from_iter_values(min..=max)))
+}
+
+impl AsyncFileReader for RowGroupFilterReader {
+    fn get_bytes(
+        &mut self,
+        range: Range<usize>,
+    ) -> BoxFuture<'_, datafusion::parquet::errors::Result<Bytes>> {
+        let location = &self.meta.location;
+        self.store
+            .get_range(location, range)
+            .map_err(move |e| {
+                ParquetError::General(format!("Failed to read {}: {}", location, e))
+            })
+            .boxed()
+    }
+
+    fn get_metadata(
+        &mut self,
+    ) -> BoxFuture<'_, datafusion::parquet::errors::Result<Arc<ParquetMetaData>>> {
+        Box::pin(async move {
+            let metadata = fetch_parquet_metadata(
+                self.store.as_ref(),
+                &self.meta,
+                self.metadata_size_hint,
+            )
+            .await
+            .map_err(|e| ParquetError::General(e.to_string()))?;
+            let row_groups: Vec<RowGroupMetaData> = metadata
+                .row_groups()
+                .iter()
+                .filter(|row_group| self.may_contain(row_group))
+                .cloned()
+                .collect();
+            self.row_groups_pruned
+                .add(metadata.num_row_groups() - row_groups.len());
+            Ok(Arc::new(ParquetMetaData::new(
+                metadata.file_metadata().clone(),
+                row_groups,
+            )))
+        })
+    }
+}
+
+impl ExecutionPlan for BloomFilterExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        self.input.output_ordering()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "BloomFilterExec wrong number of children".to_string(),
+            ));
+        }
+        Ok(Arc::new(
+            BloomFilterExec::new(
+                children[0].clone(),
+                self.build_stage_id,
+                self.keys.clone(),
+            )
+            .with_filter(self.filter.clone()),
+        ))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("BloomFilterExec::execute({})", partition);
+
+        let input = self.input.execute(partition, context)?;
+        let filter = match &self.filter {
+            Some(filter) => filter.clone(),
+            None => return Ok(input),
+        };
+        let keys = self.keys.clone();
+        let stream = input.map(move |batch| {
+            let batch = batch?;
+            let mask = filter
+                .contains(&key_columns(&batch, &keys))
+                .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+            filter_record_batch(&batch, &mask)
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let keys: Vec<String> = self.keys.iter().map(|k| k.to_string()).collect();
+                write!(
+                    f,
+                    "BloomFilterExec: build_stage={}, keys=[{}], resolved={}",
+                    self.build_stage_id,
+                    keys.join(", "),
+                    self.filter.is_some()
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::common;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+
+    #[tokio::test]
+    async fn drop_rows_not_in_build_side() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = |ids: Vec<Option<i64>>| {
+            let names: Vec<String> = ids.iter().map(|id| format!("{:?}", id)).collect();
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(StringArray::from(names)),
+                ],
+            )
+        };
+        let num_rows = |batches: &[RecordBatch]| -> usize {
+            batches.iter().map(|batch| batch.num_rows()).sum()
+        };
+        let keys = vec![Column::new("id", 0)];
+        let ctx = SessionContext::new();
+
+        let build_input = Arc::new(MemoryExec::try_new(
+            &[
+                vec![batch(vec![Some(1), Some(3), None])?],
+                vec![batch(vec![Some(5)])?],
+            ],
+            schema.clone(),
+            None,
+        )?);
+        let mut filter = BloomFilter::new(1024);
+        for partition in 0..2 {
+            let build =
+                BloomFilterBuildExec::new(build_input.clone(), keys.clone(), 1024);
+            // Rows pass through the build side unchanged
+            let batches =
+                common::collect(build.execute(partition, ctx.task_ctx())?).await?;
+            assert_eq!(num_rows(&batches), [3, 1][partition]);
+            let task_filter = BloomFilter::from_bytes(&build.bloom_filter().to_bytes())?;
+            filter.union(&task_filter)?;
+        }
+        assert!(filter.union(&BloomFilter::new(64)).is_err());
+
+        let probe_input = Arc::new(MemoryExec::try_new(
+            &[vec![batch(vec![Some(1), Some(2), Some(5), None, Some(7)])?]],
+            schema.clone(),
+            None,
+        )?);
+        let probe = BloomFilterExec::new(probe_input.clone(), 1, keys.clone());
+        let batches = common::collect(probe.execute(0, ctx.task_ctx())?).await?;
+        assert_eq!(num_rows(&batches), 5);
+
+        let probe = probe.with_filter(Some(Arc::new(filter)));
+        let batches = common::collect(probe.execute(0, ctx.task_ctx())?).await?;
+        let ids: Vec<Option<i64>> = batches
+            .iter()
+            .flat_map(|batch| {
+                batch
+                    .column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .iter()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        // The keys of the build side are always kept, and the null key is dropped.
+        // Keys 2 and 7 are very unlikely to be false positives in a filter of 1 KiB.
+        assert_eq!(ids, vec![Some(1), Some(5)]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skip_row_groups_not_in_build_side() -> Result<()> {
+        use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+        use datafusion::parquet::arrow::ArrowWriter;
+        use datafusion::parquet::file::properties::WriterProperties;
+        use datafusion::physical_plan::file_format::FileScanConfig;
+        use futures::TryStreamExt;
+
+        let schema =
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let dir = tempfile::tempdir()?;
+        let file = std::fs::File::create(dir.path().join("ids.parquet"))?;
+        // Row groups of the ids 0 to 99 and 1000 to 1099
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(100)
+            .build();
+        let mut writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+        writer.write(&RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from_iter_values(
+                (0..100).chain(1000..1100),
+            ))],
+        )?)?;
+        writer.close()?;
+
+        let ctx = SessionContext::new();
+        let runtime = ctx.runtime_env();
+        let url = ListingTableUrl::parse(dir.path().to_str().unwrap())?;
+        let store = runtime.object_store(url.object_store())?;
+        let files: Vec<PartitionedFile> = url
+            .list_all_files(store.as_ref(), ".parquet")
+            .map_ok(|object_meta| PartitionedFile {
+                object_meta,
+                partition_values: vec![],
+                range: None,
+            })
+            .try_collect()
+            .await?;
+        let scan = Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: url.object_store(),
+                file_schema: schema.clone(),
+                file_groups: vec![files],
+                statistics: Statistics::default(),
+                projection: None,
+                limit: None,
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+        ));
+
+        let mut filter = BloomFilter::new(1024);
+        filter.insert(&[Arc::new(Int64Array::from(vec![5, 50])) as ArrayRef])?;
+        let probe = BloomFilterExec::new(scan, 1, vec![Column::new("id", 0)])
+            .with_filter(Some(Arc::new(filter)))
+            .with_row_group_filter(&runtime)?;
+        let batches = common::collect(probe.execute(0, ctx.task_ctx())?).await?;
+        let num_rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+        assert_eq!(num_rows, 2);
+
+        // Only the first row group was read
+        let pruned: usize = probe
+            .input()
+            .metrics()
+            .unwrap()
+            .iter()
+            .filter(|metric| metric.value().name() == "row_groups_pruned_bloom_filter")
+            .map(|metric| metric.value().as_usize())
+            .sum();
+        assert_eq!(pruned, 1);
+        Ok(())
+    }
+}
@@ -15,14 +15,19 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use crate::auth::authorized_request;
 use crate::client::BallistaClient;
 use crate::config::BallistaConfig;
+use crate::flight_transfer::IpcCompression;
 use crate::serde::protobuf::execute_query_params::OptionalSessionId;
 use crate::serde::protobuf::{
     execute_query_params::Query, job_status, scheduler_grpc_client::SchedulerGrpcClient,
     ExecuteQueryParams, GetJobStatusParams, GetJobStatusResult, KeyValuePair,
     PartitionLocation,
 };
+use crate::serde::BallistaLogicalExtensionCodec;
+use crate::telemetry;
+use crate::tls::create_grpc_channel;
 use datafusion::arrow::datatypes::SchemaRef;
 use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
 use datafusion::arrow::record_batch::RecordBatch;
@@ -34,16 +39,17 @@ use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
 use datafusion::physical_plan::{
     DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
 };
-use datafusion_proto::logical_plan::{
-    AsLogicalPlan, DefaultLogicalExtensionCodec, LogicalExtensionCodec,
-};
+use datafusion_proto::logical_plan::{AsLogicalPlan, LogicalExtensionCodec};
 use futures::{Stream, StreamExt, TryFutureExt, TryStreamExt};
 use log::{error, info};
+use opentelemetry::trace::{Span, TraceContextExt};
+use opentelemetry::Context;
 use std::any::Any;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::time::Duration;
+use tonic::transport::Channel;
 
 /// This operator sends a logical plan to a Ballista scheduler for execution and
 /// polls the scheduler until the query is complete and then fetches the resulting
@@ -76,7 +82,7 @@ impl<T: 'static + AsLogicalPlan> DistributedQueryExec<T> {
             scheduler_url,
             config,
             plan,
-            extension_codec: Arc::new(DefaultLogicalExtensionCodec {}),
+            extension_codec: Arc::new(BallistaLogicalExtensionCodec {}),
             plan_repr: PhantomData,
             session_id,
         }
@@ -116,6 +122,54 @@ impl<T: 'static + AsLogicalPlan> DistributedQueryExec<T> {
             session_id,
         }
     }
+
+    /// The request submitting the logical plan as a job, whose spans are recorded
+    /// under `trace_context`
+    fn query_params(
+        &self,
+        trace_context: Vec<KeyValuePair>,
+    ) -> Result<ExecuteQueryParams> {
+        let mut buf: Vec<u8> = vec![];
+        let plan_message =
+            T::try_from_logical_plan(&self.plan, self.extension_codec.as_ref()).map_err(
+                |e| {
+                    DataFusionError::Internal(format!(
+                        "failed to serialize logical plan: {:?}",
+                        e
+                    ))
+                },
+            )?;
+        plan_message.try_encode(&mut buf).map_err(|e| {
+            DataFusionError::Execution(format!("failed to encode logical plan: {:?}", e))
+        })?;
+
+        Ok(ExecuteQueryParams {
+            query: Some(Query::LogicalPlan(buf)),
+            settings: self
+                .config
+                .settings()
+                .iter()
+                .map(|(k, v)| KeyValuePair {
+                    key: k.to_owned(),
+                    value: v.to_owned(),
+                })
+                .collect::<Vec<_>>(),
+            optional_session_id: Some(OptionalSessionId::SessionId(
+                self.session_id.clone(),
+            )),
+            trace_context,
+            job_id: String::new(),
+        })
+    }
+
+    /// Submit the logical plan as a job without waiting for it to complete, returning
+    /// the ID of the job. Its output can be fetched with [fetch_job_output] once the
+    /// scheduler reports it completed.
+    pub async fn submit(&self) -> Result<String> {
+        let query = self.query_params(vec![])?;
+        let mut scheduler = connect_scheduler(&self.scheduler_url).await?;
+        submit_query(&mut scheduler, &self.session_id, query).await
+    }
 }
 
 impl<T: 'static + AsLogicalPlan> ExecutionPlan for DistributedQueryExec<T> {
@@ -164,39 +218,35 @@ impl<T: 'static + AsLogicalPlan> ExecutionPlan for DistributedQueryExec<T> {
     ) -> Result<SendableRecordBatchStream> {
         assert_eq!(0, partition);
 
-        let mut buf: Vec<u8> = vec![];
-        let plan_message =
-            T::try_from_logical_plan(&self.plan, self.extension_codec.as_ref()).map_err(
-                |e| {
-                    DataFusionError::Internal(format!(
-                        "failed to serialize logical plan: {:?}",
-                        e
-                    ))
-                },
-            )?;
-        plan_message.try_encode(&mut buf).map_err(|e| {
-            DataFusionError::Execution(format!("failed to encode logical plan: {:?}", e))
-        })?;
+        let mut span = telemetry::start_span("execute_query", &Context::current());
+        let trace_context = telemetry::inject_context(
+            &Context::current().with_remote_span_context(span.span_context().clone()),
+        );
 
-        let query = ExecuteQueryParams {
-            query: Some(Query::LogicalPlan(buf)),
-            settings: self
-                .config
-                .settings()
-                .iter()
-                .map(|(k, v)| KeyValuePair {
-                    key: k.to_owned(),
-                    value: v.to_owned(),
-                })
-                .collect::<Vec<_>>(),
-            optional_session_id: Some(OptionalSessionId::SessionId(
-                self.session_id.clone(),
-            )),
-        };
+        let query = self.query_params(trace_context)?;
 
+        let scheduler_url = self.scheduler_url.clone();
+        let session_id = self.session_id.clone();
+        // The output of jobs caching a table is kept by the executors
+        let fetch_results = self.config.cache_table().is_none();
+        let compression = self.config.results_compression();
         let stream = futures::stream::once(
-            execute_query(self.scheduler_url.clone(), self.session_id.clone(), query)
-                .map_err(|e| ArrowError::ExternalError(Box::new(e))),
+            async move {
+                let result = execute_query(
+                    scheduler_url,
+                    session_id,
+                    query,
+                    fetch_results,
+                    compression,
+                )
+                .await;
+                telemetry::end_span(
+                    &mut span,
+                    result.as_ref().err().map(|e| e.to_string()).as_deref(),
+                );
+                result
+            }
+            .map_err(|e| ArrowError::ExternalError(Box::new(e))),
         )
         .try_flatten();
 
@@ -228,20 +278,24 @@ impl<T: 'static + AsLogicalPlan> ExecutionPlan for DistributedQueryExec<T> {
     }
 }
 
-async fn execute_query(
-    scheduler_url: String,
-    session_id: String,
-    query: ExecuteQueryParams,
-) -> Result<impl Stream<Item = ArrowResult<RecordBatch>> + Send> {
+async fn connect_scheduler(scheduler_url: &str) -> Result<SchedulerGrpcClient<Channel>> {
     info!("Connecting to Ballista scheduler at {}", scheduler_url);
     // TODO reuse the scheduler to avoid connecting to the Ballista scheduler again and again
+    Ok(SchedulerGrpcClient::new(
+        create_grpc_channel(scheduler_url)
+            .await
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?,
+    ))
+}
 
-    let mut scheduler = SchedulerGrpcClient::connect(scheduler_url.clone())
-        .await
-        .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
-
+/// Submit `query` as a job, returning the ID of the job
+async fn submit_query(
+    scheduler: &mut SchedulerGrpcClient<Channel>,
+    session_id: &str,
+    query: ExecuteQueryParams,
+) -> Result<String> {
     let query_result = scheduler
-        .execute_query(query)
+        .execute_query(authorized_request(query))
         .await
         .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
         .into_inner();
@@ -250,15 +304,34 @@ async fn execute_query(
         session_id, query_result.session_id,
         "Session id inconsistent between Client and Server side in DistributedQueryExec."
     );
+    if let Some(hint) = &query_result.resource_hint {
+        info!(
+            "Job {} ran {} times before, recommended task slots: {}, executors: {}",
+            query_result.job_id,
+            hint.completed_runs,
+            hint.recommended_task_slots,
+            hint.recommended_executors
+        );
+    }
+    Ok(query_result.job_id)
+}
 
-    let job_id = query_result.job_id;
+async fn execute_query(
+    scheduler_url: String,
+    session_id: String,
+    query: ExecuteQueryParams,
+    fetch_results: bool,
+    compression: IpcCompression,
+) -> Result<impl Stream<Item = ArrowResult<RecordBatch>> + Send> {
+    let mut scheduler = connect_scheduler(&scheduler_url).await?;
+    let job_id = submit_query(&mut scheduler, &session_id, query).await?;
     let mut prev_status: Option<job_status::Status> = None;
 
     loop {
         let GetJobStatusResult { status } = scheduler
-            .get_job_status(GetJobStatusParams {
+            .get_job_status(authorized_request(GetJobStatusParams {
                 job_id: job_id.clone(),
-            })
+            }))
             .await
             .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?
             .into_inner();
@@ -268,9 +341,20 @@ async fn execute_query(
         let wait_future = tokio::time::sleep(Duration::from_millis(100));
         let has_status_change = prev_status.map(|x| x != status).unwrap_or(true);
         match status {
-            job_status::Status::Queued(_) => {
+            job_status::Status::Queued(ref queued) => {
                 if has_status_change {
-                    info!("Job {} still queued...", job_id);
+                    match &queued.queue {
+                        Some(queue) if queue.eta_seconds > 0 => info!(
+                            "Job {} still queued at position {} of the {} queue, \
+                             about {}s to go...",
+                            job_id, queue.position, queue.queue, queue.eta_seconds
+                        ),
+                        Some(queue) => info!(
+                            "Job {} still queued at position {} of the {} queue...",
+                            job_id, queue.position, queue.queue
+                        ),
+                        None => info!("Job {} still queued...", job_id),
+                    }
                 }
                 wait_future.await;
                 prev_status = Some(status);
@@ -283,26 +367,41 @@ async fn execute_query(
                 prev_status = Some(status);
             }
             job_status::Status::Failed(err) => {
-                let msg = format!("Job {} failed: {}", job_id, err.error);
+                let msg =
+                    format!("Job {} failed ({:?}): {}", job_id, err.kind(), err.error);
                 error!("{}", msg);
                 break Err(DataFusionError::Execution(msg));
             }
             job_status::Status::Completed(completed) => {
-                let streams = completed.partition_location.into_iter().map(|p| {
-                    let f = fetch_partition(p)
-                        .map_err(|e| ArrowError::ExternalError(Box::new(e)));
-
-                    futures::stream::once(f).try_flatten()
-                });
-
-                break Ok(futures::stream::iter(streams).flatten());
+                let partition_location = if fetch_results {
+                    completed.partition_location
+                } else {
+                    vec![]
+                };
+                break Ok(fetch_job_output(partition_location, compression));
             }
         };
     }
 }
 
+/// Fetch the output partitions of a completed job from the executors keeping them,
+/// one after the other, sent with their buffers compressed with `compression`
+pub fn fetch_job_output(
+    partition_location: Vec<PartitionLocation>,
+    compression: IpcCompression,
+) -> impl Stream<Item = ArrowResult<RecordBatch>> + Send {
+    let streams = partition_location.into_iter().map(move |p| {
+        let f = fetch_partition(p, compression)
+            .map_err(|e| ArrowError::ExternalError(Box::new(e)));
+
+        futures::stream::once(f).try_flatten()
+    });
+    futures::stream::iter(streams).flatten()
+}
+
 async fn fetch_partition(
     location: PartitionLocation,
+    compression: IpcCompression,
 ) -> Result<SendableRecordBatchStream> {
     let metadata = location.executor_meta.ok_or_else(|| {
         DataFusionError::Internal("Received empty executor metadata".to_owned())
@@ -320,6 +419,7 @@ async fn fetch_partition(
             partition_id.stage_id as usize,
             partition_id.partition_id as usize,
             &location.path,
+            compression,
         )
         .await
         .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))
@@ -0,0 +1,285 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::kafka::{connect, kafka_schema, KafkaOffsetRange};
+use datafusion::arrow::array::{
+    ArrayRef, Int32Array, Int64Array, StringArray, TimestampMillisecondArray,
+};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::{StreamExt, TryStreamExt};
+use log::info;
+use rskafka::client::partition::PartitionClient;
+use rskafka::record::RecordAndOffset;
+use std::any::Any;
+use std::fmt::{self, Formatter};
+use std::sync::Arc;
+
+/// Maximum number of bytes of records fetched by a request
+const MAX_FETCH_BYTES: i32 = 1024 * 1024;
+
+/// Time the broker waits for records before answering a fetch request
+const MAX_FETCH_WAIT_MS: i32 = 500;
+
+/// KafkaScanExec reads ranges of offsets of the partitions of a Kafka topic, each in a
+/// partition of the plan
+#[derive(Debug, Clone)]
+pub struct KafkaScanExec {
+    brokers: Vec<String>,
+    topic: String,
+    ranges: Vec<KafkaOffsetRange>,
+    projection: Option<Vec<usize>>,
+    schema: SchemaRef,
+}
+
+impl KafkaScanExec {
+    pub fn try_new(
+        brokers: Vec<String>,
+        topic: String,
+        ranges: Vec<KafkaOffsetRange>,
+        projection: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        let schema = match &projection {
+            Some(columns) => Arc::new(kafka_schema().project(columns)?),
+            None => kafka_schema(),
+        };
+        Ok(Self {
+            brokers,
+            topic,
+            ranges,
+            projection,
+            schema,
+        })
+    }
+
+    pub fn brokers(&self) -> &[String] {
+        &self.brokers
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn ranges(&self) -> &[KafkaOffsetRange] {
+        &self.ranges
+    }
+
+    pub fn projection(&self) -> Option<&Vec<usize>> {
+        self.projection.as_ref()
+    }
+}
+
+impl ExecutionPlan for KafkaScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.ranges.len())
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn relies_on_input_order(&self) -> bool {
+        false
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Err(DataFusionError::Plan(
+            "Ballista KafkaScanExec does not support with_new_children()".to_owned(),
+        ))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("KafkaScanExec::execute({})", partition);
+
+        let range = self.ranges[partition];
+        let brokers = self.brokers.clone();
+        let topic = self.topic.clone();
+        let projection = self.projection.clone();
+        let stream = futures::stream::once(async move {
+            let client = connect(&brokers).await?;
+            let partition_client = client
+                .partition_client(topic, range.partition)
+                .await
+                .map_err(external)?;
+            Ok::<_, ArrowError>(fetch_batches(partition_client, range))
+        })
+        .try_flatten()
+        .map(move |batch| match &projection {
+            Some(columns) => batch?.project(columns),
+            None => batch,
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                write!(f, "KafkaScanExec: topic={} ranges=[", self.topic)?;
+                for (i, range) in self.ranges.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(
+                        f,
+                        "{}:{}..{}",
+                        range.partition, range.start_offset, range.end_offset
+                    )?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+/// The records of `range`, in a batch per fetch request
+fn fetch_batches(
+    client: PartitionClient,
+    range: KafkaOffsetRange,
+) -> impl futures::Stream<Item = ArrowResult<RecordBatch>> {
+    futures::stream::try_unfold(
+        (client, range.start_offset),
+        move |(client, offset)| async move {
+            if offset >= range.end_offset {
+                return Ok(None);
+            }
+            let (records, _high_watermark) = client
+                .fetch_records(offset, 1..MAX_FETCH_BYTES, MAX_FETCH_WAIT_MS)
+                .await
+                .map_err(external)?;
+            let records: Vec<_> = records
+                .into_iter()
+                .filter(|record| record.offset < range.end_offset)
+                .collect();
+            let next_offset = match records.last() {
+                Some(record) => record.offset + 1,
+                // Records of the range were deleted by retention or compaction
+                None => range.end_offset,
+            };
+            let batch = to_record_batch(range.partition, &records)?;
+            Ok(Some((batch, (client, next_offset))))
+        },
+    )
+}
+
+fn to_record_batch(
+    partition: i32,
+    records: &[RecordAndOffset],
+) -> ArrowResult<RecordBatch> {
+    let text = |bytes: &Option<Vec<u8>>| {
+        bytes
+            .as_ref()
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(Int32Array::from(vec![partition; records.len()])),
+        Arc::new(Int64Array::from(
+            records.iter().map(|r| r.offset).collect::<Vec<_>>(),
+        )),
+        Arc::new(TimestampMillisecondArray::from(
+            records
+                .iter()
+                .map(|r| r.record.timestamp.timestamp_millis())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            records
+                .iter()
+                .map(|r| text(&r.record.key))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            records
+                .iter()
+                .map(|r| text(&r.record.value))
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    RecordBatch::try_new(kafka_schema(), columns)
+}
+
+fn external(e: rskafka::client::error::Error) -> ArrowError {
+    ArrowError::ExternalError(Box::new(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+    use rskafka::record::Record;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn record_batches() -> ArrowResult<()> {
+        let record = |offset: i64, value: &[u8]| RecordAndOffset {
+            record: Record {
+                key: None,
+                value: Some(value.to_vec()),
+                headers: BTreeMap::new(),
+                timestamp: Utc.timestamp_millis(1_667_232_000_000 + offset),
+            },
+            offset,
+        };
+        let batch = to_record_batch(3, &[record(10, b"a"), record(11, b"\xff")])?;
+        assert_eq!(batch.num_rows(), 2);
+        let values = batch
+            .column(4)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(values.value(0), "a");
+        assert_eq!(values.value(1), "\u{fffd}");
+        assert!(batch.column(3).is_null(0));
+
+        let projected = batch.project(&[1])?;
+        assert_eq!(projected.schema().field(0).name(), "offset");
+        Ok(())
+    }
+}
@@ -18,12 +18,30 @@
 //! This module contains execution plans that are needed to distribute DataFusion's execution plans into
 //! several Ballista executors.
 
+mod bloom_filter;
 mod distributed_query;
+#[cfg(feature = "kafka")]
+mod kafka_scan;
+mod node_local_scan;
+mod parquet_sink;
+#[cfg(feature = "postgres")]
+mod postgres_scan;
 mod shuffle_reader;
 mod shuffle_writer;
+mod top_k;
 mod unresolved_shuffle;
 
-pub use distributed_query::DistributedQueryExec;
+pub use bloom_filter::{BloomFilter, BloomFilterBuildExec, BloomFilterExec};
+pub use distributed_query::{fetch_job_output, DistributedQueryExec};
+#[cfg(feature = "kafka")]
+pub use kafka_scan::KafkaScanExec;
+pub use node_local_scan::NodeLocalScanExec;
+pub use parquet_sink::{
+    read_manifest, ParquetManifest, ParquetSinkExec, WrittenFile, MANIFEST_FILE,
+};
+#[cfg(feature = "postgres")]
+pub use postgres_scan::PostgresScanExec;
 pub use shuffle_reader::ShuffleReaderExec;
 pub use shuffle_writer::ShuffleWriterExec;
+pub use top_k::TopKExec;
 pub use unresolved_shuffle::UnresolvedShuffleExec;
@@ -0,0 +1,199 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::ArrowError;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::file_format::{FileScanConfig, ParquetExec};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::TryStreamExt;
+use log::info;
+use std::any::Any;
+use std::fmt::{self, Formatter};
+use std::sync::Arc;
+
+/// NodeLocalScanExec reads the Parquet files of a directory of the local disks of
+/// several nodes, each in a partition of the plan. The scheduler only runs the task of a
+/// partition on the executors whose label `node_label` is the node of the partition.
+#[derive(Debug, Clone)]
+pub struct NodeLocalScanExec {
+    path: String,
+    file_extension: String,
+    node_label: String,
+    nodes: Vec<String>,
+    /// Schema of the files
+    file_schema: SchemaRef,
+    projection: Option<Vec<usize>>,
+    schema: SchemaRef,
+}
+
+impl NodeLocalScanExec {
+    pub fn try_new(
+        path: String,
+        file_extension: String,
+        node_label: String,
+        nodes: Vec<String>,
+        file_schema: SchemaRef,
+        projection: Option<Vec<usize>>,
+    ) -> Result<Self> {
+        let schema = match &projection {
+            Some(columns) => Arc::new(file_schema.project(columns)?),
+            None => file_schema.clone(),
+        };
+        Ok(Self {
+            path,
+            file_extension,
+            node_label,
+            nodes,
+            file_schema,
+            projection,
+            schema,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn file_extension(&self) -> &str {
+        &self.file_extension
+    }
+
+    /// The label naming the node an executor runs on
+    pub fn node_label(&self) -> &str {
+        &self.node_label
+    }
+
+    /// The node whose files each partition reads
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    pub fn file_schema(&self) -> SchemaRef {
+        self.file_schema.clone()
+    }
+
+    pub fn projection(&self) -> Option<&Vec<usize>> {
+        self.projection.as_ref()
+    }
+}
+
+impl ExecutionPlan for NodeLocalScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.nodes.len())
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn relies_on_input_order(&self) -> bool {
+        false
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Err(DataFusionError::Plan(
+            "Ballista NodeLocalScanExec does not support with_new_children()".to_owned(),
+        ))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!(
+            "NodeLocalScanExec::execute({}) of node {}",
+            partition, self.nodes[partition]
+        );
+
+        let url = ListingTableUrl::parse(&self.path)?;
+        let file_extension = self.file_extension.clone();
+        let file_schema = self.file_schema.clone();
+        let projection = self.projection.clone();
+        let stream = futures::stream::once(async move {
+            let store = context.runtime_env().object_store(url.object_store())?;
+            let files: Vec<PartitionedFile> = url
+                .list_all_files(store.as_ref(), &file_extension)
+                .map_ok(|object_meta| PartitionedFile {
+                    object_meta,
+                    partition_values: vec![],
+                    range: None,
+                })
+                .try_collect()
+                .await?;
+            let scan = ParquetExec::new(
+                FileScanConfig {
+                    object_store_url: url.object_store(),
+                    file_schema,
+                    file_groups: vec![files],
+                    statistics: Statistics::default(),
+                    projection,
+                    limit: None,
+                    table_partition_cols: vec![],
+                },
+                None,
+                None,
+            );
+            Ok::<_, ArrowError>(scan.execute(0, context)?)
+        })
+        .try_flatten();
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                write!(
+                    f,
+                    "NodeLocalScanExec: path={} {}=[{}]",
+                    self.path,
+                    self.node_label,
+                    self.nodes.join(", ")
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
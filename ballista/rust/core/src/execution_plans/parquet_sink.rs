@@ -0,0 +1,980 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! ParquetSinkExec writes the output of a query as Parquet files.
+//!
+//! Each partition is written by a task, which splits its rows by the values of the
+//! partition columns into hive-style directories such as `year=2022/month=11`, leaving
+//! the partition columns out of the files. The files written by a task are listed in a
+//! task manifest under `_staging`, and [ParquetSinkExec::commit] publishes the files of
+//! all the tasks at once in the `_manifest.json` file of the output, once the job has
+//! completed. Files which are not listed in the manifest, such as the files of failed
+//! jobs, are not part of the output.
+//!
+//! The files and the task manifest of a task are named after its attempt, so that the
+//! attempts of a retried or duplicated task never overwrite each other. Only the files
+//! of the attempt the scheduler accepted the output of are committed, once per
+//! partition.
+//!
+//! Jobs rewriting the files of a table for a DELETE or UPDATE statement commit their
+//! files to a new version of the table with [ParquetSinkExec::commit_to_table] instead.
+//! The columns of the files written for tables which match columns by ID, such as
+//! Iceberg tables, carry the field IDs of the table in the Parquet schema.
+
+use crate::table_format::TableCommit;
+use datafusion::arrow::array::{ArrayRef, StringArray, UInt32Array, UInt64Array};
+use datafusion::arrow::compute::take;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::arrow::util::display::array_value_to_string;
+use datafusion::datasource::listing::ListingTableUrl;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::parquet::arrow::ArrowWriter;
+use datafusion::parquet::format::FileMetaData;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::StreamExt;
+use log::{info, warn};
+use object_store::path::{Path, DELIMITER};
+use object_store::{MultipartId, ObjectStore};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::fmt::{self, Formatter};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use thrift::protocol::{TCompactInputProtocol, TCompactOutputProtocol, TOutputProtocol};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Name of the manifest of the committed output
+pub const MANIFEST_FILE: &str = "_manifest.json";
+
+/// Directory of the task manifests of the writes in progress
+const STAGING_DIR: &str = "_staging";
+
+/// Name of the directory of the rows whose partition column is null
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// Magic number at the start and the end of Parquet files
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+
+/// A Parquet file of the output
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WrittenFile {
+    /// Path of the file relative to the location of the output
+    pub path: String,
+    pub num_rows: u64,
+    /// Size of the file in bytes
+    #[serde(default)]
+    pub size: u64,
+    /// Values of the partition columns of the rows of the file, `None` for nulls
+    #[serde(default)]
+    pub partition_values: BTreeMap<String, Option<String>>,
+}
+
+/// The files of the output committed by a write
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParquetManifest {
+    pub write_id: String,
+    pub partition_by: Vec<String>,
+    pub files: Vec<WrittenFile>,
+}
+
+/// ParquetSinkExec writes each partition of its input as Parquet files, and outputs
+/// the files it wrote
+#[derive(Debug, Clone)]
+pub struct ParquetSinkExec {
+    input: Arc<dyn ExecutionPlan>,
+    path: String,
+    url: ListingTableUrl,
+    partition_by: Vec<String>,
+    write_id: String,
+    /// Attempt of the task executing the plan
+    attempt: u32,
+    /// Parquet field IDs of the columns of the files by the dotted path of each column
+    field_ids: HashMap<String, i32>,
+}
+
+impl ParquetSinkExec {
+    pub fn try_new(
+        input: Arc<dyn ExecutionPlan>,
+        path: String,
+        partition_by: Vec<String>,
+        write_id: String,
+    ) -> Result<Self> {
+        let schema = input.schema();
+        for column in &partition_by {
+            schema.index_of(column)?;
+        }
+        if partition_by.len() == schema.fields().len() {
+            return Err(DataFusionError::Plan(
+                "Cannot write Parquet files partitioned by all of their columns"
+                    .to_owned(),
+            ));
+        }
+        Ok(Self {
+            input,
+            url: ListingTableUrl::parse(&path)?,
+            path,
+            partition_by,
+            write_id,
+            attempt: 0,
+            field_ids: HashMap::new(),
+        })
+    }
+
+    /// Set the attempt of the task executing the plan
+    pub fn with_attempt(mut self, attempt: u32) -> Self {
+        self.attempt = attempt;
+        self
+    }
+
+    /// Set the field IDs of the columns of the files, by the dotted path of each column
+    /// in the Parquet schema, such as `address.city` for the field `city` of the struct
+    /// column `address`
+    pub fn with_field_ids(mut self, field_ids: HashMap<String, i32>) -> Self {
+        self.field_ids = field_ids;
+        self
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn partition_by(&self) -> &[String] {
+        &self.partition_by
+    }
+
+    pub fn write_id(&self) -> &str {
+        &self.write_id
+    }
+
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    pub fn field_ids(&self) -> &HashMap<String, i32> {
+        &self.field_ids
+    }
+
+    /// The schema of the output of the plan, a row per written file
+    pub fn output_schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("path", DataType::Utf8, false),
+            Field::new("num_rows", DataType::UInt64, false),
+        ]))
+    }
+
+    fn staging_dir(&self) -> Path {
+        self.url
+            .prefix()
+            .child(STAGING_DIR)
+            .child(self.write_id.as_str())
+    }
+
+    fn task_manifest(&self, partition: usize, attempt: u32) -> Path {
+        self.staging_dir()
+            .child(format!("part-{:05}-{}.json", partition, attempt))
+    }
+
+    /// Publish the files written by every partition of the plan in the manifest of
+    /// the output, replacing the previous output at the same location. Called once all
+    /// the partitions have been written, with the attempt whose output was accepted for
+    /// each partition.
+    pub async fn commit(
+        &self,
+        runtime: &RuntimeEnv,
+        attempts: &[u32],
+    ) -> Result<ParquetManifest> {
+        let store = runtime.object_store(self.url.object_store())?;
+        let (files, task_manifests) =
+            self.accepted_files(store.as_ref(), attempts).await?;
+
+        let manifest_path = self.url.prefix().child(MANIFEST_FILE);
+        let previous = match read_manifest(store.as_ref(), &self.url).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                warn!(
+                    "Could not read the manifest replaced by {}: {}",
+                    self.path, e
+                );
+                None
+            }
+        };
+        let manifest = ParquetManifest {
+            write_id: self.write_id.clone(),
+            partition_by: self.partition_by.clone(),
+            files,
+        };
+        let bytes = serde_json::to_vec(&manifest)
+            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
+        store.put(&manifest_path, bytes.into()).await?;
+        info!(
+            "Committed {} Parquet files of write {} to {}",
+            manifest.files.len(),
+            self.write_id,
+            self.path
+        );
+
+        // The output is committed, the files of the previous output are not part of it
+        let uncommitted = previous.map(|previous| previous.files).unwrap_or_default();
+        self.clean_up(store.as_ref(), task_manifests, &manifest.files, uncommitted)
+            .await;
+        Ok(manifest)
+    }
+
+    /// Commit the files written by every partition of the plan to a new version of the
+    /// table they were written to with `table`, instead of a manifest. Called once all
+    /// the partitions have been written, with the attempt whose output was accepted for
+    /// each partition.
+    pub async fn commit_to_table(
+        &self,
+        runtime: &RuntimeEnv,
+        attempts: &[u32],
+        table: &dyn TableCommit,
+    ) -> Result<Vec<WrittenFile>> {
+        let store = runtime.object_store(self.url.object_store())?;
+        let (files, task_manifests) =
+            self.accepted_files(store.as_ref(), attempts).await?;
+        if let Err(e) = table.commit(&files, runtime).await {
+            // The table does not refer to any of the written files
+            self.clean_up(store.as_ref(), task_manifests, &[], files)
+                .await;
+            return Err(e);
+        }
+        info!(
+            "Committed {} Parquet files of write {} to table {}",
+            files.len(),
+            self.write_id,
+            self.path
+        );
+        self.clean_up(store.as_ref(), task_manifests, &files, vec![])
+            .await;
+        Ok(files)
+    }
+
+    /// The files written by the accepted `attempts` of the partitions, and the task
+    /// manifests listing them
+    async fn accepted_files(
+        &self,
+        store: &dyn ObjectStore,
+        attempts: &[u32],
+    ) -> Result<(Vec<WrittenFile>, HashSet<Path>)> {
+        let num_partitions = self.output_partitioning().partition_count();
+        if attempts.len() != num_partitions {
+            return Err(DataFusionError::Internal(format!(
+                "Cannot commit write {} of {} partitions with {} attempts",
+                self.write_id,
+                num_partitions,
+                attempts.len()
+            )));
+        }
+        let mut files = vec![];
+        let mut task_manifests = HashSet::new();
+        for (partition, attempt) in attempts.iter().enumerate() {
+            let path = self.task_manifest(partition, *attempt);
+            files.extend(read_task_manifest(store, &path).await?);
+            task_manifests.insert(path);
+        }
+        Ok((files, task_manifests))
+    }
+
+    /// Delete `task_manifests`, the `uncommitted` files and the files of the attempts
+    /// which were not committed, on a best effort basis. The `committed` files are
+    /// kept.
+    async fn clean_up(
+        &self,
+        store: &dyn ObjectStore,
+        task_manifests: HashSet<Path>,
+        committed: &[WrittenFile],
+        mut uncommitted: Vec<WrittenFile>,
+    ) {
+        let mut garbage: Vec<Path> = task_manifests.iter().cloned().collect();
+        match store.list(Some(&self.staging_dir())).await {
+            Ok(mut staged) => {
+                while let Some(meta) = staged.next().await {
+                    let path = match meta {
+                        Ok(meta) => meta.location,
+                        Err(e) => {
+                            warn!("Could not list staged task manifests: {}", e);
+                            break;
+                        }
+                    };
+                    if task_manifests.contains(&path) {
+                        continue;
+                    }
+                    match read_task_manifest(store, &path).await {
+                        Ok(written) => uncommitted.extend(written),
+                        Err(e) => warn!("Could not read task manifest {}: {}", path, e),
+                    }
+                    garbage.push(path);
+                }
+            }
+            Err(e) => warn!("Could not list staged task manifests: {}", e),
+        }
+        let committed: HashSet<&str> =
+            committed.iter().map(|f| f.path.as_str()).collect();
+        for file in uncommitted {
+            if committed.contains(file.path.as_str()) {
+                continue;
+            }
+            // Paths in manifests are already encoded
+            match Path::parse(format!("{}/{}", self.url.prefix(), file.path)) {
+                Ok(path) => garbage.push(path),
+                Err(e) => warn!("Invalid path {} in manifest: {}", file.path, e),
+            }
+        }
+        for path in garbage {
+            if let Err(e) = store.delete(&path).await {
+                warn!("Could not delete {}: {}", path, e);
+            }
+        }
+    }
+}
+
+impl ExecutionPlan for ParquetSinkExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Self::output_schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(
+            self.input.output_partitioning().partition_count(),
+        )
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "ParquetSinkExec wrong number of children".to_string(),
+            ));
+        }
+        Ok(Arc::new(
+            ParquetSinkExec::try_new(
+                children[0].clone(),
+                self.path.clone(),
+                self.partition_by.clone(),
+                self.write_id.clone(),
+            )?
+            .with_attempt(self.attempt)
+            .with_field_ids(self.field_ids.clone()),
+        ))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("ParquetSinkExec::execute({})", partition);
+
+        let input = self.input.execute(partition, context.clone())?;
+        let store = context
+            .runtime_env()
+            .object_store(self.url.object_store())?;
+        let writer = PartitionWriter::try_new(self, partition)?;
+        let task_manifest = self.task_manifest(partition, self.attempt);
+        let stream = futures::stream::once(async move {
+            let files = writer.write(input, store.as_ref()).await?;
+            let bytes = serde_json::to_vec(&files)
+                .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+            store
+                .put(&task_manifest, bytes.into())
+                .await
+                .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+            RecordBatch::try_new(
+                Self::output_schema(),
+                vec![
+                    Arc::new(StringArray::from(
+                        files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        files.iter().map(|f| f.num_rows).collect::<Vec<_>>(),
+                    )),
+                ],
+            )
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            Self::output_schema(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                write!(
+                    f,
+                    "ParquetSinkExec: path={} partition_by=[{}]",
+                    self.path,
+                    self.partition_by.join(", ")
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+/// Writes the rows of a partition of the input of a [ParquetSinkExec] to a file per
+/// directory of partition values
+struct PartitionWriter {
+    prefix: Path,
+    file_name: String,
+    partition_by: Vec<String>,
+    /// Indices of the partition columns in the input
+    partition_columns: Vec<usize>,
+    /// Indices of the columns written to the files in the input
+    data_columns: Vec<usize>,
+    data_schema: SchemaRef,
+    field_ids: Arc<HashMap<String, i32>>,
+}
+
+impl PartitionWriter {
+    fn try_new(sink: &ParquetSinkExec, partition: usize) -> Result<Self> {
+        let schema = sink.input.schema();
+        let partition_columns = sink
+            .partition_by
+            .iter()
+            .map(|column| schema.index_of(column))
+            .collect::<ArrowResult<Vec<_>>>()?;
+        let data_columns: Vec<usize> = (0..schema.fields().len())
+            .filter(|i| !partition_columns.contains(i))
+            .collect();
+        Ok(Self {
+            prefix: sink.url.prefix().clone(),
+            file_name: format!(
+                "part-{}-{:05}-{}.parquet",
+                sink.write_id, partition, sink.attempt
+            ),
+            data_schema: Arc::new(schema.project(&data_columns)?),
+            partition_by: sink.partition_by.clone(),
+            partition_columns,
+            data_columns,
+            field_ids: Arc::new(sink.field_ids.clone()),
+        })
+    }
+
+    /// Write the rows of `input`, uploading the files with multipart uploads as their
+    /// row groups are written, so that at most a row group per file is held in memory.
+    /// The uploads are aborted if any fails.
+    async fn write(
+        &self,
+        input: SendableRecordBatchStream,
+        store: &dyn ObjectStore,
+    ) -> ArrowResult<Vec<WrittenFile>> {
+        let mut uploads = BTreeMap::new();
+        if let Err(e) = self.upload(input, store, &mut uploads).await {
+            for upload in uploads.into_values() {
+                upload.abort(store).await;
+            }
+            return Err(e);
+        }
+
+        let mut files = vec![];
+        let mut uploads = uploads.into_iter();
+        while let Some((values, upload)) = uploads.next() {
+            match upload.finish(store).await {
+                Ok((path, num_rows, size)) => files.push(WrittenFile {
+                    path: relative_path(&self.prefix, &path),
+                    num_rows,
+                    size,
+                    partition_values: self
+                        .partition_by
+                        .iter()
+                        .cloned()
+                        .zip(values)
+                        .collect(),
+                }),
+                Err(e) => {
+                    for (_, upload) in uploads {
+                        upload.abort(store).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Write the rows of `input` to `uploads`, a file per directory of partition values
+    async fn upload(
+        &self,
+        mut input: SendableRecordBatchStream,
+        store: &dyn ObjectStore,
+        uploads: &mut BTreeMap<Vec<Option<String>>, FileUpload>,
+    ) -> ArrowResult<()> {
+        while let Some(batch) = input.next().await {
+            for (values, batch) in self.split(&batch?)? {
+                if !uploads.contains_key(&values) {
+                    let upload = FileUpload::try_new(
+                        store,
+                        self.file_path(&values),
+                        &self.data_schema,
+                        self.field_ids.clone(),
+                    )
+                    .await?;
+                    uploads.insert(values.clone(), upload);
+                }
+                uploads.get_mut(&values).unwrap().write(&batch).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The path of the file of the rows with partition values `values`
+    fn file_path(&self, values: &[Option<String>]) -> Path {
+        let mut path = self.prefix.clone();
+        for (column, value) in self.partition_by.iter().zip(values) {
+            let directory = format!(
+                "{}={}",
+                column,
+                value.as_deref().unwrap_or(DEFAULT_PARTITION)
+            );
+            path = path.child(directory.as_str());
+        }
+        path.child(self.file_name.as_str())
+    }
+
+    /// Split the rows of `batch` by their partition values, leaving out the partition
+    /// columns
+    fn split(
+        &self,
+        batch: &RecordBatch,
+    ) -> ArrowResult<Vec<(Vec<Option<String>>, RecordBatch)>> {
+        let mut rows: BTreeMap<Vec<Option<String>>, Vec<u32>> = BTreeMap::new();
+        for row in 0..batch.num_rows() {
+            let values = self
+                .partition_columns
+                .iter()
+                .map(|i| {
+                    let column = batch.column(*i);
+                    if column.is_null(row) {
+                        Ok(None)
+                    } else {
+                        array_value_to_string(column, row).map(Some)
+                    }
+                })
+                .collect::<ArrowResult<Vec<_>>>()?;
+            rows.entry(values).or_default().push(row as u32);
+        }
+        rows.into_iter()
+            .map(|(values, rows)| {
+                let indices = UInt32Array::from(rows);
+                let columns = self
+                    .data_columns
+                    .iter()
+                    .map(|i| take(batch.column(*i).as_ref(), &indices, None))
+                    .collect::<ArrowResult<Vec<ArrayRef>>>()?;
+                Ok((
+                    values,
+                    RecordBatch::try_new(self.data_schema.clone(), columns)?,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// The manifest of the output committed at `url`, `None` if nothing was committed there
+pub async fn read_manifest(
+    store: &dyn ObjectStore,
+    url: &ListingTableUrl,
+) -> Result<Option<ParquetManifest>> {
+    let path = url.prefix().child(MANIFEST_FILE);
+    let bytes = match store.get(&path).await {
+        Ok(result) => result.bytes().await?,
+        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    serde_json::from_slice(&bytes).map(Some).map_err(|e| {
+        DataFusionError::Execution(format!("Invalid manifest {}: {}", path, e))
+    })
+}
+
+/// The files listed in the task manifest at `path`
+async fn read_task_manifest(
+    store: &dyn ObjectStore,
+    path: &Path,
+) -> Result<Vec<WrittenFile>> {
+    let bytes = store.get(path).await?.bytes().await?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))
+}
+
+/// The path of `path` relative to the directory `prefix`
+fn relative_path(prefix: &Path, path: &Path) -> String {
+    path.as_ref()
+        .strip_prefix(prefix.as_ref())
+        .unwrap_or_else(|| path.as_ref())
+        .trim_start_matches(DELIMITER)
+        .to_owned()
+}
+
+/// A Parquet file uploaded with a multipart upload as it is written
+struct FileUpload {
+    path: Path,
+    writer: ArrowWriter<SharedBuffer>,
+    /// The bytes written by `writer` which are not uploaded yet
+    buffer: SharedBuffer,
+    id: MultipartId,
+    upload: Box<dyn AsyncWrite + Unpin + Send>,
+    num_rows: u64,
+    /// Number of bytes uploaded
+    size: u64,
+    field_ids: Arc<HashMap<String, i32>>,
+}
+
+impl FileUpload {
+    async fn try_new(
+        store: &dyn ObjectStore,
+        path: Path,
+        schema: &SchemaRef,
+        field_ids: Arc<HashMap<String, i32>>,
+    ) -> ArrowResult<Self> {
+        let buffer = SharedBuffer::default();
+        let writer = ArrowWriter::try_new(buffer.clone(), schema.clone(), None)?;
+        let (id, upload) = store
+            .put_multipart(&path)
+            .await
+            .map_err(|e| ArrowError::ExternalError(Box::new(e)))?;
+        Ok(Self {
+            path,
+            writer,
+            buffer,
+            id,
+            upload,
+            num_rows: 0,
+            size: 0,
+            field_ids,
+        })
+    }
+
+    async fn write(&mut self, batch: &RecordBatch) -> ArrowResult<()> {
+        self.writer.write(batch)?;
+        self.num_rows += batch.num_rows() as u64;
+        // The writer only writes to the buffer once a row group is complete
+        self.flush().await
+    }
+
+    /// Upload the bytes written to the buffer so far
+    async fn flush(&mut self) -> ArrowResult<()> {
+        let bytes = std::mem::take(&mut *self.buffer.0.lock().unwrap());
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        self.upload.write_all(&bytes).await?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Write the footer of the file and complete the upload, returning the path, number
+    /// of rows and size of the file. The upload is aborted if this fails.
+    async fn finish(mut self, store: &dyn ObjectStore) -> ArrowResult<(Path, u64, u64)> {
+        let footer = match self.writer.close() {
+            Ok(_) => {
+                // The footer is only written to the buffer when the writer is closed
+                let mut bytes = std::mem::take(&mut *self.buffer.0.lock().unwrap());
+                if self.field_ids.is_empty() {
+                    Ok(bytes)
+                } else {
+                    set_field_ids(&mut bytes, &self.field_ids).map(|_| bytes)
+                }
+            }
+            Err(e) => Err(e.into()),
+        };
+        let uploaded = match footer {
+            Ok(bytes) => {
+                self.size += bytes.len() as u64;
+                match self.upload.write_all(&bytes).await {
+                    Ok(()) => self.upload.shutdown().await.map_err(ArrowError::from),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = uploaded {
+            abort_upload(store, &self.path, &self.id).await;
+            return Err(e);
+        }
+        Ok((self.path, self.num_rows, self.size))
+    }
+
+    async fn abort(self, store: &dyn ObjectStore) {
+        abort_upload(store, &self.path, &self.id).await
+    }
+}
+
+async fn abort_upload(store: &dyn ObjectStore, path: &Path, id: &MultipartId) {
+    if let Err(e) = store.abort_multipart(path, id).await {
+        warn!("Could not abort the upload of {}: {}", path, e);
+    }
+}
+
+/// Set the field IDs of the schema in the footer of the Parquet file ending with
+/// `buffer` to `field_ids`, by the dotted path of each field
+fn set_field_ids(
+    buffer: &mut Vec<u8>,
+    field_ids: &HashMap<String, i32>,
+) -> ArrowResult<()> {
+    let invalid = |e: String| ArrowError::ParquetError(format!("Invalid footer: {}", e));
+    let len = buffer.len();
+    if len < 8 || &buffer[len - 4..] != PARQUET_MAGIC {
+        return Err(invalid("no magic number".to_owned()));
+    }
+    let footer_len = u32::from_le_bytes(buffer[len - 8..len - 4].try_into().unwrap());
+    let start = (len - 8)
+        .checked_sub(footer_len as usize)
+        .ok_or_else(|| invalid("footer not in buffer".to_owned()))?;
+    let mut metadata = {
+        let mut input = TCompactInputProtocol::new(&buffer[start..len - 8]);
+        FileMetaData::read_from_in_protocol(&mut input)
+            .map_err(|e| invalid(e.to_string()))?
+    };
+
+    // The schema is flattened depth first, each group followed by its children
+    let mut parents: Vec<(String, i32)> = vec![];
+    for element in metadata.schema.iter_mut() {
+        let path = match parents.last_mut() {
+            Some((parent, remaining)) => {
+                *remaining -= 1;
+                if parent.is_empty() {
+                    element.name.clone()
+                } else {
+                    format!("{}.{}", parent, element.name)
+                }
+            }
+            // The root of the schema
+            None => String::new(),
+        };
+        if let Some(id) = field_ids.get(&path) {
+            element.field_id = Some(*id);
+        }
+        while matches!(parents.last(), Some((_, 0))) {
+            parents.pop();
+        }
+        match element.num_children {
+            Some(children) if children > 0 => parents.push((path, children)),
+            _ => {}
+        }
+    }
+
+    let mut footer = vec![];
+    {
+        let mut output = TCompactOutputProtocol::new(&mut footer);
+        metadata
+            .write_to_out_protocol(&mut output)
+            .and_then(|_| output.flush())
+            .map_err(|e| invalid(e.to_string()))?;
+    }
+    buffer.truncate(start);
+    buffer.extend_from_slice(&footer);
+    buffer.extend_from_slice(&(footer.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(PARQUET_MAGIC);
+    Ok(())
+}
+
+/// An in-memory buffer shared between an [ArrowWriter] and the upload of the file it
+/// writes
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn write_partitioned() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("year", DataType::Int32, true),
+            Field::new("value", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![
+                    Some(2021),
+                    Some(2022),
+                    None,
+                    Some(2022),
+                ])),
+                Arc::new(StringArray::from(vec!["a", "b", "c", "d"])),
+            ],
+        )?;
+        let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema, None)?);
+        let dir = TempDir::new()?;
+        let path = dir.path().to_str().unwrap().to_owned();
+        let sink = ParquetSinkExec::try_new(
+            input,
+            path.clone(),
+            vec!["year".to_owned()],
+            "w1".to_owned(),
+        )?;
+
+        let ctx = SessionContext::new();
+        // Two attempts of the same task both complete
+        for attempt in [1, 2] {
+            let sink = sink.clone().with_attempt(attempt);
+            let mut stream = sink.execute(0, ctx.task_ctx())?;
+            let batch = stream.next().await.unwrap()?;
+            assert_eq!(batch.num_rows(), 3);
+        }
+
+        // Only the files of the accepted attempt are committed
+        let manifest = sink.commit(&ctx.runtime_env(), &[2]).await?;
+        let mut paths: Vec<_> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        paths.sort_unstable();
+        assert_eq!(
+            paths,
+            vec![
+                "year=2021/part-w1-00000-2.parquet",
+                "year=2022/part-w1-00000-2.parquet",
+                "year=__HIVE_DEFAULT_PARTITION__/part-w1-00000-2.parquet",
+            ]
+        );
+        assert_eq!(manifest.files.iter().map(|f| f.num_rows).sum::<u64>(), 4);
+        assert!(manifest.files.iter().all(|f| f.size > 0));
+        let mut years: Vec<_> = manifest
+            .files
+            .iter()
+            .map(|f| f.partition_values["year"].clone())
+            .collect();
+        years.sort_unstable();
+        assert_eq!(
+            years,
+            vec![None, Some("2021".to_owned()), Some("2022".to_owned())]
+        );
+        assert!(dir.path().join(MANIFEST_FILE).exists());
+        assert!(!dir
+            .path()
+            .join("year=2021/part-w1-00000-1.parquet")
+            .exists());
+        assert!(!dir
+            .path()
+            .join(STAGING_DIR)
+            .join("w1")
+            .join("part-00000-2.json")
+            .exists());
+        assert!(!dir
+            .path()
+            .join(STAGING_DIR)
+            .join("w1")
+            .join("part-00000-1.json")
+            .exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_field_ids() -> Result<()> {
+        use datafusion::arrow::array::StructArray;
+        use datafusion::parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let address = Field::new(
+            "address",
+            DataType::Struct(vec![Field::new("city", DataType::Utf8, true)]),
+            true,
+        );
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int32, false),
+            address,
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![1, 2])),
+                Arc::new(StructArray::from(vec![(
+                    Field::new("city", DataType::Utf8, true),
+                    Arc::new(StringArray::from(vec!["Paris", "Rome"])) as ArrayRef,
+                )])),
+            ],
+        )?;
+        let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema, None)?);
+        let dir = TempDir::new()?;
+        let field_ids: HashMap<String, i32> = vec![
+            ("id".to_owned(), 1),
+            ("address".to_owned(), 2),
+            ("address.city".to_owned(), 3),
+        ]
+        .into_iter()
+        .collect();
+        let sink = ParquetSinkExec::try_new(
+            input,
+            dir.path().to_str().unwrap().to_owned(),
+            vec![],
+            "w1".to_owned(),
+        )?
+        .with_field_ids(field_ids);
+        let ctx = SessionContext::new();
+        sink.execute(0, ctx.task_ctx())?.next().await.unwrap()?;
+
+        let file = std::fs::File::open(dir.path().join("part-w1-00000-0.parquet"))?;
+        let reader = SerializedFileReader::new(file)?;
+        let metadata = reader.metadata().file_metadata();
+        let root = metadata.schema();
+        let ids: Vec<i32> = root
+            .get_fields()
+            .iter()
+            .map(|field| field.get_basic_info().id())
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+        let city = &root.get_fields()[1].get_fields()[0];
+        assert_eq!(city.get_basic_info().id(), 3);
+        assert_eq!(metadata.num_rows(), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::table_format::postgres::database;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use datafusion::arrow::array::{
+    ArrayRef, BinaryArray, BooleanArray, Date32Array, Float32Array, Float64Array,
+    Int16Array, Int32Array, Int64Array, StringArray, TimestampMicrosecondArray,
+};
+use datafusion::arrow::datatypes::{DataType, SchemaRef, TimeUnit};
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use deadpool_postgres::{Manager, Pool};
+use futures::{StreamExt, TryStreamExt};
+use log::info;
+use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use tokio_postgres::types::FromSql;
+use tokio_postgres::{NoTls, Row};
+
+/// Maximum number of connections an executor opens to a database
+const MAX_CONNECTIONS: usize = 16;
+
+/// Days from 0001-01-01 to 1970-01-01
+const EPOCH_DAYS_FROM_CE: i32 = 719_163;
+
+/// PostgresScanExec reads a table of a PostgreSQL database, in partitions selecting
+/// disjoint ranges of its rows
+#[derive(Clone)]
+pub struct PostgresScanExec {
+    /// Name of the database, as configured on the executors
+    database: String,
+    /// Quoted name of the table
+    table: String,
+    /// Expressions of the selected columns, which convert them to the types of `schema`
+    columns: Vec<String>,
+    schema: SchemaRef,
+    /// Condition of the rows of each partition, empty for all rows
+    predicates: Vec<String>,
+    /// Maximum number of rows read by each partition
+    limit: Option<usize>,
+}
+
+impl PostgresScanExec {
+    pub fn new(
+        database: String,
+        table: String,
+        columns: Vec<String>,
+        schema: SchemaRef,
+        predicates: Vec<String>,
+        limit: Option<usize>,
+    ) -> Self {
+        Self {
+            database,
+            table,
+            columns,
+            schema,
+            predicates,
+            limit,
+        }
+    }
+
+    pub fn database(&self) -> &str {
+        &self.database
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn predicates(&self) -> &[String] {
+        &self.predicates
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// The query reading `partition`
+    fn query(&self, partition: usize) -> String {
+        let mut query = format!("SELECT {} FROM {}", self.columns.join(", "), self.table);
+        let predicate = &self.predicates[partition];
+        if !predicate.is_empty() {
+            query.push_str(&format!(" WHERE {}", predicate));
+        }
+        if let Some(limit) = self.limit {
+            query.push_str(&format!(" LIMIT {}", limit));
+        }
+        query
+    }
+}
+
+impl Debug for PostgresScanExec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostgresScanExec")
+            .field("database", &self.database)
+            .field("table", &self.table)
+            .field("columns", &self.columns)
+            .field("predicates", &self.predicates)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl ExecutionPlan for PostgresScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(self.predicates.len())
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        None
+    }
+
+    fn relies_on_input_order(&self) -> bool {
+        false
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Err(DataFusionError::Plan(
+            "Ballista PostgresScanExec does not support with_new_children()".to_owned(),
+        ))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("PostgresScanExec::execute({})", partition);
+
+        let query = self.query(partition);
+        let pool = pool(&self.database)?;
+        let schema = self.schema.clone();
+        let batch_size = context.session_config().batch_size;
+        let stream = futures::stream::once(async move {
+            let client = pool.get().await.map_err(external)?;
+            let rows = client
+                .query_raw(query.as_str(), std::iter::empty::<i32>())
+                .await
+                .map_err(external)?;
+            // The connection returns to the pool once all rows are read
+            Ok::<_, ArrowError>(rows.map(move |row| {
+                let _client = &client;
+                row.map_err(external)
+            }))
+        })
+        .try_flatten()
+        .try_chunks(batch_size)
+        .map(move |rows| to_record_batch(&schema, &rows.map_err(|e| e.1)?));
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                write!(
+                    f,
+                    "PostgresScanExec: table={} partitions={}",
+                    self.table,
+                    self.predicates.len()
+                )?;
+                if let Some(limit) = self.limit {
+                    write!(f, " limit={}", limit)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        Statistics::default()
+    }
+}
+
+/// The connection pool of database `name`, shared by all tasks of the executor
+fn pool(name: &str) -> Result<Pool> {
+    static POOLS: OnceCell<Mutex<HashMap<String, Pool>>> = OnceCell::new();
+    let mut pools = POOLS.get_or_init(Default::default).lock();
+    if let Some(pool) = pools.get(name) {
+        return Ok(pool.clone());
+    }
+    let database = database(name)?;
+    let config = database.config()?;
+    let manager = match database.tls()? {
+        Some(tls) => Manager::new(config, tls),
+        None => Manager::new(config, NoTls),
+    };
+    let pool = Pool::builder(manager)
+        .max_size(MAX_CONNECTIONS)
+        .build()
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+    pools.insert(name.to_owned(), pool.clone());
+    Ok(pool)
+}
+
+fn external(e: impl std::error::Error + Send + Sync + 'static) -> ArrowError {
+    ArrowError::ExternalError(Box::new(e))
+}
+
+fn to_record_batch(schema: &SchemaRef, rows: &[Row]) -> ArrowResult<RecordBatch> {
+    let columns = schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(index, field)| to_array(rows, index, field.data_type()))
+        .collect::<ArrowResult<Vec<_>>>()?;
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+fn values<'a, T: FromSql<'a>>(
+    rows: &'a [Row],
+    index: usize,
+) -> ArrowResult<Vec<Option<T>>> {
+    rows.iter()
+        .map(|row| row.try_get(index).map_err(external))
+        .collect()
+}
+
+/// The values of column `index` of `rows`, which the query converted to a type
+/// mapped to `data_type`
+fn to_array(rows: &[Row], index: usize, data_type: &DataType) -> ArrowResult<ArrayRef> {
+    Ok(match data_type {
+        DataType::Boolean => Arc::new(BooleanArray::from(values::<bool>(rows, index)?)),
+        DataType::Int16 => Arc::new(Int16Array::from(values::<i16>(rows, index)?)),
+        DataType::Int32 => Arc::new(Int32Array::from(values::<i32>(rows, index)?)),
+        DataType::Int64 => Arc::new(Int64Array::from(values::<i64>(rows, index)?)),
+        DataType::Float32 => Arc::new(Float32Array::from(values::<f32>(rows, index)?)),
+        DataType::Float64 => Arc::new(Float64Array::from(values::<f64>(rows, index)?)),
+        DataType::Utf8 => Arc::new(StringArray::from(values::<&str>(rows, index)?)),
+        DataType::Binary => {
+            Arc::new(BinaryArray::from_opt_vec(values::<&[u8]>(rows, index)?))
+        }
+        DataType::Date32 => Arc::new(Date32Array::from(
+            values::<NaiveDate>(rows, index)?
+                .into_iter()
+                .map(|date| date.map(|date| date.num_days_from_ce() - EPOCH_DAYS_FROM_CE))
+                .collect::<Vec<_>>(),
+        )),
+        // Timestamps with a time zone are selected in UTC
+        DataType::Timestamp(TimeUnit::Microsecond, timezone) => {
+            Arc::new(TimestampMicrosecondArray::from_opt_vec(
+                values::<NaiveDateTime>(rows, index)?
+                    .into_iter()
+                    .map(|timestamp| {
+                        timestamp.map(|timestamp| {
+                            timestamp.timestamp() * 1_000_000
+                                + timestamp.timestamp_subsec_micros() as i64
+                        })
+                    })
+                    .collect(),
+                timezone.clone(),
+            ))
+        }
+        other => {
+            return Err(ArrowError::NotYetImplemented(format!(
+                "Reading {:?} columns from PostgreSQL",
+                other
+            )))
+        }
+    })
+}
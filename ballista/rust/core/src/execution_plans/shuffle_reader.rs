@@ -16,35 +16,44 @@
 // under the License.
 
 use std::any::Any;
+use std::collections::HashMap;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
 
-use crate::client::BallistaClient;
-use crate::serde::scheduler::{PartitionLocation, PartitionStats};
+use crate::client::PartitionExchange;
+use crate::error::BallistaError;
+use crate::serde::scheduler::{FetchPriority, PartitionLocation, PartitionStats};
 
 use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::record_batch::RecordBatch;
 
 use datafusion::error::{DataFusionError, Result};
+use datafusion::physical_plan::coalesce_batches::concat_batches;
 use datafusion::physical_plan::expressions::PhysicalSortExpr;
 use datafusion::physical_plan::metrics::{
-    ExecutionPlanMetricsSet, MetricBuilder, MetricsSet,
+    Count, ExecutionPlanMetricsSet, MetricBuilder, MetricsSet,
 };
 use datafusion::physical_plan::{
     DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
 };
-use futures::{StreamExt, TryStreamExt};
+use futures::{Stream, StreamExt, TryStreamExt};
 
 use datafusion::arrow::error::ArrowError;
 use datafusion::execution::context::TaskContext;
 use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
-use log::info;
+use log::{info, warn};
 
 /// ShuffleReaderExec reads partitions that have already been materialized by a ShuffleWriterExec
 /// being executed by an executor
 #[derive(Debug, Clone)]
 pub struct ShuffleReaderExec {
     /// Each partition of a shuffle can read data from multiple locations
-    pub(crate) partition: Vec<Vec<PartitionLocation>>,
+    pub partition: Vec<Vec<PartitionLocation>>,
     pub(crate) schema: SchemaRef,
+    /// Priority of the fetches of the partitions among those served by their executors
+    pub fetch_priority: FetchPriority,
     /// Execution metrics
     metrics: ExecutionPlanMetricsSet,
 }
@@ -58,9 +67,16 @@ impl ShuffleReaderExec {
         Ok(Self {
             partition,
             schema,
+            fetch_priority: FetchPriority::default(),
             metrics: ExecutionPlanMetricsSet::new(),
         })
     }
+
+    /// Fetch the partitions with priority `fetch_priority`
+    pub fn with_fetch_priority(mut self, fetch_priority: FetchPriority) -> Self {
+        self.fetch_priority = fetch_priority;
+        self
+    }
 }
 
 impl ExecutionPlan for ShuffleReaderExec {
@@ -102,29 +118,52 @@ impl ExecutionPlan for ShuffleReaderExec {
     fn execute(
         &self,
         partition: usize,
-        _context: Arc<TaskContext>,
+        context: Arc<TaskContext>,
     ) -> Result<SendableRecordBatchStream> {
         info!("ShuffleReaderExec::execute({})", partition);
 
+        let target_batch_size = context.session_config().batch_size;
+
         let fetch_time =
             MetricBuilder::new(&self.metrics).subset_time("fetch_time", partition);
+        let stats_mismatches = MetricBuilder::new(&self.metrics)
+            .counter("partition_stats_mismatches", partition);
 
         let locations = self.partition[partition].clone();
+        let priority = self.fetch_priority;
+        // The partitions on the same executor are fetched one after another through a
+        // single exchange with it
+        let mut exchanges: HashMap<String, PartitionExchange> = HashMap::new();
+        for location in &locations {
+            let executor = &location.executor_meta;
+            exchanges
+                .entry(executor.id.clone())
+                .or_insert_with(|| PartitionExchange::new(executor.clone()));
+        }
         let stream = locations.into_iter().map(move |p| {
             let fetch_time = fetch_time.clone();
+            let stats_mismatches = stats_mismatches.clone();
+            let exchange = exchanges[&p.executor_meta.id].clone();
             futures::stream::once(async move {
                 let timer = fetch_time.timer();
-                let r = fetch_partition(&p).await;
+                let r = fetch_partition(&exchange, &p, priority).await;
                 timer.done();
 
-                r.map_err(|e| ArrowError::ExternalError(Box::new(e)))
+                r.map(|input| PartitionStatsCheck::new(input, p, stats_mismatches))
+                    .map_err(|e| ArrowError::ExternalError(Box::new(e)))
             })
             .try_flatten()
         });
 
+        // every map task writes its own batches for this partition, which are combined
+        // into batches of the session batch size before reaching joins and aggregations
         let result = RecordBatchStreamAdapter::new(
             Arc::new(self.schema.as_ref().clone()),
-            futures::stream::iter(stream).flatten(),
+            coalesce_batches(
+                futures::stream::iter(stream).flatten().boxed(),
+                self.schema.clone(),
+                target_batch_size,
+            ),
         );
         Ok(Box::pin(result))
     }
@@ -200,29 +239,226 @@ fn stats_for_partitions(
     )
 }
 
+/// Combine the batches of `input` into batches of at least `target_batch_size` rows,
+/// the last one excepted. Batches already large enough are passed through as they are.
+fn coalesce_batches<S>(
+    input: S,
+    schema: SchemaRef,
+    target_batch_size: usize,
+) -> impl Stream<Item = ArrowResult<RecordBatch>>
+where
+    S: Stream<Item = ArrowResult<RecordBatch>> + Unpin,
+{
+    futures::stream::unfold(Some(input), move |mut input| {
+        let schema = schema.clone();
+        async move {
+            let mut input = input.take()?;
+            let mut buffered: Vec<RecordBatch> = vec![];
+            let mut num_rows = 0;
+            while let Some(batch) = input.next().await {
+                let batch = match batch {
+                    Ok(batch) => batch,
+                    Err(e) => return Some((Err(e), None)),
+                };
+                if buffered.is_empty() && batch.num_rows() >= target_batch_size {
+                    return Some((Ok(batch), Some(input)));
+                }
+                if batch.num_rows() > 0 {
+                    num_rows += batch.num_rows();
+                    buffered.push(batch);
+                }
+                if num_rows >= target_batch_size {
+                    let batch = concat_batches(&schema, &buffered, num_rows);
+                    return Some((batch, Some(input)));
+                }
+            }
+            if buffered.is_empty() {
+                None
+            } else {
+                Some((concat_batches(&schema, &buffered, num_rows), None))
+            }
+        }
+    })
+}
+
+/// Checks the batches served for a shuffle partition against the statistics recorded
+/// when it was written, once the partition is read to the end. Mismatches, such as a
+/// partition written partially or a file replaced by the output of another task, are
+/// logged and counted. Only rows and batches are checked, as the size of the batches in
+/// memory depends on how their buffers were allocated.
+struct PartitionStatsCheck {
+    input: SendableRecordBatchStream,
+    location: PartitionLocation,
+    num_rows: u64,
+    num_batches: u64,
+    mismatches: Count,
+}
+
+impl PartitionStatsCheck {
+    fn new(
+        input: SendableRecordBatchStream,
+        location: PartitionLocation,
+        mismatches: Count,
+    ) -> Self {
+        Self {
+            input,
+            location,
+            num_rows: 0,
+            num_batches: 0,
+            mismatches,
+        }
+    }
+
+    fn check(&self) {
+        let stats = &self.location.partition_stats;
+        if stats.num_rows.map_or(true, |rows| rows == self.num_rows)
+            && stats
+                .num_batches
+                .map_or(true, |batches| batches == self.num_batches)
+        {
+            return;
+        }
+        warn!(
+            "Shuffle partition {:?} at {} on executor {} was read with {} rows in {} batches, \
+             but was written with {}",
+            self.location.partition_id,
+            self.location.path,
+            self.location.executor_meta.id,
+            self.num_rows,
+            self.num_batches,
+            stats
+        );
+        self.mismatches.add(1);
+    }
+}
+
+impl Stream for PartitionStatsCheck {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let poll = self.input.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(batch))) => {
+                self.num_rows += batch.num_rows() as u64;
+                self.num_batches += 1;
+            }
+            // Partitions which failed to be read are not checked
+            Poll::Ready(None) => self.check(),
+            _ => {}
+        }
+        poll
+    }
+}
+
 async fn fetch_partition(
+    exchange: &PartitionExchange,
     location: &PartitionLocation,
+    priority: FetchPriority,
 ) -> Result<SendableRecordBatchStream> {
     let metadata = &location.executor_meta;
     let partition_id = &location.partition_id;
-    let mut ballista_client =
-        BallistaClient::try_new(metadata.host.as_str(), metadata.port as u16)
-            .await
-            .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))?;
-    ballista_client
+    // keep the failure identifiable as a fetch failure so the task can be reported
+    // as such rather than as a generic execution error
+    let fetch_failed = |e: BallistaError| {
+        DataFusionError::External(Box::new(BallistaError::FetchFailed(
+            metadata.id.clone(),
+            partition_id.stage_id,
+            partition_id.partition_id,
+            format!("{:?}", e),
+        )))
+    };
+    exchange
         .fetch_partition(
             &partition_id.job_id,
             partition_id.stage_id as usize,
             partition_id.partition_id as usize,
             &location.path,
+            priority,
         )
         .await
-        .map_err(|e| DataFusionError::Execution(format!("{:?}", e)))
+        .map_err(fetch_failed)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::serde::scheduler::{ExecutorMetadata, ExecutorSpecification, PartitionId};
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::memory::MemoryStream;
+
+    #[tokio::test]
+    async fn test_check_partition_stats() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?;
+        let location = |num_rows: u64| PartitionLocation {
+            partition_id: PartitionId::new("job", 1, 0),
+            executor_meta: ExecutorMetadata {
+                id: "executor-1".to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+                grpc_port: 50052,
+                specification: ExecutorSpecification { task_slots: 1 },
+                labels: Default::default(),
+                protocol: Default::default(),
+            },
+            partition_stats: PartitionStats::new(Some(num_rows), Some(2), None),
+            path: "/data.arrow".to_owned(),
+        };
+
+        let mismatches = Count::new();
+        for num_rows in [6, 5] {
+            let input = MemoryStream::try_new(
+                vec![batch.clone(), batch.clone()],
+                schema.clone(),
+                None,
+            )?;
+            let batches = PartitionStatsCheck::new(
+                Box::pin(input),
+                location(num_rows),
+                mismatches.clone(),
+            )
+            .try_collect::<Vec<_>>()
+            .await?;
+            assert_eq!(batches.len(), 2);
+        }
+        assert_eq!(mismatches.value(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_batches() -> ArrowResult<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = |num_rows: i32| {
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int32Array::from(
+                    (0..num_rows).collect::<Vec<_>>(),
+                ))],
+            )
+        };
+        let input = futures::stream::iter(vec![
+            batch(3),
+            batch(0),
+            batch(4),
+            batch(10),
+            batch(2),
+            batch(1),
+        ]);
+
+        let num_rows = coalesce_batches(input, schema.clone(), 5)
+            .map(|batch| batch.map(|batch| batch.num_rows()))
+            .try_collect::<Vec<_>>()
+            .await?;
+        assert_eq!(num_rows, vec![7, 10, 3]);
+        Ok(())
+    }
 
     #[tokio::test]
     async fn test_stats_for_partitions_empty() {
@@ -24,11 +24,14 @@ use datafusion::physical_plan::expressions::PhysicalSortExpr;
 
 use std::any::Any;
 use std::future::Future;
+use std::io::Write;
 use std::iter::Iterator;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Instant;
 
+use crate::encryption::{self, EncryptionKey};
+use crate::shuffle_file::{InProgressFile, ShuffleFileWriter};
 use crate::utils;
 
 use crate::serde::protobuf::ShuffleWritePartition;
@@ -40,7 +43,7 @@ use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
 
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::error::{DataFusionError, Result};
-use datafusion::physical_plan::common::IPCWriter;
+use datafusion::physical_plan::common::batch_byte_size;
 use datafusion::physical_plan::memory::MemoryStream;
 use datafusion::physical_plan::metrics::{
     self, ExecutionPlanMetricsSet, MetricBuilder, MetricsSet,
@@ -73,6 +76,8 @@ pub struct ShuffleWriterExec {
     work_dir: String,
     /// Optional shuffle output partitioning
     shuffle_output_partitioning: Option<Partitioning>,
+    /// Key to encrypt the output files with, if any
+    encryption_key: Option<EncryptionKey>,
     /// Execution metrics
     metrics: ExecutionPlanMetricsSet,
 }
@@ -120,10 +125,17 @@ impl ShuffleWriterExec {
             plan,
             work_dir,
             shuffle_output_partitioning,
+            encryption_key: None,
             metrics: ExecutionPlanMetricsSet::new(),
         })
     }
 
+    /// Encrypt the output files with `key`
+    pub fn with_encryption_key(mut self, key: Option<EncryptionKey>) -> Self {
+        self.encryption_key = key;
+        self
+    }
+
     /// Get the Job ID for this query stage
     pub fn job_id(&self) -> &str {
         &self.job_id
@@ -151,6 +163,7 @@ impl ShuffleWriterExec {
         let write_metrics = ShuffleWriteMetrics::new(input_partition, &self.metrics);
         let output_partitioning = self.shuffle_output_partitioning.clone();
         let plan = self.plan.clone();
+        let encryption_key = self.encryption_key.clone();
 
         async move {
             let now = Instant::now();
@@ -169,6 +182,7 @@ impl ShuffleWriterExec {
                     let stats = utils::write_stream_to_disk(
                         &mut stream,
                         path,
+                        encryption_key.as_ref(),
                         &write_metrics.write_time,
                     )
                     .await
@@ -201,7 +215,7 @@ impl ShuffleWriterExec {
                 Some(Partitioning::Hash(exprs, num_output_partitions)) => {
                     // we won't necessary produce output for every possible partition, so we
                     // create writers on demand
-                    let mut writers: Vec<Option<IPCWriter>> = vec![];
+                    let mut writers: Vec<Option<PartitionWriter>> = vec![];
                     for _ in 0..num_output_partitions {
                         writers.push(None);
                     }
@@ -239,9 +253,10 @@ impl ShuffleWriterExec {
                                         ));
                                         info!("Writing results to {:?}", path);
 
-                                        let mut writer = IPCWriter::new(
-                                            &path,
+                                        let mut writer = PartitionWriter::try_new(
+                                            path,
                                             stream.schema().as_ref(),
+                                            encryption_key.as_ref(),
                                         )?;
 
                                         writer.write(&output_batch)?;
@@ -264,7 +279,7 @@ impl ShuffleWriterExec {
                                 info!(
                                     "Finished writing shuffle partition {} at {:?}. Batches: {}. Rows: {}. Bytes: {}.",
                                     i,
-                                    w.path(),
+                                    w.file.path(),
                                     w.num_batches,
                                     w.num_rows,
                                     w.num_bytes
@@ -272,7 +287,7 @@ impl ShuffleWriterExec {
 
                                 part_locs.push(ShuffleWritePartition {
                                     partition_id: i as u64,
-                                    path: w.path().to_string_lossy().to_string(),
+                                    path: w.file.path().to_string_lossy().to_string(),
                                     num_batches: w.num_batches,
                                     num_rows: w.num_rows,
                                     num_bytes: w.num_bytes,
@@ -325,13 +340,16 @@ impl ExecutionPlan for ShuffleWriterExec {
         self: Arc<Self>,
         children: Vec<Arc<dyn ExecutionPlan>>,
     ) -> Result<Arc<dyn ExecutionPlan>> {
-        Ok(Arc::new(ShuffleWriterExec::try_new(
-            self.job_id.clone(),
-            self.stage_id,
-            children[0].clone(),
-            self.work_dir.clone(),
-            self.shuffle_output_partitioning.clone(),
-        )?))
+        Ok(Arc::new(
+            ShuffleWriterExec::try_new(
+                self.job_id.clone(),
+                self.stage_id,
+                children[0].clone(),
+                self.work_dir.clone(),
+                self.shuffle_output_partitioning.clone(),
+            )?
+            .with_encryption_key(self.encryption_key.clone()),
+        ))
     }
 
     fn execute(
@@ -430,10 +448,52 @@ fn result_schema() -> SchemaRef {
     ]))
 }
 
+/// Writes one output partition to an Arrow IPC file, encrypting it if a key is given.
+/// The file only appears at its path once finished.
+struct PartitionWriter {
+    writer: ShuffleFileWriter<Box<dyn Write + Send>>,
+    file: InProgressFile,
+    num_batches: u64,
+    num_rows: u64,
+    num_bytes: u64,
+}
+
+impl PartitionWriter {
+    fn try_new(
+        path: PathBuf,
+        schema: &Schema,
+        encryption_key: Option<&EncryptionKey>,
+    ) -> Result<Self> {
+        let file = InProgressFile::new(path);
+        let writer = encryption::create_file(file.temp_path(), encryption_key)?;
+        Ok(Self {
+            writer: ShuffleFileWriter::try_new(writer, schema)?,
+            file,
+            num_batches: 0,
+            num_rows: 0,
+            num_bytes: 0,
+        })
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        self.writer.write(batch)?;
+        self.num_batches += 1;
+        self.num_rows += batch.num_rows() as u64;
+        self.num_bytes += batch_byte_size(batch) as u64;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish()?;
+        Ok(self.file.commit()?)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use datafusion::arrow::array::{StringArray, StructArray, UInt32Array, UInt64Array};
+    use datafusion::arrow::ipc::reader::FileReader;
     use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
     use datafusion::physical_plan::expressions::Column;
 
@@ -538,6 +598,40 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_encrypted() -> Result<()> {
+        let session_ctx = SessionContext::new();
+        let task_ctx = session_ctx.task_ctx();
+
+        let key = EncryptionKey::generate();
+        let work_dir = TempDir::new()?;
+        let query_stage = ShuffleWriterExec::try_new(
+            "jobOne".to_owned(),
+            1,
+            create_input_plan()?,
+            work_dir.path().to_str().unwrap().to_owned(),
+            Some(Partitioning::Hash(vec![Arc::new(Column::new("a", 0))], 2)),
+        )?
+        .with_encryption_key(Some(key.clone()));
+        let partitions = query_stage.execute_shuffle_write(0, task_ctx).await?;
+        assert!(!partitions.is_empty());
+
+        for partition in partitions {
+            let file = encryption::open_file(&partition.path, Some(&key))?;
+            let reader = FileReader::try_new(file, None)?;
+            let num_rows: usize = reader
+                .map(|batch| batch.map(|batch| batch.num_rows()))
+                .sum::<std::result::Result<_, _>>()?;
+            assert_eq!(num_rows as u64, partition.num_rows);
+
+            // The file cannot be read without the key
+            let file = encryption::open_file(&partition.path, None)?;
+            assert!(FileReader::try_new(file, None).is_err());
+        }
+
+        Ok(())
+    }
+
     fn create_input_plan() -> Result<Arc<dyn ExecutionPlan>> {
         let schema = Arc::new(Schema::new(vec![
             Field::new("a", DataType::UInt32, true),
@@ -0,0 +1,256 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! TopKExec keeps the first rows of every partition of its input by an ordering.
+//!
+//! Rows are buffered until there are twice as many as the rows to keep, at which point
+//! the buffered rows are sorted and cut down to the rows to keep. A partition therefore
+//! never holds more than about twice the rows it keeps, plus a batch, however large its
+//! input is.
+
+use datafusion::arrow::compute::{lexsort_to_indices, take};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_plan::coalesce_batches::concat_batches;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream, Statistics,
+};
+use futures::StreamExt;
+use log::info;
+use std::any::Any;
+use std::fmt::{self, Formatter};
+use std::sync::Arc;
+
+/// TopKExec outputs the first `fetch` rows of each partition of its input sorted by
+/// `expr`, in a single batch
+#[derive(Debug, Clone)]
+pub struct TopKExec {
+    input: Arc<dyn ExecutionPlan>,
+    expr: Vec<PhysicalSortExpr>,
+    fetch: usize,
+}
+
+impl TopKExec {
+    pub fn new(
+        input: Arc<dyn ExecutionPlan>,
+        expr: Vec<PhysicalSortExpr>,
+        fetch: usize,
+    ) -> Self {
+        Self { input, expr, fetch }
+    }
+
+    pub fn input(&self) -> &Arc<dyn ExecutionPlan> {
+        &self.input
+    }
+
+    pub fn expr(&self) -> &[PhysicalSortExpr] {
+        &self.expr
+    }
+
+    /// Number of rows kept of each partition
+    pub fn fetch(&self) -> usize {
+        self.fetch
+    }
+}
+
+impl ExecutionPlan for TopKExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        Some(&self.expr)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "TopKExec wrong number of children".to_string(),
+            ));
+        }
+        Ok(Arc::new(TopKExec::new(
+            children[0].clone(),
+            self.expr.clone(),
+            self.fetch,
+        )))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        info!("TopKExec::execute({})", partition);
+
+        let mut input = self.input.execute(partition, context)?;
+        let schema = self.schema();
+        let expr = self.expr.clone();
+        let fetch = self.fetch;
+        let stream = futures::stream::once(async move {
+            let mut buffered = vec![];
+            let mut num_rows = 0;
+            while let Some(batch) = input.next().await {
+                let batch = batch?;
+                num_rows += batch.num_rows();
+                buffered.push(batch);
+                if num_rows >= 2 * fetch.max(1) {
+                    let top = top_k(&schema, &expr, &buffered, num_rows, fetch)?;
+                    num_rows = top.num_rows();
+                    buffered = vec![top];
+                }
+            }
+            top_k(&schema, &expr, &buffered, num_rows, fetch)
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema(),
+            stream,
+        )))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => {
+                let expr: Vec<String> = self.expr.iter().map(|e| e.to_string()).collect();
+                write!(
+                    f,
+                    "TopKExec: fetch={}, expr=[{}]",
+                    self.fetch,
+                    expr.join(",")
+                )
+            }
+        }
+    }
+
+    fn statistics(&self) -> Statistics {
+        let input = self.input.statistics();
+        let partitions = self.output_partitioning().partition_count();
+        match input.num_rows {
+            Some(num_rows) => Statistics {
+                num_rows: Some(num_rows.min(self.fetch * partitions)),
+                ..Default::default()
+            },
+            None => Statistics::default(),
+        }
+    }
+}
+
+/// The first `fetch` of the `num_rows` rows of `batches` sorted by `expr`
+fn top_k(
+    schema: &SchemaRef,
+    expr: &[PhysicalSortExpr],
+    batches: &[RecordBatch],
+    num_rows: usize,
+    fetch: usize,
+) -> ArrowResult<RecordBatch> {
+    let batch = concat_batches(schema, batches, num_rows)?;
+    let columns = expr
+        .iter()
+        .map(|e| e.evaluate_to_sort_column(&batch))
+        .collect::<Result<Vec<_>>>()?;
+    let indices = lexsort_to_indices(&columns, Some(fetch.min(num_rows)))?;
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column.as_ref(), &indices, None))
+        .collect::<ArrowResult<Vec<_>>>()?;
+    RecordBatch::try_new(schema.clone(), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::compute::SortOptions;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::common;
+    use datafusion::physical_plan::expressions::col;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+
+    #[tokio::test]
+    async fn keep_first_rows_of_each_partition() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batch = |values: Vec<Option<i32>>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(values))])
+        };
+        let input = Arc::new(MemoryExec::try_new(
+            &[
+                vec![
+                    batch(vec![Some(5), Some(1), None])?,
+                    batch(vec![Some(9), Some(3)])?,
+                    batch(vec![Some(2), Some(8), Some(4)])?,
+                ],
+                vec![batch(vec![Some(7)])?],
+            ],
+            schema.clone(),
+            None,
+        )?);
+        let top_k = TopKExec::new(
+            input,
+            vec![PhysicalSortExpr {
+                expr: col("a", &schema)?,
+                options: SortOptions {
+                    descending: true,
+                    nulls_first: false,
+                },
+            }],
+            2,
+        );
+
+        let ctx = SessionContext::new();
+        let values = |batches: Vec<RecordBatch>| -> Vec<Option<i32>> {
+            batches
+                .iter()
+                .flat_map(|batch| {
+                    batch
+                        .column(0)
+                        .as_any()
+                        .downcast_ref::<Int32Array>()
+                        .unwrap()
+                        .iter()
+                        .collect::<Vec<_>>()
+                })
+                .collect()
+        };
+        let batches = common::collect(top_k.execute(0, ctx.task_ctx())?).await?;
+        assert_eq!(values(batches), vec![Some(9), Some(8)]);
+        // Partitions with fewer rows keep them all
+        let batches = common::collect(top_k.execute(1, ctx.task_ctx())?).await?;
+        assert_eq!(values(batches), vec![Some(7)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,210 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tuning of the Flight transfers of shuffle data between executors.
+//!
+//! The options are installed once per process with [init_flight_transfer]. From then
+//! on, the [BallistaClient](crate::client::BallistaClient)s of the process and the
+//! Flight servers set up with [configure_flight_server] and [flight_service_server]
+//! use them. Processes which do not install options keep the defaults of tonic.
+
+use crate::error::{BallistaError, Result};
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use datafusion::arrow::array::UInt32Array;
+use datafusion::arrow::compute::take;
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::ipc::writer::IpcWriteOptions;
+use datafusion::arrow::ipc::CompressionType;
+use datafusion::arrow::record_batch::RecordBatch;
+use once_cell::sync::OnceCell;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+use tonic::transport::{Endpoint, Server};
+
+static FLIGHT_TRANSFER: OnceCell<FlightTransferOptions> = OnceCell::new();
+
+/// Options of the Flight transfers of a process
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlightTransferOptions {
+    /// Batches larger than this many bytes are split into several Flight messages, so
+    /// that they fit the message size limit of clients. Unlimited if 0.
+    pub max_message_size: usize,
+    /// Maximum number of concurrent streams on each connection to a Flight server,
+    /// which bounds the partitions a peer fetches at once. Unlimited if 0.
+    pub max_concurrent_streams: u32,
+    /// Interval of the TCP keepalive probes of Flight connections. Disabled if zero.
+    pub tcp_keepalive: Duration,
+    /// Compress the partitions sent by Flight servers with gzip, for clients which
+    /// accept it
+    pub compression: bool,
+}
+
+impl FlightTransferOptions {
+    fn tcp_keepalive(&self) -> Option<Duration> {
+        (!self.tcp_keepalive.is_zero()).then(|| self.tcp_keepalive)
+    }
+}
+
+/// Use `options` for all Flight transfers of this process
+pub fn init_flight_transfer(options: FlightTransferOptions) -> Result<()> {
+    FLIGHT_TRANSFER.set(options).map_err(|_| {
+        BallistaError::General("Flight transfer options are already set".to_owned())
+    })
+}
+
+/// The Flight transfer options of this process
+pub fn flight_transfer_options() -> FlightTransferOptions {
+    FLIGHT_TRANSFER.get().cloned().unwrap_or_default()
+}
+
+/// Apply the connection options of this process to a Flight server
+pub fn configure_flight_server(builder: Server) -> Server {
+    let options = flight_transfer_options();
+    let max_concurrent_streams =
+        (options.max_concurrent_streams > 0).then(|| options.max_concurrent_streams);
+    builder
+        .max_concurrent_streams(max_concurrent_streams)
+        .tcp_keepalive(options.tcp_keepalive())
+}
+
+/// Apply the connection options of this process to a connection to a Flight server
+pub fn configure_flight_endpoint(endpoint: Endpoint) -> Endpoint {
+    endpoint.tcp_keepalive(flight_transfer_options().tcp_keepalive())
+}
+
+/// Serve `service`, compressing its responses if compression is enabled in this
+/// process. Compressed requests are always accepted.
+pub fn flight_service_server<T: FlightService>(service: T) -> FlightServiceServer<T> {
+    let server =
+        FlightServiceServer::new(service).accept_compressed(CompressionEncoding::Gzip);
+    if flight_transfer_options().compression {
+        server.send_compressed(CompressionEncoding::Gzip)
+    } else {
+        server
+    }
+}
+
+/// Compression of the buffers of the IPC messages of a partition, which clients ask for
+/// when they fetch it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpcCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl IpcCompression {
+    /// The options writing the IPC messages of a partition compressed this way
+    pub fn write_options(&self) -> ArrowResult<IpcWriteOptions> {
+        let compression = match self {
+            IpcCompression::None => return Ok(IpcWriteOptions::default()),
+            IpcCompression::Lz4 => CompressionType::LZ4_FRAME,
+            IpcCompression::Zstd => CompressionType::ZSTD,
+        };
+        IpcWriteOptions::default().try_with_compression(Some(compression))
+    }
+}
+
+impl FromStr for IpcCompression {
+    type Err = BallistaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(IpcCompression::None),
+            "lz4" => Ok(IpcCompression::Lz4),
+            "zstd" => Ok(IpcCompression::Zstd),
+            _ => Err(BallistaError::General(format!(
+                "Unknown IPC compression {}, expected none, lz4 or zstd",
+                s
+            ))),
+        }
+    }
+}
+
+/// Split `batch` into consecutive batches of at most about `max_message_size` bytes.
+/// Batches of a single row are never split. The dictionary-encoded columns of the parts
+/// keep the whole dictionary of `batch`, which counts towards its size but is sent once,
+/// so the messages of a batch with large dictionaries are smaller than the limit.
+pub fn split_batch(
+    batch: &RecordBatch,
+    max_message_size: usize,
+) -> ArrowResult<Vec<RecordBatch>> {
+    let size: usize = batch
+        .columns()
+        .iter()
+        .map(|column| column.get_array_memory_size())
+        .sum();
+    if max_message_size == 0 || size <= max_message_size || batch.num_rows() <= 1 {
+        return Ok(vec![batch.clone()]);
+    }
+
+    let parts = (size + max_message_size - 1) / max_message_size;
+    let rows_per_part = (batch.num_rows() + parts - 1) / parts;
+    (0..batch.num_rows())
+        .step_by(rows_per_part)
+        .map(|start| {
+            // Rows are copied, as sliced arrays are written with their whole buffers
+            let end = (start + rows_per_part).min(batch.num_rows());
+            let indices = UInt32Array::from_iter_values(start as u32..end as u32);
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| take(column.as_ref(), &indices, None))
+                .collect::<ArrowResult<Vec<_>>>()?;
+            RecordBatch::try_new(batch.schema(), columns)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Array, Int64Array};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::sync::Arc;
+
+    #[test]
+    fn parse_ipc_compression() -> Result<()> {
+        assert_eq!("none".parse::<IpcCompression>()?, IpcCompression::None);
+        assert_eq!("LZ4".parse::<IpcCompression>()?, IpcCompression::Lz4);
+        assert_eq!("zstd".parse::<IpcCompression>()?, IpcCompression::Zstd);
+        assert!("gzip".parse::<IpcCompression>().is_err());
+        assert!(IpcCompression::Zstd.write_options().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn split_large_batches() -> ArrowResult<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![Arc::new(Int64Array::from_iter_values(0..10_000))],
+        )?;
+
+        assert_eq!(split_batch(&batch, 0)?.len(), 1);
+        assert_eq!(split_batch(&batch, 1024 * 1024)?.len(), 1);
+
+        let parts = split_batch(&batch, 8 * 1024)?;
+        assert!(parts.len() >= 10);
+        assert_eq!(parts.iter().map(|b| b.num_rows()).sum::<usize>(), 10_000);
+        let last = parts.last().unwrap().column(0);
+        let last = last.as_any().downcast_ref::<Int64Array>().unwrap();
+        assert_eq!(last.value(last.len() - 1), 9_999);
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Standard gRPC services served next to the Ballista services, so that tools such as
+//! grpcurl, load balancers and service meshes can probe and introspect the servers.
+
+use crate::error::{BallistaError, Result};
+use crate::serde::protobuf::FILE_DESCRIPTOR_SET;
+use tonic_health::server::{Health, HealthServer};
+use tonic_reflection::server::{ServerReflection, ServerReflectionServer};
+
+pub use tonic_health::server::HealthReporter;
+pub use tonic_health::ServingStatus;
+
+/// The `grpc.health.v1.Health` service along with the reporter setting the status of
+/// the services. The overall status of the server, checked with an empty service
+/// name, is serving from the start.
+pub fn health_service() -> (HealthReporter, HealthServer<impl Health>) {
+    tonic_health::server::health_reporter()
+}
+
+/// The `grpc.reflection.v1alpha.ServerReflection` service, describing the Ballista
+/// services, the health service and the services of `file_descriptor_sets`
+pub fn reflection_service(
+    file_descriptor_sets: &[&[u8]],
+) -> Result<ServerReflectionServer<impl ServerReflection>> {
+    let mut builder = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(
+            tonic_health::proto::GRPC_HEALTH_V1_FILE_DESCRIPTOR_SET,
+        );
+    for file_descriptor_set in file_descriptor_sets {
+        builder = builder.register_encoded_file_descriptor_set(file_descriptor_set);
+    }
+    builder.build().map_err(|e| {
+        BallistaError::General(format!("Could not build reflection service: {}", e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_reflection_service() {
+        assert!(reflection_service(&[]).is_ok());
+    }
+}
@@ -0,0 +1,158 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Bounded reads of Kafka topics, the micro-batches of streaming queries.
+//!
+//! A [KafkaTable] is the slice of a topic between two offsets of each of its partitions.
+//! Its scans read every partition of the topic in a partition of a [KafkaScanExec], so
+//! the micro-batch is read in parallel by the executors. The records of a topic are
+//! read as [kafka_schema]: keys and values are decoded as UTF-8, replacing invalid
+//! sequences.
+
+use crate::execution_plans::KafkaScanExec;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::ExecutionPlan;
+use rskafka::client::partition::OffsetAt;
+use rskafka::client::{Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The records of partition `partition` of a topic from `start_offset` included to
+/// `end_offset` excluded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KafkaOffsetRange {
+    pub partition: i32,
+    pub start_offset: i64,
+    pub end_offset: i64,
+}
+
+/// The schema of the records of Kafka topics
+pub fn kafka_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("partition", DataType::Int32, false),
+        Field::new("offset", DataType::Int64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        ),
+        Field::new("key", DataType::Utf8, true),
+        Field::new("value", DataType::Utf8, true),
+    ]))
+}
+
+/// Connect to the Kafka cluster of `brokers`, over plaintext TCP without
+/// authentication, so clusters requiring TLS or SASL are not supported
+pub async fn connect(brokers: &[String]) -> Result<Client> {
+    ClientBuilder::new(brokers.to_vec())
+        .build()
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))
+}
+
+/// The earliest and latest offsets of the partitions of `topic`. The latest offset of a
+/// partition is the offset of the next record written to it.
+pub async fn partition_offsets(
+    brokers: &[String],
+    topic: &str,
+) -> Result<BTreeMap<i32, (i64, i64)>> {
+    let client = connect(brokers).await?;
+    let partitions = client
+        .list_topics()
+        .await
+        .map_err(|e| DataFusionError::External(Box::new(e)))?
+        .into_iter()
+        .find(|t| t.name == topic)
+        .ok_or_else(|| DataFusionError::Plan(format!("Kafka topic {} not found", topic)))?
+        .partitions;
+
+    let mut offsets = BTreeMap::new();
+    for partition in partitions {
+        let partition_client = client
+            .partition_client(topic, partition)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let earliest = partition_client
+            .get_offset(OffsetAt::Earliest)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let latest = partition_client
+            .get_offset(OffsetAt::Latest)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        offsets.insert(partition, (earliest, latest));
+    }
+    Ok(offsets)
+}
+
+/// The records of a Kafka topic in given offset ranges of its partitions
+pub struct KafkaTable {
+    brokers: Vec<String>,
+    topic: String,
+    ranges: Vec<KafkaOffsetRange>,
+}
+
+impl KafkaTable {
+    pub fn new(
+        brokers: Vec<String>,
+        topic: String,
+        ranges: Vec<KafkaOffsetRange>,
+    ) -> Self {
+        Self {
+            brokers,
+            topic,
+            ranges,
+        }
+    }
+}
+
+#[async_trait]
+impl TableProvider for KafkaTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        kafka_schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(KafkaScanExec::try_new(
+            self.brokers.clone(),
+            self.topic.clone(),
+            self.ranges.clone(),
+            projection.clone(),
+        )?))
+    }
+}
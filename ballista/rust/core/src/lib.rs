@@ -22,13 +22,30 @@ pub fn print_version() {
     println!("Ballista version: {}", BALLISTA_VERSION)
 }
 
+pub mod auth;
+pub mod catalog;
 pub mod client;
+pub mod cluster_config;
 pub mod config;
+pub mod control_plane;
+pub mod encryption;
 pub mod error;
 pub mod event_loop;
 pub mod execution_plans;
+pub mod flight_transfer;
+pub mod grpc_services;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod local_socket;
+pub mod logging;
+pub mod object_store_registry;
 /// some plugins
 pub mod plugin;
+pub mod protocol;
+pub mod shuffle_file;
+pub mod table_format;
+pub mod telemetry;
+pub mod tls;
 pub mod utils;
 
 #[macro_use]
@@ -0,0 +1,159 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Unix domain sockets for fetching shuffle partitions between executors running on
+//! the same host.
+//!
+//! Executors given a socket directory serve their Flight endpoint on a socket named
+//! after their ID in that directory, besides their TCP port. An executor fetching a
+//! partition from an executor whose registered host is one of its own connects to that
+//! socket if it exists, and falls back to TCP otherwise. Executors sharing a host are
+//! therefore expected to share the socket directory and to register with the same
+//! external host.
+//!
+//! Connections through the socket are not encrypted, even if TLS is enabled. Instead,
+//! the directory of the sockets and the sockets themselves are only accessible to the
+//! user the executors run as, and executors only accept connections from processes of
+//! that user.
+
+use crate::error::Result;
+use crate::serde::scheduler::ExecutorMetadata;
+use once_cell::sync::OnceCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tonic::transport::Channel;
+
+static LOCAL_SOCKETS: OnceCell<LocalSocketOptions> = OnceCell::new();
+
+#[derive(Clone, Debug)]
+pub struct LocalSocketOptions {
+    /// Directory of the sockets of the executors running on this host
+    pub dir: PathBuf,
+    /// Hosts other executors may have registered with when running on this host
+    pub hosts: HashSet<String>,
+}
+
+impl LocalSocketOptions {
+    /// Options of an executor registered with `external_host`, if it has one
+    pub fn new(dir: impl Into<PathBuf>, external_host: Option<&str>) -> Self {
+        let mut hosts: HashSet<String> = ["localhost", "127.0.0.1", "::1"]
+            .iter()
+            .map(|host| host.to_string())
+            .collect();
+        hosts.extend(external_host.map(str::to_owned));
+        Self {
+            dir: dir.into(),
+            hosts,
+        }
+    }
+
+    /// The socket of `executor`, if it runs on this host and serves one
+    fn socket_of(&self, executor: &ExecutorMetadata) -> Option<PathBuf> {
+        if !self.hosts.contains(&executor.host) {
+            return None;
+        }
+        let path = socket_path(&self.dir, &executor.id);
+        if path.exists() {
+            Some(path)
+        } else {
+            None
+        }
+    }
+}
+
+/// Enable fetching partitions through the sockets of the executors on this host, once
+/// per process
+pub fn init_local_sockets(options: LocalSocketOptions) {
+    if LOCAL_SOCKETS.set(options).is_err() {
+        log::warn!("Local executor sockets are already set up");
+    }
+}
+
+/// Path of the socket of the executor with ID `executor_id` in `dir`
+pub fn socket_path(dir: &Path, executor_id: &str) -> PathBuf {
+    dir.join(format!("{}.sock", executor_id))
+}
+
+/// Connect to `executor` through its socket if it runs on this host and serves one,
+/// `None` otherwise
+pub async fn connect_local(executor: &ExecutorMetadata) -> Option<Result<Channel>> {
+    let path = LOCAL_SOCKETS.get()?.socket_of(executor)?;
+    Some(connect(path).await)
+}
+
+#[cfg(unix)]
+async fn connect(path: PathBuf) -> Result<Channel> {
+    use tokio::net::UnixStream;
+    use tonic::transport::{Endpoint, Uri};
+
+    // The URI is required by the endpoint but never used to connect
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(tower::service_fn(move |_: Uri| {
+            UnixStream::connect(path.clone())
+        }))
+        .await?;
+    Ok(channel)
+}
+
+#[cfg(not(unix))]
+async fn connect(path: PathBuf) -> Result<Channel> {
+    Err(crate::error::BallistaError::General(format!(
+        "Unix domain sockets are not supported, cannot connect to {:?}",
+        path
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::scheduler::ExecutorSpecification;
+
+    fn executor(id: &str, host: &str) -> ExecutorMetadata {
+        ExecutorMetadata {
+            id: id.to_owned(),
+            host: host.to_owned(),
+            port: 50051,
+            grpc_port: 50052,
+            specification: ExecutorSpecification { task_slots: 1 },
+            labels: Default::default(),
+            protocol: Default::default(),
+        }
+    }
+
+    #[test]
+    fn find_sockets_of_local_executors() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let options = LocalSocketOptions::new(dir.path(), Some("node-1"));
+        std::fs::File::create(socket_path(dir.path(), "executor-1"))?;
+
+        assert_eq!(
+            options.socket_of(&executor("executor-1", "node-1")),
+            Some(dir.path().join("executor-1.sock"))
+        );
+        assert!(options
+            .socket_of(&executor("executor-1", "localhost"))
+            .is_some());
+        // Executors on other hosts, or without a socket, are reached through TCP
+        assert!(options
+            .socket_of(&executor("executor-1", "node-2"))
+            .is_none());
+        assert!(options
+            .socket_of(&executor("executor-2", "node-1"))
+            .is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Log line formatting shared by the scheduler and executor binaries, which set up the
+//! logger itself.
+//!
+//! In [crate::config::LogFormat::Json] mode every log line is a JSON object. Besides the level, target and
+//! message it carries the fields of the [LogContext] of the current tokio task (if any) and the
+//! static fields of the process, such as the executor ID.
+
+use serde_json::{Map, Value};
+use std::future::Future;
+
+tokio::task_local! {
+    static LOG_CONTEXT: LogContext;
+}
+
+/// Identifies the unit of work being processed by the current tokio task
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LogContext {
+    pub job_id: Option<String>,
+    pub stage_id: Option<usize>,
+    pub partition_id: Option<usize>,
+}
+
+impl LogContext {
+    pub fn job(job_id: impl Into<String>) -> Self {
+        Self {
+            job_id: Some(job_id.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn task(job_id: impl Into<String>, stage_id: usize, partition_id: usize) -> Self {
+        Self {
+            job_id: Some(job_id.into()),
+            stage_id: Some(stage_id),
+            partition_id: Some(partition_id),
+        }
+    }
+
+    fn append_to(&self, fields: &mut Map<String, Value>) {
+        if let Some(job_id) = &self.job_id {
+            fields.insert("job_id".to_owned(), Value::from(job_id.as_str()));
+        }
+        if let Some(stage_id) = self.stage_id {
+            fields.insert("stage_id".to_owned(), Value::from(stage_id));
+        }
+        if let Some(partition_id) = self.partition_id {
+            fields.insert("partition_id".to_owned(), Value::from(partition_id));
+        }
+    }
+}
+
+/// Run `f` with `context` attached to every log line it emits.
+///
+/// The context is not inherited by tasks spawned from within `f`.
+pub async fn with_log_context<F: Future>(context: LogContext, f: F) -> F::Output {
+    LOG_CONTEXT.scope(context, f).await
+}
+
+/// Format `record` as a [crate::config::LogFormat::Json] line, with the [LogContext] of the current
+/// tokio task and the `static_fields` of the process
+pub fn json_line(
+    timestamp: &str,
+    record: &log::Record,
+    static_fields: &[(&'static str, String)],
+) -> String {
+    let context = LOG_CONTEXT.try_with(|c| c.clone()).unwrap_or_default();
+    format_json(timestamp, record, &context, static_fields)
+}
+
+fn format_json(
+    timestamp: &str,
+    record: &log::Record,
+    context: &LogContext,
+    static_fields: &[(&'static str, String)],
+) -> String {
+    let mut fields = Map::new();
+    fields.insert("timestamp".to_owned(), Value::from(timestamp));
+    fields.insert("level".to_owned(), Value::from(record.level().as_str()));
+    fields.insert("target".to_owned(), Value::from(record.target()));
+    fields.insert("message".to_owned(), Value::from(record.args().to_string()));
+    for (key, value) in static_fields {
+        fields.insert((*key).to_owned(), Value::from(value.as_str()));
+    }
+    context.append_to(&mut fields);
+
+    Value::Object(fields).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(context: &LogContext) -> Value {
+        let line = format_json(
+            "2022-08-01T00:00:00.000Z",
+            &log::Record::builder()
+                .args(format_args!("Done with task {}", "job/1/2"))
+                .level(log::Level::Info)
+                .target("ballista_executor")
+                .build(),
+            context,
+            &[("executor_id", "executor-1".to_owned())],
+        );
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn json_line_with_task_context() {
+        let line = format(&LogContext::task("job", 1, 2));
+
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "ballista_executor");
+        assert_eq!(line["message"], "Done with task job/1/2");
+        assert_eq!(line["executor_id"], "executor-1");
+        assert_eq!(line["job_id"], "job");
+        assert_eq!(line["stage_id"], 1);
+        assert_eq!(line["partition_id"], 2);
+    }
+
+    #[test]
+    fn json_line_without_context() {
+        let line = format(&LogContext::default());
+
+        assert_eq!(line["executor_id"], "executor-1");
+        assert!(line.get("job_id").is_none());
+        assert!(line.get("stage_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn scoped_log_context() {
+        let context = with_log_context(LogContext::job("job"), async {
+            LOG_CONTEXT.try_with(|c| c.clone()).unwrap_or_default()
+        })
+        .await;
+        assert_eq!(context, LogContext::job("job"));
+
+        assert!(LOG_CONTEXT.try_with(|c| c.clone()).is_err());
+    }
+}
@@ -0,0 +1,596 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Caching of the metadata read from object stores while planning queries.
+//!
+//! Planning a scan of a listing table lists the files of the table and, for Parquet
+//! tables, reads the footers of the files to infer their schema and statistics. A
+//! [CachingObjectStore] keeps the listings, object metadata and small ranges of objects
+//! such as footers it reads until they expire, so repeated queries over large tables
+//! are planned without listing the files and reading their footers again. The cache is
+//! bounded by the estimated size of its entries, evicting the least recently used ones
+//! first. Writes through the store invalidate the entries of the written paths, and
+//! files written by other processes are seen once the entries expire or are
+//! invalidated with [CachingObjectStore::invalidate].
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::ready;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use object_store::path::{Path, DELIMITER};
+use object_store::{GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::mem::size_of;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWrite;
+
+/// Largest range of an object which is cached, enough for the footers of Parquet files
+const MAX_CACHED_RANGE: usize = 1024 * 1024;
+
+/// How long and how much a [CachingObjectStore] caches
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MetadataCacheOptions {
+    pub ttl: Duration,
+    /// Maximum estimated size of the cached entries in bytes
+    pub max_bytes: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum CacheKey {
+    List(Option<Path>),
+    ListWithDelimiter(Option<Path>),
+    Head(Path),
+    Range(Path, Range<usize>),
+}
+
+impl CacheKey {
+    /// Whether a write to `location` or below changes the cached value
+    fn is_affected_by(&self, location: &Path) -> bool {
+        match self {
+            // Listings of a directory include the objects of its subdirectories
+            CacheKey::List(prefix) | CacheKey::ListWithDelimiter(prefix) => {
+                prefix.as_ref().map_or(true, |prefix| {
+                    is_below(location, prefix) || is_below(prefix, location)
+                })
+            }
+            CacheKey::Head(path) | CacheKey::Range(path, _) => is_below(path, location),
+        }
+    }
+
+    /// Estimated size of the key in bytes
+    fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                CacheKey::List(prefix) | CacheKey::ListWithDelimiter(prefix) => {
+                    prefix.as_ref().map_or(0, |prefix| prefix.as_ref().len())
+                }
+                CacheKey::Head(path) | CacheKey::Range(path, _) => path.as_ref().len(),
+            }
+    }
+}
+
+#[derive(Clone)]
+enum CacheValue {
+    List(Vec<ObjectMeta>),
+    ListWithDelimiter {
+        next_token: Option<String>,
+        common_prefixes: Vec<Path>,
+        objects: Vec<ObjectMeta>,
+    },
+    Head(ObjectMeta),
+    Range(Bytes),
+}
+
+impl CacheValue {
+    /// Estimated size of the value in bytes
+    fn size(&self) -> usize {
+        let path = |path: &Path| size_of::<Path>() + path.as_ref().len();
+        let meta =
+            |meta: &ObjectMeta| size_of::<ObjectMeta>() + meta.location.as_ref().len();
+        size_of::<Self>()
+            + match self {
+                CacheValue::List(metas) => metas.iter().map(meta).sum(),
+                CacheValue::ListWithDelimiter {
+                    next_token,
+                    common_prefixes,
+                    objects,
+                } => {
+                    next_token.as_ref().map_or(0, String::len)
+                        + common_prefixes.iter().map(path).sum::<usize>()
+                        + objects.iter().map(meta).sum::<usize>()
+                }
+                CacheValue::Head(head) => meta(head),
+                CacheValue::Range(bytes) => bytes.len(),
+            }
+    }
+}
+
+struct Entry {
+    inserted: Instant,
+    /// When the entry was last used, its key in [Entries::lru]
+    used: u64,
+    size: usize,
+    value: CacheValue,
+}
+
+/// The entries of a [CachingObjectStore], ordered by when they were last used
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<CacheKey, Entry>,
+    /// The keys of the entries by when they were last used, least recently used first
+    lru: BTreeMap<u64, CacheKey>,
+    /// Incremented every time an entry is used
+    clock: u64,
+    /// Estimated size of the entries in bytes
+    bytes: usize,
+}
+
+impl Entries {
+    fn get(&mut self, key: &CacheKey, ttl: Duration) -> Option<CacheValue> {
+        if self.entries.get(key)?.inserted.elapsed() >= ttl {
+            self.remove(key);
+            return None;
+        }
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.used);
+        entry.used = self.clock;
+        self.lru.insert(self.clock, key.clone());
+        Some(entry.value.clone())
+    }
+
+    /// Insert an entry, evicting the least recently used entries until the entries fit
+    /// in `max_bytes`
+    fn insert(&mut self, key: CacheKey, value: CacheValue, max_bytes: usize) {
+        self.remove(&key);
+        let size = key.size() + value.size();
+        if size > max_bytes {
+            return;
+        }
+        while self.bytes + size > max_bytes {
+            let oldest = match self.lru.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            self.remove(&oldest);
+        }
+        self.clock += 1;
+        self.lru.insert(self.clock, key.clone());
+        self.bytes += size;
+        self.entries.insert(
+            key,
+            Entry {
+                inserted: Instant::now(),
+                used: self.clock,
+                size,
+                value,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.used);
+            self.bytes -= entry.size;
+        }
+    }
+
+    /// Drop the entries changed by a write to `location` or below
+    fn invalidate(&mut self, location: &Path) {
+        let affected: Vec<CacheKey> = self
+            .entries
+            .keys()
+            .filter(|key| key.is_affected_by(location))
+            .cloned()
+            .collect();
+        for key in affected {
+            self.remove(&key);
+        }
+    }
+}
+
+/// An [ObjectStore] caching the listings, metadata and footers read from another store
+pub struct CachingObjectStore {
+    inner: Arc<dyn ObjectStore>,
+    options: MetadataCacheOptions,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl CachingObjectStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, options: MetadataCacheOptions) -> Self {
+        Self {
+            inner,
+            options,
+            entries: Arc::new(Mutex::new(Entries::default())),
+        }
+    }
+
+    /// Drop the cached entries of the objects below `prefix`, or all entries if `None`
+    pub fn invalidate(&self, prefix: Option<&Path>) {
+        let mut entries = self.entries.lock();
+        match prefix {
+            Some(prefix) => entries.invalidate(prefix),
+            None => *entries = Entries::default(),
+        }
+    }
+
+    /// Number of cached entries, including expired ones
+    pub fn len(&self) -> usize {
+        self.entries.lock().entries.len()
+    }
+
+    /// Estimated size of the cached entries in bytes
+    pub fn size(&self) -> usize {
+        self.entries.lock().bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup(&self, key: &CacheKey) -> Option<CacheValue> {
+        self.entries.lock().get(key, self.options.ttl)
+    }
+
+    fn insert(&self, key: CacheKey, value: CacheValue) {
+        self.entries
+            .lock()
+            .insert(key, value, self.options.max_bytes);
+    }
+}
+
+/// A multipart upload through a [CachingObjectStore], which invalidates the entries of
+/// the written path once the upload completes and the object is visible
+struct InvalidatingUpload {
+    inner: Box<dyn AsyncWrite + Unpin + Send>,
+    entries: Arc<Mutex<Entries>>,
+    location: Path,
+}
+
+impl AsyncWrite for InvalidatingUpload {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        let result = ready!(Pin::new(&mut self.inner).poll_shutdown(cx));
+        self.entries.lock().invalidate(&self.location);
+        Poll::Ready(result)
+    }
+}
+
+#[async_trait]
+impl ObjectStore for CachingObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        let result = self.inner.put(location, bytes).await;
+        self.invalidate(Some(location));
+        result
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.invalidate(Some(location));
+        let (id, upload) = self.inner.put_multipart(location).await?;
+        let upload = InvalidatingUpload {
+            inner: upload,
+            entries: self.entries.clone(),
+            location: location.clone(),
+        };
+        Ok((id, Box::new(upload)))
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get(&self, location: &Path) -> object_store::Result<GetResult> {
+        self.inner.get(location).await
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        range: Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        if range.end.saturating_sub(range.start) > MAX_CACHED_RANGE {
+            return self.inner.get_range(location, range).await;
+        }
+        let key = CacheKey::Range(location.clone(), range.clone());
+        if let Some(CacheValue::Range(bytes)) = self.lookup(&key) {
+            return Ok(bytes);
+        }
+        let bytes = self.inner.get_range(location, range).await?;
+        self.insert(key, CacheValue::Range(bytes.clone()));
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        let key = CacheKey::Head(location.clone());
+        if let Some(CacheValue::Head(meta)) = self.lookup(&key) {
+            return Ok(meta);
+        }
+        let meta = self.inner.head(location).await?;
+        self.insert(key, CacheValue::Head(meta.clone()));
+        Ok(meta)
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        let result = self.inner.delete(location).await;
+        self.invalidate(Some(location));
+        result
+    }
+
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        let key = CacheKey::List(prefix.cloned());
+        let metas = match self.lookup(&key) {
+            Some(CacheValue::List(metas)) => metas,
+            _ => {
+                let metas: Vec<ObjectMeta> =
+                    self.inner.list(prefix).await?.try_collect().await?;
+                self.insert(key, CacheValue::List(metas.clone()));
+                metas
+            }
+        };
+        Ok(stream::iter(metas.into_iter().map(Ok)).boxed())
+    }
+
+    async fn list_with_delimiter(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<ListResult> {
+        let key = CacheKey::ListWithDelimiter(prefix.cloned());
+        if let Some(CacheValue::ListWithDelimiter {
+            next_token,
+            common_prefixes,
+            objects,
+        }) = self.lookup(&key)
+        {
+            return Ok(ListResult {
+                next_token,
+                common_prefixes,
+                objects,
+            });
+        }
+        let result = self.inner.list_with_delimiter(prefix).await?;
+        self.insert(
+            key,
+            CacheValue::ListWithDelimiter {
+                next_token: result.next_token.clone(),
+                common_prefixes: result.common_prefixes.clone(),
+                objects: result.objects.clone(),
+            },
+        );
+        Ok(result)
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let result = self.inner.copy(from, to).await;
+        self.invalidate(Some(to));
+        result
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let result = self.inner.rename(from, to).await;
+        self.invalidate(Some(from));
+        self.invalidate(Some(to));
+        result
+    }
+
+    async fn copy_if_not_exists(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> object_store::Result<()> {
+        let result = self.inner.copy_if_not_exists(from, to).await;
+        self.invalidate(Some(to));
+        result
+    }
+
+    async fn rename_if_not_exists(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> object_store::Result<()> {
+        let result = self.inner.rename_if_not_exists(from, to).await;
+        self.invalidate(Some(from));
+        self.invalidate(Some(to));
+        result
+    }
+}
+
+impl Display for CachingObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CachingObjectStore({})", self.inner)
+    }
+}
+
+impl Debug for CachingObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+/// Whether `path` is `prefix` or below it
+fn is_below(path: &Path, prefix: &Path) -> bool {
+    let (path, prefix) = (path.as_ref(), prefix.as_ref());
+    prefix.is_empty()
+        || path
+            .strip_prefix(prefix)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with(DELIMITER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use tokio::io::AsyncWriteExt;
+
+    fn store(ttl: Duration) -> CachingObjectStore {
+        store_of_size(ttl, 1024 * 1024)
+    }
+
+    fn store_of_size(ttl: Duration, max_bytes: usize) -> CachingObjectStore {
+        CachingObjectStore::new(
+            Arc::new(InMemory::new()),
+            MetadataCacheOptions { ttl, max_bytes },
+        )
+    }
+
+    async fn list(store: &CachingObjectStore, prefix: &str) -> Vec<String> {
+        let prefix = Path::from(prefix);
+        store
+            .list(Some(&prefix))
+            .await
+            .unwrap()
+            .map_ok(|meta| meta.location.to_string())
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn cached_listings() -> object_store::Result<()> {
+        let store = store(Duration::from_secs(60));
+        store
+            .put(&Path::from("t/a.parquet"), Bytes::from("a"))
+            .await?;
+        assert_eq!(list(&store, "t").await, vec!["t/a.parquet"]);
+
+        // Files written by other processes are not seen until invalidation
+        store
+            .inner
+            .put(&Path::from("t/b.parquet"), Bytes::from("b"))
+            .await?;
+        assert_eq!(list(&store, "t").await.len(), 1);
+        store.invalidate(Some(&Path::from("t/b.parquet")));
+        assert_eq!(list(&store, "t").await.len(), 2);
+
+        // Writes through the store invalidate the listings including them
+        store
+            .put(&Path::from("t/c.parquet"), Bytes::from("c"))
+            .await?;
+        assert_eq!(list(&store, "t").await.len(), 3);
+        assert_eq!(list(&store, "u").await.len(), 0);
+        store
+            .put(&Path::from("tt/d.parquet"), Bytes::from("d"))
+            .await?;
+        assert_eq!(store.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_ranges() -> object_store::Result<()> {
+        let store = store(Duration::from_secs(60));
+        let path = Path::from("t/a.parquet");
+        store.put(&path, Bytes::from("footer")).await?;
+        assert_eq!(store.get_range(&path, 0..3).await?, Bytes::from("foo"));
+        store.inner.put(&path, Bytes::from("FOOTER")).await?;
+        assert_eq!(store.get_range(&path, 0..3).await?, Bytes::from("foo"));
+        assert_eq!(store.get_range(&path, 3..6).await?, Bytes::from("TER"));
+        store.delete(&path).await?;
+        assert!(store.get_range(&path, 0..3).await.is_err());
+
+        let store = self::store(Duration::ZERO);
+        store.put(&path, Bytes::from("footer")).await?;
+        store.get_range(&path, 0..3).await?;
+        store.inner.put(&path, Bytes::from("FOOTER")).await?;
+        assert_eq!(store.get_range(&path, 0..3).await?, Bytes::from("FOO"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn evict_least_recently_used() -> object_store::Result<()> {
+        let paths: Vec<Path> =
+            ["t/a", "t/b", "t/c"].into_iter().map(Path::from).collect();
+        let measured = store(Duration::from_secs(60));
+        measured.put(&paths[0], Bytes::from("aaa")).await?;
+        measured.get_range(&paths[0], 0..3).await?;
+        let entry_size = measured.size();
+
+        // Two ranges fit in the cache
+        let store = store_of_size(Duration::from_secs(60), 2 * entry_size);
+        for path in &paths {
+            store.put(path, Bytes::from("old")).await?;
+        }
+        store.get_range(&paths[0], 0..3).await?;
+        store.get_range(&paths[1], 0..3).await?;
+        store.get_range(&paths[0], 0..3).await?;
+        store.get_range(&paths[2], 0..3).await?;
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.size(), 2 * entry_size);
+        for path in &paths {
+            store.inner.put(path, Bytes::from("new")).await?;
+        }
+        // The range of b was used least recently, so it was evicted
+        assert_eq!(store.get_range(&paths[0], 0..3).await?, Bytes::from("old"));
+        assert_eq!(store.get_range(&paths[1], 0..3).await?, Bytes::from("new"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalidate_completed_uploads() -> object_store::Result<()> {
+        let store = store(Duration::from_secs(60));
+        let path = Path::from("t/a.parquet");
+        let (_, mut upload) = store.put_multipart(&path).await?;
+        upload.write_all(b"data").await.unwrap();
+        // The object is not visible until the upload completes
+        assert!(list(&store, "t").await.is_empty());
+        upload.shutdown().await.unwrap();
+        assert_eq!(list(&store, "t").await, vec!["t/a.parquet"]);
+        Ok(())
+    }
+
+    #[test]
+    fn paths_below() {
+        let below = |path: &str, prefix: &str| is_below(&path.into(), &prefix.into());
+        assert!(below("t/a", "t"));
+        assert!(below("t", "t"));
+        assert!(below("t", ""));
+        assert!(!below("tt/a", "t"));
+        assert!(!below("t", "t/a"));
+    }
+}
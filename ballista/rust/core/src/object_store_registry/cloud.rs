@@ -0,0 +1,342 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! [ObjectStoreFactory]s for the object stores of cloud providers.
+//!
+//! For the stores of the cluster, options which are not given fall back to the
+//! environment variables the SDKs of the providers read, and stores configured without
+//! any credentials use the credentials of the machine they run on, such as the EC2
+//! instance profile or a web identity token.
+//!
+//! Stores of clients only use the options the client passed. They must carry their own
+//! credentials, and options which point the credentials of the machine or its files at a
+//! host of the client's choosing are rejected.
+
+use super::{ObjectStoreFactory, OptionsSource};
+use crate::error::{BallistaError, Result};
+use object_store::ObjectStore;
+use std::collections::HashMap;
+use std::sync::Arc;
+use url::Url;
+
+/// The value of `key` in `options`, or for the stores of the cluster of the environment
+/// variable `env` if it is not set
+fn option(
+    options: &HashMap<String, String>,
+    key: &str,
+    env: &str,
+    source: OptionsSource,
+) -> Option<String> {
+    options
+        .get(key)
+        .cloned()
+        .or_else(|| match source {
+            OptionsSource::Cluster => std::env::var(env).ok(),
+            OptionsSource::Client => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+/// Fails if a client passed `key`, which only the operator may configure
+fn reject_client_option(
+    url: &Url,
+    options: &HashMap<String, String>,
+    key: &str,
+    source: OptionsSource,
+) -> Result<()> {
+    if source == OptionsSource::Client && options.contains_key(key) {
+        return Err(BallistaError::General(format!(
+            "Option {} of object store {} can only be configured by the operator",
+            key, url
+        )));
+    }
+    Ok(())
+}
+
+fn missing_credentials(url: &Url) -> BallistaError {
+    BallistaError::General(format!("No credentials given for object store {}", url))
+}
+
+fn bool_option(options: &HashMap<String, String>, key: &str) -> Result<bool> {
+    match options.get(key) {
+        Some(value) => value.parse().map_err(|_| {
+            BallistaError::General(format!("Invalid value {} for {}", value, key))
+        }),
+        None => Ok(false),
+    }
+}
+
+/// The bucket or container of a store URL such as `s3://bucket`
+fn bucket(url: &Url) -> Result<&str> {
+    url.host_str().ok_or_else(|| {
+        BallistaError::General(format!("Object store URL {} has no bucket", url))
+    })
+}
+
+fn build_error(url: &Url, error: object_store::Error) -> BallistaError {
+    BallistaError::General(format!("Could not create object store {}: {}", url, error))
+}
+
+/// Creates Amazon S3 stores, or stores of S3 compatible services with `endpoint` set.
+///
+/// Options: `access_key_id`, `secret_access_key`, `session_token`, `region`, `endpoint`
+/// and `allow_http`. Clients must pass the keys and cannot set `endpoint`.
+#[cfg(feature = "s3")]
+pub struct AmazonS3Factory;
+
+#[cfg(feature = "s3")]
+impl ObjectStoreFactory for AmazonS3Factory {
+    fn create(
+        &self,
+        url: &Url,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<Arc<dyn ObjectStore>> {
+        use object_store::aws::AmazonS3Builder;
+
+        reject_client_option(url, options, "endpoint", source)?;
+        let mut builder = AmazonS3Builder::new()
+            .with_bucket_name(bucket(url)?)
+            .with_allow_http(bool_option(options, "allow_http")?);
+        if let Some(region) = option(options, "region", "AWS_REGION", source)
+            .or_else(|| option(options, "region", "AWS_DEFAULT_REGION", source))
+        {
+            builder = builder.with_region(region);
+        }
+        if let Some(endpoint) = option(options, "endpoint", "AWS_ENDPOINT", source) {
+            builder = builder.with_endpoint(endpoint);
+        }
+        // Keys from the options and from the environment are not mixed
+        let keys =
+            if options.contains_key("access_key_id") || source == OptionsSource::Client {
+                (
+                    options.get("access_key_id").cloned(),
+                    options.get("secret_access_key").cloned(),
+                    options.get("session_token").cloned(),
+                )
+            } else {
+                (
+                    std::env::var("AWS_ACCESS_KEY_ID").ok(),
+                    std::env::var("AWS_SECRET_ACCESS_KEY").ok(),
+                    std::env::var("AWS_SESSION_TOKEN").ok(),
+                )
+            };
+        if let (Some(access_key_id), Some(secret_access_key), session_token) = keys {
+            builder = builder
+                .with_access_key_id(access_key_id)
+                .with_secret_access_key(secret_access_key);
+            if let Some(token) = session_token {
+                builder = builder.with_token(token);
+            }
+        } else if source == OptionsSource::Client {
+            // Without keys the store would use the instance profile of the machine
+            return Err(missing_credentials(url));
+        }
+        Ok(Arc::new(builder.build().map_err(|e| build_error(url, e))?))
+    }
+}
+
+/// Creates Google Cloud Storage stores.
+///
+/// Options: `service_account_path`, the path of a service account key file. As the key
+/// has to be a file of the machine the store runs on, only the operator can configure
+/// these stores.
+#[cfg(feature = "gcs")]
+pub struct GoogleCloudStorageFactory;
+
+#[cfg(feature = "gcs")]
+impl ObjectStoreFactory for GoogleCloudStorageFactory {
+    fn create(
+        &self,
+        url: &Url,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<Arc<dyn ObjectStore>> {
+        use object_store::gcp::GoogleCloudStorageBuilder;
+
+        if source == OptionsSource::Client {
+            return Err(BallistaError::General(format!(
+                "Object store {} can only be configured by the operator",
+                url
+            )));
+        }
+        let service_account_path = option(
+            options,
+            "service_account_path",
+            "GOOGLE_APPLICATION_CREDENTIALS",
+            source,
+        )
+        .ok_or_else(|| {
+            BallistaError::General(format!(
+                "No service account configured for object store {}",
+                url
+            ))
+        })?;
+        let store = GoogleCloudStorageBuilder::new()
+            .with_bucket_name(bucket(url)?)
+            .with_service_account_path(service_account_path)
+            .build()
+            .map_err(|e| build_error(url, e))?;
+        Ok(Arc::new(store))
+    }
+}
+
+/// Creates Azure Blob Storage stores for URLs such as `az://container`.
+///
+/// Options: `account`, and either `access_key` or `client_id`, `client_secret` and
+/// `tenant_id` of a service principal. `use_emulator` connects to a local Azurite
+/// instead, which clients cannot use.
+#[cfg(feature = "azure")]
+pub struct MicrosoftAzureFactory;
+
+#[cfg(feature = "azure")]
+impl ObjectStoreFactory for MicrosoftAzureFactory {
+    fn create(
+        &self,
+        url: &Url,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<Arc<dyn ObjectStore>> {
+        use object_store::azure::MicrosoftAzureBuilder;
+
+        reject_client_option(url, options, "use_emulator", source)?;
+        let mut builder = MicrosoftAzureBuilder::new()
+            .with_container_name(bucket(url)?)
+            .with_use_emulator(bool_option(options, "use_emulator")?);
+        if let Some(account) =
+            option(options, "account", "AZURE_STORAGE_ACCOUNT_NAME", source)
+        {
+            builder = builder.with_account(account);
+        }
+        if let Some(access_key) =
+            option(options, "access_key", "AZURE_STORAGE_ACCOUNT_KEY", source)
+        {
+            builder = builder.with_access_key(access_key);
+        } else if let (Some(client_id), Some(client_secret), Some(tenant_id)) = (
+            option(options, "client_id", "AZURE_CLIENT_ID", source),
+            option(options, "client_secret", "AZURE_CLIENT_SECRET", source),
+            option(options, "tenant_id", "AZURE_TENANT_ID", source),
+        ) {
+            builder = builder.with_client_secret_authorization(
+                client_id,
+                client_secret,
+                tenant_id,
+            );
+        } else if source == OptionsSource::Client {
+            return Err(missing_credentials(url));
+        }
+        Ok(Arc::new(builder.build().map_err(|e| build_error(url, e))?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options() -> Result<()> {
+        let options: HashMap<String, String> = vec![
+            ("region".to_owned(), "eu-west-1".to_owned()),
+            ("allow_http".to_owned(), "true".to_owned()),
+            ("endpoint".to_owned(), "".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        let cluster = OptionsSource::Cluster;
+        assert_eq!(
+            option(&options, "region", "BALLISTA_TEST_UNSET", cluster),
+            Some("eu-west-1".to_owned())
+        );
+        assert_eq!(
+            option(&options, "endpoint", "BALLISTA_TEST_UNSET", cluster),
+            None
+        );
+        assert!(bool_option(&options, "allow_http")?);
+        assert!(!bool_option(&options, "use_emulator")?);
+        assert!(bool_option(&options, "region").is_err());
+
+        assert_eq!(bucket(&Url::parse("s3://data/path").unwrap())?, "data");
+        assert!(bucket(&Url::parse("file:///data").unwrap()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn client_options() {
+        std::env::set_var("BALLISTA_TEST_CLOUD_REGION", "eu-west-1");
+        let url = Url::parse("s3://data").unwrap();
+        let options: HashMap<String, String> =
+            vec![("endpoint".to_owned(), "http://169.254.169.254".to_owned())]
+                .into_iter()
+                .collect();
+        assert_eq!(
+            option(
+                &options,
+                "region",
+                "BALLISTA_TEST_CLOUD_REGION",
+                OptionsSource::Cluster
+            ),
+            Some("eu-west-1".to_owned())
+        );
+        assert_eq!(
+            option(
+                &options,
+                "region",
+                "BALLISTA_TEST_CLOUD_REGION",
+                OptionsSource::Client
+            ),
+            None
+        );
+        assert!(
+            reject_client_option(&url, &options, "endpoint", OptionsSource::Cluster)
+                .is_ok()
+        );
+        assert!(
+            reject_client_option(&url, &options, "endpoint", OptionsSource::Client)
+                .is_err()
+        );
+        assert!(
+            reject_client_option(&url, &options, "region", OptionsSource::Client).is_ok()
+        );
+    }
+
+    #[cfg(feature = "s3")]
+    #[test]
+    fn client_s3_credentials() {
+        let url = Url::parse("s3://data").unwrap();
+        let factory = AmazonS3Factory;
+        let keys: HashMap<String, String> = vec![
+            ("access_key_id".to_owned(), "key".to_owned()),
+            ("secret_access_key".to_owned(), "secret".to_owned()),
+            ("region".to_owned(), "eu-west-1".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+        assert!(factory.create(&url, &keys, OptionsSource::Client).is_ok());
+        assert!(factory
+            .create(&url, &HashMap::new(), OptionsSource::Client)
+            .is_err());
+
+        let mut endpoint = keys;
+        endpoint.insert("endpoint".to_owned(), "http://localhost:9000".to_owned());
+        assert!(factory
+            .create(&url, &endpoint, OptionsSource::Client)
+            .is_err());
+        assert!(factory
+            .create(&url, &endpoint, OptionsSource::Cluster)
+            .is_ok());
+    }
+}
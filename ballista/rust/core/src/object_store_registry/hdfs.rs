@@ -0,0 +1,544 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! An [ObjectStore] reading from and writing to HDFS through libhdfs.
+//!
+//! libhdfs runs the Hadoop client in a JVM, so `JAVA_HOME`, `HADOOP_HOME` and a
+//! `CLASSPATH` containing the Hadoop jars must be set in the environment of the
+//! process. Secured clusters are accessed with the Kerberos ticket cache of the user
+//! running the process, or with the delegation tokens of the file the process was
+//! started with in `HADOOP_TOKEN_FILE_LOCATION`.
+//!
+//! Objects are read and written in chunks of [CHUNK_SIZE] bytes, so reading, writing or
+//! copying a large file does not hold all of it in memory.
+
+use super::{ObjectStoreFactory, OptionsSource};
+use crate::error::{BallistaError, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
+use futures::future::BoxFuture;
+use futures::stream::{self, BoxStream, StreamExt};
+use futures::{ready, FutureExt};
+use hdfs::hdfs::{get_hdfs_by_full_path, FileStatus, HdfsErr, HdfsFs};
+use object_store::path::Path;
+use object_store::{GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::AsyncWrite;
+use url::Url;
+
+/// The number of bytes read or written to HDFS at once
+pub const CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Creates [HadoopFileSystem]s for URLs such as `hdfs://namenode:8020`.
+///
+/// The stores take no options: the Hadoop client authenticates with the credentials
+/// of the process, which all HDFS stores of the process share, so only the operator
+/// can configure them.
+pub struct HadoopFileSystemFactory;
+
+impl ObjectStoreFactory for HadoopFileSystemFactory {
+    fn create(
+        &self,
+        url: &Url,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<Arc<dyn ObjectStore>> {
+        if source == OptionsSource::Client {
+            return Err(BallistaError::General(format!(
+                "Object store {} can only be configured by the operator",
+                url
+            )));
+        }
+        if let Some(option) = options.keys().next() {
+            return Err(BallistaError::General(format!(
+                "Unknown option {} of object store {}",
+                option, url
+            )));
+        }
+        Ok(Arc::new(HadoopFileSystem::try_new(url.as_str())?))
+    }
+}
+
+/// An HDFS cluster, with object paths relative to its root
+pub struct HadoopFileSystem {
+    hdfs: Arc<HdfsFs>,
+}
+
+impl HadoopFileSystem {
+    /// Connect to the name node of `url`
+    pub fn try_new(url: &str) -> Result<Self> {
+        let hdfs = get_hdfs_by_full_path(url).map_err(|e| {
+            BallistaError::General(format!("Could not connect to HDFS {}: {}", url, e))
+        })?;
+        Ok(Self { hdfs })
+    }
+
+    /// Run blocking libhdfs calls on a thread of their own
+    async fn spawn<F, T>(&self, f: F) -> object_store::Result<T>
+    where
+        F: FnOnce(&HdfsFs) -> std::result::Result<T, HdfsErr> + Send + 'static,
+        T: Send + 'static,
+    {
+        let hdfs = self.hdfs.clone();
+        tokio::task::spawn_blocking(move || f(&hdfs))
+            .await
+            .map_err(|e| to_error(Box::new(e)))?
+            .map_err(|e| to_error(Box::new(e)))
+    }
+
+    /// The metadata of a file listed in this file system
+    fn object_meta(&self, status: &FileStatus) -> object_store::Result<ObjectMeta> {
+        Ok(ObjectMeta {
+            location: self.path(status.name())?,
+            last_modified: Utc.timestamp(status.last_modified(), 0),
+            size: status.len(),
+        })
+    }
+
+    /// The object path of a file name, which libhdfs returns as a full URL
+    fn path(&self, name: &str) -> object_store::Result<Path> {
+        let name = name.strip_prefix(self.hdfs.url()).unwrap_or(name);
+        Path::parse(name.trim_start_matches('/')).map_err(|e| to_error(Box::new(e)))
+    }
+}
+
+/// The absolute HDFS path of an object
+fn hdfs_path(location: &Path) -> String {
+    format!("/{}", location)
+}
+
+fn to_error(source: Box<dyn std::error::Error + Send + Sync>) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "HadoopFileSystem",
+        source,
+    }
+}
+
+/// The ranges of the chunks of a file of `len` bytes
+fn chunks(len: usize) -> Vec<Range<usize>> {
+    (0..len)
+        .step_by(CHUNK_SIZE)
+        .map(|start| start..len.min(start + CHUNK_SIZE))
+        .collect()
+}
+
+/// The hidden file a multipart upload to `path` is written to until it completes,
+/// which listings of tables skip as it does not have the extension of their files
+fn upload_path(path: &str, multipart_id: &str) -> String {
+    match path.rsplit_once('/') {
+        Some((dir, name)) => format!("{}/.{}.{}.upload", dir, name, multipart_id),
+        None => format!(".{}.{}.upload", path, multipart_id),
+    }
+}
+
+fn read_range(
+    hdfs: &HdfsFs,
+    path: &str,
+    range: Range<usize>,
+) -> std::result::Result<Bytes, HdfsErr> {
+    let file = hdfs.open(path)?;
+    let mut buf = vec![0; range.end - range.start];
+    let mut read = 0;
+    while read < buf.len() {
+        let n = file.read_with_pos((range.start + read) as i64, &mut buf[read..])?;
+        if n <= 0 {
+            break;
+        }
+        read += n as usize;
+    }
+    buf.truncate(read);
+    file.close()?;
+    Ok(buf.into())
+}
+
+fn write(hdfs: &HdfsFs, path: &str, bytes: &[u8]) -> std::result::Result<(), HdfsErr> {
+    let file = hdfs.create_with_overwrite(path, true)?;
+    file.write(bytes)?;
+    file.close()?;
+    Ok(())
+}
+
+/// Append `bytes` to the file at `path`, creating it first unless `exists`
+fn append(
+    hdfs: &HdfsFs,
+    path: &str,
+    bytes: &[u8],
+    exists: bool,
+) -> std::result::Result<(), HdfsErr> {
+    let file = if exists {
+        hdfs.append(path)?
+    } else {
+        hdfs.create_with_overwrite(path, true)?
+    };
+    file.write(bytes)?;
+    file.close()?;
+    Ok(())
+}
+
+/// Copy the file at `from` to `to` a chunk at a time
+fn copy(hdfs: &HdfsFs, from: &str, to: &str) -> std::result::Result<(), HdfsErr> {
+    let len = hdfs.get_file_status(from)?.len();
+    let target = hdfs.create_with_overwrite(to, true)?;
+    for range in chunks(len) {
+        target.write(&read_range(hdfs, from, range)?)?;
+    }
+    target.close()?;
+    Ok(())
+}
+
+/// Replace the file at `to` with the file at `from`
+fn replace(hdfs: &HdfsFs, from: &str, to: &str) -> std::result::Result<(), HdfsErr> {
+    if hdfs.exist(to) {
+        hdfs.delete(to, false)?;
+    }
+    hdfs.rename(from, to)?;
+    Ok(())
+}
+
+/// Collect the files below `dir`, which does not need to exist
+fn list_files(
+    hdfs: &HdfsFs,
+    dir: &str,
+    files: &mut Vec<FileStatus>,
+) -> std::result::Result<(), HdfsErr> {
+    if !hdfs.exist(dir) {
+        return Ok(());
+    }
+    for status in hdfs.list_status(dir)? {
+        if status.is_directory() {
+            list_files(hdfs, status.name(), files)?;
+        } else {
+            files.push(status);
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl ObjectStore for HadoopFileSystem {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        let path = hdfs_path(location);
+        self.spawn(move |hdfs| write(hdfs, &path, &bytes)).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        let multipart_id = uuid::Uuid::new_v4().to_string();
+        let path = hdfs_path(location);
+        let writer = MultipartUpload {
+            hdfs: self.hdfs.clone(),
+            upload: upload_path(&path, &multipart_id),
+            path,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+            created: false,
+            completed: false,
+            pending: None,
+        };
+        Ok((multipart_id, Box::new(writer)))
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        let upload = upload_path(&hdfs_path(location), multipart_id);
+        self.spawn(move |hdfs| {
+            if hdfs.exist(&upload) {
+                hdfs.delete(&upload, false)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get(&self, location: &Path) -> object_store::Result<GetResult> {
+        let path = hdfs_path(location);
+        let status = {
+            let path = path.clone();
+            self.spawn(move |hdfs| hdfs.get_file_status(&path)).await?
+        };
+        let hdfs = self.hdfs.clone();
+        let stream = stream::iter(chunks(status.len())).then(move |range| {
+            let (hdfs, path) = (hdfs.clone(), path.clone());
+            async move {
+                tokio::task::spawn_blocking(move || read_range(&hdfs, &path, range))
+                    .await
+                    .map_err(|e| to_error(Box::new(e)))?
+                    .map_err(|e| to_error(Box::new(e)))
+            }
+        });
+        Ok(GetResult::Stream(stream.boxed()))
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        range: Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        let path = hdfs_path(location);
+        self.spawn(move |hdfs| read_range(hdfs, &path, range)).await
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        let path = hdfs_path(location);
+        let status = self.spawn(move |hdfs| hdfs.get_file_status(&path)).await?;
+        self.object_meta(&status)
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        let path = hdfs_path(location);
+        self.spawn(move |hdfs| hdfs.delete(&path, false).map(|_| ()))
+            .await
+    }
+
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        let dir = prefix.map(hdfs_path).unwrap_or_else(|| "/".to_owned());
+        let files = self
+            .spawn(move |hdfs| {
+                let mut files = vec![];
+                list_files(hdfs, &dir, &mut files)?;
+                Ok(files)
+            })
+            .await?;
+        let metas: Vec<_> = files
+            .iter()
+            .map(|status| self.object_meta(status))
+            .collect();
+        Ok(stream::iter(metas).boxed())
+    }
+
+    async fn list_with_delimiter(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<ListResult> {
+        let dir = prefix.map(hdfs_path).unwrap_or_else(|| "/".to_owned());
+        let statuses = self
+            .spawn(move |hdfs| {
+                if hdfs.exist(&dir) {
+                    hdfs.list_status(&dir)
+                } else {
+                    Ok(vec![])
+                }
+            })
+            .await?;
+        let mut common_prefixes = BTreeSet::new();
+        let mut objects = vec![];
+        for status in &statuses {
+            if status.is_directory() {
+                common_prefixes.insert(self.path(status.name())?);
+            } else {
+                objects.push(self.object_meta(status)?);
+            }
+        }
+        Ok(ListResult {
+            next_token: None,
+            common_prefixes: common_prefixes.into_iter().collect(),
+            objects,
+        })
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let (from, to) = (hdfs_path(from), hdfs_path(to));
+        self.spawn(move |hdfs| copy(hdfs, &from, &to)).await
+    }
+
+    async fn copy_if_not_exists(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> object_store::Result<()> {
+        let target = hdfs_path(to);
+        if self.spawn(move |hdfs| Ok(hdfs.exist(&target))).await? {
+            return Err(object_store::Error::AlreadyExists {
+                path: to.to_string(),
+                source: "Destination exists".into(),
+            });
+        }
+        self.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let (from, to) = (hdfs_path(from), hdfs_path(to));
+        self.spawn(move |hdfs| hdfs.rename(&from, &to).map(|_| ()))
+            .await
+    }
+}
+
+/// A multipart upload, appending a chunk at a time to a hidden file which replaces the
+/// object when the upload is shut down
+struct MultipartUpload {
+    hdfs: Arc<HdfsFs>,
+    path: String,
+    upload: String,
+    buffer: Vec<u8>,
+    /// Whether the hidden file was created
+    created: bool,
+    /// Whether the hidden file replaced the object
+    completed: bool,
+    pending: Option<BoxFuture<'static, io::Result<()>>>,
+}
+
+impl MultipartUpload {
+    /// Run `f` on a thread of its own, polled by [Self::poll_pending]
+    fn start<F>(&mut self, f: F)
+    where
+        F: FnOnce(&HdfsFs) -> std::result::Result<(), HdfsErr> + Send + 'static,
+    {
+        let hdfs = self.hdfs.clone();
+        self.pending = Some(
+            async move {
+                tokio::task::spawn_blocking(move || f(&hdfs))
+                    .await
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            }
+            .boxed(),
+        );
+    }
+
+    /// Append the buffered bytes to the hidden file
+    fn start_append(&mut self) {
+        let bytes = std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        let (upload, exists) = (self.upload.clone(), self.created);
+        self.created = true;
+        self.start(move |hdfs| append(hdfs, &upload, &bytes, exists));
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(pending) = self.pending.as_mut() {
+            let result = ready!(pending.poll_unpin(cx));
+            self.pending = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MultipartUpload {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if this.buffer.len() >= CHUNK_SIZE {
+            this.start_append();
+            ready!(this.poll_pending(cx))?;
+        }
+        this.buffer.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if !this.buffer.is_empty() {
+            this.start_append();
+            ready!(this.poll_pending(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        if !this.buffer.is_empty() || !this.created {
+            this.start_append();
+            ready!(this.poll_pending(cx))?;
+        }
+        if !this.completed {
+            this.completed = true;
+            let (upload, path) = (this.upload.clone(), this.path.clone());
+            this.start(move |hdfs| replace(hdfs, &upload, &path));
+            ready!(this.poll_pending(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Display for HadoopFileSystem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HadoopFileSystem({})", self.hdfs.url())
+    }
+}
+
+impl Debug for HadoopFileSystem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_ranges() {
+        assert!(chunks(0).is_empty());
+        assert_eq!(chunks(10), vec![0..10]);
+        assert_eq!(chunks(CHUNK_SIZE), vec![0..CHUNK_SIZE]);
+        assert_eq!(
+            chunks(2 * CHUNK_SIZE + 1),
+            vec![
+                0..CHUNK_SIZE,
+                CHUNK_SIZE..2 * CHUNK_SIZE,
+                2 * CHUNK_SIZE..2 * CHUNK_SIZE + 1
+            ]
+        );
+    }
+
+    #[test]
+    fn upload_paths() {
+        assert_eq!(
+            upload_path("/data/part-0.parquet", "id"),
+            "/data/.part-0.parquet.id.upload"
+        );
+        assert_eq!(
+            upload_path("/part-0.parquet", "id"),
+            "/.part-0.parquet.id.upload"
+        );
+    }
+
+    #[test]
+    fn client_options() {
+        let url = Url::parse("hdfs://namenode:8020").unwrap();
+        let options = HashMap::new();
+        assert!(HadoopFileSystemFactory
+            .create(&url, &options, OptionsSource::Client)
+            .is_err());
+        let options = vec![("delegation_token_file".to_owned(), "/tmp/t".to_owned())]
+            .into_iter()
+            .collect();
+        assert!(HadoopFileSystemFactory
+            .create(&url, &options, OptionsSource::Cluster)
+            .is_err());
+    }
+}
@@ -0,0 +1,290 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Reading object stores on a dedicated I/O runtime.
+//!
+//! Executors run the tasks of jobs on runtimes of their own, whose threads are busy
+//! computing most of the time. An [IoRuntimeObjectStore] runs the requests to the store
+//! it wraps on the I/O runtime of the executor instead, so the responses of the store
+//! are read as soon as they arrive rather than once a compute thread polls the scan
+//! again, and the compute threads never drive the connections of the store.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
+use futures::stream::{BoxStream, StreamExt};
+use futures::SinkExt;
+use object_store::path::Path;
+use object_store::{GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore};
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use tokio::runtime::Handle;
+
+const STORE: &str = "IoRuntime";
+
+/// Chunks of streamed objects and listings buffered ahead of their reader
+const STREAM_BUFFER: usize = 2;
+
+/// An [ObjectStore] running the requests to another store on an I/O runtime
+pub struct IoRuntimeObjectStore {
+    inner: Arc<dyn ObjectStore>,
+    handle: Handle,
+}
+
+impl IoRuntimeObjectStore {
+    /// Run the requests to `inner` on the runtime of `handle`
+    pub fn new(inner: Arc<dyn ObjectStore>, handle: Handle) -> Self {
+        Self { inner, handle }
+    }
+
+    /// Run the request `request` makes to the inner store on the I/O runtime
+    async fn spawn<T, F>(
+        &self,
+        request: impl FnOnce(Arc<dyn ObjectStore>) -> F,
+    ) -> object_store::Result<T>
+    where
+        T: Send + 'static,
+        F: Future<Output = object_store::Result<T>> + Send + 'static,
+    {
+        self.handle
+            .spawn(request(self.inner.clone()))
+            .await
+            .map_err(|e| object_store::Error::Generic {
+                store: STORE,
+                source: Box::new(e),
+            })?
+    }
+
+    /// Poll `stream` on the I/O runtime, sending its items to the returned stream
+    fn forward<T: Send + 'static>(
+        &self,
+        stream: BoxStream<'static, object_store::Result<T>>,
+    ) -> BoxStream<'static, object_store::Result<T>> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        self.handle.spawn(send_all(stream, tx));
+        rx.boxed()
+    }
+}
+
+/// Send the items of `stream` to `tx` until either is done
+async fn send_all<T>(
+    mut stream: BoxStream<'_, object_store::Result<T>>,
+    mut tx: mpsc::Sender<object_store::Result<T>>,
+) {
+    while let Some(item) = stream.next().await {
+        if tx.send(item).await.is_err() {
+            // The reader dropped the stream
+            break;
+        }
+    }
+}
+
+#[async_trait]
+impl ObjectStore for IoRuntimeObjectStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> object_store::Result<()> {
+        let location = location.clone();
+        self.spawn(move |store| async move { store.put(&location, bytes).await })
+            .await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> object_store::Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        // The writer is driven by the task writing the object
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(
+        &self,
+        location: &Path,
+        multipart_id: &MultipartId,
+    ) -> object_store::Result<()> {
+        let (location, multipart_id) = (location.clone(), multipart_id.clone());
+        self.spawn(move |store| async move {
+            store.abort_multipart(&location, &multipart_id).await
+        })
+        .await
+    }
+
+    async fn get(&self, location: &Path) -> object_store::Result<GetResult> {
+        let location = location.clone();
+        let result = self
+            .spawn(move |store| async move { store.get(&location).await })
+            .await?;
+        Ok(match result {
+            GetResult::Stream(stream) => GetResult::Stream(self.forward(stream)),
+            file => file,
+        })
+    }
+
+    async fn get_range(
+        &self,
+        location: &Path,
+        range: Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        let location = location.clone();
+        self.spawn(move |store| async move { store.get_range(&location, range).await })
+            .await
+    }
+
+    async fn head(&self, location: &Path) -> object_store::Result<ObjectMeta> {
+        let location = location.clone();
+        self.spawn(move |store| async move { store.head(&location).await })
+            .await
+    }
+
+    async fn delete(&self, location: &Path) -> object_store::Result<()> {
+        let location = location.clone();
+        self.spawn(move |store| async move { store.delete(&location).await })
+            .await
+    }
+
+    async fn list(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<BoxStream<'_, object_store::Result<ObjectMeta>>> {
+        // The listing borrows the inner store, so it is started and polled by a single
+        // task which reports whether it could be started first
+        let (started_tx, started_rx) = oneshot::channel();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let (store, prefix) = (self.inner.clone(), prefix.cloned());
+        self.handle.spawn(async move {
+            match store.list(prefix.as_ref()).await {
+                Ok(stream) => {
+                    if started_tx.send(Ok(())).is_ok() {
+                        send_all(stream, tx).await;
+                    }
+                }
+                Err(e) => {
+                    let _ = started_tx.send(Err(e));
+                }
+            }
+        });
+        started_rx
+            .await
+            .map_err(|e| object_store::Error::Generic {
+                store: STORE,
+                source: Box::new(e),
+            })??;
+        Ok(rx.boxed())
+    }
+
+    async fn list_with_delimiter(
+        &self,
+        prefix: Option<&Path>,
+    ) -> object_store::Result<ListResult> {
+        let prefix = prefix.cloned();
+        self.spawn(move |store| async move {
+            store.list_with_delimiter(prefix.as_ref()).await
+        })
+        .await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let (from, to) = (from.clone(), to.clone());
+        self.spawn(move |store| async move { store.copy(&from, &to).await })
+            .await
+    }
+
+    async fn rename(&self, from: &Path, to: &Path) -> object_store::Result<()> {
+        let (from, to) = (from.clone(), to.clone());
+        self.spawn(move |store| async move { store.rename(&from, &to).await })
+            .await
+    }
+
+    async fn copy_if_not_exists(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> object_store::Result<()> {
+        let (from, to) = (from.clone(), to.clone());
+        self.spawn(move |store| async move { store.copy_if_not_exists(&from, &to).await })
+            .await
+    }
+
+    async fn rename_if_not_exists(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> object_store::Result<()> {
+        let (from, to) = (from.clone(), to.clone());
+        self.spawn(
+            move |store| async move { store.rename_if_not_exists(&from, &to).await },
+        )
+        .await
+    }
+}
+
+impl Display for IoRuntimeObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IoRuntimeObjectStore({})", self.inner)
+    }
+}
+
+impl Debug for IoRuntimeObjectStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use tokio::runtime::Builder;
+
+    #[test]
+    fn run_requests_on_io_runtime() -> object_store::Result<()> {
+        let io_runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let store = IoRuntimeObjectStore::new(
+            Arc::new(InMemory::new()),
+            io_runtime.handle().clone(),
+        );
+        assert!(store.to_string().starts_with("IoRuntimeObjectStore"));
+
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let path = Path::from("t/a.parquet");
+            store.put(&path, Bytes::from("footer")).await?;
+            assert_eq!(store.get_range(&path, 0..3).await?, Bytes::from("foo"));
+            assert_eq!(
+                store.get(&path).await?.bytes().await?,
+                Bytes::from("footer")
+            );
+            assert_eq!(store.head(&path).await?.size, 6);
+
+            let listed: Vec<ObjectMeta> = store
+                .list(Some(&Path::from("t")))
+                .await?
+                .try_collect()
+                .await?;
+            assert_eq!(listed.len(), 1);
+            store.delete(&path).await?;
+            assert!(store.get(&path).await.is_err());
+            Ok(())
+        })
+    }
+}
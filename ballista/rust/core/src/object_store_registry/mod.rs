@@ -0,0 +1,500 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Object stores of the cluster and of single jobs.
+//!
+//! Stores configured when starting the scheduler and executors are available to all
+//! jobs. Clients can also attach [ObjectStoreCredentials] to a query with the
+//! `ballista.object_store.credentials` setting. The scheduler keeps them out of the
+//! persisted session settings, registers the stores in the session it plans the query
+//! in and sends the credentials to executors along with the tasks of the job only.
+//! Executors run those tasks in a runtime of their own, which has the stores of the job
+//! registered in addition to the local file system and the stores of the cluster.
+//!
+//! Stores are created from their options by the [ObjectStoreFactory] registered for the
+//! scheme of their URL. [ObjectStoreFactories::builtin] has factories for the stores of
+//! the major cloud providers, depending on the enabled features.
+//!
+//! The scheduler can cache the metadata it reads from the stores of the cluster and the
+//! local file system while planning queries, see [cache]. Executors run the requests to
+//! the stores on their I/O runtime, see [io_runtime].
+
+pub mod cache;
+#[cfg(any(feature = "s3", feature = "gcs", feature = "azure"))]
+pub mod cloud;
+#[cfg(feature = "hdfs")]
+pub mod hdfs;
+pub mod io_runtime;
+
+use crate::error::{BallistaError, Result};
+use crate::serde::protobuf;
+use cache::{CachingObjectStore, MetadataCacheOptions};
+use datafusion::datasource::object_store::ObjectStoreRegistry;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use io_runtime::IoRuntimeObjectStore;
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use url::Url;
+
+/// Options of the object stores a job reads from, by the URL of the store such as
+/// `s3://bucket`. Which options are supported depends on the [ObjectStoreFactory] of the
+/// scheme.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct ObjectStoreCredentials {
+    stores: HashMap<String, HashMap<String, String>>,
+}
+
+impl ObjectStoreCredentials {
+    pub fn new(stores: HashMap<String, HashMap<String, String>>) -> Self {
+        Self { stores }
+    }
+
+    /// Parse credentials given as a JSON object of option objects by store URL, e.g.
+    /// `{"s3://bucket": {"access_key_id": "...", "secret_access_key": "..."}}`
+    pub fn from_json(json: &str) -> Result<Self> {
+        let stores = serde_json::from_str(json).map_err(|e| {
+            BallistaError::General(format!("Invalid object store credentials: {}", e))
+        })?;
+        Ok(Self::new(stores))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stores.is_empty()
+    }
+
+    pub fn to_proto(&self) -> Vec<protobuf::ObjectStoreOptions> {
+        self.stores
+            .iter()
+            .map(|(url, options)| protobuf::ObjectStoreOptions {
+                url: url.clone(),
+                options: options
+                    .iter()
+                    .map(|(key, value)| protobuf::KeyValuePair {
+                        key: key.clone(),
+                        value: value.clone(),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    pub fn from_proto(stores: &[protobuf::ObjectStoreOptions]) -> Self {
+        Self::new(
+            stores
+                .iter()
+                .map(|store| {
+                    let options = store
+                        .options
+                        .iter()
+                        .map(|kv| (kv.key.clone(), kv.value.clone()))
+                        .collect();
+                    (store.url.clone(), options)
+                })
+                .collect(),
+        )
+    }
+}
+
+impl Debug for ObjectStoreCredentials {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Only show which stores are configured, never the secrets
+        f.debug_set().entries(self.stores.keys()).finish()
+    }
+}
+
+/// Who configured the options an object store is created from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionsSource {
+    /// The operator, for the stores of the cluster, which may use the credentials of
+    /// the machine they run on and its local files
+    Cluster,
+    /// A client, for the stores of its jobs, which may only use the credentials the
+    /// client passed
+    Client,
+}
+
+/// Creates object stores from the options given by the operator or by clients
+pub trait ObjectStoreFactory: Send + Sync {
+    fn create(
+        &self,
+        url: &Url,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<Arc<dyn ObjectStore>>;
+}
+
+/// The [ObjectStoreFactory]s of a scheduler or executor, by URL scheme, and the stores
+/// of the cluster created with them
+#[derive(Clone, Default)]
+pub struct ObjectStoreFactories {
+    factories: HashMap<String, Arc<dyn ObjectStoreFactory>>,
+    /// Scheme, host and store of every store of the cluster
+    cluster_stores: Vec<(String, String, Arc<dyn ObjectStore>)>,
+    /// Options of the metadata caches of the stores of the cluster, if enabled
+    metadata_cache: Option<MetadataCacheOptions>,
+    /// Scheme, host and metadata cache of every cached store of the cluster
+    metadata_caches: Vec<(String, String, Arc<CachingObjectStore>)>,
+    /// Runtime the requests to the created stores run on, if not the calling one
+    io_runtime: Option<Handle>,
+}
+
+impl ObjectStoreFactories {
+    /// The factories of the object stores built into Ballista: `s3`, `gs`, `az` and
+    /// `hdfs`
+    pub fn builtin() -> Self {
+        #[allow(unused_mut)]
+        let mut factories = Self::default();
+        #[cfg(feature = "s3")]
+        factories.register("s3", Arc::new(cloud::AmazonS3Factory));
+        #[cfg(feature = "gcs")]
+        factories.register("gs", Arc::new(cloud::GoogleCloudStorageFactory));
+        #[cfg(feature = "azure")]
+        factories.register("az", Arc::new(cloud::MicrosoftAzureFactory));
+        #[cfg(feature = "hdfs")]
+        factories.register("hdfs", Arc::new(hdfs::HadoopFileSystemFactory));
+        factories
+    }
+
+    /// Create the stores of URLs with the given scheme with `factory`
+    pub fn register(&mut self, scheme: &str, factory: Arc<dyn ObjectStoreFactory>) {
+        self.factories.insert(scheme.to_owned(), factory);
+    }
+
+    /// Run the requests to the stores created from now on on the runtime of `handle`,
+    /// so must be called before adding the stores of the cluster
+    pub fn run_io_on(&mut self, handle: Handle) {
+        self.io_runtime = Some(handle);
+    }
+
+    /// Create the stores configured in `config`, which are registered along with the
+    /// stores of every job
+    pub fn add_cluster_stores(&mut self, config: &ObjectStoreCredentials) -> Result<()> {
+        for (url, options) in &config.stores {
+            let (url, store) = self.create_store(url, options, OptionsSource::Cluster)?;
+            self.add_cluster_store(
+                url.scheme().to_owned(),
+                url.host_str().unwrap_or_default().to_owned(),
+                store,
+            );
+        }
+        Ok(())
+    }
+
+    /// Cache the listings, object metadata and Parquet footers read from the stores of
+    /// the cluster and the local file system, so repeated queries over the same tables
+    /// are planned without reading them again. Stores configured by clients for a
+    /// single job are not cached.
+    pub fn cache_metadata(&mut self, options: MetadataCacheOptions) {
+        if self.metadata_cache.is_some() {
+            return;
+        }
+        self.metadata_cache = Some(options);
+        let stores = std::mem::take(&mut self.cluster_stores);
+        for (scheme, host, store) in stores {
+            self.add_cluster_store(scheme, host, store);
+        }
+        if !self
+            .cluster_stores
+            .iter()
+            .any(|(scheme, _, _)| scheme == "file")
+        {
+            self.add_cluster_store(
+                "file".to_owned(),
+                String::new(),
+                Arc::new(LocalFileSystem::new()),
+            );
+        }
+    }
+
+    /// The store and path of `location`, which is either the URL of a directory in one of
+    /// the stores of the cluster, such as `s3://bucket/events`, or a local directory,
+    /// which is created if it does not exist
+    pub fn store_at(&self, location: &str) -> Result<(Arc<dyn ObjectStore>, Path)> {
+        let url = match Url::parse(location) {
+            // A single letter scheme is the drive of a Windows path
+            Ok(url) if url.scheme().len() > 1 && url.scheme() != "file" => url,
+            Ok(url) if url.scheme() == "file" => {
+                return self.store_at(url.path());
+            }
+            _ => {
+                std::fs::create_dir_all(location)?;
+                let store = LocalFileSystem::new_with_prefix(location).map_err(|e| {
+                    BallistaError::General(format!(
+                        "Could not open directory {}: {}",
+                        location, e
+                    ))
+                })?;
+                return Ok((Arc::new(store), Path::default()));
+            }
+        };
+        let store = self
+            .cluster_stores
+            .iter()
+            .find(|(scheme, host, _)| {
+                scheme == url.scheme() && host == url.host_str().unwrap_or_default()
+            })
+            .map(|(_, _, store)| store.clone())
+            .ok_or_else(|| {
+                BallistaError::General(format!(
+                    "{} is not in one of the object stores of the cluster",
+                    location
+                ))
+            })?;
+        let path = Path::parse(url.path()).map_err(|e| {
+            BallistaError::General(format!("Invalid path {}: {}", location, e))
+        })?;
+        Ok((store, path))
+    }
+
+    /// Drop the cached metadata of the objects below `url`, such as `s3://bucket/table`,
+    /// or of all objects if `url` is `None`
+    pub fn invalidate_metadata_cache(&self, url: Option<&str>) -> Result<()> {
+        let url = match url {
+            Some(url) => Some(Url::parse(url).map_err(|e| {
+                BallistaError::General(format!("Invalid object store URL {}: {}", url, e))
+            })?),
+            None => None,
+        };
+        for (scheme, host, cache) in &self.metadata_caches {
+            match &url {
+                Some(url)
+                    if url.scheme() == scheme
+                        && url.host_str().unwrap_or_default() == host =>
+                {
+                    let prefix = Path::parse(url.path()).map_err(|e| {
+                        BallistaError::General(format!("Invalid path {}: {}", url, e))
+                    })?;
+                    cache.invalidate(Some(&prefix));
+                }
+                Some(_) => {}
+                None => cache.invalidate(None),
+            }
+        }
+        Ok(())
+    }
+
+    fn add_cluster_store(
+        &mut self,
+        scheme: String,
+        host: String,
+        store: Arc<dyn ObjectStore>,
+    ) {
+        let store: Arc<dyn ObjectStore> = match self.metadata_cache {
+            Some(options) => {
+                let cache = Arc::new(CachingObjectStore::new(store, options));
+                self.metadata_caches
+                    .push((scheme.clone(), host.clone(), cache.clone()));
+                cache
+            }
+            None => store,
+        };
+        self.cluster_stores.push((scheme, host, store));
+    }
+
+    /// Register the stores of the cluster and create the stores configured in
+    /// `credentials` and register them in `runtime`
+    pub fn register_stores(
+        &self,
+        runtime: &RuntimeEnv,
+        credentials: &ObjectStoreCredentials,
+    ) -> Result<()> {
+        for (scheme, host, store) in &self.cluster_stores {
+            runtime.register_object_store(scheme, host, store.clone());
+        }
+        for (url, options) in &credentials.stores {
+            let (url, store) = self.create_store(url, options, OptionsSource::Client)?;
+            runtime.register_object_store(
+                url.scheme(),
+                url.host_str().unwrap_or_default(),
+                store,
+            );
+        }
+        Ok(())
+    }
+
+    fn create_store(
+        &self,
+        url: &str,
+        options: &HashMap<String, String>,
+        source: OptionsSource,
+    ) -> Result<(Url, Arc<dyn ObjectStore>)> {
+        let url = Url::parse(url).map_err(|e| {
+            BallistaError::General(format!("Invalid object store URL {}: {}", url, e))
+        })?;
+        let factory = self.factories.get(url.scheme()).ok_or_else(|| {
+            BallistaError::General(format!(
+                "No object store factory for scheme {}",
+                url.scheme()
+            ))
+        })?;
+        let store = factory.create(&url, options, source)?;
+        let store: Arc<dyn ObjectStore> = match &self.io_runtime {
+            Some(handle) => Arc::new(IoRuntimeObjectStore::new(store, handle.clone())),
+            None => store,
+        };
+        Ok((url, store))
+    }
+
+    /// A runtime to run the tasks of a job with the given credentials in. It shares the
+    /// memory and disk managers of `runtime`, but has its own object stores so the stores
+    /// of the job are not visible to other jobs. `runtime` is returned as is if there
+    /// are no credentials, so it must have the stores of the cluster registered.
+    pub fn job_runtime(
+        &self,
+        runtime: &Arc<RuntimeEnv>,
+        credentials: &ObjectStoreCredentials,
+    ) -> Result<Arc<RuntimeEnv>> {
+        if credentials.is_empty() {
+            return Ok(runtime.clone());
+        }
+        let job_runtime = RuntimeEnv {
+            memory_manager: runtime.memory_manager.clone(),
+            disk_manager: runtime.disk_manager.clone(),
+            object_store_registry: Arc::new(ObjectStoreRegistry::new()),
+        };
+        self.register_stores(&job_runtime, credentials)?;
+        Ok(Arc::new(job_runtime))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::datasource::object_store::ObjectStoreUrl;
+    use datafusion::execution::runtime_env::RuntimeConfig;
+    use object_store::memory::InMemory;
+
+    struct InMemoryFactory;
+
+    impl ObjectStoreFactory for InMemoryFactory {
+        fn create(
+            &self,
+            _url: &Url,
+            options: &HashMap<String, String>,
+            _source: OptionsSource,
+        ) -> Result<Arc<dyn ObjectStore>> {
+            match options.get("token").map(String::as_str) {
+                Some("secret") => Ok(Arc::new(InMemory::new())),
+                _ => Err(BallistaError::General("Invalid token".to_owned())),
+            }
+        }
+    }
+
+    #[test]
+    fn job_scoped_stores() -> Result<()> {
+        let credentials = ObjectStoreCredentials::from_json(
+            r#"{"mem://bucket": {"token": "secret"}}"#,
+        )?;
+        assert_eq!(
+            ObjectStoreCredentials::from_proto(&credentials.to_proto()),
+            credentials
+        );
+        assert_eq!(format!("{:?}", credentials), r#"{"mem://bucket"}"#);
+
+        let mut factories = ObjectStoreFactories::default();
+        let runtime = Arc::new(RuntimeEnv::new(RuntimeConfig::new())?);
+        assert!(factories.job_runtime(&runtime, &credentials).is_err());
+
+        factories.register("mem", Arc::new(InMemoryFactory));
+        let job_runtime = factories.job_runtime(&runtime, &credentials)?;
+        let url = ObjectStoreUrl::parse("mem://bucket")?;
+        assert!(job_runtime.object_store(&url).is_ok());
+        // The store is not visible to other jobs
+        assert!(runtime.object_store(&url).is_err());
+
+        let credentials =
+            ObjectStoreCredentials::from_json(r#"{"mem://bucket": {"token": "wrong"}}"#)?;
+        assert!(factories.job_runtime(&runtime, &credentials).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn cluster_stores() -> Result<()> {
+        let mut factories = ObjectStoreFactories::default();
+        factories.register("mem", Arc::new(InMemoryFactory));
+        factories.add_cluster_stores(&ObjectStoreCredentials::from_json(
+            r#"{"mem://shared": {"token": "secret"}}"#,
+        )?)?;
+        assert!(factories
+            .add_cluster_stores(&ObjectStoreCredentials::from_json(
+                r#"{"mem://other": {"token": "wrong"}}"#
+            )?)
+            .is_err());
+
+        let runtime = Arc::new(RuntimeEnv::new(RuntimeConfig::new())?);
+        factories.register_stores(&runtime, &ObjectStoreCredentials::default())?;
+        let credentials = ObjectStoreCredentials::from_json(
+            r#"{"mem://bucket": {"token": "secret"}}"#,
+        )?;
+        let job_runtime = factories.job_runtime(&runtime, &credentials)?;
+        for runtime in [&runtime, &job_runtime] {
+            assert!(runtime
+                .object_store(&ObjectStoreUrl::parse("mem://shared")?)
+                .is_ok());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn store_at() -> Result<()> {
+        let mut factories = ObjectStoreFactories::default();
+        factories.register("mem", Arc::new(InMemoryFactory));
+        factories.add_cluster_stores(&ObjectStoreCredentials::from_json(
+            r#"{"mem://shared": {"token": "secret"}}"#,
+        )?)?;
+
+        let (_, path) = factories.store_at("mem://shared/history/events")?;
+        assert_eq!(path, Path::from("history/events"));
+        assert!(factories.store_at("mem://other/history").is_err());
+
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("events");
+        let (_, path) = factories.store_at(local.to_str().unwrap())?;
+        assert_eq!(path, Path::default());
+        assert!(local.is_dir());
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_caches() -> Result<()> {
+        let mut factories = ObjectStoreFactories::default();
+        factories.register("mem", Arc::new(InMemoryFactory));
+        factories.add_cluster_stores(&ObjectStoreCredentials::from_json(
+            r#"{"mem://shared": {"token": "secret"}}"#,
+        )?)?;
+        factories.cache_metadata(MetadataCacheOptions {
+            ttl: std::time::Duration::from_secs(60),
+            max_bytes: 1024 * 1024,
+        });
+
+        let runtime = Arc::new(RuntimeEnv::new(RuntimeConfig::new())?);
+        factories.register_stores(&runtime, &ObjectStoreCredentials::default())?;
+        for url in ["mem://shared", "file://"] {
+            let store = runtime.object_store(&ObjectStoreUrl::parse(url)?)?;
+            assert!(store.to_string().starts_with("CachingObjectStore"));
+        }
+        factories.invalidate_metadata_cache(Some("mem://shared/table"))?;
+        factories.invalidate_metadata_cache(None)?;
+        assert!(factories.invalidate_metadata_cache(Some("table")).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Versioning of the task protocol between the scheduler and the executors.
+//!
+//! The scheduler and the executors exchange the version of the protocol they speak and
+//! the optional features, or capabilities, they support when executors register. Peers
+//! at most one version apart work together, so that a cluster can be upgraded one
+//! process at a time, and the scheduler only sends an executor the fields of a task
+//! that it supports. Peers which predate versioning speak version 0, without
+//! capabilities.
+
+use crate::error::{BallistaError, Result};
+use crate::serde::protobuf;
+use serde::Serialize;
+use std::collections::BTreeSet;
+
+/// Version of the task protocol of this build
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features of the task protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Tasks carry the trace context of their stage
+    TraceContext,
+    /// Tasks carry the key encrypting the shuffle files of their job
+    ShuffleEncryption,
+    /// Tasks carry the object stores configured by the client for their job
+    ObjectStores,
+    /// The output of the jobs caching tables is kept until the scheduler removes it
+    CachedTables,
+    /// Tasks writing Parquet files name them after their attempt
+    WriteAttempts,
+    /// Tasks copy their shuffle partitions to another executor through Flight DoPut
+    ShuffleReplication,
+    /// Plans may contain the operators building and applying the Bloom filters of joins
+    BloomFilters,
+}
+
+impl Capability {
+    /// The capabilities of this build
+    pub const ALL: [Capability; 7] = [
+        Capability::TraceContext,
+        Capability::ShuffleEncryption,
+        Capability::ObjectStores,
+        Capability::CachedTables,
+        Capability::WriteAttempts,
+        Capability::ShuffleReplication,
+        Capability::BloomFilters,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::TraceContext => "trace_context",
+            Capability::ShuffleEncryption => "shuffle_encryption",
+            Capability::ObjectStores => "object_stores",
+            Capability::CachedTables => "cached_tables",
+            Capability::WriteAttempts => "write_attempts",
+            Capability::ShuffleReplication => "shuffle_replication",
+            Capability::BloomFilters => "bloom_filters",
+        }
+    }
+}
+
+/// Protocol version and capabilities of a scheduler or an executor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProtocolInfo {
+    pub version: u32,
+    /// Names of the capabilities, which may include capabilities unknown to this build
+    pub capabilities: BTreeSet<String>,
+}
+
+impl ProtocolInfo {
+    /// The protocol of this build
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            capabilities: Capability::ALL
+                .iter()
+                .map(|capability| capability.as_str().to_owned())
+                .collect(),
+        }
+    }
+
+    /// The protocol of peers which predate protocol versioning
+    pub fn legacy() -> Self {
+        Self {
+            version: 0,
+            capabilities: BTreeSet::new(),
+        }
+    }
+
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(capability.as_str())
+    }
+
+    /// Check that a peer speaking this protocol can work with this build
+    pub fn check_compatible(&self) -> Result<()> {
+        check_compatible_version(self.version)
+    }
+}
+
+impl Default for ProtocolInfo {
+    fn default() -> Self {
+        Self::current()
+    }
+}
+
+/// Check that a peer speaking protocol `version` can work with this build
+pub fn check_compatible_version(version: u32) -> Result<()> {
+    if version.saturating_add(1) >= PROTOCOL_VERSION && version <= PROTOCOL_VERSION + 1 {
+        Ok(())
+    } else {
+        Err(BallistaError::General(format!(
+            "Protocol version {} is incompatible with version {}, upgrade the \
+            scheduler and the executors one minor version at a time",
+            version, PROTOCOL_VERSION
+        )))
+    }
+}
+
+impl From<Option<protobuf::ProtocolInfo>> for ProtocolInfo {
+    fn from(protocol: Option<protobuf::ProtocolInfo>) -> Self {
+        match protocol {
+            Some(protocol) => Self {
+                version: protocol.version,
+                capabilities: protocol.capabilities.into_iter().collect(),
+            },
+            None => Self::legacy(),
+        }
+    }
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<protobuf::ProtocolInfo> for ProtocolInfo {
+    fn into(self) -> protobuf::ProtocolInfo {
+        protobuf::ProtocolInfo {
+            version: self.version,
+            capabilities: self.capabilities.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compatible_versions() {
+        assert!(check_compatible_version(PROTOCOL_VERSION - 1).is_ok());
+        assert!(check_compatible_version(PROTOCOL_VERSION).is_ok());
+        assert!(check_compatible_version(PROTOCOL_VERSION + 1).is_ok());
+        assert!(check_compatible_version(PROTOCOL_VERSION + 2).is_err());
+    }
+
+    #[test]
+    fn legacy_protocol() {
+        let protocol = ProtocolInfo::from(None);
+        assert_eq!(protocol, ProtocolInfo::legacy());
+        assert!(protocol.check_compatible().is_ok());
+        assert!(!protocol.supports(Capability::TraceContext));
+
+        let current = ProtocolInfo::from(Some(ProtocolInfo::current().into()));
+        assert!(Capability::ALL
+            .iter()
+            .all(|capability| current.supports(*capability)));
+    }
+}
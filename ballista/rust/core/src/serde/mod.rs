@@ -18,14 +18,18 @@
 //! This crate contains code generated from the Ballista Protocol Buffer Definition as well
 //! as convenience code for interacting with the generated code.
 
+use crate::table_format::ExternalTableScan;
 use crate::{error::BallistaError, serde::scheduler::Action as BallistaAction};
+use datafusion::arrow::datatypes::Schema;
+use datafusion::error::DataFusionError;
+use datafusion::execution::context::SessionContext;
 use datafusion::execution::runtime_env::RuntimeEnv;
-use datafusion::logical_plan::{FunctionRegistry, Operator};
+use datafusion::logical_plan::plan::Extension;
+use datafusion::logical_plan::{FunctionRegistry, LogicalPlan, Operator};
 use datafusion::physical_plan::join_utils::JoinSide;
 use datafusion::physical_plan::ExecutionPlan;
-use datafusion_proto::logical_plan::{
-    AsLogicalPlan, DefaultLogicalExtensionCodec, LogicalExtensionCodec,
-};
+use datafusion_proto::from_proto::parse_expr;
+use datafusion_proto::logical_plan::{AsLogicalPlan, LogicalExtensionCodec};
 use prost::bytes::BufMut;
 use prost::Message;
 use std::fmt::Debug;
@@ -37,6 +41,10 @@ use std::{convert::TryInto, io::Cursor};
 #[allow(clippy::all)]
 pub mod protobuf {
     include!(concat!(env!("OUT_DIR"), "/ballista.protobuf.rs"));
+
+    /// The encoded descriptors of the Ballista protobuf files, for gRPC reflection
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/ballista_descriptor.bin"));
 }
 
 pub mod physical_plan;
@@ -120,6 +128,93 @@ impl PhysicalExtensionCodec for DefaultPhysicalExtensionCodec {
     }
 }
 
+/// Codec for the logical plan extensions of Ballista, which are [ExternalTableScan]s
+#[derive(Debug, Clone, Default)]
+pub struct BallistaLogicalExtensionCodec {}
+
+impl LogicalExtensionCodec for BallistaLogicalExtensionCodec {
+    fn try_decode(
+        &self,
+        buf: &[u8],
+        _inputs: &[LogicalPlan],
+        ctx: &SessionContext,
+    ) -> Result<Extension, DataFusionError> {
+        let scan = protobuf::ExternalTableScanNode::decode(buf).map_err(|e| {
+            DataFusionError::Internal(format!("failed to decode logical plan: {:?}", e))
+        })?;
+        let schema: Schema = scan
+            .schema
+            .as_ref()
+            .ok_or_else(|| {
+                DataFusionError::Internal(
+                    "Missing schema of ExternalTableScanNode".to_string(),
+                )
+            })?
+            .try_into()?;
+        let node = ExternalTableScan::try_new(
+            scan.table_name,
+            scan.format,
+            scan.location,
+            scan.options
+                .into_iter()
+                .map(|kv| (kv.key, kv.value))
+                .collect(),
+            Arc::new(schema),
+            scan.projection
+                .map(|p| p.columns.into_iter().map(|c| c as usize).collect()),
+            scan.filters
+                .iter()
+                .map(|expr| parse_expr(expr, ctx))
+                .collect::<Result<_, _>>()?,
+        )?;
+        Ok(Extension {
+            node: Arc::new(node),
+        })
+    }
+
+    fn try_encode(
+        &self,
+        node: &Extension,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), DataFusionError> {
+        let scan = node
+            .node
+            .as_any()
+            .downcast_ref::<ExternalTableScan>()
+            .ok_or_else(|| {
+                DataFusionError::NotImplemented(format!(
+                    "Unsupported logical plan extension {:?}",
+                    node.node
+                ))
+            })?;
+        let proto = protobuf::ExternalTableScanNode {
+            table_name: scan.table_name.clone(),
+            format: scan.format.clone(),
+            location: scan.location.clone(),
+            options: scan
+                .options
+                .iter()
+                .map(|(key, value)| protobuf::KeyValuePair {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect(),
+            schema: Some(scan.table_schema.as_ref().into()),
+            projection: scan.projection.as_ref().map(|p| protobuf::ScanProjection {
+                columns: p.iter().map(|c| *c as u32).collect(),
+            }),
+            filters: scan
+                .filters
+                .iter()
+                .map(|expr| expr.try_into())
+                .collect::<Result<_, _>>()?,
+        };
+        proto.encode(buf).map_err(|e| {
+            DataFusionError::Internal(format!("failed to encode logical plan: {:?}", e))
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BallistaCodec<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> {
     logical_extension_codec: Arc<dyn LogicalExtensionCodec>,
@@ -133,7 +228,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> Default
 {
     fn default() -> Self {
         Self {
-            logical_extension_codec: Arc::new(DefaultLogicalExtensionCodec {}),
+            logical_extension_codec: Arc::new(BallistaLogicalExtensionCodec {}),
             physical_extension_codec: Arc::new(DefaultPhysicalExtensionCodec {}),
             logical_plan_repr: PhantomData,
             physical_plan_repr: PhantomData,
@@ -167,7 +262,9 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> BallistaCodec<T,
 macro_rules! convert_required {
     ($PB:expr) => {{
         if let Some(field) = $PB.as_ref() {
-            Ok(field.try_into().map_err(|e| proto_error("Failed to convert!"))?)
+            Ok(field
+                .try_into()
+                .map_err(|e| proto_error("Failed to convert!"))?)
         } else {
             Err(proto_error("Missing required field in protobuf"))
         }
@@ -22,6 +22,7 @@ use std::ops::Deref;
 use std::sync::Arc;
 
 use chrono::{TimeZone, Utc};
+use datafusion::arrow::compute::SortOptions;
 use datafusion::arrow::datatypes::Schema;
 use datafusion::common::ScalarValue;
 use datafusion::datasource::listing::{FileRange, PartitionedFile};
@@ -34,7 +35,8 @@ use datafusion::physical_plan::file_format::FileScanConfig;
 use datafusion::physical_plan::{
     expressions::{
         BinaryExpr, CaseExpr, CastExpr, Column, InListExpr, IsNotNullExpr, IsNullExpr,
-        Literal, NegativeExpr, NotExpr, TryCastExpr, DEFAULT_DATAFUSION_CAST_OPTIONS,
+        Literal, NegativeExpr, NotExpr, PhysicalSortExpr, TryCastExpr,
+        DEFAULT_DATAFUSION_CAST_OPTIONS,
     },
     functions, Partitioning,
 };
@@ -89,19 +91,22 @@ pub(crate) fn parse_physical_expr(
             )?,
         )),
         ExprType::AggregateExpr(_) => {
-            return Err(BallistaError::General(
-                "Cannot convert aggregate expr node to physical expression".to_owned(),
-            ));
+            return Err(BallistaError::General(format!(
+                "Cannot convert aggregate expr node to physical expression: {:?}",
+                proto
+            )));
         }
         ExprType::WindowExpr(_) => {
-            return Err(BallistaError::General(
-                "Cannot convert window expr node to physical expression".to_owned(),
-            ));
+            return Err(BallistaError::General(format!(
+                "Cannot convert window expr node to physical expression: {:?}",
+                proto
+            )));
         }
         ExprType::Sort(_) => {
-            return Err(BallistaError::General(
-                "Cannot convert sort expr node to physical expression".to_owned(),
-            ));
+            return Err(BallistaError::General(format!(
+                "Cannot convert sort expr node to physical expression: {:?}",
+                proto
+            )));
         }
         ExprType::IsNullExpr(e) => Arc::new(IsNullExpr::new(
             parse_required_physical_box_expr(&e.expr, registry, "expr", input_schema)?,
@@ -243,6 +248,25 @@ fn parse_required_physical_expr(
         })
 }
 
+pub(crate) fn parse_physical_sort_expr(
+    proto: &protobuf::PhysicalSortExprNode,
+    registry: &dyn FunctionRegistry,
+    input_schema: &Schema,
+) -> Result<PhysicalSortExpr, BallistaError> {
+    Ok(PhysicalSortExpr {
+        expr: parse_required_physical_box_expr(
+            &proto.expr,
+            registry,
+            "expr",
+            input_schema,
+        )?,
+        options: SortOptions {
+            descending: !proto.asc,
+            nulls_first: proto.nulls_first,
+        },
+    })
+}
+
 impl TryFrom<&protobuf::physical_window_expr_node::WindowFunction> for WindowFunction {
     type Error = BallistaError;
 
@@ -402,7 +426,7 @@ impl TryInto<FileScanConfig> for &protobuf::FileScanExecConf {
             statistics,
             projection,
             limit: self.limit.as_ref().map(|sl| sl.limit as usize),
-            table_partition_cols: vec![],
+            table_partition_cols: self.table_partition_cols.clone(),
         })
     }
 }
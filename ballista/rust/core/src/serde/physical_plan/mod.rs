@@ -15,7 +15,8 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
 use std::sync::Arc;
 
 use prost::bytes::BufMut;
@@ -37,7 +38,7 @@ use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::explain::ExplainExec;
 use datafusion::physical_plan::expressions::{Column, PhysicalSortExpr};
 use datafusion::physical_plan::file_format::{
-    AvroExec, CsvExec, FileScanConfig, ParquetExec,
+    AvroExec, CsvExec, FileScanConfig, NdJsonExec, ParquetExec,
 };
 use datafusion::physical_plan::filter::FilterExec;
 use datafusion::physical_plan::hash_join::{HashJoinExec, PartitionMode};
@@ -49,16 +50,25 @@ use datafusion::physical_plan::sorts::sort::SortExec;
 use datafusion::physical_plan::union::UnionExec;
 use datafusion::physical_plan::windows::{create_window_expr, WindowAggExec};
 use datafusion::physical_plan::{
-    AggregateExpr, ExecutionPlan, Partitioning, PhysicalExpr, WindowExpr,
+    AggregateExpr, DisplayFormatType, ExecutionPlan, Partitioning, PhysicalExpr,
+    WindowExpr,
 };
 use datafusion_proto::from_proto::parse_expr;
 
 use crate::error::BallistaError;
+#[cfg(feature = "kafka")]
+use crate::execution_plans::KafkaScanExec;
+#[cfg(feature = "postgres")]
+use crate::execution_plans::PostgresScanExec;
 use crate::execution_plans::{
-    ShuffleReaderExec, ShuffleWriterExec, UnresolvedShuffleExec,
+    BloomFilter, BloomFilterBuildExec, BloomFilterExec, NodeLocalScanExec,
+    ParquetSinkExec, ShuffleReaderExec, ShuffleWriterExec, TopKExec,
+    UnresolvedShuffleExec,
 };
+#[cfg(feature = "kafka")]
+use crate::kafka::KafkaOffsetRange;
 use crate::serde::physical_plan::from_proto::{
-    parse_physical_expr, parse_protobuf_hash_partitioning,
+    parse_physical_expr, parse_physical_sort_expr, parse_protobuf_hash_partitioning,
 };
 use crate::serde::protobuf::physical_expr_node::ExprType;
 use crate::serde::protobuf::physical_plan_node::PhysicalPlanType;
@@ -99,6 +109,30 @@ impl AsExecutionPlan for PhysicalPlanNode {
         registry: &dyn FunctionRegistry,
         runtime: &RuntimeEnv,
         extension_codec: &dyn PhysicalExtensionCodec,
+    ) -> Result<Arc<dyn ExecutionPlan>, BallistaError> {
+        self.decode_plan_node(registry, runtime, extension_codec)
+            .map_err(|e| e.with_plan_context(self.plan_node_name()))
+    }
+
+    fn try_from_physical_plan(
+        plan: Arc<dyn ExecutionPlan>,
+        extension_codec: &dyn PhysicalExtensionCodec,
+    ) -> Result<Self, BallistaError>
+    where
+        Self: Sized,
+    {
+        let description = describe_plan_node(plan.as_ref());
+        Self::encode_plan_node(plan, extension_codec)
+            .map_err(|e| e.with_plan_context(description))
+    }
+}
+
+impl PhysicalPlanNode {
+    fn decode_plan_node(
+        &self,
+        registry: &dyn FunctionRegistry,
+        runtime: &RuntimeEnv,
+        extension_codec: &dyn PhysicalExtensionCodec,
     ) -> Result<Arc<dyn ExecutionPlan>, BallistaError> {
         let plan = self.physical_plan_type.as_ref().ok_or_else(|| {
             proto_error(format!(
@@ -165,8 +199,11 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     .as_ref()
                     .map(|expr| parse_expr(expr, registry))
                     .transpose()?;
+                let base_conf = scan.base_conf.as_ref().ok_or_else(|| {
+                    proto_error("Missing required field base_conf in ParquetScanExecNode")
+                })?;
                 Ok(Arc::new(ParquetExec::new(
-                    decode_scan_config(scan.base_conf.as_ref().unwrap())?,
+                    decode_scan_config(base_conf)?,
                     predicate,
                     None,
                 )))
@@ -174,6 +211,122 @@ impl AsExecutionPlan for PhysicalPlanNode {
             PhysicalPlanType::AvroScan(scan) => Ok(Arc::new(AvroExec::new(
                 decode_scan_config(scan.base_conf.as_ref().unwrap())?,
             ))),
+            PhysicalPlanType::JsonScan(scan) => {
+                let base_conf = scan.base_conf.as_ref().ok_or_else(|| {
+                    proto_error("Missing required field base_conf in JsonScanExecNode")
+                })?;
+                Ok(Arc::new(NdJsonExec::new(decode_scan_config(base_conf)?)))
+            }
+            #[cfg(feature = "postgres")]
+            PhysicalPlanType::PostgresScan(scan) => {
+                let schema = Arc::new(convert_required!(scan.schema)?);
+                Ok(Arc::new(PostgresScanExec::new(
+                    scan.database.clone(),
+                    scan.table.clone(),
+                    scan.columns.clone(),
+                    schema,
+                    scan.predicates.clone(),
+                    scan.limit.as_ref().map(|limit| limit.limit as usize),
+                )))
+            }
+            #[cfg(not(feature = "postgres"))]
+            PhysicalPlanType::PostgresScan(_) => Err(BallistaError::NotImplemented(
+                "Scanning PostgreSQL tables requires the postgres feature".to_owned(),
+            )),
+            #[cfg(feature = "kafka")]
+            PhysicalPlanType::KafkaScan(scan) => {
+                let ranges = scan
+                    .ranges
+                    .iter()
+                    .map(|range| KafkaOffsetRange {
+                        partition: range.partition,
+                        start_offset: range.start_offset,
+                        end_offset: range.end_offset,
+                    })
+                    .collect();
+                let projection = scan.projection.as_ref().map(|projection| {
+                    projection.columns.iter().map(|i| *i as usize).collect()
+                });
+                Ok(Arc::new(KafkaScanExec::try_new(
+                    scan.brokers.clone(),
+                    scan.topic.clone(),
+                    ranges,
+                    projection,
+                )?))
+            }
+            #[cfg(not(feature = "kafka"))]
+            PhysicalPlanType::KafkaScan(_) => Err(BallistaError::NotImplemented(
+                "Scanning Kafka topics requires the kafka feature".to_owned(),
+            )),
+            PhysicalPlanType::NodeLocalScan(scan) => {
+                let file_schema = Arc::new(convert_required!(scan.file_schema)?);
+                let projection = scan.projection.as_ref().map(|projection| {
+                    projection.columns.iter().map(|i| *i as usize).collect()
+                });
+                Ok(Arc::new(NodeLocalScanExec::try_new(
+                    scan.path.clone(),
+                    scan.file_extension.clone(),
+                    scan.node_label.clone(),
+                    scan.nodes.clone(),
+                    file_schema,
+                    projection,
+                )?))
+            }
+            PhysicalPlanType::ParquetSink(sink) => {
+                let input: Arc<dyn ExecutionPlan> =
+                    into_physical_plan!(sink.input, registry, runtime, extension_codec)?;
+                Ok(Arc::new(
+                    ParquetSinkExec::try_new(
+                        input,
+                        sink.path.clone(),
+                        sink.partition_by.clone(),
+                        sink.write_id.clone(),
+                    )?
+                    .with_attempt(sink.attempt)
+                    .with_field_ids(sink.field_ids.clone()),
+                ))
+            }
+            PhysicalPlanType::TopK(top_k) => {
+                let input: Arc<dyn ExecutionPlan> =
+                    into_physical_plan!(top_k.input, registry, runtime, extension_codec)?;
+                let expr = top_k
+                    .expr
+                    .iter()
+                    .map(|e| parse_physical_sort_expr(e, registry, &input.schema()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Arc::new(TopKExec::new(input, expr, top_k.fetch as usize)))
+            }
+            PhysicalPlanType::BloomFilterBuild(build) => {
+                let input: Arc<dyn ExecutionPlan> =
+                    into_physical_plan!(build.input, registry, runtime, extension_codec)?;
+                Ok(Arc::new(BloomFilterBuildExec::new(
+                    input,
+                    build.keys.iter().map(|key| key.into()).collect(),
+                    build.num_bytes as usize,
+                )))
+            }
+            PhysicalPlanType::BloomFilter(bloom_filter) => {
+                let input: Arc<dyn ExecutionPlan> = into_physical_plan!(
+                    bloom_filter.input,
+                    registry,
+                    runtime,
+                    extension_codec
+                )?;
+                let filter = if bloom_filter.filter.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(BloomFilter::from_bytes(&bloom_filter.filter)?))
+                };
+                Ok(Arc::new(
+                    BloomFilterExec::new(
+                        input,
+                        bloom_filter.build_stage_id as usize,
+                        bloom_filter.keys.iter().map(|key| key.into()).collect(),
+                    )
+                    .with_filter(filter)
+                    .with_row_group_filter(runtime)?,
+                ))
+            }
             PhysicalPlanType::CoalesceBatches(coalesce_batches) => {
                 let input: Arc<dyn ExecutionPlan> = into_physical_plan!(
                     coalesce_batches.input,
@@ -245,7 +398,8 @@ impl AsExecutionPlan for PhysicalPlanNode {
                 } else {
                     None
                 };
-                let fetch = if limit.fetch > 0 {
+                // a fetch of 0 is a valid limit, only a negative fetch means unlimited
+                let fetch = if limit.fetch >= 0 {
                     Some(limit.fetch as usize)
                 } else {
                     None
@@ -287,31 +441,47 @@ impl AsExecutionPlan for PhysicalPlanNode {
 
                         match expr_type {
                             ExprType::WindowExpr(window_node) => {
-                                let window_node_expr = window_node
+                                // built-in functions such as ROW_NUMBER() take no argument
+                                let args = window_node
                                     .expr
-                                    .as_ref()
+                                    .as_deref()
+                                    .into_iter()
+                                    .chain(window_node.args.iter())
                                     .map(|e| {
-                                        parse_physical_expr(
-                                            e.as_ref(),
+                                        parse_physical_expr(e, registry, &physical_schema)
+                                    })
+                                    .collect::<Result<Vec<_>, _>>()?;
+                                let partition_by = window_node
+                                    .partition_by
+                                    .iter()
+                                    .map(|e| {
+                                        parse_physical_expr(e, registry, &physical_schema)
+                                    })
+                                    .collect::<Result<Vec<_>, _>>()?;
+                                let order_by = window_node
+                                    .order_by
+                                    .iter()
+                                    .map(|e| {
+                                        parse_physical_sort_expr(
+                                            e,
                                             registry,
                                             &physical_schema,
                                         )
                                     })
-                                    .transpose()?
-                                    .ok_or_else(|| {
-                                        proto_error(
-                                            "missing window_node expr expression"
-                                                .to_string(),
-                                        )
-                                    })?;
+                                    .collect::<Result<Vec<_>, _>>()?;
+                                let window_frame = window_node
+                                    .window_frame
+                                    .clone()
+                                    .map(WindowFrame::try_from)
+                                    .transpose()?;
 
                                 Ok(create_window_expr(
                                     &convert_required!(window_node.window_function)?,
                                     name.to_owned(),
-                                    &[window_node_expr],
-                                    &[],
-                                    &[],
-                                    Some(WindowFrame::default()),
+                                    &args,
+                                    &partition_by,
+                                    &order_by,
+                                    window_frame,
                                     &physical_schema,
                                 )?)
                             }
@@ -424,7 +594,7 @@ impl AsExecutionPlan for PhysicalPlanNode {
 
                                 Ok(create_aggregate_expr(
                                     &aggr_function.into(),
-                                    false,
+                                    agg_node.distinct,
                                     input_phy_expr.as_slice(),
                                     &physical_schema,
                                     name.to_string(),
@@ -595,7 +765,14 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     })
                     .collect::<Result<Vec<_>, BallistaError>>()?;
                 let shuffle_reader =
-                    ShuffleReaderExec::try_new(partition_location, schema)?;
+                    ShuffleReaderExec::try_new(partition_location, schema)?
+                        .with_fetch_priority(
+                            shuffle_reader
+                                .fetch_priority
+                                .clone()
+                                .map(Into::into)
+                                .unwrap_or_default(),
+                        );
                 Ok(Arc::new(shuffle_reader))
             }
             PhysicalPlanType::Empty(empty) => {
@@ -641,7 +818,11 @@ impl AsExecutionPlan for PhysicalPlanNode {
                         }
                     })
                     .collect::<Result<Vec<_>, _>>()?;
-                Ok(Arc::new(SortExec::try_new(exprs, input)?))
+                Ok(Arc::new(SortExec::new_with_partitioning(
+                    exprs,
+                    input,
+                    sort.preserve_partitioning,
+                )))
             }
             PhysicalPlanType::Unresolved(unresolved_shuffle) => {
                 let schema = Arc::new(convert_required!(unresolved_shuffle.schema)?);
@@ -672,16 +853,57 @@ impl AsExecutionPlan for PhysicalPlanNode {
         }
     }
 
-    fn try_from_physical_plan(
+    fn encode_plan_node(
         plan: Arc<dyn ExecutionPlan>,
         extension_codec: &dyn PhysicalExtensionCodec,
-    ) -> Result<Self, BallistaError>
-    where
-        Self: Sized,
-    {
+    ) -> Result<Self, BallistaError> {
         let plan_clone = plan.clone();
         let plan = plan.as_any();
 
+        #[cfg(feature = "postgres")]
+        if let Some(exec) = plan.downcast_ref::<PostgresScanExec>() {
+            return Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::PostgresScan(
+                    protobuf::PostgresScanExecNode {
+                        database: exec.database().to_owned(),
+                        table: exec.table().to_owned(),
+                        columns: exec.columns().to_vec(),
+                        schema: Some(exec.schema().as_ref().into()),
+                        predicates: exec.predicates().to_vec(),
+                        limit: exec.limit().map(|limit| protobuf::ScanLimit {
+                            limit: limit as u32,
+                        }),
+                    },
+                )),
+            });
+        }
+
+        #[cfg(feature = "kafka")]
+        if let Some(exec) = plan.downcast_ref::<KafkaScanExec>() {
+            return Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::KafkaScan(
+                    protobuf::KafkaScanExecNode {
+                        brokers: exec.brokers().to_vec(),
+                        topic: exec.topic().to_owned(),
+                        ranges: exec
+                            .ranges()
+                            .iter()
+                            .map(|range| protobuf::KafkaOffsetRange {
+                                partition: range.partition,
+                                start_offset: range.start_offset,
+                                end_offset: range.end_offset,
+                            })
+                            .collect(),
+                        projection: exec.projection().map(|columns| {
+                            protobuf::ScanProjection {
+                                columns: columns.iter().map(|i| *i as u32).collect(),
+                            }
+                        }),
+                    },
+                )),
+            });
+        }
+
         if let Some(exec) = plan.downcast_ref::<ExplainExec>() {
             Ok(protobuf::PhysicalPlanNode {
                 physical_plan_type: Some(PhysicalPlanType::Explain(
@@ -740,7 +962,7 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     protobuf::GlobalLimitExecNode {
                         input: Some(Box::new(input)),
                         skip: *limit.skip().unwrap_or(&0) as u32,
-                        fetch: *limit.fetch().unwrap_or(&0) as u32,
+                        fetch: limit.fetch().map(|fetch| *fetch as i64).unwrap_or(-1),
                     },
                 ))),
             })
@@ -845,6 +1067,33 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     },
                 ))),
             })
+        } else if let Some(exec) = plan.downcast_ref::<WindowAggExec>() {
+            let input = protobuf::PhysicalPlanNode::try_from_physical_plan(
+                exec.input().to_owned(),
+                extension_codec,
+            )?;
+            let window_expr = exec
+                .window_expr()
+                .iter()
+                .map(|expr| expr.to_owned().try_into())
+                .collect::<Result<Vec<_>, BallistaError>>()?;
+            let window_expr_name = exec
+                .window_expr()
+                .iter()
+                .map(|expr| expr.name().to_owned())
+                .collect();
+            let input_schema = exec.input_schema();
+
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::Window(Box::new(
+                    protobuf::WindowAggExecNode {
+                        input: Some(Box::new(input)),
+                        window_expr,
+                        window_expr_name,
+                        input_schema: Some(input_schema.as_ref().into()),
+                    },
+                ))),
+            })
         } else if let Some(exec) = plan.downcast_ref::<AggregateExec>() {
             let groups: Vec<bool> = exec
                 .group_expr()
@@ -972,6 +1221,105 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     },
                 )),
             })
+        } else if let Some(exec) = plan.downcast_ref::<NodeLocalScanExec>() {
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::NodeLocalScan(
+                    protobuf::NodeLocalScanExecNode {
+                        path: exec.path().to_owned(),
+                        file_extension: exec.file_extension().to_owned(),
+                        node_label: exec.node_label().to_owned(),
+                        nodes: exec.nodes().to_vec(),
+                        file_schema: Some(exec.file_schema().as_ref().into()),
+                        projection: exec.projection().map(|columns| {
+                            protobuf::ScanProjection {
+                                columns: columns.iter().map(|i| *i as u32).collect(),
+                            }
+                        }),
+                    },
+                )),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<ParquetSinkExec>() {
+            let input = protobuf::PhysicalPlanNode::try_from_physical_plan(
+                exec.input().to_owned(),
+                extension_codec,
+            )?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::ParquetSink(Box::new(
+                    protobuf::ParquetSinkExecNode {
+                        input: Some(Box::new(input)),
+                        path: exec.path().to_owned(),
+                        partition_by: exec.partition_by().to_vec(),
+                        write_id: exec.write_id().to_owned(),
+                        attempt: exec.attempt(),
+                        field_ids: exec.field_ids().clone(),
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<TopKExec>() {
+            let input = protobuf::PhysicalPlanNode::try_from_physical_plan(
+                exec.input().to_owned(),
+                extension_codec,
+            )?;
+            let expr = exec
+                .expr()
+                .iter()
+                .map(|e| {
+                    Ok(protobuf::PhysicalSortExprNode {
+                        expr: Some(Box::new(e.expr.clone().try_into()?)),
+                        asc: !e.options.descending,
+                        nulls_first: e.options.nulls_first,
+                    })
+                })
+                .collect::<Result<Vec<_>, BallistaError>>()?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::TopK(Box::new(
+                    protobuf::TopKExecNode {
+                        input: Some(Box::new(input)),
+                        expr,
+                        fetch: exec.fetch() as u64,
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<BloomFilterBuildExec>() {
+            let input = protobuf::PhysicalPlanNode::try_from_physical_plan(
+                exec.input().to_owned(),
+                extension_codec,
+            )?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::BloomFilterBuild(Box::new(
+                    protobuf::BloomFilterBuildExecNode {
+                        input: Some(Box::new(input)),
+                        keys: exec.keys().iter().map(physical_column).collect(),
+                        num_bytes: exec.num_bytes() as u64,
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<BloomFilterExec>() {
+            let input = protobuf::PhysicalPlanNode::try_from_physical_plan(
+                exec.input().to_owned(),
+                extension_codec,
+            )?;
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::BloomFilter(Box::new(
+                    protobuf::BloomFilterExecNode {
+                        input: Some(Box::new(input)),
+                        build_stage_id: exec.build_stage_id() as u32,
+                        keys: exec.keys().iter().map(physical_column).collect(),
+                        filter: exec
+                            .filter()
+                            .map(|filter| filter.to_bytes())
+                            .unwrap_or_default(),
+                    },
+                ))),
+            })
+        } else if let Some(exec) = plan.downcast_ref::<NdJsonExec>() {
+            Ok(protobuf::PhysicalPlanNode {
+                physical_plan_type: Some(PhysicalPlanType::JsonScan(
+                    protobuf::JsonScanExecNode {
+                        base_conf: Some(exec.base_config().try_into()?),
+                    },
+                )),
+            })
         } else if let Some(exec) = plan.downcast_ref::<ShuffleReaderExec>() {
             let mut partition = vec![];
             for location in &exec.partition {
@@ -987,6 +1335,7 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     protobuf::ShuffleReaderExecNode {
                         partition,
                         schema: Some(exec.schema().as_ref().into()),
+                        fetch_priority: Some(exec.fetch_priority.into()),
                     },
                 )),
             })
@@ -1060,6 +1409,7 @@ impl AsExecutionPlan for PhysicalPlanNode {
                     protobuf::SortExecNode {
                         input: Some(Box::new(input)),
                         expr,
+                        preserve_partitioning: exec.preserve_partitioning(),
                     },
                 ))),
             })
@@ -1139,6 +1489,64 @@ impl AsExecutionPlan for PhysicalPlanNode {
             })
         }
     }
+
+    /// Name of the operator this node decodes to, used to give serde errors plan context
+    fn plan_node_name(&self) -> &'static str {
+        match &self.physical_plan_type {
+            Some(PhysicalPlanType::Explain(_)) => "ExplainExec",
+            Some(PhysicalPlanType::Projection(_)) => "ProjectionExec",
+            Some(PhysicalPlanType::Filter(_)) => "FilterExec",
+            Some(PhysicalPlanType::CsvScan(_)) => "CsvExec",
+            Some(PhysicalPlanType::ParquetScan(_)) => "ParquetExec",
+            Some(PhysicalPlanType::AvroScan(_)) => "AvroExec",
+            Some(PhysicalPlanType::JsonScan(_)) => "NdJsonExec",
+            Some(PhysicalPlanType::PostgresScan(_)) => "PostgresScanExec",
+            Some(PhysicalPlanType::KafkaScan(_)) => "KafkaScanExec",
+            Some(PhysicalPlanType::NodeLocalScan(_)) => "NodeLocalScanExec",
+            Some(PhysicalPlanType::ParquetSink(_)) => "ParquetSinkExec",
+            Some(PhysicalPlanType::TopK(_)) => "TopKExec",
+            Some(PhysicalPlanType::BloomFilterBuild(_)) => "BloomFilterBuildExec",
+            Some(PhysicalPlanType::BloomFilter(_)) => "BloomFilterExec",
+            Some(PhysicalPlanType::CoalesceBatches(_)) => "CoalesceBatchesExec",
+            Some(PhysicalPlanType::Merge(_)) => "CoalescePartitionsExec",
+            Some(PhysicalPlanType::Repartition(_)) => "RepartitionExec",
+            Some(PhysicalPlanType::GlobalLimit(_)) => "GlobalLimitExec",
+            Some(PhysicalPlanType::LocalLimit(_)) => "LocalLimitExec",
+            Some(PhysicalPlanType::Window(_)) => "WindowAggExec",
+            Some(PhysicalPlanType::Aggregate(_)) => "AggregateExec",
+            Some(PhysicalPlanType::HashJoin(_)) => "HashJoinExec",
+            Some(PhysicalPlanType::Union(_)) => "UnionExec",
+            Some(PhysicalPlanType::CrossJoin(_)) => "CrossJoinExec",
+            Some(PhysicalPlanType::ShuffleWriter(_)) => "ShuffleWriterExec",
+            Some(PhysicalPlanType::ShuffleReader(_)) => "ShuffleReaderExec",
+            Some(PhysicalPlanType::Empty(_)) => "EmptyExec",
+            Some(PhysicalPlanType::Sort(_)) => "SortExec",
+            Some(PhysicalPlanType::Unresolved(_)) => "UnresolvedShuffleExec",
+            Some(PhysicalPlanType::Extension(_)) => "Extension",
+            None => "<empty>",
+        }
+    }
+}
+
+/// One line description of a plan node including its expressions, e.g.
+/// `FilterExec: a@0 > 1`
+fn describe_plan_node(plan: &dyn ExecutionPlan) -> String {
+    struct OneLine<'a>(&'a dyn ExecutionPlan);
+
+    impl fmt::Display for OneLine<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            self.0.fmt_as(DisplayFormatType::Default, f)
+        }
+    }
+
+    OneLine(plan).to_string()
+}
+
+fn physical_column(column: &Column) -> protobuf::PhysicalColumn {
+    protobuf::PhysicalColumn {
+        name: column.name().to_owned(),
+        index: column.index() as u32,
+    }
 }
 
 fn decode_scan_config(
@@ -1175,7 +1583,7 @@ fn decode_scan_config(
         statistics,
         projection,
         limit: proto.limit.as_ref().map(|sl| sl.limit as usize),
-        table_partition_cols: vec![],
+        table_partition_cols: proto.table_partition_cols.clone(),
     })
 }
 
@@ -1198,15 +1606,25 @@ mod roundtrip_tests {
     use std::sync::Arc;
 
     use datafusion::arrow::array::ArrayRef;
+    use datafusion::datasource::file_format::csv::CsvFormat;
+    use datafusion::datasource::listing::{
+        ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+    };
     use datafusion::datasource::object_store::ObjectStoreUrl;
     use datafusion::execution::context::ExecutionProps;
-    use datafusion::logical_expr::{BuiltinScalarFunction, Volatility};
-    use datafusion::logical_plan::create_udf;
+    use datafusion::logical_expr::window_function::{
+        BuiltInWindowFunction, WindowFunction,
+    };
+    use datafusion::logical_expr::{
+        AggregateFunction, BuiltinScalarFunction, Volatility,
+    };
+    use datafusion::logical_plan::{create_udf, source_as_provider, LogicalPlan};
     use datafusion::physical_expr::ScalarFunctionExpr;
-    use datafusion::physical_plan::aggregates::PhysicalGroupBy;
+    use datafusion::physical_plan::aggregates::{create_aggregate_expr, PhysicalGroupBy};
     use datafusion::physical_plan::functions;
     use datafusion::physical_plan::functions::make_scalar_function;
     use datafusion::physical_plan::projection::ProjectionExec;
+    use datafusion::physical_plan::windows::{create_window_expr, WindowAggExec};
     use datafusion::{
         arrow::{
             compute::kernels::sort::SortOptions,
@@ -1219,10 +1637,11 @@ mod roundtrip_tests {
             empty::EmptyExec,
             expressions::{binary, col, lit, InListExpr, NotExpr},
             expressions::{Avg, Column, PhysicalSortExpr},
-            file_format::{FileScanConfig, ParquetExec},
+            file_format::{FileScanConfig, NdJsonExec, ParquetExec},
             filter::FilterExec,
             hash_join::{HashJoinExec, PartitionMode},
             limit::{GlobalLimitExec, LocalLimitExec},
+            memory::MemoryExec,
             sorts::sort::SortExec,
             AggregateExpr, ExecutionPlan, Partitioning, PhysicalExpr, Statistics,
         },
@@ -1230,9 +1649,22 @@ mod roundtrip_tests {
         scalar::ScalarValue,
     };
 
-    use crate::execution_plans::ShuffleWriterExec;
+    use crate::error::BallistaError;
+    #[cfg(feature = "kafka")]
+    use crate::execution_plans::KafkaScanExec;
+    #[cfg(feature = "postgres")]
+    use crate::execution_plans::PostgresScanExec;
+    use crate::execution_plans::{
+        BloomFilter, BloomFilterBuildExec, BloomFilterExec, NodeLocalScanExec,
+        ParquetSinkExec, ShuffleWriterExec, TopKExec,
+    };
+    #[cfg(feature = "kafka")]
+    use crate::kafka::KafkaOffsetRange;
     use crate::serde::protobuf::PhysicalPlanNode;
     use crate::serde::{AsExecutionPlan, BallistaCodec};
+    use datafusion::physical_plan::{collect, displayable};
+    use datafusion::prelude::CsvReadOptions;
+    use datafusion_proto::logical_plan::AsLogicalPlan;
     use datafusion_proto::protobuf::LogicalPlanNode;
 
     use super::super::super::error::Result;
@@ -1312,6 +1744,20 @@ mod roundtrip_tests {
         )))
     }
 
+    #[test]
+    fn roundtrip_global_limit_zero_and_unlimited() -> Result<()> {
+        roundtrip_test(Arc::new(GlobalLimitExec::new(
+            Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))),
+            None,
+            Some(0),
+        )))?;
+        roundtrip_test(Arc::new(GlobalLimitExec::new(
+            Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))),
+            Some(10),
+            None,
+        )))
+    }
+
     #[test]
     fn roundtrip_hash_join() -> Result<()> {
         let field_a = Field::new("col", DataType::Int64, false);
@@ -1373,6 +1819,32 @@ mod roundtrip_tests {
         )?))
     }
 
+    #[test]
+    fn roundtrip_distinct_count() -> Result<()> {
+        let field_a = Field::new("a", DataType::Int64, false);
+        let field_b = Field::new("b", DataType::Int64, false);
+        let schema = Arc::new(Schema::new(vec![field_a, field_b]));
+
+        let groups: Vec<(Arc<dyn PhysicalExpr>, String)> =
+            vec![(col("a", &schema)?, "unused".to_string())];
+
+        let aggregates = vec![create_aggregate_expr(
+            &AggregateFunction::Count,
+            true,
+            &[col("b", &schema)?],
+            &schema,
+            "COUNT(DISTINCT b)",
+        )?];
+
+        roundtrip_test(Arc::new(AggregateExec::try_new(
+            AggregateMode::Partial,
+            PhysicalGroupBy::new_single(groups),
+            aggregates,
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            schema,
+        )?))
+    }
+
     #[test]
     fn roundtrip_filter_with_not_and_in_list() -> Result<()> {
         let field_a = Field::new("a", DataType::Boolean, false);
@@ -1423,6 +1895,150 @@ mod roundtrip_tests {
         )?))
     }
 
+    #[test]
+    fn roundtrip_sort_preserve_partitioning() -> Result<()> {
+        let field_a = Field::new("a", DataType::Boolean, false);
+        let field_b = Field::new("b", DataType::Int64, false);
+        let schema = Arc::new(Schema::new(vec![field_a, field_b]));
+        let sort_exprs = vec![PhysicalSortExpr {
+            expr: col("b", &schema)?,
+            options: SortOptions {
+                descending: false,
+                nulls_first: true,
+            },
+        }];
+        roundtrip_test(Arc::new(SortExec::new_with_partitioning(
+            sort_exprs,
+            Arc::new(EmptyExec::new(false, schema)),
+            true,
+        )))
+    }
+
+    /// Round trip a window over the built-in window function `fun` of `args`, where
+    /// column `a` is an integer and `b` a string
+    fn roundtrip_window(
+        fun: BuiltInWindowFunction,
+        args: impl FnOnce(&Schema) -> Result<Vec<Arc<dyn PhysicalExpr>>>,
+    ) -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int64, true),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let order_by = vec![PhysicalSortExpr {
+            expr: col("a", &schema)?,
+            options: SortOptions::default(),
+        }];
+        let window_expr = create_window_expr(
+            &WindowFunction::BuiltInWindowFunction(fun),
+            format!("{}(...)", fun),
+            &args(&schema)?,
+            &[col("b", &schema)?],
+            &order_by,
+            None,
+            &schema,
+        )?;
+        roundtrip_test(Arc::new(WindowAggExec::try_new(
+            vec![window_expr],
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            schema,
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_window_ranks() -> Result<()> {
+        for fun in [
+            BuiltInWindowFunction::RowNumber,
+            BuiltInWindowFunction::Rank,
+            BuiltInWindowFunction::DenseRank,
+            BuiltInWindowFunction::PercentRank,
+            BuiltInWindowFunction::CumeDist,
+        ] {
+            roundtrip_window(fun, |_| Ok(vec![]))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_window_ntile() -> Result<()> {
+        roundtrip_window(BuiltInWindowFunction::Ntile, |_| {
+            Ok(vec![lit(ScalarValue::Int64(Some(4)))])
+        })
+    }
+
+    #[test]
+    fn roundtrip_window_lag_lead() -> Result<()> {
+        for fun in [BuiltInWindowFunction::Lag, BuiltInWindowFunction::Lead] {
+            roundtrip_window(fun, |schema| Ok(vec![col("a", schema)?]))?;
+            roundtrip_window(fun, |schema| {
+                Ok(vec![col("a", schema)?, lit(ScalarValue::Int64(Some(3)))])
+            })?;
+            roundtrip_window(fun, |schema| {
+                Ok(vec![
+                    col("b", schema)?,
+                    lit(ScalarValue::Int64(Some(2))),
+                    lit(ScalarValue::Utf8(Some("none, yet".to_owned()))),
+                ])
+            })?;
+            roundtrip_window(fun, |schema| {
+                Ok(vec![
+                    col("a", schema)?,
+                    lit(ScalarValue::Int64(Some(1))),
+                    lit(ScalarValue::Int64(None)),
+                ])
+            })?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn roundtrip_window_values() -> Result<()> {
+        roundtrip_window(BuiltInWindowFunction::FirstValue, |schema| {
+            Ok(vec![col("b", schema)?])
+        })?;
+        roundtrip_window(BuiltInWindowFunction::LastValue, |schema| {
+            Ok(vec![col("b", schema)?])
+        })?;
+        roundtrip_window(BuiltInWindowFunction::NthValue, |schema| {
+            Ok(vec![col("a", schema)?, lit(ScalarValue::Int64(Some(2)))])
+        })
+    }
+
+    #[test]
+    fn roundtrip_top_k() -> Result<()> {
+        let field_a = Field::new("a", DataType::Boolean, false);
+        let field_b = Field::new("b", DataType::Int64, false);
+        let schema = Arc::new(Schema::new(vec![field_a, field_b]));
+        let sort_exprs = vec![PhysicalSortExpr {
+            expr: col("b", &schema)?,
+            options: SortOptions {
+                descending: true,
+                nulls_first: false,
+            },
+        }];
+        roundtrip_test(Arc::new(TopKExec::new(
+            Arc::new(EmptyExec::new(false, schema)),
+            sort_exprs,
+            10,
+        )))
+    }
+
+    #[test]
+    fn roundtrip_bloom_filters() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let keys = vec![Column::new("a", 0)];
+        roundtrip_test(Arc::new(BloomFilterBuildExec::new(
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            keys.clone(),
+            1024,
+        )))?;
+        let probe =
+            BloomFilterExec::new(Arc::new(EmptyExec::new(false, schema)), 2, keys);
+        roundtrip_test(Arc::new(probe.clone()))?;
+        roundtrip_test(Arc::new(
+            probe.with_filter(Some(Arc::new(BloomFilter::new(1024)))),
+        ))
+    }
+
     #[test]
     fn roundtrip_shuffle_writer() -> Result<()> {
         let field_a = Field::new("a", DataType::Int64, false);
@@ -1463,7 +2079,302 @@ mod roundtrip_tests {
         };
 
         let predicate = datafusion::prelude::col("col").eq(datafusion::prelude::lit("1"));
-        roundtrip_test(Arc::new(ParquetExec::new(scan_config, Some(predicate), None)))
+        roundtrip_test(Arc::new(ParquetExec::new(
+            scan_config,
+            Some(predicate),
+            None,
+        )))
+    }
+
+    #[test]
+    fn roundtrip_parquet_exec_with_projection_and_limit() -> Result<()> {
+        let scan_config = FileScanConfig {
+            object_store_url: ObjectStoreUrl::local_filesystem(),
+            file_schema: Arc::new(Schema::new(vec![
+                Field::new("a", DataType::Int64, false),
+                Field::new("b", DataType::Utf8, false),
+                Field::new("c", DataType::Float64, true),
+            ])),
+            file_groups: vec![
+                vec![PartitionedFile::new(
+                    "/path/to/file1.parquet".to_string(),
+                    1024,
+                )],
+                vec![PartitionedFile::new(
+                    "/path/to/file2.parquet".to_string(),
+                    2048,
+                )],
+            ],
+            statistics: Statistics {
+                num_rows: Some(100),
+                total_byte_size: Some(3072),
+                column_statistics: None,
+                is_exact: false,
+            },
+            projection: Some(vec![2, 0]),
+            limit: Some(10),
+            table_partition_cols: vec![],
+        };
+
+        let predicate = datafusion::prelude::col("a")
+            .gt(datafusion::prelude::lit(5i64))
+            .and(datafusion::prelude::col("c").is_not_null());
+        roundtrip_test(Arc::new(ParquetExec::new(
+            scan_config,
+            Some(predicate),
+            None,
+        )))
+    }
+
+    #[test]
+    fn roundtrip_json_exec() -> Result<()> {
+        let scan_config = FileScanConfig {
+            object_store_url: ObjectStoreUrl::local_filesystem(),
+            file_schema: Arc::new(Schema::new(vec![
+                Field::new("a", DataType::Int64, true),
+                Field::new("b", DataType::Utf8, true),
+            ])),
+            file_groups: vec![vec![PartitionedFile::new(
+                "/path/to/file.json".to_string(),
+                1024,
+            )]],
+            statistics: Statistics::default(),
+            projection: Some(vec![1]),
+            limit: Some(10),
+            table_partition_cols: vec![],
+        };
+        roundtrip_test(Arc::new(NdJsonExec::new(scan_config)))
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn roundtrip_postgres_scan() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("total", DataType::Float64, true),
+        ]));
+        roundtrip_test(Arc::new(PostgresScanExec::new(
+            "shop".to_owned(),
+            "\"public\".\"orders\"".to_owned(),
+            vec![
+                "\"id\"".to_owned(),
+                "\"total\"::double precision".to_owned(),
+            ],
+            schema,
+            vec![
+                "\"id\" < 100 OR \"id\" IS NULL".to_owned(),
+                "\"id\" >= 100".to_owned(),
+            ],
+            Some(10),
+        )))
+    }
+
+    #[cfg(feature = "kafka")]
+    #[test]
+    fn roundtrip_kafka_scan() -> Result<()> {
+        let range = |partition| KafkaOffsetRange {
+            partition,
+            start_offset: 100,
+            end_offset: 250,
+        };
+        roundtrip_test(Arc::new(KafkaScanExec::try_new(
+            vec!["localhost:9092".to_owned()],
+            "events".to_owned(),
+            vec![range(0), range(1)],
+            Some(vec![1, 4]),
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_node_local_scan() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        roundtrip_test(Arc::new(NodeLocalScanExec::try_new(
+            "/data/events".to_owned(),
+            ".parquet".to_owned(),
+            "k8s.node.name".to_owned(),
+            vec!["node-1".to_owned(), "node-2".to_owned()],
+            schema,
+            Some(vec![1]),
+        )?))
+    }
+
+    #[test]
+    fn roundtrip_parquet_sink() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("year", DataType::Int32, false),
+            Field::new("value", DataType::Utf8, false),
+        ]));
+        roundtrip_test(Arc::new(
+            ParquetSinkExec::try_new(
+                Arc::new(EmptyExec::new(false, schema)),
+                "s3://bucket/output".to_owned(),
+                vec!["year".to_owned()],
+                "job-1".to_owned(),
+            )?
+            .with_attempt(2)
+            .with_field_ids(vec![("value".to_owned(), 2)].into_iter().collect()),
+        ))
+    }
+
+    #[test]
+    fn roundtrip_parquet_exec_with_table_partition_cols() -> Result<()> {
+        let mut partitioned_file =
+            PartitionedFile::new("/path/to/year=2022/file.parquet".to_string(), 1024);
+        partitioned_file.partition_values =
+            vec![ScalarValue::Utf8(Some("2022".to_string()))];
+        let scan_config = FileScanConfig {
+            object_store_url: ObjectStoreUrl::local_filesystem(),
+            file_schema: Arc::new(Schema::new(vec![Field::new(
+                "col",
+                DataType::Utf8,
+                false,
+            )])),
+            file_groups: vec![vec![partitioned_file]],
+            statistics: Statistics {
+                num_rows: Some(100),
+                total_byte_size: Some(1024),
+                column_statistics: None,
+                is_exact: false,
+            },
+            // project the partition column, which follows the file columns
+            projection: Some(vec![1, 0]),
+            limit: None,
+            table_partition_cols: vec!["year".to_string()],
+        };
+
+        let predicate = datafusion::prelude::col("col").eq(datafusion::prelude::lit("1"));
+        roundtrip_test(Arc::new(ParquetExec::new(
+            scan_config,
+            Some(predicate),
+            None,
+        )))
+    }
+
+    #[tokio::test]
+    async fn roundtrip_partitioned_listing_table() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        for year in ["2021", "2022"] {
+            let partition_dir = dir.path().join(format!("year={}", year));
+            std::fs::create_dir(&partition_dir)?;
+            std::fs::write(partition_dir.join("data.csv"), "a,b\n1,x\n2,y\n")?;
+        }
+
+        let ctx = SessionContext::new();
+        ctx.register_csv(
+            "t",
+            dir.path().to_str().unwrap(),
+            CsvReadOptions::new().table_partition_cols(vec!["year".to_string()]),
+        )
+        .await?;
+
+        let codec: BallistaCodec<LogicalPlanNode, PhysicalPlanNode> =
+            BallistaCodec::default();
+        let plan = ctx
+            .sql("SELECT a, year FROM t WHERE year = '2022'")
+            .await?
+            .to_logical_plan()?;
+        let logical_proto = LogicalPlanNode::try_from_logical_plan(
+            &plan,
+            codec.logical_extension_codec(),
+        )?;
+        let logical_round_trip =
+            logical_proto.try_into_logical_plan(&ctx, codec.logical_extension_codec())?;
+        assert_eq!(format!("{:?}", plan), format!("{:?}", logical_round_trip));
+
+        let plan = ctx.create_physical_plan(&ctx.optimize(&plan)?).await?;
+        let proto = protobuf::PhysicalPlanNode::try_from_physical_plan(
+            plan.clone(),
+            codec.physical_extension_codec(),
+        )?;
+        let runtime = ctx.runtime_env();
+        let physical_round_trip = proto.try_into_physical_plan(
+            &ctx,
+            runtime.deref(),
+            codec.physical_extension_codec(),
+        )?;
+        assert_eq!(
+            displayable(plan.as_ref()).indent().to_string(),
+            displayable(physical_round_trip.as_ref())
+                .indent()
+                .to_string()
+        );
+
+        // partition pruning must select the same files on executors
+        let batches = collect(physical_round_trip, ctx.task_ctx()).await?;
+        let num_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(2, num_rows);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn roundtrip_listing_table_paths_and_file_extension() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let mut table_paths = vec![];
+        for name in ["first", "second"] {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path)?;
+            std::fs::write(path.join("data.csv"), "a,b\n1,x\n2,y\n")?;
+            // skipped as it does not have the file extension of the table
+            std::fs::write(path.join("data.txt"), "a,b\n3,z\n")?;
+            table_paths.push(ListingTableUrl::parse(path.to_str().unwrap())?);
+        }
+
+        let options = ListingOptions {
+            file_extension: ".csv".to_owned(),
+            format: Arc::new(CsvFormat::default()),
+            table_partition_cols: vec![],
+            collect_stat: true,
+            target_partitions: 2,
+        };
+        let config = ListingTableConfig::new_with_multi_paths(table_paths)
+            .with_schema(Arc::new(Schema::new(vec![
+                Field::new("a", DataType::Int64, true),
+                Field::new("b", DataType::Utf8, true),
+            ])))
+            .with_listing_options(options);
+        let ctx = SessionContext::new();
+        ctx.register_table("t", Arc::new(ListingTable::try_new(config)?))?;
+
+        let codec: BallistaCodec<LogicalPlanNode, PhysicalPlanNode> =
+            BallistaCodec::default();
+        let plan = ctx.table("t")?.to_logical_plan()?;
+        let logical_proto = LogicalPlanNode::try_from_logical_plan(
+            &plan,
+            codec.logical_extension_codec(),
+        )?;
+        let logical_round_trip =
+            logical_proto.try_into_logical_plan(&ctx, codec.logical_extension_codec())?;
+
+        let listing_table = |plan: &LogicalPlan| match plan {
+            LogicalPlan::TableScan(scan) => source_as_provider(&scan.source)
+                .unwrap()
+                .as_any()
+                .downcast_ref::<ListingTable>()
+                .map(|table| {
+                    (
+                        format!("{:?}", table.table_paths()),
+                        table.options().file_extension.clone(),
+                    )
+                }),
+            _ => None,
+        };
+        let expected = listing_table(&plan).unwrap();
+        assert_eq!(expected.1, ".csv");
+        assert_eq!(Some(expected), listing_table(&logical_round_trip));
+
+        // the files of both paths, but only those with the file extension, are scanned
+        let plan = ctx
+            .create_physical_plan(&ctx.optimize(&logical_round_trip)?)
+            .await?;
+        let batches = collect(plan, ctx.task_ctx()).await?;
+        let num_rows: usize = batches.iter().map(|b| b.num_rows()).sum();
+        assert_eq!(4, num_rows);
+
+        Ok(())
     }
 
     #[test]
@@ -1530,4 +2441,39 @@ mod roundtrip_tests {
 
         roundtrip_test_with_context(Arc::new(project), ctx)
     }
+
+    #[test]
+    fn serde_error_includes_plan_path() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        // MemoryExec has no protobuf representation and no extension codec is provided
+        let memory = Arc::new(MemoryExec::try_new(&[vec![]], schema.clone(), None)?);
+        let filter = Arc::new(FilterExec::try_new(
+            binary(
+                col("a", &schema)?,
+                Operator::Gt,
+                lit(ScalarValue::Int64(Some(1))),
+                &schema,
+            )?,
+            memory,
+        )?);
+
+        let codec: BallistaCodec<LogicalPlanNode, PhysicalPlanNode> =
+            BallistaCodec::default();
+        let err = protobuf::PhysicalPlanNode::try_from_physical_plan(
+            filter,
+            codec.physical_extension_codec(),
+        )
+        .unwrap_err();
+
+        match err {
+            BallistaError::PlanSerdeError(path, source) => {
+                assert_eq!(2, path.len());
+                assert_eq!("FilterExec: a@0 > 1", path[0]);
+                assert!(path[1].starts_with("MemoryExec"));
+                assert!(matches!(*source, BallistaError::NotImplemented(_)));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        Ok(())
+    }
 }
@@ -21,6 +21,8 @@
 //! processes.
 
 use std::{
+    any::Any,
+    collections::HashMap,
     convert::{TryFrom, TryInto},
     str::FromStr,
     sync::Arc,
@@ -41,12 +43,16 @@ use datafusion::physical_plan::file_format::FileScanConfig;
 use datafusion::physical_plan::expressions::{Count, Literal};
 
 use datafusion::physical_plan::expressions::{Avg, BinaryExpr, Column, Max, Min, Sum};
-use datafusion::physical_plan::{AggregateExpr, PhysicalExpr};
+use datafusion::physical_plan::windows::{AggregateWindowExpr, BuiltInWindowExpr};
+use datafusion::physical_plan::{AggregateExpr, PhysicalExpr, WindowExpr};
 
 use crate::serde::{protobuf, BallistaError};
 
+use datafusion::arrow::datatypes::DataType;
 use datafusion::logical_expr::BuiltinScalarFunction;
 use datafusion::physical_expr::ScalarFunctionExpr;
+use datafusion::scalar::ScalarValue;
+use datafusion_proto::protobuf::BuiltInWindowFunction;
 
 impl TryInto<protobuf::PhysicalExprNode> for Arc<dyn AggregateExpr> {
     type Error = BallistaError;
@@ -54,11 +60,15 @@ impl TryInto<protobuf::PhysicalExprNode> for Arc<dyn AggregateExpr> {
     fn try_into(self) -> Result<protobuf::PhysicalExprNode, Self::Error> {
         use datafusion::physical_plan::expressions;
         use datafusion_proto::protobuf::AggregateFunction;
+        let distinct = self
+            .as_any()
+            .downcast_ref::<expressions::DistinctCount>()
+            .is_some();
         let aggr_function = if self.as_any().downcast_ref::<Avg>().is_some() {
             Ok(AggregateFunction::Avg.into())
         } else if self.as_any().downcast_ref::<Sum>().is_some() {
             Ok(AggregateFunction::Sum.into())
-        } else if self.as_any().downcast_ref::<Count>().is_some() {
+        } else if self.as_any().downcast_ref::<Count>().is_some() || distinct {
             Ok(AggregateFunction::Count.into())
         } else if self.as_any().downcast_ref::<Min>().is_some() {
             Ok(AggregateFunction::Min.into())
@@ -152,12 +162,267 @@ impl TryInto<protobuf::PhysicalExprNode> for Arc<dyn AggregateExpr> {
                 protobuf::PhysicalAggregateExprNode {
                     aggr_function,
                     expr: expressions,
+                    distinct,
                 },
             )),
         })
     }
 }
 
+impl TryInto<protobuf::PhysicalExprNode> for Arc<dyn WindowExpr> {
+    type Error = BallistaError;
+
+    fn try_into(self) -> Result<protobuf::PhysicalExprNode, Self::Error> {
+        use protobuf::physical_window_expr_node::WindowFunction;
+
+        let expr = self.as_any();
+        let mut literal_args = vec![];
+        let (window_function, window_frame) = if let Some(window_expr) =
+            expr.downcast_ref::<AggregateWindowExpr>()
+        {
+            let aggr_expr: protobuf::PhysicalExprNode =
+                window_expr.get_aggregate_expr().clone().try_into()?;
+            let aggr_function = match aggr_expr.expr_type {
+                Some(protobuf::physical_expr_node::ExprType::AggregateExpr(aggr)) => {
+                    Ok(aggr.aggr_function)
+                }
+                _ => Err(BallistaError::Internal(format!(
+                    "Unexpected aggregate expression in window function {:?}",
+                    self
+                ))),
+            }?;
+            (
+                WindowFunction::AggrFunction(aggr_function),
+                *window_expr.get_window_frame(),
+            )
+        } else if let Some(window_expr) = expr.downcast_ref::<BuiltInWindowExpr>() {
+            let built_in = window_expr.get_built_in_func_expr();
+            let (built_in_function, args) =
+                built_in_window_function(built_in.as_any(), &format!("{:?}", built_in))?;
+            literal_args = args;
+            (
+                WindowFunction::BuiltInFunction(built_in_function.into()),
+                *window_expr.get_window_frame(),
+            )
+        } else {
+            return Err(BallistaError::NotImplemented(format!(
+                "Window expression not supported: {:?}",
+                self
+            )));
+        };
+
+        let args = self.expressions();
+        if args.len() > 1 {
+            return Err(BallistaError::NotImplemented(format!(
+                "Window functions with more than one argument are not supported: {:?}",
+                self
+            )));
+        }
+        let expr = args
+            .first()
+            .map(|e| e.clone().try_into().map(Box::new))
+            .transpose()?;
+        let partition_by = self
+            .partition_by()
+            .iter()
+            .map(|e| e.clone().try_into())
+            .collect::<Result<Vec<_>, BallistaError>>()?;
+        let order_by = self
+            .order_by()
+            .iter()
+            .map(|e| {
+                Ok(protobuf::PhysicalSortExprNode {
+                    expr: Some(Box::new(e.expr.clone().try_into()?)),
+                    asc: !e.options.descending,
+                    nulls_first: e.options.nulls_first,
+                })
+            })
+            .collect::<Result<Vec<_>, BallistaError>>()?;
+        let args = literal_args
+            .into_iter()
+            .map(|value| {
+                let literal: Arc<dyn PhysicalExpr> = Arc::new(Literal::new(value));
+                literal.try_into()
+            })
+            .collect::<Result<Vec<_>, BallistaError>>()?;
+
+        Ok(protobuf::PhysicalExprNode {
+            expr_type: Some(protobuf::physical_expr_node::ExprType::WindowExpr(
+                Box::new(protobuf::PhysicalWindowExprNode {
+                    window_function: Some(window_function),
+                    expr,
+                    partition_by,
+                    order_by,
+                    window_frame: window_frame.map(|frame| frame.into()),
+                    args,
+                }),
+            )),
+        })
+    }
+}
+
+/// The built-in window function `built_in`, with the literal arguments following its
+/// input which it keeps as parameters, such as the offset of `LAG` or the n of `NTILE`.
+/// DataFusion does not expose these parameters, so they are read from `debug`, the
+/// `Debug` representation of the function, like the operators of binary expressions.
+fn built_in_window_function(
+    built_in: &dyn Any,
+    debug: &str,
+) -> Result<(BuiltInWindowFunction, Vec<ScalarValue>), BallistaError> {
+    use datafusion::physical_plan::expressions::{
+        CumeDist, NthValue, Ntile, Rank, RowNumber, WindowShift,
+    };
+
+    let fields = debug_fields(debug);
+    let field = |name: &str| {
+        fields.get(name).copied().ok_or_else(|| {
+            BallistaError::Internal(format!(
+                "Missing {} in window function {}",
+                name, debug
+            ))
+        })
+    };
+    let parse_int = |value: &str| {
+        value.parse::<i64>().map_err(|e| {
+            BallistaError::Internal(format!(
+                "Invalid parameter {} of window function {}: {}",
+                value, debug, e
+            ))
+        })
+    };
+    let not_supported = || {
+        BallistaError::NotImplemented(format!(
+            "Built-in window function not supported: {}",
+            debug
+        ))
+    };
+
+    if built_in.is::<RowNumber>() {
+        Ok((BuiltInWindowFunction::RowNumber, vec![]))
+    } else if built_in.is::<CumeDist>() {
+        Ok((BuiltInWindowFunction::CumeDist, vec![]))
+    } else if built_in.is::<Rank>() {
+        let function = match field("rank_type")? {
+            "Basic" => BuiltInWindowFunction::Rank,
+            "Dense" => BuiltInWindowFunction::DenseRank,
+            "Percent" => BuiltInWindowFunction::PercentRank,
+            _ => return Err(not_supported()),
+        };
+        Ok((function, vec![]))
+    } else if built_in.is::<Ntile>() {
+        let n = parse_int(field("n")?)?;
+        Ok((
+            BuiltInWindowFunction::Ntile,
+            vec![ScalarValue::Int64(Some(n))],
+        ))
+    } else if built_in.is::<WindowShift>() {
+        // LEAD keeps its offset negated
+        let shift_offset = parse_int(field("shift_offset")?)?;
+        let function = if shift_offset >= 0 {
+            BuiltInWindowFunction::Lag
+        } else {
+            BuiltInWindowFunction::Lead
+        };
+        let mut args = vec![ScalarValue::Int64(Some(shift_offset.abs()))];
+        let default_value = field("default_value")?;
+        if default_value != "None" {
+            let default_value = default_value
+                .strip_prefix("Some(")
+                .and_then(|value| value.strip_suffix(')'))
+                .ok_or_else(not_supported)?;
+            args.push(parse_debug_scalar(default_value).ok_or_else(not_supported)?);
+        }
+        Ok((function, args))
+    } else if built_in.is::<NthValue>() {
+        match field("kind")? {
+            "First" => Ok((BuiltInWindowFunction::FirstValue, vec![])),
+            "Last" => Ok((BuiltInWindowFunction::LastValue, vec![])),
+            kind => {
+                let n = kind
+                    .strip_prefix("Nth(")
+                    .and_then(|n| n.strip_suffix(')'))
+                    .ok_or_else(not_supported)?;
+                Ok((
+                    BuiltInWindowFunction::NthValue,
+                    vec![ScalarValue::Int64(Some(parse_int(n)?))],
+                ))
+            }
+        }
+    } else {
+        Err(not_supported())
+    }
+}
+
+/// The fields of `debug`, the `Debug` representation of a struct, by name
+fn debug_fields(debug: &str) -> HashMap<&str, &str> {
+    let body = match (debug.find('{'), debug.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &debug[start + 1..end],
+        _ => return HashMap::new(),
+    };
+    let mut fields = vec![];
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in body.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' if depth == 0 => {
+                fields.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    fields.push(&body[start..]);
+    fields
+        .into_iter()
+        .filter_map(|field| field.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect()
+}
+
+/// The scalar value of `debug`, its `Debug` representation such as `Int64(5)` or
+/// `Utf8("a")`, if it is of a type of the literals of SQL queries
+fn parse_debug_scalar(debug: &str) -> Option<ScalarValue> {
+    let (type_name, value) = debug.strip_suffix(')')?.split_once('(')?;
+    let data_type = match type_name {
+        "Boolean" => DataType::Boolean,
+        "Int8" => DataType::Int8,
+        "Int16" => DataType::Int16,
+        "Int32" => DataType::Int32,
+        "Int64" => DataType::Int64,
+        "UInt8" => DataType::UInt8,
+        "UInt16" => DataType::UInt16,
+        "UInt32" => DataType::UInt32,
+        "UInt64" => DataType::UInt64,
+        "Float32" => DataType::Float32,
+        "Float64" => DataType::Float64,
+        "Utf8" => DataType::Utf8,
+        "LargeUtf8" => DataType::LargeUtf8,
+        _ => return None,
+    };
+    if value == "NULL" {
+        return ScalarValue::try_from(&data_type).ok();
+    }
+    let value = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value);
+    ScalarValue::try_from_string(value.to_owned(), &data_type).ok()
+}
+
 impl TryFrom<Arc<dyn PhysicalExpr>> for protobuf::PhysicalExprNode {
     type Error = BallistaError;
 
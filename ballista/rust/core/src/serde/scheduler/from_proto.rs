@@ -18,9 +18,12 @@
 use std::convert::TryInto;
 
 use crate::error::BallistaError;
+use crate::flight_transfer::IpcCompression;
 use crate::serde::protobuf;
 use crate::serde::protobuf::action::ActionType;
-use crate::serde::scheduler::{Action, PartitionId, PartitionLocation, PartitionStats};
+use crate::serde::scheduler::{
+    Action, FetchPriority, PartitionId, PartitionLocation, PartitionStats,
+};
 
 impl TryInto<Action> for protobuf::Action {
     type Error = BallistaError;
@@ -31,7 +34,9 @@ impl TryInto<Action> for protobuf::Action {
                 job_id: fetch.job_id,
                 stage_id: fetch.stage_id as usize,
                 partition_id: fetch.partition_id as usize,
+                compression: fetch.compression().into(),
                 path: fetch.path,
+                priority: fetch.priority.map(Into::into),
             }),
             _ => Err(BallistaError::General(
                 "scheduler::from_proto(Action) invalid or missing action".to_owned(),
@@ -40,6 +45,25 @@ impl TryInto<Action> for protobuf::Action {
     }
 }
 
+impl From<protobuf::FetchPriority> for FetchPriority {
+    fn from(priority: protobuf::FetchPriority) -> Self {
+        FetchPriority {
+            job_priority: priority.job_priority,
+            stages_remaining: priority.stages_remaining,
+        }
+    }
+}
+
+impl From<protobuf::IpcCompression> for IpcCompression {
+    fn from(compression: protobuf::IpcCompression) -> Self {
+        match compression {
+            protobuf::IpcCompression::NoCompression => IpcCompression::None,
+            protobuf::IpcCompression::Lz4Frame => IpcCompression::Lz4,
+            protobuf::IpcCompression::Zstd => IpcCompression::Zstd,
+        }
+    }
+}
+
 impl TryInto<PartitionId> for protobuf::PartitionId {
     type Error = BallistaError;
 
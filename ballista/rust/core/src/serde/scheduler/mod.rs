@@ -15,7 +15,11 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::{collections::HashMap, fmt, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
 
 use datafusion::arrow::array::{
     ArrayBuilder, StructArray, StructBuilder, UInt64Array, UInt64Builder,
@@ -28,6 +32,8 @@ use serde::Serialize;
 
 use super::protobuf;
 use crate::error::BallistaError;
+use crate::flight_transfer::IpcCompression;
+use crate::protocol::ProtocolInfo;
 
 pub mod from_proto;
 pub mod to_proto;
@@ -41,9 +47,24 @@ pub enum Action {
         stage_id: usize,
         partition_id: usize,
         path: String,
+        /// Compression of the IPC messages the partition is sent in
+        compression: IpcCompression,
+        /// How critical the fetch is to the completion of its job, None for fetches of
+        /// the output of jobs, which come before all others
+        priority: Option<FetchPriority>,
     },
 }
 
+/// How critical the fetches of the partitions read by a stage are to the completion of
+/// its job
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FetchPriority {
+    /// Priority of the job, higher first
+    pub job_priority: u32,
+    /// Stages of the job left to run after the fetching stage, fewer first
+    pub stages_remaining: u32,
+}
+
 /// Unique identifier for the output partition of an operator.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PartitionId {
@@ -70,6 +91,23 @@ pub struct PartitionLocation {
     pub path: String,
 }
 
+/// Label of an executor with the name of the Kubernetes pod it runs in
+pub const POD_NAME_LABEL: &str = "k8s.pod.name";
+/// Label of an executor with the Kubernetes namespace of its pod
+pub const POD_NAMESPACE_LABEL: &str = "k8s.namespace.name";
+/// Label of an executor with the name of the Kubernetes node its pod runs on
+pub const NODE_NAME_LABEL: &str = "k8s.node.name";
+/// Label of an executor with the zone of the node it runs on
+pub const ZONE_LABEL: &str = "topology.kubernetes.io/zone";
+/// Label of an executor with the resource profile of the stages whose tasks it is
+/// offered first: `memory`, `io` or `general`
+pub const RESOURCE_PROFILE_LABEL: &str = "ballista.resource_profile";
+/// Label of an executor with the pool of executors it belongs to, whose slots only run
+/// the tasks of the jobs routed to that pool
+pub const POOL_LABEL: &str = "ballista.pool";
+/// The pool of the executors without a pool label, and of the jobs not routed to a pool
+pub const DEFAULT_POOL: &str = "default";
+
 /// Meta-data for an executor, used when fetching shuffle partitions from other executors
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct ExecutorMetadata {
@@ -78,6 +116,17 @@ pub struct ExecutorMetadata {
     pub port: u16,
     pub grpc_port: u16,
     pub specification: ExecutorSpecification,
+    /// Where the executor runs, such as the Kubernetes pod and node
+    pub labels: BTreeMap<String, String>,
+    /// Version of the task protocol spoken by the executor
+    pub protocol: ProtocolInfo,
+}
+
+impl ExecutorMetadata {
+    /// The value of label `key` of the executor, if it has that label
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.labels.get(key).map(|value| value.as_str())
+    }
 }
 
 #[allow(clippy::from_over_into)]
@@ -89,6 +138,12 @@ impl Into<protobuf::ExecutorMetadata> for ExecutorMetadata {
             port: self.port as u32,
             grpc_port: self.grpc_port as u32,
             specification: Some(self.specification.into()),
+            labels: self
+                .labels
+                .into_iter()
+                .map(|(key, value)| protobuf::KeyValuePair { key, value })
+                .collect(),
+            protocol: Some(self.protocol.into()),
         }
     }
 }
@@ -101,6 +156,12 @@ impl From<protobuf::ExecutorMetadata> for ExecutorMetadata {
             port: meta.port as u16,
             grpc_port: meta.grpc_port as u16,
             specification: meta.specification.unwrap().into(),
+            labels: meta
+                .labels
+                .into_iter()
+                .map(|pair| (pair.key, pair.value))
+                .collect(),
+            protocol: meta.protocol.into(),
         }
     }
 }
@@ -282,6 +343,18 @@ impl PartitionStats {
         }
     }
 
+    pub fn num_rows(&self) -> Option<u64> {
+        self.num_rows
+    }
+
+    pub fn num_batches(&self) -> Option<u64> {
+        self.num_batches
+    }
+
+    pub fn num_bytes(&self) -> Option<u64> {
+        self.num_bytes
+    }
+
     pub fn arrow_struct_repr(self) -> Field {
         Field::new(
             "partition_stats",
@@ -355,6 +428,15 @@ impl PartitionStats {
     }
 }
 
+impl protobuf::FailureKind {
+    /// Whether a task or job that failed for this reason may succeed if it is run again.
+    /// Failed shuffle fetches are transient, everything else would fail the same way on
+    /// retry.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, protobuf::FailureKind::FetchFailure)
+    }
+}
+
 /// Task that can be sent to an executor to execute one stage of a query and write
 /// results out to disk
 #[derive(Debug, Clone)]
@@ -18,10 +18,16 @@
 use std::convert::TryInto;
 
 use crate::error::BallistaError;
+use crate::flight_transfer::IpcCompression;
 use crate::serde::protobuf;
 use crate::serde::protobuf::action::ActionType;
-use crate::serde::scheduler::{Action, PartitionId, PartitionLocation, PartitionStats};
+use crate::serde::scheduler::{
+    Action, FetchPriority, PartitionId, PartitionLocation, PartitionStats,
+};
+use datafusion::arrow::error::ArrowError;
+use datafusion::error::DataFusionError;
 use datafusion::physical_plan::Partitioning;
+use std::error::Error;
 
 impl TryInto<protobuf::Action> for Action {
     type Error = BallistaError;
@@ -33,12 +39,16 @@ impl TryInto<protobuf::Action> for Action {
                 stage_id,
                 partition_id,
                 path,
+                compression,
+                priority,
             } => Ok(protobuf::Action {
                 action_type: Some(ActionType::FetchPartition(protobuf::FetchPartition {
                     job_id,
                     stage_id: stage_id as u32,
                     partition_id: partition_id as u32,
                     path,
+                    compression: protobuf::IpcCompression::from(compression).into(),
+                    priority: priority.map(Into::into),
                 })),
                 settings: vec![],
             }),
@@ -46,6 +56,25 @@ impl TryInto<protobuf::Action> for Action {
     }
 }
 
+impl From<FetchPriority> for protobuf::FetchPriority {
+    fn from(priority: FetchPriority) -> Self {
+        protobuf::FetchPriority {
+            job_priority: priority.job_priority,
+            stages_remaining: priority.stages_remaining,
+        }
+    }
+}
+
+impl From<IpcCompression> for protobuf::IpcCompression {
+    fn from(compression: IpcCompression) -> Self {
+        match compression {
+            IpcCompression::None => protobuf::IpcCompression::NoCompression,
+            IpcCompression::Lz4 => protobuf::IpcCompression::Lz4Frame,
+            IpcCompression::Zstd => protobuf::IpcCompression::Zstd,
+        }
+    }
+}
+
 #[allow(clippy::from_over_into)]
 impl Into<protobuf::PartitionId> for PartitionId {
     fn into(self) -> protobuf::PartitionId {
@@ -105,3 +134,88 @@ pub fn hash_partitioning_to_proto(
         }
     }
 }
+
+impl From<&BallistaError> for protobuf::FailureKind {
+    fn from(error: &BallistaError) -> Self {
+        match error {
+            BallistaError::FetchFailed(..) => protobuf::FailureKind::FetchFailure,
+            BallistaError::PlanSerdeError(..) => protobuf::FailureKind::SerdeError,
+            BallistaError::Timeout(_) => protobuf::FailureKind::Timeout,
+            BallistaError::DataFusionError(e) => e.into(),
+            BallistaError::ArrowError(ArrowError::ExternalError(e)) => {
+                external_failure_kind(e.as_ref())
+            }
+            _ => protobuf::FailureKind::ExecutionError,
+        }
+    }
+}
+
+impl From<&DataFusionError> for protobuf::FailureKind {
+    fn from(error: &DataFusionError) -> Self {
+        match error {
+            DataFusionError::ResourcesExhausted(_) => protobuf::FailureKind::OutOfMemory,
+            DataFusionError::External(e) => external_failure_kind(e.as_ref()),
+            DataFusionError::ArrowError(ArrowError::ExternalError(e)) => {
+                external_failure_kind(e.as_ref())
+            }
+            _ => protobuf::FailureKind::ExecutionError,
+        }
+    }
+}
+
+/// Errors raised while streaming record batches are boxed up as external errors, so look
+/// through them for the underlying cause
+fn external_failure_kind(error: &(dyn Error + 'static)) -> protobuf::FailureKind {
+    if let Some(e) = error.downcast_ref::<BallistaError>() {
+        e.into()
+    } else if let Some(e) = error.downcast_ref::<DataFusionError>() {
+        e.into()
+    } else if let Some(ArrowError::ExternalError(e)) = error.downcast_ref::<ArrowError>()
+    {
+        external_failure_kind(e.as_ref())
+    } else {
+        protobuf::FailureKind::ExecutionError
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_kind_from_error() {
+        let fetch_failed = BallistaError::FetchFailed(
+            "executor-1".to_owned(),
+            1,
+            2,
+            "connection refused".to_owned(),
+        );
+        assert_eq!(
+            protobuf::FailureKind::FetchFailure,
+            protobuf::FailureKind::from(&fetch_failed)
+        );
+
+        // fetch failures surface from the shuffle reader stream wrapped in other errors
+        let wrapped = BallistaError::DataFusionError(DataFusionError::ArrowError(
+            ArrowError::ExternalError(Box::new(DataFusionError::External(Box::new(
+                fetch_failed,
+            )))),
+        ));
+        let kind = protobuf::FailureKind::from(&wrapped);
+        assert_eq!(protobuf::FailureKind::FetchFailure, kind);
+        assert!(kind.is_retryable());
+
+        let oom = BallistaError::DataFusionError(DataFusionError::ResourcesExhausted(
+            "out of memory".to_owned(),
+        ));
+        let kind = protobuf::FailureKind::from(&oom);
+        assert_eq!(protobuf::FailureKind::OutOfMemory, kind);
+        assert!(!kind.is_retryable());
+
+        let general = BallistaError::General("boom".to_owned());
+        assert_eq!(
+            protobuf::FailureKind::ExecutionError,
+            protobuf::FailureKind::from(&general)
+        );
+    }
+}
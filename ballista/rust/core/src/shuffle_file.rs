@@ -0,0 +1,312 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Arrow IPC files the shuffle partitions are written to.
+//!
+//! Partitions are written in the Arrow IPC file format, which only allows a single
+//! dictionary per field. Batches of dictionary-encoded columns usually come with
+//! dictionaries of their own, so partitions with dictionary-encoded fields are written
+//! in the IPC stream format instead, which allows the dictionary of a field to change
+//! from one batch to the next. Dictionaries stay encoded through the shuffle, and one
+//! is only written again when it differs from the dictionary of the previous batch.
+//!
+//! The executors serve partitions in the stream format as they were written, message
+//! by message, without decoding them.
+//!
+//! Partitions are written under a temporary name and only renamed to their final path
+//! once complete, so that readers never see a truncated partition. Every attempt of a
+//! task writes its own temporary files, so attempts running at once each leave either
+//! nothing or a complete partition behind.
+
+use crate::error::{BallistaError, Result};
+use arrow_flight::FlightData;
+use datafusion::arrow::datatypes::{DataType, Schema};
+use datafusion::arrow::error::Result as ArrowResult;
+use datafusion::arrow::ipc::root_as_message;
+use datafusion::arrow::ipc::writer::{FileWriter, StreamWriter};
+use datafusion::arrow::record_batch::RecordBatch;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/// Magic bytes starting files in the Arrow IPC file format
+const FILE_MAGIC: &[u8; 6] = b"ARROW1";
+
+/// Marker preceding the length of each message of the Arrow IPC stream format
+const CONTINUATION_MARKER: [u8; 4] = [0xff; 4];
+
+/// Whether batches of `schema` are written in the IPC stream format
+fn has_dictionaries(schema: &Schema) -> bool {
+    schema
+        .fields()
+        .iter()
+        .any(|field| matches!(field.data_type(), DataType::Dictionary(_, _)))
+}
+
+/// Writes a shuffle partition in the Arrow IPC file format, or in the stream format if
+/// its schema has dictionary-encoded fields
+pub enum ShuffleFileWriter<W: Write> {
+    File(FileWriter<W>),
+    Stream(StreamWriter<W>),
+}
+
+impl<W: Write> ShuffleFileWriter<W> {
+    pub fn try_new(writer: W, schema: &Schema) -> ArrowResult<Self> {
+        Ok(if has_dictionaries(schema) {
+            Self::Stream(StreamWriter::try_new(writer, schema)?)
+        } else {
+            Self::File(FileWriter::try_new(writer, schema)?)
+        })
+    }
+
+    pub fn write(&mut self, batch: &RecordBatch) -> ArrowResult<()> {
+        match self {
+            Self::File(writer) => writer.write(batch),
+            Self::Stream(writer) => writer.write(batch),
+        }
+    }
+
+    pub fn finish(&mut self) -> ArrowResult<()> {
+        match self {
+            Self::File(writer) => writer.finish(),
+            Self::Stream(writer) => writer.finish(),
+        }
+    }
+}
+
+/// A shuffle partition being written to a temporary file, which is renamed to the final
+/// path of the partition on [InProgressFile::commit]. The temporary file is removed if
+/// it is dropped before.
+pub struct InProgressFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    committed: bool,
+}
+
+impl InProgressFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}.inprogress", Uuid::new_v4()));
+        Self {
+            temp_path: path.with_file_name(name),
+            path,
+            committed: false,
+        }
+    }
+
+    /// Path the partition is written to until it is committed
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Final path of the partition
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Move the complete partition to its final path, replacing the partition an
+    /// earlier attempt of the task may have written
+    pub fn commit(&mut self) -> std::io::Result<()> {
+        std::fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for InProgressFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Whether the shuffle partition read by `reader` is written in the IPC stream format.
+/// The reader is left at the start of the partition.
+pub fn is_stream_file<R: Read + Seek>(reader: &mut R) -> Result<bool> {
+    let mut magic = [0u8; 6];
+    reader.read_exact(&mut magic)?;
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(&magic != FILE_MAGIC)
+}
+
+/// Reads the messages of a shuffle partition written in the IPC stream format as
+/// [FlightData], starting with its schema
+pub struct StreamMessageReader<R: Read> {
+    reader: R,
+    finished: bool,
+}
+
+impl<R: Read> StreamMessageReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            finished: false,
+        }
+    }
+
+    /// The next message, `None` at the end of the stream
+    fn read_message(&mut self) -> Result<Option<FlightData>> {
+        let mut word = [0u8; 4];
+        self.reader.read_exact(&mut word)?;
+        if word == CONTINUATION_MARKER {
+            self.reader.read_exact(&mut word)?;
+        }
+        let metadata_len = i32::from_le_bytes(word);
+        if metadata_len <= 0 {
+            return Ok(None);
+        }
+
+        let mut data_header = vec![0u8; metadata_len as usize];
+        self.reader.read_exact(&mut data_header)?;
+        let body_len = root_as_message(&data_header)
+            .map_err(|e| BallistaError::General(format!("Invalid IPC message: {:?}", e)))?
+            .bodyLength();
+
+        let mut data_body = vec![0u8; body_len as usize];
+        self.reader.read_exact(&mut data_body)?;
+        Ok(Some(FlightData {
+            data_header,
+            data_body,
+            ..Default::default()
+        }))
+    }
+}
+
+impl<R: Read> Iterator for StreamMessageReader<R> {
+    type Item = Result<FlightData>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let message = self.read_message();
+        self.finished = !matches!(message, Ok(Some(_)));
+        message.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::decode_flight_data;
+    use datafusion::arrow::array::{ArrayRef, DictionaryArray, Int32Array};
+    use datafusion::arrow::datatypes::{Field, Int32Type};
+    use datafusion::arrow::ipc::reader::FileReader;
+    use std::collections::HashMap;
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    fn batch(schema: &Arc<Schema>, values: Vec<&str>) -> RecordBatch {
+        let array: DictionaryArray<Int32Type> = values.into_iter().collect();
+        RecordBatch::try_new(schema.clone(), vec![Arc::new(array)]).unwrap()
+    }
+
+    #[test]
+    fn keep_dictionaries_encoded() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "a",
+            DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+            false,
+        )]));
+
+        // Each batch has a dictionary of its own
+        let mut file = tempfile::tempfile()?;
+        let mut writer = ShuffleFileWriter::try_new(&file, &schema)?;
+        writer.write(&batch(&schema, vec!["x", "y", "x"]))?;
+        writer.write(&batch(&schema, vec!["z", "z"]))?;
+        writer.finish()?;
+        drop(writer);
+
+        assert!(is_stream_file(&mut file)?);
+        let mut messages = StreamMessageReader::new(file);
+        let schema = Arc::new(Schema::try_from(&messages.next().unwrap()?)?);
+
+        let mut dictionaries_by_id: HashMap<i64, ArrayRef> = HashMap::new();
+        let mut batches = vec![];
+        for message in messages {
+            if let Some(batch) =
+                decode_flight_data(&message?, &schema, &mut dictionaries_by_id)?
+            {
+                batches.push(batch);
+            }
+        }
+        assert_eq!(batches.len(), 2);
+        for batch in &batches {
+            assert!(matches!(
+                batch.column(0).data_type(),
+                DataType::Dictionary(_, _)
+            ));
+        }
+        let values = batches[1]
+            .column(0)
+            .as_any()
+            .downcast_ref::<DictionaryArray<Int32Type>>()
+            .unwrap()
+            .values()
+            .len();
+        assert_eq!(values, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn write_file_format_without_dictionaries() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let mut file = tempfile::tempfile()?;
+        let mut writer = ShuffleFileWriter::try_new(&file, &schema)?;
+        writer.write(&RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int32Array::from(vec![1, 2, 3]))],
+        )?)?;
+        writer.finish()?;
+        drop(writer);
+
+        assert!(!is_stream_file(&mut file)?);
+        let num_rows: usize = FileReader::try_new(file, None)?
+            .map(|batch| batch.map(|batch| batch.num_rows()))
+            .sum::<std::result::Result<_, _>>()?;
+        assert_eq!(num_rows, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn commit_complete_partitions_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("data.arrow");
+
+        // An attempt which fails leaves nothing behind
+        let file = InProgressFile::new(&path);
+        std::fs::write(file.temp_path(), b"partial")?;
+        drop(file);
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+
+        // Attempts write to files of their own, and the last to commit wins
+        let mut first = InProgressFile::new(&path);
+        let mut second = InProgressFile::new(&path);
+        assert_ne!(first.temp_path(), second.temp_path());
+        std::fs::write(first.temp_path(), b"first")?;
+        std::fs::write(second.temp_path(), b"second")?;
+        assert!(!path.exists());
+        first.commit()?;
+        second.commit()?;
+        assert_eq!(std::fs::read(&path)?, b"second");
+        drop((first, second));
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tables cached on the executors with `CACHE TABLE`.
+//!
+//! The output of the query of a cached table is kept by the executors which ran the last
+//! stage of its job, as the Arrow IPC files of the partitions of the stage, and the
+//! scheduler records which executor keeps each partition. Clients register cached
+//! tables as [super::ExternalTable]s of the `cache` format, which only the scheduler can
+//! open: scans of a [CachedTable] read its partitions from the executors keeping them
+//! instead of from the object store.
+//!
+//! Temporary tables, created with `CREATE TEMPORARY TABLE`, are cached tables of a
+//! session: clients register them at [temporary_table_location] instead of their name,
+//! and they are dropped when the session is closed.
+//!
+//! The scheduler saves cached tables under [cached_table_key], so that a cached table is
+//! only visible to the user who cached it, and a temporary table only to its session.
+
+use crate::execution_plans::ShuffleReaderExec;
+use crate::serde::scheduler::PartitionLocation;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::Result;
+use datafusion::execution::context::SessionState;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::{ExecutionPlan, PhysicalExpr};
+use std::any::Any;
+use std::sync::Arc;
+
+/// Name of the table format of cached tables
+pub const CACHE: &str = "cache";
+
+/// Location of temporary table `name` of session `session_id`
+pub fn temporary_table_location(session_id: &str, name: &str) -> String {
+    format!("{}/{}", session_id, name)
+}
+
+/// Key under which the scheduler saves the cached table at `location`, cached or scanned
+/// by a client of session `session_id` authenticated as `owner`. Locations are only
+/// those of temporary tables when they are prefixed with the ID of the session itself,
+/// so clients can not reach the temporary tables of other sessions.
+pub fn cached_table_key(owner: Option<&str>, session_id: &str, location: &str) -> String {
+    let owner = match owner {
+        Some(user) => format!("user:{}", encode(user)),
+        None => "anonymous".to_owned(),
+    };
+    match location.strip_prefix(&temporary_table_location(session_id, "")) {
+        Some(name) => format!("{}{}/{}", session_tables_prefix(session_id), owner, name),
+        None => format!("table/{}/{}", owner, location),
+    }
+}
+
+/// Prefix of the keys of the temporary tables of session `session_id`
+pub fn session_tables_prefix(session_id: &str) -> String {
+    format!("session/{}/", encode(session_id))
+}
+
+/// Name of the temporary table saved under `key` by session `session_id`, if it is one
+pub fn temporary_table_name<'a>(key: &'a str, session_id: &str) -> Option<&'a str> {
+    key.strip_prefix(&session_tables_prefix(session_id))
+        .and_then(|scoped| scoped.split_once('/'))
+        .map(|(_, name)| name)
+}
+
+/// `value` with its `/` escaped, so it can be a single segment of a key
+fn encode(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+}
+
+/// A table whose partitions are kept by executors
+pub struct CachedTable {
+    schema: SchemaRef,
+    partitions: Vec<PartitionLocation>,
+}
+
+impl CachedTable {
+    pub fn new(schema: SchemaRef, partitions: Vec<PartitionLocation>) -> Self {
+        Self { schema, partitions }
+    }
+
+    pub fn partitions(&self) -> &[PartitionLocation] {
+        &self.partitions
+    }
+}
+
+#[async_trait]
+impl TableProvider for CachedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let reader = Arc::new(ShuffleReaderExec::try_new(
+            self.partitions
+                .iter()
+                .map(|location| vec![location.clone()])
+                .collect(),
+            self.schema.clone(),
+        )?);
+        match projection {
+            Some(columns) => {
+                let exprs = columns
+                    .iter()
+                    .map(|i| {
+                        let name = self.schema.field(*i).name();
+                        let column: Arc<dyn PhysicalExpr> =
+                            Arc::new(Column::new(name, *i));
+                        (column, name.to_owned())
+                    })
+                    .collect();
+                Ok(Arc::new(ProjectionExec::try_new(exprs, reader)?))
+            }
+            None => Ok(reader),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::scheduler::{
+        ExecutorMetadata, ExecutorSpecification, PartitionId, PartitionStats,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::prelude::SessionContext;
+
+    #[test]
+    fn scope_cached_table_keys() {
+        let temporary = temporary_table_location("session-1", "recent");
+        let key = cached_table_key(Some("alice"), "session-1", &temporary);
+        assert_eq!(key, "session/session-1/user:alice/recent");
+        assert_eq!(temporary_table_name(&key, "session-1"), Some("recent"));
+        assert_eq!(temporary_table_name(&key, "session-2"), None);
+
+        // Another session can not reach the temporary tables of the first one
+        let key = cached_table_key(Some("alice"), "session-2", &temporary);
+        assert_eq!(key, "table/user:alice/session-1/recent");
+        assert_eq!(
+            cached_table_key(Some("bob"), "session-1", "trips"),
+            "table/user:bob/trips"
+        );
+        assert_eq!(
+            cached_table_key(Some("a/b"), "session-1", "trips"),
+            "table/user:a%2Fb/trips"
+        );
+        assert_eq!(
+            cached_table_key(None, "session-1", "trips"),
+            "table/anonymous/trips"
+        );
+    }
+
+    #[tokio::test]
+    async fn scan_cached_partitions() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Int32, false),
+            Field::new("b", DataType::Utf8, true),
+        ]));
+        let location = |partition_id| PartitionLocation {
+            partition_id: PartitionId::new("job", 2, partition_id),
+            executor_meta: ExecutorMetadata {
+                id: "executor".to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+                grpc_port: 50052,
+                specification: ExecutorSpecification { task_slots: 4 },
+                labels: Default::default(),
+                protocol: Default::default(),
+            },
+            partition_stats: PartitionStats::default(),
+            path: format!("/tmp/job/2/{}/data.arrow", partition_id),
+        };
+        let table = CachedTable::new(schema, vec![location(0), location(1)]);
+
+        let state = SessionContext::new().state.read().clone();
+        let plan = table.scan(&state, &Some(vec![1]), &[], None).await?;
+        assert_eq!(plan.output_partitioning().partition_count(), 2);
+        assert_eq!(plan.schema().fields().len(), 1);
+        assert_eq!(plan.schema().field(0).name(), "b");
+        assert!(plan.children()[0]
+            .as_any()
+            .downcast_ref::<ShuffleReaderExec>()
+            .is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,1316 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Delta Lake tables.
+//!
+//! A table is resolved by replaying its transaction log in `_delta_log`: the latest
+//! checkpoint, if there is one, and the JSON commits after it. The data files of the
+//! table are scanned with a [ParquetExec], which carries the partition values of every
+//! file to the executors. Files are pruned by the filters on partition columns when
+//! planning, and row groups by the remaining filters when scanning.
+//!
+//! DELETE and UPDATE statements rewrite the files which may hold the rows they change,
+//! as pruned by the partition values of the files. The scheduler commits the new files
+//! in place of the rewritten ones as the next version of the table, which it creates
+//! only if no other writer created it first, so concurrent changes fail instead of being
+//! lost. Object stores which cannot copy a file only if the destination does not exist,
+//! such as S3, only keep the commits of the schedulers of the cluster from overwriting
+//! each other, as they hold a lock on the table while committing, not those of other
+//! writers.
+//!
+//! Tables up to writer version 2 can be changed. The rows of append-only tables, and of
+//! tables with invariants, which are not checked against the written rows, cannot be
+//! changed, but their files can be compacted.
+//!
+//! `OPTIMIZE TABLE` compacts the files smaller than a target size the same way. The
+//! small files of each partition are packed into groups of about the target size, and
+//! each group is scanned by a task which writes its rows to a single file.
+//!
+//! Deletion vectors and column mapping are not supported.
+
+use super::{
+    copy_if_not_exists, SavedCommit, TableCommit, TableFormat, TableRewrite, TableVersion,
+};
+use crate::execution_plans::WrittenFile;
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::arrow::json::writer::record_batches_to_json_rows;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_expr::TableProviderFilterPushDown;
+use datafusion::logical_plan::{Expr, ExpressionVisitor, Operator, Recursion};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::file_format::{FileScanConfig, ParquetExec};
+use datafusion::physical_plan::{ExecutionPlan, Statistics};
+use datafusion::prelude::{ParquetReadOptions, SessionConfig, SessionContext};
+use datafusion::scalar::ScalarValue;
+use futures::TryStreamExt;
+use log::warn;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOG_DIR: &str = "_delta_log";
+
+/// Opens Delta Lake tables. The `version` option selects the version of the table to
+/// read instead of the latest one, and `as_of_timestamp` the latest version committed at
+/// or before a time in milliseconds since the epoch.
+pub struct DeltaFormat;
+
+#[async_trait]
+impl TableFormat for DeltaFormat {
+    async fn open(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let version = match (options.get("version"), options.get("as_of_timestamp")) {
+            (Some(_), Some(_)) => {
+                return Err(DataFusionError::Plan(
+                    "Only one of version and as_of_timestamp can be set".to_owned(),
+                ))
+            }
+            (Some(version), None) => Some(version.parse::<i64>().map_err(|_| {
+                DataFusionError::Plan(format!("Invalid Delta table version {}", version))
+            })?),
+            (None, Some(timestamp)) => {
+                let timestamp = timestamp.parse::<i64>().map_err(|_| {
+                    DataFusionError::Plan(format!(
+                        "Invalid Delta table timestamp {}",
+                        timestamp
+                    ))
+                })?;
+                Some(DeltaTable::version_at(location, timestamp, runtime.clone()).await?)
+            }
+            (None, None) => None,
+        };
+        Ok(Arc::new(
+            DeltaTable::load(location, version, runtime).await?,
+        ))
+    }
+
+    fn options_as_of(
+        &self,
+        options: &BTreeMap<String, String>,
+        version: TableVersion,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut options = options.clone();
+        options.remove("version");
+        options.remove("as_of_timestamp");
+        match version {
+            TableVersion::Version(version) => {
+                options.insert("version".to_owned(), version.to_string())
+            }
+            TableVersion::Timestamp(timestamp) => {
+                options.insert("as_of_timestamp".to_owned(), timestamp.to_string())
+            }
+        };
+        Ok(options)
+    }
+
+    async fn rewrite(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        filters: &[Expr],
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        if options.contains_key("version") || options.contains_key("as_of_timestamp") {
+            return Err(DataFusionError::Plan(
+                "Past versions of Delta tables cannot be changed".to_owned(),
+            ));
+        }
+        let mut table = DeltaTable::load(location, None, runtime).await?;
+        table.check_writable(location, true)?;
+        let partition_columns = table.partition_columns.clone();
+        table.files.retain(|file| {
+            !filters.iter().any(|filter| {
+                prune(filter, &partition_columns, &file.partition_values) == Some(false)
+            })
+        });
+        let commit = DeltaCommit {
+            location: location.to_owned(),
+            version: table.version,
+            removed: table.files.clone(),
+            data_change: true,
+        };
+        Ok(TableRewrite {
+            location: location.to_owned(),
+            partition_by: table.partition_columns.clone(),
+            repartition: true,
+            field_ids: HashMap::new(),
+            commit: Arc::new(commit),
+            table: Arc::new(table),
+        })
+    }
+
+    async fn compact(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        target_file_bytes: u64,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        if options.contains_key("version") || options.contains_key("as_of_timestamp") {
+            return Err(DataFusionError::Plan(
+                "Past versions of Delta tables cannot be changed".to_owned(),
+            ));
+        }
+        let mut table = DeltaTable::load(location, None, runtime).await?;
+        table.check_writable(location, false)?;
+        let groups = compaction_groups(&table.files, target_file_bytes as usize);
+        table.files = groups.iter().flatten().cloned().collect();
+        table.file_groups = Some(groups);
+        let commit = DeltaCommit {
+            location: location.to_owned(),
+            version: table.version,
+            removed: table.files.clone(),
+            data_change: false,
+        };
+        Ok(TableRewrite {
+            location: location.to_owned(),
+            partition_by: table.partition_columns.clone(),
+            repartition: false,
+            field_ids: HashMap::new(),
+            commit: Arc::new(commit),
+            table: Arc::new(table),
+        })
+    }
+}
+
+/// The files smaller than `target_size` packed into groups of files of the same
+/// partition, each of about `target_size` bytes. Files which would be alone in their
+/// group are not compacted.
+fn compaction_groups(files: &[DeltaFile], target_size: usize) -> Vec<Vec<DeltaFile>> {
+    let mut partitions: BTreeMap<Vec<(String, Option<String>)>, Vec<&DeltaFile>> =
+        BTreeMap::new();
+    for file in files.iter().filter(|file| file.size < target_size) {
+        let mut values: Vec<(String, Option<String>)> = file
+            .partition_values
+            .iter()
+            .map(|(column, value)| (column.clone(), value.clone()))
+            .collect();
+        values.sort_unstable();
+        partitions.entry(values).or_default().push(file);
+    }
+
+    let mut groups = vec![];
+    for (_, mut files) in partitions {
+        // Packing the largest files first leaves the smallest to fill up the groups
+        files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        let mut group: Vec<DeltaFile> = vec![];
+        let mut group_size = 0;
+        for file in files {
+            if group_size + file.size > target_size && !group.is_empty() {
+                groups.push(std::mem::take(&mut group));
+                group_size = 0;
+            }
+            group_size += file.size;
+            group.push(file.clone());
+        }
+        groups.push(group);
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Commits the files of a rewrite of a Delta table as the version after the one the
+/// rewritten files were read from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct DeltaCommit {
+    location: String,
+    /// The version the rewrite was planned with
+    version: i64,
+    /// The rewritten files
+    removed: Vec<DeltaFile>,
+    /// Whether the commit changes the rows of the table, rather than only their files
+    data_change: bool,
+}
+
+impl DeltaCommit {
+    /// The actions of the commit, one JSON object per line
+    fn actions(&self, written: &[WrittenFile], timestamp: i64) -> String {
+        let removes = self.removed.iter().map(|file| {
+            serde_json::json!({"remove": {
+                "path": file.path,
+                "deletionTimestamp": timestamp,
+                "dataChange": self.data_change,
+                "extendedFileMetadata": true,
+                "partitionValues": file.partition_values,
+                "size": file.size,
+            }})
+        });
+        let adds = written.iter().map(|file| {
+            serde_json::json!({"add": {
+                "path": file.path,
+                "partitionValues": file.partition_values,
+                "size": file.size,
+                "modificationTime": timestamp,
+                "dataChange": self.data_change,
+                "stats": serde_json::json!({"numRecords": file.num_rows}).to_string(),
+            }})
+        });
+        removes
+            .chain(adds)
+            .map(|action| action.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[async_trait]
+impl TableCommit for DeltaCommit {
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    async fn commit(&self, written: &[WrittenFile], runtime: &RuntimeEnv) -> Result<()> {
+        if self.removed.is_empty() && written.is_empty() {
+            return Ok(());
+        }
+        let url = ListingTableUrl::parse(&self.location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let log_dir = url.prefix().child(LOG_DIR);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis() as i64)
+            .unwrap_or_default();
+
+        // The commit is written aside, then copied to the file of its version unless
+        // another writer committed that version first
+        let staged = log_dir.child(format!("_commit_{}.json.tmp", uuid::Uuid::new_v4()));
+        let commit = log_dir.child(format!("{:020}.json", self.version + 1));
+        store
+            .put(&staged, self.actions(written, timestamp).into())
+            .await
+            .map_err(external_error)?;
+        let copied = copy_if_not_exists(store.as_ref(), &staged, &commit).await;
+        if let Err(e) = store.delete(&staged).await {
+            warn!("Could not delete {}: {}", staged, e);
+        }
+        match copied {
+            Ok(()) => Ok(()),
+            Err(object_store::Error::AlreadyExists { .. }) => {
+                Err(DataFusionError::Execution(format!(
+                    "Delta table {} was changed since version {}, which the statement \
+                     read",
+                    self.location, self.version
+                )))
+            }
+            Err(e) => Err(external_error(e)),
+        }
+    }
+
+    fn to_json(&self) -> Result<Value> {
+        SavedCommit::Delta(self.clone()).to_json()
+    }
+}
+
+/// A data file of a Delta table
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct DeltaFile {
+    /// Path relative to the root of the table, or an absolute URL
+    path: String,
+    size: usize,
+    /// Milliseconds since the epoch
+    modification_time: i64,
+    partition_values: HashMap<String, Option<String>>,
+}
+
+/// The state of a Delta table at one version
+#[derive(Debug, Default)]
+struct Snapshot {
+    version: i64,
+    schema_string: Option<String>,
+    partition_columns: Vec<String>,
+    configuration: HashMap<String, String>,
+    min_writer_version: i64,
+    files: BTreeMap<String, DeltaFile>,
+}
+
+impl Snapshot {
+    /// Apply an action of the transaction log
+    fn apply(&mut self, action: &Value) -> Result<()> {
+        if let Some(add) = action.get("add") {
+            let file = DeltaFile {
+                path: string_field(add, "path")?,
+                size: add.get("size").and_then(Value::as_u64).unwrap_or_default()
+                    as usize,
+                modification_time: add
+                    .get("modificationTime")
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default(),
+                partition_values: add
+                    .get("partitionValues")
+                    .and_then(Value::as_object)
+                    .map(|values| {
+                        values
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.as_str().map(str::to_owned)))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            };
+            self.files.insert(file.path.clone(), file);
+        } else if let Some(remove) = action.get("remove") {
+            self.files.remove(&string_field(remove, "path")?);
+        } else if let Some(metadata) = action.get("metaData") {
+            self.schema_string = Some(string_field(metadata, "schemaString")?);
+            self.partition_columns = metadata
+                .get("partitionColumns")
+                .and_then(Value::as_array)
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .filter_map(|c| c.as_str().map(str::to_owned))
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.configuration = metadata
+                .get("configuration")
+                .and_then(Value::as_object)
+                .map(|configuration| {
+                    configuration
+                        .iter()
+                        .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_owned())))
+                        .collect()
+                })
+                .unwrap_or_default();
+        } else if let Some(protocol) = action.get("protocol") {
+            self.min_writer_version = protocol
+                .get("minWriterVersion")
+                .and_then(Value::as_i64)
+                .unwrap_or(1);
+            let min_reader_version = protocol
+                .get("minReaderVersion")
+                .and_then(Value::as_i64)
+                .unwrap_or(1);
+            if min_reader_version > 1 {
+                return Err(DataFusionError::NotImplemented(format!(
+                    "Delta tables with reader version {} are not supported",
+                    min_reader_version
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a field of the Delta schema `value` has an invariant
+fn has_invariants(value: &Value) -> bool {
+    match value {
+        Value::Object(object) => object.iter().any(|(key, value)| {
+            (key == "metadata" && value.get("delta.invariants").is_some())
+                || has_invariants(value)
+        }),
+        Value::Array(values) => values.iter().any(has_invariants),
+        _ => false,
+    }
+}
+
+fn string_field(value: &Value, name: &str) -> Result<String> {
+    value
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| {
+            DataFusionError::Execution(format!("Delta log action without {}", name))
+        })
+}
+
+fn external_error(e: object_store::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+/// A Delta table at one version
+pub struct DeltaTable {
+    url: ListingTableUrl,
+    version: i64,
+    schema: SchemaRef,
+    /// Schema of the data files, which do not contain the partition columns
+    file_schema: SchemaRef,
+    partition_columns: Vec<String>,
+    files: Vec<DeltaFile>,
+    /// Groups of `files` each scanned by a single task, instead of as many groups as the
+    /// target partitions
+    file_groups: Option<Vec<Vec<DeltaFile>>>,
+    /// The writer version of the protocol of the table
+    min_writer_version: i64,
+    /// Whether files can only be added to the table
+    append_only: bool,
+    /// Whether columns of the table have invariants
+    has_invariants: bool,
+}
+
+impl DeltaTable {
+    /// Load the table at `location` at `version`, or at the latest version if `None`
+    pub async fn load(
+        location: &str,
+        version: Option<i64>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Self> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let log_dir = url.prefix().child(LOG_DIR);
+
+        let latest =
+            latest_version(store.as_ref(), &log_dir)
+                .await?
+                .ok_or_else(|| {
+                    DataFusionError::Plan(format!("{} is not a Delta table", location))
+                })?;
+        let version = match version {
+            Some(version) if version > latest => {
+                return Err(DataFusionError::Plan(format!(
+                    "Delta table {} has no version {}, the latest is {}",
+                    location, version, latest
+                )))
+            }
+            Some(version) => version,
+            None => latest,
+        };
+
+        let mut snapshot = Snapshot::default();
+        let mut next = 0;
+        if let Some((checkpoint, parts)) =
+            last_checkpoint(store.as_ref(), &log_dir).await?
+        {
+            if checkpoint <= version {
+                for action in
+                    read_checkpoint(location, checkpoint, parts, &runtime).await?
+                {
+                    snapshot.apply(&action)?;
+                }
+                next = checkpoint + 1;
+            }
+        }
+        for commit in next..=version {
+            let path = log_dir.child(format!("{:020}.json", commit));
+            let bytes = store
+                .get(&path)
+                .await
+                .map_err(external_error)?
+                .bytes()
+                .await
+                .map_err(external_error)?;
+            for line in String::from_utf8_lossy(&bytes).lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let action: Value = serde_json::from_str(line).map_err(|e| {
+                    DataFusionError::Execution(format!(
+                        "Invalid Delta log entry in {}: {}",
+                        path, e
+                    ))
+                })?;
+                snapshot.apply(&action)?;
+            }
+        }
+        snapshot.version = version;
+
+        let schema_string = snapshot.schema_string.as_ref().ok_or_else(|| {
+            DataFusionError::Execution(format!("Delta table {} has no schema", location))
+        })?;
+        let schema: Value = serde_json::from_str(schema_string).map_err(|e| {
+            DataFusionError::Execution(format!("Invalid Delta table schema: {}", e))
+        })?;
+        let fields = match arrow_type(&schema)? {
+            DataType::Struct(fields) => fields,
+            _ => {
+                return Err(DataFusionError::Execution(
+                    "Delta table schema is not a struct".to_owned(),
+                ))
+            }
+        };
+
+        // Like the listing tables of DataFusion, partition columns come after the columns
+        // of the data files
+        let (partition_fields, file_fields): (Vec<Field>, Vec<Field>) = fields
+            .into_iter()
+            .partition(|field| snapshot.partition_columns.contains(field.name()));
+        let file_schema = Arc::new(Schema::new(file_fields.clone()));
+        let mut table_fields = file_fields;
+        for column in &snapshot.partition_columns {
+            if !partition_fields.iter().any(|field| field.name() == column) {
+                return Err(DataFusionError::Execution(format!(
+                    "Partition column {} is not in the schema of the Delta table",
+                    column
+                )));
+            }
+            table_fields.push(Field::new(
+                column,
+                DataType::Dictionary(
+                    Box::new(DataType::UInt16),
+                    Box::new(DataType::Utf8),
+                ),
+                true,
+            ));
+        }
+
+        Ok(Self {
+            url,
+            version: snapshot.version,
+            schema: Arc::new(Schema::new(table_fields)),
+            file_schema,
+            partition_columns: snapshot.partition_columns,
+            files: snapshot.files.into_values().collect(),
+            file_groups: None,
+            min_writer_version: snapshot.min_writer_version,
+            append_only: snapshot
+                .configuration
+                .get("delta.appendOnly")
+                .map_or(false, |value| value.eq_ignore_ascii_case("true")),
+            has_invariants: has_invariants(&schema),
+        })
+    }
+
+    /// The latest version of the table at `location` committed at or before `timestamp`,
+    /// in milliseconds since the epoch
+    pub async fn version_at(
+        location: &str,
+        timestamp: i64,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<i64> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let log_dir = url.prefix().child(LOG_DIR);
+        commits(store.as_ref(), &log_dir)
+            .await?
+            .into_iter()
+            .filter(|(_, commit)| commit.last_modified.timestamp_millis() <= timestamp)
+            .map(|(version, _)| version)
+            .max()
+            .ok_or_else(|| {
+                DataFusionError::Plan(format!(
+                    "Delta table {} has no version at or before {}",
+                    location,
+                    Utc.timestamp_millis(timestamp)
+                ))
+            })
+    }
+
+    pub fn version(&self) -> i64 {
+        self.version
+    }
+
+    /// Check that the files of the table at `location` can be rewritten, changing its
+    /// rows if `data_change`
+    fn check_writable(&self, location: &str, data_change: bool) -> Result<()> {
+        if self.min_writer_version > 2 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Delta tables with writer version {} cannot be changed",
+                self.min_writer_version
+            )));
+        }
+        if data_change && self.append_only {
+            return Err(DataFusionError::Plan(format!(
+                "Delta table {} is append-only, its rows cannot be changed",
+                location
+            )));
+        }
+        if data_change && self.has_invariants {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Delta table {} has invariants, the rows of such tables cannot be changed",
+                location
+            )));
+        }
+        Ok(())
+    }
+
+    fn partitioned_file(&self, file: &DeltaFile) -> Result<PartitionedFile> {
+        let location = if file.path.contains("://") {
+            let url = url::Url::parse(&file.path).map_err(|e| {
+                DataFusionError::Execution(format!("Invalid Delta file URL: {}", e))
+            })?;
+            Path::from_url_path(url.path())
+        } else {
+            Path::from_url_path(format!("{}/{}", self.url.prefix(), file.path))
+        }
+        .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        Ok(PartitionedFile {
+            object_meta: ObjectMeta {
+                location,
+                last_modified: Utc.timestamp_millis(file.modification_time),
+                size: file.size,
+            },
+            partition_values: self
+                .partition_columns
+                .iter()
+                .map(|column| {
+                    ScalarValue::Utf8(
+                        file.partition_values.get(column).cloned().flatten(),
+                    )
+                })
+                .collect(),
+            range: None,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for DeltaTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let scanned_files = |files: &[DeltaFile]| {
+            files
+                .iter()
+                .filter(|file| {
+                    !filters.iter().any(|filter| {
+                        prune(filter, &self.partition_columns, &file.partition_values)
+                            == Some(false)
+                    })
+                })
+                .map(|file| self.partitioned_file(file))
+                .collect::<Result<Vec<_>>>()
+        };
+        let file_groups: Vec<Vec<PartitionedFile>> = match &self.file_groups {
+            Some(groups) => groups
+                .iter()
+                .map(|group| scanned_files(group))
+                .collect::<Result<Vec<_>>>()?
+                .into_iter()
+                .filter(|group| !group.is_empty())
+                .collect(),
+            None => {
+                let files = scanned_files(&self.files)?;
+                let target_partitions = ctx.config.target_partitions.max(1);
+                let chunk_size =
+                    (files.len() + target_partitions - 1) / target_partitions;
+                files
+                    .chunks(chunk_size.max(1))
+                    .map(|chunk| chunk.to_vec())
+                    .collect()
+            }
+        };
+        if file_groups.is_empty() {
+            let schema = match projection {
+                Some(columns) => Arc::new(self.schema.project(columns)?),
+                None => self.schema.clone(),
+            };
+            return Ok(Arc::new(EmptyExec::new(false, schema)));
+        }
+
+        // The pruning predicate is evaluated against the statistics of the data files,
+        // which do not contain the partition columns
+        let predicate = filters
+            .iter()
+            .filter(|filter| !references_any(filter, &self.partition_columns))
+            .cloned()
+            .reduce(|a, b| a.and(b));
+
+        Ok(Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: self.url.object_store(),
+                file_schema: self.file_schema.clone(),
+                file_groups,
+                statistics: Statistics::default(),
+                projection: projection.clone(),
+                limit,
+                table_partition_cols: self.partition_columns.clone(),
+            },
+            predicate,
+            None,
+        )))
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        _filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown> {
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// Evaluate `filter` against the partition values of a file. Returns `Some(false)` if no
+/// row of the file can match, and `None` if that cannot be decided from the partition
+/// values alone.
+pub(super) fn prune(
+    filter: &Expr,
+    partition_columns: &[String],
+    values: &HashMap<String, Option<String>>,
+) -> Option<bool> {
+    let value_of = |expr: &Expr| match expr {
+        Expr::Column(column) if partition_columns.contains(&column.name) => {
+            Some(values.get(&column.name).cloned().flatten())
+        }
+        _ => None,
+    };
+    let literal = |expr: &Expr| match expr {
+        Expr::Literal(value) if !value.is_null() => Some(value.to_string()),
+        _ => None,
+    };
+    match filter {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            let left = prune(left.as_ref(), partition_columns, values);
+            let right = prune(right.as_ref(), partition_columns, values);
+            if left == Some(false) || right == Some(false) {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        Expr::BinaryExpr { left, op, right }
+            if matches!(op, Operator::Eq | Operator::NotEq) =>
+        {
+            let (left, right) = (left.as_ref(), right.as_ref());
+            let (value, literal) = match (value_of(left), literal(right)) {
+                (Some(value), Some(literal)) => (value, literal),
+                _ => (value_of(right)?, literal(left)?),
+            };
+            // Comparisons with null are never true
+            let value = value?;
+            Some((value == literal) == (*op == Operator::Eq))
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated: false,
+        } => {
+            let value = value_of(expr.as_ref())?;
+            let literals = list.iter().map(literal).collect::<Option<Vec<_>>>()?;
+            Some(value.map_or(false, |value| literals.contains(&value)))
+        }
+        Expr::IsNull(expr) => Some(value_of(expr.as_ref())?.is_none()),
+        Expr::IsNotNull(expr) => Some(value_of(expr.as_ref())?.is_some()),
+        _ => None,
+    }
+}
+
+/// Whether `expr` references any of `columns`
+pub(super) fn references_any(expr: &Expr, columns: &[String]) -> bool {
+    let mut found = false;
+    let _ = expr.accept(ColumnFinder {
+        columns,
+        found: &mut found,
+    });
+    found
+}
+
+struct ColumnFinder<'a> {
+    columns: &'a [String],
+    found: &'a mut bool,
+}
+
+impl<'a> ExpressionVisitor for ColumnFinder<'a> {
+    fn pre_visit(self, expr: &Expr) -> Result<Recursion<Self>> {
+        if let Expr::Column(column) = expr {
+            if self.columns.contains(&column.name) {
+                *self.found = true;
+                return Ok(Recursion::Stop(self));
+            }
+        }
+        Ok(Recursion::Continue(self))
+    }
+}
+
+/// The latest version in the log, `None` if there is no log
+async fn latest_version(store: &dyn ObjectStore, log_dir: &Path) -> Result<Option<i64>> {
+    Ok(commits(store, log_dir)
+        .await?
+        .into_iter()
+        .map(|(version, _)| version)
+        .max())
+}
+
+/// The commits of the log, with their versions
+async fn commits(
+    store: &dyn ObjectStore,
+    log_dir: &Path,
+) -> Result<Vec<(i64, ObjectMeta)>> {
+    let files: Vec<ObjectMeta> = store
+        .list(Some(log_dir))
+        .await
+        .map_err(external_error)?
+        .try_collect()
+        .await
+        .map_err(external_error)?;
+    Ok(files
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.location.as_ref().rsplit('/').next()?;
+            let version = name.strip_suffix(".json")?.parse::<i64>().ok()?;
+            Some((version, file))
+        })
+        .collect())
+}
+
+/// The version and number of parts of the latest checkpoint, if any
+async fn last_checkpoint(
+    store: &dyn ObjectStore,
+    log_dir: &Path,
+) -> Result<Option<(i64, Option<i64>)>> {
+    let bytes = match store.get(&log_dir.child("_last_checkpoint")).await {
+        Ok(result) => result.bytes().await.map_err(external_error)?,
+        Err(object_store::Error::NotFound { .. }) => return Ok(None),
+        Err(e) => return Err(external_error(e)),
+    };
+    let checkpoint: Value = serde_json::from_slice(&bytes).map_err(|e| {
+        DataFusionError::Execution(format!("Invalid Delta checkpoint: {}", e))
+    })?;
+    Ok(checkpoint
+        .get("version")
+        .and_then(Value::as_i64)
+        .map(|version| (version, checkpoint.get("parts").and_then(Value::as_i64))))
+}
+
+/// The actions of a checkpoint, which is a Parquet file with a column per action type
+async fn read_checkpoint(
+    location: &str,
+    version: i64,
+    parts: Option<i64>,
+    runtime: &Arc<RuntimeEnv>,
+) -> Result<Vec<Value>> {
+    let log_dir = format!("{}/{}", location.trim_end_matches('/'), LOG_DIR);
+    let paths: Vec<String> = match parts {
+        None => vec![format!("{}/{:020}.checkpoint.parquet", log_dir, version)],
+        Some(parts) => (1..=parts)
+            .map(|part| {
+                format!(
+                    "{}/{:020}.checkpoint.{:010}.{:010}.parquet",
+                    log_dir, version, part, parts
+                )
+            })
+            .collect(),
+    };
+    let ctx = SessionContext::with_config_rt(SessionConfig::new(), runtime.clone());
+    let mut actions = vec![];
+    for path in paths {
+        let batches = ctx
+            .read_parquet(path, ParquetReadOptions::default())
+            .await?
+            .collect()
+            .await?;
+        actions.extend(
+            record_batches_to_json_rows(&batches)?
+                .into_iter()
+                .map(checkpoint_action),
+        );
+    }
+    Ok(actions)
+}
+
+/// The action of a row of a checkpoint, which holds a single action with the columns of
+/// the other action types null
+fn checkpoint_action(mut row: serde_json::Map<String, Value>) -> Value {
+    row.retain(|_, action| !is_null_action(action));
+    Value::Object(row)
+}
+
+/// Whether a column of a checkpoint row holds no action, which the conversion of the
+/// row to JSON turns into null or an object without any values
+fn is_null_action(value: &Value) -> bool {
+    match value {
+        Value::Null => true,
+        Value::Object(fields) => fields.values().all(is_null_action),
+        _ => false,
+    }
+}
+
+/// The Arrow type of a type of a Delta table schema
+fn arrow_type(delta_type: &Value) -> Result<DataType> {
+    let unsupported = || {
+        DataFusionError::NotImplemented(format!("Unsupported Delta type {}", delta_type))
+    };
+    Ok(match delta_type {
+        Value::String(name) => match name.as_str() {
+            "string" => DataType::Utf8,
+            "long" => DataType::Int64,
+            "integer" => DataType::Int32,
+            "short" => DataType::Int16,
+            "byte" => DataType::Int8,
+            "float" => DataType::Float32,
+            "double" => DataType::Float64,
+            "boolean" => DataType::Boolean,
+            "binary" => DataType::Binary,
+            "date" => DataType::Date32,
+            "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, None),
+            _ => return Err(unsupported()),
+        },
+        Value::Object(object) => match object.get("type").and_then(Value::as_str) {
+            Some("struct") => DataType::Struct(
+                object
+                    .get("fields")
+                    .and_then(Value::as_array)
+                    .ok_or_else(unsupported)?
+                    .iter()
+                    .map(|field| {
+                        Ok(Field::new(
+                            &string_field(field, "name")?,
+                            arrow_type(field.get("type").ok_or_else(unsupported)?)?,
+                            field
+                                .get("nullable")
+                                .and_then(Value::as_bool)
+                                .unwrap_or(true),
+                        ))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            Some("array") => DataType::List(Box::new(Field::new(
+                "element",
+                arrow_type(object.get("elementType").ok_or_else(unsupported)?)?,
+                object
+                    .get("containsNull")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(true),
+            ))),
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_format::table_commit_from_json;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::parquet::arrow::ArrowWriter;
+    use std::fs::{self, File};
+
+    const SCHEMA: &str = r#"{"type":"struct","fields":[{"name":"id","type":"long","nullable":true,"metadata":{}},{"name":"part","type":"string","nullable":true,"metadata":{}}]}"#;
+
+    fn write_data_file(dir: &std::path::Path, name: &str, ids: Vec<i64>) -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, true)]));
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int64Array::from(ids))])?;
+        fs::create_dir_all(dir.join(name).parent().unwrap())?;
+        let mut writer =
+            ArrowWriter::try_new(File::create(dir.join(name))?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    fn add(path: &str, part: &str) -> String {
+        format!(
+            r#"{{"add":{{"path":"{}","partitionValues":{{"part":"{}"}},"size":1,"modificationTime":0,"dataChange":true}}}}"#,
+            path, part
+        )
+    }
+
+    async fn count(table: DeltaTable, filter: Option<Expr>) -> Result<usize> {
+        let ctx = SessionContext::new();
+        let mut df = ctx.read_table(Arc::new(table))?;
+        if let Some(filter) = filter {
+            df = df.filter(filter)?;
+        }
+        Ok(df.collect().await?.iter().map(|b| b.num_rows()).sum())
+    }
+
+    #[tokio::test]
+    async fn replay_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write_data_file(dir.path(), "part=a/1.parquet", vec![1, 2])?;
+        write_data_file(dir.path(), "part=b/2.parquet", vec![3])?;
+        write_data_file(dir.path(), "part=b/3.parquet", vec![4, 5, 6])?;
+
+        let log_dir = dir.path().join(LOG_DIR);
+        fs::create_dir(&log_dir)?;
+        let metadata = serde_json::json!({"metaData": {
+            "id": "test",
+            "format": {"provider": "parquet", "options": {}},
+            "schemaString": SCHEMA,
+            "partitionColumns": ["part"],
+            "configuration": {},
+        }});
+        fs::write(
+            log_dir.join(format!("{:020}.json", 0)),
+            [
+                r#"{"protocol":{"minReaderVersion":1,"minWriterVersion":2}}"#.to_owned(),
+                metadata.to_string(),
+                add("part=a/1.parquet", "a"),
+                add("part=b/2.parquet", "b"),
+            ]
+            .join("\n"),
+        )?;
+        fs::write(
+            log_dir.join(format!("{:020}.json", 1)),
+            [
+                r#"{"remove":{"path":"part=b/2.parquet","dataChange":true}}"#.to_owned(),
+                add("part=b/3.parquet", "b"),
+            ]
+            .join("\n"),
+        )?;
+
+        let location = dir.path().to_str().unwrap();
+        let runtime = SessionContext::new().runtime_env();
+        let table = DeltaTable::load(location, None, runtime.clone()).await?;
+        assert_eq!(table.version(), 1);
+        assert_eq!(table.schema().field(1).name(), "part");
+        assert_eq!(count(table, None).await?, 5);
+
+        let table = DeltaTable::load(location, Some(0), runtime.clone()).await?;
+        assert_eq!(count(table, Some(col("part").eq(lit("b")))).await?, 1);
+        assert!(DeltaTable::load(location, Some(2), runtime.clone())
+            .await
+            .is_err());
+
+        // Versions are committed when their log files were written
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64;
+        assert_eq!(
+            DeltaTable::version_at(location, now + 60_000, runtime.clone()).await?,
+            1
+        );
+        assert!(DeltaTable::version_at(location, 0, runtime).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rewrite_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write_data_file(dir.path(), "part=a/1.parquet", vec![1, 2])?;
+        write_data_file(dir.path(), "part=b/2.parquet", vec![3, 4])?;
+        let log_dir = dir.path().join(LOG_DIR);
+        fs::create_dir(&log_dir)?;
+        let metadata = serde_json::json!({"metaData": {
+            "id": "test",
+            "format": {"provider": "parquet", "options": {}},
+            "schemaString": SCHEMA,
+            "partitionColumns": ["part"],
+            "configuration": {},
+        }});
+        fs::write(
+            log_dir.join(format!("{:020}.json", 0)),
+            [
+                metadata.to_string(),
+                add("part=a/1.parquet", "a"),
+                add("part=b/2.parquet", "b"),
+            ]
+            .join("\n"),
+        )?;
+
+        // Only the files of the partitions the rows may be in are rewritten
+        let location = dir.path().to_str().unwrap();
+        let runtime = SessionContext::new().runtime_env();
+        let filters = vec![col("part").eq(lit("b")).and(col("id").eq(lit(4i64)))];
+        let rewrite = DeltaFormat
+            .rewrite(location, &BTreeMap::new(), &filters, runtime.clone())
+            .await?;
+        assert_eq!(rewrite.partition_by, vec!["part"]);
+        let ctx = SessionContext::new();
+        let rows = ctx.read_table(rewrite.table.clone())?.collect().await?;
+        assert_eq!(rows.iter().map(|b| b.num_rows()).sum::<usize>(), 2);
+
+        // The rows which are kept are written to a new file, which replaces the
+        // rewritten one
+        write_data_file(dir.path(), "part=b/3.parquet", vec![3])?;
+        let written = vec![WrittenFile {
+            path: "part=b/3.parquet".to_owned(),
+            num_rows: 1,
+            size: fs::metadata(dir.path().join("part=b/3.parquet"))?.len(),
+            partition_values: vec![("part".to_owned(), Some("b".to_owned()))]
+                .into_iter()
+                .collect(),
+        }];
+        // The commit is saved by the scheduler until the job completes
+        let commit = table_commit_from_json(rewrite.commit.to_json()?)?;
+        commit.commit(&written, &runtime).await?;
+        let table = DeltaTable::load(location, None, runtime.clone()).await?;
+        assert_eq!(table.version(), 1);
+        assert_eq!(count(table, None).await?, 3);
+
+        // A rewrite planned with a previous version is not committed
+        assert!(rewrite.commit.commit(&written, &runtime).await.is_err());
+        let table = DeltaTable::load(location, None, runtime.clone()).await?;
+        assert_eq!(table.version(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn check_writer_protocol() -> Result<()> {
+        let write_log = |dir: &std::path::Path, protocol: &str, metadata: Value| {
+            let log_dir = dir.join(LOG_DIR);
+            fs::create_dir_all(&log_dir)?;
+            fs::write(
+                log_dir.join(format!("{:020}.json", 0)),
+                [protocol.to_owned(), metadata.to_string()].join("\n"),
+            )
+        };
+        let metadata = |schema: &str, configuration: Value| {
+            serde_json::json!({"metaData": {
+                "id": "test",
+                "format": {"provider": "parquet", "options": {}},
+                "schemaString": schema,
+                "partitionColumns": ["part"],
+                "configuration": configuration,
+            }})
+        };
+        let v2 = r#"{"protocol":{"minReaderVersion":1,"minWriterVersion":2}}"#;
+        let runtime = SessionContext::new().runtime_env();
+        let rewrite = |location: String| {
+            let runtime = runtime.clone();
+            async move {
+                DeltaFormat
+                    .rewrite(&location, &BTreeMap::new(), &[], runtime)
+                    .await
+                    .map(|_| ())
+            }
+        };
+        let compact = |location: String| {
+            let runtime = runtime.clone();
+            async move {
+                DeltaFormat
+                    .compact(&location, &BTreeMap::new(), 1024, runtime)
+                    .await
+                    .map(|_| ())
+            }
+        };
+
+        // Newer writer versions may have features which are not supported
+        let dir = tempfile::tempdir()?;
+        write_log(
+            dir.path(),
+            r#"{"protocol":{"minReaderVersion":1,"minWriterVersion":3}}"#,
+            metadata(SCHEMA, serde_json::json!({})),
+        )?;
+        let location = dir.path().to_str().unwrap().to_owned();
+        assert!(DeltaTable::load(&location, None, runtime.clone())
+            .await
+            .is_ok());
+        assert!(rewrite(location.clone()).await.is_err());
+        assert!(compact(location).await.is_err());
+
+        // The files of append-only tables can be compacted, but not rewritten
+        let dir = tempfile::tempdir()?;
+        write_log(
+            dir.path(),
+            v2,
+            metadata(SCHEMA, serde_json::json!({"delta.appendOnly": "true"})),
+        )?;
+        let location = dir.path().to_str().unwrap().to_owned();
+        assert!(rewrite(location.clone()).await.is_err());
+        compact(location).await?;
+
+        let dir = tempfile::tempdir()?;
+        let schema = SCHEMA.replacen(
+            r#""metadata":{}"#,
+            r#""metadata":{"delta.invariants":"{\"expression\":{\"expression\":\"id > 0\"}}"}"#,
+            1,
+        );
+        write_log(dir.path(), v2, metadata(&schema, serde_json::json!({})))?;
+        let location = dir.path().to_str().unwrap().to_owned();
+        assert!(rewrite(location.clone()).await.is_err());
+        compact(location).await?;
+
+        let dir = tempfile::tempdir()?;
+        write_log(dir.path(), v2, metadata(SCHEMA, serde_json::json!({})))?;
+        rewrite(dir.path().to_str().unwrap().to_owned()).await?;
+        Ok(())
+    }
+
+    #[test]
+    fn null_checkpoint_actions() -> Result<()> {
+        let mut snapshot = Snapshot::default();
+        let rows = vec![
+            serde_json::json!({"add": {"path": "1.parquet", "partitionValues": {}}, "remove": {}}),
+            serde_json::json!({"add": {"partitionValues": {}}, "remove": {"path": null}, "metaData": null}),
+        ];
+        for row in rows {
+            snapshot.apply(&checkpoint_action(row.as_object().unwrap().clone()))?;
+        }
+        assert_eq!(snapshot.files.keys().collect::<Vec<_>>(), vec!["1.parquet"]);
+        Ok(())
+    }
+
+    #[test]
+    fn group_small_files() {
+        let file = |path: &str, part: &str, size: usize| DeltaFile {
+            path: path.to_owned(),
+            size,
+            modification_time: 0,
+            partition_values: vec![("part".to_owned(), Some(part.to_owned()))]
+                .into_iter()
+                .collect(),
+        };
+        let files = vec![
+            file("a1", "a", 40),
+            file("a2", "a", 30),
+            file("a3", "a", 50),
+            file("a4", "a", 20),
+            file("a5", "a", 200),
+            file("b1", "b", 10),
+        ];
+        let groups: Vec<Vec<&str>> = compaction_groups(&files, 100)
+            .iter()
+            .map(|group| group.iter().map(|file| file.path.as_str()).collect())
+            .collect();
+        // Large files and files alone in their partition are left as they are
+        assert_eq!(groups, vec![vec!["a3", "a1"], vec!["a2", "a4"]]);
+    }
+
+    #[test]
+    fn prune_partitions() {
+        let columns = vec!["part".to_owned()];
+        let values: HashMap<_, _> = vec![("part".to_owned(), Some("a".to_owned()))]
+            .into_iter()
+            .collect();
+        let check = |filter: Expr| prune(&filter, &columns, &values);
+
+        assert_eq!(check(col("part").eq(lit("a"))), Some(true));
+        assert_eq!(check(lit("b").eq(col("part"))), Some(false));
+        assert_eq!(check(col("part").not_eq(lit("a"))), Some(false));
+        assert_eq!(
+            check(col("part").in_list(vec![lit("b"), lit("c")], false)),
+            Some(false)
+        );
+        assert_eq!(check(col("part").is_null()), Some(false));
+        assert_eq!(
+            check(col("part").eq(lit("b")).and(col("id").gt(lit(1)))),
+            Some(false)
+        );
+        assert_eq!(check(col("id").eq(lit(1))), None);
+
+        assert!(references_any(&col("part").eq(lit("a")), &columns));
+        assert!(!references_any(&col("id").eq(lit(1)), &columns));
+    }
+}
@@ -0,0 +1,2602 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Apache Iceberg tables.
+//!
+//! A table is resolved from its metadata file: the snapshot to read, and the manifests
+//! of that snapshot from its manifest list. When the table is scanned, manifests are
+//! pruned by the ranges of the partition values they summarize, and the data files of
+//! the remaining manifests by their partition values and column bounds. The files left
+//! are scanned with a [ParquetExec].
+//!
+//! DELETE and UPDATE statements rewrite the data files which may hold the rows they
+//! change, pruned as for scans. The scheduler commits the new files in place of the
+//! rewritten ones as a new snapshot: the manifests listing rewritten files are written
+//! again with their entries marked as deleted, and the new files are listed in a new
+//! manifest. The metadata file of the next version of the table is created only if no
+//! other writer created it first, so concurrent changes fail instead of being lost, as
+//! for [Delta tables](super::delta) on object stores which cannot copy conditionally.
+//! Only tables opened from their directory and partitioned by identity transforms of
+//! boolean, integer and string columns can be changed. The new data files carry the field
+//! IDs of the current schema of the table, so that other readers match their columns by
+//! ID.
+//!
+//! Only Parquet data files are supported, and row-level deletes are not. Columns are
+//! matched to the columns of the data files by name rather than by field ID, so columns
+//! which were renamed read as null from files written before the rename.
+
+use super::{
+    copy_if_not_exists, SavedCommit, TableCommit, TableFormat, TableRewrite, TableVersion,
+};
+use crate::execution_plans::WrittenFile;
+use apache_avro::types::Value as AvroValue;
+use apache_avro::{Reader, Schema as AvroSchema, Writer};
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_expr::TableProviderFilterPushDown;
+use datafusion::logical_plan::{Expr, Operator};
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::file_format::{FileScanConfig, ParquetExec};
+use datafusion::physical_plan::{ExecutionPlan, Statistics};
+use datafusion::scalar::ScalarValue;
+use futures::future::try_join_all;
+use futures::TryStreamExt;
+use log::warn;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const METADATA_DIR: &str = "metadata";
+
+/// The directory the data files written by rewrites are written to
+const DATA_DIR: &str = "data";
+
+/// Status of manifest entries of files which were added by an earlier snapshot
+const STATUS_EXISTING: i64 = 0;
+
+/// Status of manifest entries of files added by the snapshot of the manifest
+const STATUS_ADDED: i64 = 1;
+
+/// Status of manifest entries of files deleted by the snapshot of the manifest
+const STATUS_DELETED: i64 = 2;
+
+/// The block size written to manifests of format version 1, which require one
+const BLOCK_SIZE: i64 = 64 * 1024 * 1024;
+
+/// Opens Iceberg tables from the directory of the table, or from one of its metadata
+/// files. The `snapshot_id` option selects the snapshot to read instead of the current
+/// one, and `as_of_timestamp` the snapshot which was current at a time in milliseconds
+/// since the epoch.
+pub struct IcebergFormat;
+
+#[async_trait]
+impl TableFormat for IcebergFormat {
+    async fn open(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let selection = match (options.get("snapshot_id"), options.get("as_of_timestamp"))
+        {
+            (Some(_), Some(_)) => {
+                return Err(DataFusionError::Plan(
+                    "Only one of snapshot_id and as_of_timestamp can be set".to_owned(),
+                ))
+            }
+            (Some(id), None) => SnapshotSelection::Id(parse_option("snapshot_id", id)?),
+            (None, Some(timestamp)) => {
+                SnapshotSelection::AsOf(parse_option("as_of_timestamp", timestamp)?)
+            }
+            (None, None) => SnapshotSelection::Current,
+        };
+        Ok(Arc::new(
+            IcebergTable::load(location, selection, runtime).await?,
+        ))
+    }
+
+    fn options_as_of(
+        &self,
+        options: &BTreeMap<String, String>,
+        version: TableVersion,
+    ) -> Result<BTreeMap<String, String>> {
+        let mut options = options.clone();
+        options.remove("snapshot_id");
+        options.remove("as_of_timestamp");
+        match version {
+            TableVersion::Version(id) => {
+                options.insert("snapshot_id".to_owned(), id.to_string())
+            }
+            TableVersion::Timestamp(timestamp) => {
+                options.insert("as_of_timestamp".to_owned(), timestamp.to_string())
+            }
+        };
+        Ok(options)
+    }
+
+    async fn rewrite(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        filters: &[Expr],
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        if options.contains_key("snapshot_id") || options.contains_key("as_of_timestamp")
+        {
+            return Err(DataFusionError::Plan(
+                "Past snapshots of Iceberg tables cannot be changed".to_owned(),
+            ));
+        }
+        let url = ListingTableUrl::parse(location)?;
+        if url.prefix().as_ref().ends_with(".metadata.json") {
+            return Err(DataFusionError::Plan(
+                "Iceberg tables opened from a metadata file cannot be changed".to_owned(),
+            ));
+        }
+        let location: &str = url.as_ref();
+        let location = location.trim_end_matches('/').to_owned();
+        let store = runtime.object_store(url.object_store())?;
+        let (_, metadata) =
+            read_metadata(store.as_ref(), url.prefix(), &location).await?;
+        let spec_id = default_spec_id(&metadata);
+        let mut field_ids = HashMap::new();
+        add_field_ids(table_schema(&metadata, None)?, "", &mut field_ids);
+        let partition_by = identity_fields(&metadata, spec_id)?
+            .into_iter()
+            .map(|field| field.column)
+            .collect();
+        let mut table = IcebergTable::from_metadata(
+            url,
+            store,
+            &metadata,
+            SnapshotSelection::Current,
+        )
+        .await?;
+
+        let mut removed: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut files = vec![];
+        for (manifest, data_file) in table.data_file_records(filters).await? {
+            files.push(table.partitioned_file(&data_file)?);
+            removed
+                .entry(manifest.path.clone())
+                .or_default()
+                .insert(file_path(&data_file)?.to_owned());
+        }
+        let commit = IcebergCommit {
+            location: location.clone(),
+            snapshot_id: table.snapshot_id,
+            spec_id,
+            removed,
+        };
+        table.files = Some(files);
+        Ok(TableRewrite {
+            table: Arc::new(table),
+            location: format!("{}/{}", location, DATA_DIR),
+            partition_by,
+            repartition: true,
+            field_ids,
+            commit: Arc::new(commit),
+        })
+    }
+}
+
+fn parse_option(name: &str, value: &str) -> Result<i64> {
+    value.parse().map_err(|_| {
+        DataFusionError::Plan(format!(
+            "Invalid value {} of Iceberg option {}",
+            value, name
+        ))
+    })
+}
+
+/// The snapshot of an Iceberg table to read
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SnapshotSelection {
+    /// The current snapshot of the table
+    Current,
+    /// The snapshot with an ID
+    Id(i64),
+    /// The snapshot which was current at a time in milliseconds since the epoch
+    AsOf(i64),
+}
+
+/// A top-level column of a table
+#[derive(Clone, Debug)]
+struct Column {
+    id: i64,
+    name: String,
+    data_type: DataType,
+}
+
+/// A field of a partition spec
+#[derive(Clone, Debug)]
+struct PartitionField {
+    name: String,
+    /// Index of the column whose values are the values of the field, for identity
+    /// transforms, which are the only ones used for pruning
+    column: Option<usize>,
+}
+
+/// A manifest of data files listed in a manifest list
+#[derive(Clone, Debug)]
+struct Manifest {
+    path: String,
+    spec_id: i64,
+    /// Summaries of the values of the partition fields, in the order of the spec
+    partitions: Vec<FieldSummary>,
+}
+
+#[derive(Clone, Debug)]
+struct FieldSummary {
+    contains_null: bool,
+    lower_bound: Option<Vec<u8>>,
+    upper_bound: Option<Vec<u8>>,
+}
+
+/// What the metadata of a manifest or data file tells about the values of a column
+#[derive(Clone, Debug, PartialEq)]
+struct ColumnRange {
+    min: Option<ScalarValue>,
+    max: Option<ScalarValue>,
+    /// Whether the column may contain nulls
+    has_nulls: bool,
+    /// Whether the column only contains nulls
+    all_null: bool,
+}
+
+fn external_error(e: object_store::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+fn avro_error(e: apache_avro::Error) -> DataFusionError {
+    DataFusionError::External(Box::new(e))
+}
+
+/// A snapshot of an Iceberg table
+pub struct IcebergTable {
+    url: ListingTableUrl,
+    store: Arc<dyn ObjectStore>,
+    snapshot_id: Option<i64>,
+    /// Milliseconds since the epoch
+    timestamp_ms: i64,
+    schema: SchemaRef,
+    columns: Vec<Column>,
+    specs: HashMap<i64, Vec<PartitionField>>,
+    manifests: Vec<Manifest>,
+    /// The data files to scan instead of those of the manifests, for rewrites
+    files: Option<Vec<PartitionedFile>>,
+}
+
+impl IcebergTable {
+    /// Load the snapshot of the table at `location` selected by `selection`
+    pub async fn load(
+        location: &str,
+        selection: SnapshotSelection,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Self> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let (_, metadata) = read_metadata(store.as_ref(), url.prefix(), location).await?;
+        Self::from_metadata(url, store, &metadata, selection).await
+    }
+
+    /// The snapshot selected by `selection` of the table at `url` with `metadata`
+    async fn from_metadata(
+        url: ListingTableUrl,
+        store: Arc<dyn ObjectStore>,
+        metadata: &Value,
+        selection: SnapshotSelection,
+    ) -> Result<Self> {
+        let format_version = format_version(metadata);
+        if format_version > 2 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Iceberg format version {} is not supported",
+                format_version
+            )));
+        }
+
+        let snapshot = select_snapshot(metadata, selection)?;
+        // Old snapshots are read with the schema they were written with
+        let schema_id = snapshot
+            .and_then(|snapshot| snapshot.get("schema-id"))
+            .and_then(Value::as_i64);
+        let columns = columns(table_schema(metadata, schema_id)?)?;
+        let specs = partition_specs(metadata, &columns);
+        let manifests = match snapshot {
+            Some(snapshot) => read_manifest_list(store.as_ref(), snapshot).await?,
+            None => vec![],
+        };
+
+        Ok(Self {
+            url,
+            store,
+            snapshot_id: snapshot
+                .and_then(|snapshot| snapshot.get("snapshot-id"))
+                .and_then(Value::as_i64),
+            timestamp_ms: snapshot
+                .and_then(|snapshot| snapshot.get("timestamp-ms"))
+                .and_then(Value::as_i64)
+                .unwrap_or_default(),
+            schema: Arc::new(Schema::new(
+                columns
+                    .iter()
+                    .map(|column| {
+                        Field::new(&column.name, column.data_type.clone(), true)
+                    })
+                    .collect(),
+            )),
+            columns,
+            specs,
+            manifests,
+            files: None,
+        })
+    }
+
+    /// The ID of the snapshot, `None` for tables without snapshots
+    pub fn snapshot_id(&self) -> Option<i64> {
+        self.snapshot_id
+    }
+
+    /// The data files of the snapshot which may contain rows matching `filters`
+    async fn data_files(&self, filters: &[Expr]) -> Result<Vec<PartitionedFile>> {
+        self.data_file_records(filters)
+            .await?
+            .iter()
+            .map(|(_, data_file)| self.partitioned_file(data_file))
+            .collect()
+    }
+
+    /// The records of the data files of the snapshot which may contain rows matching
+    /// `filters`, with the manifests listing them
+    async fn data_file_records(
+        &self,
+        filters: &[Expr],
+    ) -> Result<Vec<(&Manifest, AvroValue)>> {
+        let manifests: Vec<&Manifest> = self
+            .manifests
+            .iter()
+            .filter(|manifest| {
+                let ranges = self.manifest_ranges(manifest);
+                filters.iter().all(|filter| may_match(filter, &ranges))
+            })
+            .collect();
+        let contents = try_join_all(
+            manifests
+                .iter()
+                .map(|manifest| read_avro(self.store.as_ref(), &manifest.path)),
+        )
+        .await?;
+
+        let mut files = vec![];
+        for (manifest, entries) in manifests.iter().zip(contents) {
+            for entry in &entries {
+                // Manifests keep the entries of deleted files until they are rewritten
+                if avro_long(avro_field(entry, "status")) == Some(STATUS_DELETED) {
+                    continue;
+                }
+                let data_file = avro_field(entry, "data_file").ok_or_else(|| {
+                    DataFusionError::Execution(
+                        "Iceberg manifest entry without data_file".to_owned(),
+                    )
+                })?;
+                let format = avro_string(avro_field(data_file, "file_format"))
+                    .unwrap_or("PARQUET");
+                if !format.eq_ignore_ascii_case("parquet") {
+                    return Err(DataFusionError::NotImplemented(format!(
+                        "Iceberg data files in {} format are not supported",
+                        format
+                    )));
+                }
+                let ranges = self.file_ranges(manifest.spec_id, data_file);
+                if filters.iter().all(|filter| may_match(filter, &ranges)) {
+                    files.push((*manifest, data_file.clone()));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// The file of the record of a data file, scanned with the other columns of the table
+    fn partitioned_file(&self, data_file: &AvroValue) -> Result<PartitionedFile> {
+        Ok(PartitionedFile {
+            object_meta: ObjectMeta {
+                location: object_path(file_path(data_file)?)?,
+                last_modified: Utc.timestamp_millis(self.timestamp_ms),
+                size: avro_long(avro_field(data_file, "file_size_in_bytes"))
+                    .unwrap_or_default() as usize,
+            },
+            partition_values: vec![],
+            range: None,
+        })
+    }
+
+    /// The ranges of the columns of identity partition fields in a manifest
+    fn manifest_ranges(&self, manifest: &Manifest) -> HashMap<String, ColumnRange> {
+        let fields = self
+            .specs
+            .get(&manifest.spec_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        fields
+            .iter()
+            .zip(&manifest.partitions)
+            .filter_map(|(field, summary)| {
+                let column = &self.columns[field.column?];
+                let decode = |bound: &Option<Vec<u8>>| {
+                    bound
+                        .as_ref()
+                        .and_then(|bound| decode_bound(bound, &column.data_type))
+                };
+                let range = ColumnRange {
+                    min: decode(&summary.lower_bound),
+                    max: decode(&summary.upper_bound),
+                    has_nulls: summary.contains_null,
+                    all_null: summary.contains_null
+                        && summary.lower_bound.is_none()
+                        && summary.upper_bound.is_none(),
+                };
+                Some((column.name.clone(), range))
+            })
+            .collect()
+    }
+
+    /// The ranges of the columns of a data file, from its column bounds and the values
+    /// of its identity partition fields
+    fn file_ranges(
+        &self,
+        spec_id: i64,
+        data_file: &AvroValue,
+    ) -> HashMap<String, ColumnRange> {
+        let lower_bounds = id_map(avro_field(data_file, "lower_bounds"));
+        let upper_bounds = id_map(avro_field(data_file, "upper_bounds"));
+        let null_counts = id_map(avro_field(data_file, "null_value_counts"));
+        let value_counts = id_map(avro_field(data_file, "value_counts"));
+
+        let mut ranges: HashMap<String, ColumnRange> = self
+            .columns
+            .iter()
+            .map(|column| {
+                let bound = |bounds: &HashMap<i64, &AvroValue>| {
+                    avro_bytes(bounds.get(&column.id).copied())
+                        .and_then(|bound| decode_bound(bound, &column.data_type))
+                };
+                let null_count = avro_long(null_counts.get(&column.id).copied());
+                let value_count = avro_long(value_counts.get(&column.id).copied());
+                let range = ColumnRange {
+                    min: bound(&lower_bounds),
+                    max: bound(&upper_bounds),
+                    has_nulls: null_count != Some(0),
+                    all_null: null_count.is_some() && null_count == value_count,
+                };
+                (column.name.clone(), range)
+            })
+            .collect();
+
+        let partition = match avro_field(data_file, "partition") {
+            Some(partition) => partition,
+            None => return ranges,
+        };
+        let fields = self
+            .specs
+            .get(&spec_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for field in fields {
+            let column = match field.column {
+                Some(index) => &self.columns[index],
+                None => continue,
+            };
+            let value = match avro_record_field(partition, &field.name).map(non_null) {
+                Some(Some(value)) => match avro_scalar(value, &column.data_type) {
+                    Some(value) => Some(value),
+                    None => continue,
+                },
+                Some(None) => None,
+                None => continue,
+            };
+            ranges.insert(
+                column.name.clone(),
+                ColumnRange {
+                    min: value.clone(),
+                    max: value.clone(),
+                    has_nulls: value.is_none(),
+                    all_null: value.is_none(),
+                },
+            );
+        }
+        ranges
+    }
+}
+
+#[async_trait]
+impl TableProvider for IcebergTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let files = match &self.files {
+            Some(files) => files.clone(),
+            None => self.data_files(filters).await?,
+        };
+        if files.is_empty() {
+            let schema = match projection {
+                Some(columns) => Arc::new(self.schema.project(columns)?),
+                None => self.schema.clone(),
+            };
+            return Ok(Arc::new(EmptyExec::new(false, schema)));
+        }
+
+        let target_partitions = ctx.config.target_partitions.max(1);
+        let chunk_size = (files.len() + target_partitions - 1) / target_partitions;
+        let file_groups = files
+            .chunks(chunk_size.max(1))
+            .map(|chunk| chunk.to_vec())
+            .collect();
+
+        Ok(Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: self.url.object_store(),
+                file_schema: self.schema.clone(),
+                file_groups,
+                statistics: Statistics::default(),
+                projection: projection.clone(),
+                limit,
+                table_partition_cols: vec![],
+            },
+            filters.iter().cloned().reduce(|a, b| a.and(b)),
+            None,
+        )))
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        _filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown> {
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// The numbers of files and rows of entries of a manifest
+#[derive(Clone, Copy, Debug, Default)]
+struct EntryCounts {
+    files: i64,
+    rows: i64,
+}
+
+impl EntryCounts {
+    fn add(&mut self, rows: i64) {
+        self.files += 1;
+        self.rows += rows;
+    }
+}
+
+/// A manifest written by a commit
+struct NewManifest {
+    path: String,
+    length: usize,
+    spec_id: i64,
+    /// The smallest sequence number of the live entries
+    min_sequence_number: Option<i64>,
+    added: EntryCounts,
+    existing: EntryCounts,
+    deleted: EntryCounts,
+    /// The summaries of the values of the partition fields
+    partitions: Option<AvroValue>,
+}
+
+impl NewManifest {
+    /// The record of the manifest in the manifest list of `snapshot`, with the names
+    /// used by both Iceberg format versions
+    fn record(&self, snapshot: &NewSnapshot) -> AvroValue {
+        let mut fields = vec![];
+        let mut field = |name: &str, value| fields.push((name.to_owned(), value));
+        field("manifest_path", AvroValue::String(self.path.clone()));
+        field("manifest_length", AvroValue::Long(self.length as i64));
+        field("partition_spec_id", AvroValue::Int(self.spec_id as i32));
+        field("content", AvroValue::Int(0));
+        field("added_snapshot_id", AvroValue::Long(snapshot.id));
+        for (kind, counts) in [
+            ("added", self.added),
+            ("existing", self.existing),
+            ("deleted", self.deleted),
+        ] {
+            let files = AvroValue::Int(counts.files as i32);
+            field(&format!("{}_files_count", kind), files.clone());
+            field(&format!("{}_data_files_count", kind), files);
+            field(
+                &format!("{}_rows_count", kind),
+                AvroValue::Long(counts.rows),
+            );
+        }
+        if let Some(sequence_number) = snapshot.sequence_number {
+            let min_sequence_number = self.min_sequence_number.unwrap_or(sequence_number);
+            field("sequence_number", AvroValue::Long(sequence_number));
+            field("min_sequence_number", AvroValue::Long(min_sequence_number));
+        }
+        if let Some(partitions) = &self.partitions {
+            field("partitions", partitions.clone());
+        }
+        AvroValue::Record(fields)
+    }
+}
+
+/// The snapshot created by a commit
+struct NewSnapshot {
+    id: i64,
+    /// The sequence number of the snapshot, `None` for tables of format version 1
+    sequence_number: Option<i64>,
+    /// Milliseconds since the epoch
+    timestamp_ms: i64,
+}
+
+/// Commits the files of a rewrite of an Iceberg table as a snapshot following the one
+/// the rewritten files were read from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct IcebergCommit {
+    /// The URL of the directory of the table
+    location: String,
+    /// The snapshot the rewrite was planned with
+    snapshot_id: Option<i64>,
+    /// The partition spec the new files are partitioned by
+    spec_id: i64,
+    /// The paths of the rewritten files by the path of the manifest listing them
+    removed: HashMap<String, HashSet<String>>,
+}
+
+impl IcebergCommit {
+    fn conflict(&self) -> DataFusionError {
+        DataFusionError::Execution(format!(
+            "Iceberg table {} was changed since snapshot {}, which the statement read",
+            self.location,
+            self.snapshot_id
+                .map_or_else(|| "none".to_owned(), |id| id.to_string())
+        ))
+    }
+
+    /// The path of a new file of the metadata directory of the table
+    fn metadata_file(&self, name: &str) -> String {
+        format!("{}/{}/{}", self.location, METADATA_DIR, name)
+    }
+
+    /// Write `manifest` of the manifest list of the rewritten snapshot again, with the
+    /// entries of the rewritten files marked as deleted by `snapshot`
+    async fn rewrite_manifest(
+        &self,
+        store: &dyn ObjectStore,
+        metadata: &Value,
+        snapshot: &NewSnapshot,
+        manifest: &AvroValue,
+        removed: &HashSet<String>,
+    ) -> Result<NewManifest> {
+        let path = avro_string(avro_field(manifest, "manifest_path")).unwrap_or_default();
+        let spec_id =
+            avro_long(avro_field(manifest, "partition_spec_id")).unwrap_or_default();
+        // Entries added by the snapshot of the manifest inherit its sequence number and
+        // snapshot ID, which they keep once they are moved to another manifest
+        let sequence_number = avro_long(avro_field(manifest, "sequence_number"));
+        let added_snapshot_id = avro_long(avro_field(manifest, "added_snapshot_id"));
+
+        let bytes = read(store, &object_path(path)?).await?;
+        let reader = Reader::new(&bytes[..]).map_err(avro_error)?;
+        let schema = reader.writer_schema().clone();
+        let mut rewritten = NewManifest {
+            path: self.metadata_file(&format!("{}-m0.avro", uuid::Uuid::new_v4())),
+            length: 0,
+            spec_id,
+            min_sequence_number: None,
+            added: EntryCounts::default(),
+            existing: EntryCounts::default(),
+            deleted: EntryCounts::default(),
+            partitions: avro_field(manifest, "partitions").cloned(),
+        };
+        let mut entries = vec![];
+        for entry in reader {
+            let mut entry = entry.map_err(avro_error)?;
+            // Files deleted by earlier snapshots are forgotten
+            if avro_long(avro_field(&entry, "status")) == Some(STATUS_DELETED) {
+                continue;
+            }
+            let (rows, is_removed) = {
+                let data_file = avro_field(&entry, "data_file").ok_or_else(|| {
+                    DataFusionError::Execution(
+                        "Iceberg manifest entry without data_file".to_owned(),
+                    )
+                })?;
+                (
+                    avro_long(avro_field(data_file, "record_count")).unwrap_or_default(),
+                    removed.contains(file_path(data_file)?),
+                )
+            };
+            let entry_sequence_number =
+                avro_long(avro_field(&entry, "sequence_number")).or(sequence_number);
+            let file_sequence_number =
+                avro_long(avro_field(&entry, "file_sequence_number")).or(sequence_number);
+            let (status, snapshot_id) = if is_removed {
+                rewritten.deleted.add(rows);
+                (STATUS_DELETED, Some(snapshot.id))
+            } else {
+                rewritten.existing.add(rows);
+                rewritten.min_sequence_number = rewritten
+                    .min_sequence_number
+                    .into_iter()
+                    .chain(entry_sequence_number)
+                    .min();
+                (
+                    STATUS_EXISTING,
+                    avro_long(avro_field(&entry, "snapshot_id")).or(added_snapshot_id),
+                )
+            };
+            set_field(&mut entry, "status", Some(AvroValue::Int(status as i32)));
+            set_field(&mut entry, "snapshot_id", snapshot_id.map(AvroValue::Long));
+            set_field(
+                &mut entry,
+                "sequence_number",
+                entry_sequence_number.map(AvroValue::Long),
+            );
+            set_field(
+                &mut entry,
+                "file_sequence_number",
+                file_sequence_number.map(AvroValue::Long),
+            );
+            entries.push(conform(&schema, Some(&entry))?);
+        }
+
+        let bytes = avro_file(&schema, &manifest_metadata(metadata, spec_id)?, entries)?;
+        rewritten.length = bytes.len();
+        store
+            .put(&object_path(&rewritten.path)?, bytes.into())
+            .await
+            .map_err(external_error)?;
+        Ok(rewritten)
+    }
+
+    /// Write a manifest listing the `written` files as added by `snapshot`
+    async fn write_manifest(
+        &self,
+        store: &dyn ObjectStore,
+        metadata: &Value,
+        snapshot: &NewSnapshot,
+        written: &[WrittenFile],
+    ) -> Result<NewManifest> {
+        let format_version = format_version(metadata);
+        let fields = identity_fields(metadata, self.spec_id)?;
+        let schema = manifest_schema(format_version, &fields)?;
+        let mut manifest = NewManifest {
+            path: self.metadata_file(&format!("{}-m1.avro", uuid::Uuid::new_v4())),
+            length: 0,
+            spec_id: self.spec_id,
+            min_sequence_number: None,
+            added: EntryCounts::default(),
+            existing: EntryCounts::default(),
+            deleted: EntryCounts::default(),
+            partitions: None,
+        };
+        let mut partitions = vec![];
+        let mut entries = vec![];
+        for file in written {
+            let partition = fields
+                .iter()
+                .map(|field| {
+                    let value = file
+                        .partition_values
+                        .get(&field.column)
+                        .cloned()
+                        .unwrap_or_default();
+                    partition_value(value, &field.column_type)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let data_file = avro_record(vec![
+                ("content", AvroValue::Int(0)),
+                (
+                    "file_path",
+                    AvroValue::String(format!(
+                        "{}/{}/{}",
+                        self.location, DATA_DIR, file.path
+                    )),
+                ),
+                ("file_format", AvroValue::String("PARQUET".to_owned())),
+                (
+                    "partition",
+                    avro_record(
+                        fields
+                            .iter()
+                            .map(|field| field.name.as_str())
+                            .zip(partition.iter().cloned())
+                            .collect(),
+                    ),
+                ),
+                ("record_count", AvroValue::Long(file.num_rows as i64)),
+                ("file_size_in_bytes", AvroValue::Long(file.size as i64)),
+                ("block_size_in_bytes", AvroValue::Long(BLOCK_SIZE)),
+            ]);
+            let entry = avro_record(vec![
+                ("status", AvroValue::Int(STATUS_ADDED as i32)),
+                ("snapshot_id", AvroValue::Long(snapshot.id)),
+                ("data_file", data_file),
+            ]);
+            entries.push(conform(&schema, Some(&entry))?);
+            manifest.added.add(file.num_rows as i64);
+            partitions.push(partition);
+        }
+        manifest.partitions = Some(partition_summaries(fields.len(), &partitions));
+
+        let bytes = avro_file(
+            &schema,
+            &manifest_metadata(metadata, self.spec_id)?,
+            entries,
+        )?;
+        manifest.length = bytes.len();
+        store
+            .put(&object_path(&manifest.path)?, bytes.into())
+            .await
+            .map_err(external_error)?;
+        Ok(manifest)
+    }
+}
+
+#[async_trait]
+impl TableCommit for IcebergCommit {
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    async fn commit(&self, written: &[WrittenFile], runtime: &RuntimeEnv) -> Result<()> {
+        if self.removed.is_empty() && written.is_empty() {
+            return Ok(());
+        }
+        let url = ListingTableUrl::parse(&self.location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let (metadata_path, mut metadata) =
+            read_metadata(store.as_ref(), url.prefix(), &self.location).await?;
+        // The table may have been upgraded since the rewrite was planned
+        if format_version(&metadata) > 2 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Iceberg format version {} is not supported",
+                format_version(&metadata)
+            )));
+        }
+        let current = select_snapshot(&metadata, SnapshotSelection::Current)?;
+        let current_id = current
+            .and_then(|snapshot| snapshot.get("snapshot-id"))
+            .and_then(Value::as_i64);
+        if current_id != self.snapshot_id || default_spec_id(&metadata) != self.spec_id {
+            return Err(self.conflict());
+        }
+        let metadata_name = metadata_path
+            .as_ref()
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let version = metadata_version(metadata_name).ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "Iceberg metadata file {} without version",
+                metadata_path
+            ))
+        })?;
+        let snapshot = NewSnapshot {
+            id: (uuid::Uuid::new_v4().as_u64_pair().0 & i64::MAX as u64) as i64,
+            sequence_number: (format_version(&metadata) > 1).then(|| {
+                metadata
+                    .get("last-sequence-number")
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default()
+                    + 1
+            }),
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_millis() as i64)
+                .unwrap_or_default(),
+        };
+
+        // The manifests listing rewritten files are replaced, the others kept as they are
+        let (list_schema, mut manifests) = match current {
+            Some(current) => {
+                let list = current
+                    .get("manifest-list")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| {
+                        DataFusionError::NotImplemented(
+                            "Iceberg snapshots without manifest list cannot be changed"
+                                .to_owned(),
+                        )
+                    })?;
+                let bytes = read(store.as_ref(), &object_path(list)?).await?;
+                let reader = Reader::new(&bytes[..]).map_err(avro_error)?;
+                let schema = reader.writer_schema().clone();
+                let records = reader
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(avro_error)?;
+                let mut manifests = vec![];
+                for manifest in records {
+                    let path = avro_string(avro_field(&manifest, "manifest_path"))
+                        .unwrap_or_default();
+                    match self.removed.get(path) {
+                        Some(removed) => {
+                            let rewritten = self
+                                .rewrite_manifest(
+                                    store.as_ref(),
+                                    &metadata,
+                                    &snapshot,
+                                    &manifest,
+                                    removed,
+                                )
+                                .await?;
+                            manifests.push(rewritten.record(&snapshot));
+                        }
+                        None => manifests.push(manifest),
+                    }
+                }
+                (schema, manifests)
+            }
+            None => (manifest_list_schema(format_version(&metadata))?, vec![]),
+        };
+        if !written.is_empty() {
+            let manifest = self
+                .write_manifest(store.as_ref(), &metadata, &snapshot, written)
+                .await?;
+            manifests.push(manifest.record(&snapshot));
+        }
+        let manifest_list = self.metadata_file(&format!(
+            "snap-{}-1-{}.avro",
+            snapshot.id,
+            uuid::Uuid::new_v4()
+        ));
+        let mut list_metadata = vec![
+            ("format-version", format_version(&metadata).to_string()),
+            ("snapshot-id", snapshot.id.to_string()),
+            (
+                "parent-snapshot-id",
+                current_id.map_or_else(|| "null".to_owned(), |id| id.to_string()),
+            ),
+        ];
+        if let Some(sequence_number) = snapshot.sequence_number {
+            list_metadata.push(("sequence-number", sequence_number.to_string()));
+        }
+        let records = manifests
+            .iter()
+            .map(|manifest| conform(&list_schema, Some(manifest)))
+            .collect::<Result<Vec<_>>>()?;
+        store
+            .put(
+                &object_path(&manifest_list)?,
+                avro_file(&list_schema, &list_metadata, records)?.into(),
+            )
+            .await
+            .map_err(external_error)?;
+
+        let operation = if written.is_empty() {
+            "delete"
+        } else {
+            "overwrite"
+        };
+        add_snapshot(
+            &mut metadata,
+            &snapshot,
+            current_id,
+            &manifest_list,
+            operation,
+            self.metadata_file(metadata_name),
+        )?;
+
+        // The metadata file is written aside, then copied to the file of the next version
+        // unless another writer committed that version first
+        let dir = url.prefix().child(METADATA_DIR);
+        let staged = dir.child(format!("_commit_{}.json.tmp", uuid::Uuid::new_v4()));
+        let committed = dir.child(format!("v{}.metadata.json", version + 1));
+        store
+            .put(&staged, metadata.to_string().into())
+            .await
+            .map_err(external_error)?;
+        let copied = copy_if_not_exists(store.as_ref(), &staged, &committed).await;
+        if let Err(e) = store.delete(&staged).await {
+            warn!("Could not delete {}: {}", staged, e);
+        }
+        match copied {
+            Ok(()) => {}
+            Err(object_store::Error::AlreadyExists { .. }) => return Err(self.conflict()),
+            Err(e) => return Err(external_error(e)),
+        }
+        // Readers of Hadoop tables find the current metadata file with the version hint
+        store
+            .put(
+                &dir.child("version-hint.text"),
+                (version + 1).to_string().into(),
+            )
+            .await
+            .map_err(external_error)
+    }
+
+    fn to_json(&self) -> Result<Value> {
+        SavedCommit::Iceberg(self.clone()).to_json()
+    }
+}
+
+/// The names and locations of the tables in a namespace of a Hadoop catalog, which
+/// stores every table in a directory `<warehouse>/<namespace>/<table>`
+pub async fn list_tables(
+    warehouse: &str,
+    namespace: &str,
+    runtime: Arc<RuntimeEnv>,
+) -> Result<Vec<(String, String)>> {
+    let location = format!(
+        "{}/{}",
+        warehouse.trim_end_matches('/'),
+        namespace.replace('.', "/")
+    );
+    let url = ListingTableUrl::parse(&location)?;
+    let store = runtime.object_store(url.object_store())?;
+    let dirs = store
+        .list_with_delimiter(Some(url.prefix()))
+        .await
+        .map_err(external_error)?
+        .common_prefixes;
+    let mut tables = vec![];
+    for dir in dirs {
+        let name = match dir.as_ref().rsplit('/').next() {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        let metadata = store
+            .list_with_delimiter(Some(&dir.child(METADATA_DIR)))
+            .await
+            .map_err(external_error)?;
+        if !metadata.objects.is_empty() {
+            let location = format!("{}/{}", location, name);
+            tables.push((name, location));
+        }
+    }
+    Ok(tables)
+}
+
+async fn read(store: &dyn ObjectStore, path: &Path) -> Result<Bytes> {
+    store
+        .get(path)
+        .await
+        .map_err(external_error)?
+        .bytes()
+        .await
+        .map_err(external_error)
+}
+
+/// The path and the contents of the current metadata file of the table at `prefix`
+async fn read_metadata(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    location: &str,
+) -> Result<(Path, Value)> {
+    let path = metadata_path(store, prefix, location).await?;
+    let metadata = serde_json::from_slice(&read(store, &path).await?).map_err(|e| {
+        DataFusionError::Execution(format!("Invalid Iceberg metadata {}: {}", path, e))
+    })?;
+    Ok((path, metadata))
+}
+
+/// The path of the current metadata file of the table at `prefix`, which may also be the
+/// path of a metadata file
+async fn metadata_path(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    location: &str,
+) -> Result<Path> {
+    if prefix.as_ref().ends_with(".metadata.json") {
+        return Ok(prefix.clone());
+    }
+    let dir = prefix.child(METADATA_DIR);
+
+    // Hadoop catalogs point to the current metadata file with a version hint
+    match store.get(&dir.child("version-hint.text")).await {
+        Ok(result) => {
+            let bytes = result.bytes().await.map_err(external_error)?;
+            let hint = String::from_utf8_lossy(&bytes);
+            let version = hint.trim().parse::<i64>().map_err(|_| {
+                DataFusionError::Execution(format!(
+                    "Invalid Iceberg version hint {} of {}",
+                    hint, location
+                ))
+            })?;
+            return Ok(dir.child(format!("v{}.metadata.json", version)));
+        }
+        Err(object_store::Error::NotFound { .. }) => {}
+        Err(e) => return Err(external_error(e)),
+    }
+
+    // Otherwise the metadata file with the highest version is the current one
+    let files: Vec<ObjectMeta> = store
+        .list(Some(&dir))
+        .await
+        .map_err(external_error)?
+        .try_collect()
+        .await
+        .map_err(external_error)?;
+    files
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.location.as_ref().rsplit('/').next()?;
+            Some((metadata_version(name)?, file.location))
+        })
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, path)| path)
+        .ok_or_else(|| {
+            DataFusionError::Plan(format!("{} is not an Iceberg table", location))
+        })
+}
+
+/// The version of a metadata file named `v<version>.metadata.json`, or
+/// `<version>-<uuid>.metadata.json`
+fn metadata_version(name: &str) -> Option<i64> {
+    let stem = name.strip_suffix(".metadata.json")?;
+    let version = match stem.strip_prefix('v') {
+        Some(version) => version,
+        None => stem.split('-').next()?,
+    };
+    version.parse().ok()
+}
+
+/// The snapshot of the table selected by `selection`, `None` if the table has no
+/// current snapshot
+fn select_snapshot(
+    metadata: &Value,
+    selection: SnapshotSelection,
+) -> Result<Option<&Value>> {
+    let snapshots = metadata
+        .get("snapshots")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let id = match selection {
+        SnapshotSelection::Current => {
+            match metadata.get("current-snapshot-id").and_then(Value::as_i64) {
+                Some(id) if id >= 0 => id,
+                _ => return Ok(None),
+            }
+        }
+        SnapshotSelection::Id(id) => id,
+        SnapshotSelection::AsOf(timestamp) => {
+            // The snapshot log records when snapshots became current, which tables
+            // written without it are assumed to have done when they were committed
+            let log = metadata
+                .get("snapshot-log")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or(snapshots);
+            log.iter()
+                .filter_map(|entry| {
+                    let time = entry.get("timestamp-ms").and_then(Value::as_i64)?;
+                    let id = entry.get("snapshot-id").and_then(Value::as_i64)?;
+                    (time <= timestamp).then(|| (time, id))
+                })
+                .max_by_key(|(time, _)| *time)
+                .map(|(_, id)| id)
+                .ok_or_else(|| {
+                    DataFusionError::Plan(format!(
+                        "Iceberg table has no snapshot at or before {}",
+                        timestamp
+                    ))
+                })?
+        }
+    };
+    snapshots
+        .iter()
+        .find(|snapshot| snapshot.get("snapshot-id").and_then(Value::as_i64) == Some(id))
+        .map(Some)
+        .ok_or_else(|| {
+            DataFusionError::Plan(format!("Iceberg table has no snapshot {}", id))
+        })
+}
+
+/// The schema with `schema_id`, or the current schema if `None`
+fn table_schema(metadata: &Value, schema_id: Option<i64>) -> Result<&Value> {
+    let schema = match metadata.get("schemas").and_then(Value::as_array) {
+        Some(schemas) => {
+            let id = schema_id
+                .or_else(|| metadata.get("current-schema-id").and_then(Value::as_i64))
+                .unwrap_or_default();
+            schemas.iter().find(|schema| {
+                schema
+                    .get("schema-id")
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default()
+                    == id
+            })
+        }
+        // Tables of format version 1 may only have a single schema
+        None => metadata.get("schema"),
+    };
+    schema.ok_or_else(|| {
+        DataFusionError::Execution("Iceberg table metadata without schema".to_owned())
+    })
+}
+
+fn columns(schema: &Value) -> Result<Vec<Column>> {
+    Ok(struct_fields(schema)?
+        .into_iter()
+        .map(|(id, field)| Column {
+            id,
+            name: field.name().clone(),
+            data_type: field.data_type().clone(),
+        })
+        .collect())
+}
+
+/// The fields of a struct type with their IDs
+fn struct_fields(struct_type: &Value) -> Result<Vec<(i64, Field)>> {
+    struct_type
+        .get("fields")
+        .and_then(Value::as_array)
+        .ok_or_else(|| {
+            DataFusionError::Execution(format!(
+                "Iceberg struct type without fields: {}",
+                struct_type
+            ))
+        })?
+        .iter()
+        .map(|field| {
+            let invalid =
+                || DataFusionError::Execution(format!("Invalid Iceberg field {}", field));
+            let id = field
+                .get("id")
+                .and_then(Value::as_i64)
+                .ok_or_else(invalid)?;
+            let name = field
+                .get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(invalid)?;
+            let required = field
+                .get("required")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let data_type = arrow_type(field.get("type").ok_or_else(invalid)?)?;
+            Ok((id, Field::new(name, data_type, !required)))
+        })
+        .collect()
+}
+
+/// Add the IDs of the fields of the Iceberg type `iceberg_type` at `path` to `ids`, by
+/// the dotted path of the field in the schema of the Parquet files written for it
+fn add_field_ids(iceberg_type: &Value, path: &str, ids: &mut HashMap<String, i32>) {
+    let child_path = |name: &str| {
+        if path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}.{}", path, name)
+        }
+    };
+    match iceberg_type.get("type").and_then(Value::as_str) {
+        Some("struct") => {
+            let fields = iceberg_type.get("fields").and_then(Value::as_array);
+            for field in fields.into_iter().flatten() {
+                let id = field.get("id").and_then(Value::as_i64);
+                let name = field.get("name").and_then(Value::as_str);
+                if let (Some(id), Some(name)) = (id, name) {
+                    let field_path = child_path(name);
+                    ids.insert(field_path.clone(), id as i32);
+                    if let Some(field_type) = field.get("type") {
+                        add_field_ids(field_type, &field_path, ids);
+                    }
+                }
+            }
+        }
+        // Lists are written as a repeated group `list` of elements named `element`
+        Some("list") => {
+            if let Some(id) = iceberg_type.get("element-id").and_then(Value::as_i64) {
+                let element_path = child_path("list.element");
+                ids.insert(element_path.clone(), id as i32);
+                if let Some(element_type) = iceberg_type.get("element") {
+                    add_field_ids(element_type, &element_path, ids);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The Arrow type of a type of an Iceberg schema
+fn arrow_type(iceberg_type: &Value) -> Result<DataType> {
+    let unsupported = || {
+        DataFusionError::NotImplemented(format!(
+            "Unsupported Iceberg type {}",
+            iceberg_type
+        ))
+    };
+    Ok(match iceberg_type {
+        Value::String(name) => match name.as_str() {
+            "boolean" => DataType::Boolean,
+            "int" => DataType::Int32,
+            "long" => DataType::Int64,
+            "float" => DataType::Float32,
+            "double" => DataType::Float64,
+            "date" => DataType::Date32,
+            "time" => DataType::Time64(TimeUnit::Microsecond),
+            "timestamp" => DataType::Timestamp(TimeUnit::Microsecond, None),
+            "timestamptz" => {
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".to_owned()))
+            }
+            "string" => DataType::Utf8,
+            "uuid" => DataType::FixedSizeBinary(16),
+            "binary" => DataType::Binary,
+            name => match name
+                .strip_prefix("fixed[")
+                .and_then(|length| length.strip_suffix(']'))
+                .and_then(|length| length.parse().ok())
+            {
+                Some(length) => DataType::FixedSizeBinary(length),
+                None => return Err(unsupported()),
+            },
+        },
+        Value::Object(object) => match object.get("type").and_then(Value::as_str) {
+            Some("struct") => DataType::Struct(
+                struct_fields(iceberg_type)?
+                    .into_iter()
+                    .map(|(_, field)| field)
+                    .collect(),
+            ),
+            Some("list") => DataType::List(Box::new(Field::new(
+                "element",
+                arrow_type(object.get("element").ok_or_else(unsupported)?)?,
+                !object
+                    .get("element-required")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            ))),
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    })
+}
+
+/// The partition specs of the table by ID
+fn partition_specs(
+    metadata: &Value,
+    columns: &[Column],
+) -> HashMap<i64, Vec<PartitionField>> {
+    let specs: Vec<(i64, &Value)> =
+        match metadata.get("partition-specs").and_then(Value::as_array) {
+            Some(specs) => specs
+                .iter()
+                .map(|spec| {
+                    (
+                        spec.get("spec-id")
+                            .and_then(Value::as_i64)
+                            .unwrap_or_default(),
+                        spec.get("fields").unwrap_or(&Value::Null),
+                    )
+                })
+                .collect(),
+            // Tables of format version 1 may only have a single spec
+            None => vec![(0, metadata.get("partition-spec").unwrap_or(&Value::Null))],
+        };
+    specs
+        .into_iter()
+        .map(|(id, fields)| {
+            let fields = fields
+                .as_array()
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .map(|field| PartitionField {
+                    name: field
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_owned(),
+                    column: match field.get("transform").and_then(Value::as_str) {
+                        Some("identity") => {
+                            let source = field.get("source-id").and_then(Value::as_i64);
+                            columns.iter().position(|column| Some(column.id) == source)
+                        }
+                        _ => None,
+                    },
+                })
+                .collect();
+            (id, fields)
+        })
+        .collect()
+}
+
+/// The manifests of a snapshot
+async fn read_manifest_list(
+    store: &dyn ObjectStore,
+    snapshot: &Value,
+) -> Result<Vec<Manifest>> {
+    let list = match snapshot.get("manifest-list").and_then(Value::as_str) {
+        Some(list) => list,
+        None => {
+            // Snapshots of format version 1 may list their manifests inline
+            return Ok(snapshot
+                .get("manifests")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|path| Manifest {
+                    path: path.to_owned(),
+                    spec_id: 0,
+                    partitions: vec![],
+                })
+                .collect());
+        }
+    };
+    read_avro(store, list)
+        .await?
+        .iter()
+        .map(|manifest| {
+            let path =
+                avro_string(avro_field(manifest, "manifest_path")).ok_or_else(|| {
+                    DataFusionError::Execution(
+                        "Iceberg manifest list entry without manifest_path".to_owned(),
+                    )
+                })?;
+            // Manifests of delete files have content 1
+            if avro_long(avro_field(manifest, "content")).unwrap_or_default() != 0 {
+                return Err(DataFusionError::NotImplemented(
+                    "Iceberg tables with row-level deletes are not supported".to_owned(),
+                ));
+            }
+            let partitions = match avro_field(manifest, "partitions") {
+                Some(AvroValue::Array(summaries)) => summaries
+                    .iter()
+                    .map(|summary| FieldSummary {
+                        contains_null: avro_bool(avro_field(summary, "contains_null"))
+                            .unwrap_or(true),
+                        lower_bound: avro_bytes(avro_field(summary, "lower_bound"))
+                            .map(<[u8]>::to_vec),
+                        upper_bound: avro_bytes(avro_field(summary, "upper_bound"))
+                            .map(<[u8]>::to_vec),
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            Ok(Manifest {
+                path: path.to_owned(),
+                spec_id: avro_long(avro_field(manifest, "partition_spec_id"))
+                    .unwrap_or_default(),
+                partitions,
+            })
+        })
+        .collect()
+}
+
+/// The records of an Avro file
+async fn read_avro(store: &dyn ObjectStore, path: &str) -> Result<Vec<AvroValue>> {
+    let bytes = read(store, &object_path(path)?).await?;
+    Reader::new(&bytes[..])
+        .map_err(avro_error)?
+        .map(|record| record.map_err(avro_error))
+        .collect()
+}
+
+/// The object store path of a path in the metadata, which is usually a URL
+fn object_path(path: &str) -> Result<Path> {
+    match url::Url::parse(path) {
+        Ok(url) => Path::from_url_path(url.path()),
+        Err(_) => Path::parse(path),
+    }
+    .map_err(|e| DataFusionError::External(Box::new(e)))
+}
+
+/// A field of an Avro record, which may be null
+fn avro_record_field<'a>(record: &'a AvroValue, name: &str) -> Option<&'a AvroValue> {
+    match record {
+        AvroValue::Record(fields) => fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value),
+        AvroValue::Union(_, record) => avro_record_field(record, name),
+        _ => None,
+    }
+}
+
+/// A field of an Avro record, `None` if it is missing or null
+fn avro_field<'a>(record: &'a AvroValue, name: &str) -> Option<&'a AvroValue> {
+    avro_record_field(record, name).and_then(non_null)
+}
+
+fn non_null(value: &AvroValue) -> Option<&AvroValue> {
+    match value {
+        AvroValue::Null => None,
+        AvroValue::Union(_, value) => non_null(value),
+        value => Some(value),
+    }
+}
+
+fn avro_long(value: Option<&AvroValue>) -> Option<i64> {
+    match value? {
+        AvroValue::Int(value) => Some(*value as i64),
+        AvroValue::Long(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn avro_bool(value: Option<&AvroValue>) -> Option<bool> {
+    match value? {
+        AvroValue::Boolean(value) => Some(*value),
+        _ => None,
+    }
+}
+
+fn avro_string(value: Option<&AvroValue>) -> Option<&str> {
+    match value? {
+        AvroValue::String(value) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+fn avro_bytes(value: Option<&AvroValue>) -> Option<&[u8]> {
+    match value? {
+        AvroValue::Bytes(value) | AvroValue::Fixed(_, value) => Some(value.as_slice()),
+        _ => None,
+    }
+}
+
+/// An Iceberg map with field IDs as keys, which Avro stores as an array of records
+fn id_map(value: Option<&AvroValue>) -> HashMap<i64, &AvroValue> {
+    match value {
+        Some(AvroValue::Array(entries)) => entries
+            .iter()
+            .filter_map(|entry| {
+                Some((
+                    avro_long(avro_field(entry, "key"))?,
+                    avro_field(entry, "value")?,
+                ))
+            })
+            .collect(),
+        _ => HashMap::new(),
+    }
+}
+
+/// A partition value of a column of `data_type`, `None` if it cannot be compared
+fn avro_scalar(value: &AvroValue, data_type: &DataType) -> Option<ScalarValue> {
+    Some(match (value, data_type) {
+        (AvroValue::Boolean(value), DataType::Boolean) => {
+            ScalarValue::Boolean(Some(*value))
+        }
+        (AvroValue::Int(value), DataType::Int32) => ScalarValue::Int32(Some(*value)),
+        (AvroValue::Int(value) | AvroValue::Date(value), DataType::Date32) => {
+            ScalarValue::Date32(Some(*value))
+        }
+        (AvroValue::Int(value), DataType::Int64) => {
+            ScalarValue::Int64(Some(*value as i64))
+        }
+        (AvroValue::Long(value), DataType::Int64) => ScalarValue::Int64(Some(*value)),
+        (
+            AvroValue::Long(value) | AvroValue::TimestampMicros(value),
+            DataType::Timestamp(TimeUnit::Microsecond, tz),
+        ) => ScalarValue::TimestampMicrosecond(Some(*value), tz.clone()),
+        (AvroValue::Float(value), DataType::Float32) => {
+            ScalarValue::Float32(Some(*value))
+        }
+        (AvroValue::Double(value), DataType::Float64) => {
+            ScalarValue::Float64(Some(*value))
+        }
+        (AvroValue::String(value), DataType::Utf8) => {
+            ScalarValue::Utf8(Some(value.clone()))
+        }
+        (AvroValue::Bytes(value), DataType::Binary) => {
+            ScalarValue::Binary(Some(value.clone()))
+        }
+        _ => return None,
+    })
+}
+
+/// A bound of a column of `data_type` in Iceberg's single-value serialization, `None`
+/// if it cannot be compared
+fn decode_bound(bytes: &[u8], data_type: &DataType) -> Option<ScalarValue> {
+    Some(match data_type {
+        DataType::Boolean => ScalarValue::Boolean(Some(*bytes.first()? != 0)),
+        DataType::Int32 => {
+            ScalarValue::Int32(Some(i32::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        DataType::Date32 => {
+            ScalarValue::Date32(Some(i32::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        // Columns promoted from int keep the bounds of the files written before
+        DataType::Int64 if bytes.len() == 4 => {
+            ScalarValue::Int64(Some(i32::from_le_bytes(bytes.try_into().ok()?) as i64))
+        }
+        DataType::Int64 => {
+            ScalarValue::Int64(Some(i64::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, tz) => {
+            ScalarValue::TimestampMicrosecond(
+                Some(i64::from_le_bytes(bytes.try_into().ok()?)),
+                tz.clone(),
+            )
+        }
+        DataType::Float32 => {
+            ScalarValue::Float32(Some(f32::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        DataType::Float64 => {
+            ScalarValue::Float64(Some(f64::from_le_bytes(bytes.try_into().ok()?)))
+        }
+        DataType::Utf8 => {
+            ScalarValue::Utf8(Some(String::from_utf8(bytes.to_vec()).ok()?))
+        }
+        DataType::Binary => ScalarValue::Binary(Some(bytes.to_vec())),
+        _ => return None,
+    })
+}
+
+fn format_version(metadata: &Value) -> i64 {
+    metadata
+        .get("format-version")
+        .and_then(Value::as_i64)
+        .unwrap_or(1)
+}
+
+/// The ID of the partition spec new data files are partitioned by
+fn default_spec_id(metadata: &Value) -> i64 {
+    metadata
+        .get("default-spec-id")
+        .and_then(Value::as_i64)
+        .unwrap_or_default()
+}
+
+/// The fields of the partition spec `spec_id`
+fn spec_fields(metadata: &Value, spec_id: i64) -> Result<Vec<Value>> {
+    let fields = match metadata.get("partition-specs").and_then(Value::as_array) {
+        Some(specs) => specs
+            .iter()
+            .find(|spec| {
+                spec.get("spec-id")
+                    .and_then(Value::as_i64)
+                    .unwrap_or_default()
+                    == spec_id
+            })
+            .ok_or_else(|| {
+                DataFusionError::Execution(format!(
+                    "Iceberg table metadata without partition spec {}",
+                    spec_id
+                ))
+            })?
+            .get("fields"),
+        // Tables of format version 1 may only have a single spec
+        None => metadata.get("partition-spec"),
+    };
+    Ok(fields
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default())
+}
+
+/// A field of a partition spec with an identity transform
+struct IdentityField {
+    name: String,
+    field_id: i64,
+    /// The name of the column whose values are the values of the field
+    column: String,
+    /// The Iceberg type of the column, which is also its Avro type
+    column_type: String,
+}
+
+/// The fields of the partition spec `spec_id` of a table to change, which must all be
+/// identity transforms of boolean, integer or string columns
+fn identity_fields(metadata: &Value, spec_id: i64) -> Result<Vec<IdentityField>> {
+    let schema = table_schema(metadata, None)?;
+    let columns = schema
+        .get("fields")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    spec_fields(metadata, spec_id)?
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            let unsupported = |what: String| {
+                DataFusionError::NotImplemented(format!(
+                    "Iceberg tables partitioned by {} cannot be changed",
+                    what
+                ))
+            };
+            let transform = field
+                .get("transform")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            if transform != "identity" {
+                return Err(unsupported(format!("{} transforms", transform)));
+            }
+            let source_id = field.get("source-id").and_then(Value::as_i64);
+            let column = columns
+                .iter()
+                .find(|column| column.get("id").and_then(Value::as_i64) == source_id)
+                .ok_or_else(|| {
+                    DataFusionError::Execution(format!(
+                        "Invalid Iceberg partition field {}",
+                        field
+                    ))
+                })?;
+            let column_type = match column.get("type") {
+                Some(Value::String(name))
+                    if ["boolean", "int", "long", "string"].contains(&name.as_str()) =>
+                {
+                    name.clone()
+                }
+                other => {
+                    return Err(unsupported(format!(
+                        "columns of type {}",
+                        other.unwrap_or(&Value::Null)
+                    )))
+                }
+            };
+            Ok(IdentityField {
+                name: field
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned(),
+                // Fields of specs of format version 1 may have implicit IDs
+                field_id: field
+                    .get("field-id")
+                    .and_then(Value::as_i64)
+                    .unwrap_or(1000 + i as i64),
+                column: column
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned(),
+                column_type,
+            })
+        })
+        .collect()
+}
+
+/// The value of a partition field of `column_type` of a written file, from the value of
+/// the column in the path of the file
+fn partition_value(value: Option<String>, column_type: &str) -> Result<AvroValue> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok(AvroValue::Null),
+    };
+    let invalid = || {
+        DataFusionError::Execution(format!(
+            "Invalid {} partition value {}",
+            column_type, value
+        ))
+    };
+    Ok(match column_type {
+        "boolean" => AvroValue::Boolean(value.parse().map_err(|_| invalid())?),
+        "int" => AvroValue::Int(value.parse().map_err(|_| invalid())?),
+        "long" => AvroValue::Long(value.parse().map_err(|_| invalid())?),
+        _ => AvroValue::String(value),
+    })
+}
+
+/// The summaries of the values of `num_fields` partition fields in `partitions`, with
+/// the bounds in Iceberg's single-value serialization
+fn partition_summaries(num_fields: usize, partitions: &[Vec<AvroValue>]) -> AvroValue {
+    let order = |a: &&AvroValue, b: &&AvroValue| match (*a, *b) {
+        (AvroValue::Boolean(a), AvroValue::Boolean(b)) => a.cmp(b),
+        (AvroValue::Int(a), AvroValue::Int(b)) => a.cmp(b),
+        (AvroValue::Long(a), AvroValue::Long(b)) => a.cmp(b),
+        (AvroValue::String(a), AvroValue::String(b)) => a.cmp(b),
+        _ => Ordering::Equal,
+    };
+    let bound = |value: Option<&AvroValue>| match value {
+        Some(AvroValue::Boolean(value)) => AvroValue::Bytes(vec![*value as u8]),
+        Some(AvroValue::Int(value)) => AvroValue::Bytes(value.to_le_bytes().to_vec()),
+        Some(AvroValue::Long(value)) => AvroValue::Bytes(value.to_le_bytes().to_vec()),
+        Some(AvroValue::String(value)) => AvroValue::Bytes(value.as_bytes().to_vec()),
+        _ => AvroValue::Null,
+    };
+    AvroValue::Array(
+        (0..num_fields)
+            .map(|i| {
+                let values: Vec<&AvroValue> =
+                    partitions.iter().map(|partition| &partition[i]).collect();
+                let non_null = values
+                    .iter()
+                    .copied()
+                    .filter(|value| !matches!(value, AvroValue::Null));
+                avro_record(vec![
+                    (
+                        "contains_null",
+                        AvroValue::Boolean(non_null.clone().count() < values.len()),
+                    ),
+                    ("lower_bound", bound(non_null.clone().min_by(order))),
+                    ("upper_bound", bound(non_null.max_by(order))),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// The path of the file of the record of a data file
+fn file_path(data_file: &AvroValue) -> Result<&str> {
+    avro_string(avro_field(data_file, "file_path")).ok_or_else(|| {
+        DataFusionError::Execution("Iceberg data file without file_path".to_owned())
+    })
+}
+
+fn avro_record(fields: Vec<(&str, AvroValue)>) -> AvroValue {
+    AvroValue::Record(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value))
+            .collect(),
+    )
+}
+
+/// Set the field `name` of an Avro record to `value`, or to null if `None`, if the
+/// record has that field
+fn set_field(record: &mut AvroValue, name: &str, value: Option<AvroValue>) {
+    if let AvroValue::Record(fields) = record {
+        if let Some((_, field)) = fields.iter_mut().find(|(field, _)| field == name) {
+            *field = value.unwrap_or(AvroValue::Null);
+        }
+    }
+}
+
+/// `value` as a value of `schema`. The fields of records are taken by name, so records
+/// read with another schema can be written with this one, and missing optional fields
+/// are null.
+fn conform(schema: &AvroSchema, value: Option<&AvroValue>) -> Result<AvroValue> {
+    let value = value.and_then(non_null);
+    Ok(match (schema, value) {
+        (AvroSchema::Union(union), value) => {
+            let variants = union.variants();
+            let index = variants
+                .iter()
+                .position(|variant| {
+                    matches!(variant, AvroSchema::Null) == value.is_none()
+                })
+                .ok_or_else(|| {
+                    DataFusionError::Execution(format!(
+                        "Iceberg value {:?} does not match the schema {:?}",
+                        value, schema
+                    ))
+                })?;
+            AvroValue::Union(index as u32, Box::new(conform(&variants[index], value)?))
+        }
+        (AvroSchema::Null, None) => AvroValue::Null,
+        (AvroSchema::Record { fields, .. }, Some(record)) => AvroValue::Record(
+            fields
+                .iter()
+                .map(|field| {
+                    let value = avro_record_field(record, &field.name);
+                    Ok((field.name.clone(), conform(&field.schema, value)?))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        (AvroSchema::Array(items), Some(AvroValue::Array(values))) => AvroValue::Array(
+            values
+                .iter()
+                .map(|value| conform(items, Some(value)))
+                .collect::<Result<_>>()?,
+        ),
+        (AvroSchema::Map(schema), Some(AvroValue::Map(values))) => AvroValue::Map(
+            values
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), conform(schema, Some(value))?)))
+                .collect::<Result<_>>()?,
+        ),
+        (AvroSchema::Long, Some(AvroValue::Int(value))) => AvroValue::Long(*value as i64),
+        (AvroSchema::Int, Some(AvroValue::Long(value))) => AvroValue::Int(*value as i32),
+        (_, Some(value)) => value.clone(),
+        (_, None) => {
+            return Err(DataFusionError::Execution(format!(
+                "Missing Iceberg value of schema {:?}",
+                schema
+            )))
+        }
+    })
+}
+
+/// An Avro file of `records` with the key-value `metadata`
+fn avro_file(
+    schema: &AvroSchema,
+    metadata: &[(&str, String)],
+    records: Vec<AvroValue>,
+) -> Result<Vec<u8>> {
+    let mut writer = Writer::new(schema, Vec::new());
+    for (key, value) in metadata {
+        writer
+            .add_user_metadata(key.to_string(), value)
+            .map_err(avro_error)?;
+    }
+    for record in records {
+        writer.append(record).map_err(avro_error)?;
+    }
+    writer.into_inner().map_err(avro_error)
+}
+
+/// The key-value metadata of a manifest of data files partitioned by spec `spec_id`
+fn manifest_metadata(
+    metadata: &Value,
+    spec_id: i64,
+) -> Result<Vec<(&'static str, String)>> {
+    let schema_id = metadata
+        .get("current-schema-id")
+        .and_then(Value::as_i64)
+        .unwrap_or_default();
+    Ok(vec![
+        ("schema", table_schema(metadata, None)?.to_string()),
+        ("schema-id", schema_id.to_string()),
+        (
+            "partition-spec",
+            Value::Array(spec_fields(metadata, spec_id)?).to_string(),
+        ),
+        ("partition-spec-id", spec_id.to_string()),
+        ("format-version", format_version(metadata).to_string()),
+        ("content", "data".to_owned()),
+    ])
+}
+
+fn optional_field(name: &str, field_type: Value, id: i64) -> Value {
+    json!({"name": name, "type": ["null", field_type], "default": null, "field-id": id})
+}
+
+fn required_field(name: &str, field_type: Value, id: i64) -> Value {
+    json!({"name": name, "type": field_type, "field-id": id})
+}
+
+/// The schema of the entries of manifests of tables of `format_version`, listing data
+/// files partitioned by `fields`
+fn manifest_schema(format_version: i64, fields: &[IdentityField]) -> Result<AvroSchema> {
+    let partition: Vec<Value> = fields
+        .iter()
+        .map(|field| {
+            optional_field(&field.name, json!(field.column_type), field.field_id)
+        })
+        .collect();
+    let mut data_file = vec![
+        required_field("file_path", json!("string"), 100),
+        required_field("file_format", json!("string"), 101),
+        required_field(
+            "partition",
+            json!({"type": "record", "name": "r102", "fields": partition}),
+            102,
+        ),
+        required_field("record_count", json!("long"), 103),
+        required_field("file_size_in_bytes", json!("long"), 104),
+    ];
+    let mut entry = vec![required_field("status", json!("int"), 0)];
+    if format_version == 1 {
+        data_file.push(required_field("block_size_in_bytes", json!("long"), 105));
+        entry.push(required_field("snapshot_id", json!("long"), 1));
+    } else {
+        data_file.insert(0, required_field("content", json!("int"), 134));
+        entry.push(optional_field("snapshot_id", json!("long"), 1));
+        entry.push(optional_field("sequence_number", json!("long"), 3));
+        entry.push(optional_field("file_sequence_number", json!("long"), 4));
+    }
+    entry.push(required_field(
+        "data_file",
+        json!({"type": "record", "name": "r2", "fields": data_file}),
+        2,
+    ));
+    let schema = json!({"type": "record", "name": "manifest_entry", "fields": entry});
+    AvroSchema::parse_str(&schema.to_string()).map_err(avro_error)
+}
+
+/// The schema of manifest lists of tables of `format_version`
+fn manifest_list_schema(format_version: i64) -> Result<AvroSchema> {
+    let summary = json!({"type": "record", "name": "r508", "fields": [
+        required_field("contains_null", json!("boolean"), 509),
+        optional_field("contains_nan", json!("boolean"), 518),
+        optional_field("lower_bound", json!("bytes"), 510),
+        optional_field("upper_bound", json!("bytes"), 511),
+    ]});
+    let mut fields = vec![
+        required_field("manifest_path", json!("string"), 500),
+        required_field("manifest_length", json!("long"), 501),
+        required_field("partition_spec_id", json!("int"), 502),
+    ];
+    if format_version == 1 {
+        fields.push(optional_field("added_snapshot_id", json!("long"), 503));
+    } else {
+        fields.extend([
+            required_field("content", json!("int"), 517),
+            required_field("sequence_number", json!("long"), 515),
+            required_field("min_sequence_number", json!("long"), 516),
+            required_field("added_snapshot_id", json!("long"), 503),
+            required_field("added_files_count", json!("int"), 504),
+            required_field("existing_files_count", json!("int"), 505),
+            required_field("deleted_files_count", json!("int"), 506),
+            required_field("added_rows_count", json!("long"), 512),
+            required_field("existing_rows_count", json!("long"), 513),
+            required_field("deleted_rows_count", json!("long"), 514),
+        ]);
+    }
+    fields.push(optional_field(
+        "partitions",
+        json!({"type": "array", "items": summary, "element-id": 508}),
+        507,
+    ));
+    let schema = json!({"type": "record", "name": "manifest_file", "fields": fields});
+    AvroSchema::parse_str(&schema.to_string()).map_err(avro_error)
+}
+
+/// Add `snapshot` listing the manifests of `manifest_list` to `metadata`, as the current
+/// snapshot following `parent_id`, replacing the metadata file `previous_metadata`
+fn add_snapshot(
+    metadata: &mut Value,
+    snapshot: &NewSnapshot,
+    parent_id: Option<i64>,
+    manifest_list: &str,
+    operation: &str,
+    previous_metadata: String,
+) -> Result<()> {
+    let format_version = format_version(metadata);
+    let metadata: &mut Map<String, Value> =
+        metadata.as_object_mut().ok_or_else(|| {
+            DataFusionError::Execution("Invalid Iceberg metadata".to_owned())
+        })?;
+    let mut entry = json!({
+        "snapshot-id": snapshot.id,
+        "timestamp-ms": snapshot.timestamp_ms,
+        "manifest-list": manifest_list,
+        "summary": {"operation": operation},
+    });
+    if let Some(parent_id) = parent_id {
+        entry["parent-snapshot-id"] = json!(parent_id);
+    }
+    if let Some(sequence_number) = snapshot.sequence_number {
+        entry["sequence-number"] = json!(sequence_number);
+        metadata.insert("last-sequence-number".to_owned(), json!(sequence_number));
+    }
+    if let Some(schema_id) = metadata.get("current-schema-id") {
+        entry["schema-id"] = schema_id.clone();
+    }
+
+    let updated = metadata
+        .get("last-updated-ms")
+        .cloned()
+        .unwrap_or_else(|| json!(snapshot.timestamp_ms));
+    // Without a snapshot log, snapshots are assumed to have been current since they
+    // were committed
+    if let Some(Value::Array(log)) = metadata.get_mut("snapshot-log") {
+        log.push(
+            json!({"timestamp-ms": snapshot.timestamp_ms, "snapshot-id": snapshot.id}),
+        );
+    }
+    for (key, value) in [
+        ("snapshots", entry),
+        (
+            "metadata-log",
+            json!({"timestamp-ms": updated, "metadata-file": previous_metadata}),
+        ),
+    ] {
+        if let Value::Array(values) =
+            metadata.entry(key.to_owned()).or_insert_with(|| json!([]))
+        {
+            values.push(value);
+        }
+    }
+    metadata.insert("current-snapshot-id".to_owned(), json!(snapshot.id));
+    metadata.insert("last-updated-ms".to_owned(), json!(snapshot.timestamp_ms));
+    if format_version > 1 || metadata.contains_key("refs") {
+        metadata
+            .entry("refs".to_owned())
+            .or_insert_with(|| json!({}))["main"] =
+            json!({"snapshot-id": snapshot.id, "type": "branch"});
+    }
+    Ok(())
+}
+
+/// Whether rows of a manifest or data file with the column `ranges` may match `filter`
+fn may_match(filter: &Expr, ranges: &HashMap<String, ColumnRange>) -> bool {
+    let range_of = |expr: &Expr| match expr {
+        Expr::Column(column) => ranges.get(&column.name),
+        _ => None,
+    };
+    let literal = |expr: &Expr| match expr {
+        Expr::Literal(value) if !value.is_null() => Some(value.clone()),
+        _ => None,
+    };
+    match filter {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => may_match(left.as_ref(), ranges) && may_match(right.as_ref(), ranges),
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Or,
+            right,
+        } => may_match(left.as_ref(), ranges) || may_match(right.as_ref(), ranges),
+        Expr::BinaryExpr { left, op, right } => {
+            let (left, right) = (left.as_ref(), right.as_ref());
+            match (range_of(left), literal(right)) {
+                (Some(range), Some(value)) => compare(range, *op, &value),
+                _ => match (range_of(right), literal(left), flip(*op)) {
+                    (Some(range), Some(value), Some(op)) => compare(range, op, &value),
+                    _ => true,
+                },
+            }
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated: false,
+        } => match range_of(expr.as_ref()) {
+            Some(range) => list.iter().any(|item| {
+                literal(item).map_or(true, |value| compare(range, Operator::Eq, &value))
+            }),
+            None => true,
+        },
+        Expr::IsNull(expr) => {
+            range_of(expr.as_ref()).map_or(true, |range| range.has_nulls)
+        }
+        Expr::IsNotNull(expr) => {
+            range_of(expr.as_ref()).map_or(true, |range| !range.all_null)
+        }
+        _ => true,
+    }
+}
+
+/// The operator of a comparison with its operands swapped
+fn flip(op: Operator) -> Option<Operator> {
+    Some(match op {
+        Operator::Eq | Operator::NotEq => op,
+        Operator::Lt => Operator::Gt,
+        Operator::LtEq => Operator::GtEq,
+        Operator::Gt => Operator::Lt,
+        Operator::GtEq => Operator::LtEq,
+        _ => return None,
+    })
+}
+
+/// Whether `column op value` may be true for a column with values in `range`
+fn compare(range: &ColumnRange, op: Operator, value: &ScalarValue) -> bool {
+    // Comparisons with null are never true
+    if range.all_null {
+        return false;
+    }
+    let cmp = |bound: &Option<ScalarValue>| {
+        bound.as_ref().and_then(|bound| bound.partial_cmp(value))
+    };
+    let (min, max) = (cmp(&range.min), cmp(&range.max));
+    match op {
+        Operator::Eq => min != Some(Ordering::Greater) && max != Some(Ordering::Less),
+        Operator::NotEq => {
+            !(min == Some(Ordering::Equal) && max == Some(Ordering::Equal))
+        }
+        Operator::Lt => matches!(min, None | Some(Ordering::Less)),
+        Operator::LtEq => min != Some(Ordering::Greater),
+        Operator::Gt => matches!(max, None | Some(Ordering::Greater)),
+        Operator::GtEq => max != Some(Ordering::Less),
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::parquet::arrow::ArrowWriter;
+    use datafusion::prelude::SessionContext;
+    use std::fs::{self, File};
+
+    const MANIFEST_LIST_SCHEMA: &str = r#"{"type": "record", "name": "manifest_file", "fields": [
+        {"name": "manifest_path", "type": "string"},
+        {"name": "partition_spec_id", "type": "int"},
+        {"name": "content", "type": "int"},
+        {"name": "partitions", "type": {"type": "array", "items": {
+            "type": "record", "name": "field_summary", "fields": [
+                {"name": "contains_null", "type": "boolean"},
+                {"name": "lower_bound", "type": ["null", "bytes"]},
+                {"name": "upper_bound", "type": ["null", "bytes"]}]}}}]}"#;
+
+    const MANIFEST_SCHEMA: &str = r#"{"type": "record", "name": "manifest_entry", "fields": [
+        {"name": "status", "type": "int"},
+        {"name": "data_file", "type": {"type": "record", "name": "r2", "fields": [
+            {"name": "file_path", "type": "string"},
+            {"name": "file_format", "type": "string"},
+            {"name": "partition", "type": {"type": "record", "name": "r102", "fields": [
+                {"name": "part", "type": ["null", "string"]}]}},
+            {"name": "record_count", "type": "long"},
+            {"name": "file_size_in_bytes", "type": "long"},
+            {"name": "lower_bounds", "type": ["null", {"type": "array", "items": {
+                "type": "record", "name": "k126_v127", "fields": [
+                    {"name": "key", "type": "int"},
+                    {"name": "value", "type": "bytes"}]}}]},
+            {"name": "upper_bounds", "type": ["null", {"type": "array", "items": {
+                "type": "record", "name": "k129_v130", "fields": [
+                    {"name": "key", "type": "int"},
+                    {"name": "value", "type": "bytes"}]}}]}]}}]}"#;
+
+    fn write_avro(
+        path: &std::path::Path,
+        schema: &str,
+        records: Vec<AvroValue>,
+    ) -> Result<()> {
+        let schema = apache_avro::Schema::parse_str(schema).map_err(avro_error)?;
+        let mut writer = Writer::new(&schema, Vec::new());
+        for record in records {
+            writer.append(record).map_err(avro_error)?;
+        }
+        fs::write(path, writer.into_inner().map_err(avro_error)?)?;
+        Ok(())
+    }
+
+    fn bounds(values: Vec<(i32, Vec<u8>)>) -> AvroValue {
+        AvroValue::Union(
+            1,
+            Box::new(AvroValue::Array(
+                values
+                    .into_iter()
+                    .map(|(key, value)| {
+                        avro_record(vec![
+                            ("key", AvroValue::Int(key)),
+                            ("value", AvroValue::Bytes(value)),
+                        ])
+                    })
+                    .collect(),
+            )),
+        )
+    }
+
+    /// Write a data file with rows of `part` and `ids`
+    fn write_data_file(path: &std::path::Path, part: &str, ids: &[i64]) -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("part", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(ids.to_vec())),
+                Arc::new(StringArray::from(vec![part; ids.len()])),
+            ],
+        )?;
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        Ok(())
+    }
+
+    /// Write a data file with rows of `part` and `ids`, and a manifest listing it
+    fn write_manifest(
+        dir: &std::path::Path,
+        part: &str,
+        ids: Vec<i64>,
+    ) -> Result<String> {
+        let data_path = dir.join(format!("data/{}.parquet", part));
+        write_data_file(&data_path, part, &ids)?;
+
+        let (min, max) = (ids.iter().min().unwrap(), ids.iter().max().unwrap());
+        let data_file = avro_record(vec![
+            (
+                "file_path",
+                AvroValue::String(format!("file://{}", data_path.display())),
+            ),
+            ("file_format", AvroValue::String("PARQUET".to_owned())),
+            (
+                "partition",
+                avro_record(vec![(
+                    "part",
+                    AvroValue::Union(1, Box::new(AvroValue::String(part.to_owned()))),
+                )]),
+            ),
+            ("record_count", AvroValue::Long(ids.len() as i64)),
+            (
+                "file_size_in_bytes",
+                AvroValue::Long(fs::metadata(&data_path)?.len() as i64),
+            ),
+            (
+                "lower_bounds",
+                bounds(vec![(1, min.to_le_bytes().to_vec())]),
+            ),
+            (
+                "upper_bounds",
+                bounds(vec![(1, max.to_le_bytes().to_vec())]),
+            ),
+        ]);
+        let manifest_path = dir.join(format!("metadata/{}-m0.avro", part));
+        write_avro(
+            &manifest_path,
+            MANIFEST_SCHEMA,
+            vec![avro_record(vec![
+                ("status", AvroValue::Int(1)),
+                ("data_file", data_file),
+            ])],
+        )?;
+        Ok(manifest_path.display().to_string())
+    }
+
+    fn manifest_file(path: &str, part: &str) -> AvroValue {
+        let bound =
+            AvroValue::Union(1, Box::new(AvroValue::Bytes(part.as_bytes().to_vec())));
+        avro_record(vec![
+            ("manifest_path", AvroValue::String(path.to_owned())),
+            ("partition_spec_id", AvroValue::Int(0)),
+            ("content", AvroValue::Int(0)),
+            (
+                "partitions",
+                AvroValue::Array(vec![avro_record(vec![
+                    ("contains_null", AvroValue::Boolean(false)),
+                    ("lower_bound", bound.clone()),
+                    ("upper_bound", bound),
+                ])]),
+            ),
+        ])
+    }
+
+    async fn count(table: IcebergTable, filter: Option<Expr>) -> Result<usize> {
+        let ctx = SessionContext::new();
+        let mut df = ctx.read_table(Arc::new(table))?;
+        if let Some(filter) = filter {
+            df = df.filter(filter)?;
+        }
+        Ok(df.collect().await?.iter().map(|b| b.num_rows()).sum())
+    }
+
+    /// Write a table partitioned by `part` whose first snapshot has rows 1 and 2 of
+    /// partition `a`, and whose second and current snapshot adds rows 3 to 5 of `b`
+    fn write_table(dir: &std::path::Path) -> Result<()> {
+        let metadata_dir = dir.join(METADATA_DIR);
+        fs::create_dir_all(&metadata_dir)?;
+        let manifest_a = write_manifest(dir, "a", vec![1, 2])?;
+        let manifest_b = write_manifest(dir, "b", vec![3, 4, 5])?;
+
+        let list_1 = metadata_dir.join("snap-1.avro");
+        write_avro(
+            &list_1,
+            MANIFEST_LIST_SCHEMA,
+            vec![manifest_file(&manifest_a, "a")],
+        )?;
+        let list_2 = metadata_dir.join("snap-2.avro");
+        write_avro(
+            &list_2,
+            MANIFEST_LIST_SCHEMA,
+            vec![
+                manifest_file(&manifest_a, "a"),
+                manifest_file(&manifest_b, "b"),
+            ],
+        )?;
+
+        let metadata = serde_json::json!({
+            "format-version": 2,
+            "location": dir.display().to_string(),
+            "current-schema-id": 0,
+            "schemas": [{"type": "struct", "schema-id": 0, "fields": [
+                {"id": 1, "name": "id", "required": false, "type": "long"},
+                {"id": 2, "name": "part", "required": false, "type": "string"},
+            ]}],
+            "default-spec-id": 0,
+            "partition-specs": [{"spec-id": 0, "fields": [
+                {"name": "part", "transform": "identity", "source-id": 2, "field-id": 1000},
+            ]}],
+            "current-snapshot-id": 2,
+            "snapshots": [
+                {"snapshot-id": 1, "timestamp-ms": 1000, "manifest-list": list_1.display().to_string()},
+                {"snapshot-id": 2, "timestamp-ms": 2000, "manifest-list": list_2.display().to_string()},
+            ],
+            "snapshot-log": [
+                {"snapshot-id": 1, "timestamp-ms": 1000},
+                {"snapshot-id": 2, "timestamp-ms": 2000},
+            ],
+        });
+        fs::write(metadata_dir.join("v2.metadata.json"), metadata.to_string())?;
+        fs::write(metadata_dir.join("version-hint.text"), "2")?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_snapshots() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write_table(dir.path())?;
+
+        let location = dir.path().to_str().unwrap();
+        let runtime = SessionContext::new().runtime_env();
+        let load = |selection| IcebergTable::load(location, selection, runtime.clone());
+
+        let table = load(SnapshotSelection::Current).await?;
+        assert_eq!(table.snapshot_id(), Some(2));
+        assert_eq!(
+            table.data_files(&[col("part").eq(lit("b"))]).await?.len(),
+            1
+        );
+        assert_eq!(
+            table.data_files(&[col("id").lt_eq(lit(2i64))]).await?.len(),
+            1
+        );
+        assert_eq!(table.data_files(&[col("id").gt(lit(5i64))]).await?.len(), 0);
+        assert_eq!(count(table, None).await?, 5);
+
+        let table = load(SnapshotSelection::Current).await?;
+        assert_eq!(count(table, Some(col("part").eq(lit("b")))).await?, 3);
+        assert_eq!(count(load(SnapshotSelection::Id(1)).await?, None).await?, 2);
+        assert_eq!(
+            count(load(SnapshotSelection::AsOf(1500)).await?, None).await?,
+            2
+        );
+        assert!(load(SnapshotSelection::AsOf(500)).await.is_err());
+        assert!(load(SnapshotSelection::Id(3)).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn nested_field_ids() {
+        let schema = serde_json::json!({"type": "struct", "fields": [
+            {"id": 1, "name": "id", "required": true, "type": "long"},
+            {"id": 2, "name": "address", "required": false, "type": {
+                "type": "struct", "fields": [
+                    {"id": 3, "name": "city", "required": false, "type": "string"},
+                ],
+            }},
+            {"id": 4, "name": "tags", "required": false, "type": {
+                "type": "list", "element-id": 5, "element-required": false,
+                "element": "string",
+            }},
+        ]});
+        let mut ids = HashMap::new();
+        add_field_ids(&schema, "", &mut ids);
+        let mut ids: Vec<_> = ids.into_iter().collect();
+        ids.sort_unstable_by_key(|(_, id)| *id);
+        assert_eq!(
+            ids,
+            vec![
+                ("id".to_owned(), 1),
+                ("address".to_owned(), 2),
+                ("address.city".to_owned(), 3),
+                ("tags".to_owned(), 4),
+                ("tags.list.element".to_owned(), 5),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rewrite_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        write_table(dir.path())?;
+
+        // Only the files which may hold the changed rows are rewritten
+        let location = dir.path().to_str().unwrap();
+        let runtime = SessionContext::new().runtime_env();
+        let filters = vec![col("part").eq(lit("b")).and(col("id").eq(lit(4i64)))];
+        let rewrite = IcebergFormat
+            .rewrite(location, &BTreeMap::new(), &filters, runtime.clone())
+            .await?;
+        assert_eq!(rewrite.partition_by, vec!["part"]);
+        assert!(rewrite.location.ends_with("/data"));
+        let field_ids: HashMap<String, i32> =
+            vec![("id".to_owned(), 1), ("part".to_owned(), 2)]
+                .into_iter()
+                .collect();
+        assert_eq!(rewrite.field_ids, field_ids);
+        let ctx = SessionContext::new();
+        let rows = ctx.read_table(rewrite.table.clone())?.collect().await?;
+        assert_eq!(rows.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+
+        // The rows which are kept are written to a new file, which replaces the
+        // rewritten one in a new snapshot
+        let data_path = dir.path().join("data/part=b/1.parquet");
+        write_data_file(&data_path, "b", &[3, 5])?;
+        let written = vec![WrittenFile {
+            path: "part=b/1.parquet".to_owned(),
+            num_rows: 2,
+            size: fs::metadata(&data_path)?.len(),
+            partition_values: vec![("part".to_owned(), Some("b".to_owned()))]
+                .into_iter()
+                .collect(),
+        }];
+        rewrite.commit.commit(&written, &runtime).await?;
+        let metadata_dir = dir.path().join(METADATA_DIR);
+        assert_eq!(
+            fs::read_to_string(metadata_dir.join("version-hint.text"))?,
+            "3"
+        );
+        let load = |selection| IcebergTable::load(location, selection, runtime.clone());
+        let table = load(SnapshotSelection::Current).await?;
+        assert_ne!(table.snapshot_id(), Some(2));
+        // The new manifest is pruned by its partition summaries
+        let files = table.data_files(&[col("part").eq(lit("b"))]).await?;
+        assert_eq!(files.len(), 1);
+        assert!(files[0]
+            .object_meta
+            .location
+            .as_ref()
+            .ends_with("part=b/1.parquet"));
+        assert_eq!(count(table, None).await?, 4);
+        assert_eq!(count(load(SnapshotSelection::Id(2)).await?, None).await?, 5);
+
+        // A rewrite planned with a previous snapshot is not committed
+        assert!(rewrite.commit.commit(&written, &runtime).await.is_err());
+        assert!(!metadata_dir.join("v4.metadata.json").exists());
+
+        // Tables partitioned by other transforms cannot be changed
+        let metadata_path = metadata_dir.join("v3.metadata.json");
+        let mut metadata: Value = serde_json::from_slice(&fs::read(&metadata_path)?)
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        metadata["partition-specs"][0]["fields"][0]["transform"] = json!("bucket[4]");
+        fs::write(&metadata_path, metadata.to_string())?;
+        assert!(IcebergFormat
+            .rewrite(location, &BTreeMap::new(), &filters, runtime)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn prune_ranges() {
+        let ranges: HashMap<_, _> = vec![
+            (
+                "id".to_owned(),
+                ColumnRange {
+                    min: decode_bound(&3i64.to_le_bytes(), &DataType::Int64),
+                    max: decode_bound(&5i64.to_le_bytes(), &DataType::Int64),
+                    has_nulls: false,
+                    all_null: false,
+                },
+            ),
+            (
+                "name".to_owned(),
+                ColumnRange {
+                    min: None,
+                    max: None,
+                    has_nulls: true,
+                    all_null: true,
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let check = |filter: Expr| may_match(&filter, &ranges);
+
+        assert!(check(col("id").eq(lit(4i64))));
+        assert!(!check(col("id").eq(lit(6i64))));
+        assert!(!check(lit(3i64).gt(col("id"))));
+        assert!(check(col("id").gt_eq(lit(5i64))));
+        assert!(!check(col("id").gt(lit(5i64))));
+        assert!(check(col("id").gt(lit(5i64)).or(col("id").lt(lit(4i64)))));
+        assert!(!check(col("id").gt(lit(5i64)).and(col("id").lt(lit(4i64)))));
+        assert!(!check(col("id").in_list(vec![lit(1i64), lit(2i64)], false)));
+        assert!(!check(col("id").is_null()));
+        assert!(!check(col("name").eq(lit("x"))));
+        assert!(!check(col("name").is_not_null()));
+        // Literals of other types and unknown columns are never pruned
+        assert!(check(col("id").eq(lit(6i32))));
+        assert!(check(col("other").eq(lit(6i64))));
+
+        assert_eq!(metadata_version("v12.metadata.json"), Some(12));
+        assert_eq!(metadata_version("00003-6b9d.metadata.json"), Some(3));
+        assert_eq!(metadata_version("version-hint.text"), None);
+    }
+}
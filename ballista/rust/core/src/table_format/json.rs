@@ -0,0 +1,257 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tables of newline-delimited JSON files.
+//!
+//! Logical plans can not serialize scans of JSON files, so clients send them to the
+//! scheduler as [ExternalTableScan]s of this format, which carry the schema of the table.
+//! The scheduler scans the files with DataFusion's JSON reader, and the resulting
+//! `NdJsonExec`s are serialized to executors.
+//!
+//! Options:
+//! - `file_extension`: extension of the files to read, `.json` by default
+//! - `table_partition_cols`: comma-separated partition columns, read from the paths of
+//!   the files
+//! - `target_partitions`: number of partitions the files are scanned in
+
+use crate::table_format::{ExternalTableScan, TableFormat};
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{Schema, SchemaRef};
+use datafusion::datasource::file_format::json::{JsonFormat, DEFAULT_JSON_EXTENSION};
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{
+    ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl,
+};
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_plan::Expr;
+use datafusion::prelude::SessionConfig;
+use futures::TryStreamExt;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The name of the format
+pub const JSON: &str = "json";
+
+/// Newline-delimited JSON files, whose schema is inferred from the files
+pub struct JsonTableFormat;
+
+#[async_trait]
+impl TableFormat for JsonTableFormat {
+    async fn open(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let url = ListingTableUrl::parse(location)?;
+        let listing_options = listing_options(options)?;
+        let store = runtime.object_store(url.object_store())?;
+        let files: Vec<_> = url
+            .list_all_files(store.as_ref(), &listing_options.file_extension)
+            .try_collect()
+            .await?;
+        if files.is_empty() {
+            return Err(DataFusionError::Plan(format!(
+                "No {} files found at {}",
+                listing_options.file_extension, location
+            )));
+        }
+        let schema = listing_options.format.infer_schema(&store, &files).await?;
+        listing_table(url, listing_options, schema)
+    }
+
+    async fn open_with_schema(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        schema: SchemaRef,
+        _runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        let listing_options = listing_options(options)?;
+        // The partition columns are appended to the columns of the files again
+        let file_schema = Schema::new(
+            schema
+                .fields()
+                .iter()
+                .filter(|field| {
+                    !listing_options.table_partition_cols.contains(field.name())
+                })
+                .cloned()
+                .collect(),
+        );
+        listing_table(
+            ListingTableUrl::parse(location)?,
+            listing_options,
+            Arc::new(file_schema),
+        )
+    }
+}
+
+/// The scan of `table` as an [ExternalTableScan], if it reads JSON files
+pub(crate) fn to_external_scan(
+    table_name: &str,
+    table: &ListingTable,
+    projection: Option<Vec<usize>>,
+    filters: Vec<Expr>,
+) -> Result<Option<ExternalTableScan>> {
+    let listing_options = table.options();
+    if !listing_options.format.as_any().is::<JsonFormat>() {
+        return Ok(None);
+    }
+    let location = match table.table_paths().as_slice() {
+        [path] => path.to_string(),
+        _ => {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Scans of JSON table {} with several paths are not supported",
+                table_name
+            )))
+        }
+    };
+    let mut options = BTreeMap::new();
+    options.insert(
+        "file_extension".to_owned(),
+        listing_options.file_extension.clone(),
+    );
+    options.insert(
+        "table_partition_cols".to_owned(),
+        listing_options.table_partition_cols.join(","),
+    );
+    options.insert(
+        "target_partitions".to_owned(),
+        listing_options.target_partitions.to_string(),
+    );
+    Ok(Some(ExternalTableScan::try_new(
+        table_name.to_owned(),
+        JSON.to_owned(),
+        location,
+        options,
+        table.schema(),
+        projection,
+        filters,
+    )?))
+}
+
+fn listing_options(options: &BTreeMap<String, String>) -> Result<ListingOptions> {
+    let target_partitions = match options.get("target_partitions") {
+        Some(value) => value.parse().map_err(|_| {
+            DataFusionError::Plan(format!("Invalid target_partitions {}", value))
+        })?,
+        None => SessionConfig::new().target_partitions,
+    };
+    Ok(ListingOptions {
+        file_extension: options
+            .get("file_extension")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_JSON_EXTENSION.to_owned()),
+        format: Arc::new(JsonFormat::default()),
+        table_partition_cols: options
+            .get("table_partition_cols")
+            .map(|columns| {
+                columns
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|column| !column.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default(),
+        collect_stat: false,
+        target_partitions,
+    })
+}
+
+fn listing_table(
+    url: ListingTableUrl,
+    listing_options: ListingOptions,
+    file_schema: SchemaRef,
+) -> Result<Arc<dyn TableProvider>> {
+    let config = ListingTableConfig::new(url)
+        .with_schema(file_schema)
+        .with_listing_options(listing_options);
+    Ok(Arc::new(ListingTable::try_new(config)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde::BallistaLogicalExtensionCodec;
+    use datafusion::logical_plan::plan::Extension;
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::prelude::SessionContext;
+    use datafusion_proto::logical_plan::LogicalExtensionCodec;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn open_json_tables() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let partition = dir.path().join("day=1");
+        std::fs::create_dir(&partition)?;
+        let mut file = std::fs::File::create(partition.join("part-0.json"))?;
+        writeln!(file, r#"{{"id": 1, "name": "a"}}"#)?;
+        writeln!(file, r#"{{"id": 2, "name": "b"}}"#)?;
+
+        let mut options = BTreeMap::new();
+        options.insert("table_partition_cols".to_owned(), "day".to_owned());
+        let location = format!("{}/", dir.path().to_str().unwrap());
+        let runtime = Arc::new(RuntimeEnv::default());
+        let table = JsonTableFormat
+            .open(&location, &options, runtime.clone())
+            .await?;
+        let names: Vec<_> = table
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(names, vec!["id", "name", "day"]);
+
+        let reopened = JsonTableFormat
+            .open_with_schema(&location, &options, table.schema(), runtime)
+            .await?;
+        assert_eq!(reopened.schema(), table.schema());
+
+        let listing_table = reopened.as_any().downcast_ref::<ListingTable>().unwrap();
+        let filters = vec![col("id").gt(lit(1i64))];
+        let scan = to_external_scan("t", listing_table, Some(vec![0]), filters.clone())?
+            .unwrap();
+        assert_eq!(scan.format, JSON);
+        assert_eq!(scan.options.get("table_partition_cols").unwrap(), "day");
+        assert_eq!(scan.table_schema, table.schema());
+        assert_eq!(scan.filters, filters);
+
+        // The filters are sent to the scheduler along with the scan
+        let codec = BallistaLogicalExtensionCodec::default();
+        let mut buf = vec![];
+        codec.try_encode(
+            &Extension {
+                node: Arc::new(scan),
+            },
+            &mut buf,
+        )?;
+        let decoded = codec.try_decode(&buf, &[], &SessionContext::new())?;
+        let decoded = decoded
+            .node
+            .as_any()
+            .downcast_ref::<ExternalTableScan>()
+            .unwrap();
+        assert_eq!(decoded.filters, filters);
+        assert_eq!(decoded.projection, Some(vec![0]));
+        Ok(())
+    }
+}
@@ -0,0 +1,307 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tables of the Parquet files written by `COPY` statements.
+//!
+//! Only the files listed in the `_manifest.json` of the location are scanned, so the
+//! files of failed writes, and of writes which are not committed yet, are not part of
+//! the table. Clients register Parquet files at a location with a manifest as tables of
+//! this format.
+
+use super::delta::{prune, references_any};
+use super::TableFormat;
+use crate::execution_plans::{read_manifest, WrittenFile};
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{ListingTableUrl, PartitionedFile};
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_expr::TableProviderFilterPushDown;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::file_format::{FileScanConfig, ParquetExec};
+use datafusion::physical_plan::{ExecutionPlan, Statistics};
+use datafusion::scalar::ScalarValue;
+use object_store::path::Path;
+use object_store::ObjectMeta;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// The name of the format
+pub const MANIFEST: &str = "manifest";
+
+/// The committed output of `COPY` statements
+pub struct ManifestFormat;
+
+#[async_trait]
+impl TableFormat for ManifestFormat {
+    async fn open(
+        &self,
+        location: &str,
+        _options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        Ok(Arc::new(ManifestTable::load(location, runtime).await?))
+    }
+}
+
+/// Whether output was committed to `location`, whose files are then read as a table of
+/// this format
+pub async fn has_manifest(location: &str, runtime: &RuntimeEnv) -> Result<bool> {
+    let url = ListingTableUrl::parse(location)?;
+    let store = runtime.object_store(url.object_store())?;
+    Ok(read_manifest(store.as_ref(), &url).await?.is_some())
+}
+
+/// The files listed in the manifest of a location
+pub struct ManifestTable {
+    url: ListingTableUrl,
+    schema: SchemaRef,
+    /// Schema of the files, which do not contain the partition columns
+    file_schema: SchemaRef,
+    partition_by: Vec<String>,
+    files: Vec<WrittenFile>,
+}
+
+impl ManifestTable {
+    pub async fn load(location: &str, runtime: Arc<RuntimeEnv>) -> Result<Self> {
+        let url = ListingTableUrl::parse(location)?;
+        let store = runtime.object_store(url.object_store())?;
+        let manifest = read_manifest(store.as_ref(), &url).await?.ok_or_else(|| {
+            DataFusionError::Plan(format!("No output was committed to {}", location))
+        })?;
+        let first = manifest.files.first().ok_or_else(|| {
+            DataFusionError::Plan(format!("The output at {} has no files", location))
+        })?;
+        let meta = store
+            .head(&file_path(&url, first)?)
+            .await
+            .map_err(|e| DataFusionError::External(Box::new(e)))?;
+        let file_schema = ParquetFormat::default()
+            .infer_schema(&store, &[meta])
+            .await?;
+
+        // Like the listing tables of DataFusion, partition columns come after the columns
+        // of the files
+        let mut fields = file_schema.fields().clone();
+        for column in &manifest.partition_by {
+            fields.push(Field::new(
+                column,
+                DataType::Dictionary(
+                    Box::new(DataType::UInt16),
+                    Box::new(DataType::Utf8),
+                ),
+                true,
+            ));
+        }
+        Ok(Self {
+            url,
+            schema: Arc::new(Schema::new(fields)),
+            file_schema,
+            partition_by: manifest.partition_by,
+            files: manifest.files,
+        })
+    }
+
+    fn partitioned_file(&self, file: &WrittenFile) -> Result<PartitionedFile> {
+        Ok(PartitionedFile {
+            object_meta: ObjectMeta {
+                location: file_path(&self.url, file)?,
+                last_modified: Utc.timestamp_millis(0),
+                size: file.size as usize,
+            },
+            partition_values: self
+                .partition_by
+                .iter()
+                .map(|column| {
+                    ScalarValue::Utf8(
+                        file.partition_values.get(column).cloned().flatten(),
+                    )
+                })
+                .collect(),
+            range: None,
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for ManifestTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let files = self
+            .files
+            .iter()
+            .filter(|file| {
+                let values: HashMap<String, Option<String>> = file
+                    .partition_values
+                    .iter()
+                    .map(|(column, value)| (column.clone(), value.clone()))
+                    .collect();
+                !filters.iter().any(|filter| {
+                    prune(filter, &self.partition_by, &values) == Some(false)
+                })
+            })
+            .map(|file| self.partitioned_file(file))
+            .collect::<Result<Vec<_>>>()?;
+        if files.is_empty() {
+            let schema = match projection {
+                Some(columns) => Arc::new(self.schema.project(columns)?),
+                None => self.schema.clone(),
+            };
+            return Ok(Arc::new(EmptyExec::new(false, schema)));
+        }
+        let target_partitions = ctx.config.target_partitions.max(1);
+        let chunk_size = (files.len() + target_partitions - 1) / target_partitions;
+        let file_groups = files
+            .chunks(chunk_size.max(1))
+            .map(|chunk| chunk.to_vec())
+            .collect();
+
+        // The pruning predicate is evaluated against the statistics of the files, which
+        // do not contain the partition columns
+        let predicate = filters
+            .iter()
+            .filter(|filter| !references_any(filter, &self.partition_by))
+            .cloned()
+            .reduce(|a, b| a.and(b));
+
+        Ok(Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: self.url.object_store(),
+                file_schema: self.file_schema.clone(),
+                file_groups,
+                statistics: Statistics::default(),
+                projection: projection.clone(),
+                limit,
+                table_partition_cols: self.partition_by.clone(),
+            },
+            predicate,
+            None,
+        )))
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        _filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown> {
+        Ok(TableProviderFilterPushDown::Inexact)
+    }
+}
+
+/// The path of a file listed in the manifest of the output at `url`
+fn file_path(url: &ListingTableUrl, file: &WrittenFile) -> Result<Path> {
+    // Paths in manifests are already encoded
+    Path::parse(format!("{}/{}", url.prefix(), file.path))
+        .map_err(|e| DataFusionError::External(Box::new(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::execution_plans::ParquetSinkExec;
+    use datafusion::arrow::array::{Int32Array, StringArray};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn scan_committed_files() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("year", DataType::Int32, false),
+            Field::new("value", DataType::Utf8, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int32Array::from(vec![2021, 2022, 2022])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )?;
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().to_str().unwrap().to_owned();
+        let ctx = SessionContext::new();
+        let runtime = ctx.runtime_env();
+        assert!(!has_manifest(&location, &runtime).await?);
+
+        let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema, None)?);
+        let sink = ParquetSinkExec::try_new(
+            input,
+            location.clone(),
+            vec!["year".to_owned()],
+            "w1".to_owned(),
+        )?;
+        sink.execute(0, ctx.task_ctx())?.next().await.unwrap()?;
+        sink.commit(&runtime, &[0]).await?;
+        // The files of a write which is not committed are not scanned
+        let uncommitted = ParquetSinkExec::try_new(
+            sink.input().clone(),
+            location.clone(),
+            vec!["year".to_owned()],
+            "w2".to_owned(),
+        )?;
+        uncommitted
+            .execute(0, ctx.task_ctx())?
+            .next()
+            .await
+            .unwrap()?;
+        assert!(has_manifest(&location, &runtime).await?);
+
+        let table = ManifestFormat
+            .open(&location, &BTreeMap::new(), runtime)
+            .await?;
+        let names: Vec<_> = table
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(names, vec!["value", "year"]);
+        let count = |df: Arc<datafusion::dataframe::DataFrame>| async move {
+            let batches = df.collect().await?;
+            Ok::<_, DataFusionError>(batches.iter().map(|b| b.num_rows()).sum::<usize>())
+        };
+        assert_eq!(count(ctx.read_table(table.clone())?).await?, 3);
+        let filtered = ctx.read_table(table)?.filter(col("year").eq(lit("2022")))?;
+        assert_eq!(count(filtered).await?, 2);
+        Ok(())
+    }
+}
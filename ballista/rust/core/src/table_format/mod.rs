@@ -0,0 +1,953 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tables stored in table formats such as Delta Lake and Apache Iceberg, tables of
+//! newline-delimited JSON files, and tables on the local disks of nodes.
+//!
+//! Clients register these tables as [ExternalTable]s, which only know where the table is
+//! stored and its schema. Scans of them are sent to the scheduler as
+//! [ExternalTableScan] nodes, and the scheduler opens the table with the [TableFormat]
+//! of the same name to resolve the files to scan. The resulting file scans are
+//! serialized to executors like the scans of any other table. Tables of formats which
+//! only the scheduler can open, such as [cache]d tables, are registered with their
+//! schema instead.
+//!
+//! DELETE and UPDATE statements are run as jobs rewriting the files of the table which
+//! hold the rows they change, and `OPTIMIZE TABLE` as jobs compacting its small files,
+//! see [TableRewrite].
+
+pub mod cache;
+pub mod delta;
+pub mod iceberg;
+pub mod json;
+pub mod manifest;
+pub mod node_local;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod time_travel;
+
+use crate::execution_plans::WrittenFile;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::datasource::empty::EmptyTable;
+use datafusion::datasource::listing::ListingTable;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_expr::TableProviderFilterPushDown;
+use datafusion::logical_plan::plan::{Explain, Extension};
+use datafusion::logical_plan::{
+    provider_as_source, source_as_provider, DFSchema, DFSchemaRef, Expr, LogicalPlan,
+    LogicalPlanBuilder, UserDefinedLogicalNode,
+};
+use datafusion::optimizer::utils::from_plan;
+use datafusion::physical_plan::ExecutionPlan;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use url::Url;
+
+/// A way of storing tables, which resolves a table from its location
+#[async_trait]
+pub trait TableFormat: Send + Sync {
+    /// Open the table at `location`, reading it with the object stores of `runtime`
+    async fn open(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>>;
+
+    /// Open the table at `location`, whose schema is known to be `schema`. Formats which
+    /// infer the schema of a table from its files use it instead.
+    async fn open_with_schema(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        _schema: SchemaRef,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        self.open(location, options, runtime).await
+    }
+
+    /// `options` changed to open a table as it was at `version`, for time travel queries
+    fn options_as_of(
+        &self,
+        _options: &BTreeMap<String, String>,
+        _version: TableVersion,
+    ) -> Result<BTreeMap<String, String>> {
+        Err(DataFusionError::NotImplemented(
+            "The table format does not support time travel".to_owned(),
+        ))
+    }
+
+    /// Plan the rewrite of the files of the latest version of the table at `location`
+    /// which may hold rows matching all of `filters`, for a DELETE or UPDATE statement
+    async fn rewrite(
+        &self,
+        _location: &str,
+        _options: &BTreeMap<String, String>,
+        _filters: &[Expr],
+        _runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        Err(DataFusionError::NotImplemented(
+            "The table format does not support DELETE and UPDATE statements".to_owned(),
+        ))
+    }
+
+    /// Plan the compaction of the files of the latest version of the table at
+    /// `location` smaller than `target_file_bytes` into files of about that size
+    async fn compact(
+        &self,
+        _location: &str,
+        _options: &BTreeMap<String, String>,
+        _target_file_bytes: u64,
+        _runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        Err(DataFusionError::NotImplemented(
+            "The table format does not support OPTIMIZE TABLE".to_owned(),
+        ))
+    }
+}
+
+/// Why the files of a table are rewritten
+#[derive(Clone, Debug, PartialEq)]
+pub enum RewriteMode {
+    /// A DELETE or UPDATE statement changing the rows matching all of the filters
+    Change(Vec<Expr>),
+    /// Compaction of the small files of the table into files of about this many bytes
+    Compact(u64),
+}
+
+/// A copy-on-write rewrite of the files of a table holding the rows changed by a DELETE
+/// or UPDATE statement. A job scans these files, keeping or updating their rows, and
+/// writes its output to new files, which replace them in a new version of the table.
+pub struct TableRewrite {
+    /// The files to rewrite, scanned by the job instead of the whole table
+    pub table: Arc<dyn TableProvider>,
+    /// The location the job writes the new files to
+    pub location: String,
+    /// Columns whose values partition the new files into directories
+    pub partition_by: Vec<String>,
+    /// Whether the rows are repartitioned by `partition_by` before they are written, so
+    /// each directory is written by a single task. Compactions write the files each
+    /// task scans, which share their partition values, to a single file instead.
+    pub repartition: bool,
+    /// The Parquet field IDs of the columns of the new files by the dotted path of each
+    /// column, for formats which match the columns of files to the table by ID
+    pub field_ids: HashMap<String, i32>,
+    pub commit: Arc<dyn TableCommit>,
+}
+
+/// Commits the files written by the job of a [TableRewrite] to a new version of the table
+#[async_trait]
+pub trait TableCommit: Debug + Send + Sync {
+    /// The location of the table, whose commits the scheduler serializes
+    fn location(&self) -> &str;
+
+    /// Replace the rewritten files with `written` in a new version of the table. Fails
+    /// without committing anything if the table changed since the rewrite was planned.
+    async fn commit(&self, written: &[WrittenFile], runtime: &RuntimeEnv) -> Result<()>;
+
+    /// The commit as JSON, saved by the scheduler until the job completes and restored
+    /// with [table_commit_from_json]
+    fn to_json(&self) -> Result<serde_json::Value>;
+}
+
+/// Copy `from` to `to` unless `to` exists, like [ObjectStore::copy_if_not_exists].
+/// Object stores which cannot copy conditionally, such as S3, copy unless `to` is found
+/// first, which is only atomic between writers which hold the lock of the scheduler on
+/// the table, see [TableCommit::location].
+pub(crate) async fn copy_if_not_exists(
+    store: &dyn ObjectStore,
+    from: &Path,
+    to: &Path,
+) -> object_store::Result<()> {
+    match store.copy_if_not_exists(from, to).await {
+        Err(object_store::Error::NotImplemented) => match store.head(to).await {
+            Ok(_) => Err(object_store::Error::AlreadyExists {
+                path: to.to_string(),
+                source: "Destination exists".into(),
+            }),
+            Err(object_store::Error::NotFound { .. }) => store.copy(from, to).await,
+            Err(e) => Err(e),
+        },
+        result => result,
+    }
+}
+
+/// The [TableCommit]s of the built-in table formats, by format
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase")]
+pub(crate) enum SavedCommit {
+    Delta(delta::DeltaCommit),
+    Iceberg(iceberg::IcebergCommit),
+}
+
+impl SavedCommit {
+    pub(crate) fn to_json(&self) -> Result<serde_json::Value> {
+        serde_json::to_value(self).map_err(|e| DataFusionError::External(Box::new(e)))
+    }
+}
+
+/// Restore a [TableCommit] saved with [TableCommit::to_json]
+pub fn table_commit_from_json(value: serde_json::Value) -> Result<Arc<dyn TableCommit>> {
+    let saved: SavedCommit = serde_json::from_value(value).map_err(|e| {
+        DataFusionError::Execution(format!("Invalid saved table commit: {}", e))
+    })?;
+    Ok(match saved {
+        SavedCommit::Delta(commit) => Arc::new(commit),
+        SavedCommit::Iceberg(commit) => Arc::new(commit),
+    })
+}
+
+/// A past state of a table, read with `FOR VERSION AS OF` or `FOR TIMESTAMP AS OF`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableVersion {
+    /// A version of the table, the snapshot ID of Iceberg tables
+    Version(i64),
+    /// The version of the table which was current at a time in milliseconds since the
+    /// epoch
+    Timestamp(i64),
+}
+
+impl TableVersion {
+    /// The version of a table current at `timestamp`, such as `2022-07-01 12:00:00`, a
+    /// date, or an RFC 3339 timestamp. Timestamps without a time zone are in UTC.
+    pub fn parse_timestamp(timestamp: &str) -> Result<Self> {
+        let timestamp = timestamp.trim();
+        let millis = if let Ok(time) = DateTime::parse_from_rfc3339(timestamp) {
+            time.timestamp_millis()
+        } else if let Some(time) = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(timestamp, format).ok())
+        {
+            time.timestamp_millis()
+        } else if let Ok(date) = NaiveDate::parse_from_str(timestamp, "%Y-%m-%d") {
+            date.and_hms(0, 0, 0).timestamp_millis()
+        } else {
+            return Err(DataFusionError::Plan(format!(
+                "Invalid timestamp {}",
+                timestamp
+            )));
+        };
+        Ok(Self::Timestamp(millis))
+    }
+}
+
+/// The [TableFormat]s known to a client or scheduler, by case-insensitive name
+#[derive(Clone)]
+pub struct TableFormats {
+    formats: HashMap<String, Arc<dyn TableFormat>>,
+}
+
+impl TableFormats {
+    /// The table formats built into Ballista: `delta`, `iceberg`, `json`, `manifest`,
+    /// `node_local` and `postgres` with the postgres feature
+    pub fn builtin() -> Self {
+        let mut formats = Self {
+            formats: HashMap::new(),
+        };
+        formats.register("delta", Arc::new(delta::DeltaFormat));
+        formats.register("iceberg", Arc::new(iceberg::IcebergFormat));
+        formats.register(json::JSON, Arc::new(json::JsonTableFormat));
+        formats.register(manifest::MANIFEST, Arc::new(manifest::ManifestFormat));
+        formats.register(
+            node_local::NODE_LOCAL,
+            Arc::new(node_local::NodeLocalFormat),
+        );
+        #[cfg(feature = "postgres")]
+        formats.register(postgres::POSTGRES, Arc::new(postgres::PostgresFormat));
+        formats
+    }
+
+    pub fn register(&mut self, name: &str, format: Arc<dyn TableFormat>) {
+        self.formats.insert(name.to_lowercase(), format);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.formats.contains_key(&name.to_lowercase())
+    }
+
+    pub async fn open(
+        &self,
+        name: &str,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        self.get(name)?.open(location, options, runtime).await
+    }
+
+    pub async fn open_with_schema(
+        &self,
+        name: &str,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        schema: SchemaRef,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        self.get(name)?
+            .open_with_schema(location, options, schema, runtime)
+            .await
+    }
+
+    /// `options` of a table of format `name` changed to open the table as it was at
+    /// `version`
+    pub fn options_as_of(
+        &self,
+        name: &str,
+        options: &BTreeMap<String, String>,
+        version: TableVersion,
+    ) -> Result<BTreeMap<String, String>> {
+        self.get(name)?.options_as_of(options, version)
+    }
+
+    pub async fn rewrite(
+        &self,
+        name: &str,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        filters: &[Expr],
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        self.get(name)?
+            .rewrite(location, options, filters, runtime)
+            .await
+    }
+
+    pub async fn compact(
+        &self,
+        name: &str,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        target_file_bytes: u64,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<TableRewrite> {
+        self.get(name)?
+            .compact(location, options, target_file_bytes, runtime)
+            .await
+    }
+
+    fn get(&self, name: &str) -> Result<&Arc<dyn TableFormat>> {
+        self.formats.get(&name.to_lowercase()).ok_or_else(|| {
+            DataFusionError::Plan(format!("Unknown table format {}", name))
+        })
+    }
+}
+
+impl Default for TableFormats {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+/// A table of a [TableFormat] registered with a client. It scans the table locally when
+/// used outside of Ballista, and is turned into an [ExternalTableScan] otherwise.
+pub struct ExternalTable {
+    format: String,
+    location: String,
+    options: BTreeMap<String, String>,
+    table: Arc<dyn TableProvider>,
+}
+
+impl ExternalTable {
+    pub async fn try_new(
+        formats: &TableFormats,
+        format: &str,
+        location: &str,
+        options: BTreeMap<String, String>,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<Self> {
+        let table = formats.open(format, location, &options, runtime).await?;
+        Ok(Self {
+            format: format.to_lowercase(),
+            location: location.to_owned(),
+            options,
+            table,
+        })
+    }
+
+    /// A table of schema `schema` of a format which only the scheduler can open. Scans
+    /// of it outside of Ballista return no rows.
+    pub fn with_schema(
+        format: &str,
+        location: &str,
+        options: BTreeMap<String, String>,
+        schema: SchemaRef,
+    ) -> Self {
+        Self {
+            format: format.to_lowercase(),
+            location: location.to_owned(),
+            options,
+            table: Arc::new(EmptyTable::new(schema)),
+        }
+    }
+
+    pub fn format(&self) -> &str {
+        &self.format
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    pub fn options(&self) -> &BTreeMap<String, String> {
+        &self.options
+    }
+}
+
+#[async_trait]
+impl TableProvider for ExternalTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.table.schema()
+    }
+
+    fn table_type(&self) -> TableType {
+        self.table.table_type()
+    }
+
+    async fn scan(
+        &self,
+        ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        self.table.scan(ctx, projection, filters, limit).await
+    }
+
+    fn supports_filter_pushdown(
+        &self,
+        filter: &Expr,
+    ) -> Result<TableProviderFilterPushDown> {
+        self.table.supports_filter_pushdown(filter)
+    }
+}
+
+/// Scan of an [ExternalTable], which the scheduler resolves with its [TableFormat]
+#[derive(Clone)]
+pub struct ExternalTableScan {
+    pub table_name: String,
+    pub format: String,
+    pub location: String,
+    pub options: BTreeMap<String, String>,
+    /// Schema of the whole table
+    pub table_schema: SchemaRef,
+    pub projection: Option<Vec<usize>>,
+    /// Filters pushed down to the scan
+    pub filters: Vec<Expr>,
+    /// Schema of the projected columns
+    schema: DFSchemaRef,
+}
+
+impl ExternalTableScan {
+    pub fn try_new(
+        table_name: String,
+        format: String,
+        location: String,
+        options: BTreeMap<String, String>,
+        table_schema: SchemaRef,
+        projection: Option<Vec<usize>>,
+        filters: Vec<Expr>,
+    ) -> Result<Self> {
+        let projected = match &projection {
+            Some(columns) => Arc::new(table_schema.project(columns)?),
+            None => table_schema.clone(),
+        };
+        let schema = Arc::new(DFSchema::try_from_qualified_schema(
+            &table_name,
+            &projected,
+        )?);
+        Ok(Self {
+            table_name,
+            format,
+            location,
+            options,
+            table_schema,
+            projection,
+            filters,
+            schema,
+        })
+    }
+
+    /// Open the table and plan its scan
+    async fn resolve(
+        &self,
+        formats: &TableFormats,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Result<LogicalPlan> {
+        let table = formats
+            .open_with_schema(
+                &self.format,
+                &self.location,
+                &self.options,
+                self.table_schema.clone(),
+                runtime,
+            )
+            .await?;
+        self.scan(table)
+    }
+
+    /// Plan the scan of `table`, the table opened by its format
+    fn scan(&self, table: Arc<dyn TableProvider>) -> Result<LogicalPlan> {
+        if table.schema() != self.table_schema {
+            return Err(DataFusionError::Plan(format!(
+                "The schema of table {} changed while planning the query",
+                self.table_name
+            )));
+        }
+        LogicalPlanBuilder::scan_with_filters(
+            &self.table_name,
+            provider_as_source(table),
+            self.projection.clone(),
+            self.filters.clone(),
+        )?
+        .build()
+    }
+}
+
+impl Debug for ExternalTableScan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.fmt_for_explain(f)
+    }
+}
+
+impl UserDefinedLogicalNode for ExternalTableScan {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![]
+    }
+
+    fn schema(&self) -> &DFSchemaRef {
+        &self.schema
+    }
+
+    fn expressions(&self) -> Vec<Expr> {
+        self.filters.clone()
+    }
+
+    fn fmt_for_explain(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ExternalTableScan: {} format={} location={}",
+            self.table_name,
+            self.format,
+            redact_password(&self.location)
+        )?;
+        if !self.filters.is_empty() {
+            write!(f, " filters={:?}", self.filters)?;
+        }
+        Ok(())
+    }
+
+    fn from_template(
+        &self,
+        exprs: &[Expr],
+        _inputs: &[LogicalPlan],
+    ) -> Arc<dyn UserDefinedLogicalNode> {
+        Arc::new(Self {
+            filters: exprs.to_vec(),
+            ..self.clone()
+        })
+    }
+}
+
+/// Replace the scans of [ExternalTable]s and JSON files in `plan` with
+/// [ExternalTableScan]s, so they can be sent to the scheduler
+pub fn to_external_scans(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    Ok(rewrite(plan, &mut |plan| {
+        if let LogicalPlan::TableScan(scan) = plan {
+            let provider = source_as_provider(&scan.source).ok();
+            let listing_table = provider
+                .as_ref()
+                .and_then(|provider| provider.as_any().downcast_ref::<ListingTable>());
+            if let Some(listing_table) = listing_table {
+                let node = json::to_external_scan(
+                    &scan.table_name,
+                    listing_table,
+                    scan.projection.clone(),
+                    scan.filters.clone(),
+                )?;
+                return Ok(node.map(|node| {
+                    LogicalPlan::Extension(Extension {
+                        node: Arc::new(node),
+                    })
+                }));
+            }
+            let table = provider
+                .as_ref()
+                .and_then(|provider| provider.as_any().downcast_ref::<ExternalTable>());
+            if let Some(table) = table {
+                let node = ExternalTableScan::try_new(
+                    scan.table_name.clone(),
+                    table.format.clone(),
+                    table.location.clone(),
+                    table.options.clone(),
+                    table.schema(),
+                    scan.projection.clone(),
+                    scan.filters.clone(),
+                )?;
+                return Ok(Some(LogicalPlan::Extension(Extension {
+                    node: Arc::new(node),
+                })));
+            }
+        }
+        Ok(None)
+    })?
+    .unwrap_or_else(|| plan.clone()))
+}
+
+/// Replace the [ExternalTableScan]s in `plan` with scans of the tables they refer to
+pub async fn resolve_external_scans(
+    plan: &LogicalPlan,
+    formats: &TableFormats,
+    runtime: Arc<RuntimeEnv>,
+) -> Result<LogicalPlan> {
+    let scans = external_scans(plan)?;
+    if scans.is_empty() {
+        return Ok(plan.clone());
+    }
+
+    let mut resolved = vec![];
+    for scan in &scans {
+        resolved.push(scan.resolve(formats, runtime.clone()).await?);
+    }
+    replace_external_scans(plan, resolved)
+}
+
+/// Plan the rewrite of table `table_name` for `mode`, and replace the
+/// [ExternalTableScan]s in `plan`, whose output is written back to the table, with scans
+/// of the tables they refer to. The scan of the rewritten table only reads the files to
+/// rewrite.
+pub async fn resolve_table_rewrite(
+    plan: &LogicalPlan,
+    table_name: &str,
+    mode: &RewriteMode,
+    formats: &TableFormats,
+    runtime: Arc<RuntimeEnv>,
+) -> Result<(LogicalPlan, TableRewrite)> {
+    let scans = external_scans(plan)?;
+    let target = match scans
+        .iter()
+        .filter(|scan| scan.table_name == table_name)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        [target] => (*target).clone(),
+        [] => {
+            return Err(DataFusionError::Plan(format!(
+                "Table {} is not a table of a table format scanned by the statement",
+                table_name
+            )))
+        }
+        _ => {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Table {} cannot be read more than once by the statements rewriting it",
+                table_name
+            )))
+        }
+    };
+    let table_rewrite = match mode {
+        RewriteMode::Change(filters) => {
+            formats
+                .rewrite(
+                    &target.format,
+                    &target.location,
+                    &target.options,
+                    filters,
+                    runtime.clone(),
+                )
+                .await?
+        }
+        RewriteMode::Compact(target_file_bytes) => {
+            formats
+                .compact(
+                    &target.format,
+                    &target.location,
+                    &target.options,
+                    *target_file_bytes,
+                    runtime.clone(),
+                )
+                .await?
+        }
+    };
+
+    let mut resolved = vec![];
+    for scan in &scans {
+        if scan.table_name == table_name {
+            resolved.push(scan.scan(table_rewrite.table.clone())?);
+        } else {
+            resolved.push(scan.resolve(formats, runtime.clone()).await?);
+        }
+    }
+    Ok((replace_external_scans(plan, resolved)?, table_rewrite))
+}
+
+/// `plan` with the location of each [ExternalTableScan] of a table of format `format`
+/// replaced with `f(location)`
+pub fn map_scan_locations(
+    plan: &LogicalPlan,
+    format: &str,
+    f: &dyn Fn(&str) -> String,
+) -> Result<LogicalPlan> {
+    Ok(rewrite(plan, &mut |plan| {
+        Ok(as_external_scan(plan)
+            .filter(|scan| scan.format == format)
+            .map(|scan| {
+                let mut scan = scan.clone();
+                scan.location = f(&scan.location);
+                LogicalPlan::Extension(Extension {
+                    node: Arc::new(scan),
+                })
+            }))
+    })?
+    .unwrap_or_else(|| plan.clone()))
+}
+
+/// The locations of the tables scanned by `plan` by the name they are scanned as, for
+/// the scans which have one. Unlike their names, which are only those the tables are
+/// registered under by a client, locations identify the data of the tables. A name
+/// several tables are scanned as has the locations of all of them.
+pub fn scanned_table_locations(
+    plan: &LogicalPlan,
+) -> Result<HashMap<String, BTreeSet<String>>> {
+    let mut locations: HashMap<String, BTreeSet<String>> = HashMap::new();
+    rewrite(plan, &mut |plan| {
+        if let Some((table, location)) = scanned_table_location(plan) {
+            locations.entry(table).or_default().insert(location);
+        }
+        Ok(None)
+    })?;
+    Ok(locations)
+}
+
+/// The name and location of the table scanned by `plan`, if it is a scan of a listing
+/// table, of an [ExternalTable] or an [ExternalTableScan]
+pub fn scanned_table_location(plan: &LogicalPlan) -> Option<(String, String)> {
+    if let Some(scan) = as_external_scan(plan) {
+        return Some((scan.table_name.clone(), scan.location.clone()));
+    }
+    let scan = match plan {
+        LogicalPlan::TableScan(scan) => scan,
+        _ => return None,
+    };
+    let provider = source_as_provider(&scan.source).ok()?;
+    let location = match provider.as_any().downcast_ref::<ExternalTable>() {
+        Some(table) => table.location.clone(),
+        None => provider
+            .as_any()
+            .downcast_ref::<ListingTable>()?
+            .table_paths()
+            .iter()
+            .map(|path| path.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+    };
+    Some((scan.table_name.clone(), location))
+}
+
+/// `location`, a URL or a local path, as a URL ending with `/`, so that the locations of
+/// the same data compare equal however they are written
+pub fn location_url(location: &str) -> String {
+    let location = location.trim();
+    let url = match Url::parse(location) {
+        // A single letter scheme is the drive of a Windows path
+        Ok(url) if url.scheme().len() > 1 => Some(url),
+        _ => std::env::current_dir().ok().and_then(|dir| {
+            let path = dir.join(location);
+            // Joining the path resolves its `.` and `..` segments
+            Url::parse("file:///")
+                .and_then(|root| root.join(&path.to_string_lossy()))
+                .ok()
+        }),
+    };
+    let mut url = url.map_or_else(|| location.to_owned(), |url| url.to_string());
+    if !url.ends_with('/') {
+        url.push('/');
+    }
+    url
+}
+
+/// The [ExternalTableScan]s in `plan`
+fn external_scans(plan: &LogicalPlan) -> Result<Vec<ExternalTableScan>> {
+    let mut scans = vec![];
+    rewrite(plan, &mut |plan| {
+        if let Some(scan) = as_external_scan(plan) {
+            scans.push(scan.clone());
+        }
+        Ok(None)
+    })?;
+    Ok(scans)
+}
+
+/// Replace the [ExternalTableScan]s in `plan` with `resolved`, the plans of their scans
+/// in the order of [external_scans]
+fn replace_external_scans(
+    plan: &LogicalPlan,
+    resolved: Vec<LogicalPlan>,
+) -> Result<LogicalPlan> {
+    let mut resolved = resolved.into_iter();
+    Ok(rewrite(plan, &mut |plan| {
+        Ok(as_external_scan(plan).and_then(|_| resolved.next()))
+    })?
+    .unwrap_or_else(|| plan.clone()))
+}
+
+/// `location` without the password of its URL, so it can be printed
+pub(crate) fn redact_password(location: &str) -> String {
+    match Url::parse(location) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("***"));
+            url.to_string()
+        }
+        _ => location.to_owned(),
+    }
+}
+
+fn as_external_scan(plan: &LogicalPlan) -> Option<&ExternalTableScan> {
+    match plan {
+        LogicalPlan::Extension(Extension { node }) => {
+            node.as_any().downcast_ref::<ExternalTableScan>()
+        }
+        _ => None,
+    }
+}
+
+/// Rewrite the nodes of `plan` for which `f` returns a replacement, depth first. Returns
+/// `None` if nothing was replaced, so plans without such nodes are never rebuilt.
+fn rewrite(
+    plan: &LogicalPlan,
+    f: &mut dyn FnMut(&LogicalPlan) -> Result<Option<LogicalPlan>>,
+) -> Result<Option<LogicalPlan>> {
+    if let Some(replacement) = f(plan)? {
+        return Ok(Some(replacement));
+    }
+    // DataFusion does not rebuild explain plans from new inputs
+    if let LogicalPlan::Explain(explain) = plan {
+        return Ok(rewrite(&explain.plan, f)?.map(|input| {
+            LogicalPlan::Explain(Explain {
+                plan: Arc::new(input),
+                ..explain.clone()
+            })
+        }));
+    }
+    let inputs = plan.inputs();
+    let mut new_inputs = Vec::with_capacity(inputs.len());
+    let mut changed = false;
+    for input in inputs {
+        match rewrite(input, f)? {
+            Some(new_input) => {
+                changed = true;
+                new_inputs.push(new_input);
+            }
+            None => new_inputs.push(input.clone()),
+        }
+    }
+    if changed {
+        Ok(Some(from_plan(plan, &plan.expressions(), &new_inputs)?))
+    } else {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps() -> Result<()> {
+        let noon = TableVersion::Timestamp(1_656_676_800_000);
+        assert_eq!(TableVersion::parse_timestamp("2022-07-01 12:00:00")?, noon);
+        assert_eq!(
+            TableVersion::parse_timestamp("2022-07-01T12:00:00.000")?,
+            noon
+        );
+        assert_eq!(
+            TableVersion::parse_timestamp("2022-07-01T14:00:00+02:00")?,
+            noon
+        );
+        assert_eq!(
+            TableVersion::parse_timestamp("2022-07-01")?,
+            TableVersion::Timestamp(1_656_633_600_000)
+        );
+        assert!(TableVersion::parse_timestamp("yesterday").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn locate_scanned_tables() -> Result<()> {
+        use datafusion::arrow::datatypes::{DataType, Field, Schema};
+        use datafusion::datasource::MemTable;
+        use datafusion::prelude::SessionContext;
+
+        let schema =
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let ctx = SessionContext::new();
+        let table = ExternalTable::with_schema(
+            "delta",
+            "s3://bucket/trips",
+            BTreeMap::new(),
+            schema.clone(),
+        );
+        ctx.register_table("trips", Arc::new(table))?;
+        ctx.register_table("ids", Arc::new(MemTable::try_new(schema, vec![])?))?;
+        let plan = ctx
+            .sql("SELECT * FROM trips JOIN ids ON trips.id = ids.id")
+            .await?
+            .to_logical_plan()?;
+
+        let expected: HashMap<String, BTreeSet<String>> = [(
+            "trips".to_owned(),
+            BTreeSet::from(["s3://bucket/trips".to_owned()]),
+        )]
+        .into();
+        assert_eq!(scanned_table_locations(&plan)?, expected);
+        // Tables sent to the scheduler keep their location
+        assert_eq!(
+            scanned_table_locations(&to_external_scans(&plan)?)?,
+            expected
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_locations() {
+        assert_eq!(location_url("s3://bucket/trips"), "s3://bucket/trips/");
+        assert_eq!(location_url("S3://bucket/trips/"), "s3://bucket/trips/");
+        assert_eq!(location_url("/data/../trips"), "file:///trips/");
+        assert_eq!(location_url("file:///trips"), "file:///trips/");
+        let relative = std::env::current_dir().unwrap().join("trips");
+        assert_eq!(
+            location_url("trips"),
+            format!("file://{}/", relative.to_str().unwrap())
+        );
+    }
+}
@@ -0,0 +1,201 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Tables of Parquet files on the local disks of the nodes of the cluster.
+//!
+//! The location of a table is a directory, at the same path on every node holding a
+//! share of the files of the table. Scans read the directory of each node in a partition
+//! of a [NodeLocalScanExec], which the scheduler only runs on the executors of that
+//! node, so the files are read where they are, as HDFS schedules reads next to their
+//! blocks. Neither clients nor the scheduler can read the files, so the columns of the
+//! table are given as an option. Options:
+//! - `nodes`: comma-separated names of the nodes holding the files
+//! - `columns`: comma-separated columns of the files, with their types in Hive's
+//!   notation, such as `id bigint, tags array<string>`
+//! - `node_label`: label of executors with the name of the node they run on,
+//!   `k8s.node.name` by default, set by their `--node-name` option
+//! - `file_extension`: extension of the files to read, `.parquet` by default
+
+use crate::catalog::{arrow_type, split_top_level};
+use crate::execution_plans::NodeLocalScanExec;
+use crate::serde::scheduler::NODE_NAME_LABEL;
+use crate::table_format::TableFormat;
+use async_trait::async_trait;
+use datafusion::arrow::datatypes::{Field, Schema, SchemaRef};
+use datafusion::datasource::file_format::parquet::DEFAULT_PARQUET_EXTENSION;
+use datafusion::datasource::{TableProvider, TableType};
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::SessionState;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::logical_plan::Expr;
+use datafusion::physical_plan::ExecutionPlan;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The name of the format
+pub const NODE_LOCAL: &str = "node_local";
+
+/// Parquet files on the local disks of nodes, read by the executors of each node
+pub struct NodeLocalFormat;
+
+#[async_trait]
+impl TableFormat for NodeLocalFormat {
+    async fn open(
+        &self,
+        location: &str,
+        options: &BTreeMap<String, String>,
+        _runtime: Arc<RuntimeEnv>,
+    ) -> Result<Arc<dyn TableProvider>> {
+        Ok(Arc::new(NodeLocalTable::try_new(location, options)?))
+    }
+}
+
+/// A table whose files are spread over the local disks of nodes
+#[derive(Debug)]
+pub struct NodeLocalTable {
+    path: String,
+    file_extension: String,
+    node_label: String,
+    nodes: Vec<String>,
+    schema: SchemaRef,
+}
+
+impl NodeLocalTable {
+    pub fn try_new(path: &str, options: &BTreeMap<String, String>) -> Result<Self> {
+        let nodes: Vec<String> = options
+            .get("nodes")
+            .map(|nodes| {
+                nodes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|node| !node.is_empty())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default();
+        if nodes.is_empty() {
+            return Err(DataFusionError::Plan(
+                "Node-local tables need the option nodes".to_owned(),
+            ));
+        }
+        let columns = options.get("columns").ok_or_else(|| {
+            DataFusionError::Plan("Node-local tables need the option columns".to_owned())
+        })?;
+        Ok(Self {
+            path: path.to_owned(),
+            file_extension: options
+                .get("file_extension")
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_PARQUET_EXTENSION.to_owned()),
+            node_label: options
+                .get("node_label")
+                .cloned()
+                .unwrap_or_else(|| NODE_NAME_LABEL.to_owned()),
+            nodes,
+            schema: Arc::new(parse_columns(columns)?),
+        })
+    }
+}
+
+#[async_trait]
+impl TableProvider for NodeLocalTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    async fn scan(
+        &self,
+        _ctx: &SessionState,
+        projection: &Option<Vec<usize>>,
+        _filters: &[Expr],
+        _limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(Arc::new(NodeLocalScanExec::try_new(
+            self.path.clone(),
+            self.file_extension.clone(),
+            self.node_label.clone(),
+            self.nodes.clone(),
+            self.schema.clone(),
+            projection.clone(),
+        )?))
+    }
+}
+
+/// The schema of `columns`, such as `id bigint, name string`
+fn parse_columns(columns: &str) -> Result<Schema> {
+    let fields = split_top_level(columns)
+        .into_iter()
+        .filter(|column| !column.is_empty())
+        .map(|column| {
+            let (name, column_type) =
+                column.split_once(char::is_whitespace).ok_or_else(|| {
+                    DataFusionError::Plan(format!("Missing type of column {}", column))
+                })?;
+            let data_type = arrow_type(column_type)
+                .map_err(|e| DataFusionError::Plan(e.to_string()))?;
+            Ok(Field::new(name, data_type, true))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if fields.is_empty() {
+        return Err(DataFusionError::Plan(
+            "Node-local tables need at least one column".to_owned(),
+        ));
+    }
+    Ok(Schema::new(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::DataType;
+
+    #[test]
+    fn open_node_local_tables() -> Result<()> {
+        let mut options = BTreeMap::new();
+        options.insert("nodes".to_owned(), "node-1, node-2".to_owned());
+        options.insert(
+            "columns".to_owned(),
+            "id bigint, tags array<string>, point struct<x:double, y:double>".to_owned(),
+        );
+        let table = NodeLocalTable::try_new("/data/events", &options)?;
+        assert_eq!(table.nodes, vec!["node-1".to_owned(), "node-2".to_owned()]);
+        assert_eq!(table.node_label, NODE_NAME_LABEL);
+        assert_eq!(table.file_extension, ".parquet");
+        let fields = table.schema.fields();
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields[0].name(), "id");
+        assert_eq!(fields[0].data_type(), &DataType::Int64);
+        assert!(
+            matches!(fields[2].data_type(), DataType::Struct(fields) if fields.len() == 2)
+        );
+
+        options.insert("columns".to_owned(), "id".to_owned());
+        assert!(NodeLocalTable::try_new("/data/events", &options).is_err());
+        options.remove("nodes");
+        assert!(NodeLocalTable::try_new("/data/events", &options).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Time travel queries, which read tables of table formats as they were at past
+//! versions.
+//!
+//! The clauses selecting the versions are removed from the SQL of a query, which reads
+//! each table at a past version from its own schema of [TIME_TRAVEL_CATALOG] instead.
+//! Tables are looked up in the catalogs of the context planning the query, like the
+//! other tables of the query, and opened with the options of their version. The client
+//! or scheduler planning the query only reads the schema of that version: its files are
+//! resolved by the scheduler when it plans the job, as for the other scans of external
+//! tables.
+
+use super::{ExternalTable, TableFormats, TableVersion};
+use datafusion::catalog::catalog::{
+    CatalogList, CatalogProvider, MemoryCatalogList, MemoryCatalogProvider,
+};
+use datafusion::catalog::schema::{MemorySchemaProvider, SchemaProvider};
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::TableProvider;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::logical_plan::{source_as_provider, LogicalPlan};
+use datafusion::prelude::SessionContext;
+use sqlparser::dialect::keywords::{Keyword, RESERVED_FOR_TABLE_ALIAS};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace, Word};
+use std::sync::Arc;
+
+/// Plan `sql` in `ctx`, reading the tables followed by `FOR VERSION AS OF` and
+/// `FOR TIMESTAMP AS OF` clauses at their versions
+pub async fn sql_as_of(
+    ctx: &SessionContext,
+    sql: &str,
+    formats: &TableFormats,
+) -> Result<Arc<DataFrame>> {
+    match parse_time_travel(sql)? {
+        Some(query) => {
+            time_travel_context(ctx, &query.tables, formats)
+                .await?
+                .sql(&query.sql)
+                .await
+        }
+        None => ctx.sql(sql).await,
+    }
+}
+
+/// A context planning queries like `ctx`, in which table `i` of `tables` is registered
+/// at its version in schema `v<i>` of [TIME_TRAVEL_CATALOG]. The catalogs of `ctx` are
+/// not changed, so queries planned concurrently by `ctx` do not see each other's tables.
+pub async fn time_travel_context(
+    ctx: &SessionContext,
+    tables: &[(String, TableVersion)],
+    formats: &TableFormats,
+) -> Result<SessionContext> {
+    let time_travel = MemoryCatalogProvider::new();
+    for (index, (name, version)) in tables.iter().enumerate() {
+        let table = external_table(ctx, name)?;
+        let table = table
+            .as_any()
+            .downcast_ref::<ExternalTable>()
+            .ok_or_else(|| {
+                DataFusionError::Plan(format!(
+                    "Table {} is not a table of a table format, it has no versions",
+                    name
+                ))
+            })?;
+        let options = formats.options_as_of(table.format(), table.options(), *version)?;
+        let table = ExternalTable::try_new(
+            formats,
+            table.format(),
+            table.location(),
+            options,
+            ctx.runtime_env(),
+        )
+        .await?;
+        let schema = MemorySchemaProvider::new();
+        let table_name = name.rsplit('.').next().unwrap_or_default();
+        schema.register_table(table_name.to_owned(), Arc::new(table))?;
+        time_travel.register_schema(&format!("v{}", index), Arc::new(schema));
+    }
+
+    let mut state = ctx.state.read().clone();
+    let catalogs = MemoryCatalogList::new();
+    for name in state.catalog_list.catalog_names() {
+        if let Some(catalog) = state.catalog_list.catalog(&name) {
+            catalogs.register_catalog(name, catalog);
+        }
+    }
+    catalogs.register_catalog(TIME_TRAVEL_CATALOG.to_owned(), Arc::new(time_travel));
+    state.catalog_list = Arc::new(catalogs);
+    Ok(SessionContext::with_state(state))
+}
+
+/// The provider of table `name` as `ctx` resolves it when planning queries
+fn external_table(ctx: &SessionContext, name: &str) -> Result<Arc<dyn TableProvider>> {
+    match ctx.table(name)?.to_logical_plan()? {
+        LogicalPlan::TableScan(scan) => source_as_provider(&scan.source),
+        _ => Err(DataFusionError::Plan(format!(
+            "Table {} is not a table of a table format, it has no versions",
+            name
+        ))),
+    }
+}
+
+/// Catalog of the tables read at past versions by time travel queries
+pub const TIME_TRAVEL_CATALOG: &str = "__time_travel";
+
+/// A query reading tables as they were at past versions
+#[derive(Debug, PartialEq)]
+pub struct TimeTravelQuery {
+    /// The query, reading table `i` of `tables` from schema `v<i>` of
+    /// [TIME_TRAVEL_CATALOG] under the alias of the table
+    pub sql: String,
+    /// The names of the tables read at past versions, as they are referenced by the
+    /// query, with their versions
+    pub tables: Vec<(String, TableVersion)>,
+}
+
+/// Parse a query reading tables of table formats as they were at past versions, with
+/// a clause following the name of each table:
+///
+/// ```sql
+/// SELECT ... FROM table FOR VERSION AS OF version [[AS] alias] ...
+/// SELECT ... FROM table FOR TIMESTAMP AS OF [TIMESTAMP] 'timestamp' [[AS] alias] ...
+/// ```
+///
+/// Returns `None` for queries without such clauses.
+pub fn parse_time_travel(sql: &str) -> Result<Option<TimeTravelQuery>> {
+    let dialect = GenericDialect {};
+    let tokens = match Tokenizer::new(&dialect, sql).tokenize() {
+        Ok(tokens) => tokens,
+        Err(_) => return Ok(None),
+    };
+    let mut output: Vec<Token> = vec![];
+    let mut tables = vec![];
+    let mut index = 0;
+    while index < tokens.len() {
+        let (version, next) = match parse_as_of(&tokens, index)? {
+            Some(as_of) => as_of,
+            None => {
+                output.push(tokens[index].clone());
+                index += 1;
+                continue;
+            }
+        };
+
+        // The name of the table before the clause is replaced by its name in the time
+        // travel catalog
+        while matches!(output.last(), Some(Token::Whitespace(_))) {
+            output.pop();
+        }
+        let mut name: Vec<Word> = vec![];
+        loop {
+            match output.pop() {
+                Some(Token::Word(word)) => name.insert(0, word),
+                _ => {
+                    return Err(DataFusionError::Plan(
+                        "FOR VERSION AS OF and FOR TIMESTAMP AS OF must follow the name \
+                         of a table"
+                            .to_owned(),
+                    ))
+                }
+            }
+            if output.last() == Some(&Token::Period) {
+                output.pop();
+            } else {
+                break;
+            }
+        }
+        let table = Token::Word(name[name.len() - 1].clone());
+        output.extend(vec![
+            Token::make_word(TIME_TRAVEL_CATALOG, None),
+            Token::Period,
+            Token::make_word(&format!("v{}", tables.len()), None),
+            Token::Period,
+            table.clone(),
+        ]);
+        // Columns qualified by the name of the table still refer to it
+        let aliased = match next_significant_token(&tokens, next).map(|i| &tokens[i]) {
+            Some(Token::Word(word)) => {
+                word.keyword == Keyword::AS
+                    || !RESERVED_FOR_TABLE_ALIAS.contains(&word.keyword)
+            }
+            _ => false,
+        };
+        if !aliased {
+            output.extend(vec![
+                Token::Whitespace(Whitespace::Space),
+                Token::make_word("AS", None),
+                Token::Whitespace(Whitespace::Space),
+                table,
+            ]);
+        }
+
+        let name: Vec<&str> = name.iter().map(|word| word.value.as_str()).collect();
+        tables.push((name.join("."), version));
+        index = next;
+    }
+    if tables.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(TimeTravelQuery {
+        sql: output.iter().map(Token::to_string).collect(),
+        tables,
+    }))
+}
+
+/// The version selected by a `FOR VERSION AS OF` or `FOR TIMESTAMP AS OF` clause at
+/// `tokens[index]`, if there is one, and the index of the token following the clause
+fn parse_as_of(tokens: &[Token], index: usize) -> Result<Option<(TableVersion, usize)>> {
+    let is_word = |index: Option<usize>, value: &str| match index.map(|i| &tokens[i]) {
+        Some(Token::Word(word)) => word.value.eq_ignore_ascii_case(value),
+        _ => false,
+    };
+    if !is_word(Some(index), "for") {
+        return Ok(None);
+    }
+    let kind = next_significant_token(tokens, index + 1);
+    let timestamp = if is_word(kind, "timestamp") {
+        true
+    } else if is_word(kind, "version") {
+        false
+    } else {
+        return Ok(None);
+    };
+    let mut next = kind;
+    for keyword in &["as", "of"] {
+        next = next_significant_token(tokens, next.unwrap_or_default() + 1);
+        if !is_word(next, keyword) {
+            return Err(DataFusionError::Plan(
+                "Expected AS OF after FOR VERSION or FOR TIMESTAMP".to_owned(),
+            ));
+        }
+    }
+    next = next_significant_token(tokens, next.unwrap_or_default() + 1);
+    if timestamp && is_word(next, "timestamp") {
+        next = next_significant_token(tokens, next.unwrap_or_default() + 1);
+    }
+    let version = match next.map(|i| &tokens[i]) {
+        Some(Token::Number(value, _)) => {
+            let value = value.parse::<i64>().map_err(|_| {
+                DataFusionError::Plan(format!("Invalid table version {}", value))
+            })?;
+            if timestamp {
+                TableVersion::Timestamp(value)
+            } else {
+                TableVersion::Version(value)
+            }
+        }
+        Some(Token::SingleQuotedString(value)) if timestamp => {
+            TableVersion::parse_timestamp(value)?
+        }
+        token => {
+            return Err(DataFusionError::Plan(format!(
+                "Invalid table version {}",
+                token.map(Token::to_string).unwrap_or_default()
+            )))
+        }
+    };
+    Ok(Some((version, next.unwrap_or_default() + 1)))
+}
+
+/// The index of the first token from `index` on which is not whitespace
+fn next_significant_token(tokens: &[Token], index: usize) -> Option<usize> {
+    (index..tokens.len()).find(|i| !matches!(tokens[*i], Token::Whitespace(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_format::TableFormat;
+    use async_trait::async_trait;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::empty::EmptyTable;
+    use datafusion::execution::runtime_env::RuntimeEnv;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn parse_time_travel_clauses() -> Result<()> {
+        let query = parse_time_travel(
+            "SELECT e.id FROM events FOR VERSION AS OF 3 e \
+             JOIN public.users FOR TIMESTAMP AS OF '2022-07-01' ON e.user = users.id",
+        )?;
+        assert_eq!(
+            query,
+            Some(TimeTravelQuery {
+                sql: "SELECT e.id FROM __time_travel.v0.events e \
+                      JOIN __time_travel.v1.users AS users ON e.user = users.id"
+                    .to_owned(),
+                tables: vec![
+                    ("events".to_owned(), TableVersion::Version(3)),
+                    (
+                        "public.users".to_owned(),
+                        TableVersion::Timestamp(1_656_633_600_000)
+                    ),
+                ],
+            })
+        );
+
+        assert_eq!(parse_time_travel("SELECT * FROM events")?, None);
+        assert!(parse_time_travel("SELECT * FROM events FOR VERSION AS OF 'x'").is_err());
+        assert!(parse_time_travel("SELECT * FROM events FOR VERSION 3").is_err());
+        Ok(())
+    }
+
+    /// Tables with a column named after the version they are opened at
+    struct Versioned;
+
+    #[async_trait]
+    impl TableFormat for Versioned {
+        async fn open(
+            &self,
+            _location: &str,
+            options: &BTreeMap<String, String>,
+            _runtime: Arc<RuntimeEnv>,
+        ) -> Result<Arc<dyn TableProvider>> {
+            let version = options.get("version").cloned().unwrap_or_default();
+            let schema = Schema::new(vec![Field::new(
+                &format!("v{}", version),
+                DataType::Int64,
+                false,
+            )]);
+            Ok(Arc::new(EmptyTable::new(Arc::new(schema))))
+        }
+
+        fn options_as_of(
+            &self,
+            options: &BTreeMap<String, String>,
+            version: TableVersion,
+        ) -> Result<BTreeMap<String, String>> {
+            let mut options = options.clone();
+            if let TableVersion::Version(version) = version {
+                options.insert("version".to_owned(), version.to_string());
+            }
+            Ok(options)
+        }
+    }
+
+    #[tokio::test]
+    async fn open_tables_at_versions() -> Result<()> {
+        let mut formats = TableFormats::builtin();
+        formats.register("versioned", Arc::new(Versioned));
+        let ctx = SessionContext::new();
+        let table = formats
+            .open(
+                "versioned",
+                "mem://events",
+                &BTreeMap::new(),
+                ctx.runtime_env(),
+            )
+            .await?;
+        let table = ExternalTable::with_schema(
+            "versioned",
+            "mem://events",
+            BTreeMap::new(),
+            table.schema(),
+        );
+        ctx.register_table("public.events", Arc::new(table))?;
+
+        // Tables are looked up like the other tables of the query
+        let df = sql_as_of(
+            &ctx,
+            "SELECT * FROM events FOR VERSION AS OF 3 JOIN public.events \
+             FOR VERSION AS OF 4 ON v3 = v4",
+            &formats,
+        )
+        .await?;
+        let names: Vec<_> = df
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        assert_eq!(names, vec!["v3", "v4"]);
+        // The catalogs of the context planning the query are not changed
+        assert!(ctx.catalog(TIME_TRAVEL_CATALOG).is_none());
+
+        assert!(
+            sql_as_of(&ctx, "SELECT * FROM missing FOR VERSION AS OF 3", &formats)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+}
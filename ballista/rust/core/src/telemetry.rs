@@ -0,0 +1,250 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Distributed tracing support.
+//!
+//! A W3C trace context is propagated as a list of [KeyValuePair]s from the client, through
+//! the scheduler, down to every task launched on an executor. Spans are only recorded once a
+//! tracer has been installed with [init_otlp_tracer]; otherwise all of these helpers are
+//! no-ops and nothing is propagated.
+
+use crate::error::{BallistaError, Result};
+use crate::serde::protobuf::{KeyValuePair, PartitionId};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace as sdktrace, Resource};
+use opentelemetry::trace::{
+    Span, SpanContext, SpanId, SpanKind, Status, TraceContextExt, Tracer,
+};
+use opentelemetry::{global, Context, KeyValue};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::SystemTime;
+
+pub use opentelemetry::global::BoxedSpan;
+
+const TRACER_NAME: &str = "ballista";
+
+/// Install a global tracer exporting spans to the OTLP (gRPC) collector at `endpoint`.
+///
+/// Must be called from within a tokio runtime.
+pub fn init_otlp_tracer(service_name: &str, endpoint: &str) -> Result<()> {
+    install_propagator();
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(sdktrace::config().with_resource(Resource::new(vec![
+            KeyValue::new("service.name", service_name.to_owned()),
+        ])))
+        .install_batch(opentelemetry::runtime::Tokio)
+        .map_err(|e| {
+            BallistaError::General(format!(
+                "Failed to install OTLP tracer for {}: {:?}",
+                endpoint, e
+            ))
+        })?;
+
+    Ok(())
+}
+
+/// Install the W3C trace context propagator used by [inject_context] and [extract_context].
+/// Without it, no trace context is propagated.
+pub fn install_propagator() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// Flush any pending spans and shut down the global tracer
+pub fn shutdown_tracer() {
+    global::shutdown_tracer_provider();
+}
+
+/// Serialize the span context of `cx` so it can be sent to another process
+pub fn inject_context(cx: &Context) -> Vec<KeyValuePair> {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut carrier)
+    });
+
+    let mut pairs: Vec<KeyValuePair> = carrier
+        .into_iter()
+        .map(|(key, value)| KeyValuePair { key, value })
+        .collect();
+    pairs.sort_by(|a, b| a.key.cmp(&b.key));
+    pairs
+}
+
+/// Restore a context serialized with [inject_context]
+pub fn extract_context(pairs: &[KeyValuePair]) -> Context {
+    let carrier: HashMap<String, String> = pairs
+        .iter()
+        .map(|kv| (kv.key.clone(), kv.value.clone()))
+        .collect();
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+/// Start a span which is a child of `parent`
+pub fn start_span(name: &'static str, parent: &Context) -> BoxedSpan {
+    global::tracer(TRACER_NAME).start_with_context(name, parent)
+}
+
+/// Return a context carrying the span of the given query stage.
+///
+/// Stage spans outlive any single scheduler call, so their span IDs are derived from the
+/// job and stage IDs. Tasks can then be parented to the stage before the stage span itself
+/// is recorded by [record_stage_span] once the stage finishes.
+pub fn stage_context(job_cx: &Context, job_id: &str, stage_id: usize) -> Context {
+    let parent = job_cx.span().span_context().clone();
+    if !parent.is_valid() {
+        return job_cx.clone();
+    }
+
+    job_cx.with_remote_span_context(SpanContext::new(
+        parent.trace_id(),
+        stage_span_id(job_id, stage_id),
+        parent.trace_flags(),
+        false,
+        parent.trace_state().clone(),
+    ))
+}
+
+/// Record the span of a finished query stage, see [stage_context]
+pub fn record_stage_span(
+    job_cx: &Context,
+    job_id: &str,
+    stage_id: usize,
+    start_time: SystemTime,
+    error: Option<&str>,
+) {
+    if !job_cx.span().span_context().is_valid() {
+        return;
+    }
+
+    let tracer = global::tracer(TRACER_NAME);
+    let mut span = tracer
+        .span_builder(format!("stage {}", stage_id))
+        .with_span_id(stage_span_id(job_id, stage_id))
+        .with_kind(SpanKind::Internal)
+        .with_start_time(start_time)
+        .with_attributes(vec![
+            KeyValue::new("ballista.job_id", job_id.to_owned()),
+            KeyValue::new("ballista.stage_id", stage_id as i64),
+        ])
+        .start_with_context(&tracer, job_cx);
+    end_span(&mut span, error);
+}
+
+/// Start the span of a single task running on an executor
+pub fn start_task_span(
+    parent: &Context,
+    task_id: &PartitionId,
+    executor_id: &str,
+) -> BoxedSpan {
+    let mut span = start_span("execute_task", parent);
+    span.set_attribute(KeyValue::new("ballista.job_id", task_id.job_id.clone()));
+    span.set_attribute(KeyValue::new("ballista.stage_id", task_id.stage_id as i64));
+    span.set_attribute(KeyValue::new(
+        "ballista.partition_id",
+        task_id.partition_id as i64,
+    ));
+    span.set_attribute(KeyValue::new(
+        "ballista.executor_id",
+        executor_id.to_owned(),
+    ));
+    span
+}
+
+/// End `span`, marking it as failed if an error is given
+pub fn end_span<S: Span>(span: &mut S, error: Option<&str>) {
+    if let Some(error) = error {
+        span.set_status(Status::error(error.to_owned()));
+    }
+    span.end();
+}
+
+fn stage_span_id(job_id: &str, stage_id: usize) -> SpanId {
+    let mut hasher = DefaultHasher::new();
+    job_id.hash(&mut hasher);
+    stage_id.hash(&mut hasher);
+    // The all-zero span ID is invalid
+    SpanId::from_bytes(hasher.finish().max(1).to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::{TraceFlags, TraceId, TraceState};
+
+    fn remote_context() -> Context {
+        Context::new().with_remote_span_context(SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ))
+    }
+
+    #[test]
+    fn propagate_trace_context() {
+        install_propagator();
+
+        let cx = remote_context();
+        let pairs = inject_context(&cx);
+        assert_eq!(
+            pairs,
+            vec![KeyValuePair {
+                key: "traceparent".to_owned(),
+                value: "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                    .to_owned()
+            }]
+        );
+
+        let extracted = extract_context(&pairs);
+        assert_eq!(
+            extracted.span().span_context().trace_id(),
+            cx.span().span_context().trace_id()
+        );
+    }
+
+    #[test]
+    fn stage_context_is_deterministic() {
+        let cx = remote_context();
+        let stage_1 = stage_context(&cx, "job", 1);
+        let stage_2 = stage_context(&cx, "job", 2);
+
+        let span_1 = stage_1.span().span_context().clone();
+        assert!(span_1.is_valid());
+        assert_eq!(span_1.trace_id(), cx.span().span_context().trace_id());
+        assert_eq!(
+            span_1.span_id(),
+            stage_context(&cx, "job", 1).span().span_context().span_id()
+        );
+        assert_ne!(span_1.span_id(), stage_2.span().span_context().span_id());
+    }
+
+    #[test]
+    fn no_context_without_parent() {
+        let cx = stage_context(&Context::new(), "job", 1);
+        assert!(!cx.span().span_context().is_valid());
+        assert!(inject_context(&cx).is_empty());
+    }
+}
@@ -0,0 +1,276 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! TLS for the gRPC and Flight endpoints of the scheduler and executors.
+//!
+//! Servers are secured by giving them a certificate and private key. Clients verify
+//! servers against a CA certificate which is installed once per process with
+//! [init_client_tls]; from then on all connections created with [grpc_url] and
+//! [create_grpc_channel] use TLS.
+//!
+//! For mutual TLS, every component gets its own certificate which it presents both as a
+//! server and as a client, and servers are given the CA to verify client certificates
+//! against with [TlsOptions::client_ca_path].
+
+use crate::error::{BallistaError, Result};
+use once_cell::sync::OnceCell;
+use std::convert::TryFrom;
+use std::io::BufReader;
+use std::sync::Arc;
+use tokio_rustls::rustls;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::{
+    Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig,
+};
+
+static CLIENT_TLS: OnceCell<ClientTlsConfig> = OnceCell::new();
+
+/// Paths of the PEM files used to secure the connections of a component. Empty paths
+/// disable the corresponding feature.
+#[derive(Clone, Debug, Default)]
+pub struct TlsOptions {
+    /// Certificate chain presented by the servers of this component, and by its clients
+    /// to servers requiring client certificates
+    pub cert_path: String,
+    /// Private key of `cert_path`
+    pub key_path: String,
+    /// CA certificate used by the clients of this component to verify servers
+    pub ca_path: String,
+    /// Name expected in the certificates of servers, instead of their host name
+    pub domain_name: String,
+    /// CA certificate used by the servers of this component to verify the certificates
+    /// of their clients
+    pub client_ca_path: String,
+}
+
+impl TlsOptions {
+    /// Whether the servers of this component use TLS
+    pub fn server_enabled(&self) -> bool {
+        !self.cert_path.is_empty()
+    }
+
+    /// Whether the clients of this component use TLS
+    pub fn client_enabled(&self) -> bool {
+        !self.ca_path.is_empty()
+    }
+
+    /// Whether the servers of this component verify client certificates
+    pub fn client_auth_enabled(&self) -> bool {
+        self.server_enabled() && !self.client_ca_path.is_empty()
+    }
+
+    /// A tonic server builder, configured for TLS if enabled. Clients without a valid
+    /// certificate are rejected if client authentication is enabled.
+    pub fn server_builder(&self) -> Result<Server> {
+        let builder = Server::builder();
+        if !self.server_enabled() {
+            return Ok(builder);
+        }
+        let mut config = ServerTlsConfig::new().identity(self.identity()?);
+        if self.client_auth_enabled() {
+            config =
+                config.client_ca_root(Certificate::from_pem(read(&self.client_ca_path)?));
+        }
+        Ok(builder.tls_config(config)?)
+    }
+
+    /// An acceptor for servers not built with tonic, `None` if TLS is disabled.
+    /// Advertises both HTTP/2 and HTTP/1.1 so gRPC and REST can share a port.
+    ///
+    /// Unlike [TlsOptions::server_builder], clients without a certificate are accepted
+    /// even if client authentication is enabled. Certificates which are presented are
+    /// verified, so services can tell authenticated clients apart by checking whether
+    /// they have peer certificates.
+    pub fn tls_acceptor(&self) -> Result<Option<TlsAcceptor>> {
+        if !self.server_enabled() {
+            return Ok(None);
+        }
+        let builder = rustls::ServerConfig::builder().with_safe_defaults();
+        let builder = if self.client_auth_enabled() {
+            builder.with_client_cert_verifier(
+                rustls::server::AllowAnyAnonymousOrAuthenticatedClient::new(root_store(
+                    &self.client_ca_path,
+                )?),
+            )
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(read_certs(&self.cert_path)?, read_key(&self.key_path)?)
+            .map_err(|e| {
+                BallistaError::General(format!("Invalid TLS certificate: {}", e))
+            })?;
+        Ok(Some(with_alpn(config)))
+    }
+
+    fn client_tls_config(&self) -> Result<ClientTlsConfig> {
+        let mut config = ClientTlsConfig::new()
+            .ca_certificate(Certificate::from_pem(read(&self.ca_path)?));
+        if !self.domain_name.is_empty() {
+            config = config.domain_name(self.domain_name.clone());
+        }
+        if !self.cert_path.is_empty() {
+            config = config.identity(self.identity()?);
+        }
+        Ok(config)
+    }
+
+    fn identity(&self) -> Result<Identity> {
+        Ok(Identity::from_pem(
+            read(&self.cert_path)?,
+            read(&self.key_path)?,
+        ))
+    }
+}
+
+/// Use TLS for all connections created by [create_grpc_channel] in this process.
+/// Does nothing if client TLS is disabled in `options`.
+pub fn init_client_tls(options: &TlsOptions) -> Result<()> {
+    if !options.client_enabled() {
+        return Ok(());
+    }
+    CLIENT_TLS
+        .set(options.client_tls_config()?)
+        .map_err(|_| BallistaError::General("Client TLS is already set".to_owned()))
+}
+
+/// The URL of the gRPC or Flight endpoint at the given host and port
+pub fn grpc_url(host: &str, port: u16) -> String {
+    let scheme = if CLIENT_TLS.get().is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    format!("{}://{}:{}", scheme, host, port)
+}
+
+/// Connect to `url`, using TLS if it was set up with [init_client_tls]
+pub async fn create_grpc_channel(url: &str) -> Result<Channel> {
+    Ok(grpc_endpoint(url)?.connect().await?)
+}
+
+/// The endpoint at `url`, using TLS if it was set up with [init_client_tls], for
+/// callers which tune the connection before opening it
+pub fn grpc_endpoint(url: &str) -> Result<Endpoint> {
+    let mut endpoint = Endpoint::from_shared(url.to_owned()).map_err(|e| {
+        BallistaError::General(format!("Invalid gRPC URL {}: {}", url, e))
+    })?;
+    if let Some(config) = CLIENT_TLS.get() {
+        endpoint = endpoint.tls_config(config.clone())?;
+    }
+    Ok(endpoint)
+}
+
+/// Whether the DER encoded certificate `cert` was issued for the DNS name `host`. The
+/// chain of the certificate is not verified here, which is left to the TLS layer. IP
+/// addresses never match, as certificates can only vouch for host names.
+pub fn certificate_valid_for(cert: &[u8], host: &str) -> bool {
+    let name = match webpki::DnsNameRef::try_from_ascii_str(host) {
+        Ok(name) => name,
+        Err(_) => return false,
+    };
+    webpki::EndEntityCert::try_from(cert)
+        .and_then(|cert| cert.verify_is_valid_for_dns_name(name))
+        .is_ok()
+}
+
+fn with_alpn(mut config: rustls::ServerConfig) -> TlsAcceptor {
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    TlsAcceptor::from(Arc::new(config))
+}
+
+/// The CA certificates of the PEM file at `ca_path`, to verify peers against
+pub fn root_store(ca_path: &str) -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in read_certs(ca_path)? {
+        roots.add(&cert).map_err(|e| {
+            BallistaError::General(format!(
+                "Invalid CA certificate in {}: {}",
+                ca_path, e
+            ))
+        })?;
+    }
+    Ok(roots)
+}
+
+fn read(path: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| {
+        BallistaError::General(format!("Could not read TLS file {}: {}", path, e))
+    })
+}
+
+fn read_certs(path: &str) -> Result<Vec<rustls::Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(read(path)?.as_slice()))
+        .map_err(|e| {
+            BallistaError::General(format!("Invalid certificate in {}: {}", path, e))
+        })?;
+    Ok(certs.into_iter().map(rustls::Certificate).collect())
+}
+
+fn read_key(path: &str) -> Result<rustls::PrivateKey> {
+    let items = rustls_pemfile::read_all(&mut BufReader::new(read(path)?.as_slice()))
+        .map_err(|e| {
+            BallistaError::General(format!("Invalid private key in {}: {}", path, e))
+        })?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            BallistaError::General(format!("No private key found in {}", path))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_by_default() -> Result<()> {
+        let options = TlsOptions::default();
+        assert!(!options.server_enabled());
+        assert!(!options.client_enabled());
+        assert!(!options.client_auth_enabled());
+        assert!(options.tls_acceptor()?.is_none());
+        init_client_tls(&options)?;
+        assert_eq!(grpc_url("localhost", 50050), "http://localhost:50050");
+        Ok(())
+    }
+
+    #[test]
+    fn missing_files() {
+        let options = TlsOptions {
+            cert_path: "/does/not/exist.pem".to_owned(),
+            key_path: "/does/not/exist.key".to_owned(),
+            ..Default::default()
+        };
+        assert!(options.server_enabled());
+        assert!(!options.client_auth_enabled());
+        assert!(options.tls_acceptor().is_err());
+        assert!(options.server_builder().is_err());
+    }
+
+    #[test]
+    fn invalid_certificate() {
+        assert!(!certificate_valid_for(b"not a certificate", "executor-1"));
+        assert!(!certificate_valid_for(b"not a certificate", "10.0.0.1"));
+    }
+}
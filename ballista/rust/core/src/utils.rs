@@ -16,20 +16,26 @@
 // under the License.
 
 use crate::config::BallistaConfig;
+use crate::encryption::{self, EncryptionKey};
 use crate::error::{BallistaError, Result};
 use crate::execution_plans::{
     DistributedQueryExec, ShuffleWriterExec, UnresolvedShuffleExec,
 };
 use crate::serde::scheduler::PartitionStats;
+use crate::serde::BallistaLogicalExtensionCodec;
+use crate::shuffle_file::{InProgressFile, ShuffleFileWriter};
+use crate::table_format::to_external_scans;
 use async_trait::async_trait;
 use datafusion::arrow::datatypes::Schema;
-use datafusion::arrow::{ipc::writer::FileWriter, record_batch::RecordBatch};
+use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::{
     QueryPlanner, SessionConfig, SessionContext, SessionState,
 };
 use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
-use datafusion::logical_plan::LogicalPlan;
+use datafusion::logical_plan::{
+    EmptyRelation, Expr, ExpressionVisitor, LogicalPlan, Recursion,
+};
 use datafusion::physical_plan::aggregates::AggregateExec;
 use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
 use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
@@ -41,9 +47,7 @@ use datafusion::physical_plan::hash_join::HashJoinExec;
 use datafusion::physical_plan::projection::ProjectionExec;
 use datafusion::physical_plan::sorts::sort::SortExec;
 use datafusion::physical_plan::{metrics, ExecutionPlan, RecordBatchStream};
-use datafusion_proto::logical_plan::{
-    AsLogicalPlan, DefaultLogicalExtensionCodec, LogicalExtensionCodec,
-};
+use datafusion_proto::logical_plan::{AsLogicalPlan, LogicalExtensionCodec};
 use futures::StreamExt;
 use std::io::{BufWriter, Write};
 use std::marker::PhantomData;
@@ -51,24 +55,28 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::{fs::File, pin::Pin};
 
-/// Stream data to disk in Arrow IPC format
+/// Stream data to disk in Arrow IPC format, encrypting it if a key is given. The file
+/// only appears at `path` once the stream is completely written.
 
 pub async fn write_stream_to_disk(
     stream: &mut Pin<Box<dyn RecordBatchStream + Send>>,
     path: &str,
+    encryption_key: Option<&EncryptionKey>,
     disk_write_metric: &metrics::Time,
 ) -> Result<PartitionStats> {
-    let file = File::create(&path).map_err(|e| {
+    let mut in_progress = InProgressFile::new(path);
+    let temp_path = in_progress.temp_path();
+    let file = encryption::create_file(temp_path, encryption_key).map_err(|e| {
         BallistaError::General(format!(
-            "Failed to create partition file at {}: {:?}",
-            path, e
+            "Failed to create partition file at {:?}: {:?}",
+            temp_path, e
         ))
     })?;
 
     let mut num_rows = 0;
     let mut num_batches = 0;
     let mut num_bytes = 0;
-    let mut writer = FileWriter::try_new(file, stream.schema().as_ref())?;
+    let mut writer = ShuffleFileWriter::try_new(file, stream.schema().as_ref())?;
 
     while let Some(result) = stream.next().await {
         let batch = result?;
@@ -84,6 +92,8 @@ pub async fn write_stream_to_disk(
     }
     let timer = disk_write_metric.timer();
     writer.finish()?;
+    drop(writer);
+    in_progress.commit()?;
     timer.done();
     Ok(PartitionStats::new(
         Some(num_rows as u64),
@@ -254,7 +264,7 @@ impl<T: 'static + AsLogicalPlan> BallistaQueryPlanner<T> {
         Self {
             scheduler_url,
             config,
-            extension_codec: Arc::new(DefaultLogicalExtensionCodec {}),
+            extension_codec: Arc::new(BallistaLogicalExtensionCodec {}),
             plan_repr: PhantomData,
         }
     }
@@ -299,14 +309,72 @@ impl<T: 'static + AsLogicalPlan> QueryPlanner for BallistaQueryPlanner<T> {
                 // table state is managed locally in the BallistaContext, not in the scheduler
                 Ok(Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))))
             }
-            _ => Ok(Arc::new(DistributedQueryExec::with_repr(
-                self.scheduler_url.clone(),
-                self.config.clone(),
-                logical_plan.clone(),
-                self.extension_codec.clone(),
-                self.plan_repr,
-                session_state.session_id.clone(),
-            ))),
+            LogicalPlan::EmptyRelation(EmptyRelation {
+                produce_one_row: false,
+                schema,
+            }) => {
+                // nothing to compute, such as after creating a table of a table format
+                Ok(Arc::new(EmptyExec::new(
+                    false,
+                    Arc::new(Schema::from(schema.as_ref())),
+                )))
+            }
+            _ => {
+                check_subqueries_decorrelated(logical_plan)?;
+                Ok(Arc::new(DistributedQueryExec::with_repr(
+                    self.scheduler_url.clone(),
+                    self.config.clone(),
+                    to_external_scans(logical_plan)?,
+                    self.extension_codec.clone(),
+                    self.plan_repr,
+                    session_state.session_id.clone(),
+                )))
+            }
+        }
+    }
+}
+
+/// Subqueries and CTEs are shipped to the scheduler as joins and aliased projections once
+/// the optimizer has decorrelated them. Any subquery expression left in the optimized plan
+/// cannot be serialized, so fail here with the offending expression rather than with an
+/// opaque serde error once the query is executed.
+fn check_subqueries_decorrelated(
+    plan: &LogicalPlan,
+) -> std::result::Result<(), DataFusionError> {
+    struct SubqueryFinder(Option<Expr>);
+
+    impl ExpressionVisitor for SubqueryFinder {
+        fn pre_visit(
+            mut self,
+            expr: &Expr,
+        ) -> std::result::Result<Recursion<Self>, DataFusionError> {
+            match expr {
+                Expr::Exists { .. }
+                | Expr::InSubquery { .. }
+                | Expr::ScalarSubquery(_) => {
+                    self.0 = Some(expr.clone());
+                    Ok(Recursion::Stop(self))
+                }
+                _ => Ok(Recursion::Continue(self)),
+            }
+        }
+    }
+
+    if let LogicalPlan::Subquery(subquery) = plan {
+        return Err(DataFusionError::NotImplemented(format!(
+            "Ballista does not support subquery plans that could not be decorrelated: {:?}",
+            subquery.subquery
+        )));
+    }
+    for expr in plan.expressions() {
+        if let Some(subquery) = expr.accept(SubqueryFinder(None))?.0 {
+            return Err(DataFusionError::NotImplemented(format!(
+                "Ballista does not support subquery expressions that could not be decorrelated: {:?}",
+                subquery
+            )));
         }
     }
+    plan.inputs()
+        .into_iter()
+        .try_for_each(check_subqueries_decorrelated)
 }
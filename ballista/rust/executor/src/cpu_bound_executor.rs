@@ -22,8 +22,12 @@
 
 use log::warn;
 use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 use std::{pin::Pin, sync::Arc};
 use tokio::sync::oneshot::Receiver;
+use tokio::time::Sleep;
 
 use futures::Future;
 
@@ -143,13 +147,26 @@ impl DedicatedExecutor {
         let (tx, rx) = tokio::sync::oneshot::channel();
 
         // create a execution plan to spawn
-        let job = Box::pin(async move {
+        self.submit(Box::pin(async move {
             let task_output = task.await;
             if tx.send(task_output).is_err() {
                 warn!("Spawned task output ignored: receiver dropped");
             }
-        });
+        }));
+
+        rx
+    }
+
+    /// Runs the specified Future (and any tasks it spawns) on the
+    /// `DedicatedExecutor`, without waiting for its output.
+    pub fn spawn_detached<T>(&self, task: T)
+    where
+        T: Future<Output = ()> + Send + 'static,
+    {
+        self.submit(Box::pin(task));
+    }
 
+    fn submit(&self, job: Task) {
         let mut state = self.state.lock();
 
         if let Some(requests) = &mut state.requests {
@@ -158,8 +175,6 @@ impl DedicatedExecutor {
         } else {
             warn!("tried to schedule task on an executor that was shutdown");
         }
-
-        rx
     }
 
     /// signals shutdown of this executor and any Clones
@@ -197,6 +212,182 @@ impl DedicatedExecutor {
     }
 }
 
+/// Dedicated runtimes sharing the cores which run the tasks of the executor. Each task
+/// runs on the runtime running the fewest tasks, so that a task never uses more than the
+/// threads of its runtime, and CPU bound tasks only slow down the tasks sharing their
+/// runtime. Running more tasks than threads suits I/O bound tasks, which leave their
+/// threads idle while they wait for data.
+#[derive(Clone, Debug)]
+pub struct TaskRuntimes {
+    /// The runtimes, with the number of tasks running on each
+    runtimes: Vec<(DedicatedExecutor, Arc<AtomicUsize>)>,
+    /// The share of a thread each task may use, unlimited if None
+    task_cpu_share: Option<f64>,
+}
+
+impl TaskRuntimes {
+    /// Split `num_threads` threads between `partitions` runtimes, of at least one thread
+    /// each
+    pub fn new(thread_name: &str, num_threads: usize, partitions: usize) -> Self {
+        let partitions = partitions.clamp(1, num_threads.max(1));
+        let runtimes = (0..partitions)
+            .map(|partition| {
+                // The threads left over go to the first runtimes
+                let threads = num_threads / partitions
+                    + usize::from(partition < num_threads % partitions);
+                let runtime = if partitions == 1 {
+                    DedicatedExecutor::new(thread_name, threads.max(1))
+                } else {
+                    DedicatedExecutor::new(
+                        format!("{}-{}", thread_name, partition),
+                        threads.max(1),
+                    )
+                };
+                (runtime, Arc::new(AtomicUsize::new(0)))
+            })
+            .collect();
+        Self {
+            runtimes,
+            task_cpu_share: None,
+        }
+    }
+
+    /// Limit each task to `percent` percent of the time of a thread, unlimited if 0 or
+    /// above 100. A task which ran longer than its share since it started is paused
+    /// until it is back within its share, leaving the threads of its runtime to the
+    /// other tasks.
+    pub fn with_task_cpu_share(mut self, percent: usize) -> Self {
+        self.task_cpu_share =
+            (percent > 0 && percent < 100).then(|| percent as f64 / 100.0);
+        self
+    }
+
+    /// Runs `task` on the runtime running the fewest tasks
+    pub fn spawn<T>(&self, task: T) -> Receiver<T::Output>
+    where
+        T: Future + Send + 'static,
+        T::Output: Send + 'static,
+    {
+        let (runtime, running) = self.least_busy();
+        let task = CpuShare::new(task, self.task_cpu_share);
+        runtime.spawn(async move {
+            // Dropped once the task completed or panicked
+            let _running = running;
+            task.await
+        })
+    }
+
+    /// Runs `task` on the runtime running the fewest tasks, without waiting for its
+    /// output
+    pub fn spawn_detached<T>(&self, task: T)
+    where
+        T: Future<Output = ()> + Send + 'static,
+    {
+        let (runtime, running) = self.least_busy();
+        let task = CpuShare::new(task, self.task_cpu_share);
+        runtime.spawn_detached(async move {
+            let _running = running;
+            task.await
+        })
+    }
+
+    /// The runtime running the fewest tasks, with its count of running tasks already
+    /// counting the task about to be spawned on it
+    fn least_busy(&self) -> (&DedicatedExecutor, RunningTask) {
+        loop {
+            let (runtime, running, observed) = self
+                .runtimes
+                .iter()
+                .map(|(runtime, running)| {
+                    (runtime, running, running.load(Ordering::SeqCst))
+                })
+                .min_by_key(|(_, _, observed)| *observed)
+                .expect("There is at least one task runtime");
+            // Another task may have been spawned on the runtime since its count was
+            // read, in which case the least busy runtime is looked up again
+            if running
+                .compare_exchange(
+                    observed,
+                    observed + 1,
+                    Ordering::SeqCst,
+                    Ordering::SeqCst,
+                )
+                .is_ok()
+            {
+                return (runtime, RunningTask(running.clone()));
+            }
+        }
+    }
+
+    /// The number of tasks running on each runtime
+    pub fn running_tasks(&self) -> Vec<usize> {
+        self.runtimes
+            .iter()
+            .map(|(_, running)| running.load(Ordering::SeqCst))
+            .collect()
+    }
+}
+
+/// A task counted as running on a runtime until it is dropped, including when the task
+/// panics or the runtime was shut down before running it
+#[derive(Debug)]
+struct RunningTask(Arc<AtomicUsize>);
+
+impl Drop for RunningTask {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs a future within a share of the time of a thread. The time spent polling the
+/// future counts as its CPU time, so that futures spawned by it on the runtime are not
+/// limited.
+struct CpuShare<F> {
+    inner: Pin<Box<F>>,
+    share: Option<f64>,
+    started: Instant,
+    busy: Duration,
+    paused: Option<Pin<Box<Sleep>>>,
+}
+
+impl<F: Future> CpuShare<F> {
+    fn new(inner: F, share: Option<f64>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            share,
+            started: Instant::now(),
+            busy: Duration::ZERO,
+            paused: None,
+        }
+    }
+}
+
+impl<F: Future> Future for CpuShare<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(paused) = self.paused.as_mut() {
+            if paused.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.paused = None;
+        }
+        let poll_start = Instant::now();
+        let output = self.inner.as_mut().poll(cx);
+        self.busy += poll_start.elapsed();
+        if output.is_pending() {
+            if let Some(share) = self.share {
+                // The time the task must have run for its CPU time to be within share
+                let due = self.started + self.busy.div_f64(share);
+                if due > Instant::now() {
+                    self.paused = Some(Box::pin(tokio::time::sleep_until(due.into())));
+                }
+            }
+        }
+        output
+    }
+}
+
 #[cfg(unix)]
 fn set_current_thread_priority(prio: i32) {
     unsafe { libc::setpriority(0, 0, prio) };
@@ -370,6 +561,96 @@ mod tests {
         exec.join();
     }
 
+    #[tokio::test]
+    async fn spread_tasks_between_runtimes() {
+        let runtimes = TaskRuntimes::new("Test TaskRuntimes", 5, 2);
+        let threads: Vec<usize> = runtimes
+            .runtimes
+            .iter()
+            .map(|(runtime, _)| runtime.state.lock().num_threads)
+            .collect();
+        assert_eq!(threads, vec![3, 2]);
+
+        let barrier = Arc::new(Barrier::new(3));
+        let task1 = runtimes.spawn(do_work(11, Arc::clone(&barrier)));
+        let task2 = runtimes.spawn(do_work(42, Arc::clone(&barrier)));
+        assert_eq!(runtimes.running_tasks(), vec![1, 1]);
+        barrier.wait();
+
+        assert_eq!(task1.await.unwrap(), 11);
+        assert_eq!(task2.await.unwrap(), 42);
+        assert_eq!(runtimes.running_tasks(), vec![0, 0]);
+
+        // Detached tasks are counted until they complete
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        runtimes.spawn_detached(async move {
+            tx.send(7).unwrap();
+        });
+        assert_eq!(rx.await.unwrap(), 7);
+        while runtimes.running_tasks() != vec![0, 0] {
+            tokio::task::yield_now().await;
+        }
+        // More partitions than threads
+        assert_eq!(
+            TaskRuntimes::new("Test TaskRuntimes", 1, 4).runtimes.len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn count_panicked_tasks_as_completed() {
+        let runtimes = TaskRuntimes::new("Test TaskRuntimes", 4, 2);
+        let task = runtimes.spawn(async move {
+            panic!("At the disco, on the task runtimes");
+        });
+        task.await.unwrap_err();
+        assert_eq!(runtimes.running_tasks(), vec![0, 0]);
+
+        // Tasks spawned at once from many threads are spread evenly
+        let runtimes = Arc::new(runtimes);
+        let barrier = Arc::new(Barrier::new(5));
+        let spawned: Vec<_> = (0..4)
+            .map(|i| {
+                let runtimes = runtimes.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    runtimes.spawn(do_work(i, barrier));
+                })
+            })
+            .collect();
+        for thread in spawned {
+            thread.join().unwrap();
+        }
+        assert_eq!(runtimes.running_tasks(), vec![2, 2]);
+        barrier.wait();
+    }
+
+    #[tokio::test]
+    async fn limit_cpu_share_of_tasks() {
+        let runtimes =
+            TaskRuntimes::new("Test TaskRuntimes", 1, 1).with_task_cpu_share(50);
+        let started = Instant::now();
+        let task = runtimes.spawn(async move {
+            for _ in 0..5 {
+                // Keep the thread busy for 10ms before yielding
+                let busy = Instant::now();
+                while busy.elapsed() < Duration::from_millis(10) {}
+                tokio::task::yield_now().await;
+            }
+        });
+        task.await.unwrap();
+        // The task was paused after each poll until it used at most half of the time
+        // it ran for
+        assert!(started.elapsed() >= Duration::from_millis(80));
+
+        assert_eq!(
+            TaskRuntimes::new("Test TaskRuntimes", 1, 1)
+                .with_task_cpu_share(100)
+                .task_cpu_share,
+            None
+        );
+    }
+
     /// Wait for the barrier and then return `result`
     async fn do_work(result: usize, barrier: Arc<Barrier>) -> usize {
         barrier.wait();
@@ -22,12 +22,18 @@ use ballista_core::serde::protobuf::{
     TaskDefinition, TaskStatus,
 };
 
-use crate::as_task_status;
 use crate::executor::Executor;
+use crate::shuffle_replication::replicate_output;
+use crate::task_interceptor::InterceptedTask;
+use crate::{as_task_status, run_killable, run_with_timeout, task_timeout};
+use ballista_core::config::{BALLISTA_CACHE_TABLE, BALLISTA_JOB_IN_MEMORY_RESULTS};
+use ballista_core::encryption::EncryptionKey;
 use ballista_core::error::BallistaError;
+use ballista_core::logging::{with_log_context, LogContext};
+use ballista_core::protocol::ProtocolInfo;
 use ballista_core::serde::physical_plan::from_proto::parse_protobuf_hash_partitioning;
-use ballista_core::serde::scheduler::ExecutorSpecification;
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
+use ballista_core::telemetry;
 use datafusion::execution::context::TaskContext;
 use datafusion_proto::logical_plan::AsLogicalPlan;
 use futures::FutureExt;
@@ -46,15 +52,7 @@ pub async fn poll_loop<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
     executor: Arc<Executor>,
     codec: BallistaCodec<T, U>,
 ) {
-    let executor_specification: ExecutorSpecification = executor
-        .metadata
-        .specification
-        .as_ref()
-        .unwrap()
-        .clone()
-        .into();
-    let available_tasks_slots =
-        Arc::new(AtomicUsize::new(executor_specification.task_slots as usize));
+    let running_tasks = Arc::new(AtomicUsize::new(0));
     let (task_status_sender, mut task_status_receiver) =
         std::sync::mpsc::channel::<TaskStatus>();
 
@@ -74,19 +72,40 @@ pub async fn poll_loop<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
         > = scheduler
             .poll_work(PollWorkParams {
                 metadata: Some(executor.metadata.clone()),
-                can_accept_task: available_tasks_slots.load(Ordering::SeqCst) > 0,
+                can_accept_task: running_tasks.load(Ordering::SeqCst)
+                    < executor.task_limit(),
                 task_status,
+                epoch: executor.epoch(),
+                task_progress: executor.running_tasks().progress(),
             })
             .await;
 
         let task_status_sender = task_status_sender.clone();
 
+        executor.set_registered(poll_work_result.is_ok());
         match poll_work_result {
             Ok(result) => {
-                if let Some(task) = result.into_inner().task {
+                let result = result.into_inner();
+                if result.epoch != 0 {
+                    executor.set_epoch(result.epoch);
+                }
+                let killed = executor.running_tasks().kill(&result.kill_tasks);
+                if killed > 0 {
+                    warn!("Killed {} tasks which stopped making progress", killed);
+                }
+                // The data of uncached tables, which is pinned
+                for job_id in &result.remove_jobs {
+                    if let Err(e) = executor.remove_job_data(job_id) {
+                        warn!("Failed to remove the data of job {}: {:?}", job_id, e);
+                    }
+                }
+                if let Err(e) = ProtocolInfo::from(result.protocol).check_compatible() {
+                    error!("Can not run the tasks of the scheduler: {}", e);
+                    executor.set_registered(false);
+                } else if let Some(task) = result.task {
                     match run_received_tasks(
                         executor.clone(),
-                        available_tasks_slots.clone(),
+                        running_tasks.clone(),
                         task_status_sender,
                         task,
                         &codec,
@@ -105,6 +124,15 @@ pub async fn poll_loop<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
                     active_job = false;
                 }
             }
+            Err(error) if error.code() == tonic::Code::FailedPrecondition => {
+                // The scheduler considered the executor lost. The statuses it rejected
+                // are dropped, and the executor polls for a new epoch.
+                warn!(
+                    "Scheduler rejected poll for work, registering again: {}",
+                    error
+                );
+                executor.set_epoch(0);
+            }
             Err(error) => {
                 warn!("Executor registration failed. If this continues to happen the executor might be marked as dead by the scheduler. Error: {}", error);
             }
@@ -130,25 +158,39 @@ pub(crate) fn any_to_string(any: &Box<dyn Any + Send>) -> String {
 
 async fn run_received_tasks<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
     executor: Arc<Executor>,
-    available_tasks_slots: Arc<AtomicUsize>,
+    running_tasks: Arc<AtomicUsize>,
     task_status_sender: Sender<TaskStatus>,
     task: TaskDefinition,
     codec: &BallistaCodec<T, U>,
 ) -> Result<(), BallistaError> {
     let task_id = task.task_id.unwrap();
+    let attempt = task.attempt;
     let task_id_log = format!(
         "{}/{}/{}",
         task_id.job_id, task_id.stage_id, task_id.partition_id
     );
     info!("Received task {}", task_id_log);
-    available_tasks_slots.fetch_sub(1, Ordering::SeqCst);
+    running_tasks.fetch_add(1, Ordering::SeqCst);
+
+    let mut span = telemetry::start_task_span(
+        &telemetry::extract_context(&task.trace_context),
+        &task_id,
+        &executor.metadata.id,
+    );
 
-    let runtime = executor.runtime.clone();
+    let runtime = executor.task_runtime(&task.object_stores)?;
     let session_id = task.session_id;
     let mut task_props = HashMap::new();
     for kv_pair in task.props {
         task_props.insert(kv_pair.key, kv_pair.value);
     }
+    // The output of the jobs of cached tables is kept until the scheduler removes it
+    let in_memory = task_props.contains_key(BALLISTA_JOB_IN_MEMORY_RESULTS);
+    let timeout = task_timeout(&task_props);
+    let props = task_props.clone();
+    if task_props.contains_key(BALLISTA_CACHE_TABLE) {
+        executor.pin_job(&task_id.job_id)?;
+    }
 
     let mut task_scalar_functions = HashMap::new();
     let mut task_aggregate_functions = HashMap::new();
@@ -176,6 +218,7 @@ async fn run_received_tasks<T: 'static + AsLogicalPlan, U: 'static + AsExecution
                 codec.physical_extension_codec(),
             )
         })?;
+    let plan = executor.share_scans(plan, &task.object_stores)?;
 
     let shuffle_output_partitioning = parse_protobuf_hash_partitioning(
         task.output_partitioning.as_ref(),
@@ -183,16 +226,46 @@ async fn run_received_tasks<T: 'static + AsLogicalPlan, U: 'static + AsExecution
         plan.schema().as_ref(),
     )?;
 
-    tokio::spawn(async move {
+    let encryption_key = EncryptionKey::from_proto(&task.encryption_key)?;
+    let replica = task.replica;
+    let intercepted = InterceptedTask {
+        task_id: task_id.clone(),
+        attempt,
+        plan: plan.clone(),
+        props,
+    };
+    let (running, registration) =
+        executor
+            .running_tasks()
+            .register(&task_id, attempt, plan.clone());
+
+    let log_context = LogContext::task(
+        task_id.job_id.clone(),
+        task_id.stage_id as usize,
+        task_id.partition_id as usize,
+    );
+    let task_runtimes = executor.task_runtimes().cloned();
+    let task = with_log_context(log_context, async move {
         use std::panic::AssertUnwindSafe;
 
-        let execution_result = match AssertUnwindSafe(executor.execute_shuffle_write(
-            task_id.job_id.clone(),
-            task_id.stage_id as usize,
-            task_id.partition_id as usize,
-            plan,
-            task_context,
-            shuffle_output_partitioning,
+        let execution_result = match AssertUnwindSafe(executor.intercept(
+            &intercepted,
+            run_killable(
+                registration,
+                run_with_timeout(
+                    timeout,
+                    executor.execute_shuffle_write(
+                        task_id.job_id.clone(),
+                        task_id.stage_id as usize,
+                        task_id.partition_id as usize,
+                        plan,
+                        task_context,
+                        shuffle_output_partitioning,
+                        encryption_key,
+                        in_memory,
+                    ),
+                ),
+            ),
         ))
         .catch_unwind()
         .await
@@ -204,17 +277,47 @@ async fn run_received_tasks<T: 'static + AsLogicalPlan, U: 'static + AsExecution
                 Err(BallistaError::Internal(format!("{:#?}", any_to_string(&r))))
             }
         };
+        let execution_result = match execution_result {
+            Ok(output) => Ok(replicate_output(
+                &executor,
+                &task_id.job_id,
+                task_id.stage_id as usize,
+                output,
+                replica,
+            )
+            .await),
+            Err(e) => Err(e),
+        };
 
         info!("Done with task {}", task_id_log);
         debug!("Statistics: {:?}", execution_result);
-        available_tasks_slots.fetch_add(1, Ordering::SeqCst);
+        telemetry::end_span(
+            &mut span,
+            execution_result
+                .as_ref()
+                .err()
+                .map(|e| e.to_string())
+                .as_deref(),
+        );
+        running_tasks.fetch_sub(1, Ordering::SeqCst);
+        drop(running);
 
         let _ = task_status_sender.send(as_task_status(
             execution_result,
             executor.metadata.id.clone(),
             task_id,
+            attempt,
         ));
     });
+    // Tasks run on the threads of the executor unless they have their own runtimes
+    match task_runtimes {
+        Some(task_runtimes) => {
+            task_runtimes.spawn_detached(task);
+        }
+        None => {
+            tokio::spawn(task);
+        }
+    }
 
     Ok(())
 }
@@ -18,20 +18,64 @@
 //! Ballista executor logic
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 
+use crate::cpu_bound_executor::TaskRuntimes;
+use crate::memory_results::{MemoryResults, ServedPartition};
 use crate::metrics::ExecutorMetricsCollector;
+use crate::running_tasks::RunningTasks;
+use crate::scan_cache::{batch_bytes, ScanCache};
+use crate::shuffle_serving::ShuffleServingLimits;
+use crate::task_interceptor::{intercept, InterceptedTask, TaskInterceptor};
+use ballista_core::config::ShuffleFetchOrder;
+use ballista_core::encryption::EncryptionKey;
 use ballista_core::error::BallistaError;
-use ballista_core::execution_plans::ShuffleWriterExec;
+use ballista_core::execution_plans::{BloomFilterBuildExec, ShuffleWriterExec};
+use ballista_core::object_store_registry::{
+    ObjectStoreCredentials, ObjectStoreFactories,
+};
 use ballista_core::serde::protobuf;
 use ballista_core::serde::protobuf::ExecutorRegistration;
+use ballista_core::utils::write_stream_to_disk;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::TaskContext;
 use datafusion::execution::runtime_env::RuntimeEnv;
+use parking_lot::Mutex;
 
+use datafusion::physical_plan::metrics::{MetricValue, Time};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
 use datafusion::physical_plan::udaf::AggregateUDF;
 use datafusion::physical_plan::udf::ScalarUDF;
-use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use datafusion::physical_plan::{ExecutionPlan, Partitioning, SendableRecordBatchStream};
+use futures::StreamExt;
+use log::info;
+use std::future::Future;
+use std::time::Duration;
+
+/// File marking a job directory of the work dir whose shuffle files are kept until the
+/// scheduler removes them, as they hold the partitions of a cached table
+pub const PINNED_FILE: &str = ".pinned";
+
+/// The output of a task writing a partition of a query stage
+#[derive(Debug)]
+pub struct TaskOutput {
+    /// The shuffle partitions written by the task
+    pub partitions: Vec<protobuf::ShuffleWritePartition>,
+    /// Bytes read from storage by the scans of the task
+    pub bytes_scanned: u64,
+    /// Bytes spilled to disk by the operators of the task which ran out of memory
+    pub spilled_bytes: u64,
+    /// Number of files these operators spilled to
+    pub spill_files: u64,
+    /// Bloom filter of the join keys of the rows the task wrote, empty if it builds none
+    pub bloom_filter: Vec<u8>,
+    /// The copies of the partitions kept by another executor, if they were copied
+    pub replica: Option<protobuf::ShuffleReplica>,
+    /// Metrics of the operators of the plan of the task, depth first from its root
+    pub operator_metrics: Vec<protobuf::OperatorMetrics>,
+}
 
 /// Ballista executor
 pub struct Executor {
@@ -55,6 +99,40 @@ pub struct Executor {
 
     /// Concurrent tasks can run in executor
     pub concurrent_tasks: usize,
+
+    /// Maximum number of tasks polled for and running at once, which can be changed at
+    /// runtime, in pull-based scheduling
+    task_limit: AtomicUsize,
+
+    /// Whether the executor is registered with the scheduler
+    registered: AtomicBool,
+
+    /// Epoch of the latest registration of the executor, 0 if it has none
+    epoch: AtomicU64,
+
+    /// Keys of the jobs whose shuffle files in `work_dir` are encrypted
+    encryption_keys: Mutex<HashMap<String, EncryptionKey>>,
+
+    /// Factories of the object stores clients configure for their jobs
+    object_store_factories: ObjectStoreFactories,
+
+    /// Batches of the Parquet scans shared by the tasks, if enabled
+    scan_cache: Option<Arc<ScanCache>>,
+
+    /// Output of the final stages of jobs kept in memory, if enabled
+    memory_results: Option<Arc<MemoryResults>>,
+
+    /// The tasks running on the executor, with their progress
+    running_tasks: RunningTasks,
+
+    /// Hooks invoked around each task, in order
+    task_interceptors: Vec<Arc<dyn TaskInterceptor>>,
+
+    /// Runtimes sharing the cores the tasks run on, if configured
+    task_runtimes: Option<TaskRuntimes>,
+
+    /// Limits of the shuffle partitions served by the Flight service
+    shuffle_serving_limits: Arc<ShuffleServingLimits>,
 }
 
 impl Executor {
@@ -75,14 +153,147 @@ impl Executor {
             runtime,
             metrics_collector,
             concurrent_tasks,
+            task_limit: AtomicUsize::new(concurrent_tasks),
+            registered: AtomicBool::new(false),
+            epoch: AtomicU64::new(0),
+            encryption_keys: Mutex::new(HashMap::new()),
+            object_store_factories: ObjectStoreFactories::default(),
+            scan_cache: None,
+            memory_results: None,
+            running_tasks: RunningTasks::default(),
+            task_interceptors: vec![],
+            task_runtimes: None,
+            shuffle_serving_limits: Arc::new(ShuffleServingLimits::default()),
         }
     }
+
+    /// Create the object stores clients configure for their jobs with `factories`
+    pub fn with_object_store_factories(
+        mut self,
+        factories: ObjectStoreFactories,
+    ) -> Self {
+        self.object_store_factories = factories;
+        self
+    }
+
+    /// Share the Parquet scans of the tasks running at once, and keep up to `capacity`
+    /// bytes of their batches for the tasks scanning the same files later. Scans are not
+    /// shared if `capacity` is 0.
+    pub fn with_scan_cache(mut self, capacity: usize) -> Self {
+        self.scan_cache = (capacity > 0).then(|| Arc::new(ScanCache::new(capacity)));
+        self
+    }
+
+    /// Keep up to `capacity` bytes of the output of the final stages of the jobs asking
+    /// for in-memory results, each partition until it is fetched or for up to `ttl`,
+    /// and send up to `serving_capacity` bytes of them from memory at once. The output
+    /// of these jobs is written to files if `capacity` is 0.
+    pub fn with_memory_results(
+        mut self,
+        capacity: usize,
+        serving_capacity: usize,
+        ttl: Duration,
+    ) -> Self {
+        self.memory_results = (capacity > 0)
+            .then(|| Arc::new(MemoryResults::new(capacity, serving_capacity, ttl)));
+        self
+    }
+
+    /// Run the tasks on `cores` threads split between `partitions` runtimes, instead of
+    /// one thread per task slot in push-based scheduling and on the threads of the
+    /// executor in pull-based scheduling. With more task slots than `cores`, the tasks
+    /// share the cores, and each task uses at most the cores of its runtime and
+    /// `share_percent` percent of the time of a thread, unlimited if 0.
+    pub fn with_task_cpu_shares(
+        mut self,
+        cores: usize,
+        partitions: usize,
+        share_percent: usize,
+    ) -> Self {
+        self.task_runtimes = Some(
+            TaskRuntimes::new("task_runner", cores, partitions)
+                .with_task_cpu_share(share_percent),
+        );
+        self
+    }
+
+    /// Send up to `max_streams` shuffle partitions at once, starting those waiting in
+    /// `fetch_order`, and up to `max_bytes_per_second` bytes of them per second, each
+    /// unlimited if 0
+    pub fn with_shuffle_serving_limits(
+        mut self,
+        max_streams: usize,
+        max_bytes_per_second: usize,
+        fetch_order: ShuffleFetchOrder,
+    ) -> Self {
+        self.shuffle_serving_limits = Arc::new(ShuffleServingLimits::new(
+            max_streams,
+            max_bytes_per_second,
+            fetch_order,
+        ));
+        self
+    }
+
+    pub fn shuffle_serving_limits(&self) -> &Arc<ShuffleServingLimits> {
+        &self.shuffle_serving_limits
+    }
+
+    /// The runtimes the tasks run on, if configured
+    pub(crate) fn task_runtimes(&self) -> Option<&TaskRuntimes> {
+        self.task_runtimes.as_ref()
+    }
+
+    /// Invoke `interceptor` around each task, after the interceptors installed before
+    pub fn with_task_interceptor(
+        mut self,
+        interceptor: Arc<dyn TaskInterceptor>,
+    ) -> Self {
+        self.task_interceptors.push(interceptor);
+        self
+    }
+
+    /// The tasks running on the executor, with their progress
+    pub fn running_tasks(&self) -> &RunningTasks {
+        &self.running_tasks
+    }
+
+    /// Maximum number of tasks the executor runs at once in pull-based scheduling
+    pub fn task_limit(&self) -> usize {
+        self.task_limit.load(Ordering::SeqCst)
+    }
+
+    /// Change the maximum number of tasks the executor runs at once in pull-based
+    /// scheduling. Running tasks are not affected if it is lowered.
+    pub fn set_task_limit(&self, task_limit: usize) {
+        self.task_limit.store(task_limit, Ordering::SeqCst);
+    }
+
+    /// Whether the executor registered with the scheduler, or its last poll for work
+    /// succeeded in pull-based scheduling
+    pub fn registered(&self) -> bool {
+        self.registered.load(Ordering::SeqCst)
+    }
+
+    pub fn set_registered(&self, registered: bool) {
+        self.registered.store(registered, Ordering::SeqCst);
+    }
+
+    /// Epoch the scheduler gave the executor when it last registered, sent with its
+    /// messages so that the scheduler rejects them once it considered it lost
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
+    }
+
+    pub fn set_epoch(&self, epoch: u64) {
+        self.epoch.store(epoch, Ordering::SeqCst);
+    }
 }
 
 impl Executor {
-    /// Execute one partition of a query stage and persist the result to disk in IPC format. On
-    /// success, return a RecordBatch containing metadata about the results, including path
-    /// and statistics.
+    /// Execute one partition of a query stage and persist the result to disk in IPC format,
+    /// encrypted with `encryption_key` if given. On success, return metadata about the
+    /// results, including path and statistics, along with the bytes the task scanned.
+    #[allow(clippy::too_many_arguments)]
     pub async fn execute_shuffle_write(
         &self,
         job_id: String,
@@ -91,7 +302,16 @@ impl Executor {
         plan: Arc<dyn ExecutionPlan>,
         task_ctx: Arc<TaskContext>,
         _shuffle_output_partitioning: Option<Partitioning>,
-    ) -> Result<Vec<protobuf::ShuffleWritePartition>, BallistaError> {
+        encryption_key: Option<EncryptionKey>,
+        in_memory: bool,
+    ) -> Result<TaskOutput, BallistaError> {
+        if let Some(key) = &encryption_key {
+            // Needed to serve the shuffle files of the job to other executors
+            self.encryption_keys
+                .lock()
+                .insert(job_id.clone(), key.clone());
+        }
+
         let exec = if let Some(shuffle_writer) =
             plan.as_any().downcast_ref::<ShuffleWriterExec>()
         {
@@ -103,6 +323,7 @@ impl Executor {
                 self.work_dir.clone(),
                 shuffle_writer.shuffle_output_partitioning().cloned(),
             )
+            .map(|exec| exec.with_encryption_key(encryption_key.clone()))
         } else {
             Err(DataFusionError::Internal(
                 "Plan passed to execute_shuffle_write is not a ShuffleWriterExec"
@@ -110,15 +331,308 @@ impl Executor {
             ))
         }?;
 
-        let partitions = exec.execute_shuffle_write(part, task_ctx).await?;
+        let partitions = match &self.memory_results {
+            Some(results)
+                if in_memory && exec.shuffle_output_partitioning().is_none() =>
+            {
+                // The directory the shuffle writer would write the partition to
+                let dir = Path::new(&self.work_dir)
+                    .join(&job_id)
+                    .join(stage_id.to_string())
+                    .join(part.to_string());
+                vec![
+                    write_in_memory(
+                        results,
+                        part,
+                        MemoryResults::path(&job_id, stage_id, part),
+                        &dir,
+                        exec.children()[0].execute(part, task_ctx)?,
+                        encryption_key.as_ref(),
+                    )
+                    .await?,
+                ]
+            }
+            _ => exec.execute_shuffle_write(part, task_ctx).await?,
+        };
 
+        let bytes_scanned = metric_total(&exec, "bytes_scanned");
+        let spilled_bytes = metric_total(&exec, "spilled_bytes");
+        let spill_files = metric_total(&exec, "spill_count");
+        if spilled_bytes > 0 {
+            info!(
+                "Task {}/{}/{} spilled {} bytes to {} files",
+                job_id, stage_id, part, spilled_bytes, spill_files
+            );
+        }
+        let bloom_filter = built_bloom_filter(&exec).unwrap_or_default();
+        let mut operator_metrics = vec![];
+        collect_operator_metrics(&exec, &mut operator_metrics);
         self.metrics_collector
             .record_stage(&job_id, stage_id, part, exec);
 
-        Ok(partitions)
+        Ok(TaskOutput {
+            partitions,
+            bytes_scanned,
+            spilled_bytes,
+            spill_files,
+            bloom_filter,
+            replica: None,
+            operator_metrics,
+        })
+    }
+
+    /// Run `run`, the execution of `task`, surrounded by the task interceptors of the
+    /// executor
+    pub async fn intercept(
+        &self,
+        task: &InterceptedTask,
+        run: impl Future<Output = Result<TaskOutput, BallistaError>>,
+    ) -> Result<TaskOutput, BallistaError> {
+        intercept(&self.task_interceptors, task, run).await
+    }
+
+    /// Partition `path` kept in memory, to send it to a client
+    pub fn serve_memory_results(&self, path: &str) -> Option<ServedPartition> {
+        self.memory_results.as_ref()?.serve(path)
     }
 
     pub fn work_dir(&self) -> &str {
         &self.work_dir
     }
+
+    /// The runtime to run a task in, which has the object stores configured for its job
+    /// registered
+    pub fn task_runtime(
+        &self,
+        object_stores: &[protobuf::ObjectStoreOptions],
+    ) -> Result<Arc<RuntimeEnv>, BallistaError> {
+        self.object_store_factories.job_runtime(
+            &self.runtime,
+            &ObjectStoreCredentials::from_proto(object_stores),
+        )
+    }
+
+    /// `plan` with its Parquet scans shared with the other tasks, unless the job of the
+    /// task reads them through object stores of its own
+    pub fn share_scans(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        object_stores: &[protobuf::ObjectStoreOptions],
+    ) -> Result<Arc<dyn ExecutionPlan>, BallistaError> {
+        match &self.scan_cache {
+            Some(cache) if object_stores.is_empty() => Ok(cache.share_scans(plan)?),
+            _ => Ok(plan),
+        }
+    }
+
+    /// Key the shuffle files of a job are encrypted with, `None` if they are not
+    /// encrypted
+    pub fn encryption_key(&self, job_id: &str) -> Option<EncryptionKey> {
+        self.encryption_keys.lock().get(job_id).cloned()
+    }
+
+    /// Serve the shuffle files of job `job_id` kept in `work_dir` encrypted with `key`,
+    /// such as the copies of the files of other executors. Fails if the job already has
+    /// another key, which is kept so that its files can still be read.
+    pub fn add_encryption_key(
+        &self,
+        job_id: &str,
+        key: EncryptionKey,
+    ) -> Result<(), BallistaError> {
+        let mut keys = self.encryption_keys.lock();
+        match keys.get(job_id) {
+            Some(existing) if *existing != key => Err(BallistaError::General(format!(
+                "Job {} already has another encryption key",
+                job_id
+            ))),
+            Some(_) => Ok(()),
+            None => {
+                keys.insert(job_id.to_owned(), key);
+                Ok(())
+            }
+        }
+    }
+
+    /// Path to keep the copy of shuffle file `file_name` of another executor at, which
+    /// holds partition `partition_id` of stage `stage_id` of job `job_id`. Its directory
+    /// is created if missing.
+    pub fn replica_path(
+        &self,
+        job_id: &str,
+        stage_id: usize,
+        partition_id: usize,
+        file_name: &str,
+    ) -> Result<PathBuf, BallistaError> {
+        // A bare file name keeps the copy within the directory of the partition
+        if Path::new(file_name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            != Some(file_name)
+        {
+            return Err(BallistaError::General(format!(
+                "Invalid shuffle file name {}",
+                file_name
+            )));
+        }
+        let dir = self
+            .job_dir(job_id)?
+            .join(stage_id.to_string())
+            .join(partition_id.to_string());
+        std::fs::create_dir_all(&dir)?;
+        Ok(dir.join(format!("replica-{}", file_name)))
+    }
+
+    /// Keep the shuffle files of job `job_id` until they are removed with
+    /// [Executor::remove_job_data], instead of cleaning them up once they expire
+    pub fn pin_job(&self, job_id: &str) -> Result<(), BallistaError> {
+        let job_dir = self.job_dir(job_id)?;
+        std::fs::create_dir_all(&job_dir)?;
+        std::fs::File::create(job_dir.join(PINNED_FILE))?;
+        Ok(())
+    }
+
+    /// Delete the shuffle files of job `job_id`, whether they are pinned or not
+    pub fn remove_job_data(&self, job_id: &str) -> Result<(), BallistaError> {
+        let job_dir = self.job_dir(job_id)?;
+        if job_dir.exists() {
+            std::fs::remove_dir_all(job_dir)?;
+        }
+        if let Some(results) = &self.memory_results {
+            results.remove_job(job_id);
+        }
+        self.encryption_keys.lock().remove(job_id);
+        Ok(())
+    }
+
+    fn job_dir(&self, job_id: &str) -> Result<PathBuf, BallistaError> {
+        // Job IDs are alphanumeric, which keeps the directory within `work_dir`
+        if job_id.is_empty() || !job_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(BallistaError::General(format!("Invalid job ID {}", job_id)));
+        }
+        Ok(Path::new(&self.work_dir).join(job_id))
+    }
+
+    /// Forget the keys of jobs whose shuffle files were removed from `work_dir`
+    pub fn remove_stale_encryption_keys(&self) {
+        let work_dir = Path::new(&self.work_dir);
+        self.encryption_keys
+            .lock()
+            .retain(|job_id, _| work_dir.join(job_id).exists());
+    }
+}
+
+/// Keep the batches of `stream`, partition `partition_id` of the final stage of a job,
+/// in `results` at `path`. The partition is written to `data.arrow` in `dir` instead if
+/// it does not fit in `results`.
+async fn write_in_memory(
+    results: &Arc<MemoryResults>,
+    partition_id: usize,
+    path: String,
+    dir: &Path,
+    mut stream: SendableRecordBatchStream,
+    encryption_key: Option<&EncryptionKey>,
+) -> Result<protobuf::ShuffleWritePartition, BallistaError> {
+    let schema = stream.schema();
+    let mut batches = vec![];
+    let mut reservation = results.reservation();
+    while let Some(batch) = stream.next().await {
+        let batch = batch?;
+        if !reservation.grow(batch_bytes(&batch)) {
+            drop(reservation);
+            // Write the batches computed so far followed by the rest of the partition
+            let computed = futures::stream::iter(batches.into_iter().chain(Some(batch)))
+                .map(Ok)
+                .chain(stream);
+            let mut stream: SendableRecordBatchStream =
+                Box::pin(RecordBatchStreamAdapter::new(schema, computed));
+            std::fs::create_dir_all(dir)?;
+            let path = dir.join("data.arrow");
+            let path = path.to_str().ok_or_else(|| {
+                BallistaError::General(format!("Invalid partition path {:?}", path))
+            })?;
+            let stats =
+                write_stream_to_disk(&mut stream, path, encryption_key, &Time::new())
+                    .await?;
+            return Ok(protobuf::ShuffleWritePartition {
+                partition_id: partition_id as u64,
+                path: path.to_owned(),
+                num_batches: stats.num_batches().unwrap_or(0),
+                num_rows: stats.num_rows().unwrap_or(0),
+                num_bytes: stats.num_bytes().unwrap_or(0),
+            });
+        }
+        batches.push(batch);
+    }
+
+    let num_batches = batches.len() as u64;
+    let num_rows = batches.iter().map(|batch| batch.num_rows() as u64).sum();
+    let bytes = reservation.bytes();
+    results.insert(path.clone(), schema, batches, reservation);
+    Ok(protobuf::ShuffleWritePartition {
+        partition_id: partition_id as u64,
+        path,
+        num_batches,
+        num_rows,
+        num_bytes: bytes as u64,
+    })
+}
+
+/// The Bloom filter built by the BloomFilterBuildExec of `plan`, once it ran, if any
+fn built_bloom_filter(plan: &dyn ExecutionPlan) -> Option<Vec<u8>> {
+    match plan.as_any().downcast_ref::<BloomFilterBuildExec>() {
+        Some(build) => Some(build.bloom_filter().to_bytes()),
+        None => plan
+            .children()
+            .iter()
+            .find_map(|child| built_bloom_filter(child.as_ref())),
+    }
+}
+
+/// Add the metrics of the operators of `plan` to `metrics`, depth first from its root,
+/// as the scheduler matches them with the operators of the plan of the stage
+fn collect_operator_metrics(
+    plan: &dyn ExecutionPlan,
+    metrics: &mut Vec<protobuf::OperatorMetrics>,
+) {
+    let named = plan
+        .metrics()
+        .map(|set| {
+            set.aggregate_by_name()
+                .iter()
+                .filter(|metric| {
+                    !matches!(
+                        metric.value(),
+                        MetricValue::StartTimestamp(_) | MetricValue::EndTimestamp(_)
+                    )
+                })
+                .map(|metric| protobuf::NamedMetric {
+                    name: metric.value().name().to_owned(),
+                    value: metric.value().as_usize() as u64,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    metrics.push(protobuf::OperatorMetrics { metrics: named });
+    for child in plan.children() {
+        collect_operator_metrics(child.as_ref(), metrics);
+    }
+}
+
+/// The total of the metrics named `name` of the operators of `plan`, such as the
+/// `bytes_scanned` of its scans or the `spilled_bytes` of its sorts. Plans are decoded
+/// for every task, so all their metrics are the task's.
+fn metric_total(plan: &dyn ExecutionPlan, name: &str) -> u64 {
+    let total: usize = plan.metrics().map_or(0, |metrics| {
+        metrics
+            .iter()
+            .filter(|metric| metric.value().name() == name)
+            .map(|metric| metric.value().as_usize())
+            .sum()
+    });
+    total as u64
+        + plan
+            .children()
+            .iter()
+            .map(|child| metric_total(child.as_ref(), name))
+            .sum::<u64>()
 }
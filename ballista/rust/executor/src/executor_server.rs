@@ -15,17 +15,24 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::cmp::max;
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
 
-use log::{debug, error, info};
-use tonic::transport::{Channel, Server};
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use tonic::transport::{Channel, NamedService};
 use tonic::{Request, Response, Status};
 
+use ballista_core::config::{BALLISTA_CACHE_TABLE, BALLISTA_JOB_IN_MEMORY_RESULTS};
+use ballista_core::encryption::EncryptionKey;
 use ballista_core::error::BallistaError;
+use ballista_core::grpc_services;
+use ballista_core::logging::{with_log_context, LogContext};
+use ballista_core::protocol::{check_compatible_version, ProtocolInfo, PROTOCOL_VERSION};
 use ballista_core::serde::physical_plan::from_proto::parse_protobuf_hash_partitioning;
 use ballista_core::serde::protobuf::executor_grpc_server::{
     ExecutorGrpc, ExecutorGrpcServer,
@@ -33,32 +40,45 @@ use ballista_core::serde::protobuf::executor_grpc_server::{
 use ballista_core::serde::protobuf::executor_registration::OptionalHost;
 use ballista_core::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
 use ballista_core::serde::protobuf::{
-    HeartBeatParams, LaunchTaskParams, LaunchTaskResult, RegisterExecutorParams,
-    StopExecutorParams, StopExecutorResult, TaskDefinition, TaskStatus,
-    UpdateTaskStatusParams,
+    self, HeartBeatParams, LaunchTaskParams, LaunchTaskResult, RegisterExecutorParams,
+    RemoveJobDataParams, RemoveJobDataResult, SchedulerShutdownParams,
+    SchedulerShutdownResult, StopExecutorParams, StopExecutorResult, TaskDefinition,
+    TaskStatus, UpdateTaskStatusParams,
 };
 use ballista_core::serde::scheduler::ExecutorState;
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
+use ballista_core::telemetry;
+use ballista_core::tls::TlsOptions;
 use datafusion::execution::context::TaskContext;
 use datafusion::physical_plan::ExecutionPlan;
 use datafusion_proto::logical_plan::AsLogicalPlan;
 use tokio::sync::mpsc::error::TryRecvError;
 
-use crate::as_task_status;
-use crate::cpu_bound_executor::DedicatedExecutor;
+use crate::cpu_bound_executor::TaskRuntimes;
 use crate::executor::Executor;
-
+use crate::health;
+use crate::shuffle_replication::replicate_output;
+use crate::task_interceptor::InterceptedTask;
+use crate::{as_task_status, run_killable, run_with_timeout, task_timeout};
+
+/// Start the executor in push-based scheduling. It registers with `scheduler`, and sends
+/// its heartbeats and the statuses of its tasks through the control plane connection
+/// `control`. Fails if the grpc service can not be set up, e.g. because of an invalid
+/// TLS certificate or key, or if the registration is refused.
 pub async fn startup<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
     mut scheduler: SchedulerGrpcClient<Channel>,
+    control: SchedulerGrpcClient<Channel>,
     executor: Arc<Executor>,
     codec: BallistaCodec<T, U>,
-) {
+    tls: TlsOptions,
+) -> Result<(), BallistaError> {
     // TODO make the buffer size configurable
     let (tx_task, rx_task) = mpsc::channel::<TaskDefinition>(1000);
     let (tx_task_status, rx_task_status) = mpsc::channel::<TaskStatus>(1000);
 
     let executor_server = ExecutorServer::new(
         scheduler.clone(),
+        control,
         executor.clone(),
         ExecutorEnv {
             tx_task,
@@ -80,25 +100,43 @@ pub async fn startup<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
                 .unwrap_or_else(|| String::from("0.0.0.0")),
             executor_meta.grpc_port
         );
-        let addr = addr.parse().unwrap();
+        let addr = addr.parse().map_err(|e| {
+            BallistaError::General(format!(
+                "Invalid executor grpc address {}: {}",
+                addr, e
+            ))
+        })?;
         info!("Setup executor grpc service for {:?}", addr);
 
         let server = ExecutorGrpcServer::new(executor_server.clone());
-        let grpc_server_future = Server::builder().add_service(server).serve(addr);
+        let (health_reporter, health_service) = grpc_services::health_service();
+        tokio::spawn(health::report_grpc_health(
+            executor.clone(),
+            health_reporter,
+            vec![<ExecutorGrpcServer<ExecutorServer<T, U>> as NamedService>::NAME],
+        ));
+        let reflection_service = grpc_services::reflection_service(&[])?;
+        let grpc_server_future = tls
+            .server_builder()
+            .map_err(|e| {
+                BallistaError::General(format!(
+                    "Could not set up TLS for executor grpc service: {}",
+                    e
+                ))
+            })?
+            .add_service(server)
+            .add_service(health_service)
+            .add_service(reflection_service)
+            .serve(addr);
         tokio::spawn(async move { grpc_server_future.await });
     }
 
     let executor_server = Arc::new(executor_server);
 
     // 2. Do executor registration
-    match register_executor(&mut scheduler, executor.clone()).await {
-        Ok(_) => {
-            info!("Executor registration succeed");
-        }
-        Err(error) => {
-            panic!("Executor registration failed due to: {}", error);
-        }
-    };
+    register_executor(&mut scheduler, executor.clone()).await?;
+    info!("Executor registration succeed");
+    executor.set_registered(true);
 
     // 3. Start Heartbeater
     {
@@ -111,6 +149,7 @@ pub async fn startup<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
         let task_runner_pool = TaskRunnerPool::new(executor_server.clone());
         task_runner_pool.start(rx_task, rx_task_status).await;
     }
+    Ok(())
 }
 
 #[allow(clippy::clone_on_copy)]
@@ -122,8 +161,13 @@ async fn register_executor(
         .register_executor(RegisterExecutorParams {
             metadata: Some(executor.metadata.clone()),
         })
-        .await?;
-    if result.into_inner().success {
+        .await?
+        .into_inner();
+    if result.success {
+        let protocol = ProtocolInfo::from(result.protocol);
+        info!("Scheduler speaks protocol version {}", protocol.version);
+        protocol.check_compatible()?;
+        executor.set_epoch(result.epoch);
         Ok(())
     } else {
         Err(BallistaError::General(
@@ -137,15 +181,19 @@ pub struct ExecutorServer<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPl
     _start_time: u128,
     executor: Arc<Executor>,
     scheduler: SchedulerGrpcClient<Channel>,
+    /// Connection to the scheduler sending the heartbeats and task statuses
+    control: SchedulerGrpcClient<Channel>,
     executor_env: ExecutorEnv,
     codec: BallistaCodec<T, U>,
+    /// State of the executor as last received by the scheduler
+    reported_state: Arc<Mutex<Option<protobuf::ExecutorState>>>,
 }
 
 #[derive(Clone)]
 struct ExecutorEnv {
-    /// Receive `TaskDefinition` from rpc then send to CPU bound tasks pool `dedicated_executor`.
+    /// Receive `TaskDefinition` from rpc then send to CPU bound tasks pool `task_runtimes`.
     tx_task: mpsc::Sender<TaskDefinition>,
-    /// Receive `TaskStatus` from CPU bound tasks pool `dedicated_executor` then use rpc send back to scheduler.
+    /// Receive `TaskStatus` from CPU bound tasks pool `task_runtimes` then use rpc send back to scheduler.
     tx_task_status: mpsc::Sender<TaskStatus>,
 }
 
@@ -154,6 +202,7 @@ unsafe impl Sync for ExecutorEnv {}
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorServer<T, U> {
     fn new(
         scheduler: SchedulerGrpcClient<Channel>,
+        control: SchedulerGrpcClient<Channel>,
         executor: Arc<Executor>,
         executor_env: ExecutorEnv,
         codec: BallistaCodec<T, U>,
@@ -165,37 +214,102 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorServer<T,
                 .as_millis(),
             executor,
             scheduler,
+            control,
             executor_env,
             codec,
+            reported_state: Arc::new(Mutex::new(None)),
         }
     }
 
-    async fn heartbeat(&self) {
-        // TODO Error handling
-        self.scheduler
+    /// Send a heartbeat to the scheduler, with the metrics which changed since the
+    /// previous one. The executor registers again if the scheduler considered it lost.
+    /// Returns the lease the scheduler granted the executor, if it answered.
+    async fn heartbeat(&self) -> Option<Duration> {
+        let state: protobuf::ExecutorState = self.get_executor_state().into();
+        let changed = changed_metrics(self.reported_state.lock().as_ref(), &state);
+        let result = self
+            .control
             .clone()
             .heart_beat_from_executor(HeartBeatParams {
                 executor_id: self.executor.metadata.id.clone(),
-                state: Some(self.get_executor_state().into()),
+                state: changed,
+                epoch: self.executor.epoch(),
+                task_progress: self.executor.running_tasks().progress(),
             })
-            .await
-            .unwrap();
+            .await;
+        if let Ok(response) = &result {
+            let killed = self
+                .executor
+                .running_tasks()
+                .kill(&response.get_ref().kill_tasks);
+            if killed > 0 {
+                warn!("Killed {} tasks which stopped making progress", killed);
+            }
+        }
+        // Whether the scheduler received the state, and whether the executor is
+        // registered
+        let lease = match &result {
+            Ok(response) if response.get_ref().lease_millis > 0 => {
+                Some(Duration::from_millis(response.get_ref().lease_millis))
+            }
+            _ => None,
+        };
+        let (received, registered) = match result {
+            Ok(response) if response.get_ref().reregister => {
+                warn!("Scheduler considered the executor lost, registering again");
+                match register_executor(
+                    &mut self.scheduler.clone(),
+                    self.executor.clone(),
+                )
+                .await
+                {
+                    Ok(_) => (false, true),
+                    Err(e) => {
+                        warn!("Failed to register the executor again: {}", e);
+                        (false, false)
+                    }
+                }
+            }
+            Ok(_) => (true, true),
+            Err(e) => {
+                warn!("Failed to send heartbeat to the scheduler: {}", e);
+                (false, false)
+            }
+        };
+        // The whole state is sent again after a failure, in case the scheduler restarted
+        *self.reported_state.lock() = received.then(|| state);
+        self.executor.set_registered(registered);
+        lease
     }
 
     async fn run_task(&self, task: TaskDefinition) -> Result<(), BallistaError> {
         let task_id = task.task_id.unwrap();
+        let attempt = task.attempt;
         let task_id_log = format!(
             "{}/{}/{}",
             task_id.job_id, task_id.stage_id, task_id.partition_id
         );
         info!("Start to run task {}", task_id_log);
 
-        let runtime = self.executor.runtime.clone();
+        let mut span = telemetry::start_task_span(
+            &telemetry::extract_context(&task.trace_context),
+            &task_id,
+            &self.executor.metadata.id,
+        );
+
+        let runtime = self.executor.task_runtime(&task.object_stores)?;
         let session_id = task.session_id;
         let mut task_props = HashMap::new();
         for kv_pair in task.props {
             task_props.insert(kv_pair.key, kv_pair.value);
         }
+        // The output of the jobs of cached tables is kept until the scheduler removes it
+        let in_memory = task_props.contains_key(BALLISTA_JOB_IN_MEMORY_RESULTS);
+        let timeout = task_timeout(&task_props);
+        let props = task_props.clone();
+        if task_props.contains_key(BALLISTA_CACHE_TABLE) {
+            self.executor.pin_job(&task_id.job_id)?;
+        }
 
         let mut task_scalar_functions = HashMap::new();
         let mut task_aggregate_functions = HashMap::new();
@@ -225,6 +339,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorServer<T,
                     self.codec.physical_extension_codec(),
                 )
             })?;
+        let plan = self.executor.share_scans(plan, &task.object_stores)?;
 
         let shuffle_output_partitioning = parse_protobuf_hash_partitioning(
             task.output_partitioning.as_ref(),
@@ -232,22 +347,72 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorServer<T,
             plan.schema().as_ref(),
         )?;
 
-        let execution_result = self
-            .executor
-            .execute_shuffle_write(
-                task_id.job_id.clone(),
+        let encryption_key = EncryptionKey::from_proto(&task.encryption_key)?;
+        let replica = task.replica;
+        let intercepted = InterceptedTask {
+            task_id: task_id.clone(),
+            attempt,
+            plan: plan.clone(),
+            props,
+        };
+        let (_running, registration) =
+            self.executor
+                .running_tasks()
+                .register(&task_id, attempt, plan.clone());
+
+        let log_context = LogContext::task(
+            task_id.job_id.clone(),
+            task_id.stage_id as usize,
+            task_id.partition_id as usize,
+        );
+        let execution_result = with_log_context(
+            log_context,
+            self.executor.intercept(
+                &intercepted,
+                run_killable(
+                    registration,
+                    run_with_timeout(
+                        timeout,
+                        self.executor.execute_shuffle_write(
+                            task_id.job_id.clone(),
+                            task_id.stage_id as usize,
+                            task_id.partition_id as usize,
+                            plan,
+                            task_context,
+                            shuffle_output_partitioning,
+                            encryption_key,
+                            in_memory,
+                        ),
+                    ),
+                ),
+            ),
+        )
+        .await;
+        let execution_result = match execution_result {
+            Ok(output) => Ok(replicate_output(
+                &self.executor,
+                &task_id.job_id,
                 task_id.stage_id as usize,
-                task_id.partition_id as usize,
-                plan,
-                task_context,
-                shuffle_output_partitioning,
+                output,
+                replica,
             )
-            .await;
+            .await),
+            Err(e) => Err(e),
+        };
         info!("Done with task {}", task_id_log);
         debug!("Statistics: {:?}", execution_result);
+        telemetry::end_span(
+            &mut span,
+            execution_result
+                .as_ref()
+                .err()
+                .map(|e| e.to_string())
+                .as_deref(),
+        );
 
         let executor_id = &self.executor.metadata.id;
-        let task_status = as_task_status(execution_result, executor_id.clone(), task_id);
+        let task_status =
+            as_task_status(execution_result, executor_id.clone(), task_id, attempt);
 
         let task_status_sender = self.executor_env.tx_task_status.clone();
         task_status_sender.send(task_status).await.unwrap();
@@ -262,6 +427,33 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorServer<T,
     }
 }
 
+/// The metrics of `state` which differ from those of the `reported` state, `None` if
+/// none changed
+fn changed_metrics(
+    reported: Option<&protobuf::ExecutorState>,
+    state: &protobuf::ExecutorState,
+) -> Option<protobuf::ExecutorState> {
+    let metrics: Vec<_> = match reported {
+        Some(reported) => state
+            .metrics
+            .iter()
+            .filter(|metric| !reported.metrics.contains(metric))
+            .cloned()
+            .collect(),
+        None => return Some(state.clone()),
+    };
+    if metrics.is_empty() {
+        None
+    } else {
+        Some(protobuf::ExecutorState { metrics })
+    }
+}
+
+/// Lease assumed until the scheduler grants one
+const DEFAULT_LEASE: Duration = Duration::from_secs(60);
+
+const MIN_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
 struct Heartbeater<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> {
     executor_server: Arc<ExecutorServer<T, U>>,
 }
@@ -275,9 +467,13 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> Heartbeater<T, U>
         let executor_server = self.executor_server.clone();
         tokio::spawn(async move {
             info!("Starting heartbeater to send heartbeat the scheduler periodically");
+            let mut lease = DEFAULT_LEASE;
             loop {
-                executor_server.heartbeat().await;
-                tokio::time::sleep(Duration::from_millis(60000)).await;
+                if let Some(granted) = executor_server.heartbeat().await {
+                    lease = granted;
+                }
+                // Leave room for a couple of lost heartbeats before the lease is over
+                tokio::time::sleep(max(lease / 3, MIN_HEARTBEAT_INTERVAL)).await;
             }
         });
     }
@@ -335,11 +531,13 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskRunnerPool<T,
                 }
 
                 if let Err(e) = executor_server
-                    .scheduler
+                    .control
                     .clone()
                     .update_task_status(UpdateTaskStatusParams {
                         executor_id: executor_server.executor.metadata.id.clone(),
                         task_status: tasks_status.clone(),
+                        protocol_version: PROTOCOL_VERSION,
+                        epoch: executor_server.executor.epoch(),
                     })
                     .await
                 {
@@ -352,12 +550,16 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskRunnerPool<T,
         let executor_server = self.executor_server.clone();
         tokio::spawn(async move {
             info!("Starting the task runner pool");
-            // Use a dedicated executor for CPU bound tasks so that the main tokio
+            // Use dedicated runtimes for CPU bound tasks so that the main tokio
             // executor can still answer requests even when under load
-            let dedicated_executor = DedicatedExecutor::new(
-                "task_runner",
-                executor_server.executor.concurrent_tasks,
-            );
+            let task_runtimes = match executor_server.executor.task_runtimes() {
+                Some(task_runtimes) => task_runtimes.clone(),
+                None => TaskRuntimes::new(
+                    "task_runner",
+                    executor_server.executor.concurrent_tasks,
+                    1,
+                ),
+            };
             loop {
                 if let Some(task) = rx_task.recv().await {
                     if let Some(task_id) = &task.task_id {
@@ -368,7 +570,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskRunnerPool<T,
                         info!("Received task {:?}", &task_id_log);
 
                         let server = executor_server.clone();
-                        dedicated_executor.spawn(async move {
+                        task_runtimes.spawn_detached(async move {
                             server.run_task(task).await.unwrap_or_else(|e| {
                                 error!(
                                     "Fail to run the task {:?} due to {:?}",
@@ -396,7 +598,12 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorGrpc
         &self,
         request: Request<LaunchTaskParams>,
     ) -> Result<Response<LaunchTaskResult>, Status> {
-        let tasks = request.into_inner().task;
+        let LaunchTaskParams {
+            task: tasks,
+            protocol_version,
+        } = request.into_inner();
+        check_compatible_version(protocol_version)
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
         let task_sender = self.executor_env.tx_task.clone();
         for task in tasks {
             task_sender.send(task).await.unwrap();
@@ -410,4 +617,28 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> ExecutorGrpc
     ) -> Result<Response<StopExecutorResult>, Status> {
         todo!()
     }
+
+    async fn remove_job_data(
+        &self,
+        request: Request<RemoveJobDataParams>,
+    ) -> Result<Response<RemoveJobDataResult>, Status> {
+        let job_id = request.into_inner().job_id;
+        info!("Removing the data of job {}", job_id);
+        self.executor.remove_job_data(&job_id).map_err(|e| {
+            Status::internal(format!(
+                "Could not remove the data of job {}: {}",
+                job_id, e
+            ))
+        })?;
+        Ok(Response::new(RemoveJobDataResult {}))
+    }
+
+    async fn scheduler_shutdown(
+        &self,
+        _request: Request<SchedulerShutdownParams>,
+    ) -> Result<Response<SchedulerShutdownResult>, Status> {
+        warn!("Scheduler is shutting down, waiting for it to be available again");
+        self.executor.set_registered(false);
+        Ok(Response::new(SchedulerShutdownResult {}))
+    }
 }
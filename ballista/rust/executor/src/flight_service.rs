@@ -17,15 +17,23 @@
 
 //! Implementation of the Apache Arrow Flight protocol that wraps an executor.
 
-use std::fs::File;
+use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::sync::Arc;
 
 use crate::executor::Executor;
+use crate::memory_results::{path_job_id, MemoryResults, ServedPartition};
+use crate::scan_cache::batch_bytes;
+use crate::shuffle_replication::{receive_replica, ReplicaSenders};
 use arrow_flight::SchemaAsIpc;
+use ballista_core::client::end_of_partition;
+use ballista_core::encryption::{self, EncryptionKey};
 use ballista_core::error::BallistaError;
+use ballista_core::flight_transfer::{flight_transfer_options, split_batch};
 use ballista_core::serde::decode_protobuf;
 use ballista_core::serde::scheduler::Action as BallistaAction;
+use ballista_core::shuffle_file::{self, StreamMessageReader};
+use ballista_core::utils::write_stream_to_disk;
 
 use arrow_flight::{
     flight_service_server::FlightService, Action, ActionType, Criteria, Empty,
@@ -33,12 +41,18 @@ use arrow_flight::{
     PutResult, SchemaResult, Ticket,
 };
 use datafusion::arrow::{
-    error::ArrowError, ipc::reader::FileReader, ipc::writer::IpcWriteOptions,
+    datatypes::SchemaRef,
+    error::{ArrowError, Result as ArrowResult},
+    ipc::reader::FileReader,
+    ipc::writer::{DictionaryTracker, IpcDataGenerator, IpcWriteOptions},
     record_batch::RecordBatch,
 };
+use datafusion::physical_plan::memory::MemoryStream;
+use datafusion::physical_plan::metrics::Time;
+use datafusion::physical_plan::SendableRecordBatchStream;
 use futures::{Stream, StreamExt};
 use log::{info, warn};
-use std::io::{Read, Seek};
+use std::io::Read;
 use tokio::sync::mpsc::channel;
 use tokio::{
     sync::mpsc::{Receiver, Sender},
@@ -46,6 +60,7 @@ use tokio::{
 };
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
 
 type FlightDataSender = Sender<Result<FlightData, Status>>;
 type FlightDataReceiver = Receiver<Result<FlightData, Status>>;
@@ -54,12 +69,205 @@ type FlightDataReceiver = Receiver<Result<FlightData, Status>>;
 #[derive(Clone)]
 pub struct BallistaFlightService {
     /// Executor
-    _executor: Arc<Executor>,
+    executor: Arc<Executor>,
+    /// Peers copies of shuffle partitions are accepted from
+    replica_senders: ReplicaSenders,
+    /// Largest copy of a shuffle partition accepted, in bytes
+    max_replica_bytes: u64,
 }
 
 impl BallistaFlightService {
-    pub fn new(_executor: Arc<Executor>) -> Self {
-        Self { _executor }
+    pub fn new(executor: Arc<Executor>) -> Self {
+        Self {
+            executor,
+            replica_senders: ReplicaSenders::default(),
+            max_replica_bytes: 0,
+        }
+    }
+
+    /// Accept copies of shuffle partitions of up to `max_bytes` bytes from `senders`,
+    /// instead of refusing them
+    pub fn with_replicas(mut self, senders: ReplicaSenders, max_bytes: u64) -> Self {
+        self.replica_senders = senders;
+        self.max_replica_bytes = max_bytes;
+        self
+    }
+
+    /// Send the partition asked for by `request` on an exchange to `tx`, followed by
+    /// the message marking its end
+    async fn exchange_partition(
+        &self,
+        request: &FlightData,
+        tx: &FlightDataSender,
+    ) -> Result<(), Status> {
+        let action =
+            decode_protobuf(&request.app_metadata).map_err(|e| from_ballista_err(&e))?;
+        let mut rx = self.serve_partition(&action).await?;
+        while let Some(data) = rx.recv().await {
+            send_response(tx, Ok(data?)).await?;
+        }
+        send_response(tx, Ok(end_of_partition())).await
+    }
+
+    /// Send the partition fetched by `action` to the returned channel, once the shuffle
+    /// serving limits of the executor let it start and no faster than they allow
+    async fn serve_partition(
+        &self,
+        action: &BallistaAction,
+    ) -> Result<FlightDataReceiver, Status> {
+        let limits = self.executor.shuffle_serving_limits().clone();
+        if !limits.is_limited() {
+            return self.fetch_partition(action);
+        }
+        let BallistaAction::FetchPartition { priority, .. } = action;
+        let permit = limits.start_stream(*priority).await;
+        let mut partition = self.fetch_partition(action)?;
+        let (tx, rx): (FlightDataSender, FlightDataReceiver) = channel(2);
+        task::spawn(async move {
+            // The partition counts against the cap until it is sent or the client
+            // stops fetching it
+            let _permit = permit;
+            while let Some(data) = partition.recv().await {
+                if let Ok(data) = &data {
+                    limits
+                        .throttle(data.data_header.len() + data.data_body.len())
+                        .await;
+                }
+                if tx.send(data).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    /// Send the partition fetched by `action` to the returned channel
+    fn fetch_partition(
+        &self,
+        action: &BallistaAction,
+    ) -> Result<FlightDataReceiver, Status> {
+        let BallistaAction::FetchPartition { compression, .. } = action;
+        let options = compression
+            .write_options()
+            .map_err(|e| from_arrow_err(&e))?;
+        match action {
+            BallistaAction::FetchPartition { job_id, path, .. }
+                if MemoryResults::is_memory_path(path) =>
+            {
+                info!("FetchPartition reading {} from memory", &path);
+                // The partition must be of the job of the ticket
+                let partition = Some(job_id.as_str())
+                    .filter(|job_id| path_job_id(path) == Some(job_id))
+                    .and_then(|_| self.executor.serve_memory_results(path))
+                    .ok_or_else(|| {
+                        Status::not_found(format!(
+                            "Partition {} is not kept in memory, it may have expired",
+                            path
+                        ))
+                    })?;
+
+                // The channel holds few messages, so batches are only taken from memory
+                // as fast as the flow control of the connection lets the client take them
+                let (tx, rx): (FlightDataSender, FlightDataReceiver) = channel(2);
+                match partition {
+                    ServedPartition::Buffered {
+                        schema,
+                        batches,
+                        mut permit,
+                    } => {
+                        task::spawn(async move {
+                            let batches = batches.into_iter().map(move |batch| {
+                                permit.release(batch_bytes(&batch));
+                                Ok(batch)
+                            });
+                            if let Err(e) =
+                                stream_flight_data(schema, batches, options, tx).await
+                            {
+                                warn!("Error streaming results: {:?}", e);
+                            }
+                        });
+                    }
+                    ServedPartition::Overflowing {
+                        schema,
+                        batches,
+                        job_id,
+                    } => {
+                        let spill_path = spill_path(self.executor.work_dir(), &job_id)?;
+                        info!(
+                            "FetchPartition spilling {} to {:?} as the partitions being \
+                            sent fill the serving capacity",
+                            &path, spill_path
+                        );
+                        let encryption_key = self.executor.encryption_key(&job_id);
+                        task::spawn(async move {
+                            if let Err(e) = stream_spilled(
+                                schema,
+                                batches,
+                                &spill_path,
+                                encryption_key,
+                                options,
+                                tx.clone(),
+                            )
+                            .await
+                            {
+                                warn!("Error streaming spilled results: {:?}", e);
+                                let _ = tx.send(Err(e)).await;
+                            }
+                            if let Err(e) = std::fs::remove_file(&spill_path) {
+                                warn!(
+                                    "Failed to remove spilled results {:?}: {:?}",
+                                    spill_path, e
+                                );
+                            }
+                        });
+                    }
+                }
+
+                Ok(rx)
+            }
+            BallistaAction::FetchPartition { job_id, path, .. } => {
+                info!("FetchPartition reading {}", &path);
+                let encryption_key = self.executor.encryption_key(job_id);
+                let mut file = encryption::open_file(&path, encryption_key.as_ref())
+                    .map_err(|e| {
+                        BallistaError::General(format!(
+                            "Failed to open partition file at {}: {:?}",
+                            path, e
+                        ))
+                    })
+                    .map_err(|e| from_ballista_err(&e))?;
+                let is_stream_file = shuffle_file::is_stream_file(&mut file)
+                    .map_err(|e| from_ballista_err(&e))?;
+
+                let (tx, rx): (FlightDataSender, FlightDataReceiver) = channel(2);
+
+                if is_stream_file {
+                    // Partitions with dictionary-encoded fields are sent as written,
+                    // uncompressed
+                    let reader = StreamMessageReader::new(file);
+                    task::spawn(async move {
+                        if let Err(e) = stream_ipc_messages(reader, tx).await {
+                            warn!("Error streaming results: {:?}", e);
+                        }
+                    });
+                } else {
+                    let reader = FileReader::try_new(file, None)
+                        .map_err(|e| from_arrow_err(&e))?;
+
+                    // Arrow IPC reader does not implement Sync + Send so we need to use a channel
+                    // to communicate
+                    task::spawn(async move {
+                        if let Err(e) =
+                            stream_flight_data(reader.schema(), reader, options, tx).await
+                        {
+                            warn!("Error streaming results: {:?}", e);
+                        }
+                    });
+                }
+
+                Ok(rx)
+            }
+        }
     }
 }
 
@@ -85,35 +293,10 @@ impl FlightService for BallistaFlightService {
         let action =
             decode_protobuf(&ticket.ticket).map_err(|e| from_ballista_err(&e))?;
 
-        match &action {
-            BallistaAction::FetchPartition { path, .. } => {
-                info!("FetchPartition reading {}", &path);
-                let file = File::open(&path)
-                    .map_err(|e| {
-                        BallistaError::General(format!(
-                            "Failed to open partition file at {}: {:?}",
-                            path, e
-                        ))
-                    })
-                    .map_err(|e| from_ballista_err(&e))?;
-                let reader =
-                    FileReader::try_new(file, None).map_err(|e| from_arrow_err(&e))?;
-
-                let (tx, rx): (FlightDataSender, FlightDataReceiver) = channel(2);
-
-                // Arrow IPC reader does not implement Sync + Send so we need to use a channel
-                // to communicate
-                task::spawn(async move {
-                    if let Err(e) = stream_flight_data(reader, tx).await {
-                        warn!("Error streaming results: {:?}", e);
-                    }
-                });
-
-                Ok(Response::new(
-                    Box::pin(ReceiverStream::new(rx)) as Self::DoGetStream
-                ))
-            }
-        }
+        let rx = self.serve_partition(&action).await?;
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::DoGetStream
+        ))
     }
 
     async fn get_schema(
@@ -148,13 +331,20 @@ impl FlightService for BallistaFlightService {
         &self,
         request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoPutStream>, Status> {
-        let mut request = request.into_inner();
-
-        while let Some(data) = request.next().await {
-            let _data = data?;
-        }
-
-        Err(Status::unimplemented("do_put"))
+        // Copies of shuffle partitions written by other executors are the only uploads
+        self.replica_senders.authenticate(&request)?;
+        let path =
+            receive_replica(&self.executor, request.into_inner(), self.max_replica_bytes)
+                .await
+                .map_err(|e| from_ballista_err(&e))?;
+        info!("Received copy of shuffle partition {}", path);
+
+        let result = PutResult {
+            app_metadata: path.into_bytes(),
+        };
+        Ok(Response::new(
+            Box::pin(futures::stream::iter(vec![Ok(result)])) as Self::DoPutStream,
+        ))
     }
 
     async fn do_action(
@@ -178,55 +368,140 @@ impl FlightService for BallistaFlightService {
 
     async fn do_exchange(
         &self,
-        _request: Request<Streaming<FlightData>>,
+        request: Request<Streaming<FlightData>>,
     ) -> Result<Response<Self::DoExchangeStream>, Status> {
-        Err(Status::unimplemented("do_exchange"))
+        let mut requests = request.into_inner();
+        let service = self.clone();
+        let (tx, rx): (FlightDataSender, FlightDataReceiver) = channel(2);
+        // Each request of the exchange fetches a partition, sent once the previous one
+        // is, until the client closes the exchange
+        task::spawn(async move {
+            while let Some(request) = requests.next().await {
+                let result = match request {
+                    Ok(request) => service.exchange_partition(&request, &tx).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    warn!("Error exchanging partitions: {:?}", e);
+                    let _ = tx.send(Err(e)).await;
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(
+            Box::pin(ReceiverStream::new(rx)) as Self::DoExchangeStream
+        ))
     }
 }
 
-/// Convert a single RecordBatch into an iterator of FlightData (containing
-/// dictionaries and batches)
-fn create_flight_iter(
-    batch: &RecordBatch,
-    options: &IpcWriteOptions,
-) -> Box<dyn Iterator<Item = Result<FlightData, Status>>> {
-    let (flight_dictionaries, flight_batch) =
-        arrow_flight::utils::flight_data_from_arrow_batch(batch, options);
-    Box::new(
-        flight_dictionaries
-            .into_iter()
-            .chain(std::iter::once(flight_batch))
-            .map(Ok),
-    )
-}
-
-async fn stream_flight_data<T>(
-    reader: FileReader<T>,
+/// Send `schema` followed by `batches`, written with `options`
+async fn stream_flight_data(
+    schema: SchemaRef,
+    batches: impl Iterator<Item = ArrowResult<RecordBatch>>,
+    options: IpcWriteOptions,
     tx: FlightDataSender,
-) -> Result<(), Status>
-where
-    T: Read + Seek,
-{
-    let options = arrow::ipc::writer::IpcWriteOptions::default();
-    let schema_flight_data = SchemaAsIpc::new(reader.schema().as_ref(), &options).into();
+) -> Result<(), Status> {
+    let schema_flight_data = SchemaAsIpc::new(schema.as_ref(), &options).into();
     send_response(&tx, Ok(schema_flight_data)).await?;
 
+    // The dictionaries of a batch are only sent when they differ from the previous ones
+    let data_gen = IpcDataGenerator::default();
+    let mut dictionary_tracker = DictionaryTracker::new(false);
+    let max_message_size = flight_transfer_options().max_message_size;
     let mut row_count = 0;
-    for batch in reader {
-        if let Ok(x) = &batch {
-            row_count += x.num_rows();
-        }
-        let batch_flight_data: Vec<_> = batch
-            .map(|b| create_flight_iter(&b, &options).collect())
-            .map_err(|e| from_arrow_err(&e))?;
-        for batch in batch_flight_data.into_iter() {
-            send_response(&tx, batch).await?;
+    for batch in batches {
+        let batch = batch.map_err(|e| from_arrow_err(&e))?;
+        row_count += batch.num_rows();
+        for batch in
+            split_batch(&batch, max_message_size).map_err(|e| from_arrow_err(&e))?
+        {
+            let (dictionaries, batch) = data_gen
+                .encoded_batch(&batch, &mut dictionary_tracker, &options)
+                .map_err(|e| from_arrow_err(&e))?;
+            for data in dictionaries.into_iter().chain(std::iter::once(batch)) {
+                send_response(&tx, Ok(data.into())).await?;
+            }
         }
     }
     info!("FetchPartition streamed {} rows", row_count);
     Ok(())
 }
 
+/// A new file to spill a partition of job `job_id` to, in the directory of the job in
+/// `work_dir`. The directory is rejected unless it is a directory of `work_dir` once
+/// links are resolved.
+fn spill_path(work_dir: &str, job_id: &str) -> Result<PathBuf, Status> {
+    let invalid =
+        || Status::internal(format!("Cannot spill to the directory of job {}", job_id));
+    let mut components = Path::new(job_id).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        return Err(invalid());
+    }
+    let io_error = |e: std::io::Error| from_ballista_err(&BallistaError::IoError(e));
+    let work_dir = Path::new(work_dir).canonicalize().map_err(io_error)?;
+    let job_dir = work_dir.join(job_id);
+    std::fs::create_dir_all(&job_dir).map_err(io_error)?;
+    let job_dir = job_dir.canonicalize().map_err(io_error)?;
+    if job_dir.parent() != Some(work_dir.as_path()) {
+        return Err(invalid());
+    }
+    Ok(job_dir.join(format!("served-{}.arrow", Uuid::new_v4())))
+}
+
+/// Write `batches` to a file at `path`, then send them from the file, so that they are
+/// not held in memory while the client fetches them
+async fn stream_spilled(
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+    path: &Path,
+    encryption_key: Option<EncryptionKey>,
+    options: IpcWriteOptions,
+    tx: FlightDataSender,
+) -> Result<(), Status> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| from_ballista_err(&BallistaError::IoError(e)))?;
+    }
+    let path = path
+        .to_str()
+        .ok_or_else(|| Status::internal(format!("Invalid spill path {:?}", path)))?;
+    let mut stream: SendableRecordBatchStream = Box::pin(
+        MemoryStream::try_new(batches, schema, None)
+            .map_err(|e| Status::internal(format!("{:?}", e)))?,
+    );
+    write_stream_to_disk(&mut stream, path, encryption_key.as_ref(), &Time::new())
+        .await
+        .map_err(|e| from_ballista_err(&e))?;
+    drop(stream);
+
+    let mut file = encryption::open_file(path, encryption_key.as_ref())
+        .map_err(|e| from_ballista_err(&BallistaError::IoError(e)))?;
+    if shuffle_file::is_stream_file(&mut file).map_err(|e| from_ballista_err(&e))? {
+        stream_ipc_messages(StreamMessageReader::new(file), tx).await
+    } else {
+        let reader = FileReader::try_new(file, None).map_err(|e| from_arrow_err(&e))?;
+        stream_flight_data(reader.schema(), reader, options, tx).await
+    }
+}
+
+/// Send the messages of a partition written in the IPC stream format
+async fn stream_ipc_messages<T: Read>(
+    reader: StreamMessageReader<T>,
+    tx: FlightDataSender,
+) -> Result<(), Status> {
+    let mut message_count = 0;
+    for message in reader {
+        let message = message.map_err(|e| from_ballista_err(&e))?;
+        send_response(&tx, Ok(message)).await?;
+        message_count += 1;
+    }
+    info!("FetchPartition streamed {} messages", message_count);
+    Ok(())
+}
+
 async fn send_response(
     tx: &FlightDataSender,
     data: Result<FlightData, Status>,
@@ -243,3 +518,29 @@ fn from_arrow_err(e: &ArrowError) -> Status {
 fn from_ballista_err(e: &ballista_core::error::BallistaError) -> Status {
     Status::internal(format!("Ballista Error: {:?}", e))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spill_to_job_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        let work_dir = dir.path().to_str().unwrap();
+        let path = spill_path(work_dir, "job-1").unwrap();
+        assert_eq!(
+            path.parent().unwrap(),
+            dir.path().canonicalize().unwrap().join("job-1")
+        );
+
+        for job_id in ["..", "../job-1", "job-1/..", "/tmp", ""] {
+            assert!(spill_path(work_dir, job_id).is_err(), "{}", job_id);
+        }
+        #[cfg(unix)]
+        {
+            let outside = tempfile::tempdir().unwrap();
+            std::os::unix::fs::symlink(outside.path(), dir.path().join("job-2")).unwrap();
+            assert!(spill_path(work_dir, "job-2").is_err());
+        }
+    }
+}
@@ -0,0 +1,136 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Liveness and readiness HTTP endpoints of the executor, for Kubernetes probes.
+//!
+//! `/health/live` answers as long as the executor is running, and `/health/ready` once
+//! the executor is registered with the scheduler. In pull-based scheduling, the executor
+//! is not ready while its polls for work fail.
+//!
+//! The gRPC servers of the executor report the same readiness through the standard
+//! `grpc.health.v1.Health` service.
+
+use crate::executor::Executor;
+use ballista_core::grpc_services::{HealthReporter, ServingStatus};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    /// `ok`, or why the executor is not ready
+    status: &'static str,
+}
+
+fn reply(status: &'static str, code: StatusCode) -> warp::reply::Response {
+    warp::reply::with_status(warp::reply::json(&HealthResponse { status }), code)
+        .into_response()
+}
+
+/// Routes of the health endpoints of `executor`
+pub fn routes(executor: Arc<Executor>) -> BoxedFilter<(warp::reply::Response,)> {
+    let live = warp::path!("health" / "live")
+        .and(warp::get())
+        .map(|| reply("ok", StatusCode::OK));
+    let ready = warp::path!("health" / "ready")
+        .and(warp::get())
+        .map(move || {
+            if executor.registered() {
+                reply("ok", StatusCode::OK)
+            } else {
+                reply("not registered", StatusCode::SERVICE_UNAVAILABLE)
+            }
+        });
+    live.or(ready).unify().boxed()
+}
+
+/// Serve the health endpoints of `executor` on `addr`
+pub async fn serve(executor: Arc<Executor>, addr: SocketAddr) {
+    warp::serve(routes(executor)).run(addr).await
+}
+
+/// Interval between two checks of the readiness reported by the gRPC health service
+const GRPC_HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep the status of the server, given by an empty service name, and of `services`
+/// reported by `reporter` serving while `executor` is ready
+pub async fn report_grpc_health(
+    executor: Arc<Executor>,
+    mut reporter: HealthReporter,
+    services: Vec<&'static str>,
+) {
+    let mut reported = None;
+    loop {
+        let ready = executor.registered();
+        if reported != Some(ready) {
+            let status = if ready {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            for service in std::iter::once("").chain(services.iter().copied()) {
+                reporter.set_service_status(service, status).await;
+            }
+            reported = Some(ready);
+        }
+        tokio::time::sleep(GRPC_HEALTH_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::LoggingMetricsCollector;
+    use ballista_core::serde::protobuf::ExecutorRegistration;
+    use datafusion::execution::runtime_env::RuntimeEnv;
+
+    #[tokio::test]
+    async fn health_endpoints() {
+        let executor = Arc::new(Executor::new(
+            ExecutorRegistration {
+                id: "executor".to_owned(),
+                optional_host: None,
+                port: 50051,
+                grpc_port: 50052,
+                specification: None,
+                labels: vec![],
+                protocol: None,
+            },
+            "/tmp",
+            Arc::new(RuntimeEnv::default()),
+            Arc::new(LoggingMetricsCollector::default()),
+            4,
+        ));
+        let routes = routes(executor.clone());
+        let get = |path: &str| warp::test::request().path(path).reply(&routes);
+
+        assert_eq!(get("/health/live").await.status(), StatusCode::OK);
+        assert_eq!(
+            get("/health/ready").await.status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        executor.set_registered(true);
+        assert_eq!(get("/health/live").await.status(), StatusCode::OK);
+        assert_eq!(get("/health/ready").await.status(), StatusCode::OK);
+        assert_eq!(get("/health").await.status(), StatusCode::NOT_FOUND);
+    }
+}
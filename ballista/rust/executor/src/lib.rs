@@ -22,46 +22,114 @@ pub mod execution_loop;
 pub mod executor;
 pub mod executor_server;
 pub mod flight_service;
+pub mod health;
+pub mod memory_results;
 pub mod metrics;
+pub mod running_tasks;
+pub mod scan_cache;
+pub mod shuffle_replication;
+pub mod shuffle_serving;
+pub mod task_interceptor;
 
 mod cpu_bound_executor;
 mod standalone;
 
 pub use standalone::new_standalone_executor;
 
+use futures::future::{AbortRegistration, Abortable};
 use log::info;
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
 
+use ballista_core::config::BALLISTA_TASK_TIMEOUT_SECONDS;
+use ballista_core::error::{BallistaError, Result};
 use ballista_core::serde::protobuf::{
-    task_status, CompletedTask, FailedTask, PartitionId, ShuffleWritePartition,
-    TaskStatus,
+    task_status, CompletedTask, FailedTask, FailureKind, PartitionId, TaskStatus,
 };
 
+use crate::executor::TaskOutput;
+
+/// The timeout set by the scheduler in the props of a task, if any
+pub fn task_timeout(task_props: &HashMap<String, String>) -> Option<Duration> {
+    task_props
+        .get(BALLISTA_TASK_TIMEOUT_SECONDS)
+        .and_then(|seconds| seconds.parse().ok())
+        .filter(|seconds| *seconds > 0)
+        .map(Duration::from_secs)
+}
+
+/// Run `task`, failing with [BallistaError::Timeout] if it runs for longer than
+/// `timeout`. The task is dropped, which stops it, once it times out.
+pub async fn run_with_timeout<T>(
+    timeout: Option<Duration>,
+    task: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, task)
+            .await
+            .unwrap_or_else(|_| {
+                Err(BallistaError::Timeout(format!(
+                    "Task ran for longer than its timeout of {:?}",
+                    timeout
+                )))
+            }),
+        None => task.await,
+    }
+}
+
+/// Run `task`, which is killed through the abort handle of `registration`, failing with
+/// [BallistaError::Timeout] if it is killed
+pub async fn run_killable<T>(
+    registration: AbortRegistration,
+    task: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    Abortable::new(task, registration)
+        .await
+        .unwrap_or_else(|_| {
+            Err(BallistaError::Timeout(
+                "Task was killed as it stopped making progress".to_owned(),
+            ))
+        })
+}
+
 pub fn as_task_status(
-    execution_result: ballista_core::error::Result<Vec<ShuffleWritePartition>>,
+    execution_result: Result<TaskOutput>,
     executor_id: String,
     task_id: PartitionId,
+    attempt: u32,
 ) -> TaskStatus {
     match execution_result {
-        Ok(partitions) => {
+        Ok(output) => {
             info!("Task {:?} finished", task_id);
 
             TaskStatus {
                 task_id: Some(task_id),
                 status: Some(task_status::Status::Completed(CompletedTask {
                     executor_id,
-                    partitions,
+                    partitions: output.partitions,
+                    bytes_scanned: output.bytes_scanned,
+                    spilled_bytes: output.spilled_bytes,
+                    spill_files: output.spill_files,
+                    bloom_filter: output.bloom_filter,
+                    replica: output.replica,
+                    operator_metrics: output.operator_metrics,
                 })),
+                attempt,
             }
         }
         Err(e) => {
             let error_msg = e.to_string();
-            info!("Task {:?} failed: {}", task_id, error_msg);
+            let kind = FailureKind::from(&e);
+            info!("Task {:?} failed ({:?}): {}", task_id, kind, error_msg);
 
             TaskStatus {
                 task_id: Some(task_id),
                 status: Some(task_status::Status::Failed(FailedTask {
-                    error: format!("Task failed due to Tokio error: {}", error_msg),
+                    error: error_msg,
+                    kind: kind.into(),
                 })),
+                attempt,
             }
         }
     }
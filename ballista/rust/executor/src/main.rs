@@ -18,31 +18,52 @@
 //! Ballista Rust executor binary.
 
 use chrono::{DateTime, Duration, Utc};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration as Core_Duration;
 
 use anyhow::{Context, Result};
 use arrow_flight::flight_service_server::FlightServiceServer;
-use ballista_executor::{execution_loop, executor_server};
-use log::{error, info};
+use ballista_executor::{execution_loop, executor_server, health};
+use log::{error, info, warn};
+use serde::Deserialize;
 use tempfile::TempDir;
 use tokio::fs::ReadDir;
+use tokio::runtime::Handle;
 use tokio::{fs, time};
-use tonic::transport::Server;
+use tonic::transport::NamedService;
 use uuid::Uuid;
 
-use ballista_core::config::TaskSchedulingPolicy;
+use ballista_core::cluster_config::ClusterConfig;
+use ballista_core::config::{LogFormat, TaskSchedulingPolicy};
+use ballista_core::control_plane;
 use ballista_core::error::BallistaError;
+use ballista_core::flight_transfer::{self, FlightTransferOptions};
+use ballista_core::grpc_services;
+use ballista_core::local_socket::{self, LocalSocketOptions};
+use ballista_core::object_store_registry::{
+    ObjectStoreCredentials, ObjectStoreFactories,
+};
+use ballista_core::protocol::ProtocolInfo;
 use ballista_core::serde::protobuf::{
     executor_registration, scheduler_grpc_client::SchedulerGrpcClient,
-    ExecutorRegistration, PhysicalPlanNode,
+    ExecutorRegistration, KeyValuePair, PhysicalPlanNode,
+};
+use ballista_core::serde::scheduler::{
+    ExecutorSpecification, NODE_NAME_LABEL, POD_NAMESPACE_LABEL, POD_NAME_LABEL,
+    ZONE_LABEL,
 };
-use ballista_core::serde::scheduler::ExecutorSpecification;
 use ballista_core::serde::BallistaCodec;
+use ballista_core::tls::{self, TlsOptions};
+use ballista_core::{logging, telemetry};
 use ballista_core::{print_version, BALLISTA_VERSION};
-use ballista_executor::executor::Executor;
+use ballista_executor::executor::{Executor, PINNED_FILE};
 use ballista_executor::flight_service::BallistaFlightService;
 use ballista_executor::metrics::LoggingMetricsCollector;
+use ballista_executor::shuffle_replication::ReplicaSenders;
 use config::prelude::*;
 use datafusion::execution::runtime_env::{RuntimeConfig, RuntimeEnv};
 use datafusion_proto::protobuf::LogicalPlanNode;
@@ -61,23 +82,58 @@ mod config {
 #[global_allocator]
 static ALLOC: snmalloc_rs::SnMalloc = snmalloc_rs::SnMalloc;
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    // the options of the cluster configuration file have the lowest precedence
+    let cluster_config =
+        ClusterConfig::try_new("executor", include_str!("../executor_config_spec.toml"))?;
+    let cluster_config_dir = TempDir::new()?;
+    let cluster_options = cluster_config_dir.path().join("executor.toml");
+    cluster_config.write_to(&cluster_options)?;
+
     // parse command-line arguments
-    let (opt, _remaining_args) =
-        Config::including_optional_config_files(&["/etc/ballista/executor.toml"])
-            .unwrap_or_exit();
+    let (opt, _remaining_args) = Config::including_optional_config_files(&[
+        cluster_options.as_path(),
+        Path::new("/etc/ballista/executor.toml"),
+    ])
+    .unwrap_or_exit();
 
     if opt.version {
         print_version();
         std::process::exit(0);
     }
 
+    // the I/O runtime answers the scheduler, serves shuffle data and reads the object
+    // stores, while the tasks run on runtimes of their own
+    let mut io_runtime = tokio::runtime::Builder::new_multi_thread();
+    io_runtime.enable_all().thread_name("io");
+    if opt.io_threads > 0 {
+        io_runtime.worker_threads(opt.io_threads);
+    }
+    io_runtime.build()?.block_on(run(opt, cluster_config))
+}
+
+async fn run(opt: Config, cluster_config: ClusterConfig) -> Result<()> {
+    // assign this executor a unique ID
+    let executor_id = Uuid::new_v4().to_string();
+
     let special_mod_log_level = opt.log_level_setting;
-    env_logger::builder()
-        .parse_filters(&*special_mod_log_level)
-        .format_timestamp_millis()
-        .init();
+    let mut logger = env_logger::builder();
+    logger
+        .parse_filters(&special_mod_log_level)
+        .format_timestamp_millis();
+    if let LogFormat::Json = opt.log_format {
+        let static_fields = vec![("executor_id", executor_id.clone())];
+        logger.format(move |buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            let line = logging::json_line(&timestamp, record, &static_fields);
+            writeln!(buf, "{}", line)
+        });
+    }
+    logger.init();
+
+    if !opt.otlp_endpoint.is_empty() {
+        telemetry::init_otlp_tracer("ballista-executor", &opt.otlp_endpoint)?;
+    }
 
     let external_host = opt.external_host;
     let bind_host = opt.bind_host;
@@ -89,9 +145,36 @@ async fn main() -> Result<()> {
         .parse()
         .with_context(|| format!("Could not parse address: {}", addr))?;
 
+    let tls = TlsOptions {
+        cert_path: opt.tls_cert,
+        key_path: opt.tls_key,
+        ca_path: opt.tls_ca_cert,
+        domain_name: opt.tls_domain,
+        client_ca_path: opt.tls_client_ca_cert,
+    };
+    tls::init_client_tls(&tls)?;
+    flight_transfer::init_flight_transfer(FlightTransferOptions {
+        max_message_size: opt.flight_max_message_size * 1024,
+        max_concurrent_streams: opt.flight_max_concurrent_streams,
+        tcp_keepalive: Core_Duration::from_secs(opt.flight_tcp_keepalive_seconds),
+        compression: opt.flight_compression,
+    })?;
+
+    // executors on the same host fetch partitions from each other through local sockets
+    let local_socket = if opt.local_socket_dir.is_empty() {
+        None
+    } else {
+        let dir = PathBuf::from(&opt.local_socket_dir);
+        local_socket::init_local_sockets(LocalSocketOptions::new(
+            &dir,
+            external_host.as_deref(),
+        ));
+        Some(local_socket::socket_path(&dir, &executor_id))
+    };
+
     let scheduler_host = opt.scheduler_host;
     let scheduler_port = opt.scheduler_port;
-    let scheduler_url = format!("http://{}:{}", scheduler_host, scheduler_port);
+    let scheduler_url = tls::grpc_url(&scheduler_host, scheduler_port);
 
     let work_dir = opt.work_dir.unwrap_or(
         TempDir::new()?
@@ -103,9 +186,31 @@ async fn main() -> Result<()> {
     info!("Running with config:");
     info!("work_dir: {}", work_dir);
     info!("concurrent_tasks: {}", opt.concurrent_tasks);
+    let task_cpu_cores = if opt.task_cpu_cores > 0 {
+        opt.task_cpu_cores
+    } else {
+        opt.concurrent_tasks
+    };
+    info!(
+        "task_cpu_cores: {} in {} partitions, {}% of a thread per task",
+        task_cpu_cores, opt.task_cpu_partitions, opt.task_cpu_share
+    );
+
+    let mut labels = parse_labels(&opt.labels)?;
+    for (key, value) in [
+        (POD_NAME_LABEL, opt.pod_name),
+        (POD_NAMESPACE_LABEL, opt.pod_namespace),
+        (NODE_NAME_LABEL, opt.node_name),
+        (ZONE_LABEL, opt.zone),
+    ] {
+        if !value.is_empty() {
+            labels.insert(key.to_owned(), value);
+        }
+    }
+    info!("labels: {:?}", labels);
 
     let executor_meta = ExecutorRegistration {
-        id: Uuid::new_v4().to_string(), // assign this executor a unique ID
+        id: executor_id,
         optional_host: external_host
             .clone()
             .map(executor_registration::OptionalHost::Host),
@@ -117,6 +222,11 @@ async fn main() -> Result<()> {
             }
             .into(),
         ),
+        labels: labels
+            .into_iter()
+            .map(|(key, value)| KeyValuePair { key, value })
+            .collect(),
+        protocol: Some(ProtocolInfo::current().into()),
     };
 
     let config = RuntimeConfig::new().with_temp_file_path(work_dir.clone());
@@ -124,74 +234,361 @@ async fn main() -> Result<()> {
         BallistaError::Internal("Failed to init Executor RuntimeEnv".to_owned())
     })?);
 
+    let mut object_store_factories = ObjectStoreFactories::builtin();
+    object_store_factories.run_io_on(Handle::current());
+    if !opt.object_stores.is_empty() {
+        object_store_factories
+            .add_cluster_stores(&ObjectStoreCredentials::from_json(&opt.object_stores)?)
+            .context("Could not create object stores")?;
+    }
+    if !opt.postgres_databases.is_empty() {
+        #[cfg(feature = "postgres")]
+        ballista_core::table_format::postgres::init_databases(&opt.postgres_databases)
+            .context("Could not configure the PostgreSQL databases")?;
+        #[cfg(not(feature = "postgres"))]
+        return Err(anyhow::anyhow!(
+            "build the executor with the `postgres` feature to read PostgreSQL databases"
+        ));
+    }
+    object_store_factories
+        .register_stores(&runtime, &ObjectStoreCredentials::default())?;
+
     let metrics_collector = Arc::new(LoggingMetricsCollector::default());
 
-    let executor = Arc::new(Executor::new(
+    let executor = Executor::new(
         executor_meta,
         &work_dir,
         runtime,
         metrics_collector,
         opt.concurrent_tasks,
-    ));
+    )
+    .with_object_store_factories(object_store_factories)
+    .with_scan_cache(opt.scan_cache_size * 1024 * 1024)
+    .with_memory_results(
+        opt.memory_results_size * 1024 * 1024,
+        opt.memory_results_serving_size * 1024 * 1024,
+        Core_Duration::from_secs(opt.memory_results_ttl_seconds),
+    )
+    .with_task_cpu_shares(task_cpu_cores, opt.task_cpu_partitions, opt.task_cpu_share)
+    .with_shuffle_serving_limits(
+        opt.shuffle_max_concurrent_streams,
+        opt.shuffle_max_rate * 1024 * 1024,
+        opt.shuffle_fetch_order,
+    );
+    let executor = Arc::new(executor);
+
+    if opt.health_port > 0 {
+        let health_addr = format!("{}:{}", bind_host, opt.health_port);
+        let health_addr = health_addr
+            .parse()
+            .with_context(|| format!("Could not parse address: {}", health_addr))?;
+        tokio::spawn(health::serve(executor.clone(), health_addr));
+    }
 
-    let scheduler = SchedulerGrpcClient::connect(scheduler_url)
-        .await
-        .context("Could not connect to scheduler")?;
+    let scheduler = SchedulerGrpcClient::new(
+        tls::create_grpc_channel(&scheduler_url)
+            .await
+            .context("Could not connect to scheduler")?,
+    );
+    // heartbeats and task statuses have a connection of their own
+    let control = SchedulerGrpcClient::new(
+        control_plane::create_control_channel(&scheduler_url, opt.control_plane_dscp)
+            .await
+            .context("Could not open control plane connection to scheduler")?,
+    );
 
     let default_codec: BallistaCodec<LogicalPlanNode, PhysicalPlanNode> =
         BallistaCodec::default();
 
     let scheduler_policy = opt.task_scheduling_policy;
-    let cleanup_ttl = opt.executor_cleanup_ttl;
+    let cleanup_ttl = Arc::new(AtomicU64::new(opt.executor_cleanup_ttl));
+
+    #[cfg(unix)]
+    {
+        let executor = executor.clone();
+        let cleanup_ttl = cleanup_ttl.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                reload_on_hangup(cluster_config, executor, scheduler_policy, cleanup_ttl)
+                    .await
+            {
+                error!("Could not reload cluster configuration on SIGHUP: {}", e);
+            }
+        });
+    }
 
     if opt.executor_cleanup_enable {
         let mut interval_time =
             time::interval(Core_Duration::from_secs(opt.executor_cleanup_interval));
+        let executor = executor.clone();
         tokio::spawn(async move {
             loop {
                 interval_time.tick().await;
-                if let Err(e) =
-                    clean_shuffle_data_loop(&work_dir, cleanup_ttl as i64).await
-                {
+                let ttl = cleanup_ttl.load(Ordering::SeqCst);
+                if let Err(e) = clean_shuffle_data_loop(&work_dir, ttl as i64).await {
                     error!("Ballista executor fail to clean_shuffle_data {:?}", e)
                 }
+                executor.remove_stale_encryption_keys();
             }
         });
     }
 
     match scheduler_policy {
         TaskSchedulingPolicy::PushStaged => {
-            tokio::spawn(executor_server::startup(
+            executor_server::startup(
                 scheduler,
+                control,
                 executor.clone(),
                 default_codec,
-            ));
+                tls.clone(),
+            )
+            .await?;
         }
         _ => {
             tokio::spawn(execution_loop::poll_loop(
-                scheduler,
+                control,
                 executor.clone(),
                 default_codec,
             ));
         }
     }
 
+    let max_replica_bytes = opt.shuffle_replica_max_size * 1024 * 1024;
+    // The socket is removed when the executor stops
+    #[cfg(unix)]
+    let _local_socket = match local_socket {
+        Some(path) => Some(serve_local_socket(
+            executor.clone(),
+            path,
+            max_replica_bytes,
+        )?),
+        None => None,
+    };
+
     // Arrow flight service
     {
-        let service = BallistaFlightService::new(executor.clone());
-        let server = FlightServiceServer::new(service);
+        // Copies of shuffle partitions are only accepted from executors which
+        // authenticate with a client certificate
+        let replica_senders = if tls.client_auth_enabled() {
+            ReplicaSenders::Certified {
+                domain: Some(tls.domain_name.clone()).filter(|name| !name.is_empty()),
+            }
+        } else {
+            ReplicaSenders::Nobody
+        };
+        let service = BallistaFlightService::new(executor.clone())
+            .with_replicas(replica_senders, max_replica_bytes);
+        let server = flight_transfer::flight_service_server(service);
+        let (health_reporter, health_service) = grpc_services::health_service();
+        tokio::spawn(health::report_grpc_health(
+            executor.clone(),
+            health_reporter,
+            vec![<FlightServiceServer<BallistaFlightService> as NamedService>::NAME],
+        ));
+        let reflection_service = grpc_services::reflection_service(&[])?;
         info!(
             "Ballista v{} Rust Executor listening on {:?}",
             BALLISTA_VERSION, addr
         );
-        let server_future =
-            tokio::spawn(Server::builder().add_service(server).serve(addr));
-        server_future
+        let server_future = tokio::spawn(
+            flight_transfer::configure_flight_server(tls.server_builder()?)
+                .add_service(server)
+                .add_service(health_service)
+                .add_service(reflection_service)
+                .serve(addr),
+        );
+        tokio::select! {
+            result = server_future => {
+                result
+                    .context("Tokio error")?
+                    .context("Could not start executor server")?;
+            }
+            _ = termination() => {
+                info!("Executor received a termination signal, shutting down");
+            }
+        }
+    }
+
+    telemetry::shutdown_tracer();
+    Ok(())
+}
+
+/// Wait for the process to be interrupted or terminated
+async fn termination() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminations) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminations.recv() => {}
+                }
+                return;
+            }
+            Err(e) => warn!("Could not handle SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Could not handle interrupts: {}", e);
+        futures::future::pending::<()>().await;
+    }
+}
+
+/// Unix domain socket serving the Flight service of the executor, removed when dropped
+#[cfg(unix)]
+struct LocalSocket {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl Drop for LocalSocket {
+    fn drop(&mut self) {
+        remove_socket(&self.path);
+    }
+}
+
+/// Remove the socket at `path`, if there is one
+#[cfg(unix)]
+fn remove_socket(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => info!("Removed socket {:?}", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Could not remove socket {:?}: {}", path, e),
+    }
+}
+
+/// Serve the Flight service of the executor on the unix domain socket at `path`, for
+/// the executors running on the same host. The directory of the socket is private to
+/// the user of the executor, and only processes of that user may connect, which
+/// authenticates the executors fetching partitions and sending copies of partitions of up
+/// to `max_replica_bytes` bytes as TLS client certificates do over TCP.
+#[cfg(unix)]
+fn serve_local_socket(
+    executor: Arc<Executor>,
+    path: PathBuf,
+    max_replica_bytes: u64,
+) -> Result<LocalSocket> {
+    use std::fs::{DirBuilder, Permissions};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+    use tokio::net::UnixListener;
+    use tokio_stream::wrappers::UnixListenerStream;
+    use tokio_stream::StreamExt;
+    use tonic::transport::Server;
+
+    // Safety: getuid has no preconditions and can not fail
+    let uid = unsafe { libc::getuid() };
+    let dir = path
+        .parent()
+        .with_context(|| format!("Socket {:?} has no directory", path))?;
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    let metadata = std::fs::metadata(dir)?;
+    if metadata.uid() != uid {
+        return Err(anyhow::anyhow!(
+            "Socket directory {:?} is owned by another user",
+            dir
+        ));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, Permissions::from_mode(0o700))?;
+    }
+
+    // A socket left behind by an executor with the same ID which did not stop cleanly
+    remove_socket(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Could not bind socket {:?}", path))?;
+    let socket = LocalSocket { path };
+    std::fs::set_permissions(&socket.path, Permissions::from_mode(0o600))?;
+    info!("Executor Flight service listening on {:?}", socket.path);
+
+    let incoming = UnixListenerStream::new(listener).filter(move |stream| {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => return true,
+        };
+        match stream.peer_cred() {
+            Ok(cred) if cred.uid() == uid => true,
+            Ok(cred) => {
+                warn!("Refused connection to local socket by user {}", cred.uid());
+                false
+            }
+            Err(e) => {
+                warn!("Could not authenticate connection to local socket: {}", e);
+                false
+            }
+        }
+    });
+    let server = FlightServiceServer::new(
+        BallistaFlightService::new(executor)
+            .with_replicas(ReplicaSenders::Anyone, max_replica_bytes),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = flight_transfer::configure_flight_server(Server::builder())
+            .add_service(server)
+            .serve_with_incoming(incoming)
             .await
-            .context("Tokio error")?
-            .context("Could not start executor server")?;
+        {
+            error!("Executor Flight service on local socket failed: {}", e);
+        }
+    });
+    Ok(socket)
+}
+
+/// Options of the cluster configuration file which are applied again on SIGHUP
+#[cfg(unix)]
+#[derive(Deserialize)]
+struct ReloadableOptions {
+    concurrent_tasks: Option<usize>,
+    executor_cleanup_ttl: Option<u64>,
+}
+
+/// Apply the reloadable options of the cluster configuration file whenever the process
+/// receives SIGHUP
+#[cfg(unix)]
+async fn reload_on_hangup(
+    cluster_config: ClusterConfig,
+    executor: Arc<Executor>,
+    scheduler_policy: TaskSchedulingPolicy,
+    cleanup_ttl: Arc<AtomicU64>,
+) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        info!("Reloading {}", cluster_config.path().display());
+        if let Err(e) = reload(&cluster_config, &executor, scheduler_policy, &cleanup_ttl)
+        {
+            error!("Could not reload cluster configuration: {}", e);
+        }
     }
+    Ok(())
+}
 
+/// Apply the reloadable options of the cluster configuration file
+#[cfg(unix)]
+fn reload(
+    cluster_config: &ClusterConfig,
+    executor: &Executor,
+    scheduler_policy: TaskSchedulingPolicy,
+    cleanup_ttl: &AtomicU64,
+) -> Result<()> {
+    let options: ReloadableOptions = cluster_config.read_as()?;
+    let concurrent_tasks = options
+        .concurrent_tasks
+        .filter(|concurrent_tasks| *concurrent_tasks != executor.task_limit());
+    if let Some(concurrent_tasks) = concurrent_tasks {
+        if let TaskSchedulingPolicy::PushStaged = scheduler_policy {
+            warn!("concurrent_tasks can not change with push-based scheduling");
+        } else {
+            info!("concurrent_tasks: {}", concurrent_tasks);
+            executor.set_task_limit(concurrent_tasks);
+        }
+    }
+    if let Some(ttl) = options.executor_cleanup_ttl {
+        info!("executor_cleanup_ttl: {}", ttl);
+        cleanup_ttl.store(ttl, Ordering::SeqCst);
+    }
     Ok(())
 }
 
@@ -203,8 +600,8 @@ async fn clean_shuffle_data_loop(work_dir: &str, seconds: i64) -> Result<()> {
     let mut need_delete_dir;
     while let Some(child) = dir.next_entry().await? {
         if let Ok(metadata) = child.metadata().await {
-            // only delete the job dir
-            if metadata.is_dir() {
+            // only delete the job dir, unless it holds the partitions of a cached table
+            if metadata.is_dir() && !child.path().join(PINNED_FILE).exists() {
                 let dir = fs::read_dir(child.path()).await?;
                 match check_modified_time_in_dirs(vec![dir], seconds).await {
                     Ok(x) => match x {
@@ -261,9 +658,28 @@ async fn check_modified_time_in_dirs(
     Ok(true)
 }
 
+/// Parse comma separated `key=value` labels
+fn parse_labels(labels: &str) -> Result<BTreeMap<String, String>> {
+    labels
+        .split(',')
+        .map(str::trim)
+        .filter(|label| !label.is_empty())
+        .map(|label| match label.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok((key.trim().to_owned(), value.trim().to_owned()))
+            }
+            _ => Err(anyhow::anyhow!(
+                "Invalid executor label {}, expected key=value",
+                label
+            )),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::clean_shuffle_data_loop;
+    use crate::{clean_shuffle_data_loop, parse_labels};
+    use ballista_executor::executor::PINNED_FILE;
     use std::fs;
     use std::fs::File;
     use std::io::Write;
@@ -298,4 +714,151 @@ mod tests {
         let count2 = fs::read_dir(work_dir.clone()).unwrap().count();
         assert_eq!(count2, 0);
     }
+
+    #[tokio::test]
+    async fn test_executor_clean_up_pinned() {
+        let work_dir = TempDir::new().unwrap().into_path();
+        let job_dir = work_dir.as_path().join("cached");
+        fs::create_dir(&job_dir).unwrap();
+        File::create(job_dir.join("data.arrow")).unwrap();
+        File::create(job_dir.join(PINNED_FILE)).unwrap();
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        clean_shuffle_data_loop(work_dir.to_str().unwrap(), 1)
+            .await
+            .unwrap();
+        assert!(job_dir.join("data.arrow").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reload_cluster_config() {
+        use crate::reload;
+        use ballista_core::cluster_config::ClusterConfig;
+        use ballista_core::config::TaskSchedulingPolicy;
+        use ballista_core::serde::protobuf::ExecutorRegistration;
+        use ballista_executor::executor::Executor;
+        use ballista_executor::metrics::LoggingMetricsCollector;
+        use datafusion::execution::runtime_env::RuntimeEnv;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("ballista.toml");
+        let cluster_config = ClusterConfig::try_new_with_path(
+            &path,
+            "executor",
+            include_str!("../executor_config_spec.toml"),
+        )
+        .unwrap();
+        let executor = Executor::new(
+            ExecutorRegistration {
+                id: "executor".to_owned(),
+                optional_host: None,
+                port: 50051,
+                grpc_port: 50052,
+                specification: None,
+                labels: vec![],
+                protocol: None,
+            },
+            dir.path().to_str().unwrap(),
+            Arc::new(RuntimeEnv::default()),
+            Arc::new(LoggingMetricsCollector::default()),
+            4,
+        );
+        let cleanup_ttl = AtomicU64::new(3600);
+
+        fs::write(
+            &path,
+            "[executor]\nconcurrent_tasks = 8\nexecutor_cleanup_ttl = 60\n",
+        )
+        .unwrap();
+        reload(
+            &cluster_config,
+            &executor,
+            TaskSchedulingPolicy::PullStaged,
+            &cleanup_ttl,
+        )
+        .unwrap();
+        assert_eq!(executor.task_limit(), 8);
+        assert_eq!(cleanup_ttl.load(Ordering::SeqCst), 60);
+
+        // The task slots of push-based scheduling are registered with the scheduler
+        fs::write(&path, "[executor]\nconcurrent_tasks = 2\n").unwrap();
+        reload(
+            &cluster_config,
+            &executor,
+            TaskSchedulingPolicy::PushStaged,
+            &cleanup_ttl,
+        )
+        .unwrap();
+        assert_eq!(executor.task_limit(), 8);
+
+        // Invalid files are not applied
+        fs::write(&path, "[executor]\nconcurrent_tasks = \"many\"\n").unwrap();
+        assert!(reload(
+            &cluster_config,
+            &executor,
+            TaskSchedulingPolicy::PullStaged,
+            &cleanup_ttl,
+        )
+        .is_err());
+        assert_eq!(executor.task_limit(), 8);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_serve_local_socket() {
+        use crate::serve_local_socket;
+        use ballista_core::serde::protobuf::ExecutorRegistration;
+        use ballista_executor::executor::Executor;
+        use ballista_executor::metrics::LoggingMetricsCollector;
+        use datafusion::execution::runtime_env::RuntimeEnv;
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::Arc;
+
+        let work_dir = TempDir::new().unwrap();
+        let executor = Arc::new(Executor::new(
+            ExecutorRegistration {
+                id: "executor".to_owned(),
+                optional_host: None,
+                port: 50051,
+                grpc_port: 50052,
+                specification: None,
+                labels: vec![],
+                protocol: None,
+            },
+            work_dir.path().to_str().unwrap(),
+            Arc::new(RuntimeEnv::default()),
+            Arc::new(LoggingMetricsCollector::default()),
+            4,
+        ));
+        let dir = work_dir.path().join("sockets");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = dir.join("executor.sock");
+        // A socket left behind by an earlier run does not prevent serving
+        File::create(&path).unwrap();
+
+        let socket = serve_local_socket(executor, path.clone(), 1024).unwrap();
+        let mode = |path: &std::path::Path| {
+            fs::metadata(path).unwrap().permissions().mode() & 0o777
+        };
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        drop(socket);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_parse_labels() {
+        assert!(parse_labels("").unwrap().is_empty());
+        let labels = parse_labels("rack=r1, disk = ssd,").unwrap();
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels["rack"], "r1");
+        assert_eq!(labels["disk"], "ssd");
+        assert!(parse_labels("rack").is_err());
+        assert!(parse_labels("=r1").is_err());
+    }
 }
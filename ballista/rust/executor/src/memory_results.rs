@@ -0,0 +1,422 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The output of the final stages of jobs, kept in the memory of the executor.
+//!
+//! Jobs submitted with `ballista.job.in_memory_results` have the tasks of their final
+//! stage keep their output here rather than in files. Their partitions are given paths
+//! starting with [MEMORY_PATH_PREFIX] in the job completion status, and the Flight
+//! service of the executor serves them from memory, so that interactive queries skip
+//! the filesystem entirely. The paths end with a random token, so that only the clients
+//! which got the job completion status can fetch the partitions.
+//!
+//! Partitions are kept up to the capacity of the store, and the tasks whose output does
+//! not fit write it to a file as usual. A partition may be fetched any number of times,
+//! so that clients can retry and several readers can fetch it, until it has not been
+//! fetched for the TTL of the store or the data of its job is removed.
+//!
+//! A fetched partition is held in memory until the client has received it, which a slow
+//! client delays, as the Flight service only sends its batches as fast as the flow
+//! control of the connection allows. The partitions being sent are bounded by the
+//! serving capacity of the store, and those fetched beyond it are spilled to a file and
+//! sent from there.
+
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::record_batch::RecordBatch;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Prefix of the paths of the partitions kept in memory
+pub const MEMORY_PATH_PREFIX: &str = "memory://";
+
+struct StoredPartition {
+    /// Path of the partition, with its token
+    path: String,
+    schema: SchemaRef,
+    batches: Vec<RecordBatch>,
+    bytes: usize,
+    /// When the partition was stored or last fetched
+    used_at: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Bytes of the stored partitions and of the partitions being computed
+    used: usize,
+    /// Bytes of the fetched partitions not sent yet
+    serving: usize,
+    /// The stored partitions by `<job_id>/<stage_id>/<partition_id>`
+    partitions: HashMap<String, StoredPartition>,
+}
+
+pub struct MemoryResults {
+    capacity: usize,
+    serving_capacity: usize,
+    ttl: Duration,
+    inner: Mutex<Inner>,
+}
+
+/// A partition fetched from the store
+pub enum ServedPartition {
+    /// The batches of the partition, sent from memory while `permit` holds their bytes
+    /// in the serving capacity of the store
+    Buffered {
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+        permit: ServingPermit,
+    },
+    /// The batches of a partition which does not fit in the serving capacity of the
+    /// store, to be spilled before they are sent
+    Overflowing {
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+        /// The job the partition was kept for, whose directory it is spilled to
+        job_id: String,
+    },
+}
+
+/// Bytes of a partition being sent, given back to the serving capacity of the store as
+/// they are sent and once the permit is dropped
+pub struct ServingPermit {
+    results: Arc<MemoryResults>,
+    bytes: usize,
+}
+
+impl ServingPermit {
+    /// Give back `bytes` which were sent
+    pub fn release(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.bytes -= bytes;
+        let mut inner = self.results.inner.lock();
+        inner.serving = inner.serving.saturating_sub(bytes);
+    }
+}
+
+impl Drop for ServingPermit {
+    fn drop(&mut self) {
+        self.release(self.bytes);
+    }
+}
+
+/// Bytes reserved for a partition being computed, given back to the capacity of the
+/// store once the reservation is dropped, unless the partition is kept
+pub struct Reservation {
+    results: Arc<MemoryResults>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Reserve `bytes` more, after removing the expired partitions. Returns whether they
+    /// fit in the capacity of the store.
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        let fits = self.results.reserve(bytes);
+        if fits {
+            self.bytes += bytes;
+        }
+        fits
+    }
+
+    /// Bytes reserved so far
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let mut inner = self.results.inner.lock();
+        inner.used = inner.used.saturating_sub(self.bytes);
+    }
+}
+
+impl MemoryResults {
+    /// A store keeping up to `capacity` bytes of partitions, each for up to `ttl` after
+    /// it was last fetched, and sending up to `serving_capacity` bytes of them from
+    /// memory at once. Sending is unlimited if `serving_capacity` is 0.
+    pub fn new(capacity: usize, serving_capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            serving_capacity,
+            ttl,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// A new path of partition `partition_id` of stage `stage_id` of job `job_id`, with
+    /// a random token
+    pub fn path(job_id: &str, stage_id: usize, partition_id: usize) -> String {
+        format!(
+            "{}{}/{}/{}/{}",
+            MEMORY_PATH_PREFIX,
+            job_id,
+            stage_id,
+            partition_id,
+            Uuid::new_v4()
+        )
+    }
+
+    /// Whether `path` is the path of a partition kept in memory
+    pub fn is_memory_path(path: &str) -> bool {
+        path.starts_with(MEMORY_PATH_PREFIX)
+    }
+
+    /// Start reserving memory for a partition being computed
+    pub fn reservation(self: &Arc<Self>) -> Reservation {
+        Reservation {
+            results: self.clone(),
+            bytes: 0,
+        }
+    }
+
+    fn reserve(&self, bytes: usize) -> bool {
+        let mut inner = self.inner.lock();
+        let ttl = self.ttl;
+        let mut expired = 0;
+        inner.partitions.retain(|_, partition| {
+            let keep = partition.used_at.elapsed() < ttl;
+            if !keep {
+                expired += partition.bytes;
+            }
+            keep
+        });
+        inner.used -= expired;
+        if inner.used + bytes > self.capacity {
+            return false;
+        }
+        inner.used += bytes;
+        true
+    }
+
+    /// Keep the `batches` of the partition at `path`, holding the bytes of
+    /// `reservation`
+    pub fn insert(
+        &self,
+        path: String,
+        schema: SchemaRef,
+        batches: Vec<RecordBatch>,
+        mut reservation: Reservation,
+    ) {
+        let key = match partition_key(&path) {
+            Some(key) => key.to_owned(),
+            None => return,
+        };
+        let partition = StoredPartition {
+            path,
+            schema,
+            batches,
+            bytes: std::mem::take(&mut reservation.bytes),
+            used_at: Instant::now(),
+        };
+        let mut inner = self.inner.lock();
+        // A partition computed again by another attempt replaces the previous one
+        if let Some(previous) = inner.partitions.insert(key, partition) {
+            inner.used = inner.used.saturating_sub(previous.bytes);
+        }
+    }
+
+    /// The schema and batches of the partition at `path`
+    pub fn get(&self, path: &str) -> Option<(SchemaRef, Vec<RecordBatch>)> {
+        let mut inner = self.inner.lock();
+        let partition = inner.get(path)?;
+        Some((partition.schema.clone(), partition.batches.clone()))
+    }
+
+    /// The partition at `path` to send to a client, holding its bytes in the serving
+    /// capacity of the store if they fit
+    pub fn serve(self: &Arc<Self>, path: &str) -> Option<ServedPartition> {
+        let mut inner = self.inner.lock();
+        let partition = inner.get(path)?;
+        // The job is taken from the path the partition was kept at rather than from the
+        // request, as it names the directory the partition may be spilled to
+        let (schema, batches, bytes, job_id) = (
+            partition.schema.clone(),
+            partition.batches.clone(),
+            partition.bytes,
+            path_job_id(&partition.path)?.to_owned(),
+        );
+        if self.serving_capacity > 0 && inner.serving + bytes > self.serving_capacity {
+            return Some(ServedPartition::Overflowing {
+                schema,
+                batches,
+                job_id,
+            });
+        }
+        inner.serving += bytes;
+        Some(ServedPartition::Buffered {
+            schema,
+            batches,
+            permit: ServingPermit {
+                results: self.clone(),
+                bytes,
+            },
+        })
+    }
+
+    /// Drop the partitions of job `job_id`
+    pub fn remove_job(&self, job_id: &str) {
+        let prefix = format!("{}/", job_id);
+        let mut inner = self.inner.lock();
+        let mut removed = 0;
+        inner.partitions.retain(|key, partition| {
+            let keep = !key.starts_with(&prefix);
+            if !keep {
+                removed += partition.bytes;
+            }
+            keep
+        });
+        inner.used = inner.used.saturating_sub(removed);
+    }
+
+    /// Bytes of the partitions kept and being computed
+    pub fn used_bytes(&self) -> usize {
+        self.inner.lock().used
+    }
+
+    /// Bytes of the partitions being sent from memory
+    pub fn serving_bytes(&self) -> usize {
+        self.inner.lock().serving
+    }
+}
+
+impl Inner {
+    /// The partition at `path`, if its token matches
+    fn get(&mut self, path: &str) -> Option<&mut StoredPartition> {
+        let partition = self.partitions.get_mut(partition_key(path)?)?;
+        if partition.path != path {
+            return None;
+        }
+        partition.used_at = Instant::now();
+        Some(partition)
+    }
+}
+
+/// The `<job_id>/<stage_id>/<partition_id>` of the partition at `path`
+fn partition_key(path: &str) -> Option<&str> {
+    let (key, _token) = path.strip_prefix(MEMORY_PATH_PREFIX)?.rsplit_once('/')?;
+    Some(key)
+}
+
+/// The job of the partition at `path`
+pub fn path_job_id(path: &str) -> Option<&str> {
+    let (job_id, _) = partition_key(path)?.split_once('/')?;
+    Some(job_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Schema;
+
+    fn keep(results: &Arc<MemoryResults>, path: &str, bytes: usize) -> bool {
+        let mut reservation = results.reservation();
+        if !reservation.grow(bytes) {
+            return false;
+        }
+        let schema = Arc::new(Schema::empty());
+        results.insert(path.to_owned(), schema, vec![], reservation);
+        true
+    }
+
+    #[test]
+    fn keep_partitions_up_to_capacity() {
+        let results = Arc::new(MemoryResults::new(100, 0, Duration::from_secs(60)));
+        let path = MemoryResults::path("job", 2, 0);
+        assert!(MemoryResults::is_memory_path(&path));
+        assert!(!MemoryResults::is_memory_path("/tmp/job/2/0/data.arrow"));
+        assert_eq!(path_job_id(&path), Some("job"));
+        assert_ne!(path, MemoryResults::path("job", 2, 0));
+
+        assert!(keep(&results, &path, 60));
+        assert!(!keep(&results, &MemoryResults::path("job", 2, 1), 60));
+        assert_eq!(results.used_bytes(), 60);
+
+        // Partitions are fetched again by retries and other readers
+        assert!(results.get(&path).is_some());
+        assert!(results.get(&path).is_some());
+        // but not without their token
+        assert!(results.get("memory://job/2/0/token").is_none());
+
+        results.remove_job("job");
+        assert!(results.get(&path).is_none());
+        assert_eq!(results.used_bytes(), 0);
+    }
+
+    #[test]
+    fn release_reservations_on_drop() {
+        let results = Arc::new(MemoryResults::new(100, 0, Duration::from_secs(60)));
+        let mut reservation = results.reservation();
+        assert!(reservation.grow(40));
+        assert!(reservation.grow(40));
+        assert!(!reservation.grow(40));
+        assert_eq!(reservation.bytes(), 80);
+        assert_eq!(results.used_bytes(), 80);
+        drop(reservation);
+        assert_eq!(results.used_bytes(), 0);
+    }
+
+    #[test]
+    fn remove_expired_partitions() {
+        let results = Arc::new(MemoryResults::new(100, 0, Duration::ZERO));
+        let path = MemoryResults::path("job", 1, 0);
+        assert!(keep(&results, &path, 80));
+
+        // Reserving drops the partition which expired right away
+        let mut reservation = results.reservation();
+        assert!(reservation.grow(80));
+        assert!(results.get(&path).is_none());
+        assert_eq!(results.used_bytes(), 80);
+    }
+
+    #[test]
+    fn spill_partitions_beyond_serving_capacity() {
+        let results = Arc::new(MemoryResults::new(100, 50, Duration::from_secs(60)));
+        let paths: Vec<_> = (0..2)
+            .map(|partition| MemoryResults::path("job", 1, partition))
+            .collect();
+        for path in &paths {
+            assert!(keep(&results, path, 40));
+        }
+
+        let mut permit = match results.serve(&paths[0]) {
+            Some(ServedPartition::Buffered { permit, .. }) => permit,
+            _ => panic!("Expected the first partition to be sent from memory"),
+        };
+        assert_eq!(results.serving_bytes(), 40);
+        assert_eq!(results.used_bytes(), 80);
+
+        // The second partition does not fit while the first one is being sent
+        let second = results.serve(&paths[1]);
+        match second {
+            Some(ServedPartition::Overflowing { job_id, .. }) => {
+                assert_eq!(job_id, "job")
+            }
+            _ => panic!("Expected the second partition to be spilled"),
+        }
+
+        permit.release(30);
+        assert_eq!(results.serving_bytes(), 10);
+        drop(permit);
+        assert_eq!(results.serving_bytes(), 0);
+        assert!(matches!(
+            results.serve(&paths[1]),
+            Some(ServedPartition::Buffered { .. })
+        ));
+    }
+}
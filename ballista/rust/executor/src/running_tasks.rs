@@ -0,0 +1,174 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The tasks running on an executor and their progress.
+//!
+//! The progress of the running tasks, the rows output by their operators so far, is
+//! sent to the scheduler with the heartbeats of the executor, or with its polls for work
+//! with pull-based scheduling. The scheduler answers with the tasks which stopped
+//! making progress, which are killed.
+
+use ballista_core::serde::protobuf::{PartitionId, TaskAttempt, TaskProgress};
+use datafusion::physical_plan::ExecutionPlan;
+use futures::future::{AbortHandle, AbortRegistration};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Job ID, stage ID, partition ID and attempt of a task
+type TaskKey = (String, u32, u32, u32);
+
+struct RunningTask {
+    plan: Arc<dyn ExecutionPlan>,
+    abort: AbortHandle,
+}
+
+#[derive(Default)]
+pub struct RunningTasks {
+    tasks: Arc<Mutex<HashMap<TaskKey, RunningTask>>>,
+}
+
+impl RunningTasks {
+    /// Track attempt `attempt` of task `task_id`, which runs `plan`, until the returned
+    /// guard is dropped. The task must be run as an
+    /// [Abortable](futures::future::Abortable) with the returned registration, so that
+    /// it can be killed.
+    pub fn register(
+        &self,
+        task_id: &PartitionId,
+        attempt: u32,
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> (RunningTaskGuard, AbortRegistration) {
+        let (abort, registration) = AbortHandle::new_pair();
+        let key = task_key(task_id, attempt);
+        self.tasks
+            .lock()
+            .insert(key.clone(), RunningTask { plan, abort });
+        let guard = RunningTaskGuard {
+            tasks: self.tasks.clone(),
+            key,
+        };
+        (guard, registration)
+    }
+
+    /// The progress of the running tasks
+    pub fn progress(&self) -> Vec<TaskProgress> {
+        self.tasks
+            .lock()
+            .iter()
+            .map(
+                |((job_id, stage_id, partition_id, attempt), task)| TaskProgress {
+                    task_id: Some(PartitionId {
+                        job_id: job_id.clone(),
+                        stage_id: *stage_id,
+                        partition_id: *partition_id,
+                    }),
+                    attempt: *attempt,
+                    output_rows: output_rows(task.plan.as_ref()),
+                },
+            )
+            .collect()
+    }
+
+    /// Kill `tasks`, if they still run. Returns the number of tasks killed.
+    pub fn kill(&self, tasks: &[TaskAttempt]) -> usize {
+        let running = self.tasks.lock();
+        let mut killed = 0;
+        for task in tasks {
+            let key = match task.task_id.as_ref() {
+                Some(task_id) => task_key(task_id, task.attempt),
+                None => continue,
+            };
+            if let Some(found) = running.get(&key) {
+                found.abort.abort();
+                killed += 1;
+            }
+        }
+        killed
+    }
+}
+
+/// Stops tracking a task once it finished, is killed or failed
+pub struct RunningTaskGuard {
+    tasks: Arc<Mutex<HashMap<TaskKey, RunningTask>>>,
+    key: TaskKey,
+}
+
+impl Drop for RunningTaskGuard {
+    fn drop(&mut self) {
+        self.tasks.lock().remove(&self.key);
+    }
+}
+
+fn task_key(task_id: &PartitionId, attempt: u32) -> TaskKey {
+    (
+        task_id.job_id.clone(),
+        task_id.stage_id,
+        task_id.partition_id,
+        attempt,
+    )
+}
+
+/// Rows output by the operators of `plan` so far
+fn output_rows(plan: &dyn ExecutionPlan) -> u64 {
+    let rows = plan
+        .metrics()
+        .and_then(|metrics| metrics.output_rows())
+        .unwrap_or_default() as u64;
+    rows + plan
+        .children()
+        .iter()
+        .map(|child| output_rows(child.as_ref()))
+        .sum::<u64>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Schema;
+    use datafusion::physical_plan::empty::EmptyExec;
+    use futures::future::{Abortable, Aborted};
+
+    #[tokio::test]
+    async fn kill_running_tasks() {
+        let tasks = RunningTasks::default();
+        let task_id = PartitionId {
+            job_id: "job".to_owned(),
+            stage_id: 1,
+            partition_id: 0,
+        };
+        let plan = Arc::new(EmptyExec::new(false, Arc::new(Schema::empty())));
+        let (guard, registration) = tasks.register(&task_id, 2, plan);
+
+        let progress = tasks.progress();
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].attempt, 2);
+
+        // Only the given attempt is killed
+        let attempt = |attempt| TaskAttempt {
+            task_id: Some(task_id.clone()),
+            attempt,
+        };
+        assert_eq!(tasks.kill(&[attempt(1)]), 0);
+        assert_eq!(tasks.kill(&[attempt(2)]), 1);
+        let result = Abortable::new(futures::future::pending::<()>(), registration).await;
+        assert_eq!(result, Err(Aborted));
+
+        drop(guard);
+        assert!(tasks.progress().is_empty());
+    }
+}
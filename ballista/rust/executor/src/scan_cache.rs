@@ -0,0 +1,707 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Sharing of the Parquet scans of the tasks of an executor.
+//!
+//! The Parquet scans of tasks are wrapped in [SharedScanExec]s, which identify the
+//! partition they scan by the scan, with its projection and predicate, and by the
+//! version of its files. The partitions scanned by several tasks at once are read once,
+//! by whichever of their streams is polled first, and their batches are kept for the
+//! other streams, which read them at their own pace. No stream waits for another one to
+//! be polled, so the scans of the same partition in one task, such as those of a
+//! self-join, do not wait for each other. Once a partition is scanned, its batches are
+//! kept for the later tasks to replay them.
+//!
+//! Scanned partitions are kept up to the capacity of the cache, the least recently
+//! used ones being dropped first. A partition larger than the whole cache is not kept:
+//! its batches are only kept until all the streams reading it have read them, and the
+//! streams which had not read any batch yet scan it themselves.
+//!
+//! The scans below a [BloomFilterExec] whose filter is set skip some of their row
+//! groups, so that they do not return all the rows of their partitions, and are not
+//! shared.
+
+use ballista_core::execution_plans::BloomFilterExec;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::error::{ArrowError, Result as ArrowResult};
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::error::{DataFusionError, Result};
+use datafusion::execution::context::TaskContext;
+use datafusion::physical_plan::expressions::PhysicalSortExpr;
+use datafusion::physical_plan::file_format::ParquetExec;
+use datafusion::physical_plan::memory::MemoryStream;
+use datafusion::physical_plan::metrics::MetricsSet;
+use datafusion::physical_plan::{
+    displayable, with_new_children_if_necessary, DisplayFormatType, ExecutionPlan,
+    Partitioning, RecordBatchStream, SendableRecordBatchStream, Statistics,
+};
+use futures::task::{waker, ArcWake};
+use futures::{Stream, StreamExt};
+use log::debug;
+use parking_lot::Mutex;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Formatter};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+enum Slot {
+    /// The partition is being scanned
+    Scanning(Arc<SharedScan>),
+    /// The partition was scanned
+    Scanned {
+        batches: Arc<Vec<RecordBatch>>,
+        /// Bytes of the batches, along with the key of the partition
+        bytes: usize,
+        /// Tick of the last time the partition was read, its key in [CacheState::lru]
+        last_used: u64,
+    },
+}
+
+#[derive(Default)]
+struct CacheState {
+    slots: HashMap<String, Slot>,
+    /// Keys of the scanned partitions, from the least recently used one
+    lru: BTreeMap<u64, String>,
+    bytes: usize,
+    tick: u64,
+}
+
+impl CacheState {
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+enum Lookup {
+    /// The partition was scanned
+    Cached(Arc<Vec<RecordBatch>>),
+    /// The partition is being scanned, for the stream to read it along with the others
+    Scanning(Arc<SharedScan>),
+}
+
+/// Batches of the partitions scanned by the tasks of an executor
+pub struct ScanCache {
+    /// Maximum bytes of the batches kept
+    capacity: usize,
+    state: Mutex<CacheState>,
+}
+
+impl ScanCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Bytes of the scanned partitions kept
+    pub fn cached_bytes(&self) -> usize {
+        self.state.lock().bytes
+    }
+
+    /// `plan` with its Parquet scans shared with the other tasks
+    pub fn share_scans(
+        self: &Arc<Self>,
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if plan.as_any().is::<ParquetExec>() {
+            return Ok(Arc::new(SharedScanExec::new(plan, self.clone())));
+        }
+        let filtered = plan
+            .as_any()
+            .downcast_ref::<BloomFilterExec>()
+            .map_or(false, |exec| exec.filter().is_some());
+        if filtered {
+            return Ok(plan);
+        }
+        let children = plan
+            .children()
+            .into_iter()
+            .map(|child| self.share_scans(child))
+            .collect::<Result<Vec<_>>>()?;
+        with_new_children_if_necessary(plan, children)
+    }
+
+    /// The batches of the partition under `key`, or its scan, which is started with
+    /// `scan` if it is not being scanned
+    fn lookup(
+        &self,
+        key: &str,
+        scan: impl FnOnce() -> Result<SendableRecordBatchStream>,
+    ) -> Result<Lookup> {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        let tick = state.next_tick();
+        match state.slots.get_mut(key) {
+            Some(Slot::Scanned {
+                batches, last_used, ..
+            }) => {
+                state.lru.remove(last_used);
+                state.lru.insert(tick, key.to_owned());
+                *last_used = tick;
+                Ok(Lookup::Cached(batches.clone()))
+            }
+            Some(Slot::Scanning(shared)) => Ok(Lookup::Scanning(shared.clone())),
+            None => {
+                let shared = Arc::new(SharedScan::new(scan()?));
+                state
+                    .slots
+                    .insert(key.to_owned(), Slot::Scanning(shared.clone()));
+                Ok(Lookup::Scanning(shared))
+            }
+        }
+    }
+
+    /// Keep the batches of the partition scanned under `key` by `scan`, dropping the
+    /// least recently used partitions to make room for them
+    fn finish(&self, key: &str, scan: &Arc<SharedScan>, batches: Vec<RecordBatch>) {
+        let bytes = batches.iter().map(batch_bytes).sum::<usize>() + key.len();
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        if !matches!(state.slots.get(key), Some(Slot::Scanning(s)) if Arc::ptr_eq(s, scan))
+        {
+            return;
+        }
+        if bytes > self.capacity {
+            state.slots.remove(key);
+            return;
+        }
+        let tick = state.next_tick();
+        state.slots.insert(
+            key.to_owned(),
+            Slot::Scanned {
+                batches: Arc::new(batches),
+                bytes,
+                last_used: tick,
+            },
+        );
+        state.lru.insert(tick, key.to_owned());
+        state.bytes += bytes;
+        while state.bytes > self.capacity {
+            let oldest = match state.lru.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            let key = state.lru.remove(&oldest).unwrap_or_default();
+            if let Some(Slot::Scanned { bytes, .. }) = state.slots.remove(&key) {
+                state.bytes -= bytes;
+            }
+        }
+    }
+
+    /// Forget the partition scanned under `key` by `scan`, which failed, was stopped
+    /// or outgrew the cache, so that later tasks scan it themselves
+    fn forget(&self, key: &str, scan: &Arc<SharedScan>) {
+        let mut state = self.state.lock();
+        if matches!(state.slots.get(key), Some(Slot::Scanning(s)) if Arc::ptr_eq(s, scan))
+        {
+            state.slots.remove(key);
+        }
+    }
+}
+
+/// Bytes of memory held by `batch`
+pub(crate) fn batch_bytes(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| column.get_array_memory_size())
+        .sum()
+}
+
+/// Wakers of the streams waiting for the next batch of a shared scan, which are all
+/// woken when the scan is ready to be polled, whichever stream polled it
+#[derive(Default)]
+struct Wakers(Mutex<Vec<Waker>>);
+
+impl Wakers {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl ArcWake for Wakers {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let wakers = std::mem::take(&mut *arc_self.0.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// The scan of a partition read by several streams
+struct SharedScan {
+    state: Mutex<SharedState>,
+    wakers: Arc<Wakers>,
+}
+
+struct SharedState {
+    /// The scan of the partition, `None` once it ended
+    input: Option<SendableRecordBatchStream>,
+    /// The batches kept, from the `first`-th batch of the partition on
+    batches: VecDeque<RecordBatch>,
+    first: usize,
+    bytes: usize,
+    /// Whether all the batches are kept for the cache, which they are not once they
+    /// outgrow it
+    keep: bool,
+    /// Error the scan failed with
+    error: Option<String>,
+    /// Index of the next batch of each stream reading the partition, by stream
+    readers: HashMap<usize, usize>,
+    next_reader: usize,
+}
+
+impl SharedScan {
+    fn new(input: SendableRecordBatchStream) -> Self {
+        Self {
+            state: Mutex::new(SharedState {
+                input: Some(input),
+                batches: VecDeque::new(),
+                first: 0,
+                bytes: 0,
+                keep: true,
+                error: None,
+                readers: HashMap::new(),
+                next_reader: 0,
+            }),
+            wakers: Default::default(),
+        }
+    }
+
+    /// Add a stream reading the partition from its first batch, unless the batches it
+    /// would read first are no longer kept
+    fn add_reader(&self) -> Option<usize> {
+        let mut state = self.state.lock();
+        if !state.keep || state.error.is_some() {
+            return None;
+        }
+        let reader = state.next_reader;
+        state.next_reader += 1;
+        state.readers.insert(reader, 0);
+        Some(reader)
+    }
+}
+
+impl SharedState {
+    /// Drop the batches read by all the streams, unless all the batches are kept
+    fn trim(&mut self) {
+        if self.keep {
+            return;
+        }
+        let read = self.readers.values().min().copied().unwrap_or(usize::MAX);
+        while self.first < read && !self.batches.is_empty() {
+            self.batches.pop_front();
+            self.first += 1;
+        }
+    }
+
+    /// Stop the streams except `reader` which have not read any batch from reading
+    /// along with the others, so they scan the partition themselves
+    fn detach_unstarted(&mut self, reader: usize) {
+        self.readers
+            .retain(|other, position| *other == reader || *position > 0);
+    }
+}
+
+/// SharedScanExec outputs the partitions of its input through a [ScanCache], so that
+/// the tasks scanning the same partitions at once read them once
+#[derive(Clone)]
+pub struct SharedScanExec {
+    input: Arc<dyn ExecutionPlan>,
+    /// The scan, with the files of all its partitions
+    key: String,
+    cache: Arc<ScanCache>,
+}
+
+impl SharedScanExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>, cache: Arc<ScanCache>) -> Self {
+        let key = displayable(input.as_ref()).indent().to_string();
+        Self { input, key, cache }
+    }
+
+    /// Key of partition `partition`, which changes with the version of its files
+    fn partition_key(&self, partition: usize) -> String {
+        let mut key = format!("{}/{}", self.key, partition);
+        if let Some(exec) = self.input.as_any().downcast_ref::<ParquetExec>() {
+            let config = exec.base_config();
+            key.push_str(config.object_store_url.as_str());
+            for file in config.file_groups.get(partition).into_iter().flatten() {
+                key.push_str(&format!(
+                    "/{}:{}:{}:{:?}",
+                    file.object_meta.location,
+                    file.object_meta.size,
+                    file.object_meta.last_modified.timestamp_nanos(),
+                    file.range
+                ));
+            }
+        }
+        key
+    }
+}
+
+impl fmt::Debug for SharedScanExec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedScanExec")
+            .field("input", &self.input)
+            .finish()
+    }
+}
+
+impl ExecutionPlan for SharedScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn output_ordering(&self) -> Option<&[PhysicalSortExpr]> {
+        self.input.output_ordering()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        if children.len() != 1 {
+            return Err(DataFusionError::Plan(
+                "SharedScanExec wrong number of children".to_string(),
+            ));
+        }
+        Ok(Arc::new(SharedScanExec::new(
+            children[0].clone(),
+            self.cache.clone(),
+        )))
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let key = self.partition_key(partition);
+        let lookup = self
+            .cache
+            .lookup(&key, || self.input.execute(partition, context.clone()))?;
+        let scan = match lookup {
+            Lookup::Cached(batches) => {
+                debug!("Replaying scanned partition {}", partition);
+                return Ok(Box::pin(MemoryStream::try_new(
+                    batches.to_vec(),
+                    self.schema(),
+                    None,
+                )?));
+            }
+            Lookup::Scanning(scan) => scan,
+        };
+        let reader = match scan.add_reader() {
+            Some(reader) => reader,
+            None => return self.input.execute(partition, context),
+        };
+        Ok(Box::pin(SharedScanStream {
+            scan,
+            reader,
+            cache: self.cache.clone(),
+            key,
+            input: self.input.clone(),
+            partition,
+            context,
+            own_scan: None,
+            done: false,
+        }))
+    }
+
+    fn fmt_as(&self, t: DisplayFormatType, f: &mut Formatter) -> fmt::Result {
+        match t {
+            DisplayFormatType::Default => write!(f, "SharedScanExec"),
+        }
+    }
+
+    fn metrics(&self) -> Option<MetricsSet> {
+        self.input.metrics()
+    }
+
+    fn statistics(&self) -> Statistics {
+        self.input.statistics()
+    }
+}
+
+/// Stream of the batches of a partition read along with the other streams reading it
+struct SharedScanStream {
+    scan: Arc<SharedScan>,
+    reader: usize,
+    cache: Arc<ScanCache>,
+    key: String,
+    /// The scan of the partition, to scan it alone once the stream is detached from the
+    /// shared scan
+    input: Arc<dyn ExecutionPlan>,
+    partition: usize,
+    context: Arc<TaskContext>,
+    own_scan: Option<SendableRecordBatchStream>,
+    /// Whether the stream returned its last batch or an error
+    done: bool,
+}
+
+impl SharedScanStream {
+    /// Poll the shared scan, returning `None` if the stream was detached from it
+    fn poll_shared(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Option<Poll<Option<ArrowResult<RecordBatch>>>> {
+        let mut state = self.scan.state.lock();
+        loop {
+            let position = *state.readers.get(&self.reader)?;
+            if let Some(batch) = state.batches.get(position - state.first).cloned() {
+                state.readers.insert(self.reader, position + 1);
+                state.trim();
+                return Some(Poll::Ready(Some(Ok(batch))));
+            }
+            if let Some(error) = &state.error {
+                let error = ArrowError::ExternalError(Box::new(
+                    DataFusionError::Execution(error.clone()),
+                ));
+                return Some(Poll::Ready(Some(Err(error))));
+            }
+            let input = match state.input.as_mut() {
+                Some(input) => input,
+                None => return Some(Poll::Ready(None)),
+            };
+            // Whichever stream polls the scan, all the streams waiting for its next
+            // batch are woken once it is ready
+            self.scan.wakers.register(cx.waker());
+            let waker = waker(self.scan.wakers.clone());
+            match input.poll_next_unpin(&mut Context::from_waker(&waker)) {
+                Poll::Ready(Some(Ok(batch))) => {
+                    state.bytes += batch_bytes(&batch);
+                    state.batches.push_back(batch);
+                    if state.keep && state.bytes > self.cache.capacity {
+                        state.keep = false;
+                        state.detach_unstarted(self.reader);
+                        self.cache.forget(&self.key, &self.scan);
+                    }
+                    Wakers::wake_by_ref(&self.scan.wakers);
+                }
+                Poll::Ready(Some(Err(e))) => {
+                    state.input = None;
+                    state.error = Some(e.to_string());
+                    state.detach_unstarted(self.reader);
+                    self.cache.forget(&self.key, &self.scan);
+                    Wakers::wake_by_ref(&self.scan.wakers);
+                    return Some(Poll::Ready(Some(Err(e))));
+                }
+                Poll::Ready(None) => {
+                    state.input = None;
+                    if state.keep {
+                        let batches = state.batches.iter().cloned().collect();
+                        self.cache.finish(&self.key, &self.scan, batches);
+                    }
+                    Wakers::wake_by_ref(&self.scan.wakers);
+                }
+                Poll::Pending => return Some(Poll::Pending),
+            }
+        }
+    }
+}
+
+impl Stream for SharedScanStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if let Some(own_scan) = &mut this.own_scan {
+            return own_scan.poll_next_unpin(cx);
+        }
+        match this.poll_shared(cx) {
+            Some(poll) => {
+                if matches!(poll, Poll::Ready(None) | Poll::Ready(Some(Err(_)))) {
+                    this.done = true;
+                }
+                poll
+            }
+            None => {
+                debug!(
+                    "Scanning partition {} no longer kept for the other tasks",
+                    this.partition
+                );
+                match this.input.execute(this.partition, this.context.clone()) {
+                    Ok(own_scan) => this.own_scan.insert(own_scan).poll_next_unpin(cx),
+                    Err(e) => {
+                        this.done = true;
+                        Poll::Ready(Some(Err(ArrowError::ExternalError(Box::new(e)))))
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RecordBatchStream for SharedScanStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+impl Drop for SharedScanStream {
+    fn drop(&mut self) {
+        let mut state = self.scan.state.lock();
+        state.readers.remove(&self.reader);
+        state.trim();
+        // The scan is stopped once no stream reads it, and the partition is not kept as
+        // it was not scanned to its end
+        if state.readers.is_empty() && state.input.is_some() {
+            state.input = None;
+            state.keep = false;
+            state.batches.clear();
+            self.cache.forget(&self.key, &self.scan);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::Int32Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::common;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+
+    fn memory_exec() -> Result<Arc<dyn ExecutionPlan>> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let batch = |values: Vec<i32>| {
+            RecordBatch::try_new(schema.clone(), vec![Arc::new(Int32Array::from(values))])
+        };
+        let partition = vec![batch(vec![1, 2])?, batch(vec![3])?];
+        Ok(Arc::new(MemoryExec::try_new(
+            &[partition.clone(), partition.clone(), partition],
+            schema.clone(),
+            None,
+        )?))
+    }
+
+    fn is_cached(cache: &ScanCache, key: &str) -> bool {
+        matches!(
+            cache.state.lock().slots.get(key),
+            Some(Slot::Scanned { .. })
+        )
+    }
+
+    #[tokio::test]
+    async fn share_partitions_scanned_at_once() -> Result<()> {
+        let ctx = SessionContext::new();
+        let cache = Arc::new(ScanCache::new(1 << 20));
+        let scan = SharedScanExec::new(memory_exec()?, cache.clone());
+
+        let mut first = scan.execute(0, ctx.task_ctx())?;
+        first.next().await.unwrap()?;
+        // The second scan waits for the batches of the first
+        let second = scan.execute(0, ctx.task_ctx())?;
+        assert_eq!(common::collect(first).await?.len(), 1);
+        assert_eq!(common::collect(second).await?.len(), 2);
+        let cached_bytes = cache.cached_bytes();
+        assert!(cached_bytes > 0);
+
+        // Later scans replay the kept batches
+        let third = scan.execute(0, ctx.task_ctx())?;
+        assert_eq!(common::collect(third).await?.len(), 2);
+        assert_eq!(cache.cached_bytes(), cached_bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_partition_twice_in_one_task() -> Result<()> {
+        let ctx = SessionContext::new();
+        let cache = Arc::new(ScanCache::new(1 << 20));
+        let scan = SharedScanExec::new(memory_exec()?, cache.clone());
+
+        // Like the sides of a self-join, the stream executed first is read last
+        let first = scan.execute(0, ctx.task_ctx())?;
+        let second = scan.execute(0, ctx.task_ctx())?;
+        assert_eq!(common::collect(second).await?.len(), 2);
+        assert_eq!(common::collect(first).await?.len(), 2);
+        assert!(is_cached(&cache, &scan.partition_key(0)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_least_recently_used_partitions() -> Result<()> {
+        let ctx = SessionContext::new();
+        let scan = SharedScanExec::new(memory_exec()?, Arc::new(ScanCache::new(1 << 20)));
+        common::collect(scan.execute(0, ctx.task_ctx())?).await?;
+        let partition_bytes = scan.cache.cached_bytes();
+
+        // Two of the three partitions fit in the cache
+        let cache = Arc::new(ScanCache::new(2 * partition_bytes + 1));
+        let scan = SharedScanExec::new(memory_exec()?, cache.clone());
+        for partition in [0, 1, 0, 2] {
+            common::collect(scan.execute(partition, ctx.task_ctx())?).await?;
+        }
+        assert!(is_cached(&cache, &scan.partition_key(0)));
+        assert!(!is_cached(&cache, &scan.partition_key(1)));
+        assert!(is_cached(&cache, &scan.partition_key(2)));
+        assert_eq!(cache.cached_bytes(), 2 * partition_bytes);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn scan_partitions_not_kept() -> Result<()> {
+        let ctx = SessionContext::new();
+        let cache = Arc::new(ScanCache::new(1));
+        let scan = SharedScanExec::new(memory_exec()?, cache.clone());
+
+        let first = scan.execute(0, ctx.task_ctx())?;
+        let second = scan.execute(0, ctx.task_ctx())?;
+        assert_eq!(common::collect(first).await?.len(), 2);
+        // The partition is larger than the cache, so the second scan reads it again
+        assert_eq!(common::collect(second).await?.len(), 2);
+        assert_eq!(cache.cached_bytes(), 0);
+
+        // Scans stopped early are not kept either
+        let cache = Arc::new(ScanCache::new(1 << 20));
+        let scan = SharedScanExec::new(memory_exec()?, cache.clone());
+        let mut stopped = scan.execute(0, ctx.task_ctx())?;
+        stopped.next().await.unwrap()?;
+        drop(stopped);
+        assert_eq!(cache.cached_bytes(), 0);
+        assert_eq!(
+            common::collect(scan.execute(0, ctx.task_ctx())?)
+                .await?
+                .len(),
+            2
+        );
+        Ok(())
+    }
+}
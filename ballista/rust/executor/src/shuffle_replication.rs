@@ -0,0 +1,412 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Copies of shuffle partitions kept by a second executor.
+//!
+//! When the scheduler asks a task to replicate its output, the executor pushes each
+//! shuffle file the task wrote to the executor the scheduler chose with a Flight `DoPut`
+//! call, once the task has written them. The receiving executor keeps the copy in the
+//! directory of the partition in its work dir, next to its own shuffle files of the job,
+//! so the copy is cleaned up with them. Should the executor which wrote the partition be
+//! lost, the scheduler has the stages reading it fetch the copy instead of running the
+//! task again.
+//!
+//! Copies are only accepted from the peers of [ReplicaSenders], which over TCP requires
+//! executors to authenticate each other with TLS client certificates, and up to a
+//! maximum size. They also carry the encryption key of the job, which never replaces
+//! the key the executor already has for the job.
+
+use std::path::Path;
+
+use crate::executor::{Executor, TaskOutput};
+use arrow_flight::FlightData;
+use ballista_core::client::BallistaClient;
+use ballista_core::encryption::EncryptionKey;
+use ballista_core::error::BallistaError;
+use ballista_core::serde::protobuf;
+use ballista_core::serde::scheduler::ExecutorMetadata;
+use ballista_core::tls::certificate_valid_for;
+use futures::{Stream, StreamExt};
+use log::{info, warn};
+use prost::Message;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tonic::{Request, Status};
+
+/// The peers an executor accepts copies of shuffle partitions from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicaSenders {
+    /// No peer, as peers can not be authenticated
+    Nobody,
+    /// Peers presenting a verified TLS client certificate, which must be issued for
+    /// `domain` if set, the name all executors have in their certificates
+    Certified { domain: Option<String> },
+    /// Any peer, for services only the processes of the user of the executor connect to
+    Anyone,
+}
+
+impl Default for ReplicaSenders {
+    fn default() -> Self {
+        Self::Nobody
+    }
+}
+
+impl ReplicaSenders {
+    /// Reject `request` unless it comes from a peer which may send copies
+    pub fn authenticate<R>(&self, request: &Request<R>) -> Result<(), Status> {
+        let domain = match self {
+            Self::Nobody => {
+                return Err(Status::permission_denied(
+                    "Executor does not accept shuffle copies",
+                ))
+            }
+            Self::Anyone => return Ok(()),
+            Self::Certified { domain } => domain,
+        };
+        let cert = request
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|cert| cert.get_ref().to_vec()));
+        match cert {
+            Some(cert)
+                if domain
+                    .as_ref()
+                    .map_or(true, |domain| certificate_valid_for(&cert, domain)) =>
+            {
+                Ok(())
+            }
+            Some(_) => {
+                warn!(
+                    "Refusing shuffle copy from {:?} as its certificate was not issued for an executor",
+                    request.remote_addr()
+                );
+                Err(Status::permission_denied(
+                    "The client certificate was not issued for an executor",
+                ))
+            }
+            None => {
+                warn!(
+                    "Refusing shuffle copy from {:?} without a client certificate",
+                    request.remote_addr()
+                );
+                Err(Status::unauthenticated(
+                    "Executors must present a client certificate",
+                ))
+            }
+        }
+    }
+}
+
+/// Copy the shuffle partitions in `output`, written by a task of stage `stage_id` of
+/// job `job_id`, to executor `replica` if given. The task still succeeds if they fail to
+/// be copied, without a copy.
+pub async fn replicate_output(
+    executor: &Executor,
+    job_id: &str,
+    stage_id: usize,
+    mut output: TaskOutput,
+    replica: Option<protobuf::ExecutorMetadata>,
+) -> TaskOutput {
+    if let Some(replica) = replica {
+        match copy_partitions(executor, job_id, stage_id, &output, replica.clone()).await
+        {
+            Ok(paths) => {
+                output.replica = Some(protobuf::ShuffleReplica {
+                    executor: Some(replica),
+                    paths,
+                })
+            }
+            Err(e) => warn!(
+                "Failed to copy the shuffle partitions of stage {} of job {} to executor {}: {:?}",
+                stage_id, job_id, replica.id, e
+            ),
+        }
+    }
+    output
+}
+
+async fn copy_partitions(
+    executor: &Executor,
+    job_id: &str,
+    stage_id: usize,
+    output: &TaskOutput,
+    replica: protobuf::ExecutorMetadata,
+) -> Result<Vec<String>, BallistaError> {
+    let replica = ExecutorMetadata::from(replica);
+    let encryption_key = executor.encryption_key(job_id);
+    let mut client = BallistaClient::try_new_for_executor(&replica).await?;
+
+    let mut paths = Vec::with_capacity(output.partitions.len());
+    for partition in &output.partitions {
+        let path = client
+            .replicate_partition(
+                job_id,
+                stage_id,
+                partition.partition_id as usize,
+                &partition.path,
+                encryption_key.as_ref(),
+            )
+            .await?;
+        paths.push(path);
+    }
+    info!(
+        "Copied {} shuffle partitions of stage {} of job {} to executor {}",
+        paths.len(),
+        stage_id,
+        job_id,
+        replica.id
+    );
+    Ok(paths)
+}
+
+/// Keep the copy of a shuffle partition of up to `max_bytes` bytes sent by another
+/// executor in `messages`, returning the path it is kept at
+pub async fn receive_replica<S>(
+    executor: &Executor,
+    mut messages: S,
+    max_bytes: u64,
+) -> Result<String, BallistaError>
+where
+    S: Stream<Item = Result<FlightData, Status>> + Unpin,
+{
+    let first =
+        messages.next().await.transpose()?.ok_or_else(|| {
+            BallistaError::General("Received no shuffle partition".into())
+        })?;
+    let replica = first
+        .flight_descriptor
+        .as_ref()
+        .map(|descriptor| protobuf::ReplicatePartition::decode(descriptor.cmd.as_slice()))
+        .transpose()
+        .map_err(|e| BallistaError::General(format!("Invalid shuffle partition: {}", e)))?
+        .ok_or_else(|| {
+            BallistaError::General("Shuffle partition sent without a descriptor".into())
+        })?;
+    if replica.num_bytes > max_bytes {
+        return Err(BallistaError::General(format!(
+            "Shuffle partition of {} bytes is larger than the {} bytes copies may have",
+            replica.num_bytes, max_bytes
+        )));
+    }
+
+    let path = executor.replica_path(
+        &replica.job_id,
+        replica.stage_id as usize,
+        replica.partition_id as usize,
+        &replica.file_name,
+    )?;
+    if let Some(key) = EncryptionKey::from_proto(&replica.encryption_key)? {
+        executor.add_encryption_key(&replica.job_id, key)?;
+    }
+
+    // Written aside and renamed, so a partial copy is never served
+    let partial = path.with_extension("partial");
+    match write_replica(&partial, first, messages, replica.num_bytes).await {
+        Ok(()) => tokio::fs::rename(&partial, &path).await?,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+    }
+    path.to_str().map(|path| path.to_owned()).ok_or_else(|| {
+        BallistaError::General(format!("Invalid shuffle file path {:?}", path))
+    })
+}
+
+async fn write_replica<S>(
+    path: &Path,
+    first: FlightData,
+    mut messages: S,
+    num_bytes: u64,
+) -> Result<(), BallistaError>
+where
+    S: Stream<Item = Result<FlightData, Status>> + Unpin,
+{
+    let mut file = File::create(path).await?;
+    let mut written = 0;
+    let mut message = Some(first);
+    while let Some(data) = message {
+        written += data.data_body.len() as u64;
+        // Peers may not send more bytes than they announced, which were checked
+        // against the maximum size
+        if written > num_bytes {
+            break;
+        }
+        file.write_all(&data.data_body).await?;
+        message = messages.next().await.transpose()?;
+    }
+    file.flush().await?;
+    if written != num_bytes {
+        return Err(BallistaError::General(format!(
+            "Received {} bytes of a shuffle partition of {} bytes",
+            written, num_bytes
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::LoggingMetricsCollector;
+    use arrow_flight::flight_descriptor::DescriptorType;
+    use arrow_flight::FlightDescriptor;
+    use ballista_core::serde::protobuf::ExecutorRegistration;
+    use datafusion::execution::runtime_env::RuntimeEnv;
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn messages(
+        file_name: &str,
+        num_bytes: u64,
+        chunks: &[&[u8]],
+    ) -> impl Stream<Item = Result<FlightData, Status>> + Unpin {
+        messages_with_key(file_name, num_bytes, chunks, None)
+    }
+
+    fn messages_with_key(
+        file_name: &str,
+        num_bytes: u64,
+        chunks: &[&[u8]],
+        key: Option<&EncryptionKey>,
+    ) -> impl Stream<Item = Result<FlightData, Status>> + Unpin {
+        let replica = protobuf::ReplicatePartition {
+            job_id: "job1".to_owned(),
+            stage_id: 1,
+            partition_id: 2,
+            file_name: file_name.to_owned(),
+            num_bytes,
+            encryption_key: key.map(|key| key.as_bytes().to_vec()).unwrap_or_default(),
+        };
+        let first = FlightData {
+            flight_descriptor: Some(FlightDescriptor {
+                r#type: DescriptorType::Cmd.into(),
+                cmd: replica.encode_to_vec(),
+                path: vec![],
+            }),
+            ..Default::default()
+        };
+        let chunks: Vec<FlightData> = chunks
+            .iter()
+            .map(|chunk| FlightData {
+                data_body: chunk.to_vec(),
+                ..Default::default()
+            })
+            .collect();
+        futures::stream::iter(std::iter::once(first).chain(chunks).map(Ok))
+    }
+
+    #[tokio::test]
+    async fn receive_replicas() {
+        let work_dir = TempDir::new().unwrap();
+        let executor = Executor::new(
+            ExecutorRegistration {
+                id: "executor".to_owned(),
+                optional_host: None,
+                port: 50051,
+                grpc_port: 50052,
+                specification: None,
+                labels: vec![],
+                protocol: None,
+            },
+            work_dir.path().to_str().unwrap(),
+            Arc::new(RuntimeEnv::default()),
+            Arc::new(LoggingMetricsCollector::default()),
+            4,
+        );
+        let partition_dir = work_dir.path().join("job1").join("1").join("2");
+
+        let path = receive_replica(
+            &executor,
+            messages("data-0.arrow", 6, &[b"foo", b"bar"]),
+            1024,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            Path::new(&path),
+            partition_dir.join("replica-data-0.arrow").as_path()
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"foobar");
+
+        // A copy missing some of the bytes of the file is not kept
+        let truncated =
+            receive_replica(&executor, messages("data-1.arrow", 7, &[b"foo"]), 1024);
+        assert!(truncated.await.is_err());
+        assert!(!partition_dir.join("replica-data-1.arrow").exists());
+        assert!(!partition_dir.join("replica-data-1.partial").exists());
+
+        let outside =
+            receive_replica(&executor, messages("../data-2.arrow", 3, &[b"foo"]), 1024);
+        assert!(outside.await.is_err());
+        assert!(!work_dir.path().join("job1/1/replica-data-2.arrow").exists());
+
+        // Copies larger than the maximum size, or than they announced, are not kept
+        let large =
+            receive_replica(&executor, messages("data-3.arrow", 6, &[b"foobar"]), 5);
+        assert!(large.await.is_err());
+        let longer =
+            receive_replica(&executor, messages("data-3.arrow", 3, &[b"foobar"]), 5);
+        assert!(longer.await.is_err());
+        assert!(!partition_dir.join("replica-data-3.arrow").exists());
+
+        // The key of a job is never replaced
+        let key = EncryptionKey::generate();
+        executor.add_encryption_key("job1", key.clone()).unwrap();
+        let other_key = EncryptionKey::generate();
+        let replaced = receive_replica(
+            &executor,
+            messages_with_key("data-4.arrow", 3, &[b"foo"], Some(&other_key)),
+            1024,
+        );
+        assert!(replaced.await.is_err());
+        assert!(executor.encryption_key("job1") == Some(key.clone()));
+        let same = receive_replica(
+            &executor,
+            messages_with_key("data-4.arrow", 3, &[b"foo"], Some(&key)),
+            1024,
+        );
+        assert!(same.await.is_ok());
+    }
+
+    #[test]
+    fn authenticate_replica_senders() {
+        let request = Request::new(());
+        assert!(ReplicaSenders::Anyone.authenticate(&request).is_ok());
+        assert_eq!(
+            ReplicaSenders::Nobody
+                .authenticate(&request)
+                .unwrap_err()
+                .code(),
+            tonic::Code::PermissionDenied
+        );
+        // Requests without a client certificate come from unauthenticated peers
+        let certified = ReplicaSenders::Certified {
+            domain: Some("executor.ballista".to_owned()),
+        };
+        assert_eq!(
+            certified.authenticate(&request).unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            ReplicaSenders::Certified { domain: None }
+                .authenticate(&request)
+                .unwrap_err()
+                .code(),
+            tonic::Code::Unauthenticated
+        );
+    }
+}
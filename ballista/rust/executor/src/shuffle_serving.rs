@@ -0,0 +1,342 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Limits of the shuffle data served by the executor.
+//!
+//! The tasks of a reduce stage fetch their input partitions from the executors which ran
+//! the map stages before it. A reduce stage of thousands of tasks can fetch more
+//! partitions from an executor at once than its disks read while its own tasks keep
+//! running, so the Flight service of the executor can cap the partitions it sends at
+//! once and the bytes it sends per second. Fetches beyond the cap wait for another
+//! partition to be sent, and the messages of all partitions are sent no faster than the
+//! rate allows.
+//!
+//! Waiting fetches start in the [ShuffleFetchOrder] of the executor, either as they
+//! arrived or by the priority the scheduler gave the stage fetching them, so that the
+//! reduce stages closest to completing their job, or those of the most important jobs,
+//! are not held up by the fetches of other stages.
+
+use ballista_core::config::ShuffleFetchOrder;
+use ballista_core::serde::scheduler::FetchPriority;
+use parking_lot::Mutex;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Bytes of at most this long without sending are sent at once
+const BURST: Duration = Duration::from_secs(1);
+
+/// The limits of the partitions the executor sends at once and of their bytes per
+/// second, which are unlimited by default
+#[derive(Default)]
+pub struct ShuffleServingLimits {
+    /// Partitions sent at once, if capped
+    streams: Option<Arc<StreamSlots>>,
+    /// Bytes sent per second, if limited
+    rate: Option<RateLimiter>,
+}
+
+impl ShuffleServingLimits {
+    /// Send up to `max_streams` partitions at once, starting those waiting in
+    /// `fetch_order`, and up to `max_bytes_per_second` bytes per second, each unlimited
+    /// if 0
+    pub fn new(
+        max_streams: usize,
+        max_bytes_per_second: usize,
+        fetch_order: ShuffleFetchOrder,
+    ) -> Self {
+        Self {
+            streams: (max_streams > 0)
+                .then(|| Arc::new(StreamSlots::new(max_streams, fetch_order))),
+            rate: (max_bytes_per_second > 0)
+                .then(|| RateLimiter::new(max_bytes_per_second)),
+        }
+    }
+
+    /// Whether the partitions sent are limited at all
+    pub fn is_limited(&self) -> bool {
+        self.streams.is_some() || self.rate.is_some()
+    }
+
+    /// Wait until another partition fetched with `priority` can be sent, returning the
+    /// permit to hold while it is, if the partitions are capped. Fetches of the output of
+    /// jobs have no priority.
+    pub async fn start_stream(
+        &self,
+        priority: Option<FetchPriority>,
+    ) -> Option<StreamPermit> {
+        match &self.streams {
+            Some(streams) => streams.acquire(priority).await,
+            None => None,
+        }
+    }
+
+    /// Wait until `bytes` more can be sent
+    pub async fn throttle(&self, bytes: usize) {
+        if let Some(rate) = &self.rate {
+            let delay = rate.reserve(bytes, Instant::now());
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// The partitions which may be sent at once, given to the waiting fetches in order
+struct StreamSlots {
+    order: ShuffleFetchOrder,
+    state: Mutex<SlotState>,
+}
+
+struct SlotState {
+    available: usize,
+    waiting: BinaryHeap<Waiting>,
+    /// Arrival number of the next fetch to wait
+    next_seq: u64,
+}
+
+/// A fetch waiting for a partition to be sent, the greatest of which is started first
+struct Waiting {
+    rank: (bool, u32, u32),
+    seq: u64,
+    tx: oneshot::Sender<StreamPermit>,
+}
+
+impl PartialEq for Waiting {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiting {}
+
+impl PartialOrd for Waiting {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiting {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Fetches of the same rank start in the order they arrived
+        (self.rank, Reverse(self.seq)).cmp(&(other.rank, Reverse(other.seq)))
+    }
+}
+
+/// The rank of a fetch with `priority` in `order`, the highest first. Fetches of the
+/// output of jobs, without a priority, rank above all others.
+fn rank(order: ShuffleFetchOrder, priority: Option<FetchPriority>) -> (bool, u32, u32) {
+    let priority = match priority {
+        Some(priority) => priority,
+        None => return (true, 0, 0),
+    };
+    let urgency = u32::MAX - priority.stages_remaining;
+    match order {
+        ShuffleFetchOrder::Arrival => (false, 0, 0),
+        ShuffleFetchOrder::CriticalStage => (false, urgency, priority.job_priority),
+        ShuffleFetchOrder::JobPriority => (false, priority.job_priority, urgency),
+    }
+}
+
+impl StreamSlots {
+    fn new(slots: usize, order: ShuffleFetchOrder) -> Self {
+        Self {
+            order,
+            state: Mutex::new(SlotState {
+                available: slots,
+                waiting: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+        }
+    }
+
+    async fn acquire(
+        self: &Arc<Self>,
+        priority: Option<FetchPriority>,
+    ) -> Option<StreamPermit> {
+        let rx = {
+            let mut state = self.state.lock();
+            if state.available > 0 {
+                state.available -= 1;
+                return Some(StreamPermit {
+                    slots: Some(self.clone()),
+                });
+            }
+            let (tx, rx) = oneshot::channel();
+            let seq = state.next_seq;
+            state.next_seq += 1;
+            state.waiting.push(Waiting {
+                rank: rank(self.order, priority),
+                seq,
+                tx,
+            });
+            rx
+        };
+        // Waiting fetches are only dropped once they were sent a permit
+        rx.await.ok()
+    }
+
+    /// Give a released slot to the first waiting fetch still waiting, if any
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock();
+        while let Some(waiting) = state.waiting.pop() {
+            let permit = StreamPermit {
+                slots: Some(self.clone()),
+            };
+            match waiting.tx.send(permit) {
+                Ok(()) => return,
+                // The client stopped fetching the partition before it started, so the
+                // permit must not be released again
+                Err(mut permit) => permit.slots = None,
+            }
+        }
+        state.available += 1;
+    }
+}
+
+/// The permit to hold while a partition is sent, whose slot is given to the next
+/// waiting fetch once dropped
+pub struct StreamPermit {
+    slots: Option<Arc<StreamSlots>>,
+}
+
+impl Drop for StreamPermit {
+    fn drop(&mut self) {
+        if let Some(slots) = self.slots.take() {
+            slots.release();
+        }
+    }
+}
+
+struct RateLimiter {
+    bytes_per_second: f64,
+    /// When the bytes reserved so far are sent at the rate
+    sent_at: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(bytes_per_second: usize) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second as f64,
+            sent_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserve the sending of `bytes` at `now`, returning how long to wait before
+    /// sending them
+    fn reserve(&self, bytes: usize, now: Instant) -> Duration {
+        let mut sent_at = self.sent_at.lock();
+        // The rate left unused during the last BURST is used at once
+        let start = (*sent_at).max(now.checked_sub(BURST).unwrap_or(now));
+        *sent_at = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second);
+        sent_at.saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limit_bytes_per_second() {
+        let rate = RateLimiter::new(1000);
+        let now = Instant::now() + BURST;
+        // A burst of one second of the rate is sent at once
+        assert_eq!(rate.reserve(750, now), Duration::ZERO);
+        assert_eq!(rate.reserve(250, now), Duration::ZERO);
+        assert_eq!(rate.reserve(500, now), Duration::from_millis(500));
+        assert_eq!(rate.reserve(500, now), Duration::from_secs(1));
+        // Waiting makes room for more bytes
+        let later = now + Duration::from_secs(3);
+        assert_eq!(rate.reserve(1000, later), Duration::ZERO);
+        assert_eq!(rate.reserve(250, later), Duration::from_millis(250));
+    }
+
+    fn priority(job_priority: u32, stages_remaining: u32) -> Option<FetchPriority> {
+        Some(FetchPriority {
+            job_priority,
+            stages_remaining,
+        })
+    }
+
+    #[tokio::test]
+    async fn cap_streams() {
+        let limits = ShuffleServingLimits::new(2, 0, ShuffleFetchOrder::Arrival);
+        assert!(limits.is_limited());
+        let first = limits.start_stream(None).await;
+        let _second = limits.start_stream(None).await;
+        assert!(first.is_some());
+        assert_eq!(limits.streams.as_ref().unwrap().state.lock().available, 0);
+        drop(first);
+        assert!(limits.start_stream(None).await.is_some());
+
+        let unlimited = ShuffleServingLimits::default();
+        assert!(!unlimited.is_limited());
+        assert!(unlimited.start_stream(None).await.is_none());
+        unlimited.throttle(usize::MAX).await;
+    }
+
+    #[tokio::test]
+    async fn start_critical_fetches_first() {
+        let limits = ShuffleServingLimits::new(1, 0, ShuffleFetchOrder::CriticalStage);
+        let first = limits.start_stream(priority(0, 0)).await;
+        let mut early = Box::pin(limits.start_stream(priority(9, 3)));
+        let mut critical = Box::pin(limits.start_stream(priority(0, 1)));
+        let mut cancelled = Box::pin(limits.start_stream(priority(0, 0)));
+        let mut output = Box::pin(limits.start_stream(None));
+        assert!(futures::poll!(&mut early).is_pending());
+        assert!(futures::poll!(&mut critical).is_pending());
+        assert!(futures::poll!(&mut cancelled).is_pending());
+        assert!(futures::poll!(&mut output).is_pending());
+
+        // The slot skips the fetch which stopped waiting, and goes to the fetch of the
+        // output of a job first
+        drop(cancelled);
+        drop(first);
+        assert!(futures::poll!(&mut critical).is_pending());
+        let output = output.await;
+        assert!(output.is_some());
+        drop(output);
+        assert!(futures::poll!(&mut early).is_pending());
+        let critical = critical.await;
+        assert!(critical.is_some());
+        drop(critical);
+        assert!(early.await.is_some());
+        assert_eq!(limits.streams.as_ref().unwrap().state.lock().available, 1);
+    }
+
+    #[test]
+    fn rank_fetches() {
+        let (urgent, important) = (priority(0, 1), priority(5, 2));
+        let order = ShuffleFetchOrder::CriticalStage;
+        assert!(rank(order, urgent) > rank(order, important));
+        let order = ShuffleFetchOrder::JobPriority;
+        assert!(rank(order, urgent) < rank(order, important));
+        let order = ShuffleFetchOrder::Arrival;
+        assert_eq!(rank(order, urgent), rank(order, important));
+        // Fetches of the output of jobs come first in every order
+        for order in [
+            ShuffleFetchOrder::Arrival,
+            ShuffleFetchOrder::CriticalStage,
+            ShuffleFetchOrder::JobPriority,
+        ] {
+            assert!(rank(order, None) > rank(order, priority(u32::MAX, 0)));
+        }
+    }
+}
@@ -18,6 +18,7 @@
 use crate::metrics::LoggingMetricsCollector;
 use crate::{execution_loop, executor::Executor, flight_service::BallistaFlightService};
 use arrow_flight::flight_service_server::FlightServiceServer;
+use ballista_core::protocol::ProtocolInfo;
 use ballista_core::serde::scheduler::ExecutorSpecification;
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
 use ballista_core::{
@@ -63,6 +64,8 @@ pub async fn new_standalone_executor<
             }
             .into(),
         ),
+        labels: vec![],
+        protocol: Some(ProtocolInfo::current().into()),
     };
     let work_dir = TempDir::new()?
         .into_path()
@@ -0,0 +1,172 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Hooks invoked around the tasks an executor runs.
+//!
+//! [TaskInterceptor]s are installed with
+//! [Executor::with_task_interceptor](crate::executor::Executor::with_task_interceptor)
+//! by the processes embedding an executor. They are invoked in the order they were
+//! installed before each task runs, and in the reverse order once it finished, on the
+//! thread running the task.
+
+use crate::executor::TaskOutput;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf::PartitionId;
+use datafusion::physical_plan::ExecutionPlan;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+/// A task run by the executor
+#[derive(Debug)]
+pub struct InterceptedTask {
+    pub task_id: PartitionId,
+    pub attempt: u32,
+    /// The plan of the task, which writes partition `task_id.partition_id` of its stage
+    pub plan: Arc<dyn ExecutionPlan>,
+    /// The settings of the session which submitted the job of the task
+    pub props: HashMap<String, String>,
+}
+
+/// Hooks invoked around each task an executor runs, for instrumentation, admission
+/// checks or setting up the environment of the tasks
+pub trait TaskInterceptor: Send + Sync {
+    /// Invoked before `task` runs. The task fails without running if an error is
+    /// returned.
+    fn before_task(&self, _task: &InterceptedTask) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked once `task` finished with `result`, if [TaskInterceptor::before_task]
+    /// succeeded for it, including when it failed, timed out or was killed
+    fn after_task(&self, _task: &InterceptedTask, _result: &Result<TaskOutput>) {}
+}
+
+/// Run `run`, the execution of `task`, surrounded by `interceptors`
+pub(crate) async fn intercept(
+    interceptors: &[Arc<dyn TaskInterceptor>],
+    task: &InterceptedTask,
+    run: impl Future<Output = Result<TaskOutput>>,
+) -> Result<TaskOutput> {
+    let mut admitted = 0;
+    let mut rejection: Option<BallistaError> = None;
+    for interceptor in interceptors {
+        match interceptor.before_task(task) {
+            Ok(()) => admitted += 1,
+            Err(e) => {
+                rejection = Some(e);
+                break;
+            }
+        }
+    }
+    let result = match rejection {
+        Some(e) => Err(e),
+        None => run.await,
+    };
+    for interceptor in interceptors[..admitted].iter().rev() {
+        interceptor.after_task(task, &result);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::Schema;
+    use datafusion::physical_plan::empty::EmptyExec;
+    use parking_lot::Mutex;
+
+    /// Records the calls it gets, and rejects the tasks of job "rejected"
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl TaskInterceptor for Recorder {
+        fn before_task(&self, task: &InterceptedTask) -> Result<()> {
+            self.calls.lock().push(format!("before {}", self.name));
+            if self.name == "second" && task.task_id.job_id == "rejected" {
+                return Err(BallistaError::General("rejected".to_owned()));
+            }
+            Ok(())
+        }
+
+        fn after_task(&self, _task: &InterceptedTask, result: &Result<TaskOutput>) {
+            self.calls
+                .lock()
+                .push(format!("after {} ({})", self.name, result.is_ok()));
+        }
+    }
+
+    fn task(job_id: &str) -> InterceptedTask {
+        InterceptedTask {
+            task_id: PartitionId {
+                job_id: job_id.to_owned(),
+                stage_id: 1,
+                partition_id: 0,
+            },
+            attempt: 0,
+            plan: Arc::new(EmptyExec::new(false, Arc::new(Schema::empty()))),
+            props: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn intercept_tasks() {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let interceptors: Vec<Arc<dyn TaskInterceptor>> = vec!["first", "second"]
+            .into_iter()
+            .map(|name| {
+                Arc::new(Recorder {
+                    name,
+                    calls: calls.clone(),
+                }) as Arc<dyn TaskInterceptor>
+            })
+            .collect();
+        let output = || async {
+            Ok(TaskOutput {
+                partitions: vec![],
+                bytes_scanned: 0,
+                spilled_bytes: 0,
+                spill_files: 0,
+                bloom_filter: vec![],
+                replica: None,
+                operator_metrics: vec![],
+            })
+        };
+
+        let result = intercept(&interceptors, &task("job"), output()).await;
+        assert!(result.is_ok());
+        assert_eq!(
+            calls.lock().drain(..).collect::<Vec<_>>(),
+            vec![
+                "before first",
+                "before second",
+                "after second (true)",
+                "after first (true)"
+            ]
+        );
+
+        // Only the interceptors which admitted the task are invoked after it
+        let result = intercept(&interceptors, &task("rejected"), output()).await;
+        assert!(result.is_err());
+        assert_eq!(
+            calls.lock().drain(..).collect::<Vec<_>>(),
+            vec!["before first", "before second", "after first (false)"]
+        );
+    }
+}
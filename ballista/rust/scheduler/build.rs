@@ -23,7 +23,11 @@ fn main() -> Result<(), String> {
         .map_err(|e| format!("configure_me code generation failed: {}", e))?;
 
     println!("cargo:rerun-if-changed=proto/keda.proto");
+    let out_dir = std::env::var("OUT_DIR").map_err(|e| e.to_string())?;
     tonic_build::configure()
+        .file_descriptor_set_path(
+            std::path::Path::new(&out_dir).join("externalscaler_descriptor.bin"),
+        )
         .compile(&["proto/keda.proto"], &["proto"])
         .map_err(|e| format!("protobuf compilation failed: {}", e))
 }
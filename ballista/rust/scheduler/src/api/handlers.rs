@@ -10,10 +10,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::api::model::{
+    job_response, stage_plan_responses, stage_responses, task_responses,
+    ExecutorResponse, HealthResponse, JobFilter, JobSummary, MetadataCacheParams,
+    PlanExportParams, PoolResponse, QueryHistoryParams, QueryHistoryResponse,
+    TenantUsageResponse,
+};
+use crate::auth::{Operation, Principal};
+use crate::plan_export::{PlanFormat, PlanGraph};
+use crate::query_history::{QueryRunStats, MAX_RUNS};
 use crate::scheduler_server::SchedulerServer;
+use ballista_core::auth::parse_bearer_token;
+use ballista_core::serde::protobuf::{executor_metric, job_status};
 use ballista_core::serde::AsExecutionPlan;
 use ballista_core::BALLISTA_VERSION;
 use datafusion_proto::logical_plan::AsLogicalPlan;
+use log::warn;
+use std::collections::BTreeMap;
+use tonic::{Code, Status};
+use warp::http::{header::CONTENT_TYPE, Response, StatusCode};
 use warp::Rejection;
 
 #[derive(Debug, serde::Serialize)]
@@ -56,3 +71,721 @@ pub(crate) async fn scheduler_state<T: AsLogicalPlan, U: AsExecutionPlan>(
     };
     Ok(warp::reply::json(&response))
 }
+
+/// Expose the scheduler metrics in the Prometheus text format
+pub(crate) async fn scheduler_metrics<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    if let Err(e) = data_server.state.refresh_metrics().await {
+        warn!("Failed to refresh scheduler metrics: {:?}", e);
+    }
+
+    let metrics = &data_server.state.metrics;
+    let response = match metrics.gather() {
+        Ok(body) => Response::builder()
+            .header(CONTENT_TYPE, metrics.content_type())
+            .body(body),
+        Err(e) => Response::builder()
+            .status(StatusCode::INTERNAL_SERVER_ERROR)
+            .body(format!("{:?}", e).into_bytes()),
+    };
+    response.map_err(|_| warp::reject())
+}
+
+fn not_found() -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&()), StatusCode::NOT_FOUND)
+}
+
+fn internal_error(message: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(
+        warp::reply::json(&message),
+        StatusCode::INTERNAL_SERVER_ERROR,
+    )
+}
+
+/// List the jobs known to the scheduler which match `filter`
+pub(crate) async fn list_jobs<T: AsLogicalPlan, U: AsExecutionPlan>(
+    filter: JobFilter,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let task_manager = &data_server.state.task_manager;
+    let job_ids = task_manager.get_job_ids().await.map_err(|e| {
+        warn!("Failed to list jobs: {:?}", e);
+        warp::reject()
+    })?;
+
+    let limit = filter.limit.unwrap_or(usize::MAX);
+    let mut jobs = Vec::with_capacity(job_ids.len().min(limit));
+    for job_id in job_ids {
+        if jobs.len() >= limit {
+            break;
+        }
+        if data_server
+            .check_job_owner(principal.as_ref(), &job_id)
+            .await
+            .is_err()
+        {
+            continue;
+        }
+        // A job may move between keyspaces while we are listing them
+        let status = match task_manager.get_job_status(&job_id).await {
+            Ok(Some(status)) => status,
+            _ => continue,
+        };
+        let graph = task_manager.get_execution_graph(&job_id).await.ok();
+        let job = job_response(job_id, &status, graph.as_ref());
+        if filter.matches(&job) {
+            jobs.push(job);
+        }
+    }
+    Ok(warp::reply::json(&jobs))
+}
+
+/// The stage DAG of a job along with the progress of each stage
+pub(crate) async fn job_stages<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    check_job_reader(&data_server, principal, &job_id).await?;
+    let reply = match data_server
+        .state
+        .task_manager
+        .get_execution_graph(&job_id)
+        .await
+    {
+        Ok(graph) => warp::reply::with_status(
+            warp::reply::json(&stage_responses(&graph)),
+            StatusCode::OK,
+        ),
+        Err(_) => not_found(),
+    };
+    Ok(reply)
+}
+
+/// The physical plans of the stages of a job
+pub(crate) async fn job_plan<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    check_job_reader(&data_server, principal, &job_id).await?;
+    let reply = match data_server
+        .state
+        .task_manager
+        .get_execution_graph(&job_id)
+        .await
+    {
+        Ok(graph) => warp::reply::with_status(
+            warp::reply::json(&stage_plan_responses(
+                &graph,
+                !data_server.plan_rewriters().is_empty(),
+            )),
+            StatusCode::OK,
+        ),
+        Err(_) => not_found(),
+    };
+    Ok(reply)
+}
+
+/// The stages of a job, the operators of their plans and the metrics of the stages, as
+/// a Graphviz `dot` graph or as JSON
+pub(crate) async fn export_job_plan<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    params: PlanExportParams,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    check_job_reader(&data_server, principal, &job_id).await?;
+    let format = match params
+        .format
+        .as_deref()
+        .unwrap_or("json")
+        .parse::<PlanFormat>()
+    {
+        Ok(format) => format,
+        Err(e) => {
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(e.to_string().into_bytes())
+                .map_err(|_| warp::reject())
+        }
+    };
+    let task_manager = &data_server.state.task_manager;
+    let status = match task_manager.get_job_status(&job_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(vec![])
+                .map_err(|_| warp::reject())
+        }
+        Err(e) => {
+            warn!("Failed to get the status of job {}: {:?}", job_id, e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(e.to_string().into_bytes())
+                .map_err(|_| warp::reject());
+        }
+    };
+    // Operators are exported without their expressions when plans are rewritten, as
+    // those may be row filters or column masks
+    let redact = !data_server.plan_rewriters().is_empty();
+    let response = match task_manager.find_execution_graph(&job_id).await {
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(vec![]),
+        Ok(Some(graph)) => match PlanGraph::new(&graph, &status, redact).export(format) {
+            Ok(body) => Response::builder()
+                .header(CONTENT_TYPE, format.content_type())
+                .body(body.into_bytes()),
+            Err(e) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(e.to_string().into_bytes()),
+        },
+        Err(e) => {
+            warn!("Failed to get the plan of job {}: {:?}", job_id, e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(e.to_string().into_bytes())
+        }
+    };
+    response.map_err(|_| warp::reject())
+}
+
+/// The tasks of a single stage of a job
+pub(crate) async fn stage_tasks<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    stage_id: usize,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    check_job_reader(&data_server, principal, &job_id).await?;
+    let graph = match data_server
+        .state
+        .task_manager
+        .get_execution_graph(&job_id)
+        .await
+    {
+        Ok(graph) => graph,
+        Err(_) => return Ok(not_found()),
+    };
+    let stage = match graph.stages.get(&stage_id) {
+        Some(stage) => stage,
+        None => return Ok(not_found()),
+    };
+
+    let tasks = task_responses(stage);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&tasks),
+        StatusCode::OK,
+    ))
+}
+
+/// A job along with all of its stages and tasks
+pub(crate) async fn job_detail<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    principal: Option<Principal>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    check_job_reader(&data_server, principal, &job_id).await?;
+    let task_manager = &data_server.state.task_manager;
+    let status = match task_manager.get_job_status(&job_id).await {
+        Ok(Some(status)) => status,
+        _ => return Ok(not_found()),
+    };
+    let summary = match task_manager.get_execution_graph(&job_id).await {
+        Ok(graph) => JobSummary::new(&graph, &status),
+        Err(_) => JobSummary::without_graph(job_id, &status),
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&summary),
+        StatusCode::OK,
+    ))
+}
+
+/// Rejection of a request whose client is not authenticated, or may not perform the
+/// requested operation
+#[derive(Debug)]
+pub(crate) struct ClientRejection(Status);
+
+impl warp::reject::Reject for ClientRejection {}
+
+/// The response to a request failing with `status`
+fn status_reply(status: &Status) -> warp::reply::WithStatus<warp::reply::Json> {
+    let code = match status.code() {
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    warp::reply::with_status(warp::reply::json(&status.message()), code)
+}
+
+/// The client a request with the `authorization` header was made by, `None` if client
+/// authentication is disabled. Fails if the client may not perform `operation`.
+async fn authorize<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: &SchedulerServer<T, U>,
+    authorization: Option<&str>,
+    operation: &Operation<'_>,
+) -> Result<Option<Principal>, Status> {
+    let auth = match &data_server.state.client_auth {
+        Some(auth) => auth,
+        None => return Ok(None),
+    };
+    let principal = auth
+        .authenticate(authorization.and_then(parse_bearer_token))
+        .await?;
+    auth.authorize(&principal, operation).await?;
+    Ok(Some(principal))
+}
+
+/// The response rejecting a request whose client may not perform `operation`, if
+/// client authentication is enabled
+async fn unauthorized<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: &SchedulerServer<T, U>,
+    authorization: Option<&str>,
+    operation: &Operation<'_>,
+) -> Option<warp::reply::WithStatus<warp::reply::Json>> {
+    let status = authorize(data_server, authorization, operation)
+        .await
+        .err()?;
+    Some(status_reply(&status))
+}
+
+/// The client of a request reading the state of the scheduler or of its jobs, `None`
+/// if client authentication is disabled. Rejects clients which may not read it.
+pub(crate) async fn authorize_reader<T: AsLogicalPlan, U: AsExecutionPlan>(
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<Option<Principal>, Rejection> {
+    authorize(
+        &data_server,
+        authorization.as_deref(),
+        &Operation::ReadState,
+    )
+    .await
+    .map_err(|status| warp::reject::custom(ClientRejection(status)))
+}
+
+/// Reject the request of `principal` for job `job_id` if the job belongs to another
+/// client
+async fn check_job_reader<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: &SchedulerServer<T, U>,
+    principal: Option<Principal>,
+    job_id: &str,
+) -> Result<(), Rejection> {
+    data_server
+        .check_job_owner(principal.as_ref(), job_id)
+        .await
+        .map_err(|status| warp::reject::custom(ClientRejection(status)))
+}
+
+/// Respond to the requests rejected with [ClientRejection], passing on other rejections
+pub(crate) async fn recover_client_rejection(
+    rejection: Rejection,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    match rejection.find::<ClientRejection>() {
+        Some(ClientRejection(status)) => Ok(status_reply(status)),
+        None => Err(rejection),
+    }
+}
+
+/// Cancel a queued or running job
+pub(crate) async fn cancel_job<T: AsLogicalPlan, U: AsExecutionPlan>(
+    job_id: String,
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let operation = Operation::CancelJob { job_id: &job_id };
+    let principal =
+        match authorize(&data_server, authorization.as_deref(), &operation).await {
+            Ok(principal) => principal,
+            Err(status) => return Ok(status_reply(&status)),
+        };
+    if let Err(status) = data_server
+        .check_job_owner(principal.as_ref(), &job_id)
+        .await
+    {
+        return Ok(status_reply(&status));
+    }
+
+    let status = match data_server.state.task_manager.get_job_status(&job_id).await {
+        Ok(Some(status)) => status,
+        Ok(None) => return Ok(not_found()),
+        Err(e) => {
+            warn!("Failed to get the status of job {}: {:?}", job_id, e);
+            return Ok(internal_error(e.to_string()));
+        }
+    };
+    if matches!(
+        status.status,
+        Some(job_status::Status::Completed(_)) | Some(job_status::Status::Failed(_))
+    ) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&job_response(job_id, &status, None)),
+            StatusCode::CONFLICT,
+        ));
+    }
+
+    if let Err(e) = data_server
+        .cancel_job(&job_id, authorization.as_deref())
+        .await
+    {
+        warn!("Failed to cancel job {}: {:?}", job_id, e);
+        return Ok(internal_error(e.to_string()));
+    }
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::ACCEPTED,
+    ))
+}
+
+/// Drop the cached metadata of the objects below the URL of `params`, or of all
+/// objects if it has none
+pub(crate) async fn invalidate_metadata_cache<T: AsLogicalPlan, U: AsExecutionPlan>(
+    params: MetadataCacheParams,
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let url = params.url.as_deref();
+    let operation = Operation::InvalidateMetadataCache { url };
+    if let Some(reply) =
+        unauthorized(&data_server, authorization.as_deref(), &operation).await
+    {
+        return Ok(reply);
+    }
+
+    let result = data_server
+        .state
+        .object_store_factories
+        .invalidate_metadata_cache(url);
+    Ok(match result {
+        Ok(()) => warp::reply::with_status(warp::reply::json(&()), StatusCode::OK),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&e.to_string()),
+            StatusCode::BAD_REQUEST,
+        ),
+    })
+}
+
+/// Drop the cached plans of all SQL queries
+pub(crate) async fn invalidate_plan_cache<T: AsLogicalPlan, U: AsExecutionPlan>(
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    if let Some(reply) = unauthorized(
+        &data_server,
+        authorization.as_deref(),
+        &Operation::InvalidatePlanCache,
+    )
+    .await
+    {
+        return Ok(reply);
+    }
+
+    data_server.state.plan_cache.invalidate();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+/// Drop the cached results of all queries
+pub(crate) async fn invalidate_result_cache<T: AsLogicalPlan, U: AsExecutionPlan>(
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    if let Some(reply) = unauthorized(
+        &data_server,
+        authorization.as_deref(),
+        &Operation::InvalidateResultCache,
+    )
+    .await
+    {
+        return Ok(reply);
+    }
+
+    data_server.state.result_cache.invalidate();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+/// Stop scheduling tasks on an executor, letting the tasks it runs finish
+pub(crate) async fn drain_executor<T: AsLogicalPlan, U: AsExecutionPlan>(
+    executor_id: String,
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let operation = Operation::DrainExecutor {
+        executor_id: &executor_id,
+    };
+    if let Some(reply) =
+        unauthorized(&data_server, authorization.as_deref(), &operation).await
+    {
+        return Ok(reply);
+    }
+
+    let drained = data_server
+        .state
+        .executor_manager
+        .drain_executor(&executor_id)
+        .await
+        .map_err(|e| {
+            warn!("Failed to drain executor {}: {:?}", executor_id, e);
+            warp::reject()
+        })?;
+    if !drained {
+        return Ok(not_found());
+    }
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+/// Schedule tasks on a drained executor again
+pub(crate) async fn undrain_executor<T: AsLogicalPlan, U: AsExecutionPlan>(
+    executor_id: String,
+    authorization: Option<String>,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let operation = Operation::DrainExecutor {
+        executor_id: &executor_id,
+    };
+    if let Some(reply) =
+        unauthorized(&data_server, authorization.as_deref(), &operation).await
+    {
+        return Ok(reply);
+    }
+
+    let undrained = data_server
+        .state
+        .executor_manager
+        .undrain_executor(&executor_id)
+        .await
+        .map_err(|e| {
+            warn!("Failed to undrain executor {}: {:?}", executor_id, e);
+            warp::reject()
+        })?;
+    if !undrained {
+        return Ok(not_found());
+    }
+    Ok(warp::reply::with_status(
+        warp::reply::json(&()),
+        StatusCode::OK,
+    ))
+}
+
+/// All executors which ever sent a heartbeat, along with their latest metrics
+pub(crate) async fn list_executors<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let executor_manager = &data_server.state.executor_manager;
+    let alive = executor_manager.get_alive_executors();
+    let draining = executor_manager
+        .get_draining_executors()
+        .await
+        .unwrap_or_else(|e| {
+            warn!("Failed to get draining executors: {:?}", e);
+            Default::default()
+        });
+
+    let mut heartbeats = executor_manager.get_executor_heartbeats();
+    heartbeats.sort_by(|a, b| a.executor_id.cmp(&b.executor_id));
+
+    let mut executors = Vec::with_capacity(heartbeats.len());
+    for heartbeat in heartbeats {
+        let executor_id = heartbeat.executor_id;
+        let metadata = match executor_manager.get_executor_metadata(&executor_id).await {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                warn!(
+                    "Failed to get metadata of executor {}: {:?}",
+                    executor_id, e
+                );
+                continue;
+            }
+        };
+        let (total_task_slots, available_task_slots) =
+            match executor_manager.get_executor_data(&executor_id).await {
+                Ok(data) => (data.total_task_slots, data.available_task_slots),
+                Err(_) => (metadata.specification.task_slots, 0),
+            };
+        let available_memory = heartbeat.state.and_then(|state| {
+            state
+                .metrics
+                .into_iter()
+                .find_map(|metric| match metric.metric {
+                    Some(executor_metric::Metric::AvailableMemory(memory)) => {
+                        Some(memory)
+                    }
+                    _ => None,
+                })
+        });
+
+        executors.push(ExecutorResponse {
+            alive: alive.contains(&executor_id),
+            draining: draining.contains(&executor_id),
+            id: executor_id,
+            host: metadata.host,
+            port: metadata.port,
+            grpc_port: metadata.grpc_port,
+            total_task_slots,
+            available_task_slots,
+            last_heartbeat: heartbeat.timestamp,
+            available_memory,
+            labels: metadata.labels,
+            protocol_version: metadata.protocol.version,
+        });
+    }
+    Ok(warp::reply::json(&executors))
+}
+
+/// The resources used by the finished jobs of each tenant since the scheduler started
+pub(crate) async fn list_tenant_usage<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let usage: Vec<TenantUsageResponse> = data_server
+        .state
+        .task_manager
+        .tenant_usage()
+        .into_iter()
+        .map(|(tenant, usage)| TenantUsageResponse::new(tenant, &usage))
+        .collect();
+    Ok(warp::reply::json(&usage))
+}
+
+/// The task slots of the executors of each pool, and the jobs routed to each pool,
+/// ordered by pool
+pub(crate) async fn list_pools<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let slots = data_server
+        .state
+        .executor_manager
+        .pool_task_slots()
+        .await
+        .map_err(|e| {
+            warn!("Failed to count the task slots of the executors: {:?}", e);
+            warp::reject()
+        })?;
+    let (jobs, reserved) = data_server.state.task_manager.pool_usage();
+
+    let mut pools: BTreeMap<String, PoolResponse> = slots
+        .into_iter()
+        .map(|(pool, slots)| {
+            let response = PoolResponse {
+                pool: pool.clone(),
+                executors: slots.executors,
+                total_task_slots: slots.total_task_slots,
+                available_task_slots: slots.available_task_slots,
+                ..Default::default()
+            };
+            (pool, response)
+        })
+        .collect();
+    // Jobs may be routed to pools without alive executors
+    for (pool, running_jobs) in jobs {
+        pools
+            .entry(pool.clone())
+            .or_insert_with(|| PoolResponse {
+                pool,
+                ..Default::default()
+            })
+            .running_jobs = running_jobs;
+    }
+    for (pool, reserved_task_slots) in reserved {
+        if let Some(response) = pools.get_mut(&pool) {
+            response.reserved_task_slots = reserved_task_slots;
+        }
+    }
+    let pools: Vec<PoolResponse> = pools.into_values().collect();
+    Ok(warp::reply::json(&pools))
+}
+
+/// The runs of the query with plan fingerprint `fingerprint`, if the query history is
+/// enabled
+pub(crate) async fn query_history<T: AsLogicalPlan, U: AsExecutionPlan>(
+    fingerprint: String,
+    params: QueryHistoryParams,
+    data_server: SchedulerServer<T, U>,
+) -> Result<impl warp::Reply, Rejection> {
+    let query_history = match &data_server.state.query_history {
+        Some(query_history) => query_history,
+        None => return Ok(not_found()),
+    };
+    let runs = query_history
+        .runs(&fingerprint, params.limit.unwrap_or(MAX_RUNS))
+        .await
+        .map_err(|e| {
+            warn!(
+                "Failed to read the history of query {}: {:?}",
+                fingerprint, e
+            );
+            warp::reject()
+        })?;
+    let response = QueryHistoryResponse {
+        stats: QueryRunStats::new(&runs),
+        plan_fingerprint: fingerprint,
+        runs,
+    };
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        StatusCode::OK,
+    ))
+}
+
+/// Liveness of the scheduler, which is alive as long as it serves requests
+pub(crate) async fn scheduler_liveness() -> Result<impl warp::Reply, Rejection> {
+    Ok(warp::reply::json(&HealthResponse {
+        status: "ok".to_owned(),
+        backend_latency: None,
+        error: None,
+    }))
+}
+
+/// Readiness of the scheduler, which is not ready once it is shutting down, so that no
+/// new queries are sent to it while it drains its jobs
+pub(crate) async fn scheduler_readiness<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    if data_server.state.shutting_down() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&HealthResponse {
+                status: "shutting_down".to_owned(),
+                backend_latency: None,
+                error: None,
+            }),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ));
+    }
+    scheduler_health(data_server).await
+}
+
+/// Health of the scheduler's state backend, which the scheduler needs to be ready
+pub(crate) async fn scheduler_health<T: AsLogicalPlan, U: AsExecutionPlan>(
+    data_server: SchedulerServer<T, U>,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Rejection> {
+    let reply = match data_server.state.check_backend().await {
+        Ok(latency) => warp::reply::with_status(
+            warp::reply::json(&HealthResponse {
+                status: "ok".to_owned(),
+                backend_latency: Some(latency.as_millis() as u64),
+                error: None,
+            }),
+            StatusCode::OK,
+        ),
+        Err(e) => warp::reply::with_status(
+            warp::reply::json(&HealthResponse {
+                status: "error".to_owned(),
+                backend_latency: None,
+                error: Some(e.to_string()),
+            }),
+            StatusCode::SERVICE_UNAVAILABLE,
+        ),
+    };
+    Ok(reply)
+}
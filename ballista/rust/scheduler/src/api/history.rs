@@ -0,0 +1,109 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handlers of the history server, which serves the REST API of the scheduler from the
+//! event log instead of the scheduler state.
+
+use crate::api::model::TaskResponse;
+use crate::event_log::EventLog;
+use ballista_core::BALLISTA_VERSION;
+use log::warn;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use warp::http::StatusCode;
+use warp::Rejection;
+
+#[derive(Debug, serde::Serialize)]
+struct HistoryStateResponse {
+    executors: Vec<()>,
+    started: u128,
+    version: &'static str,
+    history_server: bool,
+}
+
+fn not_found() -> warp::reply::WithStatus<warp::reply::Json> {
+    warp::reply::with_status(warp::reply::json(&()), StatusCode::NOT_FOUND)
+}
+
+fn internal_error(e: ballista_core::error::BallistaError) -> Rejection {
+    warn!("Failed to read the event log: {:?}", e);
+    warp::reject()
+}
+
+pub(crate) async fn history_state() -> Result<impl warp::Reply, Rejection> {
+    Ok(warp::reply::json(&HistoryStateResponse {
+        executors: vec![],
+        started: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis(),
+        version: BALLISTA_VERSION,
+        history_server: true,
+    }))
+}
+
+/// List all jobs in the event log
+pub(crate) async fn list_jobs(
+    event_log: Arc<EventLog>,
+) -> Result<impl warp::Reply, Rejection> {
+    let job_ids = event_log.job_ids().await.map_err(internal_error)?;
+    let mut jobs = Vec::with_capacity(job_ids.len());
+    for job_id in job_ids {
+        if let Some(job) = event_log.replay(&job_id).await.map_err(internal_error)? {
+            jobs.push(job);
+        }
+    }
+    Ok(warp::reply::json(&jobs))
+}
+
+/// The lifecycle events of a job
+pub(crate) async fn job_events(
+    job_id: String,
+    event_log: Arc<EventLog>,
+) -> Result<impl warp::Reply, Rejection> {
+    let events = event_log.events(&job_id).await.map_err(internal_error)?;
+    Ok(warp::reply::json(&events))
+}
+
+/// The stages of a finished job
+pub(crate) async fn job_stages(
+    job_id: String,
+    event_log: Arc<EventLog>,
+) -> Result<impl warp::Reply, Rejection> {
+    let reply = match event_log.summary(&job_id).await.map_err(internal_error)? {
+        Some(summary) => {
+            warp::reply::with_status(warp::reply::json(&summary.stages), StatusCode::OK)
+        }
+        None => not_found(),
+    };
+    Ok(reply)
+}
+
+/// The tasks of a single stage of a finished job
+pub(crate) async fn stage_tasks(
+    job_id: String,
+    stage_id: usize,
+    event_log: Arc<EventLog>,
+) -> Result<impl warp::Reply, Rejection> {
+    let tasks: Option<Vec<TaskResponse>> = event_log
+        .summary(&job_id)
+        .await
+        .map_err(internal_error)?
+        .and_then(|mut summary| summary.tasks.remove(&stage_id));
+    let reply = match tasks {
+        Some(tasks) => {
+            warp::reply::with_status(warp::reply::json(&tasks), StatusCode::OK)
+        }
+        None => not_found(),
+    };
+    Ok(reply)
+}
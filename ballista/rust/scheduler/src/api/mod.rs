@@ -11,17 +11,26 @@
 // limitations under the License.
 
 mod handlers;
+mod history;
+pub mod model;
 
+use crate::api::model::{
+    JobFilter, MetadataCacheParams, PlanExportParams, QueryHistoryParams,
+};
+use crate::auth::Principal;
+use crate::event_log::EventLog;
 use crate::scheduler_server::SchedulerServer;
 use anyhow::Result;
+use ballista_core::auth::AUTHORIZATION_HEADER;
 use ballista_core::serde::AsExecutionPlan;
 use datafusion_proto::logical_plan::AsLogicalPlan;
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context as TaskContext, Poll},
 };
 use warp::filters::BoxedFilter;
-use warp::{Buf, Filter, Reply};
+use warp::{Buf, Filter, Rejection, Reply};
 
 pub enum EitherBody<A, B> {
     Left(A),
@@ -82,11 +91,245 @@ fn with_data_server<T: AsLogicalPlan + Clone, U: 'static + AsExecutionPlan>(
     warp::any().map(move || db.clone())
 }
 
+/// Authenticates the clients reading the state of the scheduler, if client
+/// authentication is enabled, and extracts them
+fn with_reader<T: AsLogicalPlan + Clone, U: 'static + AsExecutionPlan>(
+    db: SchedulerServer<T, U>,
+) -> impl Filter<Extract = (Option<Principal>,), Error = Rejection> + Clone {
+    warp::header::optional::<String>(AUTHORIZATION_HEADER)
+        .and(with_data_server(db))
+        .and_then(handlers::authorize_reader)
+}
+
+/// Like [with_reader], for the requests which do not depend on the client
+fn reader<T: AsLogicalPlan + Clone, U: 'static + AsExecutionPlan>(
+    db: SchedulerServer<T, U>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    with_reader(db).map(|_| ()).untuple_one()
+}
+
 pub fn get_routes<T: AsLogicalPlan + Clone, U: 'static + AsExecutionPlan>(
     scheduler_server: SchedulerServer<T, U>,
 ) -> BoxedFilter<(impl Reply,)> {
-    let routes = warp::path("state")
-        .and(with_data_server(scheduler_server))
+    let route_state = warp::path("state")
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
         .and_then(handlers::scheduler_state);
+    let route_metrics = warp::path("metrics")
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::scheduler_metrics);
+    let route_jobs = warp::path!("jobs")
+        .and(warp::query::<JobFilter>())
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::list_jobs);
+    let route_job = warp::path!("job" / String)
+        .and(warp::get())
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::job_detail);
+    let route_cancel_job = warp::path!("job" / String / "cancel")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::cancel_job);
+    let route_invalidate_metadata_cache = warp::path!("metadata_cache" / "invalidate")
+        .and(warp::post())
+        .and(warp::query::<MetadataCacheParams>())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::invalidate_metadata_cache);
+    let route_invalidate_plan_cache = warp::path!("plan_cache" / "invalidate")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::invalidate_plan_cache);
+    let route_invalidate_result_cache = warp::path!("result_cache" / "invalidate")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::invalidate_result_cache);
+    let route_executors = warp::path!("executors")
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::list_executors);
+    let route_tenant_usage = warp::path!("tenants" / "usage")
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::list_tenant_usage);
+    let route_pools = warp::path!("pools")
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::list_pools);
+    let route_health = warp::path!("health")
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::scheduler_health);
+    let route_liveness =
+        warp::path!("health" / "live").and_then(handlers::scheduler_liveness);
+    let route_readiness = warp::path!("health" / "ready")
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::scheduler_readiness);
+    let route_job_stages = warp::path!("job" / String / "stages")
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::job_stages);
+    let route_stage_tasks = warp::path!("job" / String / "stage" / usize / "tasks")
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::stage_tasks);
+    let route_job_plan = warp::path!("job" / String / "plan")
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::job_plan);
+    let route_export_job_plan = warp::path!("job" / String / "plan" / "export")
+        .and(warp::get())
+        .and(warp::query::<PlanExportParams>())
+        .and(with_reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::export_job_plan);
+    let route_query_history = warp::path!("history" / String)
+        .and(warp::get())
+        .and(warp::query::<QueryHistoryParams>())
+        .and(reader(scheduler_server.clone()))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::query_history);
+    let route_drain_executor = warp::path!("executor" / String / "drain")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server.clone()))
+        .and_then(handlers::drain_executor);
+    let route_undrain_executor = warp::path!("executor" / String / "undrain")
+        .and(warp::post())
+        .and(warp::header::optional::<String>(AUTHORIZATION_HEADER))
+        .and(with_data_server(scheduler_server))
+        .and_then(handlers::undrain_executor);
+    let routes = route_state
+        .or(route_metrics)
+        .or(route_jobs)
+        .or(route_job)
+        .or(route_cancel_job)
+        .or(route_invalidate_metadata_cache)
+        .or(route_invalidate_plan_cache)
+        .or(route_invalidate_result_cache)
+        .or(route_executors)
+        .or(route_tenant_usage)
+        .or(route_pools)
+        .or(route_health)
+        .or(route_liveness)
+        .or(route_readiness)
+        .or(route_job_stages)
+        .or(route_stage_tasks)
+        .or(route_job_plan)
+        .or(route_export_job_plan)
+        .or(route_query_history)
+        .or(route_drain_executor)
+        .or(route_undrain_executor)
+        .recover(handlers::recover_client_rejection);
+    routes.boxed()
+}
+
+fn with_event_log(
+    event_log: Arc<EventLog>,
+) -> impl Filter<Extract = (Arc<EventLog>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || event_log.clone())
+}
+
+/// Routes of the history server, serving the jobs recorded in `event_log`
+pub fn get_history_routes(event_log: Arc<EventLog>) -> BoxedFilter<(impl Reply,)> {
+    let route_state = warp::path("state").and_then(history::history_state);
+    let route_jobs = warp::path!("jobs")
+        .and(with_event_log(event_log.clone()))
+        .and_then(history::list_jobs);
+    let route_job_events = warp::path!("job" / String / "events")
+        .and(with_event_log(event_log.clone()))
+        .and_then(history::job_events);
+    let route_job_stages = warp::path!("job" / String / "stages")
+        .and(with_event_log(event_log.clone()))
+        .and_then(history::job_stages);
+    let route_stage_tasks = warp::path!("job" / String / "stage" / usize / "tasks")
+        .and(with_event_log(event_log))
+        .and_then(history::stage_tasks);
+    let routes = route_state
+        .or(route_jobs)
+        .or(route_job_events)
+        .or(route_job_stages)
+        .or(route_stage_tasks);
     routes.boxed()
 }
+
+#[cfg(all(test, feature = "sled"))]
+mod tests {
+    use super::get_routes;
+    use crate::auth::{AllowAll, ClientAuth, Principal, StaticTokenValidator};
+    use crate::scheduler_server::SchedulerServer;
+    use crate::state::backend::standalone::StandaloneClient;
+    use ballista_core::error::Result;
+    use ballista_core::serde::protobuf::PhysicalPlanNode;
+    use ballista_core::serde::BallistaCodec;
+    use datafusion_proto::protobuf::LogicalPlanNode;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use warp::http::StatusCode;
+
+    #[tokio::test]
+    async fn plans_of_other_clients() -> Result<()> {
+        let principal = |name: &str| Principal {
+            name: name.to_owned(),
+            roles: vec![],
+        };
+        let tokens = HashMap::from([
+            ("secret-1".to_owned(), principal("alice")),
+            ("secret-2".to_owned(), principal("bob")),
+        ]);
+        let auth = ClientAuth::new(
+            Arc::new(StaticTokenValidator::new(tokens)),
+            Arc::new(AllowAll),
+        );
+        let scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new(
+                Arc::new(StandaloneClient::try_new_temporary()?),
+                "default".to_owned(),
+                BallistaCodec::default(),
+            )
+            .with_client_auth(auth);
+        scheduler.state.task_manager.queue_job("job").await?;
+        scheduler
+            .state
+            .task_manager
+            .save_job_owner("job", "alice")
+            .await?;
+        let routes = get_routes(scheduler);
+        let status = |path: &'static str, token: Option<&'static str>| {
+            let routes = routes.clone();
+            async move {
+                let mut request = warp::test::request().path(path);
+                if let Some(token) = token {
+                    request =
+                        request.header("authorization", format!("Bearer {}", token));
+                }
+                request.reply(&routes).await.status()
+            }
+        };
+
+        // The job is queued, so it has no plan yet
+        assert_eq!(
+            status("/job/job/plan", Some("secret-1")).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status("/job/job/plan", Some("secret-2")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status("/job/job/plan", None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status("/job/job/plan/export?format=dot", Some("secret-2")).await,
+            StatusCode::FORBIDDEN
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! JSON models of jobs, stages and tasks served by the REST API. The same models are
+//! persisted in the event log so the history server can serve completed jobs.
+
+use crate::planner::resource_profile_label;
+use crate::query_history::{QueryRun, QueryRunStats};
+use crate::state::execution_graph::{ExecutionGraph, ExecutionStage, StageStatus};
+use crate::state::tenant_usage::TenantUsage;
+use ballista_core::serde::protobuf::{
+    job_status, task_status, JobResourceUsage, JobStatus,
+};
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobResponse {
+    pub job_id: String,
+    pub status: String,
+    pub error: Option<String>,
+    pub num_stages: usize,
+    pub completed_stages: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageResponse {
+    pub stage_id: usize,
+    pub status: StageStatus,
+    pub partitions: usize,
+    pub running_tasks: usize,
+    pub completed_tasks: usize,
+    /// IDs of the stages whose output is read by this stage
+    pub inputs: Vec<usize>,
+    /// ID of the first stage reading the output of this stage, `None` for the final stage
+    pub output_link: Option<usize>,
+    /// IDs of the stages reading the output of this stage, empty for the final stage
+    pub output_links: Vec<usize>,
+    pub num_rows: Option<u64>,
+    pub num_batches: Option<u64>,
+    pub num_bytes: Option<u64>,
+    /// Bytes spilled to disk by the completed tasks of the stage
+    pub spilled_bytes: u64,
+    /// Number of files the completed tasks of the stage spilled to
+    pub spill_files: u64,
+    /// The resources the tasks of the stage mostly use: `memory`, `io` or `general`
+    pub resource_profile: String,
+}
+
+/// The physical plan of a stage
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StagePlanResponse {
+    pub stage_id: usize,
+    /// The plan of the stage, one operator per line indented by its depth
+    pub plan: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TaskResponse {
+    pub partition_id: usize,
+    pub status: String,
+    pub executor_id: Option<String>,
+    pub error: Option<String>,
+    /// Number of times the task was launched
+    pub attempts: u32,
+    /// Time the latest attempt waited for an executor, in milliseconds
+    pub queue_time: Option<u64>,
+    /// Run time of the latest attempt, in milliseconds
+    pub run_time: Option<u64>,
+    /// Total bytes of the shuffle partitions written by a completed task
+    pub shuffle_bytes: Option<u64>,
+    /// Bytes spilled to disk by a completed task
+    pub spilled_bytes: Option<u64>,
+}
+
+/// Filters of the job list
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct JobFilter {
+    /// Only list jobs with this status, case insensitive
+    pub status: Option<String>,
+    /// List at most this many jobs
+    pub limit: Option<usize>,
+}
+
+impl JobFilter {
+    pub fn matches(&self, job: &JobResponse) -> bool {
+        self.status
+            .as_ref()
+            .map(|status| status.eq_ignore_ascii_case(&job.status))
+            .unwrap_or(true)
+    }
+}
+
+/// Parameters of the export of the plan of a job
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlanExportParams {
+    /// `dot` or `json`, `json` by default
+    pub format: Option<String>,
+}
+
+/// Parameters of the history of a query
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryHistoryParams {
+    /// Only return this many of the latest runs, at most 1000 which are returned by
+    /// default
+    pub limit: Option<usize>,
+}
+
+/// The runs of a query along with their statistics
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryHistoryResponse {
+    pub plan_fingerprint: String,
+    pub stats: QueryRunStats,
+    /// Runs of the query, in the order they were submitted
+    pub runs: Vec<QueryRun>,
+}
+
+/// Parameters of the invalidation of the metadata cache
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetadataCacheParams {
+    /// Only drop the cached metadata of the objects below this URL
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ExecutorResponse {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    pub grpc_port: u16,
+    pub total_task_slots: u32,
+    pub available_task_slots: u32,
+    /// Time of the last heartbeat, in seconds since the epoch
+    pub last_heartbeat: u64,
+    /// Whether a heartbeat was received in the last minute
+    pub alive: bool,
+    /// Available memory reported in the last heartbeat, in bytes
+    pub available_memory: Option<u64>,
+    /// Where the executor runs, such as the Kubernetes pod and node
+    pub labels: BTreeMap<String, String>,
+    /// Version of the task protocol spoken by the executor, to follow rolling upgrades
+    pub protocol_version: u32,
+    /// Whether tasks are no longer scheduled on the executor
+    pub draining: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HealthResponse {
+    /// `ok` if the state backend is reachable, `error` otherwise
+    pub status: String,
+    /// Latency of a read from the state backend, in milliseconds
+    pub backend_latency: Option<u64>,
+    pub error: Option<String>,
+}
+
+/// Resources used by the tasks of a job or of the jobs of a tenant
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUsageResponse {
+    /// Total run time of the task attempts, in seconds
+    pub slot_seconds: f64,
+    /// Bytes written by the tasks of the stages whose output is read by other stages
+    pub bytes_shuffled: u64,
+    /// Bytes read from storage by the scans of the tasks
+    pub bytes_scanned: u64,
+}
+
+impl From<&JobResourceUsage> for ResourceUsageResponse {
+    fn from(usage: &JobResourceUsage) -> Self {
+        Self {
+            slot_seconds: usage.slot_millis as f64 / 1000.0,
+            bytes_shuffled: usage.bytes_shuffled,
+            bytes_scanned: usage.bytes_scanned,
+        }
+    }
+}
+
+/// Resources used by the finished jobs of a tenant
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TenantUsageResponse {
+    pub tenant: String,
+    /// Number of jobs which completed or failed
+    pub jobs: u64,
+    pub usage: ResourceUsageResponse,
+}
+
+impl TenantUsageResponse {
+    pub fn new(tenant: String, usage: &TenantUsage) -> Self {
+        Self {
+            tenant,
+            jobs: usage.jobs,
+            usage: (&usage.usage).into(),
+        }
+    }
+}
+
+/// The task slots of the alive executors of a pool, and the jobs routed to it
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PoolResponse {
+    pub pool: String,
+    pub executors: usize,
+    pub total_task_slots: usize,
+    pub available_task_slots: usize,
+    /// Task slots reserved by the running jobs of the pool
+    pub reserved_task_slots: usize,
+    /// Running jobs submitted to this scheduler
+    pub running_jobs: usize,
+}
+
+/// Everything the REST API exposes about a single job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub job: JobResponse,
+    pub stages: Vec<StageResponse>,
+    /// Tasks of each stage, by stage ID
+    pub tasks: BTreeMap<usize, Vec<TaskResponse>>,
+    /// Resources used by the tasks of the job so far, `None` before it is planned
+    pub usage: Option<ResourceUsageResponse>,
+}
+
+impl JobSummary {
+    /// Summary of a job which has not been planned yet or failed during planning
+    pub fn without_graph(job_id: String, status: &JobStatus) -> Self {
+        Self {
+            job: job_response(job_id, status, None),
+            stages: vec![],
+            tasks: BTreeMap::new(),
+            usage: None,
+        }
+    }
+
+    pub fn new(graph: &ExecutionGraph, status: &JobStatus) -> Self {
+        Self {
+            job: job_response(graph.job_id().to_owned(), status, Some(graph)),
+            stages: stage_responses(graph),
+            tasks: graph
+                .stages
+                .iter()
+                .map(|(stage_id, stage)| (*stage_id, task_responses(stage)))
+                .collect(),
+            usage: Some(graph.usage().into()),
+        }
+    }
+}
+
+/// Name of the job status along with the error of a failed job
+pub fn job_status_name(status: &JobStatus) -> (&'static str, Option<String>) {
+    match &status.status {
+        Some(job_status::Status::Queued(_)) => ("Queued", None),
+        Some(job_status::Status::Running(_)) => ("Running", None),
+        Some(job_status::Status::Completed(_)) => ("Completed", None),
+        Some(job_status::Status::Failed(failed)) => {
+            ("Failed", Some(failed.error.clone()))
+        }
+        None => ("Unknown", None),
+    }
+}
+
+pub fn job_response(
+    job_id: String,
+    status: &JobStatus,
+    graph: Option<&ExecutionGraph>,
+) -> JobResponse {
+    let (num_stages, completed_stages) = graph
+        .map(|graph| {
+            (
+                graph.stages.len(),
+                graph.stages.values().filter(|s| s.complete()).count(),
+            )
+        })
+        .unwrap_or_default();
+    let (status, error) = job_status_name(status);
+    JobResponse {
+        job_id,
+        status: status.to_owned(),
+        error,
+        num_stages,
+        completed_stages,
+    }
+}
+
+pub fn stage_responses(graph: &ExecutionGraph) -> Vec<StageResponse> {
+    let mut stages: Vec<StageResponse> = graph
+        .stages
+        .values()
+        .map(|stage| {
+            let stats = graph.stage_output_stats(stage.stage_id).unwrap_or_default();
+            let mut inputs: Vec<usize> = stage.inputs.keys().copied().collect();
+            inputs.sort_unstable();
+            let (spilled_bytes, spill_files) = stage.spilled();
+            StageResponse {
+                stage_id: stage.stage_id,
+                status: stage.status(),
+                partitions: stage.partitions,
+                running_tasks: stage.running_tasks(),
+                completed_tasks: stage.completed_tasks(),
+                inputs,
+                output_link: stage.output_links.first().copied(),
+                output_links: stage.output_links.clone(),
+                num_rows: stats.num_rows(),
+                num_batches: stats.num_batches(),
+                num_bytes: stats.num_bytes(),
+                spilled_bytes,
+                spill_files,
+                resource_profile: resource_profile_label(stage.resource_profile)
+                    .to_owned(),
+            }
+        })
+        .collect();
+    stages.sort_by_key(|stage| stage.stage_id);
+    stages
+}
+
+/// The plans of the stages of `graph`, whose operators are only shown by their names if
+/// `redact`
+pub fn stage_plan_responses(
+    graph: &ExecutionGraph,
+    redact: bool,
+) -> Vec<StagePlanResponse> {
+    let mut plans: Vec<StagePlanResponse> = graph
+        .stages
+        .values()
+        .map(|stage| {
+            let plan = DisplayableExecutionPlan::new(stage.plan.as_ref())
+                .indent()
+                .to_string();
+            let plan = if redact {
+                plan.lines()
+                    .map(|line| {
+                        format!("{}\n", line.split(':').next().unwrap_or_default())
+                    })
+                    .collect()
+            } else {
+                plan
+            };
+            StagePlanResponse {
+                stage_id: stage.stage_id,
+                plan,
+            }
+        })
+        .collect();
+    plans.sort_by_key(|plan| plan.stage_id);
+    plans
+}
+
+pub fn task_responses(stage: &ExecutionStage) -> Vec<TaskResponse> {
+    stage
+        .task_statuses
+        .iter()
+        .zip(stage.task_infos.iter())
+        .enumerate()
+        .map(|(partition_id, (status, info))| {
+            let (status, error, shuffle_bytes, spilled_bytes) = match status {
+                None => ("Pending", None, None, None),
+                Some(task_status::Status::Running(_)) => ("Running", None, None, None),
+                Some(task_status::Status::Completed(completed)) => (
+                    "Completed",
+                    None,
+                    Some(completed.partitions.iter().map(|p| p.num_bytes).sum()),
+                    Some(completed.spilled_bytes),
+                ),
+                Some(task_status::Status::Failed(failed)) => {
+                    ("Failed", Some(failed.error.clone()), None, None)
+                }
+            };
+            TaskResponse {
+                partition_id,
+                status: status.to_owned(),
+                executor_id: info.executor_id.clone(),
+                error,
+                attempts: info.attempts,
+                queue_time: info.queue_time(),
+                run_time: info.run_time(),
+                shuffle_bytes,
+                spilled_bytes,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JobFilter, JobResponse};
+
+    fn job(status: &str) -> JobResponse {
+        JobResponse {
+            job_id: "job".to_owned(),
+            status: status.to_owned(),
+            error: None,
+            num_stages: 0,
+            completed_stages: 0,
+        }
+    }
+
+    #[test]
+    fn filter_jobs_by_status() {
+        let filter: JobFilter = serde_json::from_str(r#"{"status": "running"}"#).unwrap();
+        assert!(filter.matches(&job("Running")));
+        assert!(!filter.matches(&job("Completed")));
+        assert!(JobFilter::default().matches(&job("Completed")));
+    }
+}
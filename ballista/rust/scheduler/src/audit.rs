@@ -0,0 +1,298 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Audit log of submitted queries.
+//!
+//! Every query accepted by the scheduler produces an [AuditRecord] which is written to an
+//! [AuditSink] twice: once when the query is submitted and again, with its final status
+//! and output statistics, when the job finishes. Sinks should treat the second write as
+//! an update of the first one, e.g. by keying records on the job ID.
+//!
+//! Records of running jobs are kept in memory between the two writes, so a job which is
+//! still running when the scheduler restarts only has its submission record.
+
+use crate::api::model::job_status_name;
+use crate::state::execution_graph::ExecutionGraph;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf::JobStatus;
+use datafusion::logical_plan::LogicalPlan;
+use log::{info, warn};
+use object_store::local::LocalFileSystem;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Audit record of a single query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub job_id: String,
+    pub session_id: String,
+    /// Authenticated client which submitted the query, `None` if client authentication
+    /// is disabled
+    pub user: Option<String>,
+    /// Address of the client which submitted the query
+    pub client_address: Option<String>,
+    /// SQL text of the query, `None` if a logical plan was submitted
+    pub sql: Option<String>,
+    /// Hash of the logical plan, identifying repeated executions of the same query
+    pub plan_fingerprint: String,
+    /// Time the query was submitted, in milliseconds since the epoch
+    pub submitted_at: u64,
+    /// Time the job finished, in milliseconds since the epoch
+    pub finished_at: Option<u64>,
+    pub status: String,
+    pub error: Option<String>,
+    /// Rows produced by the final stage of a completed job
+    pub num_rows: Option<u64>,
+    /// Bytes produced by the final stage of a completed job
+    pub num_bytes: Option<u64>,
+}
+
+impl AuditRecord {
+    pub fn submitted(
+        job_id: impl Into<String>,
+        session_id: impl Into<String>,
+        user: Option<String>,
+        client_address: Option<String>,
+        sql: Option<String>,
+        plan: &LogicalPlan,
+    ) -> Self {
+        Self {
+            job_id: job_id.into(),
+            session_id: session_id.into(),
+            user,
+            client_address,
+            sql,
+            plan_fingerprint: plan_fingerprint(plan),
+            submitted_at: timestamp_millis(),
+            finished_at: None,
+            status: "Queued".to_owned(),
+            error: None,
+            num_rows: None,
+            num_bytes: None,
+        }
+    }
+
+    /// Record the final status of the job along with the output of its final stage.
+    /// Jobs failing during planning do not have an execution graph.
+    pub fn finish(&mut self, status: &JobStatus, graph: Option<&ExecutionGraph>) {
+        let (name, error) = job_status_name(status);
+        self.status = name.to_owned();
+        self.error = error;
+        self.finished_at = Some(timestamp_millis());
+
+        let stats = graph.and_then(|graph| {
+            graph
+                .stages
+                .values()
+                .find(|stage| stage.output_links.is_empty())
+                .and_then(|stage| graph.stage_output_stats(stage.stage_id))
+        });
+        if let Some(stats) = stats {
+            self.num_rows = stats.num_rows();
+            self.num_bytes = stats.num_bytes();
+        }
+    }
+}
+
+/// Destination of [AuditRecord]s
+#[tonic::async_trait]
+pub trait AuditSink: Send + Sync {
+    async fn write(&self, record: &AuditRecord) -> Result<()>;
+}
+
+/// Writes each record as a JSON object named after the job to an [ObjectStore]
+pub struct ObjectStoreAuditSink {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl ObjectStoreAuditSink {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Path) -> Self {
+        Self { store, prefix }
+    }
+
+    /// A sink writing to the given local directory, which is created if it does not exist
+    pub fn try_new_local(dir: &str) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let store = LocalFileSystem::new_with_prefix(dir).map_err(|e| {
+            BallistaError::General(format!(
+                "Could not open audit log directory {}: {}",
+                dir, e
+            ))
+        })?;
+        Ok(Self::new(Arc::new(store), Path::default()))
+    }
+
+    fn path(&self, record: &AuditRecord) -> Path {
+        // Zero-padded submission times make listings chronological
+        let file_name = format!("{:013}-{}.json", record.submitted_at, record.job_id);
+        self.prefix.child(file_name.as_str())
+    }
+}
+
+#[tonic::async_trait]
+impl AuditSink for ObjectStoreAuditSink {
+    async fn write(&self, record: &AuditRecord) -> Result<()> {
+        let bytes = serde_json::to_vec(record).map_err(|e| {
+            BallistaError::Internal(format!("Could not serialize audit record: {}", e))
+        })?;
+        self.store
+            .put(&self.path(record), bytes.into())
+            .await
+            .map_err(|e| BallistaError::General(format!("Audit log error: {}", e)))
+    }
+}
+
+/// Writes each record as a JSON log line with the `ballista_audit` target, which can be
+/// routed to a separate destination by the logging setup
+pub struct LogAuditSink;
+
+#[tonic::async_trait]
+impl AuditSink for LogAuditSink {
+    async fn write(&self, record: &AuditRecord) -> Result<()> {
+        let line = serde_json::to_string(record).map_err(|e| {
+            BallistaError::Internal(format!("Could not serialize audit record: {}", e))
+        })?;
+        info!(target: "ballista_audit", "{}", line);
+        Ok(())
+    }
+}
+
+/// Tracks the audit records of running jobs and writes them to an [AuditSink]
+pub struct AuditLog {
+    sink: Arc<dyn AuditSink>,
+    running: Mutex<HashMap<String, AuditRecord>>,
+}
+
+impl AuditLog {
+    pub fn new(sink: Arc<dyn AuditSink>) -> Self {
+        Self {
+            sink,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Write the record of a newly submitted query
+    pub async fn submitted(&self, record: AuditRecord) {
+        self.write(&record).await;
+        self.running.lock().insert(record.job_id.clone(), record);
+    }
+
+    /// Write the final record of a finished job
+    pub async fn finished(
+        &self,
+        job_id: &str,
+        status: &JobStatus,
+        graph: Option<&ExecutionGraph>,
+    ) {
+        let record = self.running.lock().remove(job_id);
+        match record {
+            Some(mut record) => {
+                record.finish(status, graph);
+                self.write(&record).await;
+            }
+            None => warn!("No audit record of finished job {}", job_id),
+        }
+    }
+
+    async fn write(&self, record: &AuditRecord) {
+        if let Err(e) = self.sink.write(record).await {
+            warn!(
+                "Failed to write audit record of job {}: {:?}",
+                record.job_id, e
+            );
+        }
+    }
+}
+
+/// SHA-256 of `plan`, identifying repeated executions of the same query across
+/// schedulers and releases
+pub(crate) fn plan_fingerprint(plan: &LogicalPlan) -> String {
+    let digest = Sha256::digest(plan.display_indent().to_string().as_bytes());
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn timestamp_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AuditLog, AuditRecord, ObjectStoreAuditSink};
+    use ballista_core::serde::protobuf::{job_status, FailedJob, JobStatus};
+    use datafusion::logical_plan::LogicalPlanBuilder;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use object_store::ObjectStore;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn write_submitted_and_finished_records() {
+        let store = Arc::new(InMemory::new());
+        let log = AuditLog::new(Arc::new(ObjectStoreAuditSink::new(
+            store.clone(),
+            Path::from("audit"),
+        )));
+        let plan = LogicalPlanBuilder::empty(false).build().unwrap();
+        let record = AuditRecord::submitted(
+            "job",
+            "session",
+            Some("alice".to_owned()),
+            None,
+            Some("SELECT 1".to_owned()),
+            &plan,
+        );
+        let path = Path::from(format!("audit/{:013}-job.json", record.submitted_at));
+        assert_eq!(record.plan_fingerprint.len(), 64);
+
+        log.submitted(record.clone()).await;
+        let read = |store: Arc<InMemory>, path: Path| async move {
+            let bytes = store.get(&path).await.unwrap().bytes().await.unwrap();
+            serde_json::from_slice::<AuditRecord>(&bytes).unwrap()
+        };
+        assert_eq!(read(store.clone(), path.clone()).await, record);
+
+        let status = JobStatus {
+            status: Some(job_status::Status::Failed(FailedJob {
+                error: "boom".to_owned(),
+                kind: 0,
+            })),
+        };
+        log.finished("job", &status, None).await;
+
+        let finished = read(store.clone(), path).await;
+        assert_eq!(finished.status, "Failed");
+        assert_eq!(finished.error.as_deref(), Some("boom"));
+        assert!(finished.finished_at.is_some());
+        assert_eq!(finished.plan_fingerprint, record.plan_fingerprint);
+
+        // The record is updated in place
+        let objects: Vec<_> =
+            store.list(None).await.unwrap().try_collect().await.unwrap();
+        assert_eq!(objects.len(), 1);
+    }
+}
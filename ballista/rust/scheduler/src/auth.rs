@@ -0,0 +1,332 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Authentication and authorization of clients.
+//!
+//! Clients send a bearer token with their requests (see [ballista_core::auth]) which a
+//! [TokenValidator] maps to a [Principal]. An [Authorizer] then decides whether the
+//! principal may perform the requested [Operation]. Validators for JWTs or OAuth token
+//! introspection can be plugged in by implementing [TokenValidator].
+
+use ballista_core::error::{BallistaError, Result};
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::{LogicalPlan, PlanVisitor};
+use log::{error, warn};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::Status;
+
+/// An authenticated client
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Principal {
+    pub name: String,
+    pub roles: Vec<String>,
+}
+
+/// An operation a client asks the scheduler to perform
+#[derive(Debug)]
+pub enum Operation<'a> {
+    /// Create or update a session
+    CreateSession,
+    /// Run a query reading the given tables
+    SubmitQuery {
+        sql: Option<&'a str>,
+        tables: &'a [String],
+    },
+    GetJobStatus {
+        job_id: &'a str,
+    },
+    CancelJob {
+        job_id: &'a str,
+    },
+    /// Infer the schema of a file
+    GetFileMetadata {
+        path: &'a str,
+    },
+    /// Drop the cached metadata of the objects below a URL, or of all objects
+    InvalidateMetadataCache {
+        url: Option<&'a str>,
+    },
+    /// Drop the cached plans of all SQL queries
+    InvalidatePlanCache,
+    /// Drop the cached results of all queries
+    InvalidateResultCache,
+    /// Drop a table cached on the executors
+    UncacheTable {
+        name: &'a str,
+    },
+    /// Close a session, dropping its temporary tables
+    CloseSession {
+        session_id: &'a str,
+    },
+    /// Save a table uploaded to a location in the catalog of the scheduler
+    RegisterTable {
+        name: &'a str,
+        location: &'a str,
+    },
+    /// Read a table of the catalog of the scheduler
+    GetTable {
+        name: &'a str,
+    },
+    /// Analyze the table at a location, replacing the statistics queries scanning it are
+    /// planned with
+    UpdateTableStatistics {
+        table: &'a str,
+    },
+    /// Stop scheduling tasks on an executor
+    DrainExecutor {
+        executor_id: &'a str,
+    },
+    /// Read the state of the scheduler, of its executors and of its jobs through the
+    /// REST API. Jobs submitted by other clients are not visible either way.
+    ReadState,
+}
+
+/// Maps bearer tokens to principals
+#[tonic::async_trait]
+pub trait TokenValidator: Send + Sync {
+    /// The principal the token belongs to, `None` if the token is not valid
+    async fn validate(&self, token: &str) -> Result<Option<Principal>>;
+}
+
+/// Decides which operations principals may perform
+#[tonic::async_trait]
+pub trait Authorizer: Send + Sync {
+    async fn authorize(
+        &self,
+        principal: &Principal,
+        operation: &Operation<'_>,
+    ) -> Result<bool>;
+}
+
+/// Allows every authenticated principal to perform all operations
+pub struct AllowAll;
+
+#[tonic::async_trait]
+impl Authorizer for AllowAll {
+    async fn authorize(&self, _: &Principal, _: &Operation<'_>) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Validates a fixed set of tokens, which can be replaced at runtime
+pub struct StaticTokenValidator {
+    tokens: RwLock<HashMap<String, Principal>>,
+}
+
+impl StaticTokenValidator {
+    pub fn new(tokens: HashMap<String, Principal>) -> Self {
+        Self {
+            tokens: RwLock::new(tokens),
+        }
+    }
+
+    /// Read tokens from a file with one `<token> <name> [<role>,<role>...]` entry per
+    /// line. Empty lines and lines starting with `#` are ignored.
+    pub fn try_from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            BallistaError::General(format!("Could not read tokens file {}: {}", path, e))
+        })?;
+        Self::parse(&contents)
+    }
+
+    /// Replace the tokens with the ones of the file at `path`, keeping the current tokens
+    /// if it is not valid
+    pub fn reload(&self, path: &str) -> Result<()> {
+        let tokens = Self::try_from_file(path)?.tokens.into_inner();
+        *self.tokens.write() = tokens;
+        Ok(())
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut tokens = HashMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (token, name, roles) = match fields.as_slice() {
+                [token, name] => (token, name, vec![]),
+                [token, name, roles] => (
+                    token,
+                    name,
+                    roles.split(',').map(|role| role.to_owned()).collect(),
+                ),
+                _ => {
+                    return Err(BallistaError::General(format!(
+                        "Invalid token entry on line {}",
+                        index + 1
+                    )))
+                }
+            };
+            tokens.insert(
+                token.to_string(),
+                Principal {
+                    name: name.to_string(),
+                    roles,
+                },
+            );
+        }
+        Ok(Self::new(tokens))
+    }
+}
+
+#[tonic::async_trait]
+impl TokenValidator for StaticTokenValidator {
+    async fn validate(&self, token: &str) -> Result<Option<Principal>> {
+        Ok(self.tokens.read().get(token).cloned())
+    }
+}
+
+/// Authenticates and authorizes the requests of clients
+pub struct ClientAuth {
+    validator: Arc<dyn TokenValidator>,
+    authorizer: Arc<dyn Authorizer>,
+}
+
+impl ClientAuth {
+    pub fn new(
+        validator: Arc<dyn TokenValidator>,
+        authorizer: Arc<dyn Authorizer>,
+    ) -> Self {
+        Self {
+            validator,
+            authorizer,
+        }
+    }
+
+    /// The principal the bearer token of a request belongs to
+    pub async fn authenticate(&self, token: Option<&str>) -> Result<Principal, Status> {
+        let token =
+            token.ok_or_else(|| Status::unauthenticated("Missing bearer token"))?;
+        match self.validator.validate(token).await {
+            Ok(Some(principal)) => Ok(principal),
+            Ok(None) => Err(Status::unauthenticated("Invalid bearer token")),
+            Err(e) => {
+                error!("Error validating bearer token: {:?}", e);
+                Err(Status::internal("Could not validate bearer token"))
+            }
+        }
+    }
+
+    pub async fn authorize(
+        &self,
+        principal: &Principal,
+        operation: &Operation<'_>,
+    ) -> Result<(), Status> {
+        match self.authorizer.authorize(principal, operation).await {
+            Ok(true) => Ok(()),
+            Ok(false) => {
+                warn!("Denied {:?} to {}", operation, principal.name);
+                Err(Status::permission_denied(format!(
+                    "{} is not allowed to perform this operation",
+                    principal.name
+                )))
+            }
+            Err(e) => {
+                error!("Error authorizing {:?}: {:?}", operation, e);
+                Err(Status::internal("Could not authorize request"))
+            }
+        }
+    }
+}
+
+/// Names of the tables scanned by `plan`
+pub fn scanned_tables(plan: &LogicalPlan) -> Vec<String> {
+    struct TableCollector(Vec<String>);
+
+    impl PlanVisitor for TableCollector {
+        type Error = DataFusionError;
+
+        fn pre_visit(
+            &mut self,
+            plan: &LogicalPlan,
+        ) -> std::result::Result<bool, Self::Error> {
+            if let LogicalPlan::TableScan(scan) = plan {
+                self.0.push(scan.table_name.clone());
+            }
+            Ok(true)
+        }
+    }
+
+    let mut collector = TableCollector(vec![]);
+    // The collector never fails
+    plan.accept(&mut collector).unwrap();
+    collector.0.sort();
+    collector.0.dedup();
+    collector.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ClientAuth, Operation, Principal, StaticTokenValidator};
+    use crate::auth::Authorizer;
+    use ballista_core::error::Result;
+    use std::sync::Arc;
+    use tonic::Code;
+
+    struct ReadOnly;
+
+    #[tonic::async_trait]
+    impl Authorizer for ReadOnly {
+        async fn authorize(
+            &self,
+            principal: &Principal,
+            operation: &Operation<'_>,
+        ) -> Result<bool> {
+            Ok(principal.roles.iter().any(|role| role == "admin")
+                || !matches!(operation, Operation::CancelJob { .. }))
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticate_and_authorize() -> Result<()> {
+        let validator = StaticTokenValidator::parse(
+            "# token name roles\nsecret-1 alice admin,dev\n\nsecret-2 bob\n",
+        )?;
+        let auth = ClientAuth::new(Arc::new(validator), Arc::new(ReadOnly));
+
+        let alice = auth.authenticate(Some("secret-1")).await.unwrap();
+        assert_eq!(alice.name, "alice");
+        assert_eq!(alice.roles, vec!["admin", "dev"]);
+        let bob = auth.authenticate(Some("secret-2")).await.unwrap();
+
+        assert_eq!(
+            auth.authenticate(Some("secret-3"))
+                .await
+                .unwrap_err()
+                .code(),
+            Code::Unauthenticated
+        );
+        assert_eq!(
+            auth.authenticate(None).await.unwrap_err().code(),
+            Code::Unauthenticated
+        );
+
+        let cancel = Operation::CancelJob { job_id: "job" };
+        assert!(auth.authorize(&alice, &cancel).await.is_ok());
+        assert_eq!(
+            auth.authorize(&bob, &cancel).await.unwrap_err().code(),
+            Code::PermissionDenied
+        );
+
+        assert!(StaticTokenValidator::parse("only-a-token").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,419 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Column masking enforced by the scheduler.
+//!
+//! A [MaskingPolicy] replaces the values of a column an identity reads with those of a
+//! masking expression, such as `md5(email)`. The policies of an identity take precedence
+//! over the policies for identity `*`, so privileged identities are exempted from a mask
+//! with a policy whose expression is the column itself.
+//!
+//! [ColumnMasking] is a [PlanRewriter] projecting the masked values of the columns above
+//! the scans of every job, so the rest of the plan only sees those. Like row policies,
+//! masking policies apply to the tables at their location rather than to the tables of
+//! a name, and to the principals clients are authenticated as.
+
+use crate::plan_rewrite::{PlanRewriter, RewriteContext};
+use crate::row_security::{
+    find_scanned_table, overlaps, scan_locations, split_fields, subqueries, ANY_IDENTITY,
+};
+use crate::sql_expr::parse_sql_expr;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::table_format::location_url;
+use datafusion::logical_plan::{
+    Column, DFSchema, Expr, ExprSchemable, LogicalPlan, LogicalPlanBuilder,
+};
+use datafusion::optimizer::utils::{expr_to_columns, from_plan};
+use std::collections::HashSet;
+
+/// The values of a column of the tables at a location an identity reads
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaskingPolicy {
+    /// URL of the location, as returned by [location_url]
+    pub location: String,
+    pub column: String,
+    /// The identity the policy applies to, or [ANY_IDENTITY]
+    pub identity: String,
+    /// The values the identity reads, computed from the columns of the table
+    pub mask: Expr,
+}
+
+impl MaskingPolicy {
+    /// A policy masking `column` of the tables at `location` with `mask`, a SQL
+    /// expression over the columns of the tables, for `identity`
+    pub fn try_new(
+        location: &str,
+        column: &str,
+        identity: &str,
+        mask: &str,
+    ) -> Result<Self> {
+        let mask = parse_sql_expr(mask)?;
+        // Masks are projected above the scan of a single table, so they can not read
+        // the columns of other tables
+        let mut columns = HashSet::new();
+        expr_to_columns(&mask, &mut columns)?;
+        if let Some(column) = columns.iter().find(|column| column.relation.is_some()) {
+            return Err(BallistaError::General(format!(
+                "Mask reads qualified column {}, masks may only read the unqualified \
+                 columns of their table",
+                column.flat_name()
+            )));
+        }
+        Ok(Self {
+            location: location_url(location),
+            column: column.to_owned(),
+            identity: identity.to_owned(),
+            mask,
+        })
+    }
+}
+
+/// Rewrites the plans of jobs to read the masked values of the columns of their tables
+#[derive(Debug, Default)]
+pub struct ColumnMasking {
+    policies: Vec<MaskingPolicy>,
+}
+
+impl ColumnMasking {
+    pub fn new(policies: Vec<MaskingPolicy>) -> Self {
+        Self { policies }
+    }
+
+    /// Load the policies of the file at `path`, one
+    /// `<location> <column> <identity> <mask>` entry per line. Fails if any mask is
+    /// invalid.
+    pub fn try_from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            BallistaError::General(format!(
+                "Could not read masking policies file {}: {}",
+                path, e
+            ))
+        })?;
+        Self::parse(&contents)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut policies = vec![];
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let policy = match split_fields(line, 4).as_slice() {
+                [location, column, identity, mask] => {
+                    MaskingPolicy::try_new(location, column, identity, mask)
+                }
+                _ => Err(BallistaError::General("Missing mask".to_owned())),
+            }
+            .map_err(|e| {
+                BallistaError::General(format!(
+                    "Invalid masking policy on line {}: {}",
+                    index + 1,
+                    e
+                ))
+            })?;
+            policies.push(policy);
+        }
+        Ok(Self::new(policies))
+    }
+
+    /// The mask of `column` of a table at `locations` for `identity`, if it is masked
+    fn mask(
+        &self,
+        locations: &[String],
+        column: &str,
+        identity: Option<&str>,
+    ) -> Option<&Expr> {
+        let policies = || {
+            self.policies.iter().filter(|policy| {
+                overlaps(&policy.location, locations)
+                    && policy.column.eq_ignore_ascii_case(column)
+            })
+        };
+        policies()
+            .find(|policy| Some(policy.identity.as_str()) == identity)
+            .or_else(|| policies().find(|policy| policy.identity == ANY_IDENTITY))
+            .map(|policy| &policy.mask)
+    }
+
+    fn has_policies(&self, locations: &[String]) -> bool {
+        self.policies
+            .iter()
+            .any(|policy| overlaps(&policy.location, locations))
+    }
+
+    /// `plan` reading the masked values of the columns of its tables for the identity of
+    /// the job
+    fn apply(&self, plan: &LogicalPlan, context: &RewriteContext) -> Result<LogicalPlan> {
+        if let LogicalPlan::TableScan(scan) = plan {
+            let locations = scan_locations(plan, context);
+            let schema = scan.source.schema();
+            let table_schema = DFSchema::try_from(schema.as_ref().clone())?;
+            let masks = schema
+                .fields()
+                .iter()
+                .map(|field| {
+                    let name = field.name().as_str();
+                    let mask = self.mask(&locations, name, context.identity);
+                    // Plans submitted by clients were planned against the type of the
+                    // column, which its mask must keep
+                    if let Some(mask) = mask {
+                        let mask_type = mask.get_type(&table_schema).map_err(|e| {
+                            BallistaError::General(format!(
+                                "Invalid mask {:?} of column {} of table {}: {}",
+                                mask, name, scan.table_name, e
+                            ))
+                        })?;
+                        if &mask_type != field.data_type() {
+                            return Err(BallistaError::General(format!(
+                                "Mask {:?} of column {} of table {} is of type {:?}, \
+                                 rather than {:?} like the column",
+                                mask,
+                                name,
+                                scan.table_name,
+                                mask_type,
+                                field.data_type()
+                            )));
+                        }
+                    }
+                    Ok((name, mask))
+                })
+                .collect::<Result<Vec<(&str, Option<&Expr>)>>>()?;
+            let masked: HashSet<&str> = masks
+                .iter()
+                .filter(|(_, mask)| mask.is_some())
+                .map(|(name, _)| *name)
+                .collect();
+            if masked.is_empty() {
+                return Ok(plan.clone());
+            }
+
+            // Filters pushed down into the scan would read the values of the masked
+            // columns, so they are applied to the masked values instead
+            let mut scan_filters = vec![];
+            let mut masked_filters = vec![];
+            for filter in &scan.filters {
+                let mut columns = HashSet::new();
+                expr_to_columns(filter, &mut columns)?;
+                if columns.iter().any(|c| masked.contains(c.name.as_str())) {
+                    masked_filters.push(filter.clone());
+                } else {
+                    scan_filters.push(filter.clone());
+                }
+            }
+
+            // Masks may read columns the scan does not project, so the whole table is
+            // scanned and the columns of the scan projected again
+            let values: Vec<Expr> = masks
+                .into_iter()
+                .map(|(name, mask)| match mask {
+                    Some(mask) => mask.clone().alias(name),
+                    None => Expr::Column(Column::from_name(name)),
+                })
+                .collect();
+            let mut builder = LogicalPlanBuilder::scan_with_filters(
+                &scan.table_name,
+                scan.source.clone(),
+                None,
+                scan_filters,
+            )?
+            .project(values)?
+            .alias(&scan.table_name)?;
+            if let Some(filter) = masked_filters.into_iter().reduce(Expr::and) {
+                builder = builder.filter(filter)?;
+            }
+            let columns: Vec<Expr> = scan
+                .projected_schema
+                .fields()
+                .iter()
+                .map(|field| Expr::Column(field.qualified_column()))
+                .collect();
+            return Ok(builder.project(columns)?.build()?);
+        }
+
+        for expr in plan.expressions() {
+            for subquery in subqueries(&expr)? {
+                let masked = |scan: &LogicalPlan| {
+                    self.has_policies(&scan_locations(scan, context))
+                };
+                if let Some(table) = find_scanned_table(&subquery, &masked)? {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Table {} has masking policies and cannot be read in a subquery",
+                        table
+                    )));
+                }
+            }
+        }
+        let inputs = plan
+            .inputs()
+            .into_iter()
+            .map(|input| self.apply(input, context))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(from_plan(plan, &plan.expressions(), &inputs)?)
+    }
+}
+
+impl PlanRewriter for ColumnMasking {
+    fn name(&self) -> &str {
+        "column_masking"
+    }
+
+    fn rewrite_logical_plan(
+        &self,
+        plan: LogicalPlan,
+        context: &RewriteContext,
+    ) -> Result<LogicalPlan> {
+        self.apply(&plan, context)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::empty::EmptyTable;
+    use datafusion::logical_expr::BuiltinScalarFunction;
+    use datafusion::logical_plan::{col, lit, provider_as_source};
+    use datafusion::prelude::SessionContext;
+    use std::collections::{BTreeSet, HashMap};
+    use std::sync::Arc;
+
+    fn scan(table: &str, projection: Option<Vec<usize>>) -> LogicalPlan {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("email", DataType::Utf8, false),
+        ]);
+        let source = provider_as_source(Arc::new(EmptyTable::new(Arc::new(schema))));
+        LogicalPlanBuilder::scan(table, source, projection)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    /// The values projected above the scan of the masked `plan`
+    fn values_of(plan: &LogicalPlan) -> Option<&[Expr]> {
+        let mut plan = plan;
+        loop {
+            match plan {
+                LogicalPlan::Projection(projection)
+                    if matches!(projection.input.as_ref(), LogicalPlan::TableScan(_)) =>
+                {
+                    return Some(&projection.expr)
+                }
+                LogicalPlan::TableScan(_) => return None,
+                _ => plan = plan.inputs()[0],
+            }
+        }
+    }
+
+    #[test]
+    fn parse_policies() -> Result<()> {
+        let masking = ColumnMasking::parse(
+            "# location             column  identity  mask\n\
+             s3://crm/customers  email   *         md5(email)\n\
+             s3://crm/customers  email   admin     email\n",
+        )?;
+        assert_eq!(
+            masking.policies,
+            vec![
+                MaskingPolicy {
+                    location: "s3://crm/customers/".to_owned(),
+                    column: "email".to_owned(),
+                    identity: ANY_IDENTITY.to_owned(),
+                    mask: Expr::ScalarFunction {
+                        fun: BuiltinScalarFunction::MD5,
+                        args: vec![col("email")],
+                    },
+                },
+                MaskingPolicy {
+                    location: "s3://crm/customers/".to_owned(),
+                    column: "email".to_owned(),
+                    identity: "admin".to_owned(),
+                    mask: col("email"),
+                },
+            ]
+        );
+        assert!(ColumnMasking::parse("s3://crm/customers email *").is_err());
+        // Invalid masks fail the whole file
+        for mask in ["md5(", "CAST(email AS INT)", "other.email", "nope(email)"] {
+            let policies = format!(
+                "s3://crm/customers id * id\ns3://crm/customers email * {}",
+                mask
+            );
+            assert!(ColumnMasking::parse(&policies).is_err(), "{}", mask);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mask_columns_of_scans() -> Result<()> {
+        let masking = ColumnMasking::new(vec![
+            MaskingPolicy::try_new("s3://crm/customers", "email", "*", "'***'")?,
+            MaskingPolicy::try_new("s3://crm/customers", "email", "admin", "email")?,
+            MaskingPolicy::try_new("s3://crm/leads", "id", "*", "'none'")?,
+        ]);
+        let session_ctx = SessionContext::new();
+        // The same data is registered as customers and as a copy of them
+        let table_locations: HashMap<String, BTreeSet<String>> = [
+            ("customers", "s3://crm/customers"),
+            ("copy", "s3://crm/customers/2022"),
+            ("leads", "s3://crm/leads"),
+        ]
+        .into_iter()
+        .map(|(table, location)| {
+            (table.to_owned(), BTreeSet::from([location.to_owned()]))
+        })
+        .collect();
+        let context = |identity| RewriteContext {
+            job_id: "job",
+            identity,
+            table_locations: &table_locations,
+            session_ctx: &session_ctx,
+        };
+
+        // Only the id column is projected, though the email column is masked
+        let plan =
+            masking.apply(&scan("customers", Some(vec![0])), &context(Some("alice")))?;
+        assert_eq!(
+            values_of(&plan),
+            Some(&[col("customers.id"), lit("***").alias("email"),][..])
+        );
+        assert_eq!(plan.schema(), scan("customers", Some(vec![0])).schema());
+
+        let plan = masking.apply(&scan("copy", None), &context(None))?;
+        assert_eq!(
+            values_of(&plan),
+            Some(&[col("copy.id"), lit("***").alias("email"),][..])
+        );
+
+        let plan = masking.apply(&scan("customers", None), &context(Some("admin")))?;
+        assert_eq!(
+            values_of(&plan),
+            Some(&[col("customers.id"), col("customers.email").alias("email")][..])
+        );
+        assert_eq!(plan.schema(), scan("customers", None).schema());
+
+        // Masks must keep the type of their column
+        assert!(masking.apply(&scan("leads", None), &context(None)).is_err());
+
+        // Tables without policies are read as they are
+        let plan =
+            ColumnMasking::default().apply(&scan("customers", None), &context(None))?;
+        assert!(matches!(plan, LogicalPlan::TableScan(_)));
+        Ok(())
+    }
+}
@@ -0,0 +1,323 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Persistent log of job lifecycle events.
+//!
+//! Every job gets its own directory in the event log containing one JSON object per
+//! lifecycle event and, once the job has finished, a [JobSummary] with its final stage
+//! and task metrics:
+//!
+//! ```text
+//! <job_id>/events/<timestamp>-<event>.json
+//! <job_id>/summary.json
+//! ```
+//!
+//! The log outlives the scheduler state, which allows the history server to serve
+//! finished jobs after the scheduler restarts. It is kept in a local directory or in
+//! one of the object stores of the cluster, which the history server can read from
+//! another host.
+
+use crate::api::model::{JobResponse, JobSummary};
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::object_store_registry::ObjectStoreFactories;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const EVENTS_DIR: &str = "events";
+const SUMMARY_FILE: &str = "summary.json";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobEventKind {
+    Queued,
+    Submitted,
+    Completed,
+    Failed,
+    /// The job failed and is queued again
+    Retried,
+}
+
+/// A single lifecycle event of a job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub job_id: String,
+    pub kind: JobEventKind,
+    /// Time of the event, in milliseconds since the epoch
+    pub timestamp: u64,
+    /// Error message of a failed or retried job
+    pub error: Option<String>,
+}
+
+impl JobEvent {
+    pub fn new(job_id: impl Into<String>, kind: JobEventKind) -> Self {
+        Self {
+            job_id: job_id.into(),
+            kind,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_millis() as u64,
+            error: None,
+        }
+    }
+
+    pub fn failed(job_id: impl Into<String>, error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Self::new(job_id, JobEventKind::Failed)
+        }
+    }
+
+    pub fn retried(job_id: impl Into<String>, error: impl Into<String>) -> Self {
+        Self {
+            error: Some(error.into()),
+            ..Self::new(job_id, JobEventKind::Retried)
+        }
+    }
+}
+
+/// Reads and writes the event log in an [ObjectStore]
+pub struct EventLog {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+}
+
+impl EventLog {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Path) -> Self {
+        Self { store, prefix }
+    }
+
+    /// An event log in `location`, a local directory or the URL of a directory in one of
+    /// the object stores of the cluster, see [ObjectStoreFactories::store_at]
+    pub fn try_new(location: &str, stores: &ObjectStoreFactories) -> Result<Self> {
+        let (store, prefix) = stores.store_at(location)?;
+        Ok(Self::new(store, prefix))
+    }
+
+    pub async fn log_event(&self, event: &JobEvent) -> Result<()> {
+        let file_name = format!("{:013}-{:?}.json", event.timestamp, event.kind);
+        let path = self
+            .job_path(&event.job_id)
+            .child(EVENTS_DIR)
+            .child(file_name.as_str());
+        self.put_json(&path, event).await
+    }
+
+    pub async fn write_summary(&self, summary: &JobSummary) -> Result<()> {
+        let path = self.job_path(&summary.job.job_id).child(SUMMARY_FILE);
+        self.put_json(&path, summary).await
+    }
+
+    /// IDs of all jobs in the event log
+    pub async fn job_ids(&self) -> Result<Vec<String>> {
+        let listing = self
+            .store
+            .list_with_delimiter(Some(&self.prefix))
+            .await
+            .map_err(to_ballista_error)?;
+        let mut job_ids: Vec<String> = listing
+            .common_prefixes
+            .iter()
+            .filter_map(|path| path.parts().last().map(|part| part.as_ref().to_owned()))
+            .collect();
+        job_ids.sort();
+        Ok(job_ids)
+    }
+
+    /// All events of a job in the order they were logged
+    pub async fn events(&self, job_id: &str) -> Result<Vec<JobEvent>> {
+        let prefix = self.job_path(job_id).child(EVENTS_DIR);
+        let mut locations: Vec<Path> = self
+            .store
+            .list(Some(&prefix))
+            .await
+            .map_err(to_ballista_error)?
+            .map_ok(|meta| meta.location)
+            .try_collect()
+            .await
+            .map_err(to_ballista_error)?;
+        // File names start with the zero-padded timestamp
+        locations.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+
+        let mut events = Vec::with_capacity(locations.len());
+        for location in locations {
+            events.push(self.get_json(&location).await?);
+        }
+        Ok(events)
+    }
+
+    /// The summary of a finished job, `None` if the job has not finished or the
+    /// scheduler stopped before it could write the summary
+    pub async fn summary(&self, job_id: &str) -> Result<Option<JobSummary>> {
+        let path = self.job_path(job_id).child(SUMMARY_FILE);
+        match self.store.head(&path).await {
+            Ok(_) => Ok(Some(self.get_json(&path).await?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(to_ballista_error(e)),
+        }
+    }
+
+    /// Replay the events of a job to find its last known state
+    pub async fn replay(&self, job_id: &str) -> Result<Option<JobResponse>> {
+        if let Some(summary) = self.summary(job_id).await? {
+            return Ok(Some(summary.job));
+        }
+
+        Ok(self
+            .events(job_id)
+            .await?
+            .into_iter()
+            .fold(None, |_, event| Some(job_response_from_event(event))))
+    }
+
+    fn job_path(&self, job_id: &str) -> Path {
+        self.prefix.child(job_id)
+    }
+
+    async fn put_json<V: Serialize>(&self, path: &Path, value: &V) -> Result<()> {
+        let bytes = serde_json::to_vec(value).map_err(|e| {
+            BallistaError::Internal(format!("Could not serialize {}: {}", path, e))
+        })?;
+        self.store
+            .put(path, bytes.into())
+            .await
+            .map_err(to_ballista_error)
+    }
+
+    async fn get_json<V: for<'de> Deserialize<'de>>(&self, path: &Path) -> Result<V> {
+        let bytes = self
+            .store
+            .get(path)
+            .await
+            .map_err(to_ballista_error)?
+            .bytes()
+            .await
+            .map_err(to_ballista_error)?;
+        serde_json::from_slice(&bytes).map_err(|e| {
+            BallistaError::Internal(format!("Could not deserialize {}: {}", path, e))
+        })
+    }
+}
+
+fn job_response_from_event(event: JobEvent) -> JobResponse {
+    let status = match event.kind {
+        JobEventKind::Queued => "Queued",
+        JobEventKind::Submitted => "Running",
+        JobEventKind::Completed => "Completed",
+        JobEventKind::Failed => "Failed",
+        // The job is queued again after failing
+        JobEventKind::Retried => "Queued",
+    };
+    JobResponse {
+        job_id: event.job_id,
+        status: status.to_owned(),
+        error: event.error,
+        num_stages: 0,
+        completed_stages: 0,
+    }
+}
+
+fn to_ballista_error(e: object_store::Error) -> BallistaError {
+    BallistaError::General(format!("Event log error: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventLog, JobEvent, JobEventKind};
+    use crate::api::model::{JobResponse, JobSummary};
+    use ballista_core::error::Result;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn event(job_id: &str, kind: JobEventKind, timestamp: u64) -> JobEvent {
+        JobEvent {
+            timestamp,
+            ..JobEvent::new(job_id, kind)
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_events() -> Result<()> {
+        let log = EventLog::new(Arc::new(InMemory::new()), Path::from("history"));
+        log.log_event(&event("job-a", JobEventKind::Queued, 1))
+            .await?;
+        log.log_event(&event("job-a", JobEventKind::Submitted, 2))
+            .await?;
+        log.log_event(&event("job-b", JobEventKind::Queued, 3))
+            .await?;
+
+        assert_eq!(log.job_ids().await?, vec!["job-a", "job-b"]);
+
+        let events = log.events("job-a").await?;
+        assert_eq!(
+            events.iter().map(|e| e.kind).collect::<Vec<_>>(),
+            vec![JobEventKind::Queued, JobEventKind::Submitted]
+        );
+
+        let job = log.replay("job-a").await?.unwrap();
+        assert_eq!(job.status, "Running");
+        assert!(log.replay("job-c").await?.is_none());
+
+        log.log_event(&event("job-a", JobEventKind::Failed, 4))
+            .await?;
+        log.log_event(&JobEvent {
+            timestamp: 5,
+            ..JobEvent::retried("job-a", "executor lost")
+        })
+        .await?;
+        let job = log.replay("job-a").await?.unwrap();
+        assert_eq!(job.status, "Queued");
+        assert_eq!(job.error.as_deref(), Some("executor lost"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replay_summary() -> Result<()> {
+        let log = EventLog::new(Arc::new(InMemory::new()), Path::default());
+        log.log_event(&JobEvent::failed("job", "boom")).await?;
+        assert_eq!(
+            log.replay("job").await?.unwrap().error.as_deref(),
+            Some("boom")
+        );
+        assert!(log.summary("job").await?.is_none());
+
+        let summary = JobSummary {
+            job: JobResponse {
+                job_id: "job".to_owned(),
+                status: "Failed".to_owned(),
+                error: Some("boom".to_owned()),
+                num_stages: 2,
+                completed_stages: 1,
+            },
+            stages: vec![],
+            tasks: BTreeMap::new(),
+            usage: None,
+        };
+        log.write_summary(&summary).await?;
+
+        assert_eq!(log.summary("job").await?, Some(summary.clone()));
+        assert_eq!(log.replay("job").await?, Some(summary.job));
+
+        Ok(())
+    }
+}
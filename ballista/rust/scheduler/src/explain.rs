@@ -0,0 +1,165 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! `EXPLAIN` of the distributed plan of a query.
+//!
+//! The physical plan DataFusion plans for an `EXPLAIN` is the plan of a single process,
+//! which never runs as such on a cluster. The scheduler shows the query stages the job
+//! of the explained query would run instead: each with its task count, the stages whose
+//! output it reads, how its own output is partitioned for the stages reading it and its
+//! estimated size, followed by its plan. Shuffle exchanges show as the
+//! `ShuffleWriterExec` at the root of a stage and the `UnresolvedShuffleExec`s reading
+//! it in later stages.
+
+use std::sync::Arc;
+
+use datafusion::arrow::datatypes::Schema;
+use datafusion::logical_plan::plan::{Explain, PlanType, StringifiedPlan};
+use datafusion::logical_plan::LogicalPlan;
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::explain::ExplainExec;
+use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+
+use crate::planner::estimated_bytes;
+use crate::state::execution_graph::{ExecutionGraph, ExecutionStage};
+
+/// The plan of the job answering `explain`: the plans it explains, with the stages of
+/// `graph` as the physical plan. `logical_plan` is the optimized plan explained, if
+/// known.
+pub fn distributed_explain(
+    explain: &Explain,
+    logical_plan: Option<&LogicalPlan>,
+    graph: &ExecutionGraph,
+) -> Arc<dyn ExecutionPlan> {
+    let mut plans = explain.stringified_plans.clone();
+    if let Some(plan) = logical_plan {
+        plans.push(StringifiedPlan::new(
+            PlanType::FinalLogicalPlan,
+            plan.display_indent().to_string(),
+        ));
+    }
+    plans.push(StringifiedPlan::new(
+        PlanType::FinalPhysicalPlan,
+        explain_stages(graph),
+    ));
+    let schema: Schema = explain.schema.as_ref().clone().into();
+    Arc::new(ExplainExec::new(Arc::new(schema), plans, explain.verbose))
+}
+
+/// The stages of `graph` in the order they run, each followed by its plan
+pub fn explain_stages(graph: &ExecutionGraph) -> String {
+    let mut stages: Vec<&ExecutionStage> = graph.stages.values().collect();
+    stages.sort_by_key(|stage| stage.stage_id);
+    stages
+        .into_iter()
+        .map(explain_stage)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn explain_stage(stage: &ExecutionStage) -> String {
+    let mut inputs: Vec<usize> = stage.inputs.keys().copied().collect();
+    inputs.sort_unstable();
+    let output = if stage.output_links.is_empty() {
+        "final".to_owned()
+    } else {
+        match &stage.output_partitioning {
+            Some(partitioning) => explain_partitioning(partitioning),
+            None => format!("{} partitions", stage.partitions),
+        }
+    };
+    let estimate = |value: Option<usize>| {
+        value.map_or_else(|| "unknown".to_owned(), |value| value.to_string())
+    };
+    let plan = DisplayableExecutionPlan::new(stage.plan.as_ref()).indent();
+    format!(
+        "Stage[id={}, tasks={}, inputs={:?}, output={}, estimated_rows={}, \
+         estimated_bytes={}]\n{}",
+        stage.stage_id,
+        stage.partitions,
+        inputs,
+        output,
+        estimate(stage.plan.statistics().num_rows),
+        estimate(estimated_bytes(stage.plan.as_ref())),
+        plan
+    )
+}
+
+pub(crate) fn explain_partitioning(partitioning: &Partitioning) -> String {
+    match partitioning {
+        Partitioning::Hash(exprs, count) => format!(
+            "hash([{}], {})",
+            exprs
+                .iter()
+                .map(|expr| expr.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+            count
+        ),
+        Partitioning::RoundRobinBatch(count) => format!("round_robin({})", count),
+        Partitioning::UnknownPartitioning(count) => format!("{} partitions", count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::datafusion_test_context;
+    use ballista_core::error::Result;
+
+    #[tokio::test]
+    async fn explain_aggregate_stages() -> Result<()> {
+        let ctx = datafusion_test_context("testdata").await?;
+        let df = ctx
+            .sql(
+                "EXPLAIN select l_returnflag, sum(l_extendedprice) as revenue
+                from lineitem
+                group by l_returnflag",
+            )
+            .await?;
+        let explain = match df.to_logical_plan()? {
+            LogicalPlan::Explain(explain) => explain,
+            plan => panic!("Expected an explain plan, got {:?}", plan),
+        };
+        let plan = ctx.optimize(explain.plan.as_ref())?;
+        let physical_plan = ctx.create_physical_plan(&plan).await?;
+        let graph = ExecutionGraph::new("job", "session", physical_plan)?;
+
+        let exec = distributed_explain(&explain, Some(&plan), &graph);
+        let exec = exec.as_any().downcast_ref::<ExplainExec>().unwrap();
+        let physical_plan = exec
+            .stringified_plans()
+            .iter()
+            .find(|plan| plan.plan_type == PlanType::FinalPhysicalPlan)
+            .unwrap();
+        let stages: Vec<&str> = physical_plan
+            .plan
+            .lines()
+            .filter(|line| line.starts_with("Stage["))
+            .collect();
+        assert_eq!(stages.len(), 2);
+        assert!(stages[0].starts_with("Stage[id=1, "));
+        assert!(stages[0].contains("inputs=[], output=hash([l_returnflag@0], 2)"));
+        assert!(stages[1].starts_with("Stage[id=2, tasks=2, inputs=[1], output=final"));
+        assert!(physical_plan.plan.contains("UnresolvedShuffleExec"));
+        assert!(exec
+            .stringified_plans()
+            .iter()
+            .any(|plan| plan.plan_type == PlanType::FinalLogicalPlan));
+        Ok(())
+    }
+}
@@ -26,7 +26,7 @@ use arrow_flight::sql::{
     CommandPreparedStatementQuery, CommandPreparedStatementUpdate, CommandStatementQuery,
     CommandStatementUpdate, SqlInfo, TicketStatementQuery,
 };
-use arrow_flight::{FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, Location, Ticket};
+use arrow_flight::{Action, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest, HandshakeResponse, Location, SchemaResult, Ticket};
 use log::{debug, error, warn};
 use std::collections::HashMap;
 use std::pin::Pin;
@@ -34,15 +34,19 @@ use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tonic::{Request, Response, Status, Streaming};
 
+use crate::auth::{scanned_tables, Operation, Principal};
+use crate::planner::ScanTaskOptions;
 use crate::scheduler_server::event::QueryStageSchedulerEvent;
 use crate::scheduler_server::SchedulerServer;
 use arrow_flight::SchemaAsIpc;
+use ballista_core::auth::{bearer_token, AUTHORIZATION_HEADER};
 use ballista_core::config::BallistaConfig;
 use ballista_core::serde::protobuf;
 use ballista_core::serde::protobuf::job_status;
 use ballista_core::serde::protobuf::CompletedJob;
 use ballista_core::serde::protobuf::JobStatus;
 use ballista_core::serde::protobuf::PhysicalPlanNode;
+use ballista_core::table_format::time_travel::sql_as_of;
 use datafusion::arrow;
 use datafusion::arrow::datatypes::Schema;
 use datafusion::arrow::ipc::writer::{IpcDataGenerator, IpcWriteOptions};
@@ -55,6 +59,12 @@ use tokio::time::sleep;
 use tonic::codegen::futures_core::Stream;
 use uuid::Uuid;
 
+tokio::task_local! {
+    /// The client a Flight SQL request is handled for, `None` if client authentication
+    /// is disabled
+    static PRINCIPAL: Option<Principal>;
+}
+
 pub struct FlightSqlServiceImpl {
     server: SchedulerServer<LogicalPlanNode, PhysicalPlanNode>,
     statements: Arc<Mutex<HashMap<Uuid, LogicalPlan>>>,
@@ -86,11 +96,11 @@ impl FlightSqlServiceImpl {
     }
 
     async fn prepare_statement(
+        &self,
         query: &str,
         ctx: &Arc<SessionContext>,
     ) -> Result<LogicalPlan, Status> {
-        let plan = ctx
-            .sql(query)
+        let plan = sql_as_of(ctx, query, &self.server.state.table_formats)
             .await
             .and_then(|df| df.to_logical_plan())
             .map_err(|e| Status::internal(format!("Error building plan: {}", e)))?;
@@ -131,7 +141,8 @@ impl FlightSqlServiceImpl {
             job_status::Status::Failed(e) => {
                 warn!("Error executing plan: {:?}", e);
                 Err(Status::internal(format!(
-                    "Error executing plan: {}",
+                    "Error executing plan ({:?}): {}",
+                    e.kind(),
                     e.error
                 )))?
             }
@@ -153,6 +164,8 @@ impl FlightSqlServiceImpl {
                     stage_id: id.stage_id,
                     partition_id: id.partition_id,
                     path: loc.path.clone(),
+                    compression: protobuf::IpcCompression::NoCompression.into(),
+                    priority: None,
                 };
                 protobuf::Action {
                     action_type: Some(protobuf::action::ActionType::FetchPartition(
@@ -242,7 +255,37 @@ impl FlightSqlServiceImpl {
         ctx: Arc<SessionContext>,
         plan: &LogicalPlan,
     ) -> Result<String, Status> {
-        let job_id = self.server.state.task_manager.generate_job_id();
+        if self.server.state.shutting_down() {
+            return Err(Status::unavailable(
+                "Scheduler is shutting down and does not accept new jobs",
+            ));
+        }
+        let config = BallistaConfig::new()
+            .map_err(|e| Status::internal(format!("Error building config: {}", e)))?;
+        let principal = PRINCIPAL.try_with(Clone::clone).ok().flatten();
+        if let (Some(auth), Some(principal)) =
+            (&self.server.state.client_auth, &principal)
+        {
+            let tables = scanned_tables(plan);
+            let operation = Operation::SubmitQuery {
+                sql: None,
+                tables: &tables,
+            };
+            auth.authorize(principal, &operation).await?;
+        }
+        let job_id = self.server.state.task_manager.generate_local_job_id();
+        if let Some(principal) = &principal {
+            self.server
+                .state
+                .task_manager
+                .save_job_owner(&job_id, &principal.name)
+                .await
+                .map_err(|e| {
+                    let msg = format!("Error saving owner of job {}: {:?}", job_id, e);
+                    error!("{}", msg);
+                    Status::internal(msg)
+                })?;
+        }
         self.server
             .state
             .task_manager
@@ -270,6 +313,19 @@ impl FlightSqlServiceImpl {
                 session_id: ctx.session_id().clone(),
                 session_ctx: ctx,
                 plan: Box::new(plan.clone()),
+                trace_context: vec![],
+                identity: principal.map(|principal| principal.name),
+                table_locations: HashMap::new(),
+                parquet_output: None,
+                plan_key: None,
+                cache_result: true,
+                shuffle_partitions: None,
+                scan_tasks: Some(ScanTaskOptions::from_config(&config)),
+                partial_aggregation_max_groups: None,
+                bloom_filter_bytes: None,
+                job_priority: 0,
+                replicate_shuffles: false,
+                retry: None,
             })
             .await
             .map_err(|e| {
@@ -361,7 +417,7 @@ impl FlightSqlService for FlightSqlServiceImpl {
             .map_err(|_| Status::invalid_argument("authorization not parsable"))?;
         let str = String::from_utf8(bytes)
             .map_err(|_| Status::invalid_argument("authorization not parsable"))?;
-        let parts: Vec<_> = str.split(":").collect();
+        let parts: Vec<_> = str.splitn(2, ':').collect();
         if parts.len() != 2 {
             Err(Status::invalid_argument(format!(
                 "Invalid authorization header"
@@ -369,16 +425,35 @@ impl FlightSqlService for FlightSqlServiceImpl {
         }
         let user = parts[0];
         let pass = parts[1];
-        if user != "admin" || pass != "password" {
+        // The password is one of the bearer tokens of the scheduler, which clients send
+        // with their following requests
+        let auth = match &self.server.state.client_auth {
+            Some(auth) => auth,
+            None => {
+                Err(Status::unimplemented("Client authentication is not enabled"))?
+            }
+        };
+        let principal = auth.authenticate(Some(pass)).await?;
+        if principal.name != user {
+            warn!(
+                "Rejecting Flight SQL handshake of {} with the token of {}",
+                user, principal.name
+            );
             Err(Status::unauthenticated("Invalid credentials!"))?
         }
+        let bearer = format!("Bearer {}", pass)
+            .parse()
+            .map_err(|_| Status::invalid_argument("authorization not parsable"))?;
         let result = HandshakeResponse {
             protocol_version: 0,
-            payload: Uuid::new_v4().as_bytes().to_vec(),
+            payload: pass.as_bytes().to_vec(),
         };
         let result = Ok(result);
-        let output = futures::stream::iter(vec![result]);
-        return Ok(Response::new(Box::pin(output)));
+        let output: Pin<Box<dyn Stream<Item = Result<HandshakeResponse, Status>> + Send>> =
+            Box::pin(futures::stream::iter(vec![result]));
+        let mut response = Response::new(output);
+        response.metadata_mut().insert(AUTHORIZATION_HEADER, bearer);
+        return Ok(response);
     }
 
     async fn get_flight_info_statement(
@@ -389,7 +464,7 @@ impl FlightSqlService for FlightSqlServiceImpl {
         debug!("Got query:\n{}", query.query);
 
         let ctx = self.create_ctx().await?;
-        let plan = Self::prepare_statement(&query.query, &ctx).await?;
+        let plan = self.prepare_statement(&query.query, &ctx).await?;
         let resp = self.execute_plan(ctx, &plan).await?;
 
         debug!("Responding to query...");
@@ -589,7 +664,7 @@ impl FlightSqlService for FlightSqlServiceImpl {
         query: ActionCreatePreparedStatementRequest,
     ) -> Result<ActionCreatePreparedStatementResult, Status> {
         let ctx = self.create_ctx().await?;
-        let plan = Self::prepare_statement(&query.query, &ctx).await?;
+        let plan = self.prepare_statement(&query.query, &ctx).await?;
         let schema_bytes = self.df_schema_to_arrow(plan.schema())?;
         let handle = self.cache_plan(plan)?;
         let res = ActionCreatePreparedStatementResult {
@@ -615,3 +690,113 @@ impl FlightSqlService for FlightSqlServiceImpl {
 
     async fn register_sql_info(&self, _id: i32, _result: &SqlInfo) {}
 }
+
+/// Authenticates the clients of a [FlightSqlServiceImpl] by the bearer tokens they were
+/// given in the handshake, if client authentication is enabled
+pub struct AuthenticatedFlightSqlService {
+    inner: FlightSqlServiceImpl,
+}
+
+impl AuthenticatedFlightSqlService {
+    pub fn new(server: SchedulerServer<LogicalPlanNode, PhysicalPlanNode>) -> Self {
+        Self {
+            inner: FlightSqlServiceImpl::new(server),
+        }
+    }
+
+    async fn authenticate<R>(
+        &self,
+        request: &Request<R>,
+    ) -> Result<Option<Principal>, Status> {
+        match &self.inner.server.state.client_auth {
+            Some(auth) => {
+                let token = bearer_token(request.metadata());
+                auth.authenticate(token).await.map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl FlightService for AuthenticatedFlightSqlService {
+    type HandshakeStream = <FlightSqlServiceImpl as FlightService>::HandshakeStream;
+    type ListFlightsStream = <FlightSqlServiceImpl as FlightService>::ListFlightsStream;
+    type DoGetStream = <FlightSqlServiceImpl as FlightService>::DoGetStream;
+    type DoPutStream = <FlightSqlServiceImpl as FlightService>::DoPutStream;
+    type DoActionStream = <FlightSqlServiceImpl as FlightService>::DoActionStream;
+    type ListActionsStream = <FlightSqlServiceImpl as FlightService>::ListActionsStream;
+    type DoExchangeStream = <FlightSqlServiceImpl as FlightService>::DoExchangeStream;
+
+    /// Checks the credentials itself, see [FlightSqlServiceImpl::do_handshake]
+    async fn handshake(
+        &self,
+        request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        self.inner.handshake(request).await
+    }
+
+    async fn list_flights(
+        &self,
+        request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.list_flights(request)).await
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.get_flight_info(request)).await
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.get_schema(request)).await
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.do_get(request)).await
+    }
+
+    async fn do_put(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.do_put(request)).await
+    }
+
+    async fn do_action(
+        &self,
+        request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.do_action(request)).await
+    }
+
+    async fn list_actions(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.list_actions(request)).await
+    }
+
+    async fn do_exchange(
+        &self,
+        request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        let principal = self.authenticate(&request).await?;
+        PRINCIPAL.scope(principal, self.inner.do_exchange(request)).await
+    }
+}
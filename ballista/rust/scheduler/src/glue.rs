@@ -0,0 +1,327 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Catalog of the tables of the AWS Glue Data Catalog.
+//!
+//! Glue describes tables like a Hive Metastore, so its tables are resolved into
+//! [ListingTable](datafusion::datasource::listing::ListingTable)s by
+//! [TableDefinition::listing_table]. Databases and tables are looked up when queries are
+//! planned, which blocks the planning thread on the Glue API, so the catalog must be used
+//! on a multi-threaded Tokio runtime like the one of the scheduler.
+
+use aws_sdk_glue::model::{Column, Table};
+use aws_sdk_glue::Client;
+use ballista_core::catalog::{ColumnDefinition, TableDefinition};
+use ballista_core::error::{BallistaError, Result};
+use datafusion::catalog::catalog::CatalogProvider;
+use datafusion::catalog::schema::SchemaProvider;
+use datafusion::datasource::TableProvider;
+use datafusion::prelude::SessionConfig;
+use log::warn;
+use std::any::Any;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+
+/// Catalog of the databases of the AWS Glue Data Catalog
+pub struct GlueCatalog {
+    glue: Glue,
+    target_partitions: usize,
+}
+
+impl GlueCatalog {
+    /// The catalog of the account of the credentials found in the environment, in the
+    /// region configured by it
+    pub async fn from_env() -> Self {
+        Self::new(Client::new(&aws_config::load_from_env().await))
+    }
+
+    /// Must be called on the Tokio runtime the catalog is used on
+    pub fn new(client: Client) -> Self {
+        Self {
+            glue: Glue {
+                client,
+                catalog_id: None,
+                runtime: Handle::current(),
+            },
+            target_partitions: SessionConfig::new().target_partitions,
+        }
+    }
+
+    /// Read the catalog of another AWS account, which shares it with the account of the
+    /// credentials
+    pub fn with_catalog_id(mut self, catalog_id: impl Into<String>) -> Self {
+        self.glue.catalog_id = Some(catalog_id.into());
+        self
+    }
+
+    /// The number of partitions the tables are scanned in
+    pub fn with_target_partitions(mut self, target_partitions: usize) -> Self {
+        self.target_partitions = target_partitions;
+        self
+    }
+}
+
+impl CatalogProvider for GlueCatalog {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        self.glue.databases().unwrap_or_else(|e| {
+            warn!("Could not list the Glue databases: {}", e);
+            vec![]
+        })
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        Some(Arc::new(GlueSchema {
+            glue: self.glue.clone(),
+            database: name.to_owned(),
+            target_partitions: self.target_partitions,
+        }))
+    }
+}
+
+/// The tables of a database of the AWS Glue Data Catalog
+pub struct GlueSchema {
+    glue: Glue,
+    database: String,
+    target_partitions: usize,
+}
+
+impl SchemaProvider for GlueSchema {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.glue.tables(&self.database).unwrap_or_else(|e| {
+            warn!(
+                "Could not list the tables of Glue database {}: {}",
+                self.database, e
+            );
+            vec![]
+        })
+    }
+
+    fn table(&self, name: &str) -> Option<Arc<dyn TableProvider>> {
+        let table = self.glue.table(&self.database, name).and_then(|table| {
+            table
+                .map(|table| {
+                    let table = table.listing_table(self.target_partitions)?;
+                    Ok(Arc::new(table) as Arc<dyn TableProvider>)
+                })
+                .transpose()
+        });
+        table.unwrap_or_else(|e| {
+            warn!(
+                "Could not load Glue table {}.{}: {}",
+                self.database, name, e
+            );
+            None
+        })
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        matches!(self.glue.table(&self.database, name), Ok(Some(_)))
+    }
+}
+
+/// Blocking calls of the Glue API
+#[derive(Clone)]
+struct Glue {
+    client: Client,
+    catalog_id: Option<String>,
+    runtime: Handle,
+}
+
+impl Glue {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::task::block_in_place(|| self.runtime.block_on(future))
+    }
+
+    fn databases(&self) -> Result<Vec<String>> {
+        self.block_on(async {
+            let mut names = vec![];
+            let mut next_token = None;
+            loop {
+                let page = self
+                    .client
+                    .get_databases()
+                    .set_catalog_id(self.catalog_id.clone())
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                    .map_err(|e| glue_error("GetDatabases", e))?;
+                names.extend(
+                    page.database_list()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|database| database.name().map(str::to_owned)),
+                );
+                next_token = page.next_token().map(str::to_owned);
+                if next_token.is_none() {
+                    return Ok(names);
+                }
+            }
+        })
+    }
+
+    fn tables(&self, database: &str) -> Result<Vec<String>> {
+        self.block_on(async {
+            let mut names = vec![];
+            let mut next_token = None;
+            loop {
+                let page = self
+                    .client
+                    .get_tables()
+                    .set_catalog_id(self.catalog_id.clone())
+                    .database_name(database)
+                    .set_next_token(next_token)
+                    .send()
+                    .await
+                    .map_err(|e| glue_error("GetTables", e))?;
+                names.extend(
+                    page.table_list()
+                        .unwrap_or_default()
+                        .iter()
+                        .filter_map(|table| table.name().map(str::to_owned)),
+                );
+                next_token = page.next_token().map(str::to_owned);
+                if next_token.is_none() {
+                    return Ok(names);
+                }
+            }
+        })
+    }
+
+    fn table(&self, database: &str, name: &str) -> Result<Option<TableDefinition>> {
+        self.block_on(async {
+            let result = self
+                .client
+                .get_table()
+                .set_catalog_id(self.catalog_id.clone())
+                .database_name(database)
+                .name(name)
+                .send()
+                .await;
+            match result {
+                Ok(output) => Ok(output.table().map(table_definition)),
+                Err(e) => {
+                    let e = e.into_service_error();
+                    if e.is_entity_not_found_exception() {
+                        Ok(None)
+                    } else {
+                        Err(glue_error("GetTable", e))
+                    }
+                }
+            }
+        })
+    }
+}
+
+fn glue_error(operation: &str, error: impl std::error::Error) -> BallistaError {
+    BallistaError::General(format!("Glue {} failed: {}", operation, error))
+}
+
+/// The definition of a table of Glue, which has the fields of a table of a Hive
+/// Metastore
+fn table_definition(table: &Table) -> TableDefinition {
+    let columns = |columns: Option<&[Column]>| {
+        columns
+            .unwrap_or_default()
+            .iter()
+            .map(|column| ColumnDefinition {
+                name: column.name().unwrap_or_default().to_owned(),
+                data_type: column.r#type().unwrap_or_default().to_owned(),
+            })
+            .collect()
+    };
+    let storage = table.storage_descriptor();
+    let serde = storage.and_then(|storage| storage.serde_info());
+    TableDefinition {
+        name: table.name().unwrap_or_default().to_owned(),
+        location: storage
+            .and_then(|storage| storage.location())
+            .unwrap_or_default()
+            .to_owned(),
+        columns: columns(storage.and_then(|storage| storage.columns())),
+        partition_keys: columns(table.partition_keys()),
+        input_format: storage
+            .and_then(|storage| storage.input_format())
+            .map(str::to_owned),
+        serialization_library: serde
+            .and_then(|serde| serde.serialization_library())
+            .map(str::to_owned),
+        serde_parameters: serde
+            .and_then(|serde| serde.parameters())
+            .cloned()
+            .unwrap_or_default(),
+        parameters: table.parameters().cloned().unwrap_or_default(),
+        table_type: table.table_type().map(str::to_owned),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_glue::model::{SerDeInfo, StorageDescriptor};
+
+    #[test]
+    fn glue_tables() {
+        let column = |name: &str, data_type: &str| {
+            Column::builder().name(name).r#type(data_type).build()
+        };
+        let table = Table::builder()
+            .name("orders")
+            .table_type("EXTERNAL_TABLE")
+            .parameters("classification", "csv")
+            .partition_keys(column("day", "string"))
+            .storage_descriptor(
+                StorageDescriptor::builder()
+                    .location("s3a://lake/orders/")
+                    .columns(column("id", "bigint"))
+                    .columns(column("items", "array<struct<sku:string,count:int>>"))
+                    .input_format("org.apache.hadoop.mapred.TextInputFormat")
+                    .serde_info(
+                        SerDeInfo::builder()
+                            .serialization_library(
+                                "org.apache.hadoop.hive.serde2.lazy.LazySimpleSerDe",
+                            )
+                            .parameters("field.delim", ",")
+                            .build(),
+                    )
+                    .build(),
+            )
+            .build();
+
+        let definition = table_definition(&table);
+        assert_eq!(definition.name, "orders");
+        assert_eq!(definition.location, "s3a://lake/orders/");
+        assert_eq!(definition.columns.len(), 2);
+        assert_eq!(definition.partition_keys[0].name, "day");
+        assert_eq!(
+            definition
+                .serde_parameters
+                .get("field.delim")
+                .map(String::as_str),
+            Some(",")
+        );
+        assert_eq!(definition.table_type.as_deref(), Some("EXTERNAL_TABLE"));
+    }
+}
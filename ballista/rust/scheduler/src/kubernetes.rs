@@ -0,0 +1,215 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Watch of the Kubernetes pods of the executors.
+//!
+//! Executors running in Kubernetes register the name of their pod as their
+//! [POD_NAME_LABEL] label. The scheduler watches the executor pods, and considers the
+//! executors of a pod which is being deleted or whose containers have stopped lost
+//! right away, instead of scheduling tasks on them until their heartbeats time out.
+
+use crate::scheduler_server::SchedulerServer;
+use crate::state::executor_manager::ExecutorManager;
+use ballista_core::error::Result;
+use ballista_core::serde::scheduler::{POD_NAMESPACE_LABEL, POD_NAME_LABEL};
+use ballista_core::serde::AsExecutionPlan;
+use datafusion_proto::logical_plan::AsLogicalPlan;
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::ListParams;
+use kube::runtime::watcher::{watcher, Event};
+use kube::{Api, Client};
+use log::{info, warn};
+use std::time::Duration;
+
+/// Delay before watching the pods again after the watch failed
+const WATCH_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The executor pods watched by the scheduler
+#[derive(Debug, Clone)]
+pub struct ExecutorPods {
+    /// Namespace of the pods, the namespace of the scheduler if `None`
+    pub namespace: Option<String>,
+    /// Label selector of the pods, e.g. `app=ballista-executor`
+    pub label_selector: String,
+}
+
+impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T, U> {
+    /// Spawn a task watching the executor `pods`, using the Kubernetes configuration of
+    /// the environment
+    pub fn watch_executor_pods(&self, pods: ExecutorPods) {
+        tokio::spawn(watch(self.state.executor_manager.clone(), pods));
+    }
+}
+
+async fn watch(executor_manager: ExecutorManager, pods: ExecutorPods) {
+    let client = match Client::try_default().await {
+        Ok(client) => client,
+        Err(e) => {
+            warn!(
+                "Could not create Kubernetes client to watch executor pods: {}",
+                e
+            );
+            return;
+        }
+    };
+    let api: Api<Pod> = match &pods.namespace {
+        Some(namespace) => Api::namespaced(client, namespace),
+        None => Api::default_namespaced(client),
+    };
+    info!("Watching executor pods {}", pods.label_selector);
+
+    let mut events =
+        watcher(api, ListParams::default().labels(&pods.label_selector)).boxed();
+    while let Some(event) = events.next().await {
+        let lost = match event {
+            Ok(Event::Applied(pod)) if stopped(&pod) => vec![pod],
+            Ok(Event::Applied(_)) => vec![],
+            Ok(Event::Deleted(pod)) => vec![pod],
+            Ok(Event::Restarted(pods)) => pods.into_iter().filter(stopped).collect(),
+            Err(e) => {
+                warn!("Error watching executor pods: {}", e);
+                tokio::time::sleep(WATCH_RETRY_DELAY).await;
+                continue;
+            }
+        };
+        for pod in lost {
+            if let Err(e) = expire_executors(&executor_manager, &pod).await {
+                warn!("Could not expire executors of stopped pod: {}", e);
+            }
+        }
+    }
+}
+
+/// Whether the executors of `pod` can no longer run tasks. The phase of a pod stays
+/// `Running` while its containers are restarted, so a container which terminated, or
+/// waits to be restarted, stops the pod as well: a restarted executor registers again
+/// under a new ID.
+fn stopped(pod: &Pod) -> bool {
+    let status = match &pod.status {
+        Some(status) => status,
+        None => return pod.metadata.deletion_timestamp.is_some(),
+    };
+    let container_stopped = status.container_statuses.iter().flatten().any(|container| {
+        match &container.state {
+            Some(state) => {
+                state.terminated.is_some()
+                    || (state.waiting.is_some() && container.restart_count > 0)
+            }
+            None => false,
+        }
+    });
+    pod.metadata.deletion_timestamp.is_some()
+        || matches!(status.phase.as_deref(), Some("Failed") | Some("Succeeded"))
+        || container_stopped
+}
+
+async fn expire_executors(executor_manager: &ExecutorManager, pod: &Pod) -> Result<()> {
+    let name = match &pod.metadata.name {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    let namespace = pod.metadata.namespace.as_deref();
+    for executor in executor_manager
+        .get_alive_executors_with_label(POD_NAME_LABEL, name)
+        .await?
+    {
+        // Pods of other namespaces may have the same name
+        let executor_namespace = executor.label(POD_NAMESPACE_LABEL);
+        if executor_namespace.is_some()
+            && namespace.is_some()
+            && executor_namespace != namespace
+        {
+            continue;
+        }
+        info!("Executor {} lost with its pod {}", executor.id, name);
+        executor_manager.expire_executor(&executor.id).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::api::core::v1::{
+        ContainerState, ContainerStateRunning, ContainerStateTerminated,
+        ContainerStateWaiting, ContainerStatus, PodStatus,
+    };
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    fn pod(phase: &str) -> Pod {
+        Pod {
+            status: Some(PodStatus {
+                phase: Some(phase.to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn stopped_pods() {
+        assert!(!stopped(&Pod::default()));
+        assert!(!stopped(&pod("Pending")));
+        assert!(!stopped(&pod("Running")));
+        assert!(stopped(&pod("Failed")));
+        assert!(stopped(&pod("Succeeded")));
+
+        let mut deleted = pod("Running");
+        deleted.metadata.deletion_timestamp = Some(Time(k8s_openapi::chrono::Utc::now()));
+        assert!(stopped(&deleted));
+    }
+
+    #[test]
+    fn stopped_containers() {
+        let running = |state, restart_count| {
+            let mut pod = pod("Running");
+            pod.status.as_mut().unwrap().container_statuses =
+                Some(vec![ContainerStatus {
+                    name: "executor".to_owned(),
+                    state: Some(state),
+                    restart_count,
+                    ..Default::default()
+                }]);
+            pod
+        };
+        let started = ContainerState {
+            running: Some(ContainerStateRunning::default()),
+            ..Default::default()
+        };
+        let waiting = ContainerState {
+            waiting: Some(ContainerStateWaiting {
+                reason: Some("CrashLoopBackOff".to_owned()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let terminated = ContainerState {
+            terminated: Some(ContainerStateTerminated {
+                exit_code: 137,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(!stopped(&running(started.clone(), 0)));
+        assert!(!stopped(&running(started, 1)));
+        // The container is waiting to start for the first time
+        assert!(!stopped(&running(waiting.clone(), 0)));
+        assert!(stopped(&running(waiting, 1)));
+        assert!(stopped(&running(terminated, 0)));
+    }
+}
@@ -18,11 +18,29 @@
 #![doc = include_str ! ("../README.md")]
 
 pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod column_masking;
+pub mod event_log;
+pub mod explain;
+#[cfg(feature = "glue")]
+pub mod glue;
+#[cfg(feature = "kubernetes")]
+pub mod kubernetes;
+pub mod metrics;
+pub mod plan_export;
+pub mod plan_rewrite;
 pub mod planner;
+pub mod query_history;
+pub mod row_security;
 pub mod scheduler_server;
+pub mod sql_expr;
 #[cfg(feature = "sled")]
 pub mod standalone;
 pub mod state;
+#[cfg(feature = "kafka")]
+pub mod streaming;
+pub mod webhook;
 
 pub mod flight_sql;
 #[cfg(test)]
@@ -19,33 +19,67 @@
 
 use anyhow::{Context, Result};
 use arrow_flight::flight_service_server::FlightServiceServer;
-use ballista_scheduler::scheduler_server::externalscaler::external_scaler_server::ExternalScalerServer;
+use ballista_scheduler::scheduler_server::externalscaler::{
+    self, external_scaler_server::ExternalScalerServer,
+};
 use futures::future::{self, Either, TryFutureExt};
+use futures::stream::{self, Stream, StreamExt};
+use hyper::server::accept;
 use hyper::{server::conn::AddrStream, service::make_service_fn, Server};
+use serde::Deserialize;
 use std::convert::Infallible;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc};
-use tonic::transport::server::Connected;
-use tonic::transport::Server as TonicServer;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tonic::transport::server::{Connected, TcpConnectInfo, TlsConnectInfo};
+use tonic::transport::{NamedService, Server as TonicServer};
 use tower::Service;
 
+use ballista_core::cluster_config::ClusterConfig;
+use ballista_core::grpc_services::{self, HealthReporter, ServingStatus};
+use ballista_core::object_store_registry::cache::MetadataCacheOptions;
+use ballista_core::object_store_registry::{
+    ObjectStoreCredentials, ObjectStoreFactories,
+};
+use ballista_core::tls::{self, TlsOptions};
 use ballista_core::BALLISTA_VERSION;
+use ballista_core::{logging, telemetry};
 use ballista_core::{
     print_version,
     serde::protobuf::{scheduler_grpc_server::SchedulerGrpcServer, PhysicalPlanNode},
 };
-use ballista_scheduler::api::{get_routes, EitherBody, Error};
+use ballista_scheduler::api::{get_history_routes, get_routes, EitherBody, Error};
+use ballista_scheduler::audit::{AuditSink, ObjectStoreAuditSink};
+use ballista_scheduler::auth::{AllowAll, ClientAuth, StaticTokenValidator};
+use ballista_scheduler::column_masking::ColumnMasking;
+use ballista_scheduler::event_log::EventLog;
+#[cfg(feature = "kubernetes")]
+use ballista_scheduler::kubernetes::ExecutorPods;
+use ballista_scheduler::query_history::QueryHistory;
+use ballista_scheduler::row_security::RowLevelSecurity;
 #[cfg(feature = "etcd")]
 use ballista_scheduler::state::backend::etcd::EtcdClient;
 #[cfg(feature = "sled")]
 use ballista_scheduler::state::backend::standalone::StandaloneClient;
+use ballista_scheduler::state::executor_pools::TenantPools;
+use ballista_scheduler::state::job_shards::JobShards;
+use ballista_scheduler::state::tenant_priorities::TenantPriorities;
 use datafusion_proto::protobuf::LogicalPlanNode;
 
 use ballista_scheduler::scheduler_server::SchedulerServer;
 use ballista_scheduler::state::backend::{StateBackend, StateBackendClient};
+use ballista_scheduler::state::plan_cache::PlanCacheOptions;
+use ballista_scheduler::state::result_cache::ResultCacheOptions;
+#[cfg(feature = "kafka")]
+use ballista_scheduler::streaming::StreamingQuery;
 
-use ballista_core::config::TaskSchedulingPolicy;
+use ballista_core::config::{LogFormat, TaskSchedulingPolicy};
 use ballista_core::serde::BallistaCodec;
-use log::info;
+use log::{error, info, warn};
 
 #[macro_use]
 extern crate configure_me;
@@ -60,15 +94,53 @@ mod config {
     ));
 }
 
-use ballista_scheduler::flight_sql::FlightSqlServiceImpl;
+use ballista_scheduler::flight_sql::AuthenticatedFlightSqlService;
 use config::prelude::*;
+use datafusion::catalog::catalog::CatalogProvider;
 use datafusion::execution::context::default_session_builder;
 
+/// Optional features of the scheduler
+#[derive(Default)]
+struct ServerOptions {
+    event_log: Option<Arc<EventLog>>,
+    job_webhook_url: Option<String>,
+    job_callback_hosts: Vec<String>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    query_history: Option<Arc<QueryHistory>>,
+    tenant_pools: TenantPools,
+    tenant_priorities: TenantPriorities,
+    job_shards: Option<JobShards>,
+    client_auth: Option<ClientAuth>,
+    column_masking: Option<Arc<ColumnMasking>>,
+    row_security: Option<Arc<RowLevelSecurity>>,
+    encrypt_shuffle_files: bool,
+    object_store_factories: ObjectStoreFactories,
+    catalogs: Vec<(String, Arc<dyn CatalogProvider>)>,
+    plan_cache: Option<PlanCacheOptions>,
+    result_cache: Option<ResultCacheOptions>,
+    #[cfg(feature = "kafka")]
+    streaming_queries: Vec<StreamingQuery>,
+    #[cfg(feature = "kubernetes")]
+    executor_pods: Option<ExecutorPods>,
+    tls: TlsOptions,
+    /// Time after which executors the scheduler did not hear of are considered lost
+    executor_lease: Duration,
+    /// Time after the last query of a session after which it is closed, if any
+    session_idle_timeout: Option<Duration>,
+    /// Time to let the unfinished jobs complete when shutting down
+    shutdown_timeout: Duration,
+}
+
+/// Maximum number of TLS handshakes performed concurrently by the scheduler
+const TLS_HANDSHAKE_CONCURRENCY: usize = 128;
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
 async fn start_server(
     config_backend: Arc<dyn StateBackendClient>,
     namespace: String,
     addr: SocketAddr,
     policy: TaskSchedulingPolicy,
+    options: ServerOptions,
 ) -> Result<()> {
     info!(
         "Ballista v{} Scheduler listening on {:?}",
@@ -95,16 +167,85 @@ async fn start_server(
             ),
         };
 
+    if let Some(event_log) = options.event_log {
+        scheduler_server = scheduler_server.with_event_log(event_log);
+    }
+    if let Some(url) = options.job_webhook_url {
+        scheduler_server = scheduler_server.with_job_webhook(url);
+    }
+    if !options.job_callback_hosts.is_empty() {
+        scheduler_server =
+            scheduler_server.with_job_callback_hosts(options.job_callback_hosts);
+    }
+    if let Some(sink) = options.audit_sink {
+        scheduler_server = scheduler_server.with_audit_sink(sink);
+    }
+    if let Some(query_history) = options.query_history {
+        scheduler_server = scheduler_server.with_query_history(query_history);
+    }
+    scheduler_server = scheduler_server.with_tenant_pools(options.tenant_pools);
+    scheduler_server = scheduler_server.with_tenant_priorities(options.tenant_priorities);
+    if let Some(shards) = options.job_shards {
+        scheduler_server = scheduler_server.with_job_shards(shards);
+    }
+    if let Some(auth) = options.client_auth {
+        scheduler_server = scheduler_server.with_client_auth(auth);
+    }
+    // Columns are masked above the row filters, which read the values of the columns
+    if let Some(column_masking) = options.column_masking {
+        scheduler_server = scheduler_server.with_plan_rewriter(column_masking);
+    }
+    if let Some(row_security) = options.row_security {
+        scheduler_server = scheduler_server.with_plan_rewriter(row_security);
+    }
+    if options.encrypt_shuffle_files {
+        scheduler_server = scheduler_server.with_shuffle_encryption();
+    }
+    if options.tls.client_auth_enabled() {
+        scheduler_server = scheduler_server.with_executor_authentication();
+    }
+    scheduler_server =
+        scheduler_server.with_object_store_factories(options.object_store_factories);
+    for (name, catalog) in options.catalogs {
+        scheduler_server = scheduler_server.with_catalog(&name, catalog);
+    }
+    if let Some(plan_cache) = options.plan_cache {
+        scheduler_server = scheduler_server.with_plan_cache(plan_cache);
+    }
+    if let Some(result_cache) = options.result_cache {
+        scheduler_server = scheduler_server.with_result_cache(result_cache);
+    }
+    #[cfg(feature = "kafka")]
+    for query in options.streaming_queries {
+        scheduler_server = scheduler_server.with_streaming_query(query);
+    }
+    scheduler_server = scheduler_server.with_executor_lease(options.executor_lease);
+    if let Some(timeout) = options.session_idle_timeout {
+        scheduler_server = scheduler_server.with_session_idle_timeout(timeout);
+    }
+
     scheduler_server.init().await?;
+    #[cfg(feature = "kubernetes")]
+    if let Some(pods) = options.executor_pods {
+        scheduler_server.watch_executor_pods(pods);
+    }
 
-    Server::bind(&addr)
-        .serve(make_service_fn(move |request: &AddrStream| {
+    let (mut health_reporter, health_service) = grpc_services::health_service();
+    set_serving_status(&mut health_reporter, ServingStatus::Serving).await;
+    let reflection_service =
+        grpc_services::reflection_service(&[externalscaler::FILE_DESCRIPTOR_SET])?;
+
+    let shutdown_timeout = options.shutdown_timeout;
+    let scheduler = scheduler_server.clone();
+    let make_service =
+        move |connect_info: TcpConnectInfo,
+              tls_info: Option<TlsConnectInfo<TcpConnectInfo>>| {
             let scheduler_grpc_server =
                 SchedulerGrpcServer::new(scheduler_server.clone());
 
-            let flight_sql_server = FlightServiceServer::new(FlightSqlServiceImpl::new(
-                scheduler_server.clone(),
-            ));
+            let flight_sql_server = FlightServiceServer::new(
+                AuthenticatedFlightSqlService::new(scheduler_server.clone()),
+            );
 
             let keda_scaler = ExternalScalerServer::new(scheduler_server.clone());
 
@@ -112,19 +253,28 @@ async fn start_server(
                 .add_service(scheduler_grpc_server)
                 .add_service(flight_sql_server)
                 .add_service(keda_scaler)
+                .add_service(health_service.clone())
+                .add_service(reflection_service.clone())
                 .into_service();
             let mut warp = warp::service(get_routes(scheduler_server.clone()));
 
-            let connect_info = request.connect_info();
             future::ok::<_, Infallible>(tower::service_fn(
                 move |req: hyper::Request<hyper::Body>| {
                     // Set the connect info from hyper to tonic
                     let (mut parts, body) = req.into_parts();
                     parts.extensions.insert(connect_info.clone());
+                    if let Some(tls_info) = &tls_info {
+                        parts.extensions.insert(tls_info.clone());
+                    }
                     let req = http::Request::from_parts(parts, body);
 
+                    // Prometheus scrapers and Kubernetes probes do not ask for JSON, so
+                    // route the metrics and health endpoints to warp by path
                     let header = req.headers().get(hyper::header::ACCEPT);
-                    if header.is_some() && header.unwrap().eq("application/json") {
+                    if (header.is_some() && header.unwrap().eq("application/json"))
+                        || req.uri().path() == "/metrics"
+                        || req.uri().path().starts_with("/health/")
+                    {
                         return Either::Left(
                             warp.call(req)
                                 .map_ok(|res| res.map(EitherBody::Left))
@@ -139,17 +289,127 @@ async fn start_server(
                     )
                 },
             ))
-        }))
-        .await
+        };
+
+    let acceptor = options.tls.tls_acceptor()?;
+    let server = async move {
+        match acceptor {
+            Some(acceptor) => {
+                info!("Serving the scheduler API over TLS");
+                let listener = TcpListener::bind(addr).await?;
+                Server::builder(accept::from_stream(tls_incoming(listener, acceptor)))
+                    .serve(make_service_fn(move |conn: &TlsStream<TcpStream>| {
+                        // Exposes the client certificates to the services
+                        let tls_info = conn.connect_info();
+                        make_service(tls_info.get_ref().clone(), Some(tls_info))
+                    }))
+                    .await
+            }
+            None => {
+                Server::bind(&addr)
+                    .serve(make_service_fn(move |conn: &AddrStream| {
+                        make_service(conn.connect_info(), None)
+                    }))
+                    .await
+            }
+        }
         .context("Could not start grpc server")
+    };
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result,
+        signal = shutdown_signal() => signal?,
+    }
+    // Load balancers stop sending new work while the jobs drain
+    set_serving_status(&mut health_reporter, ServingStatus::NotServing).await;
+    // Keep serving while the jobs drain, for executors to report the status of tasks
+    tokio::select! {
+        result = &mut server => result,
+        result = scheduler.shutdown(shutdown_timeout) => Ok(result?),
+    }
+}
+
+/// Set the status reported by the health service for the services of the scheduler
+async fn set_serving_status(reporter: &mut HealthReporter, status: ServingStatus) {
+    type Scheduler = SchedulerServer<LogicalPlanNode, PhysicalPlanNode>;
+    let services = [
+        <SchedulerGrpcServer<Scheduler> as NamedService>::NAME,
+        <FlightServiceServer<AuthenticatedFlightSqlService> as NamedService>::NAME,
+        <ExternalScalerServer<Scheduler> as NamedService>::NAME,
+        "",
+    ];
+    for service in services {
+        reporter.set_service_status(service, status).await;
+    }
+}
+
+/// Complete when the process receives SIGTERM or Ctrl-C
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            _ = terminate.recv() => {}
+            result = tokio::signal::ctrl_c() => result?,
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    info!("Received shutdown signal");
+    Ok(())
+}
+
+/// Accept TLS connections on `listener`. Handshakes run concurrently so a slow client
+/// does not hold up others, and connections failing the handshake are dropped.
+fn tls_incoming(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+) -> impl Stream<Item = std::io::Result<TlsStream<TcpStream>>> {
+    stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .map(move |accepted| {
+        let acceptor = acceptor.clone();
+        async move {
+            let (tcp, _) = accepted?;
+            tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(tcp))
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::TimedOut, e))?
+        }
+    })
+    .buffer_unordered(TLS_HANDSHAKE_CONCURRENCY)
+    .filter_map(|result| async move {
+        match result {
+            Ok(stream) => Some(Ok(stream)),
+            Err(e) => {
+                warn!("Failed to accept TLS connection: {}", e);
+                None
+            }
+        }
+    })
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // the options of the cluster configuration file have the lowest precedence
+    let cluster_config = ClusterConfig::try_new(
+        "scheduler",
+        include_str!("../scheduler_config_spec.toml"),
+    )?;
+    let cluster_config_dir = tempfile::TempDir::new()?;
+    let cluster_options = cluster_config_dir.path().join("scheduler.toml");
+    cluster_config.write_to(&cluster_options)?;
+
     // parse options
-    let (opt, _remaining_args) =
-        Config::including_optional_config_files(&["/etc/ballista/scheduler.toml"])
-            .unwrap_or_exit();
+    let (opt, _remaining_args) = Config::including_optional_config_files(&[
+        cluster_options.as_path(),
+        Path::new("/etc/ballista/scheduler.toml"),
+    ])
+    .unwrap_or_exit();
 
     if opt.version {
         print_version();
@@ -157,17 +417,86 @@ async fn main() -> Result<()> {
     }
 
     let special_mod_log_level = opt.log_level_setting;
-    env_logger::builder()
-        .parse_filters(&*special_mod_log_level)
-        .format_timestamp_millis()
-        .init();
+    let mut logger = env_logger::builder();
+    logger
+        .parse_filters(&special_mod_log_level)
+        .format_timestamp_millis();
+    if let LogFormat::Json = opt.log_format {
+        logger.format(|buf, record| {
+            let timestamp = buf.timestamp_millis().to_string();
+            writeln!(buf, "{}", logging::json_line(&timestamp, record, &[]))
+        });
+    }
+    logger.init();
+
+    if !opt.otlp_endpoint.is_empty() {
+        telemetry::init_otlp_tracer("ballista-scheduler", &opt.otlp_endpoint)?;
+    }
 
     let namespace = opt.namespace;
     let bind_host = opt.bind_host;
     let port = opt.bind_port;
 
     let addr = format!("{}:{}", bind_host, port);
-    let addr = addr.parse()?;
+    let addr: SocketAddr = addr.parse()?;
+
+    let tls = TlsOptions {
+        cert_path: opt.tls_cert,
+        key_path: opt.tls_key,
+        ca_path: opt.tls_ca_cert,
+        domain_name: opt.tls_domain,
+        client_ca_path: opt.tls_client_ca_cert,
+    };
+    tls::init_client_tls(&tls)?;
+    // The keys travel with the tasks, to executors polling the scheduler and from the
+    // scheduler launching tasks on executors
+    if opt.encrypt_shuffle_files && !(tls.server_enabled() && tls.client_enabled()) {
+        return Err(anyhow::anyhow!(
+            "--encrypt-shuffle-files requires --tls-cert, --tls-key and --tls-ca-cert, \
+             as the keys are sent to executors along with the tasks"
+        ));
+    }
+
+    let mut object_store_factories = ObjectStoreFactories::builtin();
+    if !opt.object_stores.is_empty() {
+        object_store_factories
+            .add_cluster_stores(&ObjectStoreCredentials::from_json(&opt.object_stores)?)
+            .context("Could not create object stores")?;
+    }
+    if !opt.postgres_databases.is_empty() {
+        #[cfg(feature = "postgres")]
+        ballista_core::table_format::postgres::init_databases(&opt.postgres_databases)
+            .context("Could not configure the PostgreSQL databases")?;
+        #[cfg(not(feature = "postgres"))]
+        return Err(anyhow::anyhow!(
+            "build the scheduler with the `postgres` feature to read PostgreSQL databases"
+        ));
+    }
+    if opt.metadata_cache_ttl_seconds > 0 {
+        object_store_factories.cache_metadata(MetadataCacheOptions {
+            ttl: Duration::from_secs(opt.metadata_cache_ttl_seconds),
+            max_bytes: opt.metadata_cache_max_bytes,
+        });
+    }
+    let event_log = if opt.event_log_dir.is_empty() {
+        None
+    } else {
+        Some(Arc::new(EventLog::try_new(
+            &opt.event_log_dir,
+            &object_store_factories,
+        )?))
+    };
+
+    if opt.history_server {
+        let event_log = event_log.context("The history server requires event_log_dir")?;
+        info!(
+            "Ballista v{} history server listening on {:?}",
+            BALLISTA_VERSION, addr
+        );
+        warp::serve(get_history_routes(event_log)).run(addr).await;
+        telemetry::shutdown_tracer();
+        return Ok(());
+    }
 
     let client: Arc<dyn StateBackendClient> = match opt.config_backend {
         #[cfg(not(any(feature = "sled", feature = "etcd")))]
@@ -211,6 +540,277 @@ async fn main() -> Result<()> {
     };
 
     let policy: TaskSchedulingPolicy = opt.scheduler_policy;
-    start_server(client, namespace, addr, policy).await?;
+    let audit_sink: Option<Arc<dyn AuditSink>> = if opt.audit_log_dir.is_empty() {
+        None
+    } else {
+        Some(Arc::new(ObjectStoreAuditSink::try_new_local(
+            &opt.audit_log_dir,
+        )?))
+    };
+    let query_history = if opt.query_history_dir.is_empty() {
+        None
+    } else {
+        Some(Arc::new(QueryHistory::try_new(
+            &opt.query_history_dir,
+            &object_store_factories,
+        )?))
+    };
+    let validator = if opt.auth_tokens_file.is_empty() {
+        None
+    } else {
+        Some(Arc::new(StaticTokenValidator::try_from_file(
+            &opt.auth_tokens_file,
+        )?))
+    };
+    // SIGHUP would otherwise terminate the scheduler
+    #[cfg(unix)]
+    {
+        let validator = validator.clone();
+        let tokens_file = opt.auth_tokens_file.clone();
+        tokio::spawn(async move {
+            if let Err(e) = reload_on_hangup(cluster_config, validator, tokens_file).await
+            {
+                error!("Could not reload cluster configuration on SIGHUP: {}", e);
+            }
+        });
+    }
+    let client_auth =
+        validator.map(|validator| ClientAuth::new(validator, Arc::new(AllowAll)));
+    let column_masking = if opt.masking_policies_file.is_empty() {
+        None
+    } else if client_auth.is_none() {
+        // Policies apply to the principals clients are authenticated as
+        return Err(anyhow::anyhow!(
+            "Masking policies require client authentication, set auth_tokens_file"
+        ));
+    } else {
+        Some(Arc::new(ColumnMasking::try_from_file(
+            &opt.masking_policies_file,
+        )?))
+    };
+    let row_security = if opt.row_policies_file.is_empty() {
+        None
+    } else if client_auth.is_none() {
+        // Policies apply to the principals clients are authenticated as
+        return Err(anyhow::anyhow!(
+            "Row policies require client authentication, set auth_tokens_file"
+        ));
+    } else {
+        Some(Arc::new(RowLevelSecurity::try_from_file(
+            &opt.row_policies_file,
+        )?))
+    };
+    #[allow(unused_mut)]
+    let mut catalogs: Vec<(String, Arc<dyn CatalogProvider>)> = vec![];
+    if !opt.hive_metastore.is_empty() {
+        #[cfg(feature = "hive")]
+        catalogs.push((
+            "hive".to_owned(),
+            Arc::new(ballista_core::catalog::hive::HiveCatalog::new(
+                opt.hive_metastore.clone(),
+            )),
+        ));
+        #[cfg(not(feature = "hive"))]
+        return Err(anyhow::anyhow!(
+            "build the scheduler with the `hive` feature to use a Hive Metastore"
+        ));
+    }
+    if opt.glue_catalog {
+        #[cfg(feature = "glue")]
+        {
+            let mut glue = ballista_scheduler::glue::GlueCatalog::from_env().await;
+            if !opt.glue_catalog_id.is_empty() {
+                glue = glue.with_catalog_id(opt.glue_catalog_id.clone());
+            }
+            catalogs.push(("glue".to_owned(), Arc::new(glue)));
+        }
+        #[cfg(not(feature = "glue"))]
+        return Err(anyhow::anyhow!(
+            "build the scheduler with the `glue` feature to use the AWS Glue catalog"
+        ));
+    }
+    #[cfg(feature = "kafka")]
+    let streaming_queries = if opt.streaming_queries.is_empty() {
+        vec![]
+    } else {
+        StreamingQuery::from_file(&opt.streaming_queries)?
+    };
+    #[cfg(not(feature = "kafka"))]
+    if !opt.streaming_queries.is_empty() {
+        return Err(anyhow::anyhow!(
+            "build the scheduler with the `kafka` feature to run streaming queries"
+        ));
+    }
+    #[cfg(not(feature = "kubernetes"))]
+    if !opt.kubernetes_executor_selector.is_empty() {
+        return Err(anyhow::anyhow!(
+            "build the scheduler with the `kubernetes` feature to watch executor pods"
+        ));
+    }
+    // Executors polling for work only run the jobs of the scheduler they poll, so
+    // that the jobs of a scheduler polled by none would never run
+    if !opt.scheduler_shards.is_empty()
+        && !matches!(policy, TaskSchedulingPolicy::PushStaged)
+    {
+        return Err(anyhow::anyhow!(
+            "scheduler shards require the push-staged scheduling policy"
+        ));
+    }
+    let job_shards = if opt.scheduler_shards.is_empty() {
+        None
+    } else {
+        Some(JobShards::try_new(
+            &opt.scheduler_shards,
+            &opt.shard_address,
+        )?)
+    };
+    let options = ServerOptions {
+        event_log,
+        job_webhook_url: Some(opt.job_webhook_url).filter(|url| !url.is_empty()),
+        job_callback_hosts: opt
+            .job_callback_hosts
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        audit_sink,
+        query_history,
+        tenant_pools: TenantPools::parse(&opt.tenant_pools)?,
+        tenant_priorities: TenantPriorities::parse(&opt.tenant_max_job_priorities)?,
+        job_shards,
+        client_auth,
+        column_masking,
+        row_security,
+        encrypt_shuffle_files: opt.encrypt_shuffle_files,
+        object_store_factories,
+        catalogs,
+        plan_cache: (opt.plan_cache_ttl_seconds > 0).then(|| PlanCacheOptions {
+            ttl: Duration::from_secs(opt.plan_cache_ttl_seconds),
+            max_entries: opt.plan_cache_max_entries,
+        }),
+        result_cache: (opt.result_cache_ttl_seconds > 0).then(|| ResultCacheOptions {
+            ttl: Duration::from_secs(opt.result_cache_ttl_seconds),
+            max_entries: opt.result_cache_max_entries,
+        }),
+        #[cfg(feature = "kafka")]
+        streaming_queries,
+        #[cfg(feature = "kubernetes")]
+        executor_pods: Some(opt.kubernetes_executor_selector)
+            .filter(|selector| !selector.is_empty())
+            .map(|label_selector| ExecutorPods {
+                namespace: Some(opt.kubernetes_namespace)
+                    .filter(|namespace| !namespace.is_empty()),
+                label_selector,
+            }),
+        tls,
+        executor_lease: Duration::from_secs(opt.executor_lease_seconds),
+        session_idle_timeout: (opt.session_idle_timeout_seconds > 0)
+            .then(|| Duration::from_secs(opt.session_idle_timeout_seconds)),
+        shutdown_timeout: Duration::from_secs(opt.shutdown_timeout_seconds),
+    };
+    let result = start_server(client, namespace, addr, policy, options).await;
+    telemetry::shutdown_tracer();
+    result
+}
+
+/// Options of the cluster configuration file which are applied again on SIGHUP
+#[cfg(unix)]
+#[derive(Deserialize)]
+struct ReloadableOptions {
+    auth_tokens_file: Option<String>,
+}
+
+/// Read the tokens of clients again whenever the process receives SIGHUP, from the tokens
+/// file of the cluster configuration file if it sets one
+#[cfg(unix)]
+async fn reload_on_hangup(
+    cluster_config: ClusterConfig,
+    validator: Option<Arc<StaticTokenValidator>>,
+    mut tokens_file: String,
+) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup())?;
+    while hangups.recv().await.is_some() {
+        info!("Reloading {}", cluster_config.path().display());
+        if let Err(e) = reload(&cluster_config, validator.as_deref(), &mut tokens_file) {
+            error!("Could not reload cluster configuration: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Apply the reloadable options of the cluster configuration file. Client
+/// authentication can not be enabled without restarting the scheduler.
+#[cfg(unix)]
+fn reload(
+    cluster_config: &ClusterConfig,
+    validator: Option<&StaticTokenValidator>,
+    tokens_file: &mut String,
+) -> Result<()> {
+    let options: ReloadableOptions = cluster_config.read_as()?;
+    if let Some(path) = options.auth_tokens_file.filter(|path| !path.is_empty()) {
+        *tokens_file = path;
+    }
+    match validator {
+        Some(validator) => {
+            validator.reload(tokens_file)?;
+            info!("Reloaded client tokens from {}", tokens_file);
+        }
+        None if !tokens_file.is_empty() => warn!(
+            "Client authentication is disabled until the scheduler restarts, ignoring \
+             the tokens file {}",
+            tokens_file
+        ),
+        None => {}
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reload_client_tokens() -> anyhow::Result<()> {
+        use crate::reload;
+        use ballista_core::cluster_config::ClusterConfig;
+        use ballista_scheduler::auth::{StaticTokenValidator, TokenValidator};
+
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("ballista.toml");
+        let tokens = dir.path().join("tokens");
+        let other_tokens = dir.path().join("other_tokens");
+        std::fs::write(&tokens, "secret alice\n")?;
+        std::fs::write(&other_tokens, "other bob\n")?;
+        let cluster_config = ClusterConfig::try_new_with_path(
+            &path,
+            "scheduler",
+            include_str!("../scheduler_config_spec.toml"),
+        )?;
+        let mut tokens_file = tokens.to_str().unwrap().to_owned();
+        let validator = StaticTokenValidator::try_from_file(&tokens_file)?;
+
+        // The tokens file is read again, although the configuration file does not exist
+        std::fs::write(&tokens, "secret alice\nnew carol\n")?;
+        reload(&cluster_config, Some(&validator), &mut tokens_file)?;
+        assert_eq!(validator.validate("new").await?.unwrap().name, "carol");
+
+        std::fs::write(
+            &path,
+            format!(
+                "[scheduler]\nauth_tokens_file = {:?}\n",
+                other_tokens.to_str().unwrap()
+            ),
+        )?;
+        reload(&cluster_config, Some(&validator), &mut tokens_file)?;
+        assert_eq!(tokens_file, other_tokens.to_str().unwrap());
+        assert!(validator.validate("secret").await?.is_none());
+        assert_eq!(validator.validate("other").await?.unwrap().name, "bob");
+
+        // Without client authentication, there are no tokens to reload
+        let mut tokens_file = String::new();
+        reload(&cluster_config, None, &mut tokens_file)?;
+        Ok(())
+    }
+}
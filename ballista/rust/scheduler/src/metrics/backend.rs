@@ -0,0 +1,112 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use crate::metrics::SchedulerMetrics;
+use crate::state::backend::{Keyspace, Lock, StateBackendClient, Watch};
+use ballista_core::error::Result;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// A [StateBackendClient] which records the latency of every operation of the
+/// wrapped client in [SchedulerMetrics].
+pub(crate) struct MeteredStateBackend {
+    inner: Arc<dyn StateBackendClient>,
+    metrics: Arc<SchedulerMetrics>,
+}
+
+impl MeteredStateBackend {
+    pub(crate) fn new(
+        inner: Arc<dyn StateBackendClient>,
+        metrics: Arc<SchedulerMetrics>,
+    ) -> Self {
+        Self { inner, metrics }
+    }
+
+    async fn timed<T, F: Future<Output = T>>(&self, operation: &str, op: F) -> T {
+        let start = Instant::now();
+        let result = op.await;
+        self.metrics
+            .record_state_backend_op(operation, start.elapsed());
+        result
+    }
+}
+
+#[tonic::async_trait]
+impl StateBackendClient for MeteredStateBackend {
+    async fn get(&self, keyspace: Keyspace, key: &str) -> Result<Vec<u8>> {
+        self.timed("get", self.inner.get(keyspace, key)).await
+    }
+
+    async fn get_from_prefix(
+        &self,
+        keyspace: Keyspace,
+        prefix: &str,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        self.timed(
+            "get_from_prefix",
+            self.inner.get_from_prefix(keyspace, prefix),
+        )
+        .await
+    }
+
+    async fn scan(
+        &self,
+        keyspace: Keyspace,
+        limit: Option<usize>,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        self.timed("scan", self.inner.scan(keyspace, limit)).await
+    }
+
+    async fn scan_keys(&self, keyspace: Keyspace) -> Result<HashSet<String>> {
+        self.timed("scan_keys", self.inner.scan_keys(keyspace))
+            .await
+    }
+
+    async fn put(&self, keyspace: Keyspace, key: String, value: Vec<u8>) -> Result<()> {
+        self.timed("put", self.inner.put(keyspace, key, value))
+            .await
+    }
+
+    async fn put_txn(&self, ops: Vec<(Keyspace, String, Vec<u8>)>) -> Result<()> {
+        self.timed("put_txn", self.inner.put_txn(ops)).await
+    }
+
+    async fn mv(
+        &self,
+        from_keyspace: Keyspace,
+        to_keyspace: Keyspace,
+        key: &str,
+    ) -> Result<()> {
+        self.timed("mv", self.inner.mv(from_keyspace, to_keyspace, key))
+            .await
+    }
+
+    async fn lock(&self, keyspace: Keyspace, key: &str) -> Result<Box<dyn Lock>> {
+        self.timed("lock", self.inner.lock(keyspace, key)).await
+    }
+
+    async fn watch(&self, keyspace: Keyspace, prefix: String) -> Result<Box<dyn Watch>> {
+        self.timed("watch", self.inner.watch(keyspace, prefix))
+            .await
+    }
+
+    async fn delete(&self, keyspace: Keyspace, key: &str) -> Result<()> {
+        self.timed("delete", self.inner.delete(keyspace, key)).await
+    }
+}
@@ -0,0 +1,240 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Scheduler-level metrics exposed in the Prometheus text format.
+
+mod backend;
+
+pub(crate) use backend::MeteredStateBackend;
+
+use ballista_core::error::{BallistaError, Result};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntGauge, Registry,
+    TextEncoder,
+};
+use std::time::Duration;
+
+/// Latency buckets (in seconds) shared by the scheduler histograms. State backend
+/// operations are usually sub-millisecond, while planning a large job can take seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Metrics collected by a single scheduler instance. Each instance owns its own
+/// [Registry] so that several schedulers can live in the same process (e.g. in tests).
+pub struct SchedulerMetrics {
+    registry: Registry,
+    /// Number of jobs currently in the `ActiveJobs` keyspace
+    pub(crate) active_jobs: IntGauge,
+    /// Number of tasks in active jobs which are ready to be scheduled
+    pub(crate) pending_tasks: IntGauge,
+    /// Number of executors which sent a heartbeat in the last minute
+    pub(crate) registered_executors: IntGauge,
+    pub(crate) jobs_submitted: IntCounter,
+    pub(crate) jobs_completed: IntCounter,
+    pub(crate) jobs_failed: IntCounter,
+    /// Number of SQL queries whose cached plans were used
+    pub(crate) plan_cache_hits: IntCounter,
+    /// Number of SQL queries planned while plan caching is enabled
+    pub(crate) plan_cache_misses: IntCounter,
+    /// Number of jobs completed with the cached result of an earlier job
+    pub(crate) result_cache_hits: IntCounter,
+    /// Number of jobs whose result could be cached which had to run
+    pub(crate) result_cache_misses: IntCounter,
+    /// Time taken to optimize and physically plan a queued job
+    pub(crate) planning_latency: Histogram,
+    /// Time taken to fill a set of offered reservations and launch the assigned tasks
+    pub(crate) scheduling_latency: Histogram,
+    /// Latency of state backend operations, labelled by operation
+    pub(crate) state_backend_latency: HistogramVec,
+}
+
+impl SchedulerMetrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("ballista_scheduler".to_owned()), None)
+            .expect("valid registry prefix");
+
+        let active_jobs =
+            IntGauge::new("active_jobs", "Number of active jobs").expect("valid metric");
+        let pending_tasks = IntGauge::new(
+            "pending_tasks",
+            "Number of tasks in active jobs waiting to be scheduled",
+        )
+        .expect("valid metric");
+        let registered_executors = IntGauge::new(
+            "registered_executors",
+            "Number of executors seen alive in the last minute",
+        )
+        .expect("valid metric");
+        let jobs_submitted = IntCounter::new(
+            "jobs_submitted_total",
+            "Number of jobs planned and submitted",
+        )
+        .expect("valid metric");
+        let jobs_completed = IntCounter::new(
+            "jobs_completed_total",
+            "Number of jobs completed successfully",
+        )
+        .expect("valid metric");
+        let jobs_failed = IntCounter::new("jobs_failed_total", "Number of failed jobs")
+            .expect("valid metric");
+        let plan_cache_hits = IntCounter::new(
+            "plan_cache_hits_total",
+            "Number of SQL queries whose cached plans were used",
+        )
+        .expect("valid metric");
+        let plan_cache_misses = IntCounter::new(
+            "plan_cache_misses_total",
+            "Number of SQL queries planned while plan caching is enabled",
+        )
+        .expect("valid metric");
+        let result_cache_hits = IntCounter::new(
+            "result_cache_hits_total",
+            "Number of jobs completed with the cached result of an earlier job",
+        )
+        .expect("valid metric");
+        let result_cache_misses = IntCounter::new(
+            "result_cache_misses_total",
+            "Number of jobs whose result could be cached which had to run",
+        )
+        .expect("valid metric");
+        let planning_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "planning_latency_seconds",
+                "Time taken to plan a queued job",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .expect("valid metric");
+        let scheduling_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "scheduling_latency_seconds",
+                "Time taken to fill offered task slots and launch the assigned tasks",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+        )
+        .expect("valid metric");
+        let state_backend_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "state_backend_latency_seconds",
+                "Latency of state backend operations",
+            )
+            .buckets(LATENCY_BUCKETS.to_vec()),
+            &["operation"],
+        )
+        .expect("valid metric");
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(active_jobs.clone()),
+            Box::new(pending_tasks.clone()),
+            Box::new(registered_executors.clone()),
+            Box::new(jobs_submitted.clone()),
+            Box::new(jobs_completed.clone()),
+            Box::new(jobs_failed.clone()),
+            Box::new(plan_cache_hits.clone()),
+            Box::new(plan_cache_misses.clone()),
+            Box::new(result_cache_hits.clone()),
+            Box::new(result_cache_misses.clone()),
+            Box::new(planning_latency.clone()),
+            Box::new(scheduling_latency.clone()),
+            Box::new(state_backend_latency.clone()),
+        ];
+        for collector in collectors {
+            registry
+                .register(collector)
+                .expect("scheduler metrics are registered once");
+        }
+
+        Self {
+            registry,
+            active_jobs,
+            pending_tasks,
+            registered_executors,
+            jobs_submitted,
+            jobs_completed,
+            jobs_failed,
+            plan_cache_hits,
+            plan_cache_misses,
+            result_cache_hits,
+            result_cache_misses,
+            planning_latency,
+            scheduling_latency,
+            state_backend_latency,
+        }
+    }
+
+    pub(crate) fn record_state_backend_op(&self, operation: &str, elapsed: Duration) {
+        self.state_backend_latency
+            .with_label_values(&[operation])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// The content type of the output of [SchedulerMetrics::gather]
+    pub fn content_type(&self) -> String {
+        TextEncoder::new().format_type().to_owned()
+    }
+
+    /// Encode all metrics in the Prometheus text exposition format
+    pub fn gather(&self) -> Result<Vec<u8>> {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| {
+                BallistaError::Internal(format!("Error encoding metrics: {:?}", e))
+            })?;
+        Ok(buffer)
+    }
+}
+
+impl Default for SchedulerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SchedulerMetrics;
+    use std::time::Duration;
+
+    #[test]
+    fn gather_prometheus_text() {
+        let metrics = SchedulerMetrics::new();
+        metrics.active_jobs.set(3);
+        metrics.jobs_failed.inc();
+        metrics.record_state_backend_op("put", Duration::from_millis(2));
+
+        let text = String::from_utf8(metrics.gather().unwrap()).unwrap();
+
+        assert!(text.contains("ballista_scheduler_active_jobs 3"));
+        assert!(text.contains("ballista_scheduler_jobs_failed_total 1"));
+        assert!(text.contains(
+            "ballista_scheduler_state_backend_latency_seconds_count{operation=\"put\"} 1"
+        ));
+    }
+
+    #[test]
+    fn independent_registries() {
+        let first = SchedulerMetrics::new();
+        let second = SchedulerMetrics::new();
+        first.jobs_submitted.inc();
+
+        assert_eq!(first.jobs_submitted.get(), 1);
+        assert_eq!(second.jobs_submitted.get(), 0);
+    }
+}
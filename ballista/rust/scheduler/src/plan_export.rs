@@ -0,0 +1,508 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Export of the distributed plan of a job to external visualization tools.
+//!
+//! A [PlanGraph] holds the stages of a job, the operators of their plans and the
+//! metrics the scheduler collected for each stage and each operator so far, and renders
+//! them as a Graphviz `dot` graph or as JSON. In the graph, each stage is a cluster of
+//! its operators, and the root of each stage has an edge to the shuffle readers of the
+//! stages reading its output.
+//!
+//! The plans of jobs rewritten by plan rewriters may contain the predicates of row
+//! filters and the expressions of column masks, which their clients must not see, so
+//! operators may be exported with their names only.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::execution_plans::{ShuffleReaderExec, UnresolvedShuffleExec};
+use ballista_core::serde::protobuf::{task_status, JobStatus, OperatorMetrics};
+use datafusion::physical_plan::display::DisplayableExecutionPlan;
+use datafusion::physical_plan::ExecutionPlan;
+use serde::{Deserialize, Serialize};
+
+use crate::api::model::job_status_name;
+use crate::explain::explain_partitioning;
+use crate::state::execution_graph::{ExecutionGraph, ExecutionStage, StageStatus};
+
+/// A format the plan of a job is exported in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlanFormat {
+    /// Graphviz `dot`
+    Dot,
+    Json,
+}
+
+impl PlanFormat {
+    /// Media type of plans exported in this format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Dot => "text/vnd.graphviz",
+            Self::Json => "application/json",
+        }
+    }
+}
+
+impl FromStr for PlanFormat {
+    type Err = BallistaError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "dot" => Ok(Self::Dot),
+            "json" => Ok(Self::Json),
+            _ => Err(BallistaError::General(format!(
+                "Unknown plan format {}, expected dot or json",
+                s
+            ))),
+        }
+    }
+}
+
+/// The distributed plan of a job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlanGraph {
+    pub job_id: String,
+    pub status: String,
+    /// Stages of the job, in the order of their IDs
+    pub stages: Vec<StageNode>,
+}
+
+/// A stage of a job and the metrics of its completed tasks
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageNode {
+    pub stage_id: usize,
+    pub status: StageStatus,
+    /// Number of tasks of the stage
+    pub partitions: usize,
+    /// IDs of the stages whose output is read by this stage
+    pub inputs: Vec<usize>,
+    /// IDs of the stages reading the output of this stage, empty for the final stage
+    pub output_links: Vec<usize>,
+    /// How the output of the stage is partitioned for the stages reading it, `None`
+    /// if it keeps the partitions of its plan
+    pub output_partitioning: Option<String>,
+    pub metrics: StageMetrics,
+    /// Root operator of the plan of the stage
+    pub plan: OperatorNode,
+}
+
+/// Metrics of the completed tasks of a stage
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StageMetrics {
+    pub completed_tasks: usize,
+    pub num_rows: Option<u64>,
+    pub num_batches: Option<u64>,
+    pub num_bytes: Option<u64>,
+    /// Bytes spilled to disk by the completed tasks
+    pub spilled_bytes: u64,
+    /// Total run time of the latest attempts of the tasks, in milliseconds
+    pub task_time: u64,
+}
+
+/// An operator of the plan of a stage
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OperatorNode {
+    /// Name of the operator, such as `HashJoinExec`
+    pub name: String,
+    /// The operator as shown by `EXPLAIN`, or its name if redacted
+    pub description: String,
+    /// Number of output partitions of the operator
+    pub partitions: usize,
+    /// Totals of the metrics of the operator in the completed tasks of its stage, by
+    /// name, such as `output_rows` or `elapsed_compute` in nanoseconds
+    pub metrics: BTreeMap<String, u64>,
+    /// ID of the stage whose output the operator reads, for shuffle readers
+    pub input_stage: Option<usize>,
+    pub children: Vec<OperatorNode>,
+}
+
+impl PlanGraph {
+    /// The plan of the job of `graph`, whose operators are only described by their
+    /// names if `redact`
+    pub fn new(graph: &ExecutionGraph, status: &JobStatus, redact: bool) -> Self {
+        let mut stages: Vec<&ExecutionStage> = graph.stages.values().collect();
+        stages.sort_by_key(|stage| stage.stage_id);
+        Self {
+            job_id: graph.job_id().to_owned(),
+            status: job_status_name(status).0.to_owned(),
+            stages: stages
+                .into_iter()
+                .map(|stage| stage_node(graph, stage, redact))
+                .collect(),
+        }
+    }
+
+    /// Render the plan in `format`
+    pub fn export(&self, format: PlanFormat) -> Result<String> {
+        match format {
+            PlanFormat::Dot => Ok(self.to_dot()),
+            PlanFormat::Json => serde_json::to_string_pretty(self).map_err(|e| {
+                BallistaError::General(format!("Could not export plan: {}", e))
+            }),
+        }
+    }
+
+    /// Render the plan as a Graphviz `dot` graph
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph \"{}\" {{", escape(&self.job_id)).unwrap();
+        writeln!(
+            dot,
+            "  label=\"Job {} ({})\";",
+            escape(&self.job_id),
+            self.status
+        )
+        .unwrap();
+        writeln!(dot, "  node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        // Shuffle readers, by the stage whose output they read
+        let mut readers = vec![];
+        for stage in &self.stages {
+            writeln!(dot, "  subgraph cluster_stage_{} {{", stage.stage_id).unwrap();
+            writeln!(dot, "    label=\"{}\";", escape(&stage_label(stage))).unwrap();
+            let mut next_id = 0;
+            write_operator(
+                &mut dot,
+                stage.stage_id,
+                &stage.plan,
+                &mut next_id,
+                &mut readers,
+            );
+            writeln!(dot, "  }}").unwrap();
+        }
+        for (input_stage, reader) in readers {
+            if let Some(input) = self.stages.iter().find(|s| s.stage_id == input_stage) {
+                let label = input
+                    .output_partitioning
+                    .clone()
+                    .unwrap_or_else(|| format!("{} partitions", input.partitions));
+                writeln!(
+                    dot,
+                    "  {} -> {} [style=dashed, label=\"{}\"];",
+                    node_id(input_stage, 0),
+                    reader,
+                    escape(&label)
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn stage_node(graph: &ExecutionGraph, stage: &ExecutionStage, redact: bool) -> StageNode {
+    let mut inputs: Vec<usize> = stage.inputs.keys().copied().collect();
+    inputs.sort_unstable();
+    let stats = graph.stage_output_stats(stage.stage_id).unwrap_or_default();
+    let task_time = stage
+        .task_infos
+        .iter()
+        .filter_map(|info| info.run_time())
+        .sum();
+    let task_metrics: Vec<&[OperatorMetrics]> = stage
+        .task_statuses
+        .iter()
+        .filter_map(|status| match status {
+            Some(task_status::Status::Completed(completed)) => {
+                Some(completed.operator_metrics.as_slice())
+            }
+            _ => None,
+        })
+        .collect();
+    StageNode {
+        stage_id: stage.stage_id,
+        status: stage.status(),
+        partitions: stage.partitions,
+        inputs,
+        output_links: stage.output_links.clone(),
+        output_partitioning: stage.output_partitioning.as_ref().map(explain_partitioning),
+        metrics: StageMetrics {
+            completed_tasks: stage.completed_tasks(),
+            num_rows: stats.num_rows(),
+            num_batches: stats.num_batches(),
+            num_bytes: stats.num_bytes(),
+            spilled_bytes: stage.spilled().0,
+            task_time,
+        },
+        plan: operator_node(&stage.plan, &task_metrics, &mut 0, redact),
+    }
+}
+
+/// The node of operator `plan`, numbered `index` depth first from the root of the plan
+/// of its stage, as are the operator metrics of each task in `task_metrics`
+fn operator_node(
+    plan: &Arc<dyn ExecutionPlan>,
+    task_metrics: &[&[OperatorMetrics]],
+    index: &mut usize,
+    redact: bool,
+) -> OperatorNode {
+    // The first line of the plan shown by EXPLAIN is its root operator
+    let description = DisplayableExecutionPlan::new(plan.as_ref())
+        .indent()
+        .to_string()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_owned();
+    let name = description.split(':').next().unwrap_or_default().to_owned();
+    let description = if redact { name.clone() } else { description };
+    let mut metrics = BTreeMap::new();
+    for metric in task_metrics
+        .iter()
+        .filter_map(|operators| operators.get(*index))
+        .flat_map(|operator| &operator.metrics)
+    {
+        *metrics.entry(metric.name.clone()).or_insert(0) += metric.value;
+    }
+    *index += 1;
+    let input_stage =
+        if let Some(shuffle) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+            Some(shuffle.stage_id)
+        } else if let Some(reader) = plan.as_any().downcast_ref::<ShuffleReaderExec>() {
+            reader
+                .partition
+                .iter()
+                .flatten()
+                .next()
+                .map(|location| location.partition_id.stage_id)
+        } else {
+            None
+        };
+    OperatorNode {
+        name,
+        description,
+        partitions: plan.output_partitioning().partition_count(),
+        metrics,
+        input_stage,
+        children: plan
+            .children()
+            .iter()
+            .map(|child| operator_node(child, task_metrics, index, redact))
+            .collect(),
+    }
+}
+
+fn stage_label(stage: &StageNode) -> String {
+    let metrics = &stage.metrics;
+    let mut label = format!(
+        "Stage {}: {:?}, {}/{} tasks",
+        stage.stage_id, stage.status, metrics.completed_tasks, stage.partitions
+    );
+    if let Some(num_rows) = metrics.num_rows {
+        write!(label, ", {} rows", num_rows).unwrap();
+    }
+    if let Some(num_bytes) = metrics.num_bytes {
+        write!(label, ", {} bytes", num_bytes).unwrap();
+    }
+    if metrics.spilled_bytes > 0 {
+        write!(label, ", {} bytes spilled", metrics.spilled_bytes).unwrap();
+    }
+    if metrics.task_time > 0 {
+        write!(label, ", {} ms of tasks", metrics.task_time).unwrap();
+    }
+    label
+}
+
+/// Write the nodes of `operator` and its children, numbered depth first from
+/// `next_id`, and the edges from each child to its parent. The shuffle readers are
+/// added to `readers` along with the stage they read.
+fn write_operator(
+    dot: &mut String,
+    stage_id: usize,
+    operator: &OperatorNode,
+    next_id: &mut usize,
+    readers: &mut Vec<(usize, String)>,
+) -> String {
+    let id = node_id(stage_id, *next_id);
+    *next_id += 1;
+    let mut label = format!(
+        "{}\n{} partitions",
+        operator.description, operator.partitions
+    );
+    for (name, value) in &operator.metrics {
+        write!(label, "\n{}={}", name, value).unwrap();
+    }
+    writeln!(dot, "    {} [label=\"{}\"];", id, escape(&label)).unwrap();
+    if let Some(input_stage) = operator.input_stage {
+        readers.push((input_stage, id.clone()));
+    }
+    for child in &operator.children {
+        let child_id = write_operator(dot, stage_id, child, next_id, readers);
+        writeln!(dot, "    {} -> {};", child_id, id).unwrap();
+    }
+    id
+}
+
+fn node_id(stage_id: usize, operator: usize) -> String {
+    format!("stage_{}_{}", stage_id, operator)
+}
+
+/// Escape `s` to be quoted in a `dot` graph, where its lines are centered
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::datafusion_test_context;
+    use ballista_core::serde::protobuf::{
+        job_status, CompletedTask, NamedMetric, QueuedJob,
+    };
+
+    async fn aggregate_graph() -> Result<ExecutionGraph> {
+        let ctx = datafusion_test_context("testdata").await?;
+        let df = ctx
+            .sql(
+                "select l_returnflag, sum(l_extendedprice) as revenue
+                from lineitem
+                group by l_returnflag",
+            )
+            .await?;
+        let plan = ctx.optimize(&df.to_logical_plan()?)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+        ExecutionGraph::new("job", "session", plan)
+    }
+
+    fn queued() -> JobStatus {
+        JobStatus {
+            status: Some(job_status::Status::Queued(QueuedJob { queue: None })),
+        }
+    }
+
+    #[tokio::test]
+    async fn export_json() -> Result<()> {
+        let graph = aggregate_graph().await?;
+        let plan = PlanGraph::new(&graph, &queued(), false);
+        assert_eq!(plan.job_id, "job");
+        assert_eq!(plan.status, "Queued");
+        assert_eq!(plan.stages.len(), 2);
+
+        let first = &plan.stages[0];
+        assert_eq!(first.plan.name, "ShuffleWriterExec");
+        assert_eq!(first.output_links, vec![2]);
+        assert_eq!(first.metrics, StageMetrics::default());
+        let second = &plan.stages[1];
+        assert_eq!(second.inputs, vec![1]);
+        assert!(second.output_partitioning.is_none());
+        fn readers(operator: &OperatorNode) -> Vec<usize> {
+            operator
+                .input_stage
+                .into_iter()
+                .chain(operator.children.iter().flat_map(readers))
+                .collect()
+        }
+        assert_eq!(readers(&second.plan), vec![1]);
+
+        let json = plan.export(PlanFormat::Json)?;
+        let parsed: PlanGraph = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, plan);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_dot() -> Result<()> {
+        let graph = aggregate_graph().await?;
+        let dot = PlanGraph::new(&graph, &queued(), false).export(PlanFormat::Dot)?;
+        assert!(dot.starts_with("digraph \"job\" {\n"));
+        assert!(dot.contains("subgraph cluster_stage_1 {"));
+        assert!(dot.contains("subgraph cluster_stage_2 {"));
+        assert!(dot.contains("stage_1_1 -> stage_1_0;"));
+        // The output of stage 1 is read by the shuffle reader of stage 2
+        assert!(dot
+            .lines()
+            .any(|line| line.starts_with("  stage_1_0 -> stage_2_")
+                && line.contains("style=dashed")));
+        assert!(dot.ends_with("}\n"));
+
+        assert_eq!("DOT".parse::<PlanFormat>()?, PlanFormat::Dot);
+        assert!("svg".parse::<PlanFormat>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_operator_metrics() -> Result<()> {
+        let mut graph = aggregate_graph().await?;
+        let task_metrics = |output_rows: u64| {
+            task_status::Status::Completed(CompletedTask {
+                operator_metrics: vec![
+                    OperatorMetrics {
+                        metrics: vec![NamedMetric {
+                            name: "output_rows".to_owned(),
+                            value: output_rows,
+                        }],
+                    },
+                    OperatorMetrics {
+                        metrics: vec![NamedMetric {
+                            name: "elapsed_compute".to_owned(),
+                            value: 1000,
+                        }],
+                    },
+                ],
+                ..Default::default()
+            })
+        };
+        let stage = graph.stages.get_mut(&1).unwrap();
+        stage.task_statuses[0] = Some(task_metrics(3));
+        if stage.task_statuses.len() > 1 {
+            stage.task_statuses[1] = Some(task_metrics(4));
+        }
+        let tasks = stage.task_statuses.len().min(2) as u64;
+
+        let plan = PlanGraph::new(&graph, &queued(), false);
+        let root = &plan.stages[0].plan;
+        let output_rows = if tasks > 1 { 7 } else { 3 };
+        assert_eq!(root.metrics.get("output_rows"), Some(&output_rows));
+        assert_eq!(
+            root.children[0].metrics.get("elapsed_compute"),
+            Some(&(1000 * tasks))
+        );
+        // Operators the tasks reported no metrics for have none
+        assert!(root.children[0].children[0].metrics.is_empty());
+        assert!(plan.stages[1].plan.metrics.is_empty());
+        let dot = plan.export(PlanFormat::Dot)?;
+        assert!(dot.contains(&format!("\\noutput_rows={}", output_rows)));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redact_operators() -> Result<()> {
+        let graph = aggregate_graph().await?;
+        let plan = PlanGraph::new(&graph, &queued(), true);
+        fn descriptions(operator: &OperatorNode) -> Vec<String> {
+            std::iter::once(operator.description.clone())
+                .chain(operator.children.iter().flat_map(descriptions))
+                .collect()
+        }
+        for stage in &plan.stages {
+            for description in descriptions(&stage.plan) {
+                assert!(description.ends_with("Exec"), "{}", description);
+            }
+        }
+        let dot = plan.export(PlanFormat::Dot)?;
+        assert!(!dot.contains("l_returnflag"));
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Custom rules rewriting the plans of jobs before they are planned.
+//!
+//! [PlanRewriter]s are registered with the scheduler, either before it starts with
+//! [SchedulerServer::with_plan_rewriter](crate::scheduler_server::SchedulerServer::with_plan_rewriter)
+//! or at any time through
+//! [SchedulerServer::plan_rewriters](crate::scheduler_server::SchedulerServer::plan_rewriters).
+//! The logical plan of every job is rewritten by each rewriter in the order they were
+//! registered before it is optimized, and its physical plan once it was created, before
+//! it is split into stages. Rewriters get the principal of the client which submitted
+//! the job and the locations of the tables it scans, so they can inject the filters of
+//! a tenant or enforce row-level security.
+//!
+//! The physical plans of SQL queries are not cached while rewriters are registered, as
+//! they may differ by identity.
+
+use ballista_core::error::Result;
+use datafusion::logical_plan::LogicalPlan;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::prelude::SessionContext;
+use parking_lot::RwLock;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+/// The job whose plan is rewritten
+pub struct RewriteContext<'a> {
+    pub job_id: &'a str,
+    /// The principal which submitted the job when client authentication is enabled
+    pub identity: Option<&'a str>,
+    /// The locations of the tables scanned by the job by the name they are scanned as
+    pub table_locations: &'a HashMap<String, BTreeSet<String>>,
+    /// The session of the job
+    pub session_ctx: &'a SessionContext,
+}
+
+/// A rule rewriting the plans of jobs before they are planned
+pub trait PlanRewriter: Send + Sync {
+    /// Name of the rewriter, unique among the rewriters of a scheduler
+    fn name(&self) -> &str;
+
+    /// Rewrite `plan`, the logical plan of a job, before it is optimized
+    fn rewrite_logical_plan(
+        &self,
+        plan: LogicalPlan,
+        _context: &RewriteContext,
+    ) -> Result<LogicalPlan> {
+        Ok(plan)
+    }
+
+    /// Rewrite `plan`, the physical plan of a job, before it is split into stages
+    fn rewrite_physical_plan(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        _context: &RewriteContext,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(plan)
+    }
+}
+
+/// The plan rewriters of a scheduler, in the order they run
+#[derive(Clone, Default)]
+pub struct PlanRewriters {
+    rewriters: Arc<RwLock<Vec<Arc<dyn PlanRewriter>>>>,
+}
+
+impl PlanRewriters {
+    /// Rewrite the plans of the jobs planned from now on with `rewriter`, after the
+    /// other rewriters. A rewriter of the same name is replaced, keeping its place.
+    pub fn register(&self, rewriter: Arc<dyn PlanRewriter>) {
+        let mut rewriters = self.rewriters.write();
+        match rewriters.iter_mut().find(|r| r.name() == rewriter.name()) {
+            Some(existing) => *existing = rewriter,
+            None => rewriters.push(rewriter),
+        }
+    }
+
+    /// Stop rewriting plans with rewriter `name`. Returns whether it was registered.
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut rewriters = self.rewriters.write();
+        let count = rewriters.len();
+        rewriters.retain(|rewriter| rewriter.name() != name);
+        rewriters.len() < count
+    }
+
+    /// Names of the registered rewriters, in the order they run
+    pub fn names(&self) -> Vec<String> {
+        self.rewriters
+            .read()
+            .iter()
+            .map(|rewriter| rewriter.name().to_owned())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rewriters.read().is_empty()
+    }
+
+    /// Rewrite `plan` with each logical plan rewriter in turn
+    pub fn rewrite_logical_plan(
+        &self,
+        plan: LogicalPlan,
+        context: &RewriteContext,
+    ) -> Result<LogicalPlan> {
+        self.snapshot().iter().try_fold(plan, |plan, rewriter| {
+            rewriter.rewrite_logical_plan(plan, context)
+        })
+    }
+
+    /// Rewrite `plan` with each physical plan rewriter in turn
+    pub fn rewrite_physical_plan(
+        &self,
+        plan: Arc<dyn ExecutionPlan>,
+        context: &RewriteContext,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        self.snapshot().iter().try_fold(plan, |plan, rewriter| {
+            rewriter.rewrite_physical_plan(plan, context)
+        })
+    }
+
+    /// The registered rewriters, which are not locked while they run
+    fn snapshot(&self) -> Vec<Arc<dyn PlanRewriter>> {
+        self.rewriters.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ballista_core::error::BallistaError;
+    use datafusion::logical_plan::LogicalPlanBuilder;
+    use parking_lot::Mutex;
+
+    /// Records the identities it rewrites plans for, and rejects the jobs of "mallory"
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl PlanRewriter for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn rewrite_logical_plan(
+            &self,
+            plan: LogicalPlan,
+            context: &RewriteContext,
+        ) -> Result<LogicalPlan> {
+            let identity = context.identity.unwrap_or("anonymous");
+            if identity == "mallory" {
+                return Err(BallistaError::General("Access denied".to_owned()));
+            }
+            self.calls
+                .lock()
+                .push(format!("{} {}", self.name, identity));
+            Ok(plan)
+        }
+    }
+
+    #[test]
+    fn rewrite_plans() -> Result<()> {
+        let rewriters = PlanRewriters::default();
+        let calls = Arc::new(Mutex::new(vec![]));
+        let recorder = |name| {
+            Arc::new(Recorder {
+                name,
+                calls: calls.clone(),
+            })
+        };
+        assert!(rewriters.is_empty());
+        rewriters.register(recorder("first"));
+        // Rewriters of the same name are replaced
+        rewriters.register(recorder("second"));
+        assert_eq!(rewriters.names(), vec!["recorder".to_owned()]);
+
+        let session_ctx = SessionContext::new();
+        let table_locations = HashMap::new();
+        let context = |identity| RewriteContext {
+            job_id: "job",
+            identity,
+            table_locations: &table_locations,
+            session_ctx: &session_ctx,
+        };
+        let plan = LogicalPlanBuilder::empty(false).build()?;
+        rewriters.rewrite_logical_plan(plan.clone(), &context(Some("alice")))?;
+        rewriters.rewrite_logical_plan(plan.clone(), &context(None))?;
+        assert_eq!(
+            *calls.lock(),
+            vec!["second alice".to_owned(), "second anonymous".to_owned()]
+        );
+        assert!(rewriters
+            .rewrite_logical_plan(plan, &context(Some("mallory")))
+            .is_err());
+
+        assert!(rewriters.unregister("recorder"));
+        assert!(!rewriters.unregister("recorder"));
+        assert!(rewriters.is_empty());
+        Ok(())
+    }
+}
@@ -22,16 +22,44 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use ballista_core::config::BallistaConfig;
 use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf::column_statistics::OptionalDistinctCount;
+use ballista_core::serde::protobuf::{
+    PhysicalPlanNode, ResourceProfile, TableStatistics,
+};
+use ballista_core::serde::{AsExecutionPlan, DefaultPhysicalExtensionCodec};
 use ballista_core::{
-    execution_plans::{ShuffleReaderExec, ShuffleWriterExec, UnresolvedShuffleExec},
-    serde::scheduler::PartitionLocation,
+    execution_plans::{
+        BloomFilter, BloomFilterBuildExec, BloomFilterExec, NodeLocalScanExec,
+        ShuffleReaderExec, ShuffleWriterExec, TopKExec, UnresolvedShuffleExec,
+    },
+    serde::scheduler::{FetchPriority, PartitionLocation},
+};
+use datafusion::datasource::listing::{FileRange, PartitionedFile};
+use datafusion::logical_expr::AggregateFunction;
+use datafusion::logical_plan::{
+    self, Expr, JoinConstraint, JoinType, LogicalPlan, LogicalPlanBuilder,
 };
+use datafusion::optimizer::utils::from_plan;
+use datafusion::physical_plan::aggregates::{AggregateExec, AggregateMode};
+use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
 use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::cross_join::CrossJoinExec;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::file_format::{
+    AvroExec, CsvExec, FileScanConfig, NdJsonExec, ParquetExec,
+};
+use datafusion::physical_plan::hash_join::{HashJoinExec, PartitionMode};
+use datafusion::physical_plan::limit::{GlobalLimitExec, LocalLimitExec};
 use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::sorts::sort::SortExec;
+use datafusion::physical_plan::sorts::sort_preserving_merge::SortPreservingMergeExec;
 use datafusion::physical_plan::windows::WindowAggExec;
 use datafusion::physical_plan::{
-    with_new_children_if_necessary, ExecutionPlan, Partitioning,
+    with_new_children_if_necessary, ColumnStatistics, Distribution, ExecutionPlan,
+    Partitioning, Statistics,
 };
 
 use log::info;
@@ -40,11 +68,16 @@ type PartialQueryStageResult = (Arc<dyn ExecutionPlan>, Vec<Arc<ShuffleWriterExe
 
 pub struct DistributedPlanner {
     next_stage_id: usize,
+    /// Stages planned so far, by the fingerprint of their input plan and partitioning
+    stages: HashMap<Vec<u8>, Arc<ShuffleWriterExec>>,
 }
 
 impl DistributedPlanner {
     pub fn new() -> Self {
-        Self { next_stage_id: 0 }
+        Self {
+            next_stage_id: 0,
+            stages: HashMap::new(),
+        }
     }
 }
 
@@ -84,6 +117,7 @@ impl DistributedPlanner {
         execution_plan: Arc<dyn ExecutionPlan>,
     ) -> Result<PartialQueryStageResult> {
         // async move {
+        let execution_plan = with_partial_limit(execution_plan)?;
         // recurse down and replace children
         if execution_plan.children().is_empty() {
             return Ok((execution_plan, vec![]));
@@ -102,24 +136,8 @@ impl DistributedPlanner {
             .as_any()
             .downcast_ref::<CoalescePartitionsExec>()
         {
-            let shuffle_writer = create_shuffle_writer(
-                job_id,
-                self.next_stage_id(),
-                children[0].clone(),
-                None,
-            )?;
-            let unresolved_shuffle = Arc::new(UnresolvedShuffleExec::new(
-                shuffle_writer.stage_id(),
-                shuffle_writer.schema(),
-                shuffle_writer.output_partitioning().partition_count(),
-                shuffle_writer
-                    .shuffle_output_partitioning()
-                    .map(|p| p.partition_count())
-                    .unwrap_or_else(|| {
-                        shuffle_writer.output_partitioning().partition_count()
-                    }),
-            ));
-            stages.push(shuffle_writer);
+            let unresolved_shuffle =
+                self.shuffle_stage(job_id, children[0].clone(), None, &mut stages)?;
             Ok((
                 with_new_children_if_necessary(execution_plan, vec![unresolved_shuffle])?,
                 stages,
@@ -129,24 +147,12 @@ impl DistributedPlanner {
         {
             match repart.output_partitioning() {
                 Partitioning::Hash(_, _) => {
-                    let shuffle_writer = create_shuffle_writer(
+                    let unresolved_shuffle = self.shuffle_stage(
                         job_id,
-                        self.next_stage_id(),
                         children[0].clone(),
                         Some(repart.partitioning().to_owned()),
+                        &mut stages,
                     )?;
-                    let unresolved_shuffle = Arc::new(UnresolvedShuffleExec::new(
-                        shuffle_writer.stage_id(),
-                        shuffle_writer.schema(),
-                        shuffle_writer.output_partitioning().partition_count(),
-                        shuffle_writer
-                            .shuffle_output_partitioning()
-                            .map(|p| p.partition_count())
-                            .unwrap_or_else(|| {
-                                shuffle_writer.output_partitioning().partition_count()
-                            }),
-                    ));
-                    stages.push(shuffle_writer);
                     Ok((unresolved_shuffle, stages))
                 }
                 _ => {
@@ -154,13 +160,6 @@ impl DistributedPlanner {
                     Ok((children[0].clone(), stages))
                 }
             }
-        } else if let Some(window) =
-            execution_plan.as_any().downcast_ref::<WindowAggExec>()
-        {
-            Err(BallistaError::NotImplemented(format!(
-                "WindowAggExec with window {:?}",
-                window
-            )))
         } else {
             Ok((
                 with_new_children_if_necessary(execution_plan, children)?,
@@ -169,6 +168,56 @@ impl DistributedPlanner {
         }
     }
 
+    /// Returns an [UnresolvedShuffleExec] reading the shuffle of `plan` by `partitioning`.
+    /// The shuffle is written by a new stage, added to `stages`, unless an identical
+    /// stage was already planned for this job, in which case its output is shared.
+    fn shuffle_stage(
+        &mut self,
+        job_id: &str,
+        plan: Arc<dyn ExecutionPlan>,
+        partitioning: Option<Partitioning>,
+        stages: &mut Vec<Arc<ShuffleWriterExec>>,
+    ) -> Result<Arc<UnresolvedShuffleExec>> {
+        let fingerprint = stage_fingerprint(plan.clone(), partitioning.as_ref());
+        let existing = fingerprint
+            .as_ref()
+            .and_then(|fingerprint| self.stages.get(fingerprint))
+            .cloned();
+        let shuffle_writer = match existing {
+            Some(shuffle_writer) => {
+                info!(
+                    "reusing the output of identical stage {}",
+                    shuffle_writer.stage_id()
+                );
+                shuffle_writer
+            }
+            None => {
+                let shuffle_writer = create_shuffle_writer(
+                    job_id,
+                    self.next_stage_id(),
+                    plan,
+                    partitioning,
+                )?;
+                if let Some(fingerprint) = fingerprint {
+                    self.stages.insert(fingerprint, shuffle_writer.clone());
+                }
+                stages.push(shuffle_writer.clone());
+                shuffle_writer
+            }
+        };
+        Ok(Arc::new(UnresolvedShuffleExec::new(
+            shuffle_writer.stage_id(),
+            shuffle_writer.schema(),
+            shuffle_writer.output_partitioning().partition_count(),
+            shuffle_writer
+                .shuffle_output_partitioning()
+                .map(|p| p.partition_count())
+                .unwrap_or_else(|| {
+                    shuffle_writer.output_partitioning().partition_count()
+                }),
+        )))
+    }
+
     /// Generate a new stage ID
     fn next_stage_id(&mut self) -> usize {
         self.next_stage_id += 1;
@@ -176,6 +225,53 @@ impl DistributedPlanner {
     }
 }
 
+/// Limit every partition of the input of a LIMIT read through a single partition, so
+/// that the stage shuffling the input writes at most the rows the LIMIT may keep. When
+/// the input is sorted before the LIMIT, every partition keeps its first rows by the
+/// same ordering instead.
+fn with_partial_limit(plan: Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>> {
+    let (limit, fetch) = match plan.as_any().downcast_ref::<GlobalLimitExec>() {
+        Some(limit) => match limit.fetch() {
+            Some(fetch) => (limit, *fetch),
+            None => return Ok(plan),
+        },
+        None => return Ok(plan),
+    };
+    let sort = limit
+        .input()
+        .as_any()
+        .downcast_ref::<SortExec>()
+        .filter(|sort| !sort.preserve_partitioning());
+    let coalesce = match sort {
+        Some(sort) => sort.input(),
+        None => limit.input(),
+    };
+    let partitions = match coalesce.as_any().downcast_ref::<CoalescePartitionsExec>() {
+        Some(coalesce) => coalesce.input(),
+        None => return Ok(plan),
+    };
+    if partitions.as_any().is::<LocalLimitExec>() || partitions.as_any().is::<TopKExec>()
+    {
+        return Ok(plan);
+    }
+
+    let skip = limit.skip().copied().unwrap_or_default();
+    let partial_limit: Arc<dyn ExecutionPlan> = match sort {
+        Some(sort) => Arc::new(TopKExec::new(
+            partitions.clone(),
+            sort.expr().to_vec(),
+            skip + fetch,
+        )),
+        None => Arc::new(LocalLimitExec::new(partitions.clone(), skip + fetch)),
+    };
+    let coalesce = with_new_children_if_necessary(coalesce.clone(), vec![partial_limit])?;
+    let input = match sort {
+        Some(_) => with_new_children_if_necessary(limit.input().clone(), vec![coalesce])?,
+        None => coalesce,
+    };
+    Ok(with_new_children_if_necessary(plan, vec![input])?)
+}
+
 /// Returns the unresolved shuffles in the execution plan
 pub fn find_unresolved_shuffles(
     plan: &Arc<dyn ExecutionPlan>,
@@ -199,6 +295,7 @@ pub fn find_unresolved_shuffles(
 pub fn remove_unresolved_shuffles(
     stage: Arc<dyn ExecutionPlan>,
     partition_locations: &HashMap<usize, HashMap<usize, Vec<PartitionLocation>>>,
+    fetch_priority: FetchPriority,
 ) -> Result<Arc<dyn ExecutionPlan>> {
     let mut new_children: Vec<Arc<dyn ExecutionPlan>> = vec![];
     for child in stage.children() {
@@ -235,17 +332,1032 @@ pub fn remove_unresolved_shuffles(
                     .collect::<Vec<_>>()
                     .join("\n")
             );
-            new_children.push(Arc::new(ShuffleReaderExec::try_new(
-                relevant_locations,
-                unresolved_shuffle.schema().clone(),
-            )?))
+            new_children.push(Arc::new(
+                ShuffleReaderExec::try_new(
+                    relevant_locations,
+                    unresolved_shuffle.schema().clone(),
+                )?
+                .with_fetch_priority(fetch_priority),
+            ))
+        } else {
+            new_children.push(remove_unresolved_shuffles(
+                child,
+                partition_locations,
+                fetch_priority,
+            )?);
+        }
+    }
+    Ok(with_new_children_if_necessary(stage, new_children)?)
+}
+
+/// Replace the ShuffleReaderExec operators of a resolved stage with the
+/// UnresolvedShuffleExec operators they were resolved from, so that the stage is resolved
+/// again once the outputs of its input stages are complete again. `input_partition_counts`
+/// maps the ID of each input stage of the stage to its number of tasks.
+pub fn rollback_resolved_shuffles(
+    stage: Arc<dyn ExecutionPlan>,
+    input_partition_counts: &HashMap<usize, usize>,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let mut new_children: Vec<Arc<dyn ExecutionPlan>> = vec![];
+    for child in stage.children() {
+        if let Some(shuffle_reader) = child.as_any().downcast_ref::<ShuffleReaderExec>() {
+            // A reader of an empty output has no location to tell its stage from
+            let stage_id = match shuffle_reader.partition.iter().flatten().next() {
+                Some(location) => location.partition_id.stage_id,
+                None if input_partition_counts.len() == 1 => {
+                    *input_partition_counts.keys().next().unwrap()
+                }
+                None => return Err(BallistaError::General(
+                    "Cannot tell the input stage of a shuffle reader without locations"
+                        .to_owned(),
+                )),
+            };
+            let input_partition_count =
+                input_partition_counts.get(&stage_id).ok_or_else(|| {
+                    BallistaError::General(format!(
+                        "Stage {} is not an input of the stage to roll back",
+                        stage_id
+                    ))
+                })?;
+            new_children.push(Arc::new(UnresolvedShuffleExec::new(
+                stage_id,
+                shuffle_reader.schema(),
+                *input_partition_count,
+                shuffle_reader.partition.len(),
+            )))
         } else {
-            new_children.push(remove_unresolved_shuffles(child, partition_locations)?);
+            new_children.push(rollback_resolved_shuffles(child, input_partition_counts)?);
         }
     }
     Ok(with_new_children_if_necessary(stage, new_children)?)
 }
 
+/// A hash join reading both its sides from the output of other stages, whose probe side
+/// may be filtered by the keys of its build side
+struct BloomFilterJoin {
+    build_stage_id: usize,
+    build_keys: Vec<Column>,
+    probe_stage_id: usize,
+    probe_keys: Vec<Column>,
+}
+
+/// Filter the rows the stages of the probe sides of hash joins write by Bloom filters of
+/// `num_bytes` of the keys the stages of their build sides write. The build stage builds
+/// the filter as it writes its output, and the probe stage then runs after it, dropping
+/// the rows whose keys cannot match before they are shuffled.
+///
+/// Only joins which never output the rows of their probe side without a match are
+/// filtered, when their probe stage is read by no other operator, and when delaying
+/// the probe stage behind the build stage creates no cycle. A stage builds or applies at
+/// most one filter.
+pub fn plan_bloom_filters(
+    stages: Vec<Arc<ShuffleWriterExec>>,
+    num_bytes: usize,
+) -> Result<Vec<Arc<ShuffleWriterExec>>> {
+    let mut inputs: HashMap<usize, Vec<usize>> = HashMap::new();
+    let mut reads: HashMap<usize, usize> = HashMap::new();
+    let mut joins = vec![];
+    for stage in &stages {
+        let plan: Arc<dyn ExecutionPlan> = stage.clone();
+        for shuffle in find_unresolved_shuffles(&plan)? {
+            inputs
+                .entry(stage.stage_id())
+                .or_default()
+                .push(shuffle.stage_id);
+            *reads.entry(shuffle.stage_id).or_default() += 1;
+        }
+        collect_bloom_filter_joins(&plan, &mut joins);
+    }
+
+    let mut build_keys: HashMap<usize, Vec<Column>> = HashMap::new();
+    let mut probe_keys: HashMap<usize, (usize, Vec<Column>)> = HashMap::new();
+    for join in joins {
+        let (build, probe) = (join.build_stage_id, join.probe_stage_id);
+        let filtered = |stage: &usize| {
+            build_keys.contains_key(stage) || probe_keys.contains_key(stage)
+        };
+        if build == probe
+            || reads.get(&probe) != Some(&1)
+            || filtered(&build)
+            || filtered(&probe)
+            || depends_on(&inputs, build, probe)
+        {
+            continue;
+        }
+        info!(
+            "stage {} is filtered by a Bloom filter of the keys of stage {}",
+            probe, build
+        );
+        inputs.entry(probe).or_default().push(build);
+        build_keys.insert(build, join.build_keys);
+        probe_keys.insert(probe, (build, join.probe_keys));
+    }
+
+    stages
+        .into_iter()
+        .map(|stage| {
+            let input = stage.children()[0].clone();
+            let input: Arc<dyn ExecutionPlan> =
+                if let Some(keys) = build_keys.remove(&stage.stage_id()) {
+                    Arc::new(BloomFilterBuildExec::new(input, keys, num_bytes))
+                } else if let Some((build, keys)) = probe_keys.remove(&stage.stage_id()) {
+                    Arc::new(BloomFilterExec::new(input, build, keys))
+                } else {
+                    return Ok(stage);
+                };
+            create_shuffle_writer(
+                stage.job_id(),
+                stage.stage_id(),
+                input,
+                stage.shuffle_output_partitioning().cloned(),
+            )
+        })
+        .collect()
+}
+
+/// Collect the hash joins of `plan` whose probe side may be filtered by a Bloom filter of
+/// the keys of their build side: those never outputting the rows of their probe side
+/// without a match, and reading both sides from the output of other stages
+fn collect_bloom_filter_joins(
+    plan: &Arc<dyn ExecutionPlan>,
+    joins: &mut Vec<BloomFilterJoin>,
+) {
+    if let Some(join) = plan.as_any().downcast_ref::<HashJoinExec>() {
+        let drops_unmatched_probe_rows = matches!(
+            join.join_type(),
+            JoinType::Inner | JoinType::Left | JoinType::Semi | JoinType::Anti
+        ) && !*join.null_equals_null();
+        if let (true, Some(build_stage_id), Some(probe_stage_id)) = (
+            drops_unmatched_probe_rows,
+            shuffle_stage_id(join.left()),
+            shuffle_stage_id(join.right()),
+        ) {
+            joins.push(BloomFilterJoin {
+                build_stage_id,
+                build_keys: join.on().iter().map(|(left, _)| left.clone()).collect(),
+                probe_stage_id,
+                probe_keys: join.on().iter().map(|(_, right)| right.clone()).collect(),
+            });
+        }
+    }
+    for child in plan.children() {
+        collect_bloom_filter_joins(&child, joins);
+    }
+}
+
+/// The stage whose output `plan` reads all the rows of, unchanged
+fn shuffle_stage_id(plan: &Arc<dyn ExecutionPlan>) -> Option<usize> {
+    if let Some(shuffle) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+        Some(shuffle.stage_id)
+    } else if plan.as_any().is::<CoalesceBatchesExec>()
+        || plan.as_any().is::<CoalescePartitionsExec>()
+    {
+        shuffle_stage_id(&plan.children()[0])
+    } else {
+        None
+    }
+}
+
+/// Whether stage `stage_id` runs after stage `other`, by the input stages of each stage
+fn depends_on(
+    inputs: &HashMap<usize, Vec<usize>>,
+    stage_id: usize,
+    other: usize,
+) -> bool {
+    inputs.get(&stage_id).map_or(false, |stage_inputs| {
+        stage_inputs
+            .iter()
+            .any(|input| *input == other || depends_on(inputs, *input, other))
+    })
+}
+
+/// Set the filters of the BloomFilterExec operators of a stage to the Bloom filters built
+/// by their build stages, by stage ID. Operators whose build stage built no complete
+/// filter pass all rows through.
+pub fn resolve_bloom_filters(
+    stage: Arc<dyn ExecutionPlan>,
+    bloom_filters: &HashMap<usize, Arc<BloomFilter>>,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if let Some(exec) = stage.as_any().downcast_ref::<BloomFilterExec>() {
+        let filter = bloom_filters.get(&exec.build_stage_id()).cloned();
+        return Ok(Arc::new(exec.clone().with_filter(filter)));
+    }
+    let children = stage
+        .children()
+        .into_iter()
+        .map(|child| resolve_bloom_filters(child, bloom_filters))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(with_new_children_if_necessary(stage, children)?)
+}
+
+/// Bounds of the partition counts of the shuffles of a job, when they are chosen from the
+/// estimated size of their input rather than set by `ballista.shuffle.partitions`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShufflePartitionOptions {
+    /// Estimated input bytes per shuffle partition
+    pub bytes_per_partition: usize,
+    pub min_partitions: usize,
+    pub max_partitions: usize,
+}
+
+impl ShufflePartitionOptions {
+    /// The options set by `config`, `None` if the partition counts are not chosen from
+    /// statistics
+    pub fn from_config(config: &BallistaConfig) -> Option<Self> {
+        config.shuffle_partitions_auto().then(|| {
+            let min_partitions = config.shuffle_partitions_min().max(1);
+            Self {
+                bytes_per_partition: config.shuffle_partition_bytes().max(1),
+                min_partitions,
+                max_partitions: config.shuffle_partitions_max().max(min_partitions),
+            }
+        })
+    }
+
+    /// Number of partitions of a shuffle of `bytes` of input. The size of the input
+    /// is not always known, in which case there is a partition per task slot of the
+    /// cluster.
+    pub fn partition_count(&self, bytes: Option<usize>, total_slots: usize) -> usize {
+        let count = match bytes {
+            Some(bytes) => {
+                bytes / self.bytes_per_partition
+                    + usize::from(bytes % self.bytes_per_partition != 0)
+            }
+            None => total_slots,
+        };
+        count.min(self.max_partitions).max(self.min_partitions)
+    }
+}
+
+/// Choose the partition count of every hash repartition of `plan` from the estimated
+/// size of its input, on a cluster of `total_slots`. Both inputs of a partitioned hash
+/// join get the larger of their counts, since they must be partitioned alike.
+pub fn plan_shuffle_partitions(
+    plan: Arc<dyn ExecutionPlan>,
+    options: &ShufflePartitionOptions,
+    total_slots: usize,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let mut children = plan
+        .children()
+        .into_iter()
+        .map(|child| plan_shuffle_partitions(child, options, total_slots))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(repart) = plan.as_any().downcast_ref::<RepartitionExec>() {
+        if let Partitioning::Hash(exprs, _) = repart.partitioning() {
+            let input = children.remove(0);
+            let count =
+                options.partition_count(estimated_bytes(input.as_ref()), total_slots);
+            return Ok(Arc::new(RepartitionExec::try_new(
+                input,
+                Partitioning::Hash(exprs.clone(), count),
+            )?));
+        }
+    }
+
+    if let Some(join) = plan.as_any().downcast_ref::<HashJoinExec>() {
+        if *join.partition_mode() == PartitionMode::Partitioned {
+            let counts = children
+                .iter()
+                .map(hash_partition_count)
+                .collect::<Option<Vec<_>>>();
+            children = match counts.and_then(|counts| counts.into_iter().max()) {
+                Some(count) => children
+                    .into_iter()
+                    .map(|child| with_hash_partition_count(child, count))
+                    .collect::<Result<_>>()?,
+                // An input is not repartitioned, so keep the counts planned by DataFusion
+                None => children
+                    .into_iter()
+                    .zip(plan.children())
+                    .map(|(child, original)| match hash_partition_count(&original) {
+                        Some(count) => with_hash_partition_count(child, count),
+                        None => Ok(child),
+                    })
+                    .collect::<Result<_>>()?,
+            };
+        }
+    }
+
+    Ok(with_new_children_if_necessary(plan, children)?)
+}
+
+/// Partition count of the hash repartition producing the output of `plan`, if any
+fn hash_partition_count(plan: &Arc<dyn ExecutionPlan>) -> Option<usize> {
+    if let Some(repart) = plan.as_any().downcast_ref::<RepartitionExec>() {
+        match repart.partitioning() {
+            Partitioning::Hash(_, count) => Some(*count),
+            _ => None,
+        }
+    } else if plan.as_any().is::<CoalesceBatchesExec>() {
+        hash_partition_count(&plan.children()[0])
+    } else {
+        None
+    }
+}
+
+/// Set the partition count of the hash repartition producing the output of `plan`
+fn with_hash_partition_count(
+    plan: Arc<dyn ExecutionPlan>,
+    count: usize,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if let Some(repart) = plan.as_any().downcast_ref::<RepartitionExec>() {
+        if let Partitioning::Hash(exprs, _) = repart.partitioning() {
+            return Ok(Arc::new(RepartitionExec::try_new(
+                repart.input().clone(),
+                Partitioning::Hash(exprs.clone(), count),
+            )?));
+        }
+        Ok(plan)
+    } else if plan.as_any().is::<CoalesceBatchesExec>() {
+        let child = with_hash_partition_count(plan.children()[0].clone(), count)?;
+        Ok(with_new_children_if_necessary(plan, vec![child])?)
+    } else {
+        Ok(plan)
+    }
+}
+
+/// Estimated size of the output of `plan`: its statistics if they are known, otherwise
+/// the size of the files it scans
+pub(crate) fn estimated_bytes(plan: &dyn ExecutionPlan) -> Option<usize> {
+    plan.statistics()
+        .total_byte_size
+        .or_else(|| scanned_bytes(plan))
+}
+
+/// Total size of the files scanned by `plan`, `None` if it reads anything else
+fn scanned_bytes(plan: &dyn ExecutionPlan) -> Option<usize> {
+    let children = plan.children();
+    if !children.is_empty() {
+        return children
+            .iter()
+            .map(|child| scanned_bytes(child.as_ref()))
+            .sum();
+    }
+    let any = plan.as_any();
+    let config: &FileScanConfig = if let Some(exec) = any.downcast_ref::<ParquetExec>() {
+        exec.base_config()
+    } else if let Some(exec) = any.downcast_ref::<CsvExec>() {
+        exec.base_config()
+    } else if let Some(exec) = any.downcast_ref::<AvroExec>() {
+        exec.base_config()
+    } else if let Some(exec) = any.downcast_ref::<NdJsonExec>() {
+        exec.base_config()
+    } else {
+        return any.is::<EmptyExec>().then(|| 0);
+    };
+    Some(config.file_groups.iter().flatten().map(file_bytes).sum())
+}
+
+/// Size of the part of its file a scan reads
+fn file_bytes(file: &PartitionedFile) -> usize {
+    match &file.range {
+        Some(range) => (range.end - range.start).max(0) as usize,
+        None => file.object_meta.size,
+    }
+}
+
+/// How the files read by the scans of a job are grouped into tasks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanTaskOptions {
+    /// Bytes of files read by each task
+    pub target_partition_bytes: usize,
+    /// Files, or ranges of files, read by each task at most
+    pub max_files_per_partition: usize,
+}
+
+impl ScanTaskOptions {
+    pub fn from_config(config: &BallistaConfig) -> Self {
+        Self {
+            target_partition_bytes: config.scan_target_partition_bytes().max(1),
+            max_files_per_partition: config.scan_max_files_per_partition().max(1),
+        }
+    }
+}
+
+/// Regroup the files read by the Parquet scans of `plan` into tasks of about
+/// `target_partition_bytes` each, whatever the layout of the files. Files larger than a
+/// task are split into ranges of bytes, each reading the row groups starting in it, and
+/// smaller files are packed together, at most `max_files_per_partition` per task. Scans
+/// whose partition count the rest of the plan relies on, such as the input of a sort of
+/// a single partition, are left as they are.
+pub fn split_scan_tasks(
+    plan: Arc<dyn ExecutionPlan>,
+    options: &ScanTaskOptions,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    split_scans(plan, options, false)
+}
+
+fn split_scans(
+    plan: Arc<dyn ExecutionPlan>,
+    options: &ScanTaskOptions,
+    keep_partitioning: bool,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    if let Some(exec) = plan.as_any().downcast_ref::<ParquetExec>() {
+        if keep_partitioning {
+            return Ok(plan);
+        }
+        let config = exec.base_config();
+        return match scan_tasks(&config.file_groups, options) {
+            Some(file_groups) => {
+                let predicate = exec
+                    .pruning_predicate()
+                    .map(|predicate| predicate.logical_expr().clone());
+                Ok(Arc::new(ParquetExec::new(
+                    FileScanConfig {
+                        file_groups,
+                        ..config.clone()
+                    },
+                    predicate,
+                    None,
+                )))
+            }
+            None => Ok(plan),
+        };
+    }
+
+    let any = plan.as_any();
+    let keep_partitioning = match plan.required_child_distribution() {
+        Distribution::UnspecifiedDistribution => {
+            let partitioned_join = any
+                .downcast_ref::<HashJoinExec>()
+                .map(|join| *join.partition_mode() == PartitionMode::Partitioned)
+                .unwrap_or(false);
+            // The partition count of these does not depend on their input
+            let repartitions = any.is::<RepartitionExec>()
+                || any.is::<CoalescePartitionsExec>()
+                || any.is::<SortPreservingMergeExec>();
+            partitioned_join || (keep_partitioning && !repartitions)
+        }
+        _ => true,
+    };
+    let children = plan
+        .children()
+        .into_iter()
+        .map(|child| split_scans(child, options, keep_partitioning))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(with_new_children_if_necessary(plan, children)?)
+}
+
+/// The files of `file_groups` split and packed into tasks of about the same size, `None`
+/// if they are left as they are
+fn scan_tasks(
+    file_groups: &[Vec<PartitionedFile>],
+    options: &ScanTaskOptions,
+) -> Option<Vec<Vec<PartitionedFile>>> {
+    let files: Vec<&PartitionedFile> = file_groups.iter().flatten().collect();
+    // Files already read by ranges were split by their table
+    if files.is_empty() || files.iter().any(|file| file.range.is_some()) {
+        return None;
+    }
+    let total_bytes: usize = files.iter().map(|file| file.object_meta.size).sum();
+    let task_bytes = options.target_partition_bytes.max(1);
+    let max_files = options.max_files_per_partition.max(1);
+
+    let mut pieces = vec![];
+    for file in files {
+        let size = file.object_meta.size;
+        let ranges = size / task_bytes + usize::from(size % task_bytes != 0);
+        if ranges <= 1 {
+            pieces.push((size, file.clone()));
+            continue;
+        }
+        let range_bytes = size / ranges + usize::from(size % ranges != 0);
+        for start in (0..size).step_by(range_bytes) {
+            let end = (start + range_bytes).min(size);
+            let mut piece = file.clone();
+            piece.range = Some(FileRange {
+                start: start as i64,
+                end: end as i64,
+            });
+            pieces.push((end - start, piece));
+        }
+    }
+
+    let tasks = (total_bytes / task_bytes + usize::from(total_bytes % task_bytes != 0))
+        .max(pieces.len() / max_files + usize::from(pieces.len() % max_files != 0))
+        .clamp(1, pieces.len());
+    // The largest pieces are placed first, each in the task with the fewest bytes so far
+    // among those which may read more files
+    pieces.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let mut groups: Vec<(usize, Vec<PartitionedFile>)> = vec![(0, vec![]); tasks];
+    for (size, piece) in pieces {
+        let task = groups
+            .iter_mut()
+            .filter(|(_, group)| group.len() < max_files)
+            .min_by_key(|(bytes, _)| *bytes);
+        if let Some((bytes, group)) = task {
+            *bytes += size;
+            group.push(piece);
+        }
+    }
+    Some(groups.into_iter().map(|(_, group)| group).collect())
+}
+
+/// Shuffle the input of the partial aggregations of `plan` which are expected to
+/// output more than `max_groups_percent` percent of the rows of their input, so that
+/// they run after the shuffle rather than before it. Such aggregations hardly reduce the
+/// rows to shuffle, while every task before the shuffle holds a hash table of nearly
+/// all the groups. After the shuffle, the table of each task only holds the groups of
+/// its partition. Partial aggregations whose groups cannot be estimated are kept before
+/// the shuffle.
+pub fn plan_partial_aggregations(
+    plan: Arc<dyn ExecutionPlan>,
+    max_groups_percent: usize,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    shuffle_before_partial_aggregations(plan, &|partial| {
+        estimated_groups_percent(partial)
+            .map_or(false, |percent| percent > max_groups_percent)
+    })
+}
+
+/// Shuffle the input of the partial aggregations of `plan` for which `skip` holds
+fn shuffle_before_partial_aggregations(
+    plan: Arc<dyn ExecutionPlan>,
+    skip: &dyn Fn(&AggregateExec) -> bool,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let children = plan
+        .children()
+        .into_iter()
+        .map(|child| shuffle_before_partial_aggregations(child, skip))
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(repart) = plan.as_any().downcast_ref::<RepartitionExec>() {
+        if let Some(partial) = children[0].as_any().downcast_ref::<AggregateExec>() {
+            let skip = matches!(repart.partitioning(), Partitioning::Hash(_, _))
+                && *partial.mode() == AggregateMode::Partial
+                && skip(partial);
+            if skip {
+                return shuffle_before_partial_aggregation(
+                    children[0].clone(),
+                    repart.partitioning().partition_count(),
+                );
+            }
+        }
+    }
+
+    Ok(with_new_children_if_necessary(plan, children)?)
+}
+
+/// Hash repartition the input of the partial aggregation `partial` by its group
+/// expressions into `count` partitions, and aggregate the repartitioned rows
+fn shuffle_before_partial_aggregation(
+    partial: Arc<dyn ExecutionPlan>,
+    count: usize,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    let aggregate = partial
+        .as_any()
+        .downcast_ref::<AggregateExec>()
+        .ok_or_else(|| {
+            BallistaError::Internal("Expected a partial aggregation".to_owned())
+        })?;
+    let exprs = aggregate
+        .group_expr()
+        .expr()
+        .iter()
+        .map(|(expr, _)| expr.clone())
+        .collect();
+    let repart = Arc::new(RepartitionExec::try_new(
+        aggregate.input().clone(),
+        Partitioning::Hash(exprs, count),
+    )?);
+    Ok(with_new_children_if_necessary(partial, vec![repart])?)
+}
+
+/// Estimated number of groups of the partial aggregation `partial`, as a percentage of
+/// the rows of its input. `None` unless it groups by columns of its input only, and the
+/// statistics of its input bound the distinct values of all of them.
+fn estimated_groups_percent(partial: &AggregateExec) -> Option<usize> {
+    let group_by = partial.group_expr();
+    if group_by.expr().is_empty() || !group_by.null_expr().is_empty() {
+        return None;
+    }
+    let columns = group_by
+        .expr()
+        .iter()
+        .map(|(expr, _)| expr.as_any().downcast_ref::<Column>().map(Column::index))
+        .collect::<Option<Vec<_>>>()?;
+    groups_percent(&partial.input().statistics(), &columns)
+}
+
+/// Estimated number of groups of rows with `statistics` grouped by the columns at
+/// `columns`, as a percentage of the rows
+fn groups_percent(statistics: &Statistics, columns: &[usize]) -> Option<usize> {
+    let num_rows = statistics.num_rows.filter(|num_rows| *num_rows > 0)?;
+    let column_statistics = statistics.column_statistics.as_ref()?;
+    let groups = columns.iter().try_fold(1usize, |groups, index| {
+        let distinct = distinct_values(column_statistics.get(*index)?)?;
+        Some(groups.saturating_mul(distinct))
+    })?;
+    Some(groups.min(num_rows) * 100 / num_rows)
+}
+
+/// The distinct values of a column, if its statistics count them. The range of the
+/// values of an integer column is no estimate, as the values of sparse columns, such as
+/// IDs, only cover a small part of their range.
+fn distinct_values(statistics: &ColumnStatistics) -> Option<usize> {
+    statistics.distinct_count
+}
+
+/// Estimate the `COUNT(DISTINCT)`s of `plan` with `APPROX_DISTINCT`, whose partial
+/// aggregations shuffle a HyperLogLog sketch per group rather than the distinct values
+/// of the group. The estimates keep the names and the type of the counts they replace.
+pub fn approximate_distinct_counts(plan: &LogicalPlan) -> Result<LogicalPlan> {
+    let inputs = plan
+        .inputs()
+        .into_iter()
+        .map(approximate_distinct_counts)
+        .collect::<Result<Vec<_>>>()?;
+
+    if let LogicalPlan::Aggregate(aggregate) = plan {
+        let aggr_expr: Vec<Expr> = aggregate
+            .aggr_expr
+            .iter()
+            .map(approximate_distinct_count)
+            .collect();
+        if aggr_expr != aggregate.aggr_expr {
+            let approximate = LogicalPlanBuilder::from(inputs[0].clone())
+                .aggregate(aggregate.group_expr.clone(), aggr_expr)?
+                .build()?;
+            // Cast the estimates back to the type of the counts
+            let expr: Vec<Expr> = approximate
+                .schema()
+                .fields()
+                .iter()
+                .zip(aggregate.schema.fields())
+                .map(|(field, original)| {
+                    let column = Expr::Column(field.qualified_column());
+                    if field.data_type() == original.data_type() {
+                        column
+                    } else {
+                        Expr::Cast {
+                            expr: Box::new(column),
+                            data_type: original.data_type().clone(),
+                        }
+                        .alias(original.name())
+                    }
+                })
+                .collect();
+            return Ok(LogicalPlanBuilder::from(approximate)
+                .project(expr)?
+                .build()?);
+        }
+    }
+
+    Ok(from_plan(plan, &plan.expressions(), &inputs)?)
+}
+
+/// `expr` with `APPROX_DISTINCT` in place of a `COUNT(DISTINCT)` of a single expression
+fn approximate_distinct_count(expr: &Expr) -> Expr {
+    match expr {
+        Expr::AggregateFunction {
+            fun: AggregateFunction::Count,
+            args,
+            distinct: true,
+        } if args.len() == 1 => Expr::AggregateFunction {
+            fun: AggregateFunction::ApproxDistinct,
+            args: args.clone(),
+            distinct: false,
+        },
+        Expr::Alias(expr, name) => {
+            Expr::Alias(Box::new(approximate_distinct_count(expr)), name.clone())
+        }
+        expr => expr.clone(),
+    }
+}
+
+/// Fraction of the rows of its input a filter is assumed to keep
+const FILTER_SELECTIVITY: f64 = 0.2;
+
+/// Reorder the inner joins of `plan` by the estimated number of rows of their inputs,
+/// from the statistics of the tables they scan by table name. A series of joins starts
+/// with its smallest input, which is joined with the input connected to it yielding the
+/// fewest rows, and so on. The smaller side of every join is its left side, which hash
+/// joins build their hash table from. Joins of inputs whose number of rows cannot be
+/// estimated are left as they are.
+pub fn reorder_joins(
+    plan: &LogicalPlan,
+    statistics: &HashMap<String, TableStatistics>,
+) -> Result<LogicalPlan> {
+    if is_reorderable_join(plan) {
+        let mut inputs = vec![];
+        let mut on = vec![];
+        flatten_joins(plan, &mut inputs, &mut on);
+        let inputs = inputs
+            .into_iter()
+            .map(|input| reorder_joins(input, statistics))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(joined) = join_in_order(&inputs, &on, statistics)? {
+            let columns: Vec<_> = plan
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.qualified_column())
+                .collect();
+            let joined_columns: Vec<_> = joined
+                .schema()
+                .fields()
+                .iter()
+                .map(|field| field.qualified_column())
+                .collect();
+            if columns == joined_columns {
+                return Ok(joined);
+            }
+            // The columns keep their order
+            let expr = columns.into_iter().map(Expr::Column).collect::<Vec<_>>();
+            return Ok(LogicalPlanBuilder::from(joined).project(expr)?.build()?);
+        }
+    }
+
+    let inputs = plan
+        .inputs()
+        .into_iter()
+        .map(|input| reorder_joins(input, statistics))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(from_plan(plan, &plan.expressions(), &inputs)?)
+}
+
+/// Whether `plan` is an inner join on equal columns only, which can be done in any order
+/// with the inner joins around it
+fn is_reorderable_join(plan: &LogicalPlan) -> bool {
+    match plan {
+        LogicalPlan::Join(join) => {
+            matches!(join.join_type, JoinType::Inner)
+                && matches!(join.join_constraint, JoinConstraint::On)
+                && join.filter.is_none()
+                && !join.null_equals_null
+        }
+        _ => false,
+    }
+}
+
+/// Collect the inputs of the series of reorderable joins `plan`, and their conditions
+fn flatten_joins<'a>(
+    plan: &'a LogicalPlan,
+    inputs: &mut Vec<&'a LogicalPlan>,
+    on: &mut Vec<(logical_plan::Column, logical_plan::Column)>,
+) {
+    match plan {
+        LogicalPlan::Join(join) if is_reorderable_join(plan) => {
+            flatten_joins(join.left.as_ref(), inputs, on);
+            flatten_joins(join.right.as_ref(), inputs, on);
+            on.extend(join.on.iter().cloned());
+        }
+        plan => inputs.push(plan),
+    }
+}
+
+/// Join `inputs` on the equal columns of `on`, starting with the smallest input and
+/// then joining the input which yields the fewest rows. `None` if the number of rows of
+/// an input cannot be estimated, or if an input is not joined with the others.
+fn join_in_order(
+    inputs: &[LogicalPlan],
+    on: &[(logical_plan::Column, logical_plan::Column)],
+    statistics: &HashMap<String, TableStatistics>,
+) -> Result<Option<LogicalPlan>> {
+    let rows = match inputs
+        .iter()
+        .map(|input| estimated_rows(input, statistics))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(rows) => rows,
+        None => return Ok(None),
+    };
+    // The input of each column of the conditions
+    let input_of = |column: &logical_plan::Column| {
+        let mut matching = inputs
+            .iter()
+            .enumerate()
+            .filter(|(_, input)| input.schema().index_of_column(column).is_ok())
+            .map(|(index, _)| index);
+        match (matching.next(), matching.next()) {
+            (Some(index), None) => Some(index),
+            _ => None,
+        }
+    };
+    let on = match on
+        .iter()
+        .map(|(left, right)| {
+            let (left_input, right_input) = (input_of(left)?, input_of(right)?);
+            (left_input != right_input).then(|| (left_input, left, right_input, right))
+        })
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(on) => on,
+        None => return Ok(None),
+    };
+
+    let first = (0..inputs.len())
+        .min_by(|a, b| rows[*a].total_cmp(&rows[*b]))
+        .expect("joins have inputs");
+    let mut joined = vec![first];
+    let mut plan = inputs[first].clone();
+    let mut plan_rows = rows[first];
+    while joined.len() < inputs.len() {
+        let mut best: Option<(f64, LogicalPlan, usize)> = None;
+        for next in (0..inputs.len()).filter(|index| !joined.contains(index)) {
+            let mut keys = vec![];
+            let mut next_keys = vec![];
+            for (left_input, left, right_input, right) in &on {
+                if joined.contains(left_input) && *right_input == next {
+                    keys.push((*left).clone());
+                    next_keys.push((*right).clone());
+                } else if joined.contains(right_input) && *left_input == next {
+                    keys.push((*right).clone());
+                    next_keys.push((*left).clone());
+                }
+            }
+            if keys.is_empty() {
+                continue;
+            }
+            let builder = if plan_rows <= rows[next] {
+                LogicalPlanBuilder::from(plan.clone()).join(
+                    &inputs[next],
+                    JoinType::Inner,
+                    (keys, next_keys),
+                    None,
+                )?
+            } else {
+                LogicalPlanBuilder::from(inputs[next].clone()).join(
+                    &plan,
+                    JoinType::Inner,
+                    (next_keys, keys),
+                    None,
+                )?
+            };
+            let candidate = builder.build()?;
+            let candidate_rows = match estimated_rows(&candidate, statistics) {
+                Some(rows) => rows,
+                None => return Ok(None),
+            };
+            if best
+                .as_ref()
+                .map_or(true, |(rows, _, _)| candidate_rows < *rows)
+            {
+                best = Some((candidate_rows, candidate, next));
+            }
+        }
+        match best {
+            Some((rows, candidate, next)) => {
+                plan = candidate;
+                plan_rows = rows;
+                joined.push(next);
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(plan))
+}
+
+/// Estimated number of rows of `plan`, from the statistics of the tables it scans
+fn estimated_rows(
+    plan: &LogicalPlan,
+    statistics: &HashMap<String, TableStatistics>,
+) -> Option<f64> {
+    match plan {
+        LogicalPlan::TableScan(scan) => {
+            let table = statistics.get(&scan.table_name)?;
+            Some(
+                table.num_rows as f64
+                    * FILTER_SELECTIVITY.powi(scan.filters.len() as i32),
+            )
+        }
+        LogicalPlan::Filter(_) => {
+            Some(estimated_rows(plan.inputs()[0], statistics)? * FILTER_SELECTIVITY)
+        }
+        LogicalPlan::Projection(_)
+        | LogicalPlan::SubqueryAlias(_)
+        | LogicalPlan::Sort(_)
+        | LogicalPlan::Repartition(_) => estimated_rows(plan.inputs()[0], statistics),
+        LogicalPlan::Join(join) if is_reorderable_join(plan) => {
+            let left_rows = estimated_rows(&join.left, statistics)?;
+            let right_rows = estimated_rows(&join.right, statistics)?;
+            // Rows match on the key with the most distinct values
+            let distinct = join
+                .on
+                .iter()
+                .filter_map(|(left, right)| {
+                    match (
+                        distinct_count(&join.left, left, statistics),
+                        distinct_count(&join.right, right, statistics),
+                    ) {
+                        (Some(left), Some(right)) => Some(left.max(right)),
+                        (distinct, None) | (None, distinct) => distinct,
+                    }
+                })
+                .fold(0.0, f64::max);
+            if distinct >= 1.0 {
+                Some(left_rows * right_rows / distinct)
+            } else {
+                // Without distinct counts, every row of the larger side is assumed to
+                // match one row of the other side
+                Some(left_rows.max(right_rows))
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Distinct count of `column` of `plan`, as analyzed for the table it comes from
+fn distinct_count(
+    plan: &LogicalPlan,
+    column: &logical_plan::Column,
+    statistics: &HashMap<String, TableStatistics>,
+) -> Option<f64> {
+    match plan {
+        LogicalPlan::TableScan(scan) => {
+            plan.schema().index_of_column(column).ok()?;
+            let table = statistics.get(&scan.table_name)?;
+            let column = table.columns.iter().find(|c| c.name == column.name)?;
+            match column.optional_distinct_count {
+                Some(OptionalDistinctCount::DistinctCount(count)) => Some(count as f64),
+                None => None,
+            }
+        }
+        LogicalPlan::SubqueryAlias(_) => {
+            plan.schema().index_of_column(column).ok()?;
+            distinct_count(
+                plan.inputs()[0],
+                &logical_plan::Column::from_name(&column.name),
+                statistics,
+            )
+        }
+        LogicalPlan::Projection(_)
+        | LogicalPlan::Filter(_)
+        | LogicalPlan::Sort(_)
+        | LogicalPlan::Repartition(_) => {
+            distinct_count(plan.inputs()[0], column, statistics)
+        }
+        LogicalPlan::Join(join) => distinct_count(&join.left, column, statistics)
+            .or_else(|| distinct_count(&join.right, column, statistics)),
+        _ => None,
+    }
+}
+
+/// The resources the tasks of a stage running `plan` mostly use. Stages building hash
+/// tables of joins or of final aggregations, sorting or computing windows are
+/// memory-heavy, whatever else they do. Other stages scanning files are I/O-heavy.
+pub fn stage_resource_profile(plan: &dyn ExecutionPlan) -> ResourceProfile {
+    fn visit(plan: &dyn ExecutionPlan, scans: &mut bool) -> bool {
+        let any = plan.as_any();
+        let memory_heavy = any.is::<HashJoinExec>()
+            || any.is::<CrossJoinExec>()
+            || any.is::<SortExec>()
+            || any.is::<WindowAggExec>()
+            || any
+                .downcast_ref::<AggregateExec>()
+                .map_or(false, |aggregate| {
+                    *aggregate.mode() != AggregateMode::Partial
+                        && !aggregate.group_expr().expr().is_empty()
+                });
+        if memory_heavy {
+            return true;
+        }
+        *scans |= any.is::<ParquetExec>()
+            || any.is::<CsvExec>()
+            || any.is::<AvroExec>()
+            || any.is::<NdJsonExec>()
+            || any.is::<NodeLocalScanExec>();
+        plan.children()
+            .iter()
+            .any(|child| visit(child.as_ref(), scans))
+    }
+
+    let mut scans = false;
+    if visit(plan, &mut scans) {
+        ResourceProfile::Memory
+    } else if scans {
+        ResourceProfile::Io
+    } else {
+        ResourceProfile::General
+    }
+}
+
+/// Value of the `ballista.resource_profile` label of the executors offered the tasks of
+/// the stages of `profile` first
+pub fn resource_profile_label(profile: ResourceProfile) -> &'static str {
+    match profile {
+        ResourceProfile::General => "general",
+        ResourceProfile::Memory => "memory",
+        ResourceProfile::Io => "io",
+    }
+}
+
+/// Structural fingerprint of a stage shuffling `plan` by `partitioning`: the serialized
+/// plan, in which the stages it reads are identified by their ID. `None` if the plan
+/// cannot be serialized, in which case the stage is never shared.
+fn stage_fingerprint(
+    plan: Arc<dyn ExecutionPlan>,
+    partitioning: Option<&Partitioning>,
+) -> Option<Vec<u8>> {
+    let node =
+        PhysicalPlanNode::try_from_physical_plan(plan, &DefaultPhysicalExtensionCodec {})
+            .ok()?;
+    let mut fingerprint = format!("{:?}", partitioning).into_bytes();
+    node.try_encode(&mut fingerprint).ok()?;
+    Some(fingerprint)
+}
+
 fn create_shuffle_writer(
     job_id: &str,
     stage_id: usize,
@@ -263,23 +1375,47 @@ fn create_shuffle_writer(
 
 #[cfg(test)]
 mod test {
-    use crate::planner::DistributedPlanner;
+    use crate::planner::{
+        approximate_distinct_counts, file_bytes, find_unresolved_shuffles,
+        groups_percent, plan_bloom_filters, plan_partial_aggregations,
+        plan_shuffle_partitions, reorder_joins, shuffle_before_partial_aggregations,
+        split_scan_tasks, stage_resource_profile, DistributedPlanner, ScanTaskOptions,
+        ShufflePartitionOptions,
+    };
     use crate::test_utils::datafusion_test_context;
     use ballista_core::error::BallistaError;
-    use ballista_core::execution_plans::UnresolvedShuffleExec;
+    use ballista_core::execution_plans::{
+        BloomFilterBuildExec, BloomFilterExec, TopKExec, UnresolvedShuffleExec,
+    };
     use ballista_core::serde::{protobuf, AsExecutionPlan, BallistaCodec};
+    use datafusion::arrow::compute::SortOptions;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::listing::PartitionedFile;
+    use datafusion::datasource::object_store::ObjectStoreUrl;
+    use datafusion::logical_plan::LogicalPlan;
     use datafusion::physical_plan::aggregates::{AggregateExec, AggregateMode};
     use datafusion::physical_plan::coalesce_batches::CoalesceBatchesExec;
+    use datafusion::physical_plan::empty::EmptyExec;
+    use datafusion::physical_plan::expressions::{col, ApproxDistinct, PhysicalSortExpr};
+    use datafusion::physical_plan::file_format::{CsvExec, FileScanConfig, ParquetExec};
     use datafusion::physical_plan::hash_join::HashJoinExec;
+    use datafusion::physical_plan::limit::{GlobalLimitExec, LocalLimitExec};
+    use datafusion::physical_plan::repartition::RepartitionExec;
     use datafusion::physical_plan::sorts::sort::SortExec;
+    use datafusion::physical_plan::windows::WindowAggExec;
     use datafusion::physical_plan::{
         coalesce_partitions::CoalescePartitionsExec, projection::ProjectionExec,
     };
-    use datafusion::physical_plan::{displayable, ExecutionPlan};
+    use datafusion::physical_plan::{
+        displayable, ColumnStatistics, ExecutionPlan, Partitioning, Statistics,
+    };
     use datafusion::prelude::SessionContext;
+    use datafusion::scalar::ScalarValue;
+    use std::collections::HashMap;
     use std::ops::Deref;
 
     use ballista_core::serde::protobuf::PhysicalPlanNode;
+    use datafusion_proto::logical_plan::AsLogicalPlan;
     use datafusion_proto::protobuf::LogicalPlanNode;
     use std::sync::Arc;
     use uuid::Uuid;
@@ -376,16 +1512,51 @@ mod test {
     }
 
     #[tokio::test]
-    async fn distributed_join_plan() -> Result<(), BallistaError> {
+    async fn stage_resource_profiles() -> Result<(), BallistaError> {
         let ctx = datafusion_test_context("testdata").await?;
-
-        // simplified form of TPC-H query 12
         let df = ctx
             .sql(
-                "select
-    l_shipmode,
-    sum(case
-            when o_orderpriority = '1-URGENT'
+                "select l_returnflag, sum(l_extendedprice) from lineitem
+                group by l_returnflag order by l_returnflag",
+            )
+            .await?;
+        let plan = ctx.optimize(&df.to_logical_plan()?)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+        let stages = DistributedPlanner::new().plan_query_stages("job", plan)?;
+
+        // The partial aggregation of the scan, the final aggregation and the sort
+        let profiles: Vec<protobuf::ResourceProfile> = stages
+            .iter()
+            .map(|stage| stage_resource_profile(stage.as_ref()))
+            .collect();
+        assert_eq!(
+            profiles,
+            vec![
+                protobuf::ResourceProfile::Io,
+                protobuf::ResourceProfile::Memory,
+                protobuf::ResourceProfile::Memory,
+            ]
+        );
+
+        let empty = EmptyExec::new(false, Arc::new(Schema::empty()));
+        assert_eq!(
+            stage_resource_profile(&empty),
+            protobuf::ResourceProfile::General
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_join_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        // simplified form of TPC-H query 12
+        let df = ctx
+            .sql(
+                "select
+    l_shipmode,
+    sum(case
+            when o_orderpriority = '1-URGENT'
                 or o_orderpriority = '2-HIGH'
                 then 1
             else 0
@@ -548,6 +1719,724 @@ order by
         Ok(())
     }
 
+    #[tokio::test]
+    async fn distributed_window_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select l_returnflag, sum(l_extendedprice) over (partition by l_returnflag order by l_shipdate) as running_price
+            from lineitem",
+            )
+            .await?;
+
+        let plan = df.to_logical_plan()?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        /* Expected result:
+
+        ShuffleWriterExec: Some(Hash([Column { name: "l_returnflag", index: 8 }], 2))
+          CsvExec: source=Path(testdata/lineitem: [testdata/lineitem/partition0.tbl,testdata/lineitem/partition1.tbl]), has_header=false
+
+        ShuffleWriterExec: None
+          ProjectionExec: expr=[l_returnflag@8 as l_returnflag, SUM(lineitem.l_extendedprice) PARTITION BY [lineitem.l_returnflag] ORDER BY [lineitem.l_shipdate ASC NULLS LAST]@16 as running_price]
+            WindowAggExec: wdw=[SUM(lineitem.l_extendedprice): Ok(Field { name: "SUM(lineitem.l_extendedprice)", ... }), frame: None }]
+              SortExec: [l_returnflag@8 ASC NULLS LAST,l_shipdate@10 ASC NULLS LAST]
+                CoalesceBatchesExec: target_batch_size=4096
+                  UnresolvedShuffleExec
+        */
+
+        assert_eq!(2, stages.len());
+
+        // the window partition keys drive the shuffle
+        let partitioning = stages[0].shuffle_output_partitioning();
+        assert!(matches!(partitioning, Some(Partitioning::Hash(_, 2))));
+
+        // sorting happens within each shuffled partition
+        let projection = stages[1].children()[0].clone();
+        let window = projection.children()[0].clone();
+        let window = downcast_exec!(window, WindowAggExec);
+        let sort = window.children()[0].clone();
+        let sort = downcast_exec!(sort, SortExec);
+        assert!(sort.preserve_partitioning());
+        assert_eq!(sort.output_partitioning().partition_count(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_limit_plan() -> Result<(), BallistaError> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let input = Arc::new(RepartitionExec::try_new(
+            Arc::new(EmptyExec::new(false, schema)),
+            Partitioning::RoundRobinBatch(4),
+        )?);
+        let plan = Arc::new(GlobalLimitExec::new(
+            Arc::new(CoalescePartitionsExec::new(input)),
+            Some(5),
+            Some(10),
+        ));
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        /* Expected result:
+
+        ShuffleWriterExec: None
+          LocalLimitExec: fetch=15
+            EmptyExec: produce_one_row=false
+
+        ShuffleWriterExec: None
+          GlobalLimitExec: skip=5, fetch=10
+            CoalescePartitionsExec
+              UnresolvedShuffleExec
+        */
+
+        assert_eq!(2, stages.len());
+
+        // every task of the first stage writes the rows the limit may keep at most
+        let partial_limit = stages[0].children()[0].clone();
+        let partial_limit = downcast_exec!(partial_limit, LocalLimitExec);
+        assert_eq!(partial_limit.fetch(), 15);
+
+        let limit = stages[1].children()[0].clone();
+        let limit = downcast_exec!(limit, GlobalLimitExec);
+        let coalesce = limit.children()[0].clone();
+        let coalesce = downcast_exec!(coalesce, CoalescePartitionsExec);
+        let unresolved_shuffle = coalesce.children()[0].clone();
+        assert_eq!(
+            downcast_exec!(unresolved_shuffle, UnresolvedShuffleExec).stage_id,
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_top_k_plan() -> Result<(), BallistaError> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let input = Arc::new(RepartitionExec::try_new(
+            Arc::new(EmptyExec::new(false, schema.clone())),
+            Partitioning::RoundRobinBatch(4),
+        )?);
+        let sort_expr = vec![PhysicalSortExpr {
+            expr: col("a", &schema)?,
+            options: SortOptions::default(),
+        }];
+        let plan = Arc::new(GlobalLimitExec::new(
+            Arc::new(SortExec::try_new(
+                sort_expr,
+                Arc::new(CoalescePartitionsExec::new(input)),
+            )?),
+            None,
+            Some(10),
+        ));
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        /* Expected result:
+
+        ShuffleWriterExec: None
+          TopKExec: fetch=10, expr=[a@0 ASC]
+            EmptyExec: produce_one_row=false
+
+        ShuffleWriterExec: None
+          GlobalLimitExec: skip=None, fetch=10
+            SortExec: [a@0 ASC]
+              CoalescePartitionsExec
+                UnresolvedShuffleExec
+        */
+
+        assert_eq!(2, stages.len());
+
+        // every task of the first stage writes its first rows by the ordering only
+        let top_k = stages[0].children()[0].clone();
+        let top_k = downcast_exec!(top_k, TopKExec);
+        assert_eq!(top_k.fetch(), 10);
+        assert_eq!(top_k.expr().len(), 1);
+
+        let limit = stages[1].children()[0].clone();
+        let limit = downcast_exec!(limit, GlobalLimitExec);
+        let sort = limit.children()[0].clone();
+        let sort = downcast_exec!(sort, SortExec);
+        let coalesce = sort.children()[0].clone();
+        let coalesce = downcast_exec!(coalesce, CoalescePartitionsExec);
+        let unresolved_shuffle = coalesce.children()[0].clone();
+        assert_eq!(
+            downcast_exec!(unresolved_shuffle, UnresolvedShuffleExec).stage_id,
+            1
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_self_join_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select * from lineitem a join lineitem b on a.l_orderkey = b.l_orderkey",
+            )
+            .await?;
+
+        let plan = df.to_logical_plan()?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        // both sides of the join shuffle the same scan, which is planned once
+        assert_eq!(2, stages.len());
+        let stage: Arc<dyn ExecutionPlan> = stages[1].clone();
+        let unresolved_shuffles = find_unresolved_shuffles(&stage)?;
+        assert_eq!(2, unresolved_shuffles.len());
+        for unresolved_shuffle in unresolved_shuffles {
+            assert_eq!(stages[0].stage_id(), unresolved_shuffle.stage_id);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_bloom_filter_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+        let job_id = Uuid::new_v4().to_string();
+
+        let df = ctx
+            .sql(
+                "select l_shipmode, o_orderpriority from lineitem join orders on l_orderkey = o_orderkey",
+            )
+            .await?;
+        let plan = ctx.optimize(&df.to_logical_plan()?)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+        let stages = DistributedPlanner::new().plan_query_stages(&job_id, plan)?;
+        let stages = plan_bloom_filters(stages, 1024)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        // The stage of the build side builds the filter of its keys, which the stage of
+        // the probe side applies
+        assert_eq!(3, stages.len());
+        let build = stages[0].children()[0].clone();
+        let build = build
+            .as_any()
+            .downcast_ref::<BloomFilterBuildExec>()
+            .unwrap();
+        assert_eq!(build.keys()[0].name(), "l_orderkey");
+        assert_eq!(build.num_bytes(), 1024);
+        let probe = stages[1].children()[0].clone();
+        let probe = probe.as_any().downcast_ref::<BloomFilterExec>().unwrap();
+        assert_eq!(probe.build_stage_id(), stages[0].stage_id());
+        assert_eq!(probe.keys()[0].name(), "o_orderkey");
+        assert!(probe.filter().is_none());
+
+        // Joins keeping the rows of their probe side without a match, and stages read
+        // twice, are not filtered
+        for sql in [
+            "select * from lineitem right join orders on l_orderkey = o_orderkey",
+            "select * from lineitem a join lineitem b on a.l_orderkey = b.l_orderkey",
+        ] {
+            let df = ctx.sql(sql).await?;
+            let plan = ctx.optimize(&df.to_logical_plan()?)?;
+            let plan = ctx.create_physical_plan(&plan).await?;
+            let stages = DistributedPlanner::new().plan_query_stages(&job_id, plan)?;
+            for stage in plan_bloom_filters(stages, 1024)? {
+                let input = stage.children()[0].clone();
+                assert!(!input.as_any().is::<BloomFilterBuildExec>());
+                assert!(!input.as_any().is::<BloomFilterExec>());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_plan_shuffle_partitions() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select l_orderkey, o_orderpriority
+            from lineitem join orders on l_orderkey = o_orderkey",
+            )
+            .await?;
+
+        let plan = df.to_logical_plan()?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        // lineitem is scanned from 2402 bytes of files and orders from 1118 bytes
+        let options = ShufflePartitionOptions {
+            bytes_per_partition: 1000,
+            min_partitions: 1,
+            max_partitions: 10,
+        };
+        let plan = plan_shuffle_partitions(plan, &options, 4)?;
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        // both inputs of the join take the partition count of the larger one
+        assert_eq!(3, stages.len());
+        for stage in &stages[0..2] {
+            assert_eq!(
+                3,
+                stage
+                    .shuffle_output_partitioning()
+                    .unwrap()
+                    .partition_count()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn shuffle_partition_count() {
+        let options = ShufflePartitionOptions {
+            bytes_per_partition: 100,
+            min_partitions: 2,
+            max_partitions: 8,
+        };
+        assert_eq!(3, options.partition_count(Some(201), 4));
+        assert_eq!(2, options.partition_count(Some(0), 4));
+        assert_eq!(8, options.partition_count(Some(10_000), 4));
+        // the size of the input is unknown
+        assert_eq!(4, options.partition_count(None, 4));
+        assert_eq!(2, options.partition_count(None, 0));
+    }
+
+    #[tokio::test]
+    async fn distributed_partial_aggregation_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select l_returnflag, sum(l_extendedprice) as sum_price
+            from lineitem
+            group by l_returnflag",
+            )
+            .await?;
+
+        let plan = df.to_logical_plan()?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        // the groups of the scanned files cannot be estimated
+        let kept = plan_partial_aggregations(plan.clone(), 0)?;
+        let mut planner = DistributedPlanner::new();
+        let stages = planner.plan_query_stages(&Uuid::new_v4().to_string(), kept)?;
+        let stage0 = stages[0].children()[0].clone();
+        let partial_hash = downcast_exec!(stage0, AggregateExec);
+        assert!(*partial_hash.mode() == AggregateMode::Partial);
+
+        let plan = shuffle_before_partial_aggregations(plan, &|_| true)?;
+        let mut planner = DistributedPlanner::new();
+        let stages = planner.plan_query_stages(&Uuid::new_v4().to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        /* Expected result:
+
+        ShuffleWriterExec: Some(Hash([Column { name: "l_returnflag", index: 1 }], 2))
+          CsvExec: source=Path(testdata/lineitem: [testdata/lineitem/partition0.tbl,testdata/lineitem/partition1.tbl]), has_header=false
+
+        ShuffleWriterExec: None
+          ProjectionExec: expr=[l_returnflag@0 as l_returnflag, SUM(lineitem.l_extendedprice)@1 as sum_price]
+            AggregateExec: mode=FinalPartitioned, gby=[l_returnflag@0 as l_returnflag], aggr=[SUM(lineitem.l_extendedprice)]
+              CoalesceBatchesExec: target_batch_size=4096
+                AggregateExec: mode=Partial, gby=[l_returnflag@1 as l_returnflag], aggr=[SUM(lineitem.l_extendedprice)]
+                  UnresolvedShuffleExec
+        */
+
+        assert_eq!(2, stages.len());
+
+        // verify stage 0
+        let stage0 = stages[0].children()[0].clone();
+        downcast_exec!(stage0, CsvExec);
+        assert_eq!(
+            2,
+            stages[0]
+                .shuffle_output_partitioning()
+                .unwrap()
+                .partition_count()
+        );
+
+        // verify stage 1
+        let stage1 = stages[1].children()[0].clone();
+        let projection = downcast_exec!(stage1, ProjectionExec);
+        let final_hash = projection.children()[0].clone();
+        let final_hash = downcast_exec!(final_hash, AggregateExec);
+        assert!(*final_hash.mode() == AggregateMode::FinalPartitioned);
+        let coalesce = final_hash.children()[0].clone();
+        let partial_hash = coalesce.children()[0].clone();
+        let partial_hash = downcast_exec!(partial_hash, AggregateExec);
+        assert!(*partial_hash.mode() == AggregateMode::Partial);
+        let unresolved_shuffle = partial_hash.children()[0].clone();
+        let unresolved_shuffle =
+            downcast_exec!(unresolved_shuffle, UnresolvedShuffleExec);
+        assert_eq!(unresolved_shuffle.stage_id, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn approximate_count_distinct_plan() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select l_returnflag, count(distinct l_orderkey) as orders
+            from lineitem
+            group by l_returnflag",
+            )
+            .await?;
+
+        let plan = approximate_distinct_counts(&df.to_logical_plan()?)?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+        // the estimates keep the type of the counts
+        assert_eq!(plan.schema().field(1).data_type(), &DataType::Int64);
+
+        let mut planner = DistributedPlanner::new();
+        let stages = planner.plan_query_stages(&Uuid::new_v4().to_string(), plan)?;
+        for stage in &stages {
+            println!("{}", displayable(stage.as_ref()).indent());
+        }
+
+        // the distinct values are estimated in a single aggregation, whose partial
+        // aggregations shuffle sketches
+        assert_eq!(2, stages.len());
+        let stage0 = stages[0].children()[0].clone();
+        let partial_hash = downcast_exec!(stage0, AggregateExec);
+        assert!(*partial_hash.mode() == AggregateMode::Partial);
+        assert!(partial_hash.aggr_expr()[0].as_any().is::<ApproxDistinct>());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reorder_joins_by_table_statistics() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+        let df = ctx
+            .sql(
+                "select l_quantity, o_orderdate, c_name
+            from lineitem
+            join orders on l_orderkey = o_orderkey
+            join customer on o_custkey = c_custkey",
+            )
+            .await?;
+        let plan = df.to_logical_plan()?;
+
+        let table = |name: &str, num_rows, columns: &[(&str, u64)]| {
+            let columns = columns
+                .iter()
+                .map(|(name, distinct_count)| protobuf::ColumnStatistics {
+                    name: name.to_string(),
+                    null_count: 0,
+                    optional_distinct_count: Some(
+                        protobuf::column_statistics::OptionalDistinctCount::DistinctCount(
+                            *distinct_count,
+                        ),
+                    ),
+                    min_value: None,
+                    max_value: None,
+                })
+                .collect();
+            let statistics = protobuf::TableStatistics {
+                table: name.to_owned(),
+                num_rows,
+                columns,
+                sample_fraction: 1.0,
+                analyzed_at: 0,
+            };
+            (name.to_owned(), statistics)
+        };
+        let statistics = HashMap::from([
+            table("lineitem", 6_000_000, &[("l_orderkey", 1_500_000)]),
+            table(
+                "orders",
+                1_500_000,
+                &[("o_orderkey", 1_500_000), ("o_custkey", 100_000)],
+            ),
+            table("customer", 150_000, &[("c_custkey", 150_000)]),
+        ]);
+
+        // Names of the tables joined, in order
+        fn joined_tables(plan: &LogicalPlan) -> Vec<String> {
+            match plan {
+                LogicalPlan::TableScan(scan) => vec![scan.table_name.clone()],
+                plan => plan.inputs().into_iter().flat_map(joined_tables).collect(),
+            }
+        }
+
+        // The smallest table is joined first, and every join builds its hash table
+        // from its smaller side
+        let reordered = reorder_joins(&plan, &statistics)?;
+        assert_eq!(
+            joined_tables(&reordered),
+            vec!["customer", "orders", "lineitem"]
+        );
+        assert_eq!(reordered.schema(), plan.schema());
+
+        // Joins are left as they are without the statistics of all their inputs
+        let statistics = HashMap::from([table("customer", 150_000, &[])]);
+        let unchanged = reorder_joins(&plan, &statistics)?;
+        assert_eq!(
+            joined_tables(&unchanged),
+            vec!["lineitem", "orders", "customer"]
+        );
+
+        Ok(())
+    }
+
+    /// A Parquet scan of a single partition reading files of `sizes` megabytes
+    fn parquet_scan(sizes: &[usize]) -> Arc<dyn ExecutionPlan> {
+        let files = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| {
+                PartitionedFile::new(
+                    format!("/data/t/part-{}.parquet", i),
+                    (size * 1024 * 1024) as u64,
+                )
+            })
+            .collect();
+        Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: ObjectStoreUrl::local_filesystem(),
+                file_schema: Arc::new(Schema::new(vec![Field::new(
+                    "a",
+                    DataType::Int32,
+                    false,
+                )])),
+                file_groups: vec![files],
+                statistics: Statistics::default(),
+                projection: None,
+                limit: None,
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+        ))
+    }
+
+    /// Megabytes read by each task of the Parquet scan of `plan`
+    fn scan_task_sizes(plan: &Arc<dyn ExecutionPlan>) -> Vec<usize> {
+        match plan.as_any().downcast_ref::<ParquetExec>() {
+            Some(scan) => scan
+                .base_config()
+                .file_groups
+                .iter()
+                .map(|group| group.iter().map(file_bytes).sum::<usize>() / (1024 * 1024))
+                .collect(),
+            None => scan_task_sizes(&plan.children()[0]),
+        }
+    }
+
+    #[test]
+    fn split_scan_tasks_by_size() -> Result<(), BallistaError> {
+        let options = ScanTaskOptions {
+            target_partition_bytes: 64 * 1024 * 1024,
+            max_files_per_partition: 100,
+        };
+        // A huge file is split into ranges
+        let plan = split_scan_tasks(parquet_scan(&[256]), &options)?;
+        assert_eq!(scan_task_sizes(&plan), vec![64, 64, 64, 64]);
+        let scan = downcast_exec!(plan, ParquetExec);
+        assert!(scan
+            .base_config()
+            .file_groups
+            .iter()
+            .flatten()
+            .all(|file| file.range.is_some()));
+
+        // Tiny files are packed together
+        let plan = split_scan_tasks(parquet_scan(&[1; 100]), &options)?;
+        assert_eq!(scan_task_sizes(&plan), vec![50, 50]);
+
+        let mut sizes = vec![192];
+        sizes.extend([1; 64]);
+        let plan = split_scan_tasks(parquet_scan(&sizes), &options)?;
+        assert_eq!(scan_task_sizes(&plan), vec![64, 64, 64, 64]);
+
+        // No task reads more than the maximum number of files
+        let options = ScanTaskOptions {
+            max_files_per_partition: 10,
+            ..options
+        };
+        let plan = split_scan_tasks(parquet_scan(&[1; 100]), &options)?;
+        assert_eq!(scan_task_sizes(&plan), vec![10; 10]);
+
+        // A sort of a single partition relies on the scan not being split
+        let scan = parquet_scan(&[256]);
+        let sort_expr = vec![PhysicalSortExpr {
+            expr: col("a", &scan.schema())?,
+            options: SortOptions::default(),
+        }];
+        let plan =
+            split_scan_tasks(Arc::new(SortExec::try_new(sort_expr, scan)?), &options)?;
+        assert_eq!(scan_task_sizes(&plan), vec![256]);
+        assert_eq!(1, plan.output_partitioning().partition_count());
+        Ok(())
+    }
+
+    #[test]
+    fn partial_aggregation_groups_percent() {
+        let column = |distinct_count, min, max| ColumnStatistics {
+            null_count: None,
+            max_value: max,
+            min_value: min,
+            distinct_count,
+        };
+        let statistics = Statistics {
+            num_rows: Some(1000),
+            total_byte_size: None,
+            column_statistics: Some(vec![
+                column(Some(5), None, None),
+                column(Some(40), None, None),
+                column(
+                    None,
+                    Some(ScalarValue::Int32(Some(1))),
+                    Some(ScalarValue::Int32(Some(1_000_000))),
+                ),
+            ]),
+            is_exact: false,
+        };
+        assert_eq!(Some(0), groups_percent(&statistics, &[0]));
+        assert_eq!(Some(4), groups_percent(&statistics, &[1]));
+        assert_eq!(Some(20), groups_percent(&statistics, &[0, 1]));
+        // there are at most as many groups as rows
+        assert_eq!(Some(100), groups_percent(&statistics, &[0, 1, 1]));
+        // the range of a column does not bound its distinct values, which may be sparse
+        assert_eq!(None, groups_percent(&statistics, &[0, 2]));
+        assert_eq!(
+            None,
+            groups_percent(
+                &Statistics {
+                    num_rows: None,
+                    ..statistics
+                },
+                &[0]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn roundtrip_serde_window() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let df = ctx
+            .sql(
+                "select l_returnflag,
+                    sum(l_extendedprice) over (partition by l_returnflag order by l_shipdate) as running_price,
+                    row_number() over (partition by l_returnflag order by l_shipdate desc) as rn
+            from lineitem",
+            )
+            .await?;
+
+        let plan = df.to_logical_plan()?;
+        let plan = ctx.optimize(&plan)?;
+        let plan = ctx.create_physical_plan(&plan).await?;
+
+        let mut planner = DistributedPlanner::new();
+        let job_uuid = Uuid::new_v4();
+        let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+
+        let projection = stages[1].children()[0].clone();
+        let window = projection.children()[0].clone();
+        let window_serde = roundtrip_operator(window.clone())?;
+
+        let window = downcast_exec!(window, WindowAggExec);
+        let window_serde = downcast_exec!(window_serde, WindowAggExec);
+
+        assert_eq!(format!("{:?}", window), format!("{:?}", window_serde));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn distributed_subquery_plans() -> Result<(), BallistaError> {
+        let ctx = datafusion_test_context("testdata").await?;
+
+        let queries = [
+            // correlated EXISTS, decorrelated into a semi join
+            "select o_orderkey from orders
+            where exists (select * from lineitem where l_orderkey = o_orderkey)",
+            // uncorrelated IN, decorrelated into a semi join
+            "select o_orderkey from orders
+            where o_orderkey not in (select l_orderkey from lineitem where l_returnflag = 'R')",
+            // uncorrelated scalar subquery, rewritten into a cross join
+            "select l_orderkey from lineitem
+            where l_extendedprice > (select avg(l_extendedprice) from lineitem)",
+            // common table expression
+            "with returned as (select l_orderkey, l_extendedprice from lineitem where l_returnflag = 'R')
+            select l_orderkey, sum(l_extendedprice) from returned group by l_orderkey",
+        ];
+
+        let codec: BallistaCodec<LogicalPlanNode, PhysicalPlanNode> =
+            BallistaCodec::default();
+        for sql in queries {
+            let plan = ctx.sql(sql).await?.to_logical_plan()?;
+
+            // the plan must survive the trip from the client to the scheduler
+            let logical_proto = LogicalPlanNode::try_from_logical_plan(
+                &plan,
+                codec.logical_extension_codec(),
+            )?;
+            let plan = logical_proto
+                .try_into_logical_plan(&ctx, codec.logical_extension_codec())?;
+
+            let plan = ctx.optimize(&plan)?;
+            let plan = ctx.create_physical_plan(&plan).await?;
+
+            let mut planner = DistributedPlanner::new();
+            let job_uuid = Uuid::new_v4();
+            let stages = planner.plan_query_stages(&job_uuid.to_string(), plan)?;
+            assert!(stages.len() > 1, "expected a multi-stage plan for {}", sql);
+
+            for stage in stages {
+                let stage: Arc<dyn ExecutionPlan> = stage;
+                let stage_serde = roundtrip_operator(stage.clone())?;
+                assert_eq!(
+                    displayable(stage.as_ref()).indent().to_string(),
+                    displayable(stage_serde.as_ref()).indent().to_string()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn roundtrip_serde_aggregate() -> Result<(), BallistaError> {
         let ctx = datafusion_test_context("testdata").await?;
@@ -0,0 +1,458 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Persistent history of the performance of queries.
+//!
+//! Every finished job produces a [QueryRun] with its duration, the resources it used and
+//! the timings and output sizes of its stages. Runs are grouped by the fingerprint of
+//! the logical plan of their query, the `plan_fingerprint` of the audit log, so that the
+//! runs of a repeated query can be compared:
+//!
+//! ```text
+//! <fingerprint>/<submitted_at>-<job_id>.json
+//! ```
+//!
+//! The fingerprint of a running job is kept in memory until it finishes, so a job which
+//! is still running when the scheduler restarts has no run in the history.
+
+use crate::api::model::job_status_name;
+use crate::audit::plan_fingerprint;
+use crate::state::execution_graph::{timestamp_millis, ExecutionGraph};
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::object_store_registry::ObjectStoreFactories;
+use ballista_core::serde::protobuf::JobStatus;
+use datafusion::logical_plan::LogicalPlan;
+use futures::TryStreamExt;
+use log::warn;
+use object_store::path::Path;
+use object_store::ObjectStore;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Ratio of the duration of the latest run of a query to the median duration of its
+/// earlier runs above which the latest run is a regression
+const REGRESSION_RATIO: f64 = 1.5;
+
+/// Number of earlier completed runs needed to detect a regression
+const MIN_BASELINE_RUNS: usize = 3;
+
+/// Number of latest runs of a query its resource needs are predicted from
+const PREDICTION_RUNS: usize = 20;
+
+/// Maximum number of runs of a query read at once
+pub const MAX_RUNS: usize = 1000;
+
+/// A finished job of a query
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueryRun {
+    pub job_id: String,
+    pub plan_fingerprint: String,
+    /// Time the job was submitted, in milliseconds since the epoch
+    pub submitted_at: u64,
+    /// Time the job finished, in milliseconds since the epoch
+    pub finished_at: u64,
+    pub status: String,
+    /// Total run time of the task attempts, in milliseconds
+    pub slot_millis: u64,
+    /// Bytes written by the tasks of the stages whose output is read by other stages
+    pub bytes_shuffled: u64,
+    /// Bytes read from storage by the scans of the tasks
+    pub bytes_scanned: u64,
+    /// Stages of the job, empty if it failed during planning
+    pub stages: Vec<StageRun>,
+}
+
+impl QueryRun {
+    /// Milliseconds from the submission of the job until it finished
+    pub fn duration(&self) -> u64 {
+        self.finished_at.saturating_sub(self.submitted_at)
+    }
+
+    pub fn completed(&self) -> bool {
+        self.status == "Completed"
+    }
+
+    /// The average number of tasks the job ran at once, from the launch of its first
+    /// task until its last task finished, up to the partitions of its largest stage
+    pub fn parallelism(&self) -> Option<u64> {
+        let start = self.stages.iter().filter_map(|s| s.start_time).min()?;
+        let end = self.stages.iter().filter_map(|s| s.end_time).max()?;
+        let max_partitions = self.stages.iter().map(|s| s.partitions).max()? as u64;
+        let busy = end.saturating_sub(start).max(1);
+        let parallelism = (self.slot_millis + busy - 1) / busy;
+        Some(parallelism.clamp(1, max_partitions.max(1)))
+    }
+
+    fn new(
+        job_id: &str,
+        plan_fingerprint: String,
+        submitted_at: u64,
+        status: &JobStatus,
+        graph: Option<&ExecutionGraph>,
+    ) -> Self {
+        let usage = graph
+            .map(|graph| graph.usage())
+            .cloned()
+            .unwrap_or_default();
+        let mut stages: Vec<StageRun> = graph
+            .map(|graph| {
+                graph
+                    .stages
+                    .keys()
+                    .map(|id| StageRun::new(graph, *id))
+                    .collect()
+            })
+            .unwrap_or_default();
+        stages.sort_by_key(|stage| stage.stage_id);
+        Self {
+            job_id: job_id.to_owned(),
+            plan_fingerprint,
+            submitted_at,
+            finished_at: timestamp_millis(),
+            status: job_status_name(status).0.to_owned(),
+            slot_millis: usage.slot_millis,
+            bytes_shuffled: usage.bytes_shuffled,
+            bytes_scanned: usage.bytes_scanned,
+            stages,
+        }
+    }
+}
+
+/// A stage of a finished job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageRun {
+    pub stage_id: usize,
+    pub partitions: usize,
+    /// Time the first task of the stage was launched, in milliseconds since the epoch
+    pub start_time: Option<u64>,
+    /// Time the last task of the stage finished, in milliseconds since the epoch
+    pub end_time: Option<u64>,
+    /// Rows written by the tasks of the stage
+    pub output_rows: Option<u64>,
+    /// Bytes written by the tasks of the stage
+    pub output_bytes: Option<u64>,
+}
+
+impl StageRun {
+    fn new(graph: &ExecutionGraph, stage_id: usize) -> Self {
+        let stage = &graph.stages[&stage_id];
+        let stats = graph.stage_output_stats(stage_id);
+        Self {
+            stage_id,
+            partitions: stage.partitions,
+            start_time: stage.start_time,
+            end_time: stage
+                .task_infos
+                .iter()
+                .filter_map(|info| info.end_time)
+                .max(),
+            output_rows: stats.as_ref().and_then(|stats| stats.num_rows()),
+            output_bytes: stats.as_ref().and_then(|stats| stats.num_bytes()),
+        }
+    }
+
+    /// Milliseconds from the launch of the first task of the stage until its last task
+    /// finished
+    pub fn duration(&self) -> Option<u64> {
+        Some(self.end_time?.saturating_sub(self.start_time?))
+    }
+}
+
+/// Statistics of the completed runs of a query
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QueryRunStats {
+    /// Number of completed runs
+    pub completed_runs: usize,
+    /// Median duration of the completed runs, in milliseconds
+    pub median_duration: Option<u64>,
+    /// Median total run time of the task attempts of the completed runs, in
+    /// milliseconds
+    pub median_slot_millis: Option<u64>,
+    /// Median bytes shuffled by the completed runs
+    pub median_bytes_shuffled: Option<u64>,
+    /// Median number of tasks the completed runs ran at once, which is the number of
+    /// task slots the query is recommended to run on
+    pub recommended_task_slots: Option<u64>,
+    /// Duration of the latest completed run, in milliseconds
+    pub latest_duration: Option<u64>,
+    /// Whether the latest completed run took more than 1.5 times the median duration of
+    /// the earlier ones. At least 3 earlier runs are needed.
+    pub regression: bool,
+}
+
+impl QueryRunStats {
+    /// Statistics of `runs`, in the order they were submitted
+    pub fn new(runs: &[QueryRun]) -> Self {
+        let completed: Vec<&QueryRun> =
+            runs.iter().filter(|run| run.completed()).collect();
+        let latest_duration = completed.last().map(|run| run.duration());
+        let regression = match completed.split_last() {
+            Some((latest, earlier)) if earlier.len() >= MIN_BASELINE_RUNS => {
+                let baseline = median(earlier.iter().map(|run| run.duration())).unwrap();
+                latest.duration() as f64 > baseline as f64 * REGRESSION_RATIO
+            }
+            _ => false,
+        };
+        Self {
+            completed_runs: completed.len(),
+            median_duration: median(completed.iter().map(|run| run.duration())),
+            median_slot_millis: median(completed.iter().map(|run| run.slot_millis)),
+            median_bytes_shuffled: median(completed.iter().map(|run| run.bytes_shuffled)),
+            recommended_task_slots: median(
+                completed.iter().filter_map(|run| run.parallelism()),
+            ),
+            latest_duration,
+            regression,
+        }
+    }
+}
+
+fn median(values: impl Iterator<Item = u64>) -> Option<u64> {
+    let mut values: Vec<u64> = values.collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied()
+}
+
+/// Reads and writes the runs of queries in an [ObjectStore]
+pub struct QueryHistory {
+    store: Arc<dyn ObjectStore>,
+    prefix: Path,
+    /// Plan fingerprints and submission times of running jobs, by job ID
+    running: Mutex<HashMap<String, (String, u64)>>,
+}
+
+impl QueryHistory {
+    pub fn new(store: Arc<dyn ObjectStore>, prefix: Path) -> Self {
+        Self {
+            store,
+            prefix,
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A history in `location`, a local directory or the URL of a directory in one of
+    /// the object stores of the cluster, see [ObjectStoreFactories::store_at]
+    pub fn try_new(location: &str, stores: &ObjectStoreFactories) -> Result<Self> {
+        let (store, prefix) = stores.store_at(location)?;
+        Ok(Self::new(store, prefix))
+    }
+
+    /// Remember the fingerprint of the plan of newly submitted job `job_id`, returning
+    /// it
+    pub fn submitted(&self, job_id: &str, plan: &LogicalPlan) -> String {
+        let fingerprint = plan_fingerprint(plan);
+        self.running
+            .lock()
+            .insert(job_id.to_owned(), (fingerprint.clone(), timestamp_millis()));
+        fingerprint
+    }
+
+    /// Write the run of finished job `job_id`. Jobs failing during planning do not have
+    /// an execution graph.
+    pub async fn finished(
+        &self,
+        job_id: &str,
+        status: &JobStatus,
+        graph: Option<&ExecutionGraph>,
+    ) {
+        let running = self.running.lock().remove(job_id);
+        let (fingerprint, submitted_at) = match running {
+            Some(running) => running,
+            None => return,
+        };
+        let run = QueryRun::new(job_id, fingerprint, submitted_at, status, graph);
+        if let Err(e) = self.write(&run).await {
+            warn!("Failed to write the run of job {}: {:?}", job_id, e);
+        }
+    }
+
+    /// Statistics of the latest runs of the query with plan fingerprint `fingerprint`,
+    /// predicting the resources it needs
+    pub async fn predict(&self, fingerprint: &str) -> Result<QueryRunStats> {
+        let runs = self.runs(fingerprint, PREDICTION_RUNS).await?;
+        Ok(QueryRunStats::new(&runs))
+    }
+
+    async fn write(&self, run: &QueryRun) -> Result<()> {
+        // Zero-padded submission times make listings chronological
+        let file_name = format!("{:013}-{}.json", run.submitted_at, run.job_id);
+        let path = self
+            .prefix
+            .child(run.plan_fingerprint.as_str())
+            .child(file_name.as_str());
+        let bytes = serde_json::to_vec(run).map_err(|e| {
+            BallistaError::Internal(format!("Could not serialize query run: {}", e))
+        })?;
+        self.store
+            .put(&path, bytes.into())
+            .await
+            .map_err(to_ballista_error)
+    }
+
+    /// The latest `limit` runs of the query with plan fingerprint `fingerprint`, up to
+    /// [MAX_RUNS], in the order they were submitted
+    pub async fn runs(&self, fingerprint: &str, limit: usize) -> Result<Vec<QueryRun>> {
+        let prefix = self.prefix.child(fingerprint);
+        let mut locations: Vec<Path> = self
+            .store
+            .list(Some(&prefix))
+            .await
+            .map_err(to_ballista_error)?
+            .map_ok(|meta| meta.location)
+            .try_collect()
+            .await
+            .map_err(to_ballista_error)?;
+        locations.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        let skip = locations.len().saturating_sub(limit.min(MAX_RUNS));
+
+        let mut runs = Vec::with_capacity(locations.len() - skip);
+        for location in locations.into_iter().skip(skip) {
+            let bytes = self
+                .store
+                .get(&location)
+                .await
+                .map_err(to_ballista_error)?
+                .bytes()
+                .await
+                .map_err(to_ballista_error)?;
+            runs.push(serde_json::from_slice(&bytes).map_err(|e| {
+                BallistaError::Internal(format!(
+                    "Could not deserialize {}: {}",
+                    location, e
+                ))
+            })?);
+        }
+        Ok(runs)
+    }
+}
+
+fn to_ballista_error(e: object_store::Error) -> BallistaError {
+    BallistaError::General(format!("Query history error: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ballista_core::serde::protobuf::{job_status, CompletedJob, FailedJob};
+    use datafusion::logical_plan::LogicalPlanBuilder;
+    use object_store::memory::InMemory;
+
+    fn run(duration: u64, status: &str) -> QueryRun {
+        QueryRun {
+            job_id: "job".to_owned(),
+            plan_fingerprint: "fingerprint".to_owned(),
+            submitted_at: 1_000,
+            finished_at: 1_000 + duration,
+            status: status.to_owned(),
+            slot_millis: duration * 4,
+            bytes_shuffled: 0,
+            bytes_scanned: 0,
+            stages: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn write_and_read_runs() -> Result<()> {
+        let history = QueryHistory::new(Arc::new(InMemory::new()), Path::from("history"));
+        let plan = LogicalPlanBuilder::empty(false).build()?;
+        let fingerprint = history.submitted("job-1", &plan);
+        assert_eq!(history.submitted("job-2", &plan), fingerprint);
+
+        let failed = JobStatus {
+            status: Some(job_status::Status::Failed(FailedJob {
+                error: "boom".to_owned(),
+                kind: 0,
+            })),
+        };
+        history.finished("job-1", &failed, None).await;
+        let completed = JobStatus {
+            status: Some(job_status::Status::Completed(CompletedJob::default())),
+        };
+        history.finished("job-2", &completed, None).await;
+        // Jobs submitted before a restart have no run
+        history.finished("job-3", &completed, None).await;
+
+        let runs = history.runs(&fingerprint, MAX_RUNS).await?;
+        let statuses: Vec<(&str, &str)> = runs
+            .iter()
+            .map(|run| (run.job_id.as_str(), run.status.as_str()))
+            .collect();
+        assert_eq!(statuses, vec![("job-1", "Failed"), ("job-2", "Completed")]);
+        let latest = history.runs(&fingerprint, 1).await?;
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].job_id, "job-2");
+        assert!(history.runs("unknown", MAX_RUNS).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn detect_regressions() {
+        let mut runs = vec![
+            run(100, "Completed"),
+            run(120, "Completed"),
+            run(5, "Failed"),
+            run(110, "Completed"),
+        ];
+        let stats = QueryRunStats::new(&runs);
+        assert_eq!(stats.completed_runs, 3);
+        assert_eq!(stats.median_duration, Some(110));
+        assert_eq!(stats.median_slot_millis, Some(440));
+        // Not enough earlier runs
+        assert!(!stats.regression);
+
+        runs.push(run(160, "Completed"));
+        assert!(!QueryRunStats::new(&runs).regression);
+        runs.push(run(500, "Completed"));
+        let stats = QueryRunStats::new(&runs);
+        assert_eq!(stats.latest_duration, Some(500));
+        assert!(stats.regression);
+    }
+
+    #[test]
+    fn recommend_task_slots() {
+        let stage = |partitions, start_time, end_time| StageRun {
+            stage_id: 0,
+            partitions,
+            start_time: Some(start_time),
+            end_time: Some(end_time),
+            output_rows: None,
+            output_bytes: None,
+        };
+        // 4 tasks at once on average between the first launch and the last end
+        let mut first = run(1_000, "Completed");
+        first.slot_millis = 3_200;
+        first.stages = vec![stage(8, 1_100, 1_600), stage(2, 1_600, 1_900)];
+        assert_eq!(first.parallelism(), Some(4));
+        // Bounded by the partitions of the largest stage
+        let mut second = run(1_000, "Completed");
+        second.slot_millis = 100_000;
+        second.stages = vec![stage(8, 1_000, 2_000)];
+        assert_eq!(second.parallelism(), Some(8));
+        let mut third = run(1_000, "Completed");
+        third.slot_millis = 2_000;
+        third.stages = vec![stage(8, 1_000, 2_000)];
+        // Runs failing during planning have no stages
+        let failed = run(10, "Failed");
+        assert_eq!(failed.parallelism(), None);
+
+        let stats = QueryRunStats::new(&[first, second, third, failed]);
+        assert_eq!(stats.recommended_task_slots, Some(4));
+        assert_eq!(QueryRunStats::new(&[]).recommended_task_slots, None);
+    }
+}
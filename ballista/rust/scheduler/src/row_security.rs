@@ -0,0 +1,441 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Row-level security enforced by the scheduler.
+//!
+//! A [RowPolicy] restricts the rows of the tables at a location an identity reads to
+//! those matching a filter, as if the identity queried a view of the tables rather than
+//! the tables themselves. Once a location has a policy, identities without a policy for
+//! it are denied access to it. Policies for identity `*` apply to every identity, and
+//! rows matching any of the policies which apply to an identity are read.
+//!
+//! [RowLevelSecurity] is a [PlanRewriter] adding the filters of the policies above the
+//! scans of every job. Tables are matched by their location rather than their name, as
+//! clients may register the same data under any name: a policy applies to the scans of
+//! the tables at its location, below it, or containing it. Identities are the principals
+//! clients are authenticated as, so the scheduler only loads policies when client
+//! authentication is enabled.
+
+use crate::plan_rewrite::{PlanRewriter, RewriteContext};
+use crate::sql_expr::parse_sql_expr;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::table_format::{location_url, scanned_table_location};
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::{
+    Expr, ExpressionVisitor, LogicalPlan, LogicalPlanBuilder, Recursion,
+};
+use datafusion::optimizer::utils::from_plan;
+
+/// Identity of the policies which apply to every identity
+pub const ANY_IDENTITY: &str = "*";
+
+/// The rows of the tables at a location an identity may read
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowPolicy {
+    /// URL of the location, as returned by [location_url]
+    pub location: String,
+    /// The identity the policy applies to, or [ANY_IDENTITY]
+    pub identity: String,
+    /// The rows of the table the identity reads
+    pub filter: Expr,
+}
+
+impl RowPolicy {
+    /// A policy letting `identity` read the rows of the tables at `location` matching
+    /// `filter`, a SQL expression over their columns
+    pub fn try_new(location: &str, identity: &str, filter: &str) -> Result<Self> {
+        Ok(Self {
+            location: location_url(location),
+            identity: identity.to_owned(),
+            filter: parse_sql_expr(filter)?,
+        })
+    }
+
+    fn applies_to(&self, identity: Option<&str>) -> bool {
+        self.identity == ANY_IDENTITY || Some(self.identity.as_str()) == identity
+    }
+}
+
+/// Rewrites the plans of jobs to only read the rows their identity may read
+#[derive(Debug, Default)]
+pub struct RowLevelSecurity {
+    policies: Vec<RowPolicy>,
+}
+
+impl RowLevelSecurity {
+    pub fn new(policies: Vec<RowPolicy>) -> Self {
+        Self { policies }
+    }
+
+    /// Load the policies of the file at `path`, one `<location> <identity> <filter>`
+    /// entry per line
+    pub fn try_from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            BallistaError::General(format!(
+                "Could not read row policies file {}: {}",
+                path, e
+            ))
+        })?;
+        Self::parse(&contents)
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut policies = vec![];
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let policy = match split_fields(line, 3).as_slice() {
+                [location, identity, filter] => {
+                    RowPolicy::try_new(location, identity, filter)
+                }
+                _ => Err(BallistaError::General("Missing filter".to_owned())),
+            }
+            .map_err(|e| {
+                BallistaError::General(format!(
+                    "Invalid row policy on line {}: {}",
+                    index + 1,
+                    e
+                ))
+            })?;
+            policies.push(policy);
+        }
+        Ok(Self::new(policies))
+    }
+
+    /// The filter of the rows `identity` reads of `table`, a table at `locations`, `None`
+    /// if the locations have no policy. Fails if the identity may not read the table.
+    fn filter(
+        &self,
+        table: &str,
+        locations: &[String],
+        identity: Option<&str>,
+    ) -> Result<Option<Expr>> {
+        let mut policies = self
+            .policies
+            .iter()
+            .filter(|policy| overlaps(&policy.location, locations))
+            .peekable();
+        if policies.peek().is_none() {
+            return Ok(None);
+        }
+        policies
+            .filter(|policy| policy.applies_to(identity))
+            .map(|policy| policy.filter.clone())
+            .reduce(Expr::or)
+            .map(Some)
+            .ok_or_else(|| {
+                BallistaError::General(format!(
+                    "{} is not allowed to read table {}",
+                    identity.unwrap_or("Anonymous client"),
+                    table
+                ))
+            })
+    }
+
+    /// `plan` with the filters of the policies the identity of the job reads its tables
+    /// with
+    fn secure(
+        &self,
+        plan: &LogicalPlan,
+        context: &RewriteContext,
+    ) -> Result<LogicalPlan> {
+        if let LogicalPlan::TableScan(scan) = plan {
+            let locations = scan_locations(plan, context);
+            let filter =
+                match self.filter(&scan.table_name, &locations, context.identity)? {
+                    Some(filter) => filter,
+                    None => return Ok(plan.clone()),
+                };
+            // The filter may read columns the scan does not project, so the whole
+            // table is scanned and the columns of the scan projected again
+            let columns: Vec<Expr> = scan
+                .projected_schema
+                .fields()
+                .iter()
+                .map(|field| Expr::Column(field.qualified_column()))
+                .collect();
+            return Ok(LogicalPlanBuilder::scan(
+                &scan.table_name,
+                scan.source.clone(),
+                None,
+            )?
+            .filter(filter)?
+            .project(columns)?
+            .build()?);
+        }
+
+        for expr in plan.expressions() {
+            for subquery in subqueries(&expr)? {
+                let secured = |scan: &LogicalPlan| {
+                    self.has_policies(&scan_locations(scan, context))
+                };
+                if let Some(table) = find_scanned_table(&subquery, &secured)? {
+                    return Err(BallistaError::NotImplemented(format!(
+                        "Table {} has row policies and cannot be read in a subquery",
+                        table
+                    )));
+                }
+            }
+        }
+        let inputs = plan
+            .inputs()
+            .into_iter()
+            .map(|input| self.secure(input, context))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(from_plan(plan, &plan.expressions(), &inputs)?)
+    }
+
+    fn has_policies(&self, locations: &[String]) -> bool {
+        self.policies
+            .iter()
+            .any(|policy| overlaps(&policy.location, locations))
+    }
+}
+
+impl PlanRewriter for RowLevelSecurity {
+    fn name(&self) -> &str {
+        "row_level_security"
+    }
+
+    fn rewrite_logical_plan(
+        &self,
+        plan: LogicalPlan,
+        context: &RewriteContext,
+    ) -> Result<LogicalPlan> {
+        self.secure(&plan, context)
+    }
+}
+
+/// The first `count - 1` whitespace separated fields of `line`, followed by the rest of
+/// the line
+pub(crate) fn split_fields(line: &str, count: usize) -> Vec<&str> {
+    let mut fields = vec![];
+    let mut rest = line.trim();
+    while fields.len() + 1 < count {
+        match rest.split_once(char::is_whitespace) {
+            Some((field, tail)) => {
+                fields.push(field);
+                rest = tail.trim_start();
+            }
+            None => break,
+        }
+    }
+    if !rest.is_empty() {
+        fields.push(rest);
+    }
+    fields
+}
+
+/// The URLs of the locations of the table scanned by `plan`: that of its provider, or
+/// else the locations the job scans tables under its name at. Listing tables may have
+/// several locations.
+pub(crate) fn scan_locations(
+    plan: &LogicalPlan,
+    context: &RewriteContext,
+) -> Vec<String> {
+    let locations: Vec<String> = match scanned_table_location(plan) {
+        Some((_, location)) => vec![location],
+        None => match plan {
+            LogicalPlan::TableScan(scan) => context
+                .table_locations
+                .get(&scan.table_name)
+                .map(|locations| locations.iter().cloned().collect())
+                .unwrap_or_default(),
+            _ => vec![],
+        },
+    };
+    locations
+        .iter()
+        .flat_map(|location| location.split(','))
+        .map(location_url)
+        .collect()
+}
+
+/// Whether a policy of `location` applies to a table at `locations`, some of which is at,
+/// below or above the location
+pub(crate) fn overlaps(location: &str, locations: &[String]) -> bool {
+    locations
+        .iter()
+        .any(|other| other.starts_with(location) || location.starts_with(other.as_str()))
+}
+
+/// A table whose scan matches `matches` which `plan` or its subqueries scan, if any
+pub(crate) fn find_scanned_table(
+    plan: &LogicalPlan,
+    matches: &dyn Fn(&LogicalPlan) -> bool,
+) -> Result<Option<String>> {
+    if let LogicalPlan::TableScan(scan) = plan {
+        return Ok(matches(plan).then(|| scan.table_name.clone()));
+    }
+    for expr in plan.expressions() {
+        for subquery in subqueries(&expr)? {
+            if let Some(table) = find_scanned_table(&subquery, matches)? {
+                return Ok(Some(table));
+            }
+        }
+    }
+    for input in plan.inputs() {
+        if let Some(table) = find_scanned_table(input, matches)? {
+            return Ok(Some(table));
+        }
+    }
+    Ok(None)
+}
+
+/// The plans of the subqueries of `expr`
+pub(crate) fn subqueries(expr: &Expr) -> Result<Vec<LogicalPlan>> {
+    struct SubqueryCollector(Vec<LogicalPlan>);
+
+    impl ExpressionVisitor for SubqueryCollector {
+        fn pre_visit(
+            mut self,
+            expr: &Expr,
+        ) -> std::result::Result<Recursion<Self>, DataFusionError> {
+            match expr {
+                Expr::Exists { subquery, .. }
+                | Expr::InSubquery { subquery, .. }
+                | Expr::ScalarSubquery(subquery) => {
+                    self.0.push(subquery.subquery.as_ref().clone())
+                }
+                _ => {}
+            }
+            Ok(Recursion::Continue(self))
+        }
+    }
+
+    Ok(expr.accept(SubqueryCollector(vec![]))?.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::empty::EmptyTable;
+    use datafusion::logical_plan::{col, lit, provider_as_source};
+    use datafusion::prelude::SessionContext;
+    use std::collections::{BTreeSet, HashMap};
+    use std::sync::Arc;
+
+    fn scan(table: &str, projection: Option<Vec<usize>>) -> LogicalPlan {
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("region", DataType::Utf8, false),
+        ]);
+        let source = provider_as_source(Arc::new(EmptyTable::new(Arc::new(schema))));
+        LogicalPlanBuilder::scan(table, source, projection)
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    /// The filter below the projection of the secured scan `plan`
+    fn filter_of(plan: &LogicalPlan) -> Option<&Expr> {
+        match plan {
+            LogicalPlan::Projection(projection) => match projection.input.as_ref() {
+                LogicalPlan::Filter(filter) => Some(&filter.predicate),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_policies() -> Result<()> {
+        let security = RowLevelSecurity::parse(
+            "# location identity filter\n\
+             s3://bucket/orders  alice    region = 'EU'\n\
+             s3://bucket/orders  *        region IN ('US', 'CA')\n",
+        )?;
+        assert_eq!(
+            security.policies,
+            vec![
+                RowPolicy {
+                    location: "s3://bucket/orders/".to_owned(),
+                    identity: "alice".to_owned(),
+                    filter: col("region").eq(lit("EU")),
+                },
+                RowPolicy {
+                    location: "s3://bucket/orders/".to_owned(),
+                    identity: ANY_IDENTITY.to_owned(),
+                    filter: col("region").in_list(vec![lit("US"), lit("CA")], false),
+                },
+            ]
+        );
+        assert!(RowLevelSecurity::parse("s3://bucket/orders alice").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn filter_scans_of_secured_tables() -> Result<()> {
+        let security = RowLevelSecurity::new(vec![
+            RowPolicy::try_new("s3://bucket/orders", "alice", "region = 'EU'")?,
+            RowPolicy::try_new("s3://bucket/orders/", "bob", "region = 'US'")?,
+            RowPolicy::try_new("s3://bucket", "bob", "id < 100")?,
+        ]);
+        let session_ctx = SessionContext::new();
+        // The same data is registered as orders and as a copy of them
+        let table_locations: HashMap<String, BTreeSet<String>> = [
+            ("orders", "s3://bucket/orders"),
+            ("copy", "s3://bucket/orders/2022"),
+            ("customers", "s3://other/customers"),
+        ]
+        .into_iter()
+        .map(|(table, location)| {
+            (table.to_owned(), BTreeSet::from([location.to_owned()]))
+        })
+        .collect();
+        let context = |identity| RewriteContext {
+            job_id: "job",
+            identity,
+            table_locations: &table_locations,
+            session_ctx: &session_ctx,
+        };
+
+        // Only the id column is projected, though the filter reads the region
+        let plan =
+            security.secure(&scan("orders", Some(vec![0])), &context(Some("alice")))?;
+        assert_eq!(filter_of(&plan), Some(&col("orders.region").eq(lit("EU"))));
+        assert_eq!(plan.schema(), scan("orders", Some(vec![0])).schema());
+
+        let plan = security.secure(&scan("copy", None), &context(Some("bob")))?;
+        assert_eq!(
+            filter_of(&plan),
+            Some(
+                &col("copy.region")
+                    .eq(lit("US"))
+                    .or(col("copy.id").lt(lit(100_i64)))
+            )
+        );
+
+        // Identities without a policy for the location can not read it
+        assert!(security
+            .secure(&scan("orders", None), &context(Some("carol")))
+            .is_err());
+        assert!(security
+            .secure(&scan("copy", None), &context(None))
+            .is_err());
+
+        // Tables at locations without policies are read by everyone
+        assert!(matches!(
+            security.secure(&scan("customers", None), &context(None))?,
+            LogicalPlan::TableScan(_)
+        ));
+        Ok(())
+    }
+}
@@ -15,12 +15,20 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use crate::planner::{ScanTaskOptions, ShufflePartitionOptions};
 use crate::state::executor_manager::ExecutorReservation;
+use crate::state::plan_cache::PlanKey;
+
+use ballista_core::config::BallistaConfig;
+use ballista_core::serde::protobuf::{FailedJob, KeyValuePair};
+use ballista_core::table_format::TableCommit;
 
 use datafusion::logical_plan::LogicalPlan;
 
 use datafusion::prelude::SessionContext;
+use std::collections::{BTreeSet, HashMap};
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub enum SchedulerServerEvent {
@@ -28,6 +36,44 @@ pub enum SchedulerServerEvent {
     Offer(Vec<ExecutorReservation>),
 }
 
+/// Location and partition columns of the Parquet files a job writes its output to
+#[derive(Clone, Debug)]
+pub struct ParquetOutput {
+    pub path: String,
+    pub partition_by: Vec<String>,
+    /// Whether the output is repartitioned by the values of the `partition_by` columns
+    /// before it is written, rather than each task writing the partitions it reads
+    pub repartition: bool,
+    /// Parquet field IDs of the columns of the files by the dotted path of each column
+    pub field_ids: HashMap<String, i32>,
+    /// Commits the files to the table they rewrite for a DELETE, UPDATE or OPTIMIZE
+    /// TABLE statement, instead of a manifest
+    pub table_commit: Option<Arc<dyn TableCommit>>,
+}
+
+/// How many times a failed job is run again, and how long it waits before each retry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JobRetryPolicy {
+    pub max_retries: usize,
+    /// Wait before the first retry, doubled for each following one
+    pub backoff: Duration,
+}
+
+impl JobRetryPolicy {
+    /// The policy set by `config`, `None` if failed jobs are not retried
+    pub fn from_config(config: &BallistaConfig) -> Option<Self> {
+        (config.job_retries() > 0).then(|| Self {
+            max_retries: config.job_retries(),
+            backoff: Duration::from_millis(config.job_retry_backoff_ms() as u64),
+        })
+    }
+
+    /// Wait before retry number `retry`, starting from 1
+    pub fn backoff(&self, retry: usize) -> Duration {
+        self.backoff * 2u32.saturating_pow(retry.saturating_sub(1) as u32)
+    }
+}
+
 #[derive(Clone)]
 pub enum QueryStageSchedulerEvent {
     JobQueued {
@@ -35,8 +81,52 @@ pub enum QueryStageSchedulerEvent {
         session_id: String,
         session_ctx: Arc<SessionContext>,
         plan: Box<LogicalPlan>,
+        /// Trace context of the client which submitted the query
+        trace_context: Vec<KeyValuePair>,
+        /// The principal which submitted the job, if clients are authenticated
+        identity: Option<String>,
+        /// The locations of the tables scanned by the job by the name they are scanned
+        /// as, including those of external tables which were resolved
+        table_locations: HashMap<String, BTreeSet<String>>,
+        /// Where the job writes its output to, if it is not returned to the client
+        parquet_output: Option<ParquetOutput>,
+        /// Key of the cached plans of the SQL query of the job, if they may be cached
+        plan_key: Option<PlanKey>,
+        /// Whether the job may complete with the cached result of an earlier job of the
+        /// same plan, and cache its own result
+        cache_result: bool,
+        /// How the partition counts of the shuffles of the job are chosen from the
+        /// size of their input, if they are
+        shuffle_partitions: Option<ShufflePartitionOptions>,
+        /// How the files read by the scans of the job are grouped into tasks, if they
+        /// are regrouped rather than read as their tables planned
+        scan_tasks: Option<ScanTaskOptions>,
+        /// Percentage of the rows of its input above which the estimated groups of a
+        /// partial aggregation make it skipped, if partial aggregations may be skipped
+        partial_aggregation_max_groups: Option<usize>,
+        /// Bytes of the Bloom filters of the keys of the build sides of the joins of the
+        /// job filtering their probe sides, if they are
+        bloom_filter_bytes: Option<usize>,
+        /// Priority of the fetches of the shuffle partitions of the job, higher first
+        job_priority: u32,
+        /// Whether the shuffle partitions of the job are copied to a second executor
+        replicate_shuffles: bool,
+        /// How the job is run again if it fails, if it is
+        retry: Option<JobRetryPolicy>,
     },
     JobSubmitted(String),
     JobFinished(String),
-    JobFailed(String, String),
+    JobFailed(String, FailedJob),
+}
+
+impl QueryStageSchedulerEvent {
+    /// ID of the job this event refers to
+    pub fn job_id(&self) -> &str {
+        match self {
+            QueryStageSchedulerEvent::JobQueued { job_id, .. }
+            | QueryStageSchedulerEvent::JobSubmitted(job_id)
+            | QueryStageSchedulerEvent::JobFinished(job_id)
+            | QueryStageSchedulerEvent::JobFailed(job_id, _) => job_id,
+        }
+    }
 }
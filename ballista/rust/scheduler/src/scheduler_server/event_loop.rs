@@ -16,6 +16,7 @@
 // under the License.
 
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
 use log::{error, info};
@@ -37,7 +38,7 @@ pub(crate) struct SchedulerServerEventAction<
     T: 'static + AsLogicalPlan,
     U: 'static + AsExecutionPlan,
 > {
-    state: Arc<SchedulerState<T, U>>,
+    pub(crate) state: Arc<SchedulerState<T, U>>,
 }
 
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
@@ -48,7 +49,8 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
     }
 
     /// Process reservations which are offered. The basic process is
-    /// 1. Attempt to fill the offered reservations with available tasks
+    /// 1. Attempt to fill the offered reservations with available tasks, except those of draining
+    ///    executors
     /// 2. For any reservation that filled, launch the assigned task on the executor.
     /// 3. For any reservations that could not be filled, cancel the reservation (i.e. return the
     ///    task slot back to the pool of available task slots).
@@ -56,10 +58,30 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
     /// NOTE Error handling in this method is very important. No matter what we need to ensure
     /// that unfilled reservations are cancelled or else they could become permanently "invisible"
     /// to the scheduler.
-    async fn offer_reservation(
+    pub(crate) async fn offer_reservation(
         &self,
         reservations: Vec<ExecutorReservation>,
     ) -> Result<Option<SchedulerServerEvent>> {
+        let start = Instant::now();
+        let draining = self
+            .state
+            .executor_manager
+            .get_draining_executors()
+            .await
+            .unwrap_or_else(|e| {
+                error!("Failed to get draining executors: {:?}", e);
+                Default::default()
+            });
+        let (reservations, drained): (Vec<_>, Vec<_>) = reservations
+            .into_iter()
+            .partition(|reservation| !draining.contains(&reservation.executor_id));
+        if !drained.is_empty() {
+            self.state
+                .executor_manager
+                .cancel_reservations(drained)
+                .await?;
+        }
+
         let (free_list, pending_tasks) = match self
             .state
             .task_manager
@@ -75,10 +97,20 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
                         .await
                     {
                         Ok(executor) => {
-                            if let Err(e) =
-                                self.state.task_manager.launch_task(&executor, task).await
+                            let replica =
+                                self.state.replica_executor(&executor.id, &task).await;
+                            if let Err(e) = self
+                                .state
+                                .task_manager
+                                .launch_task(&executor, task.clone(), replica)
+                                .await
                             {
                                 error!("Failed to launch new task: {:?}", e);
+                                if let Err(e) =
+                                    self.state.task_manager.return_task(task).await
+                                {
+                                    error!("Failed to return task: {:?}", e);
+                                }
                                 unassigned_reservations.push(
                                     ExecutorReservation::new_free(executor_id.clone()),
                                 );
@@ -86,6 +118,11 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
                         }
                         Err(e) => {
                             error!("Failed to launch new task, could not get executor metadata: {:?}", e);
+                            if let Err(e) =
+                                self.state.task_manager.return_task(task).await
+                            {
+                                error!("Failed to return task: {:?}", e);
+                            }
                             unassigned_reservations
                                 .push(ExecutorReservation::new_free(executor_id.clone()));
                         }
@@ -98,6 +135,10 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
                 (reservations, 0)
             }
         };
+        self.state
+            .metrics
+            .scheduling_latency
+            .observe(start.elapsed().as_secs_f64());
 
         dbg!(free_list.clone());
         dbg!(pending_tasks);
@@ -232,19 +273,51 @@ mod test {
         // Create 4 jobs so we have four pending tasks
         state
             .task_manager
-            .submit_job("job-1", session_ctx.session_id().as_str(), plan.clone())
+            .submit_job(
+                "job-1",
+                session_ctx.session_id().as_str(),
+                plan.clone(),
+                vec![],
+                None,
+                0,
+                false,
+            )
             .await?;
         state
             .task_manager
-            .submit_job("job-2", session_ctx.session_id().as_str(), plan.clone())
+            .submit_job(
+                "job-2",
+                session_ctx.session_id().as_str(),
+                plan.clone(),
+                vec![],
+                None,
+                0,
+                false,
+            )
             .await?;
         state
             .task_manager
-            .submit_job("job-3", session_ctx.session_id().as_str(), plan.clone())
+            .submit_job(
+                "job-3",
+                session_ctx.session_id().as_str(),
+                plan.clone(),
+                vec![],
+                None,
+                0,
+                false,
+            )
             .await?;
         state
             .task_manager
-            .submit_job("job-4", session_ctx.session_id().as_str(), plan.clone())
+            .submit_job(
+                "job-4",
+                session_ctx.session_id().as_str(),
+                plan.clone(),
+                vec![],
+                None,
+                0,
+                false,
+            )
             .await?;
 
         let executors = test_executors(1, 4);
@@ -292,7 +365,15 @@ mod test {
         // Create a job
         state
             .task_manager
-            .submit_job("job-1", session_ctx.session_id().as_str(), plan.clone())
+            .submit_job(
+                "job-1",
+                session_ctx.session_id().as_str(),
+                plan.clone(),
+                vec![],
+                None,
+                0,
+                false,
+            )
             .await?;
 
         let executors = test_executors(1, 4);
@@ -325,7 +406,14 @@ mod test {
                     status: Some(task_status::Status::Completed(CompletedTask {
                         executor_id: "executor-1".to_string(),
                         partitions,
+                        bytes_scanned: 0,
+                        spilled_bytes: 0,
+                        spill_files: 0,
+                        bloom_filter: vec![],
+                        replica: None,
+                        operator_metrics: vec![],
                     })),
+                    attempt: 0,
                 }],
             )
             .await?;
@@ -378,6 +466,8 @@ mod test {
                     specification: ExecutorSpecification {
                         task_slots: slots_per_executor,
                     },
+                    labels: Default::default(),
+                    protocol: Default::default(),
                 },
                 ExecutorData {
                     executor_id: format!("executor-{}", i),
@@ -15,20 +15,37 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use ballista_core::config::{BallistaConfig, TaskSchedulingPolicy};
+use ballista_core::auth::{bearer_token, AUTHORIZATION_HEADER};
+use ballista_core::config::{
+    BallistaConfig, TaskSchedulingPolicy, BALLISTA_OBJECT_STORE_CREDENTIALS,
+};
+use ballista_core::object_store_registry::ObjectStoreCredentials;
+use ballista_core::protocol::{check_compatible_version, ProtocolInfo};
 
 use ballista_core::serde::protobuf::execute_query_params::{OptionalSessionId, Query};
 
 use ballista_core::serde::protobuf::executor_registration::OptionalHost;
 use ballista_core::serde::protobuf::scheduler_grpc_server::SchedulerGrpc;
 use ballista_core::serde::protobuf::{
+    job_status, CancelJobParams, CancelJobResult, CloseSessionParams, CloseSessionResult,
     ExecuteQueryParams, ExecuteQueryResult, ExecutorHeartbeat, GetFileMetadataParams,
-    GetFileMetadataResult, GetJobStatusParams, GetJobStatusResult, HeartBeatParams,
-    HeartBeatResult, PollWorkParams, PollWorkResult, RegisterExecutorParams,
-    RegisterExecutorResult, UpdateTaskStatusParams, UpdateTaskStatusResult,
+    GetFileMetadataResult, GetJobStatusParams, GetJobStatusResult, GetTableParams,
+    GetTableResult, HeartBeatParams, HeartBeatResult, KeyValuePair, PollWorkParams,
+    PollWorkResult, RegisterExecutorParams, RegisterExecutorResult, RegisterTableParams,
+    RegisterTableResult, ResourceHint, UncacheTableParams, UncacheTableResult,
+    UpdateTaskStatusParams, UpdateTaskStatusResult,
 };
 use ballista_core::serde::scheduler::{ExecutorData, ExecutorMetadata};
 use ballista_core::serde::AsExecutionPlan;
+use ballista_core::table_format::cache::{
+    cached_table_key, temporary_table_location, CACHE,
+};
+use ballista_core::table_format::time_travel::sql_as_of;
+use ballista_core::table_format::{
+    map_scan_locations, resolve_external_scans, resolve_table_rewrite,
+    scanned_table_location, scanned_table_locations, RewriteMode,
+};
+use ballista_core::tls::certificate_valid_for;
 
 use object_store::{local::LocalFileSystem, path::Path, ObjectStore};
 
@@ -40,16 +57,268 @@ use futures::TryStreamExt;
 use log::{debug, error, info, trace, warn};
 
 // use http_body::Body;
+use std::collections::HashMap;
 use std::convert::TryInto;
 use std::ops::Deref;
 use std::sync::Arc;
 
 use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::metadata::MetadataMap;
 use tonic::{Request, Response, Status};
 
-use crate::scheduler_server::event::{QueryStageSchedulerEvent, SchedulerServerEvent};
+use crate::audit::AuditRecord;
+use crate::auth::{scanned_tables, Operation, Principal};
+use crate::planner::{
+    approximate_distinct_counts, reorder_joins, ScanTaskOptions, ShufflePartitionOptions,
+};
+use crate::query_history::QueryHistory;
+use crate::scheduler_server::event::{
+    JobRetryPolicy, ParquetOutput, QueryStageSchedulerEvent, SchedulerServerEvent,
+};
 use crate::scheduler_server::SchedulerServer;
+use crate::sql_expr::parse_sql_expr;
 use crate::state::executor_manager::ExecutorReservation;
+use crate::state::job_timeouts::Timeouts;
+use crate::state::plan_cache::{is_volatile, PlanKey};
+use crate::state::table_catalog;
+use crate::state::table_statistics::analyze_plan;
+
+impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T, U> {
+    /// Reject requests from executors which did not present a verified client
+    /// certificate, if executor authentication is enabled. Returns the DER encoded
+    /// certificate, to be checked against the executor the request is made on behalf of
+    /// with [SchedulerServer::authorize_executor].
+    fn authenticate_executor<R>(
+        &self,
+        request: &Request<R>,
+    ) -> Result<Option<Vec<u8>>, Status> {
+        if !self.authenticate_executors {
+            return Ok(None);
+        }
+        let cert = request
+            .peer_certs()
+            .and_then(|certs| certs.first().map(|cert| cert.get_ref().to_vec()));
+        if cert.is_none() {
+            warn!(
+                "Rejecting executor request from {:?} without a client certificate",
+                request.remote_addr()
+            );
+            return Err(Status::unauthenticated(
+                "Executors must present a client certificate",
+            ));
+        }
+        Ok(cert)
+    }
+
+    /// Reject requests on behalf of `executor_id` unless the client certificate `cert`
+    /// was issued for the host the executor is registered with, and for `host` if the
+    /// request (re)registers the executor, so that executors can not act as each other.
+    async fn authorize_executor(
+        &self,
+        cert: Option<Vec<u8>>,
+        executor_id: &str,
+        host: Option<&str>,
+    ) -> Result<(), Status> {
+        let cert = match cert {
+            Some(cert) => cert,
+            None => return Ok(()),
+        };
+        let registered = self
+            .state
+            .executor_manager
+            .get_executor_metadata(executor_id)
+            .await
+            .ok()
+            .map(|metadata| metadata.host)
+            .filter(|host| !host.is_empty());
+        for host in registered.iter().map(String::as_str).chain(host) {
+            if !certificate_valid_for(&cert, host) {
+                warn!(
+                    "Rejecting request for executor {} as its certificate was not issued for {}",
+                    executor_id, host
+                );
+                return Err(Status::permission_denied(format!(
+                    "The client certificate was not issued for executor host {}",
+                    host
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Register a new epoch of the executor, fencing off its previous registration if it
+    /// has one. The tasks of the previous registration are rescheduled, as their statuses
+    /// are rejected from now on, and so are the tasks whose output it kept.
+    async fn fence_executor(&self, executor_id: &str) -> Result<u64, Status> {
+        let epoch = self
+            .state
+            .executor_manager
+            .register_epoch(executor_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Could not register executor epoch: {}", e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        let reset = self
+            .state
+            .task_manager
+            .reset_executor_tasks(executor_id)
+            .await
+            .map_err(|e| {
+                let msg = format!(
+                    "Could not reschedule the tasks of executor {}: {}",
+                    executor_id, e
+                );
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        if reset > 0 {
+            warn!(
+                "Executor {} registered again, rescheduled {} tasks of its previous registration",
+                executor_id, reset
+            );
+        }
+        Ok(epoch)
+    }
+
+    /// The principal a client request was made by, `None` if client authentication is
+    /// disabled
+    async fn authenticate_client(
+        &self,
+        metadata: &MetadataMap,
+    ) -> Result<Option<Principal>, Status> {
+        match &self.state.client_auth {
+            Some(auth) => auth.authenticate(bearer_token(metadata)).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Check that `principal` may perform `operation`
+    async fn authorize_client(
+        &self,
+        principal: Option<&Principal>,
+        operation: Operation<'_>,
+    ) -> Result<(), Status> {
+        match (&self.state.client_auth, principal) {
+            (Some(auth), Some(principal)) => auth.authorize(principal, &operation).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that session `session_id` belongs to `principal`, unless it has no owner
+    /// as clients were not authenticated when it was created
+    async fn check_session_owner(
+        &self,
+        principal: Option<&Principal>,
+        session_id: &str,
+    ) -> Result<(), Status> {
+        let principal = match principal {
+            Some(principal) => principal,
+            None => return Ok(()),
+        };
+        let owner = self
+            .state
+            .session_manager
+            .session_owner(session_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Error reading session {}: {:?}", session_id, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        match owner {
+            Some(owner) if owner != principal.name => {
+                warn!(
+                    "Denied session {} of {} to {}",
+                    session_id, owner, principal.name
+                );
+                Err(Status::permission_denied(format!(
+                    "Session {} does not belong to {}",
+                    session_id, principal.name
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that job `job_id` was submitted by `principal`, unless it has no owner as
+    /// clients were not authenticated when it was submitted
+    pub(crate) async fn check_job_owner(
+        &self,
+        principal: Option<&Principal>,
+        job_id: &str,
+    ) -> Result<(), Status> {
+        let principal = match principal {
+            Some(principal) => principal,
+            None => return Ok(()),
+        };
+        let owner = self
+            .state
+            .task_manager
+            .job_owner(job_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Error reading owner of job {}: {:?}", job_id, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        match owner {
+            Some(owner) if owner != principal.name => {
+                warn!("Denied job {} of {} to {}", job_id, owner, principal.name);
+                Err(Status::permission_denied(format!(
+                    "Job {} does not belong to {}",
+                    job_id, principal.name
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The resources the query with plan fingerprint `fingerprint` is predicted to need
+    /// from its earlier runs in `pool`, `None` if it never completed
+    async fn resource_hint(
+        &self,
+        query_history: &QueryHistory,
+        fingerprint: String,
+        pool: &str,
+    ) -> Option<ResourceHint> {
+        let stats = match query_history.predict(&fingerprint).await {
+            Ok(stats) if stats.completed_runs > 0 => stats,
+            Ok(_) => return None,
+            Err(e) => {
+                warn!(
+                    "Failed to read the history of query {}: {:?}",
+                    fingerprint, e
+                );
+                return None;
+            }
+        };
+        let task_slots = stats.recommended_task_slots.unwrap_or_default();
+        let (executors, total_slots) =
+            match self.state.executor_manager.pool_capacity(pool).await {
+                Ok(capacity) => capacity,
+                Err(e) => {
+                    warn!("Failed to count the task slots of the executors: {:?}", e);
+                    (0, 0)
+                }
+            };
+        let total_slots = total_slots as u64;
+        // Executors with the average task slots of the alive ones of the pool
+        let recommended_executors = if total_slots > 0 {
+            (task_slots * executors as u64 + total_slots - 1) / total_slots
+        } else {
+            0
+        };
+        Some(ResourceHint {
+            plan_fingerprint: fingerprint,
+            completed_runs: stats.completed_runs as u32,
+            recommended_task_slots: task_slots as u32,
+            recommended_executors: recommended_executors as u32,
+            expected_duration_millis: stats.median_duration.unwrap_or_default(),
+        })
+    }
+}
 
 #[tonic::async_trait]
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
@@ -59,6 +328,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<PollWorkParams>,
     ) -> Result<Response<PollWorkResult>, Status> {
+        let cert = self.authenticate_executor(&request)?;
         if let TaskSchedulingPolicy::PushStaged = self.policy {
             error!("Poll work interface is not supported for push-based task scheduling");
             return Err(tonic::Status::failed_precondition(
@@ -70,6 +340,8 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
             metadata: Some(metadata),
             can_accept_task,
             task_status,
+            epoch,
+            task_progress,
         } = request.into_inner()
         {
             debug!("Received poll_work request for {:?}", metadata);
@@ -84,6 +356,29 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 port: metadata.port as u16,
                 grpc_port: metadata.grpc_port as u16,
                 specification: metadata.specification.unwrap().into(),
+                labels: metadata
+                    .labels
+                    .into_iter()
+                    .map(|pair| (pair.key, pair.value))
+                    .collect(),
+                protocol: metadata.protocol.into(),
+            };
+            check_executor_protocol(&metadata)?;
+            self.authorize_executor(cert, &metadata.id, Some(&metadata.host))
+                .await?;
+            // Executors poll without an epoch until they are given one
+            let epoch = if epoch == 0 {
+                self.fence_executor(&metadata.id).await?
+            } else {
+                self.state
+                    .executor_manager
+                    .check_epoch(&metadata.id, epoch)
+                    .await
+                    .map_err(|e| {
+                        warn!("{}", e);
+                        Status::failed_precondition(e.to_string())
+                    })?;
+                epoch
             };
             let executor_heartbeat = ExecutorHeartbeat {
                 executor_id: metadata.id.clone(),
@@ -92,6 +387,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     .expect("Time went backwards")
                     .as_secs(),
                 state: None,
+                version: 0,
             };
 
             self.state
@@ -114,6 +410,14 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     Status::internal(msg)
                 })?;
 
+            let task_status = self
+                .forward_task_statuses(
+                    &metadata.id,
+                    epoch,
+                    metadata.protocol.version,
+                    task_status,
+                )
+                .await;
             self.update_task_status(&metadata.id, task_status)
                 .await
                 .map_err(|e| {
@@ -125,8 +429,23 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     Status::internal(msg)
                 })?;
 
+            let kill_tasks = self
+                .state
+                .task_manager
+                .record_task_progress(&metadata.id, task_progress);
+
+            // A draining executor is not given new tasks
+            let draining =
+                match self.state.executor_manager.is_draining(&metadata.id).await {
+                    Ok(draining) => draining,
+                    Err(e) => {
+                        warn!("Failed to get draining executors: {:?}", e);
+                        false
+                    }
+                };
+
             // If executor can accept another task, try and find one.
-            let next_task = if can_accept_task {
+            let next_task = if can_accept_task && !draining {
                 let reservations =
                     vec![ExecutorReservation::new_free(metadata.id.clone())];
                 if let Ok((mut assignments, _, _)) = self
@@ -136,10 +455,22 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     .await
                 {
                     if let Some((_, task)) = assignments.pop() {
-                        match self.state.task_manager.prepare_task_definition(task) {
+                        let replica =
+                            self.state.replica_executor(&metadata.id, &task).await;
+                        match self.state.task_manager.prepare_task_definition(
+                            task.clone(),
+                            &metadata.protocol,
+                            replica,
+                        ) {
                             Ok(task_definition) => Some(task_definition),
                             Err(e) => {
                                 error!("Error preparing task definition: {:?}", e);
+                                // The task is left to the executors which can run it
+                                if let Err(e) =
+                                    self.state.task_manager.return_task(task).await
+                                {
+                                    error!("Failed to return task: {:?}", e);
+                                }
                                 None
                             }
                         }
@@ -153,7 +484,26 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 None
             };
 
-            Ok(Response::new(PollWorkResult { task: next_task }))
+            let remove_jobs = self
+                .state
+                .task_manager
+                .take_job_data_removals(&metadata.id)
+                .await
+                .unwrap_or_else(|e| {
+                    warn!(
+                        "Could not read the job data executor {} must remove: {:?}",
+                        metadata.id, e
+                    );
+                    vec![]
+                });
+
+            Ok(Response::new(PollWorkResult {
+                task: next_task,
+                protocol: Some(ProtocolInfo::current().into()),
+                epoch,
+                kill_tasks,
+                remove_jobs,
+            }))
         } else {
             warn!("Received invalid executor poll_work request");
             Err(Status::invalid_argument("Missing metadata in request"))
@@ -164,6 +514,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<RegisterExecutorParams>,
     ) -> Result<Response<RegisterExecutorResult>, Status> {
+        let cert = self.authenticate_executor(&request)?;
         let remote_addr = request.remote_addr();
         if let RegisterExecutorParams {
             metadata: Some(metadata),
@@ -181,12 +532,22 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 port: metadata.port as u16,
                 grpc_port: metadata.grpc_port as u16,
                 specification: metadata.specification.unwrap().into(),
+                labels: metadata
+                    .labels
+                    .into_iter()
+                    .map(|pair| (pair.key, pair.value))
+                    .collect(),
+                protocol: metadata.protocol.into(),
             };
+            check_executor_protocol(&metadata)?;
+            self.authorize_executor(cert, &metadata.id, Some(&metadata.host))
+                .await?;
             let executor_data = ExecutorData {
                 executor_id: metadata.id.clone(),
                 total_task_slots: metadata.specification.task_slots,
                 available_task_slots: metadata.specification.task_slots,
             };
+            let epoch = self.fence_executor(&metadata.id).await?;
 
             if let Ok(Some(sender)) =
                 self.event_loop.as_ref().map(|e| e.get_sender()).transpose()
@@ -213,7 +574,11 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     .unwrap();
             }
 
-            Ok(Response::new(RegisterExecutorResult { success: true }))
+            Ok(Response::new(RegisterExecutorResult {
+                success: true,
+                protocol: Some(ProtocolInfo::current().into()),
+                epoch,
+            }))
         } else {
             warn!("Received invalid register executor request");
             Err(Status::invalid_argument("Missing metadata in request"))
@@ -224,10 +589,37 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<HeartBeatParams>,
     ) -> Result<Response<HeartBeatResult>, Status> {
-        let HeartBeatParams { executor_id, state } = request.into_inner();
+        let cert = self.authenticate_executor(&request)?;
+        let HeartBeatParams {
+            executor_id,
+            state,
+            epoch,
+            task_progress,
+        } = request.into_inner();
+        self.authorize_executor(cert, &executor_id, None).await?;
 
         debug!("Received heart beat request for {:?}", executor_id);
         trace!("Related executor state is {:?}", state);
+        let lease_millis =
+            self.state.executor_manager.executor_lease().as_millis() as u64;
+        if let Err(e) = self
+            .state
+            .executor_manager
+            .check_epoch(&executor_id, epoch)
+            .await
+        {
+            // A stale executor is not brought back to life by its heartbeats
+            warn!("{}", e);
+            return Ok(Response::new(HeartBeatResult {
+                reregister: true,
+                lease_millis,
+                kill_tasks: vec![],
+            }));
+        }
+        let kill_tasks = self
+            .state
+            .task_manager
+            .record_task_progress(&executor_id, task_progress);
         let executor_heartbeat = ExecutorHeartbeat {
             executor_id,
             timestamp: SystemTime::now()
@@ -235,6 +627,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 .expect("Time went backwards")
                 .as_secs(),
             state,
+            version: 0,
         };
         self.state
             .executor_manager
@@ -245,23 +638,44 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 error!("{}", msg);
                 Status::internal(msg)
             })?;
-        Ok(Response::new(HeartBeatResult { reregister: false }))
+        Ok(Response::new(HeartBeatResult {
+            reregister: false,
+            lease_millis,
+            kill_tasks,
+        }))
     }
 
     async fn update_task_status(
         &self,
         request: Request<UpdateTaskStatusParams>,
     ) -> Result<Response<UpdateTaskStatusResult>, Status> {
+        let cert = self.authenticate_executor(&request)?;
         let UpdateTaskStatusParams {
             executor_id,
             task_status,
+            protocol_version,
+            epoch,
         } = request.into_inner();
+        self.authorize_executor(cert, &executor_id, None).await?;
+        check_compatible_version(protocol_version)
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        self.state
+            .executor_manager
+            .check_epoch(&executor_id, epoch)
+            .await
+            .map_err(|e| {
+                warn!("{}", e);
+                Status::failed_precondition(e.to_string())
+            })?;
 
         debug!(
             "Received task status update request for executor {:?}",
             executor_id
         );
 
+        let task_status = self
+            .forward_task_statuses(&executor_id, epoch, protocol_version, task_status)
+            .await;
         self.update_task_status(&executor_id, task_status)
             .await
             .map_err(|e| {
@@ -280,11 +694,17 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<GetFileMetadataParams>,
     ) -> Result<Response<GetFileMetadataResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
         // TODO support multiple object stores
         let obj_store: Arc<dyn ObjectStore> = Arc::new(LocalFileSystem::new());
         // TODO shouldn't this take a ListingOption object as input?
 
         let GetFileMetadataParams { path, file_type } = request.into_inner();
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::GetFileMetadata { path: &path },
+        )
+        .await?;
 
         let file_type: FileType = file_type.try_into().map_err(|e| {
             let msg = format!("Error reading request: {}", e);
@@ -335,13 +755,32 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<ExecuteQueryParams>,
     ) -> Result<Response<ExecuteQueryResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let remote_addr = request.remote_addr();
         let query_params = request.into_inner();
         if let ExecuteQueryParams {
             query: Some(query),
-            settings,
+            mut settings,
             optional_session_id,
+            trace_context,
+            job_id,
         } = query_params
         {
+            // Clients cannot choose the ID of their job, nor therefore its scheduler
+            if !job_id.is_empty() {
+                return Err(Status::invalid_argument(
+                    "Job IDs are generated by the scheduler",
+                ));
+            }
+            // The jobs submitted to a scheduler are owned by it
+            let job_id = self.state.task_manager.generate_local_job_id();
+            if self.state.shutting_down() {
+                return Err(Status::unavailable(
+                    "Scheduler is shutting down and does not accept new jobs",
+                ));
+            }
+            let credentials = take_object_store_credentials(&mut settings)?;
+
             // parse config
             let mut config_builder = BallistaConfig::builder();
             for kv_pair in &settings {
@@ -352,13 +791,61 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 error!("{}", msg);
                 Status::internal(msg)
             })?;
+            let parquet_output = config.write_parquet_path().map(|path| ParquetOutput {
+                path,
+                partition_by: config.write_parquet_partition_by(),
+                repartition: true,
+                field_ids: HashMap::new(),
+                table_commit: None,
+            });
+            let rewrite_table = config.rewrite_table();
+            if rewrite_table.is_some() {
+                if matches!(query, Query::Sql(_)) {
+                    return Err(Status::invalid_argument(
+                        "DELETE, UPDATE and OPTIMIZE TABLE statements must be submitted \
+                         as logical plans",
+                    ));
+                }
+                // Rewriters filtering or masking rows would change the rows written back
+                if !self.state.plan_rewriters.is_empty() {
+                    return Err(Status::failed_precondition(
+                        "DELETE, UPDATE and OPTIMIZE TABLE statements cannot be run by \
+                         schedulers with plan rewriters",
+                    ));
+                }
+            }
+            // Files are rewritten only if they may hold the rows changed by the
+            // statement, all of them if the scheduler cannot parse its predicate
+            let rewrite_filters = match config.rewrite_predicate() {
+                Some(predicate) => match parse_sql_expr(&predicate) {
+                    Ok(filter) => vec![filter],
+                    Err(e) => {
+                        debug!("Rewriting all the files of the table: {}", e);
+                        vec![]
+                    }
+                },
+                None => vec![],
+            };
+            let rewrite_mode = if config.rewrite_compact() {
+                RewriteMode::Compact(config.compact_target_file_bytes() as u64)
+            } else {
+                RewriteMode::Change(rewrite_filters)
+            };
+            let mut table_rewrite = None;
+            // Tables are identified by their location rather than by the names they are
+            // registered under by clients
+            let mut table_locations = HashMap::new();
+            let mut analyzed_table = None;
 
+            let owner = principal.as_ref().map(|principal| principal.name.as_str());
             let (session_id, session_ctx) = match optional_session_id {
                 Some(OptionalSessionId::SessionId(session_id)) => {
+                    self.check_session_owner(principal.as_ref(), &session_id)
+                        .await?;
                     let ctx = self
                         .state
                         .session_manager
-                        .update_session(&session_id, &config)
+                        .update_session(&session_id, &config, owner)
                         .await
                         .map_err(|e| {
                             Status::internal(format!(
@@ -372,7 +859,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     let ctx = self
                         .state
                         .session_manager
-                        .create_session(&config)
+                        .create_owned_session(&config, owner)
                         .await
                         .map_err(|e| {
                             Status::internal(format!(
@@ -385,33 +872,337 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                 }
             };
 
-            let plan = match query {
-                Query::LogicalPlan(message) => T::try_decode(message.as_slice())
-                    .and_then(|m| {
-                        m.try_into_logical_plan(
-                            session_ctx.deref(),
-                            self.codec.logical_extension_codec(),
-                        )
+            self.state
+                .object_store_factories
+                .register_stores(session_ctx.runtime_env().as_ref(), &credentials)
+                .map_err(|e| {
+                    Status::invalid_argument(format!(
+                        "Could not create object stores: {}",
+                        e
+                    ))
+                })?;
+            for (name, catalog) in &self.state.catalogs {
+                session_ctx.register_catalog(name, catalog.clone());
+            }
+            session_ctx.register_catalog(
+                table_catalog::CATALOG,
+                self.state.table_catalog.provider(
+                    self.state.table_formats.clone(),
+                    session_ctx.runtime_env(),
+                ),
+            );
+
+            let sql = match &query {
+                Query::Sql(sql) => Some(sql.clone()),
+                Query::LogicalPlan(_) => None,
+            };
+            // Repeated SQL queries are not planned again, unless their jobs configure
+            // their own object stores
+            let plan_key = match &query {
+                Query::Sql(sql)
+                    if self.state.plan_cache.is_enabled() && credentials.is_empty() =>
+                {
+                    Some(
+                        self.state
+                            .plan_cache
+                            .key(sql, &settings, &session_id, owner),
+                    )
+                }
+                _ => None,
+            };
+            let cached_plan = plan_key
+                .as_ref()
+                .and_then(|key| self.state.plan_cache.logical_plan(key));
+            if plan_key.is_some() {
+                if cached_plan.is_some() {
+                    self.state.metrics.plan_cache_hits.inc();
+                } else {
+                    self.state.metrics.plan_cache_misses.inc();
+                }
+            }
+            let plan = match cached_plan {
+                Some(plan) => plan,
+                None => {
+                    let plan = match query {
+                        Query::LogicalPlan(message) => {
+                            let plan = T::try_decode(message.as_slice())
+                                .and_then(|m| {
+                                    m.try_into_logical_plan(
+                                        session_ctx.deref(),
+                                        self.codec.logical_extension_codec(),
+                                    )
+                                })
+                                .map_err(|e| {
+                                    let msg = format!(
+                                        "Could not parse logical plan protobuf: {}",
+                                        e
+                                    );
+                                    error!("{}", msg);
+                                    Status::internal(msg)
+                                })?;
+                            // Cached tables are those of the user, and of the session
+                            let plan = map_scan_locations(&plan, CACHE, &|location| {
+                                cached_table_key(owner, &session_id, location)
+                            })
+                            .map_err(|e| Status::internal(e.to_string()))?;
+                            table_locations = scanned_table_locations(&plan)
+                                .map_err(|e| Status::internal(e.to_string()))?;
+                            if config.analyze_table() {
+                                analyzed_table = scanned_table_location(&plan);
+                            }
+                            // Scans of external tables are resolved to the files to scan here
+                            let resolved =
+                                match &rewrite_table {
+                                    Some(table) => resolve_table_rewrite(
+                                        &plan,
+                                        table,
+                                        &rewrite_mode,
+                                        &self.state.table_formats,
+                                        session_ctx.runtime_env(),
+                                    )
+                                    .await
+                                    .map(|(plan, rewrite)| {
+                                        table_rewrite = Some(rewrite);
+                                        plan
+                                    }),
+                                    None => {
+                                        resolve_external_scans(
+                                            &plan,
+                                            &self.state.table_formats,
+                                            session_ctx.runtime_env(),
+                                        )
+                                        .await
+                                    }
+                                };
+                            resolved.map_err(|e| {
+                                let msg =
+                                    format!("Could not resolve external tables: {}", e);
+                                error!("{}", msg);
+                                Status::invalid_argument(msg)
+                            })?
+                        }
+                        Query::Sql(sql) => {
+                            sql_as_of(&session_ctx, &sql, &self.state.table_formats)
+                                .await
+                                .and_then(|df| df.to_logical_plan())
+                                .map_err(|e| {
+                                    let msg = format!("Error parsing SQL: {}", e);
+                                    error!("{}", msg);
+                                    Status::internal(msg)
+                                })?
+                        }
+                    };
+                    let plan = if config.approximate_count_distinct() {
+                        approximate_distinct_counts(&plan).map_err(|e| {
+                            let msg = format!("Could not plan COUNT(DISTINCT): {}", e);
+                            error!("{}", msg);
+                            Status::internal(msg)
+                        })?
+                    } else {
+                        plan
+                    };
+                    if let Some(key) = &plan_key {
+                        self.state
+                            .plan_cache
+                            .insert_logical_plan(key.clone(), &plan);
+                    }
+                    plan
+                }
+            };
+            for (table, locations) in scanned_table_locations(&plan)
+                .map_err(|e| Status::internal(e.to_string()))?
+            {
+                table_locations.entry(table).or_default().extend(locations);
+            }
+            // The statistics of analyzed tables are estimated by the scheduler from the
+            // aggregates of their rows computed by the job, rather than sent by clients
+            let (plan, table_analysis) = if config.analyze_table() {
+                let (table, location) = analyzed_table.ok_or_else(|| {
+                    Status::invalid_argument(
+                        "ANALYZE TABLE jobs must only scan the analyzed table",
+                    )
+                })?;
+                self.authorize_client(
+                    principal.as_ref(),
+                    Operation::UpdateTableStatistics { table: &location },
+                )
+                .await?;
+                let sample_percent = config.analyze_sample_percent();
+                if !(sample_percent > 0.0 && sample_percent <= 100.0) {
+                    return Err(Status::invalid_argument(format!(
+                        "Cannot sample {} percent of table {}",
+                        sample_percent, table
+                    )));
+                }
+                let (plan, analysis) =
+                    analyze_plan(&plan, &location, sample_percent / 100.0).map_err(
+                        |e| {
+                            let msg = format!("Could not analyze table {}: {}", table, e);
+                            error!("{}", msg);
+                            Status::internal(msg)
+                        },
+                    )?;
+                (plan, Some(analysis))
+            } else {
+                (plan, None)
+            };
+            // The output of DELETE, UPDATE and OPTIMIZE TABLE statements replaces the
+            // files they rewrite
+            let parquet_output = match table_rewrite {
+                Some(rewrite) => Some(ParquetOutput {
+                    path: rewrite.location,
+                    partition_by: rewrite.partition_by,
+                    repartition: rewrite.repartition,
+                    field_ids: rewrite.field_ids,
+                    table_commit: Some(rewrite.commit),
+                }),
+                None => parquet_output,
+            };
+            // Joins are reordered every time, by the latest statistics of their tables
+            let plan = if config.join_reordering() {
+                let mut statistics = HashMap::new();
+                // Tables scanned under the same name at different locations have no
+                // statistics, as their joins could not be told apart
+                for (table, location) in table_locations
+                    .iter()
+                    .filter(|(_, locations)| locations.len() == 1)
+                    .flat_map(|(table, locations)| {
+                        locations.iter().map(move |l| (table, l))
                     })
-                    .map_err(|e| {
-                        let msg = format!("Could not parse logical plan protobuf: {}", e);
+                {
+                    match self.state.table_statistics.get(location).await {
+                        Ok(Some(table_statistics)) => {
+                            statistics.insert(table.clone(), table_statistics);
+                        }
+                        Ok(None) => {}
+                        Err(e) => warn!(
+                            "Could not get statistics of table {} at {}: {:?}",
+                            table, location, e
+                        ),
+                    }
+                }
+                if statistics.is_empty() {
+                    plan
+                } else {
+                    reorder_joins(&plan, &statistics).map_err(|e| {
+                        let msg = format!("Could not reorder joins: {}", e);
                         error!("{}", msg);
                         Status::internal(msg)
-                    })?,
-                Query::Sql(sql) => session_ctx
-                    .sql(&sql)
+                    })?
+                }
+            } else {
+                plan
+            };
+
+            if principal.is_some() {
+                let tables = scanned_tables(&plan);
+                self.authorize_client(
+                    principal.as_ref(),
+                    Operation::SubmitQuery {
+                        sql: sql.as_deref(),
+                        tables: &tables,
+                    },
+                )
+                .await?;
+            }
+
+            if let Some(url) = config.job_callback_url() {
+                self.state
+                    .job_notifier
+                    .register(&job_id, url)
+                    .map_err(|e| Status::invalid_argument(e.to_string()))?;
+            }
+
+            debug!("Received plan for execution: {:?}", plan);
+
+            // Results read with the credentials of a client are not shared with others,
+            // and the results of volatile functions are not reused
+            let cache_result = config.cache_results()
+                && config.cache_table().is_none()
+                && !config.job_in_memory_results()
+                && !config.analyze_table()
+                && credentials.is_empty()
+                && matches!(is_volatile(&plan), Ok(false));
+            self.state
+                .task_manager
+                .register_object_stores(&job_id, credentials);
+            if let Some(name) = config.cache_table() {
+                let location = if config.cache_temporary() {
+                    temporary_table_location(&session_id, &name)
+                } else {
+                    name
+                };
+                let key = cached_table_key(
+                    principal.as_ref().map(|principal| principal.name.as_str()),
+                    &session_id,
+                    &location,
+                );
+                self.state.task_manager.register_cached_table(&job_id, key);
+            }
+            if let Some(analysis) = table_analysis {
+                self.state
+                    .task_manager
+                    .register_table_analysis(&job_id, analysis);
+            }
+            // The output of the jobs analyzing tables is also read by the scheduler
+            if config.job_in_memory_results()
+                && config.cache_table().is_none()
+                && !config.analyze_table()
+            {
+                self.state.task_manager.register_in_memory_results(&job_id);
+            }
+            if let Some(timeouts) = Timeouts::from_config(&config) {
+                self.state.task_manager.register_timeouts(&job_id, timeouts);
+            }
+            self.state.task_manager.register_spill_warning_ratio(
+                &job_id,
+                config.stage_spill_warning_ratio(),
+            );
+            let user = principal.map(|principal| principal.name);
+            if let Some(user) = &user {
+                self.state
+                    .task_manager
+                    .save_job_owner(&job_id, user)
                     .await
-                    .and_then(|df| df.to_logical_plan())
                     .map_err(|e| {
-                        let msg = format!("Error parsing SQL: {}", e);
+                        let msg =
+                            format!("Error saving owner of job {}: {:?}", job_id, e);
                         error!("{}", msg);
                         Status::internal(msg)
-                    })?,
-            };
-
-            debug!("Received plan for execution: {:?}", plan);
+                    })?;
+            }
+            let tenant = user.clone().or_else(|| config.user());
+            self.state
+                .task_manager
+                .register_tenant(&job_id, tenant.clone());
+            let pool = self
+                .state
+                .tenant_pools
+                .job_pool(config.job_pool(), tenant.as_deref())
+                .map_err(|e| Status::permission_denied(e.to_string()))?;
+            debug!("Job {} runs in executor pool {}", job_id, pool);
+            self.state.task_manager.register_pool(&job_id, pool.clone());
 
-            let job_id = self.state.task_manager.generate_job_id();
+            if let Some(audit_log) = &self.state.audit_log {
+                audit_log
+                    .submitted(AuditRecord::submitted(
+                        &job_id,
+                        &session_id,
+                        user,
+                        remote_addr.map(|addr| addr.to_string()),
+                        sql,
+                        &plan,
+                    ))
+                    .await;
+            }
+            let resource_hint = match &self.state.query_history {
+                Some(query_history) => {
+                    let fingerprint = query_history.submitted(&job_id, &plan);
+                    info!("Job {} has plan fingerprint {}", job_id, fingerprint);
+                    self.resource_hint(query_history, fingerprint, &pool).await
+                }
+                None => None,
+            };
 
             self.state
                 .task_manager
@@ -424,6 +1215,33 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     Status::internal(msg)
                 })?;
 
+            let reserved_slots = config.job_reserved_slots();
+            if reserved_slots > 0 {
+                // Slots failing to be counted are granted once other reservations
+                // are released
+                let total_slots = self
+                    .state
+                    .executor_manager
+                    .total_task_slots_in_pool(&pool)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Failed to count the task slots of the executors: {:?}", e);
+                        0
+                    });
+                if self.state.task_manager.reserve_job_slots(
+                    &job_id,
+                    reserved_slots,
+                    total_slots,
+                ) {
+                    info!("Reserved {} task slots for job {}", reserved_slots, job_id);
+                } else {
+                    info!(
+                        "Job {} waits for {} task slots to be reserved",
+                        job_id, reserved_slots
+                    );
+                }
+            }
+
             let query_stage_event_sender =
                 self.query_stage_event_loop.get_sender().map_err(|e| {
                     Status::internal(format!(
@@ -438,6 +1256,27 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     session_id: session_id.clone(),
                     session_ctx,
                     plan: Box::new(plan),
+                    trace_context,
+                    identity: user,
+                    table_locations,
+                    parquet_output,
+                    plan_key,
+                    cache_result,
+                    shuffle_partitions: ShufflePartitionOptions::from_config(&config),
+                    scan_tasks: Some(ScanTaskOptions::from_config(&config)),
+                    partial_aggregation_max_groups: Some(
+                        config.partial_aggregation_max_groups_percent(),
+                    )
+                    .filter(|percent| *percent > 0),
+                    bloom_filter_bytes: config
+                        .join_bloom_filters()
+                        .then(|| config.join_bloom_filter_bytes()),
+                    job_priority: self.state.tenant_priorities.job_priority(
+                        config.job_priority().min(u32::MAX as usize) as u32,
+                        tenant.as_deref(),
+                    ),
+                    replicate_shuffles: config.shuffle_replicate(),
+                    retry: JobRetryPolicy::from_config(&config),
                 })
                 .await
                 .map_err(|e| {
@@ -448,13 +1287,22 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
                     Status::internal(msg)
                 })?;
 
-            Ok(Response::new(ExecuteQueryResult { job_id, session_id }))
+            Ok(Response::new(ExecuteQueryResult {
+                job_id,
+                session_id,
+                resource_hint,
+            }))
         } else if let ExecuteQueryParams {
             query: None,
-            settings,
+            mut settings,
             optional_session_id: None,
+            ..
         } = query_params
         {
+            // Credentials only apply to the job they are sent with
+            take_object_store_credentials(&mut settings)?;
+            self.authorize_client(principal.as_ref(), Operation::CreateSession)
+                .await?;
             // parse config for new session
             let mut config_builder = BallistaConfig::builder();
             for kv_pair in &settings {
@@ -468,7 +1316,10 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
             let session = self
                 .state
                 .session_manager
-                .create_session(&config)
+                .create_owned_session(
+                    &config,
+                    principal.as_ref().map(|principal| principal.name.as_str()),
+                )
                 .await
                 .map_err(|e| {
                     Status::internal(format!(
@@ -480,6 +1331,7 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
             Ok(Response::new(ExecuteQueryResult {
                 job_id: "NA".to_owned(),
                 session_id: session.session_id(),
+                resource_hint: None,
             }))
         } else {
             Err(Status::internal("Error parsing request"))
@@ -490,7 +1342,14 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
         &self,
         request: Request<GetJobStatusParams>,
     ) -> Result<Response<GetJobStatusResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
         let job_id = request.into_inner().job_id;
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::GetJobStatus { job_id: &job_id },
+        )
+        .await?;
+        self.check_job_owner(principal.as_ref(), &job_id).await?;
         debug!("Received get_job_status request for job {}", job_id);
         match self.state.task_manager.get_job_status(&job_id).await {
             Ok(status) => Ok(Response::new(GetJobStatusResult { status })),
@@ -501,27 +1360,222 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerGrpc
             }
         }
     }
+
+    async fn uncache_table(
+        &self,
+        request: Request<UncacheTableParams>,
+    ) -> Result<Response<UncacheTableResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let UncacheTableParams { name, session_id } = request.into_inner();
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::UncacheTable { name: &name },
+        )
+        .await?;
+        self.check_session_owner(principal.as_ref(), &session_id)
+            .await?;
+        let key = cached_table_key(
+            principal.as_ref().map(|principal| principal.name.as_str()),
+            &session_id,
+            &name,
+        );
+        let table = self.state.table_cache.remove(&key).await.map_err(|e| {
+            let msg = format!("Error uncaching table {}: {:?}", name, e);
+            error!("{}", msg);
+            Status::internal(msg)
+        })?;
+        let found = table.is_some();
+        if let Some(table) = table {
+            info!("Uncaching table {} cached by job {}", name, table.job_id);
+            self.state.remove_cached_table_data(&table).await;
+        }
+        Ok(Response::new(UncacheTableResult { found }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<CancelJobParams>,
+    ) -> Result<Response<CancelJobResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let authorization = request
+            .metadata()
+            .get(AUTHORIZATION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let job_id = request.into_inner().job_id;
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::CancelJob { job_id: &job_id },
+        )
+        .await?;
+        self.check_job_owner(principal.as_ref(), &job_id).await?;
+        let status = self
+            .state
+            .task_manager
+            .get_job_status(&job_id)
+            .await
+            .map_err(|e| {
+                let msg = format!("Error getting status for job {}: {:?}", job_id, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?
+            .ok_or_else(|| Status::not_found(format!("Job {} not found", job_id)))?;
+        if matches!(
+            status.status,
+            Some(job_status::Status::Completed(_)) | Some(job_status::Status::Failed(_))
+        ) {
+            return Ok(Response::new(CancelJobResult { cancelled: false }));
+        }
+
+        info!("Cancelling job {}", job_id);
+        SchedulerServer::cancel_job(self, &job_id, authorization.as_deref())
+            .await
+            .map_err(|e| {
+                let msg = format!("Error cancelling job {}: {:?}", job_id, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        Ok(Response::new(CancelJobResult { cancelled: true }))
+    }
+
+    async fn close_session(
+        &self,
+        request: Request<CloseSessionParams>,
+    ) -> Result<Response<CloseSessionResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let session_id = request.into_inner().session_id;
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::CloseSession {
+                session_id: &session_id,
+            },
+        )
+        .await?;
+        self.check_session_owner(principal.as_ref(), &session_id)
+            .await?;
+        let dropped_tables =
+            self.state.close_session(&session_id).await.map_err(|e| {
+                let msg = format!("Error closing session {}: {:?}", session_id, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        Ok(Response::new(CloseSessionResult { dropped_tables }))
+    }
+
+    async fn register_table(
+        &self,
+        request: Request<RegisterTableParams>,
+    ) -> Result<Response<RegisterTableResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let mut table = request
+            .into_inner()
+            .table
+            .ok_or_else(|| Status::invalid_argument("Missing table"))?;
+        self.authorize_client(
+            principal.as_ref(),
+            Operation::RegisterTable {
+                name: &table.name,
+                location: &table.location,
+            },
+        )
+        .await?;
+        table.owner = principal
+            .map(|principal| principal.name)
+            .unwrap_or_default();
+        let name = table.name.clone();
+        let registered = self
+            .state
+            .table_catalog
+            .register(table)
+            .await
+            .map_err(|e| {
+                let msg = format!("Error registering table {}: {:?}", name, e);
+                error!("{}", msg);
+                Status::internal(msg)
+            })?;
+        if !registered {
+            return Err(Status::permission_denied(format!(
+                "Table {} was registered by another user",
+                name
+            )));
+        }
+        info!("Registered table {} in the catalog", name);
+        Ok(Response::new(RegisterTableResult {}))
+    }
+
+    async fn get_table(
+        &self,
+        request: Request<GetTableParams>,
+    ) -> Result<Response<GetTableResult>, Status> {
+        let principal = self.authenticate_client(request.metadata()).await?;
+        let name = request.into_inner().name;
+        self.authorize_client(principal.as_ref(), Operation::GetTable { name: &name })
+            .await?;
+        let table = self.state.table_catalog.get(&name).await.map_err(|e| {
+            let msg = format!("Error reading table {}: {:?}", name, e);
+            error!("{}", msg);
+            Status::internal(msg)
+        })?;
+        Ok(Response::new(GetTableResult { table }))
+    }
+}
+
+/// Remove the object store credentials of a job from the settings sent by the client, so
+/// they are not persisted along with the settings of the session
+fn take_object_store_credentials(
+    settings: &mut Vec<KeyValuePair>,
+) -> Result<ObjectStoreCredentials, Status> {
+    let mut credentials = ObjectStoreCredentials::default();
+    let mut result = Ok(());
+    settings.retain(|kv| {
+        if kv.key != BALLISTA_OBJECT_STORE_CREDENTIALS {
+            return true;
+        }
+        if !kv.value.is_empty() {
+            match ObjectStoreCredentials::from_json(&kv.value) {
+                Ok(parsed) => credentials = parsed,
+                Err(e) => result = Err(Status::invalid_argument(e.to_string())),
+            }
+        }
+        false
+    });
+    result.map(|_| credentials)
+}
+
+/// Reject executors speaking a protocol version this scheduler can not work with
+fn check_executor_protocol(executor: &ExecutorMetadata) -> Result<(), Status> {
+    executor.protocol.check_compatible().map_err(|e| {
+        warn!("Rejecting executor {}: {}", executor.id, e);
+        Status::failed_precondition(e.to_string())
+    })
 }
 
 #[cfg(all(test, feature = "sled"))]
 mod test {
+    use std::collections::HashMap;
     use std::sync::Arc;
 
     use datafusion::execution::context::default_session_builder;
     use datafusion_proto::protobuf::LogicalPlanNode;
-    use tonic::Request;
+    use tonic::{Code, Request};
 
     use ballista_core::error::BallistaError;
+    use ballista_core::protocol::PROTOCOL_VERSION;
     use ballista_core::serde::protobuf::{
-        executor_registration::OptionalHost, ExecutorRegistration, PhysicalPlanNode,
-        PollWorkParams,
+        executor_registration::OptionalHost, CancelJobParams, ExecutorRegistration,
+        GetJobStatusParams, PhysicalPlanNode, PollWorkParams, ProtocolInfo,
+        RegisterExecutorParams,
     };
     use ballista_core::serde::scheduler::ExecutorSpecification;
     use ballista_core::serde::BallistaCodec;
 
+    use crate::auth::{AllowAll, ClientAuth, Principal, StaticTokenValidator};
     use crate::state::{backend::standalone::StandaloneClient, SchedulerState};
 
-    use super::{SchedulerGrpc, SchedulerServer};
+    use super::{
+        take_object_store_credentials, KeyValuePair, SchedulerGrpc, SchedulerServer,
+        BALLISTA_OBJECT_STORE_CREDENTIALS,
+    };
 
     #[tokio::test]
     async fn test_poll_work() -> Result<(), BallistaError> {
@@ -539,11 +1593,15 @@ mod test {
             port: 0,
             grpc_port: 0,
             specification: Some(ExecutorSpecification { task_slots: 2 }.into()),
+            labels: vec![],
+            protocol: None,
         };
         let request: Request<PollWorkParams> = Request::new(PollWorkParams {
             metadata: Some(exec_meta.clone()),
             can_accept_task: false,
             task_status: vec![],
+            epoch: 0,
+            task_progress: vec![],
         });
         let response = scheduler
             .poll_work(request)
@@ -552,6 +1610,9 @@ mod test {
             .into_inner();
         // no response task since we told the scheduler we didn't want to accept one
         assert!(response.task.is_none());
+        // the executor is given an epoch to poll with from now on
+        let epoch = response.epoch;
+        assert_ne!(epoch, 0);
         let state: SchedulerState<LogicalPlanNode, PhysicalPlanNode> =
             SchedulerState::new(
                 state_storage.clone(),
@@ -577,6 +1638,8 @@ mod test {
             metadata: Some(exec_meta.clone()),
             can_accept_task: true,
             task_status: vec![],
+            epoch,
+            task_progress: vec![],
         });
         let response = scheduler
             .poll_work(request)
@@ -586,6 +1649,7 @@ mod test {
 
         // still no response task since there are no tasks in the scheduler
         assert!(response.task.is_none());
+        assert_eq!(response.epoch, epoch);
         let state: SchedulerState<LogicalPlanNode, PhysicalPlanNode> =
             SchedulerState::new(
                 state_storage.clone(),
@@ -607,6 +1671,170 @@ mod test {
         assert_eq!(stored_executor.specification.task_slots, 2);
         assert_eq!(stored_executor.host, "http://host:8080".to_owned());
 
+        // once the executor is considered lost, it is told to start over
+        state.executor_manager.expire_executor("abc").await?;
+        let request: Request<PollWorkParams> = Request::new(PollWorkParams {
+            metadata: Some(exec_meta.clone()),
+            can_accept_task: true,
+            task_status: vec![],
+            epoch,
+            task_progress: vec![],
+        });
+        let status = scheduler
+            .poll_work(request)
+            .await
+            .expect_err("Accepted a stale epoch");
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_register_executor_without_certificate() -> Result<(), BallistaError> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new(
+                state_storage.clone(),
+                "default".to_owned(),
+                BallistaCodec::default(),
+            )
+            .with_executor_authentication();
+        let request = Request::new(RegisterExecutorParams {
+            metadata: Some(ExecutorRegistration {
+                id: "abc".to_owned(),
+                optional_host: Some(OptionalHost::Host("host".to_owned())),
+                port: 0,
+                grpc_port: 0,
+                specification: Some(ExecutorSpecification { task_slots: 2 }.into()),
+                labels: vec![],
+                protocol: None,
+            }),
+        });
+
+        let status = scheduler
+            .register_executor(request)
+            .await
+            .expect_err("Executor without certificate was registered");
+        assert_eq!(status.code(), Code::Unauthenticated);
+
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_register_executor_with_incompatible_protocol(
+    ) -> Result<(), BallistaError> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new(
+                state_storage.clone(),
+                "default".to_owned(),
+                BallistaCodec::default(),
+            );
+        let request = Request::new(RegisterExecutorParams {
+            metadata: Some(ExecutorRegistration {
+                id: "abc".to_owned(),
+                optional_host: Some(OptionalHost::Host("host".to_owned())),
+                port: 0,
+                grpc_port: 0,
+                specification: Some(ExecutorSpecification { task_slots: 2 }.into()),
+                labels: vec![],
+                protocol: Some(ProtocolInfo {
+                    version: PROTOCOL_VERSION + 2,
+                    capabilities: vec![],
+                }),
+            }),
+        });
+
+        let status = scheduler
+            .register_executor(request)
+            .await
+            .expect_err("Executor with an incompatible protocol was registered");
+        assert_eq!(status.code(), Code::FailedPrecondition);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jobs_of_other_clients() -> Result<(), BallistaError> {
+        let principal = |name: &str| Principal {
+            name: name.to_owned(),
+            roles: vec![],
+        };
+        let tokens = HashMap::from([
+            ("secret-1".to_owned(), principal("alice")),
+            ("secret-2".to_owned(), principal("bob")),
+        ]);
+        let auth = ClientAuth::new(
+            Arc::new(StaticTokenValidator::new(tokens)),
+            Arc::new(AllowAll),
+        );
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new(
+                state_storage.clone(),
+                "default".to_owned(),
+                BallistaCodec::default(),
+            )
+            .with_client_auth(auth);
+        scheduler.state.task_manager.queue_job("job").await?;
+        scheduler
+            .state
+            .task_manager
+            .save_job_owner("job", "alice")
+            .await?;
+        let request = |message, token: &str| {
+            let mut request = Request::new(message);
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            request
+        };
+        let job_status = || GetJobStatusParams {
+            job_id: "job".to_owned(),
+        };
+
+        let response = scheduler
+            .get_job_status(request(job_status(), "secret-1"))
+            .await
+            .expect("Owner could not get the job status");
+        assert!(response.into_inner().status.is_some());
+        let status = scheduler
+            .get_job_status(request(job_status(), "secret-2"))
+            .await
+            .expect_err("Other client got the job status");
+        assert_eq!(status.code(), Code::PermissionDenied);
+        let cancel_job = CancelJobParams {
+            job_id: "job".to_owned(),
+        };
+        let status = scheduler
+            .cancel_job(request(cancel_job, "secret-2"))
+            .await
+            .expect_err("Other client cancelled the job");
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_object_store_credentials() {
+        let setting = |key: &str, value: &str| KeyValuePair {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        let mut settings = vec![
+            setting("ballista.shuffle.partitions", "4"),
+            setting(
+                BALLISTA_OBJECT_STORE_CREDENTIALS,
+                r#"{"s3://bucket": {"access_key_id": "key"}}"#,
+            ),
+        ];
+        let credentials = take_object_store_credentials(&mut settings).unwrap();
+        assert!(!credentials.is_empty());
+        assert_eq!(settings, vec![setting("ballista.shuffle.partitions", "4")]);
+
+        let mut settings = vec![setting(BALLISTA_OBJECT_STORE_CREDENTIALS, "not json")];
+        let status = take_object_store_credentials(&mut settings).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+    }
 }
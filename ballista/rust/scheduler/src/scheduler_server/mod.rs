@@ -15,31 +15,59 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use ballista_core::auth::AUTHORIZATION_HEADER;
 use ballista_core::config::TaskSchedulingPolicy;
-use ballista_core::error::Result;
+use ballista_core::error::{BallistaError, Result};
 use ballista_core::event_loop::{EventAction, EventLoop};
-use ballista_core::serde::protobuf::TaskStatus;
+use ballista_core::object_store_registry::ObjectStoreFactories;
+use ballista_core::serde::protobuf::{
+    CancelJobParams, FailedJob, FailureKind, TaskStatus, UpdateTaskStatusParams,
+};
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
+use ballista_core::table_format::cache::CACHE;
+use ballista_core::table_format::TableFormats;
+use datafusion::catalog::catalog::CatalogProvider;
 use datafusion::execution::context::{default_session_builder, SessionState};
+use datafusion::logical_plan::LogicalPlan;
 
-use datafusion::prelude::SessionConfig;
+use datafusion::prelude::{SessionConfig, SessionContext};
 use datafusion_proto::logical_plan::AsLogicalPlan;
 
-use log::error;
+use log::{debug, error, info, warn};
+use tonic::Request;
 
+use crate::audit::{AuditLog, AuditSink};
+use crate::auth::ClientAuth;
+use crate::event_log::EventLog;
+use crate::plan_rewrite::{PlanRewriter, PlanRewriters};
+use crate::query_history::QueryHistory;
 use crate::scheduler_server::event::{QueryStageSchedulerEvent, SchedulerServerEvent};
 use crate::scheduler_server::event_loop::SchedulerServerEventAction;
 use crate::scheduler_server::query_stage_scheduler::QueryStageScheduler;
-use crate::state::backend::StateBackendClient;
-use crate::state::SchedulerState;
+use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::execution_graph::timestamp_millis;
+use crate::state::executor_pools::TenantPools;
+use crate::state::job_shards::JobShards;
+use crate::state::plan_cache::{PlanCache, PlanCacheOptions};
+use crate::state::result_cache::{ResultCache, ResultCacheOptions};
+use crate::state::tenant_priorities::TenantPriorities;
+use crate::state::{with_lock, SchedulerState};
+#[cfg(feature = "kafka")]
+use crate::streaming::{self, StreamingQuery};
+use crate::webhook::JobNotifier;
 
 // include the generated protobuf source as a submodule
 #[allow(clippy::all)]
 pub mod externalscaler {
     include!(concat!(env!("OUT_DIR"), "/externalscaler.rs"));
+
+    /// The encoded descriptors of the KEDA protobuf files, for gRPC reflection
+    pub const FILE_DESCRIPTOR_SET: &[u8] =
+        include_bytes!(concat!(env!("OUT_DIR"), "/externalscaler_descriptor.bin"));
 }
 
 pub mod event;
@@ -50,14 +78,31 @@ mod query_stage_scheduler;
 
 pub(crate) type SessionBuilder = fn(SessionConfig) -> SessionState;
 
+/// Interval between the checks for unfinished jobs while the scheduler shuts down
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Interval between the checks for jobs running for longer than their timeouts
+const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval between the checks for sessions idle for longer than their timeout
+const SESSION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
 pub struct SchedulerServer<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> {
     pub(crate) state: Arc<SchedulerState<T, U>>,
     pub start_time: u128,
     policy: TaskSchedulingPolicy,
     event_loop: Option<EventLoop<SchedulerServerEvent>>,
+    /// Action of `event_loop` in push mode, unless given by the caller, which holds the
+    /// state along with the query stage scheduler
+    event_action: Option<Arc<SchedulerServerEventAction<T, U>>>,
     pub(crate) query_stage_event_loop: EventLoop<QueryStageSchedulerEvent>,
     codec: BallistaCodec<T, U>,
+    /// Whether executors must present a verified client certificate
+    authenticate_executors: bool,
+    /// Queries run continuously over Kafka topics once the scheduler is initialized
+    #[cfg(feature = "kafka")]
+    streaming_queries: Vec<StreamingQuery>,
 }
 
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T, U> {
@@ -104,14 +149,11 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T
             codec.clone(),
         ));
 
-        let event_loop = if matches!(policy, TaskSchedulingPolicy::PushStaged) {
-            let event_action: Arc<SchedulerServerEventAction<T, U>> =
-                Arc::new(SchedulerServerEventAction::new(state.clone()));
-            let event_loop = EventLoop::new("scheduler".to_owned(), 10000, event_action);
-            Some(event_loop)
-        } else {
-            None
-        };
+        let event_action = matches!(policy, TaskSchedulingPolicy::PushStaged)
+            .then(|| Arc::new(SchedulerServerEventAction::new(state.clone())));
+        let event_loop = event_action.clone().map(|event_action| {
+            EventLoop::new("scheduler".to_owned(), 10000, event_action)
+        });
         let query_stage_scheduler =
             Arc::new(QueryStageScheduler::new(state.clone(), None));
         let query_stage_event_loop =
@@ -124,8 +166,12 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T
                 .as_millis(),
             policy,
             event_loop,
+            event_action,
             query_stage_event_loop,
             codec,
+            authenticate_executors: false,
+            #[cfg(feature = "kafka")]
+            streaming_queries: vec![],
             // session_builder,
         }
     }
@@ -157,9 +203,198 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T
                 .as_millis(),
             policy: TaskSchedulingPolicy::PushStaged,
             event_loop: Some(event_loop),
+            event_action: None,
             query_stage_event_loop,
             codec,
+            authenticate_executors: false,
+            #[cfg(feature = "kafka")]
+            streaming_queries: vec![],
+        }
+    }
+
+    /// Write the lifecycle events of all jobs to `event_log`. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_event_log(self, event_log: Arc<EventLog>) -> Self {
+        self.with_state(|state| state.event_log = Some(event_log))
+    }
+
+    /// POST a notification to `url` whenever a job submitted without a callback URL
+    /// completes or fails. Must be called before [SchedulerServer::init].
+    pub fn with_job_webhook(self, url: String) -> Self {
+        self.with_state(|state| {
+            let hosts = state.job_notifier.callback_hosts().to_vec();
+            state.job_notifier = Arc::new(JobNotifier::new(Some(url), hosts))
+        })
+    }
+
+    /// Let clients give callback URLs on `hosts`, as `host` or `host:port`, with the
+    /// `ballista.job.callback_url` setting. Jobs with callback URLs on other hosts are
+    /// refused. Must be called before [SchedulerServer::init].
+    pub fn with_job_callback_hosts(self, hosts: Vec<String>) -> Self {
+        self.with_state(|state| {
+            let url = state.job_notifier.default_url().map(str::to_owned);
+            state.job_notifier = Arc::new(JobNotifier::new(url, hosts))
+        })
+    }
+
+    /// Write an audit record of every submitted query to `sink`. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_audit_sink(self, sink: Arc<dyn AuditSink>) -> Self {
+        self.with_state(|state| state.audit_log = Some(Arc::new(AuditLog::new(sink))))
+    }
+
+    /// Write the performance of every finished job to `query_history`. Must be called
+    /// before [SchedulerServer::init].
+    pub fn with_query_history(self, query_history: Arc<QueryHistory>) -> Self {
+        self.with_state(|state| state.query_history = Some(query_history))
+    }
+
+    /// Run the jobs of the tenants of `pools` which do not set their pool on the
+    /// executors of the pool of their tenant. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_tenant_pools(self, pools: TenantPools) -> Self {
+        self.with_state(|state| state.tenant_pools = pools)
+    }
+
+    /// Lower the priority of the jobs of tenants to their cap in `priorities`. Must be
+    /// called before [SchedulerServer::init].
+    pub fn with_tenant_priorities(self, priorities: TenantPriorities) -> Self {
+        self.with_state(|state| state.tenant_priorities = priorities)
+    }
+
+    /// Share the jobs of the cluster with the other schedulers of `shards`, only
+    /// scheduling the jobs this scheduler owns and forwarding the cancellations and task
+    /// statuses of the others to their owner. Requires push-based scheduling, as the
+    /// executors polling a scheduler only run its jobs. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_job_shards(self, shards: JobShards) -> Self {
+        let shards = Arc::new(shards);
+        self.with_state(|state| {
+            state.job_shards = Some(shards.clone());
+            state.task_manager = state.task_manager.clone().with_job_shards(shards)
+        })
+    }
+
+    /// Require clients to authenticate with a bearer token and check that they are
+    /// authorized to perform their requests. Must be called before [SchedulerServer::init].
+    pub fn with_client_auth(self, auth: ClientAuth) -> Self {
+        self.with_state(|state| state.client_auth = Some(Arc::new(auth)))
+    }
+
+    /// Create the object stores clients configure for their jobs with `factories`. Must
+    /// be called before [SchedulerServer::init].
+    pub fn with_object_store_factories(self, factories: ObjectStoreFactories) -> Self {
+        self.with_state(|state| state.object_store_factories = factories)
+    }
+
+    /// Resolve the scans of external tables with `formats`, along with the scans of
+    /// cached tables. Must be called before [SchedulerServer::init].
+    pub fn with_table_formats(self, formats: TableFormats) -> Self {
+        self.with_state(|state| {
+            state.table_formats = formats;
+            state
+                .table_formats
+                .register(CACHE, Arc::new(state.table_cache.clone()));
+        })
+    }
+
+    /// Make the tables of `catalog` available to the SQL queries of all clients as
+    /// `<name>.<schema>.<table>`. Must be called before [SchedulerServer::init].
+    pub fn with_catalog(self, name: &str, catalog: Arc<dyn CatalogProvider>) -> Self {
+        self.with_state(|state| state.catalogs.push((name.to_owned(), catalog)))
+    }
+
+    /// Cache the plans of SQL queries, so that repeated queries are not planned again.
+    /// Must be called before [SchedulerServer::init].
+    pub fn with_plan_cache(self, options: PlanCacheOptions) -> Self {
+        self.with_state(|state| state.plan_cache = Arc::new(PlanCache::new(options)))
+    }
+
+    /// Cache the results of queries, so that jobs running the same plan over unchanged
+    /// files complete with the result of an earlier job. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_result_cache(self, options: ResultCacheOptions) -> Self {
+        self.with_state(|state| state.result_cache = Arc::new(ResultCache::new(options)))
+    }
+
+    /// Rewrite the plans of jobs with `rewriter`, after the rewriters registered before
+    pub fn with_plan_rewriter(self, rewriter: Arc<dyn PlanRewriter>) -> Self {
+        self.state.plan_rewriters.register(rewriter);
+        self
+    }
+
+    /// The rules rewriting the plans of jobs, with which rewriters can be registered
+    /// while the scheduler runs
+    pub fn plan_rewriters(&self) -> &PlanRewriters {
+        &self.state.plan_rewriters
+    }
+
+    /// Encrypt the shuffle files of every job with a key generated for the job, which
+    /// executors receive along with its tasks. Must be called before
+    /// [SchedulerServer::init].
+    pub fn with_shuffle_encryption(self) -> Self {
+        self.with_state(|state| {
+            state.task_manager = state.task_manager.clone().with_shuffle_encryption()
+        })
+    }
+
+    /// Only accept requests from executors which present a client certificate verified
+    /// by the TLS layer, see [ballista_core::tls::TlsOptions::tls_acceptor]
+    pub fn with_executor_authentication(mut self) -> Self {
+        self.authenticate_executors = true;
+        self
+    }
+
+    /// Consider executors lost once `lease` passed since the scheduler last heard of
+    /// them, instead of one minute. Executors are told to send their heartbeats well
+    /// within their lease.
+    pub fn with_executor_lease(self, lease: Duration) -> Self {
+        self.state.executor_manager.set_executor_lease(lease);
+        self
+    }
+
+    /// Close sessions once `timeout` passed since their last query, dropping their
+    /// temporary tables, instead of waiting for their clients to close them
+    pub fn with_session_idle_timeout(self, timeout: Duration) -> Self {
+        self.with_state(|state| {
+            state.session_manager =
+                state.session_manager.clone().with_idle_timeout(timeout)
+        })
+    }
+
+    /// Run `query` in micro-batches once the scheduler is initialized
+    #[cfg(feature = "kafka")]
+    pub fn with_streaming_query(mut self, query: StreamingQuery) -> Self {
+        self.streaming_queries.push(query);
+        self
+    }
+
+    fn with_state(mut self, f: impl FnOnce(&mut SchedulerState<T, U>)) -> Self {
+        let mut state = self.state.as_ref().clone();
+        f(&mut state);
+        self.state = Arc::new(state);
+
+        // The state is shared by cloning, so every loop holding it is rebuilt to see the
+        // new instance: in push mode the scheduler loop offering task slots to jobs, and
+        // the query stage scheduler handling the job lifecycle
+        if let (Some(event_loop), Some(_)) = (&self.event_loop, &self.event_action) {
+            let event_action =
+                Arc::new(SchedulerServerEventAction::new(self.state.clone()));
+            self.event_loop = Some(EventLoop::new(
+                event_loop.name.clone(),
+                event_loop.buffer_size,
+                event_action.clone(),
+            ));
+            self.event_action = Some(event_action);
         }
+        let query_stage_scheduler =
+            Arc::new(QueryStageScheduler::new(self.state.clone(), None));
+        self.query_stage_event_loop = EventLoop::new(
+            self.query_stage_event_loop.name.clone(),
+            self.query_stage_event_loop.buffer_size,
+            query_stage_scheduler,
+        );
+        self
     }
 
     pub async fn init(&mut self) -> Result<()> {
@@ -169,27 +404,381 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T
         }
 
         {
-            if let Some(event_loop) = self.event_loop.as_mut() {
-                event_loop.start()?;
-
-                let query_stage_scheduler = Arc::new(QueryStageScheduler::new(
-                    self.state.clone(),
-                    Some(event_loop.get_sender()?),
-                ));
-                let query_stage_event_loop = EventLoop::new(
-                    self.query_stage_event_loop.name.clone(),
-                    self.query_stage_event_loop.buffer_size,
-                    query_stage_scheduler,
+            let event_sender = match self.event_loop.as_mut() {
+                Some(event_loop) => {
+                    event_loop.start()?;
+                    Some(event_loop.get_sender()?)
+                }
+                None => None,
+            };
+
+            let query_stage_scheduler =
+                Arc::new(QueryStageScheduler::new(self.state.clone(), event_sender));
+            self.query_stage_event_loop = EventLoop::new(
+                self.query_stage_event_loop.name.clone(),
+                self.query_stage_event_loop.buffer_size,
+                query_stage_scheduler.clone(),
+            );
+            self.query_stage_event_loop.start()?;
+            query_stage_scheduler
+                .set_retry_sender(self.query_stage_event_loop.get_sender()?);
+        }
+
+        #[cfg(feature = "kafka")]
+        for query in &self.streaming_queries {
+            tokio::spawn(streaming::run(self.clone(), query.clone()));
+        }
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(TIMEOUT_CHECK_INTERVAL).await;
+                if let Err(e) = server.fail_timed_out_jobs().await {
+                    warn!("Failed to fail the jobs which timed out: {:?}", e);
+                }
+                server.kill_stalled_tasks();
+                server.reset_lost_executors().await;
+            }
+        });
+
+        if let Some(timeout) = self.state.session_manager.idle_timeout() {
+            let server = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(SESSION_CHECK_INTERVAL.min(timeout)).await;
+                    if let Err(e) = server.close_idle_sessions(timeout).await {
+                        warn!("Failed to close the idle sessions: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Close the sessions in which no query was submitted for longer than `timeout`.
+    /// Queries of a session may be submitted to any scheduler sharing the state
+    /// backend, so sharded schedulers each close the sessions they own, and other
+    /// schedulers close a session while holding its lock, once they checked it is
+    /// still idle.
+    async fn close_idle_sessions(&self, timeout: Duration) -> Result<()> {
+        let sessions = &self.state.session_manager;
+        let now = timestamp_millis();
+        for session_id in sessions.idle_sessions(timeout, now).await? {
+            if let Some(shards) = &self.state.job_shards {
+                if !shards.is_local(&session_id) {
+                    continue;
+                }
+            }
+            let lock = self
+                .state
+                .backend()
+                .lock(Keyspace::Sessions, &session_id)
+                .await?;
+            with_lock(lock, async {
+                if sessions.is_idle_session(&session_id, timeout, now).await? {
+                    info!("Closing session {} idle for over {:?}", session_id, timeout);
+                    self.state.close_session(&session_id).await?;
+                }
+                Ok::<_, BallistaError>(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Fail the jobs which ran for longer than their job or stage timeout with
+    /// [FailureKind::Timeout]
+    async fn fail_timed_out_jobs(&self) -> Result<()> {
+        for (job_id, reason) in self.state.task_manager.timed_out_jobs().await {
+            warn!("{}", reason);
+            self.query_stage_event_loop
+                .get_sender()?
+                .post_event(QueryStageSchedulerEvent::JobFailed(
+                    job_id,
+                    FailedJob {
+                        error: reason,
+                        kind: FailureKind::Timeout.into(),
+                    },
+                ))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reschedule the tasks of the executors which were expired or whose lease is over,
+    /// switching the readers of their outputs to the copies kept by other executors
+    /// where the shuffles are replicated. An executor which registers again is reset
+    /// when fenced instead. Lost executors stop draining, as they run no task any more.
+    async fn reset_lost_executors(&self) {
+        for executor_id in self.state.executor_manager.take_lost_executors() {
+            if let Err(e) = self
+                .state
+                .executor_manager
+                .undrain_executor(&executor_id)
+                .await
+            {
+                warn!("Failed to undrain lost executor {}: {:?}", executor_id, e);
+            }
+            if let Err(e) = self
+                .state
+                .task_manager
+                .forget_job_data_removals(&executor_id)
+                .await
+            {
+                warn!(
+                    "Failed to forget the job data lost executor {} had to remove: {:?}",
+                    executor_id, e
                 );
-                self.query_stage_event_loop = query_stage_event_loop;
             }
+            match self
+                .state
+                .task_manager
+                .reset_executor_tasks(&executor_id)
+                .await
+            {
+                Ok(0) => {}
+                Ok(reset) => warn!(
+                    "Executor {} was lost, rescheduled {} of its tasks",
+                    executor_id, reset
+                ),
+                Err(e) => error!(
+                    "Failed to reschedule the tasks of lost executor {}: {:?}",
+                    executor_id, e
+                ),
+            }
+        }
+    }
 
-            self.query_stage_event_loop.start()?;
+    /// Have the executors kill the tasks which stopped making progress for longer than
+    /// their stall timeout, with the responses to their next heartbeats or polls
+    fn kill_stalled_tasks(&self) {
+        for (executor_id, task) in self.state.task_manager.kill_stalled_tasks() {
+            if let Some(task_id) = task.task_id {
+                warn!(
+                    "Killing task {}/{}/{} attempt {} on executor {}, which stopped making progress",
+                    task_id.job_id,
+                    task_id.stage_id,
+                    task_id.partition_id,
+                    task.attempt,
+                    executor_id
+                );
+            }
         }
+    }
 
+    /// Queue a job executing `plan` in the session of `session_ctx`, returning its ID.
+    /// The job is owned by this scheduler when the jobs are sharded.
+    pub(crate) async fn submit_job(
+        &self,
+        session_ctx: Arc<SessionContext>,
+        plan: LogicalPlan,
+    ) -> Result<String> {
+        if self.state.shutting_down() {
+            return Err(BallistaError::General(
+                "Scheduler is shutting down and does not accept new jobs".to_owned(),
+            ));
+        }
+        let job_id = self.state.task_manager.generate_local_job_id();
+        self.state.task_manager.queue_job(&job_id).await?;
+        self.query_stage_event_loop
+            .get_sender()?
+            .post_event(QueryStageSchedulerEvent::JobQueued {
+                job_id: job_id.clone(),
+                session_id: session_ctx.session_id(),
+                session_ctx,
+                plan: Box::new(plan),
+                trace_context: vec![],
+                identity: None,
+                table_locations: HashMap::new(),
+                parquet_output: None,
+                plan_key: None,
+                cache_result: false,
+                shuffle_partitions: None,
+                scan_tasks: None,
+                partial_aggregation_max_groups: None,
+                bloom_filter_bytes: None,
+                job_priority: 0,
+                replicate_shuffles: false,
+                retry: None,
+            })
+            .await?;
+        Ok(job_id)
+    }
+
+    /// Cancel a queued or running job by failing it with [FailureKind::Cancelled].
+    /// Tasks which are already running on executors are not interrupted, but their
+    /// results are discarded.
+    ///
+    /// Jobs owned by another scheduler are cancelled by their owner, which authorizes
+    /// the client again with `authorization`, the authorization header of its request.
+    pub(crate) async fn cancel_job(
+        &self,
+        job_id: &str,
+        authorization: Option<&str>,
+    ) -> Result<()> {
+        if let Some(shards) = &self.state.job_shards {
+            let owner = shards.owner(job_id);
+            if owner != shards.local() {
+                debug!("Forwarding the cancellation of job {} to {}", job_id, owner);
+                let mut request = Request::new(CancelJobParams {
+                    job_id: job_id.to_owned(),
+                });
+                if let Some(authorization) = authorization {
+                    let value = authorization.parse().map_err(|_| {
+                        BallistaError::General("Invalid authorization header".to_owned())
+                    })?;
+                    request.metadata_mut().insert(AUTHORIZATION_HEADER, value);
+                }
+                shards.client(owner).await?.cancel_job(request).await?;
+                return Ok(());
+            }
+        }
+        self.query_stage_event_loop
+            .get_sender()?
+            .post_event(QueryStageSchedulerEvent::JobFailed(
+                job_id.to_owned(),
+                FailedJob {
+                    error: format!("Job {} was cancelled", job_id),
+                    kind: FailureKind::Cancelled.into(),
+                },
+            ))
+            .await
+    }
+
+    /// Shut the scheduler down gracefully: stop accepting new jobs, wait up to `timeout`
+    /// for the queued and running jobs to complete, fail the jobs which are still
+    /// unfinished so that their final status is persisted, and notify the executors.
+    /// The gRPC server must keep serving meanwhile, for executors to report the status
+    /// of their tasks.
+    pub async fn shutdown(&self, timeout: Duration) -> Result<()> {
+        self.state.begin_shutdown();
+        let deadline = Instant::now() + timeout;
+        let mut job_ids = self.state.task_manager.get_unfinished_job_ids();
+        info!(
+            "Shutting down, waiting up to {:?} for {} unfinished jobs",
+            timeout,
+            job_ids.len()
+        );
+        while !job_ids.is_empty() && Instant::now() < deadline {
+            tokio::time::sleep(SHUTDOWN_POLL_INTERVAL).await;
+            job_ids = self.state.task_manager.get_unfinished_job_ids();
+        }
+
+        if !job_ids.is_empty() {
+            warn!(
+                "Failing {} jobs which did not complete before the shutdown timeout",
+                job_ids.len()
+            );
+            for job_id in &job_ids {
+                self.post_stage_event(QueryStageSchedulerEvent::JobFailed(
+                    job_id.clone(),
+                    FailedJob {
+                        error: format!(
+                            "Scheduler shut down before job {} completed",
+                            job_id
+                        ),
+                        kind: FailureKind::Cancelled.into(),
+                    },
+                ))
+                .await?;
+            }
+            // Let the query stage scheduler persist the failures
+            let deadline = Instant::now() + 10 * SHUTDOWN_POLL_INTERVAL;
+            while Instant::now() < deadline
+                && !self.state.task_manager.get_unfinished_job_ids().is_empty()
+            {
+                tokio::time::sleep(SHUTDOWN_POLL_INTERVAL / 10).await;
+            }
+        }
+
+        // Executors polling for work notice the shutdown when their polls fail
+        if matches!(self.policy, TaskSchedulingPolicy::PushStaged) {
+            let executor_manager = &self.state.executor_manager;
+            for executor_id in executor_manager.get_alive_executors() {
+                let result =
+                    match executor_manager.get_executor_metadata(&executor_id).await {
+                        Ok(executor) => {
+                            self.state
+                                .task_manager
+                                .notify_scheduler_shutdown(&executor)
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                if let Err(e) = result {
+                    warn!(
+                        "Could not notify executor {} of the shutdown: {:?}",
+                        executor_id, e
+                    );
+                }
+            }
+        }
+        info!("Scheduler shut down");
         Ok(())
     }
 
+    /// Forward the statuses of the tasks of the jobs owned by other schedulers to their
+    /// owner, returning the statuses of the tasks of the jobs this scheduler owns.
+    /// Statuses are forwarded to every owner concurrently. Those an owner could not be
+    /// reached for are lost, as when the executor cannot reach the owner, without
+    /// failing the statuses of the other owners.
+    pub(crate) async fn forward_task_statuses(
+        &self,
+        executor_id: &str,
+        epoch: u64,
+        protocol_version: u32,
+        tasks_status: Vec<TaskStatus>,
+    ) -> Vec<TaskStatus> {
+        let shards = match &self.state.job_shards {
+            Some(shards) => shards,
+            None => return tasks_status,
+        };
+        let mut local = vec![];
+        let mut forwarded: HashMap<&str, Vec<TaskStatus>> = HashMap::new();
+        for status in tasks_status {
+            let owner = status
+                .task_id
+                .as_ref()
+                .map(|task_id| shards.owner(&task_id.job_id))
+                .filter(|owner| *owner != shards.local());
+            match owner {
+                Some(owner) => forwarded.entry(owner).or_default().push(status),
+                None => local.push(status),
+            }
+        }
+        let forwards = forwarded
+            .into_iter()
+            .map(|(owner, task_status)| async move {
+                let num_status = task_status.len();
+                debug!(
+                    "Forwarding {} task statuses of executor {} to {}",
+                    num_status, executor_id, owner
+                );
+                let forward = async {
+                    shards
+                        .client(owner)
+                        .await?
+                        .update_task_status(UpdateTaskStatusParams {
+                            executor_id: executor_id.to_owned(),
+                            task_status,
+                            protocol_version,
+                            epoch,
+                        })
+                        .await?;
+                    Ok::<_, BallistaError>(())
+                };
+                if let Err(e) = forward.await {
+                    error!(
+                        "Could not forward {} task statuses of executor {} to {}: {:?}",
+                        num_status, executor_id, owner, e
+                    );
+                }
+            });
+        futures::future::join_all(forwards).await;
+        local
+    }
+
     pub(crate) async fn update_task_status(
         &self,
         executor_id: &str,
@@ -242,8 +831,9 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerServer<T
 
 #[cfg(all(test, feature = "sled"))]
 mod test {
+    use std::collections::HashMap;
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
     use datafusion::execution::context::default_session_builder;
@@ -259,11 +849,12 @@ mod test {
     use ballista_core::event_loop::EventAction;
 
     use ballista_core::serde::protobuf::{
-        job_status, task_status, CompletedTask, FailedTask, JobStatus, PartitionId,
-        PhysicalPlanNode, ShuffleWritePartition, TaskStatus,
+        job_status, task_status, CompletedTask, ExecutorHeartbeat, FailedTask,
+        FailureKind, JobStatus, PartitionId, PhysicalPlanNode, ShuffleReplica,
+        ShuffleWritePartition, TaskStatus,
     };
     use ballista_core::serde::scheduler::{
-        ExecutorData, ExecutorMetadata, ExecutorSpecification,
+        ExecutorData, ExecutorMetadata, ExecutorSpecification, POOL_LABEL,
     };
     use ballista_core::serde::BallistaCodec;
 
@@ -272,8 +863,10 @@ mod test {
     };
     use crate::scheduler_server::SchedulerServer;
     use crate::state::backend::standalone::StandaloneClient;
+    use crate::state::backend::Keyspace;
 
     use crate::state::executor_manager::ExecutorReservation;
+    use crate::state::job_shards::JobShards;
     use crate::test_utils::{
         await_condition, ExplodingTableProvider, SchedulerEventObserver,
     };
@@ -328,7 +921,7 @@ mod test {
         scheduler
             .state
             .task_manager
-            .submit_job(job_id, &session_id, plan)
+            .submit_job(job_id, &session_id, plan, vec![], None, 0, false)
             .await
             .expect("submitting plan");
 
@@ -363,12 +956,19 @@ mod test {
                     status: Some(task_status::Status::Completed(CompletedTask {
                         executor_id: "executor-1".to_owned(),
                         partitions,
+                        bytes_scanned: 0,
+                        spilled_bytes: 0,
+                        spill_files: 0,
+                        bloom_filter: vec![],
+                        replica: None,
+                        operator_metrics: vec![],
                     })),
                     task_id: Some(PartitionId {
                         job_id: job_id.to_owned(),
                         stage_id: task.partition.stage_id as u32,
                         partition_id: task.partition.partition_id as u32,
                     }),
+                    attempt: task.attempt,
                 };
 
                 scheduler
@@ -441,6 +1041,19 @@ mod test {
                 session_id,
                 session_ctx: ctx,
                 plan: Box::new(plan),
+                trace_context: vec![],
+                identity: None,
+                table_locations: HashMap::new(),
+                parquet_output: None,
+                plan_key: None,
+                cache_result: false,
+                shuffle_partitions: None,
+                scan_tasks: None,
+                partial_aggregation_max_groups: None,
+                bloom_filter_bytes: None,
+                job_priority: 0,
+                replicate_shuffles: false,
+                retry: None,
             })
             .await?;
 
@@ -491,6 +1104,12 @@ mod test {
                                         CompletedTask {
                                             executor_id: executor.id.clone(),
                                             partitions,
+                                            bytes_scanned: 0,
+                                            spilled_bytes: 0,
+                                            spill_files: 0,
+                                            bloom_filter: vec![],
+                                            replica: None,
+                                            operator_metrics: vec![],
                                         },
                                     )),
                                     task_id: Some(PartitionId {
@@ -498,6 +1117,7 @@ mod test {
                                         stage_id: task.partition.stage_id as u32,
                                         partition_id: task.partition.partition_id as u32,
                                     }),
+                                    attempt: task.attempt,
                                 };
 
                                 scheduler
@@ -581,6 +1201,19 @@ mod test {
                 session_id,
                 session_ctx: ctx,
                 plan: Box::new(plan),
+                trace_context: vec![],
+                identity: None,
+                table_locations: HashMap::new(),
+                parquet_output: None,
+                plan_key: None,
+                cache_result: false,
+                shuffle_partitions: None,
+                scan_tasks: None,
+                partial_aggregation_max_groups: None,
+                bloom_filter_bytes: None,
+                job_priority: 0,
+                replicate_shuffles: false,
+                retry: None,
             })
             .await?;
 
@@ -625,6 +1258,7 @@ mod test {
                                     status: Some(task_status::Status::Failed(
                                         FailedTask {
                                             error: "".to_string(),
+                                            kind: FailureKind::ExecutionError.into(),
                                         },
                                     )),
                                     task_id: Some(PartitionId {
@@ -632,6 +1266,7 @@ mod test {
                                         stage_id: task.partition.stage_id as u32,
                                         partition_id: task.partition.partition_id as u32,
                                     }),
+                                    attempt: task.attempt,
                                 };
 
                                 scheduler
@@ -716,6 +1351,19 @@ mod test {
                 session_id,
                 session_ctx: ctx,
                 plan: Box::new(plan),
+                trace_context: vec![],
+                identity: None,
+                table_locations: HashMap::new(),
+                parquet_output: None,
+                plan_key: None,
+                cache_result: false,
+                shuffle_partitions: None,
+                scan_tasks: None,
+                partial_aggregation_max_groups: None,
+                bloom_filter_bytes: None,
+                job_priority: 0,
+                replicate_shuffles: false,
+                retry: None,
             })
             .await?;
 
@@ -727,8 +1375,8 @@ mod test {
             Ok(matches!(
                 status,
                 Some(JobStatus {
-                    status: Some(job_status::Status::Failed(_))
-                })
+                    status: Some(job_status::Status::Failed(failed))
+                }) if failed.kind() == FailureKind::PlanningError
             ))
         };
 
@@ -740,6 +1388,473 @@ mod test {
         Ok(())
     }
 
+    // Jobs which do not complete before the shutdown timeout are failed, and no new
+    // jobs are accepted. The jobs of other schedulers are left to them.
+    #[tokio::test]
+    async fn test_shutdown() -> Result<()> {
+        let scheduler = test_scheduler(TaskSchedulingPolicy::PullStaged).await?;
+        let ctx = scheduler
+            .state
+            .session_manager
+            .create_session(&test_session(4))
+            .await?;
+        scheduler
+            .state
+            .backend()
+            .put(Keyspace::QueuedJobs, "other".to_owned(), vec![0x0])
+            .await?;
+
+        // Without executors, the job can not complete
+        let job_id = scheduler.submit_job(ctx.clone(), test_plan()).await?;
+        scheduler.shutdown(Duration::from_millis(100)).await?;
+
+        let status = scheduler.state.task_manager.get_job_status(&job_id).await?;
+        assert!(
+            matches!(
+                &status,
+                Some(JobStatus {
+                    status: Some(job_status::Status::Failed(failed))
+                }) if failed.kind() == FailureKind::Cancelled
+            ),
+            "Unexpected job status {:?}",
+            status
+        );
+        assert!(scheduler.submit_job(ctx, test_plan()).await.is_err());
+        let status = scheduler.state.task_manager.get_job_status("other").await?;
+        assert!(
+            matches!(
+                &status,
+                Some(JobStatus {
+                    status: Some(job_status::Status::Queued(_))
+                })
+            ),
+            "Unexpected job status {:?}",
+            status
+        );
+
+        Ok(())
+    }
+
+    // The state set by the builders is seen by the push-mode scheduler loop
+    #[tokio::test]
+    async fn test_push_event_action_state() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let mut scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new_with_policy(
+                state_storage,
+                "default".to_owned(),
+                TaskSchedulingPolicy::PushStaged,
+                BallistaCodec::default(),
+                default_session_builder,
+            )
+            .with_job_webhook("http://localhost:8000/hook".to_owned());
+        scheduler.init().await?;
+
+        let event_action = scheduler.event_action.as_ref().unwrap();
+        assert!(Arc::ptr_eq(&event_action.state, &scheduler.state));
+        Ok(())
+    }
+
+    // Statuses of the jobs of a scheduler which cannot be reached do not fail the
+    // statuses of the jobs of this scheduler
+    #[tokio::test]
+    async fn test_forward_task_statuses_per_scheduler() -> Result<()> {
+        // Nothing listens on the address of the other scheduler
+        let shards = JobShards::try_new("127.0.0.1:1,127.0.0.1:2", "127.0.0.1:1")?;
+        let foreign_job = (0..)
+            .map(|i| format!("job-{}", i))
+            .find(|job_id| !shards.is_local(job_id))
+            .unwrap();
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new_with_policy(
+                state_storage,
+                "default".to_owned(),
+                TaskSchedulingPolicy::PushStaged,
+                BallistaCodec::default(),
+                default_session_builder,
+            )
+            .with_job_shards(shards);
+        // Jobs submitted to the scheduler are owned by it
+        let local_job = scheduler.state.task_manager.generate_local_job_id();
+        assert!(scheduler.state.task_manager.owns_job(&local_job));
+
+        let status = |job_id: &str| TaskStatus {
+            task_id: Some(PartitionId {
+                job_id: job_id.to_owned(),
+                stage_id: 1,
+                partition_id: 0,
+            }),
+            ..Default::default()
+        };
+        let local = scheduler
+            .forward_task_statuses(
+                "executor-1",
+                0,
+                0,
+                vec![status(&local_job), status(&foreign_job)],
+            )
+            .await;
+        assert_eq!(local, vec![status(&local_job)]);
+        Ok(())
+    }
+
+    // In push mode, a scheduler sharing the jobs of the cluster with another one only
+    // offers task slots to the jobs it owns
+    #[tokio::test]
+    async fn test_push_scheduling_with_job_shards() -> Result<()> {
+        let shards = JobShards::try_new(
+            "scheduler-0:50050,scheduler-1:50050",
+            "scheduler-0:50050",
+        )?;
+        let foreign_job = (0..)
+            .map(|i| format!("job-{}", i))
+            .find(|job_id| !shards.is_local(job_id))
+            .unwrap();
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let mut scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new_with_policy(
+                state_storage,
+                "default".to_owned(),
+                TaskSchedulingPolicy::PushStaged,
+                BallistaCodec::default(),
+                default_session_builder,
+            )
+            .with_job_shards(shards);
+        scheduler.init().await?;
+
+        for (executor_metadata, executor_data) in test_executors(4) {
+            scheduler
+                .state
+                .executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        let ctx = scheduler
+            .state
+            .session_manager
+            .create_session(&test_session(4))
+            .await?;
+        let plan = ctx
+            .create_physical_plan(&ctx.optimize(&test_plan())?)
+            .await?;
+        // The job of the other scheduler, as seen in the shared state backend
+        scheduler
+            .state
+            .task_manager
+            .submit_job(
+                &foreign_job,
+                &ctx.session_id(),
+                plan,
+                vec![],
+                None,
+                0,
+                false,
+            )
+            .await?;
+
+        let reservations = scheduler.state.executor_manager.reserve_slots(4).await?;
+        assert_eq!(reservations.len(), 4);
+        let event_action = scheduler.event_action.as_ref().unwrap();
+        assert!(event_action
+            .offer_reservation(reservations)
+            .await?
+            .is_none());
+
+        let graph = scheduler
+            .state
+            .task_manager
+            .get_execution_graph(&foreign_job)
+            .await?;
+        assert_eq!(graph.running_tasks(), 0);
+        // The slots are returned to the pool
+        assert_eq!(
+            scheduler
+                .state
+                .executor_manager
+                .reserve_slots(4)
+                .await?
+                .len(),
+            4
+        );
+        Ok(())
+    }
+
+    // An executor lost without registering again has the readers of its output switched
+    // to the copies kept by another executor once its lease is over
+    #[tokio::test]
+    async fn test_lost_executor_fail_over() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let mut scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+            SchedulerServer::new_with_policy(
+                state_storage,
+                "default".to_owned(),
+                TaskSchedulingPolicy::PullStaged,
+                BallistaCodec::default(),
+                default_session_builder,
+            )
+            .with_executor_lease(Duration::from_secs(1));
+        scheduler.init().await?;
+
+        let executors = test_executors(4);
+        let replica = executors[1].0.clone();
+        for (executor_metadata, executor_data) in executors {
+            scheduler
+                .state
+                .executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        let ctx = scheduler
+            .state
+            .session_manager
+            .create_session(&test_session(4))
+            .await?;
+        let plan = ctx
+            .create_physical_plan(&ctx.optimize(&test_plan())?)
+            .await?;
+        let job_id = "job";
+        scheduler
+            .state
+            .task_manager
+            .submit_job(job_id, &ctx.session_id(), plan, vec![], None, 0, true)
+            .await?;
+
+        let graph = scheduler
+            .state
+            .task_manager
+            .get_execution_graph(job_id)
+            .await?;
+        let first_stage = graph
+            .stages
+            .iter()
+            .find(|(_, stage)| stage.available_tasks() > 0)
+            .map(|(stage_id, _)| *stage_id)
+            .unwrap();
+
+        // Executor 1 writes the output of the first stage, which it copies to executor 2
+        loop {
+            let graph = scheduler
+                .state
+                .task_manager
+                .get_execution_graph(job_id)
+                .await?;
+            if graph.stages[&first_stage].available_tasks() == 0 {
+                break;
+            }
+            let reservations =
+                vec![ExecutorReservation::new_free("executor-1".to_owned())];
+            let (mut assignments, _, _) = scheduler
+                .state
+                .task_manager
+                .fill_reservations(&reservations)
+                .await?;
+            let (_, task) = assignments.pop().unwrap();
+            let num_partitions = task
+                .output_partitioning
+                .map(|p| p.partition_count())
+                .unwrap_or(1);
+            let partitions: Vec<ShuffleWritePartition> = (0..num_partitions)
+                .map(|partition_id| ShuffleWritePartition {
+                    partition_id: partition_id as u64,
+                    path: format!("/{}/{}", task.partition.partition_id, partition_id),
+                    num_batches: 1,
+                    num_rows: 1,
+                    num_bytes: 1,
+                })
+                .collect();
+            let task_status = TaskStatus {
+                status: Some(task_status::Status::Completed(CompletedTask {
+                    executor_id: "executor-1".to_owned(),
+                    bytes_scanned: 0,
+                    spilled_bytes: 0,
+                    spill_files: 0,
+                    bloom_filter: vec![],
+                    replica: Some(ShuffleReplica {
+                        executor: Some(replica.clone().into()),
+                        paths: partitions
+                            .iter()
+                            .map(|partition| format!("/replica{}", partition.path))
+                            .collect(),
+                    }),
+                    partitions,
+                    operator_metrics: vec![],
+                })),
+                task_id: Some(PartitionId {
+                    job_id: job_id.to_owned(),
+                    stage_id: task.partition.stage_id as u32,
+                    partition_id: task.partition.partition_id as u32,
+                }),
+                attempt: task.attempt,
+            };
+            scheduler
+                .update_task_status("executor-1", vec![task_status])
+                .await?;
+        }
+
+        // Executor 2 runs the next stage, while executor 1 stops sending heartbeats
+        let reservations = vec![ExecutorReservation::new_free("executor-2".to_owned())];
+        let (assignments, _, _) = scheduler
+            .state
+            .task_manager
+            .fill_reservations(&reservations)
+            .await?;
+        assert_eq!(assignments.len(), 1);
+
+        let check = || async {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            scheduler
+                .state
+                .executor_manager
+                .save_executor_heartbeat(ExecutorHeartbeat {
+                    executor_id: "executor-2".to_owned(),
+                    timestamp,
+                    state: None,
+                    version: 0,
+                })
+                .await?;
+            let graph = scheduler
+                .state
+                .task_manager
+                .get_execution_graph(job_id)
+                .await?;
+            Ok(graph.stages[&first_stage]
+                .task_infos
+                .iter()
+                .all(|info| info.executor_id.as_deref() == Some("executor-2")))
+        };
+        let failed_over = await_condition(Duration::from_millis(100), 50, check).await?;
+        assert!(failed_over, "Output of executor 1 not read from its copies");
+
+        // The output of the first stage is not written again
+        let graph = scheduler
+            .state
+            .task_manager
+            .get_execution_graph(job_id)
+            .await?;
+        assert!(graph.stages[&first_stage].complete());
+        assert_eq!(graph.available_tasks(), 0);
+        assert_eq!(graph.running_tasks(), 1);
+        Ok(())
+    }
+
+    // A task which could not be launched on the executor it was assigned to waits for
+    // another executor
+    #[tokio::test]
+    async fn test_return_task() -> Result<()> {
+        let scheduler = test_scheduler(TaskSchedulingPolicy::PullStaged).await?;
+        for (executor_metadata, executor_data) in test_executors(4) {
+            scheduler
+                .state
+                .executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        let ctx = scheduler
+            .state
+            .session_manager
+            .create_session(&test_session(4))
+            .await?;
+        let plan = ctx
+            .create_physical_plan(&ctx.optimize(&test_plan())?)
+            .await?;
+        let job_id = "job";
+        let task_manager = &scheduler.state.task_manager;
+        task_manager
+            .submit_job(job_id, &ctx.session_id(), plan, vec![], None, 0, true)
+            .await?;
+        let available = task_manager.get_available_task_count(job_id).await?;
+
+        let reservations = vec![ExecutorReservation::new_free("executor-1".to_owned())];
+        let (mut assignments, _, _) =
+            task_manager.fill_reservations(&reservations).await?;
+        let (_, task) = assignments.pop().unwrap();
+        let (partition, attempt) = (task.partition.clone(), task.attempt);
+        assert_eq!(
+            task_manager.get_available_task_count(job_id).await?,
+            available - 1
+        );
+
+        task_manager.return_task(task).await?;
+        let graph = task_manager.get_execution_graph(job_id).await?;
+        assert_eq!(graph.available_tasks(), available);
+        assert_eq!(graph.running_tasks(), 0);
+
+        let reservations = vec![ExecutorReservation::new_free("executor-2".to_owned())];
+        let (mut assignments, _, _) =
+            task_manager.fill_reservations(&reservations).await?;
+        let (executor_id, task) = assignments.pop().unwrap();
+        assert_eq!(executor_id, "executor-2");
+        // The task was not launched, so it is still the first attempt
+        assert_eq!(task.partition, partition);
+        assert_eq!(task.attempt, attempt);
+        Ok(())
+    }
+
+    // The tasks of a job run in its pool whichever scheduler assigns them
+    #[tokio::test]
+    async fn test_persist_job_pool() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let new_scheduler = || async {
+            let mut scheduler: SchedulerServer<LogicalPlanNode, PhysicalPlanNode> =
+                SchedulerServer::new_with_policy(
+                    state_storage.clone(),
+                    "default".to_owned(),
+                    TaskSchedulingPolicy::PullStaged,
+                    BallistaCodec::default(),
+                    default_session_builder,
+                );
+            scheduler.init().await?;
+            Ok::<_, BallistaError>(scheduler)
+        };
+        let scheduler = new_scheduler().await?;
+        for (mut executor_metadata, executor_data) in test_executors(4) {
+            if executor_metadata.id == "executor-2" {
+                executor_metadata
+                    .labels
+                    .insert(POOL_LABEL.to_owned(), "batch".to_owned());
+            }
+            scheduler
+                .state
+                .executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        let ctx = scheduler
+            .state
+            .session_manager
+            .create_session(&test_session(4))
+            .await?;
+        let plan = ctx
+            .create_physical_plan(&ctx.optimize(&test_plan())?)
+            .await?;
+        let job_id = "job";
+        let task_manager = &scheduler.state.task_manager;
+        task_manager.register_pool(job_id, "batch".to_owned());
+        task_manager
+            .submit_job(job_id, &ctx.session_id(), plan, vec![], None, 0, false)
+            .await?;
+
+        let other = new_scheduler().await?;
+        let reservations = vec![
+            ExecutorReservation::new_free("executor-1".to_owned()),
+            ExecutorReservation::new_free("executor-2".to_owned()),
+        ];
+        let (assignments, unassigned, _) = other
+            .state
+            .task_manager
+            .fill_reservations(&reservations)
+            .await?;
+        assert_eq!(assignments.len(), 1);
+        assert_eq!(assignments[0].0, "executor-2");
+        assert_eq!(unassigned.len(), 1);
+        assert_eq!(unassigned[0].executor_id, "executor-1");
+        Ok(())
+    }
+
     async fn test_scheduler(
         policy: TaskSchedulingPolicy,
     ) -> Result<SchedulerServer<LogicalPlanNode, PhysicalPlanNode>> {
@@ -786,6 +1901,8 @@ mod test {
                     port: 8080,
                     grpc_port: 9090,
                     specification: ExecutorSpecification { task_slots },
+                    labels: Default::default(),
+                    protocol: Default::default(),
                 },
                 ExecutorData {
                     executor_id: "executor-1".to_owned(),
@@ -802,6 +1919,8 @@ mod test {
                     specification: ExecutorSpecification {
                         task_slots: num_partitions as u32 - task_slots,
                     },
+                    labels: Default::default(),
+                    protocol: Default::default(),
                 },
                 ExecutorData {
                     executor_id: "executor-2".to_owned(),
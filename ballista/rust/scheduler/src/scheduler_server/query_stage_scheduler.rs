@@ -15,24 +15,54 @@
 // specific language governing permissions and limitations
 // under the License.
 
+use std::collections::{BTreeSet, HashMap};
+use std::convert::TryInto;
 use std::sync::Arc;
 use std::time::Instant;
 
 use async_trait::async_trait;
+use datafusion::execution::runtime_env::RuntimeEnv;
 use datafusion::logical_plan::LogicalPlan;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::repartition::RepartitionExec;
+use datafusion::physical_plan::{ExecutionPlan, Partitioning, PhysicalExpr};
 use datafusion::prelude::SessionContext;
-use log::{debug, error, info};
+use futures::TryStreamExt;
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
 
 use ballista_core::error::{BallistaError, Result};
 use ballista_core::event_loop::{EventAction, EventSender};
+use ballista_core::execution_plans::{fetch_job_output, ParquetSinkExec};
+use ballista_core::flight_transfer::IpcCompression;
+use ballista_core::logging::{with_log_context, LogContext};
+use ballista_core::serde::protobuf::{
+    job_status, CachedTable, FailedJob, FailureKind, JobStatus, KeyValuePair,
+};
+use ballista_core::table_format::{scanned_table_locations, TableCommit};
+use ballista_core::telemetry;
 
 use ballista_core::serde::AsExecutionPlan;
 use datafusion_proto::logical_plan::AsLogicalPlan;
 
-use crate::scheduler_server::event::{QueryStageSchedulerEvent, SchedulerServerEvent};
+use crate::api::model::JobSummary;
+use crate::event_log::{JobEvent, JobEventKind};
+use crate::explain::distributed_explain;
+use crate::plan_rewrite::RewriteContext;
+use crate::planner::{
+    plan_partial_aggregations, plan_shuffle_partitions, split_scan_tasks,
+    ScanTaskOptions, ShufflePartitionOptions,
+};
+use crate::scheduler_server::event::{
+    JobRetryPolicy, ParquetOutput, QueryStageSchedulerEvent, SchedulerServerEvent,
+};
+use crate::state::execution_graph::ExecutionGraph;
 
 use crate::state::executor_manager::ExecutorReservation;
+use crate::state::plan_cache::PlanKey;
+use crate::state::result_cache::ResultKey;
 use crate::state::SchedulerState;
+use crate::webhook::JobNotification;
 
 pub(crate) struct QueryStageScheduler<
     T: 'static + AsLogicalPlan,
@@ -40,6 +70,22 @@ pub(crate) struct QueryStageScheduler<
 > {
     state: Arc<SchedulerState<T, U>>,
     event_sender: Option<EventSender<SchedulerServerEvent>>,
+    /// Keys of the results of the running jobs whose output is cached once they complete
+    result_keys: Mutex<HashMap<String, ResultKey>>,
+    /// Retries of the jobs which are run again if they fail, by job ID
+    retries: Mutex<HashMap<String, JobRetry>>,
+    /// Sender of the events of this scheduler, queueing retried jobs once their backoff
+    /// has elapsed. Jobs are not retried until it is set.
+    retry_sender: Mutex<Option<EventSender<QueryStageSchedulerEvent>>>,
+}
+
+/// The retries of a job which is run again if it fails
+struct JobRetry {
+    policy: JobRetryPolicy,
+    /// Number of times the job was retried so far
+    retries: usize,
+    /// Event queueing the job, posted again for each retry
+    event: QueryStageSchedulerEvent,
 }
 
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> QueryStageScheduler<T, U> {
@@ -50,73 +96,589 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> QueryStageSchedul
         Self {
             state,
             event_sender,
+            result_keys: Mutex::new(HashMap::new()),
+            retries: Mutex::new(HashMap::new()),
+            retry_sender: Mutex::new(None),
         }
     }
 
+    /// Queue the retries of failed jobs with `sender`, the sender of the event loop of
+    /// this scheduler
+    pub(crate) fn set_retry_sender(&self, sender: EventSender<QueryStageSchedulerEvent>) {
+        *self.retry_sender.lock() = Some(sender);
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn submit_job(
         &self,
         job_id: String,
         session_id: String,
         session_ctx: Arc<SessionContext>,
         plan: &LogicalPlan,
-    ) -> Result<()> {
+        trace_context: Vec<KeyValuePair>,
+        identity: Option<String>,
+        table_locations: HashMap<String, BTreeSet<String>>,
+        parquet_output: Option<ParquetOutput>,
+        plan_key: Option<PlanKey>,
+        cache_result: bool,
+        shuffle_partitions: Option<ShufflePartitionOptions>,
+        scan_tasks: Option<ScanTaskOptions>,
+        partial_aggregation_max_groups: Option<usize>,
+        bloom_filter_bytes: Option<usize>,
+        job_priority: u32,
+        replicate_shuffles: bool,
+    ) -> Result<Option<QueryStageSchedulerEvent>> {
         let start = Instant::now();
-        let optimized_plan = session_ctx.optimize(plan)?;
+        let mut span = telemetry::start_span(
+            "plan_job",
+            &telemetry::extract_context(&trace_context),
+        );
+        let result = self
+            .plan_job(
+                &job_id,
+                &session_id,
+                session_ctx,
+                plan,
+                trace_context,
+                identity,
+                table_locations,
+                parquet_output,
+                plan_key,
+                cache_result,
+                shuffle_partitions,
+                scan_tasks,
+                partial_aggregation_max_groups,
+                bloom_filter_bytes,
+                job_priority,
+                replicate_shuffles,
+            )
+            .await;
+        telemetry::end_span(
+            &mut span,
+            result.as_ref().err().map(|e| e.to_string()).as_deref(),
+        );
+        let event = result?;
 
-        debug!("Calculated optimized plan: {:?}", optimized_plan);
+        let elapsed = start.elapsed();
+        self.state
+            .metrics
+            .planning_latency
+            .observe(elapsed.as_secs_f64());
 
-        let plan = session_ctx.create_physical_plan(&optimized_plan).await?;
+        info!("Planned job {} in {:?}", job_id, elapsed);
 
-        self.state
+        Ok(event)
+    }
+
+    /// Plan job `job_id` and submit it, returning the event of its submission, or of its
+    /// completion if it completes with a cached result. Returns `None` if the job was
+    /// cancelled while it was queued.
+    #[allow(clippy::too_many_arguments)]
+    async fn plan_job(
+        &self,
+        job_id: &str,
+        session_id: &str,
+        session_ctx: Arc<SessionContext>,
+        plan: &LogicalPlan,
+        trace_context: Vec<KeyValuePair>,
+        identity: Option<String>,
+        table_locations: HashMap<String, BTreeSet<String>>,
+        parquet_output: Option<ParquetOutput>,
+        plan_key: Option<PlanKey>,
+        cache_result: bool,
+        shuffle_partitions: Option<ShufflePartitionOptions>,
+        scan_tasks: Option<ScanTaskOptions>,
+        partial_aggregation_max_groups: Option<usize>,
+        bloom_filter_bytes: Option<usize>,
+        job_priority: u32,
+        replicate_shuffles: bool,
+    ) -> Result<Option<QueryStageSchedulerEvent>> {
+        // EXPLAIN is answered with the stages of the job of the explained plan, which is
+        // planned like any other but not run
+        let explain = match plan {
+            LogicalPlan::Explain(explain) => Some(explain),
+            _ => None,
+        };
+        let plan = explain.map_or(plan, |explain| explain.plan.as_ref());
+        let rewriters = &self.state.plan_rewriters;
+        let mut table_locations = table_locations;
+        for (table, locations) in scanned_table_locations(plan)? {
+            table_locations.entry(table).or_default().extend(locations);
+        }
+        let context = RewriteContext {
+            job_id,
+            identity: identity.as_deref(),
+            table_locations: &table_locations,
+            session_ctx: &session_ctx,
+        };
+        // Rewritten plans may differ by identity, so they are not cached
+        let plan_key = plan_key.filter(|_| rewriters.is_empty() && explain.is_none());
+        let mut optimized_plan = None;
+        let cached_plan = plan_key
+            .as_ref()
+            .and_then(|key| self.state.plan_cache.physical_plan(key));
+        let mut plan = match cached_plan {
+            Some(plan) => {
+                debug!("Using the cached physical plan of job {}", job_id);
+                plan
+            }
+            None => {
+                let plan = rewriters.rewrite_logical_plan(plan.clone(), &context)?;
+                let optimized = session_ctx.optimize(&plan)?;
+
+                debug!("Calculated optimized plan: {:?}", optimized);
+
+                let plan = session_ctx.create_physical_plan(&optimized).await?;
+                let plan = rewriters.rewrite_physical_plan(plan, &context)?;
+                if let Some(key) = &plan_key {
+                    self.state
+                        .plan_cache
+                        .insert_physical_plan(key, plan.clone());
+                }
+                optimized_plan = Some(optimized);
+                plan
+            }
+        };
+        // Compaction jobs write the files each scan partition reads to a single file, so
+        // their scans keep the partitions planned by their table
+        let compaction = parquet_output
+            .as_ref()
+            .map_or(false, |output| !output.repartition);
+        if let Some(options) = scan_tasks.filter(|_| !compaction) {
+            plan = split_scan_tasks(plan, &options)?;
+        }
+        if let Some(max_groups_percent) = partial_aggregation_max_groups {
+            plan = plan_partial_aggregations(plan, max_groups_percent)?;
+        }
+        if let Some(options) = &shuffle_partitions {
+            let pool = self.state.task_manager.job_pool(job_id);
+            let total_slots = self
+                .state
+                .executor_manager
+                .total_task_slots_in_pool(&pool)
+                .await?;
+            plan = plan_shuffle_partitions(plan, options, total_slots)?;
+        }
+
+        let result_key = if cache_result
+            && explain.is_none()
+            && parquet_output.is_none()
+            && self.state.result_cache.is_enabled()
+        {
+            ResultKey::try_new(plan.as_ref())
+        } else {
+            None
+        };
+        if let Some(key) = &result_key {
+            let alive = self.state.executor_manager.get_alive_executors();
+            if let Some(result) = self.state.result_cache.get(key, &alive) {
+                self.state.metrics.result_cache_hits.inc();
+                info!(
+                    "Completing job {} with the result of job {}",
+                    job_id, result.job_id
+                );
+                let submitted = self
+                    .state
+                    .task_manager
+                    .submit_completed_job(
+                        job_id,
+                        session_id,
+                        plan,
+                        result.partition_location,
+                    )
+                    .await?;
+                return Ok(submitted
+                    .then(|| QueryStageSchedulerEvent::JobFinished(job_id.to_owned())));
+            }
+            self.state.metrics.result_cache_misses.inc();
+        }
+
+        if let Some(output) = &parquet_output {
+            plan = parquet_sink(job_id, &session_ctx, plan, output)?;
+        }
+        let parquet_output = parquet_output.filter(|_| explain.is_none());
+        if let Some(explain) = explain {
+            let graph = ExecutionGraph::new_with_bloom_filters(
+                job_id,
+                session_id,
+                plan,
+                bloom_filter_bytes,
+            )?;
+            plan = distributed_explain(explain, optimized_plan.as_ref(), &graph);
+        }
+
+        // The files written by the job are committed once it completes, by whichever
+        // scheduler completes it
+        if let Some(output) = &parquet_output {
+            self.state
+                .task_manager
+                .save_parquet_write(job_id, output.table_commit.as_deref())
+                .await?;
+        }
+        let submitted = self
+            .state
             .task_manager
-            .submit_job(&job_id, &session_id, plan.clone())
+            .submit_job(
+                job_id,
+                session_id,
+                plan,
+                trace_context,
+                bloom_filter_bytes,
+                job_priority,
+                replicate_shuffles,
+            )
             .await?;
+        if !submitted {
+            if parquet_output.is_some() {
+                self.state.task_manager.remove_parquet_write(job_id).await?;
+            }
+            return Ok(None);
+        }
+        if let Some(key) = result_key {
+            self.result_keys.lock().insert(job_id.to_owned(), key);
+        }
+        Ok(Some(QueryStageSchedulerEvent::JobSubmitted(
+            job_id.to_owned(),
+        )))
+    }
 
-        let elapsed = start.elapsed();
+    /// Cache the output of job `job_id` as the result of its plan, if it may be cached
+    async fn cache_result(&self, job_id: &str) -> Result<()> {
+        let key = match self.result_keys.lock().remove(job_id) {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let graph = self.state.task_manager.get_execution_graph(job_id).await?;
+        debug!(
+            "Caching the output of job {} as result {}",
+            job_id,
+            key.fingerprint()
+        );
+        self.state
+            .result_cache
+            .insert(key, job_id, graph.output_locations());
+        Ok(())
+    }
 
-        info!("Planned job {} in {:?}", job_id, elapsed);
+    /// Commit the Parquet files written by job `job_id`, if it writes its output to
+    /// Parquet files
+    async fn commit_output(&self, job_id: &str) -> Result<()> {
+        let table_commit = match self.state.task_manager.parquet_write(job_id).await? {
+            Some(table_commit) => table_commit,
+            None => return Ok(()),
+        };
+        let committed = self.commit_parquet_files(job_id, table_commit).await;
+        // A job whose commit failed is failed, so its files are never committed
+        if let Err(e) = self.state.task_manager.remove_parquet_write(job_id).await {
+            warn!(
+                "Could not remove the Parquet write of job {}: {:?}",
+                job_id, e
+            );
+        }
+        committed
+    }
 
+    async fn commit_parquet_files(
+        &self,
+        job_id: &str,
+        table_commit: Option<Arc<dyn TableCommit>>,
+    ) -> Result<()> {
+        let graph = self.state.task_manager.get_execution_graph(job_id).await?;
+        // Only the output of the attempt of each task whose status was accepted is
+        // committed
+        let (sink, attempts) = graph
+            .stages
+            .values()
+            .find_map(|stage| {
+                let sink = find_parquet_sink(stage.plan.as_ref())?;
+                let attempts: Vec<u32> =
+                    stage.task_infos.iter().map(|info| info.attempts).collect();
+                Some((sink, attempts))
+            })
+            .ok_or_else(|| {
+                BallistaError::Internal(format!(
+                    "Job {} has no ParquetSinkExec to commit",
+                    job_id
+                ))
+            })?;
+        let runtime = RuntimeEnv::default();
+        self.state.object_store_factories.register_stores(
+            &runtime,
+            &self.state.task_manager.object_store_credentials(job_id),
+        )?;
+        let files = match table_commit {
+            Some(table_commit) => {
+                self.state
+                    .task_manager
+                    .commit_to_table(&sink, &runtime, &attempts, table_commit.as_ref())
+                    .await?
+            }
+            None => sink.commit(&runtime, &attempts).await?.files,
+        };
+        info!(
+            "Committed {} Parquet files written by job {} to {}",
+            files.len(),
+            job_id,
+            sink.path()
+        );
         Ok(())
     }
-}
 
-#[async_trait]
-impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
-    EventAction<QueryStageSchedulerEvent> for QueryStageScheduler<T, U>
-{
-    fn on_start(&self) {
-        info!("Starting QueryStageScheduler");
+    /// Save the output of job `job_id` as the cached table it was run for, if any, and
+    /// remove the output of the job of the table it replaces from the executors
+    async fn cache_output(&self, job_id: &str) -> Result<()> {
+        let name = match self.state.task_manager.cached_table(job_id) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let graph = self.state.task_manager.get_execution_graph(job_id).await?;
+        let partition_location = graph
+            .output_locations()
+            .into_iter()
+            .map(|location| location.try_into())
+            .collect::<Result<Vec<_>>>()?;
+        info!(
+            "Caching the {} partitions of job {} as table {}",
+            partition_location.len(),
+            job_id,
+            name
+        );
+        let table = CachedTable {
+            name,
+            job_id: job_id.to_owned(),
+            partition_location,
+        };
+        if let Some(previous) = self.state.table_cache.put(table).await? {
+            self.state.remove_cached_table_data(&previous).await;
+        }
+        Ok(())
     }
 
-    fn on_stop(&self) {
-        info!("Stopping QueryStageScheduler")
+    /// Save the statistics of the table analyzed by job `job_id`, if any, which are
+    /// estimated from the output of the job fetched from the executors
+    async fn save_table_statistics(&self, job_id: &str) -> Result<()> {
+        let analysis = match self.state.task_manager.table_analysis(job_id) {
+            Some(analysis) => analysis,
+            None => return Ok(()),
+        };
+        let graph = self.state.task_manager.get_execution_graph(job_id).await?;
+        let partition_location = graph
+            .output_locations()
+            .into_iter()
+            .map(|location| location.try_into())
+            .collect::<Result<Vec<_>>>()?;
+        let batches: Vec<_> = fetch_job_output(partition_location, IpcCompression::None)
+            .try_collect()
+            .await?;
+        let statistics = analysis.statistics(&batches)?;
+        info!(
+            "Job {} estimated table {} to have {} rows",
+            job_id,
+            analysis.location(),
+            statistics.num_rows
+        );
+        self.state.table_statistics.put(statistics).await
     }
 
-    async fn on_receive(
+    /// Queue job `job_id` again once its backoff has elapsed, if it failed with an
+    /// error which may be transient and has retries left. Returns whether it is retried.
+    async fn retry_job(&self, job_id: &str, failure: &FailedJob) -> bool {
+        let transient = !matches!(
+            failure.kind(),
+            FailureKind::PlanningError
+                | FailureKind::SerdeError
+                | FailureKind::Cancelled
+                | FailureKind::Timeout
+        );
+        if !transient || self.state.shutting_down() {
+            return false;
+        }
+        let sender = match self.retry_sender.lock().clone() {
+            Some(sender) => sender,
+            None => return false,
+        };
+        let (event, retry, max_retries, backoff) = {
+            let mut retries = self.retries.lock();
+            let job_retry = match retries.get_mut(job_id) {
+                Some(job_retry) if job_retry.retries < job_retry.policy.max_retries => {
+                    job_retry
+                }
+                _ => return false,
+            };
+            job_retry.retries += 1;
+            (
+                job_retry.event.clone(),
+                job_retry.retries,
+                job_retry.policy.max_retries,
+                job_retry.policy.backoff(job_retry.retries),
+            )
+        };
+
+        if let Err(e) = self.state.task_manager.requeue_job(job_id).await {
+            warn!("Failed to queue job {} again: {:?}", job_id, e);
+            return false;
+        }
+        warn!(
+            "Retrying job {} ({}/{}) in {:?} after it failed: {}",
+            job_id, retry, max_retries, backoff, failure.error
+        );
+        self.log_event(JobEvent::retried(job_id, &failure.error))
+            .await;
+        let job_id = job_id.to_owned();
+        tokio::spawn(async move {
+            tokio::time::sleep(backoff).await;
+            if let Err(e) = sender.post_event(event).await {
+                error!("Failed to queue retry of job {}: {:?}", job_id, e);
+            }
+        });
+        true
+    }
+
+    async fn log_event(&self, event: JobEvent) {
+        if let Some(event_log) = &self.state.event_log {
+            if let Err(e) = event_log.log_event(&event).await {
+                warn!(
+                    "Failed to log {:?} event of job {}: {:?}",
+                    event.kind, event.job_id, e
+                );
+            }
+        }
+    }
+
+    /// Release the task slots reserved by job `job_id`, granting them to the jobs of
+    /// its pool waiting for their reservation
+    async fn release_slots(&self, job_id: &str) {
+        let pool = match self.state.task_manager.reserved_slots_pool(job_id) {
+            Some(pool) => pool,
+            None => return,
+        };
+        let total_slots = match self
+            .state
+            .executor_manager
+            .total_task_slots_in_pool(&pool)
+            .await
+        {
+            Ok(total_slots) => total_slots,
+            Err(e) => {
+                warn!("Failed to count the task slots of the executors: {:?}", e);
+                0
+            }
+        };
+        self.state
+            .task_manager
+            .release_job_slots(job_id, total_slots);
+    }
+
+    async fn log_summary(&self, graph: &ExecutionGraph, status: &JobStatus) {
+        if let Some(event_log) = &self.state.event_log {
+            let summary = JobSummary::new(graph, status);
+            if let Err(e) = event_log.write_summary(&summary).await {
+                warn!("Failed to log summary of job {}: {:?}", graph.job_id(), e);
+            }
+        }
+    }
+
+    /// Whether the execution graph of a finished job has to be loaded to record its
+    /// outcome, `notify` being whether a callback URL is notified about it
+    fn needs_graph(&self, notify: bool) -> bool {
+        self.state.event_log.is_some()
+            || self.state.audit_log.is_some()
+            || self.state.query_history.is_some()
+            || notify
+    }
+
+    async fn handle_event(
         &self,
         event: QueryStageSchedulerEvent,
     ) -> Result<Option<QueryStageSchedulerEvent>> {
+        if let QueryStageSchedulerEvent::JobQueued {
+            job_id,
+            retry: Some(policy),
+            ..
+        } = &event
+        {
+            // Retries queue the job with the event which first queued it
+            self.retries
+                .lock()
+                .entry(job_id.clone())
+                .or_insert_with(|| JobRetry {
+                    policy: *policy,
+                    retries: 0,
+                    event: event.clone(),
+                });
+        }
+
         match event {
             QueryStageSchedulerEvent::JobQueued {
                 job_id,
                 session_id,
                 session_ctx,
                 plan,
+                trace_context,
+                identity,
+                table_locations,
+                parquet_output,
+                plan_key,
+                cache_result,
+                shuffle_partitions,
+                scan_tasks,
+                partial_aggregation_max_groups,
+                bloom_filter_bytes,
+                job_priority,
+                replicate_shuffles,
+                ..
             } => {
                 info!("Job {} queued", job_id);
-                return if let Err(e) = self
-                    .submit_job(job_id.clone(), session_id, session_ctx, &plan)
+                self.log_event(JobEvent::new(&job_id, JobEventKind::Queued))
+                    .await;
+                return match self
+                    .submit_job(
+                        job_id.clone(),
+                        session_id,
+                        session_ctx,
+                        &plan,
+                        trace_context,
+                        identity,
+                        table_locations,
+                        parquet_output,
+                        plan_key,
+                        cache_result,
+                        shuffle_partitions,
+                        scan_tasks,
+                        partial_aggregation_max_groups,
+                        bloom_filter_bytes,
+                        job_priority,
+                        replicate_shuffles,
+                    )
                     .await
                 {
-                    let msg = format!("Error planning job {}: {:?}", job_id, e);
-                    error!("{}", msg);
-                    Ok(Some(QueryStageSchedulerEvent::JobFailed(job_id, msg)))
-                } else {
-                    Ok(Some(QueryStageSchedulerEvent::JobSubmitted(job_id)))
+                    Ok(Some(event)) => Ok(Some(event)),
+                    Ok(None) => {
+                        info!("Job {} was cancelled while it was queued", job_id);
+                        Ok(None)
+                    }
+                    Err(e) => {
+                        let msg = format!("Error planning job {}: {:?}", job_id, e);
+                        error!("{}", msg);
+                        let kind = match FailureKind::from(&e) {
+                            FailureKind::SerdeError => FailureKind::SerdeError,
+                            _ => FailureKind::PlanningError,
+                        };
+                        Ok(Some(QueryStageSchedulerEvent::JobFailed(
+                            job_id,
+                            FailedJob {
+                                error: msg,
+                                kind: kind.into(),
+                            },
+                        )))
+                    }
                 };
             }
             QueryStageSchedulerEvent::JobSubmitted(job_id) => {
                 info!("Job {} submitted", job_id);
+                self.state.metrics.jobs_submitted.inc();
+                self.log_event(JobEvent::new(&job_id, JobEventKind::Submitted))
+                    .await;
                 if let Some(sender) = &self.event_sender {
                     let available_tasks = self
                         .state
@@ -152,20 +714,191 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
                 }
             }
             QueryStageSchedulerEvent::JobFinished(job_id) => {
+                let output = async {
+                    self.commit_output(&job_id).await?;
+                    self.cache_output(&job_id).await?;
+                    self.save_table_statistics(&job_id).await
+                }
+                .await;
+                if let Err(e) = output {
+                    let msg =
+                        format!("Error committing output of job {}: {:?}", job_id, e);
+                    error!("{}", msg);
+                    return Ok(Some(QueryStageSchedulerEvent::JobFailed(
+                        job_id,
+                        FailedJob {
+                            error: msg,
+                            kind: FailureKind::ExecutionError.into(),
+                        },
+                    )));
+                }
                 info!("Job {} complete", job_id);
+                self.retries.lock().remove(&job_id);
+                let callback_url = self.state.job_notifier.take_url(&job_id);
+                self.state.metrics.jobs_completed.inc();
+                // Reserved slots are released even if the job fails to be moved
+                self.release_slots(&job_id).await;
                 self.state.task_manager.complete_job(&job_id).await?;
+                if let Err(e) = self.cache_result(&job_id).await {
+                    warn!("Failed to cache the result of job {}: {:?}", job_id, e);
+                }
+                self.log_event(JobEvent::new(&job_id, JobEventKind::Completed))
+                    .await;
+                if self.needs_graph(callback_url.is_some()) {
+                    let graph =
+                        self.state.task_manager.get_execution_graph(&job_id).await?;
+                    let status = graph.status();
+                    self.log_summary(&graph, &status).await;
+                    if let Some(audit_log) = &self.state.audit_log {
+                        audit_log.finished(&job_id, &status, Some(&graph)).await;
+                    }
+                    if let Some(query_history) = &self.state.query_history {
+                        query_history.finished(&job_id, &status, Some(&graph)).await;
+                    }
+                    if let Some(url) = callback_url {
+                        self.state.job_notifier.notify(
+                            url,
+                            JobNotification::new(&job_id, &status, Some(&graph)),
+                        );
+                    }
+                }
             }
-            QueryStageSchedulerEvent::JobFailed(job_id, fail_message) => {
-                error!("Job {} failed: {}", job_id, fail_message);
-                self.state
-                    .task_manager
-                    .fail_job(&job_id, fail_message)
-                    .await?;
+            QueryStageSchedulerEvent::JobFailed(job_id, failure) => {
+                if let Err(e) =
+                    self.state.task_manager.remove_parquet_write(&job_id).await
+                {
+                    warn!(
+                        "Could not remove the Parquet write of job {}: {:?}",
+                        job_id, e
+                    );
+                }
+                self.result_keys.lock().remove(&job_id);
+                if self.retry_job(&job_id, &failure).await {
+                    return Ok(None);
+                }
+                self.retries.lock().remove(&job_id);
+                let callback_url = self.state.job_notifier.take_url(&job_id);
+                error!(
+                    "Job {} failed ({:?}): {}",
+                    job_id,
+                    failure.kind(),
+                    failure.error
+                );
+                self.state.metrics.jobs_failed.inc();
+
+                // The execution graph of a failed job is removed from the state, so
+                // capture it for the event log and notification first. Jobs failing
+                // during planning do not have one.
+                let graph = if self.needs_graph(callback_url.is_some()) {
+                    self.state
+                        .task_manager
+                        .get_execution_graph(&job_id)
+                        .await
+                        .ok()
+                } else {
+                    None
+                };
+                let event = JobEvent::failed(&job_id, &failure.error);
+                let status = JobStatus {
+                    status: Some(job_status::Status::Failed(failure.clone())),
+                };
+
+                self.release_slots(&job_id).await;
+                self.state.task_manager.fail_job(&job_id, failure).await?;
+                self.log_event(event).await;
+                if let Some(graph) = &graph {
+                    self.log_summary(graph, &status).await;
+                }
+                if let Some(audit_log) = &self.state.audit_log {
+                    audit_log.finished(&job_id, &status, graph.as_ref()).await;
+                }
+                if let Some(query_history) = &self.state.query_history {
+                    query_history
+                        .finished(&job_id, &status, graph.as_ref())
+                        .await;
+                }
+                if let Some(url) = callback_url {
+                    self.state.job_notifier.notify(
+                        url,
+                        JobNotification::new(&job_id, &status, graph.as_ref()),
+                    );
+                }
             }
         }
 
         Ok(None)
     }
+}
+
+/// Wrap `plan` in a [ParquetSinkExec] writing its output to Parquet files. Rows are
+/// first repartitioned by the partition columns, so that each directory of the output
+/// is written by a single task.
+fn parquet_sink(
+    job_id: &str,
+    session_ctx: &SessionContext,
+    plan: Arc<dyn ExecutionPlan>,
+    output: &ParquetOutput,
+) -> Result<Arc<dyn ExecutionPlan>> {
+    // Compaction jobs write the files each scan partition coalesces as they are read
+    let repartition = output.repartition && !output.partition_by.is_empty();
+    let input: Arc<dyn ExecutionPlan> = if !repartition {
+        plan
+    } else {
+        let schema = plan.schema();
+        let columns = output
+            .partition_by
+            .iter()
+            .map(|name| {
+                Ok(Arc::new(Column::new(name, schema.index_of(name)?))
+                    as Arc<dyn PhysicalExpr>)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let partitions = session_ctx.copied_config().target_partitions;
+        Arc::new(RepartitionExec::try_new(
+            plan,
+            Partitioning::Hash(columns, partitions),
+        )?)
+    };
+    Ok(Arc::new(
+        ParquetSinkExec::try_new(
+            input,
+            output.path.clone(),
+            output.partition_by.clone(),
+            job_id.to_owned(),
+        )?
+        .with_field_ids(output.field_ids.clone()),
+    ))
+}
+
+fn find_parquet_sink(plan: &dyn ExecutionPlan) -> Option<ParquetSinkExec> {
+    match plan.as_any().downcast_ref::<ParquetSinkExec>() {
+        Some(sink) => Some(sink.clone()),
+        None => plan
+            .children()
+            .iter()
+            .find_map(|child| find_parquet_sink(child.as_ref())),
+    }
+}
+
+#[async_trait]
+impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>
+    EventAction<QueryStageSchedulerEvent> for QueryStageScheduler<T, U>
+{
+    fn on_start(&self) {
+        info!("Starting QueryStageScheduler");
+    }
+
+    fn on_stop(&self) {
+        info!("Stopping QueryStageScheduler")
+    }
+
+    async fn on_receive(
+        &self,
+        event: QueryStageSchedulerEvent,
+    ) -> Result<Option<QueryStageSchedulerEvent>> {
+        let log_context = LogContext::job(event.job_id());
+        with_log_context(log_context, self.handle_event(event)).await
+    }
 
     fn on_error(&self, error: BallistaError) {
         error!("Error received by QueryStageScheduler: {:?}", error);
@@ -0,0 +1,233 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Parsing of the SQL expressions of the access policies of the scheduler, and of the
+//! predicates of DELETE and UPDATE statements.
+//!
+//! Policies are written as SQL expressions over the columns of a single table, such as
+//! `region = 'EU' AND amount < 1000`. Columns, literals, comparisons, arithmetic,
+//! `AND`, `OR`, `NOT`, `IS [NOT] NULL`, `[NOT] IN (...)`, `[NOT] BETWEEN`, `CASE` and
+//! the built-in scalar functions of DataFusion, such as `md5(email)`, are supported.
+
+use ballista_core::error::{BallistaError, Result};
+use datafusion::logical_expr::BuiltinScalarFunction;
+use datafusion::logical_plan::{binary_expr, col, lit, Expr, Operator};
+use datafusion::scalar::ScalarValue;
+use sqlparser::ast::{
+    BinaryOperator, Expr as SqlExpr, Function, FunctionArg, FunctionArgExpr,
+    UnaryOperator, Value as SqlValue,
+};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::Parser;
+use sqlparser::tokenizer::{Token, Tokenizer};
+use std::str::FromStr;
+
+/// Parse `sql`, a SQL expression
+pub fn parse_sql_expr(sql: &str) -> Result<Expr> {
+    let dialect = GenericDialect {};
+    let tokens = Tokenizer::new(&dialect, sql).tokenize().map_err(|e| {
+        BallistaError::General(format!("Could not parse expression {}: {:?}", sql, e))
+    })?;
+    let mut parser = Parser::new(tokens, &dialect);
+    let expr = parser.parse_expr()?;
+    let token = parser.peek_token();
+    if token != Token::EOF {
+        return Err(BallistaError::General(format!(
+            "Unexpected {} at the end of expression {}",
+            token, sql
+        )));
+    }
+    to_expr(&expr)
+}
+
+fn to_expr(expr: &SqlExpr) -> Result<Expr> {
+    Ok(match expr {
+        SqlExpr::Identifier(ident) => col(&ident.value),
+        SqlExpr::CompoundIdentifier(idents) => {
+            let names: Vec<&str> =
+                idents.iter().map(|ident| ident.value.as_str()).collect();
+            col(&names.join("."))
+        }
+        SqlExpr::Value(value) => to_literal(value)?,
+        SqlExpr::Nested(expr) => to_expr(expr)?,
+        SqlExpr::BinaryOp { left, op, right } => {
+            binary_expr(to_expr(left)?, to_operator(op)?, to_expr(right)?)
+        }
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Not,
+            expr,
+        } => Expr::Not(Box::new(to_expr(expr)?)),
+        SqlExpr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => Expr::Negative(Box::new(to_expr(expr)?)),
+        SqlExpr::IsNull(expr) => Expr::IsNull(Box::new(to_expr(expr)?)),
+        SqlExpr::IsNotNull(expr) => Expr::IsNotNull(Box::new(to_expr(expr)?)),
+        SqlExpr::InList {
+            expr,
+            list,
+            negated,
+        } => Expr::InList {
+            expr: Box::new(to_expr(expr)?),
+            list: list.iter().map(to_expr).collect::<Result<_>>()?,
+            negated: *negated,
+        },
+        SqlExpr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => Expr::Between {
+            expr: Box::new(to_expr(expr)?),
+            negated: *negated,
+            low: Box::new(to_expr(low)?),
+            high: Box::new(to_expr(high)?),
+        },
+        SqlExpr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => Expr::Case {
+            expr: operand.as_ref().map(|e| to_boxed_expr(e)).transpose()?,
+            when_then_expr: conditions
+                .iter()
+                .zip(results)
+                .map(|(when, then)| Ok((to_boxed_expr(when)?, to_boxed_expr(then)?)))
+                .collect::<Result<_>>()?,
+            else_expr: else_result.as_ref().map(|e| to_boxed_expr(e)).transpose()?,
+        },
+        SqlExpr::Function(function) if function.over.is_none() && !function.distinct => {
+            to_function(function)?
+        }
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Unsupported expression in policy: {}",
+                other
+            )))
+        }
+    })
+}
+
+fn to_boxed_expr(expr: &SqlExpr) -> Result<Box<Expr>> {
+    Ok(Box::new(to_expr(expr)?))
+}
+
+fn to_function(function: &Function) -> Result<Expr> {
+    let name = function.name.to_string().to_lowercase();
+    let fun = BuiltinScalarFunction::from_str(&name).map_err(|_| {
+        BallistaError::NotImplemented(format!("Unsupported function in policy: {}", name))
+    })?;
+    let args = function
+        .args
+        .iter()
+        .map(|arg| match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(arg)) => to_expr(arg),
+            other => Err(BallistaError::NotImplemented(format!(
+                "Unsupported function argument in policy: {}",
+                other
+            ))),
+        })
+        .collect::<Result<_>>()?;
+    Ok(Expr::ScalarFunction { fun, args })
+}
+
+fn to_literal(value: &SqlValue) -> Result<Expr> {
+    Ok(match value {
+        SqlValue::Number(n, _) => match (n.parse::<i64>(), n.parse::<f64>()) {
+            (Ok(n), _) => lit(n),
+            (_, Ok(n)) => lit(n),
+            _ => return Err(BallistaError::General(format!("Invalid number {}", n))),
+        },
+        SqlValue::SingleQuotedString(s) => lit(s.clone()),
+        SqlValue::Boolean(b) => lit(*b),
+        SqlValue::Null => Expr::Literal(ScalarValue::Null),
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Unsupported literal in policy: {}",
+                other
+            )))
+        }
+    })
+}
+
+fn to_operator(op: &BinaryOperator) -> Result<Operator> {
+    Ok(match op {
+        BinaryOperator::Eq => Operator::Eq,
+        BinaryOperator::NotEq => Operator::NotEq,
+        BinaryOperator::Lt => Operator::Lt,
+        BinaryOperator::LtEq => Operator::LtEq,
+        BinaryOperator::Gt => Operator::Gt,
+        BinaryOperator::GtEq => Operator::GtEq,
+        BinaryOperator::And => Operator::And,
+        BinaryOperator::Or => Operator::Or,
+        BinaryOperator::Plus => Operator::Plus,
+        BinaryOperator::Minus => Operator::Minus,
+        BinaryOperator::Multiply => Operator::Multiply,
+        BinaryOperator::Divide => Operator::Divide,
+        BinaryOperator::Modulo => Operator::Modulo,
+        other => {
+            return Err(BallistaError::NotImplemented(format!(
+                "Unsupported operator in policy: {}",
+                other
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policy_expressions() -> Result<()> {
+        assert_eq!(
+            parse_sql_expr("region = 'EU' AND (amount < 1000 OR vip)")?,
+            col("region")
+                .eq(lit("EU"))
+                .and(col("amount").lt(lit(1000_i64)).or(col("vip")))
+        );
+        assert_eq!(
+            parse_sql_expr("o.tenant IN ('acme', 'initech')")?,
+            col("o.tenant").in_list(vec![lit("acme"), lit("initech")], false)
+        );
+        assert_eq!(
+            parse_sql_expr("deleted_at IS NULL")?,
+            col("deleted_at").is_null()
+        );
+
+        assert_eq!(
+            parse_sql_expr("CASE WHEN vip THEN MD5(email) ELSE '***' END")?,
+            Expr::Case {
+                expr: None,
+                when_then_expr: vec![(
+                    Box::new(col("vip")),
+                    Box::new(Expr::ScalarFunction {
+                        fun: BuiltinScalarFunction::MD5,
+                        args: vec![col("email")],
+                    }),
+                )],
+                else_expr: Some(Box::new(lit("***"))),
+            }
+        );
+
+        assert!(parse_sql_expr("region = 'EU' extra").is_err());
+        assert!(parse_sql_expr("no_such_function(region)").is_err());
+        assert!(parse_sql_expr("region LIKE 'E%'").is_err());
+        Ok(())
+    }
+}
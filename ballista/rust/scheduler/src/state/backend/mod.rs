@@ -59,6 +59,16 @@ pub enum Keyspace {
     Slots,
     Sessions,
     Heartbeats,
+    Streams,
+    CachedTables,
+    ExecutorEpochs,
+    TableStatistics,
+    DrainingExecutors,
+    JobOwners,
+    ParquetWrites,
+    TableCommits,
+    JobDataRemovals,
+    CatalogTables,
 }
 
 /// A trait that contains the necessary methods to save and retrieve the state and configuration of a cluster.
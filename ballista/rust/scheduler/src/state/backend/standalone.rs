@@ -15,7 +15,7 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::{sync::Arc, task::Poll};
 
 use ballista_core::error::{ballista_error, BallistaError, Result};
@@ -23,15 +23,51 @@ use ballista_core::error::{ballista_error, BallistaError, Result};
 use futures::{FutureExt, Stream};
 use log::warn;
 use sled_package as sled;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedMutexGuard};
 
 use crate::state::backend::{Keyspace, Lock, StateBackendClient, Watch, WatchEvent};
+use crate::state::sharded_map::ShardedMap;
 
 /// A [`StateBackendClient`] implementation that uses file-based storage to save cluster configuration.
 #[derive(Clone)]
 pub struct StandaloneClient {
     db: sled::Db,
-    locks: Arc<Mutex<HashMap<String, Arc<Mutex<()>>>>>,
+    /// Mutexes of the keys which are locked or waited for, sharded so that locking
+    /// the keys of different jobs does not contend on a single map
+    locks: Arc<ShardedMap<String, KeyMutex>>,
+}
+
+/// The mutex of a key, with the number of tasks holding or waiting for it
+#[derive(Default)]
+struct KeyMutex {
+    mutex: Arc<Mutex<()>>,
+    users: usize,
+}
+
+/// The lock of a key, which removes the mutex of the key once no other task holds or
+/// waits for it, so that the mutexes of the keys of finished jobs are not kept
+struct KeyLock {
+    key: String,
+    locks: Arc<ShardedMap<String, KeyMutex>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl Drop for KeyLock {
+    fn drop(&mut self) {
+        self.guard.take();
+        let mut locks = self.locks.shard(&self.key);
+        if let Some(key_mutex) = locks.get_mut(&self.key) {
+            key_mutex.users -= 1;
+            if key_mutex.users == 0 {
+                locks.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Lock for KeyLock {
+    async fn unlock(&mut self) {}
 }
 
 impl StandaloneClient {
@@ -39,7 +75,7 @@ impl StandaloneClient {
     pub fn try_new<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
         Ok(Self {
             db: sled::open(path).map_err(sled_to_ballista_error)?,
-            locks: Arc::new(Mutex::new(HashMap::new())),
+            locks: Default::default(),
         })
     }
 
@@ -50,7 +86,7 @@ impl StandaloneClient {
                 .temporary(true)
                 .open()
                 .map_err(sled_to_ballista_error)?,
-            locks: Arc::new(Mutex::new(HashMap::new())),
+            locks: Default::default(),
         })
     }
 }
@@ -209,15 +245,24 @@ impl StateBackendClient for StandaloneClient {
     }
 
     async fn lock(&self, keyspace: Keyspace, key: &str) -> Result<Box<dyn Lock>> {
-        let mut mlock = self.locks.lock().await;
         let lock_key = format!("/{:?}/{}", keyspace, key);
-        if let Some(lock) = mlock.get(&lock_key) {
-            Ok(Box::new(lock.clone().lock_owned().await))
-        } else {
-            let new_lock = Arc::new(Mutex::new(()));
-            mlock.insert(lock_key, new_lock.clone());
-            Ok(Box::new(new_lock.lock_owned().await))
-        }
+        // Only wait for the lock of the key once its shard of the map of locks is
+        // released, so that waiting for one key does not hold up the locks of other keys. The lock is
+        // counted as a user of the mutex before waiting, and released by its drop even
+        // if the wait is cancelled.
+        let (mutex, mut lock) = {
+            let mut locks = self.locks.shard(&lock_key);
+            let key_mutex = locks.entry(lock_key.clone()).or_default();
+            key_mutex.users += 1;
+            let lock = KeyLock {
+                key: lock_key,
+                locks: self.locks.clone(),
+                guard: None,
+            };
+            (key_mutex.mutex.clone(), lock)
+        };
+        lock.guard = Some(mutex.lock_owned().await);
+        Ok(Box::new(lock))
     }
 
     async fn watch(&self, keyspace: Keyspace, prefix: String) -> Result<Box<dyn Watch>> {
@@ -282,11 +327,100 @@ mod tests {
     use crate::state::backend::Keyspace;
     use futures::StreamExt;
     use std::result::Result;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
 
     fn create_instance() -> Result<StandaloneClient, Box<dyn std::error::Error>> {
         Ok(StandaloneClient::try_new_temporary()?)
     }
 
+    #[tokio::test]
+    async fn lock_keys_independently() -> Result<(), Box<dyn std::error::Error>> {
+        let client = Arc::new(create_instance()?);
+        let lock = client.lock(Keyspace::ActiveJobs, "job-1").await?;
+
+        // Wait for the lock of job-1 in the background
+        let waiting = {
+            let client = client.clone();
+            tokio::spawn(async move {
+                client.lock(Keyspace::ActiveJobs, "job-1").await.map(|_| ())
+            })
+        };
+        tokio::task::yield_now().await;
+
+        // The lock of another job is available meanwhile
+        let other = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.lock(Keyspace::ActiveJobs, "job-2"),
+        )
+        .await?;
+        assert!(other.is_ok());
+
+        drop(lock);
+        waiting.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_released_locks() -> Result<(), Box<dyn std::error::Error>> {
+        let client = create_instance()?;
+        let lock = client.lock(Keyspace::ActiveJobs, "job-1").await?;
+        let other = client.lock(Keyspace::ActiveJobs, "job-2").await?;
+        assert_eq!(client.locks.len(), 2);
+
+        // A cancelled wait for the lock of job-1 does not keep its mutex
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(10),
+            client.lock(Keyspace::ActiveJobs, "job-1"),
+        )
+        .await;
+        assert!(waiting.is_err());
+        drop(other);
+        assert_eq!(client.locks.len(), 1);
+
+        drop(lock);
+        assert!(client.locks.is_empty());
+        Ok(())
+    }
+
+    /// Lock, read and write the keys of `jobs` jobs from `tasks` concurrent tasks,
+    /// and return how long it took
+    async fn lock_concurrently(
+        client: &Arc<StandaloneClient>,
+        tasks: usize,
+        jobs: usize,
+    ) -> Result<Duration, Box<dyn std::error::Error>> {
+        let start = Instant::now();
+        let handles: Vec<_> = (0..tasks)
+            .map(|task| {
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let job_id = format!("job-{}", task % jobs);
+                    let _lock = client.lock(Keyspace::ActiveJobs, &job_id).await?;
+                    let value = client.get(Keyspace::ActiveJobs, &job_id).await?;
+                    client.put(Keyspace::ActiveJobs, job_id, value).await
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await??;
+        }
+        Ok(start.elapsed())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    // Run with `cargo test --release -- --ignored bench_lock_concurrent_tasks --nocapture`
+    async fn bench_lock_concurrent_tasks() -> Result<(), Box<dyn std::error::Error>> {
+        let client = Arc::new(create_instance()?);
+        for jobs in [1, 10, 100, 1000] {
+            let elapsed = lock_concurrently(&client, 10_000, jobs).await?;
+            println!("10000 tasks of {} jobs locked in {:?}", jobs, elapsed);
+        }
+        assert!(client.locks.is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn put_read() -> Result<(), Box<dyn std::error::Error>> {
         let client = create_instance()?;
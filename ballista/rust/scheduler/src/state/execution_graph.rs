@@ -15,25 +15,39 @@
 // specific language governing permissions and limitations
 // under the License.
 
-use crate::planner::DistributedPlanner;
+use crate::planner::{
+    plan_bloom_filters, resolve_bloom_filters, resource_profile_label,
+    rollback_resolved_shuffles, stage_resource_profile, DistributedPlanner,
+};
+use ballista_core::encryption::EncryptionKey;
 use ballista_core::error::{BallistaError, Result};
-use ballista_core::execution_plans::{ShuffleWriterExec, UnresolvedShuffleExec};
+use ballista_core::execution_plans::{
+    BloomFilter, BloomFilterExec, NodeLocalScanExec, ShuffleWriterExec,
+    UnresolvedShuffleExec,
+};
 
 use ballista_core::serde::protobuf::{
-    self, CompletedJob, JobStatus, QueuedJob, TaskStatus,
+    self, CompletedJob, JobResourceUsage, JobStatus, KeyValuePair, QueuedJob,
+    ResourceProfile, RunningJob, TaskStatus,
 };
 use ballista_core::serde::protobuf::{job_status, FailedJob, ShuffleWritePartition};
-use ballista_core::serde::protobuf::{task_status, RunningTask};
+use ballista_core::serde::protobuf::{task_status, CompletedTask, RunningTask};
 use ballista_core::serde::scheduler::{
-    ExecutorMetadata, PartitionId, PartitionLocation, PartitionStats,
+    ExecutorMetadata, FetchPriority, PartitionId, PartitionLocation, PartitionStats,
+    DEFAULT_POOL, RESOURCE_PROFILE_LABEL,
 };
+use ballista_core::telemetry;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::limit::GlobalLimitExec;
+use datafusion::physical_plan::union::UnionExec;
 use datafusion::physical_plan::{
     accept, ExecutionPlan, ExecutionPlanVisitor, Partitioning,
 };
-use log::debug;
-use std::collections::HashMap;
+use log::{debug, info};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 use std::fmt::{Debug, Formatter};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use datafusion::physical_plan::display::DisplayableExecutionPlan;
 use std::sync::Arc;
@@ -47,6 +61,9 @@ pub struct StageOutput {
     pub(crate) partition_locations: HashMap<usize, Vec<PartitionLocation>>,
     /// Flag indicating whether all tasks are complete
     pub(crate) complete: bool,
+    /// Bloom filter of the join keys of the rows written by the tasks, once they are
+    /// complete, if every one of them built one
+    pub(crate) bloom_filter: Option<Arc<BloomFilter>>,
 }
 
 impl StageOutput {
@@ -54,6 +71,7 @@ impl StageOutput {
         Self {
             partition_locations: HashMap::new(),
             complete: false,
+            bloom_filter: None,
         }
     }
 
@@ -77,6 +95,56 @@ impl StageOutput {
     }
 }
 
+/// Summary of the progress of an `ExecutionStage`, used for display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum StageStatus {
+    /// Waiting for the outputs of its child stages
+    Pending,
+    /// All inputs are available but no task has been scheduled yet
+    Resolved,
+    /// At least one task has been scheduled
+    Running,
+    /// All tasks completed successfully
+    Completed,
+    /// At least one task failed
+    Failed,
+}
+
+/// Scheduling history of a single task of an `ExecutionStage`.
+/// All times are in milliseconds since the epoch.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TaskInfo {
+    /// Number of times this task has been launched
+    pub(crate) attempts: u32,
+    /// Executor the latest attempt was launched on
+    pub(crate) executor_id: Option<String>,
+    /// Time the task became available for scheduling
+    pub(crate) ready_time: Option<u64>,
+    /// Time the latest attempt was launched
+    pub(crate) launch_time: Option<u64>,
+    /// Time the latest attempt completed or failed
+    pub(crate) end_time: Option<u64>,
+}
+
+impl TaskInfo {
+    /// Time the task waited for an executor before its latest attempt was launched
+    pub fn queue_time(&self) -> Option<u64> {
+        match (self.ready_time, self.launch_time) {
+            (Some(ready), Some(launch)) => Some(launch.saturating_sub(ready)),
+            _ => None,
+        }
+    }
+
+    /// Run time of the latest attempt, up to now if it is still running
+    pub fn run_time(&self) -> Option<u64> {
+        self.launch_time.map(|launch| {
+            self.end_time
+                .unwrap_or_else(timestamp_millis)
+                .saturating_sub(launch)
+        })
+    }
+}
+
 /// A stage in the ExecutionGraph.
 ///
 /// This represents a set of tasks (one per each `partition`) which can
@@ -97,12 +165,26 @@ pub struct ExecutionStage {
     pub(crate) plan: Arc<dyn ExecutionPlan>,
     /// Status of each already scheduled task. If status is None, the partition has not yet been scheduled
     pub(crate) task_statuses: Vec<Option<task_status::Status>>,
-    /// Stage ID of the stage that will take this stages outputs as inputs.
-    /// If `output_link` is `None` then this the final stage in the `ExecutionGraph`
-    pub(crate) output_link: Option<usize>,
+    /// Stage IDs of the stages that will take this stages outputs as inputs.
+    /// If `output_links` is empty then this the final stage in the `ExecutionGraph`
+    pub(crate) output_links: Vec<usize>,
     /// Flag indicating whether all input partitions have been resolved and the plan
     /// has UnresovledShuffleExec operators resolved to ShuffleReadExec operators.
     pub(crate) resolved: bool,
+    /// Time the first task of this stage was launched, in milliseconds since the epoch.
+    pub(crate) start_time: Option<u64>,
+    /// Scheduling history of each task
+    pub(crate) task_infos: Vec<TaskInfo>,
+    /// The resources the tasks of this stage mostly use
+    pub(crate) resource_profile: ResourceProfile,
+    /// The node whose node-local data each task of this stage reads, if any
+    pub(crate) task_nodes: Vec<Option<TaskNode>>,
+    /// Bloom filter of the join keys of the rows written by the completed tasks, merged
+    /// as they complete rather than kept with the status of every task
+    pub(crate) bloom_filter: Option<BloomFilter>,
+    /// Whether a completed task reported no Bloom filter, so that the merged filter
+    /// misses some of the keys the stage wrote
+    pub(crate) bloom_filter_incomplete: bool,
 }
 
 impl Debug for ExecutionStage {
@@ -131,7 +213,7 @@ impl ExecutionStage {
         stage_id: usize,
         plan: Arc<dyn ExecutionPlan>,
         output_partitioning: Option<Partitioning>,
-        output_link: Option<usize>,
+        output_links: Vec<usize>,
         child_stages: Vec<usize>,
     ) -> Self {
         let num_tasks = plan.output_partitioning().partition_count();
@@ -144,6 +226,15 @@ impl ExecutionStage {
             inputs.insert(*input_stage_id, StageOutput::new());
         }
 
+        let ready_time = if resolved {
+            Some(timestamp_millis())
+        } else {
+            None
+        };
+        let resource_profile = stage_resource_profile(plan.as_ref());
+        // Plans reading several nodes in a task are rejected when the graph is built
+        let nodes = task_nodes(&plan).unwrap_or_default();
+
         Self {
             stage_id,
             partitions: num_tasks,
@@ -151,8 +242,20 @@ impl ExecutionStage {
             inputs,
             plan,
             task_statuses: vec![None; num_tasks],
-            output_link,
+            output_links,
             resolved,
+            start_time: None,
+            task_infos: vec![
+                TaskInfo {
+                    ready_time,
+                    ..Default::default()
+                };
+                num_tasks
+            ],
+            resource_profile,
+            task_nodes: nodes,
+            bloom_filter: None,
+            bloom_filter_incomplete: false,
         }
     }
 
@@ -177,10 +280,42 @@ impl ExecutionStage {
             .count()
     }
 
-    /// Marks the input stage ID as complete.
-    pub fn complete_input(&mut self, stage_id: usize) {
+    /// Returns the number of tasks which are scheduled but not yet finished
+    pub fn running_tasks(&self) -> usize {
+        self.task_statuses
+            .iter()
+            .filter(|status| matches!(status, Some(task_status::Status::Running(_))))
+            .count()
+    }
+
+    /// Returns a summary of the progress of this stage
+    pub fn status(&self) -> StageStatus {
+        if self
+            .task_statuses
+            .iter()
+            .any(|status| matches!(status, Some(task_status::Status::Failed(_))))
+        {
+            StageStatus::Failed
+        } else if self.complete() {
+            StageStatus::Completed
+        } else if self.task_statuses.iter().any(|status| status.is_some()) {
+            StageStatus::Running
+        } else if self.resolved {
+            StageStatus::Resolved
+        } else {
+            StageStatus::Pending
+        }
+    }
+
+    /// Marks the input stage ID as complete, with the Bloom filter it built, if any.
+    pub fn complete_input(
+        &mut self,
+        stage_id: usize,
+        bloom_filter: Option<Arc<BloomFilter>>,
+    ) {
         if let Some(input) = self.inputs.get_mut(&stage_id) {
             input.complete = true;
+            input.bloom_filter = bloom_filter;
         }
     }
 
@@ -201,8 +336,9 @@ impl ExecutionStage {
         }
     }
 
-    /// Resolve any UnresolvedShuffleExec operators within this stage's plan
-    pub fn resolve_shuffles(&mut self) -> Result<()> {
+    /// Resolve any UnresolvedShuffleExec operators within this stage's plan, whose
+    /// partitions are fetched with priority `fetch_priority`
+    pub fn resolve_shuffles(&mut self, fetch_priority: FetchPriority) -> Result<()> {
         println!("Resolving shuffles\n{:?}", self);
         if self.resolved {
             // If this stage has no input shuffles, then it is already resolved
@@ -213,20 +349,315 @@ impl ExecutionStage {
                 .iter()
                 .map(|(stage, outputs)| (*stage, outputs.partition_locations.clone()))
                 .collect();
+            let bloom_filters = self
+                .inputs
+                .iter()
+                .filter_map(|(stage, outputs)| {
+                    outputs.bloom_filter.clone().map(|filter| (*stage, filter))
+                })
+                .collect();
             // Otherwise, rewrite the plan to replace UnresolvedShuffleExec with ShuffleReadExec
             let new_plan = crate::planner::remove_unresolved_shuffles(
                 self.plan.clone(),
                 &input_locations,
+                fetch_priority,
             )?;
-            self.plan = new_plan;
+            self.plan = resolve_bloom_filters(new_plan, &bloom_filters)?;
             self.resolved = true;
+
+            let now = timestamp_millis();
+            for info in self.task_infos.iter_mut() {
+                info.ready_time = Some(now);
+            }
             Ok(())
         }
     }
 
+    /// Why the status of the given attempt of a task is to be ignored, if it is. Attempt
+    /// 0 is sent by executors which predate attempts.
+    fn ignored_status_reason(
+        &self,
+        partition: usize,
+        attempt: u32,
+    ) -> Option<&'static str> {
+        let latest = self.task_infos.get(partition).map(|info| info.attempts);
+        if attempt != 0 && latest.map_or(false, |latest| latest != attempt) {
+            Some("not the latest attempt")
+        } else if matches!(
+            self.task_statuses.get(partition),
+            Some(Some(task_status::Status::Completed(_)))
+        ) {
+            Some("the task already completed")
+        } else if matches!(self.task_statuses.get(partition), Some(None)) {
+            Some("the task is not running")
+        } else {
+            None
+        }
+    }
+
+    /// Number of rows written by the completed tasks
+    fn output_rows(&self) -> u64 {
+        self.task_statuses
+            .iter()
+            .filter_map(|status| match status {
+                Some(task_status::Status::Completed(completed)) => Some(
+                    completed
+                        .partitions
+                        .iter()
+                        .map(|partition| partition.num_rows)
+                        .sum::<u64>(),
+                ),
+                _ => None,
+            })
+            .sum()
+    }
+
+    /// The Bloom filter of the join keys of the rows written by the tasks of the stage,
+    /// merged, if every task completed with one
+    fn bloom_filter(&self) -> Option<Arc<BloomFilter>> {
+        if self.bloom_filter_incomplete {
+            return None;
+        }
+        self.bloom_filter.clone().map(Arc::new)
+    }
+
+    /// Merge the Bloom filter a completed task reported into the filter of the stage,
+    /// taking it out of the status of the task
+    fn merge_bloom_filter(&mut self, status: &mut task_status::Status) -> Result<()> {
+        if let task_status::Status::Completed(completed) = status {
+            if completed.bloom_filter.is_empty() {
+                self.bloom_filter_incomplete = true;
+                return Ok(());
+            }
+            let filter =
+                BloomFilter::from_bytes(&std::mem::take(&mut completed.bloom_filter))?;
+            match &mut self.bloom_filter {
+                Some(merged) => merged.union(&filter)?,
+                None => self.bloom_filter = Some(filter),
+            }
+        }
+        Ok(())
+    }
+
+    /// Bytes spilled to disk by the completed tasks of the stage, and the number of files
+    /// they spilled to
+    pub fn spilled(&self) -> (u64, u64) {
+        self.task_statuses
+            .iter()
+            .fold((0, 0), |(bytes, files), status| match status {
+                Some(task_status::Status::Completed(completed)) => (
+                    bytes + completed.spilled_bytes,
+                    files + completed.spill_files,
+                ),
+                _ => (bytes, files),
+            })
+    }
+
+    /// Bytes read by the stage, from storage by the scans of its completed tasks and
+    /// from the output of its input stages
+    pub fn input_bytes(&self) -> u64 {
+        let scanned: u64 = self
+            .task_statuses
+            .iter()
+            .filter_map(|status| match status {
+                Some(task_status::Status::Completed(completed)) => {
+                    Some(completed.bytes_scanned)
+                }
+                _ => None,
+            })
+            .sum();
+        let shuffled: u64 = self
+            .inputs
+            .values()
+            .flat_map(|output| output.partition_locations.values().flatten())
+            .map(|location| location.partition_stats.num_bytes().unwrap_or_default())
+            .sum();
+        scanned + shuffled
+    }
+
+    /// Whether the completed tasks of the stage spilled more than `ratio` times the
+    /// bytes the stage read to disk
+    pub fn spills_excessively(&self, ratio: usize) -> bool {
+        let (spilled_bytes, _) = self.spilled();
+        spilled_bytes > 0
+            && spilled_bytes > self.input_bytes().saturating_mul(ratio as u64)
+    }
+
+    /// Complete the tasks which did not complete yet without output, once the stages
+    /// reading the output of this stage have all the rows they need. Running tasks are
+    /// not interrupted, but their statuses are ignored. Returns the number of tasks
+    /// skipped.
+    fn skip_remaining_tasks(&mut self) -> usize {
+        let now = timestamp_millis();
+        let mut skipped = 0;
+        for (status, info) in self
+            .task_statuses
+            .iter_mut()
+            .zip(self.task_infos.iter_mut())
+        {
+            if !matches!(status, Some(task_status::Status::Completed(_))) {
+                *status = Some(task_status::Status::Completed(CompletedTask {
+                    executor_id: info.executor_id.clone().unwrap_or_default(),
+                    partitions: vec![],
+                    bytes_scanned: 0,
+                    spilled_bytes: 0,
+                    spill_files: 0,
+                    bloom_filter: vec![],
+                    replica: None,
+                    operator_metrics: vec![],
+                }));
+                info.end_time = Some(now);
+                skipped += 1;
+            }
+        }
+        // The skipped tasks did not insert their keys
+        self.bloom_filter_incomplete |= skipped > 0;
+        skipped
+    }
+
+    /// Return a task to the set of available tasks, to be launched again. Its attempts
+    /// are kept, so that the statuses of the previous attempt are ignored.
+    fn reset_task(&mut self, partition: usize) {
+        self.task_statuses[partition] = None;
+        let info = &mut self.task_infos[partition];
+        info.executor_id = None;
+        info.launch_time = None;
+        info.end_time = None;
+        info.ready_time = if self.resolved {
+            Some(timestamp_millis())
+        } else {
+            None
+        };
+    }
+
+    /// Move the output of completed task `partition`, written by executor `executor_id`,
+    /// to the copies of its partitions kept by another executor, if it has copies of all
+    /// of them. Returns the executor keeping the copies, with the path of each partition
+    /// and the path of its copy.
+    fn fail_over_task(
+        &mut self,
+        partition: usize,
+        executor_id: &str,
+    ) -> Option<(ExecutorMetadata, Vec<(String, String)>)> {
+        let completed = match &mut self.task_statuses[partition] {
+            Some(task_status::Status::Completed(completed)) => completed,
+            _ => return None,
+        };
+        let usable = match &completed.replica {
+            Some(replica) => {
+                replica.paths.len() == completed.partitions.len()
+                    && matches!(&replica.executor, Some(executor) if executor.id != executor_id)
+            }
+            None => false,
+        };
+        if !usable {
+            return None;
+        }
+
+        let replica = completed.replica.take()?;
+        let executor = ExecutorMetadata::from(replica.executor?);
+        let paths = completed
+            .partitions
+            .iter_mut()
+            .zip(replica.paths)
+            .map(|(shuffle, replica_path)| {
+                let path = std::mem::replace(&mut shuffle.path, replica_path.clone());
+                (path, replica_path)
+            })
+            .collect();
+        completed.executor_id = executor.id.clone();
+        self.task_infos[partition].executor_id = Some(executor.id.clone());
+        Some((executor, paths))
+    }
+
+    /// Drop the partitions of input stage `stage_id` written by executor `executor_id`,
+    /// which is to write them again. If this stage was resolved, it is rolled back, and
+    /// its running tasks, which may read the dropped partitions, are reset. Returns the
+    /// number of tasks reset.
+    fn invalidate_input(
+        &mut self,
+        stage_id: usize,
+        executor_id: &str,
+        input_partition_counts: &HashMap<usize, usize>,
+    ) -> Result<usize> {
+        if let Some(input) = self.inputs.get_mut(&stage_id) {
+            for locations in input.partition_locations.values_mut() {
+                locations.retain(|location| location.executor_meta.id != executor_id);
+            }
+            input.complete = false;
+        }
+        if !self.resolved {
+            return Ok(0);
+        }
+
+        self.plan =
+            rollback_resolved_shuffles(self.plan.clone(), input_partition_counts)?;
+        self.resolved = false;
+        let mut reset = 0;
+        for partition in 0..self.partitions {
+            match self.task_statuses[partition] {
+                Some(task_status::Status::Completed(_)) => {}
+                Some(task_status::Status::Running(_)) => {
+                    self.reset_task(partition);
+                    reset += 1;
+                }
+                _ => self.reset_task(partition),
+            }
+        }
+        Ok(reset)
+    }
+
+    /// Read the partitions of input stage `stage_id` written by executor `executor_id`
+    /// from their copies in `replicas`. If this stage was resolved, it is resolved again
+    /// with the copies, and its running tasks, which may read the lost partitions, are
+    /// reset. Returns the number of tasks reset.
+    fn fail_over_input(
+        &mut self,
+        stage_id: usize,
+        executor_id: &str,
+        replicas: &ShuffleReplicas,
+        input_partition_counts: &HashMap<usize, usize>,
+        fetch_priority: FetchPriority,
+    ) -> Result<usize> {
+        let mut moved = false;
+        if let Some(input) = self.inputs.get_mut(&stage_id) {
+            for location in input.partition_locations.values_mut().flatten() {
+                moved |= move_to_replica(location, executor_id, replicas);
+            }
+        }
+        if !moved || !self.resolved {
+            return Ok(0);
+        }
+
+        self.plan =
+            rollback_resolved_shuffles(self.plan.clone(), input_partition_counts)?;
+        self.resolved = false;
+        if self.resolvable() {
+            self.resolve_shuffles(fetch_priority)?;
+        }
+        let mut reset = 0;
+        for partition in 0..self.partitions {
+            match self.task_statuses[partition] {
+                Some(task_status::Status::Running(_)) => {
+                    self.reset_task(partition);
+                    reset += 1;
+                }
+                None if !self.resolved => self.reset_task(partition),
+                _ => {}
+            }
+        }
+        Ok(reset)
+    }
+
     /// Update the status for task partition
     pub fn update_task_status(&mut self, partition: usize, status: task_status::Status) {
         debug!("Updating task status for partition {}", partition);
+        if matches!(
+            status,
+            task_status::Status::Completed(_) | task_status::Status::Failed(_)
+        ) {
+            self.task_infos[partition].end_time = Some(timestamp_millis());
+        }
         self.task_statuses[partition] = Some(status);
     }
 
@@ -259,8 +690,8 @@ struct ExecutionStageBuilder {
     current_stage_id: usize,
     /// Map from stage ID -> List of child stage IDs
     stage_dependencies: HashMap<usize, Vec<usize>>,
-    /// Map from Stage ID -> output link
-    output_links: HashMap<usize, usize>,
+    /// Map from Stage ID -> output links
+    output_links: HashMap<usize, Vec<usize>>,
 }
 
 impl ExecutionStageBuilder {
@@ -286,7 +717,7 @@ impl ExecutionStageBuilder {
         for stage in stages {
             let partitioning = stage.shuffle_output_partitioning().cloned();
             let stage_id = stage.stage_id();
-            let output_link = self.output_links.remove(&stage_id);
+            let output_links = self.output_links.remove(&stage_id).unwrap_or_default();
 
             let child_stages = self
                 .stage_dependencies
@@ -299,7 +730,7 @@ impl ExecutionStageBuilder {
                     stage_id,
                     stage,
                     partitioning,
-                    output_link,
+                    output_links,
                     child_stages,
                 ),
             );
@@ -307,6 +738,23 @@ impl ExecutionStageBuilder {
 
         Ok(execution_stages)
     }
+
+    /// Make the current stage depend on stage `input_stage_id`. A stage shared by several
+    /// consumers, or read twice by the same one, is linked to each of them once.
+    fn link(&mut self, input_stage_id: usize) {
+        let links = self.output_links.entry(input_stage_id).or_default();
+        if !links.contains(&self.current_stage_id) {
+            links.push(self.current_stage_id);
+        }
+
+        let deps = self
+            .stage_dependencies
+            .entry(self.current_stage_id)
+            .or_default();
+        if !deps.contains(&input_stage_id) {
+            deps.push(input_stage_id);
+        }
+    }
 }
 
 impl ExecutionPlanVisitor for ExecutionStageBuilder {
@@ -321,15 +769,11 @@ impl ExecutionPlanVisitor for ExecutionStageBuilder {
         } else if let Some(unresolved_shuffle) =
             plan.as_any().downcast_ref::<UnresolvedShuffleExec>()
         {
-            self.output_links
-                .insert(unresolved_shuffle.stage_id, self.current_stage_id);
-
-            if let Some(deps) = self.stage_dependencies.get_mut(&self.current_stage_id) {
-                deps.push(unresolved_shuffle.stage_id)
-            } else {
-                self.stage_dependencies
-                    .insert(self.current_stage_id, vec![unresolved_shuffle.stage_id]);
-            }
+            self.link(unresolved_shuffle.stage_id);
+        } else if let Some(bloom_filter) = plan.as_any().downcast_ref::<BloomFilterExec>()
+        {
+            // The stage filtered by a Bloom filter runs once the filter is built
+            self.link(bloom_filter.build_stage_id());
         }
         Ok(true)
     }
@@ -343,6 +787,17 @@ pub struct Task {
     pub partition: PartitionId,
     pub plan: Arc<dyn ExecutionPlan>,
     pub output_partitioning: Option<Partitioning>,
+    /// Trace context of the stage this task belongs to
+    pub trace_context: Vec<KeyValuePair>,
+    /// Key encrypting the shuffle files of the job, if any
+    pub encryption_key: Option<EncryptionKey>,
+    /// Number of times the task was launched, including this attempt
+    pub attempt: u32,
+    /// Whether the task belongs to the final stage, whose output is the output of the
+    /// job
+    pub final_stage: bool,
+    /// Whether the task copies its shuffle partitions to a second executor
+    pub replicate_output: bool,
 }
 
 impl Debug for Task {
@@ -402,10 +857,11 @@ impl Debug for Task {
 ///
 ///
 /// The DAG structure of this `ExecutionGraph` is encoded in the stages. Each stage's `input` field
-/// will indicate which stages it depends on, and each stage's `output_link` will indicate which
-/// stage it needs to publish its output to.
+/// will indicate which stages it depends on, and each stage's `output_links` will indicate which
+/// stages it needs to publish its output to. Identical stages are planned once, so a stage
+/// may publish its output to several stages.
 ///
-/// If a stage has `output_link == None` then it is the final stage in this query, and it should
+/// If a stage has no `output_links` then it is the final stage in this query, and it should
 /// publish its outputs to the `ExecutionGraph`s `output_locations` representing the final query results.
 #[derive(Clone)]
 pub struct ExecutionGraph {
@@ -421,6 +877,18 @@ pub struct ExecutionGraph {
     pub(crate) output_partitions: usize,
     /// Locations of this `ExecutionGraph` final output locations
     pub(crate) output_locations: Vec<PartitionLocation>,
+    /// Trace context of the query which submitted this job
+    pub(crate) trace_context: Vec<KeyValuePair>,
+    /// Key encrypting the shuffle files of this job, if any
+    pub(crate) encryption_key: Option<EncryptionKey>,
+    /// Resources used by the tasks of this job so far
+    pub(crate) usage: JobResourceUsage,
+    /// Priority of the fetches of the shuffle partitions of this job, higher first
+    pub(crate) priority: u32,
+    /// Whether the tasks of this job copy their shuffle partitions to a second executor
+    pub(crate) replicate_shuffles: bool,
+    /// Executor pool running the tasks of this job
+    pub(crate) pool: String,
 }
 
 impl ExecutionGraph {
@@ -428,25 +896,48 @@ impl ExecutionGraph {
         job_id: &str,
         session_id: &str,
         plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<Self> {
+        Self::new_with_bloom_filters(job_id, session_id, plan, None)
+    }
+
+    /// Like `new`, filtering the probe sides of the joins of the plan by Bloom filters of
+    /// `bloom_filter_bytes` of the keys of their build sides, if set
+    pub fn new_with_bloom_filters(
+        job_id: &str,
+        session_id: &str,
+        plan: Arc<dyn ExecutionPlan>,
+        bloom_filter_bytes: Option<usize>,
     ) -> Result<Self> {
         let mut planner = DistributedPlanner::new();
 
         let output_partitions = plan.output_partitioning().partition_count();
 
-        let shuffle_stages = planner.plan_query_stages(job_id, plan)?;
+        let mut shuffle_stages = planner.plan_query_stages(job_id, plan)?;
+        if let Some(num_bytes) = bloom_filter_bytes {
+            shuffle_stages = plan_bloom_filters(shuffle_stages, num_bytes)?;
+        }
 
         let builder = ExecutionStageBuilder::new();
         let stages = builder.build(shuffle_stages)?;
+        for stage in stages.values() {
+            task_nodes(&stage.plan)?;
+        }
 
         Ok(Self {
             job_id: job_id.to_string(),
             session_id: session_id.to_string(),
             status: JobStatus {
-                status: Some(job_status::Status::Queued(QueuedJob {})),
+                status: Some(job_status::Status::Queued(QueuedJob { queue: None })),
             },
             stages,
             output_partitions,
             output_locations: vec![],
+            trace_context: vec![],
+            encryption_key: None,
+            usage: JobResourceUsage::default(),
+            priority: 0,
+            replicate_shuffles: false,
+            pool: DEFAULT_POOL.to_owned(),
         })
     }
 
@@ -458,8 +949,79 @@ impl ExecutionGraph {
         self.session_id.as_str()
     }
 
+    /// Set the trace context which spans of this job should be recorded under
+    pub fn with_trace_context(mut self, trace_context: Vec<KeyValuePair>) -> Self {
+        self.trace_context = trace_context;
+        self
+    }
+
+    /// Fetch the shuffle partitions of this job with priority `priority`, higher first
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Run the tasks of this job on the executors of `pool` only
+    pub fn with_pool(mut self, pool: String) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Encrypt the shuffle files of this job with a new random key
+    pub fn with_shuffle_encryption(mut self) -> Self {
+        self.encryption_key = Some(EncryptionKey::generate());
+        self
+    }
+
+    /// Have the tasks of this job copy their shuffle partitions to a second executor,
+    /// which serves them if the executor which wrote them is lost
+    pub fn with_shuffle_replication(mut self) -> Self {
+        self.replicate_shuffles = true;
+        self
+    }
+
+    /// Count the attempts of every task from `attempts`, the attempts of an earlier run
+    /// of this job, so that the statuses of the tasks of that run are ignored
+    pub fn with_previous_attempts(mut self, attempts: u32) -> Self {
+        for stage in self.stages.values_mut() {
+            for info in stage.task_infos.iter_mut() {
+                info.attempts = attempts;
+            }
+        }
+        self
+    }
+
+    /// The largest number of attempts of any task of this job
+    pub fn max_attempts(&self) -> u32 {
+        self.stages
+            .values()
+            .flat_map(|stage| stage.task_infos.iter().map(|info| info.attempts))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The status of the job, along with its progress until it completes or fails
     pub fn status(&self) -> JobStatus {
-        self.status.clone()
+        match &self.status.status {
+            Some(job_status::Status::Queued(_))
+            | Some(job_status::Status::Running(_)) => JobStatus {
+                status: Some(job_status::Status::Running(self.progress())),
+            },
+            _ => self.status.clone(),
+        }
+    }
+
+    /// The tasks and stages of the job which completed, out of all of them
+    fn progress(&self) -> RunningJob {
+        let stages = self.stages.values();
+        RunningJob {
+            completed_tasks: stages.clone().map(|s| s.completed_tasks() as u32).sum(),
+            running_tasks: self.running_tasks() as u32,
+            total_tasks: stages.clone().map(|s| s.partitions as u32).sum(),
+            completed_stages: stages.clone().filter(|s| s.complete()).count() as u32,
+            total_stages: self.stages.len() as u32,
+            queue: None,
+        }
     }
 
     /// An ExecutionGraph is complete if all its stages are complete
@@ -482,7 +1044,8 @@ impl ExecutionGraph {
                         stage_id,
                         partition_id,
                     }),
-                status: Some(task_status),
+                status: Some(mut task_status),
+                attempt,
             } = status
             {
                 if job_id != self.job_id() {
@@ -495,18 +1058,54 @@ impl ExecutionGraph {
 
                 let stage_id = stage_id as usize;
                 let partition = partition_id as usize;
+                let row_limit = self.output_row_limit(stage_id);
                 if let Some(stage) = self.stages.get_mut(&stage_id) {
+                    // Statuses are applied once per attempt, so that retried and
+                    // duplicated tasks never register their output twice
+                    if let Some(reason) = stage.ignored_status_reason(partition, attempt)
+                    {
+                        debug!(
+                            "Ignoring status of task {}/{}/{} attempt {}: {}",
+                            job_id, stage_id, partition_id, attempt, reason
+                        );
+                        continue;
+                    }
+                    stage.merge_bloom_filter(&mut task_status)?;
                     stage.update_task_status(partition, task_status.clone());
+                    record_usage(&mut self.usage, stage, partition, &task_status);
+                    if let (Some(limit), task_status::Status::Completed(_)) =
+                        (row_limit, &task_status)
+                    {
+                        if !stage.complete() && stage.output_rows() >= limit as u64 {
+                            let skipped = stage.skip_remaining_tasks();
+                            info!(
+                                "Stage {} of job {} wrote the {} rows read from it, skipping its {} remaining tasks",
+                                stage_id, job_id, limit, skipped
+                            );
+                        }
+                    }
                     let stage_complete = stage.complete();
+                    let stage_start_time = stage.start_time;
+                    let bloom_filter = if stage_complete {
+                        stage.bloom_filter()
+                    } else {
+                        None
+                    };
 
                     // TODO Should be able to reschedule this task.
                     if let task_status::Status::Failed(failed_task) = task_status {
+                        self.record_stage_span(
+                            stage_id,
+                            stage_start_time,
+                            Some(failed_task.error.as_str()),
+                        );
                         self.status = JobStatus {
                             status: Some(job_status::Status::Failed(FailedJob {
                                 error: format!(
                                     "Task {}/{}/{} failed: {}",
                                     job_id, stage_id, partition_id, failed_task.error
                                 ),
+                                kind: failed_task.kind,
                             })),
                         };
                         return Ok(());
@@ -520,29 +1119,39 @@ impl ExecutionGraph {
                             completed_task.partitions,
                         );
 
-                        if let Some(link) = stage.output_link {
-                            // If this is an intermediate stage, we need to push its `PartitionLocation`s to the parent stage
-                            if let Some(linked_stage) = self.stages.get_mut(&link) {
+                        let output_links = stage.output_links.clone();
+                        for link in &output_links {
+                            let fetch_priority = self.fetch_priority(*link);
+                            // If this is an intermediate stage, we need to push its `PartitionLocation`s to the parent stages
+                            if let Some(linked_stage) = self.stages.get_mut(link) {
                                 linked_stage.add_input_partitions(
-                                    stage_id, partition, locations,
+                                    stage_id,
+                                    partition,
+                                    locations.clone(),
                                 )?;
 
                                 // If all tasks for this stage are complete, mark the input complete in the parent stage
                                 if stage_complete {
-                                    linked_stage.complete_input(stage_id);
+                                    linked_stage
+                                        .complete_input(stage_id, bloom_filter.clone());
                                 }
 
                                 // If all input partitions are ready, we can resolve any UnresolvedShuffleExec in the parent stage plan
                                 if linked_stage.resolvable() {
-                                    linked_stage.resolve_shuffles()?;
+                                    linked_stage.resolve_shuffles(fetch_priority)?;
                                 }
                             } else {
                                 return Err(BallistaError::Internal(format!("Error updating job {}: Invalid output link {} for stage {}", job_id, stage_id, link)));
                             }
-                        } else {
-                            // If `output_link` is `None`, then this is a final stage
+                        }
+                        if output_links.is_empty() {
+                            // If there are no `output_links`, then this is a final stage
                             self.output_locations.extend(locations);
                         }
+
+                        if stage_complete {
+                            self.record_stage_span(stage_id, stage_start_time, None);
+                        }
                     }
                 } else {
                     return Err(BallistaError::Internal(format!(
@@ -557,6 +1166,48 @@ impl ExecutionGraph {
         Ok(())
     }
 
+    /// Number of rows of the output of the given stage which are read at most, if every
+    /// stage reading it only reads it through a LIMIT, in which case any rows will do
+    fn output_row_limit(&self, stage_id: usize) -> Option<usize> {
+        let output_links = &self.stages.get(&stage_id)?.output_links;
+        if output_links.is_empty() {
+            return None;
+        }
+        let mut limits = vec![];
+        for link in output_links {
+            collect_row_limits(&self.stages.get(link)?.plan, stage_id, &mut limits);
+        }
+        if limits.is_empty() {
+            return None;
+        }
+        limits
+            .into_iter()
+            .try_fold(0, |max, limit| limit.map(|limit| max.max(limit)))
+    }
+
+    /// Priority of the fetches of the input partitions of the given stage, which are
+    /// more critical the fewer stages are left to run after it
+    fn fetch_priority(&self, stage_id: usize) -> FetchPriority {
+        FetchPriority {
+            job_priority: self.priority,
+            stages_remaining: self.stages_remaining(stage_id) as u32,
+        }
+    }
+
+    /// Number of stages on the longest path from the given stage to the final stage
+    fn stages_remaining(&self, stage_id: usize) -> usize {
+        self.stages
+            .get(&stage_id)
+            .and_then(|stage| {
+                stage
+                    .output_links
+                    .iter()
+                    .map(|link| self.stages_remaining(*link) + 1)
+                    .max()
+            })
+            .unwrap_or(0)
+    }
+
     /// Total number of tasks in this plan that are ready for scheduling
     pub fn available_tasks(&self) -> usize {
         self.stages
@@ -565,6 +1216,14 @@ impl ExecutionGraph {
             .sum()
     }
 
+    /// The number of tasks of the job running on executors
+    pub fn running_tasks(&self) -> usize {
+        self.stages
+            .iter()
+            .map(|(_, stage)| stage.running_tasks())
+            .sum()
+    }
+
     /// Get next task that can be assigned to the given executor.
     /// This method should only be called when the resulting task is immediately
     /// being launched as the status will be set to Running and it will not be
@@ -572,38 +1231,125 @@ impl ExecutionGraph {
     /// If the task is not launched the status must be reset to allow the task to
     /// be scheduled elsewhere.
     pub fn pop_next_task(&mut self, executor_id: &str) -> Result<Option<Task>> {
-        let job_id = self.job_id.clone();
-        let session_id = self.session_id.clone();
-        self.stages.iter_mut().find(|(_stage_id, stage)| {
-            stage.resolved() && stage.available_tasks() > 0
-        }).map(|(stage_id, stage)| {
-            let (partition_id,_) = stage
-                .task_statuses
-                .iter()
-                .enumerate()
-                .find(|(_partition,status)| status.is_none())
-                .ok_or_else(|| {
-                BallistaError::Internal(format!("Error getting next task for job {}: Stage {} is ready but has no pending tasks", job_id, stage_id))
-            })?;
+        self.pop_next_task_on(executor_id, &BTreeMap::new())
+    }
 
-             let partition = PartitionId {
-                job_id,
-                stage_id: *stage_id,
-                partition_id
+    /// Get next task that can be assigned to the given executor, which has `labels`.
+    /// Tasks reading the node-local data of a node are only assigned to the executors
+    /// labelled with that node. The tasks of the stages whose resource profile is the
+    /// one the executor is labelled with are assigned first, then the tasks of the
+    /// stages without a particular profile.
+    pub fn pop_next_task_on(
+        &mut self,
+        executor_id: &str,
+        labels: &BTreeMap<String, String>,
+    ) -> Result<Option<Task>> {
+        let executor_profile = labels.get(RESOURCE_PROFILE_LABEL).map(String::as_str);
+        let mut next: Option<(usize, usize, usize)> = None;
+        for (stage_id, stage) in &self.stages {
+            if !stage.resolved() || stage.available_tasks() == 0 {
+                continue;
+            }
+            let rank = if executor_profile
+                == Some(resource_profile_label(stage.resource_profile))
+            {
+                0
+            } else if stage.resource_profile == ResourceProfile::General {
+                1
+            } else {
+                2
             };
+            if next.map_or(false, |(best, _, _)| best <= rank) {
+                continue;
+            }
+            let partition_id =
+                stage
+                    .task_statuses
+                    .iter()
+                    .enumerate()
+                    .position(|(partition, status)| {
+                        status.is_none()
+                            && stage
+                                .task_nodes
+                                .get(partition)
+                                .map_or(true, |node| runs_on(node, labels))
+                    });
+            if let Some(partition_id) = partition_id {
+                next = Some((rank, *stage_id, partition_id));
+                if rank == 0 {
+                    break;
+                }
+            }
+        }
+        let (stage_id, partition_id) = match next {
+            Some((_, stage_id, partition_id)) => (stage_id, partition_id),
+            None => return Ok(None),
+        };
+        let job_id = self.job_id.clone();
+        let stage = self.stages.get_mut(&stage_id).ok_or_else(|| {
+            BallistaError::Internal(format!(
+                "Error getting next task for job {}: Stage {} not found",
+                job_id, stage_id
+            ))
+        })?;
+
+        let partition = PartitionId {
+            job_id,
+            stage_id,
+            partition_id,
+        };
 
-            // Set the status to Running
-            stage.task_statuses[partition_id] = Some(task_status::Status::Running(RunningTask {
-                executor_id: executor_id.to_owned()
+        // Set the status to Running
+        stage.task_statuses[partition_id] =
+            Some(task_status::Status::Running(RunningTask {
+                executor_id: executor_id.to_owned(),
             }));
 
-            Ok(Task {
-                session_id,
-                partition,
-                plan: stage.plan.clone(),
-                output_partitioning: stage.output_partitioning.clone()
-            })
-        }).transpose()
+        let now = timestamp_millis();
+        if stage.start_time.is_none() {
+            stage.start_time = Some(now);
+        }
+
+        let info = &mut stage.task_infos[partition_id];
+        info.attempts += 1;
+        info.executor_id = Some(executor_id.to_owned());
+        info.launch_time = Some(now);
+        info.end_time = None;
+        let attempt = info.attempts;
+
+        let trace_context = telemetry::inject_context(&telemetry::stage_context(
+            &telemetry::extract_context(&self.trace_context),
+            &partition.job_id,
+            partition.stage_id,
+        ));
+
+        Ok(Some(Task {
+            session_id: self.session_id.clone(),
+            partition,
+            plan: stage.plan.clone(),
+            output_partitioning: stage.output_partitioning.clone(),
+            trace_context,
+            encryption_key: self.encryption_key.clone(),
+            attempt,
+            final_stage: stage.output_links.is_empty(),
+            replicate_output: self.replicate_shuffles,
+        }))
+    }
+
+    /// Whether some tasks of the job read the node-local data of a node, and can only
+    /// run on the executors of that node
+    pub fn reads_node_local_data(&self) -> bool {
+        self.stages
+            .values()
+            .any(|stage| stage.task_nodes.iter().any(Option::is_some))
+    }
+
+    /// Whether some stages of the job have a particular resource profile, whose tasks
+    /// are offered to the executors of that profile first
+    pub fn has_resource_profiles(&self) -> bool {
+        self.stages
+            .values()
+            .any(|stage| stage.resource_profile != ResourceProfile::General)
     }
 
     pub fn finalize(&mut self) -> Result<()> {
@@ -623,12 +1369,39 @@ impl ExecutionGraph {
         self.status = JobStatus {
             status: Some(job_status::Status::Completed(CompletedJob {
                 partition_location,
+                usage: Some(self.usage.clone()),
+            })),
+        };
+
+        Ok(())
+    }
+
+    /// Complete the job with the output of an earlier job of the same plan. Its stages
+    /// are dropped, so that none of its tasks are ever scheduled.
+    pub fn complete_with_output(&mut self, output: Vec<PartitionLocation>) -> Result<()> {
+        let partition_location = output
+            .iter()
+            .cloned()
+            .map(|l| l.try_into())
+            .collect::<Result<Vec<_>>>()?;
+
+        self.stages.clear();
+        self.output_locations = output;
+        self.status = JobStatus {
+            status: Some(job_status::Status::Completed(CompletedJob {
+                partition_location,
+                usage: Some(self.usage.clone()),
             })),
         };
 
         Ok(())
     }
 
+    /// Resources used by the tasks of this job so far
+    pub fn usage(&self) -> &JobResourceUsage {
+        &self.usage
+    }
+
     pub fn update_status(&mut self, status: JobStatus) {
         self.status = status;
     }
@@ -642,33 +1415,420 @@ impl ExecutionGraph {
 
         if let Some(stage) = self.stages.get_mut(&stage_id) {
             stage.task_statuses[partition] = None;
+
+            // The task was never launched so it keeps waiting since it became ready
+            let info = &mut stage.task_infos[partition];
+            info.attempts = info.attempts.saturating_sub(1);
+            info.executor_id = None;
+            info.launch_time = None;
         }
     }
 
-    pub fn output_locations(&self) -> Vec<PartitionLocation> {
-        self.output_locations.clone()
+    /// Whether any task of the job was launched on executor `executor_id`
+    pub fn ran_on(&self, executor_id: &str) -> bool {
+        self.stages.values().any(|stage| {
+            stage
+                .task_infos
+                .iter()
+                .any(|info| info.executor_id.as_deref() == Some(executor_id))
+        })
     }
-}
 
-impl Debug for ExecutionGraph {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let stages = self
+    /// Reschedule the tasks of executor `executor_id` after it registered again, which
+    /// fenced off its previous registration, or after it was lost. Its running tasks are reset, as their
+    /// statuses are rejected, and so are its completed tasks whose output is yet to be
+    /// read, as the output may be lost with the previous registration. Stages resolved
+    /// with that output are resolved again once it is written again. Completed tasks
+    /// whose output was copied to another executor are not reset, and the output is
+    /// read from the copies instead. Returns the number of tasks reset.
+    pub fn reset_executor(&mut self, executor_id: &str) -> Result<usize> {
+        let incomplete_stages: HashSet<usize> = self
             .stages
             .iter()
-            .map(|(_, stage)| format!("{:?}", stage))
-            .collect::<Vec<String>>()
-            .join("\n");
-        write!(f, "ExecutionGraph[job_id={}, session_id={}, available_tasks={}, complete={}]\n{}", self.job_id, self.session_id, self.available_tasks(), self.complete(), stages)
+            .filter(|(_, stage)| !stage.complete())
+            .map(|(stage_id, _)| *stage_id)
+            .collect();
+
+        let mut reset = 0;
+        let mut lost_outputs = vec![];
+        let mut replicated_outputs: HashMap<usize, ShuffleReplicas> = HashMap::new();
+        for (stage_id, stage) in self.stages.iter_mut() {
+            // The output of the final stage is read once the job completes
+            let output_needed = stage.output_links.is_empty()
+                || stage
+                    .output_links
+                    .iter()
+                    .any(|link| incomplete_stages.contains(link));
+            let mut output_lost = false;
+            for partition in 0..stage.partitions {
+                if stage.task_infos[partition].executor_id.as_deref() != Some(executor_id)
+                {
+                    continue;
+                }
+                let completed = match stage.task_statuses[partition] {
+                    Some(task_status::Status::Running(_)) => false,
+                    Some(task_status::Status::Completed(_)) if output_needed => true,
+                    _ => continue,
+                };
+                if completed {
+                    if let Some((replica, paths)) =
+                        stage.fail_over_task(partition, executor_id)
+                    {
+                        let replicas = replicated_outputs.entry(*stage_id).or_default();
+                        for (path, replica_path) in paths {
+                            replicas.insert(path, (replica.clone(), replica_path));
+                        }
+                        continue;
+                    }
+                    output_lost = true;
+                }
+                stage.reset_task(partition);
+                reset += 1;
+            }
+            if output_lost {
+                lost_outputs.push(*stage_id);
+            }
+        }
+
+        // The copies are read from before the lost output is dropped, so that stages
+        // rolled back for both are resolved once the lost output is written again
+        for (stage_id, replicas) in replicated_outputs {
+            reset += self.fail_over_output(stage_id, executor_id, &replicas)?;
+        }
+        for stage_id in lost_outputs {
+            reset += self.invalidate_output(stage_id, executor_id)?;
+        }
+        Ok(reset)
     }
-}
 
-fn partition_to_location(
-    job_id: &str,
-    stage_id: usize,
-    executor: &ExecutorMetadata,
-    shuffles: Vec<ShuffleWritePartition>,
-) -> Vec<PartitionLocation> {
-    shuffles
+    /// Drop the output of stage `stage_id` written by executor `executor_id` from the
+    /// stages reading it. Returns the number of tasks of those stages reset.
+    fn invalidate_output(&mut self, stage_id: usize, executor_id: &str) -> Result<usize> {
+        let output_links = self
+            .stages
+            .get(&stage_id)
+            .map(|stage| stage.output_links.clone())
+            .unwrap_or_default();
+        if output_links.is_empty() {
+            self.output_locations
+                .retain(|location| location.executor_meta.id != executor_id);
+        }
+
+        let mut reset = 0;
+        for link in output_links {
+            let input_partition_counts = self.input_partition_counts(link);
+            let linked_stage = self.stages.get_mut(&link).ok_or_else(|| {
+                BallistaError::Internal(format!(
+                    "Error resetting job {}: Invalid output link {} for stage {}",
+                    self.job_id, link, stage_id
+                ))
+            })?;
+            reset += linked_stage.invalidate_input(
+                stage_id,
+                executor_id,
+                &input_partition_counts,
+            )?;
+        }
+        Ok(reset)
+    }
+
+    /// Read the output of stage `stage_id` written by executor `executor_id` from the
+    /// copies in `replicas` in the stages reading it, or in the output of the job if it
+    /// is the final stage. Returns the number of tasks of those stages reset.
+    fn fail_over_output(
+        &mut self,
+        stage_id: usize,
+        executor_id: &str,
+        replicas: &ShuffleReplicas,
+    ) -> Result<usize> {
+        let output_links = self
+            .stages
+            .get(&stage_id)
+            .map(|stage| stage.output_links.clone())
+            .unwrap_or_default();
+        if output_links.is_empty() {
+            for location in self.output_locations.iter_mut() {
+                move_to_replica(location, executor_id, replicas);
+            }
+        }
+
+        let mut reset = 0;
+        for link in output_links {
+            let input_partition_counts = self.input_partition_counts(link);
+            let fetch_priority = self.fetch_priority(link);
+            let linked_stage = self.stages.get_mut(&link).ok_or_else(|| {
+                BallistaError::Internal(format!(
+                    "Error failing over job {}: Invalid output link {} for stage {}",
+                    self.job_id, link, stage_id
+                ))
+            })?;
+            reset += linked_stage.fail_over_input(
+                stage_id,
+                executor_id,
+                replicas,
+                &input_partition_counts,
+                fetch_priority,
+            )?;
+        }
+        Ok(reset)
+    }
+
+    /// Number of partitions of each input stage of stage `stage_id`
+    fn input_partition_counts(&self, stage_id: usize) -> HashMap<usize, usize> {
+        self.stages
+            .get(&stage_id)
+            .map(|stage| {
+                stage
+                    .inputs
+                    .keys()
+                    .filter_map(|input| {
+                        self.stages
+                            .get(input)
+                            .map(|input_stage| (*input, input_stage.partitions))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn output_locations(&self) -> Vec<PartitionLocation> {
+        self.output_locations.clone()
+    }
+
+    /// Total statistics of the shuffle partitions written by the given stage so far.
+    /// Returns `None` if the stage does not exist.
+    pub fn stage_output_stats(&self, stage_id: usize) -> Option<PartitionStats> {
+        let stage = self.stages.get(&stage_id)?;
+        // Every stage reading the output of a shared stage gets all its partitions
+        let locations: Vec<&PartitionLocation> = match stage.output_links.first() {
+            Some(link) => self
+                .stages
+                .get(link)
+                .and_then(|linked_stage| linked_stage.inputs.get(&stage_id))
+                .map(|output| output.partition_locations.values().flatten().collect())
+                .unwrap_or_default(),
+            None => self.output_locations.iter().collect(),
+        };
+
+        let (mut num_rows, mut num_batches, mut num_bytes) = (0, 0, 0);
+        for location in locations {
+            let stats = &location.partition_stats;
+            num_rows += stats.num_rows().unwrap_or_default();
+            num_batches += stats.num_batches().unwrap_or_default();
+            num_bytes += stats.num_bytes().unwrap_or_default();
+        }
+        Some(PartitionStats::new(
+            Some(num_rows),
+            Some(num_batches),
+            Some(num_bytes),
+        ))
+    }
+
+    fn record_stage_span(
+        &self,
+        stage_id: usize,
+        start_time: Option<u64>,
+        error: Option<&str>,
+    ) {
+        let start_time = start_time
+            .map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+            .unwrap_or_else(SystemTime::now);
+        telemetry::record_stage_span(
+            &telemetry::extract_context(&self.trace_context),
+            &self.job_id,
+            stage_id,
+            start_time,
+            error,
+        );
+    }
+}
+
+impl Debug for ExecutionGraph {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let stages = self
+            .stages
+            .iter()
+            .map(|(_, stage)| format!("{:?}", stage))
+            .collect::<Vec<String>>()
+            .join("\n");
+        write!(f, "ExecutionGraph[job_id={}, session_id={}, available_tasks={}, complete={}]\n{}", self.job_id, self.session_id, self.available_tasks(), self.complete(), stages)
+    }
+}
+
+/// An executor label and its value, naming the node whose node-local data a task reads
+pub(crate) type TaskNode = (String, String);
+
+/// The node whose node-local data each task of a stage running `plan` reads, if any.
+/// Fails if a task would read the node-local data of several nodes.
+pub(crate) fn task_nodes(plan: &Arc<dyn ExecutionPlan>) -> Result<Vec<Option<TaskNode>>> {
+    if let Some(scan) = plan.as_any().downcast_ref::<NodeLocalScanExec>() {
+        return Ok(scan
+            .nodes()
+            .iter()
+            .map(|node| Some((scan.node_label().to_owned(), node.clone())))
+            .collect());
+    }
+    if plan.as_any().is::<UnionExec>() {
+        let mut nodes = vec![];
+        for child in plan.children() {
+            nodes.extend(task_nodes(&child)?);
+        }
+        return Ok(nodes);
+    }
+
+    let mut nodes = vec![None; plan.output_partitioning().partition_count()];
+    for child in plan.children() {
+        let child_nodes = task_nodes(&child)?;
+        if child_nodes.len() == nodes.len() {
+            for (node, child_node) in nodes.iter_mut().zip(child_nodes) {
+                merge_task_node(node, child_node)?;
+            }
+        } else {
+            // Each task reads every partition of the child
+            let mut all = None;
+            for child_node in child_nodes {
+                merge_task_node(&mut all, child_node)?;
+            }
+            for node in nodes.iter_mut() {
+                merge_task_node(node, all.clone())?;
+            }
+        }
+    }
+    Ok(nodes)
+}
+
+fn merge_task_node(node: &mut Option<TaskNode>, other: Option<TaskNode>) -> Result<()> {
+    match (node.as_ref(), other) {
+        (_, None) => Ok(()),
+        (None, other) => {
+            *node = other;
+            Ok(())
+        }
+        (Some(node), Some(other)) if *node == other => Ok(()),
+        (Some((_, node)), Some((_, other))) => {
+            Err(BallistaError::NotImplemented(format!(
+                "Tasks reading the node-local data of both node {} and node {} are not supported",
+                node, other
+            )))
+        }
+    }
+}
+
+/// Whether an executor with `labels` can run a task reading the node-local data of
+/// `node`
+fn runs_on(node: &Option<TaskNode>, labels: &BTreeMap<String, String>) -> bool {
+    match node {
+        Some((label, value)) => labels.get(label) == Some(value),
+        None => true,
+    }
+}
+
+/// Collect the number of rows read by every read of the output of stage `stage_id` in
+/// `plan`, `None` for reads of the whole output
+fn collect_row_limits(
+    plan: &Arc<dyn ExecutionPlan>,
+    stage_id: usize,
+    limits: &mut Vec<Option<usize>>,
+) {
+    if let Some(limit) = plan.as_any().downcast_ref::<GlobalLimitExec>() {
+        let shuffle = limit
+            .input()
+            .as_any()
+            .downcast_ref::<CoalescePartitionsExec>()
+            .and_then(|coalesce| {
+                coalesce
+                    .input()
+                    .as_any()
+                    .downcast_ref::<UnresolvedShuffleExec>()
+            });
+        if let (Some(fetch), Some(shuffle)) = (limit.fetch(), shuffle) {
+            if shuffle.stage_id == stage_id {
+                limits.push(Some(limit.skip().copied().unwrap_or_default() + *fetch));
+            }
+            return;
+        }
+    }
+    if let Some(shuffle) = plan.as_any().downcast_ref::<UnresolvedShuffleExec>() {
+        if shuffle.stage_id == stage_id {
+            limits.push(None);
+        }
+        return;
+    }
+    if let Some(bloom_filter) = plan.as_any().downcast_ref::<BloomFilterExec>() {
+        // The filter is built from all the rows of its build stage
+        if bloom_filter.build_stage_id() == stage_id {
+            limits.push(None);
+        }
+    }
+    for child in plan.children() {
+        collect_row_limits(&child, stage_id, limits);
+    }
+}
+
+/// Add the resources used by the attempt of task `partition` of `stage` which ended
+/// with `status` to `usage`
+fn record_usage(
+    usage: &mut JobResourceUsage,
+    stage: &ExecutionStage,
+    partition: usize,
+    status: &task_status::Status,
+) {
+    match status {
+        task_status::Status::Completed(completed) => {
+            // The output of final stages is the result of the job, not shuffled
+            if !stage.output_links.is_empty() {
+                usage.bytes_shuffled += completed
+                    .partitions
+                    .iter()
+                    .map(|partition| partition.num_bytes)
+                    .sum::<u64>();
+            }
+            usage.bytes_scanned += completed.bytes_scanned;
+        }
+        task_status::Status::Failed(_) => {}
+        _ => return,
+    }
+    usage.slot_millis += stage.task_infos[partition].run_time().unwrap_or_default();
+}
+
+pub(crate) fn timestamp_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+/// Copies of shuffle partitions kept by other executors, with the executor keeping each
+/// copy and its path there, by the path of the partition it copies
+type ShuffleReplicas = HashMap<String, (ExecutorMetadata, String)>;
+
+/// Point `location`, written by executor `executor_id`, to its copy in `replicas`, if
+/// it has one. Returns whether it was moved.
+fn move_to_replica(
+    location: &mut PartitionLocation,
+    executor_id: &str,
+    replicas: &ShuffleReplicas,
+) -> bool {
+    if location.executor_meta.id != executor_id {
+        return false;
+    }
+    match replicas.get(&location.path) {
+        Some((replica, path)) => {
+            location.executor_meta = replica.clone();
+            location.path = path.clone();
+            true
+        }
+        None => false,
+    }
+}
+
+fn partition_to_location(
+    job_id: &str,
+    stage_id: usize,
+    executor: &ExecutorMetadata,
+    shuffles: Vec<ShuffleWritePartition>,
+) -> Vec<PartitionLocation> {
+    shuffles
         .into_iter()
         .map(|shuffle| PartitionLocation {
             partition_id: PartitionId {
@@ -689,18 +1849,34 @@ fn partition_to_location(
 
 #[cfg(test)]
 mod test {
-    use crate::state::execution_graph::ExecutionGraph;
+    use crate::planner::find_unresolved_shuffles;
+    use crate::state::execution_graph::{task_nodes, ExecutionGraph, StageStatus, Task};
     use ballista_core::error::Result;
-    use ballista_core::serde::protobuf::{self, job_status, task_status};
-    use ballista_core::serde::scheduler::{ExecutorMetadata, ExecutorSpecification};
+    use ballista_core::execution_plans::{
+        BloomFilterBuildExec, BloomFilterExec, NodeLocalScanExec, ShuffleReaderExec,
+    };
+    use ballista_core::serde::protobuf::{
+        self, job_status, task_status, ResourceProfile,
+    };
+    use ballista_core::serde::scheduler::{
+        ExecutorMetadata, ExecutorSpecification, FetchPriority, PartitionLocation,
+        NODE_NAME_LABEL, RESOURCE_PROFILE_LABEL,
+    };
+    use ballista_core::telemetry;
     use datafusion::arrow::datatypes::{DataType, Field, Schema};
     use datafusion::logical_expr::{col, sum, Expr};
 
     use datafusion::logical_plan::JoinType;
+    use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
     use datafusion::physical_plan::display::DisplayableExecutionPlan;
+    use datafusion::physical_plan::limit::GlobalLimitExec;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::physical_plan::union::UnionExec;
+    use datafusion::physical_plan::ExecutionPlan;
     use datafusion::prelude::{SessionConfig, SessionContext};
     use datafusion::test_util::scan_empty;
 
+    use std::collections::BTreeMap;
     use std::sync::Arc;
 
     #[tokio::test]
@@ -749,6 +1925,107 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_shared_stage() -> Result<()> {
+        let mut join_graph = test_join_plan(4).await;
+
+        // Both sides of the join scan identical empty tables, so the scan is planned
+        // once and its output is read twice by the join
+        assert_eq!(join_graph.stages.len(), 4);
+        let scan_stage = join_graph
+            .stages
+            .values()
+            .find(|stage| stage.inputs.is_empty())
+            .unwrap();
+        assert_eq!(scan_stage.output_links.len(), 1);
+        let join_stage = &join_graph.stages[&scan_stage.output_links[0]];
+        assert_eq!(join_stage.inputs.len(), 1);
+        assert_eq!(find_unresolved_shuffles(&join_stage.plan)?.len(), 2);
+
+        drain_tasks(&mut join_graph)?;
+        assert!(join_graph.complete(), "Failed to complete join plan");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_priority() -> Result<()> {
+        let mut join_graph = test_join_plan(4).await.with_priority(7);
+        drain_tasks(&mut join_graph)?;
+        assert!(join_graph.complete(), "Failed to complete join plan");
+
+        // The partitions read by stages closer to the final stage are more critical
+        let final_stage = join_graph
+            .stages
+            .values()
+            .find(|stage| stage.output_links.is_empty())
+            .unwrap()
+            .stage_id;
+        assert_eq!(join_graph.stages_remaining(final_stage), 0);
+        for stage in join_graph.stages.values() {
+            let remaining = join_graph.stages_remaining(stage.stage_id);
+            for link in &stage.output_links {
+                assert!(join_graph.stages_remaining(*link) < remaining);
+            }
+            let mut readers = vec![];
+            collect_shuffle_readers(&stage.plan, &mut readers);
+            assert_eq!(readers.is_empty(), stage.inputs.is_empty());
+            for priority in readers {
+                assert_eq!(priority.job_priority, 7);
+                assert_eq!(priority.stages_remaining as usize, remaining);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_shuffle_readers(
+        plan: &Arc<dyn ExecutionPlan>,
+        priorities: &mut Vec<FetchPriority>,
+    ) {
+        if let Some(reader) = plan.as_any().downcast_ref::<ShuffleReaderExec>() {
+            priorities.push(reader.fetch_priority);
+        }
+        for child in plan.children() {
+            collect_shuffle_readers(&child, priorities);
+        }
+    }
+
+    fn collect_shuffle_locations(
+        plan: &Arc<dyn ExecutionPlan>,
+        locations: &mut Vec<PartitionLocation>,
+    ) {
+        if let Some(reader) = plan.as_any().downcast_ref::<ShuffleReaderExec>() {
+            locations.extend(reader.partition.iter().flatten().cloned());
+        }
+        for child in plan.children() {
+            collect_shuffle_locations(&child, locations);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        let progress = match agg_graph.status().status {
+            Some(job_status::Status::Running(progress)) => progress,
+            status => panic!("Unexpected status {:?}", status),
+        };
+        assert_eq!(progress.completed_tasks, 0);
+        assert_eq!(progress.completed_stages, 0);
+        assert_eq!(progress.total_stages as usize, agg_graph.stages.len());
+
+        drain_tasks(&mut agg_graph)?;
+        let progress = match agg_graph.status().status {
+            Some(job_status::Status::Running(progress)) => progress,
+            status => panic!("Unexpected status {:?}", status),
+        };
+        assert_eq!(progress.completed_tasks, progress.total_tasks);
+        assert_eq!(progress.running_tasks, 0);
+        assert_eq!(progress.completed_stages, progress.total_stages);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_finalize() -> Result<()> {
         let mut agg_graph = test_aggregation_plan(4).await;
@@ -776,51 +2053,697 @@ mod test {
         Ok(())
     }
 
-    fn drain_tasks(graph: &mut ExecutionGraph) -> Result<()> {
-        let executor = test_executor();
-        let job_id = graph.job_id().to_owned();
-        while let Some(task) = graph.pop_next_task("executor-id")? {
-            let mut partitions: Vec<protobuf::ShuffleWritePartition> = vec![];
-
-            let num_partitions = task
-                .output_partitioning
-                .map(|p| p.partition_count())
-                .unwrap_or(1);
-
-            for partition_id in 0..num_partitions {
-                partitions.push(protobuf::ShuffleWritePartition {
-                    partition_id: partition_id as u64,
-                    path: format!(
-                        "/{}/{}/{}",
-                        task.partition.job_id,
-                        task.partition.stage_id,
-                        task.partition.partition_id
-                    ),
-                    num_batches: 1,
-                    num_rows: 1,
-                    num_bytes: 1,
-                })
+    #[tokio::test]
+    async fn test_resource_usage() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+
+        // Every task scans 10 bytes and writes a byte per output partition
+        let (tasks, shuffled) =
+            agg_graph
+                .stages
+                .values()
+                .fold((0, 0), |(tasks, shuffled), stage| {
+                    let written = if stage.output_links.is_empty() {
+                        0
+                    } else {
+                        stage.partitions
+                            * stage
+                                .output_partitioning
+                                .as_ref()
+                                .map_or(1, |p| p.partition_count())
+                    };
+                    (tasks + stage.partitions, shuffled + written)
+                });
+
+        drain_tasks(&mut agg_graph)?;
+        agg_graph.finalize()?;
+
+        let usage = match agg_graph.status().status {
+            Some(job_status::Status::Completed(completed)) => completed.usage.unwrap(),
+            status => panic!("Unexpected status {:?}", status),
+        };
+        assert_eq!(usage.bytes_scanned, 10 * tasks as u64);
+        assert_eq!(usage.bytes_shuffled, shuffled as u64);
+        assert_eq!(&usage, agg_graph.usage());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_complete_with_output() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        drain_tasks(&mut agg_graph)?;
+        agg_graph.finalize()?;
+
+        let mut cached_graph = test_aggregation_plan(4).await;
+        cached_graph.complete_with_output(agg_graph.output_locations())?;
+
+        assert!(cached_graph.complete());
+        assert_eq!(cached_graph.available_tasks(), 0);
+        assert!(cached_graph.pop_next_task("executor-1")?.is_none());
+        assert_eq!(cached_graph.status(), agg_graph.status());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_trace_context() -> Result<()> {
+        telemetry::install_propagator();
+
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let mut agg_graph = test_aggregation_plan(4).await.with_trace_context(vec![
+            protobuf::KeyValuePair {
+                key: "traceparent".to_owned(),
+                value: format!("00-{}-00f067aa0ba902b7-01", trace_id),
+            },
+        ]);
+
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        assert!(agg_graph.stages[&task.partition.stage_id]
+            .start_time
+            .is_some());
+
+        // Tasks are parented to their stage within the trace of the query
+        let traceparent = &task.trace_context[0].value;
+        assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+
+        // A rescheduled task is parented to the same stage span
+        agg_graph.reset_task_status(task.clone());
+        let retried = agg_graph.pop_next_task("executor-id")?.unwrap();
+        assert_eq!(task.partition.stage_id, retried.partition.stage_id);
+        assert_eq!(task.trace_context, retried.trace_context);
+
+        // Without a trace context nothing is propagated
+        let mut untraced = test_aggregation_plan(4).await;
+        let task = untraced.pop_next_task("executor-id")?.unwrap();
+        assert!(task.trace_context.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stage_status_and_stats() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+
+        let statuses: Vec<StageStatus> =
+            agg_graph.stages.values().map(|s| s.status()).collect();
+        assert!(statuses.contains(&StageStatus::Resolved));
+        assert!(statuses.contains(&StageStatus::Pending));
+
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        assert_eq!(
+            agg_graph.stages[&task.partition.stage_id].status(),
+            StageStatus::Running
+        );
+        let stats = agg_graph
+            .stage_output_stats(task.partition.stage_id)
+            .unwrap();
+        assert_eq!(stats.num_rows(), Some(0));
+        agg_graph.reset_task_status(task);
+
+        drain_tasks(&mut agg_graph)?;
+        agg_graph.finalize()?;
+
+        for stage in agg_graph.stages.values() {
+            assert_eq!(stage.status(), StageStatus::Completed);
+
+            // Every shuffle partition written by `drain_tasks` holds a single row
+            let stats = agg_graph.stage_output_stats(stage.stage_id).unwrap();
+            assert!(stats.num_rows().unwrap() > 0);
+            assert_eq!(stats.num_rows(), stats.num_bytes());
+            assert_eq!(stats.num_rows(), stats.num_batches());
+        }
+        assert!(agg_graph.stage_output_stats(100).is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_task_infos() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        let stage_id = task.partition.stage_id;
+        let partition_id = task.partition.partition_id;
+        let info = &agg_graph.stages[&stage_id].task_infos[partition_id];
+        assert_eq!(info.attempts, 1);
+        assert_eq!(info.executor_id.as_deref(), Some("executor-id"));
+        assert!(info.queue_time().is_some());
+        assert!(info.end_time.is_none());
+
+        // A task which failed to launch does not count as an attempt
+        agg_graph.reset_task_status(task);
+        let info = &agg_graph.stages[&stage_id].task_infos[partition_id];
+        assert_eq!(info.attempts, 0);
+        assert!(info.launch_time.is_none());
+        assert!(info.ready_time.is_some());
+
+        drain_tasks(&mut agg_graph)?;
+
+        for stage in agg_graph.stages.values() {
+            for info in &stage.task_infos {
+                assert_eq!(info.attempts, 1);
+                assert!(info.ready_time.is_some());
+                assert!(info.end_time.is_some());
+                assert!(info.run_time().is_some());
             }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_previous_attempts() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        drain_tasks(&mut agg_graph)?;
+        assert_eq!(agg_graph.max_attempts(), 1);
+
+        // The tasks of a retried job are launched with attempts the failed run did
+        // not use
+        let mut agg_graph = test_aggregation_plan(4)
+            .await
+            .with_previous_attempts(agg_graph.max_attempts());
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        assert_eq!(task.attempt, 2);
+
+        Ok(())
+    }
 
-            // Complete the task
-            let task_status = protobuf::TaskStatus {
-                status: Some(task_status::Status::Completed(protobuf::CompletedTask {
-                    executor_id: "executor-1".to_owned(),
-                    partitions,
-                })),
+    #[tokio::test]
+    async fn test_ignore_stale_and_duplicate_statuses() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        let executor = test_executor();
+        let job_id = agg_graph.job_id().to_owned();
+        let status = |task: &Task, attempt: u32, status: task_status::Status| {
+            protobuf::TaskStatus {
                 task_id: Some(protobuf::PartitionId {
                     job_id: job_id.clone(),
                     stage_id: task.partition.stage_id as u32,
                     partition_id: task.partition.partition_id as u32,
                 }),
-            };
+                status: Some(status),
+                attempt,
+            }
+        };
+        let completed = task_status::Status::Completed(protobuf::CompletedTask {
+            executor_id: "executor-1".to_owned(),
+            partitions: vec![protobuf::ShuffleWritePartition {
+                partition_id: 0,
+                path: "/data.arrow".to_owned(),
+                num_batches: 1,
+                num_rows: 1,
+                num_bytes: 1,
+            }],
+            bytes_scanned: 0,
+            spilled_bytes: 0,
+            spill_files: 0,
+            bloom_filter: vec![],
+            replica: None,
+            operator_metrics: vec![],
+        });
+        let failed = task_status::Status::Failed(protobuf::FailedTask {
+            error: "zombie".to_owned(),
+            kind: protobuf::FailureKind::ExecutionError.into(),
+        });
+
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        assert_eq!(task.attempt, 1);
+        let stage_id = task.partition.stage_id;
+
+        // The output of a task is only registered once
+        for _ in 0..2 {
+            agg_graph.update_task_status(
+                &executor,
+                vec![status(&task, task.attempt, completed.clone())],
+            )?;
+        }
+        let stats = agg_graph.stage_output_stats(stage_id).unwrap();
+        assert_eq!(stats.num_rows(), Some(1));
+
+        // A completed task does not fail afterwards
+        agg_graph.update_task_status(
+            &executor,
+            vec![status(&task, task.attempt, failed.clone())],
+        )?;
+        assert!(!matches!(
+            agg_graph.status().status,
+            Some(job_status::Status::Failed(_))
+        ));
+
+        // Nor does a task because of another attempt
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        agg_graph.update_task_status(
+            &executor,
+            vec![status(&task, task.attempt + 1, failed.clone())],
+        )?;
+        assert!(!matches!(
+            agg_graph.status().status,
+            Some(job_status::Status::Failed(_))
+        ));
+        agg_graph.update_task_status(&executor, vec![status(&task, 0, failed)])?;
+        assert!(matches!(
+            agg_graph.status().status,
+            Some(job_status::Status::Failed(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reset_executor() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        let executor = test_executor();
+
+        // Complete the first stage and launch a task of the second one
+        let task = agg_graph.pop_next_task(&executor.id)?.unwrap();
+        let first_stage = task.partition.stage_id;
+        complete_task(&mut agg_graph, task)?;
+        while agg_graph.stages[&first_stage].available_tasks() > 0 {
+            let task = agg_graph.pop_next_task(&executor.id)?.unwrap();
+            complete_task(&mut agg_graph, task)?;
+        }
+        let running = agg_graph.pop_next_task(&executor.id)?.unwrap();
+        let final_stage = running.partition.stage_id;
+        assert_ne!(final_stage, first_stage);
+
+        // The executor registers again: its output and running task are lost
+        let reset = agg_graph.reset_executor(&executor.id)?;
+        assert_eq!(reset, agg_graph.stages[&first_stage].partitions + 1);
+        assert_eq!(agg_graph.stages[&first_stage].completed_tasks(), 0);
+        let stage = &agg_graph.stages[&final_stage];
+        assert!(!stage.resolved());
+        assert_eq!(find_unresolved_shuffles(&stage.plan)?.len(), 1);
+        assert_eq!(stage.running_tasks(), 0);
+
+        // The status of the previous registration is ignored
+        complete_task(&mut agg_graph, running)?;
+        assert_eq!(agg_graph.stages[&final_stage].completed_tasks(), 0);
+
+        drain_tasks(&mut agg_graph)?;
+        assert!(agg_graph.complete());
+        for stage in agg_graph.stages.values() {
+            assert!(stage.task_infos.iter().all(|info| info.attempts >= 1));
+        }
+        assert_eq!(agg_graph.stages[&first_stage].task_infos[0].attempts, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reset_executor_with_replicas() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await.with_shuffle_replication();
+        let executor = test_executor();
+        let replica = ExecutorMetadata {
+            id: "executor-3".to_string(),
+            host: "localhost3".to_string(),
+            ..test_executor()
+        };
+
+        // Complete the first stage, copying its output to another executor, and launch
+        // a task of the second one
+        let task = agg_graph.pop_next_task(&executor.id)?.unwrap();
+        assert!(task.replicate_output);
+        let first_stage = task.partition.stage_id;
+        complete_task_replicated(&mut agg_graph, task, &replica)?;
+        while agg_graph.stages[&first_stage].available_tasks() > 0 {
+            let task = agg_graph.pop_next_task(&executor.id)?.unwrap();
+            complete_task_replicated(&mut agg_graph, task, &replica)?;
+        }
+        let running = agg_graph.pop_next_task(&executor.id)?.unwrap();
+        let final_stage = running.partition.stage_id;
+
+        // The executor registers again: only its running task is lost, and the output
+        // of the first stage is read from its copies
+        let reset = agg_graph.reset_executor(&executor.id)?;
+        assert_eq!(reset, 1);
+        let stage = &agg_graph.stages[&first_stage];
+        assert!(stage.complete());
+        assert!(stage
+            .task_infos
+            .iter()
+            .all(|info| info.executor_id.as_deref() == Some("executor-3")));
+        let stage = &agg_graph.stages[&final_stage];
+        assert!(stage.resolved());
+        assert_eq!(stage.running_tasks(), 0);
+        let mut locations = vec![];
+        collect_shuffle_locations(&stage.plan, &mut locations);
+        assert!(!locations.is_empty());
+        for location in locations {
+            assert_eq!(location.executor_meta.id, replica.id);
+            assert!(location.path.starts_with("/replica/"));
+        }
+
+        // The copies are lost along with the executor keeping them
+        let reset = agg_graph.reset_executor(&replica.id)?;
+        assert_eq!(reset, agg_graph.stages[&first_stage].partitions);
+        assert!(!agg_graph.stages[&final_stage].resolved());
+
+        drain_tasks(&mut agg_graph)?;
+        assert!(agg_graph.complete());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_skip_tasks_past_limit() -> Result<()> {
+        let mut limit_graph = test_limit_plan(4, 2);
+        let executor = test_executor();
+
+        // Every task writes a row, so the limit is reached after two of them
+        let first = limit_graph.pop_next_task(&executor.id)?.unwrap();
+        let stage_id = first.partition.stage_id;
+        let second = limit_graph.pop_next_task(&executor.id)?.unwrap();
+        let running = limit_graph.pop_next_task(&executor.id)?.unwrap();
+        complete_task(&mut limit_graph, first)?;
+        assert_eq!(limit_graph.stages[&stage_id].completed_tasks(), 1);
+        complete_task(&mut limit_graph, second)?;
+
+        // The remaining tasks complete without output, whether they run or not
+        let stage = &limit_graph.stages[&stage_id];
+        assert!(stage.complete());
+        assert_eq!(stage.output_rows(), 2);
+        assert_eq!(
+            limit_graph.stage_output_stats(stage_id).unwrap().num_rows(),
+            Some(2)
+        );
+        complete_task(&mut limit_graph, running)?;
+        assert_eq!(limit_graph.stages[&stage_id].output_rows(), 2);
+
+        drain_tasks(&mut limit_graph)?;
+        assert!(limit_graph.complete());
+
+        // Stages read without a limit run all their tasks
+        let mut agg_graph = test_aggregation_plan(4).await;
+        let task = agg_graph.pop_next_task(&executor.id)?.unwrap();
+        let stage_id = task.partition.stage_id;
+        complete_task(&mut agg_graph, task)?;
+        assert!(!agg_graph.stages[&stage_id].complete());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_node_local_tasks() -> Result<()> {
+        let scan: Arc<dyn ExecutionPlan> = test_node_local_scan();
+        let schema = scan.schema();
+        let memory = Arc::new(MemoryExec::try_new(&[vec![]], schema, None)?);
+        let union = Arc::new(UnionExec::new(vec![scan.clone(), memory]));
+        let plan = Arc::new(CoalescePartitionsExec::new(union));
+        let mut graph = ExecutionGraph::new("job", "session", plan)?;
+        assert!(graph.reads_node_local_data());
+
+        let labels = |node: &str| {
+            let mut labels = BTreeMap::new();
+            labels.insert(NODE_NAME_LABEL.to_owned(), node.to_owned());
+            labels
+        };
+        // Executors of other nodes only run the tasks which do not read node-local data
+        let task = graph
+            .pop_next_task_on("executor-1", &labels("node-3"))?
+            .unwrap();
+        assert_eq!(task.partition.partition_id, 2);
+        assert!(graph.pop_next_task("executor-1")?.is_none());
+
+        let task = graph
+            .pop_next_task_on("executor-2", &labels("node-2"))?
+            .unwrap();
+        assert_eq!(task.partition.partition_id, 1);
+        assert!(graph
+            .pop_next_task_on("executor-2", &labels("node-2"))?
+            .is_none());
+        let task = graph
+            .pop_next_task_on("executor-3", &labels("node-1"))?
+            .unwrap();
+        assert_eq!(task.partition.partition_id, 0);
+
+        // A task cannot read the data of several nodes
+        let coalesce: Arc<dyn ExecutionPlan> =
+            Arc::new(CoalescePartitionsExec::new(scan));
+        assert!(task_nodes(&coalesce).is_err());
+
+        Ok(())
+    }
 
-            graph.update_task_status(&executor, vec![task_status])?;
+    #[tokio::test]
+    async fn test_resource_profile_preference() -> Result<()> {
+        let mut join_graph = test_join_plan(4).await;
+        let mut resolved: Vec<usize> = join_graph
+            .stages
+            .values()
+            .filter(|stage| stage.resolved())
+            .map(|stage| stage.stage_id)
+            .collect();
+        resolved.sort_unstable();
+        assert_eq!(resolved.len(), 2);
+        for stage_id in &resolved {
+            join_graph
+                .stages
+                .get_mut(stage_id)
+                .unwrap()
+                .resource_profile = ResourceProfile::General;
+        }
+        join_graph
+            .stages
+            .get_mut(&resolved[1])
+            .unwrap()
+            .resource_profile = ResourceProfile::Memory;
+
+        let mut labels = BTreeMap::new();
+        labels.insert(RESOURCE_PROFILE_LABEL.to_owned(), "memory".to_owned());
+        let task = join_graph.pop_next_task_on("executor-1", &labels)?.unwrap();
+        assert_eq!(task.partition.stage_id, resolved[1]);
+
+        // Executors without a profile are offered the tasks of other stages first
+        let task = join_graph.pop_next_task("executor-2")?.unwrap();
+        assert_eq!(task.partition.stage_id, resolved[0]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stage_spills() -> Result<()> {
+        let mut agg_graph = test_aggregation_plan(4).await;
+        let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+        let stage_id = task.partition.stage_id;
+        complete_task_spilling(&mut agg_graph, task, 25)?;
+        let mut tasks = 1;
+        while !agg_graph.stages[&stage_id].complete() {
+            let task = agg_graph.pop_next_task("executor-id")?.unwrap();
+            assert_eq!(task.partition.stage_id, stage_id);
+            complete_task_spilling(&mut agg_graph, task, 25)?;
+            tasks += 1;
+        }
+
+        // Every task scanned 10 bytes and spilled 25 bytes to a file
+        let stage = &agg_graph.stages[&stage_id];
+        assert_eq!(stage.spilled(), (25 * tasks, tasks));
+        assert_eq!(stage.input_bytes(), 10 * tasks);
+        assert!(stage.spills_excessively(2));
+        assert!(!stage.spills_excessively(3));
+
+        // The next stage also reads the output of the first one
+        let next_stage = &agg_graph.stages[&stage.output_links[0]];
+        assert_eq!(next_stage.spilled(), (0, 0));
+        assert!(next_stage.input_bytes() > 0);
+        assert!(!next_stage.spills_excessively(2));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bloom_filter_stages() -> Result<()> {
+        let plan = test_join_physical_plan(4).await;
+        let mut join_graph =
+            ExecutionGraph::new_with_bloom_filters("job", "session", plan, Some(64))?;
+        let stage_of = |graph: &ExecutionGraph, build: bool| {
+            graph
+                .stages
+                .values()
+                .find(|stage| {
+                    let input = stage.plan.children()[0].clone();
+                    if build {
+                        input.as_any().is::<BloomFilterBuildExec>()
+                    } else {
+                        input.as_any().is::<BloomFilterExec>()
+                    }
+                })
+                .map(|stage| stage.stage_id)
+                .unwrap()
+        };
+        let build = stage_of(&join_graph, true);
+        let probe = stage_of(&join_graph, false);
+
+        // The probe stage runs once the build stage completed
+        assert!(join_graph.stages[&build].output_links.contains(&probe));
+        assert!(!join_graph.stages[&probe].resolved());
+        while let Some(task) = join_graph.pop_next_task("executor-id")? {
+            assert_ne!(task.partition.stage_id, probe);
+            let stage_id = task.partition.stage_id;
+            complete_task(&mut join_graph, task)?;
+            if stage_id == build && join_graph.stages[&build].complete() {
+                break;
+            }
+        }
+        // The filters of the tasks are merged into the filter of the stage
+        assert!(join_graph.stages[&build]
+            .task_statuses
+            .iter()
+            .all(|status| {
+                matches!(
+                    status,
+                    Some(task_status::Status::Completed(completed))
+                        if completed.bloom_filter.is_empty()
+                )
+            }));
+        let probe_stage = &join_graph.stages[&probe];
+        assert!(probe_stage.resolved());
+        let filter = probe_stage.plan.children()[0]
+            .as_any()
+            .downcast_ref::<BloomFilterExec>()
+            .unwrap()
+            .filter()
+            .cloned();
+        assert_eq!(filter.map(|filter| filter.to_bytes().len()), Some(64));
+
+        drain_tasks(&mut join_graph)?;
+        assert!(join_graph.complete());
+
+        // Tasks of the build stage which report no filter, like those of executors
+        // predating Bloom filters, leave the rows of the probe side unfiltered
+        let plan = test_join_physical_plan(4).await;
+        let mut join_graph =
+            ExecutionGraph::new_with_bloom_filters("job", "session", plan, Some(64))?;
+        while let Some(mut task) = join_graph.pop_next_task("executor-id")? {
+            let stage_id = task.partition.stage_id;
+            if stage_id == build {
+                // Run the stage without the operator building the filter
+                task.plan = task.plan.children()[0].children()[0].clone();
+            }
+            complete_task(&mut join_graph, task)?;
+            if stage_id == build && join_graph.stages[&build].complete() {
+                break;
+            }
+        }
+        let probe_stage = &join_graph.stages[&probe];
+        assert!(probe_stage.resolved());
+        assert!(probe_stage.plan.children()[0]
+            .as_any()
+            .downcast_ref::<BloomFilterExec>()
+            .unwrap()
+            .filter()
+            .is_none());
+
+        Ok(())
+    }
+
+    fn drain_tasks(graph: &mut ExecutionGraph) -> Result<()> {
+        while let Some(task) = graph.pop_next_task("executor-id")? {
+            complete_task(graph, task)?;
+        }
+
+        Ok(())
+    }
+
+    /// Report the task as completed by the test executor
+    fn complete_task(graph: &mut ExecutionGraph, task: Task) -> Result<()> {
+        complete_task_spilling(graph, task, 0)
+    }
+
+    /// Report the task as completed by the test executor, after spilling `spilled_bytes`
+    /// to a file if any
+    fn complete_task_spilling(
+        graph: &mut ExecutionGraph,
+        task: Task,
+        spilled_bytes: u64,
+    ) -> Result<()> {
+        complete_task_with(graph, task, spilled_bytes, None)
+    }
+
+    /// Report the task as completed by the test executor, which copied its partitions
+    /// to executor `replica`
+    fn complete_task_replicated(
+        graph: &mut ExecutionGraph,
+        task: Task,
+        replica: &ExecutorMetadata,
+    ) -> Result<()> {
+        complete_task_with(graph, task, 0, Some(replica))
+    }
+
+    fn complete_task_with(
+        graph: &mut ExecutionGraph,
+        task: Task,
+        spilled_bytes: u64,
+        replica: Option<&ExecutorMetadata>,
+    ) -> Result<()> {
+        let executor = test_executor();
+        let job_id = graph.job_id().to_owned();
+        let mut partitions: Vec<protobuf::ShuffleWritePartition> = vec![];
+
+        let num_partitions = task
+            .output_partitioning
+            .map(|p| p.partition_count())
+            .unwrap_or(1);
+
+        for partition_id in 0..num_partitions {
+            partitions.push(protobuf::ShuffleWritePartition {
+                partition_id: partition_id as u64,
+                path: format!(
+                    "/{}/{}/{}",
+                    task.partition.job_id,
+                    task.partition.stage_id,
+                    task.partition.partition_id
+                ),
+                num_batches: 1,
+                num_rows: 1,
+                num_bytes: 1,
+            })
         }
 
+        // Complete the task
+        let task_status = protobuf::TaskStatus {
+            status: Some(task_status::Status::Completed(protobuf::CompletedTask {
+                executor_id: "executor-1".to_owned(),
+                bytes_scanned: 10,
+                spilled_bytes,
+                spill_files: if spilled_bytes > 0 { 1 } else { 0 },
+                bloom_filter: built_bloom_filter(&task.plan),
+                replica: replica.map(|replica| protobuf::ShuffleReplica {
+                    executor: Some(replica.clone().into()),
+                    paths: partitions
+                        .iter()
+                        .map(|partition| format!("/replica{}", partition.path))
+                        .collect(),
+                }),
+                partitions,
+                operator_metrics: vec![],
+            })),
+            task_id: Some(protobuf::PartitionId {
+                job_id,
+                stage_id: task.partition.stage_id as u32,
+                partition_id: task.partition.partition_id as u32,
+            }),
+            attempt: task.attempt,
+        };
+
+        graph.update_task_status(&executor, vec![task_status])?;
+
         Ok(())
     }
 
+    /// The Bloom filter the task would report, empty since it did not run
+    fn built_bloom_filter(plan: &Arc<dyn ExecutionPlan>) -> Vec<u8> {
+        match plan.as_any().downcast_ref::<BloomFilterBuildExec>() {
+            Some(build) => build.bloom_filter().to_bytes(),
+            None => plan
+                .children()
+                .iter()
+                .map(built_bloom_filter)
+                .find(|filter| !filter.is_empty())
+                .unwrap_or_default(),
+        }
+    }
+
     async fn test_aggregation_plan(partition: usize) -> ExecutionGraph {
         let config = SessionConfig::new().with_target_partitions(partition);
         let ctx = Arc::new(SessionContext::with_config(config));
@@ -870,6 +2793,16 @@ mod test {
     }
 
     async fn test_join_plan(partition: usize) -> ExecutionGraph {
+        let plan = test_join_physical_plan(partition).await;
+
+        let graph = ExecutionGraph::new("job", "session", plan).unwrap();
+
+        println!("{:?}", graph);
+
+        graph
+    }
+
+    async fn test_join_physical_plan(partition: usize) -> Arc<dyn ExecutionPlan> {
         let config = SessionConfig::new().with_target_partitions(partition);
         let ctx = Arc::new(SessionContext::with_config(config));
 
@@ -907,11 +2840,7 @@ mod test {
 
         println!("{}", DisplayableExecutionPlan::new(plan.as_ref()).indent());
 
-        let graph = ExecutionGraph::new("job", "session", plan).unwrap();
-
-        println!("{:?}", graph);
-
-        graph
+        plan
     }
 
     async fn test_union_all_plan(partition: usize) -> ExecutionGraph {
@@ -962,6 +2891,39 @@ mod test {
         graph
     }
 
+    /// A LIMIT of `limit` rows over a scan of `partition` partitions
+    fn test_limit_plan(partition: usize, limit: usize) -> ExecutionGraph {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let scan = MemoryExec::try_new(&vec![vec![]; partition], schema, None).unwrap();
+        let plan = Arc::new(GlobalLimitExec::new(
+            Arc::new(CoalescePartitionsExec::new(Arc::new(scan))),
+            None,
+            Some(limit),
+        ));
+
+        let graph = ExecutionGraph::new("job", "session", plan).unwrap();
+
+        println!("{:?}", graph);
+
+        graph
+    }
+
+    /// A scan of the node-local data of nodes `node-1` and `node-2`
+    fn test_node_local_scan() -> Arc<NodeLocalScanExec> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        Arc::new(
+            NodeLocalScanExec::try_new(
+                "/data/events".to_owned(),
+                ".parquet".to_owned(),
+                NODE_NAME_LABEL.to_owned(),
+                vec!["node-1".to_owned(), "node-2".to_owned()],
+                schema,
+                None,
+            )
+            .unwrap(),
+        )
+    }
+
     fn test_executor() -> ExecutorMetadata {
         ExecutorMetadata {
             id: "executor-2".to_string(),
@@ -969,6 +2931,8 @@ mod test {
             port: 8080,
             grpc_port: 9090,
             specification: ExecutorSpecification { task_slots: 1 },
+            labels: Default::default(),
+            protocol: Default::default(),
         }
     }
 }
@@ -18,16 +18,20 @@
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::state::backend::{Keyspace, StateBackendClient, WatchEvent};
+use crate::state::executor_pools::executor_pool;
+use crate::state::heartbeats::ExecutorHeartbeats;
 
 use crate::state::{decode_into, decode_protobuf, encode_protobuf, with_lock};
 use ballista_core::error::{BallistaError, Result};
 use ballista_core::serde::protobuf;
 
-use ballista_core::serde::scheduler::{ExecutorData, ExecutorMetadata};
+use ballista_core::protocol::Capability;
+use ballista_core::serde::scheduler::{ExecutorData, ExecutorMetadata, PartitionId};
 use futures::StreamExt;
-use log::{debug, info};
+use log::{debug, info, warn};
 use parking_lot::RwLock;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryInto;
 use std::sync::Arc;
 
 /// Represents a task slot that is reserved (i.e. available for scheduling but not visible to the
@@ -66,11 +70,45 @@ impl ExecutorReservation {
     }
 }
 
+/// The task slots of the alive executors of a pool
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PoolSlots {
+    pub executors: usize,
+    pub total_task_slots: usize,
+    pub available_task_slots: usize,
+}
+
+/// Time the draining executors are read from memory for, before they are read from the
+/// state backend again to see the executors drained through other schedulers
+const DRAINING_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct DrainingExecutors {
+    executors: HashSet<String>,
+    /// When `executors` was last read from the state backend
+    refreshed: Option<Instant>,
+}
+
+#[derive(Default)]
+struct ReplicaCandidates {
+    /// Executors supporting shuffle replication which were alive and not draining, by
+    /// ID
+    executors: Vec<ExecutorMetadata>,
+    /// When `executors` was last listed, `None` once executors registered, were
+    /// drained or were undrained since
+    refreshed: Option<Instant>,
+}
+
 #[derive(Clone)]
 pub(crate) struct ExecutorManager {
     state: Arc<dyn StateBackendClient>,
+    /// Metadata of the executors, by executor ID. Executors get a new ID whenever they
+    /// start, so the metadata of an ID does not change.
     executor_metadata: Arc<RwLock<HashMap<String, ExecutorMetadata>>>,
-    executors_heartbeat: Arc<RwLock<HashMap<String, protobuf::ExecutorHeartbeat>>>,
+    executors_heartbeat: Arc<ExecutorHeartbeats>,
+    draining: Arc<RwLock<DrainingExecutors>>,
+    /// Executors the copies of shuffle partitions may be kept on
+    replica_candidates: Arc<RwLock<ReplicaCandidates>>,
 }
 
 impl ExecutorManager {
@@ -78,10 +116,18 @@ impl ExecutorManager {
         Self {
             state,
             executor_metadata: Arc::new(RwLock::new(HashMap::new())),
-            executors_heartbeat: Arc::new(RwLock::new(HashMap::new())),
+            executors_heartbeat: Arc::new(ExecutorHeartbeats::default()),
+            draining: Arc::new(RwLock::new(DrainingExecutors::default())),
+            replica_candidates: Arc::new(RwLock::new(ReplicaCandidates::default())),
         }
     }
 
+    /// Consider executors lost once `lease` passed since the scheduler last heard of
+    /// them
+    pub(crate) fn set_executor_lease(&self, lease: Duration) {
+        self.executors_heartbeat.set_lease(lease);
+    }
+
     /// Initialize the `ExecutorManager` state. This will fill the `executor_heartbeats` value
     /// with existing heartbeats. Then new updates will be consumed through the `ExecutorHeartbeatListener`
     pub async fn init(&self) -> Result<()> {
@@ -105,7 +151,10 @@ impl ExecutorManager {
             let mut reservations: Vec<ExecutorReservation> = vec![];
             let mut desired: u32 = n;
 
-            let alive_executors = self.get_alive_executors_within_one_minute();
+            let mut alive_executors = self.get_alive_executors();
+            for executor_id in self.get_draining_executors().await? {
+                alive_executors.remove(&executor_id);
+            }
 
             let mut txn_ops: Vec<(Keyspace, String, Vec<u8>)> = vec![];
 
@@ -196,16 +245,12 @@ impl ExecutorManager {
 
     /// Get a list of all executors along with the timestamp of their last recorded heartbeat
     pub async fn get_executor_state(&self) -> Result<Vec<(ExecutorMetadata, Duration)>> {
-        let heartbeat_timestamps: Vec<(String, u64)> = {
-            let heartbeats = self.executors_heartbeat.read();
-
-            heartbeats
-                .iter()
-                .map(|(executor_id, heartbeat)| {
-                    (executor_id.clone(), heartbeat.timestamp)
-                })
-                .collect()
-        };
+        let heartbeat_timestamps: Vec<(String, u64)> = self
+            .executors_heartbeat
+            .all()
+            .into_iter()
+            .map(|heartbeat| (heartbeat.executor_id, heartbeat.timestamp))
+            .collect();
 
         let mut state: Vec<(ExecutorMetadata, Duration)> = vec![];
         for (executor_id, ts) in heartbeat_timestamps {
@@ -219,6 +264,20 @@ impl ExecutorManager {
         Ok(state)
     }
 
+    /// Get the latest heartbeat of every known executor
+    pub(crate) fn get_executor_heartbeats(&self) -> Vec<protobuf::ExecutorHeartbeat> {
+        self.executors_heartbeat.all()
+    }
+
+    /// Get the task slots of the given executor
+    pub(crate) async fn get_executor_data(
+        &self,
+        executor_id: &str,
+    ) -> Result<ExecutorData> {
+        let value = self.state.get(Keyspace::Slots, executor_id).await?;
+        decode_into::<protobuf::ExecutorData, ExecutorData>(&value)
+    }
+
     pub async fn get_executor_metadata(
         &self,
         executor_id: &str,
@@ -234,17 +293,39 @@ impl ExecutorManager {
 
         let decoded =
             decode_into::<protobuf::ExecutorMetadata, ExecutorMetadata>(&value)?;
+        self.executor_metadata
+            .write()
+            .insert(executor_id.to_owned(), decoded.clone());
         Ok(decoded)
     }
 
     pub async fn save_executor_metadata(&self, metadata: ExecutorMetadata) -> Result<()> {
         let executor_id = metadata.id.clone();
-        let proto: protobuf::ExecutorMetadata = metadata.into();
+        let proto: protobuf::ExecutorMetadata = metadata.clone().into();
         let value = encode_protobuf(&proto)?;
 
         self.state
-            .put(Keyspace::Executors, executor_id, value)
-            .await
+            .put(Keyspace::Executors, executor_id.clone(), value)
+            .await?;
+        self.executor_metadata.write().insert(executor_id, metadata);
+        Ok(())
+    }
+
+    /// The labels of executor `executor_id`, none if its metadata cannot be read
+    pub(crate) async fn executor_labels(
+        &self,
+        executor_id: &str,
+    ) -> BTreeMap<String, String> {
+        match self.get_executor_metadata(executor_id).await {
+            Ok(metadata) => metadata.labels,
+            Err(e) => {
+                warn!(
+                    "Failed to read metadata of executor {}: {:?}",
+                    executor_id, e
+                );
+                BTreeMap::new()
+            }
+        }
     }
 
     /// Register the executor with the scheduler. This will save the executor metadata and the
@@ -282,8 +363,10 @@ impl ExecutorManager {
             executor_id: executor_id.clone(),
             timestamp: current_ts,
             state: None,
+            version: 0,
         })
         .await?;
+        self.replica_candidates.write().refreshed = None;
 
         if !reserve {
             let proto: protobuf::ExecutorData = specification.into();
@@ -306,14 +389,83 @@ impl ExecutorManager {
         }
     }
 
+    /// Start a new epoch of the executor, fencing off the messages it sent with the epoch
+    /// of its previous registration
+    pub(crate) async fn register_epoch(&self, executor_id: &str) -> Result<u64> {
+        let lock = self
+            .state
+            .lock(Keyspace::ExecutorEpochs, executor_id)
+            .await?;
+        with_lock(lock, async {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|e| {
+                    BallistaError::Internal(format!(
+                        "Error getting current timestamp: {:?}",
+                        e
+                    ))
+                })?
+                .as_nanos() as u64;
+            let epoch = match self.get_epoch(executor_id).await? {
+                Some(previous) => now.max(previous + 1),
+                None => now,
+            };
+            self.state
+                .put(
+                    Keyspace::ExecutorEpochs,
+                    executor_id.to_owned(),
+                    epoch.to_be_bytes().to_vec(),
+                )
+                .await?;
+            Ok(epoch)
+        })
+        .await
+    }
+
+    /// The epoch of the latest registration of the executor, `None` if it is not
+    /// registered or was considered lost since
+    pub(crate) async fn get_epoch(&self, executor_id: &str) -> Result<Option<u64>> {
+        let value = self
+            .state
+            .get(Keyspace::ExecutorEpochs, executor_id)
+            .await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let bytes: [u8; 8] = value.as_slice().try_into().map_err(|_| {
+            BallistaError::Internal(format!(
+                "Invalid epoch of executor {}: {:?}",
+                executor_id, value
+            ))
+        })?;
+        Ok(Some(u64::from_be_bytes(bytes)))
+    }
+
+    /// Check that a message of the executor was sent with the epoch of its latest
+    /// registration. Epoch 0 is sent by executors which predate epochs, and is always
+    /// accepted.
+    pub(crate) async fn check_epoch(&self, executor_id: &str, epoch: u64) -> Result<()> {
+        if epoch == 0 {
+            return Ok(());
+        }
+        match self.get_epoch(executor_id).await? {
+            Some(current) if current == epoch => Ok(()),
+            current => Err(BallistaError::General(format!(
+                "Executor {} sent a message with stale epoch {} (current epoch {:?}), it must register again",
+                executor_id, epoch, current
+            ))),
+        }
+    }
+
     #[cfg(not(test))]
     async fn test_scheduler_connectivity(
         &self,
         metadata: &ExecutorMetadata,
     ) -> Result<()> {
-        let executor_url = format!("http://{}:{}", metadata.host, metadata.grpc_port);
+        let executor_url =
+            ballista_core::tls::grpc_url(&metadata.host, metadata.grpc_port);
         debug!("Connecting to executor {:?}", executor_url);
-        let _ = protobuf::executor_grpc_client::ExecutorGrpcClient::connect(executor_url)
+        let _ = ballista_core::tls::create_grpc_channel(&executor_url)
             .await
             .map_err(|e| {
                 BallistaError::Internal(format!(
@@ -332,59 +484,290 @@ impl ExecutorManager {
         Ok(())
     }
 
+    /// Record a heartbeat of an executor, whose state only has the metrics which changed
+    /// since its previous heartbeat. Heartbeats are only written to the state backend
+    /// when the state of the executor changes, or once in a while.
     pub(crate) async fn save_executor_heartbeat(
         &self,
         heartbeat: protobuf::ExecutorHeartbeat,
     ) -> Result<()> {
-        let executor_id = heartbeat.executor_id.clone();
-        let value = encode_protobuf(&heartbeat)?;
-        self.state
-            .put(Keyspace::Heartbeats, executor_id, value)
-            .await?;
-
-        let mut executors_heartbeat = self.executors_heartbeat.write();
-        executors_heartbeat.insert(heartbeat.executor_id.clone(), heartbeat);
-
+        if let Some(heartbeat) = self.executors_heartbeat.update(heartbeat) {
+            let executor_id = heartbeat.executor_id.clone();
+            let value = encode_protobuf(&heartbeat)?;
+            self.state
+                .put(Keyspace::Heartbeats, executor_id, value)
+                .await?;
+        }
         Ok(())
     }
 
     /// Initialize the set of executor heartbeats from storage
     pub(crate) async fn init_executor_heartbeats(&self) -> Result<()> {
         let heartbeats = self.state.scan(Keyspace::Heartbeats, None).await?;
-        let mut cache = self.executors_heartbeat.write();
-
         for (_, value) in heartbeats {
             let data: protobuf::ExecutorHeartbeat = decode_protobuf(&value)?;
-            let executor_id = data.executor_id.clone();
-            cache.insert(executor_id, data);
+            self.executors_heartbeat.insert_persisted(data);
         }
         Ok(())
     }
 
-    /// Retrieve the set of all executor IDs where the executor has been observed in the last
-    /// `last_seen_ts_threshold` seconds.
-    pub(crate) fn get_alive_executors(
+    /// Retrieve the set of all executor IDs where the executor has been heard of within
+    /// its lease
+    pub(crate) fn get_alive_executors(&self) -> HashSet<String> {
+        self.executors_heartbeat.alive()
+    }
+
+    /// IDs of the executors lost since the previous call, because they were expired or
+    /// their lease is over, so that the outputs they hold are recovered once per loss
+    pub(crate) fn take_lost_executors(&self) -> Vec<String> {
+        let lost = self.executors_heartbeat.take_lost();
+        let mut metadata = self.executor_metadata.write();
+        for executor_id in &lost {
+            metadata.remove(executor_id);
+        }
+        lost
+    }
+
+    /// Time executors are considered alive for after the scheduler last heard of them,
+    /// which executors are told to send their heartbeats well within
+    pub(crate) fn executor_lease(&self) -> Duration {
+        self.executors_heartbeat.lease()
+    }
+
+    /// The task slots of the alive executors of each pool, by pool
+    pub(crate) async fn pool_task_slots(&self) -> Result<BTreeMap<String, PoolSlots>> {
+        let mut pools: BTreeMap<String, PoolSlots> = BTreeMap::new();
+        for executor_id in self.get_alive_executors() {
+            let metadata = self.get_executor_metadata(&executor_id).await?;
+            let data = self.get_executor_data(&executor_id).await?;
+            let slots = pools
+                .entry(executor_pool(&metadata.labels).to_owned())
+                .or_default();
+            slots.executors += 1;
+            slots.total_task_slots += data.total_task_slots as usize;
+            slots.available_task_slots += data.available_task_slots as usize;
+        }
+        Ok(pools)
+    }
+
+    /// Number of alive executors of `pool` and their total number of task slots, from
+    /// the cached metadata of the executors
+    pub(crate) async fn pool_capacity(&self, pool: &str) -> Result<(usize, usize)> {
+        let mut executors = 0;
+        let mut total_task_slots = 0;
+        for executor_id in self.get_alive_executors() {
+            let metadata = self.get_executor_metadata(&executor_id).await?;
+            if executor_pool(&metadata.labels) == pool {
+                executors += 1;
+                total_task_slots += metadata.specification.task_slots as usize;
+            }
+        }
+        Ok((executors, total_task_slots))
+    }
+
+    /// Total number of task slots of the alive executors of `pool`
+    pub(crate) async fn total_task_slots_in_pool(&self, pool: &str) -> Result<usize> {
+        Ok(self.pool_capacity(pool).await?.1)
+    }
+
+    /// Get the metadata of the alive executors labeled with `value` for label `key`
+    pub(crate) async fn get_alive_executors_with_label(
         &self,
-        last_seen_ts_threshold: u64,
-    ) -> HashSet<String> {
-        let executors_heartbeat = self.executors_heartbeat.read();
-        executors_heartbeat
-            .iter()
-            .filter_map(|(exec, heartbeat)| {
-                (heartbeat.timestamp > last_seen_ts_threshold).then(|| exec.clone())
-            })
-            .collect()
+        key: &str,
+        value: &str,
+    ) -> Result<Vec<ExecutorMetadata>> {
+        let mut executors = vec![];
+        for executor_id in self.get_alive_executors() {
+            let metadata = self.get_executor_metadata(&executor_id).await?;
+            if metadata.label(key) == Some(value) {
+                executors.push(metadata);
+            }
+        }
+        Ok(executors)
     }
 
-    #[allow(dead_code)]
-    fn get_alive_executors_within_one_minute(&self) -> HashSet<String> {
-        let now_epoch_ts = SystemTime::now()
+    /// Stop scheduling tasks on executor `executor_id`, letting the tasks it runs finish.
+    /// Returns `false` if the scheduler never heard of the executor.
+    pub(crate) async fn drain_executor(&self, executor_id: &str) -> Result<bool> {
+        if self
+            .state
+            .get(Keyspace::Executors, executor_id)
+            .await?
+            .is_empty()
+        {
+            return Ok(false);
+        }
+        let drained_at = SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        let last_seen_threshold = now_epoch_ts
-            .checked_sub(Duration::from_secs(60))
-            .unwrap_or_else(|| Duration::from_secs(0));
-        self.get_alive_executors(last_seen_threshold.as_secs())
+            .map_err(|e| {
+                BallistaError::Internal(format!(
+                    "Error getting current timestamp: {:?}",
+                    e
+                ))
+            })?
+            .as_secs();
+        info!("Draining executor {}", executor_id);
+        self.state
+            .put(
+                Keyspace::DrainingExecutors,
+                executor_id.to_owned(),
+                drained_at.to_string().into_bytes(),
+            )
+            .await?;
+        self.draining
+            .write()
+            .executors
+            .insert(executor_id.to_owned());
+        self.replica_candidates.write().refreshed = None;
+        Ok(true)
+    }
+
+    /// Schedule tasks on executor `executor_id` again. Returns `false` if the executor
+    /// was not draining.
+    pub(crate) async fn undrain_executor(&self, executor_id: &str) -> Result<bool> {
+        if self
+            .state
+            .get(Keyspace::DrainingExecutors, executor_id)
+            .await?
+            .is_empty()
+        {
+            return Ok(false);
+        }
+        info!("Undraining executor {}", executor_id);
+        self.state
+            .delete(Keyspace::DrainingExecutors, executor_id)
+            .await?;
+        self.draining.write().executors.remove(executor_id);
+        self.replica_candidates.write().refreshed = None;
+        Ok(true)
+    }
+
+    /// IDs of the executors no task is scheduled on any more
+    pub(crate) async fn get_draining_executors(&self) -> Result<HashSet<String>> {
+        self.refresh_draining_executors().await?;
+        Ok(self.draining.read().executors.clone())
+    }
+
+    /// Whether no task is scheduled on executor `executor_id` any more
+    pub(crate) async fn is_draining(&self, executor_id: &str) -> Result<bool> {
+        self.refresh_draining_executors().await?;
+        Ok(self.draining.read().executors.contains(executor_id))
+    }
+
+    /// Read the draining executors from the state backend if they were last read over
+    /// [DRAINING_REFRESH_INTERVAL] ago
+    async fn refresh_draining_executors(&self) -> Result<()> {
+        let fresh = self
+            .draining
+            .read()
+            .refreshed
+            .map_or(false, |at| at.elapsed() < DRAINING_REFRESH_INTERVAL);
+        if !fresh {
+            let executors = self.state.scan_keys(Keyspace::DrainingExecutors).await?;
+            *self.draining.write() = DrainingExecutors {
+                executors,
+                refreshed: Some(Instant::now()),
+            };
+        }
+        Ok(())
+    }
+
+    /// Pick the executor keeping the copies of the output of task `task`, launched on
+    /// executor `executor_id`: another alive executor supporting shuffle replication,
+    /// which is not draining, on another host if there is one. The copies of the tasks
+    /// of a stage are spread over these executors. Returns `None` if there is no such
+    /// executor.
+    pub(crate) async fn get_replica_executor(
+        &self,
+        executor_id: &str,
+        task: &PartitionId,
+    ) -> Result<Option<ExecutorMetadata>> {
+        self.refresh_replica_candidates().await?;
+        let host = self
+            .get_executor_metadata(executor_id)
+            .await
+            .ok()
+            .map(|metadata| metadata.host);
+        let alive = self.get_alive_executors();
+
+        let replica_candidates = self.replica_candidates.read();
+        let candidates: Vec<&ExecutorMetadata> = replica_candidates
+            .executors
+            .iter()
+            .filter(|metadata| metadata.id != executor_id && alive.contains(&metadata.id))
+            .collect();
+        // Copies on another host survive the loss of the host of the executor
+        let other_hosts: Vec<&ExecutorMetadata> = candidates
+            .iter()
+            .copied()
+            .filter(|metadata| Some(&metadata.host) != host.as_ref())
+            .collect();
+        let candidates = if other_hosts.is_empty() {
+            candidates
+        } else {
+            other_hosts
+        };
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let index = (task.stage_id + task.partition_id) % candidates.len();
+        Ok(Some(candidates[index].clone()))
+    }
+
+    /// List the executors copies may be kept on again if they were last listed over
+    /// [DRAINING_REFRESH_INTERVAL] ago, or executors changed since. Executors lost since
+    /// they were listed are left out when picking one.
+    async fn refresh_replica_candidates(&self) -> Result<()> {
+        let fresh = self
+            .replica_candidates
+            .read()
+            .refreshed
+            .map_or(false, |at| at.elapsed() < DRAINING_REFRESH_INTERVAL);
+        if fresh {
+            return Ok(());
+        }
+        let draining = self.get_draining_executors().await?;
+        let mut executor_ids: Vec<String> = self
+            .get_alive_executors()
+            .into_iter()
+            .filter(|id| !draining.contains(id))
+            .collect();
+        executor_ids.sort();
+
+        let mut executors = vec![];
+        for id in executor_ids {
+            let metadata = self.get_executor_metadata(&id).await?;
+            if metadata.protocol.supports(Capability::ShuffleReplication) {
+                executors.push(metadata);
+            }
+        }
+        *self.replica_candidates.write() = ReplicaCandidates {
+            executors,
+            refreshed: Some(Instant::now()),
+        };
+        Ok(())
+    }
+
+    /// Consider the executor lost without waiting for its heartbeats to time out. Its
+    /// current epoch is dropped, so that the messages it may still send are rejected
+    /// until it registers again.
+    pub(crate) async fn expire_executor(&self, executor_id: &str) -> Result<()> {
+        let lock = self
+            .state
+            .lock(Keyspace::ExecutorEpochs, executor_id)
+            .await?;
+        with_lock(
+            lock,
+            self.state.delete(Keyspace::ExecutorEpochs, executor_id),
+        )
+        .await?;
+        self.save_executor_heartbeat(protobuf::ExecutorHeartbeat {
+            executor_id: executor_id.to_owned(),
+            timestamp: 0,
+            state: None,
+            version: 0,
+        })
+        .await
     }
 }
 
@@ -393,13 +776,13 @@ impl ExecutorManager {
 /// and maintain an in-memory copy of the executor heartbeats.
 struct ExecutorHeartbeatListener {
     state: Arc<dyn StateBackendClient>,
-    executors_heartbeat: Arc<RwLock<HashMap<String, protobuf::ExecutorHeartbeat>>>,
+    executors_heartbeat: Arc<ExecutorHeartbeats>,
 }
 
 impl ExecutorHeartbeatListener {
     pub fn new(
         state: Arc<dyn StateBackendClient>,
-        executors_heartbeat: Arc<RwLock<HashMap<String, protobuf::ExecutorHeartbeat>>>,
+        executors_heartbeat: Arc<ExecutorHeartbeats>,
     ) -> Self {
         Self {
             state,
@@ -421,10 +804,7 @@ impl ExecutorHeartbeatListener {
                     if let Ok(data) =
                         decode_protobuf::<protobuf::ExecutorHeartbeat>(&value)
                     {
-                        let executor_id = data.executor_id.clone();
-                        let mut heartbeats = heartbeats.write();
-
-                        heartbeats.insert(executor_id, data);
+                        heartbeats.insert_persisted(data);
                     }
                 }
             }
@@ -437,11 +817,15 @@ impl ExecutorHeartbeatListener {
 #[cfg(test)]
 mod test {
     use crate::state::backend::standalone::StandaloneClient;
+    use crate::state::backend::{Keyspace, StateBackendClient};
     use crate::state::executor_manager::{ExecutorManager, ExecutorReservation};
     use ballista_core::error::Result;
+    use ballista_core::protocol::ProtocolInfo;
     use ballista_core::serde::scheduler::{
-        ExecutorData, ExecutorMetadata, ExecutorSpecification,
+        ExecutorData, ExecutorMetadata, ExecutorSpecification, PartitionId, DEFAULT_POOL,
+        POD_NAME_LABEL, POOL_LABEL,
     };
+    use std::collections::HashSet;
     use std::sync::Arc;
 
     #[tokio::test]
@@ -582,6 +966,246 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_expire_executor() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+
+        let executor_manager = ExecutorManager::new(state_storage);
+
+        for (executor_metadata, executor_data) in test_executors(3, 4) {
+            executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+
+        let executors = executor_manager
+            .get_alive_executors_with_label(POD_NAME_LABEL, "pod-1")
+            .await?;
+        assert_eq!(executors.len(), 1);
+        assert_eq!(executors[0].id, "executor-1");
+
+        assert_eq!(
+            executor_manager
+                .total_task_slots_in_pool(DEFAULT_POOL)
+                .await?,
+            12
+        );
+        executor_manager.expire_executor("executor-1").await?;
+        assert_eq!(
+            executor_manager
+                .total_task_slots_in_pool(DEFAULT_POOL)
+                .await?,
+            8
+        );
+
+        let alive = executor_manager.get_alive_executors();
+        assert_eq!(alive.len(), 2);
+        assert!(!alive.contains("executor-1"));
+        assert!(executor_manager
+            .get_alive_executors_with_label(POD_NAME_LABEL, "pod-1")
+            .await?
+            .is_empty());
+
+        // Only the slots of alive executors can be reserved
+        let reservations = executor_manager.reserve_slots(12).await?;
+        assert_eq!(reservations.len(), 8);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pool_task_slots() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+
+        let executor_manager = ExecutorManager::new(state_storage);
+
+        for (i, (mut executor_metadata, executor_data)) in
+            test_executors(3, 4).into_iter().enumerate()
+        {
+            if i > 0 {
+                executor_metadata
+                    .labels
+                    .insert(POOL_LABEL.to_owned(), "batch".to_owned());
+            }
+            executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        executor_manager.reserve_slots(2).await?;
+
+        let pools = executor_manager.pool_task_slots().await?;
+        assert_eq!(pools.len(), 2);
+        assert_eq!(
+            (pools["batch"].executors, pools["batch"].total_task_slots),
+            (2, 8)
+        );
+        assert_eq!(
+            (
+                pools[DEFAULT_POOL].executors,
+                pools[DEFAULT_POOL].total_task_slots
+            ),
+            (1, 4)
+        );
+        // Reserved slots are not available in any pool
+        let available: usize =
+            pools.values().map(|slots| slots.available_task_slots).sum();
+        assert_eq!(available, 10);
+        assert_eq!(
+            executor_manager
+                .total_task_slots_in_pool(DEFAULT_POOL)
+                .await?,
+            4
+        );
+        assert_eq!(executor_manager.total_task_slots_in_pool("gpu").await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_executor_labels() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+
+        let executor_manager = ExecutorManager::new(state_storage.clone());
+        let (executor_metadata, executor_data) = test_executors(1, 4).remove(0);
+        executor_manager
+            .register_executor(executor_metadata, executor_data, false)
+            .await?;
+
+        // The labels are not read from the state backend again
+        state_storage
+            .delete(Keyspace::Executors, "executor-0")
+            .await?;
+        let labels = executor_manager.executor_labels("executor-0").await;
+        assert_eq!(labels[POD_NAME_LABEL], "pod-0");
+        // Nor kept once the executor is lost
+        executor_manager.expire_executor("executor-0").await?;
+        assert_eq!(executor_manager.take_lost_executors(), vec!["executor-0"]);
+        assert!(executor_manager
+            .executor_labels("executor-0")
+            .await
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_fence_stale_epochs() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let executor_manager = ExecutorManager::new(state_storage);
+
+        let first = executor_manager.register_epoch("executor-1").await?;
+        executor_manager.check_epoch("executor-1", first).await?;
+
+        // Registering again fences off the previous epoch
+        let second = executor_manager.register_epoch("executor-1").await?;
+        assert!(second > first);
+        assert!(executor_manager
+            .check_epoch("executor-1", first)
+            .await
+            .is_err());
+        executor_manager.check_epoch("executor-1", second).await?;
+
+        // So does expiring the executor
+        executor_manager.expire_executor("executor-1").await?;
+        assert!(executor_manager
+            .check_epoch("executor-1", second)
+            .await
+            .is_err());
+
+        // Executors which predate epochs are not fenced
+        executor_manager.check_epoch("executor-1", 0).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_drain_executor() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let executor_manager = ExecutorManager::new(state_storage.clone());
+
+        for (executor_metadata, executor_data) in test_executors(2, 4) {
+            executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        assert!(!executor_manager.drain_executor("executor-9").await?);
+        assert!(executor_manager.drain_executor("executor-0").await?);
+        assert!(executor_manager.is_draining("executor-0").await?);
+        assert!(!executor_manager.is_draining("executor-1").await?);
+
+        // Other schedulers read the draining executors from the state backend
+        let other = ExecutorManager::new(state_storage);
+        assert_eq!(
+            other.get_draining_executors().await?,
+            HashSet::from(["executor-0".to_owned()])
+        );
+
+        assert!(executor_manager.undrain_executor("executor-0").await?);
+        assert!(!executor_manager.undrain_executor("executor-0").await?);
+        assert!(!executor_manager.is_draining("executor-0").await?);
+        assert!(executor_manager.get_draining_executors().await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replica_executor() -> Result<()> {
+        let state_storage = Arc::new(StandaloneClient::try_new_temporary()?);
+        let executor_manager = ExecutorManager::new(state_storage);
+
+        for (mut executor_metadata, executor_data) in test_executors(4, 4) {
+            if executor_metadata.id == "executor-3" {
+                executor_metadata.protocol = ProtocolInfo::legacy();
+            }
+            executor_manager
+                .register_executor(executor_metadata, executor_data, false)
+                .await?;
+        }
+        executor_manager.drain_executor("executor-2").await?;
+
+        // Copies go to the other executors supporting replication, which are not draining
+        let mut replicas = HashSet::new();
+        for partition_id in 0..4 {
+            let task = PartitionId::new("job", 1, partition_id);
+            let replica = executor_manager
+                .get_replica_executor("executor-0", &task)
+                .await?
+                .unwrap();
+            replicas.insert(replica.id);
+        }
+        assert_eq!(replicas, HashSet::from(["executor-1".to_owned()]));
+
+        // Executors on the host of the executor are only picked if there is no other
+        let (mut same_host, executor_data) = test_executors(5, 4).pop().unwrap();
+        same_host.host = "host-0".to_owned();
+        executor_manager
+            .register_executor(same_host, executor_data, false)
+            .await?;
+        for partition_id in 0..4 {
+            let task = PartitionId::new("job", 1, partition_id);
+            let replica = executor_manager
+                .get_replica_executor("executor-0", &task)
+                .await?
+                .unwrap();
+            assert_eq!(replica.id, "executor-1");
+        }
+        executor_manager.expire_executor("executor-1").await?;
+        let task = PartitionId::new("job", 1, 0);
+        let replica = executor_manager
+            .get_replica_executor("executor-0", &task)
+            .await?
+            .unwrap();
+        assert_eq!(replica.id, "executor-4");
+
+        executor_manager.expire_executor("executor-4").await?;
+        assert!(executor_manager
+            .get_replica_executor("executor-0", &task)
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+
     fn test_executors(
         total_executors: usize,
         slots_per_executor: u32,
@@ -598,6 +1222,10 @@ mod test {
                     specification: ExecutorSpecification {
                         task_slots: slots_per_executor,
                     },
+                    labels: vec![(POD_NAME_LABEL.to_owned(), format!("pod-{}", i))]
+                        .into_iter()
+                        .collect(),
+                    protocol: Default::default(),
                 },
                 ExecutorData {
                     executor_id: format!("executor-{}", i),
@@ -0,0 +1,188 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Named pools of executors, whose task slots only run the tasks of the jobs routed to
+//! them.
+//!
+//! An executor belongs to the pool of its `ballista.pool` label, or to the default pool.
+//! A job is routed to the pool of its tenant in the policy of the scheduler. Jobs of the
+//! other tenants are routed to the pool set by `ballista.job.pool` when they are
+//! submitted, unless the policy routes tenants to that pool, or else to the default
+//! pool.
+//!
+//! The pool of a job is saved with its execution graph, so that every scheduler runs its
+//! tasks in that pool.
+
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::scheduler::{DEFAULT_POOL, POOL_LABEL};
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+
+/// The pool of the executor labeled with `labels`
+pub fn executor_pool(labels: &BTreeMap<String, String>) -> &str {
+    labels
+        .get(POOL_LABEL)
+        .map(|pool| pool.as_str())
+        .unwrap_or(DEFAULT_POOL)
+}
+
+/// The pools the jobs of tenants are routed to when they do not set their pool
+#[derive(Clone, Debug, Default)]
+pub struct TenantPools {
+    pools: HashMap<String, String>,
+}
+
+impl TenantPools {
+    /// Parse `tenant=pool` pairs separated by commas, e.g. `alice=interactive,etl=batch`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut pools = HashMap::new();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            match pair.split_once('=') {
+                Some((tenant, pool))
+                    if !tenant.trim().is_empty() && !pool.trim().is_empty() =>
+                {
+                    pools.insert(tenant.trim().to_owned(), pool.trim().to_owned());
+                }
+                _ => {
+                    return Err(BallistaError::General(format!(
+                        "Invalid tenant pool '{}', expected tenant=pool",
+                        pair
+                    )))
+                }
+            }
+        }
+        Ok(Self { pools })
+    }
+
+    /// The pool of a job of `tenant` which asked to run in pool `requested`, if any.
+    /// Fails if the job asked for a pool other than the one its tenant is routed to, or
+    /// for a pool other tenants are routed to.
+    pub fn job_pool(
+        &self,
+        requested: Option<String>,
+        tenant: Option<&str>,
+    ) -> Result<String> {
+        let assigned = tenant.and_then(|tenant| self.pools.get(tenant));
+        match (requested, assigned) {
+            (None, assigned) => {
+                Ok(assigned.cloned().unwrap_or_else(|| DEFAULT_POOL.to_owned()))
+            }
+            (Some(requested), Some(assigned)) if requested == *assigned => Ok(requested),
+            (Some(requested), None)
+                if !self.pools.values().any(|pool| *pool == requested) =>
+            {
+                Ok(requested)
+            }
+            (Some(requested), _) => Err(BallistaError::General(format!(
+                "Tenant {} may not run jobs in executor pool {}",
+                tenant.unwrap_or("<anonymous>"),
+                requested
+            ))),
+        }
+    }
+}
+
+/// The pools of the running jobs
+#[derive(Default)]
+pub struct JobPools {
+    pools: Mutex<HashMap<String, String>>,
+}
+
+impl JobPools {
+    /// Run the tasks of job `job_id` in `pool` until it completes or fails
+    pub fn register(&self, job_id: &str, pool: String) {
+        self.pools.lock().insert(job_id.to_owned(), pool);
+    }
+
+    /// The pool of job `job_id`
+    pub fn pool(&self, job_id: &str) -> String {
+        self.pools
+            .lock()
+            .get(job_id)
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_POOL.to_owned())
+    }
+
+    pub fn remove(&self, job_id: &str) {
+        self.pools.lock().remove(job_id);
+    }
+
+    /// The number of running jobs of each pool, by pool
+    pub fn jobs_by_pool(&self) -> HashMap<String, usize> {
+        let mut jobs: HashMap<String, usize> = HashMap::new();
+        for pool in self.pools.lock().values() {
+            *jobs.entry(pool.clone()).or_default() += 1;
+        }
+        jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_jobs_to_pools() -> Result<()> {
+        let tenant_pools = TenantPools::parse("alice=interactive, etl = batch")?;
+        assert_eq!(tenant_pools.job_pool(None, Some("alice"))?, "interactive");
+        assert_eq!(tenant_pools.job_pool(None, Some("etl"))?, "batch");
+        assert_eq!(tenant_pools.job_pool(None, Some("bob"))?, DEFAULT_POOL);
+        assert_eq!(tenant_pools.job_pool(None, None)?, DEFAULT_POOL);
+        // Jobs may only ask for the pool of their tenant, or for the pools no tenant is
+        // routed to
+        assert_eq!(
+            tenant_pools.job_pool(Some("interactive".to_owned()), Some("alice"))?,
+            "interactive"
+        );
+        assert!(tenant_pools
+            .job_pool(Some("batch".to_owned()), Some("alice"))
+            .is_err());
+        assert!(tenant_pools
+            .job_pool(Some("batch".to_owned()), Some("bob"))
+            .is_err());
+        assert!(tenant_pools
+            .job_pool(Some("batch".to_owned()), None)
+            .is_err());
+        assert_eq!(
+            tenant_pools.job_pool(Some("gpu".to_owned()), Some("bob"))?,
+            "gpu"
+        );
+
+        assert!(TenantPools::parse("").is_ok());
+        assert!(TenantPools::parse("alice").is_err());
+        assert!(TenantPools::parse("alice=").is_err());
+
+        let job_pools = JobPools::default();
+        job_pools.register("job-1", "batch".to_owned());
+        assert_eq!(job_pools.pool("job-1"), "batch");
+        assert_eq!(job_pools.pool("job-2"), DEFAULT_POOL);
+        assert_eq!(job_pools.jobs_by_pool()["batch"], 1);
+        job_pools.remove("job-1");
+        assert_eq!(job_pools.pool("job-1"), DEFAULT_POOL);
+
+        let labels = vec![(POOL_LABEL.to_owned(), "batch".to_owned())]
+            .into_iter()
+            .collect();
+        assert_eq!(executor_pool(&labels), "batch");
+        assert_eq!(executor_pool(&BTreeMap::new()), DEFAULT_POOL);
+        Ok(())
+    }
+}
@@ -0,0 +1,349 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! In-memory copy of the latest heartbeat of every executor.
+//!
+//! Heartbeats are sharded by executor ID, so that the heartbeats of different executors
+//! seldom wait on the same lock. Executors only send the metrics which changed since
+//! their previous heartbeat, which are merged into the state they reported before.
+//!
+//! Heartbeats are written to the state backend, where the other schedulers watch them,
+//! whenever the state of an executor changes, and otherwise at most once every
+//! [PERSIST_INTERVAL_SECS] seconds.
+//!
+//! An executor is alive as long as its lease is not over since the scheduler last heard
+//! of it, directly or through the state backend. Leases are measured on the monotonic
+//! clock of the scheduler, so that the clocks of the executors and of the other
+//! schedulers, which may be skewed, never come into play. Heartbeats are ordered by
+//! their version, which the scheduler receiving a heartbeat takes from the latest
+//! heartbeat of the executor it knows of, rather than by their timestamps. The
+//! timestamps of heartbeats are only informative, apart from timestamp 0 which marks
+//! expired executors.
+
+use ballista_core::serde::protobuf::{ExecutorHeartbeat, ExecutorMetric, ExecutorState};
+use parking_lot::RwLock;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::mem::discriminant;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const NUM_SHARDS: usize = 16;
+
+/// Maximum time a heartbeat of an executor whose state did not change is not written
+/// to the state backend for
+pub(crate) const PERSIST_INTERVAL_SECS: u64 = 15;
+
+/// Time an executor is considered alive for after the scheduler last heard of it,
+/// unless configured otherwise
+pub const DEFAULT_EXECUTOR_LEASE: Duration = Duration::from_secs(60);
+
+struct Entry {
+    heartbeat: ExecutorHeartbeat,
+    /// When the latest heartbeat of the executor was written to or read from the state
+    /// backend
+    persisted: Instant,
+    /// When this scheduler last heard of the executor
+    seen: Instant,
+    /// Whether the executor was returned by [ExecutorHeartbeats::take_lost] since it
+    /// was last heard of
+    reported_lost: bool,
+}
+
+type Shard = RwLock<HashMap<String, Entry>>;
+
+pub(crate) struct ExecutorHeartbeats {
+    shards: Vec<Shard>,
+    /// Lease of the executors, in milliseconds
+    lease_millis: AtomicU64,
+}
+
+impl Default for ExecutorHeartbeats {
+    fn default() -> Self {
+        Self::new(DEFAULT_EXECUTOR_LEASE)
+    }
+}
+
+impl ExecutorHeartbeats {
+    pub fn new(lease: Duration) -> Self {
+        Self {
+            shards: (0..NUM_SHARDS)
+                .map(|_| RwLock::new(HashMap::new()))
+                .collect(),
+            lease_millis: AtomicU64::new(lease.as_millis() as u64),
+        }
+    }
+
+    /// Time executors are considered alive for after the scheduler last heard of them
+    pub fn lease(&self) -> Duration {
+        Duration::from_millis(self.lease_millis.load(Ordering::Relaxed))
+    }
+
+    pub fn set_lease(&self, lease: Duration) {
+        self.lease_millis
+            .store(lease.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn shard(&self, executor_id: &str) -> &Shard {
+        let mut hasher = DefaultHasher::new();
+        executor_id.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    /// Record a heartbeat received from an executor, merging its metrics into those it
+    /// reported before. Returns the merged heartbeat if it is to be written to the state
+    /// backend.
+    pub fn update(&self, mut heartbeat: ExecutorHeartbeat) -> Option<ExecutorHeartbeat> {
+        let now = Instant::now();
+        let persist_interval = Duration::from_secs(PERSIST_INTERVAL_SECS);
+        let mut shard = self.shard(&heartbeat.executor_id).write();
+        let (persist, persisted, reported_lost) = match shard.get(&heartbeat.executor_id)
+        {
+            Some(previous) => {
+                let state = merge_state(
+                    previous.heartbeat.state.as_ref(),
+                    heartbeat.state.take(),
+                );
+                let persist = state != previous.heartbeat.state
+                    // expiring the executor, or hearing from it again after it expired
+                    || heartbeat.timestamp == 0
+                    || previous.heartbeat.timestamp == 0
+                    || now.duration_since(previous.persisted) >= persist_interval;
+                heartbeat.state = state;
+                heartbeat.version = previous.heartbeat.version + 1;
+                // Expiring an executor already lost does not lose it again
+                let reported_lost = heartbeat.timestamp == 0 && previous.reported_lost;
+                (persist, previous.persisted, reported_lost)
+            }
+            None => {
+                heartbeat.version = 1;
+                (true, now, false)
+            }
+        };
+
+        shard.insert(
+            heartbeat.executor_id.clone(),
+            Entry {
+                heartbeat: heartbeat.clone(),
+                persisted: if persist { now } else { persisted },
+                seen: now,
+                reported_lost,
+            },
+        );
+        persist.then(|| heartbeat)
+    }
+
+    /// Record a heartbeat read from the state backend. The executor counts as seen when
+    /// the heartbeat is read, whenever it was written.
+    pub fn insert_persisted(&self, heartbeat: ExecutorHeartbeat) {
+        let mut shard = self.shard(&heartbeat.executor_id).write();
+        let mut reported_lost = false;
+        if let Some(entry) = shard.get(&heartbeat.executor_id) {
+            // This scheduler already knows of this heartbeat, or of a later one which
+            // may not be written yet
+            if entry.heartbeat.version >= heartbeat.version {
+                return;
+            }
+            reported_lost = heartbeat.timestamp == 0 && entry.reported_lost;
+        }
+        let now = Instant::now();
+        shard.insert(
+            heartbeat.executor_id.clone(),
+            Entry {
+                heartbeat,
+                persisted: now,
+                seen: now,
+                reported_lost,
+            },
+        );
+    }
+
+    /// IDs of the executors which did not expire, and were heard of within their lease
+    pub fn alive(&self) -> HashSet<String> {
+        let lease = self.lease();
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .read()
+                    .iter()
+                    .filter(|(_, entry)| {
+                        entry.heartbeat.timestamp != 0 && entry.seen.elapsed() < lease
+                    })
+                    .map(|(executor_id, _)| executor_id.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// IDs of the executors which expired or whose lease is over, and which were not
+    /// returned since they were last heard of, so that each loss is handled once
+    pub fn take_lost(&self) -> Vec<String> {
+        let lease = self.lease();
+        let mut lost = vec![];
+        for shard in &self.shards {
+            for (executor_id, entry) in shard.write().iter_mut() {
+                let alive =
+                    entry.heartbeat.timestamp != 0 && entry.seen.elapsed() < lease;
+                if !alive && !entry.reported_lost {
+                    entry.reported_lost = true;
+                    lost.push(executor_id.clone());
+                }
+            }
+        }
+        lost
+    }
+
+    /// The latest heartbeat of every executor
+    pub fn all(&self) -> Vec<ExecutorHeartbeat> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard
+                    .read()
+                    .values()
+                    .map(|entry| entry.heartbeat.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// Whether both metrics are measures of the same quantity
+fn same_metric(a: &ExecutorMetric, b: &ExecutorMetric) -> bool {
+    match (&a.metric, &b.metric) {
+        (Some(a), Some(b)) => discriminant(a) == discriminant(b),
+        _ => false,
+    }
+}
+
+/// The state of an executor after it reported the metrics of `update`
+fn merge_state(
+    previous: Option<&ExecutorState>,
+    update: Option<ExecutorState>,
+) -> Option<ExecutorState> {
+    match (previous, update) {
+        (previous, None) => previous.cloned(),
+        (None, update) => update,
+        (Some(previous), Some(update)) => {
+            let mut metrics = previous.metrics.clone();
+            for metric in update.metrics {
+                match metrics.iter_mut().find(|m| same_metric(m, &metric)) {
+                    Some(existing) => *existing = metric,
+                    None => metrics.push(metric),
+                }
+            }
+            Some(ExecutorState { metrics })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ballista_core::serde::protobuf::executor_metric::Metric;
+
+    fn heartbeat(timestamp: u64, available_memory: Option<u64>) -> ExecutorHeartbeat {
+        ExecutorHeartbeat {
+            executor_id: "executor-1".to_owned(),
+            timestamp,
+            state: available_memory.map(|memory| ExecutorState {
+                metrics: vec![ExecutorMetric {
+                    metric: Some(Metric::AvailableMemory(memory)),
+                }],
+            }),
+            version: 0,
+        }
+    }
+
+    #[test]
+    fn merge_heartbeats() {
+        let heartbeats = ExecutorHeartbeats::default();
+        assert!(heartbeats.update(heartbeat(100, Some(1024))).is_some());
+
+        // Heartbeats without changes keep the reported state, and are only written once
+        // in a while
+        assert!(heartbeats.update(heartbeat(101, None)).is_none());
+        let latest = heartbeats.all();
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].timestamp, 101);
+        assert_eq!(latest[0].state, heartbeat(0, Some(1024)).state);
+        for entry in heartbeats.shard("executor-1").write().values_mut() {
+            entry.persisted -= Duration::from_secs(PERSIST_INTERVAL_SECS);
+        }
+        let persisted = heartbeats.update(heartbeat(102, None)).unwrap();
+        assert_eq!(persisted.version, 3);
+        assert_eq!(persisted.state, heartbeat(0, Some(1024)).state);
+
+        // Changed metrics are written right away
+        assert!(heartbeats.update(heartbeat(120, Some(512))).is_some());
+        assert_eq!(heartbeats.all()[0].state, heartbeat(0, Some(512)).state);
+
+        // Heartbeats read back from the state backend do not go back in time
+        heartbeats.insert_persisted(heartbeat(110, Some(512)));
+        assert_eq!(heartbeats.all()[0].timestamp, 120);
+        assert_eq!(heartbeats.alive(), HashSet::from(["executor-1".to_owned()]));
+
+        // Later heartbeats received by other schedulers are taken whatever their clocks
+        let mut later = heartbeat(50, Some(256));
+        later.version = heartbeats.all()[0].version + 1;
+        heartbeats.insert_persisted(later);
+        assert_eq!(heartbeats.all()[0].timestamp, 50);
+        assert_eq!(heartbeats.all()[0].state, heartbeat(0, Some(256)).state);
+
+        // Expiring the executor and hearing from it again are written right away
+        assert!(heartbeats.update(heartbeat(0, None)).is_some());
+        assert!(heartbeats.alive().is_empty());
+        assert!(heartbeats.update(heartbeat(121, None)).is_some());
+        assert_eq!(heartbeats.alive().len(), 1);
+    }
+
+    #[test]
+    fn expire_leases_on_the_local_clock() {
+        let heartbeats = ExecutorHeartbeats::new(Duration::from_millis(50));
+        // The timestamp of the heartbeat, as written by a scheduler whose clock is far
+        // behind, does not matter
+        heartbeats.insert_persisted(heartbeat(1, None));
+        assert_eq!(heartbeats.alive().len(), 1);
+
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(heartbeats.alive().is_empty());
+        heartbeats.update(heartbeat(2, None));
+        assert_eq!(heartbeats.alive().len(), 1);
+    }
+
+    #[test]
+    fn take_lost_executors_once() {
+        let heartbeats = ExecutorHeartbeats::new(Duration::from_millis(50));
+        heartbeats.update(heartbeat(1, None));
+        assert!(heartbeats.take_lost().is_empty());
+
+        // The lease is over, and the executor is then expired
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(heartbeats.take_lost(), vec!["executor-1".to_owned()]);
+        heartbeats.update(heartbeat(0, None));
+        heartbeats.insert_persisted(heartbeat(0, None));
+        assert!(heartbeats.take_lost().is_empty());
+
+        // Lost again after it came back
+        heartbeats.update(heartbeat(3, None));
+        assert!(heartbeats.take_lost().is_empty());
+        heartbeats.update(heartbeat(0, None));
+        assert_eq!(heartbeats.take_lost(), vec!["executor-1".to_owned()]);
+    }
+}
@@ -0,0 +1,169 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The positions of the jobs waiting in a queue of the scheduler, and estimates of the
+//! time they still wait.
+//!
+//! A queue keeps how long the last jobs which left it waited, and from which position.
+//! The wait of a job is estimated as its position times the average wait per position
+//! of these jobs, less the time it already waited.
+//!
+//! Queues are kept in the memory of the scheduler the jobs were submitted to.
+
+use ballista_core::serde::protobuf::QueuePosition;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+
+/// The number of jobs which left a queue whose waits estimate the waits of the next ones
+const HISTORY_LEN: usize = 100;
+
+pub struct JobQueue {
+    name: &'static str,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// The jobs in the queue, in the order they entered it
+    waiting: Vec<WaitingJob>,
+    /// The waits of the last jobs which left the queue
+    history: VecDeque<Wait>,
+}
+
+struct WaitingJob {
+    job_id: String,
+    /// Milliseconds since the epoch when the job entered the queue
+    since: u64,
+    /// The position the job entered the queue at
+    position: usize,
+}
+
+struct Wait {
+    millis: u64,
+    position: usize,
+}
+
+impl JobQueue {
+    /// An empty queue named `name` in the status of its jobs
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Add job `job_id` at the end of the queue at `now`, in milliseconds since the
+    /// epoch, unless it is in the queue already
+    pub fn enter(&self, job_id: &str, now: u64) {
+        let mut inner = self.inner.lock();
+        if inner.waiting.iter().any(|job| job.job_id == job_id) {
+            return;
+        }
+        let position = inner.waiting.len() + 1;
+        inner.waiting.push(WaitingJob {
+            job_id: job_id.to_owned(),
+            since: now,
+            position,
+        });
+    }
+
+    /// Remove job `job_id` from the queue as its turn came at `now`, recording its wait
+    pub fn leave(&self, job_id: &str, now: u64) {
+        let mut inner = self.inner.lock();
+        if let Some(job) = inner.remove(job_id) {
+            if inner.history.len() == HISTORY_LEN {
+                inner.history.pop_front();
+            }
+            inner.history.push_back(Wait {
+                millis: now.saturating_sub(job.since),
+                position: job.position,
+            });
+        }
+    }
+
+    /// Remove job `job_id` from the queue before its turn, such as when it is cancelled,
+    /// without recording its wait
+    pub fn remove(&self, job_id: &str) {
+        self.inner.lock().remove(job_id);
+    }
+
+    /// The position of job `job_id` in the queue at `now`, if it waits in it
+    pub fn position(&self, job_id: &str, now: u64) -> Option<QueuePosition> {
+        let inner = self.inner.lock();
+        let index = inner.waiting.iter().position(|job| job.job_id == job_id)?;
+        let position = index + 1;
+        let waited = now.saturating_sub(inner.waiting[index].since);
+        Some(QueuePosition {
+            queue: self.name.to_owned(),
+            position: position as u32,
+            eta_seconds: inner.eta_seconds(position, waited),
+        })
+    }
+}
+
+impl Inner {
+    fn remove(&mut self, job_id: &str) -> Option<WaitingJob> {
+        let index = self.waiting.iter().position(|job| job.job_id == job_id)?;
+        Some(self.waiting.remove(index))
+    }
+
+    /// The seconds a job at `position` which waited `waited` milliseconds is estimated
+    /// to still wait, at least 1, or 0 if no job left the queue yet
+    fn eta_seconds(&self, position: usize, waited: u64) -> u64 {
+        let positions: usize = self.history.iter().map(|wait| wait.position).sum();
+        if positions == 0 {
+            return 0;
+        }
+        let millis: u64 = self.history.iter().map(|wait| wait.millis).sum();
+        let remaining =
+            (millis * position as u64 / positions as u64).saturating_sub(waited);
+        ((remaining + 999) / 1000).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_waits_from_history() {
+        let queue = JobQueue::new("planning");
+        queue.enter("job-1", 0);
+        queue.enter("job-2", 0);
+        queue.enter("job-2", 1_000);
+        let position = queue.position("job-2", 0).unwrap();
+        assert_eq!(position.queue, "planning");
+        assert_eq!(position.position, 2);
+        // No job left the queue yet
+        assert_eq!(position.eta_seconds, 0);
+
+        // job-1 waited 10 seconds from the first position
+        queue.leave("job-1", 10_000);
+        queue.enter("job-3", 10_000);
+        let position = queue.position("job-2", 10_000).unwrap();
+        assert_eq!((position.position, position.eta_seconds), (1, 1));
+        let position = queue.position("job-3", 12_000).unwrap();
+        assert_eq!((position.position, position.eta_seconds), (2, 18));
+
+        // Cancelled jobs do not count
+        queue.remove("job-2");
+        assert!(queue.position("job-2", 12_000).is_none());
+        assert_eq!(queue.position("job-3", 12_000).unwrap().position, 1);
+        queue.leave("job-3", 20_000);
+        assert!(queue.position("job-3", 20_000).is_none());
+    }
+}
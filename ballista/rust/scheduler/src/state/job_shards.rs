@@ -0,0 +1,194 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Sharding of the jobs of a cluster between several active schedulers.
+//!
+//! Every scheduler of the cluster is configured with the same list of schedulers, and
+//! owns the jobs whose IDs hash to it on a consistent hash ring, so that adding or
+//! removing a scheduler only moves the jobs it owns or takes over. Schedulers generate
+//! the IDs of the jobs submitted to them among those hashing to them, so that they own
+//! these jobs. A scheduler plans, schedules and tracks the jobs it owns only: the
+//! cancellation of the jobs of another scheduler and the statuses of their tasks are
+//! forwarded to their owner. Executors are shared by all the schedulers through the
+//! state backend, and run the tasks the schedulers push to them.
+
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf::scheduler_grpc_client::SchedulerGrpcClient;
+use ballista_core::tls::{create_grpc_channel, grpc_url};
+use std::collections::HashMap;
+use tokio::sync::RwLock;
+use tonic::transport::Channel;
+
+/// Points of each scheduler on the hash ring, which spread its jobs evenly
+const VIRTUAL_NODES: usize = 128;
+
+pub struct JobShards {
+    /// Address of this scheduler
+    local: String,
+    /// Points of the schedulers on the hash ring, sorted by hash
+    ring: Vec<(u64, String)>,
+    /// Clients of the other schedulers, by address
+    clients: RwLock<HashMap<String, SchedulerGrpcClient<Channel>>>,
+}
+
+impl JobShards {
+    /// Shard jobs between the schedulers of the comma-separated `host:port` addresses
+    /// of `schedulers`, among which `local` is the address of this scheduler
+    pub fn try_new(schedulers: &str, local: &str) -> Result<Self> {
+        let schedulers: Vec<&str> = schedulers
+            .split(',')
+            .map(str::trim)
+            .filter(|scheduler| !scheduler.is_empty())
+            .collect();
+        for scheduler in &schedulers {
+            parse_address(scheduler)?;
+        }
+        if !schedulers.contains(&local) {
+            return Err(BallistaError::General(format!(
+                "Scheduler {} is not one of the scheduler shards {:?}",
+                local, schedulers
+            )));
+        }
+
+        let mut ring: Vec<(u64, String)> = schedulers
+            .iter()
+            .flat_map(|scheduler| {
+                (0..VIRTUAL_NODES).map(move |node| {
+                    let point = format!("{}#{}", scheduler, node);
+                    (fnv_hash(point.as_bytes()), scheduler.to_string())
+                })
+            })
+            .collect();
+        ring.sort();
+        // Schedulers listed twice
+        ring.dedup();
+        Ok(Self {
+            local: local.to_owned(),
+            ring,
+            clients: Default::default(),
+        })
+    }
+
+    /// Address of this scheduler
+    pub fn local(&self) -> &str {
+        &self.local
+    }
+
+    /// Address of the scheduler owning job `job_id`: the first one clockwise from the
+    /// hash of the job ID on the ring
+    pub fn owner(&self, job_id: &str) -> &str {
+        let hash = fnv_hash(job_id.as_bytes());
+        let index = self.ring.partition_point(|(point, _)| *point < hash);
+        &self.ring[index % self.ring.len()].1
+    }
+
+    /// Whether this scheduler owns job `job_id`
+    pub fn is_local(&self, job_id: &str) -> bool {
+        self.owner(job_id) == self.local
+    }
+
+    /// The client of the scheduler at `address`. The clients are only locked while
+    /// looked up, so that requests to different schedulers are sent concurrently.
+    pub async fn client(&self, address: &str) -> Result<SchedulerGrpcClient<Channel>> {
+        if let Some(client) = self.clients.read().await.get(address) {
+            return Ok(client.clone());
+        }
+        let mut clients = self.clients.write().await;
+        match clients.get(address) {
+            Some(client) => Ok(client.clone()),
+            None => {
+                let (host, port) = parse_address(address)?;
+                let url = grpc_url(host, port);
+                let client = SchedulerGrpcClient::new(create_grpc_channel(&url).await?);
+                clients.insert(address.to_owned(), client.clone());
+                Ok(client)
+            }
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<(&str, u16)> {
+    address
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse().ok()?)))
+        .filter(|(host, _)| !host.is_empty())
+        .ok_or_else(|| {
+            BallistaError::General(format!(
+                "Invalid scheduler address {}, expected host:port",
+                address
+            ))
+        })
+}
+
+/// 64-bit FNV-1a hash, which unlike the hashers of the standard library is the same in
+/// every build of the scheduler
+fn fnv_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job_ids() -> Vec<String> {
+        (0..3000).map(|i| format!("job-{}", i)).collect()
+    }
+
+    #[test]
+    fn spread_jobs_between_schedulers() -> Result<()> {
+        let schedulers = "scheduler-0:50050,scheduler-1:50050,scheduler-2:50050";
+        let shards = JobShards::try_new(schedulers, "scheduler-1:50050")?;
+        // Every scheduler computes the same owners
+        let other = JobShards::try_new(schedulers, "scheduler-2:50050")?;
+
+        let mut owned: HashMap<&str, usize> = HashMap::new();
+        for job_id in job_ids() {
+            assert_eq!(shards.owner(&job_id), other.owner(&job_id));
+            *owned.entry(shards.owner(&job_id)).or_default() += 1;
+        }
+        assert_eq!(owned.len(), 3);
+        for jobs in owned.values() {
+            assert!(*jobs > 600 && *jobs < 1400, "{:?}", owned);
+        }
+        assert!(job_ids()
+            .iter()
+            .any(|job_id| shards.is_local(job_id) && !other.is_local(job_id)));
+        Ok(())
+    }
+
+    #[test]
+    fn only_move_jobs_of_removed_scheduler() -> Result<()> {
+        let three = JobShards::try_new("a:1,b:1,c:1", "a:1")?;
+        let two = JobShards::try_new("a:1,b:1", "a:1")?;
+        for job_id in job_ids() {
+            if three.owner(&job_id) != "c:1" {
+                assert_eq!(three.owner(&job_id), two.owner(&job_id));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_shards() {
+        assert!(JobShards::try_new("a:1,b:1", "c:1").is_err());
+        assert!(JobShards::try_new("a:1,b", "a:1").is_err());
+        assert!(JobShards::try_new("a:1,:2", "a:1").is_err());
+        assert!(JobShards::try_new(" a:1 , a:1 ", "a:1").is_ok());
+    }
+}
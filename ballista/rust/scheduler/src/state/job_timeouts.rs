@@ -0,0 +1,223 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The timeouts of running jobs.
+//!
+//! The scheduler fails a job which has not completed within its job timeout after it
+//! was submitted, or one of whose stages has not completed within its stage timeout
+//! after its first task was launched. Task timeouts are sent to the executors along
+//! with the tasks, and the executors kill the tasks running for longer. Tasks which stop
+//! producing rows for longer than their stall timeout are killed by the scheduler, see
+//! [TaskProgressTracker](crate::state::task_progress::TaskProgressTracker).
+//!
+//! Timeouts are kept in the memory of the scheduler the job was submitted to, and
+//! measured with its monotonic clock from when it submitted the job and assigned the
+//! first task of each stage, so that changes of the system time do not fail jobs.
+
+use ballista_core::config::BallistaConfig;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::state::execution_graph::ExecutionGraph;
+
+/// The timeouts of a job, set by `ballista.job.timeout_seconds`,
+/// `ballista.stage.timeout_seconds`, `ballista.task.timeout_seconds` and
+/// `ballista.task.stall_timeout_seconds`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    pub job: Option<Duration>,
+    pub stage: Option<Duration>,
+    pub task: Option<Duration>,
+    pub stall: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The timeouts set by `config`, `None` if it sets none
+    pub fn from_config(config: &BallistaConfig) -> Option<Self> {
+        let seconds =
+            |seconds: usize| (seconds > 0).then(|| Duration::from_secs(seconds as u64));
+        let timeouts = Self {
+            job: seconds(config.job_timeout_seconds()),
+            stage: seconds(config.stage_timeout_seconds()),
+            task: seconds(config.task_timeout_seconds()),
+            stall: seconds(config.task_stall_timeout_seconds()),
+        };
+        (timeouts != Self::default()).then(|| timeouts)
+    }
+}
+
+/// The timeouts of a running job, with when it and its stages started
+struct JobTimeout {
+    timeouts: Timeouts,
+    submitted_at: Instant,
+    /// When the first task of each started stage was assigned, by stage ID
+    stage_starts: HashMap<usize, Instant>,
+}
+
+#[derive(Default)]
+pub struct JobTimeouts {
+    /// The timeouts of the running jobs, by job ID
+    inner: Mutex<HashMap<String, JobTimeout>>,
+}
+
+impl JobTimeouts {
+    /// Enforce `timeouts` on job `job_id`, submitted at `submitted_at`
+    pub fn register(&self, job_id: &str, timeouts: Timeouts, submitted_at: Instant) {
+        self.inner.lock().insert(
+            job_id.to_owned(),
+            JobTimeout {
+                timeouts,
+                submitted_at,
+                stage_starts: HashMap::new(),
+            },
+        );
+    }
+
+    /// Record that stage `stage_id` of job `job_id` started at `now`, unless it
+    /// started before
+    pub fn stage_started(&self, job_id: &str, stage_id: usize, now: Instant) {
+        if let Some(timeout) = self.inner.lock().get_mut(job_id) {
+            if timeout.timeouts.stage.is_some() {
+                timeout.stage_starts.entry(stage_id).or_insert(now);
+            }
+        }
+    }
+
+    /// Stop enforcing the timeouts of job `job_id`
+    pub fn remove(&self, job_id: &str) {
+        self.inner.lock().remove(job_id);
+    }
+
+    /// The task timeout of job `job_id`, if any
+    pub fn task_timeout(&self, job_id: &str) -> Option<Duration> {
+        self.inner
+            .lock()
+            .get(job_id)
+            .and_then(|timeout| timeout.timeouts.task)
+    }
+
+    /// The stall timeout of job `job_id`, if any
+    pub fn stall_timeout(&self, job_id: &str) -> Option<Duration> {
+        self.inner
+            .lock()
+            .get(job_id)
+            .and_then(|timeout| timeout.timeouts.stall)
+    }
+
+    /// The jobs whose job or stage timeout is enforced
+    pub fn job_ids(&self) -> Vec<String> {
+        self.inner
+            .lock()
+            .iter()
+            .filter(|(_, timeout)| {
+                timeout.timeouts.job.is_some() || timeout.timeouts.stage.is_some()
+            })
+            .map(|(job_id, _)| job_id.clone())
+            .collect()
+    }
+
+    /// Why job `job_id` has to fail at `now` if it ran for longer than its job timeout
+    /// or one of the incomplete stages of `graph`, its execution graph if it was
+    /// planned, ran for longer than its stage timeout
+    pub fn expired(
+        &self,
+        job_id: &str,
+        graph: Option<&ExecutionGraph>,
+        now: Instant,
+    ) -> Option<String> {
+        let inner = self.inner.lock();
+        let JobTimeout {
+            timeouts,
+            submitted_at,
+            stage_starts,
+        } = inner.get(job_id)?;
+        if let Some(timeout) = timeouts.job {
+            if now.saturating_duration_since(*submitted_at) > timeout {
+                return Some(format!(
+                    "Job {} did not complete within its timeout of {:?}",
+                    job_id, timeout
+                ));
+            }
+        }
+        let (timeout, graph) = match (timeouts.stage, graph) {
+            (Some(timeout), Some(graph)) => (timeout, graph),
+            _ => return None,
+        };
+        let mut stages: Vec<_> = graph.stages.values().collect();
+        stages.sort_by_key(|stage| stage.stage_id);
+        stages
+            .into_iter()
+            .filter(|stage| !stage.complete())
+            .find(|stage| {
+                stage_starts.get(&stage.stage_id).map_or(false, |start| {
+                    now.saturating_duration_since(*start) > timeout
+                })
+            })
+            .map(|stage| {
+                format!(
+                    "Stage {} of job {} did not complete within its timeout of {:?}",
+                    stage.stage_id, job_id, timeout
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire_jobs() {
+        let timeouts = JobTimeouts::default();
+        let submitted_at = Instant::now();
+        let at = |millis: u64| submitted_at + Duration::from_millis(millis);
+        timeouts.register(
+            "job-1",
+            Timeouts {
+                job: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+            submitted_at,
+        );
+        timeouts.register(
+            "job-2",
+            Timeouts {
+                task: Some(Duration::from_secs(5)),
+                ..Default::default()
+            },
+            submitted_at,
+        );
+
+        assert_eq!(timeouts.job_ids(), vec!["job-1".to_owned()]);
+        assert_eq!(timeouts.task_timeout("job-1"), None);
+        assert_eq!(timeouts.task_timeout("job-2"), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.stall_timeout("job-2"), None);
+
+        assert!(timeouts.expired("job-1", None, at(10_000)).is_none());
+        assert!(timeouts.expired("job-1", None, at(10_001)).is_some());
+        assert!(timeouts.expired("job-2", None, at(100_000)).is_none());
+        // Jobs are not expired before they were submitted
+        let earlier = submitted_at.checked_sub(Duration::from_secs(1));
+        if let Some(earlier) = earlier {
+            assert!(timeouts.expired("job-1", None, earlier).is_none());
+        }
+
+        timeouts.remove("job-1");
+        assert!(timeouts.expired("job-1", None, at(100_000)).is_none());
+    }
+}
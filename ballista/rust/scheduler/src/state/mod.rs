@@ -16,31 +16,72 @@
 // under the License.
 
 use std::any::type_name;
+use std::collections::HashMap;
 use std::future::Future;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use prost::Message;
 
 use ballista_core::error::{BallistaError, Result};
+use ballista_core::object_store_registry::ObjectStoreFactories;
+use ballista_core::serde::protobuf;
+use ballista_core::serde::scheduler::ExecutorMetadata;
+use ballista_core::table_format::cache::{temporary_table_name, CACHE};
+use ballista_core::table_format::TableFormats;
+use datafusion::catalog::catalog::CatalogProvider;
+use log::{debug, info, warn};
 
+use crate::audit::AuditLog;
+use crate::auth::ClientAuth;
+use crate::event_log::EventLog;
+use crate::metrics::{MeteredStateBackend, SchedulerMetrics};
+use crate::plan_rewrite::PlanRewriters;
+use crate::query_history::QueryHistory;
 use crate::scheduler_server::SessionBuilder;
+use crate::webhook::JobNotifier;
 
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
 use datafusion_proto::logical_plan::AsLogicalPlan;
 
-use crate::state::backend::{Lock, StateBackendClient};
+use crate::state::backend::{Keyspace, Lock, StateBackendClient};
 
+use crate::state::execution_graph::Task;
 use crate::state::executor_manager::ExecutorManager;
+use crate::state::executor_pools::TenantPools;
+use crate::state::job_shards::JobShards;
+use crate::state::plan_cache::PlanCache;
+use crate::state::result_cache::ResultCache;
 use crate::state::session_manager::SessionManager;
+use crate::state::table_cache::TableCache;
+use crate::state::table_catalog::TableCatalog;
+use crate::state::table_statistics::TableStatisticsStore;
 use crate::state::task_manager::TaskManager;
+use crate::state::tenant_priorities::TenantPriorities;
 
 pub mod backend;
 pub mod execution_graph;
 pub mod executor_manager;
+pub mod executor_pools;
+mod heartbeats;
+pub mod job_queue;
+pub mod job_shards;
+pub mod job_timeouts;
+pub mod plan_cache;
+pub mod result_cache;
 pub mod session_manager;
 pub mod session_registry;
+pub mod sharded_map;
+pub mod slot_reservations;
+pub mod table_cache;
+pub mod table_catalog;
+pub mod table_statistics;
 mod task_manager;
+pub mod task_progress;
+pub mod tenant_priorities;
+pub mod tenant_usage;
 
 pub fn decode_protobuf<T: Message + Default>(bytes: &[u8]) -> Result<T> {
     T::decode(bytes).map_err(|e| {
@@ -82,6 +123,44 @@ pub(super) struct SchedulerState<T: 'static + AsLogicalPlan, U: 'static + AsExec
     pub executor_manager: ExecutorManager,
     pub task_manager: TaskManager<T, U>,
     pub session_manager: SessionManager,
+    pub metrics: Arc<SchedulerMetrics>,
+    /// Log of job lifecycle events, if enabled
+    pub event_log: Option<Arc<EventLog>>,
+    /// Audit log of submitted queries, if enabled
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// History of the performance of queries, if enabled
+    pub query_history: Option<Arc<QueryHistory>>,
+    /// Notifies callback URLs about finished jobs
+    pub job_notifier: Arc<JobNotifier>,
+    /// Authentication of clients, if enabled
+    pub client_auth: Option<Arc<ClientAuth>>,
+    /// Factories of the object stores clients configure for their jobs
+    pub object_store_factories: ObjectStoreFactories,
+    /// Formats of the external tables clients scan
+    pub table_formats: TableFormats,
+    /// Tables cached on the executors
+    pub table_cache: TableCache,
+    /// Statistics of the tables estimated by `ANALYZE TABLE`
+    pub table_statistics: TableStatisticsStore,
+    /// Tables uploaded by clients
+    pub table_catalog: TableCatalog,
+    /// Catalogs of tables defined in external metastores, by name
+    pub catalogs: Vec<(String, Arc<dyn CatalogProvider>)>,
+    /// Plans of recent SQL queries
+    pub plan_cache: Arc<PlanCache>,
+    /// Results of recent queries
+    pub result_cache: Arc<ResultCache>,
+    /// Custom rules rewriting the plans of jobs
+    pub plan_rewriters: PlanRewriters,
+    /// Pools of executors the jobs of tenants are routed to
+    pub tenant_pools: TenantPools,
+    /// Highest priorities of the jobs of tenants
+    pub tenant_priorities: TenantPriorities,
+    /// Schedulers sharing the jobs of the cluster with this one, if sharded
+    pub job_shards: Option<Arc<JobShards>>,
+    /// Set once the scheduler starts shutting down, to reject new jobs
+    shutting_down: Arc<AtomicBool>,
+    backend: Arc<dyn StateBackendClient>,
     _codec: BallistaCodec<T, U>,
 }
 
@@ -92,14 +171,44 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerState<T,
         session_builder: SessionBuilder,
         codec: BallistaCodec<T, U>,
     ) -> Self {
+        let metrics = Arc::new(SchedulerMetrics::new());
+        let config_client: Arc<dyn StateBackendClient> =
+            Arc::new(MeteredStateBackend::new(config_client, metrics.clone()));
+        let executor_manager = ExecutorManager::new(config_client.clone());
+        let table_cache =
+            TableCache::new(config_client.clone(), executor_manager.clone());
+        let mut table_formats = TableFormats::default();
+        table_formats.register(CACHE, Arc::new(table_cache.clone()));
+        let task_manager = TaskManager::new(
+            config_client.clone(),
+            executor_manager.clone(),
+            session_builder,
+            codec.clone(),
+        );
         Self {
-            executor_manager: ExecutorManager::new(config_client.clone()),
-            task_manager: TaskManager::new(
-                config_client.clone(),
-                session_builder,
-                codec.clone(),
-            ),
-            session_manager: SessionManager::new(config_client, session_builder),
+            executor_manager,
+            task_manager,
+            session_manager: SessionManager::new(config_client.clone(), session_builder),
+            metrics,
+            event_log: None,
+            audit_log: None,
+            query_history: None,
+            job_notifier: Arc::new(JobNotifier::new(None, vec![])),
+            client_auth: None,
+            object_store_factories: ObjectStoreFactories::default(),
+            table_formats,
+            table_cache,
+            table_statistics: TableStatisticsStore::new(config_client.clone()),
+            table_catalog: TableCatalog::new(config_client.clone()),
+            catalogs: vec![],
+            plan_cache: Arc::new(PlanCache::default()),
+            result_cache: Arc::new(ResultCache::default()),
+            plan_rewriters: PlanRewriters::default(),
+            tenant_pools: TenantPools::default(),
+            tenant_priorities: TenantPriorities::default(),
+            job_shards: None,
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            backend: config_client,
             _codec: codec,
         }
     }
@@ -107,6 +216,126 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> SchedulerState<T,
     pub async fn init(&self) -> Result<()> {
         self.executor_manager.init().await
     }
+
+    /// Check that the state backend is reachable by reading a single key, returning
+    /// the latency of the read
+    pub async fn check_backend(&self) -> Result<Duration> {
+        let start = Instant::now();
+        self.backend.get(Keyspace::Sessions, "health_check").await?;
+        Ok(start.elapsed())
+    }
+
+    /// Stop accepting new jobs
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub fn backend(&self) -> Arc<dyn StateBackendClient> {
+        self.backend.clone()
+    }
+
+    /// Remove the files of cached table `table` from the executors keeping them.
+    /// Executors which can not be reached, such as those polling for work, remove them
+    /// the next time they poll any scheduler, as they may poll others than this one.
+    /// The removals of executors which are lost meanwhile are dropped.
+    pub async fn remove_cached_table_data(&self, table: &protobuf::CachedTable) {
+        let executors: HashMap<_, _> = table
+            .partition_location
+            .iter()
+            .filter_map(|location| location.executor_meta.clone())
+            .map(|executor| (executor.id.clone(), ExecutorMetadata::from(executor)))
+            .collect();
+        for executor in executors.values() {
+            if let Err(e) = self
+                .task_manager
+                .remove_job_data(executor, &table.job_id)
+                .await
+            {
+                debug!(
+                    "Removing the data of cached table {} from executor {} when it \
+                     polls: {:?}",
+                    table.name, executor.id, e
+                );
+                if let Err(e) = self
+                    .task_manager
+                    .queue_job_data_removal(&executor.id, &table.job_id)
+                    .await
+                {
+                    warn!(
+                        "Could not remove the data of cached table {} from executor {}: \
+                         {:?}",
+                        table.name, executor.id, e
+                    );
+                }
+            }
+        }
+    }
+
+    /// Close session `session_id`, dropping its temporary tables and forgetting its
+    /// settings, and return the names of the dropped tables
+    pub async fn close_session(&self, session_id: &str) -> Result<Vec<String>> {
+        let tables = self.table_cache.remove_session_tables(session_id).await?;
+        let mut dropped_tables = Vec::with_capacity(tables.len());
+        for table in tables {
+            info!(
+                "Dropping temporary table {} cached by job {}",
+                table.name, table.job_id
+            );
+            self.remove_cached_table_data(&table).await;
+            dropped_tables.push(
+                temporary_table_name(&table.name, session_id)
+                    .unwrap_or(&table.name)
+                    .to_owned(),
+            );
+        }
+        if let Err(e) = self.session_manager.remove_session(session_id).await {
+            warn!(
+                "Could not remove the settings of session {}: {:?}",
+                session_id, e
+            );
+        }
+        self.plan_cache.remove_session(session_id);
+        Ok(dropped_tables)
+    }
+
+    /// The executor to copy the output of `task`, launched on executor `executor_id`,
+    /// to, if its job replicates its shuffle partitions. Tasks whose output can not be
+    /// copied anywhere run without a copy.
+    pub async fn replica_executor(
+        &self,
+        executor_id: &str,
+        task: &Task,
+    ) -> Option<ExecutorMetadata> {
+        if !task.replicate_output {
+            return None;
+        }
+        self.executor_manager
+            .get_replica_executor(executor_id, &task.partition)
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Could not choose an executor to copy the output of task {:?} to: {:?}",
+                    task.partition, e
+                );
+                None
+            })
+    }
+
+    /// Refresh the point-in-time gauges in [SchedulerMetrics] from the current state
+    pub async fn refresh_metrics(&self) -> Result<()> {
+        let (active_jobs, pending_tasks) =
+            self.task_manager.get_active_job_summary().await?;
+        self.metrics.active_jobs.set(active_jobs as i64);
+        self.metrics.pending_tasks.set(pending_tasks as i64);
+        self.metrics
+            .registered_executors
+            .set(self.executor_manager.get_alive_executors().len() as i64);
+        Ok(())
+    }
 }
 
 pub async fn with_lock<Out, F: Future<Output = Out>>(lock: Box<dyn Lock>, op: F) -> Out {
@@ -0,0 +1,606 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Caching of the plans of SQL queries.
+//!
+//! Dashboards submit the same SQL queries over and over. The [PlanCache] keeps the
+//! logical plan of a SQL query and the physical plan it is optimized into, keyed by the
+//! normalized text of the query, the settings, session and user it is planned for, and
+//! the version of the catalog it is planned with, so that a repeated query is neither
+//! parsed nor planned again until its plans expire. The stages of each job are still
+//! split from the cached physical plan, since they are specific to the job. Physical
+//! plans include the files of the scanned tables, so files added to a table are only
+//! scanned by repeated queries once their plans expire or the cache is invalidated.
+//!
+//! Statements creating or dropping the tables of a session change the version of its
+//! catalog, and other statements, which may write to any table, the version of the
+//! catalog of every session. The plans of queries calling functions whose result
+//! changes between runs, such as `now()`, are never cached.
+
+use ballista_core::serde::protobuf::KeyValuePair;
+use datafusion::error::DataFusionError;
+use datafusion::logical_expr::Volatility;
+use datafusion::logical_plan::{Expr, ExpressionVisitor, LogicalPlan, Recursion};
+use datafusion::physical_plan::ExecutionPlan;
+use log::warn;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long and how many queries a [PlanCache] caches the plans of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PlanCacheOptions {
+    pub ttl: Duration,
+    pub max_entries: usize,
+}
+
+/// Key of the plans of a SQL query: its normalized text, the settings, session and
+/// user it is planned for, and the version of the catalog of the session
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PlanKey {
+    sql: String,
+    settings: Vec<(String, String)>,
+    session_id: String,
+    owner: Option<String>,
+    catalog_version: u64,
+}
+
+impl PlanKey {
+    pub fn new(
+        sql: &str,
+        settings: &[KeyValuePair],
+        session_id: &str,
+        owner: Option<&str>,
+        catalog_version: u64,
+    ) -> Self {
+        let mut settings: Vec<(String, String)> = settings
+            .iter()
+            .map(|kv| (kv.key.clone(), kv.value.clone()))
+            .collect();
+        settings.sort();
+        Self {
+            sql: normalize_sql(sql),
+            settings,
+            session_id: session_id.to_owned(),
+            owner: owner.map(str::to_owned),
+            catalog_version,
+        }
+    }
+
+    /// SHA-256 of the key, identifying the query in logs
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        // Each field is prefixed with its length, so that fields can not run together
+        let mut field = |value: &[u8]| {
+            hasher.update((value.len() as u64).to_le_bytes());
+            hasher.update(value);
+        };
+        field(self.sql.as_bytes());
+        for (key, value) in &self.settings {
+            field(key.as_bytes());
+            field(value.as_bytes());
+        }
+        field(self.session_id.as_bytes());
+        field(self.owner.as_deref().unwrap_or_default().as_bytes());
+        field(&self.catalog_version.to_le_bytes());
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Remove the comments of `sql` and collapse its whitespace outside of quotes, so that
+/// queries which only differ in their formatting have the same plans
+fn normalize_sql(sql: &str) -> String {
+    let mut normalized = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' | '`' => {
+                if space && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                space = false;
+                normalized.push(c);
+                // Quotes are escaped by doubling them, which reads as two quoted parts
+                for quoted in chars.by_ref() {
+                    normalized.push(quoted);
+                    if quoted == c {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for commented in chars.by_ref() {
+                    if commented == '\n' {
+                        break;
+                    }
+                }
+                space = true;
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                for commented in chars.by_ref() {
+                    if previous == '*' && commented == '/' {
+                        break;
+                    }
+                    previous = commented;
+                }
+                space = true;
+            }
+            c if c.is_whitespace() => space = true,
+            c => {
+                if space && !normalized.is_empty() {
+                    normalized.push(' ');
+                }
+                space = false;
+                normalized.push(c);
+            }
+        }
+    }
+    while normalized.ends_with(';') || normalized.ends_with(' ') {
+        normalized.pop();
+    }
+    normalized
+}
+
+/// Whether the plan of a query may be cached. Statements such as `CREATE EXTERNAL TABLE`
+/// change the session they run in, so they are planned every time.
+fn is_query(plan: &LogicalPlan) -> bool {
+    matches!(
+        plan,
+        LogicalPlan::Projection(_)
+            | LogicalPlan::Filter(_)
+            | LogicalPlan::Aggregate(_)
+            | LogicalPlan::Sort(_)
+            | LogicalPlan::Join(_)
+            | LogicalPlan::CrossJoin(_)
+            | LogicalPlan::Repartition(_)
+            | LogicalPlan::Union(_)
+            | LogicalPlan::TableScan(_)
+            | LogicalPlan::EmptyRelation(_)
+            | LogicalPlan::Limit(_)
+            | LogicalPlan::Values(_)
+            | LogicalPlan::Window(_)
+            | LogicalPlan::SubqueryAlias(_)
+    )
+}
+
+/// Whether `plan` is a statement changing the tables of the session it runs in only
+fn changes_session(plan: &LogicalPlan) -> bool {
+    matches!(
+        plan,
+        LogicalPlan::CreateExternalTable(_)
+            | LogicalPlan::CreateMemoryTable(_)
+            | LogicalPlan::CreateView(_)
+            | LogicalPlan::CreateCatalogSchema(_)
+            | LogicalPlan::DropTable(_)
+    )
+}
+
+/// Whether `plan` calls a function whose result may change between runs of the query,
+/// such as `now()` or `random()`
+pub fn is_volatile(plan: &LogicalPlan) -> Result<bool, DataFusionError> {
+    struct VolatileFinder(bool);
+
+    impl ExpressionVisitor for VolatileFinder {
+        fn pre_visit(mut self, expr: &Expr) -> Result<Recursion<Self>, DataFusionError> {
+            let volatility = match expr {
+                Expr::ScalarFunction { fun, .. } => fun.volatility(),
+                Expr::ScalarUDF { fun, .. } => fun.signature.volatility,
+                _ => return Ok(Recursion::Continue(self)),
+            };
+            if volatility == Volatility::Immutable {
+                Ok(Recursion::Continue(self))
+            } else {
+                self.0 = true;
+                Ok(Recursion::Stop(self))
+            }
+        }
+    }
+
+    for expr in plan.expressions() {
+        if expr.accept(VolatileFinder(false))?.0 {
+            return Ok(true);
+        }
+    }
+    for input in plan.inputs() {
+        if is_volatile(input)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+struct CachedPlans {
+    inserted: Instant,
+    logical_plan: LogicalPlan,
+    /// Set once the first job of the query is planned
+    physical_plan: Option<Arc<dyn ExecutionPlan>>,
+}
+
+/// Versions of the catalogs of the sessions. Every change takes the next version, so
+/// that the version of the catalog of a session is the latest of the version of its own
+/// changes and the version of the changes of all sessions.
+#[derive(Default)]
+struct CatalogVersions {
+    latest: u64,
+    /// Version of the last change of the catalogs of all sessions
+    all: u64,
+    sessions: HashMap<String, u64>,
+}
+
+impl CatalogVersions {
+    fn get(&self, session_id: &str) -> u64 {
+        self.sessions
+            .get(session_id)
+            .map_or(self.all, |version| self.all.max(*version))
+    }
+
+    fn change_session(&mut self, session_id: &str) {
+        self.latest += 1;
+        self.sessions.insert(session_id.to_owned(), self.latest);
+    }
+
+    fn change_all(&mut self) {
+        self.latest += 1;
+        self.all = self.latest;
+        self.sessions.clear();
+    }
+}
+
+/// The plans of recent SQL queries, if plan caching is enabled
+#[derive(Default)]
+pub struct PlanCache {
+    options: Option<PlanCacheOptions>,
+    entries: Mutex<HashMap<PlanKey, CachedPlans>>,
+    catalog_versions: Mutex<CatalogVersions>,
+}
+
+impl PlanCache {
+    pub fn new(options: PlanCacheOptions) -> Self {
+        Self {
+            options: Some(options),
+            entries: Mutex::new(HashMap::new()),
+            catalog_versions: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// The key of the plans of SQL query `sql`, planned with `settings` in session
+    /// `session_id` for `owner`
+    pub fn key(
+        &self,
+        sql: &str,
+        settings: &[KeyValuePair],
+        session_id: &str,
+        owner: Option<&str>,
+    ) -> PlanKey {
+        let catalog_version = self.catalog_versions.lock().get(session_id);
+        PlanKey::new(sql, settings, session_id, owner, catalog_version)
+    }
+
+    /// The cached logical plan of the query of `key`
+    pub fn logical_plan(&self, key: &PlanKey) -> Option<LogicalPlan> {
+        self.lookup(key, |plans| Some(plans.logical_plan.clone()))
+    }
+
+    /// The cached physical plan of the query of `key`
+    pub fn physical_plan(&self, key: &PlanKey) -> Option<Arc<dyn ExecutionPlan>> {
+        self.lookup(key, |plans| plans.physical_plan.clone())
+    }
+
+    /// Cache the logical plan of the query of `key`. Other statements may change the
+    /// tables of the cached queries, so their plans invalidate the plans of the queries
+    /// they may change, except for `EXPLAIN` statements which change nothing.
+    pub fn insert_logical_plan(&self, key: PlanKey, plan: &LogicalPlan) {
+        let options = match &self.options {
+            Some(options) => options,
+            None => return,
+        };
+        if matches!(plan, LogicalPlan::Explain(_) | LogicalPlan::Analyze(_)) {
+            return;
+        }
+        if changes_session(plan) {
+            self.catalog_versions.lock().change_session(&key.session_id);
+            self.entries
+                .lock()
+                .retain(|cached, _| cached.session_id != key.session_id);
+            return;
+        }
+        if !is_query(plan) {
+            self.invalidate();
+            return;
+        }
+        match is_volatile(plan) {
+            Ok(false) => {}
+            Ok(true) => return,
+            Err(e) => {
+                warn!("Could not find the functions called by a query: {}", e);
+                return;
+            }
+        }
+        let mut entries = self.entries.lock();
+        if entries.len() >= options.max_entries {
+            let ttl = options.ttl;
+            entries.retain(|_, plans| plans.inserted.elapsed() < ttl);
+        }
+        if entries.len() >= options.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, plans)| plans.inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        if options.max_entries > 0 {
+            entries.insert(
+                key,
+                CachedPlans {
+                    inserted: Instant::now(),
+                    logical_plan: plan.clone(),
+                    physical_plan: None,
+                },
+            );
+        }
+    }
+
+    /// Cache the physical plan of the query of `key`, if its logical plan is cached
+    pub fn insert_physical_plan(&self, key: &PlanKey, plan: Arc<dyn ExecutionPlan>) {
+        if let Some(plans) = self.entries.lock().get_mut(key) {
+            plans.physical_plan = Some(plan);
+        }
+    }
+
+    /// Drop the plans of the queries of closed session `session_id`
+    pub fn remove_session(&self, session_id: &str) {
+        self.entries
+            .lock()
+            .retain(|cached, _| cached.session_id != session_id);
+        self.catalog_versions.lock().sessions.remove(session_id);
+    }
+
+    /// Drop the plans of all queries
+    pub fn invalidate(&self) {
+        self.catalog_versions.lock().change_all();
+        self.entries.lock().clear();
+    }
+
+    /// Number of queries whose plans are cached, including expired ones
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lookup<R>(
+        &self,
+        key: &PlanKey,
+        f: impl FnOnce(&CachedPlans) -> Option<R>,
+    ) -> Option<R> {
+        let ttl = self.options?.ttl;
+        let mut entries = self.entries.lock();
+        match entries.get(key) {
+            Some(plans) if plans.inserted.elapsed() < ttl => f(plans),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::logical_plan::{
+        CreateExternalTable, DFSchema, FileType, LogicalPlanBuilder,
+    };
+    use datafusion::physical_plan::empty::EmptyExec;
+
+    fn key(sql: &str) -> PlanKey {
+        PlanKey::new(sql, &[], "s1", None, 0)
+    }
+
+    fn cache(ttl: Duration) -> PlanCache {
+        PlanCache::new(PlanCacheOptions {
+            ttl,
+            max_entries: 2,
+        })
+    }
+
+    fn plan() -> LogicalPlan {
+        LogicalPlanBuilder::empty(true).build().unwrap()
+    }
+
+    #[test]
+    fn normalize_queries() {
+        assert_eq!(
+            normalize_sql("  SELECT a,\n\tb -- columns\nFROM t /* table */ ;\n"),
+            "SELECT a, b FROM t"
+        );
+        assert_eq!(
+            normalize_sql("SELECT 'a  -- b', \"c  d\" FROM t WHERE e = 'it''s'"),
+            "SELECT 'a  -- b', \"c  d\" FROM t WHERE e = 'it''s'"
+        );
+        assert_eq!(key("SELECT  1"), key("SELECT 1;"));
+        assert_ne!(key("SELECT 1"), key("SELECT 2"));
+
+        let setting = |key: &str, value: &str| KeyValuePair {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        let a = setting("ballista.shuffle.partitions", "4");
+        let b = setting("ballista.batch.size", "8192");
+        let with_settings =
+            |settings: &[KeyValuePair]| PlanKey::new("SELECT 1", settings, "s1", None, 0);
+        assert_eq!(
+            with_settings(&[a.clone(), b.clone()]),
+            with_settings(&[b, a.clone()])
+        );
+        assert_ne!(with_settings(&[a]), key("SELECT 1"));
+
+        // Queries are planned for each session, user and version of the catalog
+        assert_ne!(
+            PlanKey::new("SELECT 1", &[], "s2", None, 0),
+            key("SELECT 1")
+        );
+        assert_ne!(
+            PlanKey::new("SELECT 1", &[], "s1", Some("alice"), 0),
+            key("SELECT 1")
+        );
+        assert_ne!(
+            PlanKey::new("SELECT 1", &[], "s1", None, 1),
+            key("SELECT 1")
+        );
+        assert_eq!(
+            key("SELECT 1").fingerprint(),
+            key("SELECT  1").fingerprint()
+        );
+        assert_eq!(key("SELECT 1").fingerprint().len(), 64);
+        assert_ne!(key("SELECT 1").fingerprint(), key("SELECT 2").fingerprint());
+    }
+
+    #[test]
+    fn cache_plans() {
+        let cache = cache(Duration::from_secs(60));
+        cache.insert_physical_plan(
+            &key("SELECT 1"),
+            Arc::new(EmptyExec::new(true, Arc::new(Schema::empty()))),
+        );
+        assert!(cache.is_empty());
+
+        cache.insert_logical_plan(key("SELECT 1"), &plan());
+        assert!(cache.logical_plan(&key("SELECT  1")).is_some());
+        assert!(cache.physical_plan(&key("SELECT 1")).is_none());
+
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        cache.insert_physical_plan(
+            &key("SELECT 1"),
+            Arc::new(EmptyExec::new(true, schema)),
+        );
+        let physical_plan = cache.physical_plan(&key("SELECT 1")).unwrap();
+        assert_eq!(physical_plan.schema().fields().len(), 1);
+
+        // The plans of the oldest query are dropped when the cache is full
+        std::thread::sleep(Duration::from_millis(1));
+        cache.insert_logical_plan(key("SELECT 2"), &plan());
+        std::thread::sleep(Duration::from_millis(1));
+        cache.insert_logical_plan(key("SELECT 3"), &plan());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.logical_plan(&key("SELECT 1")).is_none());
+
+        cache.invalidate();
+        assert!(cache.logical_plan(&key("SELECT 3")).is_none());
+    }
+
+    #[test]
+    fn expire_plans() {
+        let cache = cache(Duration::from_millis(0));
+        cache.insert_logical_plan(key("SELECT 1"), &plan());
+        assert!(cache.logical_plan(&key("SELECT 1")).is_none());
+        assert!(cache.is_empty());
+
+        let disabled = PlanCache::default();
+        disabled.insert_logical_plan(key("SELECT 1"), &plan());
+        assert!(!disabled.is_enabled());
+        assert!(disabled.logical_plan(&key("SELECT 1")).is_none());
+    }
+
+    #[test]
+    fn only_cache_queries() {
+        let cache = cache(Duration::from_secs(60));
+        let create_table = LogicalPlan::CreateExternalTable(CreateExternalTable {
+            schema: Arc::new(DFSchema::empty()),
+            name: "t".to_owned(),
+            location: "/tmp/t".to_owned(),
+            file_type: FileType::CSV,
+            has_header: false,
+            delimiter: ',',
+            table_partition_cols: vec![],
+            if_not_exists: false,
+        });
+        let other_session = |cache: &PlanCache| cache.key("SELECT 1", &[], "s2", None);
+        cache.insert_logical_plan(cache.key("SELECT 1", &[], "s1", None), &plan());
+        cache.insert_logical_plan(other_session(&cache), &plan());
+        assert_eq!(cache.len(), 2);
+
+        // Explaining a query changes nothing
+        let explain = LogicalPlanBuilder::from(plan())
+            .explain(false, false)
+            .unwrap()
+            .build()
+            .unwrap();
+        cache.insert_logical_plan(
+            cache.key("EXPLAIN SELECT 1", &[], "s1", None),
+            &explain,
+        );
+        assert_eq!(cache.len(), 2);
+
+        // Creating a table only changes the catalog of its session
+        cache.insert_logical_plan(
+            cache.key("CREATE EXTERNAL TABLE t", &[], "s1", None),
+            &create_table,
+        );
+        assert_eq!(cache.len(), 1);
+        assert!(cache
+            .logical_plan(&cache.key("SELECT 1", &[], "s1", None))
+            .is_none());
+        assert!(cache.logical_plan(&other_session(&cache)).is_some());
+
+        // Other statements may change the tables of every session
+        cache.invalidate();
+        assert!(cache.is_empty());
+        assert_ne!(other_session(&cache), key("SELECT 1"));
+    }
+
+    #[test]
+    fn skip_volatile_queries() {
+        let cache = cache(Duration::from_secs(60));
+        let now = LogicalPlanBuilder::from(plan())
+            .project(vec![datafusion::logical_plan::now()])
+            .unwrap()
+            .build()
+            .unwrap();
+        cache.insert_logical_plan(key("SELECT now()"), &now);
+        assert!(cache.is_empty());
+
+        let abs = LogicalPlanBuilder::from(plan())
+            .project(vec![datafusion::logical_plan::abs(
+                datafusion::logical_plan::lit(-1),
+            )])
+            .unwrap()
+            .build()
+            .unwrap();
+        cache.insert_logical_plan(key("SELECT abs(-1)"), &abs);
+        assert_eq!(cache.len(), 1);
+    }
+}
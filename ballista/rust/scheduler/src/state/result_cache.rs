@@ -0,0 +1,331 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Cache of the results of recent queries.
+//!
+//! The result of a job is the output of its final stage, which the executors keep
+//! until it is cleaned up. It is cached under the physical plan of the job along with
+//! the size and modification time of every file the plan scans, so that a job running
+//! the same plan over unchanged files completes with the output of the earlier job,
+//! without running any task. Plans reading anything else than files are never cached.
+
+use ballista_core::serde::scheduler::PartitionLocation;
+use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::file_format::{
+    AvroExec, CsvExec, FileScanConfig, NdJsonExec, ParquetExec,
+};
+use datafusion::physical_plan::{displayable, ExecutionPlan};
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct ResultCacheOptions {
+    /// Time the result of a job is used for
+    pub ttl: Duration,
+    /// Maximum number of cached results
+    pub max_entries: usize,
+}
+
+/// Version of a file scanned by a query: its path, size and modification time
+type FileVersion = (String, usize, i64);
+
+/// Identifies the result of a query over a snapshot of its tables
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResultKey {
+    /// The physical plan of the query
+    plan: String,
+    versions: Vec<FileVersion>,
+}
+
+impl ResultKey {
+    /// The key of the result of `plan`, `None` if it reads anything else than files
+    pub fn try_new(plan: &dyn ExecutionPlan) -> Option<Self> {
+        let mut versions = vec![];
+        collect_file_versions(plan, &mut versions)?;
+        Some(Self {
+            plan: displayable(plan).indent().to_string(),
+            versions,
+        })
+    }
+
+    /// SHA-256 of the key, identifying the result in logs
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        // Each field is prefixed with its length, so that fields can not run together
+        let mut field = |value: &[u8]| {
+            hasher.update((value.len() as u64).to_le_bytes());
+            hasher.update(value);
+        };
+        field(self.plan.as_bytes());
+        for (path, size, modified) in &self.versions {
+            field(path.as_bytes());
+            field(&(*size as u64).to_le_bytes());
+            field(&modified.to_le_bytes());
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Add the versions of the files scanned by `plan` to `versions`, returning `None` if
+/// it reads anything else than files
+fn collect_file_versions(
+    plan: &dyn ExecutionPlan,
+    versions: &mut Vec<FileVersion>,
+) -> Option<()> {
+    let children = plan.children();
+    if children.is_empty() {
+        let any = plan.as_any();
+        let config: &FileScanConfig =
+            if let Some(exec) = any.downcast_ref::<ParquetExec>() {
+                exec.base_config()
+            } else if let Some(exec) = any.downcast_ref::<CsvExec>() {
+                exec.base_config()
+            } else if let Some(exec) = any.downcast_ref::<AvroExec>() {
+                exec.base_config()
+            } else if let Some(exec) = any.downcast_ref::<NdJsonExec>() {
+                exec.base_config()
+            } else if any.is::<EmptyExec>() {
+                return Some(());
+            } else {
+                return None;
+            };
+        versions.extend(config.file_groups.iter().flatten().map(|file| {
+            (
+                file.object_meta.location.as_ref().to_owned(),
+                file.object_meta.size,
+                file.object_meta.last_modified.timestamp_nanos(),
+            )
+        }));
+    }
+    for child in children {
+        collect_file_versions(child.as_ref(), versions)?;
+    }
+    Some(())
+}
+
+/// The result of a completed job
+#[derive(Debug, Clone)]
+pub struct CachedResult {
+    pub job_id: String,
+    pub partition_location: Vec<PartitionLocation>,
+}
+
+struct CacheEntry {
+    inserted: Instant,
+    result: CachedResult,
+}
+
+/// The results of recent queries, if result caching is enabled
+#[derive(Default)]
+pub struct ResultCache {
+    options: Option<ResultCacheOptions>,
+    entries: Mutex<HashMap<ResultKey, CacheEntry>>,
+}
+
+impl ResultCache {
+    pub fn new(options: ResultCacheOptions) -> Self {
+        Self {
+            options: Some(options),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.options.is_some()
+    }
+
+    /// The cached result of `key`, if all its partitions are kept by the `alive`
+    /// executors
+    pub fn get(&self, key: &ResultKey, alive: &HashSet<String>) -> Option<CachedResult> {
+        let ttl = self.options?.ttl;
+        let mut entries = self.entries.lock();
+        let valid = entries.get(key).map(|entry| {
+            entry.inserted.elapsed() < ttl
+                && entry
+                    .result
+                    .partition_location
+                    .iter()
+                    .all(|location| alive.contains(&location.executor_meta.id))
+        })?;
+        if valid {
+            entries.get(key).map(|entry| entry.result.clone())
+        } else {
+            entries.remove(key);
+            None
+        }
+    }
+
+    /// Cache the output of completed job `job_id` as the result of `key`
+    pub fn insert(
+        &self,
+        key: ResultKey,
+        job_id: &str,
+        partition_location: Vec<PartitionLocation>,
+    ) {
+        let options = match &self.options {
+            Some(options) if options.max_entries > 0 => options,
+            _ => return,
+        };
+        let mut entries = self.entries.lock();
+        if entries.len() >= options.max_entries {
+            let ttl = options.ttl;
+            entries.retain(|_, entry| entry.inserted.elapsed() < ttl);
+        }
+        if entries.len() >= options.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                inserted: Instant::now(),
+                result: CachedResult {
+                    job_id: job_id.to_owned(),
+                    partition_location,
+                },
+            },
+        );
+    }
+
+    /// Drop all cached results
+    pub fn invalidate(&self) {
+        self.entries.lock().clear();
+    }
+
+    /// Number of cached results, including expired ones
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ballista_core::serde::scheduler::{
+        ExecutorMetadata, ExecutorSpecification, PartitionId, PartitionStats,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::datasource::listing::PartitionedFile;
+    use datafusion::datasource::object_store::ObjectStoreUrl;
+    use datafusion::physical_plan::Statistics;
+    use std::sync::Arc;
+
+    fn scan(size: usize) -> Arc<dyn ExecutionPlan> {
+        Arc::new(ParquetExec::new(
+            FileScanConfig {
+                object_store_url: ObjectStoreUrl::local_filesystem(),
+                file_schema: Arc::new(Schema::new(vec![Field::new(
+                    "a",
+                    DataType::Int32,
+                    false,
+                )])),
+                file_groups: vec![vec![PartitionedFile::new(
+                    "/data/t/part-0.parquet".to_owned(),
+                    size as u64,
+                )]],
+                statistics: Statistics::default(),
+                projection: None,
+                limit: None,
+                table_partition_cols: vec![],
+            },
+            None,
+            None,
+        ))
+    }
+
+    fn location(executor_id: &str) -> PartitionLocation {
+        PartitionLocation {
+            partition_id: PartitionId::new("job-1", 1, 0),
+            executor_meta: ExecutorMetadata {
+                id: executor_id.to_owned(),
+                host: "localhost".to_owned(),
+                port: 50051,
+                grpc_port: 50052,
+                specification: ExecutorSpecification { task_slots: 1 },
+                labels: Default::default(),
+                protocol: Default::default(),
+            },
+            partition_stats: PartitionStats::default(),
+            path: "/tmp/job-1/1/0/data.arrow".to_owned(),
+        }
+    }
+
+    fn alive(executor_ids: &[&str]) -> HashSet<String> {
+        executor_ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn key_results_by_file_versions() {
+        let key = ResultKey::try_new(scan(1024).as_ref()).unwrap();
+        assert_eq!(key, ResultKey::try_new(scan(1024).as_ref()).unwrap());
+        let other = ResultKey::try_new(scan(2048).as_ref()).unwrap();
+        assert_ne!(key, other);
+        assert_eq!(key.fingerprint().len(), 64);
+        assert_ne!(key.fingerprint(), other.fingerprint());
+        assert!(ResultKey::try_new(&EmptyExec::new(false, scan(0).schema())).is_some());
+    }
+
+    #[test]
+    fn cache_results() {
+        let cache = ResultCache::new(ResultCacheOptions {
+            ttl: Duration::from_secs(60),
+            max_entries: 10,
+        });
+        let key = ResultKey::try_new(scan(1024).as_ref()).unwrap();
+        assert!(cache.get(&key, &alive(&["executor-1"])).is_none());
+
+        cache.insert(key.clone(), "job-1", vec![location("executor-1")]);
+        let result = cache.get(&key, &alive(&["executor-1"])).unwrap();
+        assert_eq!(result.job_id, "job-1");
+        assert_eq!(result.partition_location.len(), 1);
+
+        // The result is lost along with the executor keeping its partition
+        assert!(cache.get(&key, &alive(&["executor-2"])).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn expire_results() {
+        let cache = ResultCache::new(ResultCacheOptions {
+            ttl: Duration::from_secs(0),
+            max_entries: 10,
+        });
+        let key = ResultKey::try_new(scan(1024).as_ref()).unwrap();
+        cache.insert(key.clone(), "job-1", vec![location("executor-1")]);
+        assert!(cache.get(&key, &alive(&["executor-1"])).is_none());
+        assert!(cache.is_empty());
+
+        let disabled = ResultCache::default();
+        disabled.insert(key.clone(), "job-1", vec![location("executor-1")]);
+        assert!(disabled.is_empty());
+    }
+}
@@ -17,18 +17,23 @@
 
 use crate::scheduler_server::SessionBuilder;
 use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::execution_graph::timestamp_millis;
 use crate::state::{decode_protobuf, encode_protobuf};
 use ballista_core::config::BallistaConfig;
 use ballista_core::error::Result;
 use ballista_core::serde::protobuf::{self, KeyValuePair};
 use datafusion::prelude::{SessionConfig, SessionContext};
+use log::warn;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Clone)]
 pub struct SessionManager {
     state: Arc<dyn StateBackendClient>,
     session_builder: SessionBuilder,
+    /// Time after the last query of a session after which it is closed, if any
+    idle_timeout: Option<Duration>,
 }
 
 impl SessionManager {
@@ -39,13 +44,28 @@ impl SessionManager {
         Self {
             state,
             session_builder,
+            idle_timeout: None,
         }
     }
 
+    /// Close sessions once `timeout` passed since their last query
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Time after the last query of a session after which it is closed, if any
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Save `config` as the settings of session `session_id`, used now. The session
+    /// keeps its owner, or is owned by `owner` if it has none, e.g. as it was closed.
     pub async fn update_session(
         &self,
         session_id: &str,
         config: &BallistaConfig,
+        owner: Option<&str>,
     ) -> Result<Arc<SessionContext>> {
         let mut settings: Vec<KeyValuePair> = vec![];
 
@@ -56,7 +76,15 @@ impl SessionManager {
             })
         }
 
-        let value = encode_protobuf(&protobuf::SessionSettings { configs: settings })?;
+        let owner = match self.session_owner(session_id).await? {
+            Some(owner) => owner,
+            None => owner.unwrap_or_default().to_owned(),
+        };
+        let value = encode_protobuf(&protobuf::SessionSettings {
+            configs: settings,
+            owner,
+            last_used: timestamp_millis(),
+        })?;
         self.state
             .put(Keyspace::Sessions, session_id.to_owned(), value)
             .await?;
@@ -67,6 +95,16 @@ impl SessionManager {
     pub async fn create_session(
         &self,
         config: &BallistaConfig,
+    ) -> Result<Arc<SessionContext>> {
+        self.create_owned_session(config, None).await
+    }
+
+    /// Create a session with settings `config`, owned by principal `owner` if clients
+    /// are authenticated
+    pub async fn create_owned_session(
+        &self,
+        config: &BallistaConfig,
+        owner: Option<&str>,
     ) -> Result<Arc<SessionContext>> {
         let mut settings: Vec<KeyValuePair> = vec![];
 
@@ -85,7 +123,11 @@ impl SessionManager {
 
         let ctx = create_datafusion_context(&config, self.session_builder);
 
-        let value = encode_protobuf(&protobuf::SessionSettings { configs: settings })?;
+        let value = encode_protobuf(&protobuf::SessionSettings {
+            configs: settings,
+            owner: owner.unwrap_or_default().to_owned(),
+            last_used: timestamp_millis(),
+        })?;
         self.state
             .put(Keyspace::Sessions, ctx.session_id(), value)
             .await?;
@@ -93,6 +135,70 @@ impl SessionManager {
         Ok(ctx)
     }
 
+    /// The name of the principal owning session `session_id`, `None` if the session
+    /// does not exist or has no owner
+    pub async fn session_owner(&self, session_id: &str) -> Result<Option<String>> {
+        let value = self.state.get(Keyspace::Sessions, session_id).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let settings: protobuf::SessionSettings = decode_protobuf(&value)?;
+        Ok(Some(settings.owner).filter(|owner| !owner.is_empty()))
+    }
+
+    /// The sessions in which no query was submitted for longer than `timeout` at `now`,
+    /// in milliseconds since the epoch. Sessions saved before their last use was
+    /// recorded are marked as used at `now`.
+    pub async fn idle_sessions(
+        &self,
+        timeout: Duration,
+        now: u64,
+    ) -> Result<Vec<String>> {
+        let mut idle = vec![];
+        for session_id in self.state.scan_keys(Keyspace::Sessions).await? {
+            let value = self.state.get(Keyspace::Sessions, &session_id).await?;
+            // The session was closed since the keys were scanned
+            if value.is_empty() {
+                continue;
+            }
+            let mut settings: protobuf::SessionSettings = match decode_protobuf(&value) {
+                Ok(settings) => settings,
+                Err(e) => {
+                    warn!(
+                        "Could not decode the settings of session {}: {:?}",
+                        session_id, e
+                    );
+                    continue;
+                }
+            };
+            if settings.last_used == 0 {
+                settings.last_used = now;
+                self.state
+                    .put(Keyspace::Sessions, session_id, encode_protobuf(&settings)?)
+                    .await?;
+            } else if is_idle(&settings, timeout, now) {
+                idle.push(session_id);
+            }
+        }
+        Ok(idle)
+    }
+
+    /// Whether session `session_id` exists and no query was submitted in it for longer
+    /// than `timeout` at `now`
+    pub async fn is_idle_session(
+        &self,
+        session_id: &str,
+        timeout: Duration,
+        now: u64,
+    ) -> Result<bool> {
+        let value = self.state.get(Keyspace::Sessions, session_id).await?;
+        if value.is_empty() {
+            return Ok(false);
+        }
+        let settings: protobuf::SessionSettings = decode_protobuf(&value)?;
+        Ok(is_idle(&settings, timeout, now))
+    }
+
     pub async fn get_session(&self, session_id: &str) -> Result<Arc<SessionContext>> {
         let value = self.state.get(Keyspace::Sessions, session_id).await?;
 
@@ -106,6 +212,18 @@ impl SessionManager {
 
         Ok(create_datafusion_context(&config, self.session_builder))
     }
+
+    /// Forget the settings of session `session_id`
+    pub async fn remove_session(&self, session_id: &str) -> Result<()> {
+        self.state.delete(Keyspace::Sessions, session_id).await
+    }
+}
+
+/// Whether no query was submitted in the session of `settings` for longer than
+/// `timeout` at `now`
+fn is_idle(settings: &protobuf::SessionSettings, timeout: Duration, now: u64) -> bool {
+    settings.last_used != 0
+        && now.saturating_sub(settings.last_used) > timeout.as_millis() as u64
 }
 
 /// Create a DataFusion session context that is compatible with Ballista Configuration
@@ -142,3 +260,69 @@ pub fn update_datafusion_context(
     }
     session_ctx
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::backend::standalone::StandaloneClient;
+    use datafusion::execution::context::default_session_builder;
+
+    #[tokio::test]
+    async fn track_session_owners_and_use() -> Result<()> {
+        let state: Arc<dyn StateBackendClient> =
+            Arc::new(StandaloneClient::try_new_temporary()?);
+        let sessions = SessionManager::new(state, default_session_builder);
+        let config = BallistaConfig::new()?;
+
+        let owned = sessions
+            .create_owned_session(&config, Some("alice"))
+            .await?
+            .session_id();
+        let unowned = sessions.create_session(&config).await?.session_id();
+        assert_eq!(
+            sessions.session_owner(&owned).await?,
+            Some("alice".to_owned())
+        );
+        assert_eq!(sessions.session_owner(&unowned).await?, None);
+        assert_eq!(sessions.session_owner("missing").await?, None);
+
+        // Sessions keep their owner, unless they were closed
+        sessions
+            .update_session(&owned, &config, Some("bob"))
+            .await?;
+        assert_eq!(
+            sessions.session_owner(&owned).await?,
+            Some("alice".to_owned())
+        );
+        sessions.remove_session(&owned).await?;
+        sessions
+            .update_session(&owned, &config, Some("bob"))
+            .await?;
+        assert_eq!(
+            sessions.session_owner(&owned).await?,
+            Some("bob".to_owned())
+        );
+
+        let timeout = Duration::from_secs(60);
+        let now = timestamp_millis();
+        assert!(sessions.idle_sessions(timeout, now).await?.is_empty());
+        let mut idle = sessions.idle_sessions(timeout, now + 61_000).await?;
+        idle.sort();
+        let mut expected = vec![owned.clone(), unowned];
+        expected.sort();
+        assert_eq!(idle, expected);
+        assert!(!sessions.is_idle_session(&owned, timeout, now).await?);
+        assert!(
+            sessions
+                .is_idle_session(&owned, timeout, now + 61_000)
+                .await?
+        );
+        sessions.remove_session(&owned).await?;
+        assert!(
+            !sessions
+                .is_idle_session(&owned, timeout, now + 61_000)
+                .await?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Maps split into shards which are locked independently.
+//!
+//! The state of running jobs is updated by the statuses of many tasks at once. Keeping
+//! it in a map behind a single mutex would have the updates of all jobs wait on each
+//! other, so the keys are spread over shards by their hash instead, and only the shard
+//! of a key is locked to read or update it.
+
+use parking_lot::{Mutex, MutexGuard};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash, Hasher};
+
+/// Number of shards of a map
+const SHARDS: usize = 16;
+
+pub struct ShardedMap<K, V> {
+    shards: Vec<Mutex<HashMap<K, V>>>,
+    hasher: RandomState,
+}
+
+impl<K: Hash + Eq, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq, V> ShardedMap<K, V> {
+    /// Lock the shard of `key`, which holds the entry of the key if there is one
+    pub fn shard<Q>(&self, key: &Q) -> MutexGuard<'_, HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        let mut hasher = self.hasher.build_hasher();
+        key.hash(&mut hasher);
+        self.shards[hasher.finish() as usize % SHARDS].lock()
+    }
+
+    /// Number of entries of all the shards
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| shard.lock().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_shards_independently() {
+        let map: ShardedMap<String, u32> = ShardedMap::default();
+        for i in 0..100 {
+            map.shard(format!("job-{}", i).as_str())
+                .insert(format!("job-{}", i), i);
+        }
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.shard("job-42").get("job-42"), Some(&42));
+
+        // The keys of other shards are available while a shard is locked
+        let shard = map.shard("job-0");
+        let other = (1..100)
+            .map(|i| format!("job-{}", i))
+            .find(|key| !shard.contains_key(key.as_str()))
+            .unwrap();
+        assert!(map.shards.iter().any(|shard| shard.try_lock().is_none()));
+        assert!(map.shard(other.as_str()).contains_key(other.as_str()));
+        drop(shard);
+
+        for i in 0..100 {
+            map.shard(format!("job-{}", i).as_str())
+                .remove(format!("job-{}", i).as_str());
+        }
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The task slots reserved by jobs for their lifetime.
+//!
+//! A job asks for its reservation when it is submitted. The reservation is granted once
+//! the slots of the alive executors of the pool of the job not reserved by other jobs
+//! are enough, in the order the reservations of the pool were asked for, and is kept
+//! until the job finishes or fails. While a job with a granted reservation runs fewer
+//! tasks than it reserved, the slots it lacks in its pool are offered to it first. The
+//! slots it has no task ready to run on are offered to the other jobs rather than kept
+//! idle.
+//!
+//! Reservations are kept in the memory of the scheduler the job was submitted to.
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+
+#[derive(Default)]
+pub struct SlotReservations {
+    inner: Mutex<Reservations>,
+}
+
+#[derive(Default)]
+struct Reservations {
+    /// The granted reservations by job ID
+    granted: HashMap<String, Reservation>,
+    /// The jobs waiting for their reservation, in the order they asked for it
+    waiting: VecDeque<(String, Reservation)>,
+}
+
+struct Reservation {
+    pool: String,
+    slots: usize,
+    running_tasks: usize,
+}
+
+impl Reservations {
+    fn reserved_slots(&self, pool: &str) -> usize {
+        self.granted
+            .values()
+            .filter(|r| r.pool == pool)
+            .map(|r| r.slots)
+            .sum()
+    }
+
+    /// Grant the waiting reservations of `pool` which fit in its `total_slots`,
+    /// returning their jobs
+    fn grant_waiting(&mut self, pool: &str, total_slots: usize) -> Vec<String> {
+        let mut granted = vec![];
+        while let Some(index) = self.waiting.iter().position(|(_, r)| r.pool == pool) {
+            if self.reserved_slots(pool) + self.waiting[index].1.slots > total_slots {
+                break;
+            }
+            let (job_id, reservation) = self.waiting.remove(index).unwrap();
+            self.granted.insert(job_id.clone(), reservation);
+            granted.push(job_id);
+        }
+        granted
+    }
+}
+
+impl SlotReservations {
+    /// Ask for `slots` slots for job `job_id` out of the `total_slots` of the alive
+    /// executors of `pool`, returning whether the reservation is granted right away
+    pub fn request(
+        &self,
+        job_id: &str,
+        pool: &str,
+        slots: usize,
+        total_slots: usize,
+    ) -> bool {
+        let mut inner = self.inner.lock();
+        inner.waiting.push_back((
+            job_id.to_owned(),
+            Reservation {
+                pool: pool.to_owned(),
+                slots,
+                running_tasks: 0,
+            },
+        ));
+        inner.grant_waiting(pool, total_slots);
+        inner.granted.contains_key(job_id)
+    }
+
+    /// The pool of the reservation of job `job_id`, granted or not
+    pub fn pool(&self, job_id: &str) -> Option<String> {
+        let inner = self.inner.lock();
+        inner
+            .granted
+            .get(job_id)
+            .or_else(|| {
+                inner
+                    .waiting
+                    .iter()
+                    .find(|(waiting, _)| waiting == job_id)
+                    .map(|(_, r)| r)
+            })
+            .map(|r| r.pool.clone())
+    }
+
+    /// Give up the reservation of job `job_id`, granted or not, and grant the waiting
+    /// reservations of its pool which now fit in the `total_slots` of the pool,
+    /// returning their jobs
+    pub fn release(&self, job_id: &str, total_slots: usize) -> Vec<String> {
+        let mut inner = self.inner.lock();
+        let pool = match inner.granted.remove(job_id) {
+            Some(reservation) => reservation.pool,
+            None => match inner.waiting.iter().position(|(id, _)| id == job_id) {
+                Some(index) => inner.waiting.remove(index).unwrap().1.pool,
+                None => return vec![],
+            },
+        };
+        inner.grant_waiting(&pool, total_slots)
+    }
+
+    /// Whether job `job_id` waits for its reservation to be granted
+    pub fn is_waiting(&self, job_id: &str) -> bool {
+        self.inner
+            .lock()
+            .waiting
+            .iter()
+            .any(|(waiting, _)| waiting == job_id)
+    }
+
+    /// The task slots of the granted reservations of each pool, by pool
+    pub fn reserved_slots_by_pool(&self) -> HashMap<String, usize> {
+        let mut pools: HashMap<String, usize> = HashMap::new();
+        for reservation in self.inner.lock().granted.values() {
+            *pools.entry(reservation.pool.clone()).or_default() += reservation.slots;
+        }
+        pools
+    }
+
+    /// Record the number of tasks job `job_id` runs, if it has a granted reservation
+    pub fn set_running_tasks(&self, job_id: &str, running_tasks: usize) {
+        if let Some(reservation) = self.inner.lock().granted.get_mut(job_id) {
+            reservation.running_tasks = running_tasks;
+        }
+    }
+
+    /// The jobs running fewer tasks than they reserved, with the number of slots they
+    /// lack
+    pub fn shortfalls(&self) -> Vec<(String, usize)> {
+        self.inner
+            .lock()
+            .granted
+            .iter()
+            .filter(|(_, r)| r.slots > r.running_tasks)
+            .map(|(job_id, r)| (job_id.clone(), r.slots - r.running_tasks))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grant_reservations_in_order() {
+        let reservations = SlotReservations::default();
+        assert!(reservations.request("job-1", "default", 6, 10));
+        assert!(!reservations.request("job-2", "default", 6, 10));
+        // job-3 would fit but waits behind job-2
+        assert!(!reservations.request("job-3", "default", 2, 10));
+        assert!(reservations.is_waiting("job-3"));
+
+        assert_eq!(
+            reservations.release("job-1", 10),
+            vec!["job-2".to_owned(), "job-3".to_owned()]
+        );
+        let mut shortfalls = reservations.shortfalls();
+        shortfalls.sort();
+        assert_eq!(
+            shortfalls,
+            vec![("job-2".to_owned(), 6), ("job-3".to_owned(), 2)]
+        );
+    }
+
+    #[test]
+    fn shortfalls_follow_running_tasks() {
+        let reservations = SlotReservations::default();
+        assert!(reservations.request("job-1", "default", 4, 10));
+
+        reservations.set_running_tasks("job-1", 3);
+        assert_eq!(reservations.shortfalls(), vec![("job-1".to_owned(), 1)]);
+        reservations.set_running_tasks("job-1", 5);
+        assert!(reservations.shortfalls().is_empty());
+
+        // Tasks of jobs without a reservation are not recorded
+        reservations.set_running_tasks("job-2", 1);
+        assert!(reservations.release("job-1", 10).is_empty());
+        assert!(reservations.shortfalls().is_empty());
+    }
+
+    #[test]
+    fn reserve_slots_per_pool() {
+        let reservations = SlotReservations::default();
+        assert!(reservations.request("job-1", "batch", 8, 8));
+        // The slots of the other pools are reserved separately
+        assert!(reservations.request("job-2", "interactive", 4, 4));
+        assert!(!reservations.request("job-3", "batch", 2, 8));
+        assert!(!reservations.request("job-4", "interactive", 2, 4));
+        assert_eq!(reservations.pool("job-3"), Some("batch".to_owned()));
+        assert_eq!(reservations.pool("job-5"), None);
+        assert_eq!(reservations.reserved_slots_by_pool()["batch"], 8);
+
+        // Releasing a reservation only grants the waiting ones of its pool
+        assert_eq!(reservations.release("job-1", 8), vec!["job-3".to_owned()]);
+        assert!(reservations.is_waiting("job-4"));
+        assert_eq!(reservations.release("job-2", 4), vec!["job-4".to_owned()]);
+        assert!(reservations.release("job-5", 4).is_empty());
+    }
+}
@@ -0,0 +1,307 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The tables cached on the executors with `CACHE TABLE`.
+//!
+//! The locations of the partitions of a cached table are saved in the state backend
+//! under a key scoped to the user who cached it, so every scheduler sharing the backend
+//! can plan scans of it for that user. A cached table of which a partition was kept by
+//! an executor which is no longer alive can not be scanned anymore, and has to be
+//! uncached and cached again.
+//!
+//! The keys of the temporary tables of a session are prefixed with the session ID, and
+//! the tables are removed together when the session is closed.
+
+use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::executor_manager::ExecutorManager;
+use crate::state::{decode_protobuf, encode_protobuf, with_lock};
+use async_trait::async_trait;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf;
+use ballista_core::serde::scheduler::PartitionLocation;
+use ballista_core::table_format::cache::{session_tables_prefix, CachedTable};
+use ballista_core::table_format::TableFormat;
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::datasource::TableProvider;
+use datafusion::error::DataFusionError;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+/// The cached tables, which are opened as the `cache` [TableFormat]
+#[derive(Clone)]
+pub struct TableCache {
+    state: Arc<dyn StateBackendClient>,
+    executor_manager: ExecutorManager,
+}
+
+impl TableCache {
+    pub(crate) fn new(
+        state: Arc<dyn StateBackendClient>,
+        executor_manager: ExecutorManager,
+    ) -> Self {
+        Self {
+            state,
+            executor_manager,
+        }
+    }
+
+    /// The cached table saved under `name`, `None` if it is not cached
+    pub async fn get(&self, name: &str) -> Result<Option<protobuf::CachedTable>> {
+        let value = self.state.get(Keyspace::CachedTables, name).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        decode_protobuf(&value).map(Some)
+    }
+
+    /// Save `table`, returning the table of the same name it replaces, if any
+    pub async fn put(
+        &self,
+        table: protobuf::CachedTable,
+    ) -> Result<Option<protobuf::CachedTable>> {
+        let lock = self.state.lock(Keyspace::CachedTables, &table.name).await?;
+        with_lock(lock, async {
+            let previous = self.get(&table.name).await?;
+            let value = encode_protobuf(&table)?;
+            self.state
+                .put(Keyspace::CachedTables, table.name.clone(), value)
+                .await?;
+            Ok(previous)
+        })
+        .await
+    }
+
+    /// Forget the cached table `name`, returning it if it was cached
+    pub async fn remove(&self, name: &str) -> Result<Option<protobuf::CachedTable>> {
+        let lock = self.state.lock(Keyspace::CachedTables, name).await?;
+        with_lock(lock, async {
+            let table = self.get(name).await?;
+            if table.is_some() {
+                self.state.delete(Keyspace::CachedTables, name).await?;
+            }
+            Ok(table)
+        })
+        .await
+    }
+
+    /// Forget the temporary tables of session `session_id`, returning them
+    pub async fn remove_session_tables(
+        &self,
+        session_id: &str,
+    ) -> Result<Vec<protobuf::CachedTable>> {
+        let prefix = session_tables_prefix(session_id);
+        let mut tables = vec![];
+        for (_, value) in self
+            .state
+            .get_from_prefix(Keyspace::CachedTables, &prefix)
+            .await?
+        {
+            let table: protobuf::CachedTable = decode_protobuf(&value)?;
+            // The table may have been uncached since it was listed
+            if let Some(table) = self.remove(&table.name).await? {
+                tables.push(table);
+            }
+        }
+        Ok(tables)
+    }
+
+    /// The locations of the partitions of cached table `name`, which must all be kept by
+    /// live executors
+    async fn partitions(&self, name: &str) -> Result<Vec<PartitionLocation>> {
+        let table = self.get(name).await?.ok_or_else(|| {
+            BallistaError::General(format!("Table {} is not cached", name))
+        })?;
+        let partitions = table
+            .partition_location
+            .into_iter()
+            .map(|location| location.try_into())
+            .collect::<Result<Vec<PartitionLocation>>>()?;
+        let alive = self.executor_manager.get_alive_executors();
+        if let Some(lost) = partitions
+            .iter()
+            .find(|location| !alive.contains(&location.executor_meta.id))
+        {
+            return Err(BallistaError::General(format!(
+                "Cached table {} was lost with executor {}, it must be uncached and \
+                 cached again",
+                name, lost.executor_meta.id
+            )));
+        }
+        Ok(partitions)
+    }
+}
+
+#[async_trait]
+impl TableFormat for TableCache {
+    async fn open(
+        &self,
+        location: &str,
+        _options: &BTreeMap<String, String>,
+        _runtime: Arc<RuntimeEnv>,
+    ) -> datafusion::error::Result<Arc<dyn TableProvider>> {
+        Err(DataFusionError::Plan(format!(
+            "Cached table {} can only be opened with its schema",
+            location
+        )))
+    }
+
+    async fn open_with_schema(
+        &self,
+        location: &str,
+        _options: &BTreeMap<String, String>,
+        schema: SchemaRef,
+        _runtime: Arc<RuntimeEnv>,
+    ) -> datafusion::error::Result<Arc<dyn TableProvider>> {
+        let partitions = self
+            .partitions(location)
+            .await
+            .map_err(|e| DataFusionError::Plan(e.to_string()))?;
+        Ok(Arc::new(CachedTable::new(schema, partitions)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::backend::standalone::StandaloneClient;
+    use ballista_core::table_format::cache::{
+        cached_table_key, temporary_table_location,
+    };
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn cached_table(
+        name: &str,
+        job_id: &str,
+        executors: &[&str],
+    ) -> protobuf::CachedTable {
+        let partition_location = executors
+            .iter()
+            .enumerate()
+            .map(|(i, executor_id)| protobuf::PartitionLocation {
+                partition_id: Some(protobuf::PartitionId {
+                    job_id: job_id.to_owned(),
+                    stage_id: 2,
+                    partition_id: i as u32,
+                }),
+                executor_meta: Some(protobuf::ExecutorMetadata {
+                    id: executor_id.to_string(),
+                    host: "localhost".to_owned(),
+                    port: 50051,
+                    grpc_port: 50052,
+                    specification: Some(protobuf::ExecutorSpecification {
+                        resources: vec![],
+                    }),
+                    labels: vec![],
+                    protocol: None,
+                }),
+                partition_stats: Some(protobuf::PartitionStats::default()),
+                path: format!("/tmp/{}/2/{}/data.arrow", job_id, i),
+            })
+            .collect();
+        protobuf::CachedTable {
+            name: name.to_owned(),
+            job_id: job_id.to_owned(),
+            partition_location,
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_tables() -> Result<()> {
+        let state: Arc<dyn StateBackendClient> =
+            Arc::new(StandaloneClient::try_new_temporary()?);
+        let executor_manager = ExecutorManager::new(state.clone());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        executor_manager
+            .save_executor_heartbeat(protobuf::ExecutorHeartbeat {
+                executor_id: "executor-1".to_owned(),
+                timestamp,
+                state: None,
+                version: 0,
+            })
+            .await?;
+        let cache = TableCache::new(state, executor_manager);
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let runtime = Arc::new(RuntimeEnv::default());
+
+        let table = cached_table("trips", "job-1", &["executor-1", "executor-1"]);
+        assert_eq!(cache.put(table.clone()).await?, None);
+        let provider = cache
+            .open_with_schema("trips", &BTreeMap::new(), schema.clone(), runtime.clone())
+            .await?;
+        let provider = provider.as_any().downcast_ref::<CachedTable>().unwrap();
+        assert_eq!(provider.partitions().len(), 2);
+
+        // The table is lost along with the executor keeping its second partition
+        let replacement = cached_table("trips", "job-2", &["executor-1", "executor-2"]);
+        assert_eq!(cache.put(replacement.clone()).await?, Some(table));
+        let err = cache
+            .open_with_schema("trips", &BTreeMap::new(), schema.clone(), runtime.clone())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("executor-2"));
+
+        assert_eq!(cache.remove("trips").await?, Some(replacement));
+        assert_eq!(cache.remove("trips").await?, None);
+        assert!(cache
+            .open_with_schema("trips", &BTreeMap::new(), schema, runtime)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_session_tables() -> Result<()> {
+        let state: Arc<dyn StateBackendClient> =
+            Arc::new(StandaloneClient::try_new_temporary()?);
+        let cache = TableCache::new(state.clone(), ExecutorManager::new(state));
+
+        let temporary = |session_id, name| {
+            let location = temporary_table_location(session_id, name);
+            cached_table_key(Some("alice"), session_id, &location)
+        };
+        let recent =
+            cached_table(&temporary("session-1", "recent"), "job-1", &["executor-1"]);
+        let older =
+            cached_table(&temporary("session-1", "older"), "job-2", &["executor-1"]);
+        let other =
+            cached_table(&temporary("session-2", "recent"), "job-3", &["executor-1"]);
+        let trips = cached_table(
+            &cached_table_key(Some("alice"), "session-1", "trips"),
+            "job-4",
+            &["executor-1"],
+        );
+        for table in [&recent, &older, &other, &trips] {
+            cache.put(table.clone()).await?;
+        }
+
+        let mut removed = cache.remove_session_tables("session-1").await?;
+        removed.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(removed, vec![older, recent.clone()]);
+        assert_eq!(cache.get(&recent.name).await?, None);
+        assert_eq!(cache.get(&other.name).await?, Some(other));
+        assert_eq!(cache.get(&trips.name).await?, Some(trips));
+        assert!(cache.remove_session_tables("session-1").await?.is_empty());
+        Ok(())
+    }
+}
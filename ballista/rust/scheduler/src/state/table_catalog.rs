@@ -0,0 +1,265 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The catalog of the tables clients upload to the cluster.
+//!
+//! A table is saved in the state backend under its name when a client uploads it, so
+//! every scheduler sharing the backend knows it. Clients register the tables of the
+//! catalog by name, and the SQL queries the scheduler plans read them from schema
+//! `public` of catalog `ballista`. Tables are looked up when queries are planned, which
+//! blocks the planning thread on the state backend, so the catalog must be used on a
+//! multi-threaded Tokio runtime like the one of the scheduler.
+
+use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::{decode_protobuf, encode_protobuf, with_lock};
+use ballista_core::error::Result;
+use ballista_core::serde::protobuf;
+use ballista_core::table_format::{ExternalTable, TableFormats};
+use datafusion::catalog::catalog::CatalogProvider;
+use datafusion::catalog::schema::SchemaProvider;
+use datafusion::datasource::TableProvider;
+use datafusion::execution::runtime_env::RuntimeEnv;
+use log::warn;
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+
+/// Name of the catalog of the uploaded tables in the sessions of the scheduler
+pub const CATALOG: &str = "ballista";
+
+/// Name of the only schema of the catalog
+pub const SCHEMA: &str = "public";
+
+#[derive(Clone)]
+pub struct TableCatalog {
+    state: Arc<dyn StateBackendClient>,
+}
+
+impl TableCatalog {
+    pub(crate) fn new(state: Arc<dyn StateBackendClient>) -> Self {
+        Self { state }
+    }
+
+    /// The table named `name`, `None` if no client registered it
+    pub async fn get(&self, name: &str) -> Result<Option<protobuf::CatalogTable>> {
+        let value = self.state.get(Keyspace::CatalogTables, name).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        decode_protobuf(&value).map(Some)
+    }
+
+    /// Save `table` under its name, replacing the table of the same name unless another
+    /// principal registered it. Returns whether the table was saved.
+    pub async fn register(&self, table: protobuf::CatalogTable) -> Result<bool> {
+        let lock = self
+            .state
+            .lock(Keyspace::CatalogTables, &table.name)
+            .await?;
+        with_lock(lock, async {
+            if let Some(existing) = self.get(&table.name).await? {
+                if !existing.owner.is_empty() && existing.owner != table.owner {
+                    return Ok(false);
+                }
+            }
+            let value = encode_protobuf(&table)?;
+            self.state
+                .put(Keyspace::CatalogTables, table.name.clone(), value)
+                .await?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// Names of all the tables of the catalog
+    pub async fn names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self
+            .state
+            .scan_keys(Keyspace::CatalogTables)
+            .await?
+            .into_iter()
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// The catalog of a session, whose tables are opened with `formats` and read with
+    /// the object stores of `runtime`
+    pub fn provider(
+        &self,
+        formats: TableFormats,
+        runtime: Arc<RuntimeEnv>,
+    ) -> Arc<dyn CatalogProvider> {
+        Arc::new(CatalogTables {
+            catalog: self.clone(),
+            formats,
+            runtime,
+            handle: Handle::current(),
+        })
+    }
+}
+
+/// The catalog and the only schema of the tables of a [TableCatalog] in a session
+struct CatalogTables {
+    catalog: TableCatalog,
+    formats: TableFormats,
+    runtime: Arc<RuntimeEnv>,
+    handle: Handle,
+}
+
+impl CatalogTables {
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        tokio::task::block_in_place(|| self.handle.block_on(future))
+    }
+
+    fn open(&self, name: &str) -> Result<Option<ExternalTable>> {
+        self.block_on(async {
+            let table = match self.catalog.get(name).await? {
+                Some(table) => table,
+                None => return Ok(None),
+            };
+            let table = ExternalTable::try_new(
+                &self.formats,
+                &table.format,
+                &table.location,
+                BTreeMap::new(),
+                self.runtime.clone(),
+            )
+            .await?;
+            Ok(Some(table))
+        })
+    }
+}
+
+impl CatalogProvider for CatalogTables {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema_names(&self) -> Vec<String> {
+        vec![SCHEMA.to_owned()]
+    }
+
+    fn schema(&self, name: &str) -> Option<Arc<dyn SchemaProvider>> {
+        (name == SCHEMA).then(|| {
+            Arc::new(CatalogTables {
+                catalog: self.catalog.clone(),
+                formats: self.formats.clone(),
+                runtime: self.runtime.clone(),
+                handle: self.handle.clone(),
+            }) as Arc<dyn SchemaProvider>
+        })
+    }
+}
+
+impl SchemaProvider for CatalogTables {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn table_names(&self) -> Vec<String> {
+        self.block_on(self.catalog.names()).unwrap_or_else(|e| {
+            warn!("Could not list the tables of the catalog: {}", e);
+            vec![]
+        })
+    }
+
+    fn table(&self, name: &str) -> Option<Arc<dyn TableProvider>> {
+        match self.open(name) {
+            Ok(table) => table.map(|table| Arc::new(table) as Arc<dyn TableProvider>),
+            Err(e) => {
+                warn!("Could not open table {} of the catalog: {}", name, e);
+                None
+            }
+        }
+    }
+
+    fn table_exist(&self, name: &str) -> bool {
+        matches!(self.block_on(self.catalog.get(name)), Ok(Some(_)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::backend::standalone::StandaloneClient;
+    use ballista_core::execution_plans::ParquetSinkExec;
+    use ballista_core::table_format::manifest::MANIFEST;
+    use datafusion::arrow::array::Int64Array;
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::arrow::record_batch::RecordBatch;
+    use datafusion::physical_plan::collect;
+    use datafusion::physical_plan::memory::MemoryExec;
+    use datafusion::prelude::SessionContext;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_uploaded_tables() -> Result<()> {
+        let state = Arc::new(StandaloneClient::try_new_temporary()?);
+        let catalog = TableCatalog::new(state);
+
+        // The output of a write committed with a manifest
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().to_str().unwrap().to_owned();
+        let schema =
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3]))],
+        )?;
+        let ctx = SessionContext::new();
+        let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema.clone(), None)?);
+        let sink =
+            ParquetSinkExec::try_new(input, location.clone(), vec![], "w1".to_owned())?;
+        collect(Arc::new(sink.clone()), ctx.task_ctx()).await?;
+        sink.commit(&ctx.runtime_env(), &[0]).await?;
+
+        let table = |owner: &str| protobuf::CatalogTable {
+            name: "uploaded".to_owned(),
+            format: MANIFEST.to_owned(),
+            location: location.clone(),
+            schema: Some(schema.as_ref().into()),
+            owner: owner.to_owned(),
+        };
+        assert!(catalog.register(table("alice")).await?);
+        // Only the principal who registered a table may replace it
+        assert!(!catalog.register(table("bob")).await?);
+        assert!(catalog.register(table("alice")).await?);
+        assert_eq!(catalog.names().await?, vec!["uploaded".to_owned()]);
+        assert_eq!(catalog.get("uploaded").await?.unwrap().owner, "alice");
+        assert_eq!(catalog.get("missing").await?, None);
+
+        ctx.register_catalog(
+            CATALOG,
+            catalog.provider(TableFormats::builtin(), ctx.runtime_env()),
+        );
+        let batches = ctx
+            .sql("SELECT SUM(id) AS total FROM ballista.public.uploaded")
+            .await?
+            .collect()
+            .await?;
+        let expected = vec![
+            "+-------+",
+            "| total |",
+            "+-------+",
+            "| 6     |",
+            "+-------+",
+        ];
+        datafusion::assert_batches_eq!(expected, &batches);
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The statistics of the tables estimated by `ANALYZE TABLE`.
+//!
+//! A table is analyzed by a job aggregating a scan of it, whose output the scheduler
+//! estimates the statistics from once it completes. Statistics are saved in the state
+//! backend under the location of their table rather than the name a client registered it
+//! under, so every scheduler sharing the backend plans the queries scanning the table
+//! with them, whatever they name it. They describe the table as it was when it was
+//! analyzed, and are replaced when it is analyzed again.
+
+use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::{decode_protobuf, encode_protobuf};
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf;
+use ballista_core::serde::protobuf::column_statistics::OptionalDistinctCount;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::logical_plan::{
+    approx_distinct, count, lit, max, min, random, Expr, LogicalPlan, LogicalPlanBuilder,
+};
+use datafusion::scalar::ScalarValue;
+use std::convert::TryInto;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The table analyzed by a job, whose output is the row of aggregates planned by
+/// [analyze_plan]
+#[derive(Clone, Debug)]
+pub struct TableAnalysis {
+    /// Location of the table, which its statistics are saved under
+    location: String,
+    /// Fraction of the rows of the table which are aggregated
+    sample_fraction: f64,
+    /// Name of every column of the table, with whether its distinct values are counted
+    /// and whether its range is aggregated
+    columns: Vec<(String, bool, bool)>,
+}
+
+/// Plan the aggregation of `scan`, the scan of the table at `location`, which counts its
+/// rows and, for every column, its values, its distinct values with `APPROX_DISTINCT`
+/// and its minimum and maximum values. Only a random `sample_fraction` of the rows is
+/// aggregated if it is less than 1.
+pub fn analyze_plan(
+    scan: &LogicalPlan,
+    location: &str,
+    sample_fraction: f64,
+) -> Result<(LogicalPlan, TableAnalysis)> {
+    let mut aggregates = vec![count(lit(1u8))];
+    let mut columns = vec![];
+    for field in scan.schema().fields() {
+        let column = Expr::Column(field.qualified_column());
+        let distinct = has_approx_distinct(field.data_type());
+        let range = has_min_max(field.data_type());
+        aggregates.push(count(column.clone()));
+        if distinct {
+            aggregates.push(approx_distinct(column.clone()));
+        }
+        if range {
+            aggregates.push(min(column.clone()));
+            aggregates.push(max(column));
+        }
+        columns.push((field.name().clone(), distinct, range));
+    }
+    let mut builder = LogicalPlanBuilder::from(scan.clone());
+    if sample_fraction < 1.0 {
+        builder = builder.filter(random().lt(lit(sample_fraction)))?;
+    }
+    let plan = builder.aggregate(Vec::<Expr>::new(), aggregates)?.build()?;
+    let analysis = TableAnalysis {
+        location: location.to_owned(),
+        sample_fraction,
+        columns,
+    };
+    Ok((plan, analysis))
+}
+
+impl TableAnalysis {
+    /// Estimate the statistics of the table from `batches`, the output of the job
+    /// analyzing it. Row and null counts are scaled up to the whole table, and so are
+    /// the distinct counts of the columns whose sampled values are nearly all different,
+    /// since the values of the other columns mostly repeat in the rest of the table.
+    pub fn statistics(
+        &self,
+        batches: &[RecordBatch],
+    ) -> Result<protobuf::TableStatistics> {
+        let batch = batches
+            .iter()
+            .find(|batch| batch.num_rows() > 0)
+            .ok_or_else(|| {
+                BallistaError::Internal(format!(
+                    "Table {} was not aggregated",
+                    self.location
+                ))
+            })?;
+        let mut values = batch
+            .columns()
+            .iter()
+            .map(|column| ScalarValue::try_from_array(column, 0))
+            .collect::<datafusion::error::Result<Vec<_>>>()?
+            .into_iter();
+
+        let fraction = self.sample_fraction;
+        let scale = |count: u64| (count as f64 / fraction).round() as u64;
+        let sampled_rows = count_value(values.next())?;
+        let mut statistics = protobuf::TableStatistics {
+            table: self.location.clone(),
+            num_rows: scale(sampled_rows),
+            columns: vec![],
+            sample_fraction: fraction,
+            analyzed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+        };
+        for (column, distinct, range) in &self.columns {
+            let sampled_values = count_value(values.next())?;
+            let distinct_count = if *distinct {
+                let sampled_distinct = count_value(values.next())?;
+                let unique = sampled_distinct as f64 >= 0.9 * sampled_values as f64;
+                Some(if unique {
+                    scale(sampled_distinct).min(scale(sampled_values))
+                } else {
+                    sampled_distinct
+                })
+            } else {
+                None
+            };
+            let (min_value, max_value) = if *range {
+                (
+                    scalar_to_proto(values.next())?,
+                    scalar_to_proto(values.next())?,
+                )
+            } else {
+                (None, None)
+            };
+            statistics.columns.push(protobuf::ColumnStatistics {
+                name: column.clone(),
+                null_count: scale(sampled_rows.saturating_sub(sampled_values)),
+                optional_distinct_count: distinct_count
+                    .map(OptionalDistinctCount::DistinctCount),
+                min_value,
+                max_value,
+            });
+        }
+        Ok(statistics)
+    }
+
+    /// Location of the analyzed table
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+#[derive(Clone)]
+pub struct TableStatisticsStore {
+    state: Arc<dyn StateBackendClient>,
+}
+
+impl TableStatisticsStore {
+    pub(crate) fn new(state: Arc<dyn StateBackendClient>) -> Self {
+        Self { state }
+    }
+
+    /// The statistics of the table at `location`, `None` if it was never analyzed
+    pub async fn get(&self, location: &str) -> Result<Option<protobuf::TableStatistics>> {
+        let value = self.state.get(Keyspace::TableStatistics, location).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        decode_protobuf(&value).map(Some)
+    }
+
+    /// Save `statistics`, replacing the earlier statistics of the table at their location
+    pub async fn put(&self, statistics: protobuf::TableStatistics) -> Result<()> {
+        let value = encode_protobuf(&statistics)?;
+        self.state
+            .put(Keyspace::TableStatistics, statistics.table, value)
+            .await
+    }
+}
+
+/// Whether APPROX_DISTINCT counts the values of columns of type `data_type`
+fn has_approx_distinct(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Utf8
+            | DataType::LargeUtf8
+            | DataType::Binary
+            | DataType::LargeBinary
+    )
+}
+
+/// Whether MIN and MAX compare the values of columns of type `data_type`
+fn has_min_max(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Boolean
+            | DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32
+            | DataType::Float64
+            | DataType::Decimal128(_, _)
+            | DataType::Utf8
+            | DataType::LargeUtf8
+            | DataType::Date32
+            | DataType::Date64
+            | DataType::Timestamp(_, _)
+    )
+}
+
+/// The value of a COUNT or APPROX_DISTINCT aggregate
+fn count_value(value: Option<ScalarValue>) -> Result<u64> {
+    match value {
+        Some(ScalarValue::Int64(count)) => Ok(count.unwrap_or_default().max(0) as u64),
+        Some(ScalarValue::UInt64(count)) => Ok(count.unwrap_or_default()),
+        value => Err(BallistaError::Internal(format!(
+            "Unexpected count {:?}",
+            value
+        ))),
+    }
+}
+
+/// The value of a MIN or MAX aggregate, `None` if the column has no values
+fn scalar_to_proto(
+    value: Option<ScalarValue>,
+) -> Result<Option<datafusion_proto::protobuf::ScalarValue>> {
+    match value {
+        Some(value) if !value.is_null() => (&value)
+            .try_into()
+            .map(Some)
+            .map_err(|e| BallistaError::Internal(format!("{:?}", e))),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::backend::standalone::StandaloneClient;
+    use datafusion::arrow::array::{Int64Array, StringArray};
+    use datafusion::arrow::datatypes::{Field, Schema};
+    use datafusion::datasource::MemTable;
+    use datafusion::prelude::SessionContext;
+
+    #[tokio::test]
+    async fn replace_statistics() -> Result<()> {
+        let state: Arc<dyn StateBackendClient> =
+            Arc::new(StandaloneClient::try_new_temporary()?);
+        let store = TableStatisticsStore::new(state);
+        let location = "s3://bucket/lineitem";
+        assert!(store.get(location).await?.is_none());
+
+        for num_rows in [100, 200] {
+            store
+                .put(protobuf::TableStatistics {
+                    table: location.to_owned(),
+                    num_rows,
+                    columns: vec![],
+                    sample_fraction: 1.0,
+                    analyzed_at: 0,
+                })
+                .await?;
+        }
+        assert_eq!(store.get(location).await?.unwrap().num_rows, 200);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn estimate_statistics() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("city", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from((0..100).collect::<Vec<i64>>())),
+                Arc::new(StringArray::from(
+                    (0..100)
+                        .map(|i| (i % 10 != 0).then(|| ["Paris", "Oslo"][i % 2]))
+                        .collect::<Vec<_>>(),
+                )),
+            ],
+        )?;
+        let ctx = SessionContext::new();
+        let table = MemTable::try_new(schema, vec![vec![batch]])?;
+        ctx.register_table("trips", Arc::new(table))?;
+        let scan = ctx.table("trips")?.to_logical_plan()?;
+
+        let (plan, analysis) = analyze_plan(&scan, "memory://trips", 1.0)?;
+        assert_eq!(analysis.location(), "memory://trips");
+        let batches = ctx.execute_logical_plan(plan).await?.collect().await?;
+        let statistics = analysis.statistics(&batches)?;
+        assert_eq!(statistics.table, "memory://trips");
+        assert_eq!(statistics.num_rows, 100);
+        let null_counts: Vec<_> = statistics
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.null_count))
+            .collect();
+        assert_eq!(null_counts, vec![("id", 0), ("city", 10)]);
+        // Distinct counts are approximate
+        assert!(matches!(
+            statistics.columns[0].optional_distinct_count,
+            Some(OptionalDistinctCount::DistinctCount(count)) if (95..=105).contains(&count)
+        ));
+        assert_eq!(
+            statistics.columns[1].optional_distinct_count,
+            Some(OptionalDistinctCount::DistinctCount(2))
+        );
+        Ok(())
+    }
+}
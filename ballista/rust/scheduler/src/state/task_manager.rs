@@ -18,68 +18,454 @@
 use crate::scheduler_server::event::QueryStageSchedulerEvent;
 use crate::scheduler_server::SessionBuilder;
 use crate::state::backend::{Keyspace, StateBackendClient};
-use crate::state::execution_graph::{ExecutionGraph, ExecutionStage, StageOutput, Task};
-use crate::state::executor_manager::ExecutorReservation;
+use crate::state::execution_graph::{
+    task_nodes, timestamp_millis, ExecutionGraph, ExecutionStage, StageOutput, Task,
+    TaskInfo,
+};
+use crate::state::executor_manager::{ExecutorManager, ExecutorReservation};
+use crate::state::executor_pools::{executor_pool, JobPools};
+use crate::state::job_queue::JobQueue;
+use crate::state::job_shards::JobShards;
+use crate::state::job_timeouts::{JobTimeouts, Timeouts};
+use crate::state::sharded_map::ShardedMap;
+use crate::state::slot_reservations::SlotReservations;
+use crate::state::table_statistics::TableAnalysis;
+use crate::state::task_progress::TaskProgressTracker;
+use crate::state::tenant_usage::{TenantUsage, TenantUsages};
 use crate::state::{decode_protobuf, encode_protobuf, with_lock};
-use ballista_core::config::BallistaConfig;
+use ballista_core::config::{
+    BallistaConfig, BALLISTA_CACHE_TABLE, BALLISTA_JOB_IN_MEMORY_RESULTS,
+    BALLISTA_TASK_TIMEOUT_SECONDS,
+};
+use ballista_core::encryption::EncryptionKey;
 use ballista_core::error::{BallistaError, Result};
+use ballista_core::execution_plans::{
+    BloomFilter, BloomFilterBuildExec, BloomFilterExec, ParquetSinkExec, WrittenFile,
+};
+use ballista_core::object_store_registry::ObjectStoreCredentials;
+use ballista_core::protocol::{Capability, ProtocolInfo};
 use ballista_core::serde::physical_plan::from_proto::parse_protobuf_hash_partitioning;
 use ballista_core::serde::protobuf::executor_grpc_client::ExecutorGrpcClient;
 
 use crate::state::session_manager::create_datafusion_context;
 use ballista_core::serde::protobuf::{
-    self, job_status, task_status, FailedJob, JobStatus, PartitionId, QueuedJob,
-    TaskDefinition, TaskStatus,
+    self, job_status, task_status, FailedJob, JobResourceUsage, JobStatus, KeyValuePair,
+    PartitionId, QueuedJob, TaskAttempt, TaskDefinition, TaskProgress, TaskStatus,
 };
 use ballista_core::serde::scheduler::to_proto::hash_partitioning_to_proto;
-use ballista_core::serde::scheduler::{ExecutorMetadata, PartitionLocation};
+use ballista_core::serde::scheduler::{
+    ExecutorMetadata, PartitionLocation, DEFAULT_POOL,
+};
 use ballista_core::serde::{AsExecutionPlan, BallistaCodec};
-use datafusion::physical_plan::{ExecutionPlan, Partitioning};
+use ballista_core::table_format::{table_commit_from_json, TableCommit};
+use ballista_core::tls::{create_grpc_channel, grpc_url};
+use datafusion::execution::runtime_env::RuntimeEnv;
+use datafusion::physical_plan::{
+    with_new_children_if_necessary, ExecutionPlan, Partitioning,
+};
 use datafusion::prelude::SessionContext;
 use datafusion_proto::logical_plan::AsLogicalPlan;
 use log::{debug, info, warn};
+use parking_lot::Mutex;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::default::Default;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 use tonic::transport::Channel;
 
+/// How often the jobs whose data an executor polling for work must remove are read
+const JOB_DATA_REMOVAL_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 type ExecutorClients = Arc<RwLock<HashMap<String, ExecutorGrpcClient<Channel>>>>;
 
 #[derive(Clone)]
 pub struct TaskManager<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> {
     state: Arc<dyn StateBackendClient>,
+    /// Caches the labels of the executors tasks are assigned to
+    executor_manager: ExecutorManager,
     #[allow(dead_code)]
     clients: ExecutorClients,
     session_builder: SessionBuilder,
     codec: BallistaCodec<T, U>,
+    /// Whether the shuffle files of new jobs are encrypted
+    encrypt_shuffle_files: bool,
+    /// Object stores configured by clients for running jobs, by job ID. Only kept in
+    /// memory so the credentials are never persisted.
+    object_stores: Arc<ShardedMap<String, ObjectStoreCredentials>>,
+    /// Names of the tables running jobs cache their output as, by job ID
+    cached_tables: Arc<ShardedMap<String, String>>,
+    /// Tables running jobs estimate the statistics of, by job ID
+    table_analyses: Arc<ShardedMap<String, TableAnalysis>>,
+    /// Running jobs whose output the executors keep in memory
+    in_memory_results: Arc<Mutex<HashSet<String>>>,
+    /// Multiples of the bytes they read above which the stages of running jobs warn of
+    /// the bytes they spill, by job ID
+    spill_warning_ratios: Arc<ShardedMap<String, usize>>,
+    /// Attempts of the tasks of the failed runs of jobs which are retried, by job ID,
+    /// from which the attempts of their next run are counted, along with the resources
+    /// the failed runs used
+    retried_attempts: Arc<ShardedMap<String, (u32, JobResourceUsage)>>,
+    /// Pools of executors running the tasks of running jobs
+    job_pools: Arc<JobPools>,
+    /// Task slots reserved by running jobs
+    slot_reservations: Arc<SlotReservations>,
+    /// Unfinished jobs submitted to this scheduler, which it plans and runs
+    local_jobs: Arc<Mutex<HashSet<String>>>,
+    /// Queued jobs waiting to be planned
+    planning_queue: Arc<JobQueue>,
+    /// Running jobs waiting for the task slots they reserved
+    reservation_queue: Arc<JobQueue>,
+    /// Timeouts of running jobs
+    job_timeouts: Arc<JobTimeouts>,
+    /// Resources used by the finished jobs of each tenant
+    tenant_usages: Arc<TenantUsages>,
+    /// Progress of the tasks running on the executors
+    task_progress: Arc<TaskProgressTracker>,
+    /// When the jobs whose data each executor must remove when it polls for work were
+    /// last read from the state backend, by executor ID
+    job_data_removal_checks: Arc<Mutex<HashMap<String, Instant>>>,
+    /// Schedulers sharing the jobs of the cluster with this one, if sharded
+    job_shards: Option<Arc<JobShards>>,
 }
 
 impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U> {
-    pub fn new(
+    pub(crate) fn new(
         state: Arc<dyn StateBackendClient>,
+        executor_manager: ExecutorManager,
         session_builder: SessionBuilder,
         codec: BallistaCodec<T, U>,
     ) -> Self {
         Self {
             state,
+            executor_manager,
             clients: Default::default(),
             session_builder,
             codec,
+            encrypt_shuffle_files: false,
+            object_stores: Default::default(),
+            cached_tables: Default::default(),
+            table_analyses: Default::default(),
+            in_memory_results: Default::default(),
+            spill_warning_ratios: Default::default(),
+            retried_attempts: Default::default(),
+            job_pools: Default::default(),
+            slot_reservations: Default::default(),
+            local_jobs: Default::default(),
+            planning_queue: Arc::new(JobQueue::new("planning")),
+            reservation_queue: Arc::new(JobQueue::new("reserved_slots")),
+            job_timeouts: Default::default(),
+            tenant_usages: Default::default(),
+            task_progress: Default::default(),
+            job_data_removal_checks: Default::default(),
+            job_shards: None,
+        }
+    }
+
+    /// Encrypt the shuffle files of every job submitted from now on with a key
+    /// generated for the job
+    pub fn with_shuffle_encryption(mut self) -> Self {
+        self.encrypt_shuffle_files = true;
+        self
+    }
+
+    /// Only schedule the jobs this scheduler owns among `shards`
+    pub fn with_job_shards(mut self, shards: Arc<JobShards>) -> Self {
+        self.job_shards = Some(shards);
+        self
+    }
+
+    /// Whether this scheduler owns job `job_id`, which it always does unless the jobs
+    /// of the cluster are sharded
+    pub fn owns_job(&self, job_id: &str) -> bool {
+        self.job_shards
+            .as_ref()
+            .map(|shards| shards.is_local(job_id))
+            .unwrap_or(true)
+    }
+
+    /// Send the object stores configured by the client to the executors running the
+    /// tasks of the job, until the job completes or fails
+    pub fn register_object_stores(
+        &self,
+        job_id: &str,
+        credentials: ObjectStoreCredentials,
+    ) {
+        if !credentials.is_empty() {
+            self.object_stores
+                .shard(job_id)
+                .insert(job_id.to_owned(), credentials);
+        }
+    }
+
+    /// The object stores configured by the client for job `job_id`
+    pub fn object_store_credentials(&self, job_id: &str) -> ObjectStoreCredentials {
+        self.object_stores
+            .shard(job_id)
+            .get(job_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Keep the output of job `job_id` on the executors running its tasks, to be scanned
+    /// as table `name` once the job completes
+    pub fn register_cached_table(&self, job_id: &str, name: String) {
+        self.cached_tables
+            .shard(job_id)
+            .insert(job_id.to_owned(), name);
+    }
+
+    /// The name of the table job `job_id` caches its output as, if any
+    pub fn cached_table(&self, job_id: &str) -> Option<String> {
+        self.cached_tables.shard(job_id).get(job_id).cloned()
+    }
+
+    /// Estimate the statistics of the table analyzed by job `job_id` from its output,
+    /// once it completes
+    pub fn register_table_analysis(&self, job_id: &str, analysis: TableAnalysis) {
+        self.table_analyses
+            .shard(job_id)
+            .insert(job_id.to_owned(), analysis);
+    }
+
+    /// The table job `job_id` estimates the statistics of, if any
+    pub fn table_analysis(&self, job_id: &str) -> Option<TableAnalysis> {
+        self.table_analyses.shard(job_id).get(job_id).cloned()
+    }
+
+    /// Have the executors keep the output of job `job_id` in memory and serve it from
+    /// there, rather than writing it to files
+    pub fn register_in_memory_results(&self, job_id: &str) {
+        self.in_memory_results.lock().insert(job_id.to_owned());
+    }
+
+    /// Log a warning when a stage of job `job_id` spills more than `ratio` times the
+    /// bytes it read to disk, until the job completes or fails
+    pub fn register_spill_warning_ratio(&self, job_id: &str, ratio: usize) {
+        if ratio > 0 {
+            self.spill_warning_ratios
+                .shard(job_id)
+                .insert(job_id.to_owned(), ratio);
+        }
+    }
+
+    /// Enforce `timeouts` on job `job_id` from now on, until it completes or fails
+    pub fn register_timeouts(&self, job_id: &str, timeouts: Timeouts) {
+        self.job_timeouts.register(job_id, timeouts, Instant::now());
+    }
+
+    /// Record the progress of all the tasks running on executor `executor_id`, and take
+    /// the tasks it has to kill as they stopped making progress
+    pub fn record_task_progress(
+        &self,
+        executor_id: &str,
+        progress: Vec<TaskProgress>,
+    ) -> Vec<TaskAttempt> {
+        self.task_progress
+            .record(executor_id, progress, timestamp_millis());
+        self.task_progress.take_kills(executor_id)
+    }
+
+    /// Have the executors kill the tasks which did not produce rows for longer than the
+    /// stall timeout of their job, returning them along with their executors
+    pub fn kill_stalled_tasks(&self) -> Vec<(String, TaskAttempt)> {
+        self.task_progress.kill_stalled(
+            |job_id| self.job_timeouts.stall_timeout(job_id),
+            timestamp_millis(),
+        )
+    }
+
+    /// Account the resources used by job `job_id` to `tenant`, or to the default tenant,
+    /// once it completes or fails
+    pub fn register_tenant(&self, job_id: &str, tenant: Option<String>) {
+        self.tenant_usages.register(job_id, tenant);
+    }
+
+    /// The resources used by the finished jobs of each tenant, ordered by tenant
+    pub fn tenant_usage(&self) -> Vec<(String, TenantUsage)> {
+        self.tenant_usages.usage()
+    }
+
+    /// Add the resources used by job `job_id`, which is finishing, to the usage of its
+    /// tenant. Jobs failing before they were planned used none.
+    async fn record_usage(&self, job_id: &str) {
+        if !self.tenant_usages.is_registered(job_id) {
+            return;
+        }
+        let usage = self
+            .get_execution_graph(job_id)
+            .await
+            .map(|graph| graph.usage)
+            .unwrap_or_default();
+        self.tenant_usages.record(job_id, &usage);
+    }
+
+    /// The running jobs which ran for longer than their job or stage timeout, with why
+    /// they have to fail
+    pub async fn timed_out_jobs(&self) -> Vec<(String, String)> {
+        let mut timed_out = vec![];
+        for job_id in self.job_timeouts.job_ids() {
+            // Queued jobs do not have an execution graph yet
+            let graph = self.get_execution_graph(&job_id).await.ok();
+            let finished = graph.as_ref().map_or(false, |graph| {
+                matches!(
+                    graph.status.status,
+                    Some(job_status::Status::Completed(_))
+                        | Some(job_status::Status::Failed(_))
+                )
+            });
+            if finished {
+                continue;
+            }
+            if let Some(reason) =
+                self.job_timeouts
+                    .expired(&job_id, graph.as_ref(), Instant::now())
+            {
+                // Jobs are only failed once for timing out
+                self.job_timeouts.remove(&job_id);
+                timed_out.push((job_id, reason));
+            }
+        }
+        timed_out
+    }
+
+    /// Run the tasks of job `job_id` on the executors of `pool` only, until it completes
+    /// or fails. The pool is saved with the execution graph of the job once it is
+    /// planned.
+    pub fn register_pool(&self, job_id: &str, pool: String) {
+        self.job_pools.register(job_id, pool);
+    }
+
+    /// The pool of executors running the tasks of job `job_id`
+    pub fn job_pool(&self, job_id: &str) -> String {
+        self.job_pools.pool(job_id)
+    }
+
+    /// Reserve `slots` task slots for job `job_id` until it completes or fails, out of
+    /// the `total_slots` of the alive executors of its pool. Returns whether the
+    /// reservation is granted right away rather than waiting for other reservations to
+    /// be released.
+    pub fn reserve_job_slots(
+        &self,
+        job_id: &str,
+        slots: usize,
+        total_slots: usize,
+    ) -> bool {
+        let pool = self.job_pools.pool(job_id);
+        let granted = self
+            .slot_reservations
+            .request(job_id, &pool, slots, total_slots);
+        if !granted {
+            self.reservation_queue.enter(job_id, timestamp_millis());
+        }
+        granted
+    }
+
+    /// The number of running jobs of each pool and the task slots they reserved, by
+    /// pool
+    pub fn pool_usage(&self) -> (HashMap<String, usize>, HashMap<String, usize>) {
+        (
+            self.job_pools.jobs_by_pool(),
+            self.slot_reservations.reserved_slots_by_pool(),
+        )
+    }
+
+    /// The pool of the task slots reserved by job `job_id`, if it reserved any
+    pub fn reserved_slots_pool(&self, job_id: &str) -> Option<String> {
+        self.slot_reservations.pool(job_id)
+    }
+
+    /// Release the task slots reserved by job `job_id`, if any, and grant the waiting
+    /// reservations which fit in the `total_slots` of the alive executors of its pool
+    pub fn release_job_slots(&self, job_id: &str, total_slots: usize) {
+        self.reservation_queue.remove(job_id);
+        for granted in self.slot_reservations.release(job_id, total_slots) {
+            info!("Granted the task slots reserved by job {}", granted);
+            self.reservation_queue.leave(&granted, timestamp_millis());
         }
     }
 
     /// Generate an ExecutionGraph for the job and save it to the persistent state.
+    /// Spans of the job are recorded under the given `trace_context`, if any. The probe
+    /// sides of its joins are filtered by Bloom filters of `bloom_filter_bytes` of their
+    /// build sides, if set. Its shuffle partitions are fetched with priority `priority`.
+    /// Returns `false` if the job was cancelled while it was queued, in which case
+    /// nothing is saved.
     pub async fn submit_job(
         &self,
         job_id: &str,
         session_id: &str,
         plan: Arc<dyn ExecutionPlan>,
+        trace_context: Vec<KeyValuePair>,
+        bloom_filter_bytes: Option<usize>,
+        priority: u32,
+        replicate_shuffles: bool,
+    ) -> Result<bool> {
+        if self.cancelled_while_queued(job_id).await? {
+            return Ok(false);
+        }
+
+        let mut graph = ExecutionGraph::new_with_bloom_filters(
+            job_id,
+            session_id,
+            plan,
+            bloom_filter_bytes,
+        )?
+        .with_trace_context(trace_context)
+        .with_priority(priority)
+        .with_pool(self.job_pools.pool(job_id));
+        if let Some((attempts, usage)) =
+            self.retried_attempts.shard(job_id).remove(job_id)
+        {
+            graph = graph.with_previous_attempts(attempts);
+            graph.usage = usage;
+        }
+        if self.encrypt_shuffle_files {
+            graph = graph.with_shuffle_encryption();
+        }
+        if replicate_shuffles {
+            graph = graph.with_shuffle_replication();
+        }
+        self.save_submitted_graph(job_id, graph).await?;
+        Ok(true)
+    }
+
+    /// Save the job as completed with `output`, the output of an earlier job of the
+    /// same plan, without running any of its tasks. Returns `false` if the job was
+    /// cancelled while it was queued, in which case nothing is saved.
+    pub async fn submit_completed_job(
+        &self,
+        job_id: &str,
+        session_id: &str,
+        plan: Arc<dyn ExecutionPlan>,
+        output: Vec<PartitionLocation>,
+    ) -> Result<bool> {
+        if self.cancelled_while_queued(job_id).await? {
+            return Ok(false);
+        }
+
+        let mut graph = ExecutionGraph::new(job_id, session_id, plan)?;
+        graph.complete_with_output(output)?;
+        self.save_submitted_graph(job_id, graph).await?;
+        Ok(true)
+    }
+
+    async fn cancelled_while_queued(&self, job_id: &str) -> Result<bool> {
+        Ok(!self
+            .state
+            .get(Keyspace::FailedJobs, job_id)
+            .await?
+            .is_empty())
+    }
+
+    async fn save_submitted_graph(
+        &self,
+        job_id: &str,
+        graph: ExecutionGraph,
     ) -> Result<()> {
-        let graph = ExecutionGraph::new(job_id, session_id, plan)?;
         self.state
             .put(
                 Keyspace::ActiveJobs,
@@ -91,28 +477,54 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         if let Err(e) = self.state.delete(Keyspace::QueuedJobs, job_id).await {
             warn!("Failed to remove key in QueuedJobs for {}: {:?}", job_id, e);
         }
-
+        self.planning_queue.leave(job_id, timestamp_millis());
         Ok(())
     }
 
+    /// Queue job `job_id` again after it failed, to be planned and submitted anew. The
+    /// objects registered for the job are kept, and the statuses of the tasks of the
+    /// failed run, which may still be running, are ignored by the next run.
+    pub async fn requeue_job(&self, job_id: &str) -> Result<()> {
+        if let Ok(graph) = self.get_execution_graph(job_id).await {
+            self.retried_attempts
+                .shard(job_id)
+                .insert(job_id.to_owned(), (graph.max_attempts(), graph.usage));
+        }
+        self.queue_job(job_id).await?;
+        let lock = self.state.lock(Keyspace::ActiveJobs, job_id).await?;
+        with_lock(lock, self.state.delete(Keyspace::ActiveJobs, job_id)).await
+    }
+
     /// Queue a job. When a batch job is submitted we do the physical planning asynchronously so we
     /// need to add a marker so we can report on its status.
     pub async fn queue_job(&self, job_id: &str) -> Result<()> {
         self.state
             .put(Keyspace::QueuedJobs, job_id.to_owned(), vec![0x0])
-            .await
+            .await?;
+        self.local_jobs.lock().insert(job_id.to_owned());
+        self.planning_queue.enter(job_id, timestamp_millis());
+        Ok(())
     }
 
     /// Get the status of of a job. First look in Active/Completed jobs, and then in Queued jobs, and
-    /// finally in FailedJobs.
+    /// finally in FailedJobs. Jobs waiting in a queue of this scheduler report their
+    /// position in it.
     pub async fn get_job_status(&self, job_id: &str) -> Result<Option<JobStatus>> {
         let queue_marker = self.state.get(Keyspace::QueuedJobs, job_id).await?;
         if !queue_marker.is_empty() {
+            let queue = self.planning_queue.position(job_id, timestamp_millis());
             Ok(Some(JobStatus {
-                status: Some(job_status::Status::Queued(QueuedJob {})),
+                status: Some(job_status::Status::Queued(QueuedJob { queue })),
             }))
         } else if let Ok(graph) = self.get_execution_graph(job_id).await {
-            Ok(Some(graph.status()))
+            let mut status = graph.status();
+            if let Some(job_status::Status::Running(progress)) = &mut status.status {
+                if self.slot_reservations.is_waiting(job_id) {
+                    progress.queue =
+                        self.reservation_queue.position(job_id, timestamp_millis());
+                }
+            }
+            Ok(Some(status))
         } else {
             let value = self.state.get(Keyspace::FailedJobs, job_id).await?;
 
@@ -125,6 +537,97 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         }
     }
 
+    /// Record that job `job_id` was submitted by the authenticated client `owner`
+    pub async fn save_job_owner(&self, job_id: &str, owner: &str) -> Result<()> {
+        self.state
+            .put(
+                Keyspace::JobOwners,
+                job_id.to_owned(),
+                owner.as_bytes().to_vec(),
+            )
+            .await
+    }
+
+    /// The name of the authenticated client which submitted job `job_id`, `None` if
+    /// clients were not authenticated when it was submitted
+    pub async fn job_owner(&self, job_id: &str) -> Result<Option<String>> {
+        let value = self.state.get(Keyspace::JobOwners, job_id).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        String::from_utf8(value).map(Some).map_err(|e| {
+            BallistaError::General(format!("Invalid owner of job {}: {}", job_id, e))
+        })
+    }
+
+    /// Record that job `job_id` writes its output to Parquet files, committed with
+    /// `table_commit` if it rewrites a table and to a manifest otherwise, so that the
+    /// files are committed by whichever scheduler completes the job
+    pub async fn save_parquet_write(
+        &self,
+        job_id: &str,
+        table_commit: Option<&dyn TableCommit>,
+    ) -> Result<()> {
+        let value = match table_commit {
+            Some(table_commit) => table_commit.to_json()?,
+            None => serde_json::Value::Null,
+        };
+        self.state
+            .put(
+                Keyspace::ParquetWrites,
+                job_id.to_owned(),
+                value.to_string().into_bytes(),
+            )
+            .await
+    }
+
+    /// How the Parquet files written by job `job_id` are committed, `None` if the job
+    /// does not write Parquet files, and `Some(None)` if they are committed to a
+    /// manifest
+    pub async fn parquet_write(
+        &self,
+        job_id: &str,
+    ) -> Result<Option<Option<Arc<dyn TableCommit>>>> {
+        let value = self.state.get(Keyspace::ParquetWrites, job_id).await?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        let value: serde_json::Value = serde_json::from_slice(&value).map_err(|e| {
+            BallistaError::General(format!(
+                "Invalid Parquet write of job {}: {}",
+                job_id, e
+            ))
+        })?;
+        Ok(Some(match value {
+            serde_json::Value::Null => None,
+            value => Some(table_commit_from_json(value)?),
+        }))
+    }
+
+    pub async fn remove_parquet_write(&self, job_id: &str) -> Result<()> {
+        self.state.delete(Keyspace::ParquetWrites, job_id).await
+    }
+
+    /// Commit the files written by `sink` to a new version of the table of
+    /// `table_commit`. The schedulers of the cluster commit to a table one at a time, as
+    /// object stores such as S3 cannot create the next version of a table only if no
+    /// other commit created it first.
+    pub async fn commit_to_table(
+        &self,
+        sink: &ParquetSinkExec,
+        runtime: &RuntimeEnv,
+        attempts: &[u32],
+        table_commit: &dyn TableCommit,
+    ) -> Result<Vec<WrittenFile>> {
+        let lock = self
+            .state
+            .lock(Keyspace::TableCommits, table_commit.location())
+            .await?;
+        with_lock(lock, sink.commit_to_table(runtime, attempts, table_commit))
+            .await
+            .map_err(BallistaError::from)
+    }
+
     /// Generate a new random Job ID
     pub fn generate_job_id(&self) -> String {
         let mut rng = thread_rng();
@@ -135,10 +638,23 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             .collect()
     }
 
-    /// Atomically update given task statuses in the respective job and return a tuple containing:
+    /// Generate a new random Job ID owned by this scheduler
+    pub fn generate_local_job_id(&self) -> String {
+        loop {
+            let job_id = self.generate_job_id();
+            if self.owns_job(&job_id) {
+                return job_id;
+            }
+        }
+    }
+
+    /// Update given task statuses in the respective job and return a tuple containing:
     /// 1. A list of QueryStageSchedulerEvent to publish.
     /// 2. A list of reservations that can now be offered.
     ///
+    /// The statuses of each job are updated atomically while holding the lock of that
+    /// job only, so that updates of different jobs do not wait on each other.
+    ///
     /// When a task is updated, there may or may not be more tasks pending for its job. If there are more
     /// tasks pending then we want to reschedule one of those tasks on the same task slot. In that case
     /// we will set the `job_id` on the `ExecutorReservation` so the scheduler attempts to assign tasks from
@@ -149,84 +665,183 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         executor: &ExecutorMetadata,
         task_status: Vec<TaskStatus>,
     ) -> Result<(Vec<QueryStageSchedulerEvent>, Vec<ExecutorReservation>)> {
-        let lock = self.state.lock(Keyspace::ActiveJobs, "").await?;
+        let mut events: Vec<QueryStageSchedulerEvent> = vec![];
+        let mut reservation: Vec<ExecutorReservation> = vec![];
 
-        with_lock(lock, async {
-            let mut events: Vec<QueryStageSchedulerEvent> = vec![];
-            let mut reservation: Vec<ExecutorReservation> = vec![];
-
-            let mut job_updates: HashMap<String, Vec<TaskStatus>> = HashMap::new();
-
-            for status in task_status {
-                debug!("Task Update\n{:?}", status);
-                if let Some(job_id) = status.task_id.as_ref().map(|id| &id.job_id) {
-                    if let Some(statuses) = job_updates.get_mut(job_id) {
-                        statuses.push(status)
-                    } else {
-                        job_updates.insert(job_id.clone(), vec![status]);
-                    }
+        let mut job_updates: HashMap<String, Vec<TaskStatus>> = HashMap::new();
+
+        for status in task_status {
+            debug!("Task Update\n{:?}", status);
+            if let Some(job_id) = status.task_id.as_ref().map(|id| &id.job_id) {
+                if let Some(statuses) = job_updates.get_mut(job_id) {
+                    statuses.push(status)
                 } else {
-                    warn!("Received task with no job ID");
+                    job_updates.insert(job_id.clone(), vec![status]);
                 }
+            } else {
+                warn!("Received task with no job ID");
             }
+        }
 
-            let mut txn_ops: Vec<(Keyspace, String, Vec<u8>)> = vec![];
-
-            for (job_id, statuses) in job_updates {
-                let num_tasks = statuses.len();
-                debug!("Updating {} tasks in job {}", num_tasks, job_id);
-
-                let mut graph = self.get_execution_graph(&job_id).await?;
-
-                graph.update_task_status(executor, statuses)?;
+        for (job_id, statuses) in job_updates {
+            let num_tasks = statuses.len();
+            debug!("Updating {} tasks in job {}", num_tasks, job_id);
 
-                if graph.complete() {
-                    // If this ExecutionGraph is complete, finalize it
-                    info!(
-                        "Job {} is complete, finalizing output partitions",
-                        graph.job_id()
-                    );
-                    graph.finalize()?;
-                    events.push(QueryStageSchedulerEvent::JobFinished(job_id.clone()));
+            let lock = self.state.lock(Keyspace::ActiveJobs, &job_id).await?;
+            let result = with_lock(
+                lock,
+                self.update_job_task_statuses(executor, &job_id, statuses),
+            )
+            .await;
 
+            match result {
+                Ok(None) => {
+                    // Keep the task slots reserved for this job
                     for _ in 0..num_tasks {
-                        reservation
-                            .push(ExecutorReservation::new_free(executor.id.to_owned()));
+                        reservation.push(ExecutorReservation::new_assigned(
+                            executor.id.to_owned(),
+                            job_id.clone(),
+                        ));
                     }
-                } else if let Some(job_status::Status::Failed(failure)) =
-                    graph.status().status
-                {
-                    events.push(QueryStageSchedulerEvent::JobFailed(
-                        job_id.clone(),
-                        failure.error,
-                    ));
-
+                }
+                Ok(Some(event)) => {
+                    events.push(event);
                     for _ in 0..num_tasks {
                         reservation
                             .push(ExecutorReservation::new_free(executor.id.to_owned()));
                     }
-                } else {
-                    // Otherwise keep the task slots reserved for this job
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to update {} tasks in job {}: {:?}",
+                        num_tasks, job_id, e
+                    );
                     for _ in 0..num_tasks {
-                        reservation.push(ExecutorReservation::new_assigned(
-                            executor.id.to_owned(),
-                            job_id.clone(),
-                        ));
+                        reservation
+                            .push(ExecutorReservation::new_free(executor.id.to_owned()));
                     }
                 }
+            }
+        }
 
-                txn_ops.push((
-                    Keyspace::ActiveJobs,
-                    job_id.clone(),
-                    self.encode_execution_graph(graph)?,
-                ));
+        Ok((events, reservation))
+    }
+
+    /// Update the statuses of tasks of job `job_id`, whose lock must be held. Returns the
+    /// event to publish if the job completed or failed.
+    async fn update_job_task_statuses(
+        &self,
+        executor: &ExecutorMetadata,
+        job_id: &str,
+        statuses: Vec<TaskStatus>,
+    ) -> Result<Option<QueryStageSchedulerEvent>> {
+        let mut graph = self.get_execution_graph(job_id).await?;
+
+        let completed_stages: HashSet<usize> = graph
+            .stages
+            .iter()
+            .filter(|(_, stage)| stage.complete())
+            .map(|(stage_id, _)| *stage_id)
+            .collect();
+        graph.update_task_status(executor, statuses)?;
+        self.slot_reservations
+            .set_running_tasks(job_id, graph.running_tasks());
+
+        let spill_warning_ratio =
+            self.spill_warning_ratios.shard(job_id).get(job_id).copied();
+        if let Some(ratio) = spill_warning_ratio {
+            for (stage_id, stage) in &graph.stages {
+                if stage.complete()
+                    && !completed_stages.contains(stage_id)
+                    && stage.spills_excessively(ratio)
+                {
+                    let (spilled_bytes, spill_files) = stage.spilled();
+                    warn!(
+                        "Stage {} of job {} spilled {} bytes to {} files, more than {} times the {} bytes it read, its tasks may lack memory",
+                        stage_id,
+                        job_id,
+                        spilled_bytes,
+                        spill_files,
+                        ratio,
+                        stage.input_bytes()
+                    );
+                }
             }
+        }
 
-            self.state.put_txn(txn_ops).await?;
+        let event = if graph.complete() {
+            // If this ExecutionGraph is complete, finalize it
+            info!(
+                "Job {} is complete, finalizing output partitions",
+                graph.job_id()
+            );
+            graph.finalize()?;
+            Some(QueryStageSchedulerEvent::JobFinished(job_id.to_owned()))
+        } else if let Some(job_status::Status::Failed(failure)) = graph.status().status {
+            Some(QueryStageSchedulerEvent::JobFailed(
+                job_id.to_owned(),
+                failure,
+            ))
+        } else {
+            None
+        };
 
-            Ok((events, reservation))
-        })
-        .await
+        self.state
+            .put(
+                Keyspace::ActiveJobs,
+                job_id.to_owned(),
+                self.encode_execution_graph(graph)?,
+            )
+            .await?;
+        Ok(event)
+    }
+
+    /// Reschedule the tasks of the active jobs which ran on executor `executor_id`, after
+    /// it registered again, see [ExecutionGraph::reset_executor]. Jobs are locked one at
+    /// a time. Returns the number of tasks reset.
+    ///
+    /// The executor registers with a single scheduler, which resets the tasks of the
+    /// jobs of every scheduler when the jobs are sharded.
+    pub(crate) async fn reset_executor_tasks(&self, executor_id: &str) -> Result<usize> {
+        let mut reset = 0;
+        for job_id in self.state.scan_keys(Keyspace::ActiveJobs).await? {
+            let lock = self.state.lock(Keyspace::ActiveJobs, &job_id).await?;
+            reset += with_lock(lock, self.reset_job_executor_tasks(&job_id, executor_id))
+                .await?;
+        }
+        Ok(reset)
+    }
+
+    /// Reschedule the tasks of job `job_id` which ran on executor `executor_id`. The lock
+    /// of the job must be held.
+    async fn reset_job_executor_tasks(
+        &self,
+        job_id: &str,
+        executor_id: &str,
+    ) -> Result<usize> {
+        let mut graph = match self.get_active_execution_graph(job_id).await? {
+            Some(graph) if !graph.complete() && graph.ran_on(executor_id) => graph,
+            _ => return Ok(0),
+        };
+        let reset = graph.reset_executor(executor_id)?;
+        if reset > 0 {
+            self.slot_reservations
+                .set_running_tasks(job_id, graph.running_tasks());
+            info!(
+                "Rescheduling {} tasks of job {} which ran on executor {}",
+                reset, job_id, executor_id
+            );
+        }
+        // Saved even when no task is reset, as the readers of the outputs of the
+        // executor may have switched to their copies
+        self.state
+            .put(
+                Keyspace::ActiveJobs,
+                job_id.to_owned(),
+                self.encode_execution_graph(graph)?,
+            )
+            .await?;
+        Ok(reset)
     }
 
     /// Take a list of executor reservations and fill them with tasks that are ready
@@ -237,11 +852,17 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
     /// Here we use the following  algorithm:
     ///
     /// 1. For each reservation with a `job_id` assigned try and assign another task from the same job.
+    ///    While jobs run fewer tasks than the slots they reserved, only the reservations of
+    ///    those jobs are kept for them.
     /// 2. If a reservation either does not have a `job_id` or there are no available tasks for its `job_id`,
     ///    add it to a list of "free" reservations.
-    /// 3. For each free reservation, try to assign a task from one of the jobs we have already considered.
-    /// 4. If we cannot find a task, then looks for a task among all active jobs
-    /// 5. If we cannot find a task in all active jobs, then add the reservation to the list of unassigned reservations
+    /// 3. Fill free reservations with tasks of the jobs running fewer tasks than the slots they
+    ///    reserved, up to the slots they lack.
+    /// 4. For each free reservation, try to assign a task from one of the jobs we have already assigned tasks of.
+    /// 5. If we cannot find a task, then looks for a task among all active jobs
+    /// 6. If we cannot find a task in all active jobs, then add the reservation to the list of unassigned reservations
+    ///
+    /// Jobs are locked one at a time, while their tasks are assigned.
     ///
     /// Finally, we return:
     /// 1. A list of assignments which is a (Executor ID, Task) tuple
@@ -251,141 +872,264 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         &self,
         reservations: &[ExecutorReservation],
     ) -> Result<(Vec<(String, Task)>, Vec<ExecutorReservation>, usize)> {
-        let lock = self.state.lock(Keyspace::ActiveJobs, "").await?;
-
-        with_lock(lock, async {
-            let mut assignments: Vec<(String, Task)> = vec![];
-            let mut free_reservations: Vec<ExecutorReservation> = vec![];
-            // let _txn_ops: Vec<(Keyspace, String, Vec<u8>)> = vec![];
-
-            // Need to collect graphs we update so we can update them in storage when we are done
-            let mut graphs: HashMap<String, ExecutionGraph> = HashMap::new();
-
-            // First try and fill reservations for particular jobs. If the job has no more tasks
-            // free the reservation.
-            for reservation in reservations {
-                debug!(
-                "Filling reservation for executor {} from job {:?}",
-                reservation.executor_id, reservation.job_id
+        let mut assignments: Vec<(String, Task)> = vec![];
+        // Executors of the free reservations
+        let mut free: Vec<String> = vec![];
+        // Pending tasks of the jobs we assigned tasks of
+        let mut pending_tasks: HashMap<String, usize> = HashMap::new();
+
+        // Jobs running fewer tasks than the slots they reserved
+        let shortfalls = self.slot_reservations.shortfalls();
+
+        // First try and fill reservations for particular jobs. If the job has no more tasks
+        // free the reservation.
+        let mut job_reservations: HashMap<&str, Vec<String>> = HashMap::new();
+        for reservation in reservations {
+            let job_id = reservation.job_id.as_ref().filter(|job_id| {
+                shortfalls.is_empty() || shortfalls.iter().any(|(id, _)| id == *job_id)
+            });
+            match job_id {
+                Some(job_id) => job_reservations
+                    .entry(job_id.as_str())
+                    .or_default()
+                    .push(reservation.executor_id.clone()),
+                None => free.push(reservation.executor_id.clone()),
+            }
+        }
+        for (job_id, executor_ids) in job_reservations {
+            debug!(
+                "Filling reservations for executors {:?} from job {}",
+                executor_ids, job_id
             );
-                let executor_id = &reservation.executor_id;
-                if let Some(job_id) = &reservation.job_id {
-                    if let Some(graph) = graphs.get_mut(job_id) {
-                        if let Ok(Some(next_task)) = graph.pop_next_task(executor_id) {
-                            debug!(
-                            "Filled reservation for executor {} with task {:?}",
-                            executor_id, next_task
-                        );
-                            assignments.push((executor_id.clone(), next_task));
-                        } else {
-                            debug!("Cannot fill reservation for executor {} from job {}, freeing reservation", executor_id, job_id);
-                            free_reservations
-                                .push(ExecutorReservation::new_free(executor_id.clone()));
-                        }
-                    } else {
-                        // let lock = self.state.lock(Keyspace::ActiveJobs, job_id).await?;
-                        let mut graph = self.get_execution_graph(job_id).await?;
-
-                        if let Ok(Some(next_task)) = graph.pop_next_task(executor_id) {
-                            debug!(
-                            "Filled reservation for executor {} with task {:?}",
-                            executor_id, next_task
-                        );
-                            assignments.push((executor_id.clone(), next_task));
-                            graphs.insert(job_id.clone(), graph);
-                            // locks.push(lock);
-                        } else {
-                            debug!("Cannot fill reservation for executor {} from job {}, freeing reservation", executor_id, job_id);
-                            free_reservations
-                                .push(ExecutorReservation::new_free(executor_id.clone()));
-                        }
-                    }
-                } else {
-                    free_reservations.push(reservation.clone());
+            let unfilled = self
+                .fill_job_reservations(
+                    job_id,
+                    executor_ids,
+                    &mut assignments,
+                    &mut pending_tasks,
+                )
+                .await;
+            if !unfilled.is_empty() {
+                debug!("Cannot fill reservations for executors {:?} from job {}, freeing reservations", unfilled, job_id);
+            }
+            free.extend(unfilled);
+        }
+
+        // Then give jobs the reserved slots they lack in their pool. The slots they
+        // cannot use, as they have no task ready to run on them, are left to the other
+        // jobs.
+        if !shortfalls.is_empty() {
+            let mut pools: HashMap<String, String> = HashMap::new();
+            for executor_id in &free {
+                let labels = self.executor_manager.executor_labels(executor_id).await;
+                let pool = executor_pool(&labels).to_owned();
+                pools.insert(executor_id.clone(), pool);
+            }
+            for (job_id, lacking) in self.slot_reservations.shortfalls() {
+                let pool = match self.slot_reservations.pool(&job_id) {
+                    Some(pool) => pool,
+                    None => continue,
+                };
+                let executor_ids = take_in_pool(&mut free, &pools, &pool, lacking);
+                if executor_ids.is_empty() {
+                    continue;
                 }
+                debug!(
+                    "Filling reserved slots for executors {:?} from job {}",
+                    executor_ids, job_id
+                );
+                let unfilled = self
+                    .fill_job_reservations(
+                        &job_id,
+                        executor_ids,
+                        &mut assignments,
+                        &mut pending_tasks,
+                    )
+                    .await;
+                free.extend(unfilled);
             }
+        }
 
-            let mut other_jobs: Vec<String> =
-                self.get_active_jobs().await?.into_iter().collect();
+        // Now try and find tasks for free reservations from the jobs we already assigned
+        // tasks of, then from the other active jobs
+        let mut job_ids: Vec<String> = pending_tasks.keys().cloned().collect();
+        if !free.is_empty() {
+            let active_jobs = self.get_active_jobs().await?;
+            job_ids.extend(
+                active_jobs
+                    .into_iter()
+                    .filter(|job_id| !pending_tasks.contains_key(job_id)),
+            );
+        }
+        for job_id in job_ids {
+            if free.is_empty() {
+                break;
+            }
+            debug!(
+                "Filling free reservations for executors {:?} from job {}",
+                free, job_id
+            );
+            free = self
+                .fill_job_reservations(
+                    &job_id,
+                    free,
+                    &mut assignments,
+                    &mut pending_tasks,
+                )
+                .await;
+        }
 
-            let mut unassigned: Vec<ExecutorReservation> = vec![];
-            // Now try and find tasks for free reservations from current set of graphs
-            for reservation in free_reservations {
-                debug!(
-                "Filling free reservation for executor {}",
-                reservation.executor_id
+        if !free.is_empty() {
+            debug!(
+                "Unable to fill reservations for executors {:?}, no tasks available",
+                free
             );
-                let mut assigned = false;
-                let executor_id = reservation.executor_id.clone();
+        }
+        let unassigned = free
+            .into_iter()
+            .map(ExecutorReservation::new_free)
+            .collect();
 
-                // Try and find a task in the graphs we already have locks on
-                if let Ok(Some(assignment)) = find_next_task(&executor_id, &mut graphs) {
-                    debug!(
-                    "Filled free reservation for executor {} with task {:?}",
-                    reservation.executor_id, assignment.1
-                );
-                    // First check if we can find another task
-                    assignments.push(assignment);
-                    assigned = true;
-                } else {
-                    // Otherwise start searching through other active jobs.
-                    debug!(
-                    "Filling free reservation for executor {} from active jobs {:?}",
-                    reservation.executor_id, other_jobs
-                );
-                    while let Some(job_id) = other_jobs.pop() {
-                        if graphs.get(&job_id).is_none() {
-                            // let lock = self.state.lock(Keyspace::ActiveJobs, &job_id).await?;
-                            let mut graph = self.get_execution_graph(&job_id).await?;
-
-                            if let Ok(Some(task)) = graph.pop_next_task(&executor_id) {
-                                debug!(
-                                "Filled free reservation for executor {} with task {:?}",
-                                reservation.executor_id, task
-                            );
-                                assignments.push((executor_id.clone(), task));
-                                // locks.push(lock);
-                                graphs.insert(job_id, graph);
-                                assigned = true;
-                                break;
-                            } else {
-                                debug!("No available tasks for job {}", job_id);
-                            }
-                        }
-                    }
-                }
+        Ok((assignments, unassigned, pending_tasks.values().sum()))
+    }
 
-                if !assigned {
-                    debug!(
-                    "Unable to fill reservation for executor {}, no tasks available",
-                    executor_id
-                );
-                    unassigned.push(reservation);
+    /// Fill the reservations of `executor_ids` with tasks of job `job_id`, while holding
+    /// the lock of that job only. Adds the assigned tasks to `assignments` and records the
+    /// number of tasks of the job still pending in `pending_tasks`. Returns the executors
+    /// of the reservations which could not be filled.
+    async fn fill_job_reservations(
+        &self,
+        job_id: &str,
+        executor_ids: Vec<String>,
+        assignments: &mut Vec<(String, Task)>,
+        pending_tasks: &mut HashMap<String, usize>,
+    ) -> Vec<String> {
+        let result = match self.state.lock(Keyspace::ActiveJobs, job_id).await {
+            Ok(lock) => {
+                with_lock(lock, self.assign_job_tasks(job_id, &executor_ids)).await
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok((assigned, unfilled, pending)) => {
+                if !assigned.is_empty() {
+                    pending_tasks.insert(job_id.to_owned(), pending);
                 }
+                assignments.extend(assigned);
+                unfilled
             }
+            Err(e) => {
+                warn!("Failed to assign tasks of job {}: {:?}", job_id, e);
+                executor_ids
+            }
+        }
+    }
 
-            let mut pending_tasks = 0;
+    /// Assign tasks of job `job_id`, whose lock must be held, to the executors of
+    /// `executor_ids`. Returns the assigned tasks, the executors no task could be assigned
+    /// to, and the number of tasks of the job still pending.
+    async fn assign_job_tasks(
+        &self,
+        job_id: &str,
+        executor_ids: &[String],
+    ) -> Result<(Vec<(String, Task)>, Vec<String>, usize)> {
+        // The job may have completed since it was listed
+        let mut graph = match self.get_active_execution_graph(job_id).await? {
+            Some(graph) => graph,
+            None => return Ok((vec![], executor_ids.to_vec(), 0)),
+        };
 
-            // Transactional update graphs now that we have assigned tasks
-            let txn_ops: Vec<(Keyspace, String, Vec<u8>)> = graphs
-                .into_iter()
-                .map(|(job_id, graph)| {
-                    pending_tasks += graph.available_tasks();
-                    let value = self.encode_execution_graph(graph)?;
-                    Ok((Keyspace::ActiveJobs, job_id, value))
-                })
-                .collect::<Result<Vec<_>>>()?;
+        // Tasks are only assigned to the executors of the pool of the job. Tasks reading
+        // node-local data are only assigned to executors of their node, and the tasks
+        // of the stages of the resource profile of an executor first.
+        let pool = graph.pool.clone();
+        let placed = graph.reads_node_local_data() || graph.has_resource_profiles();
+        let mut assigned = vec![];
+        let mut unfilled = vec![];
+        for executor_id in executor_ids {
+            let labels = self.executor_manager.executor_labels(executor_id).await;
+            if executor_pool(&labels) != pool {
+                unfilled.push(executor_id.clone());
+                continue;
+            }
+            let task = if placed {
+                graph.pop_next_task_on(executor_id, &labels)
+            } else {
+                graph.pop_next_task(executor_id)
+            };
+            match task {
+                Ok(Some(task)) => {
+                    debug!(
+                        "Filled reservation for executor {} with task {:?}",
+                        executor_id, task
+                    );
+                    self.job_timeouts.stage_started(
+                        job_id,
+                        task.partition.stage_id,
+                        Instant::now(),
+                    );
+                    assigned.push((executor_id.clone(), task))
+                }
+                _ => unfilled.push(executor_id.clone()),
+            }
+        }
 
-            self.state.put_txn(txn_ops).await?;
+        let pending = graph.available_tasks();
+        if !assigned.is_empty() {
+            self.slot_reservations
+                .set_running_tasks(job_id, graph.running_tasks());
+            self.state
+                .put(
+                    Keyspace::ActiveJobs,
+                    job_id.to_owned(),
+                    self.encode_execution_graph(graph)?,
+                )
+                .await?;
+        }
+        Ok((assigned, unfilled, pending))
+    }
 
-            Ok((assignments, unassigned, pending_tasks))
-        }).await
+    /// Return `task`, which was assigned to an executor but could not be launched on
+    /// it, to the tasks of its job waiting for an executor
+    pub async fn return_task(&self, task: Task) -> Result<()> {
+        let job_id = task.partition.job_id.clone();
+        let lock = self.state.lock(Keyspace::ActiveJobs, &job_id).await?;
+        with_lock(lock, async {
+            // The job may have failed since the task was assigned
+            let mut graph = match self.get_active_execution_graph(&job_id).await? {
+                Some(graph) => graph,
+                None => return Ok(()),
+            };
+            graph.reset_task_status(task);
+            self.slot_reservations
+                .set_running_tasks(&job_id, graph.running_tasks());
+            self.state
+                .put(
+                    Keyspace::ActiveJobs,
+                    job_id.clone(),
+                    self.encode_execution_graph(graph)?,
+                )
+                .await
+        })
+        .await
     }
 
     /// Move the given job to the CompletedJobs keyspace in persistent storage.
     pub async fn complete_job(&self, job_id: &str) -> Result<()> {
         debug!("Moving job {} from Active to Completed", job_id);
-        let lock = self.state.lock(Keyspace::ActiveJobs, "").await?;
+        self.record_usage(job_id).await;
+        self.job_timeouts.remove(job_id);
+        self.task_progress.remove_job(job_id);
+        self.object_stores.shard(job_id).remove(job_id);
+        self.cached_tables.shard(job_id).remove(job_id);
+        self.table_analyses.shard(job_id).remove(job_id);
+        self.in_memory_results.lock().remove(job_id);
+        self.spill_warning_ratios.shard(job_id).remove(job_id);
+        self.job_pools.remove(job_id);
+        self.reservation_queue.remove(job_id);
+        self.local_jobs.lock().remove(job_id);
+        let lock = self.state.lock(Keyspace::ActiveJobs, job_id).await?;
         with_lock(
             lock,
             self.state
@@ -397,16 +1141,27 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
     /// Mark a job as failed. This will create a key under the FailedJobs keyspace
     /// and remove the job from ActiveJobs or QueuedJobs
     /// TODO this should be atomic
-    pub async fn fail_job(&self, job_id: &str, error_message: String) -> Result<()> {
-        let lock = self.state.lock(Keyspace::ActiveJobs, "").await?;
+    pub async fn fail_job(&self, job_id: &str, failure: FailedJob) -> Result<()> {
+        self.record_usage(job_id).await;
+        self.object_stores.shard(job_id).remove(job_id);
+        self.cached_tables.shard(job_id).remove(job_id);
+        self.table_analyses.shard(job_id).remove(job_id);
+        self.in_memory_results.lock().remove(job_id);
+        self.spill_warning_ratios.shard(job_id).remove(job_id);
+        self.retried_attempts.shard(job_id).remove(job_id);
+        self.job_pools.remove(job_id);
+        self.job_timeouts.remove(job_id);
+        self.task_progress.remove_job(job_id);
+        self.planning_queue.remove(job_id);
+        self.reservation_queue.remove(job_id);
+        self.local_jobs.lock().remove(job_id);
+        let lock = self.state.lock(Keyspace::ActiveJobs, job_id).await?;
         with_lock(lock, self.state.delete(Keyspace::ActiveJobs, job_id)).await?;
 
         self.state.delete(Keyspace::QueuedJobs, job_id).await?;
 
         let status = JobStatus {
-            status: Some(job_status::Status::Failed(FailedJob {
-                error: error_message,
-            })),
+            status: Some(job_status::Status::Failed(failure)),
         };
         let value = encode_protobuf(&status)?;
 
@@ -416,53 +1171,169 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
     }
 
     #[cfg(not(test))]
-    /// Launch the given task on the specified executor
+    /// Launch the given task on the specified executor, which copies its output to
+    /// executor `replica` if given
     pub async fn launch_task(
         &self,
         executor: &ExecutorMetadata,
         task: Task,
+        replica: Option<ExecutorMetadata>,
     ) -> Result<()> {
         info!("Launching task {:?} on executor {:?}", task, executor.id);
-        let task_definition = self.prepare_task_definition(task)?;
-        let mut clients = self.clients.write().await;
-        if let Some(client) = clients.get_mut(&executor.id) {
-            client
-                .launch_task(protobuf::LaunchTaskParams {
-                    task: vec![task_definition],
-                })
-                .await
-                .map_err(|e| {
-                    BallistaError::Internal(format!(
-                        "Failed to connect to executor {}: {:?}",
-                        executor.id, e
-                    ))
-                })?;
-        } else {
-            let executor_id = executor.id.clone();
-            let executor_url = format!("http://{}:{}", executor.host, executor.grpc_port);
-            let mut client = ExecutorGrpcClient::connect(executor_url).await?;
-            clients.insert(executor_id, client.clone());
-            client
-                .launch_task(protobuf::LaunchTaskParams {
-                    task: vec![task_definition],
-                })
-                .await
-                .map_err(|e| {
-                    BallistaError::Internal(format!(
-                        "Failed to connect to executor {}: {:?}",
-                        executor.id, e
-                    ))
-                })?;
+        let task_definition =
+            self.prepare_task_definition(task, &executor.protocol, replica)?;
+        self.executor_client(executor)
+            .await?
+            .launch_task(protobuf::LaunchTaskParams {
+                task: vec![task_definition],
+                protocol_version: ballista_core::protocol::PROTOCOL_VERSION,
+            })
+            .await
+            .map_err(|e| {
+                BallistaError::Internal(format!(
+                    "Failed to connect to executor {}: {:?}",
+                    executor.id, e
+                ))
+            })?;
+        Ok(())
+    }
+
+    /// Delete the shuffle files of job `job_id` kept by `executor`, including the files
+    /// of a cached table
+    pub async fn remove_job_data(
+        &self,
+        executor: &ExecutorMetadata,
+        job_id: &str,
+    ) -> Result<()> {
+        self.executor_client(executor)
+            .await?
+            .remove_job_data(protobuf::RemoveJobDataParams {
+                job_id: job_id.to_owned(),
+            })
+            .await
+            .map_err(|e| {
+                BallistaError::Internal(format!(
+                    "Failed to remove the data of job {} from executor {}: {:?}",
+                    job_id, executor.id, e
+                ))
+            })?;
+        Ok(())
+    }
+
+    /// Have executor `executor_id` remove the data of job `job_id` the next time it polls
+    /// for work. The removal is saved in the state backend, so that it reaches the
+    /// executor whichever scheduler it polls.
+    pub async fn queue_job_data_removal(
+        &self,
+        executor_id: &str,
+        job_id: &str,
+    ) -> Result<()> {
+        self.state
+            .put(
+                Keyspace::JobDataRemovals,
+                job_data_removal_key(executor_id, job_id),
+                job_id.as_bytes().to_vec(),
+            )
+            .await
+    }
+
+    /// Take the jobs whose data executor `executor_id` must remove. They are only read
+    /// from the state backend if they were last read over
+    /// [JOB_DATA_REMOVAL_CHECK_INTERVAL] ago, so that polls do not all read them.
+    pub async fn take_job_data_removals(&self, executor_id: &str) -> Result<Vec<String>> {
+        {
+            let mut checks = self.job_data_removal_checks.lock();
+            match checks.get(executor_id) {
+                Some(at) if at.elapsed() < JOB_DATA_REMOVAL_CHECK_INTERVAL => {
+                    return Ok(vec![])
+                }
+                _ => checks.insert(executor_id.to_owned(), Instant::now()),
+            };
+        }
+        self.remove_job_data_removals(executor_id).await
+    }
+
+    /// Drop the jobs whose data executor `executor_id` must remove once it is lost, as
+    /// executors get a new ID whenever they start
+    pub async fn forget_job_data_removals(&self, executor_id: &str) -> Result<()> {
+        self.job_data_removal_checks.lock().remove(executor_id);
+        self.remove_job_data_removals(executor_id).await?;
+        Ok(())
+    }
+
+    /// Remove the jobs whose data executor `executor_id` must remove from the state
+    /// backend, returning them
+    async fn remove_job_data_removals(&self, executor_id: &str) -> Result<Vec<String>> {
+        let removals = self
+            .state
+            .get_from_prefix(
+                Keyspace::JobDataRemovals,
+                &job_data_removal_key(executor_id, ""),
+            )
+            .await?;
+        let mut job_ids = Vec::with_capacity(removals.len());
+        for (_, value) in removals {
+            let job_id = String::from_utf8(value).map_err(|e| {
+                BallistaError::Internal(format!("Invalid job ID of data removal: {}", e))
+            })?;
+            self.state
+                .delete(
+                    Keyspace::JobDataRemovals,
+                    &job_data_removal_key(executor_id, &job_id),
+                )
+                .await?;
+            job_ids.push(job_id);
         }
+        Ok(job_ids)
+    }
+
+    /// Tell `executor` that the scheduler is shutting down
+    pub async fn notify_scheduler_shutdown(
+        &self,
+        executor: &ExecutorMetadata,
+    ) -> Result<()> {
+        self.executor_client(executor)
+            .await?
+            .scheduler_shutdown(protobuf::SchedulerShutdownParams {})
+            .await
+            .map_err(|e| {
+                BallistaError::Internal(format!(
+                    "Failed to notify executor {} of the scheduler shutdown: {:?}",
+                    executor.id, e
+                ))
+            })?;
         Ok(())
     }
 
+    /// The client of `executor`. The clients are only locked while looked up, so that
+    /// requests to different executors are sent concurrently.
+    async fn executor_client(
+        &self,
+        executor: &ExecutorMetadata,
+    ) -> Result<ExecutorGrpcClient<Channel>> {
+        if let Some(client) = self.clients.read().await.get(&executor.id) {
+            return Ok(client.clone());
+        }
+        let mut clients = self.clients.write().await;
+        match clients.get(&executor.id) {
+            Some(client) => Ok(client.clone()),
+            None => {
+                let executor_url = grpc_url(&executor.host, executor.grpc_port);
+                let client =
+                    ExecutorGrpcClient::new(create_grpc_channel(&executor_url).await?);
+                clients.insert(executor.id.clone(), client.clone());
+                Ok(client)
+            }
+        }
+    }
+
     /// In unit tests, we do not have actual executors running, so it simplifies things to just noop.
     #[cfg(test)]
     pub async fn launch_task(
         &self,
         _executor: &ExecutorMetadata,
         _task: Task,
+        _replica: Option<ExecutorMetadata>,
     ) -> Result<()> {
         Ok(())
     }
@@ -475,16 +1346,63 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         Ok(graph.available_tasks())
     }
 
+    /// Return the number of active jobs along with the total number of tasks across
+    /// those jobs which are ready to be scheduled. The value returned is strictly a
+    /// point-in-time snapshot
+    pub async fn get_active_job_summary(&self) -> Result<(usize, usize)> {
+        let job_ids = self.get_active_jobs().await?;
+        let mut pending_tasks = 0;
+        for job_id in &job_ids {
+            // A job may complete between listing and reading it
+            if let Ok(graph) = self.get_execution_graph(job_id).await {
+                pending_tasks += graph.available_tasks();
+            }
+        }
+        Ok((job_ids.len(), pending_tasks))
+    }
+
     #[allow(dead_code)]
-    pub fn prepare_task_definition(&self, task: Task) -> Result<TaskDefinition> {
+    /// Encode `task` for an executor speaking `protocol`, which copies the output of the
+    /// task to executor `replica` if given
+    pub fn prepare_task_definition(
+        &self,
+        task: Task,
+        protocol: &ProtocolInfo,
+        replica: Option<ExecutorMetadata>,
+    ) -> Result<TaskDefinition> {
         debug!("Preparing task definition for {:?}", task);
+        let plan = match with_write_attempt(task.plan.clone(), task.attempt)? {
+            Some(_) if !protocol.supports(Capability::WriteAttempts) => {
+                return Err(BallistaError::General(format!(
+                    "Task {:?} writes Parquet files, which requires the {} capability \
+                    the executor speaking protocol version {} does not support",
+                    task.partition,
+                    Capability::WriteAttempts.as_str(),
+                    protocol.version
+                )));
+            }
+            Some(plan) => plan,
+            None => task.plan,
+        };
+        // Tasks run the same without their Bloom filters, only filtering fewer rows
+        let plan = if protocol.supports(Capability::BloomFilters) {
+            plan
+        } else {
+            without_bloom_filters(plan)?
+        };
         let mut plan_buf: Vec<u8> = vec![];
         let plan_proto =
-            U::try_from_physical_plan(task.plan, self.codec.physical_extension_codec())?;
+            U::try_from_physical_plan(plan, self.codec.physical_extension_codec())?;
         plan_proto.try_encode(&mut plan_buf)?;
 
         let output_partitioning =
             hash_partitioning_to_proto(task.output_partitioning.as_ref())?;
+        // Only the output of the final stage is the output of the job
+        let in_memory = task.final_stage
+            && self
+                .in_memory_results
+                .lock()
+                .contains(&task.partition.job_id);
 
         let task_definition = TaskDefinition {
             task_id: Some(PartitionId {
@@ -495,17 +1413,88 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             plan: plan_buf,
             output_partitioning,
             session_id: task.session_id,
-            props: vec![],
+            // Executors keep the output of the jobs of cached tables until it is removed
+            props: self
+                .cached_table(&task.partition.job_id)
+                .map(|name| KeyValuePair {
+                    key: BALLISTA_CACHE_TABLE.to_owned(),
+                    value: name,
+                })
+                .into_iter()
+                .chain(in_memory.then(|| KeyValuePair {
+                    key: BALLISTA_JOB_IN_MEMORY_RESULTS.to_owned(),
+                    value: "true".to_owned(),
+                }))
+                .chain(self.job_timeouts.task_timeout(&task.partition.job_id).map(
+                    |timeout| KeyValuePair {
+                        key: BALLISTA_TASK_TIMEOUT_SECONDS.to_owned(),
+                        value: timeout.as_secs().to_string(),
+                    },
+                ))
+                .collect(),
+            trace_context: task.trace_context,
+            attempt: task.attempt,
+            encryption_key: task
+                .encryption_key
+                .map(|key| key.as_bytes().to_vec())
+                .unwrap_or_default(),
+            object_stores: self
+                .object_stores
+                .shard(&task.partition.job_id)
+                .get(&task.partition.job_id)
+                .map(|credentials| credentials.to_proto())
+                .unwrap_or_default(),
+            // Output kept in memory has no file to copy
+            replica: replica.filter(|_| !in_memory).map(Into::into),
         };
-        Ok(task_definition)
+        gate_task_definition(task_definition, protocol)
+    }
+
+    /// Return the IDs of all known jobs, whether queued, running, completed or failed
+    pub async fn get_job_ids(&self) -> Result<Vec<String>> {
+        let mut job_ids = HashSet::new();
+        for keyspace in [
+            Keyspace::QueuedJobs,
+            Keyspace::ActiveJobs,
+            Keyspace::CompletedJobs,
+            Keyspace::FailedJobs,
+        ] {
+            job_ids.extend(self.state.scan_keys(keyspace).await?);
+        }
+        let mut job_ids: Vec<String> = job_ids.into_iter().collect();
+        job_ids.sort();
+        Ok(job_ids)
+    }
+
+    /// Return the IDs of the jobs submitted to this scheduler which are queued or
+    /// running. The jobs of the other schedulers sharing the state backend are not
+    /// included, as they are run by their own scheduler.
+    pub fn get_unfinished_job_ids(&self) -> HashSet<String> {
+        self.local_jobs.lock().clone()
     }
 
     ///  Return a set of active job IDs. This will return all keys
     /// in the `ActiveJobs` keyspace stripped of any prefixes used for
-    /// the storage layer (i.e. just the Job IDs).
+    /// the storage layer (i.e. just the Job IDs), of the jobs owned by this
+    /// scheduler.
     async fn get_active_jobs(&self) -> Result<HashSet<String>> {
         debug!("Scanning for active job IDs");
-        self.state.scan_keys(Keyspace::ActiveJobs).await
+        let mut job_ids = self.state.scan_keys(Keyspace::ActiveJobs).await?;
+        job_ids.retain(|job_id| self.owns_job(job_id));
+        Ok(job_ids)
+    }
+
+    /// Get the `ExecutionGraph` of the given job ID if it is active
+    async fn get_active_execution_graph(
+        &self,
+        job_id: &str,
+    ) -> Result<Option<ExecutionGraph>> {
+        let value = self.state.get(Keyspace::ActiveJobs, job_id).await?;
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            self.decode_execution_graph(value).await.map(Some)
+        }
     }
 
     /// Get the `ExecutionGraph` for the given job ID. This will search fist in the `ActiveJobs`
@@ -524,6 +1513,23 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
         }
     }
 
+    /// The `ExecutionGraph` of job `job_id`, `None` if the job is unknown or has no
+    /// graph, such as queued jobs and jobs which failed to be planned
+    pub(crate) async fn find_execution_graph(
+        &self,
+        job_id: &str,
+    ) -> Result<Option<ExecutionGraph>> {
+        if let Some(graph) = self.get_active_execution_graph(job_id).await? {
+            return Ok(Some(graph));
+        }
+        let value = self.state.get(Keyspace::CompletedJobs, job_id).await?;
+        if value.is_empty() {
+            Ok(None)
+        } else {
+            self.decode_execution_graph(value).await.map(Some)
+        }
+    }
+
     async fn get_session(&self, session_id: &str) -> Result<Arc<SessionContext>> {
         let value = self.state.get(Keyspace::Sessions, session_id).await?;
 
@@ -559,19 +1565,31 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             let mut task_statuses: Vec<Option<task_status::Status>> =
                 vec![None; partitions];
 
+            let resource_profile = stage.resource_profile();
             for status in stage.task_statuses {
                 if let Some(task_id) = status.task_id.as_ref() {
                     task_statuses[task_id.partition_id as usize] = status.status
                 }
             }
 
-            // This is a little hacky but since we can't make an optional
-            // primitive field in protobuf, we just use 0 to encode None.
-            // Should work since stage IDs are 1-indexed.
-            let output_link = if stage.output_link == 0 {
-                None
+            let mut task_infos: Vec<TaskInfo> = vec![TaskInfo::default(); partitions];
+            for info in stage.task_infos {
+                if let Some(task_info) = task_infos.get_mut(info.partition_id as usize) {
+                    *task_info = decode_task_info(info);
+                }
+            }
+
+            // Graphs saved before stages could be shared only have `output_link`, where
+            // 0 encodes no link. Should work since stage IDs are 1-indexed.
+            let output_links = if stage.output_links.is_empty() && stage.output_link != 0
+            {
+                vec![stage.output_link as usize]
             } else {
-                Some(stage.output_link as usize)
+                stage
+                    .output_links
+                    .iter()
+                    .map(|link| *link as usize)
+                    .collect()
             };
 
             let output_partitioning: Option<Partitioning> =
@@ -599,12 +1617,18 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                         Ok((partition, locations))
                     })
                     .collect::<Result<HashMap<usize, Vec<PartitionLocation>>>>()?;
+                let bloom_filter = if input.bloom_filter.is_empty() {
+                    None
+                } else {
+                    Some(Arc::new(BloomFilter::from_bytes(&input.bloom_filter)?))
+                };
 
                 inputs.insert(
                     stage_id,
                     StageOutput {
                         partition_locations: outputs,
                         complete: input.complete,
+                        bloom_filter,
                     },
                 );
             }
@@ -614,10 +1638,24 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                 partitions,
                 output_partitioning,
                 inputs,
+                task_nodes: task_nodes(&plan)?,
                 plan,
                 task_statuses,
-                output_link,
+                output_links,
                 resolved: stage.resolved,
+                start_time: if stage.start_time == 0 {
+                    None
+                } else {
+                    Some(stage.start_time)
+                },
+                task_infos,
+                resource_profile,
+                bloom_filter: if stage.bloom_filter.is_empty() {
+                    None
+                } else {
+                    Some(BloomFilter::from_bytes(&stage.bloom_filter)?)
+                },
+                bloom_filter_incomplete: stage.bloom_filter_incomplete,
             };
             stages.insert(stage_id, execution_stage);
         }
@@ -639,6 +1677,16 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             stages,
             output_partitions: proto.output_partitions as usize,
             output_locations,
+            trace_context: proto.trace_context,
+            encryption_key: EncryptionKey::from_proto(&proto.encryption_key)?,
+            usage: proto.usage.unwrap_or_default(),
+            priority: proto.priority,
+            replicate_shuffles: proto.replicate_shuffles,
+            pool: if proto.pool.is_empty() {
+                DEFAULT_POOL.to_owned()
+            } else {
+                proto.pool
+            },
         })
     }
 
@@ -651,12 +1699,12 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             .map(|(stage_id, stage)| {
                 // This is a little hacky but since we can't make an optional
                 // primitive field in protobuf, we just use 0 to encode None.
-                // Should work since stage IDs are 1-indexed.
-                let output_link = if let Some(link) = stage.output_link {
-                    link as u32
-                } else {
-                    0
-                };
+                // Should work since stage IDs are 1-indexed. `output_link` is kept
+                // for schedulers that predate `output_links`.
+                let output_link =
+                    stage.output_links.first().copied().unwrap_or_default() as u32;
+                let output_links =
+                    stage.output_links.iter().map(|link| *link as u32).collect();
 
                 let mut plan: Vec<u8> = vec![];
 
@@ -685,6 +1733,10 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                             })
                             .collect::<Result<Vec<_>>>()?,
                         complete: output.complete,
+                        bloom_filter: output
+                            .bloom_filter
+                            .map(|filter| filter.to_bytes())
+                            .unwrap_or_default(),
                     });
                 }
 
@@ -700,6 +1752,8 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                                 partition_id: partition as u32,
                             }),
                             status: Some(status),
+                            // Attempts are kept in the task infos of the stage
+                            attempt: 0,
                         })
                     })
                     .collect();
@@ -707,6 +1761,13 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                 let output_partitioning =
                     hash_partitioning_to_proto(stage.output_partitioning.as_ref())?;
 
+                let task_infos: Vec<protobuf::GraphTaskInfo> = stage
+                    .task_infos
+                    .into_iter()
+                    .enumerate()
+                    .map(|(partition, info)| encode_task_info(partition, info))
+                    .collect();
+
                 Ok(protobuf::ExecutionGraphStage {
                     stage_id: stage_id as u64,
                     partitions: stage.partitions as u32,
@@ -715,7 +1776,16 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
                     plan,
                     task_statuses,
                     output_link,
+                    output_links,
                     resolved: stage.resolved,
+                    start_time: stage.start_time.unwrap_or_default(),
+                    task_infos,
+                    resource_profile: stage.resource_profile.into(),
+                    bloom_filter: stage
+                        .bloom_filter
+                        .map(|filter| filter.to_bytes())
+                        .unwrap_or_default(),
+                    bloom_filter_incomplete: stage.bloom_filter_incomplete,
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -733,19 +1803,297 @@ impl<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan> TaskManager<T, U>
             stages,
             output_partitions: graph.output_partitions as u64,
             output_locations,
+            trace_context: graph.trace_context,
+            encryption_key: graph
+                .encryption_key
+                .map(|key| key.as_bytes().to_vec())
+                .unwrap_or_default(),
+            usage: Some(graph.usage),
+            priority: graph.priority,
+            replicate_shuffles: graph.replicate_shuffles,
+            pool: graph.pool,
         })
     }
 }
 
-/// Find the next available task in a set of `ExecutionGraph`s
-fn find_next_task(
-    executor_id: &str,
-    graphs: &mut HashMap<String, ExecutionGraph>,
-) -> Result<Option<(String, Task)>> {
-    for graph in graphs.values_mut() {
-        if let Ok(Some(task)) = graph.pop_next_task(executor_id) {
-            return Ok(Some((executor_id.to_owned(), task)));
+/// Key of the removal of the data of job `job_id` by executor `executor_id`
+fn job_data_removal_key(executor_id: &str, job_id: &str) -> String {
+    format!("{}/{}", executor_id, job_id)
+}
+
+// As with `output_link`, 0 and the empty string are used to encode `None`
+fn encode_task_info(partition: usize, info: TaskInfo) -> protobuf::GraphTaskInfo {
+    protobuf::GraphTaskInfo {
+        partition_id: partition as u32,
+        attempts: info.attempts,
+        executor_id: info.executor_id.unwrap_or_default(),
+        ready_time: info.ready_time.unwrap_or_default(),
+        launch_time: info.launch_time.unwrap_or_default(),
+        end_time: info.end_time.unwrap_or_default(),
+    }
+}
+
+fn decode_task_info(info: protobuf::GraphTaskInfo) -> TaskInfo {
+    let non_zero = |time: u64| if time == 0 { None } else { Some(time) };
+    TaskInfo {
+        attempts: info.attempts,
+        executor_id: if info.executor_id.is_empty() {
+            None
+        } else {
+            Some(info.executor_id)
+        },
+        ready_time: non_zero(info.ready_time),
+        launch_time: non_zero(info.launch_time),
+        end_time: non_zero(info.end_time),
+    }
+}
+
+/// Set the attempt of the task running `plan` on the [ParquetSinkExec] of the plan, so
+/// that the files written by each attempt are committed separately. `None` if the plan
+/// does not write Parquet files.
+fn with_write_attempt(
+    plan: Arc<dyn ExecutionPlan>,
+    attempt: u32,
+) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+    if let Some(sink) = plan.as_any().downcast_ref::<ParquetSinkExec>() {
+        return Ok(Some(Arc::new(sink.clone().with_attempt(attempt))));
+    }
+    let children = plan.children();
+    let mut new_children = Vec::with_capacity(children.len());
+    let mut found = false;
+    for child in children {
+        match with_write_attempt(child.clone(), attempt)? {
+            Some(child) => {
+                found = true;
+                new_children.push(child);
+            }
+            None => new_children.push(child),
+        }
+    }
+    if found {
+        Ok(Some(plan.with_new_children(new_children)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// `plan` without the operators building and applying Bloom filters, for executors
+/// which cannot decode them. Build stages then report no filter, so that the stages of
+/// the probe sides are not filtered either.
+fn without_bloom_filters(plan: Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>> {
+    if let Some(build) = plan.as_any().downcast_ref::<BloomFilterBuildExec>() {
+        return without_bloom_filters(build.input().clone());
+    }
+    if let Some(probe) = plan.as_any().downcast_ref::<BloomFilterExec>() {
+        return without_bloom_filters(probe.input().clone());
+    }
+    let children = plan
+        .children()
+        .into_iter()
+        .map(without_bloom_filters)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(with_new_children_if_necessary(plan, children)?)
+}
+
+/// Only send an executor speaking `protocol` the fields of `task_definition` which it
+/// supports, failing if the task can not run correctly without one of them
+fn gate_task_definition(
+    mut task_definition: TaskDefinition,
+    protocol: &ProtocolInfo,
+) -> Result<TaskDefinition> {
+    let require = |capability: Capability, required: bool| {
+        if required && !protocol.supports(capability) {
+            Err(BallistaError::General(format!(
+                "Task {:?} requires the {} capability, which the executor speaking \
+                protocol version {} does not support",
+                task_definition.task_id,
+                capability.as_str(),
+                protocol.version
+            )))
+        } else {
+            Ok(())
         }
+    };
+    require(
+        Capability::ShuffleEncryption,
+        !task_definition.encryption_key.is_empty(),
+    )?;
+    require(
+        Capability::ObjectStores,
+        !task_definition.object_stores.is_empty(),
+    )?;
+    require(
+        Capability::CachedTables,
+        task_definition
+            .props
+            .iter()
+            .any(|kv| kv.key == BALLISTA_CACHE_TABLE),
+    )?;
+    // Tasks run the same without their trace context
+    if !protocol.supports(Capability::TraceContext) {
+        task_definition.trace_context.clear();
+    }
+    // or without copying their output, which is then lost with the executor
+    if !protocol.supports(Capability::ShuffleReplication) {
+        task_definition.replica = None;
+    }
+    Ok(task_definition)
+}
+
+/// Take up to `n` of the executors of `executor_ids` which belong to `pool` according
+/// to `pools`, from the last ones
+fn take_in_pool(
+    executor_ids: &mut Vec<String>,
+    pools: &HashMap<String, String>,
+    pool: &str,
+    n: usize,
+) -> Vec<String> {
+    let mut taken = vec![];
+    let mut index = executor_ids.len();
+    while index > 0 && taken.len() < n {
+        index -= 1;
+        if pools.get(&executor_ids[index]).map(String::as_str) == Some(pool) {
+            taken.push(executor_ids.remove(index));
+        }
+    }
+    taken
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::backend::standalone::StandaloneClient;
+    use ballista_core::serde::protobuf::{LogicalPlanNode, PhysicalPlanNode};
+    use datafusion::execution::context::default_session_builder;
+
+    #[test]
+    fn gate_task_definitions() -> Result<()> {
+        let task_definition = TaskDefinition {
+            trace_context: vec![KeyValuePair {
+                key: "traceparent".to_owned(),
+                value: "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+                    .to_owned(),
+            }],
+            ..Default::default()
+        };
+        let current = ProtocolInfo::current();
+        let legacy = ProtocolInfo::legacy();
+
+        let gated = gate_task_definition(task_definition.clone(), &current)?;
+        assert_eq!(gated.trace_context.len(), 1);
+        let gated = gate_task_definition(task_definition.clone(), &legacy)?;
+        assert!(gated.trace_context.is_empty());
+
+        let replicated = TaskDefinition {
+            replica: Some(protobuf::ExecutorMetadata {
+                id: "executor-2".to_owned(),
+                ..Default::default()
+            }),
+            ..task_definition.clone()
+        };
+        let gated = gate_task_definition(replicated.clone(), &current)?;
+        assert!(gated.replica.is_some());
+        let gated = gate_task_definition(replicated, &legacy)?;
+        assert!(gated.replica.is_none());
+
+        let encrypted = TaskDefinition {
+            encryption_key: vec![1; 32],
+            ..task_definition
+        };
+        assert!(gate_task_definition(encrypted.clone(), &current).is_ok());
+        assert!(gate_task_definition(encrypted, &legacy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn strip_bloom_filters() -> Result<()> {
+        use datafusion::arrow::datatypes::{DataType, Field, Schema};
+        use datafusion::physical_plan::expressions::Column;
+        use datafusion::physical_plan::memory::MemoryExec;
+
+        let schema =
+            Arc::new(Schema::new(vec![Field::new("id", DataType::Int64, false)]));
+        let scan: Arc<dyn ExecutionPlan> =
+            Arc::new(MemoryExec::try_new(&[vec![]], schema, None)?);
+        let keys = vec![Column::new("id", 0)];
+        let probe: Arc<dyn ExecutionPlan> =
+            Arc::new(BloomFilterExec::new(scan, 1, keys.clone()));
+        let build: Arc<dyn ExecutionPlan> =
+            Arc::new(BloomFilterBuildExec::new(probe, keys, 64));
+
+        let stripped = without_bloom_filters(build)?;
+        assert!(stripped.as_any().is::<MemoryExec>());
+        assert!(!ProtocolInfo::legacy().supports(Capability::BloomFilters));
+        Ok(())
+    }
+
+    #[test]
+    fn take_executors_in_pool() {
+        let mut executor_ids: Vec<String> = vec![
+            "a".to_owned(),
+            "b".to_owned(),
+            "c".to_owned(),
+            "d".to_owned(),
+        ];
+        let pools: HashMap<String, String> = vec![
+            ("a".to_owned(), "batch".to_owned()),
+            ("b".to_owned(), "default".to_owned()),
+            ("c".to_owned(), "batch".to_owned()),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            take_in_pool(&mut executor_ids, &pools, "batch", 1),
+            vec!["c".to_owned()]
+        );
+        assert_eq!(
+            take_in_pool(&mut executor_ids, &pools, "batch", 5),
+            vec!["a".to_owned()]
+        );
+        assert!(take_in_pool(&mut executor_ids, &pools, "gpu", 5).is_empty());
+        assert_eq!(executor_ids, vec!["b".to_owned(), "d".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn persist_job_data_removals() -> Result<()> {
+        let state: Arc<dyn StateBackendClient> =
+            Arc::new(StandaloneClient::try_new_temporary()?);
+        let task_manager: TaskManager<LogicalPlanNode, PhysicalPlanNode> =
+            TaskManager::new(
+                state.clone(),
+                ExecutorManager::new(state.clone()),
+                default_session_builder,
+                BallistaCodec::default(),
+            );
+        task_manager
+            .queue_job_data_removal("executor-1", "job-1")
+            .await?;
+        task_manager
+            .queue_job_data_removal("executor-1", "job-2")
+            .await?;
+        task_manager
+            .queue_job_data_removal("executor-2", "job-1")
+            .await?;
+
+        // Any scheduler sharing the state backend hands out the removals
+        let other: TaskManager<LogicalPlanNode, PhysicalPlanNode> = TaskManager::new(
+            state.clone(),
+            ExecutorManager::new(state),
+            default_session_builder,
+            BallistaCodec::default(),
+        );
+        let mut job_ids = other.take_job_data_removals("executor-1").await?;
+        job_ids.sort();
+        assert_eq!(job_ids, vec!["job-1".to_owned(), "job-2".to_owned()]);
+        assert!(task_manager
+            .take_job_data_removals("executor-1")
+            .await?
+            .is_empty());
+
+        // The removals of lost executors are dropped
+        task_manager.forget_job_data_removals("executor-2").await?;
+        assert!(other.take_job_data_removals("executor-2").await?.is_empty());
+        Ok(())
     }
-    Ok(None)
 }
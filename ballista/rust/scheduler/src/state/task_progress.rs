@@ -0,0 +1,235 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The progress of the tasks running on the executors.
+//!
+//! Executors report the rows produced so far by each of their running tasks with their
+//! heartbeats, or with their polls for work under pull-based scheduling. A task whose
+//! rows did not change for longer than the stall timeout of its job is considered hung
+//! rather than slow, and its executor is told to kill it in the response to its next
+//! heartbeat or poll.
+//!
+//! Progress is kept in the memory of the scheduler the executors report to.
+
+use ballista_core::serde::protobuf::{PartitionId, TaskAttempt, TaskProgress};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+/// Job ID, stage ID and partition ID of a task
+type TaskKey = (String, u32, u32);
+
+struct Progress {
+    executor_id: String,
+    attempt: u32,
+    output_rows: u64,
+    /// Time the rows of the task last changed, in milliseconds since the epoch
+    last_progress: u64,
+    /// Whether the executor was told to kill the task
+    killed: bool,
+}
+
+#[derive(Default)]
+pub struct TaskProgressTracker {
+    inner: Mutex<Tracker>,
+}
+
+#[derive(Default)]
+struct Tracker {
+    /// The progress of the running tasks
+    tasks: HashMap<TaskKey, Progress>,
+    /// The tasks executors are still to be told to kill, by executor ID
+    kills: HashMap<String, Vec<TaskAttempt>>,
+}
+
+impl TaskProgressTracker {
+    /// Record `progress`, the progress of all the tasks running on executor
+    /// `executor_id` at `now`, in milliseconds since the epoch. The tasks of the
+    /// executor which are not reported any more are no longer running.
+    pub fn record(&self, executor_id: &str, progress: Vec<TaskProgress>, now: u64) {
+        let mut inner = self.inner.lock();
+        let reported: HashSet<TaskKey> = progress
+            .iter()
+            .filter_map(|task| task.task_id.as_ref().map(task_key))
+            .collect();
+        inner.tasks.retain(|key, task| {
+            task.executor_id != executor_id || reported.contains(key)
+        });
+
+        for task in progress {
+            let key = match task.task_id.as_ref() {
+                Some(task_id) => task_key(task_id),
+                None => continue,
+            };
+            match inner.tasks.get_mut(&key) {
+                Some(known)
+                    if known.executor_id == executor_id
+                        && known.attempt == task.attempt =>
+                {
+                    if known.output_rows != task.output_rows {
+                        known.output_rows = task.output_rows;
+                        known.last_progress = now;
+                    }
+                }
+                _ => {
+                    inner.tasks.insert(
+                        key,
+                        Progress {
+                            executor_id: executor_id.to_owned(),
+                            attempt: task.attempt,
+                            output_rows: task.output_rows,
+                            last_progress: now,
+                            killed: false,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Have the executors kill the tasks whose rows did not change at `now` for longer
+    /// than the stall timeout of their job, as given by `stall_timeout`. Returns the
+    /// tasks to kill along with their executors.
+    pub fn kill_stalled(
+        &self,
+        stall_timeout: impl Fn(&str) -> Option<Duration>,
+        now: u64,
+    ) -> Vec<(String, TaskAttempt)> {
+        let mut inner = self.inner.lock();
+        let mut stalled = vec![];
+        for ((job_id, stage_id, partition_id), task) in inner.tasks.iter_mut() {
+            if task.killed {
+                continue;
+            }
+            let timeout = match stall_timeout(job_id) {
+                Some(timeout) => timeout,
+                None => continue,
+            };
+            if now.saturating_sub(task.last_progress) > timeout.as_millis() as u64 {
+                task.killed = true;
+                stalled.push((
+                    task.executor_id.clone(),
+                    TaskAttempt {
+                        task_id: Some(PartitionId {
+                            job_id: job_id.clone(),
+                            stage_id: *stage_id,
+                            partition_id: *partition_id,
+                        }),
+                        attempt: task.attempt,
+                    },
+                ));
+            }
+        }
+        for (executor_id, task) in &stalled {
+            inner
+                .kills
+                .entry(executor_id.clone())
+                .or_default()
+                .push(task.clone());
+        }
+        stalled
+    }
+
+    /// Take the tasks executor `executor_id` has to kill
+    pub fn take_kills(&self, executor_id: &str) -> Vec<TaskAttempt> {
+        self.inner
+            .lock()
+            .kills
+            .remove(executor_id)
+            .unwrap_or_default()
+    }
+
+    /// Forget the tasks of job `job_id`, which finished
+    pub fn remove_job(&self, job_id: &str) {
+        let mut inner = self.inner.lock();
+        inner.tasks.retain(|(job, _, _), _| job != job_id);
+        for tasks in inner.kills.values_mut() {
+            tasks.retain(|task| {
+                task.task_id
+                    .as_ref()
+                    .map_or(false, |id| id.job_id != job_id)
+            });
+        }
+        inner.kills.retain(|_, tasks| !tasks.is_empty());
+    }
+}
+
+fn task_key(task_id: &PartitionId) -> TaskKey {
+    (
+        task_id.job_id.clone(),
+        task_id.stage_id,
+        task_id.partition_id,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(partition_id: u32, output_rows: u64) -> TaskProgress {
+        TaskProgress {
+            task_id: Some(PartitionId {
+                job_id: "job".to_owned(),
+                stage_id: 1,
+                partition_id,
+            }),
+            attempt: 1,
+            output_rows,
+        }
+    }
+
+    #[test]
+    fn kill_tasks_without_progress() {
+        let tracker = TaskProgressTracker::default();
+        let timeout = |_: &str| Some(Duration::from_secs(10));
+        tracker.record("executor-1", vec![progress(0, 0), progress(1, 0)], 0);
+
+        // Task 0 keeps producing rows while task 1 is hung
+        tracker.record("executor-1", vec![progress(0, 100), progress(1, 0)], 8_000);
+        assert!(tracker.kill_stalled(timeout, 10_000).is_empty());
+        let stalled = tracker.kill_stalled(timeout, 10_001);
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].0, "executor-1");
+        assert_eq!(stalled[0].1.task_id.as_ref().unwrap().partition_id, 1);
+
+        // Tasks are only killed once
+        tracker.record("executor-1", vec![progress(0, 200), progress(1, 0)], 15_000);
+        assert!(tracker.kill_stalled(timeout, 20_000).is_empty());
+        assert_eq!(tracker.take_kills("executor-1"), vec![stalled[0].1.clone()]);
+        assert!(tracker.take_kills("executor-1").is_empty());
+
+        // Jobs without a stall timeout are never killed
+        assert!(tracker.kill_stalled(|_| None, 100_000).is_empty());
+    }
+
+    #[test]
+    fn forget_finished_tasks() {
+        let tracker = TaskProgressTracker::default();
+        let timeout = |_: &str| Some(Duration::from_secs(10));
+        tracker.record("executor-1", vec![progress(0, 0)], 0);
+        tracker.record("executor-2", vec![progress(1, 0)], 0);
+
+        // Task 0 finished, task 1 runs on an executor which did not report
+        tracker.record("executor-1", vec![], 5_000);
+        let stalled = tracker.kill_stalled(timeout, 20_000);
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].0, "executor-2");
+
+        tracker.remove_job("job");
+        assert!(tracker.take_kills("executor-2").is_empty());
+    }
+}
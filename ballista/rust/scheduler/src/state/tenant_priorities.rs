@@ -0,0 +1,100 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The highest priority the jobs of each tenant may run at.
+//!
+//! Jobs set their priority with `ballista.job.priority`, which orders their fetches of
+//! shuffle partitions. The priority of a job is lowered to the cap of its tenant, or to
+//! the cap of `*` for the tenants without one. Priorities are not capped if no cap is
+//! set.
+
+use ballista_core::error::{BallistaError, Result};
+use std::collections::HashMap;
+
+/// Tenant whose cap applies to the tenants without one
+const OTHER_TENANTS: &str = "*";
+
+/// The highest priority of the jobs of each tenant
+#[derive(Clone, Debug, Default)]
+pub struct TenantPriorities {
+    caps: HashMap<String, u32>,
+}
+
+impl TenantPriorities {
+    /// Parse `tenant=priority` pairs separated by commas, e.g. `etl=10,alice=5,*=0`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut caps = HashMap::new();
+        for pair in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            match pair
+                .split_once('=')
+                .map(|(tenant, cap)| (tenant.trim(), cap.trim().parse::<u32>()))
+            {
+                Some((tenant, Ok(cap))) if !tenant.is_empty() => {
+                    caps.insert(tenant.to_owned(), cap);
+                }
+                _ => {
+                    return Err(BallistaError::General(format!(
+                        "Invalid tenant priority '{}', expected tenant=priority",
+                        pair
+                    )))
+                }
+            }
+        }
+        Ok(Self { caps })
+    }
+
+    /// The priority of a job of `tenant` which asked to run at `requested`
+    pub fn job_priority(&self, requested: u32, tenant: Option<&str>) -> u32 {
+        let cap = tenant
+            .and_then(|tenant| self.caps.get(tenant))
+            .or_else(|| self.caps.get(OTHER_TENANTS));
+        match cap {
+            Some(cap) => requested.min(*cap),
+            None => requested,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cap_job_priorities() -> Result<()> {
+        let priorities = TenantPriorities::parse("etl=10, alice = 5, *=1")?;
+        assert_eq!(priorities.job_priority(20, Some("etl")), 10);
+        assert_eq!(priorities.job_priority(3, Some("alice")), 3);
+        assert_eq!(priorities.job_priority(9, Some("alice")), 5);
+        assert_eq!(priorities.job_priority(9, Some("bob")), 1);
+        assert_eq!(priorities.job_priority(9, None), 1);
+
+        let uncapped = TenantPriorities::parse("")?;
+        assert_eq!(uncapped.job_priority(9, Some("bob")), 9);
+        assert_eq!(
+            TenantPriorities::parse("etl=10")?.job_priority(9, Some("bob")),
+            9
+        );
+        assert!(TenantPriorities::parse("etl").is_err());
+        assert!(TenantPriorities::parse("etl=high").is_err());
+        assert!(TenantPriorities::parse("=3").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! The resources used by the jobs of each tenant.
+//!
+//! The tenant of a job is the principal which submitted it when client authentication
+//! is enabled, or else the user set by `ballista.user`. The usage of a job is added to
+//! the usage of its tenant once it completes or fails.
+//!
+//! Usage is kept in the memory of the scheduler the jobs were submitted to, from the
+//! time it started.
+
+use ballista_core::serde::protobuf::JobResourceUsage;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+/// Tenant of the jobs submitted without a principal or user
+pub const DEFAULT_TENANT: &str = "default";
+
+/// Resources used by the finished jobs of a tenant
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TenantUsage {
+    /// Number of jobs which completed or failed
+    pub jobs: u64,
+    pub usage: JobResourceUsage,
+}
+
+impl TenantUsage {
+    fn add(&mut self, usage: &JobResourceUsage) {
+        self.jobs += 1;
+        self.usage.slot_millis += usage.slot_millis;
+        self.usage.bytes_shuffled += usage.bytes_shuffled;
+        self.usage.bytes_scanned += usage.bytes_scanned;
+    }
+}
+
+#[derive(Default)]
+pub struct TenantUsages {
+    inner: Mutex<Usages>,
+}
+
+#[derive(Default)]
+struct Usages {
+    /// The tenants of the running jobs by job ID
+    tenants: HashMap<String, String>,
+    /// The usage of each tenant by tenant
+    usage: HashMap<String, TenantUsage>,
+}
+
+impl TenantUsages {
+    /// Account the resources used by job `job_id` to `tenant`, or to the default tenant
+    pub fn register(&self, job_id: &str, tenant: Option<String>) {
+        self.inner.lock().tenants.insert(
+            job_id.to_owned(),
+            tenant.unwrap_or_else(|| DEFAULT_TENANT.to_owned()),
+        );
+    }
+
+    /// Whether the usage of job `job_id` is still to be recorded
+    pub fn is_registered(&self, job_id: &str) -> bool {
+        self.inner.lock().tenants.contains_key(job_id)
+    }
+
+    /// Add `usage`, used by job `job_id` which finished, to the usage of its tenant.
+    /// Jobs are only accounted once.
+    pub fn record(&self, job_id: &str, usage: &JobResourceUsage) {
+        let mut inner = self.inner.lock();
+        if let Some(tenant) = inner.tenants.remove(job_id) {
+            inner.usage.entry(tenant).or_default().add(usage);
+        }
+    }
+
+    /// The usage of every tenant, ordered by tenant
+    pub fn usage(&self) -> Vec<(String, TenantUsage)> {
+        let mut usage: Vec<_> = self
+            .inner
+            .lock()
+            .usage
+            .iter()
+            .map(|(tenant, usage)| (tenant.clone(), usage.clone()))
+            .collect();
+        usage.sort_by(|(a, _), (b, _)| a.cmp(b));
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(slot_millis: u64) -> JobResourceUsage {
+        JobResourceUsage {
+            slot_millis,
+            bytes_shuffled: 100,
+            bytes_scanned: 1_000,
+        }
+    }
+
+    #[test]
+    fn aggregate_usage_by_tenant() {
+        let usages = TenantUsages::default();
+        usages.register("job-1", Some("alice".to_owned()));
+        usages.register("job-2", Some("alice".to_owned()));
+        usages.register("job-3", None);
+        assert!(usages.is_registered("job-1"));
+
+        usages.record("job-1", &usage(10));
+        usages.record("job-2", &usage(20));
+        usages.record("job-3", &usage(5));
+        // Finished jobs are not accounted again
+        usages.record("job-1", &usage(10));
+        assert!(!usages.is_registered("job-1"));
+
+        assert_eq!(
+            usages.usage(),
+            vec![
+                (
+                    "alice".to_owned(),
+                    TenantUsage {
+                        jobs: 2,
+                        usage: JobResourceUsage {
+                            slot_millis: 30,
+                            bytes_shuffled: 200,
+                            bytes_scanned: 2_000,
+                        },
+                    }
+                ),
+                (
+                    DEFAULT_TENANT.to_owned(),
+                    TenantUsage {
+                        jobs: 1,
+                        usage: usage(5),
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Continuous queries over Kafka topics, executed as micro-batches.
+//!
+//! Every interval, the scheduler reads the latest offsets of the partitions of the topic
+//! of a [StreamingQuery] and submits a job running its SQL over the records written
+//! since the previous micro-batch, which the query reads as a table. The job is recorded
+//! as pending in the checkpoint of the query in the state backend, so that schedulers
+//! sharing the backend do not run the same micro-batch, and the lock of the checkpoint
+//! is released while the job runs. Once the job has completed, the offsets it read up
+//! to are checkpointed along with the ID of the job, whose output holds the results of
+//! the micro-batch, by whichever scheduler sees it first. A micro-batch which fails is
+//! retried from the same offsets, so the records of a topic are processed at least once.
+
+use crate::scheduler_server::SchedulerServer;
+use crate::state::backend::{Keyspace, StateBackendClient};
+use crate::state::{table_catalog, with_lock};
+use ballista_core::config::BallistaConfig;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::kafka::{partition_offsets, KafkaOffsetRange, KafkaTable};
+use ballista_core::serde::protobuf::job_status;
+use ballista_core::serde::AsExecutionPlan;
+use datafusion::prelude::SessionContext;
+use datafusion_proto::logical_plan::AsLogicalPlan;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Interval between two checks of the status of the job of a micro-batch
+const JOB_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Number of checks of the status of the job of a micro-batch which may not find it
+/// before it is considered lost
+const MISSING_JOB_POLLS: usize = 20;
+
+/// A SQL query run continuously over the records of a Kafka topic
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StreamingQuery {
+    /// Unique name of the query, which identifies its checkpoint
+    pub name: String,
+    pub brokers: Vec<String>,
+    pub topic: String,
+    /// Name of the table of the records of a micro-batch in `sql`
+    #[serde(default = "default_table")]
+    pub table: String,
+    pub sql: String,
+    /// Time between the starts of two micro-batches
+    #[serde(default = "default_interval_ms")]
+    pub interval_ms: u64,
+    /// Maximum number of records of a partition read by a micro-batch, to bound the
+    /// size of the micro-batches catching up with a backlog
+    #[serde(default)]
+    pub max_records_per_partition: Option<i64>,
+    /// Time after which the job of a micro-batch is cancelled, so that the micro-batch
+    /// runs again
+    #[serde(default = "default_job_timeout_ms")]
+    pub job_timeout_ms: u64,
+}
+
+fn default_table() -> String {
+    "kafka".to_owned()
+}
+
+fn default_interval_ms() -> u64 {
+    10_000
+}
+
+fn default_job_timeout_ms() -> u64 {
+    3_600_000
+}
+
+impl StreamingQuery {
+    /// Read the queries of a JSON file holding an array of them
+    pub fn from_file(path: &str) -> Result<Vec<Self>> {
+        let file = std::fs::File::open(path)?;
+        serde_json::from_reader(file).map_err(|e| {
+            BallistaError::General(format!(
+                "Could not read streaming queries from {}: {}",
+                path, e
+            ))
+        })
+    }
+}
+
+/// Progress of a [StreamingQuery], saved when a micro-batch is submitted and once it
+/// completed
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamCheckpoint {
+    /// Number of completed micro-batches
+    pub batch: u64,
+    /// Offset of the next record to read of each partition
+    pub offsets: BTreeMap<i32, i64>,
+    /// ID of the job of the last completed micro-batch
+    pub last_job_id: Option<String>,
+    /// Micro-batch submitted and not completed yet
+    pub pending: Option<PendingBatch>,
+}
+
+/// Job of a micro-batch, with the ranges of records it reads
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PendingBatch {
+    pub job_id: String,
+    pub ranges: Vec<KafkaOffsetRange>,
+}
+
+impl StreamCheckpoint {
+    pub async fn load(backend: &dyn StateBackendClient, name: &str) -> Result<Self> {
+        let value = backend.get(Keyspace::Streams, name).await?;
+        if value.is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_slice(&value).map_err(|e| {
+            BallistaError::Internal(format!(
+                "Could not deserialize checkpoint of streaming query {}: {}",
+                name, e
+            ))
+        })
+    }
+
+    pub async fn save(&self, backend: &dyn StateBackendClient, name: &str) -> Result<()> {
+        let value = serde_json::to_vec(self).map_err(|e| {
+            BallistaError::Internal(format!(
+                "Could not serialize checkpoint of streaming query {}: {}",
+                name, e
+            ))
+        })?;
+        backend.put(Keyspace::Streams, name.to_owned(), value).await
+    }
+
+    /// Move on past the records read by the pending micro-batch, once its job completed
+    pub fn commit(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            self.batch += 1;
+            for range in pending.ranges {
+                self.offsets.insert(range.partition, range.end_offset);
+            }
+            self.last_job_id = Some(pending.job_id);
+        }
+    }
+
+    /// The ranges of records of the next micro-batch, given the earliest and latest
+    /// offsets of the partitions of the topic. Partitions without new records are
+    /// skipped, and records deleted by retention before they were read are lost.
+    pub fn next_ranges(
+        &self,
+        partition_offsets: &BTreeMap<i32, (i64, i64)>,
+        max_records_per_partition: Option<i64>,
+    ) -> Vec<KafkaOffsetRange> {
+        partition_offsets
+            .iter()
+            .filter_map(|(partition, (earliest, latest))| {
+                let start_offset = self
+                    .offsets
+                    .get(partition)
+                    .map_or(*earliest, |offset| (*offset).max(*earliest));
+                let end_offset = match max_records_per_partition {
+                    Some(max) => (*latest).min(start_offset.saturating_add(max)),
+                    None => *latest,
+                };
+                (start_offset < end_offset).then(|| KafkaOffsetRange {
+                    partition: *partition,
+                    start_offset,
+                    end_offset,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Run the micro-batches of `query` until the scheduler stops
+pub(crate) async fn run<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
+    server: SchedulerServer<T, U>,
+    query: StreamingQuery,
+) {
+    info!(
+        "Starting streaming query {} over Kafka topic {}",
+        query.name, query.topic
+    );
+    let mut interval = tokio::time::interval(Duration::from_millis(query.interval_ms));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The session of the micro-batches, created once for the whole query
+    let mut session_ctx = None;
+    loop {
+        interval.tick().await;
+        if let Err(e) = run_batch(&server, &query, &mut session_ctx).await {
+            warn!(
+                "Micro-batch of streaming query {} failed: {}",
+                query.name, e
+            );
+        }
+    }
+}
+
+/// Run the next micro-batch of `query`, if new records were written to its topic and
+/// no micro-batch is pending, in `session_ctx`, which is created on first use
+async fn run_batch<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
+    server: &SchedulerServer<T, U>,
+    query: &StreamingQuery,
+    session_ctx: &mut Option<Arc<SessionContext>>,
+) -> Result<()> {
+    let session_ctx = match session_ctx.clone() {
+        Some(session_ctx) => session_ctx,
+        None => session_ctx.insert(create_session(server).await?).clone(),
+    };
+    let backend = server.state.backend();
+    // Schedulers sharing the state backend take turns to submit the micro-batches, and
+    // only hold the lock while the checkpoint is updated, so that it does not need to
+    // outlive the jobs
+    let lock = backend.lock(Keyspace::Streams, &query.name).await?;
+    let job_id = with_lock(lock, submit_batch(server, query, &session_ctx)).await?;
+    let job_id = match job_id {
+        Some(job_id) => job_id,
+        None => return Ok(()),
+    };
+    let timeout = Duration::from_millis(query.job_timeout_ms);
+    if let Err(e) = await_job(server, &job_id, timeout).await {
+        // Cancelling the job fails it, so the next micro-batch runs it again
+        if let Err(e) = server.cancel_job(&job_id, None).await {
+            warn!("Could not cancel job {}: {}", job_id, e);
+        }
+        return Err(e);
+    }
+
+    let lock = backend.lock(Keyspace::Streams, &query.name).await?;
+    with_lock(lock, async {
+        let mut checkpoint =
+            StreamCheckpoint::load(backend.as_ref(), &query.name).await?;
+        // Unless another scheduler saw the job complete first
+        if checkpoint.pending.as_ref().map(|pending| &pending.job_id) == Some(&job_id) {
+            checkpoint.commit();
+            checkpoint.save(backend.as_ref(), &query.name).await?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Submit the job of the next micro-batch of `query` in `session_ctx`, recording it as
+/// pending in the checkpoint, after committing or dropping the pending micro-batch if
+/// its job completed or failed. Returns the ID of the submitted job. The lock of the
+/// checkpoint must be held.
+async fn submit_batch<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
+    server: &SchedulerServer<T, U>,
+    query: &StreamingQuery,
+    session_ctx: &Arc<SessionContext>,
+) -> Result<Option<String>> {
+    let backend = server.state.backend();
+    let mut checkpoint = StreamCheckpoint::load(backend.as_ref(), &query.name).await?;
+    if let Some(pending) = &checkpoint.pending {
+        let status = server
+            .state
+            .task_manager
+            .get_job_status(&pending.job_id)
+            .await?
+            .and_then(|status| status.status);
+        match status {
+            Some(job_status::Status::Completed(_)) => checkpoint.commit(),
+            Some(job_status::Status::Failed(_)) | None => {
+                warn!(
+                    "Job {} of micro-batch {} of streaming query {} did not complete, \
+                     running the micro-batch again",
+                    pending.job_id, checkpoint.batch, query.name
+                );
+                checkpoint.pending = None;
+            }
+            // Still running, possibly submitted by another scheduler
+            _ => return Ok(None),
+        }
+    }
+
+    let offsets = partition_offsets(&query.brokers, &query.topic).await?;
+    let ranges = checkpoint.next_ranges(&offsets, query.max_records_per_partition);
+    if ranges.is_empty() {
+        return checkpoint
+            .save(backend.as_ref(), &query.name)
+            .await
+            .map(|_| None);
+    }
+
+    session_ctx.deregister_table(query.table.as_str())?;
+    session_ctx.register_table(
+        query.table.as_str(),
+        Arc::new(KafkaTable::new(
+            query.brokers.clone(),
+            query.topic.clone(),
+            ranges.clone(),
+        )),
+    )?;
+    let plan = session_ctx.sql(&query.sql).await?.to_logical_plan()?;
+    let job_id = server.submit_job(session_ctx.clone(), plan).await?;
+    info!(
+        "Submitted job {} for micro-batch {} of streaming query {}",
+        job_id, checkpoint.batch, query.name
+    );
+    checkpoint.pending = Some(PendingBatch {
+        job_id: job_id.clone(),
+        ranges,
+    });
+    checkpoint.save(backend.as_ref(), &query.name).await?;
+    Ok(Some(job_id))
+}
+
+/// Create the session of the micro-batches of a query, which reads the catalogs of the
+/// scheduler
+async fn create_session<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
+    server: &SchedulerServer<T, U>,
+) -> Result<Arc<SessionContext>> {
+    let session_ctx = server
+        .state
+        .session_manager
+        .create_session(&BallistaConfig::new()?)
+        .await?;
+    for (name, catalog) in &server.state.catalogs {
+        session_ctx.register_catalog(name, catalog.clone());
+    }
+    session_ctx.register_catalog(
+        table_catalog::CATALOG,
+        server.state.table_catalog.provider(
+            server.state.table_formats.clone(),
+            session_ctx.runtime_env(),
+        ),
+    );
+    Ok(session_ctx)
+}
+
+/// Wait at most `timeout` for job `job_id` to complete
+async fn await_job<T: 'static + AsLogicalPlan, U: 'static + AsExecutionPlan>(
+    server: &SchedulerServer<T, U>,
+    job_id: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut missing_polls = 0;
+    loop {
+        let status = server
+            .state
+            .task_manager
+            .get_job_status(job_id)
+            .await?
+            .and_then(|status| status.status);
+        match status {
+            Some(job_status::Status::Completed(_)) => return Ok(()),
+            Some(job_status::Status::Failed(failed)) => {
+                return Err(BallistaError::General(format!(
+                    "Job {} failed: {}",
+                    job_id, failed.error
+                )))
+            }
+            None => {
+                missing_polls += 1;
+                if missing_polls > MISSING_JOB_POLLS {
+                    return Err(BallistaError::General(format!(
+                        "Job {} not found",
+                        job_id
+                    )));
+                }
+            }
+            _ => missing_polls = 0,
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(BallistaError::General(format!(
+                "Job {} did not complete within {:?}",
+                job_id, timeout
+            )));
+        }
+        tokio::time::sleep(JOB_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn micro_batch_ranges() {
+        let range = |partition, start_offset, end_offset| KafkaOffsetRange {
+            partition,
+            start_offset,
+            end_offset,
+        };
+        let mut offsets = BTreeMap::new();
+        offsets.insert(0, (0, 100));
+        offsets.insert(1, (50, 80));
+        offsets.insert(2, (0, 30));
+
+        let checkpoint = StreamCheckpoint::default();
+        assert_eq!(
+            checkpoint.next_ranges(&offsets, None),
+            vec![range(0, 0, 100), range(1, 50, 80), range(2, 0, 30)]
+        );
+
+        let mut checkpoint = StreamCheckpoint::default();
+        checkpoint.offsets.insert(0, 60);
+        // Records 20 to 49 of partition 1 were deleted before they were read
+        checkpoint.offsets.insert(1, 20);
+        checkpoint.offsets.insert(2, 30);
+        assert_eq!(
+            checkpoint.next_ranges(&offsets, Some(25)),
+            vec![range(0, 60, 85), range(1, 50, 75)]
+        );
+    }
+
+    #[test]
+    fn commit_pending_batch() {
+        let mut checkpoint = StreamCheckpoint::default();
+        checkpoint.offsets.insert(0, 10);
+        checkpoint.offsets.insert(1, 20);
+        checkpoint.pending = Some(PendingBatch {
+            job_id: "job".to_owned(),
+            ranges: vec![KafkaOffsetRange {
+                partition: 1,
+                start_offset: 20,
+                end_offset: 35,
+            }],
+        });
+
+        // Checkpoints are read back with their pending micro-batch
+        let value = serde_json::to_vec(&checkpoint).unwrap();
+        let read: StreamCheckpoint = serde_json::from_slice(&value).unwrap();
+        assert_eq!(read, checkpoint);
+
+        checkpoint.commit();
+        assert_eq!(checkpoint.batch, 1);
+        assert_eq!(checkpoint.offsets.get(&0), Some(&10));
+        assert_eq!(checkpoint.offsets.get(&1), Some(&35));
+        assert_eq!(checkpoint.last_job_id.as_deref(), Some("job"));
+        assert!(checkpoint.pending.is_none());
+
+        // Without a pending micro-batch, nothing is committed
+        checkpoint.commit();
+        assert_eq!(checkpoint.batch, 1);
+    }
+
+    #[test]
+    fn streaming_query_defaults() {
+        let query: StreamingQuery = serde_json::from_str(
+            r#"{
+                "name": "clicks",
+                "brokers": ["kafka:9092"],
+                "topic": "clicks",
+                "sql": "SELECT count(*) FROM kafka"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(query.table, "kafka");
+        assert_eq!(query.interval_ms, 10_000);
+        assert_eq!(query.max_records_per_partition, None);
+    }
+}
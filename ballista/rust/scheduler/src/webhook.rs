@@ -0,0 +1,283 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+//! Job completion webhooks.
+//!
+//! When a job completes or fails, the scheduler POSTs a [JobNotification] to the callback
+//! URL given with the job (the `ballista.job.callback_url` setting) or, if there is none,
+//! to the webhook URL configured for the scheduler. Only plain `http` URLs are supported.
+//!
+//! Clients may only give callback URLs on the hosts allowed by the operator, so they can
+//! not make the scheduler send requests to arbitrary hosts of its network. Per-job
+//! callback URLs are kept in memory until the job finishes, so they are lost if the
+//! scheduler restarts while the job is running.
+
+use crate::api::model::{job_response, stage_responses, JobResponse, StageResponse};
+use crate::state::execution_graph::ExecutionGraph;
+use ballista_core::error::{BallistaError, Result};
+use ballista_core::serde::protobuf::JobStatus;
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Request, Uri};
+use log::{debug, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Number of times a notification is sent before giving up
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Payload POSTed to the callback URL of a finished job
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JobNotification {
+    pub job: JobResponse,
+    /// Stages of the job along with their output statistics. Empty if the job failed
+    /// during planning.
+    pub stages: Vec<StageResponse>,
+    /// Time the job finished, in milliseconds since the epoch
+    pub timestamp: u64,
+}
+
+impl JobNotification {
+    pub fn new(job_id: &str, status: &JobStatus, graph: Option<&ExecutionGraph>) -> Self {
+        Self {
+            job: job_response(job_id.to_owned(), status, graph),
+            stages: graph.map(stage_responses).unwrap_or_default(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_millis() as u64,
+        }
+    }
+}
+
+/// Sends a [JobNotification] to the callback URL of every finished job
+pub struct JobNotifier {
+    /// URL notified about jobs submitted without a callback URL
+    default_url: Option<String>,
+    /// Hosts the callback URLs of jobs may point to, as `host` or `host:port`
+    callback_hosts: Vec<String>,
+    /// Callback URLs of running jobs, by job ID
+    callbacks: Mutex<HashMap<String, String>>,
+    client: Client<HttpConnector>,
+}
+
+impl JobNotifier {
+    pub fn new(default_url: Option<String>, callback_hosts: Vec<String>) -> Self {
+        Self {
+            default_url,
+            callback_hosts,
+            callbacks: Mutex::new(HashMap::new()),
+            client: Client::new(),
+        }
+    }
+
+    pub fn default_url(&self) -> Option<&str> {
+        self.default_url.as_deref()
+    }
+
+    pub fn callback_hosts(&self) -> &[String] {
+        &self.callback_hosts
+    }
+
+    /// Notify `url` rather than the default URL once the job finishes. Fails if `url`
+    /// is not an `http` URL on one of the allowed hosts.
+    pub fn register(&self, job_id: &str, url: String) -> Result<()> {
+        let uri: Uri = url.parse().map_err(|e| {
+            BallistaError::General(format!("Invalid callback URL {}: {:?}", url, e))
+        })?;
+        if uri.scheme_str() != Some("http") {
+            return Err(BallistaError::General(format!(
+                "Callback URL {} is not an http URL",
+                url
+            )));
+        }
+        let allowed = match uri.authority() {
+            Some(authority) => self.callback_hosts.iter().any(|host| {
+                host.eq_ignore_ascii_case(authority.host())
+                    || host.eq_ignore_ascii_case(authority.as_str())
+            }),
+            None => false,
+        };
+        if !allowed {
+            return Err(BallistaError::General(format!(
+                "Callback URL {} is not on one of the hosts allowed by the scheduler",
+                url
+            )));
+        }
+        self.callbacks.lock().insert(job_id.to_owned(), url);
+        Ok(())
+    }
+
+    /// Remove the callback of a finished job, returning the URL to notify if any.
+    /// Must be called once the job finishes, whether it is notified about or not.
+    pub fn take_url(&self, job_id: &str) -> Option<String> {
+        self.callbacks
+            .lock()
+            .remove(job_id)
+            .or_else(|| self.default_url.clone())
+    }
+
+    /// Send `notification` to `url` in the background. Failed deliveries are retried a
+    /// few times and then logged.
+    pub fn notify(&self, url: String, notification: JobNotification) {
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            let job_id = notification.job.job_id.clone();
+            if let Err(e) = send_with_retries(&client, &url, &notification).await {
+                warn!(
+                    "Failed to notify {} about completion of job {}: {:?}",
+                    url, job_id, e
+                );
+            }
+        });
+    }
+}
+
+async fn send_with_retries(
+    client: &Client<HttpConnector>,
+    url: &str,
+    notification: &JobNotification,
+) -> Result<()> {
+    let uri: Uri = url.parse().map_err(|e| {
+        BallistaError::General(format!("Invalid callback URL {}: {:?}", url, e))
+    })?;
+    let body = serde_json::to_vec(notification).map_err(|e| {
+        BallistaError::Internal(format!("Could not serialize notification: {:?}", e))
+    })?;
+
+    let mut attempt = 1;
+    loop {
+        match send(client, uri.clone(), body.clone()).await {
+            Ok(()) => {
+                debug!("Notified {} about job {}", url, notification.job.job_id);
+                return Ok(());
+            }
+            Err(e) if attempt >= MAX_ATTEMPTS => return Err(e),
+            Err(e) => {
+                debug!(
+                    "Attempt {} to notify {} failed, retrying: {:?}",
+                    attempt, url, e
+                );
+                tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+async fn send(client: &Client<HttpConnector>, uri: Uri, body: Vec<u8>) -> Result<()> {
+    let request = Request::post(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .map_err(|e| BallistaError::General(format!("Invalid request: {:?}", e)))?;
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| BallistaError::General(format!("Request failed: {:?}", e)))?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(BallistaError::General(format!(
+            "Unexpected response status {}",
+            response.status()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JobNotification, JobNotifier};
+    use ballista_core::serde::protobuf::{job_status, FailedJob, JobStatus};
+
+    #[test]
+    fn callback_url_overrides_default() {
+        let notifier = JobNotifier::new(
+            Some("http://default/hook".to_owned()),
+            vec!["job-1".to_owned()],
+        );
+        notifier
+            .register("job-1", "http://job-1/hook".to_owned())
+            .unwrap();
+
+        assert_eq!(
+            notifier.take_url("job-2").as_deref(),
+            Some("http://default/hook")
+        );
+        assert_eq!(
+            notifier.take_url("job-1").as_deref(),
+            Some("http://job-1/hook")
+        );
+        // The callback is only used once
+        assert_eq!(
+            notifier.take_url("job-1").as_deref(),
+            Some("http://default/hook")
+        );
+
+        let notifier = JobNotifier::new(None, vec!["job-1".to_owned()]);
+        assert!(notifier.take_url("job-1").is_none());
+        notifier
+            .register("job-1", "http://job-1/hook".to_owned())
+            .unwrap();
+        assert!(notifier.take_url("job-1").is_some());
+        assert!(notifier.take_url("job-1").is_none());
+    }
+
+    #[test]
+    fn callback_hosts() {
+        let notifier = JobNotifier::new(
+            None,
+            vec!["hooks.example.com".to_owned(), "10.0.0.1:8080".to_owned()],
+        );
+        for url in [
+            "http://hooks.example.com/job",
+            "http://HOOKS.example.com:9000/job",
+            "http://10.0.0.1:8080/job",
+        ] {
+            assert!(notifier.register("job", url.to_owned()).is_ok(), "{}", url);
+        }
+        for url in [
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1:9090/job",
+            "https://hooks.example.com/job",
+            "hooks.example.com/job",
+        ] {
+            assert!(notifier.register("job", url.to_owned()).is_err(), "{}", url);
+        }
+        assert!(JobNotifier::new(None, vec![])
+            .register("job", "http://hooks.example.com/job".to_owned())
+            .is_err());
+    }
+
+    #[test]
+    fn failed_job_notification() {
+        let status = JobStatus {
+            status: Some(job_status::Status::Failed(FailedJob {
+                error: "Oops".to_owned(),
+                kind: 0,
+            })),
+        };
+        let notification = JobNotification::new("job-1", &status, None);
+        let json = serde_json::to_value(&notification).unwrap();
+
+        assert_eq!(json["job"]["job_id"], "job-1");
+        assert_eq!(json["job"]["status"], "Failed");
+        assert_eq!(json["job"]["error"], "Oops");
+        assert_eq!(json["stages"], serde_json::json!([]));
+    }
+}
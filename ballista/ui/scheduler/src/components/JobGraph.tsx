@@ -0,0 +1,314 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+import React, { useEffect, useState } from "react";
+import { Badge, Box, Flex, Link, Stack, Text, VStack } from "@chakra-ui/react";
+import { Column, DataTable } from "./DataTable";
+
+export enum StageStatus {
+  PENDING = "Pending",
+  RESOLVED = "Resolved",
+  RUNNING = "Running",
+  COMPLETED = "Completed",
+  FAILED = "Failed",
+}
+
+export interface Stage {
+  stage_id: number;
+  status: StageStatus;
+  partitions: number;
+  running_tasks: number;
+  completed_tasks: number;
+  inputs: number[];
+  output_link?: number;
+  num_rows?: number;
+  num_batches?: number;
+  num_bytes?: number;
+  spilled_bytes: number;
+  spill_files: number;
+}
+
+export interface Task {
+  partition_id: number;
+  status: string;
+  executor_id?: string;
+  error?: string;
+  attempts: number;
+  queue_time?: number;
+  run_time?: number;
+  shuffle_bytes?: number;
+  spilled_bytes?: number;
+}
+
+const statusColor = (status: StageStatus) => {
+  switch (status) {
+    case StageStatus.COMPLETED:
+      return "green";
+    case StageStatus.RUNNING:
+      return "orange";
+    case StageStatus.FAILED:
+      return "red";
+    case StageStatus.RESOLVED:
+      return "blue";
+    default:
+      return "gray";
+  }
+};
+
+const formatBytes = (bytes?: number) => {
+  if (bytes === undefined || bytes === null) return "-";
+  const units = ["B", "KB", "MB", "GB", "TB"];
+  let value = bytes;
+  let unit = 0;
+  while (value >= 1024 && unit < units.length - 1) {
+    value /= 1024;
+    unit++;
+  }
+  return `${value.toFixed(unit === 0 ? 0 : 1)} ${units[unit]}`;
+};
+
+const formatDuration = (millis?: number) => {
+  if (millis === undefined || millis === null) return "-";
+  if (millis < 1000) return `${millis} ms`;
+  if (millis < 60000) return `${(millis / 1000).toFixed(1)} s`;
+  return `${Math.floor(millis / 60000)} min ${Math.round(
+    (millis % 60000) / 1000
+  )} s`;
+};
+
+// Arrange the stages in columns so that every stage is to the right of all of
+// the stages it reads from.
+const stageLevels = (stages: Stage[]): Stage[][] => {
+  const byId = new Map(stages.map((stage) => [stage.stage_id, stage]));
+  const depth = new Map<number, number>();
+  const getDepth = (stage: Stage): number => {
+    const known = depth.get(stage.stage_id);
+    if (known !== undefined) return known;
+    const inputs = stage.inputs
+      .map((id) => byId.get(id))
+      .filter((input): input is Stage => input !== undefined);
+    const value =
+      inputs.length === 0 ? 0 : Math.max(...inputs.map(getDepth)) + 1;
+    depth.set(stage.stage_id, value);
+    return value;
+  };
+
+  const levels: Stage[][] = [];
+  stages.forEach((stage) => {
+    const level = getDepth(stage);
+    levels[level] = [...(levels[level] || []), stage];
+  });
+  return levels;
+};
+
+const taskColumns: Column<any>[] = [
+  {
+    Header: "Partition",
+    accessor: "partition_id",
+  },
+  {
+    Header: "Status",
+    accessor: "status",
+  },
+  {
+    Header: "Executor",
+    accessor: "executor_id",
+  },
+  {
+    Header: "Attempts",
+    accessor: "attempts",
+    isNumeric: true,
+  },
+  {
+    Header: "Queue Time",
+    accessor: "queue_time",
+    isNumeric: true,
+    Cell: (props: any) => formatDuration(props.value),
+  },
+  {
+    Header: "Run Time",
+    accessor: "run_time",
+    isNumeric: true,
+    Cell: (props: any) => formatDuration(props.value),
+  },
+  {
+    Header: "Shuffle Bytes",
+    accessor: "shuffle_bytes",
+    isNumeric: true,
+    Cell: (props: any) => formatBytes(props.value),
+  },
+  {
+    Header: "Spilled Bytes",
+    accessor: "spilled_bytes",
+    isNumeric: true,
+    Cell: (props: any) => formatBytes(props.value),
+  },
+  {
+    Header: "Error",
+    accessor: "error",
+    Cell: (props: any) => (
+      <Text fontSize={"sm"} color={"red.500"} whiteSpace={"pre-wrap"}>
+        {props.value}
+      </Text>
+    ),
+  },
+];
+
+const fetchJson = (url: string) =>
+  fetch(url, {
+    method: "GET",
+    headers: {
+      Accept: "application/json",
+    },
+  }).then((res) => res.json());
+
+interface StageCardProps {
+  stage: Stage;
+  selected: boolean;
+  onSelect: (stageId: number) => void;
+}
+
+const StageCard: React.FunctionComponent<StageCardProps> = ({
+  stage,
+  selected,
+  onSelect,
+}) => {
+  return (
+    <Box
+      borderWidth={selected ? 2 : 1}
+      borderColor={`${statusColor(stage.status)}.400`}
+      borderRadius={"md"}
+      p={3}
+      minW={200}
+    >
+      <Flex justifyContent={"space-between"} alignItems={"center"} mb={2}>
+        <Text fontWeight={"bold"}>Stage {stage.stage_id}</Text>
+        <Badge colorScheme={statusColor(stage.status)}>{stage.status}</Badge>
+      </Flex>
+      <Text fontSize={"sm"}>
+        Tasks: {stage.completed_tasks} / {stage.partitions} completed
+        {stage.running_tasks > 0 ? `, ${stage.running_tasks} running` : ""}
+      </Text>
+      <Text fontSize={"sm"}>Rows: {stage.num_rows ?? "-"}</Text>
+      <Text fontSize={"sm"}>Output: {formatBytes(stage.num_bytes)}</Text>
+      {stage.spilled_bytes > 0 && (
+        <Text fontSize={"sm"}>
+          Spilled: {formatBytes(stage.spilled_bytes)} to {stage.spill_files}{" "}
+          files
+        </Text>
+      )}
+      <Text fontSize={"sm"}>
+        Inputs:{" "}
+        {stage.inputs.length > 0
+          ? stage.inputs.map((id) => `Stage ${id}`).join(", ")
+          : "-"}
+      </Text>
+      <Text fontSize={"sm"}>
+        Output to:{" "}
+        {stage.output_link !== undefined && stage.output_link !== null
+          ? `Stage ${stage.output_link}`
+          : "Result"}
+      </Text>
+      <Link fontSize={"sm"} onClick={() => onSelect(stage.stage_id)}>
+        Tasks
+      </Link>
+    </Box>
+  );
+};
+
+interface JobGraphProps {
+  jobId: string;
+}
+
+export const JobGraph: React.FunctionComponent<JobGraphProps> = ({
+  jobId,
+}) => {
+  const [stages, setStages] = useState<Stage[] | undefined>(undefined);
+  const [selectedStage, setSelectedStage] = useState<number | undefined>(
+    undefined
+  );
+  const [tasks, setTasks] = useState<Task[] | undefined>(undefined);
+
+  const isRunning =
+    stages === undefined ||
+    stages.some(
+      (stage) =>
+        stage.status !== StageStatus.COMPLETED &&
+        stage.status !== StageStatus.FAILED
+    );
+
+  useEffect(() => {
+    setStages(undefined);
+    setSelectedStage(undefined);
+  }, [jobId]);
+
+  // Poll the stages of the job while it is still running
+  useEffect(() => {
+    const refresh = () =>
+      fetchJson(`/job/${jobId}/stages`)
+        .then((res) => setStages(Array.isArray(res) ? res : []))
+        .catch(() => setStages([]));
+    refresh();
+    if (!isRunning) return;
+    const interval = setInterval(refresh, 2000);
+    return () => clearInterval(interval);
+  }, [jobId, isRunning]);
+
+  useEffect(() => {
+    if (selectedStage === undefined) {
+      setTasks(undefined);
+      return;
+    }
+    fetchJson(`/job/${jobId}/stage/${selectedStage}/tasks`)
+      .then((res) => setTasks(Array.isArray(res) ? res : []))
+      .catch(() => setTasks([]));
+  }, [jobId, selectedStage, stages]);
+
+  return (
+    <VStack flex={1} p={4} w={"100%"} alignItems={"flex-start"}>
+      <Text mb={4}>Job {jobId}</Text>
+      {stages !== undefined && stages.length === 0 ? (
+        <Text fontSize={"sm"}>No stages available for this job.</Text>
+      ) : null}
+      <Flex overflowX={"auto"} w={"100%"}>
+        {stageLevels(stages || []).map((level, index) => (
+          <Stack key={index} spacing={4} mr={6}>
+            {level.map((stage) => (
+              <StageCard
+                key={stage.stage_id}
+                stage={stage}
+                selected={stage.stage_id === selectedStage}
+                onSelect={setSelectedStage}
+              />
+            ))}
+          </Stack>
+        ))}
+      </Flex>
+      {selectedStage !== undefined && tasks !== undefined ? (
+        <Stack w={"100%"} pt={4}>
+          <Text>Tasks of stage {selectedStage}</Text>
+          <DataTable
+            columns={taskColumns}
+            data={tasks}
+            pageSize={10}
+            pb={10}
+          />
+        </Stack>
+      ) : null}
+    </VStack>
+  );
+};
@@ -19,63 +19,56 @@ import React from "react";
 import {
   CircularProgress,
   CircularProgressLabel,
+  Link,
   VStack,
   Skeleton,
   Stack,
   Text,
-  Flex,
-  Box,
 } from "@chakra-ui/react";
-import { Column, DateCell, DataTable, LinkCell } from "./DataTable";
-import { FaStop } from "react-icons/fa";
-import { GrPowerReset } from "react-icons/gr";
+import { Column, DataTable } from "./DataTable";
 
 export enum QueryStatus {
-  QUEUED = "QUEUED",
-  RUNNING = "RUNNING",
-  FAILED = "FAILED",
-  COMPLETED = "COMPLETED",
+  QUEUED = "Queued",
+  RUNNING = "Running",
+  FAILED = "Failed",
+  COMPLETED = "Completed",
 }
 
 export interface Query {
-  uuid: string;
-  query: string;
+  job_id: string;
   status: QueryStatus;
-  progress: number;
-  started: string;
+  error?: string;
+  num_stages: number;
+  completed_stages: number;
 }
 
 export interface QueriesListProps {
   queries?: Query[];
+  onSelectJob?: (jobId: string) => void;
 }
 
-export const ActionsCell: (props: any) => React.ReactNode = (props: any) => {
-  return (
-    <Flex>
-      <FaStop color={"red"} title={"stop"} />
-      <Box mx={2}></Box>
-      <GrPowerReset title={"Retry"} />
-    </Flex>
-  );
-};
-
 export const ProgressCell: (props: any) => React.ReactNode = (props: any) => {
+  const query: Query = props.row.original;
+  const progress =
+    query.num_stages > 0
+      ? Math.round((query.completed_stages / query.num_stages) * 100)
+      : 0;
   return (
-    <CircularProgress value={props.value} color="orange.400">
-      <CircularProgressLabel>{props.value}%</CircularProgressLabel>
+    <CircularProgress value={progress} color="orange.400">
+      <CircularProgressLabel>{progress}%</CircularProgressLabel>
     </CircularProgress>
   );
 };
 
-const columns: Column<any>[] = [
+const getColumns = (onSelectJob?: (jobId: string) => void): Column<any>[] => [
   {
-    Header: "UUID",
-    accessor: "uuid",
-    Cell: LinkCell,
-  },
-  {
-    Header: "Query",
-    accessor: "query",
+    Header: "Job ID",
+    accessor: "job_id",
+    Cell: (props: any) => (
+      <Link onClick={() => onSelectJob && onSelectJob(props.value)}>
+        {props.value}
+      </Link>
+    ),
   },
   {
     Header: "Status",
@@ -83,18 +76,12 @@ const columns: Column<any>[] = [
   },
   {
     Header: "Progress",
-    accessor: "progress",
+    accessor: "completed_stages",
     Cell: ProgressCell,
   },
   {
-    Header: "Started",
-    accessor: "started",
-    Cell: DateCell,
-  },
-  {
-    Header: "Actions",
-    accessor: "",
-    Cell: ActionsCell,
+    Header: "Error",
+    accessor: "error",
   },
 ];
 
@@ -111,23 +98,17 @@ const getSkeletion = () => (
 
 export const QueriesList: React.FunctionComponent<QueriesListProps> = ({
   queries,
+  onSelectJob,
 }) => {
   const isLoaded = typeof queries !== "undefined";
 
-  //TODO: Remove blur once queries api is ready
   return (
-    <VStack
-      flex={1}
-      p={4}
-      w={"100%"}
-      alignItems={"flex-start"}
-      filter="blur(3px)"
-    >
+    <VStack flex={1} p={4} w={"100%"} alignItems={"flex-start"}>
       <Text mb={4}>Queries</Text>
       <Stack w={"100%"} flex={1}>
         {isLoaded ? (
           <DataTable
-            columns={columns}
+            columns={getColumns(onSelectJob)}
             data={queries || []}
             pageSize={10}
             pb={10}
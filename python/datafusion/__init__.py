@@ -39,6 +39,14 @@ __all__ = [
     "literal",
 ]
 
+# BallistaContext is only built with the ballista-client feature
+try:
+    from ._internal import BallistaContext  # noqa: F401
+
+    __all__.append("BallistaContext")
+except ImportError:
+    pass
+
 
 class Accumulator(metaclass=ABCMeta):
     @abstractmethod
@@ -0,0 +1,72 @@
+# Licensed to the Apache Software Foundation (ASF) under one
+# or more contributor license agreements.  See the NOTICE file
+# distributed with this work for additional information
+# regarding copyright ownership.  The ASF licenses this file
+# to you under the Apache License, Version 2.0 (the
+# "License"); you may not use this file except in compliance
+# with the License.  You may obtain a copy of the License at
+#
+#   http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing,
+# software distributed under the License is distributed on an
+# "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+# KIND, either express or implied.  See the License for the
+# specific language governing permissions and limitations
+# under the License.
+
+import pyarrow as pa
+import pytest
+
+import datafusion
+
+
+@pytest.fixture
+def ctx():
+    if not hasattr(datafusion, "BallistaContext"):
+        pytest.skip("built without the ballista-client feature")
+    try:
+        ctx = datafusion.BallistaContext.standalone()
+    except NotImplementedError:
+        pytest.skip("built without the ballista-standalone feature")
+    yield ctx
+    ctx.close()
+
+
+def totals(ctx, name):
+    batches = ctx.sql(f"SELECT count(*) AS n, sum(id) AS total FROM {name}")
+    return batches[0].to_pydict()
+
+
+def test_ballista_context_module_is_datafusion(ctx):
+    assert type(ctx).__module__ == "datafusion"
+
+
+def test_upload_table(ctx, tmp_path):
+    location = str(tmp_path / "uploaded")
+    table = pa.Table.from_batches(
+        [
+            pa.RecordBatch.from_arrays([pa.array([1, 2])], names=["id"]),
+            pa.RecordBatch.from_arrays([pa.array([3])], names=["id"]),
+        ]
+    )
+    ctx.upload_table("uploaded", table, location, partitions=2)
+    assert totals(ctx, "uploaded") == {"n": [3], "total": [6]}
+    parquet_files = list(tmp_path.glob("uploaded/*.parquet"))
+    assert len(parquet_files) == 2
+
+    # uploading again replaces the rows and the files of the table
+    table = pa.table({"id": [10]})
+    ctx.upload_table("uploaded", table, location)
+    assert totals(ctx, "uploaded") == {"n": [1], "total": [10]}
+    assert len(list(tmp_path.glob("uploaded/*.parquet"))) == 1
+
+    # the table is in the catalog of the scheduler
+    ctx.register_catalog_table("uploaded")
+    assert totals(ctx, "uploaded") == {"n": [1], "total": [10]}
+
+
+def test_upload_table_without_rows(ctx, tmp_path):
+    table = pa.table({"id": pa.array([], type=pa.int64())})
+    with pytest.raises(Exception):
+        ctx.upload_table("empty", table, str(tmp_path / "empty"))
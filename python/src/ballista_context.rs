@@ -0,0 +1,172 @@
+// Licensed to the Apache Software Foundation (ASF) under one
+// or more contributor license agreements.  See the NOTICE file
+// distributed with this work for additional information
+// regarding copyright ownership.  The ASF licenses this file
+// to you under the Apache License, Version 2.0 (the
+// "License"); you may not use this file except in compliance
+// with the License.  You may obtain a copy of the License at
+//
+//   http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing,
+// software distributed under the License is distributed on an
+// "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.  See the License for the
+// specific language governing permissions and limitations
+// under the License.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use tokio::runtime::Runtime;
+
+use ballista::prelude::{BallistaConfig, BallistaContext};
+use datafusion::arrow::datatypes::Schema;
+use datafusion::arrow::pyarrow::PyArrowConvert;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::prelude::ParquetReadOptions;
+
+use crate::errors::DataFusionError;
+
+/// `PyBallistaContext` runs queries on a Ballista cluster, through its scheduler.
+/// In-memory tables are uploaded to an object store of the cluster, so queries can
+/// join them with the tables of the cluster.
+#[pyclass(name = "BallistaContext", module = "datafusion", subclass, unsendable)]
+pub(crate) struct PyBallistaContext {
+    ctx: BallistaContext,
+    /// Runtime of the calls of the context, which lives as long as the context like the
+    /// scheduler and the executor of a standalone cluster running on it
+    runtime: Runtime,
+}
+
+impl PyBallistaContext {
+    /// Wait for `f` on the runtime of the context, with the GIL released
+    fn wait<F>(&self, py: Python, f: F) -> F::Output
+    where
+        F: Future + Send,
+        F::Output: Send,
+    {
+        let runtime = &self.runtime;
+        py.allow_threads(|| runtime.block_on(f))
+    }
+}
+
+#[pymethods]
+impl PyBallistaContext {
+    /// Connects to the scheduler at `host` and `port`, with the Ballista `settings` of
+    /// the session, such as `ballista.shuffle.partitions`
+    #[new]
+    #[args(port = "50050", settings = "None")]
+    fn new(
+        host: &str,
+        port: u16,
+        settings: Option<HashMap<String, String>>,
+        py: Python,
+    ) -> PyResult<Self> {
+        let config = BallistaConfig::with_settings(settings.unwrap_or_default())
+            .map_err(|e| DataFusionError::Common(e.to_string()))?;
+        let runtime = Runtime::new()?;
+        let result = BallistaContext::remote(host, port, &config);
+        let ctx = py
+            .allow_threads(|| runtime.block_on(result))
+            .map_err(|e| DataFusionError::Common(e.to_string()))?;
+        Ok(PyBallistaContext { ctx, runtime })
+    }
+
+    /// Runs a scheduler and an executor of `concurrent_tasks` slots in this process,
+    /// mostly for tests. Only available when built with the `ballista-standalone`
+    /// feature.
+    #[staticmethod]
+    #[args(concurrent_tasks = "1", settings = "None")]
+    fn standalone(
+        concurrent_tasks: usize,
+        settings: Option<HashMap<String, String>>,
+        py: Python,
+    ) -> PyResult<Self> {
+        #[cfg(feature = "ballista-standalone")]
+        {
+            let config = BallistaConfig::with_settings(settings.unwrap_or_default())
+                .map_err(|e| DataFusionError::Common(e.to_string()))?;
+            let runtime = Runtime::new()?;
+            let result = BallistaContext::standalone(&config, concurrent_tasks);
+            let ctx = py
+                .allow_threads(|| runtime.block_on(result))
+                .map_err(|e| DataFusionError::Common(e.to_string()))?;
+            Ok(PyBallistaContext { ctx, runtime })
+        }
+        #[cfg(not(feature = "ballista-standalone"))]
+        {
+            let _ = (concurrent_tasks, settings, py);
+            Err(pyo3::exceptions::PyNotImplementedError::new_err(
+                "datafusion was built without the ballista-standalone feature",
+            ))
+        }
+    }
+
+    /// Runs the SQL statement on the cluster and returns the batches of its result
+    fn sql(&self, query: &str, py: Python) -> PyResult<Vec<PyObject>> {
+        let result = async {
+            let df = self.ctx.sql(query).await?;
+            df.collect().await
+        };
+        let batches = self.wait(py, result).map_err(DataFusionError::from)?;
+        batches.into_iter().map(|rb| rb.to_pyarrow(py)).collect()
+    }
+
+    fn register_parquet(&self, name: &str, path: &str, py: Python) -> PyResult<()> {
+        let result = self
+            .ctx
+            .register_parquet(name, path, ParquetReadOptions::default());
+        self.wait(py, result).map_err(DataFusionError::from)?;
+        Ok(())
+    }
+
+    /// Registers table `name` of the catalog of the scheduler, which this or another
+    /// client uploaded with `upload_table`
+    fn register_catalog_table(&self, name: &str, py: Python) -> PyResult<()> {
+        let result = self.ctx.register_catalog_table(name);
+        self.wait(py, result).map_err(DataFusionError::from)?;
+        Ok(())
+    }
+
+    /// Uploads the pyarrow Table `table` to directory `location` of an object store
+    /// of the cluster, as up to `partitions` Parquet files, and registers it as table
+    /// `name` in this context and in the catalog of the scheduler. Uploading to the
+    /// same location again replaces the table.
+    #[args(partitions = "1")]
+    fn upload_table(
+        &self,
+        name: &str,
+        table: &PyAny,
+        location: &str,
+        partitions: usize,
+        py: Python,
+    ) -> PyResult<()> {
+        if partitions == 0 {
+            return Err(PyValueError::new_err("At least one partition is required"));
+        }
+        let schema: Schema = table.getattr("schema")?.extract()?;
+        let batches: Vec<RecordBatch> = table.call_method0("to_batches")?.extract()?;
+        // The batches are spread evenly over the partitions
+        let mut uploaded = vec![vec![]; partitions];
+        for (index, batch) in batches.into_iter().enumerate() {
+            uploaded[index % partitions].push(batch);
+        }
+
+        let result = self
+            .ctx
+            .upload_table(name, location, Arc::new(schema), uploaded);
+        self.wait(py, result).map_err(DataFusionError::from)?;
+        Ok(())
+    }
+
+    /// Closes the session on the scheduler, dropping its temporary tables
+    fn close(&self, py: Python) -> PyResult<Vec<String>> {
+        let result = self.ctx.close();
+        let dropped = self.wait(py, result).map_err(DataFusionError::from)?;
+        Ok(dropped)
+    }
+}
@@ -17,6 +17,8 @@
 
 use pyo3::prelude::*;
 
+#[cfg(feature = "ballista-client")]
+mod ballista_context;
 mod catalog;
 mod context;
 mod dataframe;
@@ -34,6 +36,8 @@ mod utils;
 #[pymodule]
 fn _internal(py: Python, m: &PyModule) -> PyResult<()> {
     // Register the python classes
+    #[cfg(feature = "ballista-client")]
+    m.add_class::<ballista_context::PyBallistaContext>()?;
     m.add_class::<catalog::PyCatalog>()?;
     m.add_class::<catalog::PyDatabase>()?;
     m.add_class::<catalog::PyTable>()?;